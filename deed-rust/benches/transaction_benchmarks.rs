@@ -2,11 +2,10 @@
 //!
 //! Measures transaction overhead, throughput, and latency.
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::{Duration, Instant};
-use std::thread;
 
 /// Benchmark result
 #[derive(Debug, Clone)]
@@ -225,6 +224,68 @@ pub fn bench_aggregation(row_count: usize) -> BenchmarkResult {
     )
 }
 
+/// Benchmark: WHERE over a large collection, sequential or parallel
+pub fn bench_filter(entity_count: usize, label: &str, config: ParallelConfig) -> BenchmarkResult {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        for i in 0..entity_count {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            props.insert("age".to_string(), PropertyValue::Int((i % 100) as i64));
+            props.insert("score".to_string(), PropertyValue::Float((i % 1000) as f64 / 10.0));
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+    }
+
+    let executor = DQLExecutor::new(graph).with_parallelism(config).unwrap();
+
+    let start = Instant::now();
+
+    let result = executor
+        .execute("FROM Users WHERE age > 50 AND score * 2 < 150 AND name != 'User7' SELECT name")
+        .unwrap();
+    assert!(result.row_count() > 0);
+
+    let duration = start.elapsed();
+    BenchmarkResult::new(
+        &format!("Filter ({} entities, {})", entity_count, label),
+        1,
+        duration,
+    )
+}
+
+/// Benchmark: ORDER BY over a large collection, with or without a LIMIT
+pub fn bench_sort(entity_count: usize, limit: Option<usize>) -> BenchmarkResult {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        for i in 0..entity_count {
+            let mut props = std::collections::HashMap::new();
+            props.insert("ts".to_string(), PropertyValue::Int(((i * 7919) % entity_count) as i64));
+            props.insert("payload".to_string(), PropertyValue::String(format!("event-{}", i)));
+            g.add_entity("Events".to_string(), props).unwrap();
+        }
+    }
+    let executor = DQLExecutor::new(graph);
+
+    let query = match limit {
+        Some(limit) => format!("FROM Events SELECT ts, payload ORDER BY ts DESC LIMIT {}", limit),
+        None => "FROM Events SELECT ts, payload ORDER BY ts DESC".to_string(),
+    };
+
+    let start = Instant::now();
+
+    executor.execute(&query).unwrap();
+
+    let duration = start.elapsed();
+    let label = match limit {
+        Some(limit) => format!("ORDER BY ... LIMIT {} ({} rows)", limit, entity_count),
+        None => format!("ORDER BY, full sort ({} rows)", entity_count),
+    };
+    BenchmarkResult::new(&label, 1, duration)
+}
+
 /// Run all benchmarks
 pub fn run_all_benchmarks() {
     println!("\n╔════════════════════════════════════════════════╗");
@@ -256,6 +317,26 @@ pub fn run_all_benchmarks() {
     // 6. Mixed workload
     println!("━━━ Mixed Workload ━━━\n");
     bench_mixed_workload(100).print();
+
+    // 7. Parallel filter
+    println!("━━━ Parallel Filter ━━━\n");
+    let sequential = bench_filter(1_000_000, "1 thread", ParallelConfig { threshold: usize::MAX, max_threads: None });
+    let parallel = bench_filter(1_000_000, "all cores", ParallelConfig::default());
+    sequential.print();
+    parallel.print();
+    println!(
+        "  Speedup:       {:.2}x\n",
+        sequential.duration.as_secs_f64() / parallel.duration.as_secs_f64()
+    );
+
+    // 8. Top-k
+    println!("━━━ Top-K ━━━\n");
+    bench_sort(1_000_000, None).print();
+    bench_sort(1_000_000, Some(10)).print();
+}
+
+fn main() {
+    run_all_benchmarks();
 }
 
 #[cfg(test)]
@@ -269,9 +350,8 @@ mod tests {
         bench_insert_batched_transaction(10);
         bench_select(10);
         bench_transaction_overhead();
+        bench_filter(20_000, "1 thread", ParallelConfig { threshold: usize::MAX, max_threads: None });
+        bench_filter(20_000, "2 threads", ParallelConfig { threshold: 0, max_threads: Some(2) });
+        bench_sort(1_000, Some(10));
     }
 }
-
-fn main() {
-    run_all_benchmarks();
-}
@@ -9,7 +9,7 @@
 //!
 //! This example simulates a 5-node distributed Deed cluster.
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 
 fn main() {
@@ -32,6 +32,7 @@ fn main() {
         rewiring_probability: 0.05,
         max_latency_ms: 100,
         health_check_interval_secs: 10,
+        ..TopologyConfig::default()
     };
 
     // Create 5 nodes in the network
@@ -90,10 +91,11 @@ fn main() {
     println!("STEP 2: Configuring Shard Assignment & Consistent Hashing");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━\n");
 
-    let shard_config = ShardConfig {
+    let shard_config = distributed_shard::ShardConfig {
         virtual_nodes_per_node: 150,  // 150 virtual nodes per physical node
         replication_factor: 3,         // 3 copies of each shard
         total_shards: 64,              // 64 shards total
+        ..Default::default()
     };
 
     let shard_manager = Arc::new(ShardManager::new(shard_config));
@@ -169,7 +171,10 @@ fn main() {
         message_timeout_ms: 10000,
         heartbeat_interval_secs: 5,
         max_retries: 3,
-        buffer_size: 65536,
+        max_frame_size: 16 * 1024 * 1024,
+        // A demo on one private network; real clusters configure `tls`
+        insecure: true,
+        ..P2PConfig::default()
     };
 
     println!("Creating P2P networks for each node:");
@@ -190,7 +195,7 @@ fn main() {
                 }
             }
 
-            println!("  ✓ Node {} P2P network initialized ({})", id, address.to_string());
+            println!("  ✓ Node {} P2P network initialized ({})", id, address);
             network
         })
         .collect();
@@ -221,7 +226,7 @@ fn main() {
         .collect();
 
     // Create distributed query executor for node 1
-    let distributed_executor = DistributedQueryExecutor::new(
+    let _distributed_executor = DistributedQueryExecutor::new(
         1,
         shard_manager.clone(),
         p2p_networks[0].clone(),
@@ -283,12 +288,9 @@ fn main() {
 
     println!("Adding Node 6 to cluster...");
 
-    // Add new node
+    // Add new node; shards keep their assignments until their data moves
     let new_node_id = 6;
-    shard_manager.add_node(new_node_id);
-
-    // Calculate rebalancing operations
-    let rebalance_ops = shard_manager.calculate_rebalancing(new_node_id);
+    let rebalance_ops = shard_manager.join_node(new_node_id);
 
     println!("  ✓ Node 6 added to consistent hash ring\n");
 
@@ -12,9 +12,8 @@
 //! - Backups
 //! - Admin dashboard
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
 use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
@@ -29,7 +28,7 @@ fn main() {
 
     let graph = Arc::new(RwLock::new(Graph::new()));
     let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
-    let cache = Arc::new(RwLock::new(StigmergyCache::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
     let transaction_mgr = Arc::new(TransactionManager::new());
 
     // Setup connection pool
@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 
 fn main() {
@@ -44,7 +44,7 @@ fn main() {
     println!("🔍 Query 1: All users in San Francisco");
     let result = executor.execute(r#"
         FROM Users
-        WHERE city = "San Francisco"
+        WHERE city = 'San Francisco'
         SELECT name, age, email
     "#).unwrap();
     println!("   Result: {} rows affected\n", result.rows_affected);
@@ -71,7 +71,7 @@ fn main() {
     executor.execute(r#"
         UPDATE Users
         SET age = 26
-        WHERE name = "Bob"
+        WHERE name = 'Bob'
     "#).unwrap();
     println!("✅ Updated\n");
 
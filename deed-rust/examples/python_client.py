@@ -200,7 +200,7 @@ def main():
 
     # Update example
     print("8. Update example...")
-    result = db.execute('UPDATE Users SET age = 36 WHERE name = "Charlie"')
+    result = db.execute("UPDATE Users SET age = 36 WHERE name = 'Charlie'")
     print(f"   Result: {json.dumps(result, indent=2)}")
     print()
 
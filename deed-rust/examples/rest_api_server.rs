@@ -15,7 +15,7 @@
 //!     -H "Content-Type: application/json" \
 //!     -d '{"username": "admin", "password": "admin123"}'
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use axum::{
     Router,
@@ -111,7 +111,7 @@ async fn query_handler(
     Json(payload): Json<QueryRequest>,
 ) -> (StatusCode, Json<QueryResponse>) {
     // Validate session
-    if !state.auth.validate_session(&payload.session_id) {
+    if state.auth.validate_session(&payload.session_id).is_err() {
         return (StatusCode::UNAUTHORIZED, Json(QueryResponse {
             success: false,
             rows_affected: None,
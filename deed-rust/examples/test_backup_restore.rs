@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::fs;
 use std::path::Path;
@@ -107,10 +107,10 @@ fn main() {
         role: "User"
     })"#).expect("Insert failed");
 
-    executor.execute(r#"UPDATE Products SET stock = 5 WHERE name = "Laptop""#)
+    executor.execute(r#"UPDATE Products SET stock = 5 WHERE name = 'Laptop'"#)
         .expect("Update failed");
 
-    executor.execute(r#"DELETE FROM Products WHERE name = "Mouse""#)
+    executor.execute(r#"DELETE FROM Products WHERE name = 'Mouse'"#)
         .expect("Delete failed");
 
     println!("   ✓ Added Carol");
@@ -129,7 +129,8 @@ fn main() {
     println!("\n💾 Test 5: Creating incremental backup...");
 
     let graph_read = graph.read().unwrap();
-    let backup2_meta = backup_manager.create_incremental_backup(&graph_read, &backup1_id)
+    let backup2_meta = backup_manager
+        .create_backup(&BackupSnapshot::of_graph(&graph_read), BackupType::Incremental)
         .expect("Incremental backup failed");
     drop(graph_read);
 
@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 
 fn main() {
@@ -43,10 +43,10 @@ fn main() {
 
     // Create FOLLOWS edges
     use std::collections::HashMap;
-    graph_lock.add_edge(alice_id, bob_id, "FOLLOWS".to_string(), HashMap::new());
-    graph_lock.add_edge(alice_id, carol_id, "FOLLOWS".to_string(), HashMap::new());
-    graph_lock.add_edge(bob_id, dave_id, "FOLLOWS".to_string(), HashMap::new());
-    graph_lock.add_edge(carol_id, alice_id, "FOLLOWS".to_string(), HashMap::new());
+    graph_lock.add_edge(alice_id, bob_id, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+    graph_lock.add_edge(alice_id, carol_id, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+    graph_lock.add_edge(bob_id, dave_id, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+    graph_lock.add_edge(carol_id, alice_id, "FOLLOWS".to_string(), HashMap::new()).unwrap();
 
     println!("   ✓ Created 4 FOLLOWS relationships:");
     println!("     - Alice → Bob");
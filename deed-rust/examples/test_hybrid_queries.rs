@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
 
@@ -55,28 +55,28 @@ fn main() {
     let laptop_id = find_product("Laptop");
     let mouse_id = find_product("Mouse");
     let desk_id = find_product("Desk");
-    let chair_id = find_product("Chair");
+    let _chair_id = find_product("Chair");
 
     // Create PURCHASED edges with metadata
     let mut purchase1 = HashMap::new();
     purchase1.insert("quantity".to_string(), PropertyValue::Int(1));
     purchase1.insert("date".to_string(), PropertyValue::String("2024-01-15".to_string()));
-    graph_lock.add_edge(alice_id, laptop_id, "PURCHASED".to_string(), purchase1);
+    graph_lock.add_edge(alice_id, laptop_id, "PURCHASED".to_string(), purchase1).unwrap();
 
     let mut purchase2 = HashMap::new();
     purchase2.insert("quantity".to_string(), PropertyValue::Int(2));
     purchase2.insert("date".to_string(), PropertyValue::String("2024-01-16".to_string()));
-    graph_lock.add_edge(alice_id, mouse_id, "PURCHASED".to_string(), purchase2);
+    graph_lock.add_edge(alice_id, mouse_id, "PURCHASED".to_string(), purchase2).unwrap();
 
     let mut purchase3 = HashMap::new();
     purchase3.insert("quantity".to_string(), PropertyValue::Int(1));
     purchase3.insert("date".to_string(), PropertyValue::String("2024-01-20".to_string()));
-    graph_lock.add_edge(bob_id, desk_id, "PURCHASED".to_string(), purchase3);
+    graph_lock.add_edge(bob_id, desk_id, "PURCHASED".to_string(), purchase3).unwrap();
 
     let mut purchase4 = HashMap::new();
     purchase4.insert("quantity".to_string(), PropertyValue::Int(1));
     purchase4.insert("date".to_string(), PropertyValue::String("2024-01-22".to_string()));
-    graph_lock.add_edge(carol_id, laptop_id, "PURCHASED".to_string(), purchase4);
+    graph_lock.add_edge(carol_id, laptop_id, "PURCHASED".to_string(), purchase4).unwrap();
 
     println!("   ✓ Alice purchased: Laptop, Mouse (2)");
     println!("   ✓ Bob purchased: Desk");
@@ -88,7 +88,7 @@ fn main() {
     println!("📊 Test 1: Pure Relational Query");
     println!("   Query: SELECT all Gold members");
 
-    match executor.execute(r#"FROM Customers WHERE membership = "Gold" SELECT name, city, membership"#) {
+    match executor.execute(r#"FROM Customers WHERE membership = 'Gold' SELECT name, city, membership"#) {
         Ok(result) => {
             println!("   ✓ Found {} Gold members:", result.rows.len());
             for row in &result.rows {
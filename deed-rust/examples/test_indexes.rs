@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
@@ -77,7 +77,7 @@ fn main() {
     println!("   Query: SELECT users from NYC");
 
     let start = Instant::now();
-    match executor.execute(r#"FROM Users WHERE city = "NYC" SELECT name, age"#) {
+    match executor.execute(r#"FROM Users WHERE city = 'NYC' SELECT name, age"#) {
         Ok(result) => {
             let duration = start.elapsed();
             println!("   ✓ Found {} users", result.rows.len());
@@ -88,8 +88,8 @@ fn main() {
 
     // Execute same query again (should use cached plan)
     let start = Instant::now();
-    match executor.execute(r#"FROM Users WHERE city = "NYC" SELECT name, age"#) {
-        Ok(result) => {
+    match executor.execute(r#"FROM Users WHERE city = 'NYC' SELECT name, age"#) {
+        Ok(_) => {
             let duration = start.elapsed();
             println!("   ⏱️  Second execution: {:?} (cached plan)", duration);
             println!("   📈 Stigmergy cache working!");
@@ -103,7 +103,7 @@ fn main() {
 
     let start = Instant::now();
     match executor.execute(
-        r#"FROM Users WHERE city = "NYC" AND age > 30 AND premium = true SELECT name, age"#
+        r#"FROM Users WHERE city = 'NYC' AND age > 30 AND premium = true SELECT name, age"#
     ) {
         Ok(result) => {
             let duration = start.elapsed();
@@ -117,9 +117,9 @@ fn main() {
     // Execute again
     let start = Instant::now();
     match executor.execute(
-        r#"FROM Users WHERE city = "NYC" AND age > 30 AND premium = true SELECT name, age"#
+        r#"FROM Users WHERE city = 'NYC' AND age > 30 AND premium = true SELECT name, age"#
     ) {
-        Ok(result) => {
+        Ok(_) => {
             let duration = start.elapsed();
             println!("   ⏱️  Second execution: {:?}", duration);
             println!("   📊 Using optimized plan with pheromone reinforcement");
@@ -153,10 +153,10 @@ fn main() {
     for (idx, city) in cities.iter().enumerate() {
         let start = Instant::now();
         match executor.execute(&format!(
-            r#"FROM Users WHERE city = "{}" SELECT name, age"#,
+            r#"FROM Users WHERE city = '{}' SELECT name, age"#,
             city
         )) {
-            Ok(result) => {
+            Ok(_) => {
                 let duration = start.elapsed();
                 total_time += duration;
                 println!("   Query {}: {} in {:?}", idx + 1, city, duration);
@@ -177,7 +177,7 @@ fn main() {
     for (idx, city) in cities.iter().enumerate() {
         let start = Instant::now();
         match executor.execute(&format!(
-            r#"FROM Users WHERE city = "{}" SELECT name, age"#,
+            r#"FROM Users WHERE city = '{}' SELECT name, age"#,
             city
         )) {
             Ok(_) => {
@@ -193,8 +193,8 @@ fn main() {
     println!("   - Before: {:?}", total_time);
     println!("   - After: {:?}", optimized_time);
     if optimized_time < total_time {
-        let improvement = ((total_time.as_micros() - optimized_time.as_micros()) as f64
-                          / total_time.as_micros() as f64 * 100.0);
+        let improvement = (total_time.as_micros() - optimized_time.as_micros()) as f64
+                          / total_time.as_micros() as f64 * 100.0;
         println!("   - Improvement: {:.1}% faster!", improvement);
     }
 
@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 
 fn main() {
@@ -25,13 +25,13 @@ fn main() {
     }
 
     // Update Alice's balance
-    match executor.execute(r#"UPDATE Accounts SET balance = 1200 WHERE holder = "Alice""#) {
+    match executor.execute(r#"UPDATE Accounts SET balance = 1200 WHERE holder = 'Alice'"#) {
         Ok(result) => println!("   ✓ Updated {} account (Alice: 1000 → 1200)", result.rows_affected),
         Err(e) => println!("   ✗ Error: {}", e),
     }
 
     // Verify the change
-    match executor.execute(r#"FROM Accounts WHERE holder = "Alice" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Alice' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   ✓ Within transaction, Alice's balance:");
             for row in &result.rows {
@@ -55,13 +55,13 @@ fn main() {
     }
 
     // Update Bob's balance
-    match executor.execute(r#"UPDATE Accounts SET balance = 1000 WHERE holder = "Bob""#) {
+    match executor.execute(r#"UPDATE Accounts SET balance = 1000 WHERE holder = 'Bob'"#) {
         Ok(result) => println!("   ✓ Updated {} account (Bob: 500 → 1000)", result.rows_affected),
         Err(e) => println!("   ✗ Error: {}", e),
     }
 
     // Check balance within transaction
-    match executor.execute(r#"FROM Accounts WHERE holder = "Bob" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Bob' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   ✓ Within transaction, Bob's balance:");
             for row in &result.rows {
@@ -78,7 +78,7 @@ fn main() {
     }
 
     // Verify rollback - Bob should still have 500
-    match executor.execute(r#"FROM Accounts WHERE holder = "Bob" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Bob' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   ✓ After rollback, Bob's balance (should be 500):");
             for row in &result.rows {
@@ -119,7 +119,7 @@ fn main() {
         Err(e) => println!("     ✗ Error: {}", e),
     }
 
-    match executor.execute(r#"FROM Accounts WHERE holder = "Alice" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Alice' SELECT holder, balance"#) {
         Ok(result) => {
             println!("     ✓ First read of Alice's account:");
             for row in &result.rows {
@@ -131,7 +131,7 @@ fn main() {
 
     // In a real concurrent scenario, another transaction would modify this
     // For now, just demonstrate that we can read again
-    match executor.execute(r#"FROM Accounts WHERE holder = "Alice" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Alice' SELECT holder, balance"#) {
         Ok(result) => {
             println!("     ✓ Second read (should be same in REPEATABLE READ):");
             for row in &result.rows {
@@ -175,7 +175,7 @@ fn main() {
     }
 
     // Check initial balances
-    match executor.execute(r#"FROM Accounts WHERE holder = "Alice" OR holder = "Carol" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Alice' OR holder = 'Carol' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   Initial balances:");
             for row in &result.rows {
@@ -186,19 +186,19 @@ fn main() {
     }
 
     // Deduct from Alice (1200 - 200 = 1000)
-    match executor.execute(r#"UPDATE Accounts SET balance = 1000 WHERE holder = "Alice""#) {
+    match executor.execute(r#"UPDATE Accounts SET balance = 1000 WHERE holder = 'Alice'"#) {
         Ok(result) => println!("   ✓ Deducted $200 from Alice ({} updated)", result.rows_affected),
         Err(e) => println!("   ✗ Error: {}", e),
     }
 
     // Add to Carol (750 + 200 = 950)
-    match executor.execute(r#"UPDATE Accounts SET balance = 950 WHERE holder = "Carol""#) {
+    match executor.execute(r#"UPDATE Accounts SET balance = 950 WHERE holder = 'Carol'"#) {
         Ok(result) => println!("   ✓ Added $200 to Carol ({} updated)", result.rows_affected),
         Err(e) => println!("   ✗ Error: {}", e),
     }
 
     // Verify within transaction
-    match executor.execute(r#"FROM Accounts WHERE holder = "Alice" OR holder = "Carol" SELECT holder, balance"#) {
+    match executor.execute(r#"FROM Accounts WHERE holder = 'Alice' OR holder = 'Carol' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   Balances within transaction:");
             for row in &result.rows {
@@ -1,4 +1,4 @@
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::fs;
 use std::path::Path;
@@ -21,11 +21,7 @@ fn main() {
 
     // Create database with WAL
     let graph = Arc::new(RwLock::new(Graph::new()));
-    let wal_manager = Some(Arc::new(
-        WALManager::new(wal_dir).expect("Failed to create WAL manager")
-    ));
-
-    let executor = DQLExecutor::with_wal(graph.clone(), wal_manager.clone());
+    let executor = DQLExecutor::new_with_wal(graph.clone(), wal_dir).expect("Failed to create WAL manager");
 
     println!("   ✓ Database initialized with WAL\n");
 
@@ -88,11 +84,7 @@ fn main() {
 
     // Create new database instance
     let graph2 = Arc::new(RwLock::new(Graph::new()));
-    let wal_manager2 = Some(Arc::new(
-        WALManager::new(wal_dir).expect("Failed to create WAL manager")
-    ));
-
-    let executor2 = DQLExecutor::with_wal(graph2.clone(), wal_manager2.clone());
+    let executor2 = DQLExecutor::new_with_wal(graph2.clone(), wal_dir).expect("Failed to create WAL manager");
 
     // Recover from WAL
     println!("   📖 Reading WAL entries...");
@@ -123,14 +115,14 @@ fn main() {
 
     executor2.execute(r#"BEGIN TRANSACTION"#).ok();
 
-    executor2.execute(r#"UPDATE Accounts SET balance = 1200 WHERE holder = "Alice""#).ok();
+    executor2.execute(r#"UPDATE Accounts SET balance = 1200 WHERE holder = 'Alice'"#).ok();
 
     executor2.execute(r#"COMMIT"#).expect("Commit failed");
 
     println!("   ✓ Updated Alice's balance to 1200");
 
     // Verify update
-    match executor2.execute(r#"FROM Accounts WHERE holder = "Alice" SELECT holder, balance"#) {
+    match executor2.execute(r#"FROM Accounts WHERE holder = 'Alice' SELECT holder, balance"#) {
         Ok(result) => {
             println!("   ✓ Verified update:");
             for row in &result.rows {
@@ -2,18 +2,40 @@
 //!
 //! CLI-based administration dashboard for monitoring and managing Deed database.
 //! Provides real-time statistics, metrics, and management capabilities.
+//!
+//! The same statistics can be served over HTTP for operators and monitoring
+//! (see [`AdminDashboard::serve`]): `GET /stats` as JSON, `GET /health` for
+//! load-balancer checks and `GET /metrics` in the Prometheus text format.
 
 use crate::graph::Graph;
-use crate::auth::{AuthManager, Role};
+use crate::auth::{constant_time_eq, AuthManager, Role};
 use crate::connection_pool::{ConnectionPool, PoolStats};
 use crate::replication::{ReplicationManager, ReplicationStats, NodeRole};
-use crate::backup::{BackupManager, BackupMetadata};
+use crate::backup::{BackupMetadata, BackupScheduler, BackupStatus};
+use crate::btree::{RebuildPhase, RebuildProgress};
+use crate::query_metrics::{QueryMetrics, QueryMetricsSnapshot};
 use crate::transaction::TransactionManager;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::wal::WALManager;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Largest request head (request line and headers) the HTTP endpoint reads
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Time a client has to send its request before it is disconnected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
 
 /// Dashboard statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DashboardStats {
     /// Database statistics
     pub database: DatabaseStats,
@@ -25,11 +47,13 @@ pub struct DashboardStats {
     pub auth: AuthStats,
     /// Transaction statistics
     pub transactions: TransactionStats,
+    /// Scheduled backup status
+    pub backups: Option<BackupStatus>,
     /// System uptime
     pub uptime_seconds: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DatabaseStats {
     pub entity_count: usize,
     pub edge_count: usize,
@@ -37,32 +61,120 @@ pub struct DatabaseStats {
     pub collections: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AuthStats {
     pub total_users: usize,
     pub active_sessions: usize,
     pub users_by_role: RoleBreakdown,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RoleBreakdown {
     pub admin_count: usize,
     pub readwrite_count: usize,
     pub readonly_count: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TransactionStats {
     pub active_transactions: usize,
     pub committed_transactions: usize,
     pub rollbacked_transactions: usize,
 }
 
+/// Whether a node should take traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Serving, with replicas within the configured lag
+    Ok,
+    /// Serving, but a replica lags more than the configured maximum
+    Lagging,
+    /// A query panicked holding the graph's lock; every later one would fail
+    Unavailable,
+}
+
+/// Liveness and lag indicators, as served on `/health`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub uptime_seconds: u64,
+    /// Size of the write-ahead log in bytes (it shrinks at each checkpoint)
+    pub wal_log_bytes: Option<u64>,
+    /// Whether the checkpoint policy calls for a checkpoint
+    pub wal_checkpoint_due: Option<bool>,
+    pub replication_role: Option<NodeRole>,
+    /// Lag of the furthest-behind replica (masters only)
+    pub replication_lag_ms: Option<u64>,
+}
+
+/// The parts of a running database the HTTP endpoint reports on
+#[derive(Clone)]
+pub struct DashboardSources {
+    pub graph: Arc<RwLock<Graph>>,
+    pub auth: Arc<AuthManager>,
+    pub transactions: Arc<TransactionManager>,
+    pub pool: Option<Arc<ConnectionPool>>,
+    pub replication: Option<Arc<ReplicationManager>>,
+    pub wal: Option<Arc<WALManager>>,
+    pub backups: Option<Arc<BackupScheduler>>,
+}
+
+impl DashboardSources {
+    pub fn new(graph: Arc<RwLock<Graph>>, auth: Arc<AuthManager>, transactions: Arc<TransactionManager>) -> Self {
+        DashboardSources {
+            graph,
+            auth,
+            transactions,
+            pool: None,
+            replication: None,
+            wal: None,
+            backups: None,
+        }
+    }
+
+    pub fn with_pool(mut self, pool: Arc<ConnectionPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn with_replication(mut self, replication: Arc<ReplicationManager>) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    pub fn with_wal(mut self, wal: Arc<WALManager>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    pub fn with_backups(mut self, backups: Arc<BackupScheduler>) -> Self {
+        self.backups = Some(backups);
+        self
+    }
+}
+
+/// HTTP endpoint settings
+#[derive(Debug, Clone, Default)]
+pub struct DashboardConfig {
+    /// Token requests must present as `Authorization: Bearer <token>`;
+    /// without one, anyone who can connect is served
+    pub bearer_token: Option<String>,
+    /// Replica lag past which `/health` reports the node as lagging
+    pub max_replication_lag: Option<Duration>,
+}
+
 /// Admin dashboard
 pub struct AdminDashboard {
     start_time: u64,
 }
 
+impl Default for AdminDashboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AdminDashboard {
     pub fn new() -> Self {
         AdminDashboard {
@@ -85,10 +197,171 @@ impl AdminDashboard {
             replication: replication.map(|r| r.stats()),
             auth: self.get_auth_stats(auth),
             transactions: self.get_transaction_stats(transaction_mgr),
-            uptime_seconds: current_timestamp() - self.start_time,
+            backups: None,
+            uptime_seconds: self.uptime_seconds(),
         }
     }
 
+    /// Get dashboard statistics of the parts of a running database
+    pub fn stats_of(&self, sources: &DashboardSources) -> DashboardStats {
+        // A poisoned lock still holds readable counts
+        let graph = sources.graph.read().unwrap_or_else(PoisonError::into_inner);
+        let stats = self.get_stats(
+            &graph,
+            &sources.auth,
+            sources.pool.as_deref(),
+            sources.replication.as_deref(),
+            &sources.transactions,
+        );
+        DashboardStats {
+            backups: sources.backups.as_ref().map(|b| b.status()),
+            ..stats
+        }
+    }
+
+    /// Check whether the database should take traffic
+    pub fn health(&self, sources: &DashboardSources, config: &DashboardConfig) -> HealthReport {
+        let replication = sources.replication.as_ref().map(|r| r.stats());
+        let replication_lag_ms = replication
+            .as_ref()
+            .filter(|r| r.role == NodeRole::Master)
+            .map(|r| r.max_slave_lag_ms);
+        let lagging = match (replication_lag_ms, config.max_replication_lag) {
+            (Some(lag), Some(max_lag)) => lag > max_lag.as_millis() as u64,
+            _ => false,
+        };
+
+        let status = if sources.graph.is_poisoned() {
+            HealthStatus::Unavailable
+        } else if lagging {
+            HealthStatus::Lagging
+        } else {
+            HealthStatus::Ok
+        };
+
+        HealthReport {
+            status,
+            uptime_seconds: self.uptime_seconds(),
+            wal_log_bytes: sources.wal.as_ref().and_then(|wal| wal.log_size().ok()),
+            wal_checkpoint_due: sources.wal.as_ref().and_then(|wal| wal.checkpoint_due().ok()),
+            replication_role: replication.map(|r| r.role),
+            replication_lag_ms,
+        }
+    }
+
+    /// Serve statistics over HTTP on `addr` (port 0 picks a free port)
+    ///
+    /// `GET /stats` returns [`DashboardStats`] as JSON, `GET /health` a
+    /// [`HealthReport`] (with status 503 unless the node is ok) and
+    /// `GET /metrics` the same numbers in the Prometheus text format. With a
+    /// bearer token configured, requests without it get 401.
+    pub async fn serve<A: ToSocketAddrs>(
+        self,
+        addr: A,
+        sources: DashboardSources,
+        config: DashboardConfig,
+    ) -> Result<DashboardHandle, String> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Failed to bind dashboard: {}", e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to read dashboard address: {}", e))?;
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let endpoint = Arc::new(Endpoint {
+            dashboard: self,
+            sources,
+            config,
+        });
+        let accept_loop = tokio::spawn(accept_requests(listener, endpoint, shutdown_rx));
+
+        Ok(DashboardHandle {
+            local_addr,
+            shutdown,
+            accept_loop,
+        })
+    }
+
+    /// Format statistics in the Prometheus text exposition format
+    pub fn format_prometheus(&self, stats: &DashboardStats, health: &HealthReport) -> String {
+        let mut metrics = PrometheusText::default();
+
+        metrics.gauge("deed_up", "Whether the database can serve queries", (health.status != HealthStatus::Unavailable) as u8 as f64);
+        metrics.gauge("deed_uptime_seconds", "Seconds since the dashboard started", stats.uptime_seconds as f64);
+
+        metrics.gauge("deed_entities", "Entities in the graph", stats.database.entity_count as f64);
+        metrics.gauge("deed_edges", "Edges in the graph", stats.database.edge_count as f64);
+        metrics.gauge("deed_properties", "Properties over all entities", stats.database.total_properties as f64);
+        metrics.gauge("deed_collections", "Collections holding at least one entity", stats.database.collections.len() as f64);
+
+        let roles = &stats.auth.users_by_role;
+        metrics.family(
+            "deed_users",
+            "gauge",
+            "Users by role",
+            &[
+                ("role=\"admin\"", roles.admin_count as f64),
+                ("role=\"readwrite\"", roles.readwrite_count as f64),
+                ("role=\"readonly\"", roles.readonly_count as f64),
+            ],
+        );
+        metrics.gauge("deed_sessions_active", "Unexpired sessions", stats.auth.active_sessions as f64);
+
+        let transactions = &stats.transactions;
+        metrics.gauge("deed_transactions_active", "Open transactions", transactions.active_transactions as f64);
+        metrics.counter("deed_transactions_committed_total", "Committed transactions", transactions.committed_transactions as f64);
+        metrics.counter("deed_transactions_rolled_back_total", "Rolled back transactions", transactions.rollbacked_transactions as f64);
+
+        if let Some(pool) = &stats.pool {
+            metrics.family(
+                "deed_pool_connections",
+                "gauge",
+                "Pooled connections by state",
+                &[
+                    ("state=\"active\"", pool.active_connections as f64),
+                    ("state=\"idle\"", pool.idle_connections as f64),
+                ],
+            );
+            metrics.gauge("deed_pool_max_connections", "Largest size of the pool", pool.max_size as f64);
+            metrics.gauge("deed_pool_queue_depth", "Callers waiting for a connection", pool.queue_depth as f64);
+            metrics.gauge("deed_pool_average_wait_seconds", "Mean time taken to get a connection", pool.avg_wait_time.as_secs_f64());
+            metrics.counter("deed_pool_waits_total", "Callers that queued for a connection", pool.waits as f64);
+            metrics.counter("deed_pool_handoffs_total", "Returned connections passed straight to a queued caller", pool.handoffs as f64);
+            metrics.counter("deed_pool_evictions_total", "Idle connections closed", pool.evictions as f64);
+            metrics.counter("deed_pool_failed_health_checks_total", "Connections that failed a health check", pool.failed_health_checks as f64);
+            metrics.counter("deed_pool_replacements_total", "Broken connections replaced", pool.replacements as f64);
+        }
+
+        if let Some(replication) = &stats.replication {
+            metrics.gauge("deed_replication_sequence", "Latest replication log sequence", replication.current_seq as f64);
+            metrics.gauge("deed_replication_log_entries", "Entries held in the replication log", replication.log_size as f64);
+            metrics.gauge("deed_replication_replicas", "Registered replicas", replication.slave_count as f64);
+        }
+        if let Some(lag_ms) = health.replication_lag_ms {
+            metrics.gauge("deed_replication_max_lag_seconds", "Lag of the furthest-behind replica", lag_ms as f64 / 1000.0);
+        }
+
+        if let Some(bytes) = health.wal_log_bytes {
+            metrics.gauge("deed_wal_log_bytes", "Size of the write-ahead log", bytes as f64);
+        }
+        if let Some(due) = health.wal_checkpoint_due {
+            metrics.gauge("deed_wal_checkpoint_due", "Whether a checkpoint is due", due as u8 as f64);
+        }
+
+        if let Some(backups) = &stats.backups {
+            if let Some(time) = backups.last_backup_time {
+                metrics.gauge("deed_backup_last_success_timestamp_seconds", "When the last scheduled backup succeeded", time as f64);
+            }
+            metrics.gauge("deed_backup_last_run_failed", "Whether the last scheduled backup failed", backups.last_error.is_some() as u8 as f64);
+            if let Some(time) = backups.next_run {
+                metrics.gauge("deed_backup_next_run_timestamp_seconds", "When the next scheduled backup is due", time as f64);
+            }
+        }
+
+        metrics.output
+    }
+
     /// Format dashboard as CLI output
     pub fn format_dashboard(&self, stats: &DashboardStats) -> String {
         let mut output = String::new();
@@ -169,6 +442,87 @@ impl AdminDashboard {
         output.push_str(&format!("│ Rolled Back: {:>10}                                      │\n", stats.transactions.rollbacked_transactions));
         output.push_str("└─────────────────────────────────────────────────────────────┘\n\n");
 
+        // Scheduled Backups
+        if let Some(backups) = &stats.backups {
+            let time = |t: Option<u64>| t.map_or_else(|| "never".to_string(), format_timestamp);
+            output.push_str("┌─ BACKUPS ───────────────────────────────────────────────────┐\n");
+            output.push_str(&format!("│ Last:   {}                              │\n", pad_right(&time(backups.last_backup_time), 23)));
+            output.push_str(&format!("│ Result: {}                              │\n", pad_right(if backups.last_error.is_some() { "failed" } else { "ok" }, 23)));
+            output.push_str(&format!("│ Next:   {}                              │\n", pad_right(&time(backups.next_run), 23)));
+            output.push_str("└─────────────────────────────────────────────────────────────┘\n\n");
+        }
+
+        output
+    }
+
+    /// Get query latency, row and slow-query statistics
+    pub fn get_query_stats(&self, metrics: &QueryMetrics) -> QueryMetricsSnapshot {
+        metrics.snapshot()
+    }
+
+    /// Format query statistics and the slow-query log
+    pub fn format_query_stats(&self, stats: &QueryMetricsSnapshot) -> String {
+        let mut output = String::new();
+
+        output.push_str("┌─ QUERIES ───────────────────────────────────────────────────┐\n");
+        output.push_str(&format!("│ Total:       {:>10}  (failed: {:>6})                   │\n", stats.total_queries, stats.failed_queries));
+        output.push_str(&format!("│ Plan Cache:  {:>10} hits / {:>6} misses               │\n", stats.cache_hits, stats.cache_misses));
+        output.push_str(&format!("│ Auto-commit: {:>10}                                      │\n", stats.auto_commits));
+        output.push_str(&format!("│ Rows:        {:>10} scanned / {:>10} returned      │\n", stats.rows_scanned, stats.rows_returned));
+        output.push_str(&format!("│ Latency:     p50 {} p95 {} p99 {}        │\n",
+            format_latency(stats.p50),
+            format_latency(stats.p95),
+            format_latency(stats.p99)
+        ));
+        output.push_str("└─────────────────────────────────────────────────────────────┘\n\n");
+
+        output.push_str(&format!("┌─ SLOW QUERIES (>= {}) ──────────────────────────────────┐\n",
+            format_latency(stats.slow_query_threshold)
+        ));
+
+        if stats.slow_queries.is_empty() {
+            output.push_str("│ No slow queries.                                            │\n");
+        }
+
+        for query in &stats.slow_queries {
+            output.push_str(&format!("│ {} {}  │\n",
+                format_latency(query.elapsed),
+                pad_right(&query.text, 47)
+            ));
+            output.push_str(&format!("│            {}  │\n", pad_right(&query.plan, 47)));
+        }
+
+        output.push_str("└─────────────────────────────────────────────────────────────┘\n\n");
+        output
+    }
+
+    /// Format index rebuild progress (REINDEX)
+    pub fn format_index_rebuilds(&self, rebuilds: &[RebuildProgress]) -> String {
+        let mut output = String::new();
+
+        output.push_str("┌─ INDEX REBUILDS ────────────────────────────────────────────┐\n");
+
+        if rebuilds.is_empty() {
+            output.push_str("│ No index rebuilds.                                          │\n");
+        }
+
+        for rebuild in rebuilds {
+            let phase = match rebuild.phase {
+                RebuildPhase::Scanning => "scanning",
+                RebuildPhase::CatchingUp => "catching up",
+                RebuildPhase::Swapped => "swapped",
+                RebuildPhase::Aborted => "aborted",
+            };
+            output.push_str(&format!("│ {} {:<12} {} {:>7}/{:<7}│\n",
+                pad_right(&rebuild.index_name, 16),
+                phase,
+                format_bar(rebuild.scanned, rebuild.total, 16),
+                rebuild.scanned,
+                rebuild.total
+            ));
+        }
+
+        output.push_str("└─────────────────────────────────────────────────────────────┘\n\n");
         output
     }
 
@@ -198,6 +552,10 @@ impl AdminDashboard {
         output
     }
 
+    fn uptime_seconds(&self) -> u64 {
+        current_timestamp().saturating_sub(self.start_time)
+    }
+
     fn get_database_stats(&self, graph: &Graph) -> DatabaseStats {
         let entities = graph.get_all_entities();
         let edges = graph.get_all_edges();
@@ -211,11 +569,14 @@ impl AdminDashboard {
             collections.insert(entity.entity_type.clone());
         }
 
+        let mut collections: Vec<String> = collections.into_iter().collect();
+        collections.sort();
+
         DatabaseStats {
             entity_count: entities.len(),
             edge_count: edges.len(),
             total_properties,
-            collections: collections.into_iter().collect(),
+            collections,
         }
     }
 
@@ -257,6 +618,229 @@ impl AdminDashboard {
     }
 }
 
+/// A running HTTP endpoint
+pub struct DashboardHandle {
+    local_addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+    accept_loop: JoinHandle<()>,
+}
+
+impl DashboardHandle {
+    /// Address the endpoint listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting requests
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.accept_loop.await;
+    }
+}
+
+/// What a request is answered from
+struct Endpoint {
+    dashboard: AdminDashboard,
+    sources: DashboardSources,
+    config: DashboardConfig,
+}
+
+/// The parts of an HTTP request the endpoint reads
+struct RequestHead {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl HttpResponse {
+    fn text(status: u16, body: &str) -> Self {
+        HttpResponse {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", body),
+        }
+    }
+
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => HttpResponse {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => HttpResponse::text(500, &format!("Failed to encode response: {}", e)),
+        }
+    }
+}
+
+async fn accept_requests(listener: TcpListener, endpoint: Arc<Endpoint>, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Failed to accept dashboard connection: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => return,
+        };
+
+        tokio::spawn(handle_connection(stream, endpoint.clone()));
+    }
+}
+
+/// Answer one request, then close the connection
+async fn handle_connection(mut stream: TcpStream, endpoint: Arc<Endpoint>) {
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(Ok(request)) => endpoint.respond(request).await,
+        Ok(Err(e)) => HttpResponse::text(400, &e),
+        Err(_) => return,
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    );
+    if response.status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str("\r\n");
+
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+async fn read_request_head(stream: &mut TcpStream) -> Result<RequestHead, String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() > MAX_REQUEST_HEAD {
+            return Err(format!("Request head exceeds {} bytes", MAX_REQUEST_HEAD));
+        }
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| format!("Failed to read request: {}", e))?;
+        if read == 0 {
+            return Err("Connection closed before the request ended".to_string());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (request_line.next(), request_line.next(), request_line.next()) else {
+        return Err("Malformed request line".to_string());
+    };
+
+    let authorization = lines.take_while(|line| !line.is_empty()).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("authorization").then(|| value.trim().to_string())
+    });
+
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or(target).to_string(),
+        authorization,
+    })
+}
+
+impl Endpoint {
+    async fn respond(self: &Arc<Self>, request: RequestHead) -> HttpResponse {
+        if let Some(token) = &self.config.bearer_token {
+            let presented = request.authorization.as_deref().and_then(|value| value.strip_prefix("Bearer "));
+            // Compared as digests, in constant time, so neither the token's bytes nor its length leak
+            let matches = presented.is_some_and(|presented| {
+                constant_time_eq(&Sha256::digest(presented.as_bytes()), &Sha256::digest(token.as_bytes()))
+            });
+            if !matches {
+                return HttpResponse::text(401, "Missing or invalid bearer token");
+            }
+        }
+        if !matches!(request.path.as_str(), "/stats" | "/health" | "/metrics") {
+            return HttpResponse::text(404, &format!("No such endpoint: {}", request.path));
+        }
+        if request.method != "GET" {
+            return HttpResponse::text(405, &format!("{} only supports GET", request.path));
+        }
+
+        // Gathering statistics takes locks and scans the graph
+        let endpoint = self.clone();
+        tokio::task::spawn_blocking(move || endpoint.render(&request.path))
+            .await
+            .unwrap_or_else(|_| HttpResponse::text(500, "Failed to gather statistics"))
+    }
+
+    fn render(&self, path: &str) -> HttpResponse {
+        match path {
+            "/stats" => HttpResponse::json(200, &self.dashboard.stats_of(&self.sources)),
+            "/health" => {
+                let health = self.dashboard.health(&self.sources, &self.config);
+                let status = if health.status == HealthStatus::Ok { 200 } else { 503 };
+                HttpResponse::json(status, &health)
+            }
+            _ => {
+                let stats = self.dashboard.stats_of(&self.sources);
+                let health = self.dashboard.health(&self.sources, &self.config);
+                HttpResponse {
+                    status: 200,
+                    content_type: PROMETHEUS_CONTENT_TYPE,
+                    body: self.dashboard.format_prometheus(&stats, &health),
+                }
+            }
+        }
+    }
+}
+
+/// Prometheus text exposition, written one metric family at a time
+#[derive(Default)]
+struct PrometheusText {
+    output: String,
+}
+
+impl PrometheusText {
+    /// A family's HELP and TYPE lines, then one sample per label set
+    fn family(&mut self, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+        self.output.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                self.output.push_str(&format!("{} {}\n", name, value));
+            } else {
+                self.output.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, "gauge", help, &[("", value)]);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, "counter", help, &[("", value)]);
+    }
+}
+
 // Helper functions for formatting
 
 fn format_duration(seconds: u64) -> String {
@@ -276,23 +860,28 @@ fn format_duration(seconds: u64) -> String {
     }
 }
 
+fn format_latency(latency: Duration) -> String {
+    let micros = latency.as_micros();
+
+    if micros >= 1_000_000 {
+        format!("{:>7.2}s ", latency.as_secs_f64())
+    } else if micros >= 1_000 {
+        format!("{:>7.2}ms", micros as f64 / 1_000.0)
+    } else {
+        format!("{:>7}µs", micros)
+    }
+}
+
 fn format_timestamp(timestamp: u64) -> String {
-    use chrono::{DateTime, Utc, NaiveDateTime};
+    use chrono::DateTime;
 
-    let dt = DateTime::<Utc>::from_utc(
-        NaiveDateTime::from_timestamp(timestamp as i64, 0),
-        Utc
-    );
+    let dt = DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default();
 
     dt.format("%Y-%m-%d %H:%M:%S UTC").to_string()
 }
 
 fn format_bar(value: usize, max: usize, width: usize) -> String {
-    let filled = if max > 0 {
-        (value * width) / max
-    } else {
-        0
-    };
+    let filled = (value * width).checked_div(max).unwrap_or(0);
 
     let mut bar = String::new();
     bar.push('[');
@@ -347,6 +936,13 @@ mod tests {
         assert_eq!(format_bar(10, 10, 10), "[██████████]");
     }
 
+    #[test]
+    fn test_format_latency() {
+        assert_eq!(format_latency(Duration::from_micros(250)), "    250µs");
+        assert_eq!(format_latency(Duration::from_micros(12_500)), "  12.50ms");
+        assert_eq!(format_latency(Duration::from_millis(2_500)), "   2.50s ");
+    }
+
     #[test]
     fn test_pad_right() {
         assert_eq!(pad_right("test", 10), "test      ");
@@ -0,0 +1,706 @@
+//! Archive Tier (cold storage)
+//!
+//! Moves rarely-queried entities out of the in-memory graph into compact
+//! archive chunks while keeping a lightweight stub index hot.
+//! - Chunks: columnar, one zstd-compressed column per property plus entity
+//!   metadata and edges, behind an uncompressed header of entity IDs
+//! - Layout: `collection/column=value/id.seg` under the archive directory,
+//!   one partition per value of the column chosen at ARCHIVE, or in memory
+//! - Stub index: archived entity IDs per chunk, rebuilt from headers alone
+
+use crate::graph::{Edge, Entity};
+use crate::types::{EntityId, PropertyValue};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// First bytes of every chunk
+const CHUNK_MAGIC: &[u8; 4] = b"DARC";
+
+/// zstd level columns are compressed at
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// An archived entity together with the edges that touched it when archived
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedEntity {
+    pub entity: Entity,
+    pub edges: Vec<Edge>,
+}
+
+/// The partition a chunk belongs to: a column and the value its entities share
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ArchivePartition {
+    pub column: String,
+    /// Timestamps by calendar month (`2021-07`), `null` when unset, other values as written
+    pub value: String,
+}
+
+impl ArchivePartition {
+    /// The partition `entity` falls in when partitioned by `column`
+    fn of(entity: &Entity, column: &str) -> Result<Self, String> {
+        use chrono::{TimeZone, Utc};
+
+        let value = match entity.properties.get(column) {
+            None | Some(PropertyValue::Null) => "null".to_string(),
+            Some(PropertyValue::Timestamp(ms)) => match Utc.timestamp_millis_opt(*ms).single() {
+                Some(at) => at.format("%Y-%m").to_string(),
+                None => ms.to_string(),
+            },
+            Some(PropertyValue::String(s)) => s.clone(),
+            Some(PropertyValue::Int(i)) => i.to_string(),
+            Some(PropertyValue::Float(f)) => f.to_string(),
+            Some(PropertyValue::Bool(b)) => b.to_string(),
+            Some(_) => {
+                return Err(format!(
+                    "Can't partition the archive by '{}': list, map and bytes values aren't partition keys",
+                    column
+                ))
+            }
+        };
+
+        Ok(ArchivePartition {
+            column: column.to_string(),
+            value,
+        })
+    }
+}
+
+/// Decoded chunk contents
+struct SegmentData {
+    collection: String,
+    partition: Option<ArchivePartition>,
+    entities: Vec<ArchivedEntity>,
+}
+
+/// Uncompressed front of a chunk; enough to rebuild the stub index
+#[derive(Serialize, Deserialize)]
+struct ChunkHeader {
+    collection: String,
+    partition: Option<ArchivePartition>,
+    ids: Vec<EntityId>,
+    /// The columns that follow, in order
+    columns: Vec<ColumnMeta>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ColumnMeta {
+    column: Column,
+    /// Compressed length in bytes
+    length: u64,
+}
+
+/// What a column holds, one value per entity in header order
+#[derive(Serialize, Deserialize)]
+enum Column {
+    /// Entity fields other than properties
+    Metadata,
+    /// Edges that touched each entity
+    Edges,
+    /// A property's values; `None` where the entity doesn't set it
+    Property(String),
+}
+
+/// Entity fields kept beside the property columns
+#[derive(Serialize, Deserialize)]
+struct EntityMetadata {
+    access_count: u64,
+    last_accessed: SystemTime,
+    created_at: SystemTime,
+}
+
+/// Where a segment's bytes live
+enum SegmentStorage {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+/// Stub for one archive segment - only the IDs stay in memory
+struct SegmentStub {
+    id: u64,
+    partition: Option<ArchivePartition>,
+    entity_ids: HashSet<EntityId>,
+    storage: SegmentStorage,
+}
+
+/// Archive manager
+pub struct ArchiveManager {
+    dir: Option<PathBuf>,
+    segments: RwLock<HashMap<String, Vec<SegmentStub>>>,
+    next_segment_id: AtomicU64,
+}
+
+impl ArchiveManager {
+    /// Create an archive that keeps compressed segments in memory
+    pub fn in_memory() -> Self {
+        ArchiveManager {
+            dir: None,
+            segments: RwLock::new(HashMap::new()),
+            next_segment_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Open (or create) an on-disk archive, rebuilding the stub index from
+    /// its chunk headers
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create archive directory: {}", e))?;
+
+        let mut segments: HashMap<String, Vec<SegmentStub>> = HashMap::new();
+        let mut next_segment_id = 0;
+
+        for path in segment_files_in(&dir)? {
+            let id = segment_id_from_path(&path)
+                .ok_or_else(|| format!("Invalid archive segment name: {}", path.display()))?;
+            let file = File::open(&path)
+                .map_err(|e| format!("Failed to read archive segment: {}", e))?;
+            let header = read_header(&mut BufReader::new(file))?;
+
+            next_segment_id = next_segment_id.max(id + 1);
+            segments.entry(header.collection).or_default().push(SegmentStub {
+                id,
+                partition: header.partition,
+                entity_ids: header.ids.into_iter().collect(),
+                storage: SegmentStorage::File(path),
+            });
+        }
+
+        for stubs in segments.values_mut() {
+            stubs.sort_by_key(|stub| stub.id);
+        }
+
+        Ok(ArchiveManager {
+            dir: Some(dir),
+            segments: RwLock::new(segments),
+            next_segment_id: AtomicU64::new(next_segment_id),
+        })
+    }
+
+    /// Archive a batch of entities from one collection, as one chunk per
+    /// partition of `partition_by`, or a single chunk without one
+    pub fn archive(
+        &self,
+        collection: &str,
+        partition_by: Option<&str>,
+        entities: Vec<ArchivedEntity>,
+    ) -> Result<usize, String> {
+        let count = entities.len();
+        let mut partitions: BTreeMap<Option<ArchivePartition>, Vec<ArchivedEntity>> = BTreeMap::new();
+        for archived in entities {
+            let partition = partition_by
+                .map(|column| ArchivePartition::of(&archived.entity, column))
+                .transpose()?;
+            partitions.entry(partition).or_default().push(archived);
+        }
+
+        let mut written = Vec::with_capacity(partitions.len());
+        for (partition, entities) in partitions {
+            let id = self.next_segment_id.fetch_add(1, Ordering::SeqCst);
+            match self.write_segment(id, collection, partition, entities) {
+                Ok(stub) => written.push(stub),
+                Err(e) => {
+                    // All or nothing: chunks already written would hold rows still hot
+                    for stub in &written {
+                        self.remove_segment(stub)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        self.segments.write().unwrap()
+            .entry(collection.to_string())
+            .or_default()
+            .extend(written);
+
+        Ok(count)
+    }
+
+    /// Number of archived entities in a collection (from the stub index)
+    pub fn archived_count(&self, collection: &str) -> usize {
+        self.segments.read().unwrap()
+            .get(collection)
+            .map(|stubs| stubs.iter().map(|s| s.entity_ids.len()).sum())
+            .unwrap_or(0)
+    }
+
+    /// Check whether an entity is archived
+    pub fn is_archived(&self, collection: &str, id: EntityId) -> bool {
+        self.segments.read().unwrap()
+            .get(collection)
+            .map(|stubs| stubs.iter().any(|s| s.entity_ids.contains(&id)))
+            .unwrap_or(false)
+    }
+
+    /// Collections that have archived entities
+    pub fn collections(&self) -> Vec<String> {
+        self.segments.read().unwrap()
+            .iter()
+            .filter(|(_, stubs)| !stubs.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Partitions a collection's archived entities are kept in, in order
+    pub fn partitions(&self, collection: &str) -> Vec<ArchivePartition> {
+        let segments = self.segments.read().unwrap();
+        let partitions: BTreeSet<ArchivePartition> = segments
+            .get(collection)
+            .into_iter()
+            .flatten()
+            .filter_map(|stub| stub.partition.clone())
+            .collect();
+        partitions.into_iter().collect()
+    }
+
+    /// Read every archived entity of a collection, one segment at a time
+    pub fn scan(&self, collection: &str) -> Result<Vec<ArchivedEntity>, String> {
+        let mut result = Vec::new();
+        self.scan_with(collection, |archived| {
+            result.push(archived);
+            Ok::<_, String>(true)
+        })?;
+        Ok(result)
+    }
+
+    /// Hand each archived entity of a collection to `visit`, decoding one
+    /// segment at a time, until it returns false
+    pub fn scan_with<E: From<String>>(
+        &self,
+        collection: &str,
+        mut visit: impl FnMut(ArchivedEntity) -> Result<bool, E>,
+    ) -> Result<(), E> {
+        let segments = self.segments.read().unwrap();
+        for stub in segments.get(collection).into_iter().flatten() {
+            for archived in read_segment(&stub.storage)?.entities {
+                if !visit(archived)? {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove and return archived entities matching a predicate (for UNARCHIVE)
+    pub fn restore<F>(&self, collection: &str, predicate: F) -> Result<Vec<ArchivedEntity>, String>
+    where
+        F: Fn(&Entity) -> bool,
+    {
+        let mut segments = self.segments.write().unwrap();
+        let stubs = match segments.get_mut(collection) {
+            Some(stubs) => stubs,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut restored = Vec::new();
+        let mut kept_stubs = Vec::new();
+
+        for stub in stubs.drain(..) {
+            let data = read_segment(&stub.storage)?;
+            let (matched, kept): (Vec<_>, Vec<_>) = data.entities
+                .into_iter()
+                .partition(|archived| predicate(&archived.entity));
+
+            if matched.is_empty() {
+                kept_stubs.push(stub);
+                continue;
+            }
+
+            restored.extend(matched);
+
+            // Rewrite the segment without the restored entities, or drop it
+            self.remove_segment(&stub)?;
+            if !kept.is_empty() {
+                kept_stubs.push(self.write_segment(stub.id, collection, data.partition, kept)?);
+            }
+        }
+
+        *stubs = kept_stubs;
+        Ok(restored)
+    }
+
+    /// Paths of all on-disk segment files (empty for an in-memory archive)
+    pub fn segment_files(&self) -> Vec<PathBuf> {
+        self.segments.read().unwrap()
+            .values()
+            .flatten()
+            .filter_map(|stub| match &stub.storage {
+                SegmentStorage::File(path) => Some(path.clone()),
+                SegmentStorage::Memory(_) => None,
+            })
+            .collect()
+    }
+
+    /// Write every segment to a directory, laid out as in an on-disk archive
+    pub fn export_to<P: AsRef<Path>>(&self, dir: P) -> Result<usize, String> {
+        let dir = dir.as_ref();
+        create_dir_all(dir)
+            .map_err(|e| format!("Failed to create archive export directory: {}", e))?;
+
+        let segments = self.segments.read().unwrap();
+        let mut count = 0;
+
+        for (collection, stubs) in segments.iter() {
+            for stub in stubs {
+                let bytes = match &stub.storage {
+                    SegmentStorage::File(path) => std::fs::read(path)
+                        .map_err(|e| format!("Failed to read archive segment: {}", e))?,
+                    SegmentStorage::Memory(bytes) => bytes.clone(),
+                };
+                write_file(&dir.join(segment_path(collection, stub.partition.as_ref(), stub.id)), &bytes)?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn write_segment(
+        &self,
+        id: u64,
+        collection: &str,
+        partition: Option<ArchivePartition>,
+        entities: Vec<ArchivedEntity>,
+    ) -> Result<SegmentStub, String> {
+        let entity_ids = entities.iter().map(|a| a.entity.id).collect();
+        let bytes = encode_segment(&SegmentData {
+            collection: collection.to_string(),
+            partition: partition.clone(),
+            entities,
+        })?;
+
+        let storage = match &self.dir {
+            Some(dir) => {
+                let path = dir.join(segment_path(collection, partition.as_ref(), id));
+                write_file(&path, &bytes)?;
+                SegmentStorage::File(path)
+            }
+            None => SegmentStorage::Memory(bytes),
+        };
+
+        Ok(SegmentStub { id, partition, entity_ids, storage })
+    }
+
+    /// Delete a segment's file, and the partition directories it leaves empty
+    fn remove_segment(&self, stub: &SegmentStub) -> Result<(), String> {
+        let (SegmentStorage::File(path), Some(dir)) = (&stub.storage, &self.dir) else {
+            return Ok(());
+        };
+        std::fs::remove_file(path)
+            .map_err(|e| format!("Failed to remove archive segment: {}", e))?;
+        for parent in path.ancestors().skip(1).take_while(|parent| *parent != dir.as_path()) {
+            if std::fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a segment lives, relative to the archive directory
+fn segment_path(collection: &str, partition: Option<&ArchivePartition>, id: u64) -> PathBuf {
+    let mut path = PathBuf::from(path_component(collection));
+    if let Some(partition) = partition {
+        path.push(format!("{}={}", path_component(&partition.column), path_component(&partition.value)));
+    }
+    path.push(format!("{:08}.{}", id, SEGMENT_EXTENSION));
+    path
+}
+
+/// `name` made safe as a single path component, other bytes as `%XX`
+fn path_component(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn segment_id_from_path(path: &Path) -> Option<u64> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Segment files anywhere under `dir`
+fn segment_files_in(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = read_dir(dir)
+        .map_err(|e| format!("Failed to read archive directory: {}", e))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read archive directory: {}", e))?
+            .path();
+        if path.is_dir() {
+            files.extend(segment_files_in(&path)?);
+        } else if path.extension().and_then(|e| e.to_str()) == Some(SEGMENT_EXTENSION) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)
+            .map_err(|e| format!("Failed to create archive directory: {}", e))?;
+    }
+    std::fs::write(path, bytes)
+        .map_err(|e| format!("Failed to write archive segment: {}", e))
+}
+
+fn read_segment(storage: &SegmentStorage) -> Result<SegmentData, String> {
+    match storage {
+        SegmentStorage::File(path) => {
+            let bytes = std::fs::read(path)
+                .map_err(|e| format!("Failed to read archive segment: {}", e))?;
+            decode_segment(&bytes)
+        }
+        SegmentStorage::Memory(bytes) => decode_segment(bytes),
+    }
+}
+
+/// Lay a segment out as a chunk: magic, header length, header, then each column
+fn encode_segment(data: &SegmentData) -> Result<Vec<u8>, String> {
+    let entities = &data.entities;
+    let properties: BTreeSet<&String> = entities
+        .iter()
+        .flat_map(|archived| archived.entity.properties.keys())
+        .collect();
+
+    let metadata: Vec<EntityMetadata> = entities
+        .iter()
+        .map(|archived| EntityMetadata {
+            access_count: archived.entity.access_count,
+            last_accessed: archived.entity.last_accessed,
+            created_at: archived.entity.created_at,
+        })
+        .collect();
+    let edges: Vec<&Vec<Edge>> = entities.iter().map(|archived| &archived.edges).collect();
+
+    let mut columns = vec![
+        (Column::Metadata, compress_column(&metadata)?),
+        (Column::Edges, compress_column(&edges)?),
+    ];
+    for name in properties {
+        let values: Vec<Option<&PropertyValue>> = entities
+            .iter()
+            .map(|archived| archived.entity.properties.get(name))
+            .collect();
+        columns.push((Column::Property(name.clone()), compress_column(&values)?));
+    }
+
+    let (columns, bodies): (Vec<ColumnMeta>, Vec<Vec<u8>>) = columns
+        .into_iter()
+        .map(|(column, body)| (ColumnMeta { column, length: body.len() as u64 }, body))
+        .unzip();
+    let header = bincode::serialize(&ChunkHeader {
+        collection: data.collection.clone(),
+        partition: data.partition.clone(),
+        ids: entities.iter().map(|archived| archived.entity.id).collect(),
+        columns,
+    })
+    .map_err(|e| format!("Archive serialization error: {}", e))?;
+    let header_len = u32::try_from(header.len())
+        .map_err(|_| "Archive chunk header is too large".to_string())?;
+
+    let mut bytes = Vec::with_capacity(8 + header.len() + bodies.iter().map(Vec::len).sum::<usize>());
+    bytes.extend_from_slice(CHUNK_MAGIC);
+    bytes.extend_from_slice(&header_len.to_le_bytes());
+    bytes.extend_from_slice(&header);
+    for body in bodies {
+        bytes.extend_from_slice(&body);
+    }
+    Ok(bytes)
+}
+
+/// Read a chunk's header, leaving `reader` at its first column
+fn read_header(reader: &mut impl Read) -> Result<ChunkHeader, String> {
+    let mut prefix = [0u8; 8];
+    reader.read_exact(&mut prefix)
+        .map_err(|e| format!("Failed to read archive chunk header: {}", e))?;
+    if &prefix[..4] != CHUNK_MAGIC {
+        return Err("Not an archive chunk".to_string());
+    }
+
+    let header_len = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as usize;
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)
+        .map_err(|e| format!("Failed to read archive chunk header: {}", e))?;
+    bincode::deserialize(&header)
+        .map_err(|e| format!("Archive deserialization error: {}", e))
+}
+
+fn decode_segment(bytes: &[u8]) -> Result<SegmentData, String> {
+    let mut rest = bytes;
+    let header = read_header(&mut rest)?;
+
+    let count = header.ids.len();
+    let mut metadata: Vec<EntityMetadata> = Vec::new();
+    let mut edges: Vec<Vec<Edge>> = Vec::new();
+    let mut properties: Vec<HashMap<String, PropertyValue>> = vec![HashMap::new(); count];
+
+    for ColumnMeta { column, length } in header.columns {
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|length| *length <= rest.len())
+            .ok_or_else(|| "Archive chunk is truncated".to_string())?;
+        let (body, next) = rest.split_at(length);
+        rest = next;
+
+        match column {
+            Column::Metadata => metadata = decompress_column(body, count)?,
+            Column::Edges => edges = decompress_column(body, count)?,
+            Column::Property(name) => {
+                let values: Vec<Option<PropertyValue>> = decompress_column(body, count)?;
+                for (props, value) in properties.iter_mut().zip(values) {
+                    if let Some(value) = value {
+                        props.insert(name.clone(), value);
+                    }
+                }
+            }
+        }
+    }
+    if metadata.len() != count || edges.len() != count {
+        return Err("Archive chunk is missing its metadata or edges".to_string());
+    }
+
+    let entities = header.ids
+        .into_iter()
+        .zip(properties)
+        .zip(metadata.into_iter().zip(edges))
+        .map(|((id, properties), (meta, edges))| ArchivedEntity {
+            entity: Entity {
+                id,
+                entity_type: header.collection.clone(),
+                properties,
+                access_count: meta.access_count,
+                last_accessed: meta.last_accessed,
+                created_at: meta.created_at,
+            },
+            edges,
+        })
+        .collect();
+
+    Ok(SegmentData {
+        collection: header.collection,
+        partition: header.partition,
+        entities,
+    })
+}
+
+fn compress_column<T: Serialize>(values: &T) -> Result<Vec<u8>, String> {
+    let serialized = bincode::serialize(values)
+        .map_err(|e| format!("Archive serialization error: {}", e))?;
+    zstd::bulk::compress(&serialized, COMPRESSION_LEVEL)
+        .map_err(|e| format!("Compression error: {}", e))
+}
+
+/// Decode a column, which must hold one value per entity
+fn decompress_column<T: DeserializeOwned>(body: &[u8], count: usize) -> Result<Vec<T>, String> {
+    let serialized = zstd::stream::decode_all(body)
+        .map_err(|e| format!("Decompression error: {}", e))?;
+    let values: Vec<T> = bincode::deserialize(&serialized)
+        .map_err(|e| format!("Archive deserialization error: {}", e))?;
+    if values.len() != count {
+        return Err("Archive chunk column doesn't match its entities".to_string());
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archived(id: u64, total: i64) -> ArchivedEntity {
+        let mut props = HashMap::new();
+        props.insert("total".to_string(), PropertyValue::Int(total));
+        ArchivedEntity {
+            entity: Entity::new(EntityId(id), "Orders".to_string(), props),
+            edges: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_archive_and_restore_in_memory() {
+        let archive = ArchiveManager::in_memory();
+        archive.archive("Orders", None, vec![archived(1, 10), archived(2, 20)]).unwrap();
+
+        assert_eq!(archive.archived_count("Orders"), 2);
+        assert!(archive.is_archived("Orders", EntityId(2)));
+
+        let restored = archive.restore("Orders", |e| e.id == EntityId(1)).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(archive.archived_count("Orders"), 1);
+        assert_eq!(archive.scan("Orders").unwrap()[0].entity.id, EntityId(2));
+    }
+
+    #[test]
+    fn test_on_disk_archive_reopens() {
+        let dir = PathBuf::from("/tmp/deed_test_archive");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        {
+            let archive = ArchiveManager::open(&dir).unwrap();
+            archive.archive("Orders", None, vec![archived(7, 70)]).unwrap();
+            assert_eq!(archive.segment_files().len(), 1);
+        }
+
+        let reopened = ArchiveManager::open(&dir).unwrap();
+        assert_eq!(reopened.archived_count("Orders"), 1);
+        let entities = reopened.scan("Orders").unwrap();
+        assert_eq!(entities[0].entity.get_property("total"), Some(&PropertyValue::Int(70)));
+    }
+
+    #[test]
+    fn test_chunks_are_columnar_and_partitioned() {
+        let dir = PathBuf::from("/tmp/deed_test_archive_partitions");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut orders = vec![archived(1, 10), archived(2, 20), archived(3, 30)];
+        orders[0].entity.set_property("region".to_string(), PropertyValue::String("eu/west".to_string()));
+        orders[1].entity.set_property("region".to_string(), PropertyValue::String("eu/west".to_string()));
+        orders[1].entity.set_property("note".to_string(), PropertyValue::Null);
+        let originals: Vec<Entity> = orders.iter().map(|a| a.entity.clone()).collect();
+
+        let archive = ArchiveManager::open(&dir).unwrap();
+        archive.archive("Orders", Some("region"), orders).unwrap();
+        let partitions: Vec<String> = archive.partitions("Orders").into_iter().map(|p| p.value).collect();
+        assert_eq!(partitions, vec!["eu/west", "null"]);
+        assert!(dir.join("Orders/region=eu%2Fwest/00000000.seg").exists());
+        assert!(dir.join("Orders/region=null/00000001.seg").exists());
+
+        // One compressed column per property, after the entity metadata and edges
+        let bytes = std::fs::read(dir.join("Orders/region=eu%2Fwest/00000000.seg")).unwrap();
+        let header = read_header(&mut bytes.as_slice()).unwrap();
+        let columns: Vec<String> = header
+            .columns
+            .iter()
+            .map(|meta| match &meta.column {
+                Column::Metadata => "@metadata".to_string(),
+                Column::Edges => "@edges".to_string(),
+                Column::Property(name) => name.clone(),
+            })
+            .collect();
+        assert_eq!(columns, vec!["@metadata", "@edges", "note", "region", "total"]);
+
+        // Missing and null properties come back as they went in
+        let reopened = ArchiveManager::open(&dir).unwrap();
+        let restored = reopened.restore("Orders", |_| true).unwrap();
+        for original in originals {
+            let entity = &restored.iter().find(|a| a.entity.id == original.id).unwrap().entity;
+            assert_eq!(entity.properties, original.properties);
+            assert_eq!(entity.created_at, original.created_at);
+        }
+        assert!(!dir.join("Orders").exists(), "Emptied partitions are removed");
+    }
+}
@@ -0,0 +1,523 @@
+//! Audit Log
+//!
+//! Who changed what: every mutation or DDL statement an authenticated
+//! session runs is recorded with its user, role, statement text, the
+//! collections it touched, rows affected and outcome.
+//! - Storage: in memory, or appended as JSON lines to a file (synced per
+//!   line) and reloaded on open
+//! - Ordering: a statement is recorded as pending before it runs and its
+//!   outcome appended after, so nothing takes effect unrecorded
+//! - Retention: a maximum number of entries and/or a maximum age
+//! - Reads: `AuditLog::query`, or DQL against the read-only `_audit` collection
+
+use crate::auth::Role;
+use crate::dql_ast::{Query, ReindexQuery};
+use crate::types::{Properties, PropertyValue};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+/// System collection DQL reads the audit log through
+pub const AUDIT_COLLECTION: &str = "_audit";
+
+/// Lines of trimmed entries a log file may carry before it is rewritten
+const COMPACTION_SLACK: usize = 1024;
+
+/// How a recorded statement ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditOutcome {
+    /// Recorded before running; stays pending if the process died meanwhile
+    Pending,
+    Succeeded,
+    /// Ran and failed, with the error
+    Failed(String),
+    /// Refused by a permission check, with the error
+    Denied(String),
+}
+
+impl AuditOutcome {
+    fn name(&self) -> &'static str {
+        match self {
+            AuditOutcome::Pending => "pending",
+            AuditOutcome::Succeeded => "succeeded",
+            AuditOutcome::Failed(_) => "failed",
+            AuditOutcome::Denied(_) => "denied",
+        }
+    }
+
+    fn error(&self) -> Option<&str> {
+        match self {
+            AuditOutcome::Pending | AuditOutcome::Succeeded => None,
+            AuditOutcome::Failed(e) | AuditOutcome::Denied(e) => Some(e),
+        }
+    }
+}
+
+/// One audited statement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: i64,
+    pub username: String,
+    pub role: Role,
+    /// Statement text with runs of whitespace collapsed
+    pub statement: String,
+    pub collections: Vec<String>,
+    pub rows_affected: usize,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditEntry {
+    /// The entry as a row of the `_audit` collection
+    pub fn to_properties(&self) -> Properties {
+        let mut properties = Properties::new();
+        properties.insert("timestamp".to_string(), PropertyValue::Timestamp(self.timestamp));
+        properties.insert("user".to_string(), PropertyValue::String(self.username.clone()));
+        properties.insert("role".to_string(), PropertyValue::String(format!("{:?}", self.role)));
+        properties.insert("statement".to_string(), PropertyValue::String(self.statement.clone()));
+        properties.insert(
+            "collections".to_string(),
+            PropertyValue::List(self.collections.iter().cloned().map(PropertyValue::String).collect()),
+        );
+        properties.insert("rows_affected".to_string(), PropertyValue::Int(self.rows_affected as i64));
+        properties.insert("outcome".to_string(), PropertyValue::String(self.outcome.name().to_string()));
+        properties.insert(
+            "error".to_string(),
+            self.outcome.error().map_or(PropertyValue::Null, |e| PropertyValue::String(e.to_string())),
+        );
+        properties
+    }
+}
+
+/// How much history to keep; older entries are dropped first
+#[derive(Debug, Clone)]
+pub struct AuditRetention {
+    pub max_entries: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for AuditRetention {
+    fn default() -> Self {
+        AuditRetention {
+            max_entries: Some(100_000),
+            max_age: None,
+        }
+    }
+}
+
+/// Which entries `AuditLog::query` returns; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub username: Option<String>,
+    /// Earliest timestamp, inclusive (ms since the Unix epoch)
+    pub since: Option<i64>,
+    /// Latest timestamp, exclusive (ms since the Unix epoch)
+    pub until: Option<i64>,
+    /// Entries whose statement touched this collection
+    pub collection: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.username.as_ref().is_none_or(|u| *u == entry.username)
+            && self.since.is_none_or(|t| entry.timestamp >= t)
+            && self.until.is_none_or(|t| entry.timestamp < t)
+            && self.collection.as_ref().is_none_or(|c| entry.collections.contains(c))
+    }
+}
+
+/// An entry as stored, numbered so a later line can settle its outcome
+#[derive(Serialize, Deserialize)]
+struct AuditLine {
+    /// 0 for entries never updated
+    #[serde(default)]
+    seq: u64,
+    #[serde(flatten)]
+    entry: AuditEntry,
+}
+
+/// Append-only log file
+struct AuditFile {
+    path: PathBuf,
+    writer: File,
+    /// Entries in the file, trimmed ones included
+    lines: usize,
+}
+
+/// Audit log
+pub struct AuditLog {
+    /// Retained entries with their sequence numbers, oldest first
+    entries: RwLock<VecDeque<(u64, AuditEntry)>>,
+    next_seq: Mutex<u64>,
+    retention: AuditRetention,
+    file: Option<Mutex<AuditFile>>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").field("entries", &self.len()).finish()
+    }
+}
+
+impl AuditLog {
+    /// Create an audit log kept only in memory
+    pub fn in_memory(retention: AuditRetention) -> Self {
+        AuditLog {
+            entries: RwLock::new(VecDeque::new()),
+            next_seq: Mutex::new(1),
+            retention,
+            file: None,
+        }
+    }
+
+    /// Open (or create) an audit log persisted at `path`, loading its entries
+    ///
+    /// A torn last line, left by a crash mid-append, is ignored. A later
+    /// line for the same statement replaces its pending entry.
+    pub fn open<P: AsRef<Path>>(path: P, retention: AuditRetention) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = VecDeque::new();
+        let mut positions: HashMap<u64, usize> = HashMap::new();
+        let mut lines_read = 0;
+
+        if path.exists() {
+            let file = File::open(&path).map_err(|e| format!("Failed to open audit log: {}", e))?;
+            let lines: Vec<String> = BufReader::new(file)
+                .lines()
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Failed to read audit log: {}", e))?;
+
+            for (n, line) in lines.iter().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                lines_read += 1;
+                match serde_json::from_str::<AuditLine>(line) {
+                    Ok(AuditLine { seq, entry }) => match positions.get(&seq).filter(|_| seq != 0) {
+                        Some(&at) => entries[at] = (seq, entry),
+                        None => {
+                            positions.insert(seq, entries.len());
+                            entries.push_back((seq, entry));
+                        }
+                    },
+                    Err(_) if n + 1 == lines.len() => {}
+                    Err(e) => return Err(format!("Corrupt audit log entry on line {}: {}", n + 1, e)),
+                }
+            }
+        }
+
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open audit log: {}", e))?;
+        let next_seq = entries.iter().map(|(seq, _)| seq + 1).max().unwrap_or(1);
+        let newest = entries.back().map(|(_, e): &(u64, AuditEntry)| e.timestamp);
+
+        let log = AuditLog {
+            entries: RwLock::new(entries),
+            next_seq: Mutex::new(next_seq),
+            retention,
+            file: Some(Mutex::new(AuditFile { path, writer, lines: lines_read })),
+        };
+        if let Some(newest) = newest {
+            log.apply_retention(newest)?;
+        }
+        Ok(log)
+    }
+
+    /// Append an entry, then drop what retention no longer keeps
+    pub fn record(&self, entry: AuditEntry) -> Result<(), String> {
+        self.append(entry).map(|_| ())
+    }
+
+    /// Record a statement as pending before it runs, returning the number
+    /// `finish` settles it by
+    ///
+    /// The entry is on disk when this returns; if it fails, the statement
+    /// must not run.
+    pub fn begin(&self, entry: AuditEntry) -> Result<u64, String> {
+        self.append(AuditEntry {
+            outcome: AuditOutcome::Pending,
+            ..entry
+        })
+    }
+
+    /// Record how a statement recorded by `begin` ended
+    pub fn finish(&self, seq: u64, outcome: AuditOutcome, rows_affected: usize) -> Result<(), String> {
+        let mut entries = self.entries.write().unwrap();
+        // Dropped by retention meanwhile
+        let Some((_, entry)) = entries.iter_mut().find(|(s, _)| *s == seq) else {
+            return Ok(());
+        };
+        entry.outcome = outcome;
+        entry.rows_affected = rows_affected;
+        let entry = entry.clone();
+        drop(entries);
+
+        self.write_line(seq, &entry)
+    }
+
+    fn append(&self, entry: AuditEntry) -> Result<u64, String> {
+        let now = entry.timestamp;
+        let seq = {
+            let mut next_seq = self.next_seq.lock().unwrap();
+            *next_seq += 1;
+            *next_seq - 1
+        };
+
+        self.write_line(seq, &entry)?;
+        self.entries.write().unwrap().push_back((seq, entry));
+        self.apply_retention(now)?;
+        Ok(seq)
+    }
+
+    /// Append an entry's line to the file and sync it
+    fn write_line(&self, seq: u64, entry: &AuditEntry) -> Result<(), String> {
+        let Some(file) = &self.file else { return Ok(()) };
+        let mut line = serde_json::to_string(&AuditLine { seq, entry: entry.clone() })
+            .map_err(|e| format!("Failed to encode audit entry: {}", e))?;
+        line.push('\n');
+
+        let mut file = file.lock().unwrap();
+        file.writer
+            .write_all(line.as_bytes())
+            .and_then(|()| file.writer.sync_data())
+            .map_err(|e| format!("Failed to write audit log: {}", e))?;
+        file.lines += 1;
+        Ok(())
+    }
+
+    /// Drop entries past the retention limits as of `now` (ms since the Unix epoch)
+    pub fn apply_retention(&self, now: i64) -> Result<(), String> {
+        let mut entries = self.entries.write().unwrap();
+
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff = now - max_age.as_millis() as i64;
+            while entries.front().is_some_and(|(_, e)| e.timestamp < cutoff) {
+                entries.pop_front();
+            }
+        }
+        if let Some(max_entries) = self.retention.max_entries {
+            while entries.len() > max_entries {
+                entries.pop_front();
+            }
+        }
+
+        // Rewrite the file once trimmed entries outnumber the kept ones
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap();
+            if file.lines > 2 * entries.len() + COMPACTION_SLACK {
+                compact(&mut file, &entries)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Entries matching `filter`, oldest first
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditEntry> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, e)| filter.matches(e))
+            .map(|(_, e)| e.clone())
+            .collect()
+    }
+
+    /// Every retained entry, oldest first
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.query(&AuditFilter::default())
+    }
+
+    /// Number of retained entries
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Replace the log file with just the retained entries
+fn compact(file: &mut AuditFile, entries: &VecDeque<(u64, AuditEntry)>) -> Result<(), String> {
+    let temp_path = file.path.with_extension("compacting");
+    let mut contents = String::new();
+    for (seq, entry) in entries {
+        let line = AuditLine { seq: *seq, entry: entry.clone() };
+        contents.push_str(&serde_json::to_string(&line).map_err(|e| format!("Failed to encode audit entry: {}", e))?);
+        contents.push('\n');
+    }
+
+    let mut temp = File::create(&temp_path).map_err(|e| format!("Failed to compact audit log: {}", e))?;
+    temp.write_all(contents.as_bytes())
+        .and_then(|()| temp.sync_all())
+        .map_err(|e| format!("Failed to compact audit log: {}", e))?;
+    std::fs::rename(&temp_path, &file.path).map_err(|e| format!("Failed to compact audit log: {}", e))?;
+    file.writer = OpenOptions::new()
+        .append(true)
+        .open(&file.path)
+        .map_err(|e| format!("Failed to open audit log: {}", e))?;
+    file.lines = entries.len();
+
+    Ok(())
+}
+
+/// Collections a statement names, in order of appearance
+pub fn statement_collections(query: &Query) -> Vec<String> {
+    let named: Vec<&str> = match query {
+        Query::Select(q) => std::iter::once(q.from.collection.as_str())
+            .chain(q.joins.iter().map(|j| j.collection.as_str()))
+            .collect(),
+        Query::Insert(q) => vec![&q.collection],
+        Query::Update(q) => vec![&q.collection],
+        Query::Delete(q) => vec![&q.collection],
+        Query::UpdateEdge(q) => vec![&q.from.collection],
+        Query::DeleteEdge(q) => vec![&q.from.collection],
+        Query::CreateIndex(q) => vec![&q.collection],
+        Query::Reindex(ReindexQuery::Collection(collection)) => vec![collection],
+        Query::Analyze(Some(collection)) => vec![collection],
+        Query::Archive(q) | Query::Unarchive(q) => vec![&q.collection],
+        Query::Copy(q) => vec![&q.collection],
+        Query::DefineSchema(schema) => vec![&schema.collection],
+        Query::DropSchema(collection) => vec![collection],
+        Query::Truncate(collection) | Query::DropCollection(collection) => vec![collection],
+        Query::Explain(explain) => return statement_collections(&explain.query),
+        _ => Vec::new(),
+    };
+
+    let mut collections: Vec<String> = Vec::new();
+    for name in named {
+        if !collections.iter().any(|c| c == name) {
+            collections.push(name.to_string());
+        }
+    }
+    collections
+}
+
+/// Statement text as recorded: runs of whitespace collapsed to one space
+pub fn normalize_statement(statement: &str) -> String {
+    statement.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: i64, username: &str, collection: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            username: username.to_string(),
+            role: Role::ReadWrite,
+            statement: format!("DELETE FROM {}", collection),
+            collections: vec![collection.to_string()],
+            rows_affected: 1,
+            outcome: AuditOutcome::Succeeded,
+        }
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let log = AuditLog::in_memory(AuditRetention::default());
+        log.record(entry(1_000, "alice", "Users")).unwrap();
+        log.record(entry(2_000, "bob", "Orders")).unwrap();
+        log.record(entry(3_000, "alice", "Orders")).unwrap();
+
+        let by_alice = log.query(&AuditFilter {
+            username: Some("alice".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(by_alice.len(), 2);
+
+        let orders_since = log.query(&AuditFilter {
+            since: Some(2_500),
+            collection: Some("Orders".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(orders_since, vec![entry(3_000, "alice", "Orders")]);
+    }
+
+    #[test]
+    fn test_retention_by_count_and_age() {
+        let log = AuditLog::in_memory(AuditRetention {
+            max_entries: Some(3),
+            max_age: None,
+        });
+        for t in 0..5 {
+            log.record(entry(t, "alice", "Users")).unwrap();
+        }
+        let timestamps: Vec<i64> = log.entries().iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, [2, 3, 4]);
+
+        let log = AuditLog::in_memory(AuditRetention {
+            max_entries: None,
+            max_age: Some(Duration::from_secs(60)),
+        });
+        log.record(entry(0, "alice", "Users")).unwrap();
+        log.record(entry(30_000, "alice", "Users")).unwrap();
+        log.record(entry(70_000, "alice", "Users")).unwrap();
+        assert_eq!(log.len(), 2);
+        log.apply_retention(100_000).unwrap();
+        assert_eq!(log.len(), 1);
+    }
+
+    #[test]
+    fn test_persists_across_reopen() {
+        let dir = std::env::temp_dir().join(format!("deed_audit_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let log = AuditLog::open(&path, AuditRetention::default()).unwrap();
+            log.record(entry(1_000, "alice", "Users")).unwrap();
+            log.record(entry(2_000, "bob", "Orders")).unwrap();
+        }
+
+        // A torn final line from a crash mid-append is skipped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"timestamp\":3").unwrap();
+        drop(file);
+
+        let log = AuditLog::open(&path, AuditRetention::default()).unwrap();
+        assert_eq!(log.entries(), vec![entry(1_000, "alice", "Users"), entry(2_000, "bob", "Orders")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pending_entries_are_settled_by_later_lines() {
+        let dir = std::env::temp_dir().join(format!("deed_audit_pending_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let log = AuditLog::open(&path, AuditRetention::default()).unwrap();
+            let settled = log.begin(entry(1_000, "alice", "Users")).unwrap();
+            log.begin(entry(2_000, "bob", "Orders")).unwrap();
+            assert_eq!(log.entries()[0].outcome, AuditOutcome::Pending);
+            log.finish(settled, AuditOutcome::Succeeded, 3).unwrap();
+        }
+
+        // The second statement never finished, as after a crash
+        let log = AuditLog::open(&path, AuditRetention::default()).unwrap();
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].outcome.clone(), entries[0].rows_affected), (AuditOutcome::Succeeded, 3));
+        assert_eq!(entries[1].outcome, AuditOutcome::Pending);
+
+        // Numbering continues after the reloaded entries
+        let next = log.begin(entry(3_000, "alice", "Users")).unwrap();
+        log.finish(next, AuditOutcome::Failed("boom".to_string()), 0).unwrap();
+        assert_eq!(log.entries()[2].outcome, AuditOutcome::Failed("boom".to_string()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
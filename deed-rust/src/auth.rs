@@ -1,12 +1,47 @@
 //! Authentication and Authorization
 //!
 //! Provides user authentication, password hashing, and role-based access control.
-
+//!
+//! Passwords are stored as salted PBKDF2-HMAC-SHA256 and compared in
+//! constant time. Sessions carry a random token, expire after a fixed lifetime or
+//! when left idle, and can be rotated or revoked.
+
+use crate::dql_ast::Query;
+use crate::dql_executor::Clock;
+use crate::firewall::{Firewall, FirewallPrincipal};
+use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// PBKDF2 rounds for a new password hash, unless configured otherwise
+pub const DEFAULT_HASH_ITERATIONS: u32 = 100_000;
+
+/// Session and password policy
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// How long a session lasts after login or rotation
+    pub session_lifetime: Duration,
+    /// How long a session may go unused (`None` for no limit)
+    pub idle_timeout: Option<Duration>,
+    /// PBKDF2 rounds for new password hashes
+    pub hash_iterations: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            session_lifetime: Duration::from_secs(3600), // 1 hour
+            idle_timeout: Some(Duration::from_secs(1800)), // 30 minutes
+            hash_iterations: DEFAULT_HASH_ITERATIONS,
+        }
+    }
+}
 
 /// User role for access control
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -16,6 +51,56 @@ pub enum Role {
     ReadOnly,   // Can only read data
 }
 
+/// Access a statement needs, from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Access {
+    /// Classify a parsed statement
+    ///
+    /// Reads, session settings and transaction control need read access;
+    /// data changes need write access; indexes, schemas, truncating or
+    /// dropping collections, the firewall and COPY (which touches the
+    /// server's filesystem) need admin access.
+    pub fn required_for(query: &Query) -> Self {
+        match query {
+            Query::Select(_)
+            | Query::Begin(_)
+            | Query::Commit
+            | Query::Rollback
+            | Query::Savepoint(_)
+            | Query::RollbackToSavepoint(_)
+            | Query::ReleaseSavepoint(_)
+            | Query::Set(_)
+            | Query::ShowCollections => Access::Read,
+            Query::Insert(_)
+            | Query::Update(_)
+            | Query::Delete(_)
+            | Query::Create(_)
+            | Query::UpdateEdge(_)
+            | Query::DeleteEdge(_)
+            | Query::Archive(_)
+            | Query::Unarchive(_) => Access::Write,
+            Query::CreateIndex(_)
+            | Query::DropIndex(_)
+            | Query::Reindex(_)
+            | Query::Analyze(_)
+            | Query::Copy(_)
+            | Query::Firewall(_)
+            | Query::DefineSchema(_)
+            | Query::DropSchema(_)
+            | Query::Truncate(_)
+            | Query::DropCollection(_) => Access::Admin,
+            // EXPLAIN ANALYZE runs the statement
+            Query::Explain(explain) => Access::required_for(&explain.query),
+        }
+    }
+}
+
 /// User account
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -29,18 +114,23 @@ pub struct User {
 impl User {
     /// Create a new user with hashed password
     pub fn new(username: String, password: &str, role: Role) -> Self {
+        Self::with_hash_iterations(username, password, role, DEFAULT_HASH_ITERATIONS)
+    }
+
+    /// Create a new user, hashing the password with `iterations` rounds
+    pub fn with_hash_iterations(username: String, password: &str, role: Role, iterations: u32) -> Self {
         User {
             username,
-            password_hash: hash_password(password),
+            password_hash: hash_password(password, iterations),
             role,
             created_at: current_timestamp(),
             last_login: None,
         }
     }
 
-    /// Verify password
+    /// Verify password, in time independent of where it differs
     pub fn verify_password(&self, password: &str) -> bool {
-        self.password_hash == hash_password(password)
+        verify_password_hash(&self.password_hash, password)
     }
 
     /// Update last login timestamp
@@ -57,21 +147,24 @@ pub struct Session {
     pub role: Role,
     pub created_at: u64,
     pub expires_at: u64,
+    /// When the session was last validated
+    pub last_activity: u64,
 }
 
 impl Session {
     /// Create a new session
     pub fn new(username: String, role: Role, duration_secs: u64) -> Self {
-        let session_id = generate_session_id(&username);
-        let created_at = current_timestamp();
-        let expires_at = created_at + duration_secs;
+        Self::starting_at(username, role, current_timestamp(), duration_secs)
+    }
 
+    fn starting_at(username: String, role: Role, now: u64, duration_secs: u64) -> Self {
         Session {
-            session_id,
+            session_id: generate_session_id(),
             username,
             role,
-            created_at,
-            expires_at,
+            created_at: now,
+            expires_at: now + duration_secs,
+            last_activity: now,
         }
     }
 
@@ -92,22 +185,49 @@ impl Session {
     pub fn is_admin(&self) -> bool {
         matches!(self.role, Role::Admin)
     }
+
+    /// Check the session's role grants `access`
+    pub fn check_access(&self, access: Access) -> Result<(), String> {
+        let (allowed, required) = match access {
+            Access::Read => (self.can_read(), "read"),
+            Access::Write => (self.can_write(), "write"),
+            Access::Admin => (self.is_admin(), "admin"),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("Permission denied: {} access required", required))
+        }
+    }
 }
 
 /// Authentication manager
 pub struct AuthManager {
     users: Arc<RwLock<HashMap<String, User>>>,
     sessions: Arc<RwLock<HashMap<String, Session>>>,
-    session_duration: u64, // seconds
+    config: AuthConfig,
+    /// Current time in milliseconds since the Unix epoch
+    clock: Clock,
+    firewall: Arc<Firewall>,
+    /// Dropping this stops the session sweep thread
+    _sweep: Option<mpsc::Sender<()>>,
 }
 
 impl AuthManager {
     /// Create a new auth manager
     pub fn new() -> Self {
-        let mut manager = AuthManager {
+        Self::with_config(AuthConfig::default())
+    }
+
+    /// Create a new auth manager with a session and password policy
+    pub fn with_config(config: AuthConfig) -> Self {
+        let manager = AuthManager {
             users: Arc::new(RwLock::new(HashMap::new())),
             sessions: Arc::new(RwLock::new(HashMap::new())),
-            session_duration: 3600, // 1 hour default
+            config,
+            clock: Arc::new(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64),
+            firewall: Arc::new(Firewall::new()),
+            _sweep: None,
         };
 
         // Create default admin user
@@ -116,15 +236,51 @@ impl AuthManager {
         manager
     }
 
+    /// Read the time from `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Purge expired and idle sessions every `interval` on a background thread
+    ///
+    /// The thread uses the clock set so far, and stops when the manager is
+    /// dropped.
+    pub fn with_session_sweep(mut self, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let sessions = self.sessions.clone();
+        let config = self.config.clone();
+        let clock = self.clock.clone();
+
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = (clock)() as u64 / 1000;
+                sessions.write().unwrap().retain(|_, session| session_status(session, &config, now).is_ok());
+            }
+        });
+
+        self._sweep = Some(stop);
+        self
+    }
+
+    /// Current time in seconds since the Unix epoch
+    fn now(&self) -> u64 {
+        (self.clock)() as u64 / 1000
+    }
+
     /// Create a new user
     pub fn create_user(&self, username: String, password: &str, role: Role) -> Result<(), String> {
-        let mut users = self.users.write().unwrap();
+        if self.users.read().unwrap().contains_key(&username) {
+            return Err(format!("User {} already exists", username));
+        }
 
+        // Hash outside the lock; it's deliberately slow
+        let user = User::with_hash_iterations(username.clone(), password, role, self.config.hash_iterations);
+
+        let mut users = self.users.write().unwrap();
         if users.contains_key(&username) {
             return Err(format!("User {} already exists", username));
         }
-
-        let user = User::new(username.clone(), password, role);
         users.insert(username, user);
 
         Ok(())
@@ -143,10 +299,11 @@ impl AuthManager {
 
     /// Change user password
     pub fn change_password(&self, username: &str, new_password: &str) -> Result<(), String> {
+        let password_hash = hash_password(new_password, self.config.hash_iterations);
         let mut users = self.users.write().unwrap();
 
         if let Some(user) = users.get_mut(username) {
-            user.password_hash = hash_password(new_password);
+            user.password_hash = password_hash;
             Ok(())
         } else {
             Err(format!("User {} not found", username))
@@ -154,32 +311,50 @@ impl AuthManager {
     }
 
     /// Authenticate user and create session
+    ///
+    /// An unknown user and a wrong password fail alike, so a caller can't
+    /// tell which usernames exist.
     pub fn login(&self, username: &str, password: &str) -> Result<String, String> {
-        let mut users = self.users.write().unwrap();
+        let invalid = || "Invalid username or password".to_string();
+        let stored = self.users.read().unwrap().get(username).map(|user| user.password_hash.clone());
 
-        if let Some(user) = users.get_mut(username) {
-            if user.verify_password(password) {
-                // Update last login
-                user.update_last_login();
-
-                // Create session
-                let session = Session::new(
-                    username.to_string(),
-                    user.role.clone(),
-                    self.session_duration,
-                );
-                let session_id = session.session_id.clone();
-
-                let mut sessions = self.sessions.write().unwrap();
-                sessions.insert(session_id.clone(), session);
-
-                Ok(session_id)
-            } else {
-                Err("Invalid password".to_string())
+        let Some(stored) = stored else {
+            // Spend as long as a real check, so timing doesn't give it away either
+            std::hint::black_box(derive_key(password, &[0u8; 16], self.config.hash_iterations));
+            return Err(invalid());
+        };
+        if !verify_password_hash(&stored, password) {
+            return Err(invalid());
+        }
+
+        // Bring hashes from before the current work factor up to it
+        let rehashed = (password_hash_iterations(&stored) != Some(self.config.hash_iterations))
+            .then(|| hash_password(password, self.config.hash_iterations));
+
+        let mut users = self.users.write().unwrap();
+        let user = users.get_mut(username).ok_or_else(invalid)?;
+        if let Some(password_hash) = rehashed {
+            if user.password_hash == stored {
+                user.password_hash = password_hash;
             }
-        } else {
-            Err(format!("User {} not found", username))
         }
+
+        // Update last login
+        user.update_last_login();
+
+        // Create session
+        let session = Session::starting_at(
+            username.to_string(),
+            user.role.clone(),
+            self.now(),
+            self.config.session_lifetime.as_secs(),
+        );
+        let session_id = session.session_id.clone();
+        drop(users);
+
+        self.sessions.write().unwrap().insert(session_id.clone(), session);
+
+        Ok(session_id)
     }
 
     /// Logout (destroy session)
@@ -194,14 +369,19 @@ impl AuthManager {
     }
 
     /// Validate session
+    ///
+    /// Fails for sessions past their lifetime or idle timeout, which are
+    /// removed; otherwise records the session as active now.
     pub fn validate_session(&self, session_id: &str) -> Result<Session, String> {
         let mut sessions = self.sessions.write().unwrap();
+        let now = self.now();
 
-        if let Some(session) = sessions.get(session_id) {
-            if session.is_expired() {
+        if let Some(session) = sessions.get_mut(session_id) {
+            if let Err(e) = session_status(session, &self.config, now) {
                 sessions.remove(session_id);
-                Err("Session expired".to_string())
+                Err(e)
             } else {
+                session.last_activity = now;
                 Ok(session.clone())
             }
         } else {
@@ -209,34 +389,90 @@ impl AuthManager {
         }
     }
 
-    /// Check if session has read permission
-    pub fn check_read_permission(&self, session_id: &str) -> Result<(), String> {
+    /// Replace a valid session with a new token and a fresh lifetime
+    ///
+    /// The old token stops working immediately.
+    pub fn rotate_session(&self, session_id: &str) -> Result<String, String> {
         let session = self.validate_session(session_id)?;
-        if session.can_read() {
-            Ok(())
-        } else {
-            Err("Permission denied: read access required".to_string())
+        let rotated = Session::starting_at(
+            session.username,
+            session.role,
+            self.now(),
+            self.config.session_lifetime.as_secs(),
+        );
+        let rotated_id = rotated.session_id.clone();
+
+        let mut sessions = self.sessions.write().unwrap();
+        if sessions.remove(session_id).is_none() {
+            // Logged out or revoked meanwhile
+            return Err("Invalid session".to_string());
         }
+        sessions.insert(rotated_id.clone(), rotated);
+
+        Ok(rotated_id)
+    }
+
+    /// List a user's live sessions (admin only)
+    pub fn list_user_sessions(&self, admin_session: &str, username: &str) -> Result<Vec<Session>, String> {
+        self.check_admin_permission(admin_session)?;
+        let now = self.now();
+
+        Ok(self
+            .sessions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|s| s.username == username && session_status(s, &self.config, now).is_ok())
+            .cloned()
+            .collect())
+    }
+
+    /// End one session, whoever it belongs to (admin only)
+    pub fn revoke_session(&self, admin_session: &str, session_id: &str) -> Result<(), String> {
+        self.check_admin_permission(admin_session)?;
+        self.logout(session_id)
+    }
+
+    /// End every session of a user (admin only), returning how many there were
+    pub fn revoke_user_sessions(&self, admin_session: &str, username: &str) -> Result<usize, String> {
+        self.check_admin_permission(admin_session)?;
+
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.username != username);
+        Ok(before - sessions.len())
+    }
+
+    /// Check if session has read permission
+    pub fn check_read_permission(&self, session_id: &str) -> Result<(), String> {
+        self.validate_session(session_id)?.check_access(Access::Read)
     }
 
     /// Check if session has write permission
     pub fn check_write_permission(&self, session_id: &str) -> Result<(), String> {
-        let session = self.validate_session(session_id)?;
-        if session.can_write() {
-            Ok(())
-        } else {
-            Err("Permission denied: write access required".to_string())
-        }
+        self.validate_session(session_id)?.check_access(Access::Write)
     }
 
     /// Check if session is admin
     pub fn check_admin_permission(&self, session_id: &str) -> Result<(), String> {
+        self.validate_session(session_id)?.check_access(Access::Admin)
+    }
+
+    /// Use a shared (e.g. persisted) statement firewall
+    pub fn with_firewall(mut self, firewall: Arc<Firewall>) -> Self {
+        self.firewall = firewall;
+        self
+    }
+
+    /// Statement firewall for this database
+    pub fn firewall(&self) -> Arc<Firewall> {
+        self.firewall.clone()
+    }
+
+    /// Firewall principal for a valid session
+    pub fn session_principal(&self, session_id: &str) -> Result<FirewallPrincipal, String> {
         let session = self.validate_session(session_id)?;
-        if session.is_admin() {
-            Ok(())
-        } else {
-            Err("Permission denied: admin access required".to_string())
-        }
+        Ok(FirewallPrincipal::user(&session.username, session.role))
     }
 
     /// List all users (admin only)
@@ -248,20 +484,25 @@ impl AuthManager {
     /// List active sessions
     pub fn list_active_sessions(&self) -> Vec<Session> {
         let sessions = self.sessions.read().unwrap();
-        let now = current_timestamp();
+        let now = self.now();
 
         sessions.values()
-            .filter(|s| s.expires_at > now)
+            .filter(|s| session_status(s, &self.config, now).is_ok())
             .cloned()
             .collect()
     }
 
+    /// Number of sessions held, including expired ones not yet purged
+    pub fn session_count(&self) -> usize {
+        self.sessions.read().unwrap().len()
+    }
+
     /// Cleanup expired sessions
     pub fn cleanup_expired_sessions(&self) {
         let mut sessions = self.sessions.write().unwrap();
-        let current_time = current_timestamp();
+        let now = self.now();
 
-        sessions.retain(|_, session| session.expires_at > current_time);
+        sessions.retain(|_, session| session_status(session, &self.config, now).is_ok());
     }
 }
 
@@ -271,20 +512,89 @@ impl Default for AuthManager {
     }
 }
 
-/// Hash password using SHA-256
-fn hash_password(password: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(password.as_bytes());
-    format!("{:x}", hasher.finalize())
+/// Whether a session is still live at `now`
+fn session_status(session: &Session, config: &AuthConfig, now: u64) -> Result<(), String> {
+    if now > session.expires_at {
+        return Err("Session expired".to_string());
+    }
+    if let Some(idle_timeout) = config.idle_timeout {
+        if now.saturating_sub(session.last_activity) > idle_timeout.as_secs() {
+            return Err("Session timed out after inactivity".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Hash a password as `pbkdf2-sha256$<iterations>$<salt>$<key>`, with a random salt
+fn hash_password(password: &str, iterations: u32) -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let iterations = iterations.max(1);
+
+    format!(
+        "pbkdf2-sha256${}${}${}",
+        iterations,
+        to_hex(&salt),
+        to_hex(&derive_key(password, &salt, iterations))
+    )
 }
 
-/// Generate a session ID
-fn generate_session_id(username: &str) -> String {
-    let timestamp = current_timestamp();
-    let mut hasher = Sha256::new();
-    hasher.update(username.as_bytes());
-    hasher.update(timestamp.to_string().as_bytes());
-    format!("{:x}", hasher.finalize())
+fn derive_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), salt, iterations)
+}
+
+/// Check a password against a stored hash
+///
+/// Hashes from before salting (plain SHA-256 hex) are still accepted.
+fn verify_password_hash(stored: &str, password: &str) -> bool {
+    let expected = match stored.split('$').collect::<Vec<_>>()[..] {
+        ["pbkdf2-sha256", iterations, salt, _] => match (iterations.parse::<u32>(), from_hex(salt)) {
+            (Ok(iterations), Some(salt)) if iterations > 0 => to_hex(&derive_key(password, &salt, iterations)),
+            _ => return false,
+        },
+        [_] => format!("{:x}", Sha256::digest(password.as_bytes())),
+        _ => return false,
+    };
+    let stored_digest = stored.rsplit('$').next().unwrap_or(stored);
+
+    constant_time_eq(stored_digest.as_bytes(), expected.as_bytes())
+}
+
+/// Rounds a stored hash was made with (`None` for a plain SHA-256 hash)
+fn password_hash_iterations(stored: &str) -> Option<u32> {
+    match stored.split('$').collect::<Vec<_>>()[..] {
+        ["pbkdf2-sha256", iterations, _, _] => iterations.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Compare without stopping at the first difference
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Generate a random session token
+fn generate_session_id() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    to_hex(&token)
 }
 
 /// Get current timestamp in seconds
@@ -298,15 +608,24 @@ fn current_timestamp() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicI64, Ordering};
 
     #[test]
     fn test_password_hashing() {
         let password = "secret123";
-        let hash1 = hash_password(password);
-        let hash2 = hash_password(password);
-
-        assert_eq!(hash1, hash2); // Same password = same hash
-        assert_ne!(hash1, hash_password("different"));
+        let hash1 = hash_password(password, 10);
+        let hash2 = hash_password(password, 10);
+
+        assert_ne!(hash1, hash2); // Salted: same password, different hashes
+        assert!(verify_password_hash(&hash1, password));
+        assert!(verify_password_hash(&hash2, password));
+        assert!(!verify_password_hash(&hash1, "different"));
+        assert_eq!(password_hash_iterations(&hash1), Some(10));
+
+        // Unsalted hashes from before still verify
+        let legacy = format!("{:x}", Sha256::digest(password.as_bytes()));
+        assert!(verify_password_hash(&legacy, password));
+        assert!(!verify_password_hash(&legacy, "different"));
     }
 
     #[test]
@@ -329,11 +648,10 @@ mod tests {
         let session_id = manager.login("alice", "secret").unwrap();
         assert!(!session_id.is_empty());
 
-        // Failed login - wrong password
-        assert!(manager.login("alice", "wrong").is_err());
-
-        // Failed login - user doesn't exist
-        assert!(manager.login("bob", "secret").is_err());
+        // Failed login - wrong password, or a user that doesn't exist, alike
+        let wrong_password = manager.login("alice", "wrong").unwrap_err();
+        assert_eq!(wrong_password, "Invalid username or password");
+        assert_eq!(manager.login("bob", "secret").unwrap_err(), wrong_password);
     }
 
     #[test]
@@ -380,6 +698,25 @@ mod tests {
         assert!(manager.check_admin_permission(&reader_session).is_err());
     }
 
+    #[test]
+    fn test_statement_access() {
+        use crate::dql_parser::Parser;
+
+        let access = |query: &str| Access::required_for(&Parser::parse(query).unwrap());
+        assert_eq!(access("FROM Users SELECT name"), Access::Read);
+        assert_eq!(access("BEGIN"), Access::Read);
+        assert_eq!(access("UPDATE Users SET age = 1 WHERE name = 'Ann'"), Access::Write);
+        assert_eq!(access("CREATE INDEX idx_age ON Users(age)"), Access::Admin);
+        assert_eq!(access("EXPLAIN DELETE FROM Users"), Access::Write);
+
+        let reader = Session::new("reader".to_string(), Role::ReadOnly, 60);
+        assert!(reader.check_access(Access::Read).is_ok());
+        assert_eq!(
+            reader.check_access(Access::Write).unwrap_err(),
+            "Permission denied: write access required"
+        );
+    }
+
     #[test]
     fn test_logout() {
         let manager = AuthManager::new();
@@ -415,4 +752,132 @@ mod tests {
         // New password works
         assert!(manager.login("alice", "new_pass").is_ok());
     }
+
+    /// Manager on a clock the test moves by hand, starting at `now` (ms)
+    fn manager_at(now: &Arc<AtomicI64>) -> AuthManager {
+        let config = AuthConfig {
+            session_lifetime: Duration::from_secs(3600),
+            idle_timeout: Some(Duration::from_secs(600)),
+            hash_iterations: 1,
+        };
+        let clock_now = now.clone();
+        let manager = AuthManager::with_config(config).with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+        manager.create_user("alice".to_string(), "secret", Role::ReadWrite).unwrap();
+        manager
+    }
+
+    fn advance(now: &AtomicI64, secs: i64) {
+        now.fetch_add(secs * 1000, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_session_lifetime() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now);
+        let session_id = manager.login("alice", "secret").unwrap();
+
+        // Kept active, it still ends when its lifetime does
+        for _ in 0..6 {
+            advance(&now, 590);
+            manager.validate_session(&session_id).unwrap();
+        }
+        advance(&now, 100);
+        assert_eq!(manager.validate_session(&session_id).unwrap_err(), "Session expired");
+        assert_eq!(manager.validate_session(&session_id).unwrap_err(), "Invalid session");
+    }
+
+    #[test]
+    fn test_session_idle_timeout() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now);
+        let session_id = manager.login("alice", "secret").unwrap();
+
+        advance(&now, 500);
+        manager.validate_session(&session_id).unwrap();
+        advance(&now, 500);
+        manager.validate_session(&session_id).unwrap();
+        advance(&now, 601);
+        assert_eq!(
+            manager.validate_session(&session_id).unwrap_err(),
+            "Session timed out after inactivity"
+        );
+    }
+
+    #[test]
+    fn test_session_rotation() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now);
+        let old_id = manager.login("alice", "secret").unwrap();
+
+        // Kept active well into its lifetime
+        for _ in 0..6 {
+            advance(&now, 500);
+            manager.validate_session(&old_id).unwrap();
+        }
+        let new_id = manager.rotate_session(&old_id).unwrap();
+        assert_ne!(new_id, old_id);
+        assert_eq!(manager.validate_session(&old_id).unwrap_err(), "Invalid session");
+        assert!(manager.rotate_session(&old_id).is_err());
+
+        // The rotated session gets a fresh lifetime
+        advance(&now, 590);
+        let session = manager.validate_session(&new_id).unwrap();
+        assert_eq!(session.username, "alice");
+        assert_eq!(session.expires_at, session.created_at + 3600);
+    }
+
+    #[test]
+    fn test_admin_session_management() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now);
+        let admin = manager.login("admin", "admin").unwrap();
+        let first = manager.login("alice", "secret").unwrap();
+        manager.login("alice", "secret").unwrap();
+
+        assert_eq!(manager.list_user_sessions(&admin, "alice").unwrap().len(), 2);
+        assert_eq!(
+            manager.list_user_sessions(&first, "alice").unwrap_err(),
+            "Permission denied: admin access required"
+        );
+
+        manager.revoke_session(&admin, &first).unwrap();
+        assert!(manager.validate_session(&first).is_err());
+        assert_eq!(manager.revoke_user_sessions(&admin, "alice").unwrap(), 1);
+        assert!(manager.list_user_sessions(&admin, "alice").unwrap().is_empty());
+        assert!(manager.validate_session(&admin).is_ok());
+    }
+
+    #[test]
+    fn test_session_sweep_purges_expired_sessions() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now).with_session_sweep(Duration::from_millis(10));
+
+        for _ in 0..50 {
+            manager.login("alice", "secret").unwrap();
+        }
+        assert_eq!(manager.session_count(), 50);
+
+        advance(&now, 601);
+        let live = manager.login("alice", "secret").unwrap();
+        let start = std::time::Instant::now();
+        while manager.session_count() > 1 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(manager.session_count(), 1);
+        assert!(manager.validate_session(&live).is_ok());
+    }
+
+    #[test]
+    fn test_login_upgrades_old_password_hashes() {
+        let now = Arc::new(AtomicI64::new(1_700_000_000_000));
+        let manager = manager_at(&now);
+        let mut user = User::with_hash_iterations("bob".to_string(), "pw", Role::ReadOnly, 1);
+        user.password_hash = format!("{:x}", Sha256::digest(b"pw"));
+        manager.users.write().unwrap().insert("bob".to_string(), user);
+
+        manager.login("bob", "pw").unwrap();
+        let stored = manager.users.read().unwrap()["bob"].password_hash.clone();
+        assert_eq!(password_hash_iterations(&stored), Some(1));
+        assert!(manager.login("bob", "pw").is_ok());
+    }
 }
@@ -5,17 +5,29 @@
 //! - Incremental backups: Only changes since last backup
 //! - Compression: Optional gzip compression
 //! - Verification: Checksum validation
+//! - Scheduling: Backups taken on a timer, keeping the newest chains
 
+use crate::archive::ArchiveManager;
+use crate::btree::IndexKind;
 use crate::graph::{Graph, Entity, Edge};
+use crate::schema::Schema;
+use crate::shutdown::{BackgroundTask, ShutdownSignal};
 use crate::types::{EntityId, EdgeId, PropertyValue};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{File, create_dir_all};
-use std::io::{Read, Write, BufReader, BufWriter};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 
+/// Backup ID that restores the newest backup with a complete chain
+pub const LATEST_BACKUP: &str = "latest";
+
+/// Suffix of the metadata of a backup being deleted, which hides it from listings
+const DELETED_SUFFIX: &str = ".meta.deleted";
+
 /// Backup type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BackupType {
@@ -34,6 +46,44 @@ pub struct BackupMetadata {
     pub compressed: bool,
     pub checksum: String,
     pub parent_backup_id: Option<String>, // For incremental backups
+    #[serde(default)]
+    pub base_backup_id: Option<String>, // Full backup an incremental chain starts from
+    #[serde(default)]
+    pub archive_segments: usize,
+    #[serde(default)]
+    pub wal_lsn: u64, // WAL position the snapshot was taken at
+}
+
+/// Index definition captured in a backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub collection: String,
+    pub field: String,
+    pub unique: bool,
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+/// Point-in-time copy of a database, as backed up or restored
+#[derive(Debug, Clone, Default)]
+pub struct BackupSnapshot {
+    pub entities: Vec<Entity>,
+    pub edges: Vec<Edge>,
+    pub schemas: Vec<Schema>,
+    pub indexes: Vec<IndexDefinition>,
+    pub wal_lsn: u64,
+}
+
+impl BackupSnapshot {
+    /// Snapshot a graph's entities and edges as they are now
+    pub fn of_graph(graph: &Graph) -> Self {
+        BackupSnapshot {
+            entities: graph.get_all_entities(),
+            edges: graph.get_all_edges(),
+            ..Default::default()
+        }
+    }
 }
 
 /// Backup configuration
@@ -54,6 +104,185 @@ impl Default for BackupConfig {
     }
 }
 
+/// Where scheduled backups get the database's state from
+pub type BackupSource = Arc<dyn Fn() -> Result<BackupSnapshot, String> + Send + Sync>;
+
+/// How many backup chains scheduled backups keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Full backups to keep, with their incrementals; the newest is always kept
+    pub keep_full: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy { keep_full: 7 }
+    }
+}
+
+/// When scheduled backups are taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupSchedule {
+    /// Time between full backups
+    pub full_interval: Duration,
+    /// Time between incremental backups in between, if any are taken
+    pub incremental_interval: Option<Duration>,
+    pub retention: RetentionPolicy,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        BackupSchedule {
+            full_interval: Duration::from_secs(24 * 60 * 60),
+            incremental_interval: Some(Duration::from_secs(60 * 60)),
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+/// How scheduled backups are going, as shown on the admin dashboard
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BackupStatus {
+    /// When the last successful backup was taken (seconds since the epoch)
+    pub last_backup_time: Option<u64>,
+    pub last_backup_id: Option<String>,
+    /// Why the last run failed; `None` if it succeeded
+    pub last_error: Option<String>,
+    /// When the next backup is due (seconds since the epoch)
+    pub next_run: Option<u64>,
+}
+
+/// When the next backups are due, and how the last run went
+struct ScheduleState {
+    next_full: Instant,
+    next_incremental: Option<Instant>,
+    status: BackupStatus,
+}
+
+/// Takes full and incremental backups on a schedule, pruning old chains
+///
+/// Incrementals are taken between full backups, based on the newest backup
+/// in the directory. After each successful backup, chains
+/// past the [`RetentionPolicy`] are deleted. A failed run is reported in the
+/// [`BackupStatus`] and the schedule moves on to the next slot.
+pub struct BackupScheduler {
+    schedule: BackupSchedule,
+    manager: Mutex<BackupManager>,
+    state: Mutex<ScheduleState>,
+}
+
+impl BackupScheduler {
+    /// A scheduler whose first full backup is due at once
+    pub fn new(config: BackupConfig, schedule: BackupSchedule) -> Result<Self, String> {
+        Self::starting_at(config, schedule, Instant::now())
+    }
+
+    /// A scheduler whose first full backup is due at `start`
+    pub fn starting_at(config: BackupConfig, schedule: BackupSchedule, start: Instant) -> Result<Self, String> {
+        Ok(BackupScheduler {
+            schedule,
+            manager: Mutex::new(BackupManager::new(config)?),
+            state: Mutex::new(ScheduleState {
+                next_full: start,
+                next_incremental: None,
+                status: BackupStatus::default(),
+            }),
+        })
+    }
+
+    pub fn schedule(&self) -> &BackupSchedule {
+        &self.schedule
+    }
+
+    /// When the next backup is due
+    pub fn next_run(&self) -> Instant {
+        let state = self.state.lock().unwrap();
+        state.next_incremental.map_or(state.next_full, |incremental| incremental.min(state.next_full))
+    }
+
+    pub fn status(&self) -> BackupStatus {
+        let next_run = self.next_run();
+        let mut status = self.state.lock().unwrap().status.clone();
+        status.next_run = Some(current_timestamp() + next_run.saturating_duration_since(Instant::now()).as_secs());
+        status
+    }
+
+    /// Take the backup due at `now`, if any, from `source`
+    pub fn run_due(&self, now: Instant, source: &dyn Fn() -> Result<BackupSnapshot, String>) -> Option<Result<BackupMetadata, String>> {
+        let backup_type = {
+            let mut state = self.state.lock().unwrap();
+            if now >= state.next_full {
+                state.next_full = next_slot(state.next_full, self.schedule.full_interval, now);
+                state.next_incremental = self.schedule.incremental_interval.map(|interval| now + interval);
+                BackupType::Full
+            } else if let Some(due) = state.next_incremental.filter(|due| now >= *due) {
+                state.next_incremental = self.schedule.incremental_interval.map(|interval| next_slot(due, interval, now));
+                BackupType::Incremental
+            } else {
+                return None;
+            }
+        };
+
+        let result = self.run(source, backup_type);
+
+        let mut state = self.state.lock().unwrap();
+        match &result {
+            Ok(metadata) => {
+                state.status.last_backup_time = Some(metadata.timestamp);
+                state.status.last_backup_id = Some(metadata.backup_id.clone());
+                state.status.last_error = None;
+            }
+            Err(e) => state.status.last_error = Some(e.clone()),
+        }
+        Some(result)
+    }
+
+    /// Take one backup and apply the retention policy
+    fn run(&self, source: &dyn Fn() -> Result<BackupSnapshot, String>, backup_type: BackupType) -> Result<BackupMetadata, String> {
+        let snapshot = source()?;
+        let mut manager = self.manager.lock().unwrap();
+        let metadata = manager.create_backup(&snapshot, backup_type)?;
+        manager
+            .apply_retention(&self.schedule.retention)
+            .map_err(|e| format!("Backup {} taken, but pruning old backups failed: {}", metadata.backup_id, e))?;
+        Ok(metadata)
+    }
+
+    /// Take backups from `source` on the tokio runtime until `shutdown` is cancelled
+    ///
+    /// Backups run on the blocking pool, one at a time; a run in progress
+    /// finishes before the task stops.
+    pub fn start(self: &Arc<Self>, source: BackupSource, shutdown: &ShutdownSignal) -> BackgroundTask {
+        let scheduler = Arc::clone(self);
+        BackgroundTask::spawn("backup-scheduler", shutdown, |shutdown| async move {
+            while !shutdown.is_cancelled() {
+                let wait = scheduler.next_run().saturating_duration_since(Instant::now());
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+
+                let (scheduler, source) = (Arc::clone(&scheduler), Arc::clone(&source));
+                let run = tokio::task::spawn_blocking(move || scheduler.run_due(Instant::now(), &*source));
+                match run.await {
+                    Ok(Some(Err(e))) => eprintln!("Scheduled backup failed: {}", e),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Scheduled backup failed: {}", e),
+                }
+            }
+        })
+    }
+}
+
+/// The first slot after `now` of a schedule that was due at `due`, skipping missed ones
+fn next_slot(due: Instant, interval: Duration, now: Instant) -> Instant {
+    if interval.is_zero() {
+        return now;
+    }
+    let missed = (now.saturating_duration_since(due).as_nanos() / interval.as_nanos() + 1).min(u32::MAX as u128);
+    due + interval * missed as u32
+}
+
 /// Backup manager
 pub struct BackupManager {
     config: BackupConfig,
@@ -80,50 +309,54 @@ impl BackupManager {
 
     /// Create a full backup
     pub fn create_full_backup(&mut self, graph: &Graph) -> Result<BackupMetadata, String> {
-        let backup_id = generate_backup_id();
-        let backup_path = self.get_backup_path(&backup_id);
-
-        // Serialize graph data
-        let backup_data = BackupData {
-            entities: graph.get_all_entities()
-                .into_iter()
-                .map(|e| SerializedEntity::from_entity(&e))
-                .collect(),
-            edges: graph.get_all_edges()
-                .into_iter()
-                .map(|e| SerializedEdge::from_edge(&e))
-                .collect(),
-        };
-
-        // Write backup
-        let serialized = serde_json::to_string(&backup_data)
-            .map_err(|e| format!("Serialization error: {}", e))?;
+        self.create_backup(&BackupSnapshot::of_graph(graph), BackupType::Full)
+    }
 
-        let checksum = calculate_checksum(&serialized);
+    /// Back up a snapshot
+    ///
+    /// An incremental backup is based on the newest backup in the directory
+    /// and records only what changed since the state that backup restores to.
+    /// The entity and edge counts are always those of the whole snapshot.
+    pub fn create_backup(&mut self, snapshot: &BackupSnapshot, backup_type: BackupType) -> Result<BackupMetadata, String> {
+        let state = BackupState {
+            entities: snapshot.entities.iter().map(|e| (e.id.0, SerializedEntity::from_entity(e))).collect(),
+            edges: snapshot.edges.iter().map(|e| (e.id.0, SerializedEdge::from_edge(e))).collect(),
+            schemas: snapshot.schemas.clone(),
+            indexes: snapshot.indexes.clone(),
+        };
 
-        let mut file = File::create(&backup_path)
-            .map_err(|e| format!("Failed to create backup file: {}", e))?;
+        let (backup_data, parent_backup_id, base_backup_id) = match backup_type {
+            BackupType::Full => (state.into_data(), None, None),
+            BackupType::Incremental => {
+                let parent = self
+                    .list_backups()?
+                    .into_iter()
+                    .next()
+                    .ok_or("No backup to base an incremental backup on")?;
+                let base = match parent.backup_type {
+                    BackupType::Full => Some(parent.backup_id.clone()),
+                    BackupType::Incremental => parent.base_backup_id.clone(),
+                };
+                (state.changes_since(self.load_state(&parent.backup_id)?), Some(parent.backup_id), base)
+            }
+        };
 
-        if self.config.compress {
-            // Compress with gzip
-            let compressed = compress_data(serialized.as_bytes())?;
-            file.write_all(&compressed)
-                .map_err(|e| format!("Failed to write backup: {}", e))?;
-        } else {
-            file.write_all(serialized.as_bytes())
-                .map_err(|e| format!("Failed to write backup: {}", e))?;
-        }
+        let backup_id = generate_backup_id();
+        let checksum = self.write_data(&backup_id, &backup_data)?;
 
         // Create metadata
         let metadata = BackupMetadata {
             backup_id: backup_id.clone(),
-            backup_type: BackupType::Full,
+            backup_type,
             timestamp: current_timestamp(),
-            entity_count: backup_data.entities.len(),
-            edge_count: backup_data.edges.len(),
+            entity_count: snapshot.entities.len(),
+            edge_count: snapshot.edges.len(),
             compressed: self.config.compress,
             checksum,
-            parent_backup_id: None,
+            parent_backup_id,
+            base_backup_id,
+            archive_segments: 0,
+            wal_lsn: snapshot.wal_lsn,
         };
 
         // Save metadata
@@ -134,60 +367,98 @@ impl BackupManager {
         Ok(metadata)
     }
 
-    /// Restore from backup
-    pub fn restore_backup(&self, backup_id: &str, graph: &mut Graph) -> Result<(), String> {
-        let backup_path = self.get_backup_path(backup_id);
-        let metadata = self.load_metadata(backup_id)?;
+    /// Create a full backup that also captures the archive tier
+    pub fn create_full_backup_with_archive(
+        &mut self,
+        graph: &Graph,
+        archive: &ArchiveManager,
+    ) -> Result<BackupMetadata, String> {
+        let mut metadata = self.create_full_backup(graph)?;
 
-        // Read backup file
-        let mut file = File::open(&backup_path)
-            .map_err(|e| format!("Failed to open backup file: {}", e))?;
-
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
-            .map_err(|e| format!("Failed to read backup: {}", e))?;
+        metadata.archive_segments = archive.export_to(self.get_archive_path(&metadata.backup_id))?;
+        self.save_metadata(&metadata)?;
 
-        // Decompress if needed
-        let data = if metadata.compressed {
-            decompress_data(&buffer)?
-        } else {
-            buffer
-        };
+        Ok(metadata)
+    }
 
-        let serialized = String::from_utf8(data)
-            .map_err(|e| format!("Invalid UTF-8 in backup: {}", e))?;
+    /// Restore a backup's archive segments into an archive directory
+    pub fn restore_archive<P: AsRef<Path>>(&self, backup_id: &str, archive_dir: P) -> Result<ArchiveManager, String> {
+        let backup_id = self.resolve_backup_id(backup_id)?;
+        let metadata = self.load_metadata(&backup_id)?;
 
-        // Verify checksum
-        if self.config.verify {
-            let checksum = calculate_checksum(&serialized);
-            if checksum != metadata.checksum {
-                return Err("Backup checksum mismatch - data may be corrupted".to_string());
-            }
+        if metadata.archive_segments > 0 {
+            let backed_up = ArchiveManager::open(self.get_archive_path(&backup_id))?;
+            backed_up.export_to(&archive_dir)?;
         }
 
-        // Deserialize
-        let backup_data: BackupData = serde_json::from_str(&serialized)
-            .map_err(|e| format!("Deserialization error: {}", e))?;
+        ArchiveManager::open(archive_dir)
+    }
+
+    /// Restore from backup
+    pub fn restore_backup(&self, backup_id: &str, graph: &mut Graph) -> Result<(), String> {
+        let snapshot = self.load_snapshot(backup_id)?;
 
-        // Clear existing data
-        *graph = Graph::new();
+        // Clear existing data, keeping the change feed's subscribers
+        *graph = Graph::new().with_change_feed(graph.change_feed());
 
         // Restore entities
-        for entity in backup_data.entities {
-            let restored = entity.to_entity();
+        for entity in snapshot.entities {
             // Insert entity directly (bypassing normal APIs for restoration)
-            graph.insert_entity_with_id(restored);
+            graph.insert_entity_with_id(entity)?;
         }
 
         // Restore edges
-        for edge in backup_data.edges {
-            let restored = edge.to_edge();
-            graph.insert_edge_with_id(restored);
+        for edge in snapshot.edges {
+            graph.insert_edge_with_id(edge)?;
         }
 
         Ok(())
     }
 
+    /// Load the state a backup restores to
+    ///
+    /// An incremental backup is replayed on top of its parent chain.
+    /// [`LATEST_BACKUP`] loads the newest backup with a complete chain.
+    pub fn load_snapshot(&self, backup_id: &str) -> Result<BackupSnapshot, String> {
+        let backup_id = self.resolve_backup_id(backup_id)?;
+        let metadata = self.load_metadata(&backup_id)?;
+        let state = self.load_state(&backup_id)?;
+
+        Ok(BackupSnapshot {
+            entities: state.entities.values().map(SerializedEntity::to_entity).collect(),
+            edges: state.edges.values().map(SerializedEdge::to_edge).collect(),
+            schemas: state.schemas,
+            indexes: state.indexes,
+            wal_lsn: metadata.wal_lsn,
+        })
+    }
+
+    fn load_state(&self, backup_id: &str) -> Result<BackupState, String> {
+        let metadata = self.load_metadata(backup_id)?;
+
+        let mut state = match (&metadata.backup_type, &metadata.parent_backup_id) {
+            (BackupType::Incremental, Some(parent)) => self.load_state(parent)?,
+            (BackupType::Incremental, None) => {
+                return Err(format!("Incremental backup {} has no parent", backup_id));
+            }
+            (BackupType::Full, _) => BackupState::default(),
+        };
+
+        let serialized = self.read_backup(backup_id, &metadata)?;
+
+        // Verify checksum
+        if self.config.verify && calculate_checksum(&serialized) != metadata.checksum {
+            return Err("Backup checksum mismatch - data may be corrupted".to_string());
+        }
+
+        // Deserialize
+        let backup_data: BackupData = serde_json::from_str(&serialized)
+            .map_err(|e| format!("Deserialization error: {}", e))?;
+
+        state.apply(backup_data);
+        Ok(state)
+    }
+
     /// List all backups
     pub fn list_backups(&self) -> Result<Vec<BackupMetadata>, String> {
         let mut backups = Vec::new();
@@ -211,8 +482,8 @@ impl BackupManager {
             }
         }
 
-        // Sort by timestamp (newest first)
-        backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        // Sort by timestamp (newest first); IDs order backups within a second
+        backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.backup_id.cmp(&a.backup_id)));
 
         Ok(backups)
     }
@@ -228,15 +499,132 @@ impl BackupManager {
         std::fs::remove_file(&metadata_path)
             .map_err(|e| format!("Failed to delete metadata file: {}", e))?;
 
+        let archive_path = self.get_archive_path(backup_id);
+        if archive_path.exists() {
+            std::fs::remove_dir_all(&archive_path)
+                .map_err(|e| format!("Failed to delete archive backup: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// The newest backup whose whole chain is in the directory
+    ///
+    /// This is what restoring [`LATEST_BACKUP`] restores.
+    pub fn latest_backup(&self) -> Result<BackupMetadata, String> {
+        let backups = self.list_backups()?;
+        let bases = chain_bases(&backups);
+        backups
+            .into_iter()
+            .find(|backup| bases.contains_key(&backup.backup_id))
+            .ok_or_else(|| "No complete backup to restore".to_string())
+    }
+
+    /// The backup `backup_id` names, resolving [`LATEST_BACKUP`]
+    fn resolve_backup_id(&self, backup_id: &str) -> Result<String, String> {
+        if backup_id == LATEST_BACKUP {
+            Ok(self.latest_backup()?.backup_id)
+        } else {
+            Ok(backup_id.to_string())
+        }
+    }
+
+    /// Delete the chains of all but the newest `policy.keep_full` full backups
+    ///
+    /// Returns the IDs of the backups deleted. Each one is first hidden by
+    /// renaming its metadata away, newest first, so an interrupted prune
+    /// never leaves a listed backup whose parent is gone; the hidden ones
+    /// (and any an earlier prune left behind) are then removed. Backups
+    /// whose chain is already broken are left alone.
+    pub fn apply_retention(&self, policy: &RetentionPolicy) -> Result<Vec<String>, String> {
+        let backups = self.list_backups()?;
+        let bases = chain_bases(&backups);
+        let kept: HashSet<&str> = backups
+            .iter()
+            .filter(|backup| backup.backup_type == BackupType::Full)
+            .take(policy.keep_full.max(1))
+            .map(|backup| backup.backup_id.as_str())
+            .collect();
+
+        let doomed: Vec<String> = backups
+            .iter()
+            .filter(|backup| bases.get(&backup.backup_id).is_some_and(|base| !kept.contains(base.as_str())))
+            .map(|backup| backup.backup_id.clone())
+            .collect();
+
+        for backup_id in &doomed {
+            std::fs::rename(self.get_metadata_path(backup_id), self.get_deleted_path(backup_id))
+                .map_err(|e| format!("Failed to delete metadata file: {}", e))?;
+        }
+        self.purge_deleted()?;
+
+        Ok(doomed)
+    }
+
+    /// Remove the data of backups whose metadata was hidden for deletion
+    fn purge_deleted(&self) -> Result<(), String> {
+        let entries = std::fs::read_dir(&self.config.backup_dir)
+            .map_err(|e| format!("Failed to read backup directory: {}", e))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            let file_name = entry.file_name();
+            let Some(backup_id) = file_name.to_str().and_then(|name| name.strip_suffix(DELETED_SUFFIX)) else {
+                continue;
+            };
+
+            let backup_path = self.get_backup_path(backup_id);
+            if backup_path.exists() {
+                std::fs::remove_file(&backup_path)
+                    .map_err(|e| format!("Failed to delete backup file: {}", e))?;
+            }
+            let archive_path = self.get_archive_path(backup_id);
+            if archive_path.exists() {
+                std::fs::remove_dir_all(&archive_path)
+                    .map_err(|e| format!("Failed to delete archive backup: {}", e))?;
+            }
+            std::fs::remove_file(entry.path())
+                .map_err(|e| format!("Failed to delete metadata file: {}", e))?;
+        }
+
         Ok(())
     }
 
     /// Verify backup integrity
     pub fn verify_backup(&self, backup_id: &str) -> Result<bool, String> {
-        let backup_path = self.get_backup_path(backup_id);
         let metadata = self.load_metadata(backup_id)?;
+        let serialized = self.read_backup(backup_id, &metadata)?;
 
-        let mut file = File::open(&backup_path)
+        let checksum = calculate_checksum(&serialized);
+        Ok(checksum == metadata.checksum)
+    }
+
+    /// Write a backup's data, returning its checksum
+    fn write_data(&self, backup_id: &str, backup_data: &BackupData) -> Result<String, String> {
+        let serialized = serde_json::to_string(backup_data)
+            .map_err(|e| format!("Serialization error: {}", e))?;
+
+        let checksum = calculate_checksum(&serialized);
+
+        let mut file = File::create(self.get_backup_path(backup_id))
+            .map_err(|e| format!("Failed to create backup file: {}", e))?;
+
+        if self.config.compress {
+            // Compress with gzip
+            let compressed = compress_data(serialized.as_bytes())?;
+            file.write_all(&compressed)
+                .map_err(|e| format!("Failed to write backup: {}", e))?;
+        } else {
+            file.write_all(serialized.as_bytes())
+                .map_err(|e| format!("Failed to write backup: {}", e))?;
+        }
+
+        Ok(checksum)
+    }
+
+    /// Read a backup's serialized data, decompressing it if needed
+    fn read_backup(&self, backup_id: &str, metadata: &BackupMetadata) -> Result<String, String> {
+        let mut file = File::open(self.get_backup_path(backup_id))
             .map_err(|e| format!("Failed to open backup file: {}", e))?;
 
         let mut buffer = Vec::new();
@@ -249,21 +637,26 @@ impl BackupManager {
             buffer
         };
 
-        let serialized = String::from_utf8(data)
-            .map_err(|e| format!("Invalid UTF-8 in backup: {}", e))?;
-
-        let checksum = calculate_checksum(&serialized);
-        Ok(checksum == metadata.checksum)
+        String::from_utf8(data)
+            .map_err(|e| format!("Invalid UTF-8 in backup: {}", e))
     }
 
     fn get_backup_path(&self, backup_id: &str) -> PathBuf {
         self.config.backup_dir.join(format!("{}.backup", backup_id))
     }
 
+    fn get_archive_path(&self, backup_id: &str) -> PathBuf {
+        self.config.backup_dir.join(format!("{}.archive", backup_id))
+    }
+
     fn get_metadata_path(&self, backup_id: &str) -> PathBuf {
         self.config.backup_dir.join(format!("{}.meta", backup_id))
     }
 
+    fn get_deleted_path(&self, backup_id: &str) -> PathBuf {
+        self.config.backup_dir.join(format!("{}{}", backup_id, DELETED_SUFFIX))
+    }
+
     fn save_metadata(&self, metadata: &BackupMetadata) -> Result<(), String> {
         let path = self.get_metadata_path(&metadata.backup_id);
         let serialized = serde_json::to_string_pretty(metadata)
@@ -286,17 +679,84 @@ impl BackupManager {
 }
 
 /// Serialized backup data
+///
+/// A full backup holds everything; an incremental one holds the entities and
+/// edges added or changed since its parent, plus the IDs of deleted ones.
+/// Schemas and index definitions are always stored whole.
 #[derive(Debug, Serialize, Deserialize)]
 struct BackupData {
     entities: Vec<SerializedEntity>,
     edges: Vec<SerializedEdge>,
+    #[serde(default)]
+    deleted_entities: Vec<u64>,
+    #[serde(default)]
+    deleted_edges: Vec<u64>,
+    #[serde(default)]
+    schemas: Vec<Schema>,
+    #[serde(default)]
+    indexes: Vec<IndexDefinition>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// State a backup chain restores to, keyed by ID
+#[derive(Debug, Default)]
+struct BackupState {
+    entities: BTreeMap<u64, SerializedEntity>,
+    edges: BTreeMap<u64, SerializedEdge>,
+    schemas: Vec<Schema>,
+    indexes: Vec<IndexDefinition>,
+}
+
+impl BackupState {
+    fn into_data(self) -> BackupData {
+        BackupData {
+            entities: self.entities.into_values().collect(),
+            edges: self.edges.into_values().collect(),
+            deleted_entities: Vec::new(),
+            deleted_edges: Vec::new(),
+            schemas: self.schemas,
+            indexes: self.indexes,
+        }
+    }
+
+    /// What changed going from `base` to this state
+    fn changes_since(mut self, base: BackupState) -> BackupData {
+        let deleted_entities = base.entities.keys().filter(|id| !self.entities.contains_key(id)).copied().collect();
+        let deleted_edges = base.edges.keys().filter(|id| !self.edges.contains_key(id)).copied().collect();
+
+        self.entities.retain(|id, entity| base.entities.get(id) != Some(entity));
+        self.edges.retain(|id, edge| base.edges.get(id) != Some(edge));
+
+        BackupData {
+            deleted_entities,
+            deleted_edges,
+            ..self.into_data()
+        }
+    }
+
+    fn apply(&mut self, data: BackupData) {
+        for id in data.deleted_entities {
+            self.entities.remove(&id);
+        }
+        for id in data.deleted_edges {
+            self.edges.remove(&id);
+        }
+
+        self.entities.extend(data.entities.into_iter().map(|e| (e.id, e)));
+        self.edges.extend(data.edges.into_iter().map(|e| (e.id, e)));
+        self.schemas = data.schemas;
+        self.indexes = data.indexes;
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct SerializedEntity {
     id: u64,
     entity_type: String,
     properties: HashMap<String, PropertyValue>,
+    /// Milliseconds since the Unix epoch; backups from before it was kept
+    /// restore entities as created at restore
+    #[serde(default)]
+    created_at: Option<u64>,
 }
 
 impl SerializedEntity {
@@ -305,23 +765,28 @@ impl SerializedEntity {
             id: entity.id.0,
             entity_type: entity.entity_type.clone(),
             properties: entity.properties.clone(),
+            created_at: Some(entity.created_millis()),
         }
     }
 
     fn to_entity(&self) -> Entity {
         let now = SystemTime::now();
-        Entity {
+        let entity = Entity {
             id: EntityId(self.id),
             entity_type: self.entity_type.clone(),
             properties: self.properties.clone(),
             access_count: 0,
             last_accessed: now,
             created_at: now,
+        };
+        match self.created_at {
+            Some(created_at) => entity.with_created_at(created_at),
+            None => entity,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct SerializedEdge {
     id: u64,
     from_id: u64,
@@ -359,9 +824,39 @@ impl SerializedEdge {
     }
 }
 
+/// The full backup each backup's chain starts from, for backups whose
+/// parents are all present
+fn chain_bases(backups: &[BackupMetadata]) -> HashMap<String, String> {
+    let by_id: HashMap<&str, &BackupMetadata> = backups.iter().map(|b| (b.backup_id.as_str(), b)).collect();
+    let mut bases = HashMap::new();
+
+    for backup in backups {
+        let mut current = backup;
+        // A chain can't be longer than the backups there are
+        for _ in 0..backups.len() {
+            match (current.backup_type, &current.parent_backup_id) {
+                (BackupType::Full, _) => {
+                    bases.insert(backup.backup_id.clone(), current.backup_id.clone());
+                    break;
+                }
+                (BackupType::Incremental, Some(parent)) => match by_id.get(parent.as_str()) {
+                    Some(parent) => current = parent,
+                    None => break,
+                },
+                (BackupType::Incremental, None) => break,
+            }
+        }
+    }
+
+    bases
+}
+
 fn generate_backup_id() -> String {
-    let timestamp = current_timestamp();
-    format!("backup_{}", timestamp)
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("backup_{}", nanos)
 }
 
 fn current_timestamp() -> u64 {
@@ -419,10 +914,10 @@ mod tests {
 
     #[test]
     fn test_full_backup_and_restore() {
-        let mut graph = Graph::new();
+        let graph = Graph::new();
 
         // Add test data
-        let entity_id = graph.create_entity("User".to_string(), HashMap::new());
+        let _entity_id = graph.create_entity("User".to_string(), HashMap::new()).unwrap();
 
         // Create backup
         let config = BackupConfig {
@@ -448,8 +943,8 @@ mod tests {
 
     #[test]
     fn test_backup_verification() {
-        let mut graph = Graph::new();
-        graph.create_entity("User".to_string(), HashMap::new());
+        let graph = Graph::new();
+        graph.create_entity("User".to_string(), HashMap::new()).unwrap();
 
         let config = BackupConfig {
             backup_dir: PathBuf::from("/tmp/deed_test_backups_3"),
@@ -473,7 +968,7 @@ mod tests {
         };
 
         let mut backup_mgr = BackupManager::new(config).unwrap();
-        let mut graph = Graph::new();
+        let graph = Graph::new();
 
         // Create multiple backups
         backup_mgr.create_full_backup(&graph).unwrap();
@@ -497,4 +992,58 @@ mod tests {
         assert_eq!(checksum1, checksum2);
         assert_ne!(checksum1, checksum3);
     }
+
+    #[test]
+    fn test_scheduled_backups_follow_schedule_and_keep_newest_chains() {
+        let backup_dir = PathBuf::from("/tmp/deed_test_backups_scheduled");
+        let _ = std::fs::remove_dir_all(&backup_dir);
+        let config = BackupConfig {
+            backup_dir,
+            compress: false,
+            verify: true,
+        };
+        let hour = Duration::from_secs(60 * 60);
+        let start = Instant::now();
+        let scheduler = BackupScheduler::starting_at(
+            config.clone(),
+            BackupSchedule {
+                full_interval: 24 * hour,
+                incremental_interval: Some(6 * hour),
+                retention: RetentionPolicy { keep_full: 2 },
+            },
+            start,
+        )
+        .unwrap();
+
+        // Three days, checked every hour, with a user added before each check
+        let graph = Graph::new();
+        let mut taken = Vec::new();
+        for hours in 0..72 {
+            graph.create_entity("User".to_string(), HashMap::new()).unwrap();
+            if let Some(result) = scheduler.run_due(start + hours * hour, &|| Ok(BackupSnapshot::of_graph(&graph))) {
+                taken.push((hours, result.unwrap().backup_type));
+            }
+        }
+
+        let fulls: Vec<u32> = taken.iter().filter(|(_, t)| *t == BackupType::Full).map(|(h, _)| *h).collect();
+        let incrementals = taken.iter().filter(|(_, t)| *t == BackupType::Incremental).count();
+        assert_eq!(fulls, vec![0, 24, 48]);
+        assert_eq!(incrementals, 9);
+        assert_eq!(scheduler.next_run(), start + 72 * hour);
+
+        // The first day's chain is gone; the others have their incrementals
+        let manager = BackupManager::new(config).unwrap();
+        let backups = manager.list_backups().unwrap();
+        assert_eq!(backups.len(), 8);
+        let bases: HashSet<String> = backups.iter().map(|b| b.base_backup_id.clone().unwrap_or_else(|| b.backup_id.clone())).collect();
+        assert_eq!(bases.len(), 2);
+        assert_eq!(backups.iter().filter(|b| b.backup_type == BackupType::Full).count(), 2);
+
+        // The latest is the newest full and its incrementals
+        let latest = manager.load_snapshot(LATEST_BACKUP).unwrap();
+        assert_eq!(latest.entities.len(), 67);
+        let status = scheduler.status();
+        assert_eq!(status.last_backup_id, Some(backups[0].backup_id.clone()));
+        assert_eq!(status.last_error, None);
+    }
 }
@@ -1,12 +1,20 @@
 //! B-tree Index Implementation
 //!
-//! Provides fast O(log n) lookups for indexed fields.
+//! Provides fast O(log n) lookups for indexed fields. A full-text index is
+//! the same tree keyed by the terms of a text field instead of its value.
 
+use crate::graph::Graph;
 use crate::types::{EntityId, PropertyValue};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::{Arc, RwLock};
 
+/// Entities indexed per chunk (between lock releases) during an online rebuild
+const REBUILD_CHUNK_SIZE: usize = 256;
+
+/// Most change-log passes a rebuild makes before pausing index maintenance
+const REBUILD_CATCH_UP_ROUNDS: usize = 8;
+
 /// B-tree index for fast lookups
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BTreeIndex {
@@ -16,10 +24,43 @@ pub struct BTreeIndex {
     pub collection: String,
     /// Indexed field
     pub field: String,
-    /// B-tree mapping field value to entity IDs
-    pub tree: BTreeMap<IndexKey, Vec<EntityId>>,
+    /// B-tree mapping field value to the set of entity IDs holding it
+    pub tree: BTreeMap<IndexKey, BTreeSet<EntityId>>,
     /// Whether index is unique
     pub unique: bool,
+    /// What the tree is keyed by
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+/// What an index's tree is keyed by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexKind {
+    /// The field's value
+    #[default]
+    BTree,
+    /// Each distinct term of the field's text (see [`tokenize`])
+    FullText,
+}
+
+/// Split text into the terms a full-text index is keyed by: lowercased runs
+/// of letters and digits
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The distinct terms of an indexed value (only strings have any)
+fn value_terms(value: &PropertyValue) -> Vec<String> {
+    let PropertyValue::String(text) = value else {
+        return Vec::new();
+    };
+    let mut terms = tokenize(text);
+    terms.sort();
+    terms.dedup();
+    terms
 }
 
 /// Index key - wrapper around PropertyValue for BTreeMap
@@ -30,6 +71,8 @@ pub enum IndexKey {
     Int(i64),
     Float(OrderedFloat),
     String(String),
+    /// Milliseconds since the Unix epoch
+    Timestamp(i64),
 }
 
 /// Wrapper for f64 to make it Ord (required for BTreeMap keys)
@@ -65,6 +108,8 @@ impl From<&PropertyValue> for IndexKey {
             PropertyValue::Float(f) => IndexKey::Float(OrderedFloat(*f)),
             PropertyValue::String(s) => IndexKey::String(s.clone()),
             PropertyValue::Bytes(b) => IndexKey::String(format!("{:?}", b)), // Convert bytes to string representation for indexing
+            PropertyValue::List(_) | PropertyValue::Map(_) => IndexKey::String(format!("{:?}", value.distinct_key())),
+            PropertyValue::Timestamp(ms) => IndexKey::Timestamp(*ms),
         }
     }
 }
@@ -78,49 +123,114 @@ impl BTreeIndex {
             field,
             tree: BTreeMap::new(),
             unique,
+            kind: IndexKind::BTree,
         }
     }
 
+    /// Create a new empty full-text index
+    pub fn fulltext(name: String, collection: String, field: String) -> Self {
+        BTreeIndex::new(name, collection, field, false).with_kind(IndexKind::FullText)
+    }
+
+    fn with_kind(mut self, kind: IndexKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     /// Insert a value into the index
     pub fn insert(&mut self, key: &PropertyValue, entity_id: EntityId) -> Result<(), String> {
+        if self.kind == IndexKind::FullText {
+            for term in value_terms(key) {
+                self.tree.entry(IndexKey::String(term)).or_default().insert(entity_id);
+            }
+            return Ok(());
+        }
+
         let index_key = IndexKey::from(key);
 
         if self.unique {
-            // Check if key already exists
-            if self.tree.contains_key(&index_key) {
+            // Check if key already exists (re-inserting the same entity is a no-op)
+            if let Some(ids) = self.tree.get(&index_key) {
+                if ids.contains(&entity_id) {
+                    return Ok(());
+                }
                 return Err(format!(
-                    "Unique constraint violated: duplicate key {:?}",
+                    "Unique constraint violated: key {:?} already exists",
                     index_key
                 ));
             }
-            self.tree.insert(index_key, vec![entity_id]);
+            self.tree.insert(index_key, BTreeSet::from([entity_id]));
         } else {
-            // Non-unique index - append to list
-            self.tree
-                .entry(index_key)
-                .or_insert_with(Vec::new)
-                .push(entity_id);
+            // Non-unique index - add to the key's set
+            self.tree.entry(index_key).or_default().insert(entity_id);
         }
 
         Ok(())
     }
 
+    /// Insert a value whose uniqueness was already checked, so it never fails
+    fn insert_checked(&mut self, key: &PropertyValue, entity_id: EntityId) {
+        for index_key in self.keys_of(key) {
+            self.tree.entry(index_key).or_default().insert(entity_id);
+        }
+    }
+
     /// Remove a value from the index
     pub fn remove(&mut self, key: &PropertyValue, entity_id: EntityId) {
-        let index_key = IndexKey::from(key);
+        for index_key in self.keys_of(key) {
+            self.remove_key(&index_key, entity_id);
+        }
+    }
+
+    /// Drop an entity from one key, returning whether it was there
+    fn remove_key(&mut self, index_key: &IndexKey, entity_id: EntityId) -> bool {
+        let Some(ids) = self.tree.get_mut(index_key) else {
+            return false;
+        };
+        let removed = ids.remove(&entity_id);
+        if ids.is_empty() {
+            self.tree.remove(index_key);
+        }
+        removed
+    }
+
+    /// Keys a value is indexed under
+    fn keys_of(&self, value: &PropertyValue) -> Vec<IndexKey> {
+        match self.kind {
+            IndexKind::BTree => vec![IndexKey::from(value)],
+            IndexKind::FullText => value_terms(value).into_iter().map(IndexKey::String).collect(),
+        }
+    }
 
-        if let Some(ids) = self.tree.get_mut(&index_key) {
-            ids.retain(|id| *id != entity_id);
-            if ids.is_empty() {
-                self.tree.remove(&index_key);
+    /// Entities whose text holds every term (a full-text index's posting
+    /// lists intersected, shortest first)
+    pub fn match_terms(&self, terms: &[String]) -> Vec<EntityId> {
+        let mut postings: Vec<&BTreeSet<EntityId>> = Vec::new();
+        for term in terms {
+            match self.tree.get(&IndexKey::String(term.clone())) {
+                Some(ids) => postings.push(ids),
+                None => return Vec::new(),
             }
         }
+        postings.sort_by_key(|ids| ids.len());
+
+        let Some((shortest, rest)) = postings.split_first() else {
+            return Vec::new();
+        };
+        shortest
+            .iter()
+            .filter(|id| rest.iter().all(|ids| ids.contains(*id)))
+            .copied()
+            .collect()
     }
 
     /// Lookup entities by exact key match
     pub fn lookup(&self, key: &PropertyValue) -> Vec<EntityId> {
         let index_key = IndexKey::from(key);
-        self.tree.get(&index_key).cloned().unwrap_or_default()
+        self.tree
+            .get(&index_key)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
     }
 
     /// Range scan: find all keys in range [start, end]
@@ -161,6 +271,90 @@ impl BTreeIndex {
         result
     }
 
+    /// Lookup by value, matching numbers across Int and Float keys (30 finds 30.0)
+    pub fn lookup_value(&self, value: &PropertyValue) -> Vec<EntityId> {
+        let mut keys = vec![IndexKey::from(value)];
+        match value {
+            PropertyValue::Int(i) => keys.push(IndexKey::Float(OrderedFloat(*i as f64))),
+            PropertyValue::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                keys.push(IndexKey::Int(*f as i64))
+            }
+            _ => {}
+        }
+
+        keys.iter()
+            .filter_map(|key| self.tree.get(key))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Entities with keys between optional inclusive bounds
+    ///
+    /// Numeric bounds cover both Int and Float keys; string bounds cover
+    /// string keys. Returns `None` when the bounds can't be served from the
+    /// index (mixed or non-orderable types).
+    pub fn range_values(
+        &self,
+        lower: Option<&PropertyValue>,
+        upper: Option<&PropertyValue>,
+    ) -> Option<Vec<EntityId>> {
+        use std::ops::Bound::{Included, Unbounded};
+
+        let numeric = |v: Option<&PropertyValue>| match v {
+            None => Some(None),
+            Some(PropertyValue::Int(i)) => Some(Some(*i as f64)),
+            Some(PropertyValue::Float(f)) if !f.is_nan() => Some(Some(*f)),
+            _ => None,
+        };
+        let string = |v: Option<&PropertyValue>| match v {
+            None => Some(None),
+            Some(PropertyValue::String(s)) => Some(Some(s.clone())),
+            _ => None,
+        };
+
+        let mut result = Vec::new();
+
+        if let (Some(lo), Some(hi)) = (numeric(lower), numeric(upper)) {
+            if lower.is_none() && upper.is_none() {
+                return None;
+            }
+
+            // Int keys: round bounds inwards
+            let int_lo = lo.map_or(i64::MIN, |f| f.ceil().max(i64::MIN as f64) as i64);
+            let int_hi = hi.map_or(i64::MAX, |f| f.floor().min(i64::MAX as f64) as i64);
+            if int_lo <= int_hi {
+                for (_, ids) in self.tree.range(IndexKey::Int(int_lo)..=IndexKey::Int(int_hi)) {
+                    result.extend(ids);
+                }
+            }
+
+            let float_lo = IndexKey::Float(OrderedFloat(lo.unwrap_or(f64::NEG_INFINITY)));
+            let float_hi = IndexKey::Float(OrderedFloat(hi.unwrap_or(f64::INFINITY)));
+            if float_lo <= float_hi {
+                for (_, ids) in self.tree.range(float_lo..=float_hi) {
+                    result.extend(ids);
+                }
+            }
+
+            return Some(result);
+        }
+
+        if let (Some(lo), Some(hi)) = (string(lower), string(upper)) {
+            let lo = IndexKey::String(lo.unwrap_or_default());
+            let hi = hi.map_or(Unbounded, |s| Included(IndexKey::String(s)));
+            if matches!(&hi, Included(hi) if hi < &lo) {
+                return Some(result);
+            }
+            for (_, ids) in self.tree.range((Included(lo), hi)) {
+                result.extend(ids);
+            }
+            return Some(result);
+        }
+
+        None
+    }
+
     /// Get index size (number of unique keys)
     pub fn size(&self) -> usize {
         self.tree.len()
@@ -170,12 +364,89 @@ impl BTreeIndex {
     pub fn total_entities(&self) -> usize {
         self.tree.values().map(|v| v.len()).sum()
     }
+
+    /// Keys among `keys` held by more than one entity, described for an error
+    fn conflicts<'a>(&self, keys: impl Iterator<Item = &'a IndexKey>) -> Vec<String> {
+        keys.filter_map(|key| self.tree.get(key).map(|ids| (key, ids)))
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|(key, ids)| format!("{:?} -> {:?}", key, ids.iter().map(|id| id.0).collect::<Vec<_>>()))
+            .collect()
+    }
+}
+
+/// An index being rebuilt, with the entities index maintenance changed since it began
+#[derive(Debug, Clone)]
+struct ShadowIndex {
+    index: BTreeIndex,
+    /// Values maintenance indexed or removed, per changed entity
+    changes: HashMap<EntityId, Vec<PropertyValue>>,
+}
+
+impl ShadowIndex {
+    fn new(index: BTreeIndex) -> Self {
+        ShadowIndex {
+            index,
+            changes: HashMap::new(),
+        }
+    }
+
+    /// Log a maintenance write for an entity
+    fn record(&mut self, entity_id: EntityId, properties: &std::collections::HashMap<String, PropertyValue>) {
+        let touched = self.changes.entry(entity_id).or_default();
+        if let Some(value) = properties.get(&self.index.field) {
+            if !touched.contains(value) {
+                touched.push(value.clone());
+            }
+        }
+    }
+
+    /// Re-index the logged entities from the graph and clear the log
+    ///
+    /// Returns the entries that had to be added or removed; the keys the
+    /// entities now hold are added to `current`.
+    fn catch_up(&mut self, graph: &Graph, current: &mut BTreeSet<IndexKey>) -> usize {
+        let mut fixes = 0;
+        for (entity_id, touched) in std::mem::take(&mut self.changes) {
+            let keys: BTreeSet<IndexKey> = graph
+                .get_entity(entity_id)
+                .filter(|entity| entity.entity_type == self.index.collection)
+                .and_then(|entity| entity.properties.get(&self.index.field).map(|value| self.index.keys_of(value)))
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+
+            for value in &touched {
+                for key in self.index.keys_of(value) {
+                    if !keys.contains(&key) && self.index.remove_key(&key, entity_id) {
+                        fixes += 1;
+                    }
+                }
+            }
+            for key in &keys {
+                if self.index.tree.entry(key.clone()).or_default().insert(entity_id) {
+                    fixes += 1;
+                }
+            }
+            current.extend(keys);
+        }
+        fixes
+    }
 }
 
+/// Unique values claimed by open transactions' writes, keyed by index name
+/// and value, with the claiming transaction and entity
+type Claims = BTreeMap<(String, IndexKey), (u64, EntityId)>;
+
 /// Index manager - manages all indexes for a database
 #[derive(Debug, Clone)]
 pub struct IndexManager {
     indexes: Arc<RwLock<Vec<BTreeIndex>>>,
+    /// Shadow indexes being rebuilt, keyed by index name (double-written)
+    shadows: Arc<RwLock<HashMap<String, ShadowIndex>>>,
+    /// Progress of current and most recent rebuilds, keyed by index name
+    rebuilds: Arc<RwLock<HashMap<String, RebuildProgress>>>,
+    /// Unique values claimed by open transactions' writes
+    claims: Arc<RwLock<Claims>>,
 }
 
 impl IndexManager {
@@ -183,6 +454,9 @@ impl IndexManager {
     pub fn new() -> Self {
         IndexManager {
             indexes: Arc::new(RwLock::new(Vec::new())),
+            shadows: Arc::new(RwLock::new(HashMap::new())),
+            rebuilds: Arc::new(RwLock::new(HashMap::new())),
+            claims: Arc::new(RwLock::new(BTreeMap::new())),
         }
     }
 
@@ -194,14 +468,22 @@ impl IndexManager {
         field: String,
         unique: bool,
     ) -> Result<(), String> {
+        self.add_index(BTreeIndex::new(name, collection, field, unique))
+    }
+
+    /// Create a new full-text index
+    pub fn create_fulltext_index(&self, name: String, collection: String, field: String) -> Result<(), String> {
+        self.add_index(BTreeIndex::fulltext(name, collection, field))
+    }
+
+    fn add_index(&self, index: BTreeIndex) -> Result<(), String> {
         let mut indexes = self.indexes.write().unwrap();
 
         // Check if index already exists
-        if indexes.iter().any(|idx| idx.name == name) {
-            return Err(format!("Index {} already exists", name));
+        if indexes.iter().any(|idx| idx.name == index.name) {
+            return Err(format!("Index {} already exists", index.name));
         }
 
-        let index = BTreeIndex::new(name, collection, field, unique);
         indexes.push(index);
 
         Ok(())
@@ -221,21 +503,104 @@ impl IndexManager {
         }
     }
 
+    /// Empty every index on a collection, keeping their definitions
+    pub fn clear_collection(&self, collection: &str) {
+        let mut indexes = self.indexes.write().unwrap();
+        for index in indexes.iter_mut().filter(|idx| idx.collection == collection) {
+            index.tree.clear();
+        }
+
+        let mut shadows = self.shadows.write().unwrap();
+        for shadow in shadows.values_mut().filter(|shadow| shadow.index.collection == collection) {
+            shadow.index.tree.clear();
+        }
+    }
+
+    /// Drop every index on a collection, returning their names
+    pub fn drop_collection_indexes(&self, collection: &str) -> Vec<String> {
+        let mut indexes = self.indexes.write().unwrap();
+        let (dropped, kept): (Vec<BTreeIndex>, Vec<BTreeIndex>) =
+            indexes.drain(..).partition(|idx| idx.collection == collection);
+        *indexes = kept;
+        dropped.into_iter().map(|idx| idx.name).collect()
+    }
+
     /// Get index by name
     pub fn get_index(&self, name: &str) -> Option<BTreeIndex> {
         let indexes = self.indexes.read().unwrap();
         indexes.iter().find(|idx| idx.name == name).cloned()
     }
 
-    /// Find index for collection and field
+    /// Find the (value) index for collection and field
     pub fn find_index(&self, collection: &str, field: &str) -> Option<BTreeIndex> {
         let indexes = self.indexes.read().unwrap();
         indexes
             .iter()
-            .find(|idx| idx.collection == collection && idx.field == field)
+            .find(|idx| idx.collection == collection && idx.field == field && idx.kind == IndexKind::BTree)
             .cloned()
     }
 
+    /// Name of the (value) index on a collection's field, if any
+    pub fn index_name_for(&self, collection: &str, field: &str) -> Option<String> {
+        self.index_name_of_kind(collection, field, IndexKind::BTree)
+    }
+
+    /// Name of the full-text index on a collection's field, if any
+    pub fn fulltext_index_for(&self, collection: &str, field: &str) -> Option<String> {
+        self.index_name_of_kind(collection, field, IndexKind::FullText)
+    }
+
+    fn index_name_of_kind(&self, collection: &str, field: &str, kind: IndexKind) -> Option<String> {
+        let indexes = self.indexes.read().unwrap();
+        indexes
+            .iter()
+            .find(|idx| idx.collection == collection && idx.field == field && idx.kind == kind)
+            .map(|idx| idx.name.clone())
+    }
+
+    /// Look up entities by value in a named index
+    pub fn lookup_in_index(&self, name: &str, value: &PropertyValue) -> Result<Vec<EntityId>, String> {
+        let indexes = self.indexes.read().unwrap();
+        let index = indexes
+            .iter()
+            .find(|idx| idx.name == name)
+            .ok_or_else(|| format!("Index {} not found", name))?;
+
+        Ok(index.lookup_value(value))
+    }
+
+    /// Range scan a named index between optional inclusive bounds
+    pub fn range_scan_in_index(
+        &self,
+        name: &str,
+        lower: Option<&PropertyValue>,
+        upper: Option<&PropertyValue>,
+    ) -> Result<Vec<EntityId>, String> {
+        let indexes = self.indexes.read().unwrap();
+        let index = indexes
+            .iter()
+            .find(|idx| idx.name == name)
+            .ok_or_else(|| format!("Index {} not found", name))?;
+
+        index
+            .range_values(lower, upper)
+            .ok_or_else(|| format!("Index {} cannot serve this range", name))
+    }
+
+    /// Entities whose text holds every term, from a named full-text index
+    pub fn match_in_index(&self, name: &str, terms: &[String]) -> Result<Vec<EntityId>, String> {
+        let indexes = self.indexes.read().unwrap();
+        let index = indexes
+            .iter()
+            .find(|idx| idx.name == name)
+            .ok_or_else(|| format!("Index {} not found", name))?;
+
+        if index.kind != IndexKind::FullText {
+            return Err(format!("Index {} is not a FULLTEXT index", name));
+        }
+        Ok(index.match_terms(terms))
+    }
+
     /// Insert into all relevant indexes
     pub fn insert_into_indexes(
         &self,
@@ -253,9 +618,95 @@ impl IndexManager {
             }
         }
 
+        // Double-write into shadows so rebuilds don't miss concurrent mutations
+        let mut shadows = self.shadows.write().unwrap();
+        for shadow in shadows.values_mut().filter(|shadow| shadow.index.collection == collection) {
+            shadow.record(entity_id, properties);
+            if let Some(value) = properties.get(&shadow.index.field) {
+                shadow.index.insert(value, entity_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Claim the unique values a transaction writes for a batch of entities
+    ///
+    /// The indexes only change when the transaction commits; until then the
+    /// claims keep other transactions from writing the same values. Fails
+    /// with the row, field and value of the first collision, claiming
+    /// nothing: with another transaction's claim, another row of the batch,
+    /// or another entity that still has the value (one in the index, or one
+    /// the writer claimed it for before). `value_of` gives an entity's value
+    /// for a field as the writer sees it.
+    pub fn claim_unique<F>(
+        &self,
+        owner: u64,
+        collection: &str,
+        entries: &[(EntityId, &std::collections::HashMap<String, PropertyValue>)],
+        value_of: F,
+    ) -> Result<(), (usize, String, PropertyValue)>
+    where
+        F: Fn(EntityId, &str) -> Option<PropertyValue>,
+    {
+        let indexes = self.indexes.read().unwrap();
+        let mut claims = self.claims.write().unwrap();
+        let mut claimed = BTreeMap::new();
+
+        for index in indexes.iter().filter(|idx| idx.collection == collection && idx.unique) {
+            for (row, (entity_id, properties)) in entries.iter().enumerate() {
+                let Some(value) = properties.get(&index.field) else { continue };
+                let key = (index.name.clone(), IndexKey::from(value));
+                let collides = |other: EntityId| {
+                    other != *entity_id && value_of(other, &index.field).is_some_and(|v| IndexKey::from(&v) == key.1)
+                };
+
+                let taken = claimed.contains_key(&key)
+                    || match claims.get(&key) {
+                        Some(&(holder_txn, _)) if holder_txn != owner => true,
+                        Some(&(_, holder)) => collides(holder),
+                        None => false,
+                    }
+                    || index.lookup(value).into_iter().any(collides);
+                if taken {
+                    return Err((row, index.field.clone(), value.clone()));
+                }
+                claimed.insert(key, (owner, *entity_id));
+            }
+        }
+
+        claims.extend(claimed);
         Ok(())
     }
 
+    /// Release a finished transaction's unique claims
+    pub fn release_claims(&self, owner: u64) {
+        self.claims.write().unwrap().retain(|_, (holder_txn, _)| *holder_txn != owner);
+    }
+
+    /// Index a committed entity whose unique values were claimed for it
+    pub fn insert_claimed(
+        &self,
+        collection: &str,
+        entity_id: EntityId,
+        properties: &std::collections::HashMap<String, PropertyValue>,
+    ) {
+        let mut indexes = self.indexes.write().unwrap();
+        for index in indexes.iter_mut().filter(|idx| idx.collection == collection) {
+            if let Some(value) = properties.get(&index.field) {
+                index.insert_checked(value, entity_id);
+            }
+        }
+
+        let mut shadows = self.shadows.write().unwrap();
+        for shadow in shadows.values_mut().filter(|shadow| shadow.index.collection == collection) {
+            shadow.record(entity_id, properties);
+            if let Some(value) = properties.get(&shadow.index.field) {
+                shadow.index.insert_checked(value, entity_id);
+            }
+        }
+    }
+
     /// Remove from all relevant indexes
     pub fn remove_from_indexes(
         &self,
@@ -272,6 +723,204 @@ impl IndexManager {
                 }
             }
         }
+
+        let mut shadows = self.shadows.write().unwrap();
+        for shadow in shadows.values_mut().filter(|shadow| shadow.index.collection == collection) {
+            shadow.record(entity_id, properties);
+            if let Some(value) = properties.get(&shadow.index.field) {
+                shadow.index.remove(value, entity_id);
+            }
+        }
+    }
+
+    /// Rebuild an index online and swap it in
+    ///
+    /// A shadow index is filled by scanning the collection in chunks while
+    /// concurrent index maintenance is double-written to it and logged.
+    /// Catch-up passes re-index the logged entities from the graph; the last
+    /// one runs with index maintenance paused, just before the shadow
+    /// replaces the live index. Unique violations abort the swap and leave
+    /// the live index in service.
+    pub fn rebuild_index(&self, name: &str, graph: &Graph) -> Result<RebuildReport, String> {
+        let (collection, field, unique, kind) = {
+            let indexes = self.indexes.read().unwrap();
+            let index = indexes
+                .iter()
+                .find(|idx| idx.name == name)
+                .ok_or_else(|| format!("Index {} not found", name))?;
+            (index.collection.clone(), index.field.clone(), index.unique, index.kind)
+        };
+
+        {
+            let mut shadows = self.shadows.write().unwrap();
+            if shadows.contains_key(name) {
+                return Err(format!("Index {} is already being rebuilt", name));
+            }
+            // Shadow is non-unique while building; uniqueness is checked before the swap
+            shadows.insert(
+                name.to_string(),
+                ShadowIndex::new(
+                    BTreeIndex::new(name.to_string(), collection.clone(), field.clone(), false).with_kind(kind),
+                ),
+            );
+        }
+
+        let entity_ids: Vec<EntityId> = graph
+            .scan_collection(&collection)
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        self.set_rebuild_progress(name, RebuildPhase::Scanning, 0, entity_ids.len());
+
+        for (chunk_num, chunk) in entity_ids.chunks(REBUILD_CHUNK_SIZE).enumerate() {
+            let mut shadows = self.shadows.write().unwrap();
+            let shadow = shadows
+                .get_mut(name)
+                .ok_or_else(|| format!("Rebuild of index {} was cancelled", name))?;
+
+            // Re-read under the shadow lock so a racing writer's index update lands after ours
+            for entity_id in chunk {
+                if let Some(entity) = graph.get_entity(*entity_id) {
+                    if let Some(value) = entity.properties.get(&field) {
+                        shadow.index.insert(value, *entity_id)?;
+                    }
+                }
+            }
+            drop(shadows);
+
+            let scanned = (chunk_num * REBUILD_CHUNK_SIZE + chunk.len()).min(entity_ids.len());
+            self.set_rebuild_progress(name, RebuildPhase::Scanning, scanned, entity_ids.len());
+        }
+
+        // Catch up from the change log while index maintenance carries on
+        self.set_rebuild_progress(name, RebuildPhase::CatchingUp, entity_ids.len(), entity_ids.len());
+        let mut catch_up_fixes = 0;
+        for round in 1..=REBUILD_CATCH_UP_ROUNDS {
+            let mut shadows = self.shadows.write().unwrap();
+            let shadow = shadows
+                .get_mut(name)
+                .ok_or_else(|| format!("Rebuild of index {} was cancelled", name))?;
+            let pending = shadow.changes.len();
+            catch_up_fixes += shadow.catch_up(graph, &mut BTreeSet::new());
+
+            if pending <= REBUILD_CHUNK_SIZE || round == REBUILD_CATCH_UP_ROUNDS {
+                // Later conflicts can only come from entities logged after this pass
+                let conflicts = if unique { shadow.index.conflicts(shadow.index.tree.keys()) } else { Vec::new() };
+                if !conflicts.is_empty() {
+                    let total = shadow.index.total_entities();
+                    shadows.remove(name);
+                    drop(shadows);
+                    return Err(self.abort_rebuild(name, total, &conflicts));
+                }
+            }
+        }
+
+        // Final catch-up of entities logged since the last pass, with index maintenance paused
+        let mut indexes = self.indexes.write().unwrap();
+        let mut shadow = self
+            .shadows
+            .write()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| format!("Rebuild of index {} was cancelled", name))?;
+
+        let mut changed_keys = BTreeSet::new();
+        catch_up_fixes += shadow.catch_up(graph, &mut changed_keys);
+
+        let mut shadow = shadow.index;
+        let total = shadow.total_entities();
+        if unique {
+            let conflicts = shadow.conflicts(changed_keys.iter());
+            if !conflicts.is_empty() {
+                drop(indexes);
+                return Err(self.abort_rebuild(name, total, &conflicts));
+            }
+        }
+
+        // Swap in the verified shadow
+        shadow.unique = unique;
+        let live = indexes
+            .iter_mut()
+            .find(|idx| idx.name == name)
+            .ok_or_else(|| format!("Index {} was dropped during rebuild", name))?;
+        *live = shadow;
+        drop(indexes);
+
+        self.set_rebuild_progress(name, RebuildPhase::Swapped, total, total);
+
+        Ok(RebuildReport {
+            index_name: name.to_string(),
+            entries: total,
+            catch_up_fixes,
+        })
+    }
+
+    /// Fill a new index from a collection's entities, outside the live set
+    ///
+    /// The caller keeps the collection from changing until the index is
+    /// swapped in. Duplicate values fail a unique index.
+    pub fn build_index(&self, mut index: BTreeIndex, graph: &Graph) -> Result<BTreeIndex, String> {
+        for entity in graph.scan_collection(&index.collection) {
+            if let Some(value) = entity.properties.get(&index.field) {
+                index.insert(value, entity.id)?;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Put built indexes in service in place of the named ones, in one step
+    pub fn swap_indexes(&self, replaced: &[String], added: Vec<BTreeIndex>) -> Result<(), String> {
+        let mut indexes = self.indexes.write().unwrap();
+        let taken = |name: &String| indexes.iter().any(|idx| idx.name == *name) && !replaced.contains(name);
+        if let Some(index) = added.iter().find(|index| taken(&index.name)) {
+            return Err(format!("Index {} already exists", index.name));
+        }
+
+        indexes.retain(|idx| !replaced.contains(&idx.name));
+        indexes.extend(added);
+        Ok(())
+    }
+
+    fn abort_rebuild(&self, name: &str, total: usize, conflicts: &[String]) -> String {
+        self.set_rebuild_progress(name, RebuildPhase::Aborted, total, total);
+        format!(
+            "Rebuild of unique index {} aborted, conflicting entities: {}",
+            name,
+            conflicts.join("; ")
+        )
+    }
+
+    /// Rebuild every index on a collection
+    pub fn rebuild_collection(&self, collection: &str, graph: &Graph) -> Result<Vec<RebuildReport>, String> {
+        let names: Vec<String> = self
+            .indexes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|idx| idx.collection == collection)
+            .map(|idx| idx.name.clone())
+            .collect();
+
+        names.iter().map(|name| self.rebuild_index(name, graph)).collect()
+    }
+
+    /// Progress of current and most recent index rebuilds
+    pub fn rebuild_progress(&self) -> Vec<RebuildProgress> {
+        let mut progress: Vec<RebuildProgress> = self.rebuilds.read().unwrap().values().cloned().collect();
+        progress.sort_by(|a, b| a.index_name.cmp(&b.index_name));
+        progress
+    }
+
+    fn set_rebuild_progress(&self, name: &str, phase: RebuildPhase, scanned: usize, total: usize) {
+        self.rebuilds.write().unwrap().insert(
+            name.to_string(),
+            RebuildProgress {
+                index_name: name.to_string(),
+                phase,
+                scanned,
+                total,
+            },
+        );
     }
 
     /// List all indexes
@@ -288,6 +937,7 @@ impl IndexManager {
             collection: idx.collection.clone(),
             field: idx.field.clone(),
             unique: idx.unique,
+            kind: idx.kind,
             size: idx.size(),
             total_entities: idx.total_entities(),
         })
@@ -307,10 +957,38 @@ pub struct IndexStats {
     pub collection: String,
     pub field: String,
     pub unique: bool,
+    pub kind: IndexKind,
     pub size: usize,
     pub total_entities: usize,
 }
 
+/// Phase of an online index rebuild
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebuildPhase {
+    Scanning,
+    CatchingUp,
+    Swapped,
+    Aborted,
+}
+
+/// Progress of an online index rebuild
+#[derive(Debug, Clone)]
+pub struct RebuildProgress {
+    pub index_name: String,
+    pub phase: RebuildPhase,
+    pub scanned: usize,
+    pub total: usize,
+}
+
+/// Result of a completed index rebuild
+#[derive(Debug, Clone)]
+pub struct RebuildReport {
+    pub index_name: String,
+    pub entries: usize,
+    /// Entries the catch-up passes had to add or remove
+    pub catch_up_fixes: usize,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -388,6 +1066,33 @@ mod tests {
         assert_eq!(result.len(), 2); // Ages 25 and 30
     }
 
+    #[test]
+    fn test_value_probes_span_int_and_float_keys() {
+        let mut index = BTreeIndex::new(
+            "idx_score".to_string(),
+            "Users".to_string(),
+            "score".to_string(),
+            false,
+        );
+
+        index.insert(&PropertyValue::Int(10), EntityId::new(1)).unwrap();
+        index.insert(&PropertyValue::Float(10.5), EntityId::new(2)).unwrap();
+        index.insert(&PropertyValue::Float(12.0), EntityId::new(3)).unwrap();
+        index.insert(&PropertyValue::String("n/a".to_string()), EntityId::new(4)).unwrap();
+
+        assert_eq!(index.lookup_value(&PropertyValue::Float(10.0)), vec![EntityId::new(1)]);
+        assert_eq!(index.lookup_value(&PropertyValue::Int(12)), vec![EntityId::new(3)]);
+
+        let mut in_range = index
+            .range_values(Some(&PropertyValue::Float(9.5)), Some(&PropertyValue::Int(11)))
+            .unwrap();
+        in_range.sort_by_key(|id| id.0);
+        assert_eq!(in_range, vec![EntityId::new(1), EntityId::new(2)]);
+
+        assert_eq!(index.range_values(Some(&PropertyValue::Int(11)), None).unwrap(), vec![EntityId::new(3)]);
+        assert!(index.range_values(Some(&PropertyValue::Int(1)), Some(&PropertyValue::String("z".to_string()))).is_none());
+    }
+
     #[test]
     fn test_index_manager() {
         let manager = IndexManager::new();
@@ -432,4 +1137,57 @@ mod tests {
 
         assert_eq!(index.lookup(&PropertyValue::Int(30)).len(), 0);
     }
+
+    #[test]
+    fn test_rebuild_unique_violation_keeps_old_index() {
+        let graph = Graph::new();
+        let manager = IndexManager::new();
+        manager
+            .create_index("idx_email".to_string(), "Users".to_string(), "email".to_string(), true)
+            .unwrap();
+
+        for _ in 0..2 {
+            let mut props = std::collections::HashMap::new();
+            props.insert("email".to_string(), PropertyValue::String("dup@example.com".to_string()));
+            let id = graph.add_entity("Users".to_string(), props.clone()).unwrap();
+            let _ = manager.insert_into_indexes("Users", id, &props);
+        }
+
+        let err = manager.rebuild_index("idx_email", &graph).unwrap_err();
+        assert!(err.contains("[1, 2]"), "Report should list conflicting ids: {}", err);
+        assert_eq!(manager.get_index("idx_email").unwrap().total_entities(), 1);
+        assert_eq!(manager.rebuild_progress()[0].phase, RebuildPhase::Aborted);
+    }
+
+    #[test]
+    fn test_catch_up_reindexes_logged_entities_only() {
+        let graph = Graph::new();
+        let props = |score: i64| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("score".to_string(), PropertyValue::Int(score));
+            props
+        };
+        let logged = graph.add_entity("Users".to_string(), props(2)).unwrap();
+        let unlogged = graph.add_entity("Users".to_string(), props(5)).unwrap();
+
+        let mut shadow = ShadowIndex::new(BTreeIndex::new(
+            "idx_score".to_string(),
+            "Users".to_string(),
+            "score".to_string(),
+            false,
+        ));
+        // Maintenance for the update to 2 landed before the one it replaced
+        shadow.record(logged, &props(2));
+        shadow.index.insert(&PropertyValue::Int(2), logged).unwrap();
+        shadow.record(logged, &props(1));
+        shadow.index.insert(&PropertyValue::Int(1), logged).unwrap();
+
+        let mut current = BTreeSet::new();
+        assert_eq!(shadow.catch_up(&graph, &mut current), 1);
+        assert!(shadow.changes.is_empty());
+        assert_eq!(current, BTreeSet::from([IndexKey::Int(2)]));
+        assert_eq!(shadow.index.lookup(&PropertyValue::Int(1)), vec![]);
+        assert_eq!(shadow.index.lookup(&PropertyValue::Int(2)), vec![logged]);
+        assert!(shadow.index.lookup(&PropertyValue::Int(5)).is_empty(), "{:?} isn't logged", unlogged);
+    }
 }
@@ -0,0 +1,223 @@
+//! System Catalog
+//!
+//! Read-only collections describing the database itself, queried with DQL
+//! like any other collection:
+//! - `_indexes`: name, collection, field, unique, kind and entries of each index
+//! - `_schemas`: one row per schema field, with its type and constraints
+//! - `_transactions`: id, state, isolation, started_at and autocommit of open transactions
+//! - `_collections`: name and entity_count of each collection
+//!
+//! Rows are built from the index manager, schemas, transaction manager and
+//! graph when a statement reads one of these collections; writes to them
+//! are refused.
+
+use crate::btree::{IndexKind, IndexManager};
+use crate::graph::Graph;
+use crate::schema::{Constraint, Field, SchemaValidator};
+use crate::transaction::TransactionManager;
+use crate::types::{Properties, PropertyValue};
+
+/// System collection listing indexes
+pub const INDEXES_COLLECTION: &str = "_indexes";
+
+/// System collection listing schema fields
+pub const SCHEMAS_COLLECTION: &str = "_schemas";
+
+/// System collection listing open transactions
+pub const TRANSACTIONS_COLLECTION: &str = "_transactions";
+
+/// System collection listing collections
+pub const COLLECTIONS_COLLECTION: &str = "_collections";
+
+/// Every catalog collection
+pub const CATALOG_COLLECTIONS: [&str; 4] = [
+    INDEXES_COLLECTION,
+    SCHEMAS_COLLECTION,
+    TRANSACTIONS_COLLECTION,
+    COLLECTIONS_COLLECTION,
+];
+
+/// Whether a collection name is one of the catalog's
+pub fn is_catalog_collection(collection: &str) -> bool {
+    CATALOG_COLLECTIONS.contains(&collection)
+}
+
+/// What the catalog's rows are read from
+pub struct Catalog<'a> {
+    pub graph: &'a Graph,
+    pub indexes: &'a IndexManager,
+    pub schemas: &'a SchemaValidator,
+    pub transactions: &'a TransactionManager,
+}
+
+impl Catalog<'_> {
+    /// Rows of a catalog collection, or `None` for any other collection
+    pub fn rows(&self, collection: &str) -> Option<Vec<Properties>> {
+        match collection {
+            INDEXES_COLLECTION => Some(self.index_rows()),
+            SCHEMAS_COLLECTION => Some(self.schema_rows()),
+            TRANSACTIONS_COLLECTION => Some(self.transaction_rows()),
+            COLLECTIONS_COLLECTION => Some(self.collection_rows()),
+            _ => None,
+        }
+    }
+
+    fn index_rows(&self) -> Vec<Properties> {
+        let mut names = self.indexes.list_indexes();
+        names.sort();
+
+        names
+            .iter()
+            .filter_map(|name| self.indexes.index_stats(name))
+            .map(|stats| {
+                let kind = match stats.kind {
+                    IndexKind::BTree => "BTREE",
+                    IndexKind::FullText => "FULLTEXT",
+                };
+                Properties::from([
+                    ("name".to_string(), PropertyValue::String(stats.name)),
+                    ("collection".to_string(), PropertyValue::String(stats.collection)),
+                    ("field".to_string(), PropertyValue::String(stats.field)),
+                    ("unique".to_string(), PropertyValue::Bool(stats.unique)),
+                    ("kind".to_string(), PropertyValue::String(kind.to_string())),
+                    ("entries".to_string(), PropertyValue::Int(stats.total_entities as i64)),
+                ])
+            })
+            .collect()
+    }
+
+    fn schema_rows(&self) -> Vec<Properties> {
+        let mut schemas: Vec<_> = self.schemas.schemas().collect();
+        schemas.sort_by(|a, b| a.collection.cmp(&b.collection));
+
+        schemas
+            .into_iter()
+            .flat_map(|schema| {
+                schema.fields.iter().map(|field| {
+                    let constraints = field
+                        .constraints
+                        .iter()
+                        .map(|constraint| PropertyValue::String(constraint_text(field, constraint)))
+                        .collect();
+                    Properties::from([
+                        ("collection".to_string(), PropertyValue::String(schema.collection.clone())),
+                        ("field".to_string(), PropertyValue::String(field.name.clone())),
+                        ("type".to_string(), PropertyValue::String(field.field_type.name())),
+                        ("constraints".to_string(), PropertyValue::List(constraints)),
+                        ("allow_extra".to_string(), PropertyValue::Bool(schema.allow_extra_properties)),
+                    ])
+                })
+            })
+            .collect()
+    }
+
+    fn transaction_rows(&self) -> Vec<Properties> {
+        self.transactions
+            .active_transactions()
+            .into_iter()
+            .map(|transaction| {
+                Properties::from([
+                    ("id".to_string(), PropertyValue::Int(transaction.id as i64)),
+                    ("state".to_string(), PropertyValue::String(transaction.state.name().to_string())),
+                    (
+                        "isolation".to_string(),
+                        PropertyValue::String(transaction.isolation_level.name().to_string()),
+                    ),
+                    ("started_at".to_string(), PropertyValue::Timestamp(transaction.start_time as i64)),
+                    ("autocommit".to_string(), PropertyValue::Bool(transaction.autocommit)),
+                ])
+            })
+            .collect()
+    }
+
+    fn collection_rows(&self) -> Vec<Properties> {
+        self.graph
+            .list_collections()
+            .into_iter()
+            .map(|(name, entities)| {
+                Properties::from([
+                    ("name".to_string(), PropertyValue::String(name)),
+                    ("entity_count".to_string(), PropertyValue::Int(entities as i64)),
+                ])
+            })
+            .collect()
+    }
+}
+
+/// A field constraint as DEFINE SCHEMA writes it
+fn constraint_text(field: &Field, constraint: &Constraint) -> String {
+    match constraint {
+        Constraint::NotNull => "NOT NULL".to_string(),
+        Constraint::Unique => "UNIQUE".to_string(),
+        Constraint::PrimaryKey => "PRIMARY KEY".to_string(),
+        Constraint::Index => "INDEX".to_string(),
+        Constraint::Check => match &field.check_expression {
+            Some(expression) => format!("CHECK({})", expression),
+            None => "CHECK".to_string(),
+        },
+        Constraint::Default(value) => format!("DEFAULT {}", literal(value)),
+    }
+}
+
+fn literal(value: &PropertyValue) -> String {
+    match value {
+        PropertyValue::Null => "NULL".to_string(),
+        PropertyValue::Bool(b) => b.to_string(),
+        PropertyValue::Int(n) => n.to_string(),
+        PropertyValue::Float(f) => f.to_string(),
+        PropertyValue::String(s) => format!("'{}'", s),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{FieldType, Schema};
+    use crate::transaction::IsolationLevel;
+
+    #[test]
+    fn test_catalog_rows_describe_the_database() {
+        let graph = Graph::new();
+        graph.add_entity("Users".to_string(), Properties::new()).unwrap();
+        let indexes = IndexManager::new();
+        indexes.create_index("idx_email".to_string(), "Users".to_string(), "email".to_string(), true).unwrap();
+        let mut schemas = SchemaValidator::new();
+        let mut schema = Schema::new("Users".to_string());
+        schema.add_field(Field::new("email".to_string(), FieldType::String).with_constraint(Constraint::Unique));
+        schema.add_field(
+            Field::new("age".to_string(), FieldType::Integer).with_constraint(Constraint::Default(PropertyValue::Int(0))),
+        );
+        schemas.register_schema(schema);
+        let transactions = TransactionManager::new();
+        let txn_id = transactions.begin(IsolationLevel::Serializable).unwrap();
+
+        let catalog = Catalog {
+            graph: &graph,
+            indexes: &indexes,
+            schemas: &schemas,
+            transactions: &transactions,
+        };
+
+        let indexes = catalog.rows(INDEXES_COLLECTION).unwrap();
+        assert_eq!(indexes[0]["name"], PropertyValue::String("idx_email".to_string()));
+        assert_eq!(indexes[0]["unique"], PropertyValue::Bool(true));
+
+        let fields = catalog.rows(SCHEMAS_COLLECTION).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(
+            fields[1]["constraints"],
+            PropertyValue::List(vec![PropertyValue::String("DEFAULT 0".to_string())])
+        );
+
+        let open = catalog.rows(TRANSACTIONS_COLLECTION).unwrap();
+        assert_eq!(open[0]["id"], PropertyValue::Int(txn_id as i64));
+        assert_eq!(open[0]["isolation"], PropertyValue::String("SERIALIZABLE".to_string()));
+
+        let collections = catalog.rows(COLLECTIONS_COLLECTION).unwrap();
+        assert_eq!(collections[0]["entity_count"], PropertyValue::Int(1));
+
+        assert!(catalog.rows("Users").is_none());
+        assert!(!is_catalog_collection("_audit"));
+    }
+}
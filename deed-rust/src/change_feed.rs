@@ -0,0 +1,468 @@
+//! Change data capture
+//!
+//! The executor publishes a [`ChangeEvent`] for every insert, update and
+//! delete of an entity or edge once the transaction that made it commits;
+//! rolled back changes are never seen. Transactions are delivered whole and
+//! in the order they committed. Subscribers pick the collections, edge types
+//! and kinds of change they want and read them from a bounded queue of their
+//! own, so a slow subscriber can't hold up writers: when its queue is full
+//! it either loses its oldest events or is disconnected.
+
+use crate::graph::Edge;
+use crate::transaction::TransactionId;
+use crate::types::{EdgeId, EntityId, Properties};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+/// Events a subscriber may fall behind by unless it asks otherwise
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// What a change did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+    EdgeCreate,
+    EdgeUpdate,
+    EdgeDelete,
+}
+
+impl ChangeKind {
+    /// Whether the change was to an edge rather than an entity
+    pub fn is_edge(&self) -> bool {
+        matches!(self, ChangeKind::EdgeCreate | ChangeKind::EdgeUpdate | ChangeKind::EdgeDelete)
+    }
+}
+
+/// One committed change
+///
+/// Edge changes are keyed by the edge's id and type and its source entity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    /// Collection of the entity (`None` for edges)
+    pub collection: Option<String>,
+    /// The entity changed, or the edge's source
+    pub entity_id: EntityId,
+    pub edge_id: Option<EdgeId>,
+    /// Type of the edge (`None` for entities)
+    pub edge_type: Option<String>,
+    /// Properties before the change (`None` for inserts)
+    pub before: Option<Properties>,
+    /// Properties after the change (`None` for deletes)
+    pub after: Option<Properties>,
+    pub txn_id: TransactionId,
+    /// When the transaction committed, in milliseconds since the Unix epoch
+    pub timestamp: i64,
+}
+
+impl ChangeEvent {
+    /// A change to an entity made by a transaction, stamped with the time
+    /// when it commits
+    pub fn new(kind: ChangeKind, collection: impl Into<String>, entity_id: EntityId, txn_id: TransactionId) -> Self {
+        ChangeEvent {
+            kind,
+            collection: Some(collection.into()),
+            entity_id,
+            edge_id: None,
+            edge_type: None,
+            before: None,
+            after: None,
+            txn_id,
+            timestamp: 0,
+        }
+    }
+
+    /// A change to an edge made by a transaction
+    pub fn edge(kind: ChangeKind, edge: &Edge, txn_id: TransactionId) -> Self {
+        ChangeEvent {
+            kind,
+            collection: None,
+            entity_id: edge.source,
+            edge_id: Some(edge.id),
+            edge_type: Some(edge.edge_type.clone()),
+            before: None,
+            after: None,
+            txn_id,
+            timestamp: 0,
+        }
+    }
+
+    pub fn with_before(mut self, properties: Properties) -> Self {
+        self.before = Some(properties);
+        self
+    }
+
+    pub fn with_after(mut self, properties: Properties) -> Self {
+        self.after = Some(properties);
+        self
+    }
+}
+
+/// Which changes a subscriber receives; an empty filter matches everything
+///
+/// Naming collections or edge types limits it to changes to entities in
+/// those collections and to edges of those types.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeFilter {
+    collections: HashSet<String>,
+    edge_types: HashSet<String>,
+    kinds: HashSet<ChangeKind>,
+}
+
+impl ChangeFilter {
+    /// Match every change
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Also match changes to a collection's entities
+    pub fn with_collection(mut self, collection: impl Into<String>) -> Self {
+        self.collections.insert(collection.into());
+        self
+    }
+
+    /// Also match changes to edges of a type
+    pub fn with_edge_type(mut self, edge_type: impl Into<String>) -> Self {
+        self.edge_types.insert(edge_type.into());
+        self
+    }
+
+    /// Also match a kind of change
+    pub fn with_kind(mut self, kind: ChangeKind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    pub fn matches(&self, event: &ChangeEvent) -> bool {
+        let named = |names: &HashSet<String>, name: &Option<String>| name.as_ref().is_some_and(|name| names.contains(name));
+        let unlimited = self.collections.is_empty() && self.edge_types.is_empty();
+        (unlimited || named(&self.collections, &event.collection) || named(&self.edge_types, &event.edge_type))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+    }
+}
+
+/// What happens to a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Discard its oldest queued event to make room
+    #[default]
+    DropOldest,
+    /// Stop delivering to it; it reads what is queued, then an error
+    Disconnect,
+}
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<ChangeEvent>,
+    dropped: u64,
+    disconnected: bool,
+}
+
+struct Subscriber {
+    filter: ChangeFilter,
+    capacity: usize,
+    policy: OverflowPolicy,
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+impl Subscriber {
+    /// Queue an event without blocking; false once the subscriber is disconnected
+    fn offer(&self, event: &ChangeEvent) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.disconnected {
+            return false;
+        }
+
+        if queue.events.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    queue.events.pop_front();
+                    queue.dropped += 1;
+                }
+                OverflowPolicy::Disconnect => {
+                    queue.disconnected = true;
+                    self.ready.notify_all();
+                    return false;
+                }
+            }
+        }
+
+        queue.events.push_back(event.clone());
+        self.ready.notify_all();
+        true
+    }
+}
+
+/// Places in commit order handed out, and transactions waiting for theirs
+#[derive(Default)]
+struct Order {
+    reserved: u64,
+    published: u64,
+    waiting: BTreeMap<u64, Vec<ChangeEvent>>,
+}
+
+/// Publishes committed changes to subscribers
+#[derive(Default)]
+pub struct ChangeFeed {
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+    order: Mutex<Order>,
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe with the default capacity, dropping the oldest events on overflow
+    pub fn subscribe(&self, filter: ChangeFilter) -> ChangeSubscription {
+        self.subscribe_with(filter, DEFAULT_SUBSCRIBER_CAPACITY, OverflowPolicy::default())
+    }
+
+    /// Subscribe, holding up to `capacity` undelivered events
+    pub fn subscribe_with(&self, filter: ChangeFilter, capacity: usize, policy: OverflowPolicy) -> ChangeSubscription {
+        let subscriber = Arc::new(Subscriber {
+            filter,
+            capacity: capacity.max(1),
+            policy,
+            queue: Mutex::new(Queue::default()),
+            ready: Condvar::new(),
+        });
+        self.subscribers.lock().unwrap().push(Arc::downgrade(&subscriber));
+        ChangeSubscription { subscriber }
+    }
+
+    /// Whether anyone is subscribed, so changes are worth collecting
+    pub fn has_subscribers(&self) -> bool {
+        self.subscribers.lock().unwrap().iter().any(|subscriber| subscriber.strong_count() > 0)
+    }
+
+    /// Take the next place in commit order, while holding whatever orders commits
+    ///
+    /// The transaction's changes are delivered once every earlier place
+    /// has been published or given up.
+    pub fn reserve(&self) -> Publication<'_> {
+        let mut order = self.order.lock().unwrap();
+        let place = order.reserved;
+        order.reserved += 1;
+        Publication { feed: self, place: Some(place) }
+    }
+
+    /// Deliver a committed transaction's changes after those committed before
+    pub fn publish(&self, events: Vec<ChangeEvent>) {
+        self.reserve().publish(events);
+    }
+
+    fn release(&self, place: u64, events: Vec<ChangeEvent>) {
+        let mut order = self.order.lock().unwrap();
+        order.waiting.insert(place, events);
+        while let Some(events) = {
+            let next = order.published;
+            order.waiting.remove(&next)
+        } {
+            order.published += 1;
+            self.deliver(&events);
+        }
+    }
+
+    /// Deliver a transaction's changes to the subscribers that want them
+    ///
+    /// Transactions are delivered one at a time, so each subscriber sees
+    /// their changes never interleaved. Dropped and disconnected
+    /// subscribers are forgotten.
+    fn deliver(&self, events: &[ChangeEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| match subscriber.upgrade() {
+            Some(subscriber) => events
+                .iter()
+                .filter(|event| subscriber.filter.matches(event))
+                .all(|event| subscriber.offer(event)),
+            None => false,
+        });
+    }
+}
+
+/// A committing transaction's place in the feed
+///
+/// Dropping it unpublished gives the place up, so later transactions
+/// aren't held back by one that failed to commit.
+pub struct Publication<'a> {
+    feed: &'a ChangeFeed,
+    place: Option<u64>,
+}
+
+impl Publication<'_> {
+    /// Deliver the transaction's changes in its place
+    pub fn publish(mut self, events: Vec<ChangeEvent>) {
+        if let Some(place) = self.place.take() {
+            self.feed.release(place, events);
+        }
+    }
+}
+
+impl Drop for Publication<'_> {
+    fn drop(&mut self) {
+        if let Some(place) = self.place.take() {
+            self.feed.release(place, Vec::new());
+        }
+    }
+}
+
+/// A subscriber's end of the feed; unsubscribes when dropped
+pub struct ChangeSubscription {
+    subscriber: Arc<Subscriber>,
+}
+
+impl ChangeSubscription {
+    /// The next event, if one is queued
+    ///
+    /// Fails once a disconnected subscriber has read everything queued.
+    pub fn try_recv(&self) -> Result<Option<ChangeEvent>, String> {
+        let mut queue = self.subscriber.queue.lock().unwrap();
+        Self::next(&mut queue)
+    }
+
+    /// The next event, waiting up to `timeout` for one
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<ChangeEvent>, String> {
+        let queue = self.subscriber.queue.lock().unwrap();
+        let (mut queue, _) = self
+            .subscriber
+            .ready
+            .wait_timeout_while(queue, timeout, |queue| queue.events.is_empty() && !queue.disconnected)
+            .unwrap();
+        Self::next(&mut queue)
+    }
+
+    /// Everything queued right now
+    pub fn drain(&self) -> Vec<ChangeEvent> {
+        self.subscriber.queue.lock().unwrap().events.drain(..).collect()
+    }
+
+    /// Events discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.queue.lock().unwrap().dropped
+    }
+
+    /// Whether the feed stopped delivering because the queue overflowed
+    pub fn is_disconnected(&self) -> bool {
+        self.subscriber.queue.lock().unwrap().disconnected
+    }
+
+    fn next(queue: &mut Queue) -> Result<Option<ChangeEvent>, String> {
+        match queue.events.pop_front() {
+            Some(event) => Ok(Some(event)),
+            None if queue.disconnected => Err("Change subscriber disconnected: it fell too far behind".to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: ChangeKind, collection: &str, id: u64) -> ChangeEvent {
+        ChangeEvent::new(kind, collection, EntityId::new(id), 1).with_after(Properties::new())
+    }
+
+    #[test]
+    fn test_filters_select_collections_and_kinds() {
+        let feed = ChangeFeed::new();
+        let users = feed.subscribe(ChangeFilter::all().with_collection("Users"));
+        let deletes = feed.subscribe(ChangeFilter::all().with_kind(ChangeKind::Delete));
+
+        feed.publish(vec![
+            event(ChangeKind::Insert, "Users", 1),
+            event(ChangeKind::Delete, "Orders", 2),
+            event(ChangeKind::Delete, "Users", 1),
+        ]);
+
+        let ids = |events: Vec<ChangeEvent>| events.into_iter().map(|e| (e.collection.unwrap(), e.kind)).collect::<Vec<_>>();
+        assert_eq!(
+            ids(users.drain()),
+            vec![("Users".to_string(), ChangeKind::Insert), ("Users".to_string(), ChangeKind::Delete)]
+        );
+        assert_eq!(
+            ids(deletes.drain()),
+            vec![("Orders".to_string(), ChangeKind::Delete), ("Users".to_string(), ChangeKind::Delete)]
+        );
+    }
+
+    #[test]
+    fn test_edge_types_are_filtered_apart_from_collections() {
+        let feed = ChangeFeed::new();
+        let follows = feed.subscribe(ChangeFilter::all().with_edge_type("FOLLOWS"));
+        let edge = Edge::new(EdgeId::new(7), EntityId::new(1), EntityId::new(2), "FOLLOWS".to_string(), Properties::new());
+
+        feed.publish(vec![event(ChangeKind::Insert, "FOLLOWS", 1), ChangeEvent::edge(ChangeKind::EdgeCreate, &edge, 1)]);
+
+        let events = follows.drain();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].edge_id, Some(EdgeId::new(7)));
+        assert_eq!(events[0].collection, None);
+    }
+
+    #[test]
+    fn test_transactions_are_delivered_in_reserved_order() {
+        let feed = ChangeFeed::new();
+        let subscription = feed.subscribe(ChangeFilter::all());
+
+        let first = feed.reserve();
+        let abandoned = feed.reserve();
+        let third = feed.reserve();
+        third.publish(vec![event(ChangeKind::Insert, "Users", 3)]);
+        assert!(subscription.drain().is_empty());
+
+        first.publish(vec![event(ChangeKind::Insert, "Users", 1)]);
+        assert_eq!(subscription.drain().len(), 1);
+
+        // A place given up doesn't hold back those after it
+        drop(abandoned);
+        let ids: Vec<EntityId> = subscription.drain().iter().map(|e| e.entity_id).collect();
+        assert_eq!(ids, vec![EntityId::new(3)]);
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let feed = ChangeFeed::new();
+        let lossy = feed.subscribe_with(ChangeFilter::all(), 2, OverflowPolicy::DropOldest);
+        let strict = feed.subscribe_with(ChangeFilter::all(), 2, OverflowPolicy::Disconnect);
+
+        for id in 1..=5 {
+            feed.publish(vec![event(ChangeKind::Insert, "Users", id)]);
+        }
+
+        let ids: Vec<EntityId> = lossy.drain().iter().map(|e| e.entity_id).collect();
+        assert_eq!(ids, vec![EntityId::new(4), EntityId::new(5)]);
+        assert_eq!(lossy.dropped(), 3);
+
+        // What was queued before the overflow is still readable
+        assert!(strict.is_disconnected());
+        assert_eq!(strict.try_recv().unwrap().unwrap().entity_id, EntityId::new(1));
+        assert_eq!(strict.try_recv().unwrap().unwrap().entity_id, EntityId::new(2));
+        assert!(strict.try_recv().is_err());
+        assert!(strict.recv_timeout(Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn test_dropped_subscription_unsubscribes() {
+        let feed = ChangeFeed::new();
+        assert!(!feed.has_subscribers());
+
+        let subscription = feed.subscribe(ChangeFilter::all());
+        assert!(feed.has_subscribers());
+        assert_eq!(subscription.recv_timeout(Duration::from_millis(1)).unwrap(), None);
+
+        drop(subscription);
+        assert!(!feed.has_subscribers());
+        feed.publish(vec![event(ChangeKind::Insert, "Users", 1)]);
+        assert!(feed.subscribers.lock().unwrap().is_empty());
+    }
+}
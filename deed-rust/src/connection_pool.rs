@@ -2,15 +2,32 @@
 //!
 //! Manages a pool of database connections for concurrent client access.
 //! Provides efficient connection reuse and limits concurrent connections.
-
-use crate::dql_executor::DQLExecutor;
+//! Callers that find every connection busy queue first-come first-served,
+//! and a returned connection goes straight to the caller at the front.
+//! A background thread replaces connections broken by a panicked query and
+//! closes those idle for too long. Shutting the pool down refuses new
+//! callers, waits for handed-out connections and shuts down their executors.
+
+use crate::audit::AuditLog;
+use crate::auth::Session;
+use crate::btree::IndexManager;
+use crate::dql_executor::{DQLExecutor, QueryResult, RowStream};
+use crate::error::DeedError;
 use crate::graph::Graph;
+use crate::read_routing::ReadReplicas;
+use crate::replication::ReplicationManager;
 use crate::dql_optimizer::{AntColonyOptimizer, StigmergyCache};
+use crate::schema::SchemaValidator;
+use crate::shutdown::{BackgroundTask, ShutdownReport, ShutdownSignal};
 use crate::transaction::TransactionManager;
 use crate::wal::WALManager;
-use std::sync::{Arc, Mutex, Condvar};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, TryLockError};
 use std::time::{Duration, Instant};
 use std::collections::VecDeque;
+use tokio::sync::oneshot;
 
 /// Configuration for connection pool
 #[derive(Debug, Clone)]
@@ -25,6 +42,17 @@ pub struct PoolConfig {
     pub max_idle_time: u64,
     /// Enable connection health checks
     pub health_check_enabled: bool,
+    /// Default timeout for queries run through a handed-out connection
+    pub query_timeout: Option<Duration>,
+    /// How often a background thread evicts and health checks idle
+    /// connections (`None` leaves that to calls to `maintain`)
+    pub maintenance_interval: Option<Duration>,
+    /// Where every connection records its session's writes
+    pub audit: Option<Arc<AuditLog>>,
+    /// Replicas every connection's reads may be routed to
+    pub read_replicas: Option<Arc<ReadReplicas>>,
+    /// The master's replication state, for judging replicas' lag
+    pub replication: Option<Arc<ReplicationManager>>,
 }
 
 impl Default for PoolConfig {
@@ -35,21 +63,34 @@ impl Default for PoolConfig {
             connection_timeout: 30,
             max_idle_time: 300, // 5 minutes
             health_check_enabled: true,
+            query_timeout: None,
+            maintenance_interval: Some(Duration::from_secs(30)),
+            audit: None,
+            read_replicas: None,
+            replication: None,
         }
     }
 }
 
+/// Error of every acquisition once the pool is shut down
+const POOL_SHUT_DOWN: &str = "Connection pool is shut down";
+
 /// A connection wrapper that tracks usage
 struct PooledConnection {
-    executor: DQLExecutor,
+    id: u64,
+    executor: Arc<Mutex<DQLExecutor>>,
+    /// The executor outside its lock, to shut it down while a query holds it
+    session: DQLExecutor,
     last_used: Instant,
     in_use: bool,
 }
 
 impl PooledConnection {
-    fn new(executor: DQLExecutor) -> Self {
+    fn new(id: u64, executor: DQLExecutor) -> Self {
         PooledConnection {
-            executor,
+            id,
+            session: executor.clone(),
+            executor: Arc::new(Mutex::new(executor)),
             last_used: Instant::now(),
             in_use: false,
         }
@@ -59,10 +100,23 @@ impl PooledConnection {
         !self.in_use && self.last_used.elapsed() > max_idle_time
     }
 
-    fn checkout(&mut self) -> &mut DQLExecutor {
+    /// Probe an idle connection
+    ///
+    /// Fails when a query panicked while holding the executor or one of its
+    /// locks, since every later query on it would fail the same way.
+    fn probe(&self) -> Result<(), String> {
+        match self.executor.try_lock() {
+            Ok(executor) => executor.ping(),
+            Err(TryLockError::Poisoned(_)) => Err("Connection poisoned by a panicked query".to_string()),
+            // Still held by a stream or a late handle; not broken
+            Err(TryLockError::WouldBlock) => Ok(()),
+        }
+    }
+
+    fn checkout(&mut self) -> Arc<Mutex<DQLExecutor>> {
         self.in_use = true;
         self.last_used = Instant::now();
-        &mut self.executor
+        self.executor.clone()
     }
 
     fn checkin(&mut self) {
@@ -71,12 +125,76 @@ impl PooledConnection {
     }
 }
 
-/// Connection pool for managing database connections
-pub struct ConnectionPool {
-    connections: Arc<Mutex<VecDeque<PooledConnection>>>,
-    available: Arc<Condvar>,
+/// Counters of the pool's maintenance and queueing
+#[derive(Default)]
+struct PoolCounters {
+    evictions: AtomicU64,
+    failed_health_checks: AtomicU64,
+    replacements: AtomicU64,
+    acquisitions: AtomicU64,
+    total_wait_micros: AtomicU64,
+    waits: AtomicU64,
+    handoffs: AtomicU64,
+}
+
+/// A connection handed to a queued caller
+struct Grant {
+    id: u64,
+    executor: Arc<Mutex<DQLExecutor>>,
+}
+
+/// How a queued caller is woken
+enum Wake {
+    Thread(mpsc::Sender<Grant>),
+    Task(oneshot::Sender<Grant>),
+}
+
+/// A caller queued for a connection
+struct Waiter {
+    ticket: u64,
+    wake: Wake,
+}
+
+impl Waiter {
+    /// Hand over a connection; gives it back when the caller stopped waiting
+    fn grant(self, grant: Grant) -> Result<(), Grant> {
+        match self.wake {
+            Wake::Thread(sender) => sender.send(grant).map_err(|e| e.0),
+            Wake::Task(sender) => sender.send(grant),
+        }
+    }
+}
+
+/// Connections and the callers queued for them, under one lock
+struct PoolState {
+    connections: VecDeque<PooledConnection>,
+    /// Served first-come first-served as connections are returned
+    waiters: VecDeque<Waiter>,
+    next_ticket: u64,
+    /// Set by shutdown; no connection is handed out after it
+    shut_down: bool,
+}
+
+impl PoolState {
+    fn dequeue(&mut self, ticket: u64) -> bool {
+        match self.waiters.iter().position(|w| w.ticket == ticket) {
+            Some(idx) => {
+                self.waiters.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Pool state, shared with the maintenance thread
+struct PoolShared {
+    state: Mutex<PoolState>,
+    /// Notified as connections are returned
+    returned: Condvar,
     config: PoolConfig,
-    current_size: Arc<Mutex<usize>>,
+    next_id: AtomicU64,
+    counters: PoolCounters,
 
     // Shared database components
     graph: Arc<std::sync::RwLock<Graph>>,
@@ -84,6 +202,179 @@ pub struct ConnectionPool {
     cache: Arc<std::sync::RwLock<StigmergyCache>>,
     transaction_manager: Arc<TransactionManager>,
     wal_manager: Option<Arc<WALManager>>,
+    index_manager: Arc<IndexManager>,
+    schemas: Arc<std::sync::RwLock<SchemaValidator>>,
+}
+
+impl PoolShared {
+    fn new_connection(&self) -> PooledConnection {
+        let executor = DQLExecutor::with_shared_components(
+            self.graph.clone(),
+            self.optimizer.clone(),
+            self.cache.clone(),
+            self.transaction_manager.clone(),
+            self.wal_manager.clone(),
+            self.index_manager.clone(),
+            self.schemas.clone(),
+        );
+        let executor = match &self.config.audit {
+            Some(audit) => executor.with_audit_log(audit.clone()),
+            None => executor,
+        };
+        let executor = match &self.config.read_replicas {
+            Some(replicas) => executor.with_read_replicas(replicas.clone()),
+            None => executor,
+        };
+        let executor = match &self.config.replication {
+            Some(replication) => executor.with_replication(replication.clone()),
+            None => executor,
+        };
+
+        PooledConnection::new(self.next_id.fetch_add(1, Ordering::Relaxed), executor)
+    }
+
+    /// Replace the connection at `idx`, which failed its health check
+    fn replace(&self, connections: &mut VecDeque<PooledConnection>, idx: usize) {
+        self.counters.failed_health_checks.fetch_add(1, Ordering::Relaxed);
+        connections[idx] = self.new_connection();
+        self.counters.replacements.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Check out the connection at `idx`, replacing it first if it's broken
+    fn checkout(&self, connections: &mut VecDeque<PooledConnection>, idx: usize) -> Grant {
+        if self.config.health_check_enabled && connections[idx].probe().is_err() {
+            self.replace(connections, idx);
+        }
+
+        let conn = &mut connections[idx];
+        Grant {
+            id: conn.id,
+            executor: conn.checkout(),
+        }
+    }
+
+    /// Take a connection without waiting, unless others are queued ahead
+    fn try_checkout(&self, state: &mut PoolState) -> Option<Grant> {
+        if !state.waiters.is_empty() {
+            return None;
+        }
+
+        if let Some(idx) = state.connections.iter().position(|c| !c.in_use) {
+            return Some(self.checkout(&mut state.connections, idx));
+        }
+
+        // No available connections - try to create a new one
+        if state.connections.len() < self.config.max_size {
+            let conn = self.new_connection();
+            state.connections.push_back(conn);
+            let idx = state.connections.len() - 1;
+            return Some(self.checkout(&mut state.connections, idx));
+        }
+
+        None
+    }
+
+    /// Queue a caller behind those already waiting
+    fn enqueue(&self, state: &mut PoolState, wake: Wake) -> u64 {
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiters.push_back(Waiter { ticket, wake });
+        self.counters.waits.fetch_add(1, Ordering::Relaxed);
+        ticket
+    }
+
+    /// Give a returned connection to the longest waiting caller, if any
+    fn release(&self, state: &mut PoolState, id: u64) {
+        let Some(idx) = state.connections.iter().position(|c| c.id == id) else {
+            return;
+        };
+
+        while let Some(waiter) = state.waiters.pop_front() {
+            let grant = self.checkout(&mut state.connections, idx);
+            if waiter.grant(grant).is_ok() {
+                self.counters.handoffs.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            // That caller gave up; try the next one
+        }
+
+        state.connections[idx].checkin();
+        self.returned.notify_all();
+    }
+
+    fn handle(self: &Arc<Self>, grant: Grant, started: Instant) -> PooledConnectionHandle {
+        let waited = started.elapsed().as_micros() as u64;
+        self.counters.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.counters.total_wait_micros.fetch_add(waited, Ordering::Relaxed);
+
+        PooledConnectionHandle {
+            shared: self.clone(),
+            id: grant.id,
+            executor: grant.executor,
+            query_timeout: self.config.query_timeout,
+            caller: None,
+        }
+    }
+
+    fn maintain(&self) {
+        let mut state = self.state.lock().unwrap();
+        let connections = &mut state.connections;
+
+        if self.config.health_check_enabled {
+            for idx in 0..connections.len() {
+                if !connections[idx].in_use && connections[idx].probe().is_err() {
+                    self.replace(connections, idx);
+                }
+            }
+        }
+
+        self.evict_idle(connections);
+    }
+
+    /// Remove connections idle beyond max_idle_time, keeping min_size
+    fn evict_idle(&self, connections: &mut VecDeque<PooledConnection>) {
+        let max_idle = Duration::from_secs(self.config.max_idle_time);
+
+        while connections.len() > self.config.min_size {
+            if let Some(idx) = connections.iter().position(|c| c.is_idle_too_long(max_idle)) {
+                connections.remove(idx);
+                self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// An async caller's place in the queue
+///
+/// Dropping it, whether the wait timed out or the future was cancelled,
+/// leaves the queue; a connection granted in the meantime is passed on.
+struct QueuedTask {
+    shared: Arc<PoolShared>,
+    ticket: u64,
+    receiver: oneshot::Receiver<Grant>,
+}
+
+impl Drop for QueuedTask {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.dequeue(self.ticket) {
+            if let Ok(grant) = self.receiver.try_recv() {
+                self.shared.release(&mut state, grant.id);
+            }
+        }
+    }
+}
+
+/// Connection pool for managing database connections
+pub struct ConnectionPool {
+    shared: Arc<PoolShared>,
+    maintenance: Option<BackgroundTask>,
+    /// Stops the maintenance thread, on shutdown or drop
+    stop: ShutdownSignal,
+    /// Held through a shutdown; its report once it has finished
+    shutdown_report: Mutex<Option<ShutdownReport>>,
 }
 
 impl ConnectionPool {
@@ -99,25 +390,58 @@ impl ConnectionPool {
         if config.min_size > config.max_size {
             return Err("min_size cannot be greater than max_size".to_string());
         }
+        if config.maintenance_interval == Some(Duration::ZERO) {
+            return Err("maintenance_interval must be greater than zero".to_string());
+        }
 
-        let pool = ConnectionPool {
-            connections: Arc::new(Mutex::new(VecDeque::new())),
-            available: Arc::new(Condvar::new()),
+        let shared = Arc::new(PoolShared {
+            state: Mutex::new(PoolState {
+                connections: VecDeque::new(),
+                waiters: VecDeque::new(),
+                next_ticket: 0,
+                shut_down: false,
+            }),
+            returned: Condvar::new(),
             config,
-            current_size: Arc::new(Mutex::new(0)),
+            next_id: AtomicU64::new(0),
+            counters: PoolCounters::default(),
             graph,
             optimizer,
             cache,
             transaction_manager,
             wal_manager,
-        };
+            index_manager: Arc::new(IndexManager::new()),
+            schemas: Arc::new(std::sync::RwLock::new(SchemaValidator::new())),
+        });
 
         // Pre-create minimum connections
-        for _ in 0..pool.config.min_size {
-            pool.create_connection()?;
+        {
+            let mut state = shared.state.lock().unwrap();
+            for _ in 0..shared.config.min_size {
+                let conn = shared.new_connection();
+                state.connections.push_back(conn);
+            }
         }
 
-        Ok(pool)
+        let stop = ShutdownSignal::new();
+        let maintenance = shared.config.maintenance_interval.map(|interval| {
+            let shared = Arc::downgrade(&shared);
+            BackgroundTask::spawn_thread("pool-maintenance", &stop, move |stop| {
+                while !stop.wait_timeout(interval) {
+                    match shared.upgrade() {
+                        Some(shared) => shared.maintain(),
+                        None => break,
+                    }
+                }
+            })
+        });
+
+        Ok(ConnectionPool {
+            shared,
+            maintenance,
+            stop,
+            shutdown_report: Mutex::new(None),
+        })
     }
 
     /// Create a new connection pool with default configuration
@@ -138,107 +462,147 @@ impl ConnectionPool {
         )
     }
 
-    /// Create a new connection and add it to the pool
-    fn create_connection(&self) -> Result<(), String> {
-        let mut size = self.current_size.lock().unwrap();
-
-        if *size >= self.config.max_size {
-            return Err("Connection pool is at maximum capacity".to_string());
-        }
-
-        let executor = DQLExecutor::with_shared_components(
-            self.graph.clone(),
-            self.optimizer.clone(),
-            self.cache.clone(),
-            self.transaction_manager.clone(),
-            self.wal_manager.clone(),
-        );
-
-        let pooled_conn = PooledConnection::new(executor);
-
-        self.connections.lock().unwrap().push_back(pooled_conn);
-        *size += 1;
-
-        Ok(())
-    }
-
     /// Get a connection from the pool
+    ///
+    /// When none is free the calling thread sleeps in a first-come
+    /// first-served queue until one is returned, for at most
+    /// `connection_timeout`. With health checks enabled, a connection that
+    /// fails its probe is replaced by a fresh one instead of being handed out.
     pub fn get_connection(&self) -> Result<PooledConnectionHandle, String> {
-        let timeout = Duration::from_secs(self.config.connection_timeout);
-        let start = Instant::now();
+        let shared = &self.shared;
+        let started = Instant::now();
 
-        loop {
-            let mut connections = self.connections.lock().unwrap();
-
-            // Find an available connection
-            if let Some(conn_idx) = connections.iter().position(|c| !c.in_use) {
-                let conn = &mut connections[conn_idx];
-
-                // Health check if enabled
-                if self.config.health_check_enabled {
-                    // For now, just check if it's not idle too long
-                    let max_idle = Duration::from_secs(self.config.max_idle_time);
-                    if conn.is_idle_too_long(max_idle) {
-                        // Remove this connection and create a new one
-                        connections.remove(conn_idx);
-                        drop(connections);
+        let (ticket, receiver) = {
+            let mut state = shared.state.lock().unwrap();
+            if state.shut_down {
+                return Err(POOL_SHUT_DOWN.to_string());
+            }
+            if let Some(grant) = shared.try_checkout(&mut state) {
+                return Ok(shared.handle(grant, started));
+            }
 
-                        let mut size = self.current_size.lock().unwrap();
-                        *size -= 1;
-                        drop(size);
+            let (sender, receiver) = mpsc::channel();
+            (shared.enqueue(&mut state, Wake::Thread(sender)), receiver)
+        };
 
-                        self.create_connection()?;
-                        continue;
-                    }
+        let timeout = Duration::from_secs(shared.config.connection_timeout);
+        let grant = match receiver.recv_timeout(timeout) {
+            Ok(grant) => grant,
+            Err(_) => {
+                let mut state = shared.state.lock().unwrap();
+                if state.dequeue(ticket) {
+                    return Err("Connection timeout: no connections available".to_string());
                 }
-
-                conn.checkout();
-
-                return Ok(PooledConnectionHandle {
-                    pool: self.connections.clone(),
-                    available: self.available.clone(),
-                    index: conn_idx,
-                });
+                let shut_down = state.shut_down;
+                // Granted just as the wait ran out, or dropped from the queue by shutdown
+                drop(state);
+                receiver.try_recv().map_err(|_| {
+                    if shut_down {
+                        POOL_SHUT_DOWN.to_string()
+                    } else {
+                        "Connection timeout: no connections available".to_string()
+                    }
+                })?
             }
+        };
 
-            // No available connections - try to create a new one
-            drop(connections);
+        Ok(shared.handle(grant, started))
+    }
 
-            let current_size = *self.current_size.lock().unwrap();
-            if current_size < self.config.max_size {
-                if self.create_connection().is_ok() {
-                    continue;
-                }
+    /// Get a connection from the pool without blocking the async runtime
+    ///
+    /// Queues with blocking callers of `get_connection` on equal terms.
+    /// Dropping the future gives up its place in the queue.
+    pub async fn get_connection_async(&self) -> Result<PooledConnectionHandle, String> {
+        let shared = &self.shared;
+        let started = Instant::now();
+
+        let mut queued = {
+            let mut state = shared.state.lock().unwrap();
+            if state.shut_down {
+                return Err(POOL_SHUT_DOWN.to_string());
+            }
+            if let Some(grant) = shared.try_checkout(&mut state) {
+                return Ok(shared.handle(grant, started));
             }
 
-            // Wait for a connection to become available
-            if start.elapsed() >= timeout {
-                return Err("Connection timeout: no connections available".to_string());
+            let (sender, receiver) = oneshot::channel();
+            QueuedTask {
+                shared: shared.clone(),
+                ticket: shared.enqueue(&mut state, Wake::Task(sender)),
+                receiver,
             }
+        };
+
+        let timeout = Duration::from_secs(shared.config.connection_timeout);
+        match tokio::time::timeout(timeout, &mut queued.receiver).await {
+            Ok(Ok(grant)) => Ok(shared.handle(grant, started)),
+            // Shutdown emptied the queue
+            Ok(Err(_)) => Err(POOL_SHUT_DOWN.to_string()),
+            // Dropping `queued` leaves the queue, or passes on a connection
+            // granted as the wait ran out
+            Err(_) => Err("Connection timeout: no connections available".to_string()),
+        }
+    }
+
+    /// Shut the pool down
+    ///
+    /// New and queued callers fail at once with "Connection pool is shut
+    /// down". Handed-out connections get until `timeout` to come back; then
+    /// every connection's executor is shut down (see
+    /// `DQLExecutor::shutdown`), cancelling statements still running and
+    /// rolling back open transactions. Later calls wait for the first and
+    /// return its report.
+    pub fn shutdown(&self, timeout: Duration) -> Result<ShutdownReport, String> {
+        let deadline = Instant::now() + timeout;
+        let mut finished = self.shutdown_report.lock().unwrap();
+        if let Some(report) = &*finished {
+            return Ok(report.clone());
+        }
 
-            let (guard, timeout_result) = self.available
-                .wait_timeout(
-                    self.connections.lock().unwrap(),
-                    timeout - start.elapsed()
-                )
-                .unwrap();
+        self.stop.cancel();
+        let sessions: Vec<DQLExecutor> = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.shut_down = true;
+            // Dropping their wakers fails the queued callers
+            state.waiters.clear();
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            state = self
+                .shared
+                .returned
+                .wait_timeout_while(state, timeout, |s| s.connections.iter().any(|c| c.in_use))
+                .unwrap()
+                .0;
+            state.connections.iter().map(|c| c.session.clone()).collect()
+        };
 
-            if timeout_result.timed_out() {
-                return Err("Connection timeout: no connections available".to_string());
+        let mut report = ShutdownReport::default();
+        for session in sessions {
+            report.merge(session.shutdown(deadline.saturating_duration_since(Instant::now()))?);
+        }
+        if let Some(maintenance) = &self.maintenance {
+            if !maintenance.wait_timeout(deadline.saturating_duration_since(Instant::now())) {
+                report.unfinished_tasks.push(maintenance.name().to_string());
             }
         }
+
+        *finished = Some(report.clone());
+        Ok(report)
     }
 
     /// Get the current number of connections in the pool
     pub fn size(&self) -> usize {
-        *self.current_size.lock().unwrap()
+        self.shared.state.lock().unwrap().connections.len()
     }
 
     /// Get the number of active (in-use) connections
     pub fn active_connections(&self) -> usize {
-        self.connections
+        self.shared
+            .state
             .lock()
             .unwrap()
+            .connections
             .iter()
             .filter(|c| c.in_use)
             .count()
@@ -246,89 +610,179 @@ impl ConnectionPool {
 
     /// Get the number of idle connections
     pub fn idle_connections(&self) -> usize {
-        self.connections
+        self.shared
+            .state
             .lock()
             .unwrap()
+            .connections
             .iter()
             .filter(|c| !c.in_use)
             .count()
     }
 
+    /// Get the number of callers waiting for a connection
+    pub fn queue_depth(&self) -> usize {
+        self.shared.state.lock().unwrap().waiters.len()
+    }
+
     /// Clean up idle connections that have exceeded max idle time
     pub fn cleanup_idle_connections(&self) {
-        let max_idle = Duration::from_secs(self.config.max_idle_time);
-        let mut connections = self.connections.lock().unwrap();
-        let min_size = self.config.min_size;
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.evict_idle(&mut state.connections);
+    }
 
-        // Keep removing idle connections until we hit min_size or no more idle
-        while connections.len() > min_size {
-            if let Some(idx) = connections.iter().position(|c| c.is_idle_too_long(max_idle)) {
-                connections.remove(idx);
-                let mut size = self.current_size.lock().unwrap();
-                *size -= 1;
-            } else {
-                break;
-            }
-        }
+    /// Run one maintenance pass now
+    ///
+    /// Replaces idle connections that fail their health check and evicts
+    /// those idle beyond max_idle_time down to min_size. The background
+    /// thread does this every `maintenance_interval`.
+    pub fn maintain(&self) {
+        self.shared.maintain();
     }
 
     /// Get pool statistics
     pub fn stats(&self) -> PoolStats {
+        let counters = &self.shared.counters;
+        let acquisitions = counters.acquisitions.load(Ordering::Relaxed);
+        let avg_wait_time = match acquisitions {
+            0 => Duration::ZERO,
+            n => Duration::from_micros(counters.total_wait_micros.load(Ordering::Relaxed) / n),
+        };
+
         PoolStats {
             total_connections: self.size(),
             active_connections: self.active_connections(),
             idle_connections: self.idle_connections(),
-            max_size: self.config.max_size,
-            min_size: self.config.min_size,
+            max_size: self.shared.config.max_size,
+            min_size: self.shared.config.min_size,
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            failed_health_checks: counters.failed_health_checks.load(Ordering::Relaxed),
+            replacements: counters.replacements.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth(),
+            avg_wait_time,
+            waits: counters.waits.load(Ordering::Relaxed),
+            handoffs: counters.handoffs.load(Ordering::Relaxed),
         }
     }
 }
 
+impl Drop for ConnectionPool {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
 /// Handle to a pooled connection that automatically returns it to the pool on drop
 pub struct PooledConnectionHandle {
-    pool: Arc<Mutex<VecDeque<PooledConnection>>>,
-    available: Arc<Condvar>,
-    index: usize,
+    shared: Arc<PoolShared>,
+    id: u64,
+    executor: Arc<Mutex<DQLExecutor>>,
+    query_timeout: Option<Duration>,
+    caller: Option<Session>,
 }
 
 impl PooledConnectionHandle {
-    /// Execute a query using this connection
+    /// Set the timeout for queries run through this handle (`None` for no limit)
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    /// Check the statements run through this handle against a session's role
+    /// (`None` to stop checking); see `DQLExecutor::with_caller`
+    pub fn set_session(&mut self, session: Option<Session>) {
+        self.caller = session;
+    }
+
+    /// Borrow the connection's executor
     ///
-    /// Note: Direct executor access is not provided due to lifetime constraints.
-    /// Use this method to execute queries instead.
-    pub fn execute(&mut self, query: &str) -> Result<crate::dql_executor::QueryResult, String> {
-        let mut connections = self.pool.lock().unwrap();
+    /// Fails when an earlier query panicked on this connection; the pool
+    /// replaces it once the handle is returned.
+    pub fn executor(&mut self) -> Result<MutexGuard<'_, DQLExecutor>, String> {
+        self.executor
+            .lock()
+            .map_err(|_| "Connection poisoned by a panicked query".to_string())
+    }
 
-        if let Some(conn) = connections.get_mut(self.index) {
-            conn.executor.execute(query)
-        } else {
-            Err("Invalid connection handle".to_string())
+    /// Run `f` on the executor, checking statements against the handle's
+    /// session if set
+    fn with_executor<T, E: From<String>>(&mut self, f: impl FnOnce(&DQLExecutor) -> Result<T, E>) -> Result<T, E> {
+        let caller = self.caller.clone();
+        let executor = self.executor()?;
+
+        match caller {
+            Some(session) => f(&executor.clone().with_caller(session)),
+            None => f(&executor),
         }
     }
+
+    /// Execute a query using this connection
+    pub fn execute(&mut self, query: &str) -> Result<QueryResult, String> {
+        self.try_execute(query).map_err(String::from)
+    }
+
+    /// Execute a query using this connection, failing with a [`DeedError`]
+    pub fn try_execute(&mut self, query: &str) -> Result<QueryResult, DeedError> {
+        let timeout = self.query_timeout;
+        self.with_executor(|executor| executor.execute_timed(query, &std::collections::HashMap::new(), timeout))
+    }
+
+    /// Execute a query containing parameters using this connection
+    pub fn execute_with_params(
+        &mut self,
+        query: &str,
+        params: &std::collections::HashMap<String, crate::dql_ir::Value>,
+    ) -> Result<QueryResult, String> {
+        let timeout = self.query_timeout;
+        self.with_executor(|executor| executor.execute_timed(query, params, timeout))
+            .map_err(String::from)
+    }
+
+    /// Execute a query using this connection, delivering its rows in batches
+    ///
+    /// See `DQLExecutor::execute_stream`; the stream keeps reading after the
+    /// handle is returned to the pool.
+    pub fn execute_stream(&mut self, query: &str, batch_size: usize) -> Result<RowStream, String> {
+        self.try_execute_stream(query, batch_size).map_err(String::from)
+    }
+
+    /// Execute a query in batches using this connection, failing with a [`DeedError`]
+    pub fn try_execute_stream(&mut self, query: &str, batch_size: usize) -> Result<RowStream, DeedError> {
+        let timeout = self.query_timeout;
+        self.with_executor(|executor| executor.stream_timed(query, batch_size, timeout))
+    }
 }
 
 impl Drop for PooledConnectionHandle {
     fn drop(&mut self) {
-        // Return connection to pool
-        if let Ok(mut connections) = self.pool.lock() {
-            if let Some(conn) = connections.get_mut(self.index) {
-                conn.checkin();
-            }
+        // Return connection to pool, or straight to the next waiter
+        if let Ok(mut state) = self.shared.state.lock() {
+            self.shared.release(&mut state, self.id);
         }
-
-        // Notify waiting threads
-        self.available.notify_one();
     }
 }
 
 /// Pool statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PoolStats {
     pub total_connections: usize,
     pub active_connections: usize,
     pub idle_connections: usize,
     pub max_size: usize,
     pub min_size: usize,
+    /// Idle connections closed for exceeding max_idle_time
+    pub evictions: u64,
+    /// Connections that failed a health check
+    pub failed_health_checks: u64,
+    /// Broken connections swapped for fresh ones
+    pub replacements: u64,
+    /// Callers currently waiting for a connection
+    pub queue_depth: usize,
+    /// Mean time get_connection took, waiting included
+    pub avg_wait_time: Duration,
+    /// Callers that had to queue for a connection
+    pub waits: u64,
+    /// Returned connections passed straight to a queued caller
+    pub handoffs: u64,
 }
 
 impl PoolStats {
@@ -350,7 +804,7 @@ mod tests {
     fn create_test_pool() -> ConnectionPool {
         let graph = Arc::new(std::sync::RwLock::new(Graph::new()));
         let optimizer = Arc::new(std::sync::RwLock::new(AntColonyOptimizer::new()));
-        let cache = Arc::new(std::sync::RwLock::new(StigmergyCache::new()));
+        let cache = Arc::new(std::sync::RwLock::new(StigmergyCache::new(1000)));
         let transaction_manager = Arc::new(TransactionManager::new());
 
         ConnectionPool::with_defaults(
@@ -405,12 +859,12 @@ mod tests {
         let mut handles = vec![];
 
         // Spawn 5 threads, each getting a connection
-        for i in 0..5 {
+        for _i in 0..5 {
             let pool_clone = pool.clone();
             let handle = thread::spawn(move || {
                 let mut conn = pool_clone.get_connection().unwrap();
                 thread::sleep(Duration::from_millis(10));
-                conn.executor().unwrap();
+                drop(conn.executor().unwrap());
             });
             handles.push(handle);
         }
@@ -428,7 +882,7 @@ mod tests {
     fn test_pool_max_size() {
         let graph = Arc::new(std::sync::RwLock::new(Graph::new()));
         let optimizer = Arc::new(std::sync::RwLock::new(AntColonyOptimizer::new()));
-        let cache = Arc::new(std::sync::RwLock::new(StigmergyCache::new()));
+        let cache = Arc::new(std::sync::RwLock::new(StigmergyCache::new(1000)));
         let transaction_manager = Arc::new(TransactionManager::new());
 
         let config = PoolConfig {
@@ -437,6 +891,11 @@ mod tests {
             connection_timeout: 1,
             max_idle_time: 300,
             health_check_enabled: false,
+            query_timeout: None,
+            maintenance_interval: None,
+            audit: None,
+            read_replicas: None,
+            replication: None,
         };
 
         let pool = ConnectionPool::new(
@@ -454,7 +913,7 @@ mod tests {
         // Should not be able to get a third connection (with short timeout)
         let result = pool.get_connection();
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("timeout"));
+        assert!(result.err().unwrap().contains("timeout"));
     }
 
     #[test]
@@ -471,4 +930,190 @@ mod tests {
         assert_eq!(stats.active_connections, 1);
         assert!(stats.utilization() > 0.0);
     }
+
+    #[test]
+    fn test_stream_through_handle() {
+        let pool = create_test_pool();
+
+        let mut handle = pool.get_connection().unwrap();
+        for name in ["Ann", "Ben", "Cy"] {
+            handle.execute(&format!("INSERT INTO Users VALUES ({{name: '{}'}})", name)).unwrap();
+        }
+
+        let stream = handle.execute_stream("FROM Users SELECT name", 2).unwrap();
+        drop(handle);
+        let sizes: Vec<usize> = stream.map(|batch| batch.unwrap().row_count()).collect();
+        assert_eq!(sizes, [2, 1]);
+    }
+
+    #[test]
+    fn test_idle_connections_shrink_to_min_size() {
+        let config = PoolConfig {
+            min_size: 1,
+            max_size: 4,
+            max_idle_time: 1,
+            maintenance_interval: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let pool = ConnectionPool::new(
+            Arc::new(std::sync::RwLock::new(Graph::new())),
+            Arc::new(std::sync::RwLock::new(AntColonyOptimizer::new())),
+            Arc::new(std::sync::RwLock::new(StigmergyCache::new(1000))),
+            Arc::new(TransactionManager::new()),
+            None,
+            config,
+        ).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|_| pool.get_connection().unwrap()).collect();
+        drop(handles);
+        assert_eq!(pool.size(), 4);
+
+        let start = Instant::now();
+        while pool.size() > 1 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(50));
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.total_connections, 1);
+        assert_eq!(stats.evictions, 3);
+    }
+
+    #[test]
+    fn test_poisoned_connection_is_replaced() {
+        let config = PoolConfig {
+            min_size: 1,
+            max_size: 1,
+            maintenance_interval: None,
+            ..Default::default()
+        };
+        let pool = ConnectionPool::new(
+            Arc::new(std::sync::RwLock::new(Graph::new())),
+            Arc::new(std::sync::RwLock::new(AntColonyOptimizer::new())),
+            Arc::new(std::sync::RwLock::new(StigmergyCache::new(1000))),
+            Arc::new(TransactionManager::new()),
+            None,
+            config,
+        ).unwrap();
+
+        let mut handle = pool.get_connection().unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _executor = handle.executor().unwrap();
+            panic!("query blew up");
+        }));
+        assert!(panicked.is_err());
+        assert!(handle.execute("FROM Users SELECT name").is_err());
+        drop(handle);
+
+        // The next caller gets a working connection in its place
+        let mut handle = pool.get_connection().unwrap();
+        handle.execute("INSERT INTO Users VALUES ({name: 'Ann'})").unwrap();
+        assert_eq!(handle.execute("FROM Users SELECT name").unwrap().row_count(), 1);
+
+        let stats = pool.stats();
+        assert_eq!(stats.total_connections, 1);
+        assert_eq!(stats.failed_health_checks, 1);
+        assert_eq!(stats.replacements, 1);
+    }
+
+    fn create_sized_pool(max_size: usize) -> ConnectionPool {
+        let config = PoolConfig {
+            min_size: 1,
+            max_size,
+            maintenance_interval: None,
+            ..Default::default()
+        };
+        ConnectionPool::new(
+            Arc::new(std::sync::RwLock::new(Graph::new())),
+            Arc::new(std::sync::RwLock::new(AntColonyOptimizer::new())),
+            Arc::new(std::sync::RwLock::new(StigmergyCache::new(1000))),
+            Arc::new(TransactionManager::new()),
+            None,
+            config,
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_waiters_are_served_in_arrival_order() {
+        let pool = Arc::new(create_sized_pool(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let held = pool.get_connection().unwrap();
+
+        let mut threads = vec![];
+        for i in 0..5 {
+            let pool_clone = pool.clone();
+            let order = order.clone();
+            threads.push(thread::spawn(move || {
+                let _conn = pool_clone.get_connection().unwrap();
+                order.lock().unwrap().push(i);
+            }));
+            // Queue each caller before starting the next
+            while pool.queue_depth() < i + 1 {
+                thread::yield_now();
+            }
+        }
+
+        drop(held);
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_contended_pool_serves_every_thread() {
+        let pool = Arc::new(create_sized_pool(4));
+        let served = Arc::new(AtomicU64::new(0));
+
+        let threads: Vec<_> = (0..64)
+            .map(|_| {
+                let pool = pool.clone();
+                let served = served.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        let mut conn = pool.get_connection().unwrap();
+                        drop(conn.executor().unwrap());
+                        // Held long enough that checkouts overlap even on one core
+                        thread::sleep(Duration::from_millis(1));
+                        served.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(served.load(Ordering::Relaxed), 64 * 20);
+        assert_eq!(stats.total_connections, 4);
+        assert_eq!(stats.queue_depth, 0);
+        // Each queued caller was woken once, by the connection it was given
+        assert!(stats.waits > 0);
+        assert_eq!(stats.handoffs, stats.waits);
+    }
+
+    #[tokio::test]
+    async fn test_get_connection_async() {
+        let pool = Arc::new(create_sized_pool(1));
+        let held = pool.get_connection().unwrap();
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get_connection_async().await.map(|_| ()) }
+        });
+        while pool.queue_depth() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(held);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(pool.stats().handoffs, 1);
+
+        // A cancelled wait leaves the queue
+        let held = pool.get_connection().unwrap();
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), pool.get_connection_async()).await;
+        assert!(cancelled.is_err());
+        assert_eq!(pool.queue_depth(), 0);
+        drop(held);
+        assert_eq!(pool.idle_connections(), 1);
+    }
 }
@@ -0,0 +1,413 @@
+//! Network Server
+//!
+//! TCP protocol for clients that don't embed the engine. Every message is a
+//! frame: a 4-byte big-endian length followed by a bincode-encoded
+//! [`Request`] or [`Response`]. A client logs in for a session token, then
+//! sends queries carrying that token; each gets back a result or an error.
+//!
+//! Queries run on an executor checked out of a [`ConnectionPool`] on a
+//! client's first query and kept until it disconnects, so a transaction can
+//! span several queries. A transaction still open at disconnect is rolled back.
+//!
+//! Connections are upgraded to TLS before the first frame with the
+//! certificates of [`ServerConfig::tls`]; plaintext needs `insecure`.
+
+use crate::auth::{AuthManager, Session};
+use crate::connection_pool::{ConnectionPool, PooledConnectionHandle};
+use crate::dql_executor::QueryResult;
+use crate::dql_ir::Value;
+use crate::tls::{BoxedStream, TlsConfig, Transport};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+
+/// Server limits
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Clients connected at once; more are refused with an error frame
+    pub max_connections: usize,
+    /// Timeout of each query (`None` for the pool's default)
+    pub query_timeout: Option<Duration>,
+    /// Largest frame accepted, in bytes
+    pub max_frame_size: usize,
+    /// Certificates client connections are encrypted with; with a CA
+    /// bundle, clients must present a certificate signed by it
+    pub tls: Option<TlsConfig>,
+    /// Accept plaintext clients instead of TLS ones, for local testing
+    pub insecure: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_connections: 100,
+            query_timeout: Some(Duration::from_secs(30)),
+            max_frame_size: 16 * 1024 * 1024, // 16 MiB
+            tls: None,
+            insecure: false,
+        }
+    }
+}
+
+/// Client-to-server message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    Login { username: String, password: String },
+    /// Run a DQL statement; `params` bind its `$name` / `?1` placeholders
+    Query {
+        session: String,
+        query: String,
+        params: HashMap<String, Value>,
+    },
+    Logout { session: String },
+}
+
+/// Server-to-client message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    LoggedIn { session: String },
+    Result(QueryResult),
+    LoggedOut,
+    Error { message: String },
+}
+
+/// A database server, not yet listening
+pub struct DeedServer {
+    pool: Arc<ConnectionPool>,
+    auth: Arc<AuthManager>,
+    config: ServerConfig,
+}
+
+impl DeedServer {
+    pub fn new(pool: Arc<ConnectionPool>, auth: Arc<AuthManager>, config: ServerConfig) -> Self {
+        DeedServer { pool, auth, config }
+    }
+
+    /// Start accepting clients on `addr` (port 0 picks a free port)
+    pub async fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<ServerHandle, String> {
+        let transport = Transport::new(self.config.tls.as_ref(), self.config.insecure)?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Failed to bind server: {}", e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to read server address: {}", e))?;
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let connections = Arc::new(Semaphore::new(self.config.max_connections));
+        let server = Arc::new(self);
+        let accept_loop = tokio::spawn(accept_clients(listener, transport, server.clone(), connections.clone(), shutdown_rx));
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown,
+            accept_loop,
+            connections,
+            max_connections: server.config.max_connections,
+        })
+    }
+}
+
+/// A running server
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+    accept_loop: JoinHandle<()>,
+    /// One permit per client slot; all are free once every client is gone
+    connections: Arc<Semaphore>,
+    max_connections: usize,
+}
+
+impl ServerHandle {
+    /// Address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting clients and wait for the connected ones to finish
+    ///
+    /// A query already running completes and its response is sent; clients
+    /// are then disconnected instead of being read from again.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.accept_loop.await;
+        let _ = self.connections.acquire_many(self.max_connections as u32).await;
+    }
+}
+
+async fn accept_clients(
+    listener: TcpListener,
+    transport: Transport,
+    server: Arc<DeedServer>,
+    connections: Arc<Semaphore>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => return,
+        };
+
+        let permit = connections.clone().try_acquire_owned().ok();
+        let transport = transport.clone();
+        let server = server.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            // The handshake comes first, so even a refusal is encrypted
+            let (mut stream, _) = match transport.accept(stream).await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Rejected connection from {}: {}", peer_addr, e);
+                    return;
+                }
+            };
+            let Some(permit) = permit else {
+                let refusal = Response::Error {
+                    message: format!("Too many connections (max {})", server.config.max_connections),
+                };
+                let _ = write_frame(&mut stream, &refusal).await;
+                return;
+            };
+
+            let client = Client { server, connection: None };
+            client.serve(stream, shutdown).await;
+            drop(permit);
+        });
+    }
+}
+
+/// One connected client
+struct Client {
+    server: Arc<DeedServer>,
+    /// Pooled executor, checked out on the first query
+    connection: Option<PooledConnectionHandle>,
+}
+
+impl Client {
+    async fn serve(mut self, mut stream: BoxedStream, mut shutdown: watch::Receiver<bool>) {
+        let max_frame_size = self.server.config.max_frame_size;
+
+        loop {
+            // A request already sent is still served during shutdown
+            let frame = tokio::select! {
+                biased;
+                frame = read_frame::<Request, _>(&mut stream, max_frame_size) => frame,
+                _ = shutdown.changed() => break,
+            };
+            let request = match frame {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(message) => {
+                    // The stream can't be resynchronized after a bad frame
+                    let _ = write_frame(&mut stream, &Response::Error { message }).await;
+                    break;
+                }
+            };
+
+            let response = self.handle(request).await;
+            if write_frame(&mut stream, &response).await.is_err() {
+                break;
+            }
+        }
+
+        self.disconnect().await;
+    }
+
+    async fn handle(&mut self, request: Request) -> Response {
+        let auth = &self.server.auth;
+        let outcome = match request {
+            Request::Login { username, password } => {
+                auth.login(&username, &password).map(|session| Response::LoggedIn { session })
+            }
+            Request::Logout { session } => auth.logout(&session).map(|_| Response::LoggedOut),
+            Request::Query { session, query, params } => match auth.validate_session(&session) {
+                Ok(session) => self.run(session, query, params).await.map(Response::Result),
+                Err(e) => Err(e),
+            },
+        };
+
+        outcome.unwrap_or_else(|message| Response::Error { message })
+    }
+
+    /// Run a statement on the client's executor, off the async threads
+    ///
+    /// The executor rejects statements the session's role doesn't allow.
+    async fn run(&mut self, session: Session, query: String, params: HashMap<String, Value>) -> Result<QueryResult, String> {
+        let mut handle = match self.connection.take() {
+            Some(handle) => handle,
+            None => {
+                let mut handle = self.server.pool.get_connection_async().await?;
+                if let Some(timeout) = self.server.config.query_timeout {
+                    handle.set_query_timeout(Some(timeout));
+                }
+                handle
+            }
+        };
+
+        handle.set_session(Some(session));
+
+        let (handle, result) = tokio::task::spawn_blocking(move || {
+            let result = handle.execute_with_params(&query, &params);
+            (handle, result)
+        })
+        .await
+        .map_err(|e| format!("Query task failed: {}", e))?;
+
+        self.connection = Some(handle);
+        result
+    }
+
+    /// Roll back what the client left open and return its executor to the pool
+    async fn disconnect(&mut self) {
+        if let Some(mut handle) = self.connection.take() {
+            let _ = tokio::task::spawn_blocking(move || {
+                // Fails harmlessly when no transaction is open
+                let _ = handle.execute("ROLLBACK");
+            })
+            .await;
+        }
+    }
+}
+
+/// Read one frame; `None` when the peer closed the connection between frames
+async fn read_frame<T: for<'de> Deserialize<'de>, S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_frame_size: usize,
+) -> Result<Option<T>, String> {
+    let mut length = [0u8; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(format!("Failed to read frame: {}", e)),
+    }
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > max_frame_size {
+        return Err(format!("Frame of {} bytes exceeds the {} byte limit", length, max_frame_size));
+    }
+
+    let mut payload = vec![0u8; length];
+    stream
+        .read_exact(&mut payload)
+        .await
+        .map_err(|e| format!("Failed to read frame: {}", e))?;
+    bincode::deserialize(&payload)
+        .map(Some)
+        .map_err(|e| format!("Malformed frame: {}", e))
+}
+
+async fn write_frame<T: Serialize, S: AsyncWrite + Unpin>(stream: &mut S, message: &T) -> Result<(), String> {
+    let payload = bincode::serialize(message).map_err(|e| format!("Serialization error: {}", e))?;
+    let length = u32::try_from(payload.len()).map_err(|_| "Frame too large".to_string())?;
+
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    stream
+        .write_all(&frame)
+        .await
+        .map_err(|e| format!("Failed to write frame: {}", e))
+}
+
+/// Client for a [`DeedServer`]
+pub struct DeedClient {
+    stream: BoxedStream,
+    session: Option<String>,
+}
+
+impl DeedClient {
+    /// Connect over plaintext TCP, to a server that's `insecure`
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| format!("Failed to connect: {}", e))?;
+        Ok(DeedClient {
+            stream: Box::new(stream),
+            session: None,
+        })
+    }
+
+    /// Connect over TLS, verifying the server's certificate is for `server_name`
+    ///
+    /// `tls` needs a certificate of its own only if the server requires one.
+    pub async fn connect_tls<A: ToSocketAddrs>(addr: A, server_name: &str, tls: &TlsConfig) -> Result<Self, String> {
+        let transport = Transport::new(Some(tls), false)?;
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| format!("Failed to connect: {}", e))?;
+        Ok(DeedClient {
+            stream: transport.connect(stream, server_name, None).await?,
+            session: None,
+        })
+    }
+
+    /// Log in; later queries run as this user
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), String> {
+        let request = Request::Login {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        match self.call(&request).await? {
+            Response::LoggedIn { session } => {
+                self.session = Some(session);
+                Ok(())
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn logout(&mut self) -> Result<(), String> {
+        let session = self.session.take().ok_or("Not logged in")?;
+        match self.call(&Request::Logout { session }).await? {
+            Response::LoggedOut => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn execute(&mut self, query: &str) -> Result<QueryResult, String> {
+        self.execute_with_params(query, HashMap::new()).await
+    }
+
+    /// Execute a query containing parameters (`$name`, or `?1` for key "1")
+    pub async fn execute_with_params(
+        &mut self,
+        query: &str,
+        params: HashMap<String, Value>,
+    ) -> Result<QueryResult, String> {
+        let request = Request::Query {
+            session: self.session.clone().ok_or("Not logged in")?,
+            query: query.to_string(),
+            params,
+        };
+        match self.call(&request).await? {
+            Response::Result(result) => Ok(result),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Send a request and read its response; error responses become `Err`
+    async fn call(&mut self, request: &Request) -> Result<Response, String> {
+        write_frame(&mut self.stream, request).await?;
+        match read_frame(&mut self.stream, usize::MAX).await? {
+            Some(Response::Error { message }) => Err(message),
+            Some(response) => Ok(response),
+            None => Err("Server closed the connection".to_string()),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> String {
+    format!("Unexpected response: {:?}", response)
+}
@@ -12,7 +12,6 @@
 //! - Blocking: Coordinator failure can block participants
 
 use crate::distributed_topology::NodeId;
-use crate::distributed_shard::ShardId;
 use crate::transaction::TransactionId;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
@@ -269,12 +268,11 @@ impl TwoPhaseCommitCoordinator {
         let transactions = self.transactions.read().unwrap();
 
         for (txn_id, txn) in transactions.iter() {
-            if txn.created_at.elapsed() > self.timeout {
-                if txn.state != TwoPhaseCommitState::Committed &&
+            if txn.created_at.elapsed() > self.timeout
+                && txn.state != TwoPhaseCommitState::Committed &&
                    txn.state != TwoPhaseCommitState::Aborted {
                     timed_out.push(*txn_id);
                 }
-            }
         }
 
         timed_out
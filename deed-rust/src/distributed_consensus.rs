@@ -8,17 +8,23 @@
 //! - Log replication across all nodes
 //! - State machine for applying committed entries
 //! - Term-based conflict resolution
+//! - Term, vote and log persisted, so a restarted node can't vote twice
 //!
 //! References:
 //! - Raft Paper: "In Search of an Understandable Consensus Algorithm"
 //! - https://raft.github.io/
 
 use crate::distributed_topology::NodeId;
-use crate::distributed_p2p::{P2PNetwork, MessageType as P2PMessageType, P2PMessage};
+use crate::distributed_p2p::{P2PNetwork, MessageKind, MessageType as P2PMessageType};
+use crate::dql_executor::DQLExecutor;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, Mutex};
 use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 
 /// Raft term number (monotonically increasing)
 pub type Term = u64;
@@ -104,6 +110,185 @@ impl Default for RaftConfig {
     }
 }
 
+/// What committed log entries are applied to, in log order
+///
+/// A node with storage keeps the index of the last entry applied to a
+/// durable state machine, and carries on after it when restarted; one
+/// kept in memory has the whole log applied again, rebuilding it.
+pub trait StateMachine: Send + Sync {
+    /// Apply one committed command
+    fn apply(&self, command: &[u8]) -> Result<(), String>;
+
+    /// Whether applied commands survive a restart
+    fn is_durable(&self) -> bool {
+        false
+    }
+}
+
+/// Commands are DQL statements, typically the mutations a leader replicates
+impl StateMachine for DQLExecutor {
+    fn apply(&self, command: &[u8]) -> Result<(), String> {
+        let query = std::str::from_utf8(command)
+            .map_err(|e| format!("Command isn't UTF-8 DQL: {}", e))?;
+        self.execute(query).map(|_| ())
+    }
+
+    fn is_durable(&self) -> bool {
+        DQLExecutor::is_durable(self)
+    }
+}
+
+/// The term and vote, which must survive a restart
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HardState {
+    current_term: Term,
+    voted_for: Option<NodeId>,
+}
+
+const HARD_STATE_FILE: &str = "raft_state";
+const LOG_FILE: &str = "raft_log";
+const APPLIED_FILE: &str = "raft_applied";
+
+/// A node's Raft state on disk
+///
+/// The term and vote, and the index of the last entry applied, are
+/// rewritten whole whenever they change. Log entries are appended as
+/// length-prefixed bincode frames; dropping a conflicting suffix rewrites
+/// the file.
+struct RaftStorage {
+    dir: PathBuf,
+    /// Held while the term and vote are written, so writes land in order
+    hard_state: Mutex<()>,
+    log_file: Mutex<File>,
+}
+
+impl RaftStorage {
+    /// Open the storage in `dir`, returning what was saved there: the term
+    /// and vote, the log and the last entry applied
+    fn open(dir: &Path) -> Result<(Self, HardState, Vec<LogEntry>, LogIndex), String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create Raft directory: {}", e))?;
+
+        let hard_state = match std::fs::read(dir.join(HARD_STATE_FILE)) {
+            Ok(bytes) => bincode::deserialize(&bytes)
+                .map_err(|e| format!("Failed to decode Raft state: {}", e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HardState::default(),
+            Err(e) => return Err(format!("Failed to read Raft state: {}", e)),
+        };
+
+        let log_path = dir.join(LOG_FILE);
+        let bytes = match std::fs::read(&log_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read Raft log: {}", e)),
+        };
+        let (log, decoded) = decode_log(&bytes)?;
+
+        let applied = match std::fs::read(dir.join(APPLIED_FILE)) {
+            Ok(bytes) => bytes
+                .try_into()
+                .map(LogIndex::from_le_bytes)
+                .map_err(|_| "Failed to decode the Raft applied index".to_string())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(format!("Failed to read the Raft applied index: {}", e)),
+        };
+
+        let log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .map_err(|e| format!("Failed to open Raft log: {}", e))?;
+        let storage = RaftStorage {
+            dir: dir.to_path_buf(),
+            hard_state: Mutex::new(()),
+            log_file: Mutex::new(log_file),
+        };
+
+        // A crash mid-append leaves part of a frame, which must go before
+        // anything is appended after it
+        if decoded < bytes.len() {
+            storage.rewrite(&log)?;
+        }
+
+        Ok((storage, hard_state, log, applied))
+    }
+
+    /// Record the index of the last entry applied
+    fn save_applied(&self, index: LogIndex) -> Result<(), String> {
+        write_atomically(&self.dir.join(APPLIED_FILE), &index.to_le_bytes())
+    }
+
+    /// Write the term and vote, reading them only once earlier writes are done
+    fn save_hard_state(&self, state: impl FnOnce() -> HardState) -> Result<(), String> {
+        let _writing = self.hard_state.lock().unwrap();
+        let bytes = bincode::serialize(&state())
+            .map_err(|e| format!("Failed to encode Raft state: {}", e))?;
+        write_atomically(&self.dir.join(HARD_STATE_FILE), &bytes)
+    }
+
+    /// Add entries to the end of the log
+    fn append(&self, entries: &[LogEntry]) -> Result<(), String> {
+        let bytes = encode_log(entries)?;
+        let mut file = self.log_file.lock().unwrap();
+        file.write_all(&bytes)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Failed to append to Raft log: {}", e))
+    }
+
+    /// Replace the whole log
+    fn rewrite(&self, log: &[LogEntry]) -> Result<(), String> {
+        let bytes = encode_log(log)?;
+        let mut file = self.log_file.lock().unwrap();
+        let path = self.dir.join(LOG_FILE);
+        write_atomically(&path, &bytes)?;
+        *file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open Raft log: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Replace a file's contents, so a crash leaves either the old or the new
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)
+        .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    std::fs::rename(&temp_path, path)
+        .map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+fn encode_log(entries: &[LogEntry]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        let frame = bincode::serialize(entry)
+            .map_err(|e| format!("Failed to encode Raft log entry: {}", e))?;
+        bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&frame);
+    }
+    Ok(bytes)
+}
+
+/// Decode log frames, stopping at a partly written last one; returns the
+/// entries and how many bytes they took
+fn decode_log(bytes: &[u8]) -> Result<(Vec<LogEntry>, usize), String> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = bytes.get(offset..offset + 4) {
+        let len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+        let Some(frame) = bytes.get(offset + 4..offset + 4 + len) else {
+            break;
+        };
+        entries.push(bincode::deserialize(frame)
+            .map_err(|e| format!("Failed to decode Raft log entry {}: {}", entries.len() + 1, e))?);
+        offset += 4 + len;
+    }
+    Ok((entries, offset))
+}
+
 /// Raft consensus state machine
 pub struct RaftNode {
     config: RaftConfig,
@@ -120,6 +305,9 @@ pub struct RaftNode {
     /// Who we voted for in current term
     voted_for: Arc<RwLock<Option<NodeId>>>,
 
+    /// Candidate-only: nodes that granted us their vote this term
+    votes_received: Arc<Mutex<HashSet<NodeId>>>,
+
     /// Log entries
     log: Arc<RwLock<Vec<LogEntry>>>,
 
@@ -144,6 +332,15 @@ pub struct RaftNode {
     /// When we last heard from leader
     last_heartbeat: Arc<Mutex<Instant>>,
 
+    /// Woken when commit_index advances, to apply the new entries
+    commit_notify: Arc<Notify>,
+
+    /// What committed commands are applied to, if anything
+    state_machine: Option<Arc<dyn StateMachine>>,
+
+    /// Where the term, vote and log are kept, if anywhere
+    storage: Option<Arc<RaftStorage>>,
+
     /// P2P network for communication
     p2p_network: Arc<P2PNetwork>,
 }
@@ -161,6 +358,7 @@ impl RaftNode {
             state: Arc::new(RwLock::new(RaftState::Follower)),
             current_term: Arc::new(RwLock::new(0)),
             voted_for: Arc::new(RwLock::new(None)),
+            votes_received: Arc::new(Mutex::new(HashSet::new())),
             log: Arc::new(RwLock::new(Vec::new())),
             commit_index: Arc::new(RwLock::new(0)),
             last_applied: Arc::new(RwLock::new(0)),
@@ -169,10 +367,35 @@ impl RaftNode {
             current_leader: Arc::new(RwLock::new(None)),
             cluster_nodes: Arc::new(RwLock::new(Vec::new())),
             last_heartbeat: Arc::new(Mutex::new(Instant::now())),
+            commit_notify: Arc::new(Notify::new()),
+            state_machine: None,
+            storage: None,
             p2p_network,
         }
     }
 
+    /// Apply committed commands to `state_machine`
+    pub fn with_state_machine(mut self, state_machine: Arc<dyn StateMachine>) -> Self {
+        self.state_machine = Some(state_machine);
+        self
+    }
+
+    /// Keep the term, vote and log in `dir`, restoring what was saved there
+    ///
+    /// Entries already applied to a durable state machine are committed,
+    /// and aren't applied again.
+    pub fn with_storage(mut self, dir: &Path) -> Result<Self, String> {
+        let (storage, hard_state, log, applied) = RaftStorage::open(dir)?;
+        let applied = applied.min(log.len() as LogIndex);
+        *self.current_term.write().unwrap() = hard_state.current_term;
+        *self.voted_for.write().unwrap() = hard_state.voted_for;
+        *self.log.write().unwrap() = log;
+        *self.commit_index.write().unwrap() = applied;
+        *self.last_applied.write().unwrap() = applied;
+        self.storage = Some(Arc::new(storage));
+        Ok(self)
+    }
+
     /// Add a node to the cluster
     pub fn add_cluster_node(&self, node_id: NodeId) {
         let mut nodes = self.cluster_nodes.write().unwrap();
@@ -202,9 +425,72 @@ impl RaftNode {
     }
 
     /// Start the Raft consensus protocol
+    ///
+    /// Answers Raft messages arriving over the P2P network from then on.
+    /// Must be called inside a Tokio runtime.
     pub fn start(&self) {
+        self.serve();
         self.start_election_timer();
         self.start_heartbeat_timer();
+        self.start_applier();
+    }
+
+    /// Register the handler for Raft messages on the P2P network
+    fn serve(&self) {
+        let node = self.clone_for_async();
+
+        self.p2p_network.register_handler(MessageKind::Raft, move |msg| {
+            let P2PMessageType::Raft { message } = &msg.message_type else {
+                return None;
+            };
+            let message = match bincode::deserialize(message) {
+                Ok(message) => message,
+                Err(e) => {
+                    return Some(msg.reply(P2PMessageType::Error {
+                        message: format!("Failed to decode Raft message: {}", e),
+                    }))
+                }
+            };
+
+            let reply = node.handle_message(msg.sender_id, message)?;
+            let reply = bincode::serialize(&reply).map_or_else(
+                |e| P2PMessageType::Error { message: format!("Failed to encode Raft message: {}", e) },
+                |message| P2PMessageType::Raft { message },
+            );
+            Some(msg.reply(reply))
+        });
+    }
+
+    /// Send a Raft message to a peer, waiting up to `rpc_timeout_ms` for its reply
+    async fn send_rpc(&self, peer_id: NodeId, message: &RaftMessage) -> Result<RaftMessage, String> {
+        let message = bincode::serialize(message)
+            .map_err(|e| format!("Failed to encode Raft message: {}", e))?;
+        let rpc_timeout = Duration::from_millis(self.config.rpc_timeout_ms);
+
+        let reply = tokio::time::timeout(
+            rpc_timeout,
+            self.p2p_network.send_message(peer_id, P2PMessageType::Raft { message }),
+        )
+        .await
+        .map_err(|_| format!("No reply from node {} within {:?}", peer_id, rpc_timeout))??;
+
+        match reply.message_type {
+            P2PMessageType::Raft { message } => bincode::deserialize(&message)
+                .map_err(|e| format!("Failed to decode Raft message: {}", e)),
+            P2PMessageType::Error { message } => Err(message),
+            other => Err(format!("Unexpected reply to Raft message: {:?}", other)),
+        }
+    }
+
+    /// Every other node in the cluster
+    fn peers(&self) -> Vec<NodeId> {
+        let nodes = self.cluster_nodes.read().unwrap();
+        nodes.iter().copied().filter(|&node_id| node_id != self.node_id).collect()
+    }
+
+    /// Votes or replicas needed for a majority, counting this node
+    fn quorum(&self) -> usize {
+        self.peers().len().div_ceil(2) + 1
     }
 
     /// Start election timeout timer
@@ -213,7 +499,8 @@ impl RaftNode {
 
         tokio::spawn(async move {
             loop {
-                // Random election timeout
+                // Random election timeout, drawn afresh each time so a split
+                // vote is unlikely to split again
                 let timeout_ms = rand::random::<u64>() %
                     (node.config.election_timeout_max_ms - node.config.election_timeout_min_ms) +
                     node.config.election_timeout_min_ms;
@@ -245,18 +532,87 @@ impl RaftNode {
                 interval.tick().await;
 
                 if node.is_leader() {
-                    node.send_heartbeats().await;
+                    node.send_heartbeats();
+                }
+            }
+        });
+    }
+
+    /// Start applying entries to the state machine as they're committed
+    fn start_applier(&self) {
+        let node = self.clone_for_async();
+
+        tokio::spawn(async move {
+            loop {
+                node.commit_notify.notified().await;
+
+                // Commands may block, e.g. on the graph's lock
+                let applier = Arc::clone(&node);
+                if tokio::task::spawn_blocking(move || applier.apply_committed()).await.is_err() {
+                    break;
                 }
             }
         });
     }
 
+    /// Apply every committed entry not yet applied, in order
+    ///
+    /// Applying stops at a command the state machine rejects, to be retried
+    /// when the commit index next advances, rather than skip past it. The
+    /// index reached is saved for a durable state machine.
+    fn apply_committed(&self) {
+        loop {
+            let commit_index = *self.commit_index.read().unwrap();
+
+            // Held while applying, so no entry is applied twice
+            let mut last_applied = self.last_applied.write().unwrap();
+            if *last_applied >= commit_index {
+                return;
+            }
+
+            let entry = self.log.read().unwrap()[*last_applied as usize].clone();
+            let Some(state_machine) = &self.state_machine else {
+                *last_applied = entry.index;
+                continue;
+            };
+            if let Err(e) = state_machine.apply(&entry.command) {
+                eprintln!("Node {} stopped applying at entry {}: {}", self.node_id, entry.index, e);
+                return;
+            }
+            *last_applied = entry.index;
+
+            if let Some(storage) = self.storage.as_ref().filter(|_| state_machine.is_durable()) {
+                if let Err(e) = storage.save_applied(entry.index) {
+                    eprintln!("Node {} failed to save its applied index: {}", self.node_id, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Persist the current term and vote, if the node has storage
+    fn save_hard_state(&self) -> Result<(), String> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        storage.save_hard_state(|| HardState {
+            current_term: self.get_current_term(),
+            voted_for: *self.voted_for.read().unwrap(),
+        })
+    }
+
     /// Start an election
+    ///
+    /// Votes are requested from every other node at once and counted by
+    /// `handle_vote_response` as they arrive. If nobody wins, the election
+    /// timer starts another one.
     fn start_election(&self) {
         println!("Node {} starting election", self.node_id);
 
         // Transition to candidate
         *self.state.write().unwrap() = RaftState::Candidate;
+        *self.current_leader.write().unwrap() = None;
 
         // Increment term
         let mut term = self.current_term.write().unwrap();
@@ -266,7 +622,25 @@ impl RaftNode {
 
         // Vote for self
         *self.voted_for.write().unwrap() = Some(self.node_id);
-        let mut votes_received = 1;
+        if let Err(e) = self.save_hard_state() {
+            eprintln!("Node {} can't stand for election: {}", self.node_id, e);
+            return;
+        }
+        let votes_received = {
+            let mut votes = self.votes_received.lock().unwrap();
+            votes.clear();
+            votes.insert(self.node_id);
+            votes.len()
+        };
+
+        // The election timeout runs from here
+        *self.last_heartbeat.lock().unwrap() = Instant::now();
+
+        // Check if we won (majority), as the only node
+        if votes_received >= self.quorum() {
+            self.become_leader();
+            return;
+        }
 
         // Get last log info
         let log = self.log.read().unwrap();
@@ -275,27 +649,23 @@ impl RaftNode {
         drop(log);
 
         // Request votes from all other nodes
-        let nodes = self.cluster_nodes.read().unwrap().clone();
-        let total_nodes = nodes.len();
-
-        for node_id in nodes {
-            if node_id == self.node_id {
-                continue;
-            }
+        let request = RaftMessage::RequestVote {
+            term: new_term,
+            candidate_id: self.node_id,
+            last_log_index,
+            last_log_term,
+        };
 
+        for node_id in self.peers() {
             let node = self.clone_for_async();
+            let request = request.clone();
             tokio::spawn(async move {
-                // Send vote request
-                // In real implementation, would use P2P network
-                // For now, simplified
+                // A node that doesn't answer in time doesn't vote
+                if let Ok(RaftMessage::VoteResponse { term, vote_granted }) = node.send_rpc(node_id, &request).await {
+                    node.handle_vote_response(node_id, term, vote_granted);
+                }
             });
         }
-
-        // Check if we won (majority)
-        let quorum = (total_nodes / 2) + 1;
-        if votes_received >= quorum {
-            self.become_leader();
-        }
     }
 
     /// Become the leader
@@ -307,41 +677,69 @@ impl RaftNode {
 
         // Initialize next_index and match_index
         let log_len = self.log.read().unwrap().len() as LogIndex;
-        let nodes = self.cluster_nodes.read().unwrap().clone();
+        let nodes = self.peers();
 
         let mut next_index = self.next_index.write().unwrap();
         let mut match_index = self.match_index.write().unwrap();
 
         for node_id in nodes {
-            if node_id != self.node_id {
-                next_index.insert(node_id, log_len + 1);
-                match_index.insert(node_id, 0);
-            }
+            next_index.insert(node_id, log_len + 1);
+            match_index.insert(node_id, 0);
         }
     }
 
-    /// Send heartbeats to all followers
-    async fn send_heartbeats(&self) {
-        let nodes = self.cluster_nodes.read().unwrap().clone();
-        let current_term = self.get_current_term();
+    /// Follow a newer term learned of from another node
+    fn step_down(&self, term: Term) {
+        let mut current_term = self.current_term.write().unwrap();
+        if term > *current_term {
+            *current_term = term;
+            *self.voted_for.write().unwrap() = None;
+            *self.state.write().unwrap() = RaftState::Follower;
+            drop(current_term);
 
-        for node_id in nodes {
-            if node_id == self.node_id {
-                continue;
+            if let Err(e) = self.save_hard_state() {
+                eprintln!("Node {} failed to save its term: {}", self.node_id, e);
             }
+        }
+    }
 
-            // Send empty AppendEntries (heartbeat)
-            let msg = RaftMessage::AppendEntries {
-                term: current_term,
-                leader_id: self.node_id,
-                prev_log_index: 0,
-                prev_log_term: 0,
-                entries: vec![],
-                leader_commit: *self.commit_index.read().unwrap(),
-            };
+    /// Send AppendEntries to all followers
+    ///
+    /// Each is sent the entries from its `next_index` on, which are none when
+    /// it's caught up, so the message is just a heartbeat.
+    fn send_heartbeats(&self) {
+        for node_id in self.peers() {
+            let request = self.append_entries_request(node_id);
+            let node = self.clone_for_async();
+            tokio::spawn(async move {
+                // A follower that doesn't answer in time is sent the same
+                // entries again with the next heartbeat
+                if let Ok(RaftMessage::AppendEntriesResponse { term, success, match_index }) = node.send_rpc(node_id, &request).await {
+                    node.handle_append_entries_response(node_id, term, success, match_index);
+                }
+            });
+        }
+    }
+
+    /// The AppendEntries for a follower, with the entries from its `next_index` on
+    fn append_entries_request(&self, node_id: NodeId) -> RaftMessage {
+        let next_index = self.next_index.read().unwrap().get(&node_id).copied().unwrap_or(1);
+        let leader_commit = *self.commit_index.read().unwrap();
+
+        let log = self.log.read().unwrap();
+        let prev_log_index = next_index.saturating_sub(1).min(log.len() as LogIndex);
+        let prev_log_term = match prev_log_index {
+            0 => 0,
+            index => log[index as usize - 1].term,
+        };
 
-            // In production, would send via P2P network
-            // For now, simplified
+        RaftMessage::AppendEntries {
+            term: self.get_current_term(),
+            leader_id: self.node_id,
+            prev_log_index,
+            prev_log_term,
+            entries: log[prev_log_index as usize..].to_vec(),
+            leader_commit,
         }
     }
 
@@ -361,7 +759,14 @@ impl RaftNode {
             command,
         };
 
+        if let Some(storage) = &self.storage {
+            storage.append(std::slice::from_ref(&entry))?;
+        }
         log.push(entry);
+        drop(log);
+
+        // A leader on its own commits straight away
+        self.advance_commit_index();
 
         Ok(index)
     }
@@ -394,7 +799,8 @@ impl RaftNode {
         let mut current_term = self.current_term.write().unwrap();
 
         // Update term if we're behind
-        if term > *current_term {
+        let behind = term > *current_term;
+        if behind {
             *current_term = term;
             *self.voted_for.write().unwrap() = None;
             *self.state.write().unwrap() = RaftState::Follower;
@@ -409,6 +815,7 @@ impl RaftNode {
             (voted_for.is_none() || *voted_for == Some(candidate_id));
         drop(voted_for);
 
+        let mut vote_granted = false;
         if can_vote {
             // Check if candidate's log is at least as up-to-date
             let log = self.log.read().unwrap();
@@ -422,42 +829,57 @@ impl RaftNode {
             if log_ok {
                 *self.voted_for.write().unwrap() = Some(candidate_id);
 
-                return Some(RaftMessage::VoteResponse {
-                    term: term_value,
-                    vote_granted: true,
-                });
+                // Give the candidate time to win before standing ourselves
+                *self.last_heartbeat.lock().unwrap() = Instant::now();
+                vote_granted = true;
+            }
+        }
+
+        // A vote that could be forgotten in a restart might be cast twice
+        if behind || vote_granted {
+            if let Err(e) = self.save_hard_state() {
+                eprintln!("Node {} failed to save its vote: {}", self.node_id, e);
+                vote_granted = false;
             }
         }
 
         Some(RaftMessage::VoteResponse {
             term: term_value,
-            vote_granted: false,
+            vote_granted,
         })
     }
 
     /// Handle VoteResponse
-    fn handle_vote_response(&self, _sender: NodeId, term: Term, vote_granted: bool) {
-        if self.get_state() != RaftState::Candidate {
+    fn handle_vote_response(&self, sender: NodeId, term: Term, vote_granted: bool) {
+        if term > self.get_current_term() {
+            self.step_down(term);
             return;
         }
 
-        if term > self.get_current_term() {
-            *self.current_term.write().unwrap() = term;
-            *self.state.write().unwrap() = RaftState::Follower;
+        // Only votes in the election still running count
+        if !vote_granted || self.get_state() != RaftState::Candidate || term != self.get_current_term() {
+            return;
         }
 
-        if vote_granted {
-            // Count votes and check for majority
-            // Simplified for now
+        let votes_received = {
+            let mut votes = self.votes_received.lock().unwrap();
+            votes.insert(sender);
+            votes.len()
+        };
+
+        // Check if we won (majority); the first vote to make one decides it
+        if votes_received == self.quorum() {
+            self.become_leader();
         }
     }
 
     /// Handle AppendEntries RPC
-    fn handle_append_entries(&self, term: Term, leader_id: NodeId, _prev_log_index: LogIndex, _prev_log_term: Term, entries: Vec<LogEntry>, leader_commit: LogIndex) -> Option<RaftMessage> {
+    fn handle_append_entries(&self, term: Term, leader_id: NodeId, prev_log_index: LogIndex, prev_log_term: Term, entries: Vec<LogEntry>, leader_commit: LogIndex) -> Option<RaftMessage> {
         let mut current_term = self.current_term.write().unwrap();
 
         // Update term if behind
-        if term > *current_term {
+        let behind = term > *current_term;
+        if behind {
             *current_term = term;
             *self.voted_for.write().unwrap() = None;
             *self.state.write().unwrap() = RaftState::Follower;
@@ -466,6 +888,12 @@ impl RaftNode {
         let term_value = *current_term;
         drop(current_term);
 
+        if behind {
+            if let Err(e) = self.save_hard_state() {
+                eprintln!("Node {} failed to save its term: {}", self.node_id, e);
+            }
+        }
+
         // Reject if term is old
         if term < term_value {
             return Some(RaftMessage::AppendEntriesResponse {
@@ -475,75 +903,129 @@ impl RaftNode {
             });
         }
 
-        // Valid heartbeat from leader
+        // Valid heartbeat from leader; a candidate in the same term has lost
+        *self.state.write().unwrap() = RaftState::Follower;
         *self.current_leader.write().unwrap() = Some(leader_id);
         *self.last_heartbeat.lock().unwrap() = Instant::now();
 
-        // Append entries
-        if !entries.is_empty() {
-            let mut log = self.log.write().unwrap();
-            log.extend(entries);
+        // Reject entries that don't follow on from our log; the leader
+        // retries from further back
+        let mut log = self.log.write().unwrap();
+        let consistent = prev_log_index == 0 ||
+            log.get(prev_log_index as usize - 1).is_some_and(|e| e.term == prev_log_term);
+        if !consistent {
+            return Some(RaftMessage::AppendEntriesResponse {
+                term: term_value,
+                success: false,
+                match_index: 0,
+            });
         }
 
+        // Append entries. The leader resends entries it hasn't heard were
+        // replicated, so those we have are skipped; the first that conflicts
+        // replaces it and everything after it.
+        let last_new_index = prev_log_index + entries.len() as LogIndex;
+        let first_new = entries.iter().position(|entry| {
+            log.get(entry.index as usize - 1).is_none_or(|existing| existing.term != entry.term)
+        });
+        if let Some(first_new) = first_new {
+            let position = entries[first_new].index as usize - 1;
+            let new_entries = &entries[first_new..];
+
+            let saved = match &self.storage {
+                Some(storage) if position < log.len() => {
+                    let mut kept = log[..position].to_vec();
+                    kept.extend_from_slice(new_entries);
+                    storage.rewrite(&kept)
+                }
+                Some(storage) => storage.append(new_entries),
+                None => Ok(()),
+            };
+            if let Err(e) = saved {
+                eprintln!("Node {} failed to save log entries: {}", self.node_id, e);
+                return Some(RaftMessage::AppendEntriesResponse {
+                    term: term_value,
+                    success: false,
+                    match_index: 0,
+                });
+            }
+
+            log.truncate(position);
+            log.extend_from_slice(new_entries);
+        }
+        drop(log);
+
         // Update commit index
-        if leader_commit > *self.commit_index.read().unwrap() {
-            let log_len = self.log.read().unwrap().len() as LogIndex;
-            let new_commit = std::cmp::min(leader_commit, log_len);
-            *self.commit_index.write().unwrap() = new_commit;
+        let mut commit_index = self.commit_index.write().unwrap();
+        if leader_commit > *commit_index {
+            *commit_index = std::cmp::min(leader_commit, last_new_index).max(*commit_index);
+            self.commit_notify.notify_one();
         }
+        drop(commit_index);
 
         Some(RaftMessage::AppendEntriesResponse {
             term: term_value,
             success: true,
-            match_index: self.log.read().unwrap().len() as LogIndex,
+            match_index: last_new_index,
         })
     }
 
     /// Handle AppendEntriesResponse
     fn handle_append_entries_response(&self, sender: NodeId, term: Term, success: bool, match_index: LogIndex) {
-        if !self.is_leader() {
+        if term > self.get_current_term() {
+            self.step_down(term);
             return;
         }
 
-        if term > self.get_current_term() {
-            *self.current_term.write().unwrap() = term;
-            *self.state.write().unwrap() = RaftState::Follower;
+        if !self.is_leader() {
             return;
         }
 
         if success {
-            // Update match_index and next_index
+            // Update match_index and next_index; a late response mustn't
+            // move them back
             let mut match_idx = self.match_index.write().unwrap();
-            match_idx.insert(sender, match_index);
+            let matched = match_idx.entry(sender).or_insert(0);
+            *matched = (*matched).max(match_index);
+            let matched = *matched;
+            drop(match_idx);
 
-            let mut next_idx = self.next_index.write().unwrap();
-            next_idx.insert(sender, match_index + 1);
+            self.next_index.write().unwrap().insert(sender, matched + 1);
 
             // Check if we can advance commit_index
             self.advance_commit_index();
+        } else {
+            // The follower's log doesn't match ours before next_index
+            let mut next_idx = self.next_index.write().unwrap();
+            let next = next_idx.entry(sender).or_insert(1);
+            *next = next.saturating_sub(1).max(1);
         }
     }
 
     /// Advance commit index if majority replicated
+    ///
+    /// Only an entry from the current term is committed by counting its
+    /// replicas; earlier entries are committed along with it.
     fn advance_commit_index(&self) {
+        let quorum = self.quorum();
+        let current_term = self.get_current_term();
+        let commit_index = *self.commit_index.read().unwrap();
+
+        let log = self.log.read().unwrap();
         let match_index = self.match_index.read().unwrap();
-        let nodes = self.cluster_nodes.read().unwrap();
-        let quorum = (nodes.len() / 2) + 1;
 
         // Find highest index replicated on majority
-        let log_len = self.log.read().unwrap().len() as LogIndex;
-
-        for n in ((*self.commit_index.read().unwrap() + 1)..=log_len).rev() {
-            let mut count = 1; // Count self
-
-            for index in match_index.values() {
-                if *index >= n {
-                    count += 1;
-                }
+        for n in ((commit_index + 1)..=log.len() as LogIndex).rev() {
+            if log[n as usize - 1].term != current_term {
+                break;
             }
 
+            let count = 1 + match_index.values().filter(|&&index| index >= n).count(); // Count self
+
             if count >= quorum {
-                *self.commit_index.write().unwrap() = n;
+                let mut commit_index = self.commit_index.write().unwrap();
+                *commit_index = (*commit_index).max(n);
+                self.commit_notify.notify_one();
                 break;
             }
         }
@@ -570,6 +1052,7 @@ impl RaftNode {
             state: Arc::clone(&self.state),
             current_term: Arc::clone(&self.current_term),
             voted_for: Arc::clone(&self.voted_for),
+            votes_received: Arc::clone(&self.votes_received),
             log: Arc::clone(&self.log),
             commit_index: Arc::clone(&self.commit_index),
             last_applied: Arc::clone(&self.last_applied),
@@ -578,6 +1061,9 @@ impl RaftNode {
             current_leader: Arc::clone(&self.current_leader),
             cluster_nodes: Arc::clone(&self.cluster_nodes),
             last_heartbeat: Arc::clone(&self.last_heartbeat),
+            commit_notify: Arc::clone(&self.commit_notify),
+            state_machine: self.state_machine.clone(),
+            storage: self.storage.clone(),
             p2p_network: Arc::clone(&self.p2p_network),
         })
     }
@@ -659,4 +1145,188 @@ mod tests {
         assert_eq!(stats.current_term, 0);
         assert_eq!(stats.log_length, 0);
     }
+    /// A log whose entries have these terms
+    fn log_with_terms(terms: &[Term]) -> Vec<LogEntry> {
+        terms
+            .iter()
+            .enumerate()
+            .map(|(i, &term)| LogEntry {
+                term,
+                index: i as LogIndex + 1,
+                command: format!("entry {} of term {}", i + 1, term).into_bytes(),
+            })
+            .collect()
+    }
+
+    fn terms(node: &RaftNode) -> Vec<Term> {
+        node.log.read().unwrap().iter().map(|e| e.term).collect()
+    }
+
+    #[test]
+    fn test_conflicting_logs_converge() {
+        // The leader and followers (a), (b), (e) and (f) of figure 7 in the
+        // Raft paper: missing entries, and uncommitted ones from old terms
+        let leader = create_test_node(1);
+        *leader.log.write().unwrap() = log_with_terms(&[1, 1, 1, 4, 4, 5, 5, 6, 6, 6]);
+        *leader.current_term.write().unwrap() = 8;
+        for node_id in 2..=5 {
+            leader.add_cluster_node(node_id);
+        }
+        leader.become_leader();
+        assert_eq!(leader.append_entry(b"SET x = 8".to_vec()), Ok(11));
+
+        let followers = [
+            (2, vec![1, 1, 1, 4, 4, 5, 5, 6, 6]),
+            (3, vec![1, 1, 1, 4]),
+            (4, vec![1, 1, 1, 4, 4, 4, 4]),
+            (5, vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 3, 3]),
+        ];
+        for (node_id, follower_terms) in followers {
+            let follower = create_test_node(node_id);
+            *follower.log.write().unwrap() = log_with_terms(&follower_terms);
+            *follower.current_term.write().unwrap() = *follower_terms.last().unwrap();
+
+            // Each rejection backs next_index up by one
+            for _ in 0..=11 {
+                let request = leader.append_entries_request(node_id);
+                let response = follower.handle_message(1, request).unwrap();
+                leader.handle_message(node_id, response);
+            }
+
+            assert_eq!(terms(&follower), terms(&leader), "follower {}", node_id);
+            assert_eq!(follower.get_current_leader(), Some(1));
+        }
+
+        // The term 8 entry is on every node, so it's committed
+        assert_eq!(leader.get_statistics().commit_index, 11);
+    }
+
+    /// Records the commands applied to it, refusing the ones it's told to
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Vec<u8>>>, Mutex<HashSet<Vec<u8>>>);
+
+    impl StateMachine for Recorder {
+        fn apply(&self, command: &[u8]) -> Result<(), String> {
+            if self.1.lock().unwrap().contains(command) {
+                return Err("refused".to_string());
+            }
+            self.0.lock().unwrap().push(command.to_vec());
+            Ok(())
+        }
+
+        fn is_durable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_committed_entries_are_applied_once() {
+        let recorder = Arc::new(Recorder::default());
+        let follower = create_test_node(2).with_state_machine(recorder.clone());
+        let entries = log_with_terms(&[1, 1, 1]);
+        let append = |leader_commit| RaftMessage::AppendEntries {
+            term: 1,
+            leader_id: 1,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: entries.clone(),
+            leader_commit,
+        };
+
+        // Only what's committed is applied
+        follower.handle_message(1, append(2));
+        follower.apply_committed();
+        assert_eq!(recorder.0.lock().unwrap().len(), 2);
+
+        // Resent entries are neither appended nor applied again
+        follower.handle_message(1, append(3));
+        follower.apply_committed();
+        follower.apply_committed();
+        let commands: Vec<Vec<u8>> = entries.iter().map(|e| e.command.clone()).collect();
+        assert_eq!(*recorder.0.lock().unwrap(), commands);
+        assert_eq!(follower.get_statistics().log_length, 3);
+        assert_eq!(follower.get_statistics().last_applied, 3);
+    }
+
+    #[test]
+    fn test_restart_preserves_term_vote_and_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vote = |candidate_id| RaftMessage::RequestVote {
+            term: 5,
+            candidate_id,
+            last_log_index: 2,
+            last_log_term: 5,
+        };
+        let granted = |response: Option<RaftMessage>| matches!(response, Some(RaftMessage::VoteResponse { vote_granted: true, .. }));
+
+        let node = create_test_node(1).with_storage(dir.path()).unwrap();
+        assert!(granted(node.handle_message(2, vote(2))));
+        node.handle_message(2, RaftMessage::AppendEntries {
+            term: 5,
+            leader_id: 2,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: log_with_terms(&[5, 5]),
+            leader_commit: 0,
+        });
+        drop(node);
+
+        // Already voted in term 5, so candidate 3 is refused
+        let node = create_test_node(1).with_storage(dir.path()).unwrap();
+        assert_eq!(node.get_current_term(), 5);
+        assert_eq!(terms(&node), vec![5, 5]);
+        assert!(!granted(node.handle_message(3, vote(3))));
+
+        // A conflicting entry replaces the saved one
+        node.handle_message(3, RaftMessage::AppendEntries {
+            term: 6,
+            leader_id: 3,
+            prev_log_index: 1,
+            prev_log_term: 5,
+            entries: log_with_terms(&[5, 6])[1..].to_vec(),
+            leader_commit: 0,
+        });
+        drop(node);
+
+        let node = create_test_node(1).with_storage(dir.path()).unwrap();
+        assert_eq!(node.get_current_term(), 6);
+        assert_eq!(terms(&node), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_applying_stops_at_a_refused_entry_and_resumes_after_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let entries = log_with_terms(&[1, 1, 1]);
+        let recorder = Arc::new(Recorder::default());
+        recorder.1.lock().unwrap().insert(entries[1].command.clone());
+
+        let node = create_test_node(2).with_storage(dir.path()).unwrap().with_state_machine(recorder.clone());
+        node.handle_message(1, RaftMessage::AppendEntries {
+            term: 1,
+            leader_id: 1,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: entries.clone(),
+            leader_commit: 3,
+        });
+        node.apply_committed();
+        assert_eq!(node.get_statistics().last_applied, 1);
+        drop(node);
+
+        // Entry 1 was applied and saved, so only the rest are applied now
+        recorder.1.lock().unwrap().clear();
+        let node = create_test_node(2).with_storage(dir.path()).unwrap().with_state_machine(recorder.clone());
+        assert_eq!(node.get_statistics().last_applied, 1);
+        node.handle_message(1, RaftMessage::AppendEntries {
+            term: 1,
+            leader_id: 1,
+            prev_log_index: 3,
+            prev_log_term: 1,
+            entries: Vec::new(),
+            leader_commit: 3,
+        });
+        node.apply_committed();
+        let commands: Vec<Vec<u8>> = entries.iter().map(|e| e.command.clone()).collect();
+        assert_eq!(*recorder.0.lock().unwrap(), commands);
+    }
 }
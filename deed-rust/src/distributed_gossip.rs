@@ -0,0 +1,549 @@
+//! Gossip-based cluster membership
+//!
+//! Every round a node bumps its own heartbeat and swaps its view of the
+//! cluster with a few random peers; both sides merge what they hear. A new
+//! node only needs a seed address: its first exchange brings back the
+//! seed's whole view.
+//!
+//! Each view entry carries the node's incarnation and heartbeat. A higher
+//! incarnation replaces an entry outright, which is how a restarted node
+//! comes back. Within an incarnation a higher heartbeat wins, `Dead` is
+//! final, and `Suspect` spreads until a higher heartbeat clears it. A node
+//! whose heartbeat hasn't moved for `suspect_after` is suspected, and after
+//! `dead_after` declared dead. Only a node's own timers declare others dead:
+//! a death heard from a peer counts as suspicion. A node that hears itself
+//! suspected or declared dead refutes it by taking a higher incarnation.
+//! Dead members are forgotten after `forget_after`.
+//!
+//! Gossip is only taken from peers whose certificates the network verified,
+//! unless [`Membership::with_unverified_peers`]. A seed's reply is taken on
+//! the strength of its certificate alone, since its node isn't known yet.
+//!
+//! Joins and deaths are passed on to [`MembershipListener`]s, so the
+//! topology, shard ring and partition detector follow the membership.
+
+use crate::distributed_p2p::{MessageKind, MessageType, P2PNetwork};
+use crate::distributed_partition::PartitionManager;
+use crate::distributed_shard::ShardManager;
+use crate::distributed_topology::{NodeAddress, NodeId, NodeInfo, SmallWorldTopology};
+use crate::shutdown::{BackgroundTask, ShutdownSignal};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// Shards a node that joined through gossip is taken to have room for
+pub const GOSSIP_NODE_CAPACITY: usize = 100;
+
+/// What a node's view says of a member, from least to most final
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MemberState {
+    Alive,
+    /// Its heartbeat stopped moving; it may yet be heard from
+    Suspect,
+    /// Gone for this incarnation
+    Dead,
+}
+
+/// A node of the cluster, as a view knows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub id: NodeId,
+    pub address: NodeAddress,
+    /// Raised each time the node restarts, or refutes its suspicion
+    pub incarnation: u64,
+    /// Raised by the node every gossip round
+    pub heartbeat: u64,
+    pub state: MemberState,
+}
+
+/// A change in membership, as this node learned of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipChange {
+    /// A node joined, or came back under a new incarnation or address
+    Joined(Member),
+    Suspected(NodeId),
+    /// A suspected node was heard from again
+    Recovered(NodeId),
+    Died(NodeId),
+}
+
+/// Kept in step with membership as it changes
+///
+/// Called with no membership lock held, in the order changes were learned.
+pub trait MembershipListener: Send + Sync {
+    fn membership_changed(&self, change: &MembershipChange);
+}
+
+impl MembershipListener for SmallWorldTopology {
+    fn membership_changed(&self, change: &MembershipChange) {
+        match change {
+            MembershipChange::Joined(member) => {
+                self.add_node(NodeInfo::new(member.id, member.address.clone(), GOSSIP_NODE_CAPACITY));
+                self.build_topology();
+            }
+            MembershipChange::Died(node_id) => {
+                self.remove_node(*node_id);
+                self.build_topology();
+            }
+            MembershipChange::Suspected(_) | MembershipChange::Recovered(_) => {}
+        }
+    }
+}
+
+impl MembershipListener for ShardManager {
+    fn membership_changed(&self, change: &MembershipChange) {
+        match change {
+            MembershipChange::Joined(member) => self.add_node(member.id),
+            MembershipChange::Died(node_id) => {
+                self.remove_node(*node_id);
+            }
+            MembershipChange::Suspected(_) | MembershipChange::Recovered(_) => {}
+        }
+    }
+}
+
+impl MembershipListener for PartitionManager {
+    fn membership_changed(&self, change: &MembershipChange) {
+        match change {
+            MembershipChange::Joined(member) => self.add_node(member.id),
+            MembershipChange::Recovered(node_id) => self.record_heartbeat(*node_id),
+            MembershipChange::Suspected(node_id) => self.record_failure(*node_id),
+            MembershipChange::Died(node_id) => self.mark_unreachable(*node_id),
+        }
+    }
+}
+
+/// How often nodes gossip, with how many peers, and when silence counts
+/// as failure
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// Time between rounds
+    pub interval: Duration,
+    /// Peers gossiped with each round
+    pub fanout: usize,
+    /// Silence after which a node is suspected
+    pub suspect_after: Duration,
+    /// Silence after which a node is declared dead
+    pub dead_after: Duration,
+    /// Silence after which a dead node is forgotten; longer than
+    /// `dead_after`, so every view has declared it dead by then
+    pub forget_after: Duration,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            fanout: 3,
+            suspect_after: Duration::from_secs(5),
+            dead_after: Duration::from_secs(15),
+            forget_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A view entry, and when its heartbeat or incarnation last moved
+struct Entry {
+    member: Member,
+    progressed: Instant,
+}
+
+/// This node's membership view, kept by gossip over its P2P network
+///
+/// The network's peers follow the view: joined nodes are added, dead ones
+/// removed.
+pub struct Membership {
+    local_id: NodeId,
+    network: Arc<P2PNetwork>,
+    config: GossipConfig,
+    view: Mutex<HashMap<NodeId, Entry>>,
+    /// Where to gossip while no peer is known
+    seeds: Vec<NodeAddress>,
+    listeners: Vec<Arc<dyn MembershipListener>>,
+    /// Take gossip from peers whose identity the network didn't verify
+    unverified_peers: bool,
+}
+
+impl Membership {
+    /// Membership of the node `local_id`, reachable at `address`
+    pub fn new(local_id: NodeId, address: NodeAddress, network: Arc<P2PNetwork>, config: GossipConfig) -> Self {
+        let local = Member {
+            id: local_id,
+            address,
+            incarnation: 0,
+            heartbeat: 0,
+            state: MemberState::Alive,
+        };
+        let entry = Entry {
+            member: local,
+            progressed: Instant::now(),
+        };
+
+        Self {
+            local_id,
+            network,
+            config,
+            view: Mutex::new(HashMap::from([(local_id, entry)])),
+            seeds: Vec::new(),
+            listeners: Vec::new(),
+            unverified_peers: false,
+        }
+    }
+
+    /// Start from incarnation `incarnation`, as a node restarting should,
+    /// so the cluster takes it for a new life rather than a dead one
+    pub fn with_incarnation(self, incarnation: u64) -> Self {
+        self.view.lock().unwrap().get_mut(&self.local_id).unwrap().member.incarnation = incarnation;
+        self
+    }
+
+    /// Join the cluster through a node at `seed`
+    pub fn with_seed(mut self, seed: NodeAddress) -> Self {
+        self.seeds.push(seed);
+        self
+    }
+
+    /// Take gossip from any peer, not only ones whose certificate matched
+    /// their node's, e.g. for local testing over plaintext
+    pub fn with_unverified_peers(mut self) -> Self {
+        self.unverified_peers = true;
+        self
+    }
+
+    /// Pass membership changes on to `listener`
+    ///
+    /// It hears of this node joining when gossip starts.
+    pub fn with_listener(mut self, listener: Arc<dyn MembershipListener>) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Every member of the view, dead ones included, by id
+    pub fn members(&self) -> Vec<Member> {
+        let view = self.view.lock().unwrap();
+        let mut members: Vec<Member> = view.values().map(|entry| entry.member.clone()).collect();
+        members.sort_by_key(|member| member.id);
+        members
+    }
+
+    /// What the view says of a node
+    pub fn member(&self, node_id: NodeId) -> Option<Member> {
+        self.view.lock().unwrap().get(&node_id).map(|entry| entry.member.clone())
+    }
+
+    /// Ids of the members not declared dead, this node's included
+    pub fn live_members(&self) -> Vec<NodeId> {
+        let mut live: Vec<NodeId> = self
+            .view
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.member.state != MemberState::Dead)
+            .map(|entry| entry.member.id)
+            .collect();
+        live.sort_unstable();
+        live
+    }
+
+    /// Answer gossip and start gossiping every interval, until `shutdown`
+    /// is cancelled
+    ///
+    /// Once cancelled, gossip from other nodes is refused, so they declare
+    /// this one dead.
+    pub fn start(self: &Arc<Self>, shutdown: &ShutdownSignal) -> BackgroundTask {
+        // The network holds the handler, so it mustn't keep the membership alive
+        let membership = Arc::downgrade(self);
+        let stopped = shutdown.clone();
+        self.network.register_handler(MessageKind::Gossip, move |msg| {
+            let (MessageType::Gossip { members }, Some(membership)) = (&msg.message_type, membership.upgrade()) else {
+                return None;
+            };
+            let reply = if stopped.is_cancelled() {
+                Err("Gossip stopped".to_string())
+            } else if !msg.sender_verified && !membership.unverified_peers {
+                Err(format!("Permission denied: node {} isn't verified by its certificate", msg.sender_id))
+            } else {
+                membership.receive(members).and_then(|()| membership.encoded_view())
+            };
+            let reply = reply.map_or_else(|message| MessageType::Error { message }, |members| MessageType::Gossip { members });
+            Some(msg.reply(reply))
+        });
+
+        if let Some(local) = self.member(self.local_id) {
+            self.notify(vec![MembershipChange::Joined(local)]);
+        }
+
+        let membership = Arc::clone(self);
+        BackgroundTask::spawn("gossip", shutdown, |shutdown| async move {
+            let mut interval = tokio::time::interval(membership.config.interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => membership.gossip_round().await,
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        })
+    }
+
+    /// Run one round: bump our heartbeat, mark silent nodes, and swap views
+    /// with up to `fanout` live peers (or the seeds, while none is known)
+    pub async fn gossip_round(self: &Arc<Self>) {
+        let changes = {
+            let mut view = self.view.lock().unwrap();
+            let local = view.get_mut(&self.local_id).unwrap();
+            local.member.heartbeat += 1;
+            local.progressed = Instant::now();
+            self.expire(&mut view, Instant::now())
+        };
+        self.notify(changes);
+
+        let Ok(members) = self.encoded_view() else {
+            return;
+        };
+        let mut exchanges = JoinSet::new();
+        let peers = self.pick_peers();
+        if peers.is_empty() {
+            for seed in self.seeds.clone() {
+                let network = Arc::clone(&self.network);
+                let members = members.clone();
+                exchanges.spawn(async move { network.send_to_address(&seed, MessageType::Gossip { members }).await });
+            }
+        } else {
+            for peer in peers {
+                let network = Arc::clone(&self.network);
+                let members = members.clone();
+                exchanges.spawn(async move { network.send_message(peer, MessageType::Gossip { members }).await });
+            }
+        }
+
+        // A peer that doesn't answer in time is left to the timeouts
+        let trusted = self.unverified_peers || self.network.verifies_nodes();
+        let _ = tokio::time::timeout(self.config.interval, async {
+            while let Some(exchange) = exchanges.join_next().await {
+                if let (Ok(Ok(reply)), true) = (exchange, trusted) {
+                    if let MessageType::Gossip { members } = &reply.message_type {
+                        let _ = self.receive(members);
+                    }
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Up to `fanout` members other than us not declared dead, at random
+    fn pick_peers(&self) -> Vec<NodeId> {
+        let mut peers: Vec<NodeId> = self
+            .view
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.member.id != self.local_id && entry.member.state != MemberState::Dead)
+            .map(|entry| entry.member.id)
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(self.config.fanout);
+        peers
+    }
+
+    fn encoded_view(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(&self.members()).map_err(|e| format!("Failed to encode membership: {}", e))
+    }
+
+    /// Merge a view heard from a peer into ours
+    fn receive(&self, members: &[u8]) -> Result<(), String> {
+        let heard: Vec<Member> =
+            bincode::deserialize(members).map_err(|e| format!("Malformed membership: {}", e))?;
+        let changes = self.merge(heard);
+        self.notify(changes);
+        Ok(())
+    }
+
+    /// Merge heard members into the view, returning what changed
+    fn merge(&self, heard: Vec<Member>) -> Vec<MembershipChange> {
+        let now = Instant::now();
+        let mut view = self.view.lock().unwrap();
+        let mut changes = Vec::new();
+
+        for member in heard {
+            if member.id == self.local_id {
+                // Refute suspicion of ourselves with a new incarnation
+                let local = &mut view.get_mut(&self.local_id).unwrap().member;
+                if member.state != MemberState::Alive && member.incarnation >= local.incarnation {
+                    local.incarnation = member.incarnation + 1;
+                }
+                continue;
+            }
+
+            // Our own timers decide deaths; unknown dead nodes aren't learned,
+            // so forgotten ones don't come back
+            let Some(entry) = view.get_mut(&member.id) else {
+                if member.state != MemberState::Dead {
+                    changes.push(MembershipChange::Joined(member.clone()));
+                    view.insert(member.id, Entry { member, progressed: now });
+                }
+                continue;
+            };
+            let before = entry.member.clone();
+            let mut member = member;
+            if member.state == MemberState::Dead {
+                if before.state == MemberState::Dead {
+                    continue;
+                }
+                member.state = MemberState::Suspect;
+            }
+            if member.incarnation > before.incarnation {
+                entry.member = member;
+                entry.progressed = now;
+            } else if member.incarnation < before.incarnation || before.state == MemberState::Dead {
+                continue;
+            } else if member.heartbeat > before.heartbeat {
+                entry.member.heartbeat = member.heartbeat;
+                entry.member.state = member.state;
+                entry.progressed = now;
+            } else if member.heartbeat < before.heartbeat || member.state <= before.state {
+                continue;
+            } else {
+                entry.member.state = member.state;
+            }
+            changes.extend(transition(&before, &entry.member));
+        }
+        changes
+    }
+
+    /// Suspect, then declare dead, members whose heartbeat stopped moving,
+    /// and forget the ones dead for long enough
+    fn expire(&self, view: &mut HashMap<NodeId, Entry>, now: Instant) -> Vec<MembershipChange> {
+        view.retain(|_, entry| {
+            entry.member.state != MemberState::Dead || now.duration_since(entry.progressed) < self.config.forget_after
+        });
+        let mut changes = Vec::new();
+        for entry in view.values_mut().filter(|entry| entry.member.id != self.local_id) {
+            let silent = now.duration_since(entry.progressed);
+            let state = if silent >= self.config.dead_after {
+                MemberState::Dead
+            } else if silent >= self.config.suspect_after {
+                MemberState::Suspect
+            } else {
+                continue;
+            };
+            if state > entry.member.state {
+                let before = entry.member.clone();
+                entry.member.state = state;
+                changes.extend(transition(&before, &entry.member));
+            }
+        }
+        changes
+    }
+
+    /// Keep the network's peers and the listeners in step with changes
+    fn notify(&self, changes: Vec<MembershipChange>) {
+        for change in changes {
+            match &change {
+                MembershipChange::Joined(member) if member.id != self.local_id => {
+                    self.network.add_peer(member.id, member.address.clone());
+                }
+                MembershipChange::Died(node_id) => self.network.remove_peer(*node_id),
+                _ => {}
+            }
+            for listener in &self.listeners {
+                listener.membership_changed(&change);
+            }
+        }
+    }
+}
+
+/// The change a member's entry going from `before` to `after` amounts to
+fn transition(before: &Member, after: &Member) -> Option<MembershipChange> {
+    let reborn = after.incarnation > before.incarnation
+        && (before.state == MemberState::Dead || after.address != before.address);
+    match (before.state, after.state) {
+        (_, MemberState::Dead) if before.state != MemberState::Dead => Some(MembershipChange::Died(after.id)),
+        (_, MemberState::Dead) => None,
+        _ if reborn => Some(MembershipChange::Joined(after.clone())),
+        (MemberState::Alive, MemberState::Suspect) => Some(MembershipChange::Suspected(after.id)),
+        (MemberState::Suspect, MemberState::Alive) => Some(MembershipChange::Recovered(after.id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed_p2p::P2PConfig;
+
+    fn membership(id: NodeId) -> Membership {
+        let address = NodeAddress::new("127.0.0.1".to_string(), 9000 + id as u16);
+        let network = Arc::new(P2PNetwork::new(id, address.clone(), P2PConfig::default()));
+        Membership::new(id, address, network, GossipConfig::default())
+    }
+
+    fn heard(id: NodeId, incarnation: u64, heartbeat: u64, state: MemberState) -> Member {
+        Member {
+            id,
+            address: NodeAddress::new("127.0.0.1".to_string(), 9000 + id as u16),
+            incarnation,
+            heartbeat,
+            state,
+        }
+    }
+
+    #[test]
+    fn test_merge_resolves_by_incarnation_then_heartbeat() {
+        let local = membership(1);
+
+        let joined = local.merge(vec![heard(2, 0, 5, MemberState::Alive)]);
+        assert_eq!(joined, vec![MembershipChange::Joined(heard(2, 0, 5, MemberState::Alive))]);
+
+        // Stale heartbeats change nothing; suspicion at the same one spreads
+        assert!(local.merge(vec![heard(2, 0, 4, MemberState::Dead)]).is_empty());
+        assert_eq!(local.merge(vec![heard(2, 0, 5, MemberState::Suspect)]), vec![MembershipChange::Suspected(2)]);
+        assert_eq!(local.merge(vec![heard(2, 0, 6, MemberState::Alive)]), vec![MembershipChange::Recovered(2)]);
+
+        // A death we hear of is only suspicion; our own timers declare it
+        assert_eq!(local.merge(vec![heard(2, 0, 6, MemberState::Dead)]), vec![MembershipChange::Suspected(2)]);
+        let dead_after = Instant::now() + local.config.dead_after;
+        assert_eq!(local.expire(&mut local.view.lock().unwrap(), dead_after), vec![MembershipChange::Died(2)]);
+
+        // Dead is final for the incarnation; a higher one brings the node back
+        assert!(local.merge(vec![heard(2, 0, 9, MemberState::Alive)]).is_empty());
+        assert_eq!(local.member(2).unwrap().state, MemberState::Dead);
+        let reborn = heard(2, 1, 0, MemberState::Alive);
+        assert_eq!(local.merge(vec![reborn.clone()]), vec![MembershipChange::Joined(reborn)]);
+        assert_eq!(local.live_members(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_dead_members_are_forgotten() {
+        let local = membership(1);
+        local.merge(vec![heard(2, 0, 5, MemberState::Alive), heard(3, 0, 5, MemberState::Alive)]);
+
+        let dead_after = Instant::now() + local.config.dead_after;
+        local.expire(&mut local.view.lock().unwrap(), dead_after);
+        assert_eq!(local.live_members(), vec![1]);
+        let forget_after = Instant::now() + local.config.forget_after;
+        assert!(local.expire(&mut local.view.lock().unwrap(), forget_after).is_empty());
+        assert_eq!(local.members().len(), 1);
+
+        // News of their deaths from peers that haven't forgotten them yet
+        // doesn't bring them back
+        assert!(local.merge(vec![heard(2, 0, 5, MemberState::Dead)]).is_empty());
+        assert!(local.member(2).is_none());
+    }
+
+    #[test]
+    fn test_node_refutes_its_own_suspicion() {
+        let local = membership(1).with_incarnation(3);
+
+        local.merge(vec![heard(1, 3, 0, MemberState::Suspect)]);
+        assert_eq!(local.member(1).unwrap().incarnation, 4);
+        assert_eq!(local.member(1).unwrap().state, MemberState::Alive);
+
+        // Old news of an earlier incarnation needs no answer
+        local.merge(vec![heard(1, 2, 0, MemberState::Dead)]);
+        assert_eq!(local.member(1).unwrap().incarnation, 4);
+    }
+}
@@ -13,7 +13,7 @@
 
 use prometheus::{
     Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramVec,
-    Registry, Opts, HistogramOpts, opts,
+    Registry, Opts, HistogramOpts,
 };
 use serde::{Serialize, Deserialize};
 use std::sync::Arc;
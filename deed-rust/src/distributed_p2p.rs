@@ -8,13 +8,40 @@
 //! - Message types for data replication, query routing, and health checks
 //! - Connection pooling and retry logic
 //! - Heartbeat mechanism for failure detection
-
+//!
+//! Every message is a frame: a 4-byte big-endian length followed by a
+//! bincode-encoded [`P2PMessage`]. A node answers each message it receives
+//! with one carrying the request's id in `reply_to`, so requests can share a
+//! connection and be answered out of order.
+//!
+//! The length word's top two bits are flags. With compression on, messages
+//! of at least `compression_threshold` bytes are compressed with LZ4 and
+//! flagged as such. A message larger than `max_frame_size` goes as several
+//! frames flagged as parts, numbered so that other messages' frames can go
+//! between them, and is reassembled by the receiver up to
+//! `max_message_size`.
+//! A frame announcing more than `max_frame_size` bytes is answered with an
+//! `Error` and the connection closed, before anything is allocated for it.
+//!
+//! Connections are upgraded to TLS before any frame flows, with the
+//! certificates of [`P2PConfig::tls`]; plaintext needs `insecure`. With node
+//! fingerprints configured, every message's sender must be the node whose
+//! certificate its connection presented, and its message is marked
+//! [`P2PMessage::sender_verified`].
+
+use crate::auth::Role;
+use crate::distributed_partition::Version;
 use crate::distributed_topology::{NodeId, NodeAddress};
+use crate::tls::{self, BoxedStream, TlsConfig, Transport};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use std::time::Duration;
 
 /// Message ID for tracking request/response
@@ -27,20 +54,115 @@ pub enum MessageType {
     Ping,
     /// Health check response
     Pong,
-    /// Request data for specific shard
-    ShardDataRequest { shard_id: u64 },
-    /// Response with shard data
+    /// Read, store or drop a shard's data on the receiving node
+    ShardDataRequest { shard_id: u64, op: ShardDataOp },
+    /// Response with shard data: a bincode-encoded `ShardBatch` for a
+    /// fetch, `ShardDigest` for a digest, and empty otherwise
     ShardDataResponse { shard_id: u64, data: Vec<u8> },
-    /// Execute query on remote node
-    QueryRequest { query: String },
-    /// Query result from remote node
-    QueryResponse { result: String },
+    /// Execute query on remote node, as a caller with `role` if given;
+    /// `statement` is the bincode-encoded statement to run in its place, if
+    /// the sender rewrote it, and `shards` the shards a read answers for
+    QueryRequest { query: String, statement: Option<Vec<u8>>, role: Option<Role>, shards: Option<Vec<u64>> },
+    /// Query result from remote node, a bincode-encoded `QueryResult`
+    QueryResponse { result: Vec<u8> },
     /// Notify about shard reassignment
     ShardReassignment { shard_id: u64, new_owner: NodeId },
+    /// Ask the master for replication log entries after `since`
+    ReplicationRequest { slave_id: String, since: u64 },
+    /// Bincode-encoded replication log entries
+    ReplicationEntries { entries: Vec<u8> },
+    /// Ask the master for the part of a replication snapshot from `offset`
+    SnapshotChunkRequest { slave_id: String, seq: u64, offset: u64 },
+    /// Part of a bincode-encoded replication snapshot of `total` bytes
+    SnapshotChunk { seq: u64, offset: u64, total: u64, data: Vec<u8> },
+    /// A bincode-encoded `RaftMessage`, sent or answered
+    Raft { message: Vec<u8> },
+    /// Read a key from the receiving node's replica store
+    ReplicaRead { key: String },
+    /// Store a value in the receiving node's replica store, unless it
+    /// holds a newer version
+    ReplicaWrite { key: String, value: Vec<u8>, version: Version },
+    /// A replica's value for a key and its version, or `None` if it has none
+    ReplicaValue { value: Option<Vec<u8>>, version: Version },
     /// Acknowledge message receipt
     Ack,
     /// Error response
     Error { message: String },
+    /// A node's membership view, bincode-encoded; answered with the receiver's
+    Gossip { members: Vec<u8> },
+    /// Ask a node for its topology connections
+    NeighborRequest,
+    /// A node's connections, with the latency (ms) it measured to each
+    Neighbors { neighbors: Vec<(NodeId, u32)> },
+}
+
+/// What a [`MessageType::ShardDataRequest`] asks of a shard's data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardDataOp {
+    /// Up to `limit` of the shard's entities after entity `after`, with
+    /// their outgoing edges
+    Fetch { after: Option<u64>, limit: usize },
+    /// Store a bincode-encoded `ShardBatch`
+    Store { batch: Vec<u8> },
+    /// Count and checksum the shard's data
+    Digest,
+    /// Drop the shard's data
+    Delete,
+    /// Refuse writes to the shard while it moves, or take them again
+    Fence { fenced: bool },
+}
+
+/// Kind of a [`MessageType`], without its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Ping,
+    Pong,
+    ShardDataRequest,
+    ShardDataResponse,
+    QueryRequest,
+    QueryResponse,
+    ShardReassignment,
+    ReplicationRequest,
+    ReplicationEntries,
+    SnapshotChunkRequest,
+    SnapshotChunk,
+    Raft,
+    ReplicaRead,
+    ReplicaWrite,
+    ReplicaValue,
+    Ack,
+    Error,
+    Gossip,
+    NeighborRequest,
+    Neighbors,
+}
+
+impl MessageType {
+    /// Kind of this message, which handlers are registered for
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            MessageType::Ping => MessageKind::Ping,
+            MessageType::Pong => MessageKind::Pong,
+            MessageType::ShardDataRequest { .. } => MessageKind::ShardDataRequest,
+            MessageType::ShardDataResponse { .. } => MessageKind::ShardDataResponse,
+            MessageType::QueryRequest { .. } => MessageKind::QueryRequest,
+            MessageType::QueryResponse { .. } => MessageKind::QueryResponse,
+            MessageType::ShardReassignment { .. } => MessageKind::ShardReassignment,
+            MessageType::ReplicationRequest { .. } => MessageKind::ReplicationRequest,
+            MessageType::ReplicationEntries { .. } => MessageKind::ReplicationEntries,
+            MessageType::SnapshotChunkRequest { .. } => MessageKind::SnapshotChunkRequest,
+            MessageType::SnapshotChunk { .. } => MessageKind::SnapshotChunk,
+            MessageType::Raft { .. } => MessageKind::Raft,
+            MessageType::ReplicaRead { .. } => MessageKind::ReplicaRead,
+            MessageType::ReplicaWrite { .. } => MessageKind::ReplicaWrite,
+            MessageType::ReplicaValue { .. } => MessageKind::ReplicaValue,
+            MessageType::Ack => MessageKind::Ack,
+            MessageType::Error { .. } => MessageKind::Error,
+            MessageType::Gossip { .. } => MessageKind::Gossip,
+            MessageType::NeighborRequest => MessageKind::NeighborRequest,
+            MessageType::Neighbors { .. } => MessageKind::Neighbors,
+        }
+    }
 }
 
 /// P2P message structure
@@ -51,11 +173,16 @@ pub struct P2PMessage {
     pub receiver_id: NodeId,
     pub message_type: MessageType,
     pub timestamp: u64,
+    /// Id of the message this one answers
+    pub reply_to: Option<MessageId>,
+    /// Whether the receiver checked the sender's certificate against the
+    /// node's known fingerprint; never sent
+    #[serde(skip)]
+    pub sender_verified: bool,
 }
 
 impl P2PMessage {
     pub fn new(sender_id: NodeId, receiver_id: NodeId, message_type: MessageType) -> Self {
-        use std::sync::atomic::{AtomicU64, Ordering};
         static MESSAGE_COUNTER: AtomicU64 = AtomicU64::new(1);
 
         Self {
@@ -64,9 +191,18 @@ impl P2PMessage {
             receiver_id,
             message_type,
             timestamp: current_timestamp(),
+            reply_to: None,
+            sender_verified: false,
         }
     }
 
+    /// The answer to this message, sent back to its sender
+    pub fn reply(&self, message_type: MessageType) -> Self {
+        let mut reply = Self::new(self.receiver_id, self.sender_id, message_type);
+        reply.reply_to = Some(self.id);
+        reply
+    }
+
     /// Serialize message to bytes
     pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
         bincode::serialize(self).map_err(|e| format!("Serialization error: {}", e))
@@ -81,7 +217,7 @@ impl P2PMessage {
 /// Configuration for P2P network
 #[derive(Debug, Clone)]
 pub struct P2PConfig {
-    /// Port for incoming P2P connections
+    /// Port for incoming P2P connections (0 picks a free port)
     pub listen_port: u16,
 
     /// Timeout for connection attempts (ms)
@@ -93,11 +229,26 @@ pub struct P2PConfig {
     /// Heartbeat interval (seconds)
     pub heartbeat_interval_secs: u64,
 
-    /// Maximum retry attempts for failed messages
+    /// Maximum retry attempts for messages whose connection failed
     pub max_retries: usize,
 
-    /// Buffer size for message receiving
-    pub buffer_size: usize,
+    /// Largest frame accepted, in bytes; larger messages are split into parts
+    pub max_frame_size: usize,
+
+    /// Largest message accepted once reassembled and decompressed, in bytes
+    pub max_message_size: usize,
+
+    /// Compress large messages
+    pub compression: bool,
+
+    /// Smallest encoded message worth compressing, in bytes
+    pub compression_threshold: usize,
+
+    /// Certificates connections are encrypted and authenticated with
+    pub tls: Option<TlsConfig>,
+
+    /// Use plaintext TCP instead of TLS, for local testing
+    pub insecure: bool,
 }
 
 impl Default for P2PConfig {
@@ -108,11 +259,19 @@ impl Default for P2PConfig {
             message_timeout_ms: 10000,
             heartbeat_interval_secs: 5,
             max_retries: 3,
-            buffer_size: 65536, // 64KB
+            max_frame_size: 16 * 1024 * 1024, // 16 MiB
+            max_message_size: 256 * 1024 * 1024, // 256 MiB
+            compression: true,
+            compression_threshold: 16 * 1024, // 16 KiB
+            tls: None,
+            insecure: false,
         }
     }
 }
 
+/// Handles one kind of message, returning the reply if there is one
+type Handler = Arc<dyn Fn(&P2PMessage) -> Option<P2PMessage> + Send + Sync>;
+
 /// P2P Network Manager
 pub struct P2PNetwork {
     config: P2PConfig,
@@ -120,25 +279,40 @@ pub struct P2PNetwork {
     local_address: NodeAddress,
     /// Known peer addresses
     peers: Arc<RwLock<HashMap<NodeId, NodeAddress>>>,
-    /// Active TCP connections
-    connections: Arc<RwLock<HashMap<NodeId, Arc<RwLock<TcpStream>>>>>,
+    /// Open connections to peers, reused for every message sent to them
+    connections: Arc<RwLock<HashMap<NodeId, Arc<PeerConnection>>>>,
     /// Message handlers (callbacks for different message types)
-    handlers: Arc<RwLock<HashMap<String, Box<dyn Fn(&P2PMessage) -> Option<P2PMessage> + Send + Sync>>>>,
+    handlers: Arc<RwLock<HashMap<MessageKind, Handler>>>,
+    /// Bytes sent and received over every connection
+    transfer: Arc<TransferCounters>,
+    /// How connections are carried, or why they can't be
+    transport: Arc<Result<Transport, String>>,
 }
 
 impl P2PNetwork {
     /// Create new P2P network
+    ///
+    /// Certificates are loaded here; a TLS config that can't be used fails
+    /// every connection with its error.
     pub fn new(local_id: NodeId, local_address: NodeAddress, config: P2PConfig) -> Self {
         Self {
+            transport: Arc::new(Transport::new(config.tls.as_ref(), config.insecure)),
             config,
             local_id,
             local_address,
             peers: Arc::new(RwLock::new(HashMap::new())),
             connections: Arc::new(RwLock::new(HashMap::new())),
             handlers: Arc::new(RwLock::new(HashMap::new())),
+            transfer: Arc::new(TransferCounters::default()),
         }
     }
 
+    /// Whether peers are checked against their nodes' known certificates,
+    /// so messages they send come with `sender_verified` set
+    pub fn verifies_nodes(&self) -> bool {
+        self.transport.as_ref().as_ref().is_ok_and(Transport::verifies_nodes)
+    }
+
     /// Add a known peer
     pub fn add_peer(&self, node_id: NodeId, address: NodeAddress) {
         let mut peers = self.peers.write().unwrap();
@@ -160,53 +334,30 @@ impl P2PNetwork {
         peers.iter().map(|(&id, addr)| (id, addr.clone())).collect()
     }
 
-    /// Start listening for incoming P2P connections
-    pub async fn start_listener(&self) -> Result<(), String> {
+    /// Start listening for incoming P2P connections, returning the address bound
+    pub async fn start_listener(&self) -> Result<SocketAddr, String> {
+        let transport = self.transport()?;
         let addr = format!("{}:{}", self.local_address.host, self.config.listen_port);
         let listener = TcpListener::bind(&addr)
             .await
             .map_err(|e| format!("Failed to bind to {}: {}", addr, e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to read address of {}: {}", addr, e))?;
 
-        println!("P2P listening on {}", addr);
+        println!("P2P listening on {}", local_addr);
 
-        let local_id = self.local_id;
         let handlers = Arc::clone(&self.handlers);
-        let buffer_size = self.config.buffer_size;
+        let codec = self.codec();
+        let local_id = self.local_id;
 
         tokio::spawn(async move {
             loop {
                 match listener.accept().await {
-                    Ok((mut stream, peer_addr)) => {
+                    Ok((stream, peer_addr)) => {
                         let handlers = Arc::clone(&handlers);
-
-                        tokio::spawn(async move {
-                            let mut buffer = vec![0u8; buffer_size];
-
-                            loop {
-                                match stream.read(&mut buffer).await {
-                                    Ok(0) => {
-                                        // Connection closed
-                                        break;
-                                    }
-                                    Ok(n) => {
-                                        // Parse message
-                                        if let Ok(msg) = P2PMessage::from_bytes(&buffer[..n]) {
-                                            // Process message
-                                            if let Some(response) = Self::handle_message(&msg, &handlers) {
-                                                // Send response
-                                                if let Ok(response_bytes) = response.to_bytes() {
-                                                    let _ = stream.write_all(&response_bytes).await;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Error reading from {}: {}", peer_addr, e);
-                                        break;
-                                    }
-                                }
-                            }
-                        });
+                        let connection = Self::serve_connection(stream, peer_addr, local_id, handlers, transport.clone(), codec.clone());
+                        tokio::spawn(connection);
                     }
                     Err(e) => {
                         eprintln!("Failed to accept connection: {}", e);
@@ -215,88 +366,177 @@ impl P2PNetwork {
             }
         });
 
-        Ok(())
+        Ok(local_addr)
     }
 
-    /// Handle incoming message
-    fn handle_message(
-        msg: &P2PMessage,
-        handlers: &Arc<RwLock<HashMap<String, Box<dyn Fn(&P2PMessage) -> Option<P2PMessage> + Send + Sync>>>>,
-    ) -> Option<P2PMessage> {
-        // Default handlers for built-in message types
-        match &msg.message_type {
-            MessageType::Ping => {
-                // Respond with Pong
-                return Some(P2PMessage::new(
-                    msg.receiver_id,
-                    msg.sender_id,
-                    MessageType::Pong,
-                ));
+    /// Answer the messages arriving on an incoming connection
+    async fn serve_connection(
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+        local_id: NodeId,
+        handlers: Arc<RwLock<HashMap<MessageKind, Handler>>>,
+        transport: Transport,
+        codec: FrameCodec,
+    ) {
+        let (stream, certificate) = match transport.accept(stream).await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Rejected connection from {}: {}", peer_addr, e);
+                return;
             }
-            MessageType::Pong => {
-                // Update latency metrics
-                return None;
-            }
-            _ => {
-                // Check custom handlers
-                let handlers = handlers.read().unwrap();
-                let key = format!("{:?}", msg.message_type);
-                if let Some(handler) = handlers.get(&key) {
-                    return handler(msg);
+        };
+        let (mut reader, writer) = tokio::io::split(stream);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let mut partial = PartialMessages::default();
+
+        loop {
+            let mut msg = match codec.read(&mut reader, &mut partial).await {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(e) => {
+                    // The stream can't be resynchronized after a bad frame,
+                    // so the sender is told why before it's closed
+                    eprintln!("Error reading from {}: {}", peer_addr, e);
+                    let error = P2PMessage::new(local_id, 0, MessageType::Error { message: e });
+                    let _ = codec.write(&writer, &error).await;
+                    break;
                 }
+            };
+
+            // A node can't speak for another on a connection with its certificate
+            if let Err(e) = transport.check_node(msg.sender_id, certificate.as_deref()) {
+                eprintln!("Rejected message from {}: {}", peer_addr, e);
+                let error = P2PMessage::new(local_id, msg.sender_id, MessageType::Error { message: e });
+                let _ = codec.write(&writer, &error).await;
+                break;
             }
+            msg.sender_verified = transport.verifies_nodes();
+
+            // Handlers may block, and a slow one mustn't hold up the rest
+            let handlers = Arc::clone(&handlers);
+            let writer = Arc::clone(&writer);
+            let codec = codec.clone();
+            tokio::spawn(async move {
+                let Ok(response) = tokio::task::spawn_blocking(move || Self::handle_message(&msg, &handlers)).await else {
+                    return;
+                };
+                if let Err(e) = codec.write(&writer, &response).await {
+                    eprintln!("Error replying to {}: {}", peer_addr, e);
+                }
+            });
         }
+    }
+
+    /// Handle incoming message, returning its reply
+    ///
+    /// A registered handler takes precedence over the built-in ones; a
+    /// message nothing answers is acknowledged with `Ack`.
+    fn handle_message(msg: &P2PMessage, handlers: &RwLock<HashMap<MessageKind, Handler>>) -> P2PMessage {
+        let kind = msg.message_type.kind();
+        let handler = handlers.read().unwrap().get(&kind).cloned();
+
+        let response = match (&msg.message_type, handler) {
+            (_, Some(handler)) => handler(msg),
+            // Default handlers for built-in message types
+            (MessageType::Ping, None) => Some(msg.reply(MessageType::Pong)),
+            (MessageType::Pong | MessageType::Ack, None) => None,
+            (_, None) => Some(msg.reply(MessageType::Error {
+                message: format!("No handler for {:?} messages", kind),
+            })),
+        };
 
-        None
+        let mut response = response.unwrap_or_else(|| msg.reply(MessageType::Ack));
+        response.reply_to = Some(msg.id);
+        response
     }
 
-    /// Send message to peer
-    pub async fn send_message(&self, peer_id: NodeId, message_type: MessageType) -> Result<Option<P2PMessage>, String> {
-        // Get peer address
+    /// Send message to peer and wait for its reply
+    ///
+    /// The peer's connection is reused, or opened if there is none. When it
+    /// fails before a reply arrives the message is sent again over a new
+    /// one, up to `max_retries` times; a reply that takes longer than
+    /// `message_timeout_ms` isn't retried, since the peer may have acted on it.
+    pub async fn send_message(&self, peer_id: NodeId, message_type: MessageType) -> Result<P2PMessage, String> {
         let peer_address = {
             let peers = self.peers.read().unwrap();
             peers.get(&peer_id).cloned()
         };
 
         let peer_address = peer_address.ok_or_else(|| format!("Unknown peer: {}", peer_id))?;
+        let addr = peer_address.to_string();
 
-        // Create message
         let msg = P2PMessage::new(self.local_id, peer_id, message_type);
+        let message_timeout = Duration::from_millis(self.config.message_timeout_ms);
+
+        let mut retries = 0;
+        loop {
+            let error = match self.connection(peer_id, &peer_address).await {
+                Ok(connection) => {
+                    let error = match connection.send(&msg).await {
+                        Ok(response) => match tokio::time::timeout(message_timeout, response).await {
+                            Ok(Ok(response)) => return Ok(response),
+                            Ok(Err(_)) => connection
+                                .failure()
+                                .unwrap_or_else(|| format!("Connection to {} closed before the response", addr)),
+                            Err(_) => {
+                                connection.abandon(msg.id);
+                                return Err("Response timeout".to_string());
+                            }
+                        },
+                        Err(e) => e,
+                    };
+                    self.forget_connection(peer_id, &connection);
+                    error
+                }
+                Err(e) => e,
+            };
 
-        // Serialize message
-        let msg_bytes = msg.to_bytes()?;
+            if retries == self.config.max_retries {
+                return Err(error);
+            }
+            retries += 1;
+        }
+    }
 
-        // Connect to peer
-        let addr = format!("{}:{}", peer_address.host, peer_address.port);
-        let mut stream = tokio::time::timeout(
-            Duration::from_millis(self.config.connection_timeout_ms),
-            TcpStream::connect(&addr)
-        )
-        .await
-        .map_err(|_| format!("Connection timeout to {}", addr))?
-        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
-
-        // Send message
-        stream.write_all(&msg_bytes).await
-            .map_err(|e| format!("Failed to send message: {}", e))?;
-
-        // Wait for response (with timeout)
-        let mut buffer = vec![0u8; self.config.buffer_size];
-        let n = tokio::time::timeout(
-            Duration::from_millis(self.config.message_timeout_ms),
-            stream.read(&mut buffer)
-        )
-        .await
-        .map_err(|_| "Response timeout".to_string())?
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+    /// The open connection to a peer, connecting if there is none
+    async fn connection(&self, peer_id: NodeId, address: &NodeAddress) -> Result<Arc<PeerConnection>, String> {
+        if let Some(connection) = self.connections.read().unwrap().get(&peer_id) {
+            if !connection.is_closed() {
+                return Ok(Arc::clone(connection));
+            }
+        }
 
-        if n == 0 {
-            return Ok(None); // No response
+        let connection = PeerConnection::open(address, Some(peer_id), &self.config, self.transport()?, self.codec()).await?;
+        let connection = Arc::new(connection);
+        self.connections.write().unwrap().insert(peer_id, Arc::clone(&connection));
+        Ok(connection)
+    }
+
+    /// Stop reusing a failed connection, unless it was already replaced
+    fn forget_connection(&self, peer_id: NodeId, connection: &Arc<PeerConnection>) {
+        let mut connections = self.connections.write().unwrap();
+        if connections.get(&peer_id).is_some_and(|c| Arc::ptr_eq(c, connection)) {
+            connections.remove(&peer_id);
         }
+    }
 
-        // Parse response
-        let response = P2PMessage::from_bytes(&buffer[..n])?;
-        Ok(Some(response))
+    /// Send a message to an address whose node isn't a known peer, and wait
+    /// for its reply
+    ///
+    /// For reaching a seed node when joining a cluster; the message goes
+    /// once, over a connection of its own.
+    pub async fn send_to_address(&self, address: &NodeAddress, message_type: MessageType) -> Result<P2PMessage, String> {
+        let addr = address.to_string();
+        let connection = PeerConnection::open(address, None, &self.config, self.transport()?, self.codec()).await?;
+        let msg = P2PMessage::new(self.local_id, 0, message_type);
+        let response = connection.send(&msg).await?;
+        match tokio::time::timeout(Duration::from_millis(self.config.message_timeout_ms), response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(connection
+                .failure()
+                .unwrap_or_else(|| format!("Connection to {} closed before the response", addr))),
+            Err(_) => Err("Response timeout".to_string()),
+        }
     }
 
     /// Send ping to peer and measure latency
@@ -305,8 +545,8 @@ impl P2PNetwork {
 
         let response = self.send_message(peer_id, MessageType::Ping).await?;
 
-        match response {
-            Some(msg) if msg.message_type == MessageType::Pong => {
+        match response.message_type {
+            MessageType::Pong => {
                 Ok(start.elapsed())
             }
             _ => Err("Invalid ping response".to_string()),
@@ -314,7 +554,7 @@ impl P2PNetwork {
     }
 
     /// Broadcast message to all peers
-    pub async fn broadcast(&self, message_type: MessageType) -> Vec<Result<Option<P2PMessage>, String>> {
+    pub async fn broadcast(&self, message_type: MessageType) -> Vec<Result<P2PMessage, String>> {
         let peers: Vec<NodeId> = {
             let peers = self.peers.read().unwrap();
             peers.keys().copied().collect()
@@ -372,16 +612,30 @@ impl P2PNetwork {
             peers: Arc::clone(&self.peers),
             connections: Arc::clone(&self.connections),
             handlers: Arc::clone(&self.handlers),
+            transfer: Arc::clone(&self.transfer),
+            transport: Arc::clone(&self.transport),
         })
     }
 
-    /// Register custom message handler
-    pub fn register_handler<F>(&self, message_type_name: String, handler: F)
+    fn transport(&self) -> Result<Transport, String> {
+        self.transport.as_ref().clone()
+    }
+
+    fn codec(&self) -> FrameCodec {
+        FrameCodec::new(&self.config, Arc::clone(&self.transfer))
+    }
+
+    /// Register the handler for one kind of message
+    ///
+    /// Its reply is sent back to the message's sender; returning `None`
+    /// acknowledges the message with `Ack`. Handlers run on a blocking
+    /// thread, so they may do blocking work.
+    pub fn register_handler<F>(&self, kind: MessageKind, handler: F)
     where
         F: Fn(&P2PMessage) -> Option<P2PMessage> + Send + Sync + 'static,
     {
         let mut handlers = self.handlers.write().unwrap();
-        handlers.insert(message_type_name, Box::new(handler));
+        handlers.insert(kind, Arc::new(handler));
     }
 
     /// Get network statistics
@@ -392,7 +646,12 @@ impl P2PNetwork {
         P2PStats {
             total_peers: peers.len(),
             active_connections: connections.len(),
+            pending_requests: connections.values().map(|connection| connection.pending_count()).sum(),
             local_address: self.local_address.to_string(),
+            raw_bytes_sent: self.transfer.raw_sent.load(Ordering::Relaxed),
+            wire_bytes_sent: self.transfer.wire_sent.load(Ordering::Relaxed),
+            raw_bytes_received: self.transfer.raw_received.load(Ordering::Relaxed),
+            wire_bytes_received: self.transfer.wire_received.load(Ordering::Relaxed),
         }
     }
 }
@@ -402,7 +661,377 @@ impl P2PNetwork {
 pub struct P2PStats {
     pub total_peers: usize,
     pub active_connections: usize,
+    /// Requests sent and still awaiting their response
+    pub pending_requests: usize,
     pub local_address: String,
+    /// Bytes of messages sent, as encoded
+    pub raw_bytes_sent: u64,
+    /// Bytes of frames sent, after compression
+    pub wire_bytes_sent: u64,
+    /// Bytes of messages received, as decoded
+    pub raw_bytes_received: u64,
+    /// Bytes of frames received, before decompression
+    pub wire_bytes_received: u64,
+}
+
+/// Requests awaiting their response, by message id; `None` once closed
+type PendingRequests = Arc<Mutex<Option<HashMap<MessageId, oneshot::Sender<P2PMessage>>>>>;
+
+/// Outgoing connection to a peer, shared by the requests sent to it
+struct PeerConnection {
+    writer: tokio::sync::Mutex<WriteHalf<BoxedStream>>,
+    codec: FrameCodec,
+    /// Requests awaiting their response, by message id; `None` once closed
+    pending: PendingRequests,
+    /// Why reading responses failed, if it did
+    failure: Arc<Mutex<Option<String>>>,
+    /// Routes responses to the requests awaiting them
+    reader: JoinHandle<()>,
+}
+
+impl PeerConnection {
+    /// Connect to `address`, checking it's `node` if given
+    async fn open(
+        address: &NodeAddress,
+        node: Option<NodeId>,
+        config: &P2PConfig,
+        transport: Transport,
+        codec: FrameCodec,
+    ) -> Result<Self, String> {
+        let addr = address.to_string();
+        let connect = async {
+            let stream = TcpStream::connect(&addr)
+                .await
+                .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+            transport.connect(stream, &address.host, node).await
+        };
+        let stream = tokio::time::timeout(Duration::from_millis(config.connection_timeout_ms), connect)
+            .await
+            .map_err(|_| format!("Connection timeout to {}", addr))??;
+        let (mut reader, writer) = tokio::io::split(stream);
+
+        let pending: PendingRequests =
+            Arc::new(Mutex::new(Some(HashMap::new())));
+        let waiting = Arc::clone(&pending);
+        let failure = Arc::new(Mutex::new(None));
+        let failed = Arc::clone(&failure);
+        let responses = codec.clone();
+        let reader = tokio::spawn(async move {
+            // A response nobody awaits any more, having timed out, is dropped
+            let mut partial = PartialMessages::default();
+            loop {
+                let response = match responses.read(&mut reader, &mut partial).await {
+                    Ok(Some(response)) => response,
+                    Ok(None) => break,
+                    Err(e) => {
+                        *failed.lock().unwrap() = Some(format!("Connection to {} failed: {}", addr, e));
+                        break;
+                    }
+                };
+                let waiter = match (response.reply_to, waiting.lock().unwrap().as_mut()) {
+                    (Some(id), Some(waiting)) => waiting.remove(&id),
+                    // An error about the connection itself, sent as the peer
+                    // closes it, is the answer to every request still waiting
+                    (None, Some(pending)) if matches!(response.message_type, MessageType::Error { .. }) => {
+                        for (id, waiter) in pending.drain() {
+                            let _ = waiter.send(P2PMessage { reply_to: Some(id), ..response.clone() });
+                        }
+                        break;
+                    }
+                    _ => None,
+                };
+                if let Some(waiter) = waiter {
+                    let _ = waiter.send(response);
+                }
+            }
+
+            // Requests still waiting fail, and are retried elsewhere
+            waiting.lock().unwrap().take();
+        });
+
+        Ok(PeerConnection {
+            writer: tokio::sync::Mutex::new(writer),
+            codec,
+            pending,
+            failure,
+            reader,
+        })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().is_none()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    fn pending_count(&self) -> usize {
+        self.pending.lock().unwrap().as_ref().map_or(0, HashMap::len)
+    }
+
+    /// Stop waiting for a request's response, as when it timed out
+    fn abandon(&self, id: MessageId) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&id);
+        }
+    }
+
+    /// Send a request; the receiver gets its response, or an error if the connection fails first
+    async fn send(&self, msg: &P2PMessage) -> Result<oneshot::Receiver<P2PMessage>, String> {
+        let (waiter, response) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(msg.id, waiter),
+            None => return Err("Connection closed".to_string()),
+        };
+
+        if let Err(e) = self.codec.write(&self.writer, msg).await {
+            self.abandon(msg.id);
+            return Err(e);
+        }
+
+        Ok(response)
+    }
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Set in a frame's length word when its message is compressed
+const FLAG_COMPRESSED: u32 = 1 << 31;
+/// Set in a frame's length word when it carries one part of a message
+/// split over several frames; its payload starts with the message's number
+/// on the connection, the part's index and the number of parts, big-endian
+/// u32s
+const FLAG_PART: u32 = 1 << 30;
+/// Bits of a frame's length word holding the payload's length
+const LENGTH_MASK: u32 = FLAG_PART - 1;
+const PART_HEADER: usize = 12;
+
+/// Bytes a network sent and received, as encoded messages and as frames
+/// on the wire
+#[derive(Default)]
+struct TransferCounters {
+    raw_sent: AtomicU64,
+    wire_sent: AtomicU64,
+    raw_received: AtomicU64,
+    wire_received: AtomicU64,
+}
+
+/// How messages are framed on a network's connections
+#[derive(Clone)]
+struct FrameCodec {
+    max_frame_size: usize,
+    max_message_size: usize,
+    /// Smallest encoded message compressed, if any is
+    compression_threshold: Option<usize>,
+    counters: Arc<TransferCounters>,
+    /// Number of the next message split into parts
+    next_split: Arc<AtomicU32>,
+}
+
+/// Parts of the messages a connection is in the middle of receiving, by
+/// message number
+#[derive(Default)]
+struct PartialMessages {
+    messages: HashMap<u32, PartialMessage>,
+}
+
+struct PartialMessage {
+    flags: u32,
+    total: u32,
+    received: u32,
+    body: Vec<u8>,
+    wire: usize,
+}
+
+impl FrameCodec {
+    fn new(config: &P2PConfig, counters: Arc<TransferCounters>) -> Self {
+        Self {
+            max_frame_size: config.max_frame_size.min(LENGTH_MASK as usize),
+            max_message_size: config.max_message_size,
+            compression_threshold: config.compression.then_some(config.compression_threshold),
+            counters,
+            next_split: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Write a message, compressed if it's large enough for that to pay,
+    /// and split into parts if it still doesn't fit in one frame
+    ///
+    /// Compression runs on the blocking pool. The stream is locked for one
+    /// frame at a time, so other messages' frames go out between the parts
+    /// of a large one.
+    async fn write<W: AsyncWrite + Unpin>(&self, stream: &tokio::sync::Mutex<W>, msg: &P2PMessage) -> Result<(), String> {
+        let encoded = msg.to_bytes()?;
+        let raw = encoded.len();
+        let (body, flags) = match self.compression_threshold {
+            Some(threshold) if raw >= threshold => tokio::task::spawn_blocking(move || match compress(&encoded) {
+                compressed if compressed.len() < raw => (compressed, FLAG_COMPRESSED),
+                _ => (encoded, 0),
+            })
+            .await
+            .map_err(|e| format!("Compression error: {}", e))?,
+            _ => (encoded, 0),
+        };
+        if body.len() > self.max_message_size {
+            return Err(format!("Message of {} bytes exceeds the {} byte limit", body.len(), self.max_message_size));
+        }
+
+        let mut wire = 0;
+        if body.len() <= self.max_frame_size {
+            wire += write_frame(&mut *stream.lock().await, flags, &[], &body).await?;
+        } else {
+            let part_size = self.max_frame_size.saturating_sub(PART_HEADER).max(1);
+            let total = u32::try_from(body.len().div_ceil(part_size)).map_err(|_| "Message has too many parts".to_string())?;
+            let number = self.next_split.fetch_add(1, Ordering::Relaxed);
+            for (seq, part) in (0u32..).zip(body.chunks(part_size)) {
+                let mut header = [0u8; PART_HEADER];
+                header[..4].copy_from_slice(&number.to_be_bytes());
+                header[4..8].copy_from_slice(&seq.to_be_bytes());
+                header[8..].copy_from_slice(&total.to_be_bytes());
+                wire += write_frame(&mut *stream.lock().await, flags | FLAG_PART, &header, part).await?;
+            }
+        }
+
+        self.counters.raw_sent.fetch_add(raw as u64, Ordering::Relaxed);
+        self.counters.wire_sent.fetch_add(wire as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read frames until a message is complete, reassembling split ones in
+    /// `partial`; `None` when the peer closed the connection between frames
+    ///
+    /// Decompressing and decoding a compressed message runs on the blocking
+    /// pool.
+    async fn read<R: AsyncRead + Unpin>(&self, stream: &mut R, partial: &mut PartialMessages) -> Result<Option<P2PMessage>, String> {
+        let (flags, body, wire) = loop {
+            let Some((flags, payload)) = self.read_frame(stream).await? else {
+                if !partial.messages.is_empty() {
+                    return Err("Connection closed in the middle of a message".to_string());
+                }
+                return Ok(None);
+            };
+            if flags & FLAG_PART == 0 {
+                let wire = 4 + payload.len();
+                break (flags, payload, wire);
+            }
+
+            let (number, seq, total) = part_header(&payload)?;
+            let message = partial.messages.entry(number).or_insert_with(|| PartialMessage {
+                flags,
+                total,
+                received: 0,
+                body: Vec::new(),
+                wire: 0,
+            });
+            if seq != message.received || total != message.total || seq >= total {
+                return Err(format!("Message part {} of {} arrived out of order", seq, total));
+            }
+            if flags != message.flags {
+                return Err("Message parts disagree on how the message is encoded".to_string());
+            }
+            if message.body.len() + payload.len() - PART_HEADER > self.max_message_size {
+                return Err(format!("Message exceeds the {} byte limit", self.max_message_size));
+            }
+            message.body.extend_from_slice(&payload[PART_HEADER..]);
+            message.wire += 4 + payload.len();
+            message.received += 1;
+            if message.received == total {
+                let message = partial.messages.remove(&number).unwrap();
+                break (flags, message.body, message.wire);
+            }
+        };
+
+        let (raw, msg) = if flags & FLAG_COMPRESSED != 0 {
+            let limit = self.max_message_size;
+            tokio::task::spawn_blocking(move || {
+                let encoded = decompress(&body, limit)?;
+                Ok::<_, String>((encoded.len(), P2PMessage::from_bytes(&encoded)?))
+            })
+            .await
+            .map_err(|e| format!("Decompression error: {}", e))??
+        } else {
+            (body.len(), P2PMessage::from_bytes(&body)?)
+        };
+        self.counters.raw_received.fetch_add(raw as u64, Ordering::Relaxed);
+        self.counters.wire_received.fetch_add(wire as u64, Ordering::Relaxed);
+        Ok(Some(msg))
+    }
+
+    /// Read one frame's flags and payload; `None` when the peer closed the
+    /// connection between frames
+    ///
+    /// A length over the limit is refused before anything is allocated.
+    async fn read_frame<R: AsyncRead + Unpin>(&self, stream: &mut R) -> Result<Option<(u32, Vec<u8>)>, String> {
+        let mut word = [0u8; 4];
+        match stream.read_exact(&mut word).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(format!("Failed to read frame: {}", tls::describe_io_error(&e))),
+        }
+
+        let word = u32::from_be_bytes(word);
+        let length = (word & LENGTH_MASK) as usize;
+        if length > self.max_frame_size {
+            return Err(format!("Frame of {} bytes exceeds the {} byte limit", length, self.max_frame_size));
+        }
+
+        let mut payload = vec![0u8; length];
+        stream
+            .read_exact(&mut payload)
+            .await
+            .map_err(|e| format!("Failed to read frame: {}", tls::describe_io_error(&e)))?;
+        Ok(Some((word & !LENGTH_MASK, payload)))
+    }
+}
+
+/// Write one frame, returning the bytes it took
+async fn write_frame<W: AsyncWrite + Unpin>(stream: &mut W, flags: u32, header: &[u8], data: &[u8]) -> Result<usize, String> {
+    let length = header.len() + data.len();
+    let word = u32::try_from(length)
+        .ok()
+        .filter(|&length| length <= LENGTH_MASK)
+        .ok_or_else(|| "Frame too large".to_string())?;
+
+    let mut frame = Vec::with_capacity(4 + length);
+    frame.extend_from_slice(&(word | flags).to_be_bytes());
+    frame.extend_from_slice(header);
+    frame.extend_from_slice(data);
+    stream
+        .write_all(&frame)
+        .await
+        .map_err(|e| format!("Failed to write frame: {}", e))?;
+    Ok(frame.len())
+}
+
+/// A part's message number, its index and the number of parts of its message
+fn part_header(payload: &[u8]) -> Result<(u32, u32, u32), String> {
+    if payload.len() < PART_HEADER {
+        return Err("Message part is missing its header".to_string());
+    }
+    let word = |at: usize| u32::from_be_bytes(payload[at..at + 4].try_into().unwrap());
+    Ok((word(0), word(4), word(8)))
+}
+
+/// Compress a message with LZ4, prefixed with its length as a little-endian u32
+fn compress(data: &[u8]) -> Vec<u8> {
+    lz4_flex::block::compress_prepend_size(data)
+}
+
+/// Decompress a message, refusing to produce more than `limit` bytes
+fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 4 {
+        return Err("Decompression error: missing length".to_string());
+    }
+    let length = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+    if length > limit {
+        return Err(format!("Message exceeds the {} byte limit", limit));
+    }
+    lz4_flex::block::decompress(&data[4..], length).map_err(|e| format!("Decompression error: {}", e))
 }
 
 /// Helper function to get current Unix timestamp
@@ -473,4 +1102,41 @@ mod tests {
             assert_eq!(msg.message_type, recovered.message_type);
         }
     }
+
+    #[tokio::test]
+    async fn test_frames_of_other_messages_go_between_parts() {
+        let codec = FrameCodec::new(&P2PConfig::default(), Arc::default());
+        let large = P2PMessage::new(1, 2, MessageType::ShardDataResponse { shard_id: 1, data: vec![7; 1000] })
+            .to_bytes()
+            .unwrap();
+        let small = P2PMessage::new(1, 2, MessageType::Ping).to_bytes().unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let part = |number: u32, seq: u32| {
+            let mut header = [0u8; PART_HEADER];
+            header[..4].copy_from_slice(&number.to_be_bytes());
+            header[4..8].copy_from_slice(&seq.to_be_bytes());
+            header[8..].copy_from_slice(&2u32.to_be_bytes());
+            header
+        };
+        let (first, second) = large.split_at(500);
+        write_frame(&mut client, FLAG_PART, &part(9, 0), first).await.unwrap();
+        write_frame(&mut client, 0, &[], &small).await.unwrap();
+        write_frame(&mut client, FLAG_PART, &part(9, 1), second).await.unwrap();
+        drop(client);
+
+        let mut partial = PartialMessages::default();
+        let ping = codec.read(&mut server, &mut partial).await.unwrap().unwrap();
+        assert_eq!(ping.message_type, MessageType::Ping);
+        let shard = codec.read(&mut server, &mut partial).await.unwrap().unwrap();
+        assert!(matches!(shard.message_type, MessageType::ShardDataResponse { ref data, .. } if data.len() == 1000));
+        assert!(codec.read(&mut server, &mut partial).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decompression_refuses_a_length_over_the_limit() {
+        let compressed = compress(&[0u8; 4096]);
+        assert_eq!(decompress(&compressed, 4096).unwrap(), vec![0u8; 4096]);
+        assert_eq!(decompress(&compressed, 4095).unwrap_err(), "Message exceeds the 4095 byte limit");
+    }
 }
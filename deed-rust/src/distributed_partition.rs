@@ -8,13 +8,28 @@
 //! - Split-brain resolution using majority quorum
 //! - Automatic partition healing
 //! - Read/write quorum enforcement
+//! - Quorum reads and writes of versioned replicas, with read repair
 
 use crate::distributed_topology::NodeId;
-use crate::distributed_consensus::RaftNode;
+use crate::distributed_p2p::{MessageKind, MessageType, P2PNetwork};
+use crate::shutdown::{BackgroundTask, ShutdownSignal};
 use serde::{Serialize, Deserialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+
+/// Start of the error a statement fails with when this node's side of a
+/// partition can't serve it
+pub const CLUSTER_DEGRADED: &str = "cluster degraded: no quorum";
+
+/// Whether an error says the cluster is degraded, rather than that the query
+/// itself failed; the same statement may succeed on a node in the majority,
+/// or here once the partition heals
+pub fn is_cluster_degraded(error: &str) -> bool {
+    error.starts_with(CLUSTER_DEGRADED)
+}
 
 /// Partition detection state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -42,7 +57,7 @@ pub enum ConsistencyLevel {
 
 impl ConsistencyLevel {
     /// Calculate required number of nodes for this consistency level
-    pub fn required_nodes(&self, total_nodes: usize, replication_factor: usize) -> usize {
+    pub fn required_nodes(&self, _total_nodes: usize, replication_factor: usize) -> usize {
         match self {
             ConsistencyLevel::Quorum => (replication_factor / 2) + 1,
             ConsistencyLevel::All => replication_factor,
@@ -100,7 +115,7 @@ impl PartitionManager {
             local_id,
             state: Arc::new(RwLock::new(PartitionState::Healthy)),
             node_health: Arc::new(RwLock::new(HashMap::new())),
-            our_partition: Arc::new(RwLock::new(HashSet::new())),
+            our_partition: Arc::new(RwLock::new(HashSet::from([local_id]))),
             total_nodes,
             replication_factor,
             health_check_interval: Duration::from_secs(health_check_interval_secs),
@@ -110,6 +125,11 @@ impl PartitionManager {
 
     /// Register a node
     pub fn add_node(&self, node_id: NodeId) {
+        // We always count ourselves
+        if node_id == self.local_id {
+            return;
+        }
+
         let health = NodeHealth {
             node_id,
             last_seen: Instant::now(),
@@ -145,7 +165,20 @@ impl PartitionManager {
         }
     }
 
+    /// Mark a node unreachable straight away, as when membership declares it dead
+    pub fn mark_unreachable(&self, node_id: NodeId) {
+        let mut health_map = self.node_health.write().unwrap();
+
+        if let Some(health) = health_map.get_mut(&node_id) {
+            health.consecutive_failures = health.consecutive_failures.max(self.failure_threshold);
+            health.is_reachable = false;
+        }
+    }
+
     /// Check for network partition
+    ///
+    /// Nodes of the cluster that were never registered with `add_node`
+    /// count as unreachable: we've never heard from them.
     pub fn check_partition(&self) -> PartitionState {
         let health_map = self.node_health.read().unwrap();
 
@@ -154,10 +187,11 @@ impl PartitionManager {
             .filter(|h| h.is_reachable)
             .count() + 1; // +1 for self
 
+        let unregistered_count = self.total_nodes.saturating_sub(health_map.len() + 1);
         let unreachable_count = health_map
             .values()
             .filter(|h| !h.is_reachable)
-            .count();
+            .count() + unregistered_count;
 
         // Update our partition membership
         let mut our_partition = self.our_partition.write().unwrap();
@@ -216,6 +250,35 @@ impl PartitionManager {
         our_partition.len() >= required
     }
 
+    /// Fail with the [`CLUSTER_DEGRADED`] error unless we can write
+    pub fn check_write(&self) -> Result<(), String> {
+        if self.can_write() {
+            return Ok(());
+        }
+
+        Err(format!(
+            "{} ({} of {} nodes reachable) - writes are refused until the partition heals",
+            CLUSTER_DEGRADED,
+            self.our_partition.read().unwrap().len(),
+            self.total_nodes
+        ))
+    }
+
+    /// Fail with the [`CLUSTER_DEGRADED`] error unless we can read at `consistency`
+    pub fn check_read(&self, consistency: ConsistencyLevel) -> Result<(), String> {
+        if self.can_read(consistency) {
+            return Ok(());
+        }
+
+        Err(format!(
+            "{} ({} of {} nodes reachable) - reads at {:?} consistency are refused until the partition heals",
+            CLUSTER_DEGRADED,
+            self.our_partition.read().unwrap().len(),
+            self.total_nodes,
+            consistency
+        ))
+    }
+
     /// Get current partition state
     pub fn get_state(&self) -> PartitionState {
         *self.state.read().unwrap()
@@ -237,18 +300,22 @@ impl PartitionManager {
             .collect()
     }
 
-    /// Start automatic partition detection
-    pub fn start_monitoring(&self) {
+    /// Start automatic partition detection, until `shutdown` is cancelled
+    pub fn start_monitoring(&self, shutdown: &ShutdownSignal) -> BackgroundTask {
         let manager = self.clone_for_async();
 
-        tokio::spawn(async move {
+        BackgroundTask::spawn("partition-monitor", shutdown, |shutdown| async move {
             let mut interval = tokio::time::interval(manager.health_check_interval);
 
             loop {
-                interval.tick().await;
-                manager.check_partition();
+                tokio::select! {
+                    _ = interval.tick() => {
+                        manager.check_partition();
+                    }
+                    _ = shutdown.cancelled() => return,
+                }
             }
-        });
+        })
     }
 
     /// Attempt to heal partition
@@ -296,6 +363,129 @@ impl PartitionManager {
     }
 }
 
+/// Hybrid logical clock reading that orders writes to a key
+///
+/// Versions compare by timestamp, then by the coordinating node, so two
+/// coordinators can never issue the same version for different values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Version {
+    /// Microseconds since the epoch, or past the newest version the coordinator had seen
+    pub timestamp: u64,
+    pub node: NodeId,
+}
+
+/// A replica's value for a key, with the version that orders writes to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedValue {
+    pub value: Vec<u8>,
+    pub version: Version,
+}
+
+/// A node's replicas of quorum-managed keys
+///
+/// A write older than the version held is ignored, so repeated and
+/// reordered writes, read repairs included, leave the newest value.
+#[derive(Debug, Default)]
+pub struct LocalReplica {
+    values: RwLock<HashMap<String, VersionedValue>>,
+}
+
+impl LocalReplica {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value held for `key`, if any
+    pub fn read(&self, key: &str) -> Option<VersionedValue> {
+        self.values.read().unwrap().get(key).cloned()
+    }
+
+    /// Store `value` for `key`, unless a newer version is held
+    pub fn write(&self, key: &str, value: &VersionedValue) {
+        let mut values = self.values.write().unwrap();
+        if values.get(key).is_none_or(|held| held.version < value.version) {
+            values.insert(key.to_string(), value.clone());
+        }
+    }
+
+    /// Answer other nodes' `ReplicaRead`s and `ReplicaWrite`s on `network`
+    pub fn serve(self: &Arc<Self>, network: &P2PNetwork) {
+        let replica = Arc::clone(self);
+        network.register_handler(MessageKind::ReplicaRead, move |msg| {
+            let MessageType::ReplicaRead { key } = &msg.message_type else {
+                return None;
+            };
+            let (value, version) = match replica.read(key) {
+                Some(held) => (Some(held.value), held.version),
+                None => (None, Version::default()),
+            };
+            Some(msg.reply(MessageType::ReplicaValue { value, version }))
+        });
+
+        // Acknowledged with Ack
+        let replica = Arc::clone(self);
+        network.register_handler(MessageKind::ReplicaWrite, move |msg| {
+            if let MessageType::ReplicaWrite { key, value, version } = &msg.message_type {
+                replica.write(key, &VersionedValue { value: value.clone(), version: *version });
+            }
+            None
+        });
+    }
+}
+
+/// How a [`QuorumManager`] reaches replicas
+pub trait ReplicaTransport: Send + Sync {
+    /// `node`'s value for `key`, if it has one
+    fn read(&self, node: NodeId, key: &str) -> Result<Option<VersionedValue>, String>;
+
+    /// Store `value` for `key` on `node`, unless it holds a newer version
+    fn write(&self, node: NodeId, key: &str, value: &VersionedValue) -> Result<(), String>;
+}
+
+/// [`ReplicaTransport`] over the P2P network, to nodes serving their [`LocalReplica`]
+///
+/// Each call blocks on `runtime`; the [`QuorumManager`] makes them on
+/// blocking threads.
+pub struct P2PReplicaTransport {
+    network: Arc<P2PNetwork>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl P2PReplicaTransport {
+    pub fn new(network: Arc<P2PNetwork>, runtime: tokio::runtime::Handle) -> Self {
+        Self { network, runtime }
+    }
+
+    fn request(&self, node: NodeId, request: MessageType) -> Result<MessageType, String> {
+        let reply = self.runtime.block_on(self.network.send_message(node, request))?;
+        match reply.message_type {
+            MessageType::Error { message } => Err(message),
+            other => Ok(other),
+        }
+    }
+}
+
+impl ReplicaTransport for P2PReplicaTransport {
+    fn read(&self, node: NodeId, key: &str) -> Result<Option<VersionedValue>, String> {
+        match self.request(node, MessageType::ReplicaRead { key: key.to_string() })? {
+            MessageType::ReplicaValue { value, version } => Ok(value.map(|value| VersionedValue { value, version })),
+            other => Err(format!("Unexpected reply from node {}: {:?}", node, other.kind())),
+        }
+    }
+
+    fn write(&self, node: NodeId, key: &str, value: &VersionedValue) -> Result<(), String> {
+        let request = MessageType::ReplicaWrite {
+            key: key.to_string(),
+            value: value.value.clone(),
+            version: value.version,
+        };
+        match self.request(node, request)? {
+            MessageType::Ack => Ok(()),
+            other => Err(format!("Unexpected reply from node {}: {:?}", node, other.kind())),
+        }
+    }
+}
+
 /// Quorum-based read/write manager
 pub struct QuorumManager {
     /// This node's ID
@@ -312,6 +502,15 @@ pub struct QuorumManager {
 
     /// Partition manager
     partition_manager: Arc<PartitionManager>,
+
+    /// How replicas are reached
+    transport: Option<Arc<dyn ReplicaTransport>>,
+
+    /// How long replicas have to answer before they count as failed
+    timeout: Duration,
+
+    /// Timestamp of the newest version issued or seen here
+    last_timestamp: AtomicU64,
 }
 
 impl QuorumManager {
@@ -327,9 +526,24 @@ impl QuorumManager {
             read_consistency: ConsistencyLevel::Quorum,
             write_consistency: ConsistencyLevel::Quorum,
             partition_manager,
+            transport: None,
+            timeout: Duration::from_secs(1),
+            last_timestamp: AtomicU64::new(0),
         }
     }
 
+    /// Reach replicas through `transport`
+    pub fn with_transport(mut self, transport: Arc<dyn ReplicaTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Give replicas `timeout` to answer before counting them as failed
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Set read consistency level
     pub fn set_read_consistency(&mut self, level: ConsistencyLevel) {
         self.read_consistency = level;
@@ -342,21 +556,12 @@ impl QuorumManager {
 
     /// Check if read can proceed
     pub fn can_read(&self) -> Result<(), String> {
-        if !self.partition_manager.can_read(self.read_consistency) {
-            return Err(format!(
-                "Cannot satisfy read consistency {:?} - not enough reachable nodes",
-                self.read_consistency
-            ));
-        }
-
-        Ok(())
+        self.partition_manager.check_read(self.read_consistency)
     }
 
     /// Check if write can proceed
     pub fn can_write(&self) -> Result<(), String> {
-        if !self.partition_manager.can_write() {
-            return Err("Cannot write - no quorum".to_string());
-        }
+        self.partition_manager.check_write()?;
 
         // Additional check for write consistency
         if !self.partition_manager.can_read(self.write_consistency) {
@@ -370,63 +575,143 @@ impl QuorumManager {
     }
 
     /// Coordinate a quorum read
-    pub async fn quorum_read(&self, key: &str, replicas: Vec<NodeId>) -> Result<Vec<u8>, String> {
+    ///
+    /// Every replica is asked at once, and the newest value among the
+    /// first answers the read consistency level needs is returned. Those
+    /// that answered with an older value, or none, are sent the newest in
+    /// the background (read repair).
+    pub async fn quorum_read(&self, key: &str, replicas: Vec<NodeId>) -> Result<Option<Vec<u8>>, String> {
         self.can_read()?;
 
-        let required = self.read_consistency.required_nodes(
-            replicas.len(),
-            self.replication_factor,
-        );
+        let transport = self.transport()?;
+        let required = self.read_consistency.required_nodes(replicas.len(), replicas.len());
+        let read_key = key.to_string();
+        let responses = self
+            .gather(&transport, &replicas, required, move |transport, node| transport.read(node, &read_key))
+            .await
+            .map_err(|e| format!("Read failed - {}", e))?;
 
-        // Read from required number of replicas
-        let mut responses = Vec::new();
-
-        for (i, &node_id) in replicas.iter().enumerate() {
-            if i >= required {
-                break;
-            }
-
-            // In production, would send read request via P2P
-            // For now, simplified
-            let value = vec![1, 2, 3]; // Dummy value
-            responses.push(value);
+        let Some(newest) = responses.iter().filter_map(|(_, value)| value.clone()).max_by_key(|value| value.version) else {
+            return Ok(None);
+        };
+        self.observe(newest.version);
+
+        let stale: Vec<NodeId> = responses
+            .iter()
+            .filter(|(_, value)| value.as_ref().is_none_or(|value| value.version < newest.version))
+            .map(|(node, _)| *node)
+            .collect();
+        if !stale.is_empty() {
+            let (key, repair) = (key.to_string(), newest.clone());
+            tokio::task::spawn_blocking(move || {
+                for node in stale {
+                    // A replica the repair misses is repaired by a later read
+                    if let Err(e) = transport.write(node, &key, &repair) {
+                        eprintln!("Read repair of '{}' on node {} failed: {}", key, node, e);
+                    }
+                }
+            });
         }
 
-        // Return most recent value (would use version numbers in production)
-        responses.into_iter().next().ok_or_else(|| "No responses".to_string())
+        Ok(Some(newest.value))
     }
 
     /// Coordinate a quorum write
+    ///
+    /// Every replica is sent the write at once; it succeeds when as many as
+    /// the write consistency level needs have stored it, without waiting on
+    /// the rest.
     pub async fn quorum_write(&self, key: &str, value: Vec<u8>, replicas: Vec<NodeId>) -> Result<(), String> {
         self.can_write()?;
 
-        let required = self.write_consistency.required_nodes(
-            replicas.len(),
-            self.replication_factor,
-        );
+        let transport = self.transport()?;
+        let required = self.write_consistency.required_nodes(replicas.len(), replicas.len());
+        let (key, value) = (key.to_string(), VersionedValue { value, version: self.next_version() });
+        self.gather(&transport, &replicas, required, move |transport, node| transport.write(node, &key, &value))
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Write failed - {}", e))
+    }
+
+    fn transport(&self) -> Result<Arc<dyn ReplicaTransport>, String> {
+        self.transport.clone().ok_or_else(|| "No replica transport configured".to_string())
+    }
 
-        let mut successful_writes = 0;
+    /// Send `request` to every replica at once, returning the answers once
+    /// `required` have succeeded
+    ///
+    /// Fails as soon as too many replicas have failed for that. A replica
+    /// that hasn't answered within the timeout counts as failed; requests
+    /// still running when this returns are left to finish.
+    async fn gather<T, F>(
+        &self,
+        transport: &Arc<dyn ReplicaTransport>,
+        replicas: &[NodeId],
+        required: usize,
+        request: F,
+    ) -> Result<Vec<(NodeId, T)>, String>
+    where
+        T: Send + 'static,
+        F: Fn(&dyn ReplicaTransport, NodeId) -> Result<T, String> + Send + Sync + 'static,
+    {
+        let request = Arc::new(request);
+        let mut pending = JoinSet::new();
+        for &node in replicas {
+            let (transport, request) = (Arc::clone(transport), Arc::clone(&request));
+            pending.spawn_blocking(move || (node, (*request)(transport.as_ref(), node)));
+        }
 
-        for (i, &node_id) in replicas.iter().enumerate() {
-            if i >= required {
-                break;
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut answers = Vec::new();
+        let mut failures = Vec::new();
+        while answers.len() < required && replicas.len() - failures.len() >= required {
+            match tokio::time::timeout_at(deadline, pending.join_next()).await {
+                Ok(Some(Ok((node, Ok(answer))))) => answers.push((node, answer)),
+                Ok(Some(Ok((node, Err(e))))) => failures.push(format!("node {}: {}", node, e)),
+                Ok(Some(Err(e))) => failures.push(format!("replica request panicked: {}", e)),
+                Ok(None) => break,
+                Err(_) => {
+                    failures.push(format!("{} replicas timed out after {:?}", pending.len(), self.timeout));
+                    break;
+                }
             }
-
-            // In production, would send write request via P2P
-            // For now, simplified
-            successful_writes += 1;
         }
+        pending.detach_all();
 
-        if successful_writes >= required {
-            Ok(())
+        if answers.len() >= required {
+            Ok(answers)
         } else {
             Err(format!(
-                "Write failed - only {} of {} required nodes succeeded",
-                successful_writes, required
+                "only {} of {} required nodes succeeded ({})",
+                answers.len(),
+                required,
+                failures.join("; ")
             ))
         }
     }
 
+    /// A version newer than any this manager has issued or seen, from the
+    /// clock when it has moved on
+    fn next_version(&self) -> Version {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64);
+        let previous = self
+            .last_timestamp
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap();
+        Version {
+            timestamp: now.max(previous + 1),
+            node: self.local_id,
+        }
+    }
+
+    /// Keep later writes from here ordered after `version`, however far
+    /// behind this node's clock is
+    fn observe(&self, version: Version) {
+        self.last_timestamp.fetch_max(version.timestamp, Ordering::SeqCst);
+    }
+
     /// Get statistics
     pub fn get_statistics(&self) -> QuorumStats {
         QuorumStats {
@@ -523,6 +808,22 @@ mod tests {
         assert!(!manager.can_write());
     }
 
+    #[test]
+    fn test_unregistered_nodes_are_unreachable() {
+        // Only node 2 was ever registered in a cluster of five
+        let manager = PartitionManager::new(1, 5, 3, 10);
+        manager.add_node(2);
+
+        assert_eq!(manager.check_partition(), PartitionState::Partitioned);
+        assert!(!manager.can_write());
+        assert!(is_cluster_degraded(&manager.check_write().unwrap_err()));
+
+        // A single-node cluster has nobody else to hear from
+        let manager = PartitionManager::new(1, 1, 1, 10);
+        assert_eq!(manager.check_partition(), PartitionState::Healthy);
+        assert!(manager.can_write());
+    }
+
     #[test]
     fn test_consistency_level_required_nodes() {
         assert_eq!(ConsistencyLevel::Quorum.required_nodes(5, 3), 2);
@@ -12,14 +12,34 @@
 //! 3. Route sub-queries to responsible nodes
 //! 4. Execute in parallel
 //! 5. Aggregate results
+//!
+//! A SELECT is rewritten before it is sent, so that the rows nodes return can
+//! be merged into the statement's result: LIMIT covers the OFFSET too, AVG is
+//! computed from each node's SUM and COUNT, and ORDER BY and GROUP BY keys
+//! travel in extra columns. Results travel bincode-encoded ([`QueryResult::to_bytes`]).
+//!
+//! Writes go to the nodes owning their rows' shards. Rows are routed by one
+//! key, their `id` property ([`SHARD_KEY_PROPERTY`]), the same key the
+//! rebalancer places them by: an INSERT's rows are split by it, and a row
+//! without one is given a cluster-unique integer `id` first. A node given a
+//! [`ShardOwnership`] refuses writes to shards it doesn't own.
 
 use crate::distributed_topology::NodeId;
 use crate::distributed_shard::{ShardManager, ShardId};
-use crate::distributed_p2p::{P2PNetwork, MessageType, P2PMessage};
+use crate::distributed_p2p::{P2PNetwork, MessageKind, MessageType};
+use crate::auth::{Access, Role, Session};
+use crate::dql_ast::{AggregateFunction, Expression, InsertQuery, Literal, Query, SelectField, SelectQuery, UpdateQuery, WhereClause};
 use crate::dql_executor::{DQLExecutor, QueryResult};
+use crate::dql_ir::{Value, ValueType};
+use crate::dql_parser::Parser;
+use crate::distributed_rebalance;
+use crate::graph::Entity;
+use crate::types::{DistinctKey, Properties, PropertyValue};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Distributed query plan
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,8 +56,22 @@ pub struct DistributedQueryPlan {
     /// Whether query requires global coordination
     pub requires_coordination: bool,
 
-    /// Aggregation needed (for GROUP BY, COUNT, etc.)
-    pub aggregation: Option<AggregationType>,
+    /// How the nodes' results combine
+    pub merge: ResultMerge,
+
+    /// Whether a sub-query's shards can be answered by several nodes, each
+    /// for some of them, and the results merged
+    pub splittable: bool,
+}
+
+impl DistributedQueryPlan {
+    /// Whether the statement only reads, so its sub-queries may be retried,
+    /// answered by replicas, and left out under [`PartialResults::BestEffort`]
+    ///
+    /// A mutation whose reply was lost may still have been applied.
+    pub fn is_read(&self) -> bool {
+        matches!(self.query_type, QueryType::Select | QueryType::Aggregate | QueryType::Traverse)
+    }
 }
 
 /// Sub-query to execute on a specific node
@@ -46,6 +80,8 @@ pub struct SubQuery {
     pub node_id: NodeId,
     pub shard_ids: Vec<ShardId>,
     pub query: String,
+    /// Statement the node runs: `query`, rewritten for merging if a SELECT
+    pub statement: Query,
 }
 
 /// Type of query
@@ -59,29 +95,674 @@ pub enum QueryType {
     Aggregate,
 }
 
-/// Type of aggregation
+impl QueryType {
+    /// Classify a parsed statement
+    ///
+    /// Statements that aren't reads or mutations of entities and edges
+    /// (transactions, indexes, settings, ...) count as `Select`.
+    pub fn of(query: &Query) -> Self {
+        match query {
+            Query::Select(select) if select.traverse.is_some() => QueryType::Traverse,
+            Query::Select(select)
+                if select.group_by.is_some()
+                    || select.select.fields.iter().any(|f| matches!(f.expression, Expression::Aggregate(..))) =>
+            {
+                QueryType::Aggregate
+            }
+            Query::Insert(_) | Query::Create(_) => QueryType::Insert,
+            Query::Update(_) | Query::UpdateEdge(_) => QueryType::Update,
+            Query::Delete(_) | Query::DeleteEdge(_) => QueryType::Delete,
+            _ => QueryType::Select,
+        }
+    }
+}
+
+/// Property whose value decides which shard an entity lives on
+///
+/// An integer or string. Entities written outside cluster mode may lack
+/// it; the rebalancer places those by entity ID (see
+/// [`crate::distributed_rebalance::shard_key`]), and no write routes to them by key.
+pub const SHARD_KEY_PROPERTY: &str = "id";
+
+/// How an aggregate column's values from several nodes combine
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AggregationType {
+    /// Sum of the counts
     Count,
+    /// Sum of the sums
     Sum,
-    Avg,
+    /// Sum of the sums over sum of the counts, which nodes return in these columns
+    Avg { sum: String, count: String },
+    /// Least of the minimums
     Min,
+    /// Greatest of the maximums
     Max,
-    GroupBy { fields: Vec<String> },
+}
+
+/// Columns added to sub-queries for merging start with this, and are
+/// dropped from the merged result
+const MERGE_COLUMN_PREFIX: &str = "__merge_";
+
+/// How a statement's results from several nodes combine into one
+///
+/// The default concatenates them, as for mutations.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultMerge {
+    /// Aggregate columns and how each combines; rows with equal group keys
+    /// are merged when the statement groups
+    pub aggregates: Vec<(String, AggregationType)>,
+    /// Columns holding the GROUP BY keys
+    pub group_keys: Vec<String>,
+    /// Whether rows are grouped (by `group_keys`, or all into one)
+    pub grouped: bool,
+    /// ORDER BY columns, each with whether it ascends
+    pub order_by: Vec<(String, bool)>,
+    pub distinct: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+type Row = HashMap<String, Value>;
+
+/// Assignments of one row of an INSERT
+type InsertRow = Vec<(String, Expression)>;
+
+impl ResultMerge {
+    /// Rewrite a SELECT into what each node runs, and how to merge the results
+    ///
+    /// HAVING and DISTINCT aggregates other than MIN and MAX can't be
+    /// computed from per-node results, and are refused.
+    pub fn plan(query: &SelectQuery) -> Result<(SelectQuery, ResultMerge), String> {
+        let grouped = query.group_by.is_some()
+            || query.select.fields.iter().any(|f| matches!(f.expression, Expression::Aggregate(..)));
+        if query.having.is_some() {
+            return Err("HAVING isn't supported in distributed queries".to_string());
+        }
+
+        let mut partial = query.clone();
+        let mut merge = ResultMerge {
+            grouped,
+            distinct: query.select.distinct,
+            offset: query.offset.unwrap_or(0),
+            limit: query.limit,
+            ..Default::default()
+        };
+
+        // Columns are named explicitly, since the fields AVG adds would
+        // shift the positional names of the ones after it
+        let mut fields = Vec::new();
+        for (idx, field) in query.select.fields.iter().enumerate() {
+            let column = field.alias.clone().unwrap_or_else(|| format!("col_{}", idx));
+            let aggregation = match &field.expression {
+                Expression::Wildcard(_) => {
+                    fields.push(field.clone());
+                    continue;
+                }
+                Expression::Aggregate(function, argument, distinct) => Some(match function {
+                    AggregateFunction::Min => AggregationType::Min,
+                    AggregateFunction::Max => AggregationType::Max,
+                    _ if *distinct => {
+                        return Err(format!(
+                            "{}(DISTINCT ...) isn't supported in distributed queries",
+                            format!("{:?}", function).to_uppercase()
+                        ));
+                    }
+                    AggregateFunction::Count => AggregationType::Count,
+                    AggregateFunction::Sum => AggregationType::Sum,
+                    AggregateFunction::Avg => {
+                        let sum = format!("{}sum_{}", MERGE_COLUMN_PREFIX, idx);
+                        let count = format!("{}count_{}", MERGE_COLUMN_PREFIX, idx);
+                        for (function, alias) in [(AggregateFunction::Sum, &sum), (AggregateFunction::Count, &count)] {
+                            fields.push(SelectField {
+                                expression: Expression::Aggregate(function, argument.clone(), false),
+                                alias: Some(alias.clone()),
+                            });
+                        }
+                        merge.aggregates.push((column, AggregationType::Avg { sum, count }));
+                        continue;
+                    }
+                }),
+                _ => None,
+            };
+
+            fields.push(SelectField {
+                expression: field.expression.clone(),
+                alias: Some(column.clone()),
+            });
+            if let Some(aggregation) = aggregation {
+                merge.aggregates.push((column, aggregation));
+            }
+        }
+
+        for (idx, key) in query.group_by.iter().flat_map(|group_by| &group_by.fields).enumerate() {
+            let column = format!("{}group_{}", MERGE_COLUMN_PREFIX, idx);
+            fields.push(SelectField {
+                expression: key.clone(),
+                alias: Some(column.clone()),
+            });
+            merge.group_keys.push(column);
+        }
+
+        for (idx, key) in query.order_by.iter().flat_map(|order_by| &order_by.fields).enumerate() {
+            let column = match sort_column(&key.expression, &query.select.fields) {
+                Some(column) => column,
+                None if grouped => {
+                    return Err("ORDER BY of a distributed aggregate query must name a selected column".to_string());
+                }
+                // A merge column would make otherwise equal rows distinct
+                None if query.select.distinct => {
+                    return Err("ORDER BY keys must be selected when using SELECT DISTINCT".to_string());
+                }
+                None => {
+                    let column = format!("{}sort_{}", MERGE_COLUMN_PREFIX, idx);
+                    fields.push(SelectField {
+                        expression: key.expression.clone(),
+                        alias: Some(column.clone()),
+                    });
+                    column
+                }
+            };
+            merge.order_by.push((column, key.ascending));
+        }
+
+        partial.select.fields = fields;
+        partial.offset = None;
+        if grouped {
+            // Groups are only complete, and so only sortable and countable, once merged
+            partial.order_by = None;
+            partial.limit = None;
+        } else {
+            // Nodes return their first OFFSET + LIMIT rows, already sorted
+            partial.limit = query.limit.map(|limit| limit.saturating_add(merge.offset));
+        }
+
+        Ok((partial, merge))
+    }
+
+    /// Combine the nodes' results into the statement's
+    pub fn merge(&self, results: Vec<QueryResult>) -> Result<QueryResult, String> {
+        let mut merged = QueryResult::default();
+        let mut partials = Vec::with_capacity(results.len());
+        for result in results {
+            // Nodes agree on the columns; their types are worked out below
+            if merged.columns.is_empty() {
+                merged.columns = result.columns;
+            }
+            merged.rows_affected += result.rows_affected;
+            merged.warnings.extend(result.warnings);
+            partials.push(result.rows);
+        }
+
+        let mut rows = if self.grouped {
+            let mut groups = self.combine_groups(partials)?;
+            groups.sort_by(|a, b| self.compare(a, b));
+            groups
+        } else if self.order_by.is_empty() {
+            partials.into_iter().flatten().collect()
+        } else {
+            let columns: Vec<String> = merged.columns.iter().map(|c| c.name.clone()).collect();
+            self.merge_sorted(partials, &columns)
+        };
+
+        // AVG takes the place of the sum it was computed from
+        for (column, aggregation) in &self.aggregates {
+            if let AggregationType::Avg { sum, .. } = aggregation {
+                if let Some(info) = merged.columns.iter_mut().find(|c| &c.name == sum) {
+                    info.name = column.clone();
+                }
+            }
+        }
+        merged.columns.retain(|c| !c.name.starts_with(MERGE_COLUMN_PREFIX));
+        for row in &mut rows {
+            row.retain(|column, _| !column.starts_with(MERGE_COLUMN_PREFIX));
+        }
+
+        if self.distinct {
+            let mut seen = HashSet::new();
+            rows.retain(|row| seen.insert(row_key(row, merged.columns.iter().map(|c| &c.name))));
+        }
+        merged.rows = rows
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        // Typed from the values the result holds, as on a single node
+        for column in &mut merged.columns {
+            column.value_type = merged
+                .rows
+                .iter()
+                .filter_map(|row| row.get(&column.name))
+                .fold(ValueType::Null, |t, v| t.merge(v.value_type()));
+        }
+        Ok(merged)
+    }
+
+    /// Merge rows with the same group key, finishing their averages
+    fn combine_groups(&self, partials: Vec<Vec<Row>>) -> Result<Vec<Row>, String> {
+        let mut groups: Vec<Row> = Vec::new();
+        let mut positions: HashMap<Vec<DistinctKey>, usize> = HashMap::new();
+        for row in partials.into_iter().flatten() {
+            let key = row_key(&row, &self.group_keys);
+            match positions.get(&key) {
+                Some(&position) => {
+                    let group = &mut groups[position];
+                    for (column, aggregation) in &self.aggregates {
+                        match aggregation {
+                            AggregationType::Avg { sum, count } => {
+                                combine(group, &row, sum, aggregation)?;
+                                combine(group, &row, count, &AggregationType::Count)?;
+                            }
+                            aggregation => combine(group, &row, column, aggregation)?,
+                        }
+                    }
+                }
+                None => {
+                    positions.insert(key, groups.len());
+                    groups.push(row);
+                }
+            }
+        }
+
+        for group in &mut groups {
+            for (column, aggregation) in &self.aggregates {
+                if let AggregationType::Avg { sum, count } = aggregation {
+                    let sum = group.get(sum).and_then(Value::as_f64).unwrap_or(0.0);
+                    let average = match group.get(count) {
+                        Some(Value::Integer(count)) if *count > 0 => Value::Float(sum / *count as f64),
+                        _ => Value::Null,
+                    };
+                    group.insert(column.clone(), average);
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Merge rows each node returned sorted, keeping them sorted
+    ///
+    /// Rows that sort equal keep the order of the nodes they came from.
+    /// Under DISTINCT, duplicates are dropped as they merge, so they don't
+    /// count towards LIMIT.
+    fn merge_sorted(&self, mut partials: Vec<Vec<Row>>, columns: &[String]) -> Vec<Row> {
+        let wanted = self.limit.map_or(usize::MAX, |limit| limit.saturating_add(self.offset));
+        let mut positions = vec![0; partials.len()];
+        let mut seen = HashSet::new();
+        let mut merged = Vec::new();
+
+        while merged.len() < wanted {
+            let next = (0..partials.len())
+                .filter(|&i| positions[i] < partials[i].len())
+                .min_by(|&a, &b| self.compare(&partials[a][positions[a]], &partials[b][positions[b]]));
+            let Some(i) = next else { break };
+            let row = std::mem::take(&mut partials[i][positions[i]]);
+            positions[i] += 1;
+            if self.distinct && !seen.insert(row_key(&row, columns)) {
+                continue;
+            }
+            merged.push(row);
+        }
+        merged
+    }
+
+    fn compare(&self, a: &Row, b: &Row) -> std::cmp::Ordering {
+        for (column, ascending) in &self.order_by {
+            let a = a.get(column).unwrap_or(&Value::Null);
+            let b = b.get(column).unwrap_or(&Value::Null);
+            let ordering = a.sort_cmp(b);
+            if ordering.is_ne() {
+                return if *ascending { ordering } else { ordering.reverse() };
+            }
+        }
+        std::cmp::Ordering::Equal
+    }
+}
+
+/// Column an ORDER BY key reads: a SELECT field with the same expression,
+/// or one whose alias is the key's bare name (as the planner resolves them)
+fn sort_column(key: &Expression, fields: &[SelectField]) -> Option<String> {
+    let column = |(idx, field): (usize, &SelectField)| field.alias.clone().unwrap_or_else(|| format!("col_{}", idx));
+    if let Some(found) = fields.iter().enumerate().find(|(_, f)| &f.expression == key) {
+        return Some(column(found));
+    }
+
+    match key {
+        Expression::Property(property) => fields
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.alias.as_ref() == Some(&property.property))
+            .map(column),
+        _ => None,
+    }
+}
+
+/// Fold `row`'s partial aggregate in `column` into `group`'s
+///
+/// An integer COUNT or SUM whose total overflows fails, as it does on a
+/// single node. Given an AVG, `column` holds its sum, which becomes a float
+/// instead.
+fn combine(group: &mut Row, row: &Row, column: &str, aggregation: &AggregationType) -> Result<(), String> {
+    let ours = group.get(column).cloned().unwrap_or(Value::Null);
+    let theirs = row.get(column).cloned().unwrap_or(Value::Null);
+    let combined = match (aggregation, ours, theirs) {
+        (_, Value::Null, value) | (_, value, Value::Null) => value,
+        (AggregationType::Count, Value::Integer(a), Value::Integer(b)) => {
+            Value::Integer(a.checked_add(b).ok_or("Integer overflow in COUNT")?)
+        }
+        (AggregationType::Sum, Value::Integer(a), Value::Integer(b)) => {
+            Value::Integer(a.checked_add(b).ok_or("Integer overflow in SUM")?)
+        }
+        (AggregationType::Avg { .. }, Value::Integer(a), Value::Integer(b)) => {
+            a.checked_add(b).map_or(Value::Float(a as f64 + b as f64), Value::Integer)
+        }
+        (AggregationType::Count | AggregationType::Sum | AggregationType::Avg { .. }, a, b) => {
+            Value::Float(a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0))
+        }
+        (AggregationType::Min, a, b) => if b.sort_cmp(&a).is_lt() { b } else { a },
+        (AggregationType::Max, a, b) => if b.sort_cmp(&a).is_gt() { b } else { a },
+    };
+    group.insert(column.to_string(), combined);
+    Ok(())
+}
+
+/// Hashable identity of a row's values in `columns`
+fn row_key<'a>(row: &Row, columns: impl IntoIterator<Item = &'a String>) -> Vec<DistinctKey> {
+    columns
+        .into_iter()
+        .map(|column| row.get(column).unwrap_or(&Value::Null).distinct_key())
+        .collect()
+}
+
+/// The shard keys a condition limits matching entities to, if it limits them
+///
+/// `id = v` and `id IN (...)` pin the key wherever they appear among AND-ed
+/// conditions; an OR pins it only if both sides do.
+fn shard_keys(condition: &Expression) -> Option<HashSet<String>> {
+    let is_key = |e: &Expression| matches!(e, Expression::Property(p) if p.property == SHARD_KEY_PROPERTY);
+    match condition {
+        Expression::Equal(left, right) => match (left.as_ref(), right.as_ref()) {
+            (key, Expression::Literal(value)) | (Expression::Literal(value), key) if is_key(key) => {
+                shard_key(value).map(|key| HashSet::from([key]))
+            }
+            _ => None,
+        },
+        Expression::In(key, values) if is_key(key) => values.iter().map(shard_key).collect(),
+        Expression::And(left, right) => match (shard_keys(left), shard_keys(right)) {
+            (Some(left), Some(right)) => Some(left.intersection(&right).cloned().collect()),
+            (keys, None) | (None, keys) => keys,
+        },
+        Expression::Or(left, right) => {
+            let mut keys = shard_keys(left)?;
+            keys.extend(shard_keys(right)?);
+            Some(keys)
+        }
+        _ => None,
+    }
+}
+
+/// How a key value is hashed onto a shard
+fn shard_key(value: &Literal) -> Option<String> {
+    match value {
+        Literal::Integer(n) => Some(n.to_string()),
+        Literal::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// How a stored key value is hashed onto a shard, as [`shard_key`] hashes a literal
+fn property_shard_key(value: &PropertyValue) -> Option<String> {
+    match value {
+        PropertyValue::Int(n) => Some(n.to_string()),
+        PropertyValue::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The shard key an INSERT row sets, if it sets one
+///
+/// Fails for a key that isn't an integer or string literal, which can't be
+/// placed before the row is written.
+fn row_shard_key(row: &[(String, Expression)]) -> Result<Option<String>, String> {
+    let Some((_, value)) = row.iter().find(|(property, _)| property == SHARD_KEY_PROPERTY) else {
+        return Ok(None);
+    };
+    match value {
+        Expression::Literal(literal) => shard_key(literal).map(Some),
+        _ => None,
+    }
+    .ok_or_else(|| format!("The shard key '{}' must be an integer or string literal", SHARD_KEY_PROPERTY))
+}
+
+/// Fail if an UPDATE sets the shard key, which would leave the row on a
+/// shard its new key doesn't hash to
+fn check_key_unchanged(update: &UpdateQuery) -> Result<(), String> {
+    if update.set.iter().any(|(property, _)| property == SHARD_KEY_PROPERTY) {
+        return Err(format!("UPDATE can't change the shard key '{}' of a sharded collection", SHARD_KEY_PROPERTY));
+    }
+    Ok(())
+}
+
+/// Which node owns each shard, for a node that only takes writes to its own
+///
+/// Rows are placed by their shard key, the `id` property, as the rebalancer
+/// places them. A write another node owns fails naming that node, so the
+/// client can send it there instead, or through a [`DistributedQueryExecutor`].
+#[derive(Clone)]
+pub struct ShardOwnership {
+    local_id: NodeId,
+    shards: Arc<ShardManager>,
+}
+
+impl ShardOwnership {
+    pub fn new(local_id: NodeId, shards: Arc<ShardManager>) -> Self {
+        Self { local_id, shards }
+    }
+
+    /// The shard a key hashes to, and the node owning it
+    pub fn owner_of(&self, key: &str) -> Result<(ShardId, NodeId), String> {
+        let shard_id = self
+            .shards
+            .get_shard_for_key(key)
+            .ok_or_else(|| format!("No shard for key '{}'", key))?;
+        let owner = self
+            .shards
+            .get_node_for_shard(shard_id)
+            .ok_or_else(|| format!("Shard {} has no owner yet", shard_id))?;
+        Ok((shard_id, owner))
+    }
+
+    /// Fail unless this node owns the shards a write touches
+    ///
+    /// Every row an INSERT adds must set the shard key, to a literal whose
+    /// shard is owned here. An UPDATE or DELETE whose WHERE pins the key
+    /// must pin at least one key owned here; one that doesn't pin it only
+    /// touches this node's rows anyway. An UPDATE may not change the key.
+    /// Writes to a shard being moved are refused, and so are UPDATEs and
+    /// DELETEs that don't pin the key while any shard is. EXPLAIN ANALYZE
+    /// is checked as the statement it runs. Reads and other statements pass.
+    pub fn check_write(&self, statement: &Query) -> Result<(), String> {
+        match statement {
+            Query::Explain(explain) if explain.analyze => self.check_write(&explain.query),
+            Query::Insert(insert) => {
+                for row in &insert.rows {
+                    let key = row_shard_key(row)?.ok_or_else(|| {
+                        format!(
+                            "INSERT into {} must set the shard key '{}' on every row in cluster mode",
+                            insert.collection, SHARD_KEY_PROPERTY
+                        )
+                    })?;
+                    self.check_owner(&key)?;
+                }
+                Ok(())
+            }
+            Query::Update(update) => {
+                check_key_unchanged(update)?;
+                self.check_pinned(&update.where_clause)
+            }
+            Query::Delete(delete) => self.check_pinned(&delete.where_clause),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail unless every row of a bulk insert or import sets a shard key
+    /// owned here, as [`ShardOwnership::check_write`] requires of an INSERT's rows
+    pub fn check_rows(&self, collection: &str, rows: &[Properties]) -> Result<(), String> {
+        for row in rows {
+            let key = match row.get(SHARD_KEY_PROPERTY) {
+                None => {
+                    return Err(format!(
+                        "INSERT into {} must set the shard key '{}' on every row in cluster mode",
+                        collection, SHARD_KEY_PROPERTY
+                    ))
+                }
+                Some(value) => property_shard_key(value).ok_or_else(|| {
+                    format!("The shard key '{}' must be an integer or string", SHARD_KEY_PROPERTY)
+                })?,
+            };
+            self.check_owner(&key)?;
+        }
+        Ok(())
+    }
+
+    fn check_pinned(&self, where_clause: &Option<WhereClause>) -> Result<(), String> {
+        let Some(keys) = where_clause.as_ref().and_then(|w| shard_keys(&w.condition)) else {
+            return match self.shards.fenced_shards().first() {
+                Some(shard_id) => Err(format!(
+                    "Shard {} is being moved; writes that don't pin the shard key '{}' must wait until it's done",
+                    shard_id, SHARD_KEY_PROPERTY
+                )),
+                None => Ok(()),
+            };
+        };
+        let mut keys: Vec<String> = keys.into_iter().collect();
+        keys.sort();
+        for key in &keys {
+            if let Ok((shard_id, _)) = self.owner_of(key) {
+                self.check_unfenced(shard_id, key)?;
+            }
+        }
+
+        let mut foreign = None;
+        for key in keys {
+            match self.check_owner(&key) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    foreign.get_or_insert(e);
+                }
+            }
+        }
+        foreign.map_or(Ok(()), Err)
+    }
+
+    fn check_owner(&self, key: &str) -> Result<(), String> {
+        let (shard_id, owner) = self.owner_of(key)?;
+        if owner == self.local_id {
+            return self.check_unfenced(shard_id, key);
+        }
+        Err(format!(
+            "Not owner of shard {} (key '{}'): owner is node {}; redirect the write to node {}",
+            shard_id, key, owner, owner
+        ))
+    }
+
+    fn check_unfenced(&self, shard_id: ShardId, key: &str) -> Result<(), String> {
+        if self.shards.is_fenced(shard_id) {
+            return Err(format!(
+                "Shard {} (key '{}') is being moved; retry the write once it's done",
+                shard_id, key
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The shards a node answers a read for, among those it holds
+///
+/// An entity is in a shard by its shard key, as the rebalancer places it
+/// (see [`crate::distributed_rebalance::shard_key`]).
+#[derive(Clone)]
+pub struct ShardScope {
+    shards: Arc<ShardManager>,
+    shard_ids: HashSet<ShardId>,
+}
+
+impl ShardScope {
+    pub fn new(shards: Arc<ShardManager>, shard_ids: &[ShardId]) -> Self {
+        Self {
+            shards,
+            shard_ids: shard_ids.iter().copied().collect(),
+        }
+    }
+
+    /// Whether the entity is in one of the shards
+    pub fn contains(&self, entity: &Entity) -> bool {
+        self.shards
+            .get_shard_for_key(&distributed_rebalance::shard_key(entity))
+            .is_some_and(|shard_id| self.shard_ids.contains(&shard_id))
+    }
 }
 
 /// Result from a sub-query
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubQueryResult {
+    /// Node that answered, or last tried
     pub node_id: NodeId,
     pub shard_ids: Vec<ShardId>,
     pub success: bool,
     pub rows_affected: usize,
     pub error: Option<String>,
     pub data: Option<Vec<u8>>, // Serialized result data
+    /// Whether no node holding the shards could be reached, rather than
+    /// one answering with an error
+    pub unreachable: bool,
+}
+
+/// What a query does when some shards' nodes can't be reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PartialResults {
+    /// Fail the query
+    #[default]
+    Strict,
+    /// Merge what the reachable nodes returned, with a warning naming the
+    /// missing shards
+    BestEffort,
+}
+
+/// Retry and partial-failure policy for sub-queries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistributedQueryConfig {
+    /// Attempts per node before moving on to a replica
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for each one after it
+    pub retry_backoff_ms: u64,
+    /// Policy for queries that don't choose their own
+    pub partial_results: PartialResults,
+    /// Role served sub-queries that carry none run with; by default they
+    /// are refused
+    #[serde(default)]
+    pub anonymous_role: Option<Role>,
+}
+
+impl Default for DistributedQueryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_backoff_ms: 50,
+            partial_results: PartialResults::Strict,
+            anonymous_role: None,
+        }
+    }
+}
+
+/// Counters of sub-query retries and fallbacks
+#[derive(Default)]
+struct SubQueryCounters {
+    attempts: AtomicU64,
+    replica_fallbacks: AtomicU64,
 }
 
 /// Distributed query executor
+#[derive(Clone)]
 pub struct DistributedQueryExecutor {
     /// Local node ID
     local_id: NodeId,
@@ -97,6 +778,13 @@ pub struct DistributedQueryExecutor {
 
     /// Query cache for optimization
     query_cache: Arc<RwLock<HashMap<String, DistributedQueryPlan>>>,
+
+    config: DistributedQueryConfig,
+
+    counters: Arc<SubQueryCounters>,
+
+    /// Counter behind the ids given to inserted rows without one
+    next_id: Arc<AtomicU64>,
 }
 
 impl DistributedQueryExecutor {
@@ -113,19 +801,85 @@ impl DistributedQueryExecutor {
             p2p_network,
             local_executor,
             query_cache: Arc::new(RwLock::new(HashMap::new())),
+            config: DistributedQueryConfig::default(),
+            counters: Arc::new(SubQueryCounters::default()),
+            next_id: Arc::new(AtomicU64::new(
+                SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_micros() as u64),
+            )),
         }
     }
 
+    /// Use a retry and partial-failure policy other than the default
+    pub fn with_config(mut self, config: DistributedQueryConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Answer the sub-queries other nodes route here over the P2P network
+    ///
+    /// A sub-query runs with the permissions of the role it carries, or of
+    /// [`DistributedQueryConfig::anonymous_role`] if it carries none (and is
+    /// refused without one). One naming shards reads only their entities.
+    pub fn serve_queries(&self) {
+        let executor = Arc::clone(&self.local_executor);
+        let shard_manager = Arc::clone(&self.shard_manager);
+        let anonymous_role = self.config.anonymous_role.clone();
+        self.p2p_network.register_handler(MessageKind::QueryRequest, move |msg| {
+            let MessageType::QueryRequest { query, statement, role, shards } = &msg.message_type else {
+                return None;
+            };
+            let Some(role) = role.as_ref().or(anonymous_role.as_ref()) else {
+                let message = "Permission denied: sub-query carries no role".to_string();
+                return Some(msg.reply(MessageType::Error { message }));
+            };
+            let session = Session::new(format!("node-{}", msg.sender_id), role.clone(), 60);
+            let executor = executor.as_ref().clone().with_caller(session);
+            let executor = match shards {
+                Some(shard_ids) => executor.with_shard_scope(ShardScope::new(Arc::clone(&shard_manager), shard_ids)),
+                None => executor,
+            };
+            let outcome = match statement {
+                Some(statement) => bincode::deserialize::<Query>(statement)
+                    .map_err(|e| format!("Malformed sub-query: {}", e))
+                    .and_then(|statement| executor.execute_parsed(&statement, query)),
+                None => executor.execute(query),
+            };
+            let reply = outcome
+                .and_then(|result| result.to_bytes())
+                .map_or_else(|message| MessageType::Error { message }, |result| MessageType::QueryResponse { result });
+            Some(msg.reply(reply))
+        });
+    }
+
     /// Execute a distributed query
     pub async fn execute(&self, query: &str) -> Result<QueryResult, String> {
+        self.run(query, None, self.config.partial_results).await
+    }
+
+    /// Execute a distributed query with its own partial-failure policy
+    pub async fn execute_with(&self, query: &str, partial: PartialResults) -> Result<QueryResult, String> {
+        self.run(query, None, partial).await
+    }
+
+    /// Execute a distributed query on behalf of a logged in session
+    ///
+    /// The statement is checked against the session's role before any
+    /// sub-query is routed. Local sub-queries run as the session too, and
+    /// remote ones carry its role for the receiving node to check.
+    pub async fn execute_as(&self, session: &Session, query: &str) -> Result<QueryResult, String> {
+        session.check_access(Access::required_for(&Parser::parse(query)?))?;
+        self.run(query, Some(session), self.config.partial_results).await
+    }
+
+    async fn run(&self, query: &str, caller: Option<&Session>, partial: PartialResults) -> Result<QueryResult, String> {
         // Step 1: Create query plan
         let plan = self.create_query_plan(query)?;
 
         // Step 2: Execute sub-queries in parallel
-        let sub_results = self.execute_sub_queries(&plan).await?;
+        let sub_results = self.execute_sub_queries(&plan, caller).await?;
 
         // Step 3: Aggregate results
-        let final_result = self.aggregate_results(&plan, sub_results)?;
+        let final_result = self.aggregate_results(&plan, sub_results, partial)?;
 
         Ok(final_result)
     }
@@ -140,44 +894,72 @@ impl DistributedQueryExecutor {
             }
         }
 
-        // Parse query to determine type
-        let query_type = self.determine_query_type(query);
+        // Parse once; everything below works from the statement
+        let parsed = Parser::parse(query)?;
+        let query_type = QueryType::of(&parsed);
+
+        // Rows are placed one by one, and may be given ids, so an INSERT's
+        // plan isn't cached
+        match parsed {
+            Query::Insert(insert) => {
+                return Ok(DistributedQueryPlan {
+                    query: query.to_string(),
+                    sub_queries: self.plan_insert(query, insert)?,
+                    query_type,
+                    requires_coordination: false,
+                    merge: ResultMerge::default(),
+                    splittable: true,
+                });
+            }
+            Query::Update(ref update) => check_key_unchanged(update)?,
+            _ => {}
+        }
 
         // Determine affected shards
-        let affected_shards = self.determine_affected_shards(query)?;
+        let affected_shards = self.determine_affected_shards(&parsed);
 
         // Group shards by responsible node
         let mut node_shards: HashMap<NodeId, Vec<ShardId>> = HashMap::new();
 
         for shard_id in affected_shards {
             if let Some(node_id) = self.shard_manager.get_node_for_shard(shard_id) {
-                node_shards.entry(node_id).or_insert_with(Vec::new).push(shard_id);
+                node_shards.entry(node_id).or_default().push(shard_id);
             }
         }
 
+        // A SELECT is rewritten so that rows from several nodes can be
+        // merged, which a read falling back to replicas may need too. One
+        // that can't be merged is still answered by a single node as written.
+        let (statement, merge, splittable) = match parsed {
+            Query::Select(select) => match ResultMerge::plan(&select) {
+                Ok((partial, merge)) => (Query::Select(partial), merge, true),
+                Err(e) if node_shards.len() > 1 => return Err(e),
+                Err(_) => (Query::Select(select), ResultMerge::default(), false),
+            },
+            statement => (statement, ResultMerge::default(), true),
+        };
+
         // Create sub-queries for each node
         let mut sub_queries = Vec::new();
 
         for (node_id, shard_ids) in node_shards {
-            // For simple queries, same query can run on each shard
-            // For complex queries, might need to modify query per shard
+            // Every node runs the same statement over its own shards
             let sub_query = SubQuery {
                 node_id,
                 shard_ids,
                 query: query.to_string(),
+                statement: statement.clone(),
             };
             sub_queries.push(sub_query);
         }
 
-        // Determine if aggregation is needed
-        let aggregation = self.determine_aggregation(query);
-
         let plan = DistributedQueryPlan {
             query: query.to_string(),
             sub_queries,
             query_type,
             requires_coordination: matches!(query_type, QueryType::Aggregate | QueryType::Traverse),
-            aggregation,
+            merge,
+            splittable,
         };
 
         // Cache the plan
@@ -189,37 +971,214 @@ impl DistributedQueryExecutor {
         Ok(plan)
     }
 
+    /// Sub-queries of an INSERT: each row goes to the node owning its shard
+    /// key, given an `id` first if it has none
+    fn plan_insert(&self, query: &str, insert: InsertQuery) -> Result<Vec<SubQuery>, String> {
+        let ownership = ShardOwnership::new(self.local_id, Arc::clone(&self.shard_manager));
+        let mut node_rows: BTreeMap<NodeId, (Vec<ShardId>, Vec<InsertRow>)> = BTreeMap::new();
+
+        for mut row in insert.rows {
+            let key = match row_shard_key(&row)? {
+                Some(key) => key,
+                None => {
+                    let id = self.assign_id();
+                    row.push((SHARD_KEY_PROPERTY.to_string(), Expression::Literal(Literal::Integer(id))));
+                    id.to_string()
+                }
+            };
+            let (shard_id, owner) = ownership.owner_of(&key)?;
+            let (shard_ids, rows) = node_rows.entry(owner).or_default();
+            if !shard_ids.contains(&shard_id) {
+                shard_ids.push(shard_id);
+            }
+            rows.push(row);
+        }
+
+        Ok(node_rows
+            .into_iter()
+            .map(|(node_id, (mut shard_ids, rows))| {
+                shard_ids.sort_unstable();
+                SubQuery {
+                    node_id,
+                    shard_ids,
+                    query: query.to_string(),
+                    statement: Query::Insert(InsertQuery {
+                        collection: insert.collection.clone(),
+                        rows,
+                    }),
+                }
+            })
+            .collect())
+    }
+
+    /// A shard key unique across the cluster: a positive integer with this
+    /// node's id in bits 47 to 62, and below them a counter started from the
+    /// clock in microseconds
+    fn assign_id(&self) -> i64 {
+        let counter = self.next_id.fetch_add(1, Ordering::Relaxed) & ((1 << 47) - 1);
+        (((self.local_id & 0xFFFF) << 47) | counter) as i64
+    }
+
     /// Execute sub-queries in parallel
-    async fn execute_sub_queries(&self, plan: &DistributedQueryPlan) -> Result<Vec<SubQueryResult>, String> {
+    ///
+    /// Results come back in the plan's order, whichever node answers first;
+    /// a read falling back to replicas may bring back several for its sub-query.
+    async fn execute_sub_queries(
+        &self,
+        plan: &DistributedQueryPlan,
+        caller: Option<&Session>,
+    ) -> Result<Vec<SubQueryResult>, String> {
+        let read = plan.is_read();
+        let splittable = plan.splittable;
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, sub_query) in plan.sub_queries.iter().enumerate() {
+            let executor = self.clone();
+            let sub_query = sub_query.clone();
+            let caller = caller.cloned();
+            tasks.spawn(async move { (index, executor.execute_sub_query(sub_query, caller, read, splittable).await) });
+        }
+
+        let mut results = vec![Vec::new(); plan.sub_queries.len()];
+        while let Some(joined) = tasks.join_next().await {
+            let (index, result) = joined.map_err(|e| format!("Sub-query task failed: {}", e))?;
+            results[index] = result?;
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Execute one sub-query, falling back to the shards' replicas if a read's
+    /// node stays unreachable
+    ///
+    /// Each shard is answered once: a replica is asked only for the shards
+    /// still unanswered that it holds, and reads only those. Unless the
+    /// sub-query is `splittable`, a replica must hold all of them. Shards
+    /// no replica answered for come back as one unreachable result.
+    async fn execute_sub_query(
+        &self,
+        sub_query: SubQuery,
+        caller: Option<Session>,
+        read: bool,
+        splittable: bool,
+    ) -> Result<Vec<SubQueryResult>, String> {
+        let caller = caller.as_ref();
+        let mut unreachable = self.execute_on(sub_query.node_id, &sub_query, caller, read).await?;
+        if !read || !unreachable.unreachable {
+            return Ok(vec![unreachable]);
+        }
+
         let mut results = Vec::new();
+        let mut pending = sub_query.shard_ids.clone();
+        for replica in self.replica_nodes(&sub_query) {
+            let shard_ids: Vec<ShardId> = pending
+                .iter()
+                .copied()
+                .filter(|&shard_id| self.shard_manager.get_replicas_for_shard(shard_id).contains(&replica))
+                .collect();
+            if shard_ids.is_empty() || (!splittable && shard_ids.len() < pending.len()) {
+                continue;
+            }
 
-        // Execute local sub-queries
-        for sub_query in &plan.sub_queries {
-            if sub_query.node_id == self.local_id {
-                // Execute locally
-                let result = self.execute_local_sub_query(sub_query)?;
-                results.push(result);
-            } else {
-                // Send to remote node via P2P
-                let result = self.execute_remote_sub_query(sub_query).await?;
-                results.push(result);
+            self.counters.replica_fallbacks.fetch_add(1, Ordering::Relaxed);
+            let fallback = SubQuery {
+                node_id: replica,
+                shard_ids,
+                ..sub_query.clone()
+            };
+            let result = self.execute_on(replica, &fallback, caller, read).await?;
+            if result.unreachable {
+                unreachable = result;
+                continue;
+            }
+            pending.retain(|shard_id| !fallback.shard_ids.contains(shard_id));
+            results.push(result);
+            if pending.is_empty() {
+                return Ok(results);
             }
         }
 
+        results.push(SubQueryResult {
+            shard_ids: pending,
+            ..unreachable
+        });
         Ok(results)
     }
 
-    /// Execute sub-query on local node
-    fn execute_local_sub_query(&self, sub_query: &SubQuery) -> Result<SubQueryResult, String> {
-        match self.local_executor.execute(&sub_query.query) {
+    /// Nodes other than the sub-query's holding replicas of its shards, in
+    /// the order the shards list them
+    fn replica_nodes(&self, sub_query: &SubQuery) -> Vec<NodeId> {
+        let mut nodes = Vec::new();
+        for &shard_id in &sub_query.shard_ids {
+            for node_id in self.shard_manager.get_replicas_for_shard(shard_id) {
+                if node_id != sub_query.node_id && !nodes.contains(&node_id) {
+                    nodes.push(node_id);
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Run a sub-query on a node, retrying with exponential backoff while
+    /// the node can't be reached (reads only)
+    async fn execute_on(
+        &self,
+        node_id: NodeId,
+        sub_query: &SubQuery,
+        caller: Option<&Session>,
+        read: bool,
+    ) -> Result<SubQueryResult, String> {
+        if node_id == self.local_id {
+            self.counters.attempts.fetch_add(1, Ordering::Relaxed);
+            let executor = self.clone();
+            let sub_query = sub_query.clone();
+            let caller = caller.cloned();
+            return tokio::task::spawn_blocking(move || executor.execute_local_sub_query(&sub_query, caller.as_ref(), read))
+                .await
+                .map_err(|e| format!("Sub-query task failed: {}", e))?;
+        }
+
+        let attempts = if read { self.config.max_attempts.max(1) } else { 1 };
+        let mut backoff = Duration::from_millis(self.config.retry_backoff_ms);
+        for attempt in 1..=attempts {
+            self.counters.attempts.fetch_add(1, Ordering::Relaxed);
+            let result = self.execute_remote_sub_query(node_id, sub_query, caller, read).await?;
+            if !result.unreachable || attempt == attempts {
+                return Ok(result);
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        unreachable!("at least one attempt is made")
+    }
+
+    /// Execute sub-query on local node, reading only its shards if a read
+    fn execute_local_sub_query(
+        &self,
+        sub_query: &SubQuery,
+        caller: Option<&Session>,
+        read: bool,
+    ) -> Result<SubQueryResult, String> {
+        let executor = match caller {
+            Some(session) => self.local_executor.as_ref().clone().with_caller(session.clone()),
+            None => self.local_executor.as_ref().clone(),
+        };
+        let executor = match read {
+            true => executor.with_shard_scope(ShardScope::new(Arc::clone(&self.shard_manager), &sub_query.shard_ids)),
+            false => executor,
+        };
+
+        match executor.execute_parsed(&sub_query.statement, &sub_query.query) {
             Ok(query_result) => {
+                let data = query_result.to_bytes()?;
+
                 Ok(SubQueryResult {
                     node_id: self.local_id,
                     shard_ids: sub_query.shard_ids.clone(),
                     success: true,
                     rows_affected: query_result.rows_affected,
                     error: None,
-                    data: None, // Would serialize actual data in production
+                    data: Some(data),
+                    unreachable: false,
                 })
             }
             Err(e) => {
@@ -230,39 +1189,55 @@ impl DistributedQueryExecutor {
                     rows_affected: 0,
                     error: Some(e),
                     data: None,
+                    unreachable: false,
                 })
             }
         }
     }
 
-    /// Execute sub-query on remote node
-    async fn execute_remote_sub_query(&self, sub_query: &SubQuery) -> Result<SubQueryResult, String> {
+    /// Execute sub-query on a remote node
+    async fn execute_remote_sub_query(
+        &self,
+        node_id: NodeId,
+        sub_query: &SubQuery,
+        caller: Option<&Session>,
+        read: bool,
+    ) -> Result<SubQueryResult, String> {
+        let statement = bincode::serialize(&sub_query.statement)
+            .map_err(|e| format!("Failed to serialize sub-query: {}", e))?;
         let message = MessageType::QueryRequest {
             query: sub_query.query.clone(),
+            statement: Some(statement),
+            role: caller.map(|session| session.role.clone()),
+            shards: read.then(|| sub_query.shard_ids.clone()),
         };
 
-        match self.p2p_network.send_message(sub_query.node_id, message).await {
-            Ok(Some(response)) => {
+        match self.p2p_network.send_message(node_id, message).await {
+            Ok(response) => {
                 match response.message_type {
                     MessageType::QueryResponse { result } => {
-                        // Parse result
+                        let query_result = QueryResult::from_bytes(&result)
+                            .map_err(|e| format!("Invalid query response from node {}: {}", node_id, e))?;
+
                         Ok(SubQueryResult {
-                            node_id: sub_query.node_id,
+                            node_id,
                             shard_ids: sub_query.shard_ids.clone(),
                             success: true,
-                            rows_affected: 1, // Parse from result
+                            rows_affected: query_result.rows_affected,
                             error: None,
-                            data: Some(result.into_bytes()),
+                            data: Some(result),
+                            unreachable: false,
                         })
                     }
                     MessageType::Error { message } => {
                         Ok(SubQueryResult {
-                            node_id: sub_query.node_id,
+                            node_id,
                             shard_ids: sub_query.shard_ids.clone(),
                             success: false,
                             rows_affected: 0,
                             error: Some(message),
                             data: None,
+                            unreachable: false,
                         })
                     }
                     _ => {
@@ -270,38 +1245,37 @@ impl DistributedQueryExecutor {
                     }
                 }
             }
-            Ok(None) => {
-                Ok(SubQueryResult {
-                    node_id: sub_query.node_id,
-                    shard_ids: sub_query.shard_ids.clone(),
-                    success: false,
-                    rows_affected: 0,
-                    error: Some("No response from node".to_string()),
-                    data: None,
-                })
-            }
             Err(e) => {
                 Ok(SubQueryResult {
-                    node_id: sub_query.node_id,
+                    node_id,
                     shard_ids: sub_query.shard_ids.clone(),
                     success: false,
                     rows_affected: 0,
                     error: Some(e),
                     data: None,
+                    unreachable: true,
                 })
             }
         }
     }
 
     /// Aggregate results from multiple nodes
+    ///
+    /// Under [`PartialResults::BestEffort`], shards of a read whose nodes
+    /// couldn't be reached are left out and named in the result's warnings;
+    /// a node that answered with an error still fails the query.
     fn aggregate_results(
         &self,
         plan: &DistributedQueryPlan,
         sub_results: Vec<SubQueryResult>,
+        partial: PartialResults,
     ) -> Result<QueryResult, String> {
+        // Writes are always strict: some of their rows may have landed
+        let tolerated = |r: &SubQueryResult| r.unreachable && partial == PartialResults::BestEffort && plan.is_read();
+
         // Check for errors
         let failed_results: Vec<_> = sub_results.iter()
-            .filter(|r| !r.success)
+            .filter(|r| !r.success && !tolerated(r))
             .collect();
 
         if !failed_results.is_empty() {
@@ -311,157 +1285,50 @@ impl DistributedQueryExecutor {
             return Err(format!("Query failed on {} nodes: {}", failed_results.len(), errors.join(", ")));
         }
 
-        // Aggregate based on query type
-        match &plan.aggregation {
-            Some(AggregationType::Count) => {
-                // Sum counts from all nodes
-                let total_count: usize = sub_results.iter()
-                    .map(|r| r.rows_affected)
-                    .sum();
-
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: total_count,
-                })
-            }
-
-            Some(AggregationType::Sum) => {
-                // Sum values from all nodes
-                let total: usize = sub_results.iter()
-                    .map(|r| r.rows_affected)
-                    .sum();
-
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: total,
-                })
-            }
-
-            Some(AggregationType::Avg) => {
-                // Average values from all nodes
-                let total: usize = sub_results.iter()
-                    .map(|r| r.rows_affected)
-                    .sum();
-                let avg = if !sub_results.is_empty() {
-                    total / sub_results.len()
-                } else {
-                    0
-                };
-
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: avg,
-                })
-            }
-
-            Some(AggregationType::Min) | Some(AggregationType::Max) => {
-                // Would need to deserialize actual values and compare
-                // For now, simplified
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: 1,
-                })
-            }
-
-            Some(AggregationType::GroupBy { .. }) => {
-                // Would need to merge group-by results
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: sub_results.len(),
-                })
-            }
-
-            None => {
-                // Simple aggregation: sum rows affected
-                let total_rows: usize = sub_results.iter()
-                    .map(|r| r.rows_affected)
-                    .sum();
-
-                Ok(QueryResult {
-                    rows: Vec::new(),
-                    rows_affected: total_rows,
-                })
-            }
-        }
-    }
-
-    /// Determine query type from query string
-    fn determine_query_type(&self, query: &str) -> QueryType {
-        let query_upper = query.trim().to_uppercase();
-
-        if query_upper.starts_with("SELECT") || query_upper.starts_with("FROM") {
-            if query_upper.contains("COUNT") || query_upper.contains("SUM") ||
-               query_upper.contains("AVG") || query_upper.contains("GROUP BY") {
-                QueryType::Aggregate
-            } else {
-                QueryType::Select
-            }
-        } else if query_upper.starts_with("INSERT") {
-            QueryType::Insert
-        } else if query_upper.starts_with("UPDATE") {
-            QueryType::Update
-        } else if query_upper.starts_with("DELETE") {
-            QueryType::Delete
-        } else if query_upper.contains("TRAVERSE") {
-            QueryType::Traverse
-        } else {
-            QueryType::Select
+        let results = sub_results
+            .iter()
+            .filter_map(|r| Some((r.node_id, r.data.as_ref()?)))
+            .map(|(node_id, data)| {
+                QueryResult::from_bytes(data).map_err(|e| format!("Invalid result from node {}: {}", node_id, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut merged = plan.merge.merge(results)?;
+        for missing in sub_results.iter().filter(|r| tolerated(r)) {
+            merged.warnings.push(format!(
+                "Shards {:?} are missing from the result: node {} is unreachable ({})",
+                missing.shard_ids,
+                missing.node_id,
+                missing.error.as_deref().unwrap_or("no reply"),
+            ));
         }
+        Ok(merged)
     }
 
-    /// Determine which shards are affected by query
-    fn determine_affected_shards(&self, query: &str) -> Result<Vec<ShardId>, String> {
-        // For now, simple implementation: if query has WHERE with specific key, find that shard
-        // Otherwise, query all shards
+    /// Determine which shards are affected by a statement
+    ///
+    /// A single-collection SELECT, UPDATE or DELETE whose WHERE pins the
+    /// shard key to known values only touches those values' shards; any
+    /// other statement touches all of them.
+    fn determine_affected_shards(&self, query: &Query) -> Vec<ShardId> {
+        let where_clause = match query {
+            Query::Select(select) if select.joins.is_empty() && select.traverse.is_none() => &select.where_clause,
+            Query::Update(update) => &update.where_clause,
+            Query::Delete(delete) => &delete.where_clause,
+            _ => &None,
+        };
 
-        if let Some(key) = self.extract_key_from_where(query) {
-            // Query affects single shard
-            if let Some(shard_id) = self.shard_manager.get_shard_for_key(&key) {
-                return Ok(vec![shard_id]);
-            }
+        if let Some(keys) = where_clause.as_ref().and_then(|w| shard_keys(&w.condition)) {
+            let mut shards: Vec<ShardId> =
+                keys.iter().filter_map(|key| self.shard_manager.get_shard_for_key(key)).collect();
+            shards.sort_unstable();
+            shards.dedup();
+            return shards;
         }
 
         // Query affects all shards
         let all_shards = self.shard_manager.get_all_shards();
-        Ok(all_shards.iter().map(|s| s.shard_id).collect())
-    }
-
-    /// Extract key from WHERE clause (simplified)
-    fn extract_key_from_where(&self, query: &str) -> Option<String> {
-        // Simplified: look for "WHERE id = <value>"
-        let query_upper = query.to_uppercase();
-        if let Some(where_pos) = query_upper.find("WHERE") {
-            let where_clause = &query[where_pos + 5..];
-            if let Some(eq_pos) = where_clause.find('=') {
-                let value = where_clause[eq_pos + 1..].trim();
-                // Extract value (remove quotes, semicolon, etc.)
-                let value = value.trim_matches(|c| c == '\'' || c == '"' || c == ';' || c == ' ');
-                return Some(value.to_string());
-            }
-        }
-        None
-    }
-
-    /// Determine aggregation type
-    fn determine_aggregation(&self, query: &str) -> Option<AggregationType> {
-        let query_upper = query.to_uppercase();
-
-        if query_upper.contains("COUNT(") {
-            Some(AggregationType::Count)
-        } else if query_upper.contains("SUM(") {
-            Some(AggregationType::Sum)
-        } else if query_upper.contains("AVG(") {
-            Some(AggregationType::Avg)
-        } else if query_upper.contains("MIN(") {
-            Some(AggregationType::Min)
-        } else if query_upper.contains("MAX(") {
-            Some(AggregationType::Max)
-        } else if query_upper.contains("GROUP BY") {
-            // Extract fields from GROUP BY
-            Some(AggregationType::GroupBy { fields: vec![] })
-        } else {
-            None
-        }
+        all_shards.iter().map(|s| s.shard_id).collect()
     }
 
     /// Get statistics
@@ -471,6 +1338,8 @@ impl DistributedQueryExecutor {
         DistributedQueryStats {
             cached_plans: cache.len(),
             local_node_id: self.local_id,
+            sub_query_attempts: self.counters.attempts.load(Ordering::Relaxed),
+            replica_fallbacks: self.counters.replica_fallbacks.load(Ordering::Relaxed),
         }
     }
 }
@@ -480,6 +1349,10 @@ impl DistributedQueryExecutor {
 pub struct DistributedQueryStats {
     pub cached_plans: usize,
     pub local_node_id: NodeId,
+    /// Times a sub-query was sent to a node, retries and fallbacks included
+    pub sub_query_attempts: u64,
+    /// Times a sub-query went to a replica because its node was unreachable
+    pub replica_fallbacks: u64,
 }
 
 #[cfg(test)]
@@ -487,61 +1360,311 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_determine_query_type() {
+    fn test_query_type_of() {
+        let query_type = |query: &str| QueryType::of(&Parser::parse(query).unwrap());
+
+        assert_eq!(query_type("FROM users SELECT *"), QueryType::Select);
+        assert_eq!(query_type("INSERT INTO users VALUES ({name: 'Alice'})"), QueryType::Insert);
+        assert_eq!(query_type("UPDATE users SET age = 31 WHERE name = 'Alice'"), QueryType::Update);
+        assert_eq!(query_type("DELETE FROM users WHERE age < 18"), QueryType::Delete);
+        assert_eq!(query_type("FROM users SELECT COUNT(*)"), QueryType::Aggregate);
+        assert_eq!(query_type("FROM users SELECT city, name GROUP BY city, name"), QueryType::Aggregate);
+        assert_eq!(query_type("FROM users u TRAVERSE -[:FOLLOWS]-> f SELECT f.name"), QueryType::Traverse);
+
+        // Keywords inside literals don't count
+        assert_eq!(query_type("FROM users WHERE note = 'COUNT(*) GROUP BY' SELECT name"), QueryType::Select);
+    }
+
+    #[test]
+    fn test_unparseable_query_is_not_routed() {
         let executor = create_test_executor();
 
-        assert_eq!(executor.determine_query_type("SELECT * FROM users"), QueryType::Select);
-        assert_eq!(executor.determine_query_type("INSERT INTO users VALUES (...)"), QueryType::Insert);
-        assert_eq!(executor.determine_query_type("UPDATE users SET ..."), QueryType::Update);
-        assert_eq!(executor.determine_query_type("DELETE FROM users WHERE ..."), QueryType::Delete);
-        assert_eq!(executor.determine_query_type("SELECT COUNT(*) FROM users"), QueryType::Aggregate);
-        assert_eq!(executor.determine_query_type("FROM users TRAVERSE ..."), QueryType::Traverse);
+        assert!(executor.create_query_plan("FROM users SELECT WHERE").is_err());
+        assert!(executor.query_cache.read().unwrap().is_empty());
     }
 
     #[test]
-    fn test_determine_aggregation() {
-        let executor = create_test_executor();
+    fn test_merged_select_matches_single_node() {
+        let (single, nodes) = sharded_users();
+
+        for query in [
+            "FROM Users SELECT name, age ORDER BY age DESC, name LIMIT 3 OFFSET 2",
+            "FROM Users SELECT name ORDER BY age, name",
+            "FROM Users SELECT name ORDER BY city DESC, name LIMIT 4",
+            "FROM Users SELECT DISTINCT city ORDER BY city",
+            "FROM Users SELECT DISTINCT city ORDER BY city LIMIT 2 OFFSET 1",
+        ] {
+            let expected = single.execute(query).unwrap();
+            let merged = run_on_nodes(query, &nodes);
+            assert_eq!(merged.columns, expected.columns, "{}", query);
+            assert_eq!(merged.rows, expected.rows, "{}", query);
+        }
 
-        assert_eq!(
-            executor.determine_aggregation("SELECT COUNT(*) FROM users"),
-            Some(AggregationType::Count)
-        );
+        // Without ORDER BY, every node's rows are concatenated
+        let merged = run_on_nodes("FROM Users SELECT name AS name", &nodes);
+        let mut names: Vec<&Value> = merged.rows.iter().map(|row| &row["name"]).collect();
+        names.sort_by(|a, b| a.sort_cmp(b));
+        assert_eq!(names.len(), 7);
+        assert_eq!(names[0], &Value::String("Alice".to_string()));
+    }
 
-        assert_eq!(
-            executor.determine_aggregation("SELECT SUM(age) FROM users"),
-            Some(AggregationType::Sum)
-        );
+    #[test]
+    fn test_merged_aggregates_match_single_node() {
+        let (single, nodes) = sharded_users();
+
+        for query in [
+            "FROM Users SELECT COUNT(*), SUM(age), AVG(age), MIN(age), MAX(name)",
+            "FROM Users WHERE age > 100 SELECT AVG(age) AS mean, COUNT(*) AS n",
+            "FROM Users SELECT city, COUNT(*) AS n, SUM(age) AS total, AVG(age) AS mean, MIN(name), MAX(age) GROUP BY city ORDER BY city",
+            "FROM Users SELECT COUNT(*) AS n, AVG(age) AS mean GROUP BY city ORDER BY mean DESC LIMIT 2",
+            "FROM Users SELECT city, MIN(DISTINCT age) GROUP BY city ORDER BY city LIMIT 1 OFFSET 1",
+        ] {
+            let expected = single.execute(query).unwrap();
+            let merged = run_on_nodes(query, &nodes);
+            assert_eq!(merged.columns, expected.columns, "{}", query);
+            assert_eq!(merged.rows, expected.rows, "{}", query);
+        }
+    }
 
-        assert_eq!(
-            executor.determine_aggregation("SELECT * FROM users"),
-            None
-        );
+    #[test]
+    fn test_merged_sum_overflow_fails_like_single_node() {
+        use crate::Graph;
+
+        let big = i64::MAX / 2 + 1;
+        let executor = || DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+        let single = executor();
+        let nodes = vec![executor(), executor()];
+        for node in nodes.iter().chain([&single, &single]) {
+            node.execute(&format!("INSERT INTO Accounts VALUES ({{balance: {}}})", big)).unwrap();
+        }
+
+        let sum = "FROM Accounts SELECT SUM(balance)";
+        assert_eq!(single.execute(sum).unwrap_err(), "Integer overflow in SUM");
+        let Query::Select(select) = Parser::parse(sum).unwrap() else { unreachable!() };
+        let (partial, merge) = ResultMerge::plan(&select).unwrap();
+        let results = nodes
+            .iter()
+            .map(|node| node.execute_parsed(&Query::Select(partial.clone()), sum).unwrap())
+            .collect();
+        assert_eq!(merge.merge(results).unwrap_err(), "Integer overflow in SUM");
+
+        // The sum behind an AVG doesn't overflow
+        let average = "FROM Accounts SELECT AVG(balance) AS mean";
+        assert_eq!(run_on_nodes(average, &nodes).rows, single.execute(average).unwrap().rows);
     }
 
     #[test]
-    fn test_extract_key_from_where() {
+    fn test_unmergeable_queries_are_refused() {
+        for (query, error) in [
+            (
+                "FROM Users SELECT city GROUP BY city HAVING COUNT(*) > 1",
+                "HAVING isn't supported in distributed queries",
+            ),
+            (
+                "FROM Users SELECT COUNT(DISTINCT city)",
+                "COUNT(DISTINCT ...) isn't supported in distributed queries",
+            ),
+            (
+                "FROM Users SELECT COUNT(*) GROUP BY city ORDER BY city",
+                "ORDER BY of a distributed aggregate query must name a selected column",
+            ),
+        ] {
+            let Query::Select(select) = Parser::parse(query).unwrap() else { unreachable!() };
+            assert_eq!(ResultMerge::plan(&select).unwrap_err(), error);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_results_merges_rows_in_column_order() {
+        use crate::dql_executor::ColumnInfo;
+        use crate::dql_ir::{Value, ValueType};
+
         let executor = create_test_executor();
+        let shard_result = |value: Value| {
+            let result = QueryResult {
+                columns: vec![
+                    ColumnInfo { name: "name".to_string(), value_type: ValueType::String },
+                    ColumnInfo { name: "age".to_string(), value_type: value.value_type() },
+                ],
+                rows: vec![HashMap::from([
+                    ("name".to_string(), Value::String("Alice".to_string())),
+                    ("age".to_string(), value),
+                ])],
+                rows_affected: 0,
+                ..Default::default()
+            };
 
-        assert_eq!(
-            executor.extract_key_from_where("SELECT * FROM users WHERE id = 123"),
-            Some("123".to_string())
-        );
+            SubQueryResult {
+                node_id: 1,
+                shard_ids: vec![],
+                success: true,
+                rows_affected: 0,
+                error: None,
+                data: Some(result.to_bytes().unwrap()),
+                unreachable: false,
+            }
+        };
+
+        let plan = executor.create_query_plan("FROM users SELECT name, age").unwrap();
+        let merged = executor
+            .aggregate_results(
+                &plan,
+                vec![shard_result(Value::Integer(30)), shard_result(Value::Float(30.5))],
+                PartialResults::Strict,
+            )
+            .unwrap();
+
+        let names: Vec<&str> = merged.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["name", "age"]);
+        assert_eq!(merged.columns[1].value_type, ValueType::Float);
+        assert_eq!(merged.row_count(), 2);
+        assert_eq!(merged.row_values(1), Some(vec![Value::String("Alice".to_string()), Value::Float(30.5)]));
+    }
+
+    #[test]
+    fn test_shard_pruning() {
+        let executor = create_test_executor();
+        executor.shard_manager.add_node(1);
+        let shards = |query: &str| executor.determine_affected_shards(&Parser::parse(query).unwrap());
+        let shard_of = |key: &str| executor.shard_manager.get_shard_for_key(key).unwrap();
+        let all = shards("FROM users SELECT name");
+        assert!(all.len() > 2);
 
+        assert_eq!(shards("FROM users WHERE id = 123 SELECT name"), vec![shard_of("123")]);
+        assert_eq!(shards("FROM users u WHERE 'a' = u.id SELECT u.name"), vec![shard_of("a")]);
+
+        // The key predicate can sit anywhere among AND-ed conditions
         assert_eq!(
-            executor.extract_key_from_where("SELECT * FROM users WHERE name = 'Alice'"),
-            Some("Alice".to_string())
+            shards("FROM users u WHERE u.age > 30 AND (u.name = 'Bob' AND u.id = 7) SELECT u.name"),
+            vec![shard_of("7")]
         );
+        assert_eq!(shards("UPDATE users SET age = 1 WHERE age > 3 AND id = 7"), vec![shard_of("7")]);
+
+        let mut expected = vec![shard_of("1"), shard_of("2")];
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(shards("DELETE FROM users WHERE id IN (1, 2)"), expected);
+        assert_eq!(shards("FROM users WHERE id = 1 OR id = 2 SELECT name"), expected);
+
+        // Without a pinned key, every shard is asked
+        assert_eq!(shards("FROM users WHERE name = 'Alice' SELECT name"), all);
+        assert_eq!(shards("FROM users WHERE id = 1 OR age > 3 SELECT name"), all);
+        assert_eq!(shards("FROM users WHERE id > 1 SELECT name"), all);
+    }
+
+    #[test]
+    fn test_shard_ownership_checks_writes() {
+        use crate::distributed_shard::ShardConfig;
 
+        let shards = Arc::new(ShardManager::new(ShardConfig::default()));
+        shards.add_node(1);
+        shards.add_node(2);
+        let ownership = ShardOwnership::new(1, Arc::clone(&shards));
+        let owner = |key: &str| ownership.owner_of(key).unwrap().1;
+        let local = (0..100).map(|n| n.to_string()).find(|key| owner(key) == 1).unwrap();
+        let foreign = (0..100).map(|n| n.to_string()).find(|key| owner(key) == 2).unwrap();
+        let check = |query: String| ownership.check_write(&Parser::parse(&query).unwrap());
+
+        check(format!("INSERT INTO users VALUES ({{id: {}, name: 'Alice'}})", local)).unwrap();
+        let err = check(format!("INSERT INTO users VALUES ({{id: {}, name: 'Bob'}})", foreign)).unwrap_err();
+        let shard = shards.get_shard_for_key(&foreign).unwrap();
         assert_eq!(
-            executor.extract_key_from_where("SELECT * FROM users"),
-            None
+            err,
+            format!(
+                "Not owner of shard {} (key '{}'): owner is node 2; redirect the write to node 2",
+                shard, foreign
+            )
         );
+        assert!(check("INSERT INTO users VALUES ({name: 'Carol'})".to_string()).is_err());
+
+        // Pinning one local key is enough; pinning none touches only local rows
+        check(format!("UPDATE users SET age = 1 WHERE id IN ({}, {})", local, foreign)).unwrap();
+        assert!(check(format!("DELETE FROM users WHERE id = {}", foreign)).is_err());
+        check("DELETE FROM users WHERE age > 3".to_string()).unwrap();
+        assert!(check(format!("UPDATE users SET id = {} WHERE id = {}", foreign, local)).is_err());
+
+        // EXPLAIN ANALYZE runs its statement
+        assert!(check(format!("EXPLAIN ANALYZE DELETE FROM users WHERE id = {}", foreign)).is_err());
+        check(format!("EXPLAIN DELETE FROM users WHERE id = {}", foreign)).unwrap();
+
+        // Bulk inserts and imports are held to an INSERT's rules
+        let row = |key: Option<&str>| {
+            let mut row = Properties::new();
+            if let Some(key) = key {
+                row.insert(SHARD_KEY_PROPERTY.to_string(), PropertyValue::Int(key.parse().unwrap()));
+            }
+            row
+        };
+        ownership.check_rows("users", &[row(Some(&local))]).unwrap();
+        assert!(ownership.check_rows("users", &[row(Some(&local)), row(Some(&foreign))]).is_err());
+        assert!(ownership.check_rows("users", &[row(None)]).is_err());
+
+        // Reads stay permissive
+        check(format!("FROM users WHERE id = {} SELECT name", foreign)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_as_checks_role_before_routing() {
+        use crate::auth::Role;
+
+        let executor = create_test_executor();
+        executor.shard_manager.add_node(1);
+        let reader = Session::new("reader".to_string(), Role::ReadOnly, 3600);
+        let admin = Session::new("admin".to_string(), Role::Admin, 3600);
+        let insert = "INSERT INTO Users VALUES ({name: 'Eve'})";
+
+        let err = executor.execute_as(&reader, insert).await.unwrap_err();
+        assert_eq!(err, "Permission denied: write access required");
+        assert!(executor.query_cache.read().unwrap().is_empty());
+
+        executor.execute_as(&reader, "FROM Users SELECT name").await.unwrap();
+        executor.execute_as(&admin, insert).await.unwrap();
+    }
+
+    /// Seven users in one executor, and spread over three as if over nodes
+    fn sharded_users() -> (DQLExecutor, Vec<DQLExecutor>) {
+        use crate::Graph;
+
+        let executor = || DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+        let users = [
+            "{name: 'Alice', age: 30, city: 'Oslo'}",
+            "{name: 'Bob', age: 25, city: 'Bergen'}",
+            "{name: 'Carol', age: 35, city: 'Oslo'}",
+            "{name: 'Dave', age: 25, city: 'Tromso'}",
+            "{name: 'Erin', age: 41, city: 'Bergen'}",
+            "{name: 'Frank', age: 19, city: 'Oslo'}",
+            "{name: 'Grace', city: 'Bergen'}",
+        ];
+
+        let single = executor();
+        let nodes = vec![executor(), executor(), executor()];
+        for (i, user) in users.iter().enumerate() {
+            let insert = format!("INSERT INTO Users VALUES ({})", user);
+            single.execute(&insert).unwrap();
+            nodes[i % nodes.len()].execute(&insert).unwrap();
+        }
+        (single, nodes)
+    }
+
+    /// Run a SELECT on each node the way the coordinator would, and merge the results
+    fn run_on_nodes(query: &str, nodes: &[DQLExecutor]) -> QueryResult {
+        let Query::Select(select) = Parser::parse(query).unwrap() else { unreachable!() };
+        let (partial, merge) = ResultMerge::plan(&select).unwrap();
+        let statement = Query::Select(partial);
+
+        // Results travel between nodes encoded
+        let results = nodes
+            .iter()
+            .map(|node| node.execute_parsed(&statement, query).unwrap().to_bytes().unwrap())
+            .map(|bytes| QueryResult::from_bytes(&bytes).unwrap())
+            .collect();
+        merge.merge(results).unwrap()
     }
 
     // Helper function to create test executor
     fn create_test_executor() -> DistributedQueryExecutor {
         use crate::{Graph, DQLExecutor};
-        use crate::distributed_topology::{NodeAddress, TopologyConfig};
+        use crate::distributed_topology::NodeAddress;
         use crate::distributed_shard::ShardConfig;
         use crate::distributed_p2p::P2PConfig;
 
@@ -0,0 +1,567 @@
+//! Shard Rebalancing for Distributed Deed Database
+//!
+//! Carries out the [`RebalanceOperation`]s a [`ShardManager`] plans when the
+//! cluster changes, so that shard data lives where the assignments say.
+//!
+//! An entity belongs to the shard its shard key hashes to: its `id` property
+//! ([`SHARD_KEY_PROPERTY`]) when that is an integer or string, its entity ID
+//! otherwise. An edge belongs with its source entity, wherever its target
+//! lives. Entity and edge IDs are taken to be unique across the cluster, so
+//! data keeps its IDs as it moves.
+//!
+//! For each copy or move, the [`Rebalancer`]:
+//! 1. Fences the shard on both nodes, which refuse writes to it until done
+//! 2. Streams the shard from the source node to the destination in batches,
+//!    remembering the last entity sent so an interrupted transfer resumes
+//! 3. Checks both nodes' digests (counts and checksum) of the shard agree
+//! 4. Flips the shard's assignment
+//! 5. For a move, drops the source node's copy, and lifts the fences
+//!
+//! Transfers of different shards run concurrently, up to a limit, and share
+//! an optional entity-rate and bandwidth budget.
+
+use crate::distributed_p2p::{MessageKind, MessageType, P2PNetwork, ShardDataOp};
+use crate::distributed_query::SHARD_KEY_PROPERTY;
+use crate::distributed_shard::{RebalanceOperation, RebalanceType, ShardId, ShardManager};
+use crate::distributed_topology::NodeId;
+use crate::dql_executor::DQLExecutor;
+use crate::graph::{Edge, Entity, Graph};
+use crate::types::{EntityId, PropertyValue};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long fencing a shard waits for open transactions to end
+const FENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Part of a shard's data, in entity ID order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardBatch {
+    pub shard_id: ShardId,
+    pub entities: Vec<Entity>,
+    /// The entities' outgoing edges
+    pub edges: Vec<Edge>,
+    /// Whether the shard has no entities after these
+    pub last: bool,
+}
+
+/// Count and checksum of a shard's data on a node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardDigest {
+    pub entities: usize,
+    pub edges: usize,
+    /// Order-independent checksum of the entities' and edges' IDs, types,
+    /// endpoints and properties
+    pub checksum: u64,
+}
+
+/// The key an entity's shard is chosen by
+pub fn shard_key(entity: &Entity) -> String {
+    match entity.properties.get(SHARD_KEY_PROPERTY) {
+        Some(PropertyValue::Int(n)) => n.to_string(),
+        Some(PropertyValue::String(s)) => s.clone(),
+        _ => entity.id.as_u64().to_string(),
+    }
+}
+
+/// The shards of a node's graph
+///
+/// A shard's data is read, stored and dropped only while it's fenced, which
+/// refuses writes to it (see [`ShardManager::set_fenced`]) and keeps its
+/// entity IDs at hand, so no operation scans the graph but the fence. Data
+/// is stored and dropped through the node's executor, which logs it to the
+/// WAL and replication and keeps the indexes current.
+pub struct LocalShards {
+    executor: DQLExecutor,
+    shards: Arc<ShardManager>,
+    /// Entity IDs of each fenced shard
+    fenced: Mutex<HashMap<ShardId, BTreeSet<u64>>>,
+    /// Answer peers whose identity the network didn't verify
+    unverified_peers: bool,
+}
+
+impl LocalShards {
+    pub fn new(executor: DQLExecutor, shards: Arc<ShardManager>) -> Self {
+        Self {
+            executor,
+            shards,
+            fenced: Mutex::new(HashMap::new()),
+            unverified_peers: false,
+        }
+    }
+
+    /// Answer `ShardDataRequest`s from any peer, not only ones whose
+    /// certificate matched their node's, e.g. for local testing over plaintext
+    pub fn with_unverified_peers(mut self) -> Self {
+        self.unverified_peers = true;
+        self
+    }
+
+    fn shard_of(&self, entity: &Entity) -> Option<ShardId> {
+        self.shards.get_shard_for_key(&shard_key(entity))
+    }
+
+    /// The fenced shard's entity IDs after `after`, in order
+    fn ids_after(&self, shard_id: ShardId, after: Option<EntityId>) -> Result<Vec<u64>, String> {
+        let fenced = self.fenced.lock().unwrap();
+        let ids = fenced.get(&shard_id).ok_or_else(|| format!("Shard {} isn't fenced for a transfer", shard_id))?;
+        let start = after.map_or(0, |after| after.as_u64().saturating_add(1));
+        Ok(ids.range(start..).copied().collect())
+    }
+
+    fn outgoing_edges(graph: &Graph, id: EntityId) -> Vec<Edge> {
+        graph
+            .get_outgoing_neighbors(id, None)
+            .into_iter()
+            .filter_map(|(_, edge_id)| graph.get_edge(edge_id))
+            .collect()
+    }
+
+    /// Refuse writes to the shard and gather its entity IDs, or take writes
+    /// again once it has moved
+    ///
+    /// Fencing waits for the transactions open at the time to end, so none
+    /// of their writes to the shard is missed.
+    pub fn fence(&self, shard_id: ShardId, fenced: bool) -> Result<(), String> {
+        if !fenced {
+            self.fenced.lock().unwrap().remove(&shard_id);
+            self.shards.set_fenced(shard_id, false);
+            return Ok(());
+        }
+
+        self.shards.set_fenced(shard_id, true);
+        if let Err(e) = self.executor.wait_for_open_transactions(FENCE_TIMEOUT) {
+            self.shards.set_fenced(shard_id, false);
+            return Err(format!("Can't fence shard {}: {}", shard_id, e));
+        }
+        let graph = self.executor.graph().read().unwrap();
+        let ids = graph
+            .list_collections()
+            .into_iter()
+            .flat_map(|(collection, _)| graph.iter_collection(&collection).collect::<Vec<_>>())
+            .filter(|entity| self.shard_of(entity) == Some(shard_id))
+            .map(|entity| entity.id.as_u64())
+            .collect();
+        self.fenced.lock().unwrap().insert(shard_id, ids);
+        Ok(())
+    }
+
+    /// Up to `limit` of the fenced shard's entities after `after`, with their outgoing edges
+    pub fn fetch(&self, shard_id: ShardId, after: Option<EntityId>, limit: usize) -> Result<ShardBatch, String> {
+        let ids = self.ids_after(shard_id, after)?;
+        let last = ids.len() <= limit;
+        let graph = self.executor.graph().read().unwrap();
+        let entities: Vec<Entity> = ids.iter().take(limit).filter_map(|&id| graph.get_entity(EntityId::new(id))).collect();
+        let edges = entities.iter().flat_map(|entity| Self::outgoing_edges(&graph, entity.id)).collect();
+
+        Ok(ShardBatch { shard_id, entities, edges, last })
+    }
+
+    /// Store a batch of the fenced shard, replacing entities already here
+    /// and their outgoing edges; storing one twice is harmless
+    pub fn store(&self, batch: &ShardBatch) -> Result<(), String> {
+        self.ids_after(batch.shard_id, None)?;
+        if let Some(entity) = batch.entities.iter().find(|entity| self.shard_of(entity) != Some(batch.shard_id)) {
+            return Err(format!("Entity {} isn't in shard {}", entity.id.as_u64(), batch.shard_id));
+        }
+
+        // Transfers run concurrently, each writing in a session of its own
+        self.executor.new_session().store_with_ids(batch.entities.clone(), batch.edges.clone())?;
+        if let Some(ids) = self.fenced.lock().unwrap().get_mut(&batch.shard_id) {
+            ids.extend(batch.entities.iter().map(|entity| entity.id.as_u64()));
+        }
+        Ok(())
+    }
+
+    /// Count and checksum the fenced shard's data
+    pub fn digest(&self, shard_id: ShardId) -> Result<ShardDigest, String> {
+        let ids = self.ids_after(shard_id, None)?;
+        let graph = self.executor.graph().read().unwrap();
+        let mut digest = ShardDigest::default();
+        for entity in ids.into_iter().filter_map(|id| graph.get_entity(EntityId::new(id))) {
+            digest.entities += 1;
+            digest.checksum = digest.checksum.wrapping_add(hash_item(|hasher| {
+                entity.id.hash(hasher);
+                entity.entity_type.hash(hasher);
+                hash_properties(&entity.properties, hasher);
+            }));
+
+            for edge in Self::outgoing_edges(&graph, entity.id) {
+                digest.edges += 1;
+                digest.checksum = digest.checksum.wrapping_add(hash_item(|hasher| {
+                    edge.id.hash(hasher);
+                    (edge.source, edge.target).hash(hasher);
+                    edge.edge_type.hash(hasher);
+                    hash_properties(&edge.properties, hasher);
+                }));
+            }
+        }
+        Ok(digest)
+    }
+
+    /// Drop the fenced shard's entities and their outgoing edges
+    ///
+    /// Edges into them from other shards' entities stay with their sources.
+    pub fn delete(&self, shard_id: ShardId) -> Result<(), String> {
+        let ids: Vec<EntityId> = self.ids_after(shard_id, None)?.into_iter().map(EntityId::new).collect();
+        self.executor.new_session().evict_entities(&ids)?;
+        if let Some(ids) = self.fenced.lock().unwrap().get_mut(&shard_id) {
+            ids.clear();
+        }
+        Ok(())
+    }
+
+    /// Answer other nodes' `ShardDataRequest`s on `network`
+    ///
+    /// Only peers the network verified by their certificates are answered,
+    /// unless [`LocalShards::with_unverified_peers`].
+    pub fn serve(self: &Arc<Self>, network: &P2PNetwork) {
+        let shards = Arc::clone(self);
+        network.register_handler(MessageKind::ShardDataRequest, move |msg| {
+            let MessageType::ShardDataRequest { shard_id, op } = &msg.message_type else {
+                return None;
+            };
+            let reply = if !msg.sender_verified && !shards.unverified_peers {
+                MessageType::Error {
+                    message: format!("Permission denied: node {} isn't verified by its certificate", msg.sender_id),
+                }
+            } else {
+                shards.answer(*shard_id, op).map_or_else(
+                    |message| MessageType::Error { message },
+                    |data| MessageType::ShardDataResponse { shard_id: *shard_id, data },
+                )
+            };
+            Some(msg.reply(reply))
+        });
+    }
+
+    fn answer(&self, shard_id: ShardId, op: &ShardDataOp) -> Result<Vec<u8>, String> {
+        match op {
+            ShardDataOp::Fetch { after, limit } => encode(&self.fetch(shard_id, after.map(EntityId::new), *limit)?),
+            ShardDataOp::Store { batch } => {
+                let batch: ShardBatch = decode(batch)?;
+                if batch.shard_id != shard_id {
+                    return Err(format!("Batch of shard {} sent as shard {}", batch.shard_id, shard_id));
+                }
+                self.store(&batch)?;
+                Ok(Vec::new())
+            }
+            ShardDataOp::Digest => encode(&self.digest(shard_id)?),
+            ShardDataOp::Delete => {
+                self.delete(shard_id)?;
+                Ok(Vec::new())
+            }
+            ShardDataOp::Fence { fenced } => {
+                self.fence(shard_id, *fenced)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+fn hash_item(write: impl FnOnce(&mut DefaultHasher)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    write(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash properties independently of their map's order
+fn hash_properties(properties: &crate::types::Properties, hasher: &mut DefaultHasher) {
+    let mut entries: Vec<_> = properties.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, value) in entries {
+        key.hash(hasher);
+        value.distinct_key().hash(hasher);
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    bincode::serialize(value).map_err(|e| format!("Serialization error: {}", e))
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T, String> {
+    bincode::deserialize(bytes).map_err(|e| format!("Deserialization error: {}", e))
+}
+
+/// How the [`Rebalancer`] reaches nodes' shard data
+pub trait ShardTransport: Send + Sync {
+    /// Up to `limit` of the shard's entities on `node` after `after`, with their outgoing edges
+    fn fetch(&self, node: NodeId, shard_id: ShardId, after: Option<EntityId>, limit: usize) -> Result<ShardBatch, String>;
+
+    /// Store a batch on `node`
+    fn store(&self, node: NodeId, batch: &ShardBatch) -> Result<(), String>;
+
+    /// Count and checksum the shard's data on `node`
+    fn digest(&self, node: NodeId, shard_id: ShardId) -> Result<ShardDigest, String>;
+
+    /// Drop the shard's data on `node`
+    fn delete(&self, node: NodeId, shard_id: ShardId) -> Result<(), String>;
+
+    /// Refuse writes to the shard on `node` while it moves, or take them again
+    fn fence(&self, node: NodeId, shard_id: ShardId, fenced: bool) -> Result<(), String>;
+}
+
+/// [`ShardTransport`] over the P2P network, to nodes serving their [`LocalShards`]
+///
+/// Each call blocks on `runtime`, so it must be made from outside the
+/// runtime; the [`Rebalancer`] runs transfers on threads of its own.
+pub struct P2PShardTransport {
+    network: Arc<P2PNetwork>,
+    runtime: tokio::runtime::Handle,
+    /// This node's own shards, reached without the network
+    local: Option<(NodeId, Arc<LocalShards>)>,
+}
+
+impl P2PShardTransport {
+    pub fn new(network: Arc<P2PNetwork>, runtime: tokio::runtime::Handle) -> Self {
+        Self { network, runtime, local: None }
+    }
+
+    /// Reach node `node_id`'s shards directly, as this node's own
+    pub fn with_local_shards(mut self, node_id: NodeId, shards: Arc<LocalShards>) -> Self {
+        self.local = Some((node_id, shards));
+        self
+    }
+
+    fn request(&self, node: NodeId, shard_id: ShardId, op: ShardDataOp) -> Result<Vec<u8>, String> {
+        if let Some((_, shards)) = self.local.as_ref().filter(|(local_id, _)| *local_id == node) {
+            return shards.answer(shard_id, &op);
+        }
+
+        let request = MessageType::ShardDataRequest { shard_id, op };
+        let reply = self.runtime.block_on(self.network.send_message(node, request))?;
+        match reply.message_type {
+            MessageType::ShardDataResponse { data, .. } => Ok(data),
+            MessageType::Error { message } => Err(message),
+            other => Err(format!("Unexpected reply from node {}: {:?}", node, other.kind())),
+        }
+    }
+}
+
+impl ShardTransport for P2PShardTransport {
+    fn fetch(&self, node: NodeId, shard_id: ShardId, after: Option<EntityId>, limit: usize) -> Result<ShardBatch, String> {
+        let op = ShardDataOp::Fetch { after: after.map(|id| id.as_u64()), limit };
+        decode(&self.request(node, shard_id, op)?)
+    }
+
+    fn store(&self, node: NodeId, batch: &ShardBatch) -> Result<(), String> {
+        let op = ShardDataOp::Store { batch: encode(batch)? };
+        self.request(node, batch.shard_id, op).map(|_| ())
+    }
+
+    fn digest(&self, node: NodeId, shard_id: ShardId) -> Result<ShardDigest, String> {
+        decode(&self.request(node, shard_id, ShardDataOp::Digest)?)
+    }
+
+    fn delete(&self, node: NodeId, shard_id: ShardId) -> Result<(), String> {
+        self.request(node, shard_id, ShardDataOp::Delete).map(|_| ())
+    }
+
+    fn fence(&self, node: NodeId, shard_id: ShardId, fenced: bool) -> Result<(), String> {
+        self.request(node, shard_id, ShardDataOp::Fence { fenced }).map(|_| ())
+    }
+}
+
+/// Limits on a rebalance
+#[derive(Debug, Clone)]
+pub struct RebalanceConfig {
+    /// Shards transferred at once
+    pub max_concurrent_transfers: usize,
+    /// Entities per batch
+    pub batch_size: usize,
+    /// Entities sent per second, across all transfers
+    pub max_entities_per_sec: Option<u64>,
+    /// Batch bytes sent per second, across all transfers
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_transfers: 4,
+            batch_size: 500,
+            max_entities_per_sec: None,
+            max_bytes_per_sec: None,
+        }
+    }
+}
+
+/// Executes rebalance operations, tracking progress in the [`ShardManager`]
+pub struct Rebalancer {
+    shards: Arc<ShardManager>,
+    transport: Arc<dyn ShardTransport>,
+    config: RebalanceConfig,
+    /// When the shared entity and byte budget next has room
+    budget: Mutex<Instant>,
+}
+
+impl Rebalancer {
+    pub fn new(shards: Arc<ShardManager>, transport: Arc<dyn ShardTransport>) -> Self {
+        Self {
+            shards,
+            transport,
+            config: RebalanceConfig::default(),
+            budget: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn with_config(mut self, config: RebalanceConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Carry out operations, those of one shard in order and different
+    /// shards' concurrently
+    ///
+    /// A shard whose transfer fails keeps its assignment, and the rest go
+    /// on. Running the [`ShardManager::plan_rebalance`] operations again
+    /// retries it, resuming from the last batch that arrived.
+    pub fn run(&self, operations: Vec<RebalanceOperation>) -> Result<(), String> {
+        let mut by_shard: BTreeMap<ShardId, Vec<RebalanceOperation>> = BTreeMap::new();
+        for operation in operations {
+            by_shard.entry(operation.shard_id).or_default().push(operation);
+        }
+        let total = by_shard.len();
+        self.shards.track_rebalance(|progress| {
+            *progress = Default::default();
+            progress.pending = total;
+        });
+
+        let queue = Mutex::new(by_shard.into_iter());
+        let failures = Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            for _ in 0..self.config.max_concurrent_transfers.max(1) {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((shard_id, operations)) = next else {
+                        break;
+                    };
+                    self.shards.track_rebalance(|progress| {
+                        progress.pending -= 1;
+                        progress.in_flight += 1;
+                    });
+
+                    let outcome = operations.iter().try_for_each(|operation| self.execute(operation));
+                    self.shards.track_rebalance(|progress| {
+                        progress.in_flight -= 1;
+                        if outcome.is_ok() {
+                            progress.done += 1;
+                        } else {
+                            progress.failed += 1;
+                        }
+                    });
+                    if let Err(e) = outcome {
+                        failures.lock().unwrap().push(format!("shard {}: {}", shard_id, e));
+                    }
+                });
+            }
+        });
+
+        let failures = failures.into_inner().unwrap();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} of {} shard transfers failed: {}", failures.len(), total, failures.join("; ")))
+        }
+    }
+
+    /// Carry out an operation with the shard fenced on the nodes it touches
+    fn execute(&self, operation: &RebalanceOperation) -> Result<(), String> {
+        let nodes = match operation.operation_type {
+            RebalanceType::Delete => vec![operation.from_node],
+            RebalanceType::Copy | RebalanceType::Move => vec![operation.from_node, operation.to_node],
+        };
+        let mut fenced = Vec::new();
+        let mut outcome = nodes.iter().try_for_each(|&node| {
+            self.transport.fence(node, operation.shard_id, true)?;
+            fenced.push(node);
+            Ok(())
+        });
+        if outcome.is_ok() {
+            outcome = self.execute_fenced(operation);
+        }
+
+        for node in fenced.into_iter().rev() {
+            let unfenced = self.transport.fence(node, operation.shard_id, false);
+            outcome = outcome.and(unfenced);
+        }
+        outcome
+    }
+
+    fn execute_fenced(&self, operation: &RebalanceOperation) -> Result<(), String> {
+        match operation.operation_type {
+            RebalanceType::Copy => {
+                self.transfer(operation)?;
+                self.shards.complete_operation(operation)
+            }
+            RebalanceType::Move => {
+                self.transfer(operation)?;
+                self.shards.complete_operation(operation)?;
+                self.transport.delete(operation.from_node, operation.shard_id)
+            }
+            RebalanceType::Delete => {
+                // Stop routing to the copy before dropping it
+                self.shards.complete_operation(operation)?;
+                self.transport.delete(operation.from_node, operation.shard_id)
+            }
+        }
+    }
+
+    /// Stream a shard to the destination and check the copy matches
+    fn transfer(&self, operation: &RebalanceOperation) -> Result<(), String> {
+        let RebalanceOperation { shard_id, from_node, to_node, .. } = *operation;
+
+        let mut after = self.shards.transfer_checkpoint(shard_id, to_node);
+        loop {
+            let batch = self.transport.fetch(from_node, shard_id, after, self.config.batch_size.max(1))?;
+            let bytes = bincode::serialized_size(&batch).unwrap_or(0);
+            self.throttle(batch.entities.len() as u64, bytes);
+            self.transport.store(to_node, &batch)?;
+
+            if let Some(entity) = batch.entities.last() {
+                after = Some(entity.id);
+                self.shards.set_transfer_checkpoint(shard_id, to_node, after);
+            }
+            if batch.last {
+                break;
+            }
+        }
+
+        // A mismatched copy starts over next time
+        self.shards.set_transfer_checkpoint(shard_id, to_node, None);
+        let source = self.transport.digest(from_node, shard_id)?;
+        let copy = self.transport.digest(to_node, shard_id)?;
+        if source != copy {
+            return Err(format!(
+                "Copy on node {} doesn't match node {}'s ({:?} != {:?})",
+                to_node, from_node, copy, source
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wait until the shared budget has room for a batch
+    fn throttle(&self, entities: u64, bytes: u64) {
+        let mut cost: f64 = 0.0;
+        if let Some(rate) = self.config.max_entities_per_sec.filter(|&rate| rate > 0) {
+            cost = cost.max(entities as f64 / rate as f64);
+        }
+        if let Some(rate) = self.config.max_bytes_per_sec.filter(|&rate| rate > 0) {
+            cost = cost.max(bytes as f64 / rate as f64);
+        }
+        if cost <= 0.0 {
+            return;
+        }
+
+        let start = {
+            let mut budget = self.budget.lock().unwrap();
+            let start = (*budget).max(Instant::now());
+            *budget = start + Duration::from_secs_f64(cost);
+            start
+        };
+        std::thread::sleep(start.saturating_duration_since(Instant::now()));
+    }
+}
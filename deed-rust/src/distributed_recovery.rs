@@ -10,9 +10,9 @@ use crate::distributed_topology::NodeId;
 use crate::distributed_shard::{ShardManager, ShardId};
 use crate::distributed_partition::PartitionManager;
 use serde::{Serialize, Deserialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 /// Recovery action type
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -224,9 +224,7 @@ impl FailureRecoveryManager {
 
         // Find node with least load
         reachable_nodes
-            .into_iter()
-            .filter(|id| !failed_nodes.contains(id) && *id != self.local_id)
-            .next()
+            .into_iter().find(|id| !failed_nodes.contains(id) && *id != self.local_id)
     }
 
     /// Start recovery execution loop
@@ -8,13 +8,17 @@
 //! - Automatic shard assignment based on key hash
 //! - Shard rebalancing when nodes join/leave
 //! - Replication factor support (multiple copies of each shard)
+//! - Stable, versioned key hashing ([`KeyHasher`]), so every node and build agrees
+//!
+//! A shard's assignment only changes once its data is where the new
+//! assignment says: [`ShardManager::join_node`] plans the transfers, and
+//! each completed one flips the assignment (see `distributed_rebalance`).
 
 use crate::distributed_topology::NodeId;
-use std::collections::{HashMap, BTreeMap};
+use crate::types::EntityId;
+use std::collections::{HashMap, HashSet, BTreeMap};
 use std::sync::{Arc, RwLock};
 use serde::{Serialize, Deserialize};
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
 
 /// Shard identifier
 pub type ShardId = u64;
@@ -38,6 +42,9 @@ pub struct ShardConfig {
     /// Should be >> number of nodes for better distribution
     /// Typical: 1024 or 4096
     pub total_shards: usize,
+
+    /// How keys and virtual nodes are hashed; every node must use the same
+    pub hasher: KeyHasher,
 }
 
 impl Default for ShardConfig {
@@ -46,10 +53,190 @@ impl Default for ShardConfig {
             virtual_nodes_per_node: 150,
             replication_factor: 3,
             total_shards: 1024,
+            hasher: KeyHasher::default(),
+        }
+    }
+}
+
+/// How keys and virtual nodes are hashed
+///
+/// Every node of a cluster must hash alike, or they disagree about who owns
+/// a key, so the scheme is spelled out here rather than left to std (whose
+/// `DefaultHasher` may change between Rust releases) and versioned: it is
+/// stored as its version byte, and moving a cluster to another one is a
+/// deliberate [`ShardManager::migrate_hasher`].
+///
+/// A key is hashed as its UTF-8 bytes, and a virtual node as its node ID
+/// then its index, each a little-endian u64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum KeyHasher {
+    /// Version 1: SipHash-1-3 with an all-zero key, keys followed by a 0xff
+    /// byte; what `DefaultHasher` computed when this was written, so
+    /// placements made before versioning still hold
+    #[default]
+    SipHash13,
+    /// Version 2: xxHash64 with seed 0
+    XxHash64,
+}
+
+impl KeyHasher {
+    /// Version byte of the scheme
+    pub fn version(self) -> u8 {
+        match self {
+            KeyHasher::SipHash13 => 1,
+            KeyHasher::XxHash64 => 2,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Result<Self, String> {
+        match version {
+            1 => Ok(KeyHasher::SipHash13),
+            2 => Ok(KeyHasher::XxHash64),
+            _ => Err(format!("Unknown key hashing scheme version {}", version)),
+        }
+    }
+
+    /// Hash a key to a ring position
+    pub fn hash_key(self, key: &str) -> HashPosition {
+        match self {
+            KeyHasher::SipHash13 => {
+                let mut bytes = Vec::with_capacity(key.len() + 1);
+                bytes.extend_from_slice(key.as_bytes());
+                bytes.push(0xff);
+                siphash13(&bytes)
+            }
+            KeyHasher::XxHash64 => xxhash64(key.as_bytes()),
+        }
+    }
+
+    /// Hash a virtual node to a ring position
+    pub fn hash_vnode(self, node_id: NodeId, vnode_idx: usize) -> HashPosition {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&node_id.to_le_bytes());
+        bytes[8..].copy_from_slice(&(vnode_idx as u64).to_le_bytes());
+        match self {
+            KeyHasher::SipHash13 => siphash13(&bytes),
+            KeyHasher::XxHash64 => xxhash64(&bytes),
         }
     }
 }
 
+impl From<KeyHasher> for u8 {
+    fn from(hasher: KeyHasher) -> u8 {
+        hasher.version()
+    }
+}
+
+impl TryFrom<u8> for KeyHasher {
+    type Error = String;
+
+    fn try_from(version: u8) -> Result<Self, String> {
+        KeyHasher::from_version(version)
+    }
+}
+
+/// SipHash-1-3 of `bytes` with an all-zero key
+fn siphash13(bytes: &[u8]) -> u64 {
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    // "somepseudorandomlygeneratedbytes", xored with the (zero) key
+    let mut v = [0x736f_6d65_7073_6575, 0x646f_7261_6e64_6f6d, 0x6c79_6765_6e65_7261, 0x7465_6462_7974_6573];
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        round(&mut v);
+        v[0] ^= m;
+    }
+
+    let mut last = (bytes.len() as u64) << 56;
+    for (i, &byte) in chunks.remainder().iter().enumerate() {
+        last |= (byte as u64) << (8 * i);
+    }
+    v[3] ^= last;
+    round(&mut v);
+    v[0] ^= last;
+
+    v[2] ^= 0xff;
+    for _ in 0..3 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+const XXH_PRIME_1: u64 = 0x9E37_79B1_85EB_CA87;
+const XXH_PRIME_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const XXH_PRIME_3: u64 = 0x1656_67B1_9E37_79F9;
+const XXH_PRIME_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const XXH_PRIME_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// xxHash64 of `bytes` with seed 0
+fn xxhash64(bytes: &[u8]) -> u64 {
+    fn round(acc: u64, lane: u64) -> u64 {
+        acc.wrapping_add(lane.wrapping_mul(XXH_PRIME_2)).rotate_left(31).wrapping_mul(XXH_PRIME_1)
+    }
+    fn merge(acc: u64, lane: u64) -> u64 {
+        (acc ^ round(0, lane)).wrapping_mul(XXH_PRIME_1).wrapping_add(XXH_PRIME_4)
+    }
+    let read_u64 = |chunk: &[u8]| u64::from_le_bytes(chunk[..8].try_into().unwrap());
+    let read_u32 = |chunk: &[u8]| u32::from_le_bytes(chunk[..4].try_into().unwrap()) as u64;
+
+    let mut rest = bytes;
+    let mut hash = if bytes.len() >= 32 {
+        let mut acc = [
+            XXH_PRIME_1.wrapping_add(XXH_PRIME_2),
+            XXH_PRIME_2,
+            0,
+            XXH_PRIME_1.wrapping_neg(),
+        ];
+        while rest.len() >= 32 {
+            for (lane, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, read_u64(&rest[lane * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        acc.iter().fold(hash, |hash, &acc| merge(hash, acc))
+    } else {
+        XXH_PRIME_5
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, read_u64(rest))).rotate_left(27).wrapping_mul(XXH_PRIME_1).wrapping_add(XXH_PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash = (hash ^ read_u32(rest).wrapping_mul(XXH_PRIME_1)).rotate_left(23).wrapping_mul(XXH_PRIME_2).wrapping_add(XXH_PRIME_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = (hash ^ (byte as u64).wrapping_mul(XXH_PRIME_5)).rotate_left(11).wrapping_mul(XXH_PRIME_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(XXH_PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(XXH_PRIME_3);
+    hash ^ (hash >> 32)
+}
+
 /// Consistent hash ring for shard assignment
 pub struct ConsistentHash {
     config: ShardConfig,
@@ -57,12 +244,15 @@ pub struct ConsistentHash {
     ring: Arc<RwLock<BTreeMap<HashPosition, (NodeId, usize)>>>,
     /// Reverse mapping: node_id -> list of ring positions
     node_positions: Arc<RwLock<HashMap<NodeId, Vec<HashPosition>>>>,
+    /// Locked after the ring and positions, when held with them
+    hasher: RwLock<KeyHasher>,
 }
 
 impl ConsistentHash {
     /// Create new consistent hash ring
     pub fn new(config: ShardConfig) -> Self {
         Self {
+            hasher: RwLock::new(config.hasher),
             config,
             ring: Arc::new(RwLock::new(BTreeMap::new())),
             node_positions: Arc::new(RwLock::new(HashMap::new())),
@@ -86,6 +276,28 @@ impl ConsistentHash {
         node_positions.insert(node_id, positions);
     }
 
+    /// Hash the ring's nodes with another scheme, moving them to its positions
+    pub fn rehash(&self, hasher: KeyHasher) {
+        let mut ring = self.ring.write().unwrap();
+        let mut node_positions = self.node_positions.write().unwrap();
+        *self.hasher.write().unwrap() = hasher;
+
+        ring.clear();
+        for (&node_id, positions) in node_positions.iter_mut() {
+            positions.clear();
+            for vnode_idx in 0..self.config.virtual_nodes_per_node {
+                let position = hasher.hash_vnode(node_id, vnode_idx);
+                ring.insert(position, (node_id, vnode_idx));
+                positions.push(position);
+            }
+        }
+    }
+
+    /// The scheme the ring is hashed with
+    pub fn hasher(&self) -> KeyHasher {
+        *self.hasher.read().unwrap()
+    }
+
     /// Remove a node from the hash ring
     pub fn remove_node(&self, node_id: NodeId) -> Vec<HashPosition> {
         let mut ring = self.ring.write().unwrap();
@@ -132,7 +344,7 @@ impl ConsistentHash {
         let mut seen_nodes = std::collections::HashSet::new();
 
         // Start from hash position and go clockwise
-        let mut iter = ring.range(hash..).chain(ring.iter());
+        let iter = ring.range(hash..).chain(ring.iter());
 
         for (_, (node_id, _)) in iter {
             if !seen_nodes.contains(node_id) {
@@ -150,17 +362,12 @@ impl ConsistentHash {
 
     /// Hash a key to ring position
     fn hash_key(&self, key: &str) -> HashPosition {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        hasher.finish()
+        self.hasher().hash_key(key)
     }
 
     /// Hash a virtual node to ring position
     fn hash_vnode(&self, node_id: NodeId, vnode_idx: usize) -> HashPosition {
-        let mut hasher = DefaultHasher::new();
-        node_id.hash(&mut hasher);
-        vnode_idx.hash(&mut hasher);
-        hasher.finish()
+        self.hasher().hash_vnode(node_id, vnode_idx)
     }
 
     /// Get distribution statistics
@@ -174,11 +381,7 @@ impl ConsistentHash {
         HashRingStats {
             total_nodes,
             total_vnodes,
-            vnodes_per_node: if total_nodes > 0 {
-                total_vnodes / total_nodes
-            } else {
-                0
-            },
+            vnodes_per_node: total_vnodes.checked_div(total_nodes).unwrap_or(0),
         }
     }
 }
@@ -208,6 +411,26 @@ pub struct ShardManager {
     consistent_hash: ConsistentHash,
     /// Shard assignments
     shards: Arc<RwLock<HashMap<ShardId, ShardAssignment>>>,
+    /// Rebalance progress, and how far interrupted transfers got
+    rebalance: RwLock<RebalanceState>,
+    /// Shards being moved, which take no writes
+    fenced: RwLock<HashSet<ShardId>>,
+}
+
+/// Where a rebalance stands, counted in shards
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebalanceProgress {
+    pub pending: usize,
+    pub in_flight: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+#[derive(Default)]
+struct RebalanceState {
+    progress: RebalanceProgress,
+    /// Last entity transferred per (shard, destination), for resuming
+    checkpoints: HashMap<(ShardId, NodeId), EntityId>,
 }
 
 impl ShardManager {
@@ -219,10 +442,15 @@ impl ShardManager {
             config,
             consistent_hash,
             shards: Arc::new(RwLock::new(HashMap::new())),
+            rebalance: RwLock::new(RebalanceState::default()),
+            fenced: RwLock::new(HashSet::new()),
         }
     }
 
-    /// Add a node to the cluster
+    /// Add a node to the cluster, reassigning shards straight away
+    ///
+    /// For setting up a cluster that holds no data yet; a node joining a
+    /// cluster with data should use [`ShardManager::join_node`].
     pub fn add_node(&self, node_id: NodeId) {
         self.consistent_hash.add_node(node_id);
         self.rebuild_shard_assignments();
@@ -230,7 +458,7 @@ impl ShardManager {
 
     /// Remove a node from the cluster
     pub fn remove_node(&self, node_id: NodeId) -> Vec<ShardId> {
-        let affected_positions = self.consistent_hash.remove_node(node_id);
+        let _affected_positions = self.consistent_hash.remove_node(node_id);
         self.rebuild_shard_assignments();
 
         // Return list of shards that need to be migrated
@@ -313,6 +541,197 @@ impl ShardManager {
         }
     }
 
+    /// Add a node to a cluster that holds data
+    ///
+    /// Shards keep their assignments until their data has moved; the
+    /// returned operations are the transfers that get them there. Shards
+    /// that only change which holder is primary, and shards nobody held,
+    /// are reassigned straight away.
+    pub fn join_node(&self, node_id: NodeId) -> Vec<RebalanceOperation> {
+        self.consistent_hash.add_node(node_id);
+        self.reassign_in_place();
+        self.plan_rebalance()
+    }
+
+    /// Place shards with another hashing scheme
+    ///
+    /// As with [`ShardManager::join_node`], shards keep their assignments
+    /// until the returned transfers have moved their data to where the new
+    /// scheme places them. Which shard a key belongs to stays with the
+    /// scheme the data was sharded with (`ShardConfig::hasher`): changing
+    /// that would move entities between shards, not shards between nodes.
+    pub fn migrate_hasher(&self, hasher: KeyHasher) -> Vec<RebalanceOperation> {
+        self.consistent_hash.rehash(hasher);
+        self.reassign_in_place();
+        self.plan_rebalance()
+    }
+
+    /// Reassign the shards whose data needn't move to follow the hash ring:
+    /// those only changing which holder is primary, and those nobody held
+    fn reassign_in_place(&self) {
+        let mut shards = self.shards.write().unwrap();
+        for shard_id in 0..self.config.total_shards as ShardId {
+            let target = self.target_nodes(shard_id);
+            let Some((&primary_node, replicas)) = target.split_first() else {
+                continue;
+            };
+            let same_holders = shards
+                .get(&shard_id)
+                .map(|assignment| same_nodes(&assignment.holders(), &target));
+            if same_holders.unwrap_or(true) {
+                let (key_range_start, key_range_end) = self.key_range(shard_id);
+                let entity_count = shards.get(&shard_id).map_or(0, |a| a.entity_count);
+                shards.insert(shard_id, ShardAssignment {
+                    shard_id,
+                    primary_node,
+                    replica_nodes: replicas.to_vec(),
+                    key_range_start,
+                    key_range_end,
+                    entity_count,
+                });
+            }
+        }
+    }
+
+    /// Transfers that would bring every shard's data to the nodes the hash
+    /// ring now assigns it
+    ///
+    /// Each node a shard gains takes the data of a node it loses (a move),
+    /// or a copy of the primary's; nodes it loses with nobody taking their
+    /// place drop their copy. Moves and copies come before drops.
+    pub fn plan_rebalance(&self) -> Vec<RebalanceOperation> {
+        let shards = self.shards.read().unwrap();
+        let mut shard_ids: Vec<&ShardId> = shards.keys().collect();
+        shard_ids.sort_unstable();
+
+        let mut operations = Vec::new();
+        for &shard_id in shard_ids {
+            let assignment = &shards[&shard_id];
+            let current = assignment.holders();
+            let target = self.target_nodes(shard_id);
+            let mut leaving: Vec<NodeId> = current.iter().copied().filter(|node| !target.contains(node)).collect();
+            leaving.reverse();
+
+            for &to_node in target.iter().filter(|node| !current.contains(node)) {
+                let (from_node, operation_type) = match leaving.pop() {
+                    Some(from_node) => (from_node, RebalanceType::Move),
+                    None => (assignment.primary_node, RebalanceType::Copy),
+                };
+                operations.push(RebalanceOperation { shard_id, from_node, to_node, operation_type });
+            }
+            for &node in leaving.iter().rev() {
+                operations.push(RebalanceOperation {
+                    shard_id,
+                    from_node: node,
+                    to_node: node,
+                    operation_type: RebalanceType::Delete,
+                });
+            }
+        }
+        operations
+    }
+
+    /// Record a finished transfer in the shard's assignment
+    ///
+    /// Once the shard is held by exactly the nodes the hash ring assigns it,
+    /// the ring's primary becomes its primary.
+    pub fn complete_operation(&self, operation: &RebalanceOperation) -> Result<(), String> {
+        let target = self.target_nodes(operation.shard_id);
+        let mut shards = self.shards.write().unwrap();
+        let assignment = shards
+            .get_mut(&operation.shard_id)
+            .ok_or_else(|| format!("Shard {} is not assigned", operation.shard_id))?;
+
+        let mut holders = assignment.holders();
+        match operation.operation_type {
+            RebalanceType::Copy => {
+                if !holders.contains(&operation.to_node) {
+                    holders.push(operation.to_node);
+                }
+            }
+            RebalanceType::Move => match holders.iter().position(|&node| node == operation.from_node) {
+                Some(index) if !holders.contains(&operation.to_node) => holders[index] = operation.to_node,
+                Some(index) => {
+                    holders.remove(index);
+                }
+                None if !holders.contains(&operation.to_node) => holders.push(operation.to_node),
+                None => {}
+            },
+            RebalanceType::Delete => holders.retain(|&node| node != operation.from_node),
+        }
+        if same_nodes(&holders, &target) {
+            holders = target;
+        }
+
+        let Some((&primary_node, replicas)) = holders.split_first() else {
+            return Err(format!("Shard {} would be left on no node", operation.shard_id));
+        };
+        assignment.primary_node = primary_node;
+        assignment.replica_nodes = replicas.to_vec();
+        Ok(())
+    }
+
+    /// Nodes the hash ring assigns a shard to, primary first
+    fn target_nodes(&self, shard_id: ShardId) -> Vec<NodeId> {
+        self.consistent_hash.get_replica_nodes(&format!("shard_{}", shard_id))
+    }
+
+    /// The part of the hash space a shard covers
+    fn key_range(&self, shard_id: ShardId) -> (HashPosition, HashPosition) {
+        let shard_size = u64::MAX / self.config.total_shards as u64;
+        let start = shard_id * shard_size;
+        if shard_id == self.config.total_shards as u64 - 1 {
+            (start, u64::MAX)
+        } else {
+            (start, (shard_id + 1) * shard_size - 1)
+        }
+    }
+
+    /// Where the shards of a rebalance stand
+    pub fn rebalance_progress(&self) -> RebalanceProgress {
+        self.rebalance.read().unwrap().progress.clone()
+    }
+
+    /// Update the rebalance progress
+    pub(crate) fn track_rebalance(&self, update: impl FnOnce(&mut RebalanceProgress)) {
+        update(&mut self.rebalance.write().unwrap().progress);
+    }
+
+    /// The last entity an interrupted transfer of a shard to `to_node` got to
+    pub(crate) fn transfer_checkpoint(&self, shard_id: ShardId, to_node: NodeId) -> Option<EntityId> {
+        self.rebalance.read().unwrap().checkpoints.get(&(shard_id, to_node)).copied()
+    }
+
+    /// Record how far a transfer got, or clear it once finished (`None`)
+    pub(crate) fn set_transfer_checkpoint(&self, shard_id: ShardId, to_node: NodeId, last: Option<EntityId>) {
+        let checkpoints = &mut self.rebalance.write().unwrap().checkpoints;
+        match last {
+            Some(last) => checkpoints.insert((shard_id, to_node), last),
+            None => checkpoints.remove(&(shard_id, to_node)),
+        };
+    }
+
+    /// Refuse writes to a shard while its data moves, or take them again
+    pub fn set_fenced(&self, shard_id: ShardId, fenced: bool) {
+        let mut shards = self.fenced.write().unwrap();
+        if fenced {
+            shards.insert(shard_id);
+        } else {
+            shards.remove(&shard_id);
+        }
+    }
+
+    pub fn is_fenced(&self, shard_id: ShardId) -> bool {
+        self.fenced.read().unwrap().contains(&shard_id)
+    }
+
+    /// The shards refusing writes, in order
+    pub fn fenced_shards(&self) -> Vec<ShardId> {
+        let mut shards: Vec<ShardId> = self.fenced.read().unwrap().iter().copied().collect();
+        shards.sort_unstable();
+        shards
+    }
+
     /// Get all shard assignments
     pub fn get_all_shards(&self) -> Vec<ShardAssignment> {
         let shards = self.shards.read().unwrap();
@@ -332,33 +751,19 @@ impl ShardManager {
             .collect()
     }
 
-    /// Calculate rebalancing operations needed
+    /// Calculate the transfers that bring shards to a node
     pub fn calculate_rebalancing(&self, target_node_id: NodeId) -> Vec<RebalanceOperation> {
-        let shards = self.shards.read().unwrap();
-        let mut operations = Vec::new();
-
-        for (shard_id, assignment) in shards.iter() {
-            // If this node should own the shard but doesn't
-            let replica_nodes = self.consistent_hash.get_replica_nodes(&format!("shard_{}", shard_id));
-
-            if replica_nodes.contains(&target_node_id) && assignment.primary_node != target_node_id {
-                operations.push(RebalanceOperation {
-                    shard_id: *shard_id,
-                    from_node: assignment.primary_node,
-                    to_node: target_node_id,
-                    operation_type: RebalanceType::Copy,
-                });
-            }
-        }
-
-        operations
+        self.plan_rebalance()
+            .into_iter()
+            .filter(|operation| {
+                operation.to_node == target_node_id && !matches!(operation.operation_type, RebalanceType::Delete)
+            })
+            .collect()
     }
 
     /// Hash a key
     fn hash_key(&self, key: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        hasher.finish()
+        self.config.hasher.hash_key(key)
     }
 
     /// Get shard manager statistics
@@ -386,12 +791,26 @@ impl ShardManager {
             total_nodes: hash_stats.total_nodes,
             avg_shards_per_node,
             replication_factor: self.config.replication_factor,
+            hash_version: self.consistent_hash.hasher().version(),
+            rebalance: self.rebalance_progress(),
         }
     }
 }
 
+impl ShardAssignment {
+    /// Nodes holding the shard, primary first
+    pub fn holders(&self) -> Vec<NodeId> {
+        std::iter::once(self.primary_node).chain(self.replica_nodes.iter().copied()).collect()
+    }
+}
+
+/// Whether two lists hold the same nodes, in any order
+fn same_nodes(a: &[NodeId], b: &[NodeId]) -> bool {
+    a.len() == b.len() && a.iter().all(|node| b.contains(node))
+}
+
 /// Type of rebalancing operation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RebalanceType {
     /// Copy shard to new node
     Copy,
@@ -402,7 +821,9 @@ pub enum RebalanceType {
 }
 
 /// Rebalancing operation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// A `Delete` drops `from_node`'s copy; its `to_node` is the same node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RebalanceOperation {
     pub shard_id: ShardId,
     pub from_node: NodeId,
@@ -417,6 +838,9 @@ pub struct ShardManagerStats {
     pub total_nodes: usize,
     pub avg_shards_per_node: f64,
     pub replication_factor: usize,
+    /// Version of the scheme shards are placed with
+    pub hash_version: u8,
+    pub rebalance: RebalanceProgress,
 }
 
 #[cfg(test)]
@@ -547,10 +971,102 @@ mod tests {
         manager.add_node(3);
 
         // Add a new node and check rebalancing operations
-        manager.add_node(4);
+        let planned = manager.join_node(4);
 
         let operations = manager.calculate_rebalancing(4);
         // Should have some operations to move shards to the new node
-        assert!(operations.len() > 0);
+        assert!(!operations.is_empty());
+        assert!(operations.iter().all(|op| planned.contains(op)));
+
+        // Assignments only change as transfers complete
+        let op = &operations[0];
+        assert!(!manager.get_shards_for_node(4).iter().any(|a| a.shard_id == op.shard_id));
+        manager.complete_operation(op).unwrap();
+        assert!(manager.get_shards_for_node(4).iter().any(|a| a.shard_id == op.shard_id));
+        if op.operation_type == RebalanceType::Move {
+            assert!(!manager.get_shards_for_node(op.from_node).iter().any(|a| a.shard_id == op.shard_id));
+        }
+    }
+
+    #[test]
+    fn test_rebalance_plan_reaches_ring_assignment() {
+        let manager = ShardManager::new(ShardConfig {
+            total_shards: 32,
+            replication_factor: 2,
+            ..Default::default()
+        });
+        manager.add_node(1);
+        manager.add_node(2);
+
+        for op in manager.join_node(3) {
+            assert_ne!(op.operation_type, RebalanceType::Copy, "every gained node replaces a lost one");
+            manager.complete_operation(&op).unwrap();
+        }
+        assert!(manager.plan_rebalance().is_empty());
+        for assignment in manager.get_all_shards() {
+            assert_eq!(assignment.holders(), manager.target_nodes(assignment.shard_id));
+        }
+    }
+
+    #[test]
+    fn test_key_hashers_match_published_values() {
+        // SipHash-1-3 gives what DefaultHasher did; xxHash64 the reference vectors
+        assert_eq!(KeyHasher::SipHash13.hash_key(""), 0x3040_6ea5_23c5_3def);
+        assert_eq!(KeyHasher::SipHash13.hash_key("test_key"), 0xfebb_6d3f_6382_169b);
+        assert_eq!(KeyHasher::SipHash13.hash_key("shard_1023"), 0xead8_3f3c_70db_01a0);
+        assert_eq!(KeyHasher::SipHash13.hash_vnode(1, 0), 0x8934_f5f0_a51f_bffa);
+        assert_eq!(KeyHasher::XxHash64.hash_key(""), 0xef46_db37_51d8_e999);
+        assert_eq!(KeyHasher::XxHash64.hash_key("abc"), 0x44bc_2cf5_ad77_0999);
+        assert_eq!(KeyHasher::XxHash64.hash_key("Nobody inspects the spammish repetition"), 0xfbce_a83c_8a37_8bf1);
+        assert_eq!(KeyHasher::XxHash64.hash_vnode(7, 149), 0x70be_248e_c8d5_52b2);
+
+        // Schemes are stored as their version byte
+        assert_eq!(serde_json::to_string(&KeyHasher::XxHash64).unwrap(), "2");
+        assert_eq!(serde_json::from_str::<KeyHasher>("1").unwrap(), KeyHasher::SipHash13);
+        assert!(serde_json::from_str::<KeyHasher>("3").is_err());
+    }
+
+    #[test]
+    fn test_independent_rings_agree_on_ownership() {
+        for hasher in [KeyHasher::SipHash13, KeyHasher::XxHash64] {
+            let config = ShardConfig { hasher, ..Default::default() };
+            let (first, second) = (ConsistentHash::new(config.clone()), ConsistentHash::new(config));
+            for node in 1..=5 {
+                first.add_node(node);
+                second.add_node(6 - node);
+            }
+
+            for i in 0..10_000 {
+                let key = format!("user:{}", i);
+                assert_eq!(first.get_replica_nodes(&key), second.get_replica_nodes(&key), "{:?} {}", hasher, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrating_hasher_moves_shards_to_new_placement() {
+        let manager = ShardManager::new(ShardConfig {
+            total_shards: 64,
+            replication_factor: 2,
+            ..Default::default()
+        });
+        for node in 1..=3 {
+            manager.add_node(node);
+        }
+        let shard = manager.get_shard_for_key("user:42");
+
+        let operations = manager.migrate_hasher(KeyHasher::XxHash64);
+        assert!(!operations.is_empty());
+        assert_eq!(manager.get_statistics().hash_version, 2);
+        for op in &operations {
+            manager.complete_operation(op).unwrap();
+        }
+
+        assert!(manager.plan_rebalance().is_empty());
+        for assignment in manager.get_all_shards() {
+            assert_eq!(assignment.holders(), manager.target_nodes(assignment.shard_id));
+        }
+        // Keys stay in their shards
+        assert_eq!(manager.get_shard_for_key("user:42"), shard);
     }
 }
@@ -8,10 +8,19 @@
 //!
 //! This is inspired by social networks and neural networks where most connections
 //! are local, but a few long-range connections create "shortcuts" across the network.
-
-use std::collections::{HashMap, HashSet, VecDeque};
+//!
+//! Routes span several hops: nodes exchange their connection lists
+//! ([`SmallWorldTopology::serve_neighbors`]), and each keeps the lists it
+//! hears, for as long as they're fresh, as a routing table to search.
+
+use crate::distributed_p2p::{MessageKind, MessageType, P2PNetwork};
+use crate::shutdown::{BackgroundTask, ShutdownSignal};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
+use tokio::task::JoinSet;
 
 /// Unique identifier for a node in the distributed network
 pub type NodeId = u64;
@@ -27,9 +36,11 @@ impl NodeAddress {
     pub fn new(host: String, port: u16) -> Self {
         Self { host, port }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+impl std::fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
     }
 }
 
@@ -92,6 +103,9 @@ pub struct TopologyConfig {
 
     /// Health check interval (seconds)
     pub health_check_interval_secs: u64,
+
+    /// Age (ms) past which another node's reported neighbors aren't routed by
+    pub neighbor_ttl_ms: u64,
 }
 
 impl Default for TopologyConfig {
@@ -102,6 +116,7 @@ impl Default for TopologyConfig {
             rewiring_probability: 0.05,   // 5% chance of rewiring
             max_latency_ms: 100,          // 100ms max latency
             health_check_interval_secs: 10,
+            neighbor_ttl_ms: 30_000,      // Three missed neighbor exchanges
         }
     }
 }
@@ -117,8 +132,22 @@ pub struct SmallWorldTopology {
     nodes: Arc<RwLock<HashMap<NodeId, NodeInfo>>>,
     /// Outgoing connections from this node
     connections: Arc<RwLock<HashMap<NodeId, Connection>>>,
+    /// Other nodes' connections, as they last reported them
+    neighbor_table: Arc<RwLock<HashMap<NodeId, NeighborList>>>,
 }
 
+/// A remote node's connections, and when it reported them
+#[derive(Debug, Clone)]
+struct NeighborList {
+    /// Targets, with the latency (ms) the node measured to each
+    neighbors: Vec<(NodeId, u32)>,
+    learned_at: Instant,
+}
+
+/// What a route costs: hops, summed latency (ms), and the age of the
+/// oldest neighbor list it relies on
+type RouteCost = (usize, u64, Duration);
+
 impl SmallWorldTopology {
     /// Create a new small-world topology for this node
     pub fn new(local_id: NodeId, config: TopologyConfig) -> Self {
@@ -127,6 +156,7 @@ impl SmallWorldTopology {
             local_id,
             nodes: Arc::new(RwLock::new(HashMap::new())),
             connections: Arc::new(RwLock::new(HashMap::new())),
+            neighbor_table: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -148,6 +178,8 @@ impl SmallWorldTopology {
 
         let mut connections = self.connections.write().unwrap();
         connections.remove(&node_id);
+
+        self.neighbor_table.write().unwrap().remove(&node_id);
     }
 
     /// Get all nodes in the network
@@ -168,13 +200,16 @@ impl SmallWorldTopology {
     /// then adds random long-range connections for shortcuts.
     pub fn build_topology(&self) {
         let nodes = self.nodes.read().unwrap();
-        let node_ids: Vec<NodeId> = nodes.keys().copied().collect();
+        let mut node_ids: Vec<NodeId> = nodes.keys().copied().collect();
         drop(nodes);
 
         if node_ids.len() <= 1 {
             return; // No connections needed for single node
         }
 
+        // The ring is ordered by node ID
+        node_ids.sort_unstable();
+
         let mut new_connections = HashMap::new();
 
         // Step 1: Create ring topology with k local connections
@@ -184,6 +219,12 @@ impl SmallWorldTopology {
 
             // Connect to k/2 neighbors on each side
             for offset in 1..=(self.config.local_connections / 2) {
+                // Small rings wrap around; never connect a node to itself
+                let offset = offset % n;
+                if offset == 0 {
+                    continue;
+                }
+
                 // Right neighbor
                 let right_idx = (idx + offset) % n;
                 new_connections.insert(
@@ -256,49 +297,47 @@ impl SmallWorldTopology {
         }
     }
 
-    /// Find shortest path to target node using BFS
+    /// Find the best known path to a target node
     ///
-    /// Returns list of node IDs representing the path, or None if unreachable.
+    /// Searches this node's connections and the fresh neighbor lists other
+    /// nodes reported: fewest hops first, then the lowest latency summed
+    /// over the hops, then the freshest knowledge. Returns list of node IDs
+    /// representing the path, or None if unreachable.
     pub fn find_route(&self, target_id: NodeId) -> Option<Vec<NodeId>> {
         if target_id == self.local_id {
             return Some(vec![self.local_id]);
         }
 
-        let connections = self.connections.read().unwrap();
-        if connections.contains_key(&target_id) {
-            // Direct connection
-            return Some(vec![self.local_id, target_id]);
-        }
-
-        // BFS to find shortest path
-        let mut queue = VecDeque::new();
-        let mut visited = HashSet::new();
-        let mut parent: HashMap<NodeId, NodeId> = HashMap::new();
-
-        queue.push_back(self.local_id);
-        visited.insert(self.local_id);
+        let adjacency = self.adjacency();
+        let start: RouteCost = (0, 0, Duration::ZERO);
+        let mut best: HashMap<NodeId, (RouteCost, NodeId)> = HashMap::from([(self.local_id, (start, self.local_id))]);
+        let mut queue = BinaryHeap::from([Reverse((start, self.local_id))]);
 
-        while let Some(current) = queue.pop_front() {
+        while let Some(Reverse((cost, current))) = queue.pop() {
             if current == target_id {
                 // Reconstruct path
                 let mut path = vec![target_id];
                 let mut node = target_id;
-                while let Some(&prev) = parent.get(&node) {
-                    path.push(prev);
-                    node = prev;
+                while node != self.local_id {
+                    node = best[&node].1;
+                    path.push(node);
                 }
                 path.reverse();
                 return Some(path);
             }
+            if best[&current].0 < cost {
+                continue;
+            }
 
-            // Get neighbors of current node
-            if let Some(neighbors) = self.get_neighbors(current) {
-                for neighbor in neighbors {
-                    if !visited.contains(&neighbor) {
-                        visited.insert(neighbor);
-                        parent.insert(neighbor, current);
-                        queue.push_back(neighbor);
-                    }
+            for &(neighbor, latency_ms, age) in adjacency.get(&current).into_iter().flatten() {
+                let next = (cost.0 + 1, cost.1 + u64::from(latency_ms), cost.2.max(age));
+                let better = match best.get(&neighbor) {
+                    Some((known, _)) => next < *known,
+                    None => true,
+                };
+                if better {
+                    best.insert(neighbor, (next, current));
+                    queue.push(Reverse((next, neighbor)));
                 }
             }
         }
@@ -306,16 +345,123 @@ impl SmallWorldTopology {
         None // Target unreachable
     }
 
-    /// Get neighbors of a node (for routing purposes)
-    fn get_neighbors(&self, node_id: NodeId) -> Option<Vec<NodeId>> {
-        if node_id == self.local_id {
-            let connections = self.connections.read().unwrap();
-            Some(connections.keys().copied().collect())
-        } else {
-            // For other nodes, we'd need to query them or maintain global routing table
-            // For now, return None (will implement in distributed query execution)
-            None
+    /// Record the connections a remote node reported, with the latency it
+    /// measured to each
+    pub fn update_neighbors(&self, node_id: NodeId, neighbors: Vec<(NodeId, u32)>) {
+        let list = NeighborList {
+            neighbors,
+            learned_at: Instant::now(),
+        };
+        self.neighbor_table.write().unwrap().insert(node_id, list);
+    }
+
+    /// A node's neighbors: this node's connections, or what another node
+    /// last reported, unless that's older than `neighbor_ttl_ms`
+    pub fn known_neighbors(&self, node_id: NodeId) -> Option<Vec<NodeId>> {
+        let adjacency = self.adjacency();
+        let neighbors = adjacency.get(&node_id)?;
+        Some(neighbors.iter().map(|&(neighbor, _, _)| neighbor).collect())
+    }
+
+    /// Known nodes whose neighbors haven't been reported, or were too long ago
+    pub fn stale_nodes(&self) -> Vec<NodeId> {
+        let adjacency = self.adjacency();
+        let mut stale: Vec<NodeId> = self
+            .nodes
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|&&id| id != self.local_id && !adjacency.contains_key(&id))
+            .copied()
+            .collect();
+        stale.sort_unstable();
+        stale
+    }
+
+    /// Each node's neighbors, with the latency to them and the age of the
+    /// report, dropping reports that aged out
+    fn adjacency(&self) -> HashMap<NodeId, Vec<(NodeId, u32, Duration)>> {
+        let ttl = Duration::from_millis(self.config.neighbor_ttl_ms);
+        let mut table = self.neighbor_table.write().unwrap();
+        table.retain(|_, list| list.learned_at.elapsed() <= ttl);
+
+        let mut adjacency: HashMap<NodeId, Vec<(NodeId, u32, Duration)>> = table
+            .iter()
+            .filter(|(&id, _)| id != self.local_id)
+            .map(|(&id, list)| {
+                let age = list.learned_at.elapsed();
+                (id, list.neighbors.iter().map(|&(neighbor, latency_ms)| (neighbor, latency_ms, age)).collect())
+            })
+            .collect();
+        drop(table);
+
+        let connections = self.connections.read().unwrap();
+        let local = connections.values().map(|c| (c.target_id, c.latency_ms, Duration::ZERO)).collect();
+        adjacency.insert(self.local_id, local);
+        adjacency
+    }
+
+    /// Answer other nodes' requests for this node's connections
+    pub fn serve_neighbors(&self, network: &P2PNetwork) {
+        let connections = Arc::clone(&self.connections);
+        network.register_handler(MessageKind::NeighborRequest, move |msg| {
+            let neighbors = connections.read().unwrap().values().map(|c| (c.target_id, c.latency_ms)).collect();
+            Some(msg.reply(MessageType::Neighbors { neighbors }))
+        });
+    }
+
+    /// Ask nodes for their connections, recording the answers; returns how
+    /// many answered
+    pub async fn query_neighbors(&self, network: &Arc<P2PNetwork>, node_ids: &[NodeId]) -> usize {
+        let mut requests = JoinSet::new();
+        for &node_id in node_ids {
+            let network = Arc::clone(network);
+            requests.spawn(async move { (node_id, network.send_message(node_id, MessageType::NeighborRequest).await) });
+        }
+
+        let mut answered = 0;
+        while let Some(request) = requests.join_next().await {
+            if let Ok((node_id, Ok(reply))) = request {
+                if let MessageType::Neighbors { neighbors } = reply.message_type {
+                    self.update_neighbors(node_id, neighbors);
+                    answered += 1;
+                }
+            }
         }
+        answered
+    }
+
+    /// Find a route as [`find_route`](Self::find_route) does, first asking
+    /// nodes whose neighbors are unknown or stale for them when what's known
+    /// doesn't reach the target
+    pub async fn route_to(&self, network: &Arc<P2PNetwork>, target_id: NodeId) -> Option<Vec<NodeId>> {
+        if let Some(route) = self.find_route(target_id) {
+            return Some(route);
+        }
+
+        self.query_neighbors(network, &self.stale_nodes()).await;
+        self.find_route(target_id)
+    }
+
+    /// Ask every known node for its connections each health check
+    /// interval, until `shutdown` is cancelled
+    pub fn start_neighbor_exchange(self: &Arc<Self>, network: Arc<P2PNetwork>, shutdown: &ShutdownSignal) -> BackgroundTask {
+        let topology = Arc::clone(self);
+
+        BackgroundTask::spawn("neighbor-exchange", shutdown, |shutdown| async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(topology.config.health_check_interval_secs));
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let nodes: Vec<NodeId> =
+                            topology.get_all_nodes().iter().map(|node| node.id).filter(|&id| id != topology.local_id).collect();
+                        topology.query_neighbors(&network, &nodes).await;
+                    }
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        })
     }
 
     /// Calculate average path length (network diameter metric)
@@ -350,23 +496,32 @@ impl SmallWorldTopology {
     }
 
     /// Calculate clustering coefficient (measure of local connectivity)
+    ///
+    /// The share of pairs of this node's neighbors connected to each other,
+    /// one way or the other, as their fresh neighbor lists tell.
     pub fn calculate_clustering_coefficient(&self) -> f64 {
-        let connections = self.connections.read().unwrap();
-        let neighbors: Vec<NodeId> = connections.keys().copied().collect();
-        drop(connections);
+        let adjacency = self.adjacency();
+        let neighbors: Vec<NodeId> = adjacency[&self.local_id].iter().map(|&(id, _, _)| id).collect();
 
         if neighbors.len() < 2 {
             return 0.0;
         }
 
+        let linked = |from: NodeId, to: NodeId| {
+            adjacency.get(&from).is_some_and(|list| list.iter().any(|&(id, _, _)| id == to))
+        };
+
         // Count triangles: how many of my neighbors are connected to each other
         let mut triangle_count = 0;
         let max_triangles = neighbors.len() * (neighbors.len() - 1) / 2;
+        for (i, &a) in neighbors.iter().enumerate() {
+            for &b in &neighbors[i + 1..] {
+                if linked(a, b) || linked(b, a) {
+                    triangle_count += 1;
+                }
+            }
+        }
 
-        // For simplicity, we'll estimate based on local information
-        // In a full implementation, we'd query each neighbor's connections
-
-        // This is a simplified calculation
         triangle_count as f64 / max_triangles as f64
     }
 
@@ -465,6 +620,7 @@ mod tests {
             rewiring_probability: 0.05,
             max_latency_ms: 100,
             health_check_interval_secs: 10,
+            neighbor_ttl_ms: 30_000,
         };
 
         let topology = SmallWorldTopology::new(1, config);
@@ -539,4 +695,94 @@ mod tests {
         assert!(stats.total_connections > 0);
         assert!(stats.local_connections > 0);
     }
+
+    /// Node 1's view of a ring of `size` nodes, each reporting its two ring
+    /// neighbors, with `shortcuts` added both ways
+    fn reported_ring(size: NodeId, shortcuts: &[(NodeId, NodeId)], neighbor_ttl_ms: u64) -> SmallWorldTopology {
+        let config = TopologyConfig {
+            local_connections: 2,
+            longrange_connections: 0,
+            neighbor_ttl_ms,
+            ..TopologyConfig::default()
+        };
+        let topology = SmallWorldTopology::new(1, config);
+        for i in 1..=size {
+            topology.add_node(NodeInfo::new(i, NodeAddress::new("localhost".to_string(), 8080 + i as u16), 100));
+        }
+        topology.build_topology();
+
+        for i in 2..=size {
+            let mut neighbors = vec![(i % size + 1, 1), ((i + size - 2) % size + 1, 1)];
+            for &(a, b) in shortcuts {
+                if a == i {
+                    neighbors.push((b, 1));
+                } else if b == i {
+                    neighbors.push((a, 1));
+                }
+            }
+            topology.update_neighbors(i, neighbors);
+        }
+        topology
+    }
+
+    #[test]
+    fn test_find_route_multi_hop() {
+        let topology = reported_ring(8, &[(2, 6)], 30_000);
+        let mut connected: Vec<NodeId> = topology.get_connections().iter().map(|c| c.target_id).collect();
+        connected.sort_unstable();
+        assert_eq!(connected, vec![2, 8]);
+
+        // The shortcut saves a hop over either way round the ring
+        assert_eq!(topology.find_route(5), Some(vec![1, 2, 6, 5]));
+        assert_eq!(topology.find_route(4), Some(vec![1, 2, 3, 4]));
+        assert_eq!(topology.find_route(7), Some(vec![1, 8, 7]));
+        assert_eq!(topology.find_route(9), None);
+
+        // Equally short ways round are told apart by latency
+        let topology = reported_ring(8, &[], 30_000);
+        topology.update_neighbors(2, vec![(1, 1), (3, 50)]);
+        assert_eq!(topology.find_route(5), Some(vec![1, 8, 7, 6, 5]));
+        assert_eq!(topology.calculate_avg_path_length(), 16.0 / 7.0);
+    }
+
+    #[test]
+    fn test_stale_neighbor_lists_age_out() {
+        let topology = reported_ring(6, &[], 50);
+        assert_eq!(topology.find_route(4).map(|path| path.len()), Some(4));
+        assert!(topology.stale_nodes().is_empty());
+
+        std::thread::sleep(Duration::from_millis(80));
+        assert_eq!(topology.find_route(4), None);
+        assert_eq!(topology.find_route(2), Some(vec![1, 2]));
+        assert_eq!(topology.known_neighbors(3), None);
+        assert_eq!(topology.stale_nodes(), vec![2, 3, 4, 5, 6]);
+
+        // A fresh report is routed by again
+        topology.update_neighbors(2, vec![(3, 1)]);
+        assert_eq!(topology.find_route(3), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_clustering_coefficient_from_neighbor_lists() {
+        let config = TopologyConfig {
+            longrange_connections: 0,
+            ..TopologyConfig::default()
+        };
+        let topology = SmallWorldTopology::new(1, config);
+        for i in 1..=4 {
+            topology.add_node(NodeInfo::new(i, NodeAddress::new("localhost".to_string(), 8080 + i as u16), 100));
+        }
+        topology.build_topology();
+        assert_eq!(topology.get_connections().len(), 3);
+        assert_eq!(topology.calculate_clustering_coefficient(), 0.0);
+
+        // Of the pairs among 2, 3 and 4, only 2 and 3 are linked
+        topology.update_neighbors(2, vec![(1, 1), (3, 1)]);
+        topology.update_neighbors(3, vec![(1, 1)]);
+        topology.update_neighbors(4, vec![(1, 1)]);
+        assert_eq!(topology.calculate_clustering_coefficient(), 1.0 / 3.0);
+
+        topology.update_neighbors(4, vec![(1, 1), (2, 1), (3, 1)]);
+        assert_eq!(topology.calculate_clustering_coefficient(), 1.0);
+    }
 }
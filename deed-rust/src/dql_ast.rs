@@ -4,6 +4,11 @@
 
 use serde::{Deserialize, Serialize};
 use crate::transaction::IsolationLevel;
+use crate::firewall::{FirewallRule, FirewallSubject};
+use crate::schema::Schema;
+use crate::import_export::DataFormat;
+use crate::types::PropertyValue;
+use crate::dql_functions::ScalarFunction;
 
 /// Top-level query node
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -13,13 +18,89 @@ pub enum Query {
     Update(UpdateQuery),
     Delete(DeleteQuery),
     Create(CreateQuery),
+    // Edge mutations
+    UpdateEdge(UpdateEdgeQuery),
+    DeleteEdge(DeleteEdgeQuery),
     // Transaction commands
     Begin(BeginQuery),
     Commit,
     Rollback,
+    Savepoint(String),
+    RollbackToSavepoint(String),
+    ReleaseSavepoint(String),
     // Index commands
     CreateIndex(CreateIndexQuery),
     DropIndex(DropIndexQuery),
+    Reindex(ReindexQuery),
+    // Optimizer statistics: ANALYZE [collection]
+    Analyze(Option<String>),
+    // Session settings
+    Set(SetQuery),
+    // Archival commands
+    Archive(ArchiveQuery),
+    Unarchive(ArchiveQuery),
+    // Bulk import and export
+    Copy(CopyQuery),
+    // Firewall administration
+    Firewall(FirewallQuery),
+    // Schema commands
+    DefineSchema(Schema),
+    DropSchema(String),
+    // Collection commands
+    ShowCollections,
+    Truncate(String),
+    DropCollection(String),
+    // Plan inspection
+    Explain(ExplainQuery),
+}
+
+/// FIREWALL admin command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FirewallQuery {
+    /// FIREWALL ALLOW|DENY rule FOR USER|ROLE|KEY 'subject' [WHEN condition, ...]
+    AddRule(FirewallRule),
+    /// FIREWALL DROP rule
+    DropRule(String),
+    /// FIREWALL LEARN FOR USER|ROLE|KEY 'subject' '1h'
+    Learn { subject: FirewallSubject, duration: Literal },
+    /// FIREWALL ENFORCE FOR USER|ROLE|KEY 'subject'
+    Enforce(FirewallSubject),
+}
+
+/// ARCHIVE / UNARCHIVE query: ARCHIVE FROM Orders WHERE created_at < TIMESTAMP '2022-01-01'
+/// PARTITION BY created_at
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveQuery {
+    pub collection: String,
+    pub where_clause: Option<WhereClause>,
+    /// Column whose values split archived entities into chunks (ARCHIVE only)
+    pub partition_by: Option<String>,
+}
+
+/// COPY statement: COPY Users TO|FROM 'users.jsonl' [FORMAT JSONL|CSV]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CopyQuery {
+    pub collection: String,
+    /// Export to the file (TO) rather than import from it (FROM)
+    pub export: bool,
+    pub path: String,
+    /// Format named in the statement; otherwise taken from the path's extension
+    pub format: Option<DataFormat>,
+}
+
+/// EXPLAIN [ANALYZE] statement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExplainQuery {
+    pub query: Box<Query>,
+    /// Also execute the statement and report what each operation did
+    pub analyze: bool,
+}
+
+/// SET session option: SET max_staleness = '30s'
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetQuery {
+    pub name: String,
+    pub value: Literal,
 }
 
 /// BEGIN TRANSACTION query
@@ -28,13 +109,15 @@ pub struct BeginQuery {
     pub isolation_level: Option<IsolationLevel>,
 }
 
-/// CREATE INDEX query
+/// CREATE [UNIQUE | FULLTEXT] INDEX query
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CreateIndexQuery {
     pub index_name: String,
     pub collection: String,
     pub field: String,
     pub unique: bool,
+    /// Index the field's terms, for MATCH
+    pub fulltext: bool,
 }
 
 /// DROP INDEX query
@@ -43,10 +126,18 @@ pub struct DropIndexQuery {
     pub index_name: String,
 }
 
+/// REINDEX query: REINDEX idx_name / REINDEX COLLECTION Users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReindexQuery {
+    Index(String),
+    Collection(String),
+}
+
 /// SELECT query with optional TRAVERSE
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SelectQuery {
     pub from: FromClause,
+    pub joins: Vec<JoinClause>,
     pub traverse: Option<TraverseClause>,
     pub where_clause: Option<WhereClause>,
     pub select: SelectClause,
@@ -64,20 +155,43 @@ pub struct FromClause {
     pub alias: Option<String>,
 }
 
+/// JOIN clause: `JOIN Orders o ON u.id = o.user_id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JoinClause {
+    pub collection: String,
+    pub alias: Option<String>,
+    pub condition: Expression,
+}
+
 /// TRAVERSE clause (graph navigation)
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TraverseClause {
     pub patterns: Vec<TraversePattern>,
 }
 
-/// Single traverse pattern: -[:TYPE]-> Node
+/// Single traverse pattern: -[e:TYPE|OTHER {key: value}]-> Node
+///
+/// Edges of any listed type are followed (any type when none are listed),
+/// provided their properties equal every listed value. The optional edge
+/// alias binds the edge itself, so its properties can be referenced in
+/// WHERE and SELECT.
+///
+/// Each source reaches an entity once, by its shortest path; prefixed with
+/// ALL (`TRAVERSE ALL -[:FOLLOWS*2]-> f`), the pattern yields one row per
+/// path instead, never visiting an entity twice on the same path.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TraversePattern {
     pub direction: Direction,
-    pub edge_type: Option<String>,
+    pub edge_alias: Option<String>,
+    pub edge_types: Vec<String>,
+    pub edge_properties: Vec<(String, Literal)>,
     pub target_alias: Option<String>,
     pub min_hops: usize,
     pub max_hops: usize,
+    /// TOP k BY PHEROMONE: follow only each entity's k strongest edges
+    pub top_k: Option<usize>,
+    /// ALL: keep one row per path rather than per reached entity
+    pub all_paths: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -108,6 +222,17 @@ pub enum Expression {
     LessThanEq(Box<Expression>, Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
     GreaterThanEq(Box<Expression>, Box<Expression>),
+    In(Box<Expression>, Vec<Literal>),
+    /// element IN list (`'admin' IN u.tags`), as (list, element)
+    Contains(Box<Expression>, Box<Expression>),
+    /// expr BETWEEN low AND high (inclusive)
+    Between(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// expr LIKE 'pattern' (`%` any run, `_` any one character)
+    Like(Box<Expression>, String),
+    /// expr IS NULL (missing or explicitly null)
+    IsNull(Box<Expression>),
+    /// MATCH(field, 'terms'): the field's text holds every term
+    Match(Box<Expression>, String),
 
     // Arithmetic
     Add(Box<Expression>, Box<Expression>),
@@ -115,12 +240,41 @@ pub enum Expression {
     Multiply(Box<Expression>, Box<Expression>),
     Divide(Box<Expression>, Box<Expression>),
 
-    // Aggregations
-    Aggregate(AggregateFunction, Box<Expression>),
+    // Aggregations (function, argument, DISTINCT)
+    Aggregate(AggregateFunction, Box<Expression>, bool),
+    /// Scalar function call, evaluated per row: LOWER(u.name)
+    Function(ScalarFunction, Vec<Expression>),
+    /// HAS(u.property): the entity has the property, even if it holds NULL
+    Has(PropertyRef),
 
     // Values
     Property(PropertyRef),
     Literal(Literal),
+    /// Placeholder bound at execution time (`$name` or `?1`)
+    Parameter(String),
+    /// SHORTEST_PATH(a, b [, :TYPE] [, MAX n]) or SHORTEST_PATH_LENGTH(...)
+    ShortestPath(PathCall),
+    /// `*` or `alias.*`, only as a SELECT field
+    Wildcard(Option<String>),
+
+    // Subqueries (uncorrelated, in WHERE only)
+    /// expr IN (FROM ... SELECT one_column)
+    InSubquery(Box<Expression>, Subquery),
+    /// EXISTS (FROM ... SELECT ...): the subquery returns any row
+    Exists(Subquery),
+    /// (FROM ... SELECT one_column) used as a value: its single row's value,
+    /// or NULL when it returns none
+    ScalarSubquery(Subquery),
+}
+
+/// A parenthesized SELECT nested in a condition
+///
+/// `id` numbers the statement's subqueries in the order they were parsed;
+/// the plan runs each one first and the condition reads its result by id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subquery {
+    pub id: usize,
+    pub query: Box<SelectQuery>,
 }
 
 /// Aggregate functions
@@ -133,6 +287,68 @@ pub enum AggregateFunction {
     Max,        // MAX(field)
 }
 
+/// Shortest path between the entities bound to two aliases
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathCall {
+    pub source: String,
+    pub target: String,
+    pub edge_type: Option<String>,
+    pub max_depth: Option<usize>,
+    /// SHORTEST_PATH_LENGTH: yield the hop count instead of the path
+    pub length_only: bool,
+}
+
+/// Pseudo-property holding an entity's id, read as `@id` or `u.@id`
+pub const ID_PROPERTY: &str = "@id";
+
+/// Pseudo-property of a bound edge holding its pheromone strength, read
+/// with `PHEROMONE(e)`; `@` can't start an identifier, so it never clashes
+/// with a stored property
+pub const PHEROMONE_PROPERTY: &str = "@pheromone";
+
+/// Pseudo-property read with `SCORE()`: how often the terms of the WHERE
+/// clause's MATCH occur in the matched text. The plan builder replaces it.
+pub const SCORE_PROPERTY: &str = "@score";
+
+/// Prefix of the pseudo-properties read with `OUT_DEGREE(u [, 'TYPE'])`,
+/// `IN_DEGREE(...)` and `DEGREE(...)`: the number of edges leaving, entering
+/// or touching the entity, counted from the adjacency lists when it's read
+pub const DEGREE_PROPERTY: &str = "@degree";
+
+/// The pseudo-property counting an entity's edges in `direction`, of one
+/// type or all of them (`@degree:out:FOLLOWS`, `@degree:both`)
+pub fn degree_property(direction: &Direction, edge_type: Option<&str>) -> String {
+    let direction = match direction {
+        Direction::Outgoing => "out",
+        Direction::Incoming => "in",
+        Direction::Both => "both",
+    };
+    match edge_type {
+        Some(edge_type) => format!("{}:{}:{}", DEGREE_PROPERTY, direction, edge_type),
+        None => format!("{}:{}", DEGREE_PROPERTY, direction),
+    }
+}
+
+/// The direction and edge type a [`degree_property`] counts, if `property` is one
+pub fn parse_degree_property(property: &str) -> Option<(Direction, Option<&str>)> {
+    let rest = property.strip_prefix(DEGREE_PROPERTY)?.strip_prefix(':')?;
+    let (direction, edge_type) = match rest.split_once(':') {
+        Some((direction, edge_type)) => (direction, Some(edge_type)),
+        None => (rest, None),
+    };
+    let direction = match direction {
+        "out" => Direction::Outgoing,
+        "in" => Direction::Incoming,
+        "both" => Direction::Both,
+        _ => return None,
+    };
+    Some((direction, edge_type))
+}
+
+/// Parameter bound to the statement's start time, written `NOW()`; like
+/// [`PHEROMONE_PROPERTY`], no `$name` can spell it
+pub const NOW_PARAMETER: &str = "@now";
+
 /// Property reference: Table.column or alias.property
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PropertyRef {
@@ -148,11 +364,37 @@ pub enum Literal {
     Integer(i64),
     Float(f64),
     String(String),
+    /// `['a', 'b']`
+    List(Vec<Literal>),
+    /// `{city: 'Oslo'}`, entries in source order
+    Map(Vec<(String, Literal)>),
+    /// `TIMESTAMP '2024-03-01T12:00:00Z'`, as milliseconds since the Unix epoch
+    Timestamp(i64),
+}
+
+impl Literal {
+    /// The property value this literal stores as
+    pub fn to_property_value(&self) -> PropertyValue {
+        match self {
+            Literal::Null => PropertyValue::Null,
+            Literal::Bool(b) => PropertyValue::Bool(*b),
+            Literal::Integer(n) => PropertyValue::Int(*n),
+            Literal::Float(f) => PropertyValue::Float(*f),
+            Literal::String(s) => PropertyValue::String(s.clone()),
+            Literal::List(items) => PropertyValue::List(items.iter().map(Literal::to_property_value).collect()),
+            Literal::Map(entries) => PropertyValue::Map(
+                entries.iter().map(|(key, value)| (key.clone(), value.to_property_value())).collect(),
+            ),
+            Literal::Timestamp(ms) => PropertyValue::Timestamp(*ms),
+        }
+    }
 }
 
 /// SELECT clause (projection)
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SelectClause {
+    /// SELECT DISTINCT: repeated rows are returned once
+    pub distinct: bool,
     pub fields: Vec<SelectField>,
 }
 
@@ -187,16 +429,21 @@ pub struct OrderByField {
 }
 
 /// INSERT query
+///
+/// Property values are full expressions; they are constant-folded at plan
+/// time and evaluated without an entity in scope at execution time. Each
+/// `( {...} )` group after VALUES is one row.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InsertQuery {
     pub collection: String,
-    pub properties: Vec<(String, Literal)>,
+    pub rows: Vec<Vec<(String, Expression)>>,
 }
 
 /// UPDATE query
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UpdateQuery {
     pub collection: String,
+    pub alias: Option<String>,
     pub set: Vec<(String, Expression)>,
     pub where_clause: Option<WhereClause>,
 }
@@ -208,6 +455,25 @@ pub struct DeleteQuery {
     pub where_clause: Option<WhereClause>,
 }
 
+/// UPDATE EDGE query: UPDATE EDGE FROM Users a -[:TYPE]-> b SET ... WHERE ...
+///
+/// SET targets the matched edges' properties; WHERE tests the endpoints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateEdgeQuery {
+    pub from: FromClause,
+    pub pattern: TraversePattern,
+    pub set: Vec<(String, Expression)>,
+    pub where_clause: Option<WhereClause>,
+}
+
+/// DELETE EDGE query: DELETE EDGE FROM Users a -[:TYPE]-> b WHERE ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeleteEdgeQuery {
+    pub from: FromClause,
+    pub pattern: TraversePattern,
+    pub where_clause: Option<WhereClause>,
+}
+
 /// CREATE query (for edges/relationships)
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CreateQuery {
@@ -217,7 +483,194 @@ pub struct CreateQuery {
     pub properties: Vec<(String, Literal)>,
 }
 
+impl Query {
+    /// Replace literal values with parameters, returning each parameter's literal
+    ///
+    /// Statements that differ only in their literals normalize to the same
+    /// query, so they can share a cached plan. Parameters are named `#0`,
+    /// `#1`, ... in order of appearance, which no query text can spell.
+    /// Aggregate arguments, IN lists, LIKE patterns, edge properties and
+    /// subqueries are left in place.
+    pub fn extract_literals(&mut self) -> Vec<(String, Literal)> {
+        let mut literals = Vec::new();
+
+        match self {
+            Query::Select(q) => {
+                for join in &mut q.joins {
+                    join.condition.extract_literals(&mut literals);
+                }
+                if let Some(where_clause) = &mut q.where_clause {
+                    where_clause.condition.extract_literals(&mut literals);
+                }
+                for field in &mut q.select.fields {
+                    field.expression.extract_literals(&mut literals);
+                }
+                if let Some(group_by) = &mut q.group_by {
+                    for field in &mut group_by.fields {
+                        field.extract_literals(&mut literals);
+                    }
+                }
+                if let Some(having) = &mut q.having {
+                    having.condition.extract_literals(&mut literals);
+                }
+                if let Some(order_by) = &mut q.order_by {
+                    for field in &mut order_by.fields {
+                        field.expression.extract_literals(&mut literals);
+                    }
+                }
+            }
+            Query::Insert(q) => {
+                for (_, value) in q.rows.iter_mut().flatten() {
+                    value.extract_literals(&mut literals);
+                }
+            }
+            Query::Update(q) => {
+                for (_, value) in &mut q.set {
+                    value.extract_literals(&mut literals);
+                }
+                if let Some(where_clause) = &mut q.where_clause {
+                    where_clause.condition.extract_literals(&mut literals);
+                }
+            }
+            Query::Delete(q) => {
+                if let Some(where_clause) = &mut q.where_clause {
+                    where_clause.condition.extract_literals(&mut literals);
+                }
+            }
+            Query::Create(q) => {
+                q.source.extract_literals(&mut literals);
+                q.target.extract_literals(&mut literals);
+            }
+            Query::UpdateEdge(q) => {
+                for (_, value) in &mut q.set {
+                    value.extract_literals(&mut literals);
+                }
+                if let Some(where_clause) = &mut q.where_clause {
+                    where_clause.condition.extract_literals(&mut literals);
+                }
+            }
+            Query::DeleteEdge(q) => {
+                if let Some(where_clause) = &mut q.where_clause {
+                    where_clause.condition.extract_literals(&mut literals);
+                }
+            }
+            _ => {}
+        }
+
+        literals
+    }
+}
+
 impl Expression {
+    /// Replace literals in this expression with numbered parameters (see `Query::extract_literals`)
+    fn extract_literals(&mut self, literals: &mut Vec<(String, Literal)>) {
+        match self {
+            Expression::Literal(lit) => {
+                let name = format!("#{}", literals.len());
+                literals.push((name.clone(), lit.clone()));
+                *self = Expression::Parameter(name);
+            }
+            Expression::Property(_)
+            | Expression::Has(_)
+            | Expression::Parameter(_)
+            | Expression::Aggregate(..)
+            | Expression::ShortestPath(_)
+            | Expression::Wildcard(_)
+            | Expression::Exists(_)
+            | Expression::ScalarSubquery(_) => {}
+            Expression::Not(e)
+            | Expression::IsNull(e)
+            | Expression::In(e, _)
+            | Expression::Like(e, _)
+            | Expression::Match(e, _)
+            | Expression::InSubquery(e, _) => {
+                e.extract_literals(literals);
+            }
+            Expression::Function(_, args) => {
+                for arg in args {
+                    arg.extract_literals(literals);
+                }
+            }
+            Expression::Between(e, low, high) => {
+                e.extract_literals(literals);
+                low.extract_literals(literals);
+                high.extract_literals(literals);
+            }
+            Expression::And(l, r)
+            | Expression::Or(l, r)
+            | Expression::Equal(l, r)
+            | Expression::NotEqual(l, r)
+            | Expression::LessThan(l, r)
+            | Expression::LessThanEq(l, r)
+            | Expression::GreaterThan(l, r)
+            | Expression::GreaterThanEq(l, r)
+            | Expression::Contains(l, r)
+            | Expression::Add(l, r)
+            | Expression::Subtract(l, r)
+            | Expression::Multiply(l, r)
+            | Expression::Divide(l, r) => {
+                l.extract_literals(literals);
+                r.extract_literals(literals);
+            }
+        }
+    }
+
+    /// Subqueries in this expression, outermost first (not those nested
+    /// inside another subquery)
+    pub fn subqueries(&self) -> Vec<&Subquery> {
+        let mut found = Vec::new();
+        self.collect_subqueries(&mut found);
+        found
+    }
+
+    fn collect_subqueries<'a>(&'a self, found: &mut Vec<&'a Subquery>) {
+        match self {
+            Expression::Exists(subquery) | Expression::ScalarSubquery(subquery) => found.push(subquery),
+            Expression::InSubquery(e, subquery) => {
+                e.collect_subqueries(found);
+                found.push(subquery);
+            }
+            Expression::Property(_)
+            | Expression::Has(_)
+            | Expression::Literal(_)
+            | Expression::Parameter(_)
+            | Expression::ShortestPath(_)
+            | Expression::Wildcard(_) => {}
+            Expression::Not(e)
+            | Expression::IsNull(e)
+            | Expression::In(e, _)
+            | Expression::Like(e, _)
+            | Expression::Match(e, _)
+            | Expression::Aggregate(_, e, _) => e.collect_subqueries(found),
+            Expression::Function(_, args) => {
+                for arg in args {
+                    arg.collect_subqueries(found);
+                }
+            }
+            Expression::Between(e, low, high) => {
+                e.collect_subqueries(found);
+                low.collect_subqueries(found);
+                high.collect_subqueries(found);
+            }
+            Expression::And(l, r)
+            | Expression::Or(l, r)
+            | Expression::Equal(l, r)
+            | Expression::NotEqual(l, r)
+            | Expression::LessThan(l, r)
+            | Expression::LessThanEq(l, r)
+            | Expression::GreaterThan(l, r)
+            | Expression::GreaterThanEq(l, r)
+            | Expression::Contains(l, r)
+            | Expression::Add(l, r)
+            | Expression::Subtract(l, r)
+            | Expression::Multiply(l, r)
+            | Expression::Divide(l, r) => {
+                l.collect_subqueries(found);
+                r.collect_subqueries(found);
+            }
+        }
+    }
+
     /// Helper to create property reference
     pub fn property(entity: Option<&str>, property: &str) -> Self {
         Expression::Property(PropertyRef {
@@ -258,6 +711,7 @@ mod tests {
                 collection: "Users".to_string(),
                 alias: None,
             },
+            joins: Vec::new(),
             traverse: None,
             where_clause: Some(WhereClause {
                 condition: Expression::Equal(
@@ -266,11 +720,14 @@ mod tests {
                 ),
             }),
             select: SelectClause {
+                distinct: false,
                 fields: vec![SelectField {
                     expression: Expression::property(None, "name"),
                     alias: None,
                 }],
             },
+            group_by: None,
+            having: None,
             order_by: None,
             limit: None,
             offset: None,
@@ -287,13 +744,18 @@ mod tests {
                 collection: "Users".to_string(),
                 alias: Some("u".to_string()),
             },
+            joins: Vec::new(),
             traverse: Some(TraverseClause {
                 patterns: vec![TraversePattern {
                     direction: Direction::Outgoing,
-                    edge_type: Some("PURCHASED".to_string()),
+                    edge_alias: None,
+                    edge_types: vec!["PURCHASED".to_string()],
+                    edge_properties: Vec::new(),
                     target_alias: Some("p".to_string()),
                     min_hops: 1,
                     max_hops: 1,
+                    top_k: None,
+                    all_paths: false,
                 }],
             }),
             where_clause: Some(WhereClause {
@@ -303,6 +765,7 @@ mod tests {
                 ),
             }),
             select: SelectClause {
+                distinct: false,
                 fields: vec![
                     SelectField {
                         expression: Expression::property(Some("u"), "name"),
@@ -314,6 +777,8 @@ mod tests {
                     },
                 ],
             },
+            group_by: None,
+            having: None,
             order_by: None,
             limit: Some(10),
             offset: None,
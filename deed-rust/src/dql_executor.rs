@@ -3,1124 +3,7055 @@
 //! Executes optimized query plans against the graph storage.
 
 use crate::dql_ir::*;
-use crate::dql_optimizer::{AntColonyOptimizer, StigmergyCache};
+use crate::dql_optimizer::{self, AntColonyOptimizer, CacheStats, OptimizationReport, OptimizerConfig, StigmergyCache};
 use crate::dql_parser::Parser;
-use crate::graph::{Graph, Entity, Edge};
-use crate::transaction::{TransactionManager, TransactionId, IsolationLevel};
-use crate::wal::WALManager;
-use crate::btree::IndexManager;
-use crate::types::{EntityId, EdgeId, EdgeType, EntityType, Properties, PropertyValue};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock, Mutex};
+use crate::graph::{Degrees, Graph, Entity, Edge};
+use crate::storage::StorageEngine;
+use crate::transaction::{DeadlockError, SerializationError, TransactionManager, TransactionId, IsolationLevel};
+use crate::wal::{CheckpointPolicy, WALConfig, WALManager};
+use crate::btree::{tokenize, BTreeIndex, IndexKind, IndexManager};
+use crate::read_routing::{has_primary_hint, ReadBalancing, ReadReplicas, Route};
+use crate::replication::{NodeRole, ReplicationManager, ReplicationSeq, ReplicationSnapshot};
+use crate::archive::{ArchiveManager, ArchivedEntity};
+use crate::backup::{BackupConfig, BackupManager, BackupMetadata, BackupScheduler, BackupSnapshot, BackupType, IndexDefinition};
+use crate::auth::{Access, Session};
+use crate::distributed_partition::{ConsistencyLevel, PartitionManager};
+use crate::distributed_query::{ShardOwnership, ShardScope};
+use crate::audit::{statement_collections, normalize_statement, AuditEntry, AuditLog, AuditOutcome, AUDIT_COLLECTION};
+use crate::catalog::{is_catalog_collection, Catalog};
+use crate::error::DeedError;
+use crate::migration::{DataDirectory, OpenOutcome, OpenPolicy};
+use crate::firewall::{Firewall, FirewallPrincipal, StatementClass, StatementShape};
+use crate::graph_export::{ExportFilter, GraphFormat, Subgraph};
+use crate::import_export::{DataFormat, ImportOptions, ImportReport, MismatchPolicy, RecordReader, RecordWriter, ID_FIELD};
+use crate::dql_ast::{parse_degree_property, Direction, Literal, PathCall, ID_PROPERTY, NOW_PARAMETER, PHEROMONE_PROPERTY};
+use crate::mvcc::{ReadView, TxnWrites};
+use crate::schema::{Constraint, Schema, SchemaValidator, ValidationError};
+use crate::query_metrics::{QueryMetrics, QuerySample};
+use crate::shutdown::{BackgroundTask, ShutdownReport, ShutdownSignal};
+use crate::spill::{
+    distinct_key_size, property_value_size, row_size, value_size, ExternalSort, MemoryBudget, MemoryUsage, SpillFile,
+    Sorted,
+};
+use crate::change_feed::{ChangeEvent, ChangeFeed, ChangeFilter, ChangeKind, ChangeSubscription, OverflowPolicy};
+use crate::types::{parse_timestamp, DistinctKey, EntityId, EdgeId, Properties, PropertyValue};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, RwLock, Mutex};
 use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// When scans and filters evaluate their predicate on several threads
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParallelConfig {
+    /// Entities a scan or filter must see before it runs in parallel
+    pub threshold: usize,
+    /// Worker threads to use; `None` shares rayon's global pool (one per core)
+    pub max_threads: Option<usize>,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        ParallelConfig {
+            threshold: 10_000,
+            max_threads: None,
+        }
+    }
+}
+
+/// How often expired entities are deleted in the background, and how many at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpirationConfig {
+    /// Time between passes
+    pub interval: Duration,
+    /// Entities deleted per transaction, so no pass holds the graph for long
+    pub batch_size: usize,
+    /// Most entities one pass deletes; the rest wait for the next
+    pub max_per_pass: usize,
+}
+
+impl Default for ExpirationConfig {
+    fn default() -> Self {
+        ExpirationConfig {
+            interval: Duration::from_secs(1),
+            batch_size: 100,
+            max_per_pass: 10_000,
+        }
+    }
+}
 
 /// Query executor with biological optimization and transaction support
+///
+/// Clones share the session: its transaction, settings and components.
+#[derive(Clone)]
 pub struct DQLExecutor {
     graph: Arc<RwLock<Graph>>,
     optimizer: Arc<RwLock<AntColonyOptimizer>>,
     cache: Arc<RwLock<StigmergyCache>>,
+    /// How learning decays and where it's saved, once configured
+    optimizer_config: Option<OptimizerConfig>,
     transaction_manager: Arc<TransactionManager>,
     wal_manager: Option<Arc<WALManager>>,
     current_transaction: Arc<Mutex<Option<TransactionId>>>,
     index_manager: Arc<IndexManager>,
+    session: Arc<Mutex<SessionSettings>>,
+    replication: Option<Arc<ReplicationManager>>,
+    /// Replication log appends of the open transaction, made when it commits
+    pending_replication: Arc<Mutex<Vec<ReplicationLog>>>,
+    /// The graph's change feed, and the open transaction's changes for it
+    changes: Arc<ChangeFeed>,
+    pending_changes: Arc<Mutex<PendingChanges>>,
+    /// The open transaction's savepoints, oldest first
+    savepoints: Arc<Mutex<Vec<SavepointMark>>>,
+    /// Replicas reads may be routed to, shared by the master's sessions
+    replicas: Arc<ReadReplicas>,
+    archive: Arc<ArchiveManager>,
+    firewall: Option<(Arc<Firewall>, FirewallPrincipal)>,
+    /// Session whose role every statement is checked against
+    caller: Option<Session>,
+    /// Where the caller's writes are recorded
+    audit: Option<Arc<AuditLog>>,
+    /// This node's view of network partitions, and the consistency reads need
+    partition: Option<(Arc<PartitionManager>, ConsistencyLevel)>,
+    /// Shards this node takes writes for, as a node of a sharded cluster
+    shard_ownership: Option<ShardOwnership>,
+    /// Shards a sub-query answers for; entities of other shards are skipped
+    shard_scope: Option<ShardScope>,
+    schemas: Arc<RwLock<SchemaValidator>>,
+    parallel: ParallelConfig,
+    scan_pool: Option<Arc<rayon::ThreadPool>>,
+    metrics: Arc<QueryMetrics>,
+    /// Memory GROUP BY and sorts may hold, unless the session or statement sets their own
+    memory_budget: MemoryBudget,
+    clock: Clock,
+    /// Statements running, and whether new ones are accepted
+    lifecycle: Arc<Lifecycle>,
+}
+
+/// The open transaction's changes for the change feed
+///
+/// Whether to collect them is settled at its first change, so a subscriber
+/// arriving meanwhile is sent the whole transaction or none of it.
+#[derive(Default)]
+struct PendingChanges {
+    collecting: Option<bool>,
+    events: Vec<ChangeEvent>,
+}
+
+/// How much replication and change feed work a transaction had queued at a savepoint
+#[derive(Debug, Clone, Copy)]
+struct SavepointMark {
+    replicated: usize,
+    changes: usize,
+}
+
+/// Append of one change to a master's replication log
+type ReplicationLog = Box<dyn FnOnce(&ReplicationManager) -> Result<ReplicationSeq, String> + Send>;
+
+/// Source of the current time for NOW(), in milliseconds since the Unix epoch
+pub type Clock = Arc<dyn Fn() -> i64 + Send + Sync>;
+
+/// A streamed result row, or why producing it failed
+type RowResult = Result<HashMap<String, Value>, String>;
+
+/// An edge matched by a traversal, with the entities it binds
+type EdgeBinding = (Edge, HashMap<String, Entity>);
+
+fn system_clock() -> Clock {
+    Arc::new(|| chrono::Utc::now().timestamp_millis())
 }
 
 impl DQLExecutor {
     /// Create a new executor without WAL (non-durable)
     pub fn new(graph: Arc<RwLock<Graph>>) -> Self {
+        let changes = graph.read().unwrap().change_feed();
         DQLExecutor {
             graph,
             optimizer: Arc::new(RwLock::new(AntColonyOptimizer::new())),
             cache: Arc::new(RwLock::new(StigmergyCache::new(1000))),
+            optimizer_config: None,
             transaction_manager: Arc::new(TransactionManager::new()),
             wal_manager: None,
             current_transaction: Arc::new(Mutex::new(None)),
             index_manager: Arc::new(IndexManager::new()),
+            session: Arc::new(Mutex::new(SessionSettings::default())),
+            replication: None,
+            pending_replication: Arc::new(Mutex::new(Vec::new())),
+            changes,
+            pending_changes: Arc::new(Mutex::new(PendingChanges::default())),
+            savepoints: Arc::new(Mutex::new(Vec::new())),
+            replicas: Arc::new(ReadReplicas::new()),
+            archive: Arc::new(ArchiveManager::in_memory()),
+            firewall: None,
+            caller: None,
+            audit: None,
+            partition: None,
+            shard_ownership: None,
+            shard_scope: None,
+            schemas: Arc::new(RwLock::new(SchemaValidator::new())),
+            parallel: ParallelConfig::default(),
+            scan_pool: None,
+            metrics: Arc::new(QueryMetrics::default()),
+            memory_budget: MemoryBudget::default(),
+            clock: system_clock(),
+            lifecycle: Arc::new(Lifecycle::default()),
         }
     }
 
+    /// Create a new executor over a graph persisted in RocksDB at `path`
+    ///
+    /// The graph is loaded from whatever is already stored there, and every
+    /// change made through the executor is written back.
+    pub fn new_persistent<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let storage = StorageEngine::open(path)?;
+        let graph = Graph::load_from_storage(Arc::new(storage))?;
+        Ok(Self::new(Arc::new(RwLock::new(graph))))
+    }
+
+    /// Open an executor over a data directory, migrating its formats per `policy`
+    ///
+    /// The manifest is checked before anything else is read. The graph is then
+    /// recovered from the directory's WAL, and its archive, firewall rules,
+    /// audit log and optimizer state are opened from their usual paths.
+    /// Statements run as the anonymous firewall principal.
+    pub fn open_data_dir<P: AsRef<Path>>(root: P, policy: OpenPolicy) -> Result<Self, String> {
+        let directory = match DataDirectory::open(root, policy)? {
+            OpenOutcome::Opened(directory) => directory,
+            OpenOutcome::Migrated { directory, plan, backup_dir } => {
+                eprintln!(
+                    "Migrated data directory in {} step(s); backup kept at {}",
+                    plan.steps.len(),
+                    backup_dir.display()
+                );
+                directory
+            }
+            OpenOutcome::DryRun(plan) => {
+                return Err(format!(
+                    "Dry run opens nothing ({} migration step(s) planned)",
+                    plan.steps.len()
+                ))
+            }
+        };
+
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let archive = ArchiveManager::open(directory.archive_dir())?;
+        let firewall = Firewall::open(directory.firewall_path())?;
+        let audit = AuditLog::open(directory.audit_path(), Default::default())?;
+
+        Self::recover_from_wal(graph, directory.wal_path())?
+            .with_archive(Arc::new(archive))
+            .with_firewall(Arc::new(firewall), FirewallPrincipal::default())
+            .with_audit_log(Arc::new(audit))
+            .with_optimizer_config(OptimizerConfig::default().with_state_path(directory.optimizer_state_path()))
+    }
+
     /// Create a new executor with WAL for durability
     pub fn new_with_wal<P: AsRef<Path>>(graph: Arc<RwLock<Graph>>, wal_path: P) -> Result<Self, String> {
-        let wal_manager = WALManager::new(wal_path)
+        Self::new_with_wal_config(graph, wal_path, WALConfig::default())
+    }
+
+    /// Create a new executor with WAL, choosing how commits are synced
+    pub fn new_with_wal_config<P: AsRef<Path>>(
+        graph: Arc<RwLock<Graph>>,
+        wal_path: P,
+        config: WALConfig,
+    ) -> Result<Self, String> {
+        let wal_manager = WALManager::with_config(wal_path, config)
             .map_err(|e| format!("Failed to create WAL: {}", e))?;
+        let changes = graph.read().unwrap().change_feed();
 
         Ok(DQLExecutor {
             graph,
             optimizer: Arc::new(RwLock::new(AntColonyOptimizer::new())),
             cache: Arc::new(RwLock::new(StigmergyCache::new(1000))),
+            optimizer_config: None,
             transaction_manager: Arc::new(TransactionManager::new()),
             wal_manager: Some(Arc::new(wal_manager)),
             current_transaction: Arc::new(Mutex::new(None)),
             index_manager: Arc::new(IndexManager::new()),
+            session: Arc::new(Mutex::new(SessionSettings::default())),
+            replication: None,
+            pending_replication: Arc::new(Mutex::new(Vec::new())),
+            changes,
+            pending_changes: Arc::new(Mutex::new(PendingChanges::default())),
+            savepoints: Arc::new(Mutex::new(Vec::new())),
+            replicas: Arc::new(ReadReplicas::new()),
+            archive: Arc::new(ArchiveManager::in_memory()),
+            firewall: None,
+            caller: None,
+            audit: None,
+            partition: None,
+            shard_ownership: None,
+            shard_scope: None,
+            schemas: Arc::new(RwLock::new(SchemaValidator::new())),
+            parallel: ParallelConfig::default(),
+            scan_pool: None,
+            metrics: Arc::new(QueryMetrics::default()),
+            memory_budget: MemoryBudget::default(),
+            clock: system_clock(),
+            lifecycle: Arc::new(Lifecycle::default()),
         })
     }
 
+    /// Create an executor over an existing WAL, replaying it into the graph
+    ///
+    /// Changes of committed transactions are applied in commit order; those
+    /// of rolled back or unfinished ones are discarded. The log is then
+    /// checkpointed so it holds only the recovered state.
+    pub fn recover_from_wal<P: AsRef<Path>>(graph: Arc<RwLock<Graph>>, wal_path: P) -> Result<Self, String> {
+        let executor = Self::new_with_wal(graph, wal_path)?;
+
+        if let Some(wal) = &executor.wal_manager {
+            let recovery = wal.recover().map_err(|e| format!("Failed to read WAL: {}", e))?;
+
+            let graph = executor.graph.read().unwrap();
+            recovery.replay(&graph)?;
+            wal.checkpoint(&graph)
+                .map_err(|e| format!("Failed to checkpoint WAL: {}", e))?;
+        }
+
+        Ok(executor)
+    }
+
     /// Create a new executor with shared components (for connection pooling)
+    ///
+    /// Executors sharing a graph should share its indexes and schemas too,
+    /// or writes through one skip the indexes and constraints defined through another.
     pub fn with_shared_components(
         graph: Arc<RwLock<Graph>>,
         optimizer: Arc<RwLock<AntColonyOptimizer>>,
         cache: Arc<RwLock<StigmergyCache>>,
         transaction_manager: Arc<TransactionManager>,
         wal_manager: Option<Arc<WALManager>>,
+        index_manager: Arc<IndexManager>,
+        schemas: Arc<RwLock<SchemaValidator>>,
     ) -> Self {
+        let changes = graph.read().unwrap().change_feed();
         DQLExecutor {
             graph,
             optimizer,
             cache,
+            optimizer_config: None,
             transaction_manager,
             wal_manager,
             current_transaction: Arc::new(Mutex::new(None)),
-            index_manager: Arc::new(IndexManager::new()),
+            index_manager,
+            session: Arc::new(Mutex::new(SessionSettings::default())),
+            replication: None,
+            pending_replication: Arc::new(Mutex::new(Vec::new())),
+            changes,
+            pending_changes: Arc::new(Mutex::new(PendingChanges::default())),
+            savepoints: Arc::new(Mutex::new(Vec::new())),
+            replicas: Arc::new(ReadReplicas::new()),
+            archive: Arc::new(ArchiveManager::in_memory()),
+            firewall: None,
+            caller: None,
+            audit: None,
+            partition: None,
+            shard_ownership: None,
+            shard_scope: None,
+            schemas,
+            parallel: ParallelConfig::default(),
+            scan_pool: None,
+            metrics: Arc::new(QueryMetrics::default()),
+            memory_budget: MemoryBudget::default(),
+            clock: system_clock(),
+            lifecycle: Arc::new(Lifecycle::default()),
         }
     }
 
-    /// Execute a DQL query string
-    pub fn execute(&self, query_str: &str) -> Result<QueryResult, String> {
-        // Parse query
-        let query = Parser::parse(query_str)?;
+    /// Open another session on this executor's database
+    ///
+    /// It shares everything clones do except the session itself: it starts
+    /// with no open transaction and default settings.
+    pub fn new_session(&self) -> Self {
+        DQLExecutor {
+            current_transaction: Arc::new(Mutex::new(None)),
+            session: Arc::new(Mutex::new(SessionSettings::default())),
+            pending_replication: Arc::new(Mutex::new(Vec::new())),
+            pending_changes: Arc::new(Mutex::new(PendingChanges::default())),
+            savepoints: Arc::new(Mutex::new(Vec::new())),
+            ..self.clone()
+        }
+    }
 
-        // Handle transaction and index commands separately
-        match &query {
-            crate::dql_ast::Query::Begin(begin_query) => {
-                return self.handle_begin(begin_query);
-            }
-            crate::dql_ast::Query::Commit => {
-                return self.handle_commit();
-            }
-            crate::dql_ast::Query::Rollback => {
-                return self.handle_rollback();
-            }
-            crate::dql_ast::Query::CreateIndex(create_index) => {
-                return self.handle_create_index(create_index);
-            }
-            crate::dql_ast::Query::DropIndex(drop_index) => {
-                return self.handle_drop_index(drop_index);
-            }
-            _ => {
-                // Regular query - continue below
+    /// Route bounded-staleness reads using this master's replication state
+    ///
+    /// A master's committed mutations are also appended to its replication
+    /// log, for slaves to pull.
+    pub fn with_replication(mut self, replication: Arc<ReplicationManager>) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    /// Snapshot the graph for a slave too far behind the replication log
+    ///
+    /// The sequence number is read before the graph, so the snapshot may
+    /// already hold some later entries; applying those again is harmless.
+    /// Pass this to [`ReplicationManager::serve_slaves`].
+    pub fn replication_snapshot(&self) -> Result<ReplicationSnapshot, String> {
+        let replication = self.replication.as_ref().ok_or("No replication configured")?;
+        let seq = replication.current_seq();
+        let snapshot = self.backup_snapshot()?;
+        Ok(ReplicationSnapshot {
+            seq,
+            entities: snapshot.entities,
+            edges: snapshot.edges,
+        })
+    }
+
+    /// Decay and keep the optimizer's learning as `config` says
+    ///
+    /// Learning saved at the config's state path is loaded now, and saved
+    /// again on checkpoint and shutdown; a state file that can't be read is
+    /// logged and the optimizer starts afresh. Decay ticks run until shutdown.
+    pub fn with_optimizer_config(mut self, config: OptimizerConfig) -> Result<Self, String> {
+        config.validate()?;
+        if let Some(path) = &config.state_path {
+            let mut cache = self.cache.write().unwrap();
+            let mut optimizer = self.optimizer.write().unwrap();
+            if let Err(e) = dql_optimizer::load_learning(path, &mut optimizer, &mut cache) {
+                eprintln!("Ignoring optimizer state: {}", e);
+                optimizer.reset();
+                cache.clear();
             }
         }
 
-        // Check if this is a mutation that needs auto-commit
-        let needs_auto_commit = self.is_mutation_query(&query);
-        let had_active_txn = self.current_transaction.lock().unwrap().is_some();
+        if let Some(interval) = config.decay_interval {
+            let optimizer = Arc::downgrade(&self.optimizer);
+            let cache = Arc::downgrade(&self.cache);
+            let (factor, floor) = (config.decay_factor, config.eviction_floor);
+            let task = BackgroundTask::spawn_thread("optimizer-decay", &self.shutdown_signal(), move |stop| {
+                while !stop.wait_timeout(interval) {
+                    let (Some(optimizer), Some(cache)) = (optimizer.upgrade(), cache.upgrade()) else {
+                        break;
+                    };
+                    cache.write().unwrap().decay(factor, floor);
+                    optimizer.write().unwrap().decay(factor, floor);
+                }
+            });
+            self.register_task(task);
+        }
 
-        // Auto-begin transaction if needed
-        if needs_auto_commit && !had_active_txn {
-            let txn_id = self.transaction_manager.begin(IsolationLevel::default())?;
-            *self.current_transaction.lock().unwrap() = Some(txn_id);
+        self.optimizer_config = Some(config);
+        Ok(self)
+    }
 
-            // Log to WAL
-            if let Some(wal) = &self.wal_manager {
-                wal.log_begin(txn_id, IsolationLevel::default())
-                    .map_err(|e| format!("WAL error: {}", e))?;
-            }
+    /// Save the optimizer's learning to the configured state path now
+    pub fn save_learning(&self) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        if self.learning_path().is_none() {
+            return Err("No optimizer state path configured".to_string());
         }
+        self.persist_learning()
+    }
 
-        // Build initial plan
-        let mut builder = QueryPlanBuilder::new();
-        let plan = match &query {
-            crate::dql_ast::Query::Select(q) => builder.build_select(q)?,
-            crate::dql_ast::Query::Insert(q) => builder.build_insert(q)?,
-            crate::dql_ast::Query::Update(q) => builder.build_update(q)?,
-            crate::dql_ast::Query::Delete(q) => builder.build_delete(q)?,
-            crate::dql_ast::Query::Create(q) => builder.build_create(q)?,
-            _ => unreachable!(), // Transaction commands handled above
-        };
+    /// Forget everything the optimizer and plan cache have learned, saved state included
+    pub fn reset_learning(&self) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        self.cache.write().unwrap().clear();
+        self.optimizer.write().unwrap().reset();
+        self.persist_learning()
+    }
 
-        // Try cache first (stigmergy)
-        let query_signature = self.query_signature(query_str);
-        let optimized_plan = {
-            let mut cache = self.cache.write().unwrap();
-            if let Some(cached_plan) = cache.get(&query_signature) {
-                cached_plan
-            } else {
-                // Optimize with ant colony
-                let graph = self.graph.read().unwrap();
-                let stats = graph.stats();
-                drop(graph); // Release read lock
+    fn learning_path(&self) -> Option<&Path> {
+        self.optimizer_config.as_ref()?.state_path.as_deref()
+    }
 
-                let mut optimizer = self.optimizer.write().unwrap();
-                let optimized = optimizer.optimize(plan, &stats);
+    /// Save learning to the state path, if one is configured
+    fn persist_learning(&self) -> Result<(), String> {
+        let Some(path) = self.learning_path() else {
+            return Ok(());
+        };
+        let cache = self.cache.read().unwrap();
+        let optimizer = self.optimizer.read().unwrap();
+        dql_optimizer::save_learning(path, &optimizer, &cache)
+    }
 
-                // Cache the optimized plan
-                cache.put(query_signature.clone(), optimized.clone());
+    /// Delete expired entities in the background, as `config` says
+    ///
+    /// Expired entities are invisible to statements whether or not they
+    /// have been deleted yet. Passes run until shutdown.
+    pub fn with_expiration(self, config: ExpirationConfig) -> Result<Self, String> {
+        if config.interval.is_zero() || config.batch_size == 0 || config.max_per_pass == 0 {
+            return Err("Expiration interval, batch size and pass limit must be positive".to_string());
+        }
 
-                optimized
+        let executor = self.detached();
+        let task = BackgroundTask::spawn_thread("ttl-expiration", &self.shutdown_signal(), move |stop| {
+            while !stop.wait_timeout(config.interval) {
+                // A failed batch is rolled back and retried on the next pass
+                let _ = executor.expire_entities(config.max_per_pass, config.batch_size);
             }
-        };
+        });
+        self.register_task(task);
+        Ok(self)
+    }
 
-        // Execute the plan
-        let result = self.execute_plan(&optimized_plan);
+    /// Delete up to `limit` expired entities with their edges, then expired
+    /// edges, `batch_size` to a transaction, returning how many were deleted
+    ///
+    /// Each deletion is logged like a DELETE, so recovery and replicas agree.
+    /// Refused inside a transaction.
+    pub fn expire_entities(&self, limit: usize, batch_size: usize) -> Result<usize, String> {
+        self.check_caller(Access::Admin)?;
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err("Expired entities can't be deleted inside a transaction".to_string());
+        }
 
-        // Auto-commit if we auto-began
-        if needs_auto_commit && !had_active_txn {
-            if result.is_ok() {
-                self.handle_commit()?;
-            } else {
-                self.handle_rollback()?;
+        let now = (self.clock)();
+        let expired = self.graph.read().unwrap().expired_entities(now, limit);
+        let mut deleted = 0;
+        for batch in expired.chunks(batch_size.max(1)) {
+            // Stop between batches once shutdown begins
+            if self.lifecycle.signal.is_cancelled() {
+                break;
             }
+            deleted += self.delete_expired(batch, now)?;
         }
 
-        result
+        let expired = self.graph.read().unwrap().expired_edges(now, limit.saturating_sub(deleted));
+        for batch in expired.chunks(batch_size.max(1)) {
+            if self.lifecycle.signal.is_cancelled() {
+                break;
+            }
+            deleted += self.delete_expired_edges(batch, now)?;
+        }
+        Ok(deleted)
     }
 
-    /// Execute a query plan
-    fn execute_plan(&self, plan: &QueryPlan) -> Result<QueryResult, String> {
-        // Execution context
-        let mut ctx = ExecutionContext::new();
+    /// Delete a batch of entities expired by `now` as a transaction of its own
+    fn delete_expired(&self, ids: &[EntityId], now: i64) -> Result<usize, String> {
+        self.begin_implicit()?;
+        let txn_id = self.current_transaction.lock().unwrap().ok_or("No active transaction")?;
 
-        // Execute operations sequentially
-        for operation in &plan.operations {
-            // Check if operation needs write access
-            if self.is_mutation(operation) {
-                // Execute mutation with write lock (released per operation)
-                self.execute_mutation(operation, &mut ctx)?;
-            } else {
-                // Execute read operation with shared read lock
+        let mut deleted = 0;
+        for &id in ids {
+            // Deleted, or given a later expiry, since the batch was found
+            let expired = {
                 let graph = self.graph.read().unwrap();
-                self.execute_operation(operation, &mut ctx, &graph)?;
+                graph.get_entity_ref(id).is_some_and(|entity| graph.is_expired(&entity, now))
+            };
+            if !expired {
+                continue;
+            }
+            if let Err(e) = self.delete_entity_in(id, txn_id) {
+                self.handle_rollback()?;
+                return Err(e.into());
             }
+            deleted += 1;
         }
 
-        // Return results
-        Ok(ctx.into_result())
+        self.handle_commit()?;
+        Ok(deleted)
     }
 
-    /// Check if operation requires write access
-    fn is_mutation(&self, operation: &Operation) -> bool {
-        matches!(
-            operation,
-            Operation::InsertEntity { .. }
-                | Operation::UpdateEntities { .. }
-                | Operation::DeleteEntities { .. }
-                | Operation::CreateEdge { .. }
-        )
+    /// Delete a batch of edges expired by `now` as a transaction of its own
+    fn delete_expired_edges(&self, ids: &[EdgeId], now: i64) -> Result<usize, String> {
+        self.begin_implicit()?;
+        let txn_id = self.current_transaction.lock().unwrap().ok_or("No active transaction")?;
+
+        let mut deleted = 0;
+        for &id in ids {
+            let graph = self.graph.read().unwrap();
+            // Deleted, or given a later expiry, since the batch was found
+            let Some(edge) = graph.get_edge(id).filter(|_| graph.is_edge_expired(id, now)) else {
+                continue;
+            };
+            let result = self.delete_edge_in(&graph, &edge, txn_id);
+            drop(graph);
+            if let Err(e) = result {
+                self.handle_rollback()?;
+                return Err(e.into());
+            }
+            deleted += 1;
+        }
+
+        self.handle_commit()?;
+        Ok(deleted)
     }
 
-    /// Execute mutation operations (INSERT, UPDATE, DELETE, CREATE)
-    fn execute_mutation(
-        &self,
-        operation: &Operation,
-        ctx: &mut ExecutionContext,
-    ) -> Result<(), String> {
-        match operation {
-            Operation::InsertEntity {
-                collection,
-                properties,
-            } => {
-                let mut props = Properties::new();
-                for (key, value) in properties {
-                    props.insert(key.clone(), self.value_to_property_value(value));
-                }
+    /// A clone with a session of its own, for work running beside the session's statements
+    fn detached(&self) -> Self {
+        DQLExecutor {
+            current_transaction: Arc::new(Mutex::new(None)),
+            session: Arc::new(Mutex::new(SessionSettings::default())),
+            pending_replication: Arc::new(Mutex::new(Vec::new())),
+            pending_changes: Arc::new(Mutex::new(PendingChanges::default())),
+            savepoints: Arc::new(Mutex::new(Vec::new())),
+            caller: None,
+            ..self.clone()
+        }
+    }
 
-                // Acquire write lock for insertion
-                let graph = self.graph.read().unwrap();
-                let entity_id = graph.add_entity(collection.clone(), props);
-                drop(graph);
+    /// Set when the WAL is checkpointed automatically after a commit
+    pub fn with_checkpoint_policy(self, policy: CheckpointPolicy) -> Self {
+        if let Some(wal) = &self.wal_manager {
+            wal.set_checkpoint_policy(policy);
+        }
+        self
+    }
 
-                ctx.last_inserted_id = Some(entity_id);
-                ctx.rows_affected += 1;
+    /// Let a write wait up to `timeout` for another transaction's
+    /// uncommitted write to the same entity, instead of failing at once
+    ///
+    /// Applies to every executor sharing the transaction manager.
+    pub fn with_lock_wait_timeout(self, timeout: Duration) -> Self {
+        self.transaction_manager.set_lock_wait_timeout(timeout);
+        self
+    }
 
-                // Store result for SELECT queries after INSERT
-                let mut result_row = HashMap::new();
-                result_row.insert("id".to_string(), Value::EntityId(entity_id.as_u64()));
-                ctx.result_rows.push(result_row);
+    /// Checkpoint the WAL, replacing its history with the graph's current state
+    ///
+    /// The optimizer's learning is saved too, with a state path configured.
+    pub fn checkpoint(&self) -> Result<(), String> {
+        if self.wal_manager.is_none() {
+            return Err("No WAL configured".to_string());
+        }
 
-                Ok(())
-            }
+        if !self.try_checkpoint()? {
+            return Err("Cannot checkpoint while transactions are active".to_string());
+        }
 
-            Operation::UpdateEntities { binding, updates } => {
-                // First, get entities to update from context
-                let entity_ids: Vec<EntityId> = ctx
-                    .bindings
-                    .get(binding)
-                    .ok_or_else(|| format!("Binding not found: {}", binding))?
-                    .iter()
-                    .map(|e| e.id)
-                    .collect();
+        self.persist_learning()
+    }
 
-                // Get current transaction ID if in a transaction
-                let txn_id = self.current_transaction.lock().unwrap().clone();
+    /// Shut the executor down: refuse new statements, let running ones
+    /// finish, and leave everything committed on disk
+    ///
+    /// Statements still running after `timeout` are cancelled and given a
+    /// moment to stop. Then the session's open transaction is rolled back,
+    /// the WAL is flushed and synced, the optimizer's learning is saved, and
+    /// registered background tasks (told to stop when the shutdown began)
+    /// get what is left of `timeout` to finish. The first of these steps to fail is
+    /// returned once the tasks have been waited for. Clones share the
+    /// executor's state, so shutting one down shuts down all; later calls
+    /// wait for the first and return its report.
+    pub fn shutdown(&self, timeout: Duration) -> Result<ShutdownReport, String> {
+        let deadline = Instant::now() + timeout;
+        let mut finished = self.lifecycle.report.lock().unwrap();
+        if let Some(report) = &*finished {
+            return Ok(report.clone());
+        }
 
-                // Acquire write lock and update each entity
-                let graph = self.graph.read().unwrap();
+        self.lifecycle.signal.cancel();
+        let (cancelled_queries, unstopped_queries) = self.lifecycle.drain(deadline);
+        let mut report = ShutdownReport {
+            cancelled_queries,
+            unstopped_queries,
+            ..Default::default()
+        };
 
-                for entity_id in &entity_ids {
-                    if let Some(mut entity) = graph.get_entity(*entity_id) {
-                        // Save snapshot before modification if in a transaction
-                        if let Some(tid) = txn_id {
-                            let entity_json = serde_json::to_string(&entity)
-                                .map_err(|e| format!("Failed to serialize entity: {}", e))?;
-                            self.transaction_manager.save_entity_snapshot(tid, entity_id.0, entity_json)?;
-                        }
+        let mut failed = Ok(());
+        if self.current_transaction.lock().unwrap().is_some() {
+            failed = self.handle_rollback().map(drop).map_err(String::from);
+            report.rolled_back_transactions += 1;
+        }
+        if let Some(wal) = &self.wal_manager {
+            failed = failed.and(wal.flush().map_err(|e| format!("WAL error: {}", e)));
+        }
+        failed = failed.and(self.persist_learning());
+
+        let tasks = std::mem::take(&mut *self.lifecycle.tasks.lock().unwrap());
+        report.unfinished_tasks = tasks
+            .into_iter()
+            .filter(|task| !task.wait_timeout(deadline.saturating_duration_since(Instant::now())))
+            .map(|task| task.name().to_string())
+            .collect();
+
+        failed?;
+        *finished = Some(report.clone());
+        Ok(report)
+    }
 
-                        // Apply updates
-                        for (key, expr) in updates {
-                            let value = self.evaluate_expression(expr, &entity, ctx);
-                            entity.set_property(key.clone(), value);
-                        }
+    /// The signal `shutdown` gives background tasks, for starting them
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.lifecycle.signal.clone()
+    }
 
-                        // Write the updated entity back to storage
-                        graph.update_entity(entity)?;
-                    }
-                }
+    /// Have `shutdown` wait for a background task started with `shutdown_signal`
+    pub fn register_task(&self, task: BackgroundTask) {
+        self.lifecycle.tasks.lock().unwrap().push(task);
+    }
 
-                drop(graph);
+    /// Subscribe to the graph's committed changes
+    ///
+    /// Changes are delivered once their transaction commits, whichever
+    /// executor sharing the graph made them.
+    pub fn subscribe(&self, filter: ChangeFilter) -> ChangeSubscription {
+        self.changes.subscribe(filter)
+    }
 
-                ctx.rows_affected = entity_ids.len();
-                Ok(())
-            }
+    /// Subscribe, holding up to `capacity` undelivered changes
+    pub fn subscribe_with(&self, filter: ChangeFilter, capacity: usize, policy: OverflowPolicy) -> ChangeSubscription {
+        self.changes.subscribe_with(filter, capacity, policy)
+    }
 
-            Operation::DeleteEntities { binding } => {
-                // Get entities to delete
-                let entity_ids: Vec<EntityId> = ctx
-                    .bindings
-                    .get(binding)
-                    .ok_or_else(|| format!("Binding not found: {}", binding))?
-                    .iter()
-                    .map(|e| e.id)
-                    .collect();
+    /// Back up the database into `config.backup_dir`
+    ///
+    /// The graph is held exclusively while it is copied, and entities are read
+    /// at the latest commit, so neither half-applied statements nor open
+    /// transactions' writes end up in the backup.
+    pub fn create_backup(&self, config: &BackupConfig, backup_type: BackupType) -> Result<BackupMetadata, String> {
+        self.check_caller(Access::Admin)?;
+        let snapshot = self.backup_snapshot()?;
+        BackupManager::new(config.clone())?.create_backup(&snapshot, backup_type)
+    }
 
-                // Get current transaction ID if in a transaction
-                let txn_id = self.current_transaction.lock().unwrap().clone();
+    /// Take backups on `scheduler`'s schedule until the executor shuts down
+    ///
+    /// Each backup copies the database as [`DQLExecutor::create_backup`]
+    /// does. Must be called within a tokio runtime.
+    pub fn schedule_backups(&self, scheduler: &Arc<BackupScheduler>) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        let executor = self.clone();
+        let task = scheduler.start(Arc::new(move || executor.backup_snapshot()), &self.shutdown_signal());
+        self.register_task(task);
+        Ok(())
+    }
 
-                // Acquire write lock and delete
-                let graph = self.graph.read().unwrap();
+    fn backup_snapshot(&self) -> Result<BackupSnapshot, String> {
+        // The graph holds exactly what has been committed
+        let graph = self.graph.read().unwrap();
+        let entities = graph.get_all_entities();
+        let edges = graph.get_all_edges();
+
+        let indexes = self
+            .index_manager
+            .list_indexes()
+            .iter()
+            .filter_map(|name| self.index_manager.get_index(name))
+            .map(|index| IndexDefinition {
+                name: index.name,
+                collection: index.collection,
+                field: index.field,
+                unique: index.unique,
+                kind: index.kind,
+            })
+            .collect();
+
+        Ok(BackupSnapshot {
+            entities,
+            edges,
+            schemas: self.schemas.read().unwrap().schemas().cloned().collect(),
+            indexes,
+            wal_lsn: self.wal_manager.as_ref().map_or(0, |wal| wal.current_lsn()),
+        })
+    }
 
-                // Delete each entity from storage
-                for entity_id in &entity_ids {
-                    // Save snapshot before deletion if in a transaction
-                    if let Some(tid) = txn_id {
-                        if let Some(entity) = graph.get_entity(*entity_id) {
-                            let entity_json = serde_json::to_string(&entity)
-                                .map_err(|e| format!("Failed to serialize entity: {}", e))?;
-                            self.transaction_manager.save_entity_snapshot(tid, entity_id.0, entity_json)?;
-                        }
-                    }
+    /// Restore a backup (replaying an incremental one on top of its parents)
+    ///
+    /// [`LATEST_BACKUP`](crate::backup::LATEST_BACKUP) restores the newest
+    /// backup with a complete chain. Replaces the graph, indexes and schemas. Refuses to overwrite existing
+    /// data unless `force` is set.
+    pub fn restore_backup(&self, config: &BackupConfig, backup_id: &str, force: bool) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        let snapshot = BackupManager::new(config.clone())?.load_snapshot(backup_id)?;
+
+        let graph = self.graph.write().unwrap();
+        if !self.transaction_manager.get_active_txn_ids().is_empty() {
+            return Err("Cannot restore while transactions are active".to_string());
+        }
 
-                    graph.delete_entity(*entity_id)?;
-                }
+        let mut schemas = self.schemas.write().unwrap();
+        let existing = graph.entity_count() > 0
+            || !self.index_manager.list_indexes().is_empty()
+            || schemas.schemas().next().is_some();
+        if existing && !force {
+            return Err("Database is not empty; restore with force to replace it".to_string());
+        }
 
-                drop(graph);
+        // Replace the data in place, so a persistent graph writes it through
+        let doomed: Vec<EntityId> = graph.get_all_entities().iter().map(|e| e.id).collect();
+        graph.delete_entities(&doomed)?;
+        for entity in snapshot.entities {
+            graph.insert_entity_with_id(entity)?;
+        }
+        for edge in snapshot.edges {
+            graph.insert_edge_with_id(edge)?;
+        }
 
-                ctx.deleted_count = entity_ids.len();
-                ctx.rows_affected = entity_ids.len();
-                Ok(())
-            }
+        // The log restarts from the restored data, so recovery doesn't
+        // bring back what the restore replaced
+        if let Some(wal) = &self.wal_manager {
+            wal.checkpoint(&graph)
+                .map_err(|e| format!("Failed to checkpoint WAL: {}", e))?;
+        }
 
-            Operation::CreateEdge {
-                source,
-                target,
-                edge_type,
-                properties,
-            } => {
-                // Evaluate source and target to get entity IDs
-                // For now, we'll assume they're literal entity IDs
-                // Full implementation would evaluate expressions
+        // Every version chain is settled with no transaction open
+        let mvcc = self.transaction_manager.mvcc();
+        mvcc.garbage_collect(mvcc.current_snapshot());
 
-                let graph = self.graph.read().unwrap();
+        for name in self.index_manager.list_indexes() {
+            self.index_manager.drop_index(&name)?;
+        }
+        for index in snapshot.indexes {
+            match index.kind {
+                IndexKind::BTree => {
+                    self.index_manager.create_index(index.name.clone(), index.collection, index.field, index.unique)?
+                }
+                IndexKind::FullText => {
+                    self.index_manager.create_fulltext_index(index.name.clone(), index.collection, index.field)?
+                }
+            }
+            self.index_manager.rebuild_index(&index.name, &graph)?;
+        }
 
-                // Simplified: assume first entity as source and target
-                // In production, evaluate source/target expressions to get IDs
-                let source_id = if let Some(entities) = ctx.bindings.values().next() {
-                    entities.first().map(|e| e.id)
-                } else {
-                    None
-                };
+        *schemas = SchemaValidator::new();
+        for schema in snapshot.schemas {
+            if let Some(ttl) = schema.ttl {
+                graph.set_collection_ttl(&schema.collection, ttl);
+            }
+            schemas.register_schema(schema);
+        }
 
-                let target_id = if let Some(entities) = ctx.bindings.values().nth(1) {
-                    entities.first().map(|e| e.id)
-                } else {
-                    None
-                };
+        self.cache.write().unwrap().clear();
+        Ok(())
+    }
 
-                if let (Some(src), Some(tgt)) = (source_id, target_id) {
-                    let mut props = Properties::new();
-                    for (key, value) in properties {
-                        props.insert(key.clone(), self.value_to_property_value(value));
-                    }
+    /// Store entities and edges under their own IDs, e.g. shard data
+    /// arriving from another node, as a transaction of its own
+    ///
+    /// Entities already here are replaced, and their outgoing edges become
+    /// the given ones. Every edge must come with its source entity; its
+    /// target may live on another node.
+    pub fn store_with_ids(&self, entities: Vec<Entity>, edges: Vec<Edge>) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        let sources: HashSet<EntityId> = entities.iter().map(|entity| entity.id).collect();
+        if let Some(edge) = edges.iter().find(|edge| !sources.contains(&edge.source)) {
+            return Err(format!(
+                "Edge {} came without its source entity {}",
+                edge.id.as_u64(),
+                edge.source.as_u64()
+            ));
+        }
+        let ids: Vec<EntityId> = entities.iter().map(|entity| entity.id).collect();
+        self.write_directly(&ids, |graph, txn_id| self.store_in(graph, txn_id, entities, edges))
+    }
 
-                    if let Some(edge_id) = graph.add_edge(src, tgt, edge_type.clone(), props) {
-                        ctx.rows_affected = 1;
+    /// Log and store entities and edges under their own IDs in `txn_id`
+    fn store_in(&self, graph: &Graph, txn_id: TransactionId, entities: Vec<Entity>, edges: Vec<Edge>) -> Result<(), String> {
+        let ids: Vec<EntityId> = entities.iter().map(|entity| entity.id).collect();
+        let mut by_collection: HashMap<&str, Vec<(EntityId, &Properties)>> = HashMap::new();
+        for entity in &entities {
+            by_collection.entry(&entity.entity_type).or_default().push((entity.id, &entity.properties));
+        }
+        for (collection, entries) in &by_collection {
+            self.claim_unique(graph, txn_id, collection, entries).map_err(|(_, e)| e)?;
+        }
 
-                        // Store result
-                        let mut result_row = HashMap::new();
-                        result_row.insert("edge_id".to_string(), Value::EdgeId(edge_id.as_u64()));
-                        ctx.result_rows.push(result_row);
-                    }
-                }
+        let given: HashSet<EdgeId> = edges.iter().map(|edge| edge.id).collect();
+        let stale: Vec<Edge> = ids
+            .iter()
+            .flat_map(|&id| graph.get_outgoing_neighbors(id, None))
+            .filter(|(_, edge_id)| !given.contains(edge_id))
+            .filter_map(|(_, edge_id)| graph.get_edge(edge_id))
+            .collect();
+
+        for entity in &entities {
+            self.log_to_wal(|wal| wal.log_insert(txn_id, entity))?;
+            let (id, entity_type, properties) = (entity.id.as_u64(), entity.entity_type.clone(), entity.properties.clone());
+            let created_at = entity.created_millis();
+            self.log_to_replication(move |replication| {
+                replication.log_insert_created(id, entity_type, properties, created_at)
+            });
+        }
+        for edge in &stale {
+            self.log_to_wal(|wal| wal.log_delete_edge(txn_id, edge.id))?;
+            let id = edge.id.as_u64();
+            self.log_to_replication(move |replication| replication.log_delete_edge(id));
+        }
+        for edge in &edges {
+            self.log_to_wal(|wal| wal.log_create_edge(txn_id, edge))?;
+            let edge = edge.clone();
+            self.log_to_replication(move |replication| {
+                replication.log_create_edge(
+                    edge.id.as_u64(),
+                    edge.source.as_u64(),
+                    edge.target.as_u64(),
+                    edge.edge_type,
+                    edge.properties,
+                )
+            });
+        }
 
-                drop(graph);
-                Ok(())
+        // The graph (and its storage) changes in one step, then the indexes
+        let befores: Vec<Option<Entity>> = entities.iter().map(|entity| graph.get_entity(entity.id)).collect();
+        let created: Vec<bool> = edges.iter().map(|edge| graph.get_edge(edge.id).is_none()).collect();
+        let writes = stale
+            .iter()
+            .map(|edge| (edge.id, None))
+            .chain(edges.iter().map(|edge| (edge.id, Some(edge.clone()))))
+            .collect();
+        graph.apply_writes(entities.clone(), writes, &[])?;
+
+        for (entity, before) in entities.into_iter().zip(befores) {
+            if let Some(old) = &before {
+                self.index_manager.remove_from_indexes(&old.entity_type, old.id, &old.properties);
+            }
+            self.index_manager.insert_claimed(&entity.entity_type, entity.id, &entity.properties);
+            self.record_change(|| match before {
+                Some(old) => ChangeEvent::new(ChangeKind::Update, entity.entity_type.clone(), entity.id, txn_id)
+                    .with_before(old.properties)
+                    .with_after(entity.properties),
+                None => ChangeEvent::new(ChangeKind::Insert, entity.entity_type.clone(), entity.id, txn_id)
+                    .with_after(entity.properties),
+            });
+        }
+        for edge in stale {
+            self.record_change(|| {
+                ChangeEvent::edge(ChangeKind::EdgeDelete, &edge, txn_id)
+                    .with_before(edge.properties.clone())
+            });
+        }
+        for (edge, created) in edges.into_iter().zip(created) {
+            let kind = if created { ChangeKind::EdgeCreate } else { ChangeKind::EdgeUpdate };
+            self.record_change(|| {
+                ChangeEvent::edge(kind, &edge, txn_id)
+                    .with_after(edge.properties)
+            });
+        }
+        Ok(())
+    }
+
+    /// Remove entities and their outgoing edges, e.g. a shard that moved to
+    /// another node, as a transaction of its own
+    ///
+    /// Edges into them from entities staying here are kept, pointing where
+    /// the entities went. Entities that aren't here are skipped.
+    pub fn evict_entities(&self, ids: &[EntityId]) -> Result<(), String> {
+        self.check_caller(Access::Admin)?;
+        self.write_directly(ids, |graph, txn_id| self.evict_in(graph, txn_id, ids))
+    }
+
+    /// Log and remove entities and their outgoing edges in `txn_id`
+    fn evict_in(&self, graph: &Graph, txn_id: TransactionId, ids: &[EntityId]) -> Result<(), String> {
+        let evicted: Vec<Entity> = ids.iter().filter_map(|&id| graph.get_entity(id)).collect();
+        let ids: Vec<EntityId> = evicted.iter().map(|entity| entity.id).collect();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.log_to_wal(|wal| wal.log_evict(txn_id, &ids))?;
+        let logged = ids.iter().map(|id| id.as_u64()).collect();
+        self.log_to_replication(move |replication| replication.log_evict(logged));
+
+        for entity in &evicted {
+            self.index_manager.remove_from_indexes(&entity.entity_type, entity.id, &entity.properties);
+            self.record_change(|| {
+                ChangeEvent::new(ChangeKind::Delete, entity.entity_type.clone(), entity.id, txn_id)
+                    .with_before(entity.properties.clone())
+            });
+            for (_, edge_id) in graph.get_outgoing_neighbors(entity.id, None) {
+                if let Some(edge) = graph.get_edge(edge_id) {
+                    self.record_change(|| {
+                        ChangeEvent::edge(ChangeKind::EdgeDelete, &edge, txn_id)
+                            .with_before(edge.properties)
+                    });
+                }
             }
+        }
+        graph.evict_entities(&ids)
+    }
 
-            _ => Err("Not a mutation operation".to_string()),
+    /// Wait until the transactions open now have ended
+    pub(crate) fn wait_for_open_transactions(&self, timeout: Duration) -> Result<(), String> {
+        let open: HashSet<TransactionId> = self.transaction_manager.get_active_txn_ids().into_iter().collect();
+        let deadline = Instant::now() + timeout;
+        while self.transaction_manager.get_active_txn_ids().iter().any(|id| open.contains(id)) {
+            if Instant::now() >= deadline {
+                return Err(format!("transactions still open after {:?}", timeout));
+            }
+            std::thread::sleep(Duration::from_millis(1));
         }
+        Ok(())
     }
 
-    /// Execute a single operation
-    fn execute_operation(
+    /// Write straight to the graph as a transaction of its own
+    ///
+    /// Refused inside an explicit transaction, and while an open one has
+    /// written to one of the entities. `write` holds the graph exclusively
+    /// and logs each change before making it; if it fails, the transaction
+    /// is rolled back.
+    fn write_directly(
         &self,
-        operation: &Operation,
-        ctx: &mut ExecutionContext,
-        graph: &Graph,
+        ids: &[EntityId],
+        write: impl FnOnce(&Graph, TransactionId) -> Result<(), String>,
     ) -> Result<(), String> {
-        match operation {
-            Operation::Scan {
-                collection,
-                alias,
-                filter,
-            } => {
-                let entities = graph.scan_collection(collection);
-                let filtered = if let Some(filter_expr) = filter {
-                    entities
-                        .into_iter()
-                        .filter(|e| self.evaluate_filter(filter_expr, e, ctx))
-                        .collect()
-                } else {
-                    entities
-                };
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err("Direct writes can't run inside a transaction".to_string());
+        }
+        self.begin_implicit()?;
+        let txn_id = self.current_transaction.lock().unwrap().ok_or("No active transaction")?;
+
+        let graph = self.graph.write().unwrap();
+        let mvcc = self.transaction_manager.mvcc();
+        let written = match ids.iter().find_map(|&id| mvcc.uncommitted_writer(id, txn_id).map(|writer| (id, writer))) {
+            Some((id, writer)) => Err(format!(
+                "Entity {} has uncommitted writes from transaction {}; retry once it ends",
+                id.as_u64(),
+                writer
+            )),
+            None => write(&graph, txn_id),
+        };
+        drop(graph);
+        if let Err(e) = written {
+            self.handle_rollback()?;
+            return Err(e);
+        }
 
-                ctx.bindings.insert(alias.clone(), filtered);
-                Ok(())
-            }
+        self.cache.write().unwrap().clear();
+        self.handle_commit()?;
+        Ok(())
+    }
 
-            Operation::IndexLookup {
-                collection,
-                alias,
-                index_name: _,
-                key_values,
-            } => {
-                // For now, fall back to scan (index not implemented)
-                let entities = graph.scan_collection(collection);
+    /// Checkpoint unless a transaction is open; returns whether it ran
+    fn try_checkpoint(&self) -> Result<bool, String> {
+        let wal = match &self.wal_manager {
+            Some(wal) => wal,
+            None => return Ok(false),
+        };
 
-                // Filter by key values if provided
-                let filtered = if !key_values.is_empty() {
-                    entities
-                        .into_iter()
-                        .filter(|e| {
-                            // Simple filter on first property
-                            key_values.iter().any(|v| {
-                                e.properties.values().any(|pv| self.value_matches(v, pv))
-                            })
-                        })
-                        .collect()
-                } else {
-                    entities
-                };
+        // Statements can't change the graph while it's held exclusively,
+        // and with no transaction open it only holds committed data
+        let graph = self.graph.write().unwrap();
+        if !self.transaction_manager.get_active_txn_ids().is_empty() {
+            return Ok(false);
+        }
 
-                ctx.bindings.insert(alias.clone(), filtered);
-                Ok(())
-            }
+        wal.checkpoint(&graph)
+            .map_err(|e| format!("Failed to checkpoint WAL: {}", e))?;
+        Ok(true)
+    }
 
-            Operation::Traverse {
-                source_binding,
-                direction,
-                edge_type,
-                target_alias,
-                min_hops,
-                max_hops,
-                filter,
-            } => {
-                // Get source entities
-                let source_entities = ctx
-                    .bindings
-                    .get(source_binding)
-                    .ok_or_else(|| format!("Binding not found: {}", source_binding))?
-                    .clone();
+    /// Validate writes against a shared set of collection schemas
+    pub fn with_schemas(mut self, schemas: Arc<RwLock<SchemaValidator>>) -> Self {
+        self.schemas = schemas;
+        self
+    }
 
-                let mut target_entities = Vec::new();
+    /// Make a replica's graph available for bounded-staleness reads, and
+    /// for read splitting once that is on (see [`ReadReplicas::set_routing`])
+    pub fn attach_replica(&self, slave_id: String, graph: Arc<RwLock<Graph>>) {
+        self.replicas.attach(slave_id, graph, None);
+    }
 
-                // Traverse from each source
-                for source in source_entities {
-                    let neighbors = match direction {
-                        TraverseDirection::Outgoing => graph.get_outgoing_neighbors(
-                            source.id,
-                            edge_type.as_ref().map(|s| s.as_str()),
-                        ),
-                        TraverseDirection::Incoming => graph.get_incoming_neighbors(
-                            source.id,
-                            edge_type.as_ref().map(|s| s.as_str()),
-                        ),
-                        TraverseDirection::Both => {
-                            let mut all = graph.get_outgoing_neighbors(
-                                source.id,
-                                edge_type.as_ref().map(|s| s.as_str()),
-                            );
-                            all.extend(graph.get_incoming_neighbors(
-                                source.id,
-                                edge_type.as_ref().map(|s| s.as_str()),
-                            ));
-                            all
-                        }
-                    };
+    /// Route reads over a replica set shared with other sessions (e.g. a pool's)
+    pub fn with_read_replicas(mut self, replicas: Arc<ReadReplicas>) -> Self {
+        self.replicas = replicas;
+        self
+    }
 
-                    // Get target entities
-                    for (target_id, _edge_id) in neighbors {
-                        if let Some(target) = graph.get_entity(target_id) {
-                            // Apply filter if present
-                            if let Some(filter_expr) = filter {
-                                if self.evaluate_filter(filter_expr, &target, ctx) {
-                                    target_entities.push(target);
-                                }
-                            } else {
-                                target_entities.push(target);
-                            }
-                        }
-                    }
-                }
+    /// The replicas this session's reads may be routed to
+    pub fn read_replicas(&self) -> &Arc<ReadReplicas> {
+        &self.replicas
+    }
 
-                // Handle multi-hop traversal (simplified)
-                if *max_hops > 1 {
-                    // For now, just use 1-hop results
-                    // Full BFS/DFS traversal would go here
-                }
+    /// The graph this session reads and writes
+    pub(crate) fn graph(&self) -> &Arc<RwLock<Graph>> {
+        &self.graph
+    }
 
-                ctx.bindings.insert(target_alias.clone(), target_entities);
-                Ok(())
-            }
+    /// Read NOW() from `clock` instead of the system time
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
 
-            Operation::Filter { binding, condition } => {
-                let entities = ctx
-                    .bindings
-                    .get(binding)
-                    .ok_or_else(|| format!("Binding not found: {}", binding))?
-                    .clone();
+    /// Check statements against a firewall, running as `principal`
+    pub fn with_firewall(mut self, firewall: Arc<Firewall>, principal: FirewallPrincipal) -> Self {
+        self.firewall = Some((firewall, principal));
+        self
+    }
 
-                let filtered: Vec<Entity> = entities
-                    .into_iter()
-                    .filter(|e| self.evaluate_filter(condition, e, ctx))
-                    .collect();
+    /// Refuse writes while this node's side of a partition lacks a quorum,
+    /// and reads while it can't meet `read_consistency`
+    ///
+    /// Refused statements fail with the `CLUSTER_DEGRADED` error. The
+    /// manager is asked on every statement, so writes resume as soon as it
+    /// sees the partition heal.
+    pub fn with_partition_manager(mut self, manager: Arc<PartitionManager>, read_consistency: ConsistencyLevel) -> Self {
+        self.partition = Some((manager, read_consistency));
+        self
+    }
 
-                ctx.bindings.insert(binding.clone(), filtered);
-                Ok(())
-            }
+    /// Refuse writes to shards another node owns, as a node of a sharded cluster
+    ///
+    /// See [`ShardOwnership::check_write`]. Reads are served whatever shards
+    /// they cover.
+    pub fn with_shard_ownership(mut self, ownership: ShardOwnership) -> Self {
+        self.shard_ownership = Some(ownership);
+        self
+    }
 
-            Operation::Project { fields } => {
-                // Project fields from all bindings
-                let mut rows = Vec::new();
+    /// Read only the entities of some shards, as a node answering a
+    /// sub-query for them while it holds others too
+    ///
+    /// Collection scans and index lookups skip the rest; entities reached
+    /// through edges are read wherever they are.
+    pub fn with_shard_scope(mut self, scope: ShardScope) -> Self {
+        self.shard_scope = Some(scope);
+        self
+    }
 
-                // Get all entities from all bindings
-                let all_entities: Vec<&Entity> = ctx
-                    .bindings
-                    .values()
-                    .flat_map(|entities| entities.iter())
-                    .collect();
+    /// Whether reads see the entity, given the shard scope
+    fn in_shard_scope(&self, entity: &Entity) -> bool {
+        self.shard_scope.as_ref().is_none_or(|scope| scope.contains(entity))
+    }
 
-                for entity in all_entities {
-                    let mut row = HashMap::new();
+    /// Reject statements `session`'s role doesn't allow, before planning them
+    ///
+    /// Bulk inserts, imports, exports and backups are checked the same way.
+    pub fn with_caller(mut self, session: Session) -> Self {
+        self.caller = Some(session);
+        self
+    }
 
-                    for field in fields {
-                        let prop_value = self.evaluate_expression(&field.expression, entity, ctx);
-                        let value = self.property_value_to_value(&prop_value);
-                        row.insert(field.alias.clone(), value);
-                    }
+    /// Record the caller's mutations and DDL, and serve the log as `_audit`
+    ///
+    /// Statements run without a caller aren't recorded.
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
 
-                    rows.push(row);
-                }
+    /// Index manager used by CREATE INDEX / REINDEX
+    pub fn index_manager(&self) -> Arc<IndexManager> {
+        self.index_manager.clone()
+    }
 
-                ctx.result_rows = rows;
-                Ok(())
-            }
+    /// Share indexes (and the unique values they enforce) with other executors
+    pub fn with_index_manager(mut self, index_manager: Arc<IndexManager>) -> Self {
+        self.index_manager = index_manager;
+        self
+    }
 
-            Operation::Sort { fields } => {
-                // Sort result rows
-                ctx.result_rows.sort_by(|a, b| {
-                    for field in fields {
-                        // Simple comparison based on first sort field
-                        // Full implementation would handle all fields
-                        let a_val = a.values().next();
-                        let b_val = b.values().next();
+    /// Latency, row and slow-query metrics of the statements this executor ran
+    pub fn query_metrics(&self) -> Arc<QueryMetrics> {
+        self.metrics.clone()
+    }
 
-                        if let (Some(av), Some(bv)) = (a_val, b_val) {
-                            let cmp = self.compare_values(av, bv);
-                            if cmp != std::cmp::Ordering::Equal {
-                                return if field.ascending { cmp } else { cmp.reverse() };
-                            }
-                        }
-                    }
-                    std::cmp::Ordering::Equal
-                });
+    /// Check the executor can still run queries
+    ///
+    /// Fails when a query panicked while holding one of its locks; every later
+    /// query would panic on that lock too.
+    pub fn ping(&self) -> Result<(), String> {
+        let poisoned = self.graph.is_poisoned()
+            || self.optimizer.is_poisoned()
+            || self.cache.is_poisoned()
+            || self.current_transaction.is_poisoned()
+            || self.session.is_poisoned()
+            || self.pending_replication.is_poisoned()
+            || self.replicas.is_poisoned()
+            || self.schemas.is_poisoned();
+        if poisoned {
+            return Err("Executor lock poisoned by a panicked query".to_string());
+        }
+        Ok(())
+    }
 
-                Ok(())
-            }
+    /// Whether the session has a transaction open
+    pub fn in_transaction(&self) -> bool {
+        self.current_transaction.lock().unwrap().is_some()
+    }
 
-            Operation::Limit { count } => {
-                ctx.result_rows.truncate(*count);
-                Ok(())
-            }
+    /// Whether committed statements survive a restart, through a WAL or
+    /// a persistent graph
+    pub fn is_durable(&self) -> bool {
+        self.wal_manager.is_some() || self.graph.read().unwrap().is_persistent()
+    }
 
-            Operation::Skip { count } => {
-                ctx.result_rows = ctx.result_rows.split_off(*count.min(&ctx.result_rows.len()));
-                Ok(())
-            }
+    /// Record statement metrics into a collector shared with other executors
+    pub fn with_query_metrics(mut self, metrics: Arc<QueryMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 
-            Operation::GroupBy {
-                group_fields,
-                aggregates,
-            } => {
-                // Group entities by group_fields values
-                use std::collections::BTreeMap;
-                let mut groups: BTreeMap<Vec<String>, Vec<Entity>> = BTreeMap::new();
+    /// Set when scans and filters go parallel and how many threads they use
+    pub fn with_parallelism(mut self, config: ParallelConfig) -> Result<Self, String> {
+        self.scan_pool = match config.max_threads {
+            Some(threads) => Some(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|e| format!("Failed to start scan threads: {}", e))?,
+            )),
+            None => None,
+        };
+        self.parallel = config;
+        Ok(self)
+    }
 
-                // Get all entities from bindings
-                let all_entities: Vec<Entity> = ctx
-                    .bindings
-                    .values()
-                    .flat_map(|entities| entities.clone())
-                    .collect();
+    /// Set how much memory GROUP BY and sorts may hold before spilling, and where they spill
+    ///
+    /// A session's `SET memory_budget` and [`execute_with_memory_budget`](Self::execute_with_memory_budget)
+    /// override the limit.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory_budget = budget;
+        self
+    }
 
-                // Group entities by group_fields
-                for entity in all_entities {
-                    let mut group_key = Vec::new();
-                    for field_expr in group_fields {
-                        let prop_value = self.evaluate_expression(field_expr, &entity, ctx);
-                        let value = self.property_value_to_value(&prop_value);
-                        group_key.push(self.value_to_string(&value));
-                    }
-                    groups.entry(group_key).or_insert_with(Vec::new).push(entity);
-                }
+    /// Memory budget of a statement: its own limit, else the session's, else the executor's
+    fn memory_budget_for(&self, control: &QueryControl) -> MemoryBudget {
+        let limit = control.memory_limit.or(self.session.lock().unwrap().memory_limit);
+        MemoryBudget {
+            limit: limit.unwrap_or(self.memory_budget.limit),
+            ..self.memory_budget.clone()
+        }
+    }
 
-                // Compute aggregates for each group
-                let mut result_rows = Vec::new();
-                for (group_key, group_entities) in groups {
-                    let mut row = HashMap::new();
+    /// Use an archive tier (e.g. on-disk) for ARCHIVE / UNARCHIVE and archive reads
+    pub fn with_archive(mut self, archive: Arc<ArchiveManager>) -> Self {
+        self.archive = archive;
+        self
+    }
 
-                    // Add group fields to result
-                    for (idx, field_expr) in group_fields.iter().enumerate() {
-                        if let Some(key_value) = group_key.get(idx) {
-                            // Use first entity in group to get field name
-                            if let Some(first_entity) = group_entities.first() {
-                                let field_name = self.extract_field_name(field_expr);
-                                let prop_value = self.evaluate_expression(field_expr, first_entity, ctx);
-                                let value = self.property_value_to_value(&prop_value);
-                                row.insert(field_name, value);
-                            }
-                        }
-                    }
+    /// Execute a DQL query string
+    pub fn execute(&self, query_str: &str) -> Result<QueryResult, String> {
+        self.execute_query(query_str, None, &HashMap::new(), &QueryControl::default())
+            .map_err(String::from)
+    }
 
-                    // Compute aggregates
-                    for agg_op in aggregates {
-                        let agg_value = self.compute_aggregate(
-                            &agg_op.function,
-                            &agg_op.argument,
-                            &group_entities,
-                            ctx,
-                        );
-                        row.insert(agg_op.alias.clone(), agg_value);
-                    }
+    /// Execute a DQL query string, failing with a [`DeedError`]
+    ///
+    /// A parse error carries the line, column and snippet where parsing
+    /// stopped; other errors are classified by kind.
+    pub fn try_execute(&self, query_str: &str) -> Result<QueryResult, DeedError> {
+        self.execute_query(query_str, None, &HashMap::new(), &QueryControl::default())
+    }
 
-                    result_rows.push(row);
-                }
+    /// Execute a DQL query containing parameters, failing with a [`DeedError`]
+    pub fn try_execute_with_params(
+        &self,
+        query_str: &str,
+        params: &HashMap<String, Value>,
+    ) -> Result<QueryResult, DeedError> {
+        self.execute_query(query_str, None, params, &QueryControl::default())
+    }
 
-                ctx.result_rows = result_rows;
-                Ok(())
-            }
+    /// Execute a statement parsed, and perhaps rewritten, elsewhere
+    ///
+    /// `query_str` is the text it came from, as the firewall and the slow
+    /// query log see it.
+    pub fn execute_parsed(&self, query: &crate::dql_ast::Query, query_str: &str) -> Result<QueryResult, String> {
+        let started = Instant::now();
+        self.audited(
+            Access::required_for(query),
+            query_str,
+            statement_collections(query),
+            || self.execute_statement(query, query_str, None, &HashMap::new(), &QueryControl::default(), started),
+            |result| result.rows_affected,
+        )
+        .map_err(String::from)
+    }
 
-            Operation::Having { condition } => {
-                // Filter aggregated result rows based on HAVING condition
-                ctx.result_rows.retain(|row| {
-                    self.evaluate_having_condition(condition, row)
-                });
-                Ok(())
-            }
+    /// Execute a DQL query string on behalf of a logged in session
+    ///
+    /// Fails with a permission error when the session's role doesn't allow
+    /// the statement. Runs in this executor's session, so it can continue an
+    /// open transaction.
+    pub fn execute_as(&self, session: &Session, query_str: &str) -> Result<QueryResult, String> {
+        self.clone().with_caller(session.clone()).execute(query_str)
+    }
 
-            // Mutations are handled separately in execute_mutation()
-            Operation::InsertEntity { .. }
-            | Operation::UpdateEntities { .. }
-            | Operation::DeleteEntities { .. }
-            | Operation::CreateEdge { .. } => {
-                Err("Mutation operations should be handled by execute_mutation()".to_string())
-            }
+    /// Execute a DQL query string, failing it once it has run for `timeout`
+    ///
+    /// A timed out statement is rolled back if it began its own transaction.
+    pub fn execute_with_timeout(&self, query_str: &str, timeout: Duration) -> Result<QueryResult, String> {
+        self.execute_timed(query_str, &HashMap::new(), Some(timeout)).map_err(String::from)
+    }
 
-            // Join operation - not yet implemented
-            Operation::Join { .. } => {
-                Err("Join operations not yet implemented".to_string())
-            }
-        }
+    /// Execute a DQL query containing parameters, failing it once it has run
+    /// for `timeout` if one is given
+    pub(crate) fn execute_timed(
+        &self,
+        query_str: &str,
+        params: &HashMap<String, Value>,
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, DeedError> {
+        self.execute_query(query_str, None, params, &QueryControl::timed(timeout))
     }
 
-    /// Evaluate filter expression
-    fn evaluate_filter(&self, expr: &FilterExpr, entity: &Entity, ctx: &ExecutionContext) -> bool {
-        match expr {
-            FilterExpr::And(l, r) => {
-                self.evaluate_filter(l, entity, ctx) && self.evaluate_filter(r, entity, ctx)
-            }
-            FilterExpr::Or(l, r) => {
-                self.evaluate_filter(l, entity, ctx) || self.evaluate_filter(r, entity, ctx)
-            }
-            FilterExpr::Not(e) => !self.evaluate_filter(e, entity, ctx),
-
-            FilterExpr::Equal(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                self.property_values_equal(&lv, &rv)
-            }
-            FilterExpr::NotEqual(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                !self.property_values_equal(&lv, &rv)
-            }
-            FilterExpr::LessThan(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                matches!(self.compare_property_values(&lv, &rv), Some(std::cmp::Ordering::Less))
-            }
-            FilterExpr::LessThanEq(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                matches!(
-                    self.compare_property_values(&lv, &rv),
-                    Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)
-                )
-            }
-            FilterExpr::GreaterThan(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                matches!(self.compare_property_values(&lv, &rv), Some(std::cmp::Ordering::Greater))
-            }
-            FilterExpr::GreaterThanEq(l, r) => {
-                let lv = self.evaluate_expression(l, entity, ctx);
-                let rv = self.evaluate_expression(r, entity, ctx);
-                matches!(
-                    self.compare_property_values(&lv, &rv),
-                    Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)
-                )
-            }
+    /// Execute a DQL query string, letting GROUP BY and sorts hold `limit` bytes before spilling
+    pub fn execute_with_memory_budget(&self, query_str: &str, limit: usize) -> Result<QueryResult, String> {
+        let control = QueryControl {
+            memory_limit: Some(limit),
+            ..Default::default()
+        };
+        self.execute_query(query_str, None, &HashMap::new(), &control).map_err(String::from)
+    }
 
-            _ => true, // Default to true for other expressions
-        }
+    /// Execute a DQL query string on another thread, returning a handle that can cancel it
+    ///
+    /// The statement runs in this executor's session.
+    pub fn execute_async(&self, query_str: &str) -> QueryHandle {
+        let control = QueryControl::default();
+        let cancelled = control.cancelled.clone();
+        let executor = self.clone();
+        let query_str = query_str.to_string();
+
+        let worker = std::thread::spawn(move || {
+            executor.execute_query(&query_str, None, &HashMap::new(), &control).map_err(String::from)
+        });
+        QueryHandle { cancelled, worker }
     }
 
-    /// Evaluate expression to property value
-    fn evaluate_expression(
-        &self,
-        expr: &FilterExpr,
-        entity: &Entity,
-        _ctx: &ExecutionContext,
-    ) -> PropertyValue {
-        match expr {
-            FilterExpr::Property { binding: _, property } => {
-                entity.get_property(property).cloned().unwrap_or(PropertyValue::Null)
-            }
-            FilterExpr::Constant(value) => self.value_to_property_value(value),
+    /// Execute a DQL query containing parameters (`$name`, or `?1` for key "1")
+    ///
+    /// The plan is cached with the parameters in place, so executions that
+    /// differ only in parameter values share one plan.
+    pub fn execute_with_params(&self, query_str: &str, params: &HashMap<String, Value>) -> Result<QueryResult, String> {
+        self.execute_query(query_str, None, params, &QueryControl::default()).map_err(String::from)
+    }
 
-            FilterExpr::Add(l, r) => {
-                let lv = self.evaluate_expression(l, entity, _ctx);
-                let rv = self.evaluate_expression(r, entity, _ctx);
-                self.add_values(&lv, &rv)
-            }
+    /// Execute a DQL query on the master's graph, never a replica
+    pub fn execute_on_primary(&self, query_str: &str) -> Result<QueryResult, String> {
+        let control = QueryControl {
+            primary: true,
+            ..QueryControl::default()
+        };
+        self.execute_query(query_str, None, &HashMap::new(), &control).map_err(String::from)
+    }
 
-            _ => PropertyValue::Null, // Default for other expressions
+    /// Where a read would run now, given the session's transaction, its
+    /// `max_staleness` and the read routing
+    ///
+    /// Statements that don't parse go to the master, which reports the error.
+    pub fn read_route(&self, query_str: &str) -> Route {
+        let statement = match Parser::parse(query_str) {
+            Ok(crate::dql_ast::Query::Explain(explain)) if explain.analyze => *explain.query,
+            Ok(statement) => statement,
+            Err(_) => return Route::Primary,
+        };
+        if !is_replica_read(&statement) || self.in_transaction() {
+            return Route::Primary;
         }
+        let max_staleness = self.session.lock().unwrap().max_staleness;
+        self.route_read(query_str, max_staleness).map_or(Route::Primary, |(route, _, _)| route)
     }
 
-    /// Compare property values
-    fn compare_property_values(
+    /// Execute a DQL query containing parameters, failing it once it has run for `timeout`
+    pub fn execute_with_params_and_timeout(
         &self,
-        a: &PropertyValue,
-        b: &PropertyValue,
-    ) -> Option<std::cmp::Ordering> {
-        match (a, b) {
-            (PropertyValue::Int(a), PropertyValue::Int(b)) => Some(a.cmp(b)),
-            (PropertyValue::Float(a), PropertyValue::Float(b)) => a.partial_cmp(b),
-            (PropertyValue::Int(a), PropertyValue::Float(b)) => (*a as f64).partial_cmp(b),
-            (PropertyValue::Float(a), PropertyValue::Int(b)) => a.partial_cmp(&(*b as f64)),
-            (PropertyValue::String(a), PropertyValue::String(b)) => Some(a.cmp(b)),
-            _ => None,
-        }
+        query_str: &str,
+        params: &HashMap<String, Value>,
+        timeout: Duration,
+    ) -> Result<QueryResult, String> {
+        self.execute_timed(query_str, params, Some(timeout)).map_err(String::from)
     }
 
-    fn property_values_equal(&self, a: &PropertyValue, b: &PropertyValue) -> bool {
-        match (a, b) {
-            (PropertyValue::Null, PropertyValue::Null) => true,
-            (PropertyValue::Bool(a), PropertyValue::Bool(b)) => a == b,
-            (PropertyValue::Int(a), PropertyValue::Int(b)) => a == b,
-            (PropertyValue::Float(a), PropertyValue::Float(b)) => (a - b).abs() < f64::EPSILON,
-            (PropertyValue::String(a), PropertyValue::String(b)) => a == b,
-            _ => false,
-        }
+    /// Parse and plan a query once, for repeated execution with different parameters
+    pub fn prepare(&self, query_str: &str) -> Result<PreparedQuery, String> {
+        let query = Parser::parse(query_str)?;
+        self.check_caller(Access::required_for(&query))?;
+        let (plan, literals, _) = self.plan_query(&query)?;
+
+        Ok(PreparedQuery {
+            text: query_str.to_string(),
+            query,
+            plan,
+            literals,
+        })
     }
 
-    fn add_values(&self, a: &PropertyValue, b: &PropertyValue) -> PropertyValue {
-        match (a, b) {
-            (PropertyValue::Int(a), PropertyValue::Int(b)) => PropertyValue::Int(a + b),
-            (PropertyValue::Float(a), PropertyValue::Float(b)) => PropertyValue::Float(a + b),
-            (PropertyValue::Int(a), PropertyValue::Float(b)) => PropertyValue::Float(*a as f64 + b),
-            (PropertyValue::Float(a), PropertyValue::Int(b)) => PropertyValue::Float(a + *b as f64),
-            _ => PropertyValue::Null,
-        }
+    /// Execute a prepared query with one set of parameter values
+    pub fn execute_prepared(&self, prepared: &PreparedQuery, params: &HashMap<String, Value>) -> Result<QueryResult, String> {
+        self.audited(
+            Access::required_for(&prepared.query),
+            &prepared.text,
+            statement_collections(&prepared.query),
+            || {
+                let control = QueryControl::default();
+                let mut profile = PlanProfile::new(None, Instant::now());
+                self.run_query(
+                    &prepared.query,
+                    &prepared.plan,
+                    &prepared.literals,
+                    &prepared.text,
+                    None,
+                    params,
+                    &control,
+                    &mut profile,
+                )
+            },
+            |result| result.rows_affected,
+        )
+        .map_err(String::from)
     }
 
-    fn value_to_property_value(&self, value: &Value) -> PropertyValue {
-        match value {
-            Value::Null => PropertyValue::Null,
-            Value::Bool(b) => PropertyValue::Bool(*b),
-            Value::Integer(n) => PropertyValue::Int(*n),
-            Value::Float(f) => PropertyValue::Float(*f),
-            Value::String(s) => PropertyValue::String(s.clone()),
-            _ => PropertyValue::Null,
-        }
+    /// Insert many entities into a collection as one batch
+    ///
+    /// The rows are validated, stored and indexed together and logged to the
+    /// WAL as a single record; if any row fails, none are inserted. Runs in
+    /// the session's open transaction if there is one, otherwise in its own.
+    /// Returns the new entity IDs in row order.
+    pub fn bulk_insert(&self, collection: &str, rows: Vec<Properties>) -> Result<Vec<EntityId>, String> {
+        let statement = format!("BULK INSERT INTO {} ({} rows)", collection, rows.len());
+        self.audited(
+            Access::Write,
+            &statement,
+            vec![collection.to_string()],
+            || self.insert_rows(collection, rows, true),
+            |ids| ids.len(),
+        )
+        .map_err(String::from)
     }
 
-    fn value_matches(&self, ir_value: &Value, prop_value: &PropertyValue) -> bool {
-        match (ir_value, prop_value) {
-            (Value::Integer(a), PropertyValue::Int(b)) => a == b,
-            (Value::Float(a), PropertyValue::Float(b)) => (a - b).abs() < f64::EPSILON,
-            (Value::String(a), PropertyValue::String(b)) => a == b,
-            (Value::Bool(a), PropertyValue::Bool(b)) => a == b,
-            (Value::Null, PropertyValue::Null) => true,
-            _ => false,
+    /// Insert a batch of rows in the session's transaction or a new one
+    fn insert_rows(&self, collection: &str, rows: Vec<Properties>, validate: bool) -> Result<Vec<EntityId>, DeedError> {
+        let shape = QueryPlan::new(vec![Operation::InsertEntity {
+            collection: collection.to_string(),
+            rows: Vec::new(),
+        }]);
+        let statement = format!("BULK INSERT INTO {} ({} rows)", collection, rows.len());
+        self.check_firewall_shape(StatementClass::Insert, &shape, &statement)?;
+        if let Some(ownership) = &self.shard_ownership {
+            ownership.check_rows(collection, &rows)?;
         }
-    }
 
-    fn compare_values(&self, a: &Value, b: &Value) -> std::cmp::Ordering {
-        match (a, b) {
-            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
-            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal),
-            (Value::String(a), Value::String(b)) => a.cmp(b),
-            _ => std::cmp::Ordering::Equal,
+        let had_active_txn = self.current_transaction.lock().unwrap().is_some();
+        if !had_active_txn {
+            self.begin_implicit()?;
         }
-    }
 
-    /// Convert PropertyValue to Value
-    fn property_value_to_value(&self, prop_value: &PropertyValue) -> Value {
-        match prop_value {
-            PropertyValue::Null => Value::Null,
-            PropertyValue::Bool(b) => Value::Bool(*b),
-            PropertyValue::Int(i) => Value::Integer(*i),
-            PropertyValue::Float(f) => Value::Float(*f),
-            PropertyValue::String(s) => Value::String(s.clone()),
-            PropertyValue::Bytes(b) => Value::String(format!("{:?}", b)), // Convert bytes to debug string
+        let result = self.insert_batch(collection, rows, validate);
+
+        if !had_active_txn {
+            if result.is_ok() {
+                self.handle_commit()?;
+            } else {
+                self.handle_rollback()?;
+            }
+        }
+
+        result
+    }
+
+    /// Export a collection as JSON Lines or CSV, returning how many entities were written
+    ///
+    /// Entities are streamed to `writer` as the session sees them. CSV
+    /// columns are `_id` followed by every property key in the collection.
+    pub fn export_collection<W: Write>(&self, collection: &str, format: DataFormat, writer: W) -> Result<usize, String> {
+        self.check_caller(Access::Read)?;
+        self.export_records(collection, format, None, writer)
+    }
+
+    /// Export a collection as CSV with the given columns (`_id` for the entity ID)
+    pub fn export_collection_csv<W: Write>(&self, collection: &str, columns: &[String], writer: W) -> Result<usize, String> {
+        self.check_caller(Access::Read)?;
+        self.export_records(collection, DataFormat::Csv, Some(columns), writer)
+    }
+
+    /// Export the entities a SELECT returns, and the edges between them, as GraphML or DOT
+    ///
+    /// Every entity bound by the query (traversal targets as well as the
+    /// scanned collection) is a node; edge aliases are not. Everything after
+    /// matching (grouping, projection, ORDER BY, LIMIT) works on result rows
+    /// rather than entities, so it is ignored. Returns the number of nodes.
+    pub fn export_query_graph<W: Write>(&self, query_str: &str, format: GraphFormat, writer: W) -> Result<usize, String> {
+        let query = Parser::parse(query_str)?;
+        if !matches!(query, crate::dql_ast::Query::Select(_)) {
+            return Err("Only SELECT queries can be exported as a graph".to_string());
         }
+        self.check_caller(Access::required_for(&query))?;
+
+        let (plan, literals, _) = self.plan_query(&query)?;
+        let mut plan = bind_plan(&plan, &literals, &HashMap::new(), (self.clock)())?;
+        self.check_firewall(&query, &plan, query_str)?;
+
+        let matched = plan
+            .operations
+            .iter()
+            .position(|op| matches!(op, Operation::GroupBy { .. } | Operation::Having { .. } | Operation::Project { .. }))
+            .unwrap_or(plan.operations.len());
+        plan.operations.truncate(matched);
+
+        let edge_aliases: HashSet<String> = plan
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Traverse { edge_alias, .. } => edge_alias.clone(),
+                _ => None,
+            })
+            .collect();
+
+        let ctx = self.run_operations(&plan, &self.graph, self.read_view()?, None, &QueryControl::default())?;
+        let ids: HashSet<EntityId> = match &ctx.joined_rows {
+            Some(rows) => rows
+                .iter()
+                .flat_map(|row| row.iter())
+                .filter(|(alias, _)| !edge_aliases.contains(*alias))
+                .map(|(_, entity)| entity.id)
+                .collect(),
+            None => ctx
+                .bindings
+                .iter()
+                .filter(|(alias, _)| !edge_aliases.contains(*alias))
+                .flat_map(|(_, entities)| entities.iter().map(|e| e.id))
+                .collect(),
+        };
+
+        let graph = self.graph.read().unwrap();
+        let subgraph = Subgraph::of_entities(&graph, ids, &ExportFilter::default());
+        subgraph.write(format, BufWriter::new(writer))?;
+        Ok(subgraph.entities.len())
     }
 
-    /// Compute aggregate function
-    fn compute_aggregate(
+    fn export_records<W: Write>(
         &self,
-        function: &AggregateFunc,
-        argument: &FilterExpr,
-        entities: &[Entity],
-        ctx: &ExecutionContext,
-    ) -> Value {
-        match function {
-            AggregateFunc::Count => {
-                // COUNT(*) or COUNT(field)
-                Value::Integer(entities.len() as i64)
-            }
-            AggregateFunc::Sum => {
-                let mut sum = 0.0;
-                for entity in entities {
-                    if let PropertyValue::Int(n) = self.evaluate_expression(argument, entity, ctx) {
-                        sum += n as f64;
-                    } else if let PropertyValue::Float(f) = self.evaluate_expression(argument, entity, ctx) {
-                        sum += f;
+        collection: &str,
+        format: DataFormat,
+        columns: Option<&[String]>,
+        writer: W,
+    ) -> Result<usize, String> {
+        let graph = self.graph.read().unwrap();
+        let view = self.read_view()?;
+        let writer = BufWriter::new(writer);
+
+        let mut records = match format {
+            DataFormat::JsonLines => RecordWriter::json_lines(writer),
+            DataFormat::Csv => {
+                let columns = match columns {
+                    Some(columns) => columns.to_vec(),
+                    None => {
+                        let mut keys = BTreeSet::new();
+                        self.for_each_visible(&graph, collection, view.as_ref(), |entity| {
+                            keys.extend(entity.properties.keys().filter(|k| k.as_str() != ID_FIELD).cloned());
+                            Ok(())
+                        })?;
+                        std::iter::once(ID_FIELD.to_string()).chain(keys).collect()
                     }
-                }
-                Value::Float(sum)
+                };
+                RecordWriter::csv(writer, columns)?
             }
-            AggregateFunc::Avg => {
-                if entities.is_empty() {
-                    return Value::Null;
-                }
-                let mut sum = 0.0;
-                let mut count = 0;
-                for entity in entities {
-                    if let PropertyValue::Int(n) = self.evaluate_expression(argument, entity, ctx) {
-                        sum += n as f64;
-                        count += 1;
-                    } else if let PropertyValue::Float(f) = self.evaluate_expression(argument, entity, ctx) {
-                        sum += f;
+        };
+
+        let count = self.for_each_visible(&graph, collection, view.as_ref(), |entity| {
+            records.write(entity.id.0, &entity.properties)
+        })?;
+        records.finish()?;
+        Ok(count)
+    }
+
+    /// Visit the entities of a collection a reader sees, returning how many
+    ///
+    /// They are streamed from the graph unless older versions must be resolved.
+    fn for_each_visible<F>(&self, graph: &Graph, collection: &str, view: Option<&ReadView>, mut visit: F) -> Result<usize, String>
+    where
+        F: FnMut(&Entity) -> Result<(), String>,
+    {
+        let mvcc = self.transaction_manager.mvcc();
+        let now = (self.clock)();
+        let mut count = 0;
+        match view {
+            Some(view) if mvcc.has_versions() => {
+                for entity in mvcc.resolve_collection(collection, graph.scan_collection(collection), view) {
+                    if !graph.is_expired(&entity, now) {
+                        visit(&entity)?;
                         count += 1;
                     }
                 }
-                if count > 0 {
-                    Value::Float(sum / count as f64)
-                } else {
-                    Value::Null
-                }
             }
-            AggregateFunc::Min => {
-                let mut min: Option<PropertyValue> = None;
-                for entity in entities {
-                    let val = self.evaluate_expression(argument, entity, ctx);
-                    if let Some(current_min) = &min {
-                        if let Some(std::cmp::Ordering::Less) = self.compare_property_values(&val, current_min) {
-                            min = Some(val);
-                        }
-                    } else {
-                        min = Some(val);
+            _ => {
+                for entity in graph.iter_collection(collection) {
+                    if !graph.is_expired(&entity, now) {
+                        visit(entity.value())?;
+                        count += 1;
                     }
                 }
-                match min {
-                    Some(PropertyValue::Int(n)) => Value::Integer(n),
-                    Some(PropertyValue::Float(f)) => Value::Float(f),
-                    Some(PropertyValue::String(s)) => Value::String(s),
-                    _ => Value::Null,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Import entities into a collection from JSON Lines or CSV
+    ///
+    /// The input is read a record at a time and inserted in batches of
+    /// `options.batch_size`, each like `bulk_insert`, so memory stays bounded
+    /// however large it is. Malformed rows, and rows the collection's schema
+    /// rejects when `options.validate` is set, are skipped or abort the
+    /// import per `options.on_mismatch`; batches already inserted stay.
+    pub fn import_collection<R: Read>(
+        &self,
+        collection: &str,
+        format: DataFormat,
+        reader: R,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, String> {
+        self.audited(
+            Access::Write,
+            &format!("IMPORT INTO {} ({:?})", collection, format),
+            vec![collection.to_string()],
+            || self.import_rows(collection, format, reader, options),
+            |report| report.imported,
+        )
+        .map_err(String::from)
+    }
+
+    fn import_rows<R: Read>(
+        &self,
+        collection: &str,
+        format: DataFormat,
+        reader: R,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, DeedError> {
+        if options.batch_size == 0 {
+            return Err("Import batch size must be positive".into());
+        }
+
+        let mut report = ImportReport::default();
+        let mut batch = Vec::with_capacity(options.batch_size);
+        let mut records = RecordReader::new(BufReader::new(reader), format);
+
+        while let Some(record) = records.next() {
+            let row = record?.and_then(|mut props| {
+                if options.validate {
+                    let schemas = self.schemas.read().unwrap();
+                    schemas.apply_defaults(collection, &mut props);
+                    schemas.coerce_timestamps(collection, &mut props);
+                    schemas
+                        .validate_insert_with(collection, &props, &|expr, _, props| {
+                            self.evaluate_check(collection, expr, props)
+                        })
+                        .map_err(|e| format!("Schema violation: {}", e))?;
                 }
+                Ok(props)
+            });
+
+            match row {
+                Ok(props) => batch.push(props),
+                Err(e) => match options.on_mismatch {
+                    MismatchPolicy::Skip => report.skipped += 1,
+                    MismatchPolicy::Abort => return Err(DeedError::validation(format!("Line {}: {}", records.line(), e))),
+                },
             }
-            AggregateFunc::Max => {
-                let mut max: Option<PropertyValue> = None;
-                for entity in entities {
-                    let val = self.evaluate_expression(argument, entity, ctx);
-                    if let Some(current_max) = &max {
-                        if let Some(std::cmp::Ordering::Greater) = self.compare_property_values(&val, current_max) {
-                            max = Some(val);
-                        }
-                    } else {
-                        max = Some(val);
-                    }
+
+            if batch.len() == options.batch_size {
+                report.imported += self.insert_rows(collection, std::mem::take(&mut batch), false)?.len();
+                report.batches += 1;
+            }
+        }
+
+        if !batch.is_empty() {
+            report.imported += self.insert_rows(collection, batch, false)?.len();
+            report.batches += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Statistics of the plan cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.read().unwrap().stats()
+    }
+
+    /// What the optimizer last changed in a plan, from planning or from learning
+    pub fn last_optimization(&self) -> Option<OptimizationReport> {
+        self.optimizer.read().unwrap().last_report().cloned()
+    }
+
+    /// Execute a read that may be served by a replica at most `max_staleness` behind
+    ///
+    /// Overrides the session's `max_staleness` for this query only. Mutations
+    /// and queries inside an explicit transaction always run on the master.
+    pub fn execute_with_staleness(&self, query_str: &str, max_staleness: Duration) -> Result<QueryResult, String> {
+        self.execute_query(query_str, Some(max_staleness), &HashMap::new(), &QueryControl::default())
+            .map_err(String::from)
+    }
+
+    /// Execute a query, delivering its rows in batches of at most `batch_size`
+    ///
+    /// A SELECT that only scans, filters and projects one collection (with
+    /// any OFFSET and LIMIT) runs a batch of entities at a time, so its rows
+    /// are never all in memory. Other statements, e.g. with ORDER BY, GROUP BY,
+    /// DISTINCT or a traversal, are executed in full and their rows handed out
+    /// in batches.
+    pub fn execute_stream(&self, query_str: &str, batch_size: usize) -> Result<RowStream, String> {
+        self.try_execute_stream(query_str, batch_size).map_err(String::from)
+    }
+
+    /// Execute a query in batches, failing with a [`DeedError`]
+    ///
+    /// Errors met while reading the batches are the stream's.
+    pub fn try_execute_stream(&self, query_str: &str, batch_size: usize) -> Result<RowStream, DeedError> {
+        self.stream_query(query_str, batch_size, QueryControl::default())
+    }
+
+    /// Execute a query in batches, failing it once it has run for `timeout`
+    ///
+    /// The time between batches counts: a stream read too slowly times out.
+    pub fn execute_stream_with_timeout(
+        &self,
+        query_str: &str,
+        batch_size: usize,
+        timeout: Duration,
+    ) -> Result<RowStream, String> {
+        self.stream_timed(query_str, batch_size, Some(timeout)).map_err(String::from)
+    }
+
+    /// Execute a query in batches, failing it once it has run for `timeout`
+    /// if one is given
+    pub(crate) fn stream_timed(
+        &self,
+        query_str: &str,
+        batch_size: usize,
+        timeout: Option<Duration>,
+    ) -> Result<RowStream, DeedError> {
+        self.stream_query(query_str, batch_size, QueryControl::timed(timeout))
+    }
+
+    /// Execute a SELECT one page of at most `page_size` rows at a time
+    ///
+    /// Pass `None` for the first page, then each page's cursor for the one
+    /// after it until a page has none. A query [`execute_stream`] runs a
+    /// batch at a time resumes after the last entity (or ORDER BY key) of
+    /// the page before, reading only the entities still ahead; others are
+    /// executed in full and paged by position, so rows written between
+    /// pages may shift the later ones.
+    ///
+    /// [`execute_stream`]: DQLExecutor::execute_stream
+    pub fn execute_paged(&self, query_str: &str, cursor: Option<&str>, page_size: usize) -> Result<Page, String> {
+        self.try_execute_paged(query_str, cursor, page_size).map_err(String::from)
+    }
+
+    /// Execute a SELECT a page at a time, failing with a [`DeedError`]
+    pub fn try_execute_paged(&self, query_str: &str, cursor: Option<&str>, page_size: usize) -> Result<Page, DeedError> {
+        if page_size == 0 {
+            return Err("Page size must be at least 1".into());
+        }
+
+        let started = Instant::now();
+        let query = Parser::parse(query_str)?;
+        if !matches!(query, crate::dql_ast::Query::Select(_)) {
+            return Err("Only SELECT statements can be paged".into());
+        }
+        let after = match cursor {
+            Some(cursor) => decode_cursor(cursor, query_str)?,
+            None => PageCursor::default(),
+        };
+        self.check_caller(Access::required_for(&query))?;
+
+        let control = QueryControl::default();
+        let (result, next) = match self.scan_stream(&query, query_str, started, &control)? {
+            Some(mut scan) => {
+                scan.resume(&after, page_size);
+                scan.read_page(page_size, &after)?
+            }
+            None => {
+                let mut result = self.execute_query(query_str, None, &HashMap::new(), &control)?;
+                let mut rows = std::mem::take(&mut result.rows).into_iter().skip(after.returned);
+                result.rows = rows.by_ref().take(page_size).collect();
+                let next = rows.next().map(|_| PageCursor {
+                    returned: after.returned + result.rows.len(),
+                    ..Default::default()
+                });
+                (result, next)
+            }
+        };
+
+        let cursor = next.map(|next| encode_cursor(&next, query_str)).transpose()?;
+        Ok(Page { result, cursor })
+    }
+
+    fn stream_query(&self, query_str: &str, batch_size: usize, control: QueryControl) -> Result<RowStream, DeedError> {
+        if batch_size == 0 {
+            return Err("Batch size must be at least 1".into());
+        }
+
+        let started = Instant::now();
+        let query = Parser::parse(query_str)?;
+        self.check_caller(Access::required_for(&query))?;
+        let source = match self.scan_stream(&query, query_str, started, &control)? {
+            Some(scan) => RowSource::Scan(Box::new(scan)),
+            None => RowSource::Materialized(self.execute_query(query_str, None, &HashMap::new(), &control)?),
+        };
+
+        Ok(RowStream {
+            source,
+            batch_size,
+            delivered: false,
+        })
+    }
+
+    /// The batch-at-a-time form of a statement, when it has one
+    ///
+    /// That is a SELECT whose plan is a collection scan followed by filters,
+    /// a projection without DISTINCT, an optional ORDER BY and any OFFSET
+    /// and LIMIT, read from the master's current data. ORDER BY gathers the
+    /// rows into an external sort before the first batch goes out.
+    fn scan_stream(
+        &self,
+        query: &crate::dql_ast::Query,
+        query_str: &str,
+        started: Instant,
+        control: &QueryControl,
+    ) -> Result<Option<ScanStream>, String> {
+        let crate::dql_ast::Query::Select(select) = query else {
+            return Ok(None);
+        };
+        // Replica routing, archived entities and outstanding versions need the full read path
+        if self.session.lock().unwrap().max_staleness.is_some()
+            || select.from.collection == AUDIT_COLLECTION
+            || is_catalog_collection(&select.from.collection)
+            || self.archive.archived_count(&select.from.collection) > 0
+            || self.transaction_manager.mvcc().has_versions()
+        {
+            return Ok(None);
+        }
+
+        let (plan, literals, cached) = self.plan_query(query)?;
+        let mut plan = bind_plan(&plan, &literals, &HashMap::new(), (self.clock)())?;
+        plan.use_indexes(|collection, field, probe| self.index_for(collection, field, probe));
+
+        let Some((
+            Operation::Scan {
+                collection,
+                alias,
+                filter,
+                properties,
+            },
+            rest,
+        )) = plan.operations.split_first()
+        else {
+            return Ok(None);
+        };
+        let incremental = rest
+            .iter()
+            .take_while(|op| matches!(op, Operation::Filter { .. } | Operation::Project { distinct: false, .. }))
+            .count();
+        let mut paging = &rest[incremental..];
+        let mut sort = None;
+        if let Some((Operation::Sort { fields, limit }, after)) = paging.split_first() {
+            sort = Some(StreamSort {
+                fields: fields.clone(),
+                limit: *limit,
+                budget: self.memory_budget_for(control),
+                sorted: None,
+                columns: Vec::new(),
+                after: None,
+                keep_keys: false,
+            });
+            paging = after;
+        }
+        let (mut skip, mut limit) = (0usize, None);
+        for operation in paging {
+            match operation {
+                Operation::Skip { count } if limit.is_none() => skip = skip.saturating_add(*count),
+                Operation::Limit { count } => limit = Some(limit.map_or(*count, |l: usize| l.min(*count))),
+                _ => return Ok(None),
+            }
+        }
+        if !rest[..incremental].iter().any(|op| matches!(op, Operation::Project { .. })) {
+            return Ok(None);
+        }
+
+        self.check_firewall(query, &plan, query_str)?;
+
+        Ok(Some(ScanStream {
+            executor: self.clone(),
+            ids: self.graph.read().unwrap().collection_ids(collection).into_iter(),
+            last_id: None,
+            alias: alias.clone(),
+            filter: filter.clone(),
+            properties: properties.clone(),
+            operations: rest[..incremental].to_vec(),
+            sort,
+            skip,
+            remaining: limit,
+            control: control.under(&self.lifecycle),
+            strict_functions: self.session.lock().unwrap().strict_functions,
+            text: query_str.to_string(),
+            plan,
+            cached,
+            started,
+            rows_scanned: 0,
+            rows_returned: 0,
+            spilled_bytes: 0,
+            finished: false,
+        }))
+    }
+
+    fn execute_query(
+        &self,
+        query_str: &str,
+        max_staleness: Option<Duration>,
+        params: &HashMap<String, Value>,
+        control: &QueryControl,
+    ) -> Result<QueryResult, DeedError> {
+        let started = Instant::now();
+
+        // Parse query
+        let query = Parser::parse(query_str)?;
+        self.audited(
+            Access::required_for(&query),
+            query_str,
+            statement_collections(&query),
+            || self.execute_statement(&query, query_str, max_staleness, params, control, started),
+            |result| result.rows_affected,
+        )
+    }
+
+    /// Execute a parsed statement the caller is allowed to run
+    fn execute_statement(
+        &self,
+        query: &crate::dql_ast::Query,
+        query_str: &str,
+        max_staleness: Option<Duration>,
+        params: &HashMap<String, Value>,
+        control: &QueryControl,
+        started: Instant,
+    ) -> Result<QueryResult, DeedError> {
+        // Every statement passes the firewall; planned ones are checked with their plan
+        if !is_planned(query) {
+            self.check_firewall_command(query, query_str)?;
+        }
+
+        // A node of a sharded cluster only takes writes to its own shards
+        if let Some(ownership) = &self.shard_ownership {
+            ownership.check_write(query)?;
+        }
+
+        // Handle transaction and index commands separately
+        match query {
+            crate::dql_ast::Query::Begin(begin_query) => {
+                return self.handle_begin(begin_query);
+            }
+            crate::dql_ast::Query::Commit => {
+                return self.handle_commit();
+            }
+            crate::dql_ast::Query::Rollback => {
+                return self.handle_rollback();
+            }
+            crate::dql_ast::Query::Savepoint(name) => {
+                return self.handle_savepoint(name);
+            }
+            crate::dql_ast::Query::RollbackToSavepoint(name) => {
+                return self.handle_rollback_to_savepoint(name);
+            }
+            crate::dql_ast::Query::ReleaseSavepoint(name) => {
+                return self.handle_release_savepoint(name);
+            }
+            crate::dql_ast::Query::CreateIndex(create_index) => {
+                return self.handle_create_index(create_index);
+            }
+            crate::dql_ast::Query::DropIndex(drop_index) => {
+                return self.handle_drop_index(drop_index);
+            }
+            crate::dql_ast::Query::Reindex(reindex) => {
+                return self.handle_reindex(reindex);
+            }
+            crate::dql_ast::Query::Analyze(collection) => {
+                return self.handle_analyze(collection.as_deref());
+            }
+            crate::dql_ast::Query::Set(set_query) => {
+                return self.handle_set(set_query);
+            }
+            crate::dql_ast::Query::Archive(archive_query) => {
+                return self.handle_archive(archive_query);
+            }
+            crate::dql_ast::Query::Unarchive(archive_query) => {
+                return self.handle_unarchive(archive_query);
+            }
+            crate::dql_ast::Query::Copy(copy) => {
+                return self.handle_copy(copy);
+            }
+            crate::dql_ast::Query::Firewall(firewall_query) => {
+                return self.handle_firewall(firewall_query);
+            }
+            crate::dql_ast::Query::DefineSchema(schema) => {
+                return self.handle_define_schema(schema);
+            }
+            crate::dql_ast::Query::DropSchema(collection) => {
+                return self.handle_drop_schema(collection);
+            }
+            crate::dql_ast::Query::ShowCollections => {
+                return self.handle_show_collections();
+            }
+            crate::dql_ast::Query::Truncate(collection) => {
+                return self.handle_truncate(collection);
+            }
+            crate::dql_ast::Query::DropCollection(collection) => {
+                return self.handle_drop_collection(collection);
+            }
+            crate::dql_ast::Query::Explain(explain) => {
+                return self.handle_explain(explain, query_str, max_staleness, params, control);
+            }
+            _ => {
+                // Regular query - continue below
+            }
+        }
+
+        let (plan, literals, cached) = self.plan_query(query)?;
+        let mut profile = PlanProfile::new(Some(cached), started);
+        let result = self.run_query(query, &plan, &literals, query_str, max_staleness, params, control, &mut profile);
+        let result = result?;
+        self.learn_from(query, &plan, &profile)?;
+        Ok(result)
+    }
+
+    /// Feed the rows each operation produced back to the optimizer
+    ///
+    /// When that changes the plan it would choose for the statement, the
+    /// cached plan is replaced. Runs cut short by a LIMIT aren't learned from.
+    fn learn_from(&self, query: &crate::dql_ast::Query, plan: &QueryPlan, profile: &PlanProfile) -> Result<(), DeedError> {
+        let rows: Vec<usize> = profile.operations.iter().map(|op| op.rows).collect();
+        if rows.len() != plan.operations.len() || row_budget(plan).is_some() {
+            return Ok(());
+        }
+        // Only traversals are learned from, and only runs of them need the stats
+        if !plan.operations.iter().any(|op| matches!(op, Operation::Traverse { .. })) {
+            return Ok(());
+        }
+
+        let stats = dql_optimizer::has_traversal_run(plan).then(|| self.graph.read().unwrap().stats());
+        let reordered = self.optimizer.write().unwrap().record_execution(plan, &rows, stats.as_ref());
+        if let (Some(reordered), Some(stats)) = (reordered, &stats) {
+            let mut normalized = query.clone();
+            normalized.extract_literals();
+            let query_signature = self.query_signature(&normalized)?;
+            self.cache.write().unwrap().put_with_stats(query_signature, reordered, stats);
+        }
+
+        Ok(())
+    }
+
+    /// Optimized plan for a statement, from the plan cache when it has one
+    ///
+    /// The plan is built from the statement with its literals replaced by
+    /// parameters, so statements differing only in literal values share it;
+    /// the literals are returned as the values of those parameters, along
+    /// with whether the plan came from the cache.
+    fn plan_query(&self, query: &crate::dql_ast::Query) -> Result<(QueryPlan, HashMap<String, Value>, bool), DeedError> {
+        let mut normalized = query.clone();
+        let literals: HashMap<String, Value> = normalized
+            .extract_literals()
+            .into_iter()
+            .map(|(name, literal)| (name, Value::from_literal(&literal)))
+            .collect();
+
+        // Try cache first (stigmergy), unless the graph has outgrown the plan
+        let query_signature = self.query_signature(&normalized)?;
+        let entity_count = self.graph.read().unwrap().entity_count();
+        let mut cache = self.cache.write().unwrap();
+        if let Some(cached_plan) = cache.get_fresh(&query_signature, entity_count) {
+            self.check_text_indexes(&cached_plan)?;
+            return Ok((cached_plan, literals, true));
+        }
+
+        // Build initial plan
+        let mut builder = QueryPlanBuilder::new();
+        let plan = match &normalized {
+            crate::dql_ast::Query::Select(q) => builder.build_select(q)?,
+            crate::dql_ast::Query::Insert(q) => builder.build_insert(q)?,
+            crate::dql_ast::Query::Update(q) => builder.build_update(q)?,
+            crate::dql_ast::Query::Delete(q) => builder.build_delete(q)?,
+            crate::dql_ast::Query::Create(q) => builder.build_create(q)?,
+            crate::dql_ast::Query::UpdateEdge(q) => builder.build_update_edge(q)?,
+            crate::dql_ast::Query::DeleteEdge(q) => builder.build_delete_edge(q)?,
+            _ => return Err("Only SELECT, INSERT, UPDATE, DELETE and CREATE statements can be planned".into()),
+        };
+
+        // Optimize with ant colony
+        let graph = self.graph.read().unwrap();
+        let stats = graph.stats();
+        drop(graph); // Release read lock
+
+        let mut optimizer = self.optimizer.write().unwrap();
+        let optimized = optimizer.optimize(plan, &stats);
+        self.check_text_indexes(&optimized)?;
+
+        // Cache the optimized plan
+        cache.put_with_stats(query_signature, optimized.clone(), &stats);
+
+        Ok((optimized, literals, false))
+    }
+
+    /// Index able to serve a probe of a collection's field, if any
+    fn index_for(&self, collection: &str, field: &str, probe: &IndexProbe) -> Option<String> {
+        if let Some(by_id) = id_lookup(field, probe) {
+            return Some(by_id);
+        }
+        match probe {
+            IndexProbe::Match(_) => self.index_manager.fulltext_index_for(collection, field),
+            IndexProbe::Equal(_) | IndexProbe::In(_) | IndexProbe::Range { .. } => {
+                self.index_manager.index_name_for(collection, field)
+            }
+        }
+    }
+
+    /// Refuse a plan with a MATCH on a field no FULLTEXT index covers
+    fn check_text_indexes(&self, plan: &QueryPlan) -> Result<(), DeedError> {
+        for (collection, field) in plan.text_matches() {
+            let Some(collection) = collection else {
+                return Err(format!("MATCH on {} needs a FULLTEXT index; only scanned collections have one", field).into());
+            };
+            if self.index_manager.fulltext_index_for(collection, field).is_none() {
+                return Err(format!(
+                    "MATCH on {}.{} needs a FULLTEXT index (CREATE FULLTEXT INDEX ... ON {}({}))",
+                    collection, field, collection, field
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the statement's literals and the caller's parameters into a plan and execute it
+    ///
+    /// The executed plan and what each operation did are recorded in
+    /// `profile`, and the statement in the executor's query metrics.
+    #[allow(clippy::too_many_arguments)]
+    fn run_query(
+        &self,
+        query: &crate::dql_ast::Query,
+        plan: &QueryPlan,
+        literals: &HashMap<String, Value>,
+        query_str: &str,
+        max_staleness: Option<Duration>,
+        params: &HashMap<String, Value>,
+        control: &QueryControl,
+        profile: &mut PlanProfile,
+    ) -> Result<QueryResult, DeedError> {
+        let optimized_plan = bind_plan(plan, literals, params, (self.clock)())?;
+
+        // Check if this is a mutation that needs auto-commit
+        let needs_auto_commit = self.is_mutation_query(query);
+        let had_active_txn = self.current_transaction.lock().unwrap().is_some();
+
+        // Auto-begin transaction if needed
+        if needs_auto_commit && !had_active_txn {
+            self.begin_implicit()?;
+        }
+
+        // What an explicit transaction reads is checked against later
+        // commits when it commits (SERIALIZABLE only)
+        if let (true, Some(txn_id)) = (had_active_txn, *self.current_transaction.lock().unwrap()) {
+            if !matches!(query, crate::dql_ast::Query::Insert(_)) {
+                for collection in statement_collections(query) {
+                    self.transaction_manager.track_scan(txn_id, &collection).map_err(DeedError::TransactionError)?;
                 }
-                match max {
-                    Some(PropertyValue::Int(n)) => Value::Integer(n),
-                    Some(PropertyValue::Float(f)) => Value::Float(f),
-                    Some(PropertyValue::String(s)) => Value::String(s),
-                    _ => Value::Null,
+            }
+        }
+
+        // Reads outside a transaction may be served by a replica
+        let max_staleness = max_staleness.or(self.session.lock().unwrap().max_staleness);
+        let route = match query {
+            _ if had_active_txn || control.primary || !is_replica_read(query) => None,
+            _ => self.route_read(query_str, max_staleness).map(|(_, graph, staleness)| (graph, staleness)),
+        };
+
+        // The audit log and catalog are built for the statement in a scratch
+        // graph, which no transaction's versions apply to
+        let reads_system = matches!(
+            query,
+            crate::dql_ast::Query::Select(q) if q.from.collection == AUDIT_COLLECTION || is_catalog_collection(&q.from.collection)
+        );
+
+        // Master reads also see archived entities, unless archive_reads = off
+        let mut warnings = Vec::new();
+        let mut archived_collection = None;
+        let system_graph = match (query, &route) {
+            (crate::dql_ast::Query::Select(q), None) if q.from.collection == AUDIT_COLLECTION => Some(self.audit_graph()?),
+            (crate::dql_ast::Query::Select(q), None) if is_catalog_collection(&q.from.collection) => {
+                Some(self.catalog_graph(&q.from.collection)?)
+            }
+            (crate::dql_ast::Query::Select(q), None) => {
+                let collection = &q.from.collection;
+                let archived = self.archive.archived_count(collection);
+
+                if archived > 0 && self.session.lock().unwrap().archive_reads {
+                    archived_collection = Some(collection.as_str());
+                } else if archived > 0 {
+                    warnings.push(format!(
+                        "{} archived entities in '{}' skipped (archive_reads = off)",
+                        archived, collection
+                    ));
                 }
+                None
+            }
+            _ => None,
+        };
+
+        // Serve indexed predicates from the master's indexes. Indexes hold the
+        // committed values, so they are bypassed while other versions are
+        // tracked; fetches by @id read no index and always apply. Neither
+        // reaches archived entities, which only scans read.
+        let mut optimized_plan = optimized_plan;
+        let uses_indexes = route.is_none() && system_graph.is_none() && !self.transaction_manager.mvcc().has_versions();
+        match archived_collection {
+            Some(_) => {}
+            None if uses_indexes => {
+                optimized_plan.use_indexes(|collection, field, probe| self.index_for(collection, field, probe))
+            }
+            None => optimized_plan.use_id_lookups(),
+        }
+
+        // Execute the plan (unless the firewall refuses its shape). Replicas
+        // only hold committed data, so they are read as is.
+        let result = match self.check_firewall(query, &optimized_plan, query_str) {
+            Err(e) => Err(e),
+            Ok(()) => match &route {
+            Some((graph, staleness)) => self.execute_plan(&optimized_plan, graph, None, None, control, &mut *profile).map(|mut res| {
+                res.staleness_ms = Some(staleness.as_millis() as u64);
+                res
+            }),
+            None => self.read_view().and_then(|view| {
+                let view = view.filter(|_| !reads_system);
+                let graph = system_graph.as_ref().unwrap_or(&self.graph);
+                self.execute_plan(&optimized_plan, graph, view, archived_collection, control, &mut *profile)
+            }),
+            },
+        };
+        let result = result.map(|mut res| {
+            res.warnings = warnings;
+            res
+        });
+
+        // Auto-commit if we auto-began; a deadlock victim is rolled back
+        // either way, so the transactions it held up can go on
+        let auto_commit = needs_auto_commit && !had_active_txn;
+        if auto_commit {
+            if result.is_ok() {
+                self.handle_commit()?;
+            } else {
+                self.rollback_after_error();
+            }
+        } else if result.as_ref().is_err_and(|e| DeadlockError::is_deadlock(e.message())) {
+            self.rollback_after_error();
+        }
+
+        self.metrics.record(QuerySample {
+            text: query_str,
+            plan: &optimized_plan,
+            elapsed: profile.started.elapsed(),
+            rows_scanned: profile.rows_scanned,
+            rows_returned: result.as_ref().map_or(0, |res| res.rows.len()),
+            spilled_bytes: profile.memory.spilled_bytes,
+            cache_hit: profile.cached,
+            auto_committed: auto_commit && result.is_ok(),
+            succeeded: result.is_ok(),
+        });
+
+        result
+    }
+
+    /// Begin the transaction a mutation outside an explicit one runs in
+    fn begin_implicit(&self) -> Result<(), DeedError> {
+        let txn_id = self.transaction_manager.begin_autocommit(IsolationLevel::default()).map_err(DeedError::TransactionError)?;
+        *self.current_transaction.lock().unwrap() = Some(txn_id);
+
+        // Log to WAL
+        if let Some(wal) = &self.wal_manager {
+            wal.log_begin(txn_id, IsolationLevel::default())
+                .map_err(|e| format!("WAL error: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// The graph to serve a read from, its staleness, and where that is
+    ///
+    /// A bounded-staleness read goes to the freshest attached replica
+    /// within the bound, else reads the master's graph as is; it needs the
+    /// master's replication state to judge replicas. Other reads go where
+    /// the read routing picks, when it's on, and are left to the master's
+    /// usual path (`None`) otherwise. The `/*+ primary */` hint keeps any
+    /// read on the master.
+    fn route_read(&self, query_str: &str, max_staleness: Option<Duration>) -> Option<(Route, Arc<RwLock<Graph>>, Duration)> {
+        if has_primary_hint(query_str) {
+            return None;
+        }
+        let routing = self.replicas.routing();
+        let staleness: Option<HashMap<String, Option<Duration>>> = self
+            .replication
+            .as_ref()
+            .map(|replication| replication.replica_staleness().into_iter().collect());
+
+        let picked = match (max_staleness, &routing) {
+            (Some(bound), _) => {
+                let health_interval = routing.unwrap_or_default().health_interval;
+                let picked = staleness
+                    .as_ref()
+                    .and_then(|staleness| self.replicas.pick(Some(staleness), ReadBalancing::LeastLagged, Some(bound), health_interval));
+                return Some(match picked {
+                    Some((slave_id, graph, lag)) => (Route::Replica(slave_id), graph, lag),
+                    None => (Route::Primary, self.graph.clone(), Duration::ZERO),
+                });
+            }
+            (None, Some(config)) => {
+                self.replicas.pick(staleness.as_ref(), config.balancing, config.max_lag, config.health_interval)
+            }
+            (None, None) => None,
+        };
+        picked.map(|(slave_id, graph, lag)| (Route::Replica(slave_id), graph, lag))
+    }
+
+    /// Check the caller's role grants `access`, if there is a caller
+    ///
+    /// Nothing is allowed once the executor has shut down.
+    fn check_caller(&self, access: Access) -> Result<(), DeedError> {
+        self.lifecycle.check_open()?;
+        match &self.caller {
+            Some(session) => session.check_access(access).map_err(DeedError::PermissionError),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail if this node's side of a partition can't serve the access
+    fn check_partition(&self, access: Access) -> Result<(), DeedError> {
+        match &self.partition {
+            Some((manager, _)) if access >= Access::Write => manager.check_write().map_err(DeedError::from),
+            Some((manager, read_consistency)) => manager.check_read(*read_consistency).map_err(DeedError::from),
+            None => Ok(()),
+        }
+    }
+
+    /// Run a statement after checking the caller may, recording it in the audit log
+    ///
+    /// Writes to the `_audit` and catalog collections are refused, as is what this node's
+    /// side of a partition can't serve. When a caller runs a
+    /// write with an audit log configured, the statement is recorded as
+    /// pending before it runs (and doesn't run if that fails), then settled
+    /// as succeeded or failed; a refused one is recorded as denied.
+    fn audited<T>(
+        &self,
+        access: Access,
+        statement: &str,
+        collections: Vec<String>,
+        run: impl FnOnce() -> Result<T, DeedError>,
+        rows_affected: impl FnOnce(&T) -> usize,
+    ) -> Result<T, DeedError> {
+        let _running = self.lifecycle.admit()?;
+        let permitted = self.check_caller(access).and_then(|()| {
+            let system = collections.iter().find(|c| *c == AUDIT_COLLECTION || is_catalog_collection(c));
+            if let Some(collection) = system.filter(|_| access >= Access::Write) {
+                return Err(DeedError::PermissionError(format!("The {} collection is read-only", collection)));
+            }
+            self.check_partition(access)
+        });
+        let audit = match (&self.audit, &self.caller) {
+            (Some(audit), Some(session)) if access >= Access::Write => Some((audit, session)),
+            _ => None,
+        };
+        let Some((audit, session)) = audit else {
+            return permitted.and_then(|()| run());
+        };
+
+        let entry = AuditEntry {
+            timestamp: (self.clock)(),
+            username: session.username.clone(),
+            role: session.role.clone(),
+            statement: normalize_statement(statement),
+            collections,
+            rows_affected: 0,
+            outcome: AuditOutcome::Pending,
+        };
+        if let Err(e) = permitted {
+            audit
+                .record(AuditEntry { outcome: AuditOutcome::Denied(e.to_string()), ..entry })
+                .map_err(|e| format!("Audit log write failed: {}", e))?;
+            return Err(e);
+        }
+
+        let seq = audit.begin(entry).map_err(|e| format!("Audit log write failed: {}", e))?;
+        let result = run();
+        let (outcome, rows) = match &result {
+            Ok(value) => (AuditOutcome::Succeeded, rows_affected(value)),
+            Err(e) => (AuditOutcome::Failed(e.to_string()), 0),
+        };
+        // The statement has taken effect and is on record as pending, so its
+        // result stands even if the outcome can't be written
+        if let Err(e) = audit.finish(seq, outcome, rows) {
+            eprintln!("Audit log write failed: {}", e);
+        }
+
+        result
+    }
+
+    /// Build a scratch graph holding the audit log as the `_audit` collection
+    fn audit_graph(&self) -> Result<Arc<RwLock<Graph>>, String> {
+        let graph = Graph::new();
+        for entry in self.audit.iter().flat_map(|audit| audit.entries()) {
+            graph.add_entity(AUDIT_COLLECTION.to_string(), entry.to_properties())?;
+        }
+        Ok(Arc::new(RwLock::new(graph)))
+    }
+
+    /// Build a scratch graph holding a system catalog collection as it stands now
+    fn catalog_graph(&self, collection: &str) -> Result<Arc<RwLock<Graph>>, String> {
+        let scratch = Graph::new();
+        let graph = self.graph.read().unwrap();
+        let schemas = self.schemas.read().unwrap();
+        let catalog = Catalog {
+            graph: &graph,
+            indexes: &self.index_manager,
+            schemas: &schemas,
+            transactions: &self.transaction_manager,
+        };
+        for row in catalog.rows(collection).unwrap_or_default() {
+            scratch.add_entity(collection.to_string(), row)?;
+        }
+        Ok(Arc::new(RwLock::new(scratch)))
+    }
+
+    /// Check a planned statement against the firewall, if one is configured
+    fn check_firewall(&self, query: &crate::dql_ast::Query, plan: &QueryPlan, query_str: &str) -> Result<(), DeedError> {
+        self.check_firewall_shape(statement_class(query), plan, query_str)
+    }
+
+    /// Check a plan of the given statement class against the firewall
+    fn check_firewall_shape(&self, class: StatementClass, plan: &QueryPlan, statement: &str) -> Result<(), DeedError> {
+        if self.firewall.is_none() {
+            return Ok(());
+        }
+
+        let stats = self.graph.read().unwrap().stats();
+        let shape = StatementShape::from_plan(class, plan, &stats, |collection, field| {
+            self.index_manager.find_index(collection, field).is_some()
+        });
+        self.enforce_firewall(&shape, statement)
+    }
+
+    /// Check a statement that runs without a plan against the firewall
+    fn check_firewall_command(&self, query: &crate::dql_ast::Query, query_str: &str) -> Result<(), DeedError> {
+        if self.firewall.is_none() {
+            return Ok(());
+        }
+
+        let exports = matches!(query, crate::dql_ast::Query::Copy(copy) if copy.export);
+        let shape = StatementShape::command(statement_class(query), statement_collections(query), exports);
+        self.enforce_firewall(&shape, query_str)
+    }
+
+    fn enforce_firewall(&self, shape: &StatementShape, statement: &str) -> Result<(), DeedError> {
+        let (firewall, principal) = match &self.firewall {
+            Some(firewall) => firewall,
+            None => return Ok(()),
+        };
+
+        firewall
+            .check(principal, shape, statement)
+            .map_err(|rejected| DeedError::PermissionError(rejected.to_string()))
+    }
+
+    /// Execute a query plan, reading the versions `read_view` sees (or the graph as is)
+    ///
+    /// The rows and time of each operation are recorded in `profile`.
+    /// `control` is checked before each operation and periodically within
+    /// long ones; the graph lock is released when it stops the statement.
+    fn execute_plan(
+        &self,
+        plan: &QueryPlan,
+        graph: &Arc<RwLock<Graph>>,
+        read_view: Option<ReadView>,
+        archived: Option<&str>,
+        control: &QueryControl,
+        profile: &mut PlanProfile,
+    ) -> Result<QueryResult, DeedError> {
+        let mut ctx = self.run_operations(plan, graph, read_view, archived, control)?;
+
+        profile.plan = plan.clone();
+        profile.operations = std::mem::take(&mut ctx.profile);
+        profile.rows_scanned = ctx.rows_scanned;
+        profile.memory = ctx.memory;
+
+        // Return results
+        Ok(ctx.into_result())
+    }
+
+    /// Run a plan's operations in order, returning the context they leave behind
+    fn run_operations(
+        &self,
+        plan: &QueryPlan,
+        graph: &Arc<RwLock<Graph>>,
+        read_view: Option<ReadView>,
+        archived: Option<&str>,
+        control: &QueryControl,
+    ) -> Result<ExecutionContext, DeedError> {
+        // Execution context
+        let mut ctx = ExecutionContext::new();
+        let budget = row_budget(plan);
+        ctx.read_view = read_view;
+        ctx.archived_collection = archived.map(str::to_string);
+        ctx.now = (self.clock)();
+        ctx.control = control.under(&self.lifecycle);
+        ctx.strict_functions = self.session.lock().unwrap().strict_functions;
+        ctx.memory_budget = self.memory_budget_for(control);
+
+        // Execute operations sequentially
+        let mut scan_grouped = false;
+        for (position, operation) in plan.operations.iter().enumerate() {
+            ctx.control.check()?;
+            ctx.row_budget = budget.filter(|(at, _)| *at == position).map(|(_, rows)| rows);
+            ctx.degrees = Some(graph.read().unwrap().degrees());
+            let started = Instant::now();
+
+            // The GROUP BY a leading scan feeds runs along with it
+            if std::mem::take(&mut scan_grouped) {
+                continue;
+            }
+            if let (0, Some(group_by)) = (position, plan.operations.get(1)) {
+                let graph = graph.read().unwrap();
+                if let Some(profile) = self.group_scan(operation, group_by, &mut ctx, &graph)? {
+                    ctx.profile.extend(profile);
+                    scan_grouped = true;
+                    continue;
+                }
+            }
+
+            // Check if operation needs write access
+            if self.is_mutation(operation) {
+                // Execute mutation with write lock (released per operation)
+                self.execute_mutation(operation, &mut ctx)?;
+            } else {
+                // Execute read operation with shared read lock
+                let graph = graph.read().unwrap();
+                self.execute_operation(operation, &mut ctx, &graph)?;
+            }
+
+            let measured = OperationProfile {
+                rows: ctx.row_count(operation),
+                elapsed: started.elapsed(),
+            };
+            ctx.profile.push(measured);
+        }
+
+        Ok(ctx)
+    }
+
+    /// Insert a batch of rows into a collection, all or nothing
+    ///
+    /// Every row gets its schema defaults and is validated before any is
+    /// stored, unless `validate` is off because the caller has done so.
+    /// The batch's unique values are then claimed in one pass, and it is
+    /// logged to the WAL as a single record. The rows reach the graph when
+    /// the session's transaction commits.
+    fn insert_batch(&self, collection: &str, mut batch: Vec<Properties>, validate: bool) -> Result<Vec<EntityId>, DeedError> {
+        // Errors name the offending row when there is more than one
+        let count = batch.len();
+        let in_row = |row: usize, e: String| if count > 1 { format!("{} (row {})", e, row + 1) } else { e };
+
+        // Fill in the collection schema's defaults and coercions, then validate
+        if validate {
+            let schemas = self.schemas.read().unwrap();
+            for (row, props) in batch.iter_mut().enumerate() {
+                schemas.apply_defaults(collection, props);
+                schemas.coerce_timestamps(collection, props);
+                schemas
+                    .validate_insert_with(collection, props, &|expr, _, props| {
+                        self.evaluate_check(collection, expr, props)
+                    })
+                    .map_err(|e| DeedError::validation(in_row(row, format!("Schema violation: {}", e))))?;
+            }
+        }
+
+        let tid = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+        let graph = self.graph.read().unwrap();
+        let entities: Vec<Entity> = graph
+            .allocate_entity_ids(count)
+            .into_iter()
+            .zip(batch)
+            .map(|(id, properties)| Entity::new(id, collection.to_string(), properties))
+            .collect();
+
+        // Claim the rows' unique values; a violation fails the whole batch
+        let entries: Vec<(EntityId, &Properties)> = entities.iter().map(|e| (e.id, &e.properties)).collect();
+        self.claim_unique(&graph, tid, collection, &entries)
+            .map_err(|(row, e)| DeedError::validation(in_row(row, e)))?;
+
+        self.log_to_wal(|wal| match entities.as_slice() {
+            [entity] => wal.log_insert(tid, entity),
+            _ => wal.log_insert_batch(tid, collection, &entities),
+        })?;
+        for entity in &entities {
+            let (id, entity_type, properties) = (entity.id.as_u64(), entity.entity_type.clone(), entity.properties.clone());
+            let created_at = entity.created_millis();
+            self.log_to_replication(move |replication| {
+                replication.log_insert_created(id, entity_type, properties, created_at)
+            });
+            self.record_change(|| {
+                ChangeEvent::new(ChangeKind::Insert, collection, entity.id, tid).with_after(entity.properties.clone())
+            });
+        }
+
+        // The entities stay in the transaction's versions until it commits
+        self.transaction_manager.track_writes(tid, collection, &[])?;
+        let mvcc = self.transaction_manager.mvcc();
+        let entity_ids = entities.iter().map(|e| e.id).collect();
+        for entity in entities {
+            mvcc.record_write(tid, entity.id, None, Some(entity)).map_err(DeedError::TransactionError)?;
+        }
+
+        Ok(entity_ids)
+    }
+
+    /// Claim the unique values a transaction writes for a batch of entities
+    ///
+    /// Fails with the row that collides and the violation. The writer sees
+    /// its own versions, and the latest commit otherwise; expired entities
+    /// no longer hold their values.
+    fn claim_unique(
+        &self,
+        graph: &Graph,
+        txn_id: TransactionId,
+        collection: &str,
+        entries: &[(EntityId, &Properties)],
+    ) -> Result<(), (usize, String)> {
+        let mvcc = self.transaction_manager.mvcc();
+        let view = self.writer_view(txn_id);
+        let now = (self.clock)();
+        self.index_manager
+            .claim_unique(txn_id, collection, entries, |id, field| {
+                let entity = mvcc.resolve(id, graph.get_entity(id), &view)?;
+                if graph.is_expired(&entity, now) {
+                    return None;
+                }
+                entity.properties.get(field).cloned()
+            })
+            .map_err(|(row, field, value)| {
+                (row, format!("Schema violation: {}", ValidationError::UniqueViolation { field, value }))
+            })
+    }
+
+    /// What a transaction's writes apply to: its own versions, and the
+    /// latest commit otherwise
+    fn writer_view(&self, txn_id: TransactionId) -> ReadView {
+        let snapshot = self.transaction_manager.mvcc().current_snapshot();
+        ReadView { txn_id: Some(txn_id), snapshot, uncommitted: false }
+    }
+
+    /// Delete an entity and its edges as part of a transaction
+    ///
+    /// The graph keeps them until the transaction commits.
+    fn delete_entity_in(&self, entity_id: EntityId, txn_id: TransactionId) -> Result<(), DeedError> {
+        self.transaction_manager.wait_for_writer(txn_id, entity_id).map_err(DeedError::TransactionError)?;
+        let graph = self.graph.read().unwrap();
+        let mvcc = self.transaction_manager.mvcc();
+
+        let current = graph.get_entity(entity_id);
+        let Some(entity) = mvcc.lock_for_update(txn_id, entity_id, current.as_ref()).map_err(DeedError::TransactionError)? else {
+            return Ok(());
+        };
+        mvcc.record_write(txn_id, entity_id, current.as_ref(), None).map_err(DeedError::TransactionError)?;
+
+        let view = self.writer_view(txn_id);
+        let edge_ids: HashSet<EdgeId> = self
+            .visible_neighbors(&graph, entity_id, &TraverseDirection::Both, &[], Some(&view))
+            .into_iter()
+            .map(|(_, edge_id)| edge_id)
+            .collect();
+        for edge_id in edge_ids {
+            let Some(edge) = self.get_visible_edge(&graph, edge_id, Some(&view)) else { continue };
+            mvcc.record_edge_write(txn_id, edge_id, graph.get_edge(edge_id).as_ref(), None).map_err(DeedError::TransactionError)?;
+            self.record_change(|| ChangeEvent::edge(ChangeKind::EdgeDelete, &edge, txn_id).with_before(edge.properties.clone()));
+        }
+
+        self.transaction_manager.track_writes(txn_id, &entity.entity_type, &[entity.id])?;
+        self.log_to_wal(|wal| wal.log_delete(txn_id, &entity))?;
+        let id = entity.id.as_u64();
+        self.log_to_replication(move |replication| replication.log_delete(id));
+        self.record_change(|| {
+            ChangeEvent::new(ChangeKind::Delete, entity.entity_type.clone(), entity.id, txn_id)
+                .with_before(entity.properties.clone())
+        });
+        Ok(())
+    }
+
+    /// Delete an edge as part of a transaction
+    fn delete_edge_in(&self, graph: &Graph, edge: &Edge, txn_id: TransactionId) -> Result<(), DeedError> {
+        self.transaction_manager
+            .mvcc()
+            .record_edge_write(txn_id, edge.id, graph.get_edge(edge.id).as_ref(), None)
+            .map_err(DeedError::TransactionError)?;
+        self.log_to_wal(|wal| wal.log_delete_edge(txn_id, edge.id))?;
+        let id = edge.id.as_u64();
+        self.log_to_replication(move |replication| replication.log_delete_edge(id));
+        self.record_change(|| {
+            ChangeEvent::edge(ChangeKind::EdgeDelete, edge, txn_id)
+                .with_before(edge.properties.clone())
+        });
+        Ok(())
+    }
+
+    /// Check if operation requires write access
+    fn is_mutation(&self, operation: &Operation) -> bool {
+        matches!(
+            operation,
+            Operation::InsertEntity { .. }
+                | Operation::UpdateEntities { .. }
+                | Operation::DeleteEntities { .. }
+                | Operation::CreateEdge { .. }
+                | Operation::UpdateEdges { .. }
+                | Operation::DeleteEdges { .. }
+        )
+    }
+
+    /// Execute mutation operations (INSERT, UPDATE, DELETE, CREATE and edge mutations)
+    fn execute_mutation(
+        &self,
+        operation: &Operation,
+        ctx: &mut ExecutionContext,
+    ) -> Result<(), DeedError> {
+        match operation {
+            Operation::InsertEntity { collection, rows } => {
+                let mut batch = Vec::with_capacity(rows.len());
+                for properties in rows {
+                    let mut props = Properties::new();
+                    for (key, expr) in properties {
+                        props.insert(key.clone(), self.evaluate_insert_value(key, expr)?);
+                    }
+                    batch.push(props);
+                }
+
+                let entity_ids = self.insert_batch(collection, batch, true)?;
+
+                ctx.last_inserted_id = entity_ids.last().copied();
+                ctx.rows_affected += entity_ids.len();
+
+                // Store result for SELECT queries after INSERT
+                for entity_id in entity_ids {
+                    let mut result_row = HashMap::new();
+                    result_row.insert("id".to_string(), Value::EntityId(entity_id.as_u64()));
+                    ctx.result_rows.push(result_row);
+                }
+                ctx.columns = vec!["id".to_string()];
+
+                Ok(())
+            }
+
+            Operation::UpdateEntities { binding, updates } => {
+                // First, get entities to update from context
+                let entity_ids: Vec<EntityId> = ctx
+                    .bindings
+                    .get(binding)
+                    .ok_or_else(|| format!("Binding not found: {}", binding))?
+                    .iter()
+                    .map(|e| e.id)
+                    .collect();
+
+                let tid = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+                let mvcc = self.transaction_manager.mvcc();
+
+                let mut updated = 0;
+                for entity_id in &entity_ids {
+                    // Wait out another transaction's write to the entity first,
+                    // without holding up its commit or rollback
+                    self.transaction_manager.wait_for_writer(tid, *entity_id).map_err(DeedError::TransactionError)?;
+                    let graph = self.graph.read().unwrap();
+
+                    // The update applies to the latest commit (or the transaction's
+                    // own version), which no other transaction can change until
+                    // this one ends
+                    let current = graph.get_entity(*entity_id);
+                    let Some(before) = mvcc.lock_for_update(tid, *entity_id, current.as_ref()).map_err(DeedError::TransactionError)? else {
+                        continue;
+                    };
+                    let mut entity = before.clone();
+
+                    // Every update reads the entity's values from before the statement
+                    let mut changed = Properties::new();
+                    for (key, expr) in updates {
+                        changed.insert(key.clone(), self.evaluate_expression(expr, &before, ctx)?);
+                    }
+                    let schemas = self.schemas.read().unwrap();
+                    schemas.coerce_timestamps(&entity.entity_type, &mut changed);
+                    for (key, value) in &changed {
+                        entity.set_property(key.clone(), value.clone());
+                    }
+
+                    // CHECK constraints see the entity as it will be stored
+                    schemas
+                        .validate_update_with(&entity.entity_type, &changed, &|expr, _, _| {
+                            self.evaluate_check(&entity.entity_type, expr, &entity.properties)
+                        })
+                        .map_err(|e| DeedError::validation(format!("Schema violation: {}", e)))?;
+                    drop(schemas);
+
+                    self.claim_unique(&graph, tid, &entity.entity_type, &[(entity.id, &entity.properties)])
+                        .map_err(|(_, e)| DeedError::validation(e))?;
+                    self.transaction_manager.track_writes(tid, &entity.entity_type, &[entity.id])?;
+                    self.log_to_wal(|wal| {
+                        wal.log_update(tid, entity.id, before.properties.clone(), entity.properties.clone())
+                    })?;
+                    let (id, properties) = (entity.id.as_u64(), entity.properties.clone());
+                    self.log_to_replication(move |replication| replication.log_update(id, properties));
+                    self.record_change(|| {
+                        ChangeEvent::new(ChangeKind::Update, entity.entity_type.clone(), entity.id, tid)
+                            .with_before(before.properties.clone())
+                            .with_after(entity.properties.clone())
+                    });
+
+                    // Other transactions keep reading the committed copy until commit
+                    mvcc.record_write(tid, entity.id, current.as_ref(), Some(entity)).map_err(DeedError::TransactionError)?;
+                    updated += 1;
+                }
+
+                ctx.rows_affected = updated;
+                Ok(())
+            }
+
+            Operation::DeleteEntities { binding } => {
+                // Get entities to delete
+                let entity_ids: Vec<EntityId> = ctx
+                    .bindings
+                    .get(binding)
+                    .ok_or_else(|| format!("Binding not found: {}", binding))?
+                    .iter()
+                    .map(|e| e.id)
+                    .collect();
+
+                let txn_id = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+
+                // Delete each entity (and its edges) once the transaction commits
+                for entity_id in &entity_ids {
+                    self.delete_entity_in(*entity_id, txn_id)?;
+                }
+
+                ctx.deleted_count = entity_ids.len();
+                ctx.rows_affected = entity_ids.len();
+                Ok(())
+            }
+
+            Operation::CreateEdge {
+                source: _,
+                target: _,
+                edge_type,
+                properties,
+            } => {
+                // Evaluate source and target to get entity IDs
+                // For now, we'll assume they're literal entity IDs
+                // Full implementation would evaluate expressions
+
+                let graph = self.graph.read().unwrap();
+
+                // Simplified: assume first entity as source and target
+                // In production, evaluate source/target expressions to get IDs
+                let source_id = if let Some(entities) = ctx.bindings.values().next() {
+                    entities.first().map(|e| e.id)
+                } else {
+                    None
+                };
+
+                let target_id = if let Some(entities) = ctx.bindings.values().nth(1) {
+                    entities.first().map(|e| e.id)
+                } else {
+                    None
+                };
+
+                if let (Some(src), Some(tgt)) = (source_id, target_id) {
+                    let mut props = Properties::new();
+                    for (key, value) in properties {
+                        props.insert(key.clone(), self.value_to_property_value(value));
+                    }
+
+                    // The edge reaches the graph when the transaction commits
+                    let tid = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+                    let edge = Edge::new(graph.allocate_edge_id(), src, tgt, edge_type.clone(), props);
+                    let edge_id = edge.id;
+                    self.transaction_manager
+                        .mvcc()
+                        .record_edge_write(tid, edge_id, None, Some(edge.clone()))
+                        .map_err(DeedError::TransactionError)?;
+                    self.log_to_wal(|wal| wal.log_create_edge(tid, &edge))?;
+                    self.record_change(|| {
+                        ChangeEvent::edge(ChangeKind::EdgeCreate, &edge, tid)
+                            .with_after(edge.properties.clone())
+                    });
+                    self.log_to_replication(move |replication| {
+                        replication.log_create_edge(
+                            edge.id.as_u64(),
+                            edge.source.as_u64(),
+                            edge.target.as_u64(),
+                            edge.edge_type,
+                            edge.properties,
+                        )
+                    });
+                    ctx.rows_affected = 1;
+
+                    // Store result
+                    let mut result_row = HashMap::new();
+                    result_row.insert("edge_id".to_string(), Value::EdgeId(edge_id.as_u64()));
+                    ctx.result_rows.push(result_row);
+                    ctx.columns = vec!["edge_id".to_string()];
+                }
+
+                drop(graph);
+                Ok(())
+            }
+
+            Operation::UpdateEdges { edges, updates } => {
+                let tid = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+
+                let graph = self.graph.read().unwrap();
+                let matched = self.match_edges(edges, ctx, &graph)?;
+
+                for (edge, row) in &matched {
+                    // Every update reads the edge's (and endpoints') values from before the statement
+                    let mut properties = edge.properties.clone();
+                    for (key, expr) in updates {
+                        let bound = expr.substitute_references(&|reference| {
+                            let (FilterExpr::Property { binding, property } | FilterExpr::Has { binding, property }) = reference else {
+                                return None;
+                            };
+                            let value = if binding == EDGE_BINDING {
+                                edge.properties.get(property)
+                            } else {
+                                row.get(binding).and_then(|entity| entity.get_property(property))
+                            };
+                            Some(FilterExpr::Constant(match reference {
+                                FilterExpr::Has { .. } => Value::Bool(value.is_some()),
+                                _ => value.map(|v| self.property_value_to_value(v)).unwrap_or(Value::Null),
+                            }))
+                        });
+                        let value = self.evaluate_expression(&bound, &row[&edges.source_binding], ctx)?;
+                        properties.insert(key.clone(), value);
+                    }
+
+                    self.log_to_wal(|wal| wal.log_update_edge(tid, edge.id, properties.clone()))?;
+                    let (id, logged) = (edge.id.as_u64(), properties.clone());
+                    self.log_to_replication(move |replication| replication.log_update_edge(id, logged));
+                    self.record_change(|| {
+                        ChangeEvent::edge(ChangeKind::EdgeUpdate, edge, tid)
+                            .with_before(edge.properties.clone())
+                            .with_after(properties.clone())
+                    });
+
+                    let updated = Edge { properties, ..edge.clone() };
+                    self.transaction_manager
+                        .mvcc()
+                        .record_edge_write(tid, edge.id, graph.get_edge(edge.id).as_ref(), Some(updated))
+                        .map_err(DeedError::TransactionError)?;
+                }
+
+                drop(graph);
+
+                ctx.rows_affected = matched.len();
+                Ok(())
+            }
+
+            Operation::DeleteEdges { edges } => {
+                let tid = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+
+                let graph = self.graph.read().unwrap();
+                let matched = self.match_edges(edges, ctx, &graph)?;
+
+                for (edge, _) in &matched {
+                    self.delete_edge_in(&graph, edge, tid)?;
+                }
+
+                drop(graph);
+
+                ctx.deleted_count = matched.len();
+                ctx.rows_affected = matched.len();
+                Ok(())
+            }
+
+            _ => Err("Not a mutation operation".into()),
+        }
+    }
+
+    /// Evaluate a schema CHECK expression against the properties being written
+    fn evaluate_check(&self, collection: &str, expression: &str, properties: &Properties) -> Result<bool, String> {
+        let expr = Parser::parse_expression_source(expression)?;
+        let filter = FilterExpr::from_ast(&expr, collection);
+        let entity = Entity::new(EntityId::new(0), collection.to_string(), properties.clone());
+        Ok(self.evaluate_filter(&filter, &entity, &ExecutionContext::new())?)
+    }
+
+    /// Append a change to the WAL, if one is configured
+    fn log_to_wal(&self, log: impl FnOnce(&WALManager) -> std::io::Result<()>) -> Result<(), DeedError> {
+        match &self.wal_manager {
+            Some(wal) => log(wal).map_err(|e| format!("WAL error: {}", e).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Queue a change for a master's replication log, appended when the transaction commits
+    fn log_to_replication(
+        &self,
+        log: impl FnOnce(&ReplicationManager) -> Result<ReplicationSeq, String> + Send + 'static,
+    ) {
+        if self.replication.as_ref().is_some_and(|replication| replication.role() == NodeRole::Master) {
+            self.pending_replication.lock().unwrap().push(Box::new(log));
+        }
+    }
+
+    /// Hold a change for the change feed until the transaction commits
+    ///
+    /// Nothing is collected for a transaction no one was subscribed to
+    /// when it made its first change.
+    fn record_change(&self, event: impl FnOnce() -> ChangeEvent) {
+        let mut pending = self.pending_changes.lock().unwrap();
+        if *pending.collecting.get_or_insert_with(|| self.changes.has_subscribers()) {
+            pending.events.push(event());
+        }
+    }
+
+    /// A collection's entities as the statement's read view sees them
+    ///
+    /// Here and in the other reads, entities expired by the statement's
+    /// start are left out, whether or not they've been deleted yet.
+    fn scan_visible(&self, graph: &Graph, collection: &str, ctx: &ExecutionContext) -> Vec<Entity> {
+        let entities = graph.scan_collection(collection);
+        let mut visible = match &ctx.read_view {
+            Some(view) => self.transaction_manager.mvcc().resolve_collection(collection, entities, view),
+            None => entities,
+        };
+        visible.retain(|entity| !graph.is_expired(entity, ctx.now) && self.in_shard_scope(entity));
+        visible
+    }
+
+    /// A collection's entities that pass an optional filter, as the statement's
+    /// read view sees them, and how many entities were examined
+    ///
+    /// Streams the graph and copies only the survivors, with just the
+    /// `properties` the plan reads when it names them, stopping once the
+    /// row budget is met. When transactions have versions outstanding the
+    /// collection is materialized and resolved instead.
+    fn scan_filtered(
+        &self,
+        graph: &Graph,
+        collection: &str,
+        filter: Option<&FilterExpr>,
+        properties: Option<&[&str]>,
+        ctx: &ExecutionContext,
+    ) -> Result<(Vec<Entity>, usize), DeedError> {
+        let mvcc = self.transaction_manager.mvcc();
+        let reads_graph_as_is = || ctx.read_view.is_none() || !mvcc.has_versions();
+
+        if reads_graph_as_is() {
+            let (kept, examined) = match ctx.row_budget {
+                Some(budget) => {
+                    let mut kept = Vec::new();
+                    let mut examined = 0;
+                    for entity in graph.iter_collection(collection) {
+                        if kept.len() >= budget {
+                            break;
+                        }
+                        ctx.control.check_every(examined)?;
+                        examined += 1;
+                        if !graph.is_expired(&entity, ctx.now)
+                            && self.in_shard_scope(&entity)
+                            && self.passes_filter(filter, &entity, ctx)?
+                        {
+                            kept.push(copy_projected(graph, &entity, properties));
+                        }
+                    }
+                    (kept, examined)
+                }
+                None => {
+                    let ids = graph.collection_ids(collection);
+                    let examined = ids.len();
+                    let kept = self.filter_parallel(ids, &ctx.control, |id| {
+                        let Some(entity) = graph.get_entity_ref(id) else { return Ok(None) };
+                        if graph.is_expired(&entity, ctx.now) || !self.in_shard_scope(&entity) {
+                            return Ok(None);
+                        }
+                        Ok(self.passes_filter(filter, &entity, ctx)?.then(|| copy_projected(graph, &entity, properties)))
+                    })?;
+                    (kept, examined)
+                }
+            };
+
+            // A transaction that wrote during the scan needs its versions resolved
+            if reads_graph_as_is() {
+                return Ok((kept, examined));
+            }
+        }
+
+        let visible = self.scan_visible(graph, collection, ctx);
+        let examined = visible.len();
+        let mut kept = self.filter_entities(visible, filter, ctx)?;
+        if let Some(budget) = ctx.row_budget {
+            kept.truncate(budget);
+        }
+        Ok((kept, examined))
+    }
+
+    /// Hand the archived entities of `collection` that pass an optional
+    /// filter to `visit` while it returns true, if the statement reads that
+    /// collection's archive; returns how many entities were examined
+    ///
+    /// Segments are decoded one at a time, so only what `visit` keeps is held.
+    fn scan_archived(
+        &self,
+        graph: &Graph,
+        collection: &str,
+        filter: Option<&FilterExpr>,
+        ctx: &ExecutionContext,
+        mut visit: impl FnMut(ArchivedEntity) -> Result<bool, DeedError>,
+    ) -> Result<usize, DeedError> {
+        if ctx.archived_collection.as_deref() != Some(collection) {
+            return Ok(0);
+        }
+        let mut examined = 0;
+        self.archive.scan_with(collection, |archived| {
+            ctx.control.check_every(examined)?;
+            examined += 1;
+            let entity = &archived.entity;
+            if graph.is_expired(entity, ctx.now)
+                || !self.in_shard_scope(entity)
+                || !self.passes_filter(filter, entity, ctx)?
+            {
+                return Ok(true);
+            }
+            visit(archived)
+        })?;
+        Ok(examined)
+    }
+
+    /// Run a scan and the GROUP BY it feeds together, aggregating entities
+    /// as they're read rather than holding them all
+    ///
+    /// Returns the profile of both operations, or None having done nothing
+    /// when the operations aren't a scan and a GROUP BY or transactions have
+    /// versions the scan must resolve.
+    fn group_scan(
+        &self,
+        scan: &Operation,
+        group_by: &Operation,
+        ctx: &mut ExecutionContext,
+        graph: &Graph,
+    ) -> Result<Option<[OperationProfile; 2]>, DeedError> {
+        let (Operation::Scan { collection, filter, .. }, Operation::GroupBy { group_fields, aggregates }) = (scan, group_by)
+        else {
+            return Ok(None);
+        };
+        let mvcc = self.transaction_manager.mvcc();
+        let reads_graph_as_is = || ctx.read_view.is_none() || !mvcc.has_versions();
+        if !reads_graph_as_is() {
+            return Ok(None);
+        }
+
+        let started = Instant::now();
+        let mut table = GroupTable::new(ctx.memory_budget.clone());
+        let (mut examined, mut kept) = (0, 0);
+        for entity in graph.iter_collection(collection) {
+            ctx.control.check_every(examined)?;
+            examined += 1;
+            if !graph.is_expired(&entity, ctx.now)
+                && self.in_shard_scope(&entity)
+                && self.passes_filter(filter.as_ref(), &entity, ctx)?
+            {
+                kept += 1;
+                self.group_entity(&mut table, group_fields, aggregates, &entity, ctx)?;
+            }
+        }
+        examined += self.scan_archived(graph, collection, filter.as_ref(), ctx, |archived| {
+            kept += 1;
+            self.group_entity(&mut table, group_fields, aggregates, &archived.entity, ctx)?;
+            Ok(true)
+        })?;
+
+        // A transaction that wrote during the scan needs its versions resolved
+        if !reads_graph_as_is() {
+            return Ok(None);
+        }
+        ctx.rows_scanned += examined;
+        let scanned = OperationProfile {
+            rows: kept,
+            elapsed: started.elapsed(),
+        };
+
+        let started = Instant::now();
+        self.finish_groups(table, group_fields, aggregates, ctx)?;
+        let grouped = OperationProfile {
+            rows: ctx.row_count(group_by),
+            elapsed: started.elapsed(),
+        };
+        Ok(Some([scanned, grouped]))
+    }
+
+    /// An entity as the statement's read view sees it
+    fn get_visible(&self, graph: &Graph, entity_id: EntityId, ctx: &ExecutionContext) -> Option<Entity> {
+        let entity = graph.get_entity(entity_id);
+        let visible = match &ctx.read_view {
+            Some(view) => self.transaction_manager.mvcc().resolve(entity_id, entity, view),
+            None => entity,
+        };
+        visible.filter(|entity| !graph.is_expired(entity, ctx.now))
+    }
+
+    /// An entity as the statement's read view sees it, with only `properties`
+    /// when the plan names them and the graph can be read as is
+    fn get_visible_projected(
+        &self,
+        graph: &Graph,
+        entity_id: EntityId,
+        properties: Option<&[&str]>,
+        ctx: &ExecutionContext,
+    ) -> Option<Entity> {
+        match properties {
+            Some(properties) if ctx.read_view.is_none() || !self.transaction_manager.mvcc().has_versions() => {
+                graph.get_live_entity_projected(entity_id, properties, ctx.now)
+            }
+            _ => self.get_visible(graph, entity_id, ctx),
+        }
+    }
+
+    /// What the current statement's reads may see
+    ///
+    /// Outside a transaction and under READ COMMITTED, everything committed
+    /// so far; under REPEATABLE READ and SERIALIZABLE, what was committed
+    /// when the transaction began. READ UNCOMMITTED also sees other
+    /// transactions' uncommitted writes.
+    fn read_view(&self) -> Result<Option<ReadView>, DeedError> {
+        let snapshot = self.transaction_manager.mvcc().current_snapshot();
+        let Some(txn_id) = *self.current_transaction.lock().unwrap() else {
+            return Ok(Some(ReadView { txn_id: None, snapshot, uncommitted: false }));
+        };
+
+        let (isolation_level, txn_snapshot) = self.transaction_manager.snapshot_of(txn_id)?;
+        let snapshot = match isolation_level {
+            IsolationLevel::ReadUncommitted | IsolationLevel::ReadCommitted => snapshot,
+            IsolationLevel::RepeatableRead | IsolationLevel::Serializable => txn_snapshot,
+        };
+        let uncommitted = isolation_level == IsolationLevel::ReadUncommitted;
+
+        Ok(Some(ReadView { txn_id: Some(txn_id), snapshot, uncommitted }))
+    }
+
+    /// Edges an edge mutation applies to, each with its (source, target) row
+    fn match_edges(
+        &self,
+        edges: &EdgeMatch,
+        ctx: &ExecutionContext,
+        graph: &Graph,
+    ) -> Result<Vec<EdgeBinding>, DeedError> {
+        let sources = ctx
+            .bindings
+            .get(&edges.source_binding)
+            .ok_or_else(|| format!("Binding not found: {}", edges.source_binding))?;
+
+        let mut seen = HashSet::new();
+        let mut matched = Vec::new();
+        for source in sources {
+            let found = self.visible_neighbors(graph, source.id, &edges.direction, &edges.edge_types, ctx.read_view.as_ref());
+            for (neighbor_id, edge_id) in found {
+                if graph.is_edge_expired(edge_id, ctx.now) {
+                    continue;
+                }
+                let edge = self.get_visible_edge(graph, edge_id, ctx.read_view.as_ref());
+                let (Some(edge), Some(target)) = (edge, self.get_visible(graph, neighbor_id, ctx)) else {
+                    continue;
+                };
+                if !self.edge_has_properties(&edge, &edges.edge_properties) {
+                    continue;
+                }
+
+                let row = HashMap::from([
+                    (edges.source_binding.clone(), source.clone()),
+                    (edges.target_alias.clone(), target),
+                ]);
+                if let Some(filter) = &edges.filter {
+                    if !self.evaluate_filter(&self.bind_row(filter, &row, ctx), source, ctx)? {
+                        continue;
+                    }
+                }
+
+                // An edge reachable from both its endpoints is matched once
+                if seen.insert(edge_id) {
+                    matched.push((edge, row));
+                }
+            }
+        }
+
+        Ok(matched)
+    }
+
+    /// Neighbors of an entity in a traversal direction, as a read view sees
+    /// them
+    ///
+    /// Edges with versions outstanding are resolved; the graph's adjacency
+    /// lists stand for the rest.
+    fn visible_neighbors(
+        &self,
+        graph: &Graph,
+        entity_id: EntityId,
+        direction: &TraverseDirection,
+        edge_types: &[String],
+        view: Option<&ReadView>,
+    ) -> Vec<(EntityId, EdgeId)> {
+        let mvcc = self.transaction_manager.mvcc();
+        let mut found = neighbors(graph, entity_id, direction, edge_types);
+        let Some(view) = view.filter(|_| mvcc.has_edge_versions()) else {
+            return found;
+        };
+
+        let (chained, visible) = mvcc.edges_of(entity_id, view);
+        found.retain(|(_, edge_id)| !chained.contains(edge_id));
+        let outgoing = matches!(direction, TraverseDirection::Outgoing | TraverseDirection::Both);
+        let incoming = matches!(direction, TraverseDirection::Incoming | TraverseDirection::Both);
+        for edge in visible {
+            if !edge_types.is_empty() && !edge_types.contains(&edge.edge_type) {
+                continue;
+            }
+            if outgoing && edge.source == entity_id {
+                found.push((edge.target, edge.id));
+            }
+            if incoming && edge.target == entity_id {
+                found.push((edge.source, edge.id));
+            }
+        }
+        found
+    }
+
+    /// An edge as a read view sees it (the graph's copy without one)
+    fn get_visible_edge(&self, graph: &Graph, edge_id: EdgeId, view: Option<&ReadView>) -> Option<Edge> {
+        let edge = graph.get_edge(edge_id);
+        match view {
+            Some(view) => self.transaction_manager.mvcc().resolve_edge(edge_id, edge, view),
+            None => edge,
+        }
+    }
+
+    /// Whether an edge's properties equal every required value
+    fn edge_has_properties(&self, edge: &Edge, required: &HashMap<String, Value>) -> bool {
+        required.iter().all(|(key, value)| {
+            edge.properties
+                .get(key)
+                .is_some_and(|actual| self.property_values_equal(actual, &self.value_to_property_value(value)))
+        })
+    }
+
+    /// Execute a single operation
+    fn execute_operation(
+        &self,
+        operation: &Operation,
+        ctx: &mut ExecutionContext,
+        graph: &Graph,
+    ) -> Result<(), DeedError> {
+        match operation {
+            Operation::Scan {
+                collection,
+                alias,
+                filter,
+                properties,
+            } => {
+                let properties = property_names(properties);
+                let (mut filtered, mut examined) =
+                    self.scan_filtered(graph, collection, filter.as_ref(), properties.as_deref(), ctx)?;
+
+                // Archived entities follow, their edges kept for traversals from them
+                let mut archived = Vec::new();
+                if ctx.row_budget.is_none_or(|budget| filtered.len() < budget) {
+                    examined += self.scan_archived(graph, collection, filter.as_ref(), ctx, |entity| {
+                        filtered.push(entity.entity.clone());
+                        archived.push(entity);
+                        Ok(ctx.row_budget.is_none_or(|budget| filtered.len() < budget))
+                    })?;
+                }
+                for ArchivedEntity { entity, edges } in archived {
+                    ctx.archived_edges.insert(entity.id, edges);
+                    ctx.archived.insert(entity.id, entity);
+                }
+
+                ctx.rows_scanned += examined;
+                ctx.bindings.insert(alias.clone(), filtered);
+                Ok(())
+            }
+
+            Operation::IndexLookup {
+                collection,
+                alias,
+                index_name,
+                probe,
+                filter,
+                properties,
+            } => {
+                let properties = property_names(properties);
+                let entity_ids = match probe {
+                    // Ids name the entities themselves; values that can't be one match nothing
+                    _ if index_name == ID_PROPERTY => {
+                        let values = match probe {
+                            IndexProbe::Equal(value) => std::slice::from_ref(value),
+                            IndexProbe::In(values) => values.as_slice(),
+                            _ => &[],
+                        };
+                        let mut ids: Vec<EntityId> = values
+                            .iter()
+                            .filter_map(|value| match value {
+                                Value::Integer(n) if *n >= 0 => Some(EntityId::new(*n as u64)),
+                                Value::EntityId(id) => Some(EntityId::new(*id)),
+                                _ => None,
+                            })
+                            .collect();
+                        ids.sort_unstable_by_key(|id| id.as_u64());
+                        ids.dedup();
+                        ids
+                    }
+                    IndexProbe::Equal(value) => self
+                        .index_manager
+                        .lookup_in_index(index_name, &self.value_to_property_value(value))?,
+                    IndexProbe::Range { lower, upper } => {
+                        let lower = lower.as_ref().map(|v| self.value_to_property_value(v));
+                        let upper = upper.as_ref().map(|v| self.value_to_property_value(v));
+                        match self.index_manager.range_scan_in_index(index_name, lower.as_ref(), upper.as_ref()) {
+                            Ok(ids) => ids,
+                            // Bounds the index can't order (e.g. mixed types): check every entity
+                            Err(_) => graph.scan_collection(collection).into_iter().map(|e| e.id).collect(),
+                        }
+                    }
+                    IndexProbe::Match(terms) => self.index_manager.match_in_index(index_name, terms)?,
+                    IndexProbe::In(values) => {
+                        let mut ids = Vec::new();
+                        for value in values {
+                            for id in self.index_manager.lookup_in_index(index_name, &self.value_to_property_value(value))? {
+                                if !ids.contains(&id) {
+                                    ids.push(id);
+                                }
+                            }
+                        }
+                        ids
+                    }
+                };
+
+                // The probe narrows candidates; the filter decides the exact matches
+                let candidates: Vec<Entity> = entity_ids
+                    .into_iter()
+                    .filter(|&id| {
+                        self.shard_scope.is_none() || graph.get_entity_ref(id).is_some_and(|e| self.in_shard_scope(&e))
+                    })
+                    .filter_map(|id| self.get_visible_projected(graph, id, properties.as_deref(), ctx))
+                    .filter(|e| e.entity_type == *collection)
+                    .collect();
+                ctx.rows_scanned += candidates.len();
+                let entities = self.filter_entities(candidates, filter.as_ref(), ctx)?;
+
+                ctx.bindings.insert(alias.clone(), entities);
+                Ok(())
+            }
+
+            Operation::Traverse {
+                source_binding,
+                direction,
+                edge_types,
+                edge_alias,
+                edge_properties,
+                target_alias,
+                min_hops,
+                max_hops,
+                top_k,
+                all_paths,
+                filter,
+                properties,
+            } => {
+                let properties = property_names(properties);
+                // Joined rows so far; before the first traversal, one per source entity
+                let rows = match ctx.joined_rows.take() {
+                    Some(rows) => rows,
+                    None => ctx
+                        .bindings
+                        .get(source_binding)
+                        .ok_or_else(|| format!("Binding not found: {}", source_binding))?
+                        .iter()
+                        .map(|e| HashMap::from([(source_binding.clone(), e.clone())]))
+                        .collect(),
+                };
+
+                let mut target_entities = Vec::new();
+                let mut joined_rows = Vec::new();
+                let mut expanded = 0;
+
+                // Breadth-first expansion from each row's source. Entities are
+                // returned once per source, at their shortest hop distance,
+                // so cycles terminate even when max_hops is unbounded. With
+                // all_paths each frontier entry carries its path instead, and
+                // a path never revisits an entity, which ends cycles the same way.
+                'sources: for row in rows {
+                    let source = row
+                        .get(source_binding)
+                        .ok_or_else(|| format!("Binding not found: {}", source_binding))?;
+                    let mut visited = HashSet::new();
+                    visited.insert(source.id);
+
+                    if *min_hops == 0 && self.passes_filter(filter.as_ref(), source, ctx)? {
+                        let mut joined = row.clone();
+                        joined.insert(target_alias.clone(), source.clone());
+                        joined_rows.push(joined);
+                        target_entities.push(source.clone());
+                    }
+
+                    let start = if *all_paths { vec![source.id] } else { Vec::new() };
+                    let mut frontier = vec![(source.id, start)];
+                    let mut depth = 0;
+
+                    while !frontier.is_empty() && depth < *max_hops {
+                        depth += 1;
+                        let mut next_frontier = Vec::new();
+
+                        for (entity_id, path) in frontier {
+                            expanded += 1;
+                            ctx.control.check_every(expanded)?;
+                            // Archived entities are left through the edges archived with them
+                            let archived_edges = ctx.archived_edges.get(&entity_id);
+                            let get_edge = |edge_id: EdgeId| match archived_edges {
+                                Some(edges) => edges.iter().find(|edge| edge.id == edge_id).cloned(),
+                                None => self.get_visible_edge(graph, edge_id, ctx.read_view.as_ref()),
+                            };
+                            let mut candidates = match archived_edges {
+                                Some(edges) => archived_neighbors(edges, entity_id, direction, edge_types),
+                                None => self.visible_neighbors(graph, entity_id, direction, edge_types, ctx.read_view.as_ref()),
+                            };
+                            candidates.retain(|(_, edge_id)| !graph.is_edge_expired(*edge_id, ctx.now));
+                            if let Some(k) = top_k {
+                                // Rank only the edges that match, so k of them are followed
+                                if !edge_properties.is_empty() {
+                                    candidates.retain(|(_, edge_id)| {
+                                        get_edge(*edge_id).is_some_and(|edge| self.edge_has_properties(&edge, edge_properties))
+                                    });
+                                }
+                                candidates = match archived_edges {
+                                    Some(_) => Graph::strongest_by(candidates, *k, |edge_id| {
+                                        get_edge(edge_id).map(|edge| edge.pheromone.strength())
+                                    }),
+                                    None => graph.strongest_edges(candidates, *k),
+                                };
+                            }
+
+                            for (neighbor_id, edge_id) in candidates {
+                                // The edge itself is only fetched when it is matched or bound
+                                let edge = if edge_properties.is_empty() && edge_alias.is_none() {
+                                    None
+                                } else {
+                                    match get_edge(edge_id) {
+                                        Some(edge) if self.edge_has_properties(&edge, edge_properties) => Some(edge),
+                                        _ => continue,
+                                    }
+                                };
+                                let unseen = if *all_paths {
+                                    !path.contains(&neighbor_id)
+                                } else {
+                                    visited.insert(neighbor_id)
+                                };
+                                if !unseen {
+                                    continue;
+                                }
+                                let extended: Vec<EntityId> = if *all_paths {
+                                    path.iter().copied().chain([neighbor_id]).collect()
+                                } else {
+                                    Vec::new()
+                                };
+                                next_frontier.push((neighbor_id, extended));
+
+                                // Closer neighbors are expanded but not returned
+                                if depth < *min_hops {
+                                    continue;
+                                }
+
+                                let target = self
+                                    .get_visible_projected(graph, neighbor_id, properties.as_deref(), ctx)
+                                    .or_else(|| ctx.archived.get(&neighbor_id).cloned());
+                                if let Some(target) = target {
+                                    ctx.rows_scanned += 1;
+                                    if self.passes_filter(filter.as_ref(), &target, ctx)? {
+                                        let mut joined = row.clone();
+                                        joined.insert(target_alias.clone(), target.clone());
+                                        if let (Some(alias), Some(edge)) = (edge_alias, &edge) {
+                                            joined.insert(alias.clone(), edge_entity(edge));
+                                        }
+                                        joined_rows.push(joined);
+                                        target_entities.push(target);
+
+                                        // Stop once a downstream LIMIT is satisfied
+                                        if ctx.row_budget.is_some_and(|budget| joined_rows.len() >= budget) {
+                                            break 'sources;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+
+                        frontier = next_frontier;
+                    }
+                }
+
+                ctx.bindings.insert(target_alias.clone(), target_entities);
+                ctx.joined_rows = Some(joined_rows);
+                Ok(())
+            }
+
+            Operation::Filter { binding, condition } => {
+                // Joined rows are filtered as whole tuples
+                if let Some(rows) = ctx.joined_rows.take() {
+                    let kept = self.filter_parallel(rows, &ctx.control, |row| {
+                        let Some(entity) = row.values().next() else { return Ok(None) };
+                        Ok(self.evaluate_filter(&self.bind_row(condition, &row, ctx), entity, ctx)?.then_some(row))
+                    })?;
+                    ctx.joined_rows = Some(kept);
+                    return Ok(());
+                }
+
+                // Filtered in place; the binding is put back below
+                let entities = ctx
+                    .bindings
+                    .remove(binding)
+                    .ok_or_else(|| format!("Binding not found: {}", binding))?;
+
+                let filtered = self.filter_entities(entities, Some(condition), ctx)?;
+
+                ctx.bindings.insert(binding.clone(), filtered);
+                Ok(())
+            }
+
+            Operation::Project { fields, distinct } => {
+                // Columns each wildcard expanded to, by position in `fields`
+                let mut expanded: Vec<BTreeSet<String>> = vec![BTreeSet::new(); fields.len()];
+
+                // After GROUP BY, fields are read from the grouped rows (the
+                // planner rejects wildcards there)
+                if let Some(aggregates) = &ctx.aggregates {
+                    let mut rows = Vec::new();
+                    for grouped in &ctx.result_rows {
+                        let mut row = HashMap::new();
+                        for field in fields.iter().filter_map(Projection::as_field) {
+                            row.insert(field.alias.clone(), self.evaluate_having_expr(&field.expression, grouped, aggregates)?);
+                        }
+                        rows.push(row);
+                    }
+
+                    store_projection(ctx, fields, &expanded, rows, *distinct);
+                    return Ok(());
+                }
+
+                // After a traversal, each joined row yields one result row with
+                // every field evaluated against the entity bound to its alias
+                if let Some(joined_rows) = &ctx.joined_rows {
+                    let mut rows = Vec::new();
+
+                    for joined in joined_rows {
+                        let Some(any_entity) = joined.values().next() else { continue };
+                        let mut row = HashMap::new();
+
+                        for (position, field) in fields.iter().enumerate() {
+                            let field = match field {
+                                Projection::Field(field) => field,
+                                Projection::Wildcard { binding, prefixed } => {
+                                    if let Some(entity) = joined.get(binding) {
+                                        let columns = &mut expanded[position];
+                                        self.project_wildcard(&mut row, columns, entity, binding, *prefixed);
+                                    }
+                                    continue;
+                                }
+                            };
+                            let value = match &field.expression {
+                                FilterExpr::ShortestPath(call) => shortest_path(graph, call, joined)?,
+                                FilterExpr::Property { binding, property } if property == ID_PROPERTY => joined
+                                    .get(binding)
+                                    .map_or(Value::Null, |entity| Value::EntityId(entity.id.as_u64())),
+                                expression => {
+                                    let bound = self.bind_row(expression, joined, ctx);
+                                    let prop_value = self.evaluate_expression(&bound, any_entity, ctx)?;
+                                    self.property_value_to_value(&prop_value)
+                                }
+                            };
+                            row.insert(field.alias.clone(), value);
+                        }
+
+                        rows.push(row);
+                    }
+
+                    store_projection(ctx, fields, &expanded, rows, *distinct);
+                    return Ok(());
+                }
+
+                // Project fields from all bindings
+                let mut rows = Vec::new();
+
+                // Get all entities from all bindings
+                let all_entities: Vec<(&String, &Entity)> = ctx
+                    .bindings
+                    .iter()
+                    .flat_map(|(alias, entities)| entities.iter().map(move |entity| (alias, entity)))
+                    .collect();
+
+                for (alias, entity) in all_entities {
+                    let mut row = HashMap::new();
+
+                    for (position, field) in fields.iter().enumerate() {
+                        let field = match field {
+                            Projection::Field(field) => field,
+                            Projection::Wildcard { binding, prefixed } => {
+                                if binding == alias {
+                                    self.project_wildcard(&mut row, &mut expanded[position], entity, binding, *prefixed);
+                                }
+                                continue;
+                            }
+                        };
+                        let value = match &field.expression {
+                            FilterExpr::Property { property, .. } if property == ID_PROPERTY => {
+                                Value::EntityId(entity.id.as_u64())
+                            }
+                            expression => self.property_value_to_value(&self.evaluate_expression(expression, entity, ctx)?),
+                        };
+                        row.insert(field.alias.clone(), value);
+                    }
+
+                    rows.push(row);
+                }
+
+                store_projection(ctx, fields, &expanded, rows, *distinct);
+                Ok(())
+            }
+
+            Operation::Sort { fields, limit } => {
+                // Sort result rows key by key, left to right; under a LIMIT
+                // only the rows that survive it are selected and ordered
+                let hidden: Vec<&String> = fields.iter().filter(|f| f.hidden).map(|f| &f.column).collect();
+                let rows = std::mem::take(&mut ctx.result_rows);
+                let rows = match limit {
+                    Some(k) if *k < rows.len() => top_k(rows, fields, *k),
+                    _ => {
+                        // Rows are read back from the sorted runs one at a time
+                        let (sorted, usage) = sort_rows(rows, fields, &ctx.memory_budget)?;
+                        ctx.memory.merge(usage);
+                        let mut rows = Vec::new();
+                        for row in sorted {
+                            rows.push(row?);
+                        }
+                        rows
+                    }
+                };
+                ctx.result_rows = rows;
+
+                // Drop columns that were only projected to sort on
+                for column in hidden {
+                    for row in &mut ctx.result_rows {
+                        row.remove(column);
+                    }
+                    ctx.columns.retain(|c| c != column);
+                }
+
+                Ok(())
+            }
+
+            Operation::Limit { count } => {
+                ctx.result_rows.truncate(*count);
+                Ok(())
+            }
+
+            Operation::Skip { count } => {
+                let skipped = (*count).min(ctx.result_rows.len());
+                ctx.result_rows.drain(..skipped);
+                Ok(())
+            }
+
+            Operation::GroupBy {
+                group_fields,
+                aggregates,
+            } => {
+                let mut table = GroupTable::new(ctx.memory_budget.clone());
+
+                // Entities are aggregated as they're read, and dropped once they have been
+                let bindings = std::mem::take(&mut ctx.bindings);
+                for (seen, entity) in bindings.into_values().flatten().enumerate() {
+                    ctx.control.check_every(seen)?;
+                    self.group_entity(&mut table, group_fields, aggregates, &entity, ctx)?;
+                }
+
+                self.finish_groups(table, group_fields, aggregates, ctx)
+            }
+
+            Operation::Having { condition } => {
+                // Filter aggregated result rows based on HAVING condition
+                let aggregates = ctx.aggregates.as_deref().unwrap_or_default();
+                let mut kept = Vec::new();
+                for row in &ctx.result_rows {
+                    if self.evaluate_having_condition(condition, row, aggregates)? == Some(true) {
+                        kept.push(row.clone());
+                    }
+                }
+
+                ctx.result_rows = kept;
+                Ok(())
+            }
+
+            // The subquery runs on its own context, reading what the statement reads
+            Operation::Subquery { id, plan } => {
+                let mut sub = ExecutionContext::new();
+                sub.read_view = ctx.read_view;
+                sub.now = ctx.now;
+                sub.control = ctx.control.clone();
+                sub.strict_functions = ctx.strict_functions;
+                sub.degrees = ctx.degrees.clone();
+                sub.memory_budget = ctx.memory_budget.clone();
+
+                let budget = row_budget(plan);
+                for (position, operation) in plan.operations.iter().enumerate() {
+                    sub.control.check()?;
+                    sub.row_budget = budget.filter(|(at, _)| *at == position).map(|(_, rows)| rows);
+                    self.execute_operation(operation, &mut sub, graph)?;
+                }
+
+                ctx.rows_scanned += sub.rows_scanned;
+                ctx.memory.merge(sub.memory);
+                ctx.subqueries.insert(*id, SubqueryResult::new(&sub.columns, sub.result_rows));
+                Ok(())
+            }
+
+            // Mutations are handled separately in execute_mutation()
+            Operation::InsertEntity { .. }
+            | Operation::UpdateEntities { .. }
+            | Operation::DeleteEntities { .. }
+            | Operation::CreateEdge { .. }
+            | Operation::UpdateEdges { .. }
+            | Operation::DeleteEdges { .. } => {
+                Err("Mutation operations should be handled by execute_mutation()".into())
+            }
+
+            Operation::Join { left, right, condition } => {
+                // Rows so far; before the first join, one per left entity
+                let rows = match ctx.joined_rows.take() {
+                    Some(rows) => rows,
+                    None => ctx
+                        .bindings
+                        .get(left)
+                        .ok_or_else(|| format!("Binding not found: {}", left))?
+                        .iter()
+                        .map(|e| HashMap::from([(left.clone(), e.clone())]))
+                        .collect(),
+                };
+                let right_entities = ctx
+                    .bindings
+                    .get(right)
+                    .ok_or_else(|| format!("Binding not found: {}", right))?;
+
+                // Hash on the equalities when there are any; otherwise compare
+                // every pair. Either way candidates are checked against the
+                // full condition.
+                let keys = condition.equi_join_keys(right);
+                let candidates: Box<dyn Iterator<Item = (usize, usize)>> = if keys.is_empty() {
+                    let right_count = right_entities.len();
+                    Box::new((0..rows.len()).flat_map(move |l| (0..right_count).map(move |r| (l, r))))
+                } else {
+                    Box::new(self.hash_join(&rows, right_entities, &keys, ctx)?.into_iter())
+                };
+
+                let mut joined_rows = Vec::new();
+                for (seen, (l, r)) in candidates.enumerate() {
+                    ctx.control.check_every(seen)?;
+                    let mut row = rows[l].clone();
+                    row.insert(right.clone(), right_entities[r].clone());
+                    if self.evaluate_filter(&self.bind_row(condition, &row, ctx), &right_entities[r], ctx)? {
+                        joined_rows.push(row);
+                    }
+                }
+
+                let matched = joined_rows.iter().map(|row| row[right].clone()).collect();
+                ctx.bindings.insert(right.clone(), matched);
+                ctx.joined_rows = Some(joined_rows);
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluate filter expression
+    ///
+    /// Expressions that can't be used as a condition are an error rather
+    /// than matching everything.
+    fn evaluate_filter(&self, expr: &FilterExpr, entity: &Entity, ctx: &ExecutionContext) -> Result<bool, DeedError> {
+        Ok(self.filter_truth(expr, entity, ctx)? == Some(true))
+    }
+
+    /// Truth of a condition, `None` when it is unknown
+    ///
+    /// A comparison or predicate on NULL (or a missing property) is unknown,
+    /// so negating it doesn't make it true; AND and OR follow SQL's
+    /// three-valued logic.
+    fn filter_truth(&self, expr: &FilterExpr, entity: &Entity, ctx: &ExecutionContext) -> Result<Option<bool>, DeedError> {
+        let truth = match expr {
+            FilterExpr::And(l, r) => match self.filter_truth(l, entity, ctx)? {
+                Some(false) => Some(false),
+                left => match self.filter_truth(r, entity, ctx)? {
+                    Some(false) => Some(false),
+                    right => left.and(right),
+                },
+            },
+            FilterExpr::Or(l, r) => match self.filter_truth(l, entity, ctx)? {
+                Some(true) => Some(true),
+                left => match self.filter_truth(r, entity, ctx)? {
+                    Some(true) => Some(true),
+                    right => left.and(right),
+                },
+            },
+            FilterExpr::Not(e) => self.filter_truth(e, entity, ctx)?.map(|truth| !truth),
+
+            FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r) => {
+                use std::cmp::Ordering;
+
+                let lv = self.evaluate_expression(l, entity, ctx)?;
+                let rv = self.evaluate_expression(r, entity, ctx)?;
+                if lv == PropertyValue::Null || rv == PropertyValue::Null {
+                    return Ok(None);
+                }
+
+                Some(match expr {
+                    FilterExpr::Equal(..) => self.property_values_equal(&lv, &rv),
+                    FilterExpr::NotEqual(..) => !self.property_values_equal(&lv, &rv),
+                    FilterExpr::LessThan(..) => self.compare_property_values(&lv, &rv) == Some(Ordering::Less),
+                    FilterExpr::LessThanEq(..) => matches!(
+                        self.compare_property_values(&lv, &rv),
+                        Some(Ordering::Less | Ordering::Equal)
+                    ),
+                    FilterExpr::GreaterThan(..) => self.compare_property_values(&lv, &rv) == Some(Ordering::Greater),
+                    _ => matches!(
+                        self.compare_property_values(&lv, &rv),
+                        Some(Ordering::Greater | Ordering::Equal)
+                    ),
+                })
+            }
+            FilterExpr::IsNull(e) => Some(self.evaluate_expression(e, entity, ctx)? == PropertyValue::Null),
+            // Not found in a list holding NULL is unknown, as in SQL
+            FilterExpr::In(e, values) => {
+                let v = self.evaluate_expression(e, entity, ctx)?;
+                if v == PropertyValue::Null {
+                    None
+                } else if values
+                    .iter()
+                    .any(|candidate| self.property_values_equal(&v, &self.value_to_property_value(candidate)))
+                {
+                    Some(true)
+                } else if values.contains(&Value::Null) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            // Looked up among the subquery's distinct values, so 1 matches 1.0
+            FilterExpr::InSet(e, id) => match self.evaluate_expression(e, entity, ctx)? {
+                PropertyValue::Null => None,
+                v => Some(ctx.subquery(*id)?.keys.contains(&v.distinct_key())),
+            },
+            FilterExpr::Exists(id) => Some(!ctx.subquery(*id)?.values.is_empty()),
+            // A list holds the element; a document has it as a key
+            FilterExpr::Contains(list, element) => {
+                let element = self.evaluate_expression(element, entity, ctx)?;
+                match self.evaluate_expression(list, entity, ctx)? {
+                    PropertyValue::Null => None,
+                    _ if element == PropertyValue::Null => None,
+                    PropertyValue::List(items) => Some(items.iter().any(|item| self.property_values_equal(item, &element))),
+                    PropertyValue::Map(map) => Some(element.as_str().is_some_and(|key| map.contains_key(key))),
+                    _ => Some(false),
+                }
+            }
+            // Only strings can match a pattern
+            FilterExpr::Like(e, pattern) => match self.evaluate_expression(e, entity, ctx)? {
+                PropertyValue::Null => None,
+                PropertyValue::String(s) => Some(pattern.matches(&s)),
+                _ => Some(false),
+            },
+            // Re-checked against the text, as the index may be bypassed
+            FilterExpr::Match(e, terms) => match self.evaluate_expression(e, entity, ctx)? {
+                PropertyValue::Null => None,
+                PropertyValue::String(s) => {
+                    let words = tokenize(&s);
+                    Some(terms.iter().all(|term| words.contains(term)))
+                }
+                _ => Some(false),
+            },
+
+            // A boolean property, literal, function or subquery result filters on being TRUE
+            FilterExpr::Property { .. }
+            | FilterExpr::Has { .. }
+            | FilterExpr::Constant(_)
+            | FilterExpr::FunctionCall { .. }
+            | FilterExpr::ScalarSubquery(_) => match self.evaluate_expression(expr, entity, ctx)? {
+                PropertyValue::Null => None,
+                value => Some(value == PropertyValue::Bool(true)),
+            },
+
+            FilterExpr::Add(..)
+            | FilterExpr::Subtract(..)
+            | FilterExpr::Multiply(..)
+            | FilterExpr::Divide(..)
+            | FilterExpr::Aggregate { .. }
+            | FilterExpr::Score(..)
+            | FilterExpr::ShortestPath(_) => {
+                return Err(format!("Unsupported predicate in WHERE: {:?}", expr).into());
+            }
+
+            FilterExpr::Parameter(name) => return Err(format!("Missing value for parameter '{}'", name).into()),
+        };
+
+        Ok(truth)
+    }
+
+    /// Whether an entity passes an optional filter
+    fn passes_filter(&self, filter: Option<&FilterExpr>, entity: &Entity, ctx: &ExecutionContext) -> Result<bool, DeedError> {
+        filter.map_or(Ok(true), |f| self.evaluate_filter(f, entity, ctx))
+    }
+
+    /// Keep the entities that pass an optional filter
+    fn filter_entities(
+        &self,
+        entities: Vec<Entity>,
+        filter: Option<&FilterExpr>,
+        ctx: &ExecutionContext,
+    ) -> Result<Vec<Entity>, DeedError> {
+        if filter.is_none() {
+            return Ok(entities);
+        }
+        self.filter_parallel(entities, &ctx.control, |entity| {
+            Ok(self.passes_filter(filter, &entity, ctx)?.then_some(entity))
+        })
+    }
+
+    /// Map each item to what it keeps, dropping the `None`s
+    ///
+    /// Runs on several threads once there are at least the configured
+    /// threshold of items. Survivors come back in input order either way,
+    /// so a later stable sort orders ties the same. Stops early when the
+    /// statement is cancelled or times out.
+    fn filter_parallel<T: Send, R: Send>(
+        &self,
+        items: Vec<T>,
+        control: &QueryControl,
+        keep: impl Fn(T) -> Result<Option<R>, DeedError> + Sync,
+    ) -> Result<Vec<R>, DeedError> {
+        if items.len() < self.parallel.threshold {
+            let mut kept = Vec::new();
+            for (seen, item) in items.into_iter().enumerate() {
+                control.check_every(seen)?;
+                kept.extend(keep(item)?);
+            }
+            return Ok(kept);
+        }
+
+        let run = || {
+            items
+                .into_par_iter()
+                .enumerate()
+                .filter_map(|(seen, item)| match control.check_every(seen) {
+                    Ok(()) => keep(item).transpose(),
+                    Err(e) => Some(Err(e.into())),
+                })
+                .collect::<Result<Vec<R>, DeedError>>()
+        };
+        match &self.scan_pool {
+            Some(pool) => pool.install(run),
+            None => run(),
+        }
+    }
+
+    /// (row, right entity) pairs whose equi-join keys are equal, in row order
+    ///
+    /// Hashes the smaller side and probes with the larger. Rows with a NULL
+    /// key match nothing, as `=` against NULL is never true.
+    fn hash_join(
+        &self,
+        rows: &[HashMap<String, Entity>],
+        right: &[Entity],
+        keys: &[(String, FilterExpr, FilterExpr)],
+        ctx: &ExecutionContext,
+    ) -> Result<Vec<(usize, usize)>, DeedError> {
+        let key_of = |entity: &Entity, expr: &FilterExpr| -> Result<Option<DistinctKey>, String> {
+            let value = self.evaluate_expression(expr, entity, ctx)?;
+            Ok((value != PropertyValue::Null).then(|| value.distinct_key()))
+        };
+        let row_key = |row: &HashMap<String, Entity>| -> Result<Option<Vec<DistinctKey>>, String> {
+            keys.iter()
+                .map(|(binding, expr, _)| row.get(binding).map_or(Ok(None), |e| key_of(e, expr)))
+                .collect()
+        };
+        let right_key = |entity: &Entity| -> Result<Option<Vec<DistinctKey>>, String> {
+            keys.iter().map(|(_, _, expr)| key_of(entity, expr)).collect()
+        };
+
+        let mut pairs = Vec::new();
+        if right.len() <= rows.len() {
+            let mut table: HashMap<Vec<DistinctKey>, Vec<usize>> = HashMap::new();
+            for (r, entity) in right.iter().enumerate() {
+                ctx.control.check_every(r)?;
+                if let Some(key) = right_key(entity)? {
+                    table.entry(key).or_default().push(r);
+                }
+            }
+            for (l, row) in rows.iter().enumerate() {
+                ctx.control.check_every(l)?;
+                if let Some(matches) = row_key(row)?.and_then(|key| table.get(&key)) {
+                    pairs.extend(matches.iter().map(|&r| (l, r)));
+                }
+            }
+        } else {
+            let mut table: HashMap<Vec<DistinctKey>, Vec<usize>> = HashMap::new();
+            for (l, row) in rows.iter().enumerate() {
+                ctx.control.check_every(l)?;
+                if let Some(key) = row_key(row)? {
+                    table.entry(key).or_default().push(l);
+                }
+            }
+            for (r, entity) in right.iter().enumerate() {
+                ctx.control.check_every(r)?;
+                if let Some(matches) = right_key(entity)?.and_then(|key| table.get(&key)) {
+                    pairs.extend(matches.iter().map(|&l| (l, r)));
+                }
+            }
+            pairs.sort_unstable();
+        }
+
+        Ok(pairs)
+    }
+
+    /// Evaluate expression to property value
+    fn evaluate_expression(
+        &self,
+        expr: &FilterExpr,
+        entity: &Entity,
+        ctx: &ExecutionContext,
+    ) -> Result<PropertyValue, DeedError> {
+        let value = match expr {
+            FilterExpr::Property { binding: _, property } => self.property_of(entity, property, ctx),
+            FilterExpr::Has { binding: _, property } => PropertyValue::Bool(entity.get_property_path(property).is_some()),
+            FilterExpr::Constant(value) => self.value_to_property_value(value),
+
+            FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => {
+                let lv = self.evaluate_expression(l, entity, ctx)?;
+                let rv = self.evaluate_expression(r, entity, ctx)?;
+                self.arithmetic(expr, &lv, &rv)?
+            }
+
+            // A type error is NULL, unless the session is strict
+            FilterExpr::FunctionCall { function, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate_expression(arg, entity, ctx))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match function.apply(&args) {
+                    Ok(value) => value,
+                    Err(e) if ctx.strict_functions => return Err(e.into()),
+                    Err(_) => PropertyValue::Null,
+                }
+            }
+
+            // Conditions used as values (e.g. SELECT age > 30)
+            FilterExpr::And(..)
+            | FilterExpr::Or(..)
+            | FilterExpr::Not(_)
+            | FilterExpr::Equal(..)
+            | FilterExpr::NotEqual(..)
+            | FilterExpr::LessThan(..)
+            | FilterExpr::LessThanEq(..)
+            | FilterExpr::GreaterThan(..)
+            | FilterExpr::GreaterThanEq(..)
+            | FilterExpr::In(..)
+            | FilterExpr::Contains(..)
+            | FilterExpr::Like(..)
+            | FilterExpr::Match(..)
+            | FilterExpr::IsNull(_)
+            | FilterExpr::InSet(..)
+            | FilterExpr::Exists(_) => PropertyValue::Bool(self.evaluate_filter(expr, entity, ctx)?),
+
+            // Term frequency: occurrences of the terms in the text
+            FilterExpr::Score(e, terms) => match self.evaluate_expression(e, entity, ctx)? {
+                PropertyValue::String(s) => {
+                    let occurrences = tokenize(&s).iter().filter(|word| terms.contains(word)).count();
+                    PropertyValue::Float(occurrences as f64)
+                }
+                _ => PropertyValue::Null,
+            },
+
+            FilterExpr::ScalarSubquery(id) => self.value_to_property_value(&ctx.subquery(*id)?.scalar()?),
+
+            FilterExpr::Aggregate { .. } => {
+                return Err(format!("Aggregate not allowed outside SELECT/HAVING: {:?}", expr).into());
+            }
+
+            FilterExpr::Parameter(name) => return Err(format!("Missing value for parameter '{}'", name).into()),
+            FilterExpr::ShortestPath(_) => {
+                return Err(format!("{} is only supported as a SELECT field", expr).into());
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Add an entity's properties to a result row for `binding.*`, and its ID
+    /// under the reserved `@id` column, recording the columns written
+    ///
+    /// Reserved (`@`) pseudo-properties are left out.
+    fn project_wildcard(
+        &self,
+        row: &mut HashMap<String, Value>,
+        columns: &mut BTreeSet<String>,
+        entity: &Entity,
+        binding: &str,
+        prefixed: bool,
+    ) {
+        let column = |name: &str| if prefixed { format!("{}.{}", binding, name) } else { name.to_string() };
+
+        for (name, value) in entity.properties.iter().filter(|(name, _)| !name.starts_with('@')) {
+            let name = column(name);
+            row.insert(name.clone(), self.property_value_to_value(value));
+            columns.insert(name);
+        }
+
+        let id = column(ID_PROPERTY);
+        row.insert(id.clone(), Value::EntityId(entity.id.as_u64()));
+        columns.insert(id);
+    }
+
+    /// Evaluate an INSERT value expression (no entity in scope)
+    fn evaluate_insert_value(&self, key: &str, expr: &FilterExpr) -> Result<PropertyValue, DeedError> {
+        match expr.fold_constants() {
+            FilterExpr::Constant(value) => Ok(self.value_to_property_value(&value)),
+            _ => Err(format!("Cannot evaluate INSERT value for '{}'", key).into()),
+        }
+    }
+
+    /// Resolve property references and HAS checks against a joined row's bindings
+    ///
+    /// References to bindings (or properties) the row doesn't have become NULL.
+    fn bind_row(&self, expr: &FilterExpr, row: &HashMap<String, Entity>, ctx: &ExecutionContext) -> FilterExpr {
+        expr.substitute_references(&|reference| {
+            let value = match reference {
+                FilterExpr::Property { binding, property } => row
+                    .get(binding)
+                    .map(|entity| self.property_value_to_value(&self.property_of(entity, property, ctx))),
+                FilterExpr::Has { binding, property } => row
+                    .get(binding)
+                    .map(|entity| Value::Bool(entity.get_property_path(property).is_some())),
+                _ => return None,
+            };
+            Some(FilterExpr::Constant(value.unwrap_or(Value::Null)))
+        })
+    }
+
+    /// An entity's property, or for a degree pseudo-property (see
+    /// [`parse_degree_property`]) the number of its edges
+    ///
+    /// Edges are counted when read, so only the entities a condition gets
+    /// to are counted. A degree is NULL where there's no graph to count
+    /// in, as in a CHECK constraint.
+    fn property_of(&self, entity: &Entity, property: &str, ctx: &ExecutionContext) -> PropertyValue {
+        if property == ID_PROPERTY {
+            return PropertyValue::Int(entity.id.as_u64() as i64);
+        }
+        let Some((direction, edge_type)) = parse_degree_property(property) else {
+            return entity.get_property_path(property).cloned().unwrap_or(PropertyValue::Null);
+        };
+        let Some(degrees) = &ctx.degrees else {
+            return PropertyValue::Null;
+        };
+        let count = match direction {
+            Direction::Outgoing => degrees.out_degree(entity.id, edge_type),
+            Direction::Incoming => degrees.in_degree(entity.id, edge_type),
+            Direction::Both => degrees.degree(entity.id, edge_type),
+        };
+        PropertyValue::Int(count as i64)
+    }
+
+    /// Compare property values
+    fn compare_property_values(
+        &self,
+        a: &PropertyValue,
+        b: &PropertyValue,
+    ) -> Option<std::cmp::Ordering> {
+        match (a, b) {
+            (PropertyValue::Int(a), PropertyValue::Int(b)) => Some(a.cmp(b)),
+            (PropertyValue::Float(a), PropertyValue::Float(b)) => a.partial_cmp(b),
+            (PropertyValue::Int(a), PropertyValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (PropertyValue::Float(a), PropertyValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (PropertyValue::String(a), PropertyValue::String(b)) => Some(a.cmp(b)),
+            (PropertyValue::Timestamp(a), PropertyValue::Timestamp(b)) => Some(a.cmp(b)),
+            // ISO-8601 strings compare as the instant they name
+            (PropertyValue::Timestamp(a), PropertyValue::String(b)) => parse_timestamp(b).map(|b| a.cmp(&b)),
+            (PropertyValue::String(a), PropertyValue::Timestamp(b)) => parse_timestamp(a).map(|a| a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    fn property_values_equal(&self, a: &PropertyValue, b: &PropertyValue) -> bool {
+        match (a, b) {
+            (PropertyValue::Null, PropertyValue::Null) => true,
+            (PropertyValue::Bool(a), PropertyValue::Bool(b)) => a == b,
+            (PropertyValue::Int(a), PropertyValue::Int(b)) => a == b,
+            (PropertyValue::Float(a), PropertyValue::Float(b)) => (a - b).abs() < f64::EPSILON,
+            (PropertyValue::String(a), PropertyValue::String(b)) => a == b,
+            (PropertyValue::Bytes(a), PropertyValue::Bytes(b)) => a == b,
+            (PropertyValue::Timestamp(a), PropertyValue::Timestamp(b)) => a == b,
+            (PropertyValue::Timestamp(t), PropertyValue::String(s))
+            | (PropertyValue::String(s), PropertyValue::Timestamp(t)) => parse_timestamp(s) == Some(*t),
+            (PropertyValue::List(a), PropertyValue::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| self.property_values_equal(x, y))
+            }
+            (PropertyValue::Map(a), PropertyValue::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, x)| b.get(key).is_some_and(|y| self.property_values_equal(x, y)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Apply an arithmetic node to evaluated operands
+    ///
+    /// Non-numeric operands give NULL; integer overflow and division by zero
+    /// are errors.
+    fn arithmetic(&self, op: &FilterExpr, a: &PropertyValue, b: &PropertyValue) -> Result<PropertyValue, DeedError> {
+        if matches!(op, FilterExpr::Divide(..)) && b.as_f64() == Some(0.0) {
+            return Err("Division by zero".into());
+        }
+
+        let value = match (a, b) {
+            (PropertyValue::Int(a), PropertyValue::Int(b)) => {
+                let result = match op {
+                    FilterExpr::Add(..) => a.checked_add(*b),
+                    FilterExpr::Subtract(..) => a.checked_sub(*b),
+                    FilterExpr::Multiply(..) => a.checked_mul(*b),
+                    _ => a.checked_div(*b),
+                };
+                PropertyValue::Int(result.ok_or_else(|| "Integer overflow".to_string())?)
+            }
+            // Timestamps shift by milliseconds; two timestamps differ by milliseconds
+            (PropertyValue::Timestamp(t), PropertyValue::Int(n)) | (PropertyValue::Int(n), PropertyValue::Timestamp(t))
+                if matches!(op, FilterExpr::Add(..)) =>
+            {
+                PropertyValue::Timestamp(t.checked_add(*n).ok_or_else(|| "Timestamp overflow".to_string())?)
+            }
+            (PropertyValue::Timestamp(t), PropertyValue::Int(n)) if matches!(op, FilterExpr::Subtract(..)) => {
+                PropertyValue::Timestamp(t.checked_sub(*n).ok_or_else(|| "Timestamp overflow".to_string())?)
+            }
+            (PropertyValue::Timestamp(a), PropertyValue::Timestamp(b)) if matches!(op, FilterExpr::Subtract(..)) => {
+                PropertyValue::Int(a.checked_sub(*b).ok_or_else(|| "Integer overflow".to_string())?)
+            }
+            _ => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => PropertyValue::Float(match op {
+                    FilterExpr::Add(..) => a + b,
+                    FilterExpr::Subtract(..) => a - b,
+                    FilterExpr::Multiply(..) => a * b,
+                    _ => a / b,
+                }),
+                _ => PropertyValue::Null,
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn value_to_property_value(&self, value: &Value) -> PropertyValue {
+        match value {
+            Value::Null => PropertyValue::Null,
+            Value::Bool(b) => PropertyValue::Bool(*b),
+            Value::Integer(n) => PropertyValue::Int(*n),
+            Value::Float(f) => PropertyValue::Float(*f),
+            Value::String(s) => PropertyValue::String(s.clone()),
+            Value::Bytes(b) => PropertyValue::Bytes(b.clone()),
+            Value::List(items) => PropertyValue::List(items.iter().map(|v| self.value_to_property_value(v)).collect()),
+            Value::Map(map) => PropertyValue::Map(
+                map.iter().map(|(k, v)| (k.clone(), self.value_to_property_value(v))).collect(),
+            ),
+            Value::Timestamp(ms) => PropertyValue::Timestamp(*ms),
+            // An id read back from `@id` filters on it again
+            Value::EntityId(id) => PropertyValue::Int(*id as i64),
+            _ => PropertyValue::Null,
+        }
+    }
+
+    /// Convert PropertyValue to Value
+    fn property_value_to_value(&self, prop_value: &PropertyValue) -> Value {
+        match prop_value {
+            PropertyValue::Null => Value::Null,
+            PropertyValue::Bool(b) => Value::Bool(*b),
+            PropertyValue::Int(i) => Value::Integer(*i),
+            PropertyValue::Float(f) => Value::Float(*f),
+            PropertyValue::String(s) => Value::String(s.clone()),
+            PropertyValue::Bytes(b) => Value::Bytes(b.clone()),
+            PropertyValue::List(items) => Value::List(items.iter().map(|v| self.property_value_to_value(v)).collect()),
+            PropertyValue::Map(map) => Value::Map(
+                map.iter().map(|(k, v)| (k.clone(), self.property_value_to_value(v))).collect(),
+            ),
+            PropertyValue::Timestamp(ms) => Value::Timestamp(*ms),
+        }
+    }
+
+    /// Add an entity to its GROUP BY group
+    fn group_entity(
+        &self,
+        table: &mut GroupTable,
+        group_fields: &[FilterExpr],
+        aggregates: &[AggregateOp],
+        entity: &Entity,
+        ctx: &ExecutionContext,
+    ) -> Result<(), DeedError> {
+        let mut values = Vec::with_capacity(group_fields.len());
+        for field_expr in group_fields {
+            let prop_value = self.evaluate_expression(field_expr, entity, ctx)?;
+            values.push(self.property_value_to_value(&prop_value));
+        }
+        let mut arguments = Vec::with_capacity(aggregates.len());
+        for agg_op in aggregates {
+            arguments.push(self.evaluate_expression(&agg_op.argument, entity, ctx)?);
+        }
+        Ok(table.add(self, aggregates, values, arguments)?)
+    }
+
+    /// Turn GROUP BY's groups into result rows, finishing each group's
+    /// aggregates as it's read back
+    fn finish_groups(
+        &self,
+        table: GroupTable,
+        group_fields: &[FilterExpr],
+        aggregates: &[AggregateOp],
+        ctx: &mut ExecutionContext,
+    ) -> Result<(), DeedError> {
+        let (groups, usage) = table.finish(self, aggregates)?;
+        ctx.memory.merge(usage);
+
+        let mut result_rows = Vec::new();
+        for group in groups {
+            let group = group?;
+            let mut row = HashMap::new();
+
+            // Group fields keep the types the first entity in the group had
+            for (field_expr, value) in group_fields.iter().zip(group.values) {
+                row.insert(self.extract_field_name(field_expr), value);
+            }
+
+            for (agg_op, accumulator) in aggregates.iter().zip(group.accumulators) {
+                row.insert(agg_op.alias.clone(), self.finish_aggregate(agg_op, accumulator)?);
+            }
+
+            result_rows.push(row);
+        }
+
+        // Aggregating without GROUP BY yields one row, even over no entities
+        if group_fields.is_empty() && result_rows.is_empty() {
+            let mut row = HashMap::new();
+            for agg_op in aggregates {
+                row.insert(agg_op.alias.clone(), self.finish_aggregate(agg_op, Accumulator::default())?);
+            }
+            result_rows.push(row);
+        }
+
+        ctx.result_rows = result_rows;
+        ctx.aggregates = Some(aggregates.to_vec());
+        Ok(())
+    }
+
+    /// Add an entity's argument to an aggregate's running state
+    ///
+    /// NULLs are skipped (COUNT(*) counts a constant, so it sees every
+    /// entity); DISTINCT keeps one of each value, treating 1 and 1.0 as equal,
+    /// and aggregates them when the group is finished. Returns the estimated
+    /// bytes the state grew by.
+    fn accumulate(&self, op: &AggregateOp, accumulator: &mut Accumulator, value: PropertyValue) -> usize {
+        if value == PropertyValue::Null {
+            return 0;
+        }
+        if !op.distinct {
+            return self.accumulate_value(&op.function, accumulator, value);
+        }
+        let key = value.distinct_key();
+        let size = distinct_key_size(&key) + property_value_size(&value);
+        if !accumulator.seen.insert(key) {
+            return 0;
+        }
+        accumulator.values.push(value);
+        size
+    }
+
+    /// Add a non-NULL value to the running COUNT, SUM, AVG, MIN or MAX
+    fn accumulate_value(&self, function: &AggregateFunc, accumulator: &mut Accumulator, value: PropertyValue) -> usize {
+        accumulator.count += 1;
+        accumulator.sum.add(&value);
+        self.offer_best(function, accumulator, value)
+    }
+
+    /// Keep `value` as a MIN or MAX if it beats the one so far, returning the
+    /// estimated bytes the state grew by
+    fn offer_best(&self, function: &AggregateFunc, accumulator: &mut Accumulator, value: PropertyValue) -> usize {
+        let wanted = match function {
+            AggregateFunc::Min => std::cmp::Ordering::Less,
+            AggregateFunc::Max => std::cmp::Ordering::Greater,
+            _ => return 0,
+        };
+        let better = match &accumulator.best {
+            Some(current) => self.compare_property_values(&value, current) == Some(wanted),
+            None => true,
+        };
+        if !better {
+            return 0;
+        }
+        let size = property_value_size(&value);
+        let replaced = accumulator.best.replace(value).map_or(0, |old| property_value_size(&old));
+        size.saturating_sub(replaced)
+    }
+
+    /// Fold another partial state of the same aggregate into `accumulator`,
+    /// returning the estimated bytes it grew by
+    fn merge_accumulators(&self, op: &AggregateOp, accumulator: &mut Accumulator, other: Accumulator) -> usize {
+        let mut grown = 0;
+        for value in other.values {
+            grown += self.accumulate(op, accumulator, value);
+        }
+        if let Some(best) = other.best {
+            grown += self.offer_best(&op.function, accumulator, best);
+        }
+        accumulator.count += other.count;
+        accumulator.sum.merge(&other.sum);
+        grown
+    }
+
+    /// Compute an aggregate from its running state
+    ///
+    /// Without any value COUNT is 0 and the others are NULL.
+    ///
+    /// SUM over integers is exact and stays an integer, failing with an
+    /// overflow error if the total doesn't fit in 64 bits; a float among the
+    /// inputs makes it a float. AVG is always a float, taken from the exact
+    /// integer total. SUM and AVG ignore non-numeric values. MIN and MAX
+    /// return the winning value with its type.
+    fn finish_aggregate(&self, op: &AggregateOp, accumulator: Accumulator) -> Result<Value, DeedError> {
+        let accumulator = if op.distinct {
+            let mut all = Accumulator::default();
+            for value in accumulator.values {
+                self.accumulate_value(&op.function, &mut all, value);
+            }
+            all
+        } else {
+            accumulator
+        };
+
+        let value = match op.function {
+            AggregateFunc::Count => Value::Integer(accumulator.count as i64),
+            AggregateFunc::Sum => match accumulator.sum {
+                NumericSum { count: 0, .. } => Value::Null,
+                NumericSum { integers, floats: None, .. } => {
+                    Value::Integer(i64::try_from(integers).map_err(|_| "Integer overflow in SUM".to_string())?)
+                }
+                NumericSum { integers, floats: Some(floats), .. } => Value::Float(integers as f64 + floats),
+            },
+            AggregateFunc::Avg => match accumulator.sum {
+                NumericSum { count: 0, .. } => Value::Null,
+                NumericSum { integers, floats, count } => {
+                    Value::Float((integers as f64 + floats.unwrap_or(0.0)) / count as f64)
+                }
+            },
+            AggregateFunc::Min | AggregateFunc::Max => {
+                accumulator.best.map_or(Value::Null, |best| self.property_value_to_value(&best))
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Column a group field is kept under in the grouped rows
+    fn extract_field_name(&self, expr: &FilterExpr) -> String {
+        match expr {
+            FilterExpr::Property { binding: _, property } => property.clone(),
+            _ => expr.to_string(),
+        }
+    }
+
+    /// Evaluate HAVING condition on aggregated result row
+    ///
+    /// Comparisons involving NULL are unknown (`None`), and stay so under
+    /// NOT, as in WHERE.
+    fn evaluate_having_condition(
+        &self,
+        condition: &FilterExpr,
+        row: &HashMap<String, Value>,
+        aggregates: &[AggregateOp],
+    ) -> Result<Option<bool>, DeedError> {
+        use std::cmp::Ordering;
+
+        let compare = |l: &FilterExpr, r: &FilterExpr| -> Result<Option<Ordering>, String> {
+            let lv = self.evaluate_having_expr(l, row, aggregates)?;
+            let rv = self.evaluate_having_expr(r, row, aggregates)?;
+            if lv == Value::Null || rv == Value::Null {
+                Ok(None)
+            } else {
+                Ok(Some(lv.sort_cmp(&rv)))
+            }
+        };
+
+        let matched = match condition {
+            FilterExpr::And(l, r) => match self.evaluate_having_condition(l, row, aggregates)? {
+                Some(false) => Some(false),
+                left => match self.evaluate_having_condition(r, row, aggregates)? {
+                    Some(false) => Some(false),
+                    right => left.and(right),
+                },
+            },
+            FilterExpr::Or(l, r) => match self.evaluate_having_condition(l, row, aggregates)? {
+                Some(true) => Some(true),
+                left => match self.evaluate_having_condition(r, row, aggregates)? {
+                    Some(true) => Some(true),
+                    right => left.and(right),
+                },
+            },
+            FilterExpr::Not(e) => self.evaluate_having_condition(e, row, aggregates)?.map(|truth| !truth),
+
+            FilterExpr::GreaterThan(l, r) => compare(l, r)?.map(|o| o == Ordering::Greater),
+            FilterExpr::GreaterThanEq(l, r) => compare(l, r)?.map(|o| o != Ordering::Less),
+            FilterExpr::LessThan(l, r) => compare(l, r)?.map(|o| o == Ordering::Less),
+            FilterExpr::LessThanEq(l, r) => compare(l, r)?.map(|o| o != Ordering::Greater),
+            FilterExpr::Equal(l, r) => compare(l, r)?.map(|o| o == Ordering::Equal),
+            FilterExpr::NotEqual(l, r) => compare(l, r)?.map(|o| o != Ordering::Equal),
+
+            other => return Err(format!("Unsupported predicate in HAVING: {:?}", other).into()),
+        };
+
+        Ok(matched)
+    }
+
+    /// Evaluate expression in HAVING context (on result row)
+    ///
+    /// Aggregates read the column GROUP BY stored them under; properties and
+    /// other grouped expressions read group key columns.
+    fn evaluate_having_expr(
+        &self,
+        expr: &FilterExpr,
+        row: &HashMap<String, Value>,
+        aggregates: &[AggregateOp],
+    ) -> Result<Value, DeedError> {
+        match expr {
+            FilterExpr::Aggregate { .. } => Ok(aggregates
+                .iter()
+                .find(|a| a.computes(expr))
+                .and_then(|a| row.get(&a.alias))
+                .cloned()
+                .unwrap_or(Value::Null)),
+            FilterExpr::Constant(v) => Ok(v.clone()),
+            FilterExpr::Property { binding: _, property } => {
+                Ok(row.get(property).cloned().unwrap_or(Value::Null))
+            }
+            other => row
+                .get(&self.extract_field_name(other))
+                .cloned()
+                .ok_or_else(|| format!("Unsupported expression on grouped rows: {:?}", other).into()),
+        }
+    }
+
+    /// Check if a query is a mutation (needs transaction)
+    fn is_mutation_query(&self, query: &crate::dql_ast::Query) -> bool {
+        matches!(
+            query,
+            crate::dql_ast::Query::Insert(_)
+                | crate::dql_ast::Query::Update(_)
+                | crate::dql_ast::Query::Delete(_)
+                | crate::dql_ast::Query::Create(_)
+                | crate::dql_ast::Query::UpdateEdge(_)
+                | crate::dql_ast::Query::DeleteEdge(_)
+        )
+    }
+
+    /// Handle BEGIN TRANSACTION
+    fn handle_begin(&self, begin_query: &crate::dql_ast::BeginQuery) -> Result<QueryResult, DeedError> {
+        // Check if already in transaction
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err(DeedError::transaction("Already in a transaction"));
+        }
+
+        // Start new transaction
+        let isolation_level = begin_query.isolation_level.unwrap_or(IsolationLevel::RepeatableRead);
+        let txn_id = self.transaction_manager.begin(isolation_level).map_err(DeedError::TransactionError)?;
+
+        // Log to WAL
+        if let Some(wal) = &self.wal_manager {
+            wal.log_begin(txn_id, isolation_level)
+                .map_err(|e| format!("WAL error: {}", e))?;
+        }
+
+        // Store current transaction
+        *self.current_transaction.lock().unwrap() = Some(txn_id);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle COMMIT
+    fn handle_commit(&self) -> Result<QueryResult, DeedError> {
+        // Get current transaction
+        let txn_id = self.current_transaction.lock().unwrap().take()
+            .ok_or_else(|| DeedError::transaction("No active transaction to commit"))?;
+        let replicated = std::mem::take(&mut *self.pending_replication.lock().unwrap());
+        let mut changes = std::mem::take(&mut *self.pending_changes.lock().unwrap()).events;
+        self.savepoints.lock().unwrap().clear();
+
+        // Commit transaction; one that lost a conflict is rolled back for
+        // the client to retry. Its writes reach the graph and indexes under
+        // the graph's write lock, so readers see all of them or none, and it
+        // takes its place in the change feed's order there too.
+        let graph = self.graph.write().unwrap();
+        let writes = self.transaction_manager.mvcc().writes_of(txn_id);
+        if let Err(e) = self.transaction_manager.commit(txn_id) {
+            drop(graph);
+            if SerializationError::is_serialization_failure(&e) {
+                *self.current_transaction.lock().unwrap() = Some(txn_id);
+                self.handle_rollback()?;
+            }
+            return Err(DeedError::TransactionError(e));
+        }
+        let publication = self.changes.reserve();
+        self.apply_writes(&graph, writes)?;
+        self.index_manager.release_claims(txn_id);
+        drop(graph);
+
+        // Log to WAL
+        if let Some(wal) = &self.wal_manager {
+            // Returns once the commit is durable (batched with other commits
+            // under group commit)
+            wal.log_commit(txn_id)
+                .map_err(|e| format!("WAL error: {}", e))?;
+
+            // Keep the log bounded; a checkpoint waits for open transactions
+            if wal.checkpoint_due().map_err(|e| format!("WAL error: {}", e))? {
+                self.try_checkpoint()?;
+            }
+        }
+
+        // Slaves replay the committed changes in the order they were made
+        if let Some(replication) = &self.replication {
+            for log in replicated {
+                log(replication)?;
+            }
+        }
+
+        // Subscribers hear of the changes once they are durable, after
+        // those of transactions that committed earlier
+        let timestamp = (self.clock)();
+        for change in &mut changes {
+            change.timestamp = timestamp;
+        }
+        publication.publish(changes);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Apply a committing transaction's writes to the graph and indexes
+    ///
+    /// The indexes change once the graph has, old entries first so values
+    /// that moved between entities don't collide. An edge whose endpoint
+    /// another transaction deleted meanwhile is dropped.
+    fn apply_writes(&self, graph: &Graph, writes: TxnWrites) -> Result<(), DeedError> {
+        let mut replaced = Vec::new();
+        let mut stored = Vec::new();
+        let mut deleted = Vec::new();
+        for (id, entity) in writes.entities {
+            if let Some(old) = graph.get_entity(id) {
+                if entity.is_none() {
+                    deleted.push(id);
+                }
+                replaced.push(old);
+            }
+            stored.extend(entity);
+        }
+
+        let stored_ids: HashSet<EntityId> = stored.iter().map(|entity| entity.id).collect();
+        let present = |id: EntityId| stored_ids.contains(&id) || graph.get_entity_ref(id).is_some();
+        let edges = writes
+            .edges
+            .into_iter()
+            .filter(|(id, edge)| match edge {
+                Some(edge) if graph.get_edge(*id).is_none() => present(edge.source) && present(edge.target),
+                _ => true,
+            })
+            .collect();
+        graph.apply_writes(stored.clone(), edges, &deleted)?;
+
+        for old in replaced {
+            self.index_manager.remove_from_indexes(&old.entity_type, old.id, &old.properties);
+        }
+        for entity in &stored {
+            self.index_manager.insert_claimed(&entity.entity_type, entity.id, &entity.properties);
+        }
+        Ok(())
+    }
+
+    /// Handle ROLLBACK
+    fn handle_rollback(&self) -> Result<QueryResult, DeedError> {
+        // Get current transaction
+        let txn_id = self.current_transaction.lock().unwrap().take()
+            .ok_or_else(|| DeedError::transaction("No active transaction to rollback"))?;
+        self.pending_replication.lock().unwrap().clear();
+        *self.pending_changes.lock().unwrap() = PendingChanges::default();
+        self.savepoints.lock().unwrap().clear();
+
+        // The graph never saw the transaction's writes
+        self.transaction_manager.rollback(txn_id).map_err(DeedError::TransactionError)?;
+        self.index_manager.release_claims(txn_id);
+
+        // Log to WAL
+        if let Some(wal) = &self.wal_manager {
+            wal.log_rollback(txn_id)
+                .map_err(|e| format!("WAL error: {}", e))?;
+        }
+
+        Ok(QueryResult::default())
+    }
+
+    /// Roll back the transaction a failed statement ran in, keeping the
+    /// statement's error for the caller if the rollback fails too
+    fn rollback_after_error(&self) {
+        if let Err(e) = self.handle_rollback() {
+            eprintln!("Rollback after failed statement failed: {}", e);
+        }
+    }
+
+    /// The explicit transaction a savepoint command applies to
+    fn savepoint_transaction(&self, command: &str) -> Result<TransactionId, DeedError> {
+        self.current_transaction
+            .lock()
+            .unwrap()
+            .ok_or_else(|| DeedError::transaction(format!("{} can only be used inside a transaction", command)))
+    }
+
+    /// Handle SAVEPOINT name
+    fn handle_savepoint(&self, name: &str) -> Result<QueryResult, DeedError> {
+        let txn_id = self.savepoint_transaction("SAVEPOINT")?;
+        // Logged first: a savepoint the log doesn't have can't be rolled back to
+        self.log_to_wal(|wal| wal.log_savepoint(txn_id, name))?;
+        self.transaction_manager.savepoint(txn_id, name).map_err(DeedError::TransactionError)?;
+
+        let mark = SavepointMark {
+            replicated: self.pending_replication.lock().unwrap().len(),
+            changes: self.pending_changes.lock().unwrap().events.len(),
+        };
+        self.savepoints.lock().unwrap().push(mark);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle ROLLBACK TO SAVEPOINT name
+    ///
+    /// Undoes the transaction's changes since the savepoint, which stays
+    /// open; later savepoints are discarded. The transaction carries on.
+    fn handle_rollback_to_savepoint(&self, name: &str) -> Result<QueryResult, DeedError> {
+        let txn_id = self.savepoint_transaction("ROLLBACK TO SAVEPOINT")?;
+        self.transaction_manager.savepoint_depth(txn_id, name).map_err(DeedError::TransactionError)?;
+        self.log_to_wal(|wal| wal.log_rollback_to_savepoint(txn_id, name))?;
+
+        let depth = self.transaction_manager.rollback_to_savepoint(txn_id, name).map_err(DeedError::TransactionError)?;
+
+        // Forget the replication and change feed work queued since
+        let mut marks = self.savepoints.lock().unwrap();
+        let mark = marks[depth];
+        marks.truncate(depth + 1);
+        self.pending_replication.lock().unwrap().truncate(mark.replicated);
+        self.pending_changes.lock().unwrap().events.truncate(mark.changes);
+        drop(marks);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle RELEASE SAVEPOINT name
+    ///
+    /// Forgets the savepoint and any later ones, keeping their changes.
+    fn handle_release_savepoint(&self, name: &str) -> Result<QueryResult, DeedError> {
+        let txn_id = self.savepoint_transaction("RELEASE SAVEPOINT")?;
+        self.transaction_manager.savepoint_depth(txn_id, name).map_err(DeedError::TransactionError)?;
+        self.log_to_wal(|wal| wal.log_release_savepoint(txn_id, name))?;
+        let depth = self.transaction_manager.release_savepoint(txn_id, name).map_err(DeedError::TransactionError)?;
+        self.savepoints.lock().unwrap().truncate(depth);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle CREATE INDEX
+    fn handle_create_index(&self, create_index: &crate::dql_ast::CreateIndexQuery) -> Result<QueryResult, DeedError> {
+        if create_index.fulltext {
+            self.index_manager.create_fulltext_index(
+                create_index.index_name.clone(),
+                create_index.collection.clone(),
+                create_index.field.clone(),
+            )?;
+        } else {
+            self.index_manager.create_index(
+                create_index.index_name.clone(),
+                create_index.collection.clone(),
+                create_index.field.clone(),
+                create_index.unique,
+            )?;
+        }
+
+        // Backfill entities that already exist
+        let graph = self.graph.read().unwrap();
+        if let Err(e) = self.index_manager.rebuild_index(&create_index.index_name, &graph) {
+            self.index_manager.drop_index(&create_index.index_name)?;
+            return Err(e.into());
+        }
+
+        // Plans over the collection were optimized without this index
+        self.cache.write().unwrap().invalidate_collection(&create_index.collection);
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle DROP INDEX
+    fn handle_drop_index(&self, drop_index: &crate::dql_ast::DropIndexQuery) -> Result<QueryResult, DeedError> {
+        let collection = self.index_manager.get_index(&drop_index.index_name).map(|index| index.collection);
+        self.index_manager.drop_index(&drop_index.index_name)?;
+
+        if let Some(collection) = collection {
+            self.cache.write().unwrap().invalidate_collection(&collection);
+        }
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle DEFINE SCHEMA, replacing any schema the collection had
+    fn handle_define_schema(&self, schema: &Schema) -> Result<QueryResult, DeedError> {
+        for field in &schema.fields {
+            if let Some(default) = field.get_default() {
+                if !field.field_type.matches(default) {
+                    return Err(format!(
+                        "Default for '{}' doesn't match its type {}",
+                        field.name,
+                        field.field_type.name()
+                    ).into());
+                }
+            }
+        }
+
+        // Replace the previous schema's indexes with this one's
+        self.replace_schema_indexes(schema)?;
+
+        // The schema's TTL replaces whatever the collection had
+        let graph = self.graph.read().unwrap();
+        match schema.ttl {
+            Some(ttl) => graph.set_collection_ttl(&schema.collection, ttl),
+            None => {
+                graph.clear_collection_ttl(&schema.collection);
+            }
+        }
+
+        self.schemas.write().unwrap().register_schema(schema.clone());
+        Ok(QueryResult::default())
+    }
+
+    /// Handle DROP SCHEMA, making the collection schema-less
+    fn handle_drop_schema(&self, collection: &str) -> Result<QueryResult, DeedError> {
+        let schema = self
+            .schemas
+            .write()
+            .unwrap()
+            .drop_schema(collection)
+            .ok_or_else(|| format!("No schema defined for '{}'", collection))?;
+        if schema.ttl.is_some() {
+            self.graph.read().unwrap().clear_collection_ttl(collection);
+        }
+        self.drop_schema_indexes(collection)?;
+        Ok(QueryResult::default())
+    }
+
+    /// Handle SHOW COLLECTIONS: each collection and how many entities it holds
+    fn handle_show_collections(&self) -> Result<QueryResult, DeedError> {
+        let rows: Vec<HashMap<String, Value>> = self
+            .graph
+            .read()
+            .unwrap()
+            .list_collections()
+            .into_iter()
+            .map(|(collection, entities)| {
+                let mut row = HashMap::new();
+                row.insert("collection".to_string(), Value::String(collection));
+                row.insert("entities".to_string(), Value::Integer(entities as i64));
+                row
+            })
+            .collect();
+
+        let columns = ["collection", "entities"].map(String::from);
+
+        Ok(QueryResult {
+            columns: describe_columns(&columns, &rows),
+            rows,
+            ..Default::default()
+        })
+    }
+
+    /// Handle TRUNCATE, deleting every entity in a collection with its edges
+    fn handle_truncate(&self, collection: &str) -> Result<QueryResult, DeedError> {
+        let removed = self.truncate_collection(collection, false)?;
+
+        Ok(QueryResult {
+            rows_affected: removed,
+            ..Default::default()
+        })
+    }
+
+    /// Handle DROP COLLECTION: remove the collection with its entities, then
+    /// its schema and every index on it
+    fn handle_drop_collection(&self, collection: &str) -> Result<QueryResult, DeedError> {
+        let known = self.graph.read().unwrap().list_collections().iter().any(|(name, _)| name == collection)
+            || self.schemas.read().unwrap().get_schema(collection).is_some();
+        if !known {
+            return Err(format!("Collection '{}' not found", collection).into());
+        }
+
+        let removed = self.truncate_collection(collection, true)?;
+        self.schemas.write().unwrap().drop_schema(collection);
+        self.index_manager.drop_collection_indexes(collection);
+        self.cache.write().unwrap().invalidate_collection(collection);
+
+        Ok(QueryResult {
+            rows_affected: removed,
+            ..Default::default()
+        })
+    }
+
+    /// Delete every entity in a collection as a transaction of its own, and
+    /// the collection itself if `drop_collection`
+    ///
+    /// Refused inside an explicit transaction, whose rollback couldn't bring
+    /// the entities back, and while another open transaction has written to
+    /// the collection, whose changes would outlive it. Everything is logged
+    /// before anything is removed, so recovery and replicas don't resurrect
+    /// the entities: each deletion for TRUNCATE, one entry for DROP COLLECTION.
+    fn truncate_collection(&self, collection: &str, drop_collection: bool) -> Result<usize, DeedError> {
+        let statement = if drop_collection { "DROP COLLECTION" } else { "TRUNCATE" };
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err(DeedError::transaction(format!("{} can't run inside a transaction", statement)));
+        }
+        self.begin_implicit()?;
+        let txn_id = self.current_transaction.lock().unwrap().ok_or_else(|| DeedError::transaction("No active transaction"))?;
+
+        // Hold the graph exclusively so no reader sees a partly emptied
+        // collection, and no writer joins the transactions checked here
+        let graph = self.graph.write().unwrap();
+        let writers = self.transaction_manager.writers_of(collection, txn_id);
+        let logged = if !writers.is_empty() {
+            let writers: Vec<String> = writers.iter().map(|id| id.to_string()).collect();
+            Err(DeedError::transaction(format!(
+                "{} of '{}' conflicts with open transactions that wrote to it ({}); retry once they end",
+                statement,
+                collection,
+                writers.join(", ")
+            )))
+        } else if drop_collection {
+            self.log_to_wal(|wal| wal.log_drop_collection(txn_id, collection)).map(|()| {
+                let name = collection.to_string();
+                self.log_to_replication(move |replication| replication.log_drop_collection(name));
+            })
+        } else {
+            graph.iter_collection(collection).try_for_each(|entity| {
+                self.log_to_wal(|wal| wal.log_delete(txn_id, &entity))?;
+                let id = entity.id.as_u64();
+                self.log_to_replication(move |replication| replication.log_delete(id));
+                Ok::<(), DeedError>(())
+            })
+        };
+        if let Err(e) = logged {
+            drop(graph);
+            self.handle_rollback()?;
+            return Err(e);
+        }
+        let mut detached = HashSet::new();
+        for entity in graph.iter_collection(collection) {
+            self.record_change(|| {
+                ChangeEvent::new(ChangeKind::Delete, collection, entity.id, txn_id).with_before(entity.properties.clone())
+            });
+            for (_, edge_id) in neighbors(&graph, entity.id, &TraverseDirection::Both, &[]) {
+                if let Some(edge) = graph.get_edge(edge_id).filter(|_| detached.insert(edge_id)) {
+                    self.record_change(|| ChangeEvent::edge(ChangeKind::EdgeDelete, &edge, txn_id).with_before(edge.properties.clone()));
+                }
+            }
+        }
+
+        self.transaction_manager.track_writes(txn_id, collection, &graph.collection_ids(collection))?;
+        let removed = if drop_collection {
+            graph.drop_collection(collection).map(|removed| {
+                graph.clear_collection_ttl(collection);
+                removed.unwrap_or(0)
+            })
+        } else {
+            graph.truncate_collection(collection)
+        };
+        let removed = match removed {
+            Ok(removed) => removed,
+            Err(e) => {
+                drop(graph);
+                self.handle_rollback()?;
+                return Err(e.into());
+            }
+        };
+        self.index_manager.clear_collection(collection);
+        drop(graph);
+
+        self.cache.write().unwrap().invalidate_collection(collection);
+        self.handle_commit()?;
+        Ok(removed)
+    }
+
+    /// Build an index for each UNIQUE, PRIMARY KEY and INDEX field the
+    /// collection doesn't already have a suitable index for, then swap them
+    /// in for the indexes its previous schema created
+    ///
+    /// The previous indexes stay in service until the new ones are built,
+    /// and stay for good if one can't be.
+    fn replace_schema_indexes(&self, schema: &Schema) -> Result<(), DeedError> {
+        // Held throughout, so no commit changes the collection before the swap
+        let graph = self.graph.read().unwrap();
+        let prefix = schema_index_name(&schema.collection, "");
+        let (replaced, others): (Vec<String>, Vec<String>) =
+            self.index_manager.list_indexes().into_iter().partition(|name| name.starts_with(&prefix));
+        let others: Vec<_> = others.iter().filter_map(|name| self.index_manager.get_index(name)).collect();
+
+        let mut added = Vec::new();
+        for field in &schema.fields {
+            let unique = field.has_constraint(&Constraint::Unique) || field.has_constraint(&Constraint::PrimaryKey);
+            if !unique && !field.has_constraint(&Constraint::Index) {
+                continue;
+            }
+
+            let covered = others.iter().any(|index| {
+                index.collection == schema.collection
+                    && index.field == field.name
+                    && index.kind == IndexKind::BTree
+                    && (index.unique || !unique)
+            });
+            if covered {
+                continue;
+            }
+
+            let name = schema_index_name(&schema.collection, &field.name);
+            let index = BTreeIndex::new(name, schema.collection.clone(), field.name.clone(), unique);
+            added.push(self.index_manager.build_index(index, &graph).map_err(|e| {
+                format!("Can't enforce UNIQUE on '{}': {}", field.name, e)
+            })?);
+        }
+
+        self.index_manager.swap_indexes(&replaced, added)?;
+        self.cache.write().unwrap().invalidate_collection(&schema.collection);
+        Ok(())
+    }
+
+    /// Drop the indexes a collection's schema created
+    fn drop_schema_indexes(&self, collection: &str) -> Result<(), DeedError> {
+        let prefix = schema_index_name(collection, "");
+        for name in self.index_manager.list_indexes() {
+            if name.starts_with(&prefix) {
+                self.index_manager.drop_index(&name)?;
+            }
+        }
+
+        self.cache.write().unwrap().invalidate_collection(collection);
+        Ok(())
+    }
+
+    /// Handle FIREWALL admin commands (admin principals only)
+    fn handle_firewall(&self, firewall_query: &crate::dql_ast::FirewallQuery) -> Result<QueryResult, DeedError> {
+        let (firewall, principal) = self
+            .firewall
+            .as_ref()
+            .ok_or_else(|| "No firewall configured".to_string())?;
+
+        if !principal.is_admin() {
+            return Err(DeedError::permission("Permission denied: admin access required"));
+        }
+
+        match firewall_query {
+            crate::dql_ast::FirewallQuery::AddRule(rule) => firewall.add_rule(rule.clone())?,
+            crate::dql_ast::FirewallQuery::DropRule(name) => firewall.drop_rule(name)?,
+            crate::dql_ast::FirewallQuery::Learn { subject, duration } => {
+                let duration = parse_duration_setting(duration)?
+                    .ok_or_else(|| "Learning mode needs a duration".to_string())?;
+                firewall.learn(subject.clone(), duration)?;
+            }
+            crate::dql_ast::FirewallQuery::Enforce(subject) => firewall.enforce(subject)?,
+        }
+
+        Ok(QueryResult {
+            rows_affected: 1,
+            ..Default::default()
+        })
+    }
+
+    /// Handle ANALYZE: rebuild optimizer statistics from a sample
+    ///
+    /// Cached plans for the analyzed collections are dropped so the next
+    /// run is planned with the new statistics.
+    fn handle_analyze(&self, collection: Option<&str>) -> Result<QueryResult, DeedError> {
+        let reports = self.graph.read().unwrap().analyze(collection);
+
+        let mut cache = self.cache.write().unwrap();
+        for report in &reports {
+            cache.invalidate_collection(&report.collection);
+        }
+        drop(cache);
+
+        let rows: Vec<HashMap<String, Value>> = reports
+            .iter()
+            .map(|report| {
+                let mut row = HashMap::new();
+                row.insert("collection".to_string(), Value::String(report.collection.clone()));
+                row.insert("entities".to_string(), Value::Integer(report.entities as i64));
+                row.insert("sampled".to_string(), Value::Integer(report.sampled as i64));
+                row.insert("properties".to_string(), Value::Integer(report.properties as i64));
+                row
+            })
+            .collect();
+
+        let columns = ["collection", "entities", "sampled", "properties"].map(String::from);
+
+        Ok(QueryResult {
+            columns: describe_columns(&columns, &rows),
+            rows,
+            rows_affected: reports.len(),
+            ..Default::default()
+        })
+    }
+
+    /// Handle REINDEX: rebuild one index, or every index on a collection, online
+    fn handle_reindex(&self, reindex: &crate::dql_ast::ReindexQuery) -> Result<QueryResult, DeedError> {
+        let graph = self.graph.read().unwrap();
+        let reports = match reindex {
+            crate::dql_ast::ReindexQuery::Index(name) => vec![self.index_manager.rebuild_index(name, &graph)?],
+            crate::dql_ast::ReindexQuery::Collection(collection) => {
+                self.index_manager.rebuild_collection(collection, &graph)?
+            }
+        };
+
+        let rows: Vec<HashMap<String, Value>> = reports
+            .iter()
+            .map(|report| {
+                let mut row = HashMap::new();
+                row.insert("index".to_string(), Value::String(report.index_name.clone()));
+                row.insert("entries".to_string(), Value::Integer(report.entries as i64));
+                row.insert("catch_up_fixes".to_string(), Value::Integer(report.catch_up_fixes as i64));
+                row
+            })
+            .collect();
+
+        let columns = ["index", "entries", "catch_up_fixes"].map(String::from);
+
+        Ok(QueryResult {
+            columns: describe_columns(&columns, &rows),
+            rows,
+            rows_affected: reports.len(),
+            ..Default::default()
+        })
+    }
+
+    /// Handle EXPLAIN [ANALYZE]: the optimized plan, one row per operation
+    ///
+    /// The first row summarizes the plan. ANALYZE executes the statement
+    /// (a mutation is applied and committed as usual) and adds the rows each
+    /// operation produced and the time it took.
+    fn handle_explain(
+        &self,
+        explain: &crate::dql_ast::ExplainQuery,
+        query_str: &str,
+        max_staleness: Option<Duration>,
+        params: &HashMap<String, Value>,
+        control: &QueryControl,
+    ) -> Result<QueryResult, DeedError> {
+        let query = explain.query.as_ref();
+        let (plan, literals, cached) = self.plan_query(query)?;
+
+        let (executed, profile) = if explain.analyze {
+            let mut profile = PlanProfile::new(Some(cached), Instant::now());
+            let result =
+                self.run_query(query, &plan, &literals, query_str, max_staleness, params, control, &mut profile)?;
+            (Some(result), Some(profile))
+        } else {
+            (None, None)
+        };
+
+        // Without ANALYZE, show the plan as the master would run it
+        let shown = match &profile {
+            Some(profile) => profile.plan.clone(),
+            None => {
+                let mut bound = bind_plan(&plan, &literals, params, (self.clock)())?;
+                let reads_archive = match query {
+                    crate::dql_ast::Query::Select(q) => {
+                        self.archive.archived_count(&q.from.collection) > 0 && self.session.lock().unwrap().archive_reads
+                    }
+                    _ => false,
+                };
+                if !reads_archive && !self.transaction_manager.mvcc().has_versions() {
+                    bound.use_indexes(|collection, field, probe| self.index_for(collection, field, probe));
+                } else {
+                    bound.use_id_lookups();
+                }
+                self.check_firewall(query, &bound, query_str)?;
+                bound
+            }
+        };
+
+        let stats = self.graph.read().unwrap().stats();
+        let costs = shown.operation_costs(&stats);
+
+        let mut summary = HashMap::new();
+        summary.insert("step".to_string(), Value::Integer(0));
+        summary.insert("operation".to_string(), Value::String("Plan".to_string()));
+        summary.insert("details".to_string(), Value::String(format!("{} operations", shown.operations.len())));
+        summary.insert("estimated_cost".to_string(), Value::Float(costs.iter().sum::<f32>() as f64));
+        summary.insert("cached".to_string(), Value::Bool(cached));
+        summary.insert("pheromone_strength".to_string(), Value::Float(plan.pheromone_strength as f64));
+
+        let mut rows = vec![summary];
+        for (position, (operation, cost)) in shown.operations.iter().zip(&costs).enumerate() {
+            let mut row = HashMap::new();
+            row.insert("step".to_string(), Value::Integer(position as i64 + 1));
+            row.insert("operation".to_string(), Value::String(operation.name().to_string()));
+            row.insert("details".to_string(), Value::String(operation.details()));
+            row.insert("estimated_cost".to_string(), Value::Float(*cost as f64));
+            rows.push(row);
+        }
+
+        let mut columns: Vec<String> = ["step", "operation", "details", "estimated_cost", "cached", "pheromone_strength"]
+            .map(String::from)
+            .to_vec();
+
+        let Some(profile) = profile else {
+            return Ok(QueryResult {
+                columns: describe_columns(&columns, &rows),
+                rows,
+                ..Default::default()
+            });
+        };
+
+        let result = executed.unwrap_or_default();
+        let total: Duration = profile.operations.iter().map(|op| op.elapsed).sum();
+        rows[0].insert("rows".to_string(), Value::Integer(result.rows.len().max(result.rows_affected) as i64));
+        rows[0].insert("elapsed_ms".to_string(), Value::Float(total.as_secs_f64() * 1000.0));
+        for (row, measured) in rows[1..].iter_mut().zip(&profile.operations) {
+            row.insert("rows".to_string(), Value::Integer(measured.rows as i64));
+            row.insert("elapsed_ms".to_string(), Value::Float(measured.elapsed.as_secs_f64() * 1000.0));
+        }
+        columns.extend(["rows", "elapsed_ms"].map(String::from));
+
+        Ok(QueryResult {
+            columns: describe_columns(&columns, &rows),
+            rows,
+            rows_affected: result.rows_affected,
+            warnings: result.warnings,
+            ..Default::default()
+        })
+    }
+
+    /// Handle SET session option
+    fn handle_set(&self, set_query: &crate::dql_ast::SetQuery) -> Result<QueryResult, DeedError> {
+        let mut session = self.session.lock().unwrap();
+
+        match set_query.name.as_str() {
+            "max_staleness" => {
+                session.max_staleness = parse_duration_setting(&set_query.value)?;
+            }
+            "archive_reads" => {
+                session.archive_reads = parse_bool_setting(&set_query.value)?;
+            }
+            "strict_functions" => {
+                session.strict_functions = parse_bool_setting(&set_query.value)?;
+            }
+            "memory_budget" => {
+                session.memory_limit = parse_size_setting(&set_query.value)?;
+            }
+            other => return Err(format!("Unknown setting: {}", other).into()),
+        }
+
+        Ok(QueryResult::default())
+    }
+
+    /// Handle ARCHIVE: move matching entities from the graph to the archive tier
+    fn handle_archive(&self, archive_query: &crate::dql_ast::ArchiveQuery) -> Result<QueryResult, DeedError> {
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err(DeedError::transaction("ARCHIVE cannot run inside a transaction"));
+        }
+
+        let collection = &archive_query.collection;
+        let filter = archive_query
+            .where_clause
+            .as_ref()
+            .map(|w| FilterExpr::from_ast(&w.condition, collection));
+        let ctx = ExecutionContext::new();
+
+        let matched: Vec<EntityId> = {
+            let graph = self.graph.read().unwrap();
+            self.filter_entities(graph.scan_collection(collection), filter.as_ref(), &ctx)?
+                .iter()
+                .map(|entity| entity.id)
+                .collect()
+        };
+
+        // Hot copies leave through the log like any other write, so
+        // recovery and replicas don't bring them back
+        let mut count = 0;
+        self.write_directly(&matched, |graph, txn_id| {
+            let leaving: HashSet<EntityId> = matched.iter().copied().collect();
+            let mut inbound = Vec::new();
+            let archived: Vec<ArchivedEntity> = matched
+                .iter()
+                .filter_map(|&id| graph.get_entity(id))
+                .map(|entity| {
+                    let edge_ids: HashSet<EdgeId> = graph
+                        .get_outgoing_neighbors(entity.id, None)
+                        .into_iter()
+                        .chain(graph.get_incoming_neighbors(entity.id, None))
+                        .map(|(_, edge_id)| edge_id)
+                        .collect();
+                    let edges: Vec<Edge> = edge_ids.into_iter().filter_map(|id| graph.get_edge(id)).collect();
+                    inbound.extend(edges.iter().filter(|edge| !leaving.contains(&edge.source)).cloned());
+                    ArchivedEntity { edges, entity }
+                })
+                .collect();
+            let ids: Vec<EntityId> = archived.iter().map(|a| a.entity.id).collect();
+
+            // Write the segment before removing hot copies so a failure never loses rows
+            count = self.archive.archive(collection, archive_query.partition_by.as_deref(), archived)?;
+
+            for edge in &inbound {
+                self.log_to_wal(|wal| wal.log_delete_edge(txn_id, edge.id))?;
+                let id = edge.id.as_u64();
+                self.log_to_replication(move |replication| replication.log_delete_edge(id));
+            }
+            graph.apply_writes(Vec::new(), inbound.iter().map(|edge| (edge.id, None)).collect(), &[])?;
+            for edge in inbound {
+                self.record_change(|| {
+                    ChangeEvent::edge(ChangeKind::EdgeDelete, &edge, txn_id)
+                        .with_before(edge.properties.clone())
+                });
+            }
+            self.evict_in(graph, txn_id, &ids)
+        })?;
+
+        Ok(QueryResult {
+            rows_affected: count,
+            ..Default::default()
+        })
+    }
+
+    /// Handle COPY: export a collection to a file, or import one from it
+    fn handle_copy(&self, copy: &crate::dql_ast::CopyQuery) -> Result<QueryResult, DeedError> {
+        let path = Path::new(&copy.path);
+        let format = copy
+            .format
+            .or_else(|| DataFormat::from_path(path))
+            .ok_or_else(|| format!("Cannot tell the format of '{}'; add FORMAT JSONL or FORMAT CSV", copy.path))?;
+
+        let rows_affected = if copy.export {
+            let file = File::create(path).map_err(|e| format!("Failed to create '{}': {}", copy.path, e))?;
+            self.export_collection(&copy.collection, format, file)?
+        } else {
+            let file = File::open(path).map_err(|e| format!("Failed to open '{}': {}", copy.path, e))?;
+            self.import_collection(&copy.collection, format, file, &ImportOptions::default())?.imported
+        };
+
+        Ok(QueryResult {
+            rows_affected,
+            ..Default::default()
+        })
+    }
+
+    /// Handle UNARCHIVE: restore matching archived entities to the graph
+    fn handle_unarchive(&self, archive_query: &crate::dql_ast::ArchiveQuery) -> Result<QueryResult, DeedError> {
+        if self.current_transaction.lock().unwrap().is_some() {
+            return Err(DeedError::transaction("UNARCHIVE cannot run inside a transaction"));
+        }
+
+        let collection = &archive_query.collection;
+        let filter = archive_query
+            .where_clause
+            .as_ref()
+            .map(|w| FilterExpr::from_ast(&w.condition, collection));
+        let ctx = ExecutionContext::new();
+
+        // Evaluate the filter up front so an invalid one restores nothing
+        let archived = self.archive.scan(collection)?;
+        let matched: HashSet<EntityId> = self
+            .filter_entities(archived.iter().map(|a| a.entity.clone()).collect(), filter.as_ref(), &ctx)?
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        let restoring: Vec<ArchivedEntity> = archived.into_iter().filter(|a| matched.contains(&a.entity.id)).collect();
+        let ids: Vec<EntityId> = restoring.iter().map(|a| a.entity.id).collect();
+
+        // Store before dropping the archived copies so a failure never loses rows
+        self.write_directly(&ids, |graph, txn_id| {
+            let mut entities = Vec::with_capacity(restoring.len());
+            let mut edges = Vec::new();
+            let mut seen = HashSet::new();
+            for ArchivedEntity { entity, edges: archived_edges } in restoring {
+                entities.push(entity);
+                // Each edge comes back once, if both its ends are here again
+                edges.extend(archived_edges.into_iter().filter(|edge| {
+                    seen.insert(edge.id)
+                        && graph.get_edge(edge.id).is_none()
+                        && [edge.source, edge.target]
+                            .iter()
+                            .all(|id| matched.contains(id) || graph.get_entity(*id).is_some())
+                }));
+            }
+            self.store_in(graph, txn_id, entities, edges)
+        })?;
+        let count = self.archive.restore(collection, |e| matched.contains(&e.id))?.len();
+
+        Ok(QueryResult {
+            rows_affected: count,
+            ..Default::default()
+        })
+    }
+
+    /// Plan cache key: a hash of the normalized statement
+    ///
+    /// Keyword case and whitespace don't matter; identifiers do.
+    fn query_signature(&self, normalized: &crate::dql_ast::Query) -> Result<String, DeedError> {
+        let encoded = serde_json::to_vec(normalized)
+            .map_err(|e| format!("Failed to encode query signature: {}", e))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&encoded);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Per-connection session settings (changed with SET)
+#[derive(Debug, Clone)]
+struct SessionSettings {
+    /// Bound for reads served by replicas; `None` always reads the master
+    max_staleness: Option<Duration>,
+    /// Whether master reads include archived entities
+    archive_reads: bool,
+    /// Whether scalar functions fail on arguments of the wrong type instead
+    /// of returning NULL
+    strict_functions: bool,
+    /// Bytes GROUP BY and sorts may hold; `None` keeps the executor's budget
+    memory_limit: Option<usize>,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            max_staleness: None,
+            archive_reads: true,
+            strict_functions: false,
+            memory_limit: None,
+        }
+    }
+}
+
+/// Hop limit of SHORTEST_PATH when the query gives no MAX
+const DEFAULT_PATH_DEPTH: usize = 6;
+
+/// Evaluate SHORTEST_PATH between the entities bound in a joined row
+///
+/// A missing path is Null, as is a path longer than the depth limit.
+fn shortest_path(graph: &Graph, call: &PathCall, row: &HashMap<String, Entity>) -> Result<Value, String> {
+    let entity = |binding: &String| {
+        row.get(binding)
+            .map(|e| e.id)
+            .ok_or_else(|| format!("Binding not found: {}", binding))
+    };
+    let (source, target) = (entity(&call.source)?, entity(&call.target)?);
+    let max_depth = call.max_depth.unwrap_or(DEFAULT_PATH_DEPTH);
+
+    let value = match graph.shortest_path(source, target, call.edge_type.as_deref(), max_depth) {
+        None => Value::Null,
+        Some(path) if call.length_only => Value::Integer(path.len() as i64 - 1),
+        Some(path) => Value::Path(path.iter().map(|id| id.as_u64()).collect()),
+    };
+    Ok(value)
+}
+
+/// Name of the index a schema creates for a field; the `:` keeps it apart
+/// from indexes created with CREATE INDEX
+fn schema_index_name(collection: &str, field: &str) -> String {
+    format!("schema:{}.{}", collection, field)
+}
+
+/// Parse an on/off setting
+fn parse_bool_setting(value: &Literal) -> Result<bool, String> {
+    match value {
+        Literal::Bool(b) => Ok(*b),
+        Literal::Integer(n) => Ok(*n != 0),
+        Literal::String(s) => match s.to_lowercase().as_str() {
+            "on" | "true" => Ok(true),
+            "off" | "false" => Ok(false),
+            other => Err(format!("Invalid boolean setting: '{}'", other)),
+        },
+        other => Err(format!("Invalid boolean setting: {:?}", other)),
+    }
+}
+
+/// Whether a replica can serve a statement: a SELECT of user collections
+fn is_replica_read(query: &crate::dql_ast::Query) -> bool {
+    matches!(
+        query,
+        crate::dql_ast::Query::Select(q) if q.from.collection != AUDIT_COLLECTION && !is_catalog_collection(&q.from.collection)
+    )
+}
+
+/// Whether a statement is planned, and checked against the firewall with its plan
+fn is_planned(query: &crate::dql_ast::Query) -> bool {
+    use crate::dql_ast::Query;
+    matches!(
+        query,
+        Query::Select(_)
+            | Query::Insert(_)
+            | Query::Update(_)
+            | Query::Delete(_)
+            | Query::Create(_)
+            | Query::UpdateEdge(_)
+            | Query::DeleteEdge(_)
+            | Query::Explain(_)
+    )
+}
+
+/// Firewall class of a statement; EXPLAIN takes that of the statement it explains
+fn statement_class(query: &crate::dql_ast::Query) -> StatementClass {
+    use crate::dql_ast::Query;
+    match query {
+        Query::Select(_) | Query::ShowCollections => StatementClass::Select,
+        Query::Copy(copy) if copy.export => StatementClass::Select,
+        Query::Insert(_) | Query::Copy(_) | Query::Unarchive(_) => StatementClass::Insert,
+        Query::Update(_) | Query::UpdateEdge(_) => StatementClass::Update,
+        Query::Delete(_) | Query::DeleteEdge(_) | Query::Archive(_) => StatementClass::Delete,
+        Query::Create(_) | Query::CreateIndex(_) | Query::DefineSchema(_) => StatementClass::Create,
+        Query::DropIndex(_) | Query::DropSchema(_) | Query::DropCollection(_) | Query::Truncate(_) => {
+            StatementClass::Drop
+        }
+        Query::Set(_) | Query::Analyze(_) | Query::Reindex(_) | Query::Firewall(_) => StatementClass::Admin,
+        Query::Begin(_)
+        | Query::Commit
+        | Query::Rollback
+        | Query::Savepoint(_)
+        | Query::RollbackToSavepoint(_)
+        | Query::ReleaseSavepoint(_) => StatementClass::Transaction,
+        Query::Explain(explain) => statement_class(&explain.query),
+    }
+}
+
+/// Parse a byte size: a number of bytes, or a string with a KB, MB or GB
+/// unit ('64MB'); NULL or 'off' for none
+fn parse_size_setting(value: &Literal) -> Result<Option<usize>, String> {
+    let text = match value {
+        Literal::Null => return Ok(None),
+        Literal::Integer(n) if *n > 0 => return Ok(Some(*n as usize)),
+        Literal::String(s) => s.trim().to_lowercase(),
+        other => return Err(format!("Invalid size: {:?}", other)),
+    };
+
+    if text == "off" || text == "none" {
+        return Ok(None);
+    }
+
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: usize = number.parse().map_err(|_| format!("Invalid size: '{}'", text))?;
+
+    let multiplier: usize = match unit.trim() {
+        "" | "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err(format!("Invalid size unit in '{}'", text)),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err(format!("Invalid size: '{}'", text)),
+        Some(bytes) => Ok(Some(bytes)),
+        None => Err(format!("Size too large: '{}'", text)),
+    }
+}
+
+/// Parse a duration setting such as '30s', '500ms', '2m', '1h', or 'off'
+///
+/// Bare integers are taken as seconds.
+fn parse_duration_setting(value: &Literal) -> Result<Option<Duration>, String> {
+    let text = match value {
+        Literal::Null => return Ok(None),
+        Literal::Integer(n) if *n >= 0 => return Ok(Some(Duration::from_secs(*n as u64))),
+        Literal::String(s) => s.trim().to_lowercase(),
+        other => return Err(format!("Invalid duration: {:?}", other)),
+    };
+
+    if text == "off" || text == "none" {
+        return Ok(None);
+    }
+
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: '{}'", text))?;
+
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        "h" => Duration::from_secs(number * 3600),
+        _ => return Err(format!("Invalid duration unit in '{}'", text)),
+    };
+
+    Ok(Some(duration))
+}
+
+/// Neighbors of an archived entity through the edges archived with it
+fn archived_neighbors(
+    edges: &[Edge],
+    entity_id: EntityId,
+    direction: &TraverseDirection,
+    edge_types: &[String],
+) -> Vec<(EntityId, EdgeId)> {
+    let outgoing = matches!(direction, TraverseDirection::Outgoing | TraverseDirection::Both);
+    let incoming = matches!(direction, TraverseDirection::Incoming | TraverseDirection::Both);
+    edges
+        .iter()
+        .filter(|edge| edge_types.is_empty() || edge_types.contains(&edge.edge_type))
+        .flat_map(|edge| {
+            let out = (outgoing && edge.source == entity_id).then_some((edge.target, edge.id));
+            let into = (incoming && edge.target == entity_id).then_some((edge.source, edge.id));
+            out.into_iter().chain(into)
+        })
+        .collect()
+}
+
+/// Neighbors of an entity in a traversal direction, over edges of any of
+/// `edge_types` (any type when empty)
+fn neighbors(
+    graph: &Graph,
+    entity_id: EntityId,
+    direction: &TraverseDirection,
+    edge_types: &[String],
+) -> Vec<(EntityId, EdgeId)> {
+    let along = |edge_type: Option<&str>| match direction {
+        TraverseDirection::Outgoing => graph.get_outgoing_neighbors(entity_id, edge_type),
+        TraverseDirection::Incoming => graph.get_incoming_neighbors(entity_id, edge_type),
+        TraverseDirection::Both => {
+            let mut all = graph.get_outgoing_neighbors(entity_id, edge_type);
+            all.extend(graph.get_incoming_neighbors(entity_id, edge_type));
+            all
+        }
+    };
+
+    match edge_types {
+        [] => along(None),
+        [edge_type] => along(Some(edge_type)),
+        _ => edge_types.iter().flat_map(|edge_type| along(Some(edge_type))).collect(),
+    }
+}
+
+/// Names of the properties a scan copies, when its plan limits them
+fn property_names(properties: &Option<Vec<String>>) -> Option<Vec<&str>> {
+    properties
+        .as_ref()
+        .map(|names| names.iter().map(String::as_str).collect())
+}
+
+/// Copy a scanned entity out of storage, with only `properties` when given
+fn copy_projected(graph: &Graph, entity: &Entity, properties: Option<&[&str]>) -> Entity {
+    match properties {
+        Some(properties) => graph.clone_entity_projected(entity, properties),
+        None => graph.clone_entity(entity),
+    }
+}
+
+/// An edge bound in a joined row, so its properties resolve like an entity's
+///
+/// Its pheromone strength is exposed under `PHEROMONE_PROPERTY`.
+fn edge_entity(edge: &Edge) -> Entity {
+    let mut properties = edge.properties.clone();
+    properties.insert(
+        PHEROMONE_PROPERTY.to_string(),
+        PropertyValue::Float(edge.pheromone.strength() as f64),
+    );
+    Entity::new(EntityId::new(edge.id.as_u64()), edge.edge_type.clone(), properties)
+}
+
+/// Set a projection's rows and columns as the result, dropping repeated rows
+/// for DISTINCT
+///
+/// Duplicates are found in one pass with a set of row keys (see
+/// [`Value::distinct_key`]), keeping the first of each.
+fn store_projection(
+    ctx: &mut ExecutionContext,
+    fields: &[Projection],
+    expanded: &[BTreeSet<String>],
+    mut rows: Vec<HashMap<String, Value>>,
+    distinct: bool,
+) {
+    ctx.columns = projected_columns(fields, expanded, &mut rows);
+
+    if distinct {
+        let mut seen = HashSet::new();
+        rows.retain(|row| {
+            let key: Vec<DistinctKey> = ctx
+                .columns
+                .iter()
+                .map(|column| row.get(column).map_or(DistinctKey::Null, Value::distinct_key))
+                .collect();
+            seen.insert(key)
+        });
+    }
+
+    ctx.result_rows = rows;
+}
+
+/// Result columns of a projection, in SELECT order
+///
+/// Each wildcard contributes the union of the columns it wrote across the
+/// rows, its `@id` first and the rest by name; rows an entity gave no value
+/// for such a column get NULL.
+fn projected_columns(
+    fields: &[Projection],
+    expanded: &[BTreeSet<String>],
+    rows: &mut [HashMap<String, Value>],
+) -> Vec<String> {
+    let mut columns: Vec<String> = Vec::new();
+    for (field, wildcard_columns) in fields.iter().zip(expanded) {
+        match field {
+            Projection::Field(field) => columns.push(field.alias.clone()),
+            Projection::Wildcard { binding, prefixed } => {
+                let id = if *prefixed { format!("{}.{}", binding, ID_PROPERTY) } else { ID_PROPERTY.to_string() };
+                let names = wildcard_columns.iter().filter(|name| **name != id);
+                for name in std::iter::once(&id).chain(names) {
+                    if !columns.contains(name) {
+                        columns.push(name.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    if fields.iter().any(|field| matches!(field, Projection::Wildcard { .. })) {
+        for row in rows.iter_mut() {
+            for column in &columns {
+                row.entry(column.clone()).or_insert(Value::Null);
+            }
+        }
+    }
+
+    columns
+}
+
+/// Position of the plan's last scan or traversal, and how many entities it must produce
+///
+/// Only known when nothing but PROJECT/OFFSET/LIMIT follows that operation;
+/// filters, grouping or sorting need every candidate. A scan only stops
+/// early when it is the plan's sole source of rows.
+fn row_budget(plan: &QueryPlan) -> Option<(usize, usize)> {
+    let last_source = plan
+        .operations
+        .iter()
+        .rposition(|op| matches!(op, Operation::Scan { .. } | Operation::Traverse { .. }))?;
+
+    let scans = plan
+        .operations
+        .iter()
+        .filter(|op| matches!(op, Operation::Scan { .. } | Operation::IndexLookup { .. }))
+        .count();
+    if matches!(plan.operations[last_source], Operation::Scan { .. }) && scans > 1 {
+        return None;
+    }
+
+    let mut limit = None;
+    let mut skipped = 0usize;
+    for operation in &plan.operations[last_source + 1..] {
+        match operation {
+            // DISTINCT may drop any number of rows
+            Operation::Project { distinct: false, .. } => {}
+            Operation::Skip { count } => skipped = skipped.saturating_add(*count),
+            Operation::Limit { count } => limit = Some(*count),
+            _ => return None,
+        }
+    }
+
+    limit.map(|count| (last_source, count.saturating_add(skipped)))
+}
+
+/// Order of two result rows under ORDER BY keys, compared left to right
+fn compare_rows(fields: &[SortField], a: &HashMap<String, Value>, b: &HashMap<String, Value>) -> std::cmp::Ordering {
+    for field in fields {
+        let a_val = a.get(&field.column).unwrap_or(&Value::Null);
+        let b_val = b.get(&field.column).unwrap_or(&Value::Null);
+
+        let cmp = a_val.sort_cmp(b_val);
+        if cmp != std::cmp::Ordering::Equal {
+            return if field.ascending { cmp } else { cmp.reverse() };
+        }
+    }
+    std::cmp::Ordering::Equal
+}
+
+/// A row's place in a top-k selection; ties rank by input position, as in
+/// a stable sort
+struct RankedRow<'a> {
+    fields: &'a [SortField],
+    row: &'a HashMap<String, Value>,
+    position: usize,
+}
+
+impl Ord for RankedRow<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        compare_rows(self.fields, self.row, other.row).then(self.position.cmp(&other.position))
+    }
+}
+
+impl PartialOrd for RankedRow<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedRow<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for RankedRow<'_> {}
+
+/// Rows in ORDER BY order, sorted within a memory budget
+///
+/// Rows past the budget are sorted in runs that each fit it, written to
+/// spill files and merged back lazily as the rows are read. Ties keep
+/// their input order, as in a stable sort.
+fn sort_rows<'a>(
+    rows: impl IntoIterator<Item = HashMap<String, Value>>,
+    fields: &'a [SortField],
+    budget: &MemoryBudget,
+) -> Result<(impl Iterator<Item = RowResult> + 'a, MemoryUsage), String> {
+    let order = move |a: &HashMap<String, Value>, b: &HashMap<String, Value>| compare_rows(fields, a, b);
+    let mut sorter = ExternalSort::new(budget, order);
+    for row in rows {
+        let size = row_size(&row);
+        sorter.push(row, size)?;
+    }
+    sorter.finish()
+}
+
+/// The first `k` rows of the ORDER BY order, in that order
+///
+/// Keeps a heap of the best `k` seen so far, so the work is N log K rather
+/// than a full sort's N log N. Picks the same rows a stable sort would.
+fn top_k(rows: Vec<HashMap<String, Value>>, fields: &[SortField], k: usize) -> Vec<HashMap<String, Value>> {
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut heap = std::collections::BinaryHeap::with_capacity(k + 1);
+    for (position, row) in rows.iter().enumerate() {
+        let ranked = RankedRow { fields, row, position };
+        if heap.len() < k {
+            heap.push(ranked);
+        } else if heap.peek().is_some_and(|worst| ranked < *worst) {
+            heap.pop();
+            heap.push(ranked);
+        }
+    }
+
+    let order: Vec<usize> = heap.into_sorted_vec().into_iter().map(|ranked| ranked.position).collect();
+    let mut rows: Vec<Option<HashMap<String, Value>>> = rows.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|position| rows[position].take()).collect()
+}
+
+/// Execution context - holds intermediate results
+struct ExecutionContext {
+    bindings: HashMap<String, Vec<Entity>>,
+    result_rows: Vec<HashMap<String, Value>>,
+    /// Names of the result columns, in output order
+    columns: Vec<String>,
+    last_inserted_id: Option<EntityId>,
+    deleted_count: usize,
+    rows_affected: usize,
+    /// Rows the current scan or traversal needs to produce when only LIMIT/OFFSET follow it
+    row_budget: Option<usize>,
+    /// Joined (alias -> entity) rows produced by traversals
+    joined_rows: Option<Vec<HashMap<String, Entity>>>,
+    /// Aggregate columns of the grouped result rows, once GROUP BY has run
+    aggregates: Option<Vec<AggregateOp>>,
+    /// Versions the statement reads; `None` reads the graph as is
+    read_view: Option<ReadView>,
+    /// When the statement began, in milliseconds since the Unix epoch;
+    /// entities expired by then are invisible to it
+    now: i64,
+    /// Per-operation measurements (for EXPLAIN ANALYZE)
+    profile: Vec<OperationProfile>,
+    /// Entities examined by scans, index lookups and traversals
+    rows_scanned: usize,
+    /// Cancellation and timeout of the statement
+    control: QueryControl,
+    /// Whether a scalar function's type error fails the statement
+    strict_functions: bool,
+    /// Results of the plan's subqueries, by id
+    subqueries: HashMap<usize, SubqueryResult>,
+    /// Edge counts of the graph the statement reads, for degree functions
+    degrees: Option<Degrees>,
+    /// Memory GROUP BY and sorts may hold before spilling
+    memory_budget: MemoryBudget,
+    /// Memory they held, and what they spilled
+    memory: MemoryUsage,
+    /// Collection whose archived entities scans read along with the graph
+    archived_collection: Option<String>,
+    /// Archived entities scans kept, and their edges, for traversals
+    archived: HashMap<EntityId, Entity>,
+    archived_edges: HashMap<EntityId, Vec<Edge>>,
+}
+
+impl ExecutionContext {
+    fn new() -> Self {
+        ExecutionContext {
+            bindings: HashMap::new(),
+            result_rows: Vec::new(),
+            columns: Vec::new(),
+            last_inserted_id: None,
+            deleted_count: 0,
+            rows_affected: 0,
+            row_budget: None,
+            joined_rows: None,
+            aggregates: None,
+            read_view: None,
+            now: 0,
+            profile: Vec::new(),
+            rows_scanned: 0,
+            control: QueryControl::default(),
+            strict_functions: false,
+            subqueries: HashMap::new(),
+            degrees: None,
+            memory_budget: MemoryBudget::default(),
+            memory: MemoryUsage::default(),
+            archived_collection: None,
+            archived: HashMap::new(),
+            archived_edges: HashMap::new(),
+        }
+    }
+
+    /// Materialized result of the subquery with this id
+    fn subquery(&self, id: usize) -> Result<&SubqueryResult, String> {
+        self.subqueries
+            .get(&id)
+            .ok_or_else(|| format!("Subquery #{} has not run", id))
+    }
+
+    /// Rows an operation left for the next one
+    fn row_count(&self, operation: &Operation) -> usize {
+        match operation {
+            Operation::Scan { alias, .. } | Operation::IndexLookup { alias, .. } => {
+                self.bindings.get(alias).map_or(0, Vec::len)
+            }
+            Operation::InsertEntity { .. }
+            | Operation::UpdateEntities { .. }
+            | Operation::DeleteEntities { .. }
+            | Operation::CreateEdge { .. }
+            | Operation::UpdateEdges { .. }
+            | Operation::DeleteEdges { .. } => self.rows_affected.max(self.deleted_count),
+            Operation::Subquery { id, .. } => self.subqueries.get(id).map_or(0, |result| result.values.len()),
+            _ if !self.columns.is_empty() || self.aggregates.is_some() => self.result_rows.len(),
+            _ => match &self.joined_rows {
+                Some(rows) => rows.len(),
+                None => match operation {
+                    Operation::Filter { binding, .. } => self.bindings.get(binding).map_or(0, Vec::len),
+                    _ => self.bindings.values().map(Vec::len).max().unwrap_or(0),
+                },
+            },
+        }
+    }
+
+    fn into_result(self) -> QueryResult {
+        QueryResult {
+            columns: describe_columns(&self.columns, &self.result_rows),
+            rows: self.result_rows,
+            rows_affected: self.rows_affected.max(self.deleted_count),
+            staleness_ms: None,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// A subquery's result, kept for the conditions that read it
+struct SubqueryResult {
+    /// First column of each row
+    values: Vec<Value>,
+    /// Keys of the non-null values (see [`Value::distinct_key`]), for IN
+    keys: HashSet<DistinctKey>,
+}
+
+impl SubqueryResult {
+    fn new(columns: &[String], rows: Vec<HashMap<String, Value>>) -> Self {
+        let values: Vec<Value> = rows
+            .into_iter()
+            .map(|mut row| columns.first().and_then(|column| row.remove(column)).unwrap_or(Value::Null))
+            .collect();
+        let keys = values
+            .iter()
+            .filter(|value| **value != Value::Null)
+            .map(Value::distinct_key)
+            .collect();
+
+        SubqueryResult { values, keys }
+    }
+
+    /// The value of a scalar subquery: its only row's, or NULL without rows
+    fn scalar(&self) -> Result<Value, String> {
+        match self.values.as_slice() {
+            [] => Ok(Value::Null),
+            [value] => Ok(value.clone()),
+            values => Err(format!("Scalar subquery returned {} rows", values.len())),
+        }
+    }
+}
+
+/// Running total of the numbers among an aggregate's values
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct NumericSum {
+    /// Exact total of the integers; an i128 can't overflow on any realistic input
+    integers: i128,
+    /// Total of the floats, if there were any
+    floats: Option<f64>,
+    count: usize,
+}
+
+impl NumericSum {
+    /// Add a value; values that aren't numbers are ignored
+    fn add(&mut self, value: &PropertyValue) {
+        match value {
+            PropertyValue::Int(n) => self.integers += *n as i128,
+            PropertyValue::Float(f) => *self.floats.get_or_insert(0.0) += f,
+            _ => return,
+        }
+        self.count += 1;
+    }
+
+    /// Add the numbers another total was taken over
+    fn merge(&mut self, other: &NumericSum) {
+        self.integers += other.integers;
+        if let Some(floats) = other.floats {
+            *self.floats.get_or_insert(0.0) += floats;
+        }
+        self.count += other.count;
+    }
+}
+
+/// Running state of one aggregate over a group's values
+///
+/// See [`DQLExecutor::accumulate`] and [`DQLExecutor::finish_aggregate`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Accumulator {
+    /// Values aggregated, NULLs aside
+    count: usize,
+    sum: NumericSum,
+    /// MIN or MAX so far
+    best: Option<PropertyValue>,
+    /// One of each of a DISTINCT aggregate's values, aggregated once the group is complete
+    values: Vec<PropertyValue>,
+    /// Keys of `values`; rebuilt when a spilled state is merged back
+    #[serde(skip)]
+    seen: HashSet<DistinctKey>,
+}
+
+impl Accumulator {
+    /// Estimated bytes the state occupies
+    fn size(&self) -> usize {
+        std::mem::size_of::<Accumulator>()
+            + self.best.as_ref().map_or(0, property_value_size)
+            + self.values.iter().map(property_value_size).sum::<usize>()
+            + self.seen.iter().map(distinct_key_size).sum::<usize>()
+    }
+}
+
+/// Identity of a GROUP BY group: the canonical key of each grouping value
+///
+/// Values fall into one group when their keys match (see
+/// [`Value::distinct_key`]): `1` and `1.0` share a group, a float that isn't
+/// exactly an integer never joins one, NULL is apart from the string
+/// `'NULL'`, and all NaNs form a single group.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct GroupKey(Vec<DistinctKey>);
+
+impl GroupKey {
+    fn new(values: &[Value]) -> Self {
+        GroupKey(values.iter().map(Value::distinct_key).collect())
+    }
+}
+
+/// One GROUP BY group, aggregated as far as its entities have been read
+#[derive(Debug, Serialize, Deserialize)]
+struct Group {
+    /// Grouping values of the group's first entity
+    values: Vec<Value>,
+    /// State of each of the GROUP BY's aggregates, in order
+    accumulators: Vec<Accumulator>,
+    /// Position of the group's first entity among the entities grouped
+    first_seen: usize,
+}
+
+impl Group {
+    fn new(values: Vec<Value>, aggregates: usize, first_seen: usize) -> Self {
+        Group {
+            values,
+            accumulators: vec![Accumulator::default(); aggregates],
+            first_seen,
+        }
+    }
+
+    /// Estimated bytes the group occupies in a [`GroupTable`]
+    fn size(&self) -> usize {
+        std::mem::size_of::<Group>()
+            + std::mem::size_of::<GroupKey>()
+            + self.values.iter().map(|v| value_size(v) + std::mem::size_of::<DistinctKey>()).sum::<usize>()
+            + self.accumulators.iter().map(Accumulator::size).sum::<usize>()
+    }
+
+    /// Fold in another partial state of the same group, returning the bytes
+    /// the group grew by
+    fn merge(&mut self, executor: &DQLExecutor, aggregates: &[AggregateOp], other: Group) -> usize {
+        if other.first_seen < self.first_seen {
+            self.first_seen = other.first_seen;
+            self.values = other.values;
+        }
+        aggregates
+            .iter()
+            .zip(&mut self.accumulators)
+            .zip(other.accumulators)
+            .map(|((op, accumulator), other)| executor.merge_accumulators(op, accumulator, other))
+            .sum()
+    }
+
+    /// Output order: by grouping values as ORDER BY sorts them, then by first
+    /// appearance for keys that compare equal without being equal (e.g. a
+    /// large integer and the float nearest to it)
+    fn output_order(a: &Group, b: &Group) -> std::cmp::Ordering {
+        a.values
+            .iter()
+            .zip(&b.values)
+            .map(|(x, y)| x.sort_cmp(y))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.first_seen.cmp(&b.first_seen))
+    }
+}
+
+/// Partitions spilled GROUP BY groups are spread over, each merged on its own
+const SPILL_PARTITIONS: usize = 64;
+
+/// [`Group::output_order`], as the comparator spilled groups are merged with
+type GroupOrder = fn(&Group, &Group) -> std::cmp::Ordering;
+
+/// GROUP BY's finished groups, in output order
+type SortedGroups = Sorted<Group, GroupOrder>;
+
+/// GROUP BY's groups, aggregated an entity at a time within a memory budget
+///
+/// Groups keep running aggregates rather than their entities. When they
+/// outgrow the budget they're written to spill partitions by key and
+/// dropped, and gathering starts over; once every entity is in, each
+/// partition's partial groups are read back and merged. A partition whose
+/// groups don't fit the budget fails the statement.
+struct GroupTable {
+    groups: HashMap<GroupKey, Group>,
+    /// Estimated bytes `groups` occupy
+    held: usize,
+    budget: MemoryBudget,
+    /// Spilled partial groups, by hash of their key; none until the first spill
+    partitions: Vec<SpillFile<Group>>,
+    usage: MemoryUsage,
+    /// Entities grouped so far
+    seen: usize,
+}
+
+impl GroupTable {
+    fn new(budget: MemoryBudget) -> Self {
+        GroupTable {
+            groups: HashMap::new(),
+            held: 0,
+            budget,
+            partitions: Vec::new(),
+            usage: MemoryUsage::default(),
+            seen: 0,
+        }
+    }
+
+    /// Aggregate an entity, given its grouping values and the argument of each aggregate
+    fn add(
+        &mut self,
+        executor: &DQLExecutor,
+        aggregates: &[AggregateOp],
+        values: Vec<Value>,
+        arguments: Vec<PropertyValue>,
+    ) -> Result<(), String> {
+        let first_seen = self.seen;
+        self.seen += 1;
+
+        let group = match self.groups.entry(GroupKey::new(&values)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let group = Group::new(values, aggregates.len(), first_seen);
+                self.held += group.size();
+                entry.insert(group)
             }
+        };
+        for ((op, accumulator), argument) in aggregates.iter().zip(&mut group.accumulators).zip(arguments) {
+            self.held += executor.accumulate(op, accumulator, argument);
         }
-    }
 
-    /// Extract field name from expression
-    fn extract_field_name(&self, expr: &FilterExpr) -> String {
-        match expr {
-            FilterExpr::Property { binding: _, property } => property.clone(),
-            _ => "field".to_string(),
+        self.usage.hold(self.held);
+        if self.held > self.budget.limit {
+            self.spill()?;
         }
+        Ok(())
     }
 
-    /// Convert Value to String for grouping
-    fn value_to_string(&self, value: &Value) -> String {
-        match value {
-            Value::Null => "NULL".to_string(),
-            Value::Bool(b) => b.to_string(),
-            Value::Integer(n) => n.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::String(s) => s.clone(),
-            Value::EntityId(id) => format!("entity_{}", id),
-            Value::EdgeId(id) => format!("edge_{}", id),
+    /// Write the groups held out to their partitions, and drop them
+    fn spill(&mut self) -> Result<(), String> {
+        if self.partitions.is_empty() {
+            for _ in 0..SPILL_PARTITIONS {
+                self.partitions.push(SpillFile::create(&self.budget.spill_dir)?);
+            }
+        }
+        for (key, group) in self.groups.drain() {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            key.hash(&mut hasher);
+            let partition = (hasher.finish() % SPILL_PARTITIONS as u64) as usize;
+            self.usage.spilled_bytes += self.partitions[partition].write(&group)?;
         }
+        self.held = 0;
+        Ok(())
     }
 
-    /// Evaluate HAVING condition on aggregated result row
-    fn evaluate_having_condition(&self, condition: &FilterExpr, row: &HashMap<String, Value>) -> bool {
-        match condition {
-            FilterExpr::And(l, r) => {
-                self.evaluate_having_condition(l, row) && self.evaluate_having_condition(r, row)
-            }
-            FilterExpr::Or(l, r) => {
-                self.evaluate_having_condition(l, row) || self.evaluate_having_condition(r, row)
-            }
-            FilterExpr::Not(e) => !self.evaluate_having_condition(e, row),
+    /// Every group in [`Group::output_order`], and the memory gathering them took
+    ///
+    /// Merged partitions are sorted and written back out as runs, which
+    /// are read back a group at a time.
+    fn finish(mut self, executor: &DQLExecutor, aggregates: &[AggregateOp]) -> Result<(SortedGroups, MemoryUsage), String> {
+        if self.partitions.is_empty() {
+            let mut groups: Vec<Group> = self.groups.into_values().collect();
+            groups.sort_by(Group::output_order);
+            return Ok((Sorted::Memory(groups.into_iter()), self.usage));
+        }
 
-            FilterExpr::GreaterThan(l, r) => {
-                let lv = self.evaluate_having_expr(l, row);
-                let rv = self.evaluate_having_expr(r, row);
-                matches!(self.compare_values(&lv, &rv), std::cmp::Ordering::Greater)
-            }
-            FilterExpr::GreaterThanEq(l, r) => {
-                let lv = self.evaluate_having_expr(l, row);
-                let rv = self.evaluate_having_expr(r, row);
-                matches!(self.compare_values(&lv, &rv), std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)
-            }
-            FilterExpr::LessThan(l, r) => {
-                let lv = self.evaluate_having_expr(l, row);
-                let rv = self.evaluate_having_expr(r, row);
-                matches!(self.compare_values(&lv, &rv), std::cmp::Ordering::Less)
-            }
-            FilterExpr::Equal(l, r) => {
-                let lv = self.evaluate_having_expr(l, row);
-                let rv = self.evaluate_having_expr(r, row);
-                matches!(self.compare_values(&lv, &rv), std::cmp::Ordering::Equal)
+        self.spill()?;
+        let mut sorter = ExternalSort::new(&self.budget, Group::output_order as GroupOrder);
+        for partition in std::mem::take(&mut self.partitions) {
+            let mut groups: HashMap<GroupKey, Group> = HashMap::new();
+            let mut held = 0;
+
+            for spilled in partition.read()? {
+                let spilled = spilled?;
+                let group = match groups.entry(GroupKey::new(&spilled.values)) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let group = Group::new(spilled.values.clone(), aggregates.len(), spilled.first_seen);
+                        held += group.size();
+                        entry.insert(group)
+                    }
+                };
+                held += group.merge(executor, aggregates, spilled);
+
+                self.usage.hold(held);
+                if held > self.budget.limit {
+                    return Err(self.budget.exceeded());
+                }
             }
+            sorter.push_run(groups.into_values().collect())?;
+        }
+
+        let (sorted, usage) = sorter.finish()?;
+        self.usage.spilled_bytes += usage.spilled_bytes;
+        Ok((sorted, self.usage))
+    }
+}
+
+/// Items a long loop processes between cancellation checks
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+/// How long shutdown waits for cancelled statements to stop
+const CANCEL_GRACE: Duration = Duration::from_secs(1);
+
+/// Cancellation flag, deadline and memory limit of a running statement
+#[derive(Debug, Clone, Default)]
+struct QueryControl {
+    cancelled: Arc<AtomicBool>,
+    /// When the statement times out, and the timeout it was given
+    deadline: Option<(Instant, Duration)>,
+    /// Set when the executor's shutdown stops waiting for the statement
+    aborted: Option<Arc<AtomicBool>>,
+    /// Bytes GROUP BY and sorts may hold, overriding the session's and executor's budget
+    memory_limit: Option<usize>,
+    /// Keep a read on the master, whatever the read routing
+    primary: bool,
+}
 
-            _ => true,
+impl QueryControl {
+    /// A control failing the statement after `timeout`, if one is given
+    fn timed(timeout: Option<Duration>) -> QueryControl {
+        QueryControl {
+            deadline: timeout.map(|timeout| (Instant::now() + timeout, timeout)),
+            ..Default::default()
         }
     }
 
-    /// Evaluate expression in HAVING context (on result row)
-    fn evaluate_having_expr(&self, expr: &FilterExpr, row: &HashMap<String, Value>) -> Value {
-        match expr {
-            FilterExpr::Aggregate { function: _, argument: _ } => {
-                // Find aggregate value in row by matching pattern
-                // Simplified: just return first numeric value found
-                for value in row.values() {
-                    return value.clone();
-                }
-                Value::Null
-            }
-            FilterExpr::Constant(v) => v.clone(),
-            FilterExpr::Property { binding: _, property } => {
-                row.get(property).cloned().unwrap_or(Value::Null)
-            }
-            _ => Value::Null,
+    /// This control, also stopping the statement when `lifecycle`'s shutdown cancels it
+    fn under(&self, lifecycle: &Lifecycle) -> QueryControl {
+        QueryControl {
+            aborted: Some(lifecycle.abort.clone()),
+            ..self.clone()
         }
     }
 
-    /// Check if a query is a mutation (needs transaction)
-    fn is_mutation_query(&self, query: &crate::dql_ast::Query) -> bool {
-        matches!(
-            query,
-            crate::dql_ast::Query::Insert(_)
-                | crate::dql_ast::Query::Update(_)
-                | crate::dql_ast::Query::Delete(_)
-                | crate::dql_ast::Query::Create(_)
-        )
+    /// Fail if the statement was cancelled or has run out of time
+    fn check(&self) -> Result<(), String> {
+        if self.cancelled.load(AtomicOrdering::Relaxed) {
+            return Err("Query cancelled".to_string());
+        }
+        if self.aborted.as_ref().is_some_and(|aborted| aborted.load(AtomicOrdering::Relaxed)) {
+            return Err("Query cancelled by shutdown".to_string());
+        }
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(format!("Query timed out after {:?}", timeout))
+            }
+            _ => Ok(()),
+        }
     }
 
-    /// Handle BEGIN TRANSACTION
-    fn handle_begin(&self, begin_query: &crate::dql_ast::BeginQuery) -> Result<QueryResult, String> {
-        // Check if already in transaction
-        if self.current_transaction.lock().unwrap().is_some() {
-            return Err("Already in a transaction".to_string());
+    /// `check` on every `CANCEL_CHECK_INTERVAL`-th item of a loop
+    fn check_every(&self, seen: usize) -> Result<(), String> {
+        if seen.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            self.check()
+        } else {
+            Ok(())
         }
+    }
+}
 
-        // Start new transaction
-        let isolation_level = begin_query.isolation_level.unwrap_or(IsolationLevel::RepeatableRead);
-        let txn_id = self.transaction_manager.begin(isolation_level)?;
+/// Admission of an executor's statements, and its shutdown
+#[derive(Default)]
+struct Lifecycle {
+    state: Mutex<LifecycleState>,
+    /// Notified as statements finish
+    idle: Condvar,
+    /// Cancels the statements still running when shutdown stops waiting
+    abort: Arc<AtomicBool>,
+    /// Tells background tasks to stop
+    signal: ShutdownSignal,
+    tasks: Mutex<Vec<BackgroundTask>>,
+    /// Held through a shutdown; its report once it has finished
+    report: Mutex<Option<ShutdownReport>>,
+}
 
-        // Log to WAL
-        if let Some(wal) = &self.wal_manager {
-            wal.log_begin(txn_id, isolation_level)
-                .map_err(|e| format!("WAL error: {}", e))?;
-        }
+#[derive(Default)]
+struct LifecycleState {
+    shut_down: bool,
+    running: usize,
+}
 
-        // Store current transaction
-        *self.current_transaction.lock().unwrap() = Some(txn_id);
+/// A statement admitted by a `Lifecycle`, counted until dropped
+struct Admitted<'a>(&'a Lifecycle);
 
-        Ok(QueryResult {
-            rows: vec![],
-            rows_affected: 0,
-        })
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().running -= 1;
+        self.0.idle.notify_all();
     }
+}
 
-    /// Handle COMMIT
-    fn handle_commit(&self) -> Result<QueryResult, String> {
-        // Get current transaction
-        let txn_id = self.current_transaction.lock().unwrap().take()
-            .ok_or("No active transaction to commit".to_string())?;
+impl Lifecycle {
+    fn check_open(&self) -> Result<(), String> {
+        if self.state.lock().unwrap().shut_down {
+            return Err("Executor is shut down".to_string());
+        }
+        Ok(())
+    }
 
-        // Commit transaction
-        self.transaction_manager.commit(txn_id)?;
+    /// Count a statement as running, unless shutdown has begun
+    fn admit(&self) -> Result<Admitted<'_>, String> {
+        let mut state = self.state.lock().unwrap();
+        if state.shut_down {
+            return Err("Executor is shut down".to_string());
+        }
+        state.running += 1;
+        Ok(Admitted(self))
+    }
 
-        // Log to WAL
-        if let Some(wal) = &self.wal_manager {
-            wal.log_commit(txn_id)
-                .map_err(|e| format!("WAL error: {}", e))?;
-            wal.flush()
-                .map_err(|e| format!("WAL flush error: {}", e))?;
+    /// Refuse new statements and wait until `deadline` for running ones to
+    /// finish, then cancel the rest and give them [`CANCEL_GRACE`] to stop
+    ///
+    /// Returns how many were cancelled, and how many of those were still
+    /// running when the grace ran out.
+    fn drain(&self, deadline: Instant) -> (usize, usize) {
+        let mut state = self.state.lock().unwrap();
+        state.shut_down = true;
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        state = self.idle.wait_timeout_while(state, timeout, |s| s.running > 0).unwrap().0;
+        let cancelled = state.running;
+        if cancelled > 0 {
+            // They fail at their next cancellation check
+            self.abort.store(true, AtomicOrdering::Relaxed);
+            state = self.idle.wait_timeout_while(state, CANCEL_GRACE, |s| s.running > 0).unwrap().0;
         }
+        (cancelled, state.running)
+    }
+}
 
-        Ok(QueryResult {
-            rows: vec![],
-            rows_affected: 0,
-        })
+/// A statement running on another thread, started by `DQLExecutor::execute_async`
+pub struct QueryHandle {
+    cancelled: Arc<AtomicBool>,
+    worker: std::thread::JoinHandle<Result<QueryResult, String>>,
+}
+
+impl QueryHandle {
+    /// Stop the statement; it fails with "Query cancelled" at its next check
+    pub fn cancel(&self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
     }
 
-    /// Handle ROLLBACK
-    fn handle_rollback(&self) -> Result<QueryResult, String> {
-        // Get current transaction
-        let txn_id = self.current_transaction.lock().unwrap().take()
-            .ok_or("No active transaction to rollback".to_string())?;
+    /// Whether the statement has finished (or stopped)
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
 
-        // Rollback transaction and get snapshots to restore
-        let snapshots = self.transaction_manager.rollback(txn_id)?;
+    /// Wait for the statement's result
+    pub fn wait(self) -> Result<QueryResult, String> {
+        self.worker
+            .join()
+            .unwrap_or_else(|_| Err("Query thread panicked".to_string()))
+    }
+}
 
-        // Restore entity snapshots
-        let graph = self.graph.read().unwrap();
-        for (entity_id, entity_json) in snapshots {
-            // Deserialize the entity from JSON
-            let entity: crate::graph::Entity = serde_json::from_str(&entity_json)
-                .map_err(|e| format!("Failed to deserialize entity: {}", e))?;
+/// What one operation of an executed plan did
+#[derive(Debug, Clone)]
+struct OperationProfile {
+    rows: usize,
+    elapsed: Duration,
+}
 
-            // Restore the entity
-            graph.update_entity(entity)?;
-        }
-        drop(graph);
+/// How a statement ran: the plan it executed, with a measurement per operation
+#[derive(Debug, Clone)]
+struct PlanProfile {
+    plan: QueryPlan,
+    operations: Vec<OperationProfile>,
+    /// Entities examined by scans, index lookups and traversals
+    rows_scanned: usize,
+    /// Memory GROUP BY and sorts held, and what they spilled
+    memory: MemoryUsage,
+    /// Whether the plan came from the plan cache; `None` for prepared statements
+    cached: Option<bool>,
+    /// When the statement started, parsing and planning included
+    started: Instant,
+}
 
-        // Log to WAL
-        if let Some(wal) = &self.wal_manager {
-            wal.log_rollback(txn_id)
-                .map_err(|e| format!("WAL error: {}", e))?;
+impl PlanProfile {
+    fn new(cached: Option<bool>, started: Instant) -> Self {
+        PlanProfile {
+            plan: QueryPlan::new(Vec::new()),
+            operations: Vec::new(),
+            rows_scanned: 0,
+            memory: MemoryUsage::default(),
+            cached,
+            started,
         }
-
-        Ok(QueryResult {
-            rows: vec![],
-            rows_affected: 0,
-        })
     }
+}
 
-    /// Handle CREATE INDEX
-    fn handle_create_index(&self, create_index: &crate::dql_ast::CreateIndexQuery) -> Result<QueryResult, String> {
-        self.index_manager.create_index(
-            create_index.index_name.clone(),
-            create_index.collection.clone(),
-            create_index.field.clone(),
-            create_index.unique,
-        )?;
+/// A plan with the statement's literals and the caller's parameters bound, and
+/// NOW() fixed at `now`
+fn bind_plan(
+    plan: &QueryPlan,
+    literals: &HashMap<String, Value>,
+    params: &HashMap<String, Value>,
+    now: i64,
+) -> Result<QueryPlan, String> {
+    let mut values = params.clone();
+    values.extend(literals.iter().map(|(name, value)| (name.clone(), value.clone())));
+    values.insert(NOW_PARAMETER.to_string(), Value::Timestamp(now));
+    plan.bind_parameters(&values)
+}
 
-        Ok(QueryResult {
-            rows: vec![],
-            rows_affected: 0,
+/// Column metadata for the rows, typed from the values they hold
+fn describe_columns(names: &[String], rows: &[HashMap<String, Value>]) -> Vec<ColumnInfo> {
+    names
+        .iter()
+        .map(|name| ColumnInfo {
+            name: name.clone(),
+            value_type: rows
+                .iter()
+                .filter_map(|row| row.get(name))
+                .fold(ValueType::Null, |t, v| t.merge(v.value_type())),
         })
+        .collect()
+}
+
+/// Add a batch's rows to a page, with any columns the page lacks
+fn append_batch(page: &mut QueryResult, batch: QueryResult) {
+    let mut names: Vec<String> = page.columns.iter().map(|c| c.name.clone()).collect();
+    for column in batch.columns {
+        if !names.contains(&column.name) {
+            names.push(column.name);
+        }
     }
+    page.rows.extend(batch.rows);
+    page.columns = describe_columns(&names, &page.rows);
+}
 
-    /// Handle DROP INDEX
-    fn handle_drop_index(&self, drop_index: &crate::dql_ast::DropIndexQuery) -> Result<QueryResult, String> {
-        self.index_manager.drop_index(&drop_index.index_name)?;
+/// Where the next page of a paged query starts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PageCursor {
+    /// Rows the earlier pages returned
+    returned: usize,
+    /// Last entity read, for a scan paged in id order
+    after_id: Option<u64>,
+    /// ORDER BY key of the last row returned, for a sorted scan
+    after_key: Option<Vec<Value>>,
+    /// Rows returned so far with that key
+    ties: usize,
+}
 
-        Ok(QueryResult {
-            rows: vec![],
-            rows_affected: 0,
-        })
-    }
+/// Continuation token for the page starting at `next`
+fn encode_cursor(next: &PageCursor, query_str: &str) -> Result<String, String> {
+    let bytes = bincode::serialize(next).map_err(|e| format!("Serialization error: {}", e))?;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}.{}", hex, cursor_digest(query_str)))
+}
 
-    fn query_signature(&self, query: &str) -> String {
-        // Simple signature for now - hash would be better
-        query.chars().filter(|c| !c.is_whitespace()).collect()
+/// Where a continuation token resumes, checking it was issued for this query
+fn decode_cursor(cursor: &str, query_str: &str) -> Result<PageCursor, String> {
+    let invalid = || format!("Invalid cursor: {}", cursor);
+    let (hex, digest) = cursor.split_once('.').ok_or_else(invalid)?;
+    if digest != cursor_digest(query_str) {
+        return Err("Cursor was issued for a different query".to_string());
     }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    bincode::deserialize(&bytes).map_err(|_| invalid())
 }
 
-/// Execution context - holds intermediate results
-struct ExecutionContext {
-    bindings: HashMap<String, Vec<Entity>>,
-    result_rows: Vec<HashMap<String, Value>>,
-    last_inserted_id: Option<EntityId>,
-    deleted_count: usize,
-    rows_affected: usize,
+fn cursor_digest(query_str: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(query_str.as_bytes()));
+    digest[..16].to_string()
 }
 
-impl ExecutionContext {
-    fn new() -> Self {
-        ExecutionContext {
-            bindings: HashMap::new(),
-            result_rows: Vec::new(),
-            last_inserted_id: None,
-            deleted_count: 0,
-            rows_affected: 0,
-        }
-    }
+/// A result column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub value_type: ValueType,
+}
 
-    fn into_result(self) -> QueryResult {
-        QueryResult {
-            rows: self.result_rows,
-            rows_affected: self.rows_affected.max(self.deleted_count),
-        }
+/// A parsed and planned statement, executed with `DQLExecutor::execute_prepared`
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    text: String,
+    query: crate::dql_ast::Query,
+    plan: QueryPlan,
+    /// Values of the parameters standing in for the statement's literals
+    literals: HashMap<String, Value>,
+}
+
+impl PreparedQuery {
+    /// The query text, with its parameters
+    pub fn text(&self) -> &str {
+        &self.text
     }
 }
 
 /// Query result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct QueryResult {
+    /// Result columns in SELECT order
+    pub columns: Vec<ColumnInfo>,
+    /// Rows keyed by column name
     pub rows: Vec<HashMap<String, Value>>,
     pub rows_affected: usize,
+    /// How far behind the master (ms) the data is, for bounded-staleness reads
+    pub staleness_ms: Option<u64>,
+    /// Non-fatal notices, e.g. archived data skipped by a hot-only read
+    pub warnings: Vec<String>,
 }
 
 impl QueryResult {
     pub fn row_count(&self) -> usize {
         self.rows.len()
     }
+
+    /// Encode for sending to another node
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| format!("Serialization error: {}", e))
+    }
+
+    /// Decode a result encoded with [`QueryResult::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        bincode::deserialize(bytes).map_err(|e| format!("Deserialization error: {}", e))
+    }
+
+    /// Position of a column in SELECT order
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    /// Value of a column in a row
+    pub fn get(&self, row: usize, column: &str) -> Option<&Value> {
+        self.rows.get(row)?.get(column)
+    }
+
+    /// A row's values in column order (missing values read as Null)
+    pub fn row_values(&self, row: usize) -> Option<Vec<Value>> {
+        let row = self.rows.get(row)?;
+        Some(
+            self.columns
+                .iter()
+                .map(|c| row.get(&c.name).cloned().unwrap_or(Value::Null))
+                .collect(),
+        )
+    }
+}
+
+/// One page of a query's rows, from `DQLExecutor::execute_paged`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Page {
+    pub result: QueryResult,
+    /// Token to pass for the next page; `None` on the last one
+    pub cursor: Option<String>,
+}
+
+/// A query's rows in batches, from `DQLExecutor::execute_stream`
+///
+/// Each batch is a `QueryResult` of at most the stream's batch size; a
+/// batch's columns are those of its rows, so `SELECT *` batches may differ.
+/// There are no batches without rows, except the single one of a statement
+/// that only reports `rows_affected`. An error ends the stream.
+pub struct RowStream {
+    source: RowSource,
+    batch_size: usize,
+    /// Whether a batch has been handed out
+    delivered: bool,
+}
+
+enum RowSource {
+    /// The result of a statement executed in full
+    Materialized(QueryResult),
+    /// A collection scan run a batch at a time
+    Scan(Box<ScanStream>),
+}
+
+impl Iterator for RowStream {
+    type Item = Result<QueryResult, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match &mut self.source {
+            RowSource::Materialized(result) => {
+                if result.rows.is_empty() && (self.delivered || result.rows_affected == 0) {
+                    return None;
+                }
+                let rest = result.rows.split_off(self.batch_size.min(result.rows.len()));
+                Ok(QueryResult {
+                    columns: result.columns.clone(),
+                    rows: std::mem::replace(&mut result.rows, rest),
+                    rows_affected: std::mem::take(&mut result.rows_affected),
+                    staleness_ms: result.staleness_ms,
+                    warnings: std::mem::take(&mut result.warnings),
+                })
+            }
+            RowSource::Scan(scan) => scan.next_batch(self.batch_size)?,
+        };
+
+        self.delivered = true;
+        Some(batch)
+    }
+}
+
+/// A single-collection SELECT, run over the collection a batch of entities at a time
+struct ScanStream {
+    executor: DQLExecutor,
+    /// Ids of the collection's entities when the stream began, not yet read
+    ids: std::vec::IntoIter<EntityId>,
+    /// The last id read
+    last_id: Option<EntityId>,
+    alias: String,
+    filter: Option<FilterExpr>,
+    /// Properties copied out of each entity (all when `None`)
+    properties: Option<Vec<String>>,
+    /// The filters and projection after the scan
+    operations: Vec<Operation>,
+    sort: Option<StreamSort>,
+    /// Rows still to drop for OFFSET
+    skip: usize,
+    /// Rows still to return under LIMIT
+    remaining: Option<usize>,
+    control: QueryControl,
+    strict_functions: bool,
+    // Recorded in the query metrics once the stream ends
+    text: String,
+    plan: QueryPlan,
+    cached: bool,
+    started: Instant,
+    rows_scanned: usize,
+    rows_returned: usize,
+    spilled_bytes: usize,
+    finished: bool,
+}
+
+/// A [`ScanStream`]'s ORDER BY
+struct StreamSort {
+    fields: Vec<SortField>,
+    /// Rows that survive OFFSET and LIMIT; when no more than a batch,
+    /// they're kept as a running top-k instead of sorting every row
+    limit: Option<usize>,
+    budget: MemoryBudget,
+    /// The rows in order, once the scan has been read to the end
+    sorted: Option<Box<dyn Iterator<Item = RowResult> + Send>>,
+    /// Result columns, less those projected only to sort on
+    columns: Vec<String>,
+    /// Key of the last row of the page before; rows ordered before it are dropped unsorted
+    after: Option<HashMap<String, Value>>,
+    /// Whether rows keep the columns projected only to sort on, for the page cursor
+    keep_keys: bool,
+}
+
+impl ScanStream {
+    /// The next non-empty batch, or `None` once the stream has ended
+    fn next_batch(&mut self, batch_size: usize) -> Option<Result<QueryResult, String>> {
+        if self.finished {
+            return None;
+        }
+
+        let batch = self.read_batch(batch_size);
+        if !matches!(batch, Ok(Some(_))) {
+            self.finish(batch.is_ok());
+        }
+        batch.transpose()
+    }
+
+    /// Record the stream in the query metrics
+    fn finish(&mut self, succeeded: bool) {
+        self.finished = true;
+        self.executor.metrics.record(QuerySample {
+            text: &self.text,
+            plan: &self.plan,
+            elapsed: self.started.elapsed(),
+            rows_scanned: self.rows_scanned,
+            rows_returned: self.rows_returned,
+            spilled_bytes: self.spilled_bytes,
+            cache_hit: Some(self.cached),
+            auto_committed: false,
+            succeeded,
+        });
+    }
+
+    /// Start the scan where the page before ended, per its cursor `after`
+    ///
+    /// Entities are read in id order, so that a page can resume after the
+    /// last one read and rows with equal ORDER BY keys keep their order.
+    fn resume(&mut self, after: &PageCursor, page_size: usize) {
+        let mut ids: Vec<EntityId> = std::mem::take(&mut self.ids).collect();
+        if let (None, Some(last)) = (&self.sort, after.after_id) {
+            ids.retain(|id| id.0 > last);
+        }
+        ids.sort_unstable_by_key(|id| id.0);
+        self.ids = ids.into_iter();
+
+        // OFFSET was spent on the first page
+        if after.returned > 0 {
+            self.skip = 0;
+            self.remaining = self.remaining.map(|remaining| remaining.saturating_sub(after.returned));
+        }
+        if let Some(sort) = &mut self.sort {
+            sort.keep_keys = true;
+            if let Some(key) = &after.after_key {
+                sort.after = Some(sort.fields.iter().map(|f| f.column.clone()).zip(key.iter().cloned()).collect());
+                self.skip = after.ties;
+            }
+            // The page and a row to tell whether another follows
+            sort.limit = Some(self.skip + self.remaining.map_or(page_size + 1, |r| r.min(page_size + 1)));
+        }
+    }
+
+    /// Up to `page_size` rows, and where the page after them starts if there is one
+    fn read_page(&mut self, page_size: usize, after: &PageCursor) -> Result<(QueryResult, Option<PageCursor>), String> {
+        let page = self.fill_page(page_size, after);
+        self.finish(page.is_ok());
+        page
+    }
+
+    fn fill_page(&mut self, page_size: usize, after: &PageCursor) -> Result<(QueryResult, Option<PageCursor>), String> {
+        let mut page = QueryResult::default();
+        if self.sort.is_none() {
+            // A chunk yields no more rows than it has entities, so reading
+            // only what the page has room for ends it on the last id read
+            while page.rows.len() < page_size {
+                let Some(batch) = self.read_batch(page_size - page.rows.len())? else { break };
+                append_batch(&mut page, batch);
+            }
+            let after_id = self.last_id.map(|id| id.0);
+            let more = page.rows.len() == page_size && self.read_batch(page_size)?.is_some();
+            let next = more.then(|| PageCursor {
+                returned: after.returned + page.rows.len(),
+                after_id,
+                ..Default::default()
+            });
+            return Ok((page, next));
+        }
+
+        while page.rows.len() <= page_size {
+            let Some(batch) = self.read_batch(page_size + 1 - page.rows.len())? else { break };
+            append_batch(&mut page, batch);
+        }
+        let more = page.rows.len() > page_size;
+        page.rows.truncate(page_size);
+
+        let sort = self.sort.as_ref().expect("sorted scan");
+        let next = match page.rows.last() {
+            Some(last) if more => {
+                let mut ties = page.rows.iter().rev().take_while(|row| compare_rows(&sort.fields, row, last).is_eq()).count();
+                if ties == page.rows.len() && sort.after.as_ref().is_some_and(|key| compare_rows(&sort.fields, last, key).is_eq()) {
+                    ties += after.ties;
+                }
+                Some(PageCursor {
+                    returned: after.returned + page.rows.len(),
+                    after_key: Some(sort.fields.iter().map(|f| last.get(&f.column).cloned().unwrap_or(Value::Null)).collect()),
+                    ties,
+                    ..Default::default()
+                })
+            }
+            _ => None,
+        };
+        for row in &mut page.rows {
+            for field in sort.fields.iter().filter(|f| f.hidden) {
+                row.remove(&field.column);
+            }
+        }
+        Ok((page, next))
+    }
+
+    /// Run the scan's next `batch_size` entities through the plan until some rows come out
+    fn read_batch(&mut self, batch_size: usize) -> Result<Option<QueryResult>, String> {
+        if let Some(mut sort) = self.sort.take() {
+            let batch = self.read_sorted_batch(&mut sort, batch_size);
+            self.sort = Some(sort);
+            return batch;
+        }
+
+        while self.remaining != Some(0) {
+            let Some(mut ctx) = self.read_chunk(batch_size)? else { break };
+            self.page(&mut ctx.result_rows);
+            if !ctx.result_rows.is_empty() {
+                self.rows_returned += ctx.result_rows.len();
+                return Ok(Some(ctx.into_result()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The next batch in ORDER BY order, sorting the whole scan on the first
+    fn read_sorted_batch(&mut self, sort: &mut StreamSort, batch_size: usize) -> Result<Option<QueryResult>, String> {
+        if sort.sorted.is_none() {
+            let fields = sort.fields.clone();
+            let order = move |a: &HashMap<String, Value>, b: &HashMap<String, Value>| compare_rows(&fields, a, b);
+            let mut sorter = ExternalSort::new(&sort.budget, order);
+            let top_k_limit = sort.limit.filter(|k| *k <= batch_size);
+            let mut top = Vec::new();
+            while let Some(ctx) = self.read_chunk(batch_size)? {
+                let hidden = |column: &String| sort.fields.iter().any(|f| f.hidden && &f.column == column);
+                sort.columns = ctx.columns.iter().filter(|c| !hidden(c)).cloned().collect();
+                let after = sort.after.as_ref();
+                let rows = ctx
+                    .result_rows
+                    .into_iter()
+                    .filter(|row| after.is_none_or(|key| compare_rows(&sort.fields, row, key).is_ge()));
+                match top_k_limit {
+                    Some(k) => {
+                        top.extend(rows);
+                        let k = k.min(top.len());
+                        top = top_k(top, &sort.fields, k);
+                    }
+                    None => {
+                        for row in rows {
+                            let size = row_size(&row);
+                            sorter.push(row, size)?;
+                        }
+                    }
+                }
+            }
+            if top_k_limit.is_some() {
+                sort.sorted = Some(Box::new(top.into_iter().map(Ok)));
+            } else {
+                let (sorted, usage) = sorter.finish()?;
+                self.spilled_bytes += usage.spilled_bytes;
+                sort.sorted = Some(Box::new(sorted));
+            }
+        }
+
+        let sorted = sort.sorted.as_mut().expect("rows sorted above");
+        while self.remaining != Some(0) {
+            self.control.check()?;
+            let mut rows = Vec::new();
+            for row in sorted.by_ref().take(batch_size) {
+                let mut row = row?;
+                // Drop columns that were only projected to sort on
+                for field in sort.fields.iter().filter(|f| f.hidden && !sort.keep_keys) {
+                    row.remove(&field.column);
+                }
+                rows.push(row);
+            }
+            if rows.is_empty() {
+                break;
+            }
+
+            self.page(&mut rows);
+            if !rows.is_empty() {
+                self.rows_returned += rows.len();
+                return Ok(Some(QueryResult {
+                    columns: describe_columns(&sort.columns, &rows),
+                    rows,
+                    ..Default::default()
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Run the scan's next `batch_size` entities through the filters and
+    /// projection, or `None` once every entity has been read
+    fn read_chunk(&mut self, batch_size: usize) -> Result<Option<ExecutionContext>, String> {
+        self.control.check()?;
+        let chunk: Vec<EntityId> = self.ids.by_ref().take(batch_size).collect();
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        self.last_id = chunk.last().copied();
+
+        let mut ctx = ExecutionContext::new();
+        ctx.control = self.control.clone();
+        ctx.strict_functions = self.strict_functions;
+        ctx.now = (self.executor.clock)();
+
+        let graph = self.executor.graph.read().unwrap();
+        ctx.degrees = Some(graph.degrees());
+        let properties = property_names(&self.properties);
+        let mut entities = Vec::new();
+        for id in chunk {
+            // Deleted since the stream began
+            let Some(entity) = graph.get_entity_ref(id) else { continue };
+            self.rows_scanned += 1;
+            if !graph.is_expired(&entity, ctx.now) && self.executor.passes_filter(self.filter.as_ref(), &entity, &ctx)? {
+                entities.push(copy_projected(&graph, &entity, properties.as_deref()));
+            }
+        }
+        ctx.bindings.insert(self.alias.clone(), entities);
+        for operation in &self.operations {
+            self.executor.execute_operation(operation, &mut ctx, &graph)?;
+        }
+        drop(graph);
+
+        Ok(Some(ctx))
+    }
+
+    /// Apply what is left of OFFSET and LIMIT to the next rows out
+    fn page(&mut self, rows: &mut Vec<HashMap<String, Value>>) {
+        let skipped = self.skip.min(rows.len());
+        rows.drain(..skipped);
+        self.skip -= skipped;
+        if let Some(remaining) = &mut self.remaining {
+            rows.truncate(*remaining);
+            *remaining -= rows.len();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1137,7 +7068,7 @@ mod tests {
             let mut props = Properties::new();
             props.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
             props.insert("age".to_string(), PropertyValue::Int(25));
-            g.add_entity("User".to_string(), props);
+            g.add_entity("User".to_string(), props).unwrap();
         }
 
         let executor = DQLExecutor::new(graph);
@@ -1148,4 +7079,67 @@ mod tests {
         let res = result.unwrap();
         assert!(res.row_count() > 0);
     }
+
+    #[test]
+    fn test_group_table_aggregates_millions_of_rows_within_budget() {
+        let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+        let argument = FilterExpr::Property {
+            binding: "r".to_string(),
+            property: "v".to_string(),
+        };
+        let aggregates: Vec<AggregateOp> = [(AggregateFunc::Count, "n"), (AggregateFunc::Sum, "total")]
+            .into_iter()
+            .map(|(function, alias)| AggregateOp {
+                function,
+                argument: argument.clone(),
+                distinct: false,
+                alias: alias.to_string(),
+            })
+            .collect();
+
+        // 5M rows in 100k groups of 50, far more groups than the budget holds
+        let budget = MemoryBudget::new(4 << 20);
+        let mut table = GroupTable::new(budget.clone());
+        for i in 0..5_000_000i64 {
+            let value = PropertyValue::Int(i % 7);
+            table
+                .add(&executor, &aggregates, vec![Value::Integer(i / 50)], vec![value.clone(), value])
+                .unwrap();
+        }
+        let (groups, usage) = table.finish(&executor, &aggregates).unwrap();
+        assert!(usage.spilled_bytes > 0);
+        // Never more than one group's worth over the budget
+        assert!(usage.peak <= budget.limit + 1024, "held {} bytes", usage.peak);
+
+        // Groups are merged back from their runs in key order
+        let mut total = 0;
+        let mut count = 0;
+        for (key, group) in groups.enumerate() {
+            let group = group.unwrap();
+            count += 1;
+            assert_eq!(group.values, [Value::Integer(key as i64)]);
+            let mut accumulators = group.accumulators.into_iter();
+            let count = executor.finish_aggregate(&aggregates[0], accumulators.next().unwrap()).unwrap();
+            assert_eq!(count, Value::Integer(50));
+            match executor.finish_aggregate(&aggregates[1], accumulators.next().unwrap()).unwrap() {
+                Value::Integer(sum) => total += sum,
+                other => panic!("Expected an integer SUM, got {:?}", other),
+            }
+        }
+        assert_eq!(count, 100_000);
+        assert_eq!(total, (0..5_000_000i64).map(|i| i % 7).sum::<i64>());
+    }
+
+    #[test]
+    fn test_drain_stops_waiting_for_a_statement_that_ignores_cancellation() {
+        let lifecycle = Lifecycle::default();
+        let stuck = lifecycle.admit().unwrap();
+
+        let started = Instant::now();
+        assert_eq!(lifecycle.drain(Instant::now() + Duration::from_millis(20)), (1, 1));
+        assert!(started.elapsed() < CANCEL_GRACE * 2, "took {:?}", started.elapsed());
+        assert!(lifecycle.abort.load(AtomicOrdering::Relaxed));
+        assert!(lifecycle.admit().is_err());
+        drop(stuck);
+    }
 }
@@ -0,0 +1,197 @@
+//! Scalar functions for DQL expressions
+//!
+//! A call like `LOWER(u.name)` is evaluated per row, unlike an aggregate.
+//! Every function is listed in [`FUNCTIONS`] with its name and arity, so
+//! adding one means adding a variant, its entry, and its case in
+//! [`ScalarFunction::apply`].
+//!
+//! A NULL argument gives NULL, except to `TYPEOF`. An argument of the wrong type (`LENGTH(42)`)
+//! is an error from `apply`; the executor turns it into NULL unless the
+//! session sets `strict_functions = on`.
+
+use crate::types::PropertyValue;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A scalar (per-row) function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScalarFunction {
+    Lower,
+    Upper,
+    /// Characters of a string, or items of a list
+    Length,
+    Trim,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Concat,
+    /// Name of a value's type, NULL's included
+    TypeOf,
+}
+
+/// Number of arguments a function takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+/// Every scalar function: its DQL name, the function, and its arity
+pub const FUNCTIONS: &[(&str, ScalarFunction, Arity)] = &[
+    ("LOWER", ScalarFunction::Lower, Arity::Exactly(1)),
+    ("UPPER", ScalarFunction::Upper, Arity::Exactly(1)),
+    ("LENGTH", ScalarFunction::Length, Arity::Exactly(1)),
+    ("TRIM", ScalarFunction::Trim, Arity::Exactly(1)),
+    ("CONTAINS", ScalarFunction::Contains, Arity::Exactly(2)),
+    ("STARTS_WITH", ScalarFunction::StartsWith, Arity::Exactly(2)),
+    ("ENDS_WITH", ScalarFunction::EndsWith, Arity::Exactly(2)),
+    ("CONCAT", ScalarFunction::Concat, Arity::AtLeast(1)),
+    ("TYPEOF", ScalarFunction::TypeOf, Arity::Exactly(1)),
+];
+
+impl ScalarFunction {
+    /// The function called `name` (case-insensitive)
+    pub fn lookup(name: &str) -> Option<ScalarFunction> {
+        FUNCTIONS
+            .iter()
+            .find(|(n, _, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, function, _)| *function)
+    }
+
+    fn entry(&self) -> &'static (&'static str, ScalarFunction, Arity) {
+        FUNCTIONS
+            .iter()
+            .find(|(_, function, _)| function == self)
+            .expect("every scalar function is registered")
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.entry().0
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.entry().2
+    }
+
+    /// Check a call passes an acceptable number of arguments
+    pub fn check_arity(&self, count: usize) -> Result<(), String> {
+        let expected = match self.arity() {
+            Arity::Exactly(n) if count != n => format!("{}", n),
+            Arity::AtLeast(n) if count < n => format!("at least {}", n),
+            _ => return Ok(()),
+        };
+        Err(format!("{} takes {} argument(s), got {}", self.name(), expected, count))
+    }
+
+    /// Apply the function to evaluated arguments
+    ///
+    /// Any NULL argument gives NULL, except to `TYPEOF`; an argument of the
+    /// wrong type is an error.
+    pub fn apply(&self, args: &[PropertyValue]) -> Result<PropertyValue, String> {
+        if *self != ScalarFunction::TypeOf && args.contains(&PropertyValue::Null) {
+            return Ok(PropertyValue::Null);
+        }
+        self.check_arity(args.len())?;
+
+        let value = match self {
+            ScalarFunction::Lower => PropertyValue::String(self.string(&args[0])?.to_lowercase()),
+            ScalarFunction::Upper => PropertyValue::String(self.string(&args[0])?.to_uppercase()),
+            ScalarFunction::Trim => PropertyValue::String(self.string(&args[0])?.trim().to_string()),
+            ScalarFunction::Length => {
+                let length = match &args[0] {
+                    PropertyValue::List(items) => items.len(),
+                    other => self.string(other)?.chars().count(),
+                };
+                PropertyValue::Int(length as i64)
+            }
+            ScalarFunction::Contains => PropertyValue::Bool(self.string(&args[0])?.contains(self.string(&args[1])?)),
+            ScalarFunction::StartsWith => {
+                PropertyValue::Bool(self.string(&args[0])?.starts_with(self.string(&args[1])?))
+            }
+            ScalarFunction::EndsWith => PropertyValue::Bool(self.string(&args[0])?.ends_with(self.string(&args[1])?)),
+            ScalarFunction::Concat => {
+                let mut joined = String::new();
+                for arg in args {
+                    joined.push_str(self.string(arg)?);
+                }
+                PropertyValue::String(joined)
+            }
+            ScalarFunction::TypeOf => PropertyValue::String(type_name(&args[0]).to_string()),
+        };
+
+        Ok(value)
+    }
+
+    fn string<'a>(&self, value: &'a PropertyValue) -> Result<&'a str, String> {
+        value
+            .as_str()
+            .ok_or_else(|| format!("{} expects a string, got {:?}", self.name(), value))
+    }
+}
+
+/// What `TYPEOF` calls a value's type
+fn type_name(value: &PropertyValue) -> &'static str {
+    match value {
+        PropertyValue::Null => "Null",
+        PropertyValue::Bool(_) => "Bool",
+        PropertyValue::Int(_) => "Int",
+        PropertyValue::Float(_) => "Float",
+        PropertyValue::String(_) => "String",
+        PropertyValue::Bytes(_) => "Bytes",
+        PropertyValue::List(_) => "List",
+        PropertyValue::Map(_) => "Map",
+        PropertyValue::Timestamp(_) => "Timestamp",
+    }
+}
+
+impl fmt::Display for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(text: &str) -> PropertyValue {
+        PropertyValue::String(text.to_string())
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(ScalarFunction::lookup("lower"), Some(ScalarFunction::Lower));
+        assert_eq!(ScalarFunction::lookup("Starts_With"), Some(ScalarFunction::StartsWith));
+        assert_eq!(ScalarFunction::lookup("REVERSE"), None);
+
+        assert_eq!(ScalarFunction::Lower.apply(&[s("ÅsA")]), Ok(s("åsa")));
+        assert_eq!(ScalarFunction::Upper.apply(&[s("asa")]), Ok(s("ASA")));
+        assert_eq!(ScalarFunction::Trim.apply(&[s("  a b ")]), Ok(s("a b")));
+        assert_eq!(ScalarFunction::Length.apply(&[s("Åsa")]), Ok(PropertyValue::Int(3)));
+        assert_eq!(
+            ScalarFunction::Length.apply(&[PropertyValue::List(vec![PropertyValue::Int(1)])]),
+            Ok(PropertyValue::Int(1))
+        );
+        assert_eq!(ScalarFunction::Contains.apply(&[s("alice"), s("lic")]), Ok(PropertyValue::Bool(true)));
+        assert_eq!(ScalarFunction::StartsWith.apply(&[s("alice"), s("lic")]), Ok(PropertyValue::Bool(false)));
+        assert_eq!(ScalarFunction::EndsWith.apply(&[s("alice"), s("ce")]), Ok(PropertyValue::Bool(true)));
+        assert_eq!(ScalarFunction::Concat.apply(&[s("a"), s(" "), s("b")]), Ok(s("a b")));
+    }
+
+    #[test]
+    fn test_nulls_types_and_arity() {
+        assert_eq!(ScalarFunction::Concat.apply(&[s("a"), PropertyValue::Null]), Ok(PropertyValue::Null));
+        assert_eq!(ScalarFunction::TypeOf.apply(&[PropertyValue::Null]), Ok(s("Null")));
+        assert_eq!(ScalarFunction::TypeOf.apply(&[PropertyValue::Int(42)]), Ok(s("Int")));
+        assert_eq!(ScalarFunction::TypeOf.apply(&[PropertyValue::Bytes(vec![1])]), Ok(s("Bytes")));
+        assert!(ScalarFunction::Length.apply(&[PropertyValue::Int(42)]).is_err());
+        assert!(ScalarFunction::Contains.apply(&[s("a")]).is_err());
+
+        assert!(ScalarFunction::Concat.check_arity(0).is_err());
+        assert!(ScalarFunction::Concat.check_arity(5).is_ok());
+        assert_eq!(
+            ScalarFunction::Lower.check_arity(2),
+            Err("LOWER takes 1 argument(s), got 2".to_string())
+        );
+    }
+}
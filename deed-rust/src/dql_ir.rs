@@ -3,24 +3,44 @@
 //! Lowered representation of DQL queries optimized for execution.
 //! This is the output of the parser and input to the biological optimizer.
 
+use crate::btree::tokenize;
 use crate::dql_ast::*;
-use crate::types::{EntityId, EdgeId};
+use crate::dql_functions::ScalarFunction;
+use crate::types::{DistinctKey, PropertyValue};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 
 // Re-export GraphStats from graph module to avoid duplication
 pub use crate::graph::GraphStats;
 
+/// Prefix of grouped-row columns computed only for HAVING
+pub const HIDDEN_HAVING_COLUMN_PREFIX: &str = "__having_";
+
 /// Aggregate operation (for GROUP BY)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AggregateOp {
     pub function: AggregateFunc,
     pub argument: FilterExpr,
+    /// Aggregate only distinct argument values
+    pub distinct: bool,
     pub alias: String,
 }
 
+impl AggregateOp {
+    /// Whether this operation computes the aggregate expression `expr`
+    pub fn computes(&self, expr: &FilterExpr) -> bool {
+        matches!(
+            expr,
+            FilterExpr::Aggregate { function, argument, distinct }
+                if *function == self.function && **argument == self.argument && *distinct == self.distinct
+        )
+    }
+}
+
 /// Aggregate function type
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AggregateFunc {
     Count,
     Sum,
@@ -41,6 +61,19 @@ impl From<&AggregateFunction> for AggregateFunc {
     }
 }
 
+impl AggregateFunc {
+    /// DQL keyword of the function
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFunc::Count => "COUNT",
+            AggregateFunc::Sum => "SUM",
+            AggregateFunc::Avg => "AVG",
+            AggregateFunc::Min => "MIN",
+            AggregateFunc::Max => "MAX",
+        }
+    }
+}
+
 /// Query execution plan
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryPlan {
@@ -58,45 +91,387 @@ impl QueryPlan {
         }
     }
 
+    /// Plan whose reads copy only the properties its operations use
+    fn with_projection_pushdown(operations: Vec<Operation>) -> Self {
+        let mut plan = QueryPlan::new(operations);
+        plan.push_down_projection();
+        plan
+    }
+
+    /// Properties the plan's operations read, of any binding, or `None`
+    /// when a wildcard projection needs every property
+    ///
+    /// Subqueries are planned with projections of their own and don't count.
+    pub fn referenced_properties(&self) -> Option<BTreeSet<String>> {
+        let wildcard = self.operations.iter().any(|op| match op {
+            Operation::Project { fields, .. } => fields.iter().any(|field| field.as_field().is_none()),
+            _ => false,
+        });
+        if wildcard {
+            return None;
+        }
+
+        Some(
+            self.operations
+                .iter()
+                .flat_map(Operation::expressions)
+                .flat_map(FilterExpr::properties)
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    /// Have scans, index lookups and traversals copy only the properties
+    /// the plan reads out of storage
+    ///
+    /// Filters, projections, sort and group keys, aggregates and updates all
+    /// widen the set, so no operation sees a property missing that it reads.
+    /// Mutations re-read the entities they write in full.
+    pub fn push_down_projection(&mut self) {
+        let projected: Option<Vec<String>> = self.referenced_properties().map(|names| names.into_iter().collect());
+        for op in &mut self.operations {
+            if let Operation::Scan { properties, .. }
+            | Operation::IndexLookup { properties, .. }
+            | Operation::Traverse { properties, .. } = op
+            {
+                *properties = projected.clone();
+            }
+        }
+    }
+
     /// Calculate estimated cost based on operations
     pub fn estimate_cost(&mut self, stats: &GraphStats) {
-        let mut cost = 0.0;
+        self.estimated_cost = self.operation_costs(stats).iter().sum();
+    }
+
+    /// Estimated cost of each operation, in order
+    ///
+    /// Filters and traversals are costed against the collections their
+    /// bindings were scanned from.
+    pub fn operation_costs(&self, stats: &GraphStats) -> Vec<f32> {
+        let bindings: HashMap<&str, &str> = self
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Scan { collection, alias, .. } | Operation::IndexLookup { collection, alias, .. } => {
+                    Some((alias.as_str(), collection.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        self.operations.iter().map(|op| op.cost_in(stats, &bindings)).collect()
+    }
+
+    /// Turn scans whose filter constrains an indexed field into index lookups
+    ///
+    /// `find_index(collection, field, probe)` names the index on that field
+    /// able to serve the probe, if any. Equality predicates are preferred over
+    /// IN lists, IN lists over MATCH, and MATCH over ranges; only AND-ed
+    /// predicates against non-null constants are considered.
+    pub fn use_indexes<F>(&mut self, find_index: F)
+    where
+        F: Fn(&str, &str, &IndexProbe) -> Option<String>,
+    {
+        self.use_indexes_with(&find_index);
+    }
+
+    fn use_indexes_with(&mut self, find_index: &dyn Fn(&str, &str, &IndexProbe) -> Option<String>) {
+        for op in &mut self.operations {
+            if let Operation::Subquery { plan, .. } = op {
+                plan.use_indexes_with(find_index);
+                continue;
+            }
+            let Operation::Scan {
+                collection,
+                alias,
+                filter: Some(filter),
+                properties,
+            } = op
+            else {
+                continue;
+            };
+
+            let mut candidates: Vec<(String, IndexProbe)> = Vec::new();
+            for (field, probe) in filter.index_probes(alias) {
+                match candidates.iter_mut().find(|(f, _)| *f == field) {
+                    Some((_, existing)) => existing.narrow(probe),
+                    None => candidates.push((field, probe)),
+                }
+            }
+            candidates.sort_by_key(|(_, probe)| match probe {
+                IndexProbe::Equal(_) => 0,
+                IndexProbe::In(_) => 1,
+                IndexProbe::Match(_) => 2,
+                IndexProbe::Range { .. } => 3,
+            });
+
+            let chosen = candidates
+                .into_iter()
+                .find_map(|(field, probe)| find_index(collection, &field, &probe).map(|name| (name, probe)));
+
+            if let Some((index_name, probe)) = chosen {
+                *op = Operation::IndexLookup {
+                    collection: collection.clone(),
+                    alias: alias.clone(),
+                    index_name,
+                    probe,
+                    filter: Some(filter.clone()),
+                    properties: properties.clone(),
+                };
+            }
+        }
+    }
+
+    /// Turn scans whose filter picks entities by `@id` into fetches by id
+    ///
+    /// No index is read, so unlike [`use_indexes`](Self::use_indexes) this
+    /// suits plans that read older versions or archived entities too.
+    pub fn use_id_lookups(&mut self) {
+        self.use_indexes_with(&|_, field, probe| id_lookup(field, probe));
+    }
 
+    /// Fields the plan's MATCH predicates search, as (collection, field)
+    ///
+    /// The collection is `None` for a binding no scan reads (e.g. a traversal
+    /// target). Subqueries' predicates are included.
+    pub fn text_matches(&self) -> Vec<(Option<&str>, &str)> {
+        let bindings: HashMap<&str, &str> = self
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Scan { collection, alias, .. } | Operation::IndexLookup { collection, alias, .. } => {
+                    Some((alias.as_str(), collection.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut matches = Vec::new();
         for op in &self.operations {
-            cost += op.estimate_cost(stats);
+            let exprs: Vec<&FilterExpr> = match op {
+                Operation::Scan { filter, .. }
+                | Operation::IndexLookup { filter, .. }
+                | Operation::Traverse { filter, .. } => filter.iter().collect(),
+                Operation::Filter { condition, .. } | Operation::Join { condition, .. } => vec![condition],
+                Operation::Project { fields, .. } => {
+                    fields.iter().filter_map(Projection::as_field).map(|f| &f.expression).collect()
+                }
+                Operation::Subquery { plan, .. } => {
+                    matches.extend(plan.text_matches());
+                    Vec::new()
+                }
+                _ => Vec::new(),
+            };
+            for (binding, field) in exprs.into_iter().flat_map(FilterExpr::text_matches) {
+                matches.push((bindings.get(binding).copied(), field));
+            }
+        }
+        matches
+    }
+
+    /// Collections the plan scans or writes, then those its subqueries read
+    pub fn collections(&self) -> Vec<&str> {
+        let own = self.operations.iter().filter_map(|op| match op {
+            Operation::Scan { collection, .. }
+            | Operation::IndexLookup { collection, .. }
+            | Operation::InsertEntity { collection, .. } => Some(collection.as_str()),
+            _ => None,
+        });
+        let subqueries = self.operations.iter().flat_map(|op| match op {
+            Operation::Subquery { plan, .. } => plan.collections(),
+            _ => Vec::new(),
+        });
+
+        let mut collections: Vec<&str> = Vec::new();
+        for collection in own.chain(subqueries) {
+            if !collections.contains(&collection) {
+                collections.push(collection);
+            }
+        }
+        collections
+    }
+
+    /// Copy of the plan with every parameter replaced by its value
+    ///
+    /// Plans are built and cached with parameters in place; binding happens
+    /// per execution, before indexes are chosen.
+    pub fn bind_parameters(&self, params: &HashMap<String, Value>) -> Result<QueryPlan, String> {
+        let bind_all = |exprs: &HashMap<String, FilterExpr>| -> Result<HashMap<String, FilterExpr>, String> {
+            exprs
+                .iter()
+                .map(|(key, expr)| expr.bind_parameters(params).map(|bound| (key.clone(), bound)))
+                .collect()
+        };
+
+        let mut bound = self.clone();
+        for op in &mut bound.operations {
+            match op {
+                Operation::Scan { filter, .. }
+                | Operation::IndexLookup { filter, .. }
+                | Operation::Traverse { filter, .. } => {
+                    *filter = filter.as_ref().map(|f| f.bind_parameters(params)).transpose()?;
+                }
+                Operation::Filter { condition, .. }
+                | Operation::Join { condition, .. }
+                | Operation::Having { condition } => {
+                    *condition = condition.bind_parameters(params)?;
+                }
+                Operation::Project { fields, .. } => {
+                    for field in fields {
+                        if let Projection::Field(field) = field {
+                            field.expression = field.expression.bind_parameters(params)?;
+                        }
+                    }
+                }
+                Operation::Sort { fields, .. } => {
+                    for field in fields {
+                        field.expression = field.expression.bind_parameters(params)?;
+                    }
+                }
+                Operation::GroupBy { group_fields, aggregates } => {
+                    for field in group_fields {
+                        *field = field.bind_parameters(params)?;
+                    }
+                    for aggregate in aggregates {
+                        aggregate.argument = aggregate.argument.bind_parameters(params)?;
+                    }
+                }
+                Operation::InsertEntity { rows, .. } => {
+                    for properties in rows {
+                        *properties = bind_all(properties)?;
+                    }
+                }
+                Operation::UpdateEntities { updates, .. } => *updates = bind_all(updates)?,
+                Operation::CreateEdge { source, target, .. } => {
+                    *source = source.bind_parameters(params)?;
+                    *target = target.bind_parameters(params)?;
+                }
+                Operation::UpdateEdges { edges, updates } => {
+                    edges.filter = edges.filter.as_ref().map(|f| f.bind_parameters(params)).transpose()?;
+                    *updates = bind_all(updates)?;
+                }
+                Operation::DeleteEdges { edges } => {
+                    edges.filter = edges.filter.as_ref().map(|f| f.bind_parameters(params)).transpose()?;
+                }
+                Operation::Subquery { plan, .. } => **plan = plan.bind_parameters(params)?,
+                Operation::Limit { .. } | Operation::Skip { .. } | Operation::DeleteEntities { .. } => {}
+            }
         }
 
-        self.estimated_cost = cost;
+        Ok(bound)
     }
 }
 
+/// How an index lookup reads its index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexProbe {
+    /// Entities whose key equals the value
+    Equal(Value),
+    /// Entities whose key is one of the values
+    In(Vec<Value>),
+    /// Entities whose key lies between the (inclusive) bounds
+    Range {
+        lower: Option<Value>,
+        upper: Option<Value>,
+    },
+    /// Entities whose text holds every term (a full-text index)
+    Match(Vec<String>),
+}
+
+impl IndexProbe {
+    /// Combine another predicate on the same field (e.g. `age > 20 AND age < 30`)
+    pub(crate) fn narrow(&mut self, other: IndexProbe) {
+        match (&mut *self, other) {
+            (IndexProbe::Equal(_), _) => {}
+            (_, equal @ IndexProbe::Equal(_)) => *self = equal,
+            (IndexProbe::In(_), _) => {}
+            (_, list @ IndexProbe::In(_)) => *self = list,
+            (IndexProbe::Match(terms), IndexProbe::Match(more)) => {
+                for term in more {
+                    if !terms.contains(&term) {
+                        terms.push(term);
+                    }
+                }
+            }
+            (IndexProbe::Match(_), IndexProbe::Range { .. }) => {}
+            (IndexProbe::Range { .. }, text @ IndexProbe::Match(_)) => *self = text,
+            (IndexProbe::Range { lower, upper }, IndexProbe::Range { lower: l, upper: u }) => {
+                if lower.is_none() {
+                    *lower = l;
+                }
+                if upper.is_none() {
+                    *upper = u;
+                }
+            }
+        }
+    }
+}
+
+/// The built-in "index" on `@id` serves equality and IN probes by
+/// fetching entities directly; it's named after the pseudo-property
+pub fn id_lookup(field: &str, probe: &IndexProbe) -> Option<String> {
+    let by_id = field == ID_PROPERTY && matches!(probe, IndexProbe::Equal(_) | IndexProbe::In(_));
+    by_id.then(|| ID_PROPERTY.to_string())
+}
+
 /// Individual operation in execution plan
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operation {
     /// Scan collection (table scan)
+    ///
+    /// With `properties`, only those properties of each entity are copied
+    /// out of storage (see [`QueryPlan::push_down_projection`]).
     Scan {
         collection: String,
         alias: String,
         filter: Option<FilterExpr>,
+        #[serde(default)]
+        properties: Option<Vec<String>>,
     },
 
     /// Index lookup (optimized scan)
+    ///
+    /// `filter` is the scan's full predicate, re-checked on fetched entities.
     IndexLookup {
         collection: String,
         alias: String,
         index_name: String,
-        key_values: Vec<Value>,
+        probe: IndexProbe,
+        filter: Option<FilterExpr>,
+        #[serde(default)]
+        properties: Option<Vec<String>>,
     },
 
     /// Graph traversal
+    ///
+    /// Edges of any of `edge_types` (any type when empty) whose properties
+    /// equal every `edge_properties` value are followed. With an
+    /// `edge_alias`, each joined row also binds the edge that reached the
+    /// target, exposing its properties like an entity's. With `top_k`, only
+    /// the k matching edges with the strongest pheromone are followed out of
+    /// each entity.
+    ///
+    /// Each joined row reaches a target once, at its shortest hop distance
+    /// (targets reached from different sources are still distinct rows).
+    /// With `all_paths`, a target is joined once per simple path to it
+    /// instead, so path multiplicity can be counted.
     Traverse {
         source_binding: String,
         direction: TraverseDirection,
-        edge_type: Option<String>,
+        edge_types: Vec<String>,
+        edge_alias: Option<String>,
+        edge_properties: HashMap<String, Value>,
         target_alias: String,
         min_hops: usize,
         max_hops: usize,
+        top_k: Option<usize>,
+        #[serde(default)]
+        all_paths: bool,
         filter: Option<FilterExpr>,
+        /// Properties of each target copied out of storage (all when `None`)
+        #[serde(default)]
+        properties: Option<Vec<String>>,
     },
 
     /// Filter results
@@ -106,21 +481,28 @@ pub enum Operation {
     },
 
     /// Project (select) fields
+    ///
+    /// With `distinct`, repeated rows are dropped before any sort or limit.
     Project {
-        fields: Vec<ProjectField>,
+        fields: Vec<Projection>,
+        distinct: bool,
     },
 
     /// Sort results
+    ///
+    /// With `limit`, only the first `limit` rows of the order are kept
+    /// (a top-k selection instead of a full sort).
     Sort {
         fields: Vec<SortField>,
+        limit: Option<usize>,
     },
 
-    /// Limit results
+    /// Keep the first `count` result rows
     Limit {
         count: usize,
     },
 
-    /// Skip results
+    /// Drop the first `count` result rows (all of them when there are fewer)
     Skip {
         count: usize,
     },
@@ -132,10 +514,10 @@ pub enum Operation {
         condition: FilterExpr,
     },
 
-    /// Insert entity
+    /// Insert entities, one per row, as a single batch
     InsertEntity {
         collection: String,
-        properties: HashMap<String, Value>,
+        rows: Vec<HashMap<String, FilterExpr>>,
     },
 
     /// Update entities
@@ -157,7 +539,22 @@ pub enum Operation {
         properties: HashMap<String, Value>,
     },
 
+    /// Update properties of the matched edges
+    ///
+    /// Unqualified properties in the update expressions read the edge's own.
+    UpdateEdges {
+        edges: EdgeMatch,
+        updates: HashMap<String, FilterExpr>,
+    },
+
+    /// Delete the matched edges
+    DeleteEdges {
+        edges: EdgeMatch,
+    },
+
     /// Group by aggregation
+    ///
+    /// Each aggregate's result is stored in the grouped row under its alias.
     GroupBy {
         group_fields: Vec<FilterExpr>,
         aggregates: Vec<AggregateOp>,
@@ -167,57 +564,99 @@ pub enum Operation {
     Having {
         condition: FilterExpr,
     },
+
+    /// Run a subquery's plan and keep its result for the conditions that
+    /// read it by `id` (IN, EXISTS or a scalar value)
+    ///
+    /// Subqueries run before the rest of the plan.
+    Subquery {
+        id: usize,
+        plan: Box<QueryPlan>,
+    },
 }
 
 impl Operation {
     /// Estimate cost of operation (for optimization)
+    ///
+    /// Filters and traversals don't know which collections their bindings
+    /// come from here; [`QueryPlan::operation_costs`] does.
     pub fn estimate_cost(&self, stats: &GraphStats) -> f32 {
+        self.cost_in(stats, &HashMap::new())
+    }
+
+    /// Estimate cost of operation, given the collection each binding was scanned from
+    fn cost_in(&self, stats: &GraphStats, bindings: &HashMap<&str, &str>) -> f32 {
+        // Rows bound to `binding`, and what's known about their collection
+        let input = |binding: &str| match bindings.get(binding) {
+            Some(collection) => (stats.collection_rows(collection) as f32, stats.for_collection(collection)),
+            None => (stats.entity_count as f32, None),
+        };
+
         match self {
-            Operation::Scan { .. } => {
-                // Table scan is expensive
-                stats.entity_count as f32
+            Operation::Scan { collection, .. } => {
+                // Table scan reads the whole collection
+                stats.collection_rows(collection) as f32
             }
-            Operation::IndexLookup { .. } => {
-                // Index lookup is cheap (log N)
-                (stats.entity_count as f32).log2()
+            Operation::IndexLookup { collection, alias, filter, .. } => {
+                // Index lookup is cheap (log N), plus fetching the matches
+                let rows = stats.collection_rows(collection) as f32;
+                let matches = stats
+                    .for_collection(collection)
+                    .map_or(rows * 0.1, |c| c.estimated_rows(alias, filter.as_ref()));
+                rows.max(1.0).log2() + matches
             }
             Operation::Traverse {
-                min_hops, max_hops, ..
+                source_binding,
+                edge_types,
+                min_hops,
+                max_hops,
+                top_k,
+                ..
             } => {
                 // Traversal cost grows exponentially with hops
-                let avg_degree = if stats.entity_count > 0 {
-                    stats.edge_count as f32 / stats.entity_count as f32
-                } else {
-                    2.0
-                };
-                let avg_hops = (min_hops + max_hops) as f32 / 2.0;
-                avg_degree.powf(avg_hops)
+                let (sources, _) = input(source_binding);
+                let edges = stats.edges_of_types(edge_types) as f32;
+                let avg_degree = if sources > 0.0 { edges / sources } else { 2.0 };
+                let avg_degree = top_k.map_or(avg_degree, |k| avg_degree.min(k as f32));
+                let avg_hops = min_hops.saturating_add(*max_hops) as f32 / 2.0;
+                // Each source visits an entity at most once, which also bounds unbounded hops
+                avg_degree.powf(avg_hops).min(stats.entity_count.max(1) as f32)
             }
-            Operation::Filter { .. } => {
-                // Filter is linear in input size
-                stats.entity_count as f32 * 0.5
+            Operation::Filter { binding, condition } => {
+                // Filter is linear in input size; the rows it keeps are passed on
+                let (rows, collection) = input(binding);
+                let kept = collection.map_or(0.5, |c| c.selectivity(binding, condition));
+                rows * 0.25 * (1.0 + kept)
             }
             Operation::Project { .. } => {
                 // Projection is cheap
                 stats.entity_count as f32 * 0.1
             }
-            Operation::Sort { .. } => {
-                // Sort is N log N
+            Operation::Sort { limit, .. } => {
+                // Sort is N log N; keeping the top K is N log K
                 let n = stats.entity_count as f32;
-                n * n.log2()
+                let k = limit.map_or(n, |k| (k as f32).min(n)).max(2.0);
+                n * k.log2()
             }
             Operation::Limit { .. } | Operation::Skip { .. } => {
                 // Limit/skip are cheap
                 1.0
             }
-            Operation::Join { .. } => {
-                // Join is expensive (N * M)
-                (stats.entity_count as f32).powi(2)
+            Operation::Join { right, condition, .. } => {
+                if condition.equi_join_keys(right).is_empty() {
+                    // Nested loop compares every pair (N * M)
+                    (stats.entity_count as f32).powi(2)
+                } else {
+                    // Hash join builds on one side and probes with the other
+                    stats.entity_count as f32 * 2.0
+                }
             }
-            Operation::InsertEntity { .. } => 10.0,
+            Operation::InsertEntity { rows, .. } => 10.0 * rows.len().max(1) as f32,
             Operation::UpdateEntities { .. } => 20.0,
             Operation::DeleteEntities { .. } => 15.0,
             Operation::CreateEdge { .. } => 12.0,
+            Operation::UpdateEdges { .. } => 15.0,
+            Operation::DeleteEdges { .. } => 12.0,
             Operation::GroupBy { .. } => {
                 // Group by requires sorting/hashing - N log N
                 let n = stats.entity_count as f32;
@@ -227,10 +666,253 @@ impl Operation {
                 // Having is a simple filter on aggregated results
                 stats.entity_count as f32 * 0.1
             }
+            Operation::Subquery { plan, .. } => plan.operation_costs(stats).iter().sum(),
+        }
+    }
+
+    /// Expressions the operation evaluates (not those of a subquery's plan)
+    pub fn expressions(&self) -> Vec<&FilterExpr> {
+        match self {
+            Operation::Scan { filter, .. }
+            | Operation::IndexLookup { filter, .. }
+            | Operation::Traverse { filter, .. } => filter.iter().collect(),
+            Operation::Filter { condition, .. } | Operation::Join { condition, .. } | Operation::Having { condition } => {
+                vec![condition]
+            }
+            Operation::Project { fields, .. } => {
+                fields.iter().filter_map(Projection::as_field).map(|field| &field.expression).collect()
+            }
+            Operation::Sort { fields, .. } => fields.iter().map(|field| &field.expression).collect(),
+            Operation::GroupBy { group_fields, aggregates } => {
+                group_fields.iter().chain(aggregates.iter().map(|aggregate| &aggregate.argument)).collect()
+            }
+            Operation::InsertEntity { rows, .. } => rows.iter().flat_map(|row| row.values()).collect(),
+            Operation::UpdateEntities { updates, .. } => updates.values().collect(),
+            Operation::CreateEdge { source, target, .. } => vec![source, target],
+            Operation::UpdateEdges { edges, updates } => edges.filter.iter().chain(updates.values()).collect(),
+            Operation::DeleteEdges { edges } => edges.filter.iter().collect(),
+            Operation::Limit { .. }
+            | Operation::Skip { .. }
+            | Operation::DeleteEntities { .. }
+            | Operation::Subquery { .. } => Vec::new(),
+        }
+    }
+
+    /// Name of the operation, as shown by EXPLAIN
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Scan { .. } => "Scan",
+            Operation::IndexLookup { .. } => "IndexLookup",
+            Operation::Traverse { .. } => "Traverse",
+            Operation::Filter { .. } => "Filter",
+            Operation::Project { .. } => "Project",
+            Operation::Sort { .. } => "Sort",
+            Operation::Limit { .. } => "Limit",
+            Operation::Skip { .. } => "Skip",
+            Operation::Join { .. } => "Join",
+            Operation::InsertEntity { .. } => "InsertEntity",
+            Operation::UpdateEntities { .. } => "UpdateEntities",
+            Operation::DeleteEntities { .. } => "DeleteEntities",
+            Operation::CreateEdge { .. } => "CreateEdge",
+            Operation::UpdateEdges { .. } => "UpdateEdges",
+            Operation::DeleteEdges { .. } => "DeleteEdges",
+            Operation::GroupBy { .. } => "GroupBy",
+            Operation::Having { .. } => "Having",
+            Operation::Subquery { .. } => "Subquery",
+        }
+    }
+
+    /// One-line summary of the operation's parameters, as shown by EXPLAIN
+    pub fn details(&self) -> String {
+        fn with_filter(text: String, filter: &Option<FilterExpr>) -> String {
+            match filter {
+                Some(filter) => format!("{} WHERE {}", text, filter),
+                None => text,
+            }
+        }
+
+        fn assignments(updates: &HashMap<String, FilterExpr>) -> String {
+            let mut fields: Vec<String> = updates.iter().map(|(field, expr)| format!("{} = {}", field, expr)).collect();
+            fields.sort();
+            fields.join(", ")
+        }
+
+        fn edge_pattern(
+            source: &str,
+            direction: &TraverseDirection,
+            edge_types: &[String],
+            edge_alias: Option<&str>,
+            target: &str,
+            hops: Option<(usize, usize)>,
+        ) -> String {
+            let mut text = format!("{} {:?}", source, direction);
+            match (edge_alias, edge_types.is_empty()) {
+                (Some(alias), _) => text.push_str(&format!(" {}:{}", alias, edge_types.join("|"))),
+                (None, false) => text.push_str(&format!(" {}", edge_types.join("|"))),
+                (None, true) => {}
+            }
+            if let Some((min_hops, max_hops)) = hops {
+                text.push_str(&format!(" {}..{}", min_hops, max_hops));
+            }
+            format!("{} AS {}", text, target)
+        }
+
+        match self {
+            Operation::Scan { collection, alias, filter, .. } => with_filter(format!("{} AS {}", collection, alias), filter),
+            Operation::IndexLookup {
+                collection,
+                alias,
+                index_name,
+                probe,
+                filter,
+                ..
+            } => {
+                let probe = match probe {
+                    IndexProbe::Equal(value) => format!("= {}", value),
+                    IndexProbe::In(values) => {
+                        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                        format!("IN ({})", values.join(", "))
+                    }
+                    IndexProbe::Range { lower, upper } => format!(
+                        "BETWEEN {} AND {}",
+                        lower.as_ref().map_or("-inf".to_string(), |v| v.to_string()),
+                        upper.as_ref().map_or("+inf".to_string(), |v| v.to_string())
+                    ),
+                    IndexProbe::Match(terms) => format!("MATCH '{}'", terms.join(" ")),
+                };
+                with_filter(
+                    format!("{} AS {} USING {} {}", collection, alias, index_name, probe),
+                    filter,
+                )
+            }
+            Operation::Traverse {
+                source_binding,
+                direction,
+                edge_types,
+                edge_alias,
+                target_alias,
+                min_hops,
+                max_hops,
+                top_k,
+                all_paths,
+                filter,
+                ..
+            } => {
+                let mut pattern = edge_pattern(
+                    source_binding,
+                    direction,
+                    edge_types,
+                    edge_alias.as_deref(),
+                    target_alias,
+                    Some((*min_hops, *max_hops)),
+                );
+                if let Some(k) = top_k {
+                    pattern.push_str(&format!(" TOP {} BY PHEROMONE", k));
+                }
+                if *all_paths {
+                    pattern.push_str(" ALL PATHS");
+                }
+                with_filter(pattern, filter)
+            }
+            Operation::Filter { binding, condition } => format!("{}: {}", binding, condition),
+            Operation::Project { fields, distinct } => {
+                let fields = fields
+                    .iter()
+                    .map(|f| match f {
+                        Projection::Field(f) => format!("{} AS {}", f.expression, f.alias),
+                        Projection::Wildcard { binding, .. } => format!("{}.*", binding),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if *distinct {
+                    format!("DISTINCT {}", fields)
+                } else {
+                    fields
+                }
+            }
+            Operation::Sort { fields, limit } => {
+                let keys = fields
+                    .iter()
+                    .map(|f| format!("{} {}", f.column, if f.ascending { "ASC" } else { "DESC" }))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                match limit {
+                    Some(k) => format!("{} (top {})", keys, k),
+                    None => keys,
+                }
+            }
+            Operation::Limit { count } | Operation::Skip { count } => count.to_string(),
+            Operation::Join { left, right, condition } => format!("{} WITH {} ON {}", left, right, condition),
+            Operation::InsertEntity { collection, rows } => match rows.as_slice() {
+                [properties] => format!("{} ({})", collection, assignments(properties)),
+                _ => format!("{} ({} rows)", collection, rows.len()),
+            },
+            Operation::UpdateEntities { binding, updates } => format!("{} SET {}", binding, assignments(updates)),
+            Operation::DeleteEntities { binding } => binding.clone(),
+            Operation::CreateEdge {
+                source,
+                target,
+                edge_type,
+                ..
+            } => format!("{} -[{}]-> {}", source, edge_type, target),
+            Operation::UpdateEdges { edges, updates } => format!(
+                "{} SET {}",
+                with_filter(
+                    edge_pattern(&edges.source_binding, &edges.direction, &edges.edge_types, None, &edges.target_alias, None),
+                    &edges.filter
+                ),
+                assignments(updates)
+            ),
+            Operation::DeleteEdges { edges } => with_filter(
+                edge_pattern(&edges.source_binding, &edges.direction, &edges.edge_types, None, &edges.target_alias, None),
+                &edges.filter,
+            ),
+            Operation::GroupBy {
+                group_fields,
+                aggregates,
+            } => {
+                let keys = group_fields.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", ");
+                let aggregates = aggregates
+                    .iter()
+                    .map(|a| {
+                        let distinct = if a.distinct { "DISTINCT " } else { "" };
+                        format!("{}({}{}) AS {}", a.function.name(), distinct, a.argument, a.alias)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("[{}] {}", keys, aggregates)
+            }
+            Operation::Having { condition } => condition.to_string(),
+            Operation::Subquery { id, plan } => {
+                let steps = plan
+                    .operations
+                    .iter()
+                    .map(|op| format!("{} {}", op.name(), op.details()))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                format!("#{}: {}", id, steps)
+            }
         }
     }
 }
 
+/// Single-hop edges an edge mutation applies to
+///
+/// Edges are followed from each entity of `source_binding`; `filter` is
+/// evaluated against the (source, target) pair bound to their aliases.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeMatch {
+    pub source_binding: String,
+    pub direction: TraverseDirection,
+    pub edge_types: Vec<String>,
+    pub edge_properties: HashMap<String, Value>,
+    pub target_alias: String,
+    pub filter: Option<FilterExpr>,
+}
+
+/// Binding that unqualified properties in UPDATE EDGE ... SET resolve to
+pub const EDGE_BINDING: &str = "_edge";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TraverseDirection {
     Outgoing,
@@ -249,7 +931,7 @@ impl From<Direction> for TraverseDirection {
 }
 
 /// Filter expression (simplified from AST Expression)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FilterExpr {
     // Logical
     And(Box<FilterExpr>, Box<FilterExpr>),
@@ -263,6 +945,16 @@ pub enum FilterExpr {
     LessThanEq(Box<FilterExpr>, Box<FilterExpr>),
     GreaterThan(Box<FilterExpr>, Box<FilterExpr>),
     GreaterThanEq(Box<FilterExpr>, Box<FilterExpr>),
+    In(Box<FilterExpr>, Vec<Value>),
+    /// The list (first) holds the element (second)
+    Contains(Box<FilterExpr>, Box<FilterExpr>),
+    Like(Box<FilterExpr>, LikePattern),
+    /// True for a missing property as well as an explicit null
+    IsNull(Box<FilterExpr>),
+    /// The text holds every (tokenized) term
+    Match(Box<FilterExpr>, Vec<String>),
+    /// How often the terms occur in the text, for ranking MATCH results
+    Score(Box<FilterExpr>, Vec<String>),
 
     // Arithmetic
     Add(Box<FilterExpr>, Box<FilterExpr>),
@@ -274,6 +966,13 @@ pub enum FilterExpr {
     Aggregate {
         function: AggregateFunc,
         argument: Box<FilterExpr>,
+        distinct: bool,
+    },
+
+    /// Scalar function applied to each row's arguments
+    FunctionCall {
+        function: ScalarFunction,
+        args: Vec<FilterExpr>,
     },
 
     // Values
@@ -281,7 +980,94 @@ pub enum FilterExpr {
         binding: String,
         property: String,
     },
+    /// The bound entity has the property, even if it holds NULL
+    Has {
+        binding: String,
+        property: String,
+    },
     Constant(Value),
+    /// Query parameter, replaced by a constant before execution
+    Parameter(String),
+    /// Shortest path between two bound entities; only valid as a projected field
+    ShortestPath(PathCall),
+
+    // Subqueries, read from the results their Subquery operations materialized
+    /// The value is in the (one-column) result of the subquery with this id
+    InSet(Box<FilterExpr>, usize),
+    /// The subquery returned any row
+    Exists(usize),
+    /// The value of the subquery's only row (NULL when it returned none)
+    ScalarSubquery(usize),
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterExpr::And(l, r) => write!(f, "({} AND {})", l, r),
+            FilterExpr::Or(l, r) => write!(f, "({} OR {})", l, r),
+            FilterExpr::Not(e) => write!(f, "NOT {}", e),
+            FilterExpr::Equal(l, r) => write!(f, "{} = {}", l, r),
+            FilterExpr::NotEqual(l, r) => write!(f, "{} != {}", l, r),
+            FilterExpr::LessThan(l, r) => write!(f, "{} < {}", l, r),
+            FilterExpr::LessThanEq(l, r) => write!(f, "{} <= {}", l, r),
+            FilterExpr::GreaterThan(l, r) => write!(f, "{} > {}", l, r),
+            FilterExpr::GreaterThanEq(l, r) => write!(f, "{} >= {}", l, r),
+            FilterExpr::In(e, values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "{} IN ({})", e, values.join(", "))
+            }
+            FilterExpr::Contains(list, element) => write!(f, "{} IN {}", element, list),
+            FilterExpr::Like(e, pattern) => write!(f, "{} LIKE '{}'", e, pattern.pattern),
+            FilterExpr::IsNull(e) => write!(f, "{} IS NULL", e),
+            FilterExpr::Match(e, terms) => write!(f, "MATCH({}, '{}')", e, terms.join(" ")),
+            FilterExpr::Score(e, terms) => write!(f, "SCORE({}, '{}')", e, terms.join(" ")),
+            FilterExpr::Add(l, r) => write!(f, "({} + {})", l, r),
+            FilterExpr::Subtract(l, r) => write!(f, "({} - {})", l, r),
+            FilterExpr::Multiply(l, r) => write!(f, "({} * {})", l, r),
+            FilterExpr::Divide(l, r) => write!(f, "({} / {})", l, r),
+            FilterExpr::FunctionCall { function, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", function, args.join(", "))
+            }
+            FilterExpr::Aggregate {
+                function,
+                argument,
+                distinct,
+            } => write!(f, "{}({}{})", function.name(), if *distinct { "DISTINCT " } else { "" }, argument),
+            FilterExpr::Property { binding, property } => match parse_degree_property(property) {
+                Some((direction, edge_type)) => {
+                    let name = match direction {
+                        Direction::Outgoing => "OUT_DEGREE",
+                        Direction::Incoming => "IN_DEGREE",
+                        Direction::Both => "DEGREE",
+                    };
+                    match edge_type {
+                        Some(edge_type) => write!(f, "{}({}, '{}')", name, binding, edge_type),
+                        None => write!(f, "{}({})", name, binding),
+                    }
+                }
+                None => write!(f, "{}.{}", binding, property),
+            },
+            FilterExpr::Has { binding, property } => write!(f, "HAS({}.{})", binding, property),
+            FilterExpr::Constant(value) => write!(f, "{}", value),
+            FilterExpr::Parameter(name) if name == NOW_PARAMETER => write!(f, "NOW()"),
+            FilterExpr::Parameter(name) => write!(f, "${}", name),
+            FilterExpr::ShortestPath(call) => {
+                let name = if call.length_only { "SHORTEST_PATH_LENGTH" } else { "SHORTEST_PATH" };
+                write!(f, "{}({}, {}", name, call.source, call.target)?;
+                if let Some(edge_type) = &call.edge_type {
+                    write!(f, ", :{}", edge_type)?;
+                }
+                if let Some(depth) = call.max_depth {
+                    write!(f, ", MAX {}", depth)?;
+                }
+                write!(f, ")")
+            }
+            FilterExpr::InSet(e, id) => write!(f, "{} IN (subquery #{})", e, id),
+            FilterExpr::Exists(id) => write!(f, "EXISTS (subquery #{})", id),
+            FilterExpr::ScalarSubquery(id) => write!(f, "(subquery #{})", id),
+        }
+    }
 }
 
 impl FilterExpr {
@@ -323,6 +1109,34 @@ impl FilterExpr {
                 Box::new(Self::from_ast(l, default_binding)),
                 Box::new(Self::from_ast(r, default_binding)),
             ),
+            Expression::In(e, values) => FilterExpr::In(
+                Box::new(Self::from_ast(e, default_binding)),
+                values.iter().map(Value::from_literal).collect(),
+            ),
+            Expression::Contains(list, element) => FilterExpr::Contains(
+                Box::new(Self::from_ast(list, default_binding)),
+                Box::new(Self::from_ast(element, default_binding)),
+            ),
+            // Reversed bounds match nothing, as in SQL
+            Expression::Between(e, low, high) => {
+                let e = Self::from_ast(e, default_binding);
+                FilterExpr::And(
+                    Box::new(FilterExpr::GreaterThanEq(
+                        Box::new(e.clone()),
+                        Box::new(Self::from_ast(low, default_binding)),
+                    )),
+                    Box::new(FilterExpr::LessThanEq(
+                        Box::new(e),
+                        Box::new(Self::from_ast(high, default_binding)),
+                    )),
+                )
+            }
+            Expression::Like(e, pattern) => FilterExpr::Like(
+                Box::new(Self::from_ast(e, default_binding)),
+                LikePattern::new(pattern),
+            ),
+            Expression::IsNull(e) => FilterExpr::IsNull(Box::new(Self::from_ast(e, default_binding))),
+            Expression::Match(e, text) => FilterExpr::Match(Box::new(Self::from_ast(e, default_binding)), tokenize(text)),
             Expression::Add(l, r) => FilterExpr::Add(
                 Box::new(Self::from_ast(l, default_binding)),
                 Box::new(Self::from_ast(r, default_binding)),
@@ -346,13 +1160,600 @@ impl FilterExpr {
                     .unwrap_or_else(|| default_binding.to_string()),
                 property: prop_ref.property.clone(),
             },
+            Expression::Has(prop_ref) => FilterExpr::Has {
+                binding: prop_ref
+                    .entity
+                    .clone()
+                    .unwrap_or_else(|| default_binding.to_string()),
+                property: prop_ref.property.clone(),
+            },
             Expression::Literal(lit) => FilterExpr::Constant(Value::from_literal(lit)),
-            Expression::Aggregate(func, arg) => FilterExpr::Aggregate {
+            Expression::Parameter(name) => FilterExpr::Parameter(name.clone()),
+            Expression::ShortestPath(call) => FilterExpr::ShortestPath(call.clone()),
+            // Only valid as a SELECT field, where the plan builder expands it
+            Expression::Wildcard(_) => FilterExpr::Constant(Value::Null),
+            Expression::Aggregate(func, arg, distinct) => FilterExpr::Aggregate {
                 function: func.into(),
                 argument: Box::new(Self::from_ast(arg, default_binding)),
+                distinct: *distinct,
+            },
+            Expression::Function(function, args) => FilterExpr::FunctionCall {
+                function: *function,
+                args: args.iter().map(|arg| Self::from_ast(arg, default_binding)).collect(),
+            },
+            // The plan builder adds an operation running each subquery
+            Expression::InSubquery(e, subquery) => {
+                FilterExpr::InSet(Box::new(Self::from_ast(e, default_binding)), subquery.id)
+            }
+            Expression::Exists(subquery) => FilterExpr::Exists(subquery.id),
+            Expression::ScalarSubquery(subquery) => FilterExpr::ScalarSubquery(subquery.id),
+        }
+    }
+}
+
+impl FilterExpr {
+    /// Fold arithmetic over constants into a single constant
+    ///
+    /// Sub-expressions that cannot be folded (property references,
+    /// division by zero, mismatched types) are left in place.
+    pub fn fold_constants(&self) -> FilterExpr {
+        match self {
+            FilterExpr::Add(l, r) => {
+                Self::fold_arithmetic(l, r, FilterExpr::Add, i64::checked_add, |a, b| a + b, |a, b| match (a, b) {
+                    (Value::Timestamp(t), Value::Integer(n)) | (Value::Integer(n), Value::Timestamp(t)) => {
+                        t.checked_add(*n).map(Value::Timestamp)
+                    }
+                    _ => None,
+                })
+            }
+            FilterExpr::Subtract(l, r) => {
+                Self::fold_arithmetic(l, r, FilterExpr::Subtract, i64::checked_sub, |a, b| a - b, |a, b| match (a, b) {
+                    (Value::Timestamp(t), Value::Integer(n)) => t.checked_sub(*n).map(Value::Timestamp),
+                    (Value::Timestamp(a), Value::Timestamp(b)) => a.checked_sub(*b).map(Value::Integer),
+                    _ => None,
+                })
+            }
+            FilterExpr::Multiply(l, r) => {
+                Self::fold_arithmetic(l, r, FilterExpr::Multiply, i64::checked_mul, |a, b| a * b, |_, _| None)
+            }
+            FilterExpr::Divide(l, r) => Self::fold_arithmetic(
+                l,
+                r,
+                FilterExpr::Divide,
+                i64::checked_div,
+                |a, b| if b == 0.0 { f64::NAN } else { a / b },
+                |_, _| None,
+            ),
+            other => other.clone(),
+        }
+    }
+
+    fn fold_arithmetic(
+        l: &FilterExpr,
+        r: &FilterExpr,
+        rebuild: fn(Box<FilterExpr>, Box<FilterExpr>) -> FilterExpr,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+        timestamp_op: fn(&Value, &Value) -> Option<Value>,
+    ) -> FilterExpr {
+        let l = l.fold_constants();
+        let r = r.fold_constants();
+
+        let folded = match (&l, &r) {
+            (FilterExpr::Constant(Value::Integer(a)), FilterExpr::Constant(Value::Integer(b))) => {
+                int_op(*a, *b).map(Value::Integer)
+            }
+            (FilterExpr::Constant(a), FilterExpr::Constant(b)) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => Some(float_op(a, b)).filter(|f| f.is_finite()).map(Value::Float),
+                _ => timestamp_op(a, b),
+            },
+            _ => None,
+        };
+
+        match folded {
+            Some(value) => FilterExpr::Constant(value),
+            None => rebuild(Box::new(l), Box::new(r)),
+        }
+    }
+
+    /// Every aggregate call in this expression, outermost first
+    pub fn aggregates(&self) -> Vec<&FilterExpr> {
+        match self {
+            FilterExpr::Aggregate { .. } => vec![self],
+            FilterExpr::Property { .. }
+            | FilterExpr::Has { .. }
+            | FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => Vec::new(),
+            FilterExpr::Not(e)
+            | FilterExpr::IsNull(e)
+            | FilterExpr::In(e, _)
+            | FilterExpr::Like(e, _)
+            | FilterExpr::Match(e, _)
+            | FilterExpr::Score(e, _)
+            | FilterExpr::InSet(e, _) => e.aggregates(),
+            FilterExpr::FunctionCall { args, .. } => args.iter().flat_map(|arg| arg.aggregates()).collect(),
+            FilterExpr::And(l, r)
+            | FilterExpr::Or(l, r)
+            | FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r)
+            | FilterExpr::Contains(l, r)
+            | FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => {
+                let mut found = l.aggregates();
+                found.extend(r.aggregates());
+                found
+            }
+        }
+    }
+
+    /// The AND-ed conjuncts of this expression
+    pub fn conjuncts(self) -> Vec<FilterExpr> {
+        match self {
+            FilterExpr::And(l, r) => {
+                let mut conjuncts = l.conjuncts();
+                conjuncts.extend(r.conjuncts());
+                conjuncts
+            }
+            other => vec![other],
+        }
+    }
+
+    /// AND the conditions together (`None` when there are none)
+    pub fn all(conditions: Vec<FilterExpr>) -> Option<FilterExpr> {
+        conditions
+            .into_iter()
+            .reduce(|l, r| FilterExpr::And(Box::new(l), Box::new(r)))
+    }
+
+    /// Whether this expression references a property of any of the bindings
+    pub fn references_any(&self, bindings: &[String]) -> bool {
+        match self {
+            FilterExpr::Property { binding, .. } | FilterExpr::Has { binding, .. } => bindings.contains(binding),
+            FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => false,
+            FilterExpr::ShortestPath(call) => bindings.contains(&call.source) || bindings.contains(&call.target),
+            FilterExpr::Not(e)
+            | FilterExpr::IsNull(e)
+            | FilterExpr::In(e, _)
+            | FilterExpr::Like(e, _)
+            | FilterExpr::Match(e, _)
+            | FilterExpr::Score(e, _)
+            | FilterExpr::InSet(e, _) => e.references_any(bindings),
+            FilterExpr::Aggregate { argument, .. } => argument.references_any(bindings),
+            FilterExpr::FunctionCall { args, .. } => args.iter().any(|arg| arg.references_any(bindings)),
+            FilterExpr::And(l, r)
+            | FilterExpr::Or(l, r)
+            | FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r)
+            | FilterExpr::Contains(l, r)
+            | FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => l.references_any(bindings) || r.references_any(bindings),
+        }
+    }
+
+    /// Names of the properties this expression reads, of any binding
+    pub fn properties(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_properties(&mut names);
+        names
+    }
+
+    fn collect_properties<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            // HAS reads the property it checks for
+            FilterExpr::Property { property, .. } | FilterExpr::Has { property, .. } => names.push(property),
+            FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => {}
+            FilterExpr::Not(e)
+            | FilterExpr::IsNull(e)
+            | FilterExpr::In(e, _)
+            | FilterExpr::Like(e, _)
+            | FilterExpr::Match(e, _)
+            | FilterExpr::Score(e, _)
+            | FilterExpr::InSet(e, _) => e.collect_properties(names),
+            FilterExpr::Aggregate { argument, .. } => argument.collect_properties(names),
+            FilterExpr::FunctionCall { args, .. } => args.iter().for_each(|arg| arg.collect_properties(names)),
+            FilterExpr::And(l, r)
+            | FilterExpr::Or(l, r)
+            | FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r)
+            | FilterExpr::Contains(l, r)
+            | FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => {
+                l.collect_properties(names);
+                r.collect_properties(names);
+            }
+        }
+    }
+
+    /// Find the first property reference in this expression, if any
+    pub fn find_property(&self) -> Option<(&str, &str)> {
+        match self {
+            FilterExpr::Property { binding, property } | FilterExpr::Has { binding, property } => Some((binding, property)),
+            FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => None,
+            FilterExpr::Not(e)
+            | FilterExpr::IsNull(e)
+            | FilterExpr::In(e, _)
+            | FilterExpr::Like(e, _)
+            | FilterExpr::Match(e, _)
+            | FilterExpr::Score(e, _)
+            | FilterExpr::InSet(e, _) => e.find_property(),
+            FilterExpr::Aggregate { argument, .. } => argument.find_property(),
+            FilterExpr::FunctionCall { args, .. } => args.iter().find_map(|arg| arg.find_property()),
+            FilterExpr::And(l, r)
+            | FilterExpr::Or(l, r)
+            | FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r)
+            | FilterExpr::Contains(l, r)
+            | FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => l.find_property().or_else(|| r.find_property()),
+        }
+    }
+
+    /// The (binding, property) each MATCH in this expression searches
+    pub fn text_matches(&self) -> Vec<(&str, &str)> {
+        match self {
+            FilterExpr::Match(e, _) => match e.as_ref() {
+                FilterExpr::Property { binding, property } => vec![(binding.as_str(), property.as_str())],
+                _ => Vec::new(),
+            },
+            FilterExpr::Property { .. }
+            | FilterExpr::Has { .. }
+            | FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_)
+            | FilterExpr::Score(..) => Vec::new(),
+            FilterExpr::Not(e)
+            | FilterExpr::IsNull(e)
+            | FilterExpr::In(e, _)
+            | FilterExpr::Like(e, _)
+            | FilterExpr::InSet(e, _) => e.text_matches(),
+            FilterExpr::Aggregate { argument, .. } => argument.text_matches(),
+            FilterExpr::FunctionCall { args, .. } => args.iter().flat_map(|arg| arg.text_matches()).collect(),
+            FilterExpr::And(l, r)
+            | FilterExpr::Or(l, r)
+            | FilterExpr::Equal(l, r)
+            | FilterExpr::NotEqual(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r)
+            | FilterExpr::Contains(l, r)
+            | FilterExpr::Add(l, r)
+            | FilterExpr::Subtract(l, r)
+            | FilterExpr::Multiply(l, r)
+            | FilterExpr::Divide(l, r) => {
+                let mut found = l.text_matches();
+                found.extend(r.text_matches());
+                found
+            }
+        }
+    }
+
+    /// Index-servable predicates on `binding`'s fields among the AND-ed conjuncts
+    ///
+    /// Range bounds are inclusive; exclusive comparisons are enforced by
+    /// re-checking the full filter.
+    pub fn index_probes(&self, binding: &str) -> Vec<(String, IndexProbe)> {
+        match self {
+            FilterExpr::And(l, r) => {
+                let mut probes = l.index_probes(binding);
+                probes.extend(r.index_probes(binding));
+                probes
+            }
+            FilterExpr::Equal(l, r)
+            | FilterExpr::LessThan(l, r)
+            | FilterExpr::LessThanEq(l, r)
+            | FilterExpr::GreaterThan(l, r)
+            | FilterExpr::GreaterThanEq(l, r) => {
+                // Normalise to `property op constant`
+                let (field, value, flipped) = match (l.as_ref(), r.as_ref()) {
+                    (FilterExpr::Property { binding: b, property }, FilterExpr::Constant(v)) if b == binding => {
+                        (property, v, false)
+                    }
+                    (FilterExpr::Constant(v), FilterExpr::Property { binding: b, property }) if b == binding => {
+                        (property, v, true)
+                    }
+                    _ => return Vec::new(),
+                };
+                // A timestamp also compares with ISO strings, which the index orders apart
+                if matches!(value, Value::Null | Value::Timestamp(_)) {
+                    return Vec::new();
+                }
+
+                let lower = || IndexProbe::Range { lower: Some(value.clone()), upper: None };
+                let upper = || IndexProbe::Range { lower: None, upper: Some(value.clone()) };
+                let probe = match (self, flipped) {
+                    (FilterExpr::Equal(..), _) => IndexProbe::Equal(value.clone()),
+                    (FilterExpr::GreaterThan(..) | FilterExpr::GreaterThanEq(..), false)
+                    | (FilterExpr::LessThan(..) | FilterExpr::LessThanEq(..), true) => lower(),
+                    _ => upper(),
+                };
+
+                vec![(field.clone(), probe)]
+            }
+            FilterExpr::Match(e, terms) => match e.as_ref() {
+                FilterExpr::Property { binding: b, property } if b == binding => {
+                    vec![(property.clone(), IndexProbe::Match(terms.clone()))]
+                }
+                _ => Vec::new(),
+            },
+            FilterExpr::In(e, values) => match e.as_ref() {
+                FilterExpr::Property { binding: b, property }
+                    if b == binding && !values.iter().any(|v| matches!(v, Value::Null | Value::Timestamp(_))) =>
+                {
+                    vec![(property.clone(), IndexProbe::In(values.clone()))]
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Hash-join keys among the AND-ed conjuncts: equalities between a
+    /// property of `right` and a property of another binding
+    ///
+    /// Each key is (other binding, its property, `right`'s property).
+    pub fn equi_join_keys(&self, right: &str) -> Vec<(String, FilterExpr, FilterExpr)> {
+        match self {
+            FilterExpr::And(l, r) => {
+                let mut keys = l.equi_join_keys(right);
+                keys.extend(r.equi_join_keys(right));
+                keys
+            }
+            FilterExpr::Equal(l, r) => match (l.as_ref(), r.as_ref()) {
+                (FilterExpr::Property { binding: lb, .. }, FilterExpr::Property { binding: rb, .. })
+                    if lb != right && rb == right =>
+                {
+                    vec![(lb.clone(), (**l).clone(), (**r).clone())]
+                }
+                (FilterExpr::Property { binding: lb, .. }, FilterExpr::Property { binding: rb, .. })
+                    if lb == right && rb != right =>
+                {
+                    vec![(rb.clone(), (**r).clone(), (**l).clone())]
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Replace each property reference with the expression `resolve` returns for it
+    ///
+    /// HAS checks are left in place; see [`substitute_references`](Self::substitute_references).
+    pub fn substitute_properties<F>(&self, resolve: &F) -> FilterExpr
+    where
+        F: Fn(&str, &str) -> FilterExpr,
+    {
+        self.substitute_references(&|expr| match expr {
+            FilterExpr::Property { binding, property } => Some(resolve(binding, property)),
+            _ => None,
+        })
+    }
+
+    /// Replace each reference to a binding (a property or a HAS check) with
+    /// the expression `resolve` returns for it, where it returns one
+    ///
+    /// Used to evaluate an expression against a joined row, where each
+    /// binding refers to a different entity.
+    pub fn substitute_references<F>(&self, resolve: &F) -> FilterExpr
+    where
+        F: Fn(&FilterExpr) -> Option<FilterExpr>,
+    {
+        let binary = |l: &FilterExpr, r: &FilterExpr, rebuild: fn(Box<FilterExpr>, Box<FilterExpr>) -> FilterExpr| {
+            rebuild(Box::new(l.substitute_references(resolve)), Box::new(r.substitute_references(resolve)))
+        };
+
+        match self {
+            FilterExpr::Property { .. } | FilterExpr::Has { .. } => resolve(self).unwrap_or_else(|| self.clone()),
+            FilterExpr::Constant(_)
+            | FilterExpr::Parameter(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => self.clone(),
+            FilterExpr::Not(e) => FilterExpr::Not(Box::new(e.substitute_references(resolve))),
+            FilterExpr::InSet(e, id) => FilterExpr::InSet(Box::new(e.substitute_references(resolve)), *id),
+            FilterExpr::IsNull(e) => FilterExpr::IsNull(Box::new(e.substitute_references(resolve))),
+            FilterExpr::In(e, values) => FilterExpr::In(Box::new(e.substitute_references(resolve)), values.clone()),
+            FilterExpr::Like(e, pattern) => FilterExpr::Like(Box::new(e.substitute_references(resolve)), pattern.clone()),
+            FilterExpr::Match(e, terms) => FilterExpr::Match(Box::new(e.substitute_references(resolve)), terms.clone()),
+            FilterExpr::Score(e, terms) => FilterExpr::Score(Box::new(e.substitute_references(resolve)), terms.clone()),
+            FilterExpr::Aggregate { function, argument, distinct } => FilterExpr::Aggregate {
+                function: function.clone(),
+                argument: Box::new(argument.substitute_references(resolve)),
+                distinct: *distinct,
+            },
+            FilterExpr::FunctionCall { function, args } => FilterExpr::FunctionCall {
+                function: *function,
+                args: args.iter().map(|arg| arg.substitute_references(resolve)).collect(),
+            },
+            FilterExpr::And(l, r) => binary(l, r, FilterExpr::And),
+            FilterExpr::Or(l, r) => binary(l, r, FilterExpr::Or),
+            FilterExpr::Equal(l, r) => binary(l, r, FilterExpr::Equal),
+            FilterExpr::NotEqual(l, r) => binary(l, r, FilterExpr::NotEqual),
+            FilterExpr::LessThan(l, r) => binary(l, r, FilterExpr::LessThan),
+            FilterExpr::LessThanEq(l, r) => binary(l, r, FilterExpr::LessThanEq),
+            FilterExpr::GreaterThan(l, r) => binary(l, r, FilterExpr::GreaterThan),
+            FilterExpr::GreaterThanEq(l, r) => binary(l, r, FilterExpr::GreaterThanEq),
+            FilterExpr::Contains(l, r) => binary(l, r, FilterExpr::Contains),
+            FilterExpr::Add(l, r) => binary(l, r, FilterExpr::Add),
+            FilterExpr::Subtract(l, r) => binary(l, r, FilterExpr::Subtract),
+            FilterExpr::Multiply(l, r) => binary(l, r, FilterExpr::Multiply),
+            FilterExpr::Divide(l, r) => binary(l, r, FilterExpr::Divide),
+        }
+    }
+
+    /// Replace each parameter with its value from `params`
+    ///
+    /// A parameter without a value is an error.
+    pub fn bind_parameters(&self, params: &HashMap<String, Value>) -> Result<FilterExpr, String> {
+        let binary = |l: &FilterExpr,
+                      r: &FilterExpr,
+                      rebuild: fn(Box<FilterExpr>, Box<FilterExpr>) -> FilterExpr|
+         -> Result<FilterExpr, String> {
+            Ok(rebuild(Box::new(l.bind_parameters(params)?), Box::new(r.bind_parameters(params)?)))
+        };
+
+        let bound = match self {
+            FilterExpr::Parameter(name) => match params.get(name) {
+                Some(value) => FilterExpr::Constant(value.clone()),
+                None => return Err(format!("Missing value for parameter '{}'", name)),
+            },
+            FilterExpr::Property { .. }
+            | FilterExpr::Has { .. }
+            | FilterExpr::Constant(_)
+            | FilterExpr::ShortestPath(_)
+            | FilterExpr::Exists(_)
+            | FilterExpr::ScalarSubquery(_) => self.clone(),
+            FilterExpr::Not(e) => FilterExpr::Not(Box::new(e.bind_parameters(params)?)),
+            FilterExpr::InSet(e, id) => FilterExpr::InSet(Box::new(e.bind_parameters(params)?), *id),
+            FilterExpr::IsNull(e) => FilterExpr::IsNull(Box::new(e.bind_parameters(params)?)),
+            FilterExpr::In(e, values) => FilterExpr::In(Box::new(e.bind_parameters(params)?), values.clone()),
+            FilterExpr::Like(e, pattern) => FilterExpr::Like(Box::new(e.bind_parameters(params)?), pattern.clone()),
+            FilterExpr::Match(e, terms) => FilterExpr::Match(Box::new(e.bind_parameters(params)?), terms.clone()),
+            FilterExpr::Score(e, terms) => FilterExpr::Score(Box::new(e.bind_parameters(params)?), terms.clone()),
+            FilterExpr::Aggregate { function, argument, distinct } => FilterExpr::Aggregate {
+                function: function.clone(),
+                argument: Box::new(argument.bind_parameters(params)?),
+                distinct: *distinct,
             },
+            FilterExpr::FunctionCall { function, args } => FilterExpr::FunctionCall {
+                function: *function,
+                args: args.iter().map(|arg| arg.bind_parameters(params)).collect::<Result<_, _>>()?,
+            },
+            FilterExpr::And(l, r) => binary(l, r, FilterExpr::And)?,
+            FilterExpr::Or(l, r) => binary(l, r, FilterExpr::Or)?,
+            FilterExpr::Equal(l, r) => binary(l, r, FilterExpr::Equal)?,
+            FilterExpr::NotEqual(l, r) => binary(l, r, FilterExpr::NotEqual)?,
+            FilterExpr::LessThan(l, r) => binary(l, r, FilterExpr::LessThan)?,
+            FilterExpr::LessThanEq(l, r) => binary(l, r, FilterExpr::LessThanEq)?,
+            FilterExpr::GreaterThan(l, r) => binary(l, r, FilterExpr::GreaterThan)?,
+            FilterExpr::GreaterThanEq(l, r) => binary(l, r, FilterExpr::GreaterThanEq)?,
+            FilterExpr::Contains(l, r) => binary(l, r, FilterExpr::Contains)?,
+            FilterExpr::Add(l, r) => binary(l, r, FilterExpr::Add)?,
+            FilterExpr::Subtract(l, r) => binary(l, r, FilterExpr::Subtract)?,
+            FilterExpr::Multiply(l, r) => binary(l, r, FilterExpr::Multiply)?,
+            FilterExpr::Divide(l, r) => binary(l, r, FilterExpr::Divide)?,
+        };
+
+        Ok(bound)
+    }
+}
+
+/// LIKE pattern, compiled once when the query is planned
+///
+/// `%` matches any run of characters (including none) and `_` exactly one.
+/// Matching is case-sensitive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LikePattern {
+    pub pattern: String,
+    tokens: Vec<LikeToken>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum LikeToken {
+    Char(char),
+    AnyChar,
+    AnyRun,
+}
+
+impl LikePattern {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        for c in pattern.chars() {
+            match c {
+                // Consecutive `%` are one run
+                '%' if tokens.last() == Some(&LikeToken::AnyRun) => {}
+                '%' => tokens.push(LikeToken::AnyRun),
+                '_' => tokens.push(LikeToken::AnyChar),
+                c => tokens.push(LikeToken::Char(c)),
+            }
+        }
+
+        LikePattern {
+            pattern: pattern.to_string(),
+            tokens,
         }
     }
+
+    /// Whether the whole of `text` matches the pattern
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let (mut t, mut p) = (0, 0);
+        // Where to resume after the last `%`: (pattern position, text position)
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(LikeToken::AnyRun) => {
+                    p += 1;
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                Some(LikeToken::AnyChar) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                Some(LikeToken::Char(c)) if *c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // Mismatch: let the last `%` swallow one more character
+            match backtrack {
+                Some((resume, swallowed)) => {
+                    p = resume;
+                    t = swallowed + 1;
+                    backtrack = Some((resume, t));
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[p..].iter().all(|token| *token == LikeToken::AnyRun)
+    }
 }
 
 /// Projection field
@@ -362,13 +1763,40 @@ pub struct ProjectField {
     pub alias: String,
 }
 
+/// One item of a projection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Projection {
+    Field(ProjectField),
+    /// Every property of the entity bound to `binding`, and its `@id`, as
+    /// found at execution time; columns are named `binding.property` when
+    /// `prefixed`
+    Wildcard { binding: String, prefixed: bool },
+}
+
+impl Projection {
+    /// The explicit field, if this isn't a wildcard
+    pub fn as_field(&self) -> Option<&ProjectField> {
+        match self {
+            Projection::Field(field) => Some(field),
+            Projection::Wildcard { .. } => None,
+        }
+    }
+}
+
 /// Sort field
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SortField {
     pub expression: FilterExpr,
+    /// Projected column holding the sort key
+    pub column: String,
+    /// Whether the column was added only for sorting and is dropped afterwards
+    pub hidden: bool,
     pub ascending: bool,
 }
 
+/// Alias prefix for columns projected only to carry ORDER BY keys
+pub const HIDDEN_SORT_COLUMN_PREFIX: &str = "__sort_";
+
 /// Runtime value
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Value {
@@ -377,8 +1805,47 @@ pub enum Value {
     Integer(i64),
     Float(f64),
     String(String),
+    Bytes(Vec<u8>),
     EntityId(u64),
     EdgeId(u64),
+    /// Entity ids along a path, source first
+    Path(Vec<u64>),
+    List(Vec<Value>),
+    /// Nested document
+    Map(BTreeMap<String, Value>),
+    /// Milliseconds since the Unix epoch, UTC
+    Timestamp(i64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "'{}'", s),
+            Value::Bytes(bytes) => {
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "x'{}'", hex)
+            }
+            Value::EntityId(id) => write!(f, "entity:{}", id),
+            Value::EdgeId(id) => write!(f, "edge:{}", id),
+            Value::Path(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "path:[{}]", ids.join(" -> "))
+            }
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Map(map) => {
+                let entries: Vec<String> = map.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Value::Timestamp(ms) => write!(f, "TIMESTAMP '{}'", crate::types::format_timestamp(*ms)),
+        }
+    }
 }
 
 impl Value {
@@ -389,6 +1856,144 @@ impl Value {
             Literal::Integer(n) => Value::Integer(*n),
             Literal::Float(f) => Value::Float(*f),
             Literal::String(s) => Value::String(s.clone()),
+            Literal::List(items) => Value::List(items.iter().map(Value::from_literal).collect()),
+            Literal::Map(entries) => Value::Map(
+                entries.iter().map(|(key, value)| (key.clone(), Value::from_literal(value))).collect(),
+            ),
+            Literal::Timestamp(ms) => Value::Timestamp(*ms),
+        }
+    }
+
+    /// Total ordering used by ORDER BY
+    ///
+    /// Numbers compare numerically across Integer and Float; different types
+    /// order as Bool < numbers < Timestamp < String < Bytes < EntityId <
+    /// EdgeId < Path < List < Map, and Null sorts after everything else (last
+    /// ascending, first descending). Paths order by hop count, lists element by element.
+    pub fn sort_cmp(&self, other: &Value) -> std::cmp::Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Bool(_) => 0,
+                Value::Integer(_) | Value::Float(_) => 1,
+                Value::Timestamp(_) => 2,
+                Value::String(_) => 3,
+                Value::Bytes(_) => 4,
+                Value::EntityId(_) => 5,
+                Value::EdgeId(_) => 6,
+                Value::Path(_) => 7,
+                Value::List(_) => 8,
+                Value::Map(_) => 9,
+                Value::Null => 10,
+            }
+        }
+
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) | (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::EntityId(a), Value::EntityId(b)) | (Value::EdgeId(a), Value::EdgeId(b)) => a.cmp(b),
+            (Value::Path(a), Value::Path(b)) => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+            (Value::List(a), Value::List(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| x.sort_cmp(y))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Value::Map(a), Value::Map(b)) => a
+                .iter()
+                .zip(b)
+                .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| va.sort_cmp(vb)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                _ => rank(a).cmp(&rank(b)),
+            },
+        }
+    }
+
+    /// Numeric view of the value (integers widen to float)
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(n) => Some(*n as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Hashable identity used by SELECT DISTINCT
+    ///
+    /// Follows [`PropertyValue::distinct_key`]: `1` and `1.0` are the same
+    /// value, as are `0.0` and `-0.0`, and every NaN equals every other NaN.
+    /// Entity and edge IDs never equal a number.
+    pub fn distinct_key(&self) -> DistinctKey {
+        match self {
+            Value::Null => DistinctKey::Null,
+            Value::Bool(b) => DistinctKey::Bool(*b),
+            Value::Integer(n) => DistinctKey::Int(*n),
+            Value::Float(f) => PropertyValue::Float(*f).distinct_key(),
+            Value::String(s) => DistinctKey::String(s.clone()),
+            Value::Bytes(bytes) => DistinctKey::Bytes(bytes.clone()),
+            Value::EntityId(id) => DistinctKey::EntityId(*id),
+            Value::EdgeId(id) => DistinctKey::EdgeId(*id),
+            Value::Path(ids) => DistinctKey::Path(ids.clone()),
+            Value::List(items) => DistinctKey::List(items.iter().map(Value::distinct_key).collect()),
+            Value::Map(map) => DistinctKey::Map(map.iter().map(|(k, v)| (k.clone(), v.distinct_key())).collect()),
+            Value::Timestamp(ms) => DistinctKey::Timestamp(*ms),
+        }
+    }
+
+    /// Type of the value, as reported in result column metadata
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Null => ValueType::Null,
+            Value::Bool(_) => ValueType::Bool,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Float(_) => ValueType::Float,
+            Value::String(_) => ValueType::String,
+            Value::Bytes(_) => ValueType::Bytes,
+            Value::EntityId(_) => ValueType::EntityId,
+            Value::EdgeId(_) => ValueType::EdgeId,
+            Value::Path(_) => ValueType::Path,
+            Value::List(_) => ValueType::List,
+            Value::Map(_) => ValueType::Map,
+            Value::Timestamp(_) => ValueType::Timestamp,
+        }
+    }
+}
+
+/// Type of a result column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueType {
+    /// No non-null value was produced (or there were no rows)
+    Null,
+    Bool,
+    Integer,
+    Float,
+    String,
+    Bytes,
+    EntityId,
+    EdgeId,
+    Path,
+    List,
+    Map,
+    Timestamp,
+    /// Rows disagree on the type
+    Mixed,
+}
+
+impl ValueType {
+    /// Combined type of a column holding values of both types
+    ///
+    /// Null adopts the other type and Integer widens to Float; any other
+    /// disagreement is Mixed.
+    pub fn merge(self, other: ValueType) -> ValueType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ValueType::Null, t) | (t, ValueType::Null) => t,
+            (ValueType::Integer, ValueType::Float) | (ValueType::Float, ValueType::Integer) => ValueType::Float,
+            _ => ValueType::Mixed,
         }
     }
 }
@@ -396,18 +2001,36 @@ impl Value {
 /// Query plan builder - converts AST to IR
 pub struct QueryPlanBuilder {
     next_binding_id: usize,
+    /// Bindings of the queries enclosing the subquery being planned
+    enclosing_bindings: Vec<String>,
 }
 
 impl QueryPlanBuilder {
     pub fn new() -> Self {
         QueryPlanBuilder {
             next_binding_id: 0,
+            enclosing_bindings: Vec::new(),
         }
     }
 
     /// Build execution plan from SELECT query
+    ///
+    /// Rows pass through the stages in a fixed order: scan, joins and
+    /// traversals, filter, group, project, sort, then OFFSET and LIMIT, so
+    /// pages always count sorted result rows.
     pub fn build_select(&mut self, query: &SelectQuery) -> Result<QueryPlan, String> {
-        let mut operations = Vec::new();
+        let elsewhere = query
+            .joins
+            .iter()
+            .map(|join| &join.condition)
+            .chain(query.select.fields.iter().map(|field| &field.expression))
+            .chain(query.group_by.iter().flat_map(|group_by| &group_by.fields))
+            .chain(query.having.iter().map(|having| &having.condition))
+            .chain(query.order_by.iter().flat_map(|order_by| order_by.fields.iter().map(|f| &f.expression)));
+        Self::reject_subqueries(elsewhere)?;
+
+        // Step 0: subqueries in WHERE run first, so the condition can read their results
+        let mut operations = self.where_subqueries(query.where_clause.as_ref(), Self::select_bindings(query))?;
 
         // Step 1: FROM clause - scan or index lookup
         let from_binding = query
@@ -416,48 +2039,125 @@ impl QueryPlanBuilder {
             .clone()
             .unwrap_or_else(|| query.from.collection.clone());
 
+        // Joined rows are filtered after the joins; without joins WHERE
+        // filters the scan
+        let scan_where = query.where_clause.as_ref().filter(|_| query.joins.is_empty());
+
         // Check if WHERE can use index (simple optimization)
-        if let Some(where_clause) = &query.where_clause {
+        if let Some(where_clause) = scan_where {
             // For now, always use scan (ant colony will optimize later)
             operations.push(Operation::Scan {
                 collection: query.from.collection.clone(),
                 alias: from_binding.clone(),
                 filter: Some(FilterExpr::from_ast(&where_clause.condition, &from_binding)),
+                properties: None,
             });
         } else {
             operations.push(Operation::Scan {
                 collection: query.from.collection.clone(),
                 alias: from_binding.clone(),
                 filter: None,
+                properties: None,
+            });
+        }
+
+        // Step 1b: JOIN clauses, each scanning its collection and matching
+        // it against the rows so far
+        let mut bindings = vec![from_binding.clone()];
+        for join in &query.joins {
+            let binding = join.alias.clone().unwrap_or_else(|| join.collection.clone());
+            if bindings.contains(&binding) {
+                return Err(format!("Duplicate binding '{}' in JOIN; give it an alias", binding));
+            }
+
+            operations.push(Operation::Scan {
+                collection: join.collection.clone(),
+                alias: binding.clone(),
+                filter: None,
+                properties: None,
+            });
+            operations.push(Operation::Join {
+                left: from_binding.clone(),
+                right: binding.clone(),
+                condition: FilterExpr::from_ast(&join.condition, &from_binding),
+            });
+            bindings.push(binding);
+        }
+
+        if let Some(where_clause) = query.where_clause.as_ref().filter(|_| !query.joins.is_empty()) {
+            operations.push(Operation::Filter {
+                binding: from_binding.clone(),
+                condition: FilterExpr::from_ast(&where_clause.condition, &from_binding),
             });
         }
 
         // Step 2: TRAVERSE clause (if present)
         if let Some(traverse) = &query.traverse {
+            let mut traversed = Vec::new();
             for pattern in &traverse.patterns {
                 let target_binding = pattern
                     .target_alias
                     .clone()
                     .unwrap_or_else(|| self.next_binding());
 
+                for binding in std::iter::once(&target_binding).chain(&pattern.edge_alias) {
+                    if *binding == from_binding || traversed.contains(binding) {
+                        return Err(format!("Duplicate binding '{}' in TRAVERSE", binding));
+                    }
+                    traversed.push(binding.clone());
+                }
+
                 operations.push(Operation::Traverse {
                     source_binding: from_binding.clone(),
                     direction: pattern.direction.clone().into(),
-                    edge_type: pattern.edge_type.clone(),
+                    edge_types: pattern.edge_types.clone(),
+                    edge_alias: pattern.edge_alias.clone(),
+                    edge_properties: edge_properties(pattern),
                     target_alias: target_binding.clone(),
                     min_hops: pattern.min_hops,
                     max_hops: pattern.max_hops,
+                    top_k: pattern.top_k,
+                    all_paths: pattern.all_paths,
                     filter: None, // WHERE filter applied separately
+                    properties: None,
+                });
+                bindings.push(target_binding);
+            }
+
+            // Conditions on traversed targets or edges filter the joined rows
+            let mut joined_where = None;
+            if let Some(Operation::Scan { filter, .. }) = operations.first_mut() {
+                if let Some(condition) = filter.take() {
+                    let (joined, source): (Vec<FilterExpr>, Vec<FilterExpr>) = condition
+                        .conjuncts()
+                        .into_iter()
+                        .partition(|conjunct| conjunct.references_any(&traversed));
+                    *filter = FilterExpr::all(source);
+                    joined_where = FilterExpr::all(joined);
+                }
+            }
+            if let Some(condition) = joined_where {
+                operations.push(Operation::Filter {
+                    binding: from_binding.clone(),
+                    condition,
                 });
             }
         }
 
-        // Step 3: GROUP BY (if present)
-        if let Some(group_by) = &query.group_by {
+        // Step 3: GROUP BY (if present; aggregates without it form one group)
+        let has_aggregates = query
+            .select
+            .fields
+            .iter()
+            .any(|f| matches!(f.expression, Expression::Aggregate(..)));
+        if (query.group_by.is_some() || has_aggregates) && !query.joins.is_empty() {
+            return Err("GROUP BY and aggregates aren't supported with JOIN".to_string());
+        }
+        if query.group_by.is_some() || has_aggregates {
             // Extract aggregate functions from SELECT fields
             let mut aggregates = Vec::new();
             for (idx, field) in query.select.fields.iter().enumerate() {
-                if let Expression::Aggregate(func, arg) = &field.expression {
+                if let Expression::Aggregate(func, arg, distinct) = &field.expression {
                     let alias = field
                         .alias
                         .clone()
@@ -466,14 +2166,35 @@ impl QueryPlanBuilder {
                     aggregates.push(AggregateOp {
                         function: func.into(),
                         argument: FilterExpr::from_ast(arg, &from_binding),
+                        distinct: *distinct,
                         alias,
                     });
                 }
             }
 
-            let group_fields: Vec<FilterExpr> = group_by
-                .fields
+            // HAVING may test aggregates that aren't selected; compute them
+            // into hidden columns
+            if let Some(having) = &query.having {
+                let condition = FilterExpr::from_ast(&having.condition, &from_binding);
+                for aggregate in condition.aggregates() {
+                    let FilterExpr::Aggregate { function, argument, distinct } = aggregate else { continue };
+                    if aggregates.iter().any(|a| a.computes(aggregate)) {
+                        continue;
+                    }
+
+                    aggregates.push(AggregateOp {
+                        function: function.clone(),
+                        argument: (**argument).clone(),
+                        distinct: *distinct,
+                        alias: format!("{}{}", HIDDEN_HAVING_COLUMN_PREFIX, aggregates.len()),
+                    });
+                }
+            }
+
+            let group_fields: Vec<FilterExpr> = query
+                .group_by
                 .iter()
+                .flat_map(|group_by| &group_by.fields)
                 .map(|f| FilterExpr::from_ast(f, &from_binding))
                 .collect();
 
@@ -490,42 +2211,109 @@ impl QueryPlanBuilder {
             });
         }
 
-        // Step 5: PROJECT (SELECT fields)
-        let mut project_fields = Vec::new();
+        // SCORE() ranks rows by the WHERE clause's MATCH
+        let text_match = query
+            .where_clause
+            .as_ref()
+            .map(|w| FilterExpr::from_ast(&w.condition, &from_binding))
+            .and_then(|condition| {
+                condition.conjuncts().into_iter().find_map(|conjunct| match conjunct {
+                    FilterExpr::Match(field, terms) => Some((*field, terms)),
+                    _ => None,
+                })
+            });
+
+        // Step 5: PROJECT (SELECT fields); * expands to every entity binding,
+        // named by alias when there is more than one
+        let mut project_fields: Vec<Projection> = Vec::new();
         for (idx, field) in query.select.fields.iter().enumerate() {
+            if let Expression::Wildcard(binding) = &field.expression {
+                if query.group_by.is_some() || has_aggregates {
+                    return Err("SELECT * can't be combined with GROUP BY or aggregates".to_string());
+                }
+                let expanded = match binding {
+                    None => bindings.clone(),
+                    Some(binding) if bindings.contains(binding) => vec![binding.clone()],
+                    Some(binding) => return Err(format!("Unknown binding '{}' in SELECT {}.*", binding, binding)),
+                };
+                project_fields.extend(expanded.into_iter().map(|binding| Projection::Wildcard {
+                    binding,
+                    prefixed: bindings.len() > 1,
+                }));
+                continue;
+            }
+
             let alias = field
                 .alias
                 .clone()
                 .unwrap_or_else(|| format!("col_{}", idx));
 
-            project_fields.push(ProjectField {
-                expression: FilterExpr::from_ast(&field.expression, &from_binding),
+            if project_fields.iter().filter_map(Projection::as_field).any(|f| f.alias == alias) {
+                return Err(format!("Duplicate column name in SELECT: '{}'", alias));
+            }
+
+            // Paths are found between the entities of a joined row
+            if let Expression::ShortestPath(call) = &field.expression {
+                if bindings.len() < 2 {
+                    return Err("SHORTEST_PATH needs two bindings from JOIN or TRAVERSE".to_string());
+                }
+                if let Some(unknown) = [&call.source, &call.target].into_iter().find(|b| !bindings.contains(b)) {
+                    return Err(format!("Unknown binding '{}' in SHORTEST_PATH", unknown));
+                }
+            }
+
+            project_fields.push(Projection::Field(ProjectField {
+                expression: Self::resolve_score(FilterExpr::from_ast(&field.expression, &from_binding), text_match.as_ref())?,
                 alias,
-            });
+            }));
+        }
+
+        // Step 6: ORDER BY - each key reads a projected column, adding a
+        // hidden one when the expression isn't selected
+        let mut sort_fields = Vec::new();
+        if let Some(order_by) = &query.order_by {
+            for (idx, f) in order_by.fields.iter().enumerate() {
+                let expression = Self::resolve_score(FilterExpr::from_ast(&f.expression, &from_binding), text_match.as_ref())?;
+                let (column, hidden) = match Self::sort_column(&expression, &project_fields) {
+                    Some(column) => (column, false),
+                    // A hidden column would make otherwise equal rows distinct
+                    None if query.select.distinct => {
+                        return Err(format!("ORDER BY {} must be selected when using SELECT DISTINCT", expression));
+                    }
+                    None => {
+                        let column = format!("{}{}", HIDDEN_SORT_COLUMN_PREFIX, idx);
+                        project_fields.push(Projection::Field(ProjectField {
+                            expression: expression.clone(),
+                            alias: column.clone(),
+                        }));
+                        (column, true)
+                    }
+                };
+
+                sort_fields.push(SortField {
+                    expression,
+                    column,
+                    hidden,
+                    ascending: f.ascending,
+                });
+            }
         }
 
         operations.push(Operation::Project {
             fields: project_fields,
+            distinct: query.select.distinct,
         });
 
-        // Step 6: ORDER BY
-        if let Some(order_by) = &query.order_by {
-            let sort_fields: Vec<SortField> = order_by
-                .fields
-                .iter()
-                .map(|f| SortField {
-                    expression: FilterExpr::from_ast(&f.expression, &from_binding),
-                    ascending: f.ascending,
-                })
-                .collect();
-
+        // A LIMIT right after the sort only needs its top OFFSET + LIMIT rows
+        if !sort_fields.is_empty() {
             operations.push(Operation::Sort {
                 fields: sort_fields,
+                limit: query.limit.map(|limit| limit.saturating_add(query.offset.unwrap_or(0))),
             });
         }
 
-        // Step 7: LIMIT/OFFSET
-        if let Some(offset) = query.offset {
+        // Step 7: OFFSET, then LIMIT, whichever order the query wrote them in
+        if let Some(offset) = query.offset.filter(|offset| *offset > 0) {
             operations.push(Operation::Skip { count: offset });
         }
 
@@ -533,20 +2321,76 @@ impl QueryPlanBuilder {
             operations.push(Operation::Limit { count: limit });
         }
 
-        Ok(QueryPlan::new(operations))
+        Ok(QueryPlan::with_projection_pushdown(operations))
+    }
+
+    /// Replace SCORE() in a SELECT or ORDER BY expression with the score of
+    /// the WHERE clause's MATCH, given as (field, terms)
+    fn resolve_score(expression: FilterExpr, text_match: Option<&(FilterExpr, Vec<String>)>) -> Result<FilterExpr, String> {
+        let unmatched = Cell::new(false);
+        let resolved = expression.substitute_properties(&|binding, property| match text_match {
+            _ if property != SCORE_PROPERTY => FilterExpr::Property {
+                binding: binding.to_string(),
+                property: property.to_string(),
+            },
+            Some((field, terms)) => FilterExpr::Score(Box::new(field.clone()), terms.clone()),
+            None => {
+                unmatched.set(true);
+                FilterExpr::Constant(Value::Null)
+            }
+        });
+
+        if unmatched.get() {
+            return Err("SCORE() needs a MATCH in the WHERE clause".to_string());
+        }
+        Ok(resolved)
+    }
+
+    /// Projected column an ORDER BY expression refers to: the same
+    /// expression, or a bare name matching a SELECT alias
+    fn sort_column(expression: &FilterExpr, project_fields: &[Projection]) -> Option<String> {
+        let mut fields = project_fields.iter().filter_map(Projection::as_field);
+        if let Some(field) = fields.clone().find(|f| &f.expression == expression) {
+            return Some(field.alias.clone());
+        }
+
+        match expression {
+            FilterExpr::Property { property, .. } => fields
+                .find(|f| &f.alias == property)
+                .map(|f| f.alias.clone()),
+            _ => None,
+        }
     }
 
     /// Build execution plan from INSERT query
     pub fn build_insert(&mut self, query: &InsertQuery) -> Result<QueryPlan, String> {
-        let mut properties = HashMap::new();
+        let mut rows = Vec::with_capacity(query.rows.len());
 
-        for (key, value) in &query.properties {
-            properties.insert(key.clone(), Value::from_literal(value));
+        for row in &query.rows {
+            let mut properties = HashMap::new();
+
+            Self::reject_subqueries(row.iter().map(|(_, value)| value))?;
+            for (key, value) in row {
+                let expr = FilterExpr::from_ast(value, &query.collection);
+
+                // There is no entity in scope for INSERT values
+                if let Some((_, property)) = expr.find_property() {
+                    return Err(format!(
+                        "INSERT value for '{}' cannot reference property '{}'",
+                        key, property
+                    ));
+                }
+
+                properties.insert(key.clone(), expr.fold_constants());
+            }
+
+            rows.push(properties);
         }
 
+        // All rows go into one operation, so they are inserted as one batch
         let operations = vec![Operation::InsertEntity {
             collection: query.collection.clone(),
-            properties,
+            rows,
         }];
 
         Ok(QueryPlan::new(operations))
@@ -554,9 +2398,13 @@ impl QueryPlanBuilder {
 
     /// Build execution plan from UPDATE query
     pub fn build_update(&mut self, query: &UpdateQuery) -> Result<QueryPlan, String> {
-        let mut operations = Vec::new();
+        Self::reject_subqueries(query.set.iter().map(|(_, expr)| expr))?;
 
-        let binding = query.collection.clone();
+        let binding = query
+            .alias
+            .clone()
+            .unwrap_or_else(|| query.collection.clone());
+        let mut operations = self.where_subqueries(query.where_clause.as_ref(), vec![binding.clone()])?;
 
         // Scan with filter
         operations.push(Operation::Scan {
@@ -566,6 +2414,7 @@ impl QueryPlanBuilder {
                 .where_clause
                 .as_ref()
                 .map(|w| FilterExpr::from_ast(&w.condition, &binding)),
+            properties: None,
         });
 
         // Update
@@ -576,14 +2425,13 @@ impl QueryPlanBuilder {
 
         operations.push(Operation::UpdateEntities { binding, updates });
 
-        Ok(QueryPlan::new(operations))
+        Ok(QueryPlan::with_projection_pushdown(operations))
     }
 
     /// Build execution plan from DELETE query
     pub fn build_delete(&mut self, query: &DeleteQuery) -> Result<QueryPlan, String> {
-        let mut operations = Vec::new();
-
         let binding = query.collection.clone();
+        let mut operations = self.where_subqueries(query.where_clause.as_ref(), vec![binding.clone()])?;
 
         // Scan with filter
         operations.push(Operation::Scan {
@@ -593,16 +2441,19 @@ impl QueryPlanBuilder {
                 .where_clause
                 .as_ref()
                 .map(|w| FilterExpr::from_ast(&w.condition, &binding)),
+            properties: None,
         });
 
         // Delete
         operations.push(Operation::DeleteEntities { binding });
 
-        Ok(QueryPlan::new(operations))
+        Ok(QueryPlan::with_projection_pushdown(operations))
     }
 
     /// Build execution plan from CREATE query
     pub fn build_create(&mut self, query: &CreateQuery) -> Result<QueryPlan, String> {
+        Self::reject_subqueries([&query.source, &query.target])?;
+
         let mut properties = HashMap::new();
 
         for (key, value) in &query.properties {
@@ -619,6 +2470,132 @@ impl QueryPlanBuilder {
         Ok(QueryPlan::new(operations))
     }
 
+    /// Build execution plan from UPDATE EDGE query
+    pub fn build_update_edge(&mut self, query: &UpdateEdgeQuery) -> Result<QueryPlan, String> {
+        let (scan, edges) = self.edge_match(&query.from, &query.pattern, query.where_clause.as_ref())?;
+
+        let mut updates = HashMap::new();
+        for (key, expr) in &query.set {
+            updates.insert(key.clone(), FilterExpr::from_ast(expr, EDGE_BINDING));
+        }
+
+        Ok(QueryPlan::with_projection_pushdown(vec![scan, Operation::UpdateEdges { edges, updates }]))
+    }
+
+    /// Build execution plan from DELETE EDGE query
+    pub fn build_delete_edge(&mut self, query: &DeleteEdgeQuery) -> Result<QueryPlan, String> {
+        let (scan, edges) = self.edge_match(&query.from, &query.pattern, query.where_clause.as_ref())?;
+
+        Ok(QueryPlan::with_projection_pushdown(vec![scan, Operation::DeleteEdges { edges }]))
+    }
+
+    /// Scan of an edge mutation's sources, plus the edges it matches from them
+    fn edge_match(
+        &mut self,
+        from: &FromClause,
+        pattern: &TraversePattern,
+        where_clause: Option<&WhereClause>,
+    ) -> Result<(Operation, EdgeMatch), String> {
+        // The matched edge is what the statement changes; it has no alias
+        if let Some(alias) = &pattern.edge_alias {
+            return Err(format!("Edge alias '{}' is only supported in TRAVERSE", alias));
+        }
+        if pattern.all_paths {
+            return Err("ALL is only supported in TRAVERSE".to_string());
+        }
+        Self::reject_subqueries(where_clause.map(|w| &w.condition))?;
+
+        let source_binding = from.alias.clone().unwrap_or_else(|| from.collection.clone());
+        let target_alias = pattern
+            .target_alias
+            .clone()
+            .unwrap_or_else(|| self.next_binding());
+
+        let scan = Operation::Scan {
+            collection: from.collection.clone(),
+            alias: source_binding.clone(),
+            filter: None,
+            properties: None,
+        };
+        let edges = EdgeMatch {
+            direction: pattern.direction.clone().into(),
+            edge_types: pattern.edge_types.clone(),
+            edge_properties: edge_properties(pattern),
+            target_alias,
+            filter: where_clause.map(|w| FilterExpr::from_ast(&w.condition, &source_binding)),
+            source_binding,
+        };
+
+        Ok((scan, edges))
+    }
+
+    /// Operations running the subqueries of a WHERE condition, which go
+    /// first in the plan; `bindings` are those of the query it filters
+    ///
+    /// A subquery may only read its own bindings: a reference to one of an
+    /// enclosing query (a correlated subquery) is an error.
+    fn where_subqueries(
+        &mut self,
+        where_clause: Option<&WhereClause>,
+        bindings: Vec<String>,
+    ) -> Result<Vec<Operation>, String> {
+        let Some(where_clause) = where_clause else {
+            return Ok(Vec::new());
+        };
+
+        let depth = self.enclosing_bindings.len();
+        self.enclosing_bindings.extend(bindings);
+        let operations = where_clause
+            .condition
+            .subqueries()
+            .into_iter()
+            .map(|subquery| self.build_subquery(subquery))
+            .collect();
+        self.enclosing_bindings.truncate(depth);
+
+        operations
+    }
+
+    fn build_subquery(&mut self, subquery: &Subquery) -> Result<Operation, String> {
+        let plan = self.build_select(&subquery.query)?;
+
+        let own = Self::select_bindings(&subquery.query);
+        let outer: Vec<&String> = self.enclosing_bindings.iter().filter(|b| !own.contains(b)).collect();
+        for expression in plan.operations.iter().flat_map(Operation::expressions) {
+            if let Some(binding) = outer.iter().find(|b| expression.references_any(std::slice::from_ref(**b))) {
+                return Err(format!(
+                    "Correlated subqueries aren't supported: subquery reads '{}' of the enclosing query",
+                    binding
+                ));
+            }
+        }
+
+        Ok(Operation::Subquery {
+            id: subquery.id,
+            plan: Box::new(plan),
+        })
+    }
+
+    /// Bindings a SELECT's clauses can name
+    fn select_bindings(query: &SelectQuery) -> Vec<String> {
+        let mut bindings = vec![query.from.alias.clone().unwrap_or_else(|| query.from.collection.clone())];
+        for join in &query.joins {
+            bindings.push(join.alias.clone().unwrap_or_else(|| join.collection.clone()));
+        }
+        for pattern in query.traverse.iter().flat_map(|traverse| &traverse.patterns) {
+            bindings.extend(pattern.target_alias.iter().chain(&pattern.edge_alias).cloned());
+        }
+        bindings
+    }
+
+    /// Subqueries are only planned in the WHERE of a SELECT, UPDATE or DELETE
+    fn reject_subqueries<'a>(expressions: impl IntoIterator<Item = &'a Expression>) -> Result<(), String> {
+        if expressions.into_iter().any(|expression| !expression.subqueries().is_empty()) {
+            return Err("Subqueries are only supported in WHERE clauses of SELECT, UPDATE and DELETE".to_string());
+        }
+        Ok(())
+    }
+
     fn next_binding(&mut self) -> String {
         let id = self.next_binding_id;
         self.next_binding_id += 1;
@@ -626,6 +2603,15 @@ impl QueryPlanBuilder {
     }
 }
 
+/// Edge property values a traverse pattern requires
+fn edge_properties(pattern: &TraversePattern) -> HashMap<String, Value> {
+    pattern
+        .edge_properties
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from_literal(value)))
+        .collect()
+}
+
 impl Default for QueryPlanBuilder {
     fn default() -> Self {
         Self::new()
@@ -643,6 +2629,7 @@ mod tests {
                 collection: "Users".to_string(),
                 alias: None,
             },
+            joins: Vec::new(),
             traverse: None,
             where_clause: Some(WhereClause {
                 condition: Expression::Equal(
@@ -651,6 +2638,7 @@ mod tests {
                 ),
             }),
             select: SelectClause {
+                distinct: false,
                 fields: vec![SelectField {
                     expression: Expression::property(None, "name"),
                     alias: None,
@@ -676,17 +2664,23 @@ mod tests {
                 collection: "Users".to_string(),
                 alias: Some("u".to_string()),
             },
+            joins: Vec::new(),
             traverse: Some(TraverseClause {
                 patterns: vec![TraversePattern {
                     direction: Direction::Outgoing,
-                    edge_type: Some("PURCHASED".to_string()),
+                    edge_alias: None,
+                    edge_types: vec!["PURCHASED".to_string()],
+                    edge_properties: Vec::new(),
                     target_alias: Some("p".to_string()),
                     min_hops: 1,
                     max_hops: 1,
+                    top_k: None,
+                    all_paths: false,
                 }],
             }),
             where_clause: None,
             select: SelectClause {
+                distinct: false,
                 fields: vec![
                     SelectField {
                         expression: Expression::property(Some("u"), "name"),
@@ -711,4 +2705,93 @@ mod tests {
         // Scan + Traverse + Project + Limit
         assert!(plan.operations.len() >= 3);
     }
+
+    #[test]
+    fn test_build_select_orders_pipeline_stages() {
+        let Query::Select(mut query) = crate::dql_parser::Parser::parse(
+            "FROM Users WHERE age > 20 SELECT city, COUNT(*) AS n GROUP BY city HAVING COUNT(*) > 1 ORDER BY n DESC OFFSET 5 LIMIT 10",
+        )
+        .unwrap() else {
+            panic!("Expected SELECT query");
+        };
+
+        let plan = QueryPlanBuilder::new().build_select(&query).unwrap();
+        let stages: Vec<&str> = plan.operations.iter().map(Operation::name).collect();
+        assert_eq!(stages, ["Scan", "GroupBy", "Having", "Project", "Sort", "Skip", "Limit"]);
+        assert!(matches!(plan.operations[4], Operation::Sort { limit: Some(15), .. }));
+
+        // The top-k bound of a huge page saturates instead of overflowing
+        query.offset = Some(usize::MAX);
+        let plan = QueryPlanBuilder::new().build_select(&query).unwrap();
+        assert!(matches!(plan.operations[4], Operation::Sort { limit: Some(usize::MAX), .. }));
+
+        // OFFSET 0 is no stage at all
+        query.offset = Some(0);
+        let plan = QueryPlanBuilder::new().build_select(&query).unwrap();
+        assert!(!plan.operations.iter().any(|op| matches!(op, Operation::Skip { .. })));
+    }
+
+    #[test]
+    fn test_projection_pushdown_covers_every_referenced_property() {
+        let plan = |text: &str| match crate::dql_parser::Parser::parse(text).unwrap() {
+            Query::Select(query) => QueryPlanBuilder::new().build_select(&query).unwrap(),
+            Query::Update(query) => QueryPlanBuilder::new().build_update(&query).unwrap(),
+            _ => panic!("Expected SELECT or UPDATE"),
+        };
+        let scanned = |plan: &QueryPlan| match &plan.operations[0] {
+            Operation::Scan { properties, .. } => properties.clone(),
+            other => panic!("Expected a scan, got {}", other.name()),
+        };
+        let names = |names: &[&str]| Some(names.iter().map(|name| name.to_string()).collect::<Vec<_>>());
+
+        let narrow = plan("FROM Users WHERE age > 20 SELECT name, city GROUP BY name, city ORDER BY city");
+        assert_eq!(scanned(&narrow), names(&["age", "city", "name"]));
+
+        // Updates read the properties their SET expressions use
+        let update = plan("UPDATE Users SET score = score + bonus WHERE name = 'Alice'");
+        assert_eq!(scanned(&update), names(&["bonus", "name", "score"]));
+
+        // A wildcard needs every property
+        assert_eq!(scanned(&plan("FROM Users WHERE age > 20 SELECT *")), None);
+    }
+
+    #[test]
+    fn test_like_pattern_wildcards() {
+        let starts_with_al = LikePattern::new("Al%");
+        assert!(starts_with_al.matches("Al"));
+        assert!(starts_with_al.matches("Alice"));
+        assert!(!starts_with_al.matches("alice"));
+        assert!(!starts_with_al.matches("Bal"));
+
+        let pattern = LikePattern::new("%a_c%%");
+        assert!(pattern.matches("abc"));
+        assert!(pattern.matches("xxaXcyy"));
+        assert!(pattern.matches("aaac"));
+        assert!(!pattern.matches("ac"));
+
+        assert!(LikePattern::new("%").matches(""));
+        assert!(!LikePattern::new("_").matches(""));
+        assert!(LikePattern::new("é_").matches("éa"));
+    }
+
+    #[test]
+    fn test_distinct_key_equality() {
+        let key = Value::distinct_key;
+
+        // Integral floats are integers; NaNs are all one value; signed zeros are equal
+        assert_eq!(key(&Value::Integer(1)), key(&Value::Float(1.0)));
+        assert_ne!(key(&Value::Integer(1)), key(&Value::Float(1.5)));
+        assert_eq!(key(&Value::Float(f64::NAN)), key(&Value::Float(-f64::NAN)));
+        assert_eq!(key(&Value::Float(0.0)), key(&Value::Float(-0.0)));
+
+        // IDs and timestamps aren't numbers, and NULL is only NULL
+        assert_ne!(key(&Value::EntityId(1)), key(&Value::Integer(1)));
+        assert_ne!(key(&Value::EntityId(1)), key(&Value::EdgeId(1)));
+        assert_ne!(key(&Value::Timestamp(1)), key(&Value::Integer(1)));
+        assert_ne!(key(&Value::Null), key(&Value::String(String::new())));
+        assert_eq!(
+            key(&Value::List(vec![Value::Integer(2)])),
+            key(&Value::List(vec![Value::Float(2.0)]))
+        );
+    }
 }
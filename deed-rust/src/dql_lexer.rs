@@ -11,8 +11,19 @@
 //! SELECT User.name, Product.name, Product.price;
 //! ```
 
+use crate::error::DeedError;
 use std::fmt;
 
+/// Keywords, including the words statements match by name, that a
+/// misspelled identifier may be suggested as
+const KEYWORDS: &[&str] = &[
+    "FROM", "WHERE", "SELECT", "TRAVERSE", "JOIN", "CREATE", "UPDATE", "DELETE", "INSERT", "INTO", "VALUES",
+    "LIMIT", "OFFSET", "ORDER", "GROUP", "HAVING", "DISTINCT", "BETWEEN", "LIKE", "COUNT", "BEGIN", "COMMIT",
+    "ROLLBACK", "TRANSACTION", "ISOLATION", "LEVEL", "INDEX", "UNIQUE", "DROP", "REINDEX", "ARCHIVE",
+    "UNARCHIVE", "FIREWALL", "TRUE", "FALSE", "NULL", "DEFINE", "SCHEMA", "SHOW", "COLLECTIONS", "TRUNCATE",
+    "EXPLAIN", "ANALYZE", "COPY", "SAVEPOINT",
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords
@@ -20,6 +31,7 @@ pub enum Token {
     Where,
     Select,
     Traverse,
+    Join,
     Create,
     Update,
     Delete,
@@ -38,6 +50,11 @@ pub enum Token {
     Desc,
     GroupBy,
     Having,
+    Distinct,
+    In,
+    Between,
+    Like,
+    Is,
 
     // Aggregate functions
     Count,
@@ -59,15 +76,28 @@ pub enum Token {
     Unique,
     Drop,
     On,
+    Reindex,
+
+    // Archival commands
+    Archive,
+    Unarchive,
+
+    // Firewall administration
+    Firewall,
 
     // Literals
     Identifier(String),
+    /// `"text"`: a collection or property name when one is expected,
+    /// otherwise a string like `'text'`
+    QuotedIdentifier(String),
     String(String),
     Integer(i64),
     Float(f64),
     True,
     False,
     Null,
+    /// Query parameter: `$name`, or `?1` (named "1")
+    Parameter(String),
 
     // Operators
     Equal,           // =
@@ -84,6 +114,8 @@ pub enum Token {
     Comma,           // ,
     Semicolon,       // ;
     Colon,           // :
+    Pipe,            // |
+    At,              // @
 
     // Graph operators
     Arrow,           // ->
@@ -106,28 +138,113 @@ impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::Identifier(s) => write!(f, "Identifier({})", s),
+            Token::QuotedIdentifier(s) => write!(f, "QuotedIdentifier(\"{}\")", s),
             Token::String(s) => write!(f, "String(\"{}\")", s),
             Token::Integer(n) => write!(f, "Integer({})", n),
             Token::Float(n) => write!(f, "Float({})", n),
+            Token::Parameter(name) => write!(f, "Parameter({})", name),
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
+impl Token {
+    /// The name a keyword stands for where a property or alias is expected,
+    /// for keywords that are only special in a few positions (`AS count`, `r.level`)
+    pub fn as_name(&self) -> Option<&'static str> {
+        match self {
+            Token::Count => Some("count"),
+            Token::Sum => Some("sum"),
+            Token::Avg => Some("avg"),
+            Token::Min => Some("min"),
+            Token::Max => Some("max"),
+            Token::Isolation => Some("isolation"),
+            Token::Level => Some("level"),
+            Token::Unique => Some("unique"),
+            _ => None,
+        }
+    }
+}
+
+/// Where a token starts in the query text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset into the text
+    pub offset: usize,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position { offset: 0, line: 1, column: 1 }
+    }
+}
+
+/// A token with where it starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub position: Position,
+}
+
+/// The keyword a near-miss identifier was probably meant to be (SELCT -> SELECT)
+pub fn suggest_keyword(word: &str) -> Option<&'static str> {
+    let upper = word.to_uppercase();
+    if upper.len() < 4 || KEYWORDS.contains(&upper.as_str()) {
+        return None;
+    }
+    let allowed = if upper.len() >= 6 { 2 } else { 1 };
+
+    KEYWORDS
+        .iter()
+        .map(|keyword| (edit_distance(&upper, keyword), *keyword))
+        .filter(|(distance, _)| *distance <= allowed)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
+}
+
+/// Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
 pub struct Lexer {
+    source: String,
     input: Vec<char>,
     position: usize,
     current_char: Option<char>,
+    /// Where `current_char` is in the text
+    location: Position,
+    /// Where the token being read (or the comment that failed) starts
+    token_start: Position,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
         let chars: Vec<char> = input.chars().collect();
-        let current = chars.get(0).copied();
+        let current = chars.first().copied();
         Lexer {
+            source: input.to_string(),
             input: chars,
             position: 0,
             current_char: current,
+            location: Position::default(),
+            token_start: Position::default(),
         }
     }
 
@@ -147,17 +264,34 @@ impl Lexer {
         Ok(tokens)
     }
 
-    /// Get the next token
-    pub fn next_token(&mut self) -> Result<Token, String> {
-        // Skip whitespace
-        while let Some(ch) = self.current_char {
-            if ch.is_whitespace() {
-                self.advance();
-            } else {
+    /// Tokenize the entire input, recording where each token starts
+    ///
+    /// Errors point at the start of the token that couldn't be read.
+    pub fn tokenize_with_positions(&mut self) -> Result<Vec<SpannedToken>, DeedError> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = self
+                .next_token()
+                .map_err(|message| DeedError::parse(&self.source, self.token_start, message))?;
+            let done = token == Token::Eof;
+            tokens.push(SpannedToken {
+                token,
+                position: self.token_start,
+            });
+            if done {
                 break;
             }
         }
 
+        Ok(tokens)
+    }
+
+    /// Get the next token
+    pub fn next_token(&mut self) -> Result<Token, String> {
+        self.skip_whitespace_and_comments()?;
+        self.token_start = self.location;
+
         match self.current_char {
             None => Ok(Token::Eof),
             Some(ch) => {
@@ -167,6 +301,8 @@ impl Lexer {
                     self.read_number()
                 } else if ch == '\'' || ch == '"' {
                     self.read_string()
+                } else if ch == '$' || ch == '?' {
+                    self.read_parameter()
                 } else {
                     self.read_operator()
                 }
@@ -175,6 +311,15 @@ impl Lexer {
     }
 
     fn advance(&mut self) {
+        if let Some(ch) = self.current_char {
+            self.location.offset += ch.len_utf8();
+            if ch == '\n' {
+                self.location.line += 1;
+                self.location.column = 1;
+            } else {
+                self.location.column += 1;
+            }
+        }
         self.position += 1;
         self.current_char = self.input.get(self.position).copied();
     }
@@ -202,6 +347,7 @@ impl Lexer {
             "WHERE" => Token::Where,
             "SELECT" => Token::Select,
             "TRAVERSE" => Token::Traverse,
+            "JOIN" => Token::Join,
             "CREATE" => Token::Create,
             "UPDATE" => Token::Update,
             "DELETE" => Token::Delete,
@@ -240,6 +386,11 @@ impl Lexer {
             "ASC" => Token::Asc,
             "DESC" => Token::Desc,
             "HAVING" => Token::Having,
+            "DISTINCT" => Token::Distinct,
+            "IN" => Token::In,
+            "BETWEEN" => Token::Between,
+            "LIKE" => Token::Like,
+            "IS" => Token::Is,
 
             // Aggregate functions
             "COUNT" => Token::Count,
@@ -261,6 +412,14 @@ impl Lexer {
             "UNIQUE" => Token::Unique,
             "DROP" => Token::Drop,
             "ON" => Token::On,
+            "REINDEX" => Token::Reindex,
+
+            // Archival commands
+            "ARCHIVE" => Token::Archive,
+            "UNARCHIVE" => Token::Unarchive,
+
+            // Firewall administration
+            "FIREWALL" => Token::Firewall,
 
             "TRUE" => Token::True,
             "FALSE" => Token::False,
@@ -271,6 +430,37 @@ impl Lexer {
         Ok(token)
     }
 
+    /// Skip whitespace, `-- line` comments and `/* block */` comments
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            match (self.current_char, self.peek()) {
+                (Some('-'), Some('-')) => {
+                    while !matches!(self.current_char, None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    self.token_start = self.location;
+                    self.advance();
+                    self.advance();
+                    loop {
+                        match (self.current_char, self.peek()) {
+                            (None, _) => return Err("Unterminated block comment".to_string()),
+                            (Some('*'), Some('/')) => {
+                                self.advance();
+                                self.advance();
+                                break;
+                            }
+                            _ => self.advance(),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.current_char {
             if ch.is_whitespace() {
@@ -366,9 +556,17 @@ impl Lexer {
             } else if ch == '\\' {
                 escaped = true;
                 self.advance();
+            } else if ch == quote_char && self.peek() == Some(quote_char) {
+                // A doubled quote is a literal one: 'O''Brien'
+                result.push(ch);
+                self.advance();
+                self.advance();
             } else if ch == quote_char {
                 self.advance(); // skip closing quote
-                return Ok(Token::String(result));
+                return Ok(match quote_char {
+                    '"' => Token::QuotedIdentifier(result),
+                    _ => Token::String(result),
+                });
             } else {
                 result.push(ch);
                 self.advance();
@@ -378,6 +576,31 @@ impl Lexer {
         Err("Unterminated string literal".to_string())
     }
 
+    /// Read `$name` (identifier characters) or `?N` (digits)
+    fn read_parameter(&mut self) -> Result<Token, String> {
+        let sigil = self.current_char.unwrap();
+        self.advance();
+
+        let mut name = String::new();
+        while let Some(ch) = self.current_char {
+            let valid = match sigil {
+                '$' => ch.is_alphanumeric() || ch == '_',
+                _ => ch.is_ascii_digit(),
+            };
+            if !valid {
+                break;
+            }
+            name.push(ch);
+            self.advance();
+        }
+
+        if name.is_empty() {
+            return Err(format!("Expected parameter name after '{}'", sigil));
+        }
+
+        Ok(Token::Parameter(name))
+    }
+
     fn read_operator(&mut self) -> Result<Token, String> {
         let ch = self.current_char.unwrap();
         let next = self.peek();
@@ -458,6 +681,14 @@ impl Lexer {
                 self.advance();
                 Token::Colon
             }
+            ('|', _) => {
+                self.advance();
+                Token::Pipe
+            }
+            ('@', _) => {
+                self.advance();
+                Token::At
+            }
             ('(', _) => {
                 self.advance();
                 Token::LeftParen
@@ -514,10 +745,10 @@ mod tests {
 
     #[test]
     fn test_numbers() {
-        let mut lexer = Lexer::new("42 3.14 0 -1");
+        let mut lexer = Lexer::new("42 2.5 0 -1");
         let tokens = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Integer(42));
-        assert_eq!(tokens[1], Token::Float(3.14));
+        assert_eq!(tokens[1], Token::Float(2.5));
         assert_eq!(tokens[2], Token::Integer(0));
         assert_eq!(tokens[3], Token::Minus);
         assert_eq!(tokens[4], Token::Integer(1));
@@ -528,8 +759,47 @@ mod tests {
         let mut lexer = Lexer::new(r#"'hello' "world" 'it\'s'"#);
         let tokens = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::String("hello".to_string()));
-        assert_eq!(tokens[1], Token::String("world".to_string()));
+        assert_eq!(tokens[1], Token::QuotedIdentifier("world".to_string()));
         assert_eq!(tokens[2], Token::String("it's".to_string()));
+
+        let mut lexer = Lexer::new(r#"'O''Brien' "say ""hi""" '' 'tab\there'"#);
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::String("O'Brien".to_string()));
+        assert_eq!(tokens[1], Token::QuotedIdentifier("say \"hi\"".to_string()));
+        assert_eq!(tokens[2], Token::String(String::new()));
+        assert_eq!(tokens[3], Token::String("tab\there".to_string()));
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut lexer = Lexer::new("FROM Users -- who\n/* all\n of them */ SELECT name --");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::From,
+                Token::Identifier("Users".to_string()),
+                Token::Select,
+                Token::Identifier("name".to_string()),
+                Token::Eof
+            ]
+        );
+
+        let err = Lexer::new("FROM Users /* never closed").tokenize_with_positions().unwrap_err();
+        assert_eq!(err.message(), "Unterminated block comment");
+        assert!(matches!(err, DeedError::ParseError { line: 1, column: 12, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_parameters() {
+        let mut lexer = Lexer::new("$min_age ?1 ?12");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Parameter("min_age".to_string()));
+        assert_eq!(tokens[1], Token::Parameter("1".to_string()));
+        assert_eq!(tokens[2], Token::Parameter("12".to_string()));
+
+        assert!(Lexer::new("age = $").tokenize().is_err());
+        assert!(Lexer::new("age = ?x").tokenize().is_err());
     }
 
     #[test]
@@ -568,4 +838,25 @@ mod tests {
         assert_eq!(tokens[12], Token::Arrow);
         assert_eq!(tokens[13], Token::Identifier("Product".to_string()));
     }
+
+    #[test]
+    fn test_positions_track_lines_and_columns() {
+        let mut lexer = Lexer::new("FROM Users\n  WHERE né = 'x'");
+        let tokens = lexer.tokenize_with_positions().unwrap();
+
+        let positions: Vec<(usize, usize, usize)> =
+            tokens.iter().map(|t| (t.position.offset, t.position.line, t.position.column)).collect();
+        assert_eq!(positions, [(0, 1, 1), (5, 1, 6), (13, 2, 3), (19, 2, 9), (23, 2, 12), (25, 2, 14), (28, 2, 17)]);
+
+        let err = Lexer::new("FROM Users\nWHERE name = 'open").tokenize_with_positions().unwrap_err();
+        assert!(matches!(err, DeedError::ParseError { line: 2, column: 14, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_suggest_keyword() {
+        assert_eq!(suggest_keyword("SELCT"), Some("SELECT"));
+        assert_eq!(suggest_keyword("travers"), Some("TRAVERSE"));
+        assert_eq!(suggest_keyword("name"), None);
+        assert_eq!(suggest_keyword("SELECT"), None);
+    }
 }
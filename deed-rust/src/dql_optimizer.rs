@@ -2,17 +2,52 @@
 //!
 //! Uses biological algorithms (ant colony optimization) to find
 //! optimal query execution plans.
+//!
+//! What the optimizer learns (pheromone trails on plan shapes, traversal
+//! fan-outs) and the plans the [`StigmergyCache`] holds can be saved with
+//! [`save_learning`] and loaded back at startup. Trails decay by
+//! [`OptimizerConfig::decay_factor`] at each tick, and are forgotten once
+//! below its eviction floor, so stale learning stops steering plans.
 
 use crate::dql_ir::*;
 use crate::types::Pheromone;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Weight of the newest observation in a traversal's learned fan-out
+const FAN_OUT_LEARNING_RATE: f32 = 0.5;
 
 /// Ant Colony Optimizer for query plans
 pub struct AntColonyOptimizer {
     num_ants: usize,
     num_iterations: usize,
     pheromone_cache: HashMap<String, Pheromone>,
+    /// Observed fan-out of each traversal, by `traversal_key`
+    fan_out: HashMap<String, LearnedFanOut>,
+    /// What the optimizer last did to a plan
+    last_report: Option<OptimizationReport>,
+}
+
+/// Observed rows out per row in of a traversal, and the trail keeping it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct LearnedFanOut {
+    rows_per_row: f32,
+    /// Reinforced by each execution observed, decayed with the other trails
+    trail: Pheromone,
+}
+
+/// A plan before and after the optimizer rewrote it
+#[derive(Debug, Clone)]
+pub struct OptimizationReport {
+    pub before: QueryPlan,
+    pub after: QueryPlan,
+    /// Rewrites applied, in order
+    pub rewrites: Vec<String>,
+    /// Pheromone laid on the chosen plan's shape
+    pub pheromone_strength: f32,
 }
 
 impl AntColonyOptimizer {
@@ -21,13 +56,25 @@ impl AntColonyOptimizer {
             num_ants: 20,
             num_iterations: 10,
             pheromone_cache: HashMap::new(),
+            fan_out: HashMap::new(),
+            last_report: None,
         }
     }
 
     /// Optimize a query plan using ant colony optimization
+    ///
+    /// Filters are first pushed down to the operations producing their
+    /// bindings and traversals ordered by fan-out; the ants then explore
+    /// variants of that plan.
     pub fn optimize(&mut self, mut plan: QueryPlan, stats: &GraphStats) -> QueryPlan {
         // Initial cost estimation
         plan.estimate_cost(stats);
+        let before = plan.clone();
+
+        let mut rewrites = Vec::new();
+        self.push_down_filters(&mut plan, &mut rewrites);
+        self.order_traversals(&mut plan, stats, &mut rewrites);
+        plan.estimate_cost(stats);
 
         let mut best_plan = plan.clone();
         let mut best_cost = plan.estimated_cost;
@@ -51,7 +98,7 @@ impl AntColonyOptimizer {
                     let pheromone = self
                         .pheromone_cache
                         .entry(plan_key)
-                        .or_insert_with(Pheromone::default);
+                        .or_default();
 
                     let reinforcement = 1.0 / (1.0 + best_cost);
                     pheromone.reinforce(reinforcement);
@@ -70,99 +117,259 @@ impl AntColonyOptimizer {
             .map(|p| p.strength())
             .unwrap_or(1.0);
 
+        self.last_report = Some(OptimizationReport {
+            before,
+            after: best_plan.clone(),
+            rewrites,
+            pheromone_strength: best_plan.pheromone_strength,
+        });
+
         best_plan
     }
 
+    /// What the last `optimize` or `record_execution` call changed
+    pub fn last_report(&self) -> Option<&OptimizationReport> {
+        self.last_report.as_ref()
+    }
+
+    /// Multiply every trail's strength by `factor`, forgetting the trails,
+    /// and fan-outs, left weaker than `floor`
+    ///
+    /// Returns how many were forgotten.
+    pub fn decay(&mut self, factor: f32, floor: f32) -> usize {
+        let before = self.pheromone_cache.len() + self.fan_out.len();
+        self.pheromone_cache.retain(|_, trail| decay_trail(trail, factor, floor));
+        self.fan_out.retain(|_, learned| decay_trail(&mut learned.trail, factor, floor));
+        before - (self.pheromone_cache.len() + self.fan_out.len())
+    }
+
+    /// Forget everything learned
+    pub fn reset(&mut self) {
+        self.pheromone_cache.clear();
+        self.fan_out.clear();
+        self.last_report = None;
+    }
+
+    /// Trails and fan-outs learned
+    pub fn learned_count(&self) -> usize {
+        self.pheromone_cache.len() + self.fan_out.len()
+    }
+
+    /// Learn from an execution of `plan`, where `rows[i]` is how many rows
+    /// its i-th operation produced
+    ///
+    /// Each traversal's observed fan-out replaces the estimate used to order
+    /// traversals, and the plan's shape is reinforced more the fewer rows it
+    /// had to produce. Given `stats`, returns the plan reordered when what
+    /// was learned changes its traversal order (see [`has_traversal_run`]).
+    pub fn record_execution(&mut self, plan: &QueryPlan, rows: &[usize], stats: Option<&GraphStats>) -> Option<QueryPlan> {
+        for (i, op) in plan.operations.iter().enumerate().skip(1) {
+            if !matches!(op, Operation::Traverse { .. }) {
+                continue;
+            }
+            let (Some(&produced), Some(&input)) = (rows.get(i), rows.get(i - 1)) else { continue };
+            if input == 0 {
+                continue;
+            }
+
+            let observed = produced as f32 / input as f32;
+            self.fan_out
+                .entry(traversal_key(plan, op))
+                .and_modify(|learned| {
+                    learned.rows_per_row =
+                        FAN_OUT_LEARNING_RATE * observed + (1.0 - FAN_OUT_LEARNING_RATE) * learned.rows_per_row;
+                    learned.trail.reinforce(1.0);
+                })
+                .or_insert(LearnedFanOut {
+                    rows_per_row: observed,
+                    trail: Pheromone::default(),
+                });
+        }
+
+        let produced: usize = rows.iter().sum();
+        let plan_key = self.plan_signature(plan);
+        let pheromone = self.pheromone_cache.entry(plan_key).or_default();
+        pheromone.reinforce(1.0 / (1.0 + (produced as f32).ln_1p()));
+        let pheromone_strength = pheromone.strength();
+
+        let stats = stats?;
+        let mut reordered = plan.clone();
+        let mut rewrites = Vec::new();
+        self.order_traversals(&mut reordered, stats, &mut rewrites);
+        if rewrites.is_empty() {
+            return None;
+        }
+
+        reordered.estimate_cost(stats);
+        reordered.pheromone_strength = pheromone_strength;
+        self.last_report = Some(OptimizationReport {
+            before: plan.clone(),
+            after: reordered.clone(),
+            rewrites,
+            pheromone_strength,
+        });
+        Some(reordered)
+    }
+
     /// Explore a variant of the query plan
-    fn explore_variant(&self, plan: &QueryPlan, stats: &GraphStats) -> QueryPlan {
+    fn explore_variant(&self, plan: &QueryPlan, _stats: &GraphStats) -> QueryPlan {
         let mut variant = plan.clone();
 
         // Apply random optimizations
         let mut rng = rand::thread_rng();
-        let optimization = rng.gen_range(0..4);
+        let optimization = rng.gen_range(0..2);
 
         match optimization {
-            0 => self.try_index_optimization(&mut variant, stats),
-            1 => self.try_filter_pushdown(&mut variant),
-            2 => self.try_projection_pushdown(&mut variant),
-            3 => self.try_join_reorder(&mut variant),
+            0 => self.try_projection_pushdown(&mut variant),
+            1 => self.try_join_reorder(&mut variant),
             _ => {}
         }
 
         variant
     }
 
-    /// Try to convert scans to index lookups
-    fn try_index_optimization(&self, plan: &mut QueryPlan, _stats: &GraphStats) {
-        for op in &mut plan.operations {
-            if let Operation::Scan {
-                collection,
-                alias,
-                filter,
-            } = op
-            {
-                // Check if filter is simple equality that can use index
-                if let Some(FilterExpr::Equal(left, right)) = filter {
-                    if let (FilterExpr::Property { property, .. }, FilterExpr::Constant(value)) =
-                        (left.as_ref(), right.as_ref())
-                    {
-                        // Convert to index lookup
-                        *op = Operation::IndexLookup {
-                            collection: collection.clone(),
-                            alias: alias.clone(),
-                            index_name: format!("idx_{}", property),
-                            key_values: vec![value.clone()],
-                        };
+    /// Move each conjunct of a standalone Filter that reads a single binding
+    /// into the Scan or Traverse producing that binding
+    ///
+    /// Joined rows are filtered as whole tuples, so nothing moves below a
+    /// Join. Edge aliases stay in the Filter, as a Traverse's filter only
+    /// sees its target.
+    fn push_down_filters(&self, plan: &mut QueryPlan, rewrites: &mut Vec<String>) {
+        let mut i = 0;
+        while i < plan.operations.len() {
+            let Operation::Filter { condition, .. } = &plan.operations[i] else {
+                i += 1;
+                continue;
+            };
+            let condition = condition.clone();
+
+            // Bindings an operation since the last Join produced, and
+            // bindings no filter can be pushed to
+            let mut producers: Vec<(String, usize)> = Vec::new();
+            let mut fixed: Vec<String> = Vec::new();
+            for (j, op) in plan.operations[..i].iter().enumerate() {
+                match op {
+                    Operation::Scan { alias, .. } | Operation::IndexLookup { alias, .. } => {
+                        producers.push((alias.clone(), j));
                     }
+                    Operation::Traverse { target_alias, edge_alias, .. } => {
+                        producers.push((target_alias.clone(), j));
+                        fixed.extend(edge_alias.clone());
+                    }
+                    Operation::Join { right, .. } => {
+                        fixed.extend(producers.drain(..).map(|(binding, _)| binding));
+                        fixed.push(right.clone());
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut kept = Vec::new();
+            for conjunct in condition.conjuncts() {
+                let target = producers.iter().find(|(binding, _)| {
+                    let others: Vec<String> = producers
+                        .iter()
+                        .map(|(other, _)| other)
+                        .chain(&fixed)
+                        .filter(|other| *other != binding)
+                        .cloned()
+                        .collect();
+                    conjunct.references_any(std::slice::from_ref(binding)) && !conjunct.references_any(&others)
+                });
+                let Some((binding, j)) = target else {
+                    kept.push(conjunct);
+                    continue;
+                };
+
+                let op = &mut plan.operations[*j];
+                rewrites.push(format!("Pushed {} into {} AS {}", conjunct, op.name(), binding));
+                if let Operation::Scan { filter, .. }
+                | Operation::IndexLookup { filter, .. }
+                | Operation::Traverse { filter, .. } = op
+                {
+                    *filter = FilterExpr::all(filter.take().into_iter().chain([conjunct]).collect());
+                }
+            }
+
+            match FilterExpr::all(kept) {
+                Some(rest) => {
+                    if let Operation::Filter { condition, .. } = &mut plan.operations[i] {
+                        *condition = rest;
+                    }
+                    i += 1;
+                }
+                None => {
+                    plan.operations.remove(i);
                 }
             }
         }
     }
 
-    /// Push filters earlier in the plan
-    fn try_filter_pushdown(&self, plan: &mut QueryPlan) {
-        // Find standalone Filter operations and try to merge them into Scan
-        let mut i = 0;
-        while i < plan.operations.len() {
-            // Clone the filter info first to avoid borrowing issues
-            let filter_info = if let Operation::Filter { binding, condition } = &plan.operations[i] {
-                Some((binding.clone(), condition.clone()))
-            } else {
-                None
+    /// Order each run of consecutive traversals by fan-out, smallest first
+    ///
+    /// Traversals all expand from the scanned entities, so their order only
+    /// changes how many joined rows exist in between. Learned fan-outs are
+    /// used where a traversal has run before; otherwise it's estimated from
+    /// the graph's average degree.
+    fn order_traversals(&self, plan: &mut QueryPlan, stats: &GraphStats, rewrites: &mut Vec<String>) {
+        let mut start = 0;
+        while start < plan.operations.len() {
+            let end = start
+                + plan.operations[start..]
+                    .iter()
+                    .take_while(|op| matches!(op, Operation::Traverse { .. }))
+                    .count();
+            if end - start < 2 {
+                start = end + 1;
+                continue;
+            }
+
+            let mut run: Vec<(f32, Operation)> = plan.operations[start..end]
+                .iter()
+                .map(|op| (self.fan_out_estimate(plan, op, stats), op.clone()))
+                .collect();
+            let targets = |run: &[(f32, Operation)]| -> Vec<String> {
+                run.iter()
+                    .filter_map(|(_, op)| match op {
+                        Operation::Traverse { target_alias, .. } => Some(target_alias.clone()),
+                        _ => None,
+                    })
+                    .collect()
             };
 
-            if let Some((binding, condition)) = filter_info {
-                // Look backwards for Scan with same binding
-                for j in (0..i).rev() {
-                    if let Operation::Scan {
-                        alias,
-                        filter: scan_filter,
-                        ..
-                    } = &mut plan.operations[j]
-                    {
-                        if alias == &binding {
-                            // Merge filter into scan
-                            if let Some(existing_filter) = scan_filter {
-                                *scan_filter = Some(FilterExpr::And(
-                                    Box::new(existing_filter.clone()),
-                                    Box::new(condition.clone()),
-                                ));
-                            } else {
-                                *scan_filter = Some(condition.clone());
-                            }
-
-                            // Remove standalone filter
-                            plan.operations.remove(i);
-                            return; // Modified plan, return
-                        }
-                    }
-                }
+            let before = targets(&run);
+            run.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let after = targets(&run);
+            if before != after {
+                rewrites.push(format!(
+                    "Reordered traversals by fan-out: {} -> {}",
+                    before.join(", "),
+                    after.join(", ")
+                ));
+                plan.operations.splice(start..end, run.into_iter().map(|(_, op)| op));
             }
-            i += 1;
+
+            start = end + 1;
+        }
+    }
+
+    /// Rows a traversal is expected to produce per row it expands
+    fn fan_out_estimate(&self, plan: &QueryPlan, op: &Operation, stats: &GraphStats) -> f32 {
+        if let Some(learned) = self.fan_out.get(&traversal_key(plan, op)) {
+            return learned.rows_per_row;
+        }
+
+        let reached = op.estimate_cost(stats);
+        match op {
+            Operation::Traverse { filter: Some(_), .. } => reached * 0.5,
+            _ => reached,
         }
     }
 
     /// Push projections earlier to reduce data size
+    ///
+    /// A projection only moves ahead of operations that read no bindings;
+    /// a Filter, say, may read an edge or binding the projection drops.
     fn try_projection_pushdown(&self, plan: &mut QueryPlan) {
         // Find Project operation and try to move it earlier
         if let Some(project_idx) = plan
@@ -170,7 +377,9 @@ impl AntColonyOptimizer {
             .iter()
             .position(|op| matches!(op, Operation::Project { .. }))
         {
-            if project_idx > 1 {
+            if project_idx > 1
+                && matches!(plan.operations[project_idx - 1], Operation::Limit { .. } | Operation::Skip { .. })
+            {
                 // Try to move project earlier (simple swap)
                 plan.operations.swap(project_idx, project_idx - 1);
             }
@@ -210,33 +419,59 @@ impl AntColonyOptimizer {
                 Operation::UpdateEntities { .. } => "UPD",
                 Operation::DeleteEntities { .. } => "DEL",
                 Operation::CreateEdge { .. } => "CRE",
+                Operation::UpdateEdges { .. } => "UPE",
+                Operation::DeleteEdges { .. } => "DLE",
                 Operation::GroupBy { .. } => "G",
                 Operation::Having { .. } => "H",
+                Operation::Subquery { .. } => "SUB",
             })
             .collect::<Vec<_>>()
             .join("_")
     }
 }
 
+/// Whether a plan has traversals in a row, which learned fan-outs can reorder
+pub fn has_traversal_run(plan: &QueryPlan) -> bool {
+    plan.operations
+        .windows(2)
+        .any(|pair| pair.iter().all(|op| matches!(op, Operation::Traverse { .. })))
+}
+
+/// Key under which a traversal's fan-out is learned: the collection the
+/// plan starts from and the traversal as EXPLAIN shows it
+fn traversal_key(plan: &QueryPlan, op: &Operation) -> String {
+    let source = plan.collections().first().copied().unwrap_or_default();
+    format!("{}: {}", source, op.details())
+}
+
 impl Default for AntColonyOptimizer {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Factor by which the entity count may grow or shrink before a cached plan is re-optimized
+pub const DEFAULT_STATS_DRIFT_RATIO: f64 = 2.0;
+
 /// Stigmergy-based query cache
 ///
 /// Caches optimized query plans based on pattern similarity.
 pub struct StigmergyCache {
     cache: HashMap<String, CachedPlan>,
     max_size: usize,
+    /// Entity-count growth or shrink factor beyond which a plan is stale
+    drift_ratio: f64,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct CachedPlan {
     plan: QueryPlan,
     pheromone: Pheromone,
     hit_count: usize,
+    /// Entity count the plan was optimized against, if known
+    entity_count: Option<usize>,
+    /// Collections the plan scans or writes
+    collections: Vec<String>,
 }
 
 impl StigmergyCache {
@@ -244,9 +479,16 @@ impl StigmergyCache {
         StigmergyCache {
             cache: HashMap::new(),
             max_size,
+            drift_ratio: DEFAULT_STATS_DRIFT_RATIO,
         }
     }
 
+    /// Set the entity-count factor beyond which cached plans are re-optimized
+    pub fn with_drift_ratio(mut self, drift_ratio: f64) -> Self {
+        self.drift_ratio = drift_ratio;
+        self
+    }
+
     /// Try to get cached plan
     pub fn get(&mut self, query_signature: &str) -> Option<QueryPlan> {
         if let Some(cached) = self.cache.get_mut(query_signature) {
@@ -258,23 +500,70 @@ impl StigmergyCache {
         }
     }
 
+    /// Try to get a cached plan that is still fresh for a graph of `entity_count` entities
+    ///
+    /// A plan optimized against an entity count that differs by more than the
+    /// drift ratio is dropped, so the caller re-optimizes and stores it again.
+    pub fn get_fresh(&mut self, query_signature: &str, entity_count: usize) -> Option<QueryPlan> {
+        let cached = self.cache.get(query_signature)?;
+        if cached.entity_count.is_some_and(|planned| self.drifted(planned, entity_count)) {
+            self.cache.remove(query_signature);
+            return None;
+        }
+
+        self.get(query_signature)
+    }
+
+    /// Whether the entity count moved too far from the one a plan was optimized for
+    fn drifted(&self, planned: usize, current: usize) -> bool {
+        let low = planned.min(current).max(1) as f64;
+        let high = planned.max(current) as f64;
+        high / low > self.drift_ratio
+    }
+
     /// Store optimized plan in cache
     pub fn put(&mut self, query_signature: String, plan: QueryPlan) {
+        self.insert(query_signature, plan, None);
+    }
+
+    /// Store a plan along with the stats it was optimized against
+    pub fn put_with_stats(&mut self, query_signature: String, plan: QueryPlan, stats: &GraphStats) {
+        self.insert(query_signature, plan, Some(stats.entity_count));
+    }
+
+    fn insert(&mut self, query_signature: String, plan: QueryPlan, entity_count: Option<usize>) {
         // Evict if cache is full
         if self.cache.len() >= self.max_size {
             self.evict_weakest();
         }
 
+        let collections = plan.collections().into_iter().map(String::from).collect();
         self.cache.insert(
             query_signature,
             CachedPlan {
                 plan,
                 pheromone: Pheromone::default(),
                 hit_count: 0,
+                entity_count,
+                collections,
             },
         );
     }
 
+    /// Drop every cached plan that scans or writes `collection`
+    ///
+    /// Returns how many plans were dropped.
+    pub fn invalidate_collection(&mut self, collection: &str) -> usize {
+        let before = self.cache.len();
+        self.cache.retain(|_, cached| !cached.collections.iter().any(|c| c == collection));
+        before - self.cache.len()
+    }
+
+    /// Drop every cached plan
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
     /// Evict plan with weakest pheromone
     fn evict_weakest(&mut self) {
         if let Some(weakest_key) = self
@@ -292,6 +581,16 @@ impl StigmergyCache {
         }
     }
 
+    /// Multiply every cached plan's pheromone by `factor`, dropping the plans
+    /// left weaker than `floor`
+    ///
+    /// Returns how many were dropped.
+    pub fn decay(&mut self, factor: f32, floor: f32) -> usize {
+        let before = self.cache.len();
+        self.cache.retain(|_, cached| decay_trail(&mut cached.pheromone, factor, floor));
+        before - self.cache.len()
+    }
+
     /// Evaporate all pheromones (called periodically)
     pub fn evaporate_all(&mut self) {
         for cached in self.cache.values_mut() {
@@ -326,6 +625,118 @@ pub struct CacheStats {
     pub avg_pheromone: f32,
 }
 
+/// Version of the saved learning format; state saved in another is ignored
+pub const LEARNING_FORMAT_VERSION: u32 = 1;
+
+/// How the optimizer's learning decays, and where it's kept across restarts
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerConfig {
+    /// Factor trail strengths are multiplied by at each decay tick
+    pub decay_factor: f32,
+    /// Time between decay ticks; `None` never decays
+    pub decay_interval: Option<Duration>,
+    /// Trails weaker than this after a tick are forgotten
+    pub eviction_floor: f32,
+    /// File learning is saved to on shutdown and checkpoint, and loaded from at startup
+    pub state_path: Option<PathBuf>,
+}
+
+impl OptimizerConfig {
+    /// Keep learning in the file at `path`
+    pub fn with_state_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_path = Some(path.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.decay_factor > 0.0 && self.decay_factor <= 1.0) {
+            return Err(format!("Decay factor must be in (0, 1], got {}", self.decay_factor));
+        }
+        if self.decay_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err("Decay interval must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig {
+            decay_factor: 0.9,
+            decay_interval: Some(Duration::from_secs(60)),
+            eviction_floor: 0.25,
+            state_path: None,
+        }
+    }
+}
+
+/// What the optimizer and plan cache have learned, as saved between runs
+#[derive(Serialize, Deserialize)]
+struct Learning {
+    /// First, so another version is recognized without decoding the rest
+    version: u32,
+    pheromones: HashMap<String, Pheromone>,
+    fan_out: HashMap<String, LearnedFanOut>,
+    plans: HashMap<String, CachedPlan>,
+}
+
+/// Save what `optimizer` and `cache` have learned to `path`
+///
+/// The file is replaced whole, so a failed save leaves the previous one.
+pub fn save_learning(path: &Path, optimizer: &AntColonyOptimizer, cache: &StigmergyCache) -> Result<(), String> {
+    let learning = Learning {
+        version: LEARNING_FORMAT_VERSION,
+        pheromones: optimizer.pheromone_cache.clone(),
+        fan_out: optimizer.fan_out.clone(),
+        plans: cache.cache.clone(),
+    };
+    let bytes = bincode::serialize(&learning).map_err(|e| format!("Serialization error: {}", e))?;
+
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, bytes)
+        .and_then(|_| std::fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to save optimizer state to {}: {}", path.display(), e))
+}
+
+/// Load learning saved by [`save_learning`] into `optimizer` and `cache`,
+/// replacing what they hold
+///
+/// Returns false, changing nothing, when there's no saved state or it was
+/// saved in another format version.
+pub fn load_learning(path: &Path, optimizer: &mut AntColonyOptimizer, cache: &mut StigmergyCache) -> Result<bool, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("Failed to read optimizer state {}: {}", path.display(), e)),
+    };
+    let corrupt = |e: bincode::Error| format!("Corrupt optimizer state {}: {}", path.display(), e);
+
+    let version: u32 = bincode::deserialize(&bytes).map_err(corrupt)?;
+    if version != LEARNING_FORMAT_VERSION {
+        return Ok(false);
+    }
+    let learning: Learning = bincode::deserialize(&bytes).map_err(corrupt)?;
+
+    optimizer.reset();
+    optimizer.pheromone_cache = learning.pheromones;
+    optimizer.fan_out = learning.fan_out;
+    cache.clear();
+    for (query_signature, cached) in learning.plans {
+        if cache.cache.len() >= cache.max_size {
+            cache.evict_weakest();
+        }
+        cache.cache.insert(query_signature, cached);
+    }
+    Ok(true)
+}
+
+/// Multiply a trail's strength by `factor`, returning whether it's still at least `floor`
+fn decay_trail(trail: &mut Pheromone, factor: f32, floor: f32) -> bool {
+    let strength = trail.strength() * factor;
+    *trail = Pheromone::new(strength);
+    strength >= floor
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -337,6 +748,7 @@ mod tests {
             edge_count: 5000,
             collection_count: 10,
             avg_pheromone: 1.0,
+            ..Default::default()
         };
 
         let operations = vec![
@@ -350,15 +762,17 @@ mod tests {
                     }),
                     Box::new(FilterExpr::Constant(Value::Integer(25))),
                 )),
+                properties: None,
             },
             Operation::Project {
-                fields: vec![ProjectField {
+                fields: vec![Projection::Field(ProjectField {
                     expression: FilterExpr::Property {
                         binding: "u".to_string(),
                         property: "name".to_string(),
                     },
                     alias: "name".to_string(),
-                }],
+                })],
+                distinct: false,
             },
         ];
 
@@ -380,6 +794,216 @@ mod tests {
         println!("Has index lookup: {}", has_index);
     }
 
+    fn traverse(edge_type: &str, target: &str, edge_alias: Option<&str>) -> Operation {
+        Operation::Traverse {
+            source_binding: "u".to_string(),
+            direction: TraverseDirection::Outgoing,
+            edge_types: vec![edge_type.to_string()],
+            edge_alias: edge_alias.map(String::from),
+            edge_properties: HashMap::new(),
+            target_alias: target.to_string(),
+            min_hops: 1,
+            max_hops: 1,
+            top_k: None,
+            all_paths: false,
+            filter: None,
+            properties: None,
+        }
+    }
+
+    fn equals(binding: &str, property: &str, value: FilterExpr) -> FilterExpr {
+        FilterExpr::Equal(
+            Box::new(FilterExpr::Property {
+                binding: binding.to_string(),
+                property: property.to_string(),
+            }),
+            Box::new(value),
+        )
+    }
+
+    fn stats() -> GraphStats {
+        GraphStats {
+            entity_count: 1000,
+            edge_count: 5000,
+            collection_count: 2,
+            avg_pheromone: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn traversal_targets(plan: &QueryPlan) -> Vec<&str> {
+        plan.operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Traverse { target_alias, .. } => Some(target_alias.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filters_pushed_to_producing_operations() {
+        let name = FilterExpr::Constant(Value::String("Ann".to_string()));
+        let condition = FilterExpr::all(vec![
+            equals("u", "name", name.clone()),
+            equals("f", "name", name.clone()),
+            equals("e", "since", FilterExpr::Constant(Value::Integer(2020))),
+            equals("f", "city", FilterExpr::Property {
+                binding: "u".to_string(),
+                property: "city".to_string(),
+            }),
+        ])
+        .unwrap();
+        let plan = QueryPlan::new(vec![
+            Operation::Scan {
+                collection: "Users".to_string(),
+                alias: "u".to_string(),
+                filter: None,
+                properties: None,
+            },
+            traverse("FOLLOWS", "f", Some("e")),
+            Operation::Filter {
+                binding: "u".to_string(),
+                condition,
+            },
+        ]);
+
+        let mut optimizer = AntColonyOptimizer::new();
+        let optimized = optimizer.optimize(plan, &stats());
+
+        let Operation::Scan { filter: Some(scan_filter), .. } = &optimized.operations[0] else {
+            panic!("Expected a filtered scan");
+        };
+        assert_eq!(scan_filter, &equals("u", "name", name.clone()));
+        let Operation::Traverse { filter: Some(traverse_filter), .. } = &optimized.operations[1] else {
+            panic!("Expected a filtered traversal");
+        };
+        assert_eq!(traverse_filter, &equals("f", "name", name));
+
+        // Conditions on the edge, or across bindings, stay on the joined rows
+        let Operation::Filter { condition, .. } = &optimized.operations[2] else {
+            panic!("Expected the remaining filter");
+        };
+        assert_eq!(condition.clone().conjuncts().len(), 2);
+        assert_eq!(optimizer.last_report().unwrap().rewrites.len(), 2);
+    }
+
+    #[test]
+    fn test_traversals_reordered_by_learned_fan_out() {
+        let plan = QueryPlan::new(vec![
+            Operation::Scan {
+                collection: "Users".to_string(),
+                alias: "u".to_string(),
+                filter: None,
+                properties: None,
+            },
+            traverse("FOLLOWS", "f", None),
+            traverse("OWNS", "o", None),
+            Operation::Project { fields: Vec::new(), distinct: false },
+        ]);
+
+        // Without history both look alike, so the written order is kept
+        let mut optimizer = AntColonyOptimizer::new();
+        let optimized = optimizer.optimize(plan.clone(), &stats());
+        assert_eq!(traversal_targets(&optimized), vec!["f", "o"]);
+        assert!(optimizer.last_report().unwrap().rewrites.is_empty());
+
+        // Each user follows 50 others but owns one thing
+        let reordered = optimizer.record_execution(&optimized, &[10, 500, 500, 500], Some(&stats())).unwrap();
+        assert_eq!(traversal_targets(&reordered), vec!["o", "f"]);
+        let report = optimizer.last_report().unwrap();
+        assert_eq!(traversal_targets(&report.before), vec!["f", "o"]);
+        assert_eq!(report.rewrites, vec!["Reordered traversals by fan-out: f, o -> o, f".to_string()]);
+        assert!(report.pheromone_strength > Pheromone::INITIAL);
+
+        // Planning the statement again uses what was learned
+        assert_eq!(traversal_targets(&optimizer.optimize(plan, &stats())), vec!["o", "f"]);
+        assert!(optimizer.record_execution(&reordered, &[10, 10, 500, 500], Some(&stats())).is_none());
+    }
+
+    #[test]
+    fn test_learning_without_stats_keeps_the_plan() {
+        let scan = Operation::Scan {
+            collection: "Users".to_string(),
+            alias: "u".to_string(),
+            filter: None,
+            properties: None,
+        };
+        let single = QueryPlan::new(vec![scan.clone(), traverse("FOLLOWS", "f", None)]);
+        assert!(!has_traversal_run(&single));
+        let plan = QueryPlan::new(vec![scan, traverse("FOLLOWS", "f", None), traverse("OWNS", "o", None)]);
+        assert!(has_traversal_run(&plan));
+
+        // The fan-outs are learned all the same, for the next planning
+        let mut optimizer = AntColonyOptimizer::new();
+        assert!(optimizer.record_execution(&plan, &[10, 500, 500], None).is_none());
+        assert_eq!(traversal_targets(&optimizer.optimize(plan, &stats())), vec!["o", "f"]);
+    }
+
+    /// A plan with two traversals, and an optimizer that learned to swap them
+    fn learned_swap() -> (QueryPlan, AntColonyOptimizer) {
+        let plan = QueryPlan::new(vec![
+            Operation::Scan {
+                collection: "Users".to_string(),
+                alias: "u".to_string(),
+                filter: None,
+                properties: None,
+            },
+            traverse("FOLLOWS", "f", None),
+            traverse("OWNS", "o", None),
+        ]);
+        let mut optimizer = AntColonyOptimizer::new();
+        let optimized = optimizer.optimize(plan.clone(), &stats());
+        optimizer.record_execution(&optimized, &[10, 500, 500], Some(&stats())).unwrap();
+        (plan, optimizer)
+    }
+
+    #[test]
+    fn test_learning_survives_save_and_load() {
+        let (plan, optimizer) = learned_swap();
+        let mut cache = StigmergyCache::new(5);
+        cache.put("query1".to_string(), plan.clone());
+        let path = std::env::temp_dir().join(format!("deed_test_learning_{}", std::process::id()));
+        save_learning(&path, &optimizer, &cache).unwrap();
+
+        let mut restarted = AntColonyOptimizer::new();
+        let mut restarted_cache = StigmergyCache::new(5);
+        assert!(load_learning(&path, &mut restarted, &mut restarted_cache).unwrap());
+        assert_eq!(restarted.learned_count(), optimizer.learned_count());
+        assert_eq!(traversal_targets(&restarted.optimize(plan, &stats())), vec!["o", "f"]);
+        assert!(restarted_cache.get("query1").is_some());
+
+        // State of another format version is ignored
+        std::fs::write(&path, bincode::serialize(&(LEARNING_FORMAT_VERSION + 1)).unwrap()).unwrap();
+        assert!(!load_learning(&path, &mut restarted, &mut restarted_cache).unwrap());
+        assert_eq!(restarted_cache.stats().size, 1);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!load_learning(&path, &mut restarted, &mut restarted_cache).unwrap());
+    }
+
+    #[test]
+    fn test_decay_forgets_unused_trails() {
+        let (plan, mut optimizer) = learned_swap();
+        let mut cache = StigmergyCache::new(5);
+        cache.put("query1".to_string(), plan.clone());
+
+        // One tick leaves the learned fan-outs in place
+        optimizer.decay(0.9, 0.25);
+        assert_eq!(traversal_targets(&optimizer.optimize(plan.clone(), &stats())), vec!["o", "f"]);
+
+        let mut ticks = 0;
+        while optimizer.learned_count() > 0 {
+            optimizer.decay(0.5, 0.25);
+            ticks += 1;
+            assert!(ticks < 20);
+        }
+        assert_eq!(cache.decay(0.4, 0.25), 0);
+        assert_eq!(cache.decay(0.4, 0.25), 1);
+
+        // With the fan-outs forgotten the written order is back
+        assert_eq!(traversal_targets(&optimizer.optimize(plan, &stats())), vec!["f", "o"]);
+    }
+
     #[test]
     fn test_stigmergy_cache() {
         let mut cache = StigmergyCache::new(5);
@@ -400,4 +1024,46 @@ mod tests {
         assert_eq!(stats.size, 1);
         assert_eq!(stats.total_hits, 1);
     }
+
+    #[test]
+    fn test_stigmergy_cache_drops_plans_after_stats_drift() {
+        let mut cache = StigmergyCache::new(5);
+        let stats = |entity_count| GraphStats {
+            entity_count,
+            edge_count: 0,
+            collection_count: 1,
+            avg_pheromone: 1.0,
+            ..Default::default()
+        };
+
+        cache.put_with_stats("query1".to_string(), QueryPlan::new(vec![]), &stats(100));
+
+        // Within a factor of two either way the plan is reused
+        assert!(cache.get_fresh("query1", 150).is_some());
+        assert!(cache.get_fresh("query1", 60).is_some());
+
+        // 100x growth makes it stale
+        assert!(cache.get_fresh("query1", 10_000).is_none());
+        assert_eq!(cache.stats().size, 0);
+    }
+
+    #[test]
+    fn test_stigmergy_cache_invalidates_by_collection() {
+        let mut cache = StigmergyCache::new(5);
+        let scan = |collection: &str| {
+            QueryPlan::new(vec![Operation::Scan {
+                collection: collection.to_string(),
+                alias: collection.to_string(),
+                filter: None,
+                properties: None,
+            }])
+        };
+
+        cache.put("users".to_string(), scan("Users"));
+        cache.put("orders".to_string(), scan("Orders"));
+
+        assert_eq!(cache.invalidate_collection("Users"), 1);
+        assert!(cache.get("users").is_none());
+        assert!(cache.get("orders").is_some());
+    }
 }
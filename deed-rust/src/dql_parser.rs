@@ -3,28 +3,84 @@
 //! Converts token stream from lexer into AST.
 
 use crate::dql_ast::*;
-use crate::dql_lexer::{Lexer, Token};
+use crate::dql_lexer::{suggest_keyword, Lexer, Position, SpannedToken, Token};
+use crate::dql_functions::ScalarFunction;
+use crate::error::DeedError;
 use crate::transaction::IsolationLevel;
+use crate::auth::Role;
+use crate::firewall::{FirewallAction, FirewallCondition, FirewallRule, FirewallSubject, StatementClass};
+use crate::import_export::DataFormat;
+use crate::schema::{Constraint, Field, FieldType, Schema};
+use crate::types::{parse_timestamp, PropertyValue};
 
 pub struct Parser {
     tokens: Vec<Token>,
+    /// Where each token starts, when parsing text
+    positions: Vec<Position>,
     position: usize,
+    /// Subqueries parsed so far, numbering the next one
+    subqueries: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
         Parser {
             tokens,
+            positions: Vec::new(),
             position: 0,
+            subqueries: 0,
+        }
+    }
+
+    fn with_positions(tokens: Vec<SpannedToken>) -> Self {
+        let (tokens, positions) = tokens.into_iter().map(|t| (t.token, t.position)).unzip();
+        Parser {
+            positions,
+            ..Parser::new(tokens)
         }
     }
 
     /// Parse a DQL query string
-    pub fn parse(query: &str) -> Result<Query, String> {
-        let mut lexer = Lexer::new(query);
-        let tokens = lexer.tokenize()?;
-        let mut parser = Parser::new(tokens);
-        parser.parse_query()
+    ///
+    /// Errors report the line and column of the token parsing stopped at.
+    pub fn parse(query: &str) -> Result<Query, DeedError> {
+        let mut parser = Parser::with_positions(Lexer::new(query).tokenize_with_positions()?);
+        parser.parse_query().map_err(|message| parser.error(query, message))
+    }
+
+    /// Parse a standalone expression, such as a CHECK constraint
+    pub fn parse_expression_source(source: &str) -> Result<Expression, DeedError> {
+        let mut parser = Parser::with_positions(Lexer::new(source).tokenize_with_positions()?);
+
+        let expr = parser.parse_expression().map_err(|message| parser.error(source, message))?;
+        if parser.current() != &Token::Eof {
+            let message = format!("Unexpected {:?} after expression", parser.current());
+            return Err(parser.error(source, message));
+        }
+        Ok(expr)
+    }
+
+    /// A parse error at the current token of `source`
+    ///
+    /// A misspelled keyword usually fails at the token after it (SELCT is
+    /// read as an alias), so the previous token is checked for one too.
+    fn error(&self, source: &str, message: String) -> DeedError {
+        let at = self.position.min(self.tokens.len().saturating_sub(1));
+        let position = self.positions.get(at).copied().unwrap_or_default();
+
+        let suggestion = [Some(at), at.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .find_map(|index| match &self.tokens[index] {
+                Token::Identifier(word) => suggest_keyword(word).map(|keyword| (word, keyword)),
+                _ => None,
+            });
+        let message = match suggestion {
+            Some((word, keyword)) => format!("{} (did you mean {} instead of {}?)", message, keyword, word),
+            None => message,
+        };
+
+        DeedError::parse(source, position, message)
     }
 
     /// Parse top-level query
@@ -32,17 +88,61 @@ impl Parser {
         match self.current() {
             Token::From => Ok(Query::Select(self.parse_select()?)),
             Token::Insert => Ok(Query::Insert(self.parse_insert()?)),
+            Token::Update if self.at_edge_mutation() => Ok(Query::UpdateEdge(self.parse_update_edge()?)),
+            Token::Delete if self.at_edge_mutation() => Ok(Query::DeleteEdge(self.parse_delete_edge()?)),
             Token::Update => Ok(Query::Update(self.parse_update()?)),
             Token::Delete => Ok(Query::Delete(self.parse_delete()?)),
             Token::Create => {
                 // Check if this is CREATE INDEX or CREATE edge
-                if self.peek() == Some(&Token::Index) || self.peek() == Some(&Token::Unique) {
+                if self.peek() == Some(&Token::Index) || self.peek() == Some(&Token::Unique) || self.peek_word("FULLTEXT") {
                     Ok(Query::CreateIndex(self.parse_create_index()?))
                 } else {
                     Ok(Query::Create(self.parse_create()?))
                 }
             }
+            Token::Drop if self.peek_word("SCHEMA") => {
+                self.advance();
+                self.advance();
+                Ok(Query::DropSchema(self.parse_identifier()?))
+            }
+            Token::Drop if self.peek_word("COLLECTION") => {
+                self.advance();
+                self.advance();
+                Ok(Query::DropCollection(self.parse_identifier()?))
+            }
             Token::Drop => Ok(Query::DropIndex(self.parse_drop_index()?)),
+            Token::Reindex => Ok(Query::Reindex(self.parse_reindex()?)),
+            Token::Set => Ok(Query::Set(self.parse_set()?)),
+            Token::Archive => Ok(Query::Archive(self.parse_archive(Token::Archive)?)),
+            Token::Unarchive => Ok(Query::Unarchive(self.parse_archive(Token::Unarchive)?)),
+            Token::Firewall => Ok(Query::Firewall(self.parse_firewall()?)),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("DEFINE") => {
+                Ok(Query::DefineSchema(self.parse_define_schema()?))
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("COPY") => Ok(Query::Copy(self.parse_copy()?)),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("ANALYZE") => {
+                self.advance();
+                let collection = if matches!(self.current(), Token::Identifier(_) | Token::QuotedIdentifier(_)) {
+                    Some(self.parse_identifier()?)
+                } else {
+                    None
+                };
+                Ok(Query::Analyze(collection))
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("SHOW") => {
+                self.advance();
+                if !self.consume_word("COLLECTIONS") {
+                    return Err(format!("Expected COLLECTIONS after SHOW, got {:?}", self.current()));
+                }
+                Ok(Query::ShowCollections)
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("TRUNCATE") => {
+                self.advance();
+                Ok(Query::Truncate(self.parse_identifier()?))
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("EXPLAIN") => {
+                Ok(Query::Explain(self.parse_explain()?))
+            }
             Token::Begin => Ok(Query::Begin(self.parse_begin()?)),
             Token::Commit => {
                 self.advance();
@@ -50,8 +150,21 @@ impl Parser {
             }
             Token::Rollback => {
                 self.advance();
+                if self.consume_word("TO") {
+                    self.consume_word("SAVEPOINT");
+                    return Ok(Query::RollbackToSavepoint(self.parse_identifier()?));
+                }
                 Ok(Query::Rollback)
             }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("SAVEPOINT") => {
+                self.advance();
+                Ok(Query::Savepoint(self.parse_identifier()?))
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("RELEASE") => {
+                self.advance();
+                self.consume_word("SAVEPOINT");
+                Ok(Query::ReleaseSavepoint(self.parse_identifier()?))
+            }
             _ => Err(format!("Expected query keyword, got {:?}", self.current())),
         }
     }
@@ -60,6 +173,11 @@ impl Parser {
     fn parse_select(&mut self) -> Result<SelectQuery, String> {
         let from = self.parse_from()?;
 
+        let mut joins = Vec::new();
+        while self.current() == &Token::Join {
+            joins.push(self.parse_join()?);
+        }
+
         let traverse = if self.current() == &Token::Traverse {
             Some(self.parse_traverse()?)
         } else {
@@ -93,24 +211,31 @@ impl Parser {
             None
         };
 
-        let limit = if self.current() == &Token::Limit {
-            self.advance();
-            let limit_value = self.parse_integer()?;
-            Some(limit_value as usize)
-        } else {
-            None
-        };
-
-        let offset = if self.current() == &Token::Offset {
-            self.advance();
-            let offset_value = self.parse_integer()?;
-            Some(offset_value as usize)
-        } else {
-            None
-        };
+        // LIMIT and OFFSET may come in either order; OFFSET always applies first
+        let (mut limit, mut offset) = (None, None);
+        loop {
+            match self.current() {
+                Token::Limit => {
+                    if limit.is_some() {
+                        return Err("LIMIT given more than once".to_string());
+                    }
+                    self.advance();
+                    limit = Some(self.parse_integer()? as usize);
+                }
+                Token::Offset => {
+                    if offset.is_some() {
+                        return Err("OFFSET given more than once".to_string());
+                    }
+                    self.advance();
+                    offset = Some(self.parse_integer()? as usize);
+                }
+                _ => break,
+            }
+        }
 
         Ok(SelectQuery {
             from,
+            joins,
             traverse,
             where_clause,
             select,
@@ -141,6 +266,31 @@ impl Parser {
         Ok(FromClause { collection, alias })
     }
 
+    /// Parse JOIN clause: JOIN Orders o ON u.id = o.user_id
+    fn parse_join(&mut self) -> Result<JoinClause, String> {
+        self.expect(&Token::Join)?;
+
+        let collection = self.parse_identifier()?;
+
+        let alias = if let Token::As = self.current() {
+            self.advance();
+            Some(self.parse_identifier()?)
+        } else if let Token::Identifier(_) = self.current() {
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+
+        self.expect(&Token::On)?;
+        let condition = self.parse_expression()?;
+
+        Ok(JoinClause {
+            collection,
+            alias,
+            condition,
+        })
+    }
+
     /// Parse TRAVERSE clause
     fn parse_traverse(&mut self) -> Result<TraverseClause, String> {
         self.expect(&Token::Traverse)?;
@@ -166,8 +316,11 @@ impl Parser {
         Ok(TraverseClause { patterns })
     }
 
-    /// Parse single traverse pattern: -[:TYPE]-> alias
+    /// Parse single traverse pattern: [ALL] -[e:TYPE|OTHER]-> alias
     fn parse_traverse_pattern(&mut self) -> Result<TraversePattern, String> {
+        // ALL keeps a row per path rather than per reached entity
+        let all_paths = self.consume_word("ALL");
+
         // Parse direction
         let direction = match (self.current(), self.peek()) {
             (Token::Minus, Some(Token::LeftBracket)) | (Token::Minus, Some(Token::Arrow)) => {
@@ -185,34 +338,47 @@ impl Parser {
             _ => return Err(format!("Expected edge direction, got {:?}", self.current())),
         };
 
-        // Parse edge type: [:TYPE] or [:TYPE*min..max]
-        let (edge_type, min_hops, max_hops) = if self.current() == &Token::LeftBracket {
+        // Parse edge types: [:TYPE], [e:TYPE|OTHER] or [:TYPE*min..max]
+        let (edge_alias, edge_types, edge_properties, min_hops, max_hops) = if self.current() == &Token::LeftBracket {
             self.advance(); // consume '['
 
-            // Optional colon before type
+            // Optional edge alias, then an optional colon before the types
+            let edge_alias = match (self.current(), self.peek()) {
+                (Token::Identifier(name), Some(Token::Colon)) => {
+                    let alias = name.clone();
+                    self.advance();
+                    Some(alias)
+                }
+                _ => None,
+            };
             if self.current() == &Token::Colon {
                 self.advance();
             }
 
-            let edge_type = if let Token::Identifier(name) = self.current() {
-                let t = Some(name.clone());
+            // No type matches any edge
+            let mut edge_types = Vec::new();
+            if let Token::Identifier(name) = self.current() {
+                edge_types.push(name.clone());
                 self.advance();
-                t
-            } else {
-                None // No type specified
-            };
+
+                while self.current() == &Token::Pipe {
+                    self.advance();
+                    edge_types.push(self.parse_identifier()?);
+                }
+            }
 
             // Check for variable length: *min..max
             let (min, max) = if self.current() == &Token::Star {
                 self.advance();
                 // Parse range
-                let min = if let Token::Integer(n) = self.current() {
+                let explicit_min = if let Token::Integer(n) = self.current() {
                     let m = *n as usize;
                     self.advance();
-                    m
+                    Some(m)
                 } else {
-                    1
+                    None
                 };
+                let min = explicit_min.unwrap_or(1);
 
                 let max = if self.current() == &Token::Dot {
                     self.advance();
@@ -226,8 +392,10 @@ impl Parser {
                     } else {
                         usize::MAX // Unbounded
                     }
+                } else if explicit_min.is_some() {
+                    min // *n: exactly n hops
                 } else {
-                    min
+                    usize::MAX // Bare *: unbounded
                 };
 
                 (min, max)
@@ -235,33 +403,58 @@ impl Parser {
                 (1, 1) // Default: exactly 1 hop
             };
 
+            // Optional edge property match: {key: value, ...}
+            let edge_properties = self.parse_property_map()?;
+
             self.expect(&Token::RightBracket)?;
 
-            (edge_type, min, max)
+            (edge_alias, edge_types, edge_properties, min, max)
         } else {
-            (None, 1, 1)
+            (None, Vec::new(), Vec::new(), 1, 1)
         };
 
-        // Parse arrow for outgoing (already consumed for incoming/both)
-        if direction == Direction::Outgoing && self.current() == &Token::Arrow {
+        // Parse arrow for outgoing, or the closing '-' of an incoming <-[...]- pattern
+        let closing = match direction {
+            Direction::Outgoing => Some(Token::Arrow),
+            Direction::Incoming => Some(Token::Minus),
+            Direction::Both => None,
+        };
+        if closing.as_ref() == Some(self.current()) {
             self.advance();
         }
 
         // Parse target alias
-        let target_alias = if let Token::Identifier(name) = self.current() {
-            let alias = Some(name.clone());
-            self.advance();
-            alias
+        let target_alias = match self.current() {
+            Token::Identifier(name) if !self.at_top_modifier() => {
+                let alias = Some(name.clone());
+                self.advance();
+                alias
+            }
+            _ => None,
+        };
+
+        // Optional TOP k BY PHEROMONE
+        let top_k = if self.at_top_modifier() {
+            self.advance(); // consume TOP
+            let k = self.parse_integer()?;
+            if !(self.consume_word("BY") && self.consume_word("PHEROMONE")) {
+                return Err(format!("Expected BY PHEROMONE after TOP {}, got {:?}", k, self.current()));
+            }
+            Some(k as usize)
         } else {
             None
         };
 
         Ok(TraversePattern {
             direction,
-            edge_type,
+            edge_alias,
+            edge_types,
+            edge_properties,
             target_alias,
             min_hops,
             max_hops,
+            top_k,
+            all_paths,
         })
     }
 
@@ -330,6 +523,26 @@ impl Parser {
                     self.advance();
                     Expression::GreaterThanEq(Box::new(left.clone()), Box::new(self.parse_additive()?))
                 }
+                Token::In | Token::Between | Token::Like => self.parse_set_predicate(left.clone())?,
+                Token::Is => {
+                    self.advance();
+                    let negated = self.current() == &Token::Not;
+                    if negated {
+                        self.advance();
+                    }
+                    self.expect(&Token::Null)?;
+
+                    let is_null = Expression::IsNull(Box::new(left.clone()));
+                    if negated {
+                        Expression::Not(Box::new(is_null))
+                    } else {
+                        is_null
+                    }
+                }
+                Token::Not if matches!(self.peek(), Some(Token::In | Token::Between | Token::Like)) => {
+                    self.advance();
+                    Expression::Not(Box::new(self.parse_set_predicate(left.clone())?))
+                }
                 _ => break,
             };
             left = expr;
@@ -338,6 +551,56 @@ impl Parser {
         Ok(left)
     }
 
+    /// Parse the rest of `expr IN (...)`, `expr IN list`, `expr BETWEEN a AND b` or `expr LIKE 'pattern'`
+    fn parse_set_predicate(&mut self, left: Expression) -> Result<Expression, String> {
+        match self.current() {
+            Token::In => {
+                self.advance();
+
+                // `x IN list` tests membership of a list value
+                if self.current() != &Token::LeftParen {
+                    let list = self.parse_additive()?;
+                    return Ok(Expression::Contains(Box::new(list), Box::new(left)));
+                }
+                if self.peek() == Some(&Token::From) {
+                    return Ok(Expression::InSubquery(Box::new(left), self.parse_value_subquery()?));
+                }
+                self.expect(&Token::LeftParen)?;
+
+                let mut values = Vec::new();
+                while self.current() != &Token::RightParen {
+                    values.push(self.parse_literal()?);
+                    if self.current() == &Token::Comma {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+
+                self.expect(&Token::RightParen)?;
+                Ok(Expression::In(Box::new(left), values))
+            }
+            Token::Between => {
+                self.advance();
+                let low = self.parse_additive()?;
+                self.expect(&Token::And)?;
+                let high = self.parse_additive()?;
+                Ok(Expression::Between(Box::new(left), Box::new(low), Box::new(high)))
+            }
+            Token::Like => {
+                self.advance();
+                match self.current().clone() {
+                    Token::String(pattern) | Token::QuotedIdentifier(pattern) => {
+                        self.advance();
+                        Ok(Expression::Like(Box::new(left), pattern))
+                    }
+                    other => Err(format!("Expected pattern string after LIKE, got {:?}", other)),
+                }
+            }
+            other => Err(format!("Expected IN, BETWEEN or LIKE, got {:?}", other)),
+        }
+    }
+
     fn parse_additive(&mut self) -> Result<Expression, String> {
         let mut left = self.parse_multiplicative()?;
 
@@ -381,30 +644,157 @@ impl Parser {
     }
 
     fn parse_unary(&mut self) -> Result<Expression, String> {
-        if self.current() == &Token::Not {
-            self.advance();
-            Ok(Expression::Not(Box::new(self.parse_unary()?)))
-        } else {
-            self.parse_primary()
+        match self.current() {
+            Token::Not => {
+                self.advance();
+                Ok(Expression::Not(Box::new(self.parse_unary()?)))
+            }
+            Token::Minus => {
+                self.advance();
+
+                // Negative numbers stay literals; anything else is 0 - x
+                Ok(match self.parse_unary()? {
+                    Expression::Literal(Literal::Integer(n)) => Expression::Literal(Literal::Integer(-n)),
+                    Expression::Literal(Literal::Float(f)) => Expression::Literal(Literal::Float(-f)),
+                    operand => Expression::Subtract(
+                        Box::new(Expression::Literal(Literal::Integer(0))),
+                        Box::new(operand),
+                    ),
+                })
+            }
+            _ => self.parse_primary(),
         }
     }
 
     fn parse_primary(&mut self) -> Result<Expression, String> {
         match self.current().clone() {
             // Aggregate functions
-            Token::Count => self.parse_aggregate_function(AggregateFunction::Count),
-            Token::Sum => self.parse_aggregate_function(AggregateFunction::Sum),
-            Token::Avg => self.parse_aggregate_function(AggregateFunction::Avg),
-            Token::Min => self.parse_aggregate_function(AggregateFunction::Min),
-            Token::Max => self.parse_aggregate_function(AggregateFunction::Max),
+            Token::Count if self.peek() == Some(&Token::LeftParen) => self.parse_aggregate_function(AggregateFunction::Count),
+            Token::Sum if self.peek() == Some(&Token::LeftParen) => self.parse_aggregate_function(AggregateFunction::Sum),
+            Token::Avg if self.peek() == Some(&Token::LeftParen) => self.parse_aggregate_function(AggregateFunction::Avg),
+            Token::Min if self.peek() == Some(&Token::LeftParen) => self.parse_aggregate_function(AggregateFunction::Min),
+            Token::Max if self.peek() == Some(&Token::LeftParen) => self.parse_aggregate_function(AggregateFunction::Max),
+
+            // A keyword used as a property name: `SELECT level`
+            token if token.as_name().is_some() => Ok(Expression::Property(PropertyRef {
+                entity: None,
+                property: self.parse_identifier()?,
+            })),
+
+            // TIMESTAMP '2024-05-01T12:00:00Z'
+            Token::Identifier(name)
+                if name.eq_ignore_ascii_case("timestamp")
+                    && matches!(self.peek(), Some(Token::String(_) | Token::QuotedIdentifier(_))) =>
+            {
+                Ok(Expression::Literal(self.parse_literal()?))
+            }
+
+            // NOW() is the statement's start time, bound at execution
+            Token::Identifier(name)
+                if name.eq_ignore_ascii_case("now") && matches!(self.peek(), Some(Token::LeftParen)) =>
+            {
+                self.advance();
+                self.advance();
+                self.expect(&Token::RightParen)?;
+                Ok(Expression::Parameter(NOW_PARAMETER.to_string()))
+            }
+
+            // INTERVAL 7 DAYS is a duration in milliseconds
+            Token::Identifier(name)
+                if name.eq_ignore_ascii_case("interval") && matches!(self.peek(), Some(Token::Integer(_))) =>
+            {
+                self.advance();
+                Ok(Expression::Literal(Literal::Integer(self.parse_interval()?)))
+            }
+
+            // EXISTS (FROM ... SELECT ...)
+            Token::Identifier(name)
+                if name.eq_ignore_ascii_case("exists") && matches!(self.peek(), Some(Token::LeftParen)) =>
+            {
+                self.advance();
+                Ok(Expression::Exists(self.parse_subquery()?))
+            }
+
+            // @id is the entity's id
+            Token::At => Ok(Expression::Property(PropertyRef {
+                entity: None,
+                property: self.parse_property_name()?,
+            })),
+
+            // PHEROMONE(e) reads a bound edge's pheromone strength
+            Token::Identifier(name)
+                if name.eq_ignore_ascii_case("pheromone") && matches!(self.peek(), Some(Token::LeftParen)) =>
+            {
+                self.advance();
+                self.advance();
+                let binding = self.parse_identifier()?;
+                self.expect(&Token::RightParen)?;
+                Ok(Expression::Property(PropertyRef {
+                    entity: Some(binding),
+                    property: PHEROMONE_PROPERTY.to_string(),
+                }))
+            }
+
+            // OUT_DEGREE(u [, 'TYPE']), IN_DEGREE(...) and DEGREE(...) count a bound entity's edges
+            Token::Identifier(name)
+                if ["out_degree", "in_degree", "degree"].iter().any(|f| name.eq_ignore_ascii_case(f))
+                    && matches!(self.peek(), Some(Token::LeftParen)) =>
+            {
+                let direction = match name.to_ascii_lowercase().as_str() {
+                    "out_degree" => Direction::Outgoing,
+                    "in_degree" => Direction::Incoming,
+                    _ => Direction::Both,
+                };
+                self.parse_degree(direction)
+            }
+
+            // HAS(u.phone) tells a missing property from one holding NULL
+            Token::Identifier(name) if name.eq_ignore_ascii_case("has") && matches!(self.peek(), Some(Token::LeftParen)) => {
+                self.parse_has()
+            }
+
+            Token::Identifier(name)
+                if (name.eq_ignore_ascii_case("shortest_path") || name.eq_ignore_ascii_case("shortest_path_length"))
+                    && matches!(self.peek(), Some(Token::LeftParen)) =>
+            {
+                self.parse_shortest_path(name.eq_ignore_ascii_case("shortest_path_length"))
+            }
+
+            Token::Identifier(name) if name.eq_ignore_ascii_case("match") && matches!(self.peek(), Some(Token::LeftParen)) => {
+                self.parse_match()
+            }
+
+            Token::Identifier(name) if name.eq_ignore_ascii_case("score") && matches!(self.peek(), Some(Token::LeftParen)) => {
+                self.advance();
+                self.advance();
+                self.expect(&Token::RightParen)?;
+                Ok(Expression::Property(PropertyRef {
+                    entity: None,
+                    property: SCORE_PROPERTY.to_string(),
+                }))
+            }
+
+            // Scalar function call: LOWER(u.name), CONCAT(u.first, ' ', u.last)
+            Token::Identifier(name)
+                if matches!(self.peek(), Some(Token::LeftParen)) && ScalarFunction::lookup(&name).is_some() =>
+            {
+                self.parse_function_call()
+            }
 
-            Token::Identifier(name) => {
+            // A quoted identifier is a name, never a string: `u."unit price"`
+            Token::Identifier(name) | Token::QuotedIdentifier(name) => {
                 self.advance();
 
-                // Check for property reference: entity.property
+                // Check for property reference: entity.property, then any
+                // path into a nested document (entity.property.key...)
                 if self.current() == &Token::Dot {
                     self.advance();
-                    let property = self.parse_identifier()?;
+                    let mut property = self.parse_property_name()?;
+                    while self.current() == &Token::Dot && !property.starts_with('@') {
+                        self.advance();
+                        property.push('.');
+                        property.push_str(&self.parse_identifier()?);
+                    }
                     Ok(Expression::Property(PropertyRef {
                         entity: Some(name),
                         property,
@@ -440,6 +830,14 @@ impl Parser {
                 self.advance();
                 Ok(Expression::Literal(Literal::Null))
             }
+            Token::Parameter(name) => {
+                self.advance();
+                Ok(Expression::Parameter(name))
+            }
+            Token::LeftBracket | Token::LeftBrace => Ok(Expression::Literal(self.parse_literal()?)),
+            Token::LeftParen if self.peek() == Some(&Token::From) => {
+                Ok(Expression::ScalarSubquery(self.parse_value_subquery()?))
+            }
             Token::LeftParen => {
                 self.advance();
                 let expr = self.parse_expression()?;
@@ -450,11 +848,148 @@ impl Parser {
         }
     }
 
-    /// Parse aggregate function call: COUNT(*), SUM(field), etc.
+    /// Parse a parenthesized `(FROM ... SELECT ...)`, numbering it
+    fn parse_subquery(&mut self) -> Result<Subquery, String> {
+        self.expect(&Token::LeftParen)?;
+        let id = self.subqueries;
+        self.subqueries += 1;
+        let query = self.parse_select()?;
+        self.expect(&Token::RightParen)?;
+
+        Ok(Subquery {
+            id,
+            query: Box::new(query),
+        })
+    }
+
+    /// Parse a subquery used as a value (with IN or as a scalar), which must
+    /// select exactly one column
+    fn parse_value_subquery(&mut self) -> Result<Subquery, String> {
+        let subquery = self.parse_subquery()?;
+        match subquery.query.select.fields.as_slice() {
+            [field] if !matches!(field.expression, Expression::Wildcard(_)) => Ok(subquery),
+            _ => Err("A subquery used as a value must select exactly one column".to_string()),
+        }
+    }
+
+    /// Parse SHORTEST_PATH(a, b [, :TYPE] [, MAX n]) and SHORTEST_PATH_LENGTH(...)
+    fn parse_shortest_path(&mut self, length_only: bool) -> Result<Expression, String> {
+        self.advance(); // consume function name
+        self.expect(&Token::LeftParen)?;
+
+        let source = self.parse_identifier()?;
+        self.expect(&Token::Comma)?;
+        let target = self.parse_identifier()?;
+
+        let mut edge_type = None;
+        let mut max_depth = None;
+        while self.current() == &Token::Comma {
+            self.advance();
+            match self.current() {
+                Token::Colon if edge_type.is_none() && max_depth.is_none() => {
+                    self.advance();
+                    edge_type = Some(self.parse_identifier()?);
+                }
+                Token::Max if max_depth.is_none() => {
+                    self.advance();
+                    let depth = self.parse_integer()?;
+                    if depth < 0 {
+                        return Err(format!("SHORTEST_PATH depth must not be negative, got {}", depth));
+                    }
+                    max_depth = Some(depth as usize);
+                }
+                other => return Err(format!("Expected :TYPE or MAX in SHORTEST_PATH, got {:?}", other)),
+            }
+        }
+
+        self.expect(&Token::RightParen)?;
+        Ok(Expression::ShortestPath(PathCall {
+            source,
+            target,
+            edge_type,
+            max_depth,
+            length_only,
+        }))
+    }
+
+    /// Parse the arguments of OUT_DEGREE, IN_DEGREE or DEGREE: a binding and
+    /// an optional edge type
+    fn parse_degree(&mut self, direction: Direction) -> Result<Expression, String> {
+        self.advance(); // consume function name
+        self.expect(&Token::LeftParen)?;
+
+        let binding = self.parse_identifier()?;
+        let mut edge_type = None;
+        if self.current() == &Token::Comma {
+            self.advance();
+            match self.current().clone() {
+                Token::String(name) | Token::QuotedIdentifier(name) => {
+                    self.advance();
+                    edge_type = Some(name);
+                }
+                other => return Err(format!("Expected an edge type string in a degree function, got {:?}", other)),
+            }
+        }
+
+        self.expect(&Token::RightParen)?;
+        Ok(Expression::Property(PropertyRef {
+            entity: Some(binding),
+            property: degree_property(&direction, edge_type.as_deref()),
+        }))
+    }
+
+    /// Parse HAS(binding.property)
+    fn parse_has(&mut self) -> Result<Expression, String> {
+        self.advance(); // consume HAS
+        self.expect(&Token::LeftParen)?;
+
+        let Expression::Property(property) = self.parse_primary()? else {
+            return Err("HAS needs a property, as in HAS(u.phone)".to_string());
+        };
+        if property.property.starts_with('@') {
+            return Err(format!("HAS needs a stored property, got {}", property.property));
+        }
+
+        self.expect(&Token::RightParen)?;
+        Ok(Expression::Has(property))
+    }
+
+    /// Parse MATCH(field, 'terms')
+    fn parse_match(&mut self) -> Result<Expression, String> {
+        self.advance(); // consume MATCH
+        self.expect(&Token::LeftParen)?;
+
+        let field = self.parse_primary()?;
+        if !matches!(field, Expression::Property(_)) {
+            return Err("MATCH needs a property as its first argument".to_string());
+        }
+        self.expect(&Token::Comma)?;
+        let text = match self.current().clone() {
+            Token::String(text) | Token::QuotedIdentifier(text) => text,
+            other => return Err(format!("Expected the terms to MATCH as a string, got {:?}", other)),
+        };
+        if !text.chars().any(char::is_alphanumeric) {
+            return Err("MATCH needs at least one term to search for".to_string());
+        }
+        self.advance();
+        self.expect(&Token::RightParen)?;
+
+        Ok(Expression::Match(Box::new(field), text))
+    }
+
+    /// Parse aggregate function call: COUNT(*), SUM(field), COUNT(DISTINCT field), etc.
     fn parse_aggregate_function(&mut self, func: AggregateFunction) -> Result<Expression, String> {
         self.advance(); // consume function name
         self.expect(&Token::LeftParen)?;
 
+        let distinct = self.current() == &Token::Distinct;
+        if distinct {
+            self.advance();
+            if self.current() == &Token::Star {
+                return Err("DISTINCT needs an expression, not *".to_string());
+            }
+        }
+
         let argument = if self.current() == &Token::Star {
             // COUNT(*) - special case
             self.advance();
@@ -466,14 +1001,29 @@ impl Parser {
 
         self.expect(&Token::RightParen)?;
 
-        Ok(Expression::Aggregate(func, Box::new(argument)))
+        Ok(Expression::Aggregate(func, Box::new(argument), distinct))
     }
 
     /// Parse SELECT clause
     fn parse_select_clause(&mut self) -> Result<SelectClause, String> {
+        let distinct = self.current() == &Token::Distinct;
+        if distinct {
+            self.advance();
+        }
+
         let mut fields = Vec::new();
 
         loop {
+            // `*` and `alias.*` expand to whole entities, so take no alias
+            if let Some(wildcard) = self.parse_wildcard() {
+                fields.push(SelectField { expression: wildcard, alias: None });
+                if self.current() == &Token::Comma {
+                    self.advance();
+                    continue;
+                }
+                break;
+            }
+
             let expression = self.parse_expression()?;
 
             let alias = if self.current() == &Token::As {
@@ -492,7 +1042,25 @@ impl Parser {
             }
         }
 
-        Ok(SelectClause { fields })
+        Ok(SelectClause { distinct, fields })
+    }
+
+    /// Parse a `*` or `alias.*` SELECT field, if one comes next
+    fn parse_wildcard(&mut self) -> Option<Expression> {
+        match self.current() {
+            Token::Star => {
+                self.advance();
+                Some(Expression::Wildcard(None))
+            }
+            Token::Identifier(alias)
+                if self.peek() == Some(&Token::Dot) && self.tokens.get(self.position + 2) == Some(&Token::Star) =>
+            {
+                let alias = alias.clone();
+                self.position += 3;
+                Some(Expression::Wildcard(Some(alias)))
+            }
+            _ => None,
+        }
     }
 
     /// Parse ORDER BY clause
@@ -566,6 +1134,19 @@ impl Parser {
         let collection = self.parse_identifier()?;
 
         self.expect(&Token::Values)?;
+
+        // One or more rows: ({...}), ({...}), ...
+        let mut rows = vec![self.parse_insert_row()?];
+        while self.current() == &Token::Comma {
+            self.advance();
+            rows.push(self.parse_insert_row()?);
+        }
+
+        Ok(InsertQuery { collection, rows })
+    }
+
+    /// Parse one parenthesized INSERT row
+    fn parse_insert_row(&mut self) -> Result<Vec<(String, Expression)>, String> {
         self.expect(&Token::LeftParen)?;
 
         let mut properties = Vec::new();
@@ -577,7 +1158,7 @@ impl Parser {
             loop {
                 let key = self.parse_identifier()?;
                 self.expect(&Token::Colon)?;
-                let value = self.parse_literal()?;
+                let value = self.parse_assigned_value()?;
 
                 properties.push((key, value));
 
@@ -593,10 +1174,26 @@ impl Parser {
 
         self.expect(&Token::RightParen)?;
 
-        Ok(InsertQuery {
-            collection,
-            properties,
-        })
+        Ok(properties)
+    }
+
+    /// Parse a value assigned by INSERT or SET
+    ///
+    /// A double-quoted token that is the whole value is a string, as values
+    /// have always been written; anywhere else it's a name.
+    fn parse_assigned_value(&mut self) -> Result<Expression, String> {
+        match self.current().clone() {
+            Token::QuotedIdentifier(s)
+                if matches!(
+                    self.peek(),
+                    None | Some(Token::Comma | Token::RightBrace | Token::Where | Token::Semicolon | Token::Eof)
+                ) =>
+            {
+                self.advance();
+                Ok(Expression::Literal(Literal::String(s)))
+            }
+            _ => self.parse_expression(),
+        }
     }
 
     /// Parse UPDATE query
@@ -605,14 +1202,38 @@ impl Parser {
 
         let collection = self.parse_identifier()?;
 
+        let alias = if let Token::As = self.current() {
+            self.advance();
+            Some(self.parse_identifier()?)
+        } else if let Token::Identifier(_) = self.current() {
+            // Implicit alias without AS keyword
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+
         self.expect(&Token::Set)?;
 
         let mut set = Vec::new();
 
         loop {
-            let property = self.parse_identifier()?;
+            let mut property = self.parse_identifier()?;
+
+            // Qualified target: alias.property (must name the UPDATE target)
+            if self.current() == &Token::Dot {
+                self.advance();
+                let target = alias.as_deref().unwrap_or(&collection);
+                if property != target {
+                    return Err(format!(
+                        "SET target '{}' does not match UPDATE target '{}'",
+                        property, target
+                    ));
+                }
+                property = self.parse_identifier()?;
+            }
+
             self.expect(&Token::Equal)?;
-            let value = self.parse_expression()?;
+            let value = self.parse_assigned_value()?;
 
             set.push((property, value));
 
@@ -631,6 +1252,7 @@ impl Parser {
 
         Ok(UpdateQuery {
             collection,
+            alias,
             set,
             where_clause,
         })
@@ -674,35 +1296,117 @@ impl Parser {
         let target = self.parse_expression()?;
         self.expect(&Token::RightParen)?;
 
-        let properties = if self.current() == &Token::LeftBrace {
-            self.advance();
-            let mut props = Vec::new();
+        let properties = self.parse_property_map()?;
 
-            loop {
-                let key = self.parse_identifier()?;
-                self.expect(&Token::Colon)?;
-                let value = self.parse_literal()?;
+        Ok(CreateQuery {
+            edge_type,
+            source,
+            target,
+            properties,
+        })
+    }
 
-                props.push((key, value));
+    /// Parse an optional property map: {key: literal, ...}
+    fn parse_property_map(&mut self) -> Result<Vec<(String, Literal)>, String> {
+        let mut props = Vec::new();
+        if self.current() != &Token::LeftBrace {
+            return Ok(props);
+        }
+        self.advance();
 
-                if self.current() == &Token::Comma {
-                    self.advance();
-                } else {
-                    break;
-                }
+        loop {
+            let key = self.parse_identifier()?;
+            self.expect(&Token::Colon)?;
+            let value = self.parse_literal()?;
+
+            props.push((key, value));
+
+            if self.current() == &Token::Comma {
+                self.advance();
+            } else {
+                break;
             }
+        }
 
-            self.expect(&Token::RightBrace)?;
-            props
+        self.expect(&Token::RightBrace)?;
+        Ok(props)
+    }
+
+    /// Whether the statement continues with `EDGE FROM` (UPDATE/DELETE of edges)
+    ///
+    /// EDGE is not reserved, so `UPDATE Edge SET ...` still names a collection.
+    fn at_edge_mutation(&self) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("edge"))
+            && self.tokens.get(self.position + 2) == Some(&Token::From)
+    }
+
+    /// Parse the edges an edge mutation targets: EDGE FROM Collection [alias] -[:TYPE]-> target
+    fn parse_edge_match(&mut self) -> Result<(FromClause, TraversePattern), String> {
+        self.advance(); // consume EDGE
+        let from = self.parse_from()?;
+        let pattern = self.parse_traverse_pattern()?;
+
+        if pattern.min_hops != 1 || pattern.max_hops != 1 {
+            return Err("Edge mutations must match single-hop edges".to_string());
+        }
+        if pattern.top_k.is_some() {
+            return Err("TOP BY PHEROMONE is only supported in TRAVERSE".to_string());
+        }
+
+        Ok((from, pattern))
+    }
+
+    /// Parse UPDATE EDGE query
+    fn parse_update_edge(&mut self) -> Result<UpdateEdgeQuery, String> {
+        self.expect(&Token::Update)?;
+        let (from, pattern) = self.parse_edge_match()?;
+
+        self.expect(&Token::Set)?;
+
+        let mut set = Vec::new();
+        loop {
+            let property = self.parse_identifier()?;
+            self.expect(&Token::Equal)?;
+            let value = self.parse_assigned_value()?;
+
+            set.push((property, value));
+
+            if self.current() == &Token::Comma {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        let where_clause = if self.current() == &Token::Where {
+            Some(self.parse_where()?)
         } else {
-            Vec::new()
+            None
         };
 
-        Ok(CreateQuery {
-            edge_type,
-            source,
-            target,
-            properties,
+        Ok(UpdateEdgeQuery {
+            from,
+            pattern,
+            set,
+            where_clause,
+        })
+    }
+
+    /// Parse DELETE EDGE query
+    fn parse_delete_edge(&mut self) -> Result<DeleteEdgeQuery, String> {
+        self.expect(&Token::Delete)?;
+        let (from, pattern) = self.parse_edge_match()?;
+
+        let where_clause = if self.current() == &Token::Where {
+            Some(self.parse_where()?)
+        } else {
+            None
+        };
+
+        Ok(DeleteEdgeQuery {
+            from,
+            pattern,
+            where_clause,
         })
     }
 
@@ -749,17 +1453,18 @@ impl Parser {
         Ok(BeginQuery { isolation_level })
     }
 
-    /// Parse CREATE INDEX or CREATE UNIQUE INDEX
+    /// Parse CREATE INDEX, CREATE UNIQUE INDEX or CREATE FULLTEXT INDEX
     fn parse_create_index(&mut self) -> Result<CreateIndexQuery, String> {
         self.expect(&Token::Create)?;
 
-        // Check for UNIQUE keyword
+        // Check for UNIQUE or FULLTEXT keyword
         let unique = if self.current() == &Token::Unique {
             self.advance();
             true
         } else {
             false
         };
+        let fulltext = !unique && self.consume_word("FULLTEXT");
 
         self.expect(&Token::Index)?;
 
@@ -782,6 +1487,7 @@ impl Parser {
             collection,
             field,
             unique,
+            fulltext,
         })
     }
 
@@ -795,149 +1501,1363 @@ impl Parser {
         Ok(DropIndexQuery { index_name })
     }
 
-    // Helper methods
+    /// Parse SET session option
+    fn parse_set(&mut self) -> Result<SetQuery, String> {
+        self.expect(&Token::Set)?;
 
-    fn current(&self) -> &Token {
-        self.tokens.get(self.position).unwrap_or(&Token::Eof)
-    }
+        let name = self.parse_identifier()?.to_lowercase();
+        self.expect(&Token::Equal)?;
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.position + 1)
-    }
+        // Bare words (SET archive_reads = off) are taken as strings; ON lexes as a keyword
+        let value = match self.current().clone() {
+            Token::Identifier(word) => {
+                self.advance();
+                Literal::String(word)
+            }
+            Token::On => {
+                self.advance();
+                Literal::String("on".to_string())
+            }
+            _ => self.parse_literal()?,
+        };
 
-    fn advance(&mut self) {
-        if self.position < self.tokens.len() {
-            self.position += 1;
-        }
+        Ok(SetQuery { name, value })
     }
 
-    fn expect(&mut self, expected: &Token) -> Result<(), String> {
-        if std::mem::discriminant(self.current()) == std::mem::discriminant(expected) {
-            self.advance();
-            Ok(())
-        } else {
-            Err(format!("Expected {:?}, got {:?}", expected, self.current()))
-        }
-    }
+    /// Parse REINDEX: REINDEX idx_name / REINDEX COLLECTION Users
+    fn parse_reindex(&mut self) -> Result<ReindexQuery, String> {
+        self.expect(&Token::Reindex)?;
 
-    fn parse_identifier(&mut self) -> Result<String, String> {
-        if let Token::Identifier(name) = self.current() {
-            let result = name.clone();
-            self.advance();
-            Ok(result)
+        let name = self.parse_identifier()?;
+
+        // COLLECTION is only a keyword here, so it is matched as an identifier
+        if name.eq_ignore_ascii_case("collection") && matches!(self.current(), Token::Identifier(_)) {
+            Ok(ReindexQuery::Collection(self.parse_identifier()?))
         } else {
-            Err(format!("Expected identifier, got {:?}", self.current()))
+            Ok(ReindexQuery::Index(name))
         }
     }
 
-    fn parse_integer(&mut self) -> Result<i64, String> {
-        if let Token::Integer(n) = self.current() {
-            let result = *n;
+    /// Parse FIREWALL admin command
+    ///
+    /// Sub-commands (ALLOW, DENY, LEARN, ENFORCE) and condition words are
+    /// matched as identifiers so they stay usable as property names.
+    fn parse_firewall(&mut self) -> Result<FirewallQuery, String> {
+        self.expect(&Token::Firewall)?;
+
+        if self.current() == &Token::Drop {
             self.advance();
-            Ok(result)
-        } else {
-            Err(format!("Expected integer, got {:?}", self.current()))
+            return Ok(FirewallQuery::DropRule(self.parse_identifier()?));
         }
-    }
 
-    fn parse_literal(&mut self) -> Result<Literal, String> {
-        match self.current().clone() {
-            Token::Integer(n) => {
-                self.advance();
-                Ok(Literal::Integer(n))
-            }
-            Token::Float(f) => {
-                self.advance();
-                Ok(Literal::Float(f))
-            }
-            Token::String(s) => {
-                self.advance();
-                Ok(Literal::String(s))
+        let command = self.parse_identifier()?.to_uppercase();
+        match command.as_str() {
+            "ALLOW" | "DENY" => {
+                let action = if command == "ALLOW" {
+                    FirewallAction::Allow
+                } else {
+                    FirewallAction::Deny
+                };
+                let name = self.parse_identifier()?;
+                let subject = self.parse_firewall_subject()?;
+
+                let mut conditions = Vec::new();
+                if self.consume_word("WHEN") {
+                    loop {
+                        conditions.push(self.parse_firewall_condition()?);
+                        if self.current() == &Token::Comma {
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+
+                Ok(FirewallQuery::AddRule(FirewallRule {
+                    name,
+                    subject,
+                    action,
+                    conditions,
+                }))
             }
-            Token::True => {
-                self.advance();
-                Ok(Literal::Bool(true))
+            "LEARN" => {
+                let subject = self.parse_firewall_subject()?;
+                let duration = self.parse_literal()?;
+                Ok(FirewallQuery::Learn { subject, duration })
             }
-            Token::False => {
+            "ENFORCE" => Ok(FirewallQuery::Enforce(self.parse_firewall_subject()?)),
+            other => Err(format!("Unknown FIREWALL command: {}", other)),
+        }
+    }
+
+    /// Parse FOR USER|ROLE|KEY 'subject'
+    fn parse_firewall_subject(&mut self) -> Result<FirewallSubject, String> {
+        if !self.consume_word("FOR") {
+            return Err(format!("Expected FOR, got {:?}", self.current()));
+        }
+
+        let kind = self.parse_identifier()?.to_uppercase();
+        let value = match self.current().clone() {
+            Token::String(s) | Token::QuotedIdentifier(s) => {
                 self.advance();
-                Ok(Literal::Bool(false))
+                s
             }
-            Token::Null => {
-                self.advance();
-                Ok(Literal::Null)
+            _ => self.parse_identifier()?,
+        };
+
+        match kind.as_str() {
+            "USER" => Ok(FirewallSubject::User(value)),
+            "KEY" => Ok(FirewallSubject::ApiKey(value)),
+            "ROLE" => match value.to_lowercase().as_str() {
+                "admin" => Ok(FirewallSubject::Role(Role::Admin)),
+                "readwrite" => Ok(FirewallSubject::Role(Role::ReadWrite)),
+                "readonly" => Ok(FirewallSubject::Role(Role::ReadOnly)),
+                other => Err(format!("Unknown role: {}", other)),
+            },
+            other => Err(format!("Expected USER, ROLE or KEY, got {}", other)),
+        }
+    }
+
+    /// Parse a single firewall rule condition
+    fn parse_firewall_condition(&mut self) -> Result<FirewallCondition, String> {
+        let class = match self.current() {
+            Token::Select => Some(StatementClass::Select),
+            Token::Insert => Some(StatementClass::Insert),
+            Token::Update => Some(StatementClass::Update),
+            Token::Delete => Some(StatementClass::Delete),
+            Token::Create => Some(StatementClass::Create),
+            Token::Drop => Some(StatementClass::Drop),
+            Token::Transaction => Some(StatementClass::Transaction),
+            _ => None,
+        };
+        if let Some(class) = class {
+            self.advance();
+            return Ok(FirewallCondition::Class(class));
+        }
+
+        let word = self.parse_identifier()?.to_uppercase();
+        match word.as_str() {
+            "ADMIN" => Ok(FirewallCondition::Class(StatementClass::Admin)),
+            "COLLECTION" => Ok(FirewallCondition::Collection(self.parse_identifier()?)),
+            "EDGE" => Ok(FirewallCondition::EdgeType(self.parse_identifier()?)),
+            "NO_LIMIT" => Ok(FirewallCondition::NoLimit),
+            "FULL_SCAN" => Ok(FirewallCondition::FullScan),
+            "UNBOUNDED_TRAVERSAL" => Ok(FirewallCondition::UnboundedTraversal),
+            "UNBOUNDED" => Ok(FirewallCondition::Unbounded),
+            "COST" => {
+                self.expect(&Token::GreaterThan)?;
+                let threshold = match self.parse_literal()? {
+                    Literal::Integer(n) => n as f64,
+                    Literal::Float(f) => f,
+                    other => return Err(format!("Expected cost threshold, got {:?}", other)),
+                };
+                Ok(FirewallCondition::CostAbove(threshold))
             }
-            _ => Err(format!("Expected literal, got {:?}", self.current())),
+            other => Err(format!("Unknown firewall condition: {}", other)),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Parse EXPLAIN [ANALYZE] statement
+    fn parse_explain(&mut self) -> Result<ExplainQuery, String> {
+        self.advance(); // consume EXPLAIN
+        let analyze = self.consume_word("ANALYZE");
 
-    #[test]
-    fn test_parse_simple_select() {
-        let query = "FROM Users WHERE age = 25 SELECT name";
-        let result = Parser::parse(query).unwrap();
+        let query = match self.current() {
+            Token::From | Token::Insert | Token::Update | Token::Delete => self.parse_query()?,
+            Token::Create if self.peek() != Some(&Token::Index) && self.peek() != Some(&Token::Unique) => {
+                self.parse_query()?
+            }
+            other => return Err(format!("EXPLAIN expects a query or mutation, got {:?}", other)),
+        };
 
-        if let Query::Select(select) = result {
-            assert_eq!(select.from.collection, "Users");
-            assert!(select.traverse.is_none());
-            assert!(select.where_clause.is_some());
-        } else {
-            panic!("Expected SELECT query");
-        }
+        Ok(ExplainQuery {
+            query: Box::new(query),
+            analyze,
+        })
     }
 
-    #[test]
-    fn test_parse_hybrid_query() {
-        let query = "FROM Users u TRAVERSE -[:PURCHASED]-> p WHERE p.price > 100 SELECT u.name, p.name";
-        let result = Parser::parse(query).unwrap();
+    /// Parse DEFINE SCHEMA Collection (field Type [constraint ...], ...)
+    /// [ALLOW EXTRA] [COERCE TIMESTAMPS] [TTL n UNIT]
+    fn parse_define_schema(&mut self) -> Result<Schema, String> {
+        self.advance(); // consume DEFINE
+        if !self.consume_word("SCHEMA") {
+            return Err(format!("Expected SCHEMA, got {:?}", self.current()));
+        }
 
-        if let Query::Select(select) = result {
-            assert_eq!(select.from.collection, "Users");
-            assert_eq!(select.from.alias, Some("u".to_string()));
-            assert!(select.traverse.is_some());
+        let mut schema = Schema::new(self.parse_identifier()?);
 
-            let traverse = select.traverse.unwrap();
-            assert_eq!(traverse.patterns.len(), 1);
-            assert_eq!(
-                traverse.patterns[0].edge_type,
-                Some("PURCHASED".to_string())
-            );
-            assert_eq!(traverse.patterns[0].target_alias, Some("p".to_string()));
-        } else {
-            panic!("Expected SELECT query");
+        self.expect(&Token::LeftParen)?;
+        loop {
+            let field = self.parse_field_definition()?;
+            if schema.get_field(&field.name).is_some() {
+                return Err(format!("Field '{}' defined twice", field.name));
+            }
+            schema.add_field(field);
+
+            if self.current() == &Token::Comma {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect(&Token::RightParen)?;
+
+        loop {
+            if self.consume_word("ALLOW") {
+                if !self.consume_word("EXTRA") {
+                    return Err(format!("Expected EXTRA, got {:?}", self.current()));
+                }
+                schema.allow_extra_properties = true;
+            } else if self.consume_word("COERCE") {
+                if !self.consume_word("TIMESTAMPS") {
+                    return Err(format!("Expected TIMESTAMPS, got {:?}", self.current()));
+                }
+                schema.coerce_timestamps = true;
+            } else if self.consume_word("TTL") {
+                let millis = self.parse_interval()?;
+                if millis <= 0 {
+                    return Err("TTL must be positive".to_string());
+                }
+                schema.ttl = Some(std::time::Duration::from_millis(millis as u64));
+            } else {
+                break;
+            }
         }
+
+        Ok(schema)
     }
 
-    #[test]
-    fn test_parse_variable_length_traverse() {
-        let query = "FROM Users TRAVERSE -[:FOLLOWS*1..3]-> friend SELECT friend.name";
-        let result = Parser::parse(query).unwrap();
+    /// Parse a field type: STRING, INTEGER, ..., JSON or ARRAY<Type>
+    fn parse_field_type(&mut self) -> Result<FieldType, String> {
+        let type_name = self.parse_identifier()?;
+        let field_type = match type_name.to_uppercase().as_str() {
+            "STRING" => FieldType::String,
+            "INTEGER" | "INT" => FieldType::Integer,
+            "FLOAT" => FieldType::Float,
+            "BOOLEAN" | "BOOL" => FieldType::Boolean,
+            "TIMESTAMP" => FieldType::Timestamp,
+            "BYTES" => FieldType::Bytes,
+            "JSON" => FieldType::Json,
+            "ARRAY" => {
+                self.expect(&Token::LessThan)?;
+                let element = self.parse_field_type()?;
+                self.expect(&Token::GreaterThan)?;
+                FieldType::Array(Box::new(element))
+            }
+            _ => return Err(format!("Unknown field type: {}", type_name)),
+        };
 
-        if let Query::Select(select) = result {
-            let traverse = select.traverse.unwrap();
-            assert_eq!(traverse.patterns[0].min_hops, 1);
-            assert_eq!(traverse.patterns[0].max_hops, 3);
-        } else {
-            panic!("Expected SELECT query");
-        }
+        Ok(field_type)
     }
 
-    #[test]
-    fn test_parse_with_order_and_limit() {
-        let query = "FROM Products WHERE price > 50 SELECT name, price ORDER BY price DESC LIMIT 10";
-        let result = Parser::parse(query).unwrap();
+    /// Parse a schema field: name Type [NOT NULL] [UNIQUE] [PRIMARY KEY] [INDEX] [DEFAULT value] [CHECK(expr)]
+    fn parse_field_definition(&mut self) -> Result<Field, String> {
+        let name = self.parse_identifier()?;
+
+        let field_type = self.parse_field_type()?;
+
+        let mut field = Field::new(name, field_type);
+        loop {
+            field = match self.current().clone() {
+                Token::Not => {
+                    self.advance();
+                    self.expect(&Token::Null)?;
+                    field.with_constraint(Constraint::NotNull)
+                }
+                Token::Unique => {
+                    self.advance();
+                    field.with_constraint(Constraint::Unique)
+                }
+                Token::Index => {
+                    self.advance();
+                    field.with_constraint(Constraint::Index)
+                }
+                _ if self.consume_word("PRIMARY") => {
+                    if !self.consume_word("KEY") {
+                        return Err(format!("Expected KEY, got {:?}", self.current()));
+                    }
+                    field.with_constraint(Constraint::PrimaryKey)
+                }
+                _ if self.consume_word("DEFAULT") => {
+                    let value = self.parse_default_value()?;
+                    field.with_constraint(Constraint::Default(value))
+                }
+                _ if self.consume_word("CHECK") => {
+                    let expression = self.parse_check_source()?;
+                    field.with_check(expression)
+                }
+                _ => break,
+            };
+        }
+
+        Ok(field)
+    }
+
+    /// Parse a DEFAULT value: a literal, or a negated number
+    fn parse_default_value(&mut self) -> Result<PropertyValue, String> {
+        let negate = self.current() == &Token::Minus;
+        if negate {
+            self.advance();
+        }
+
+        match (self.parse_literal()?, negate) {
+            (Literal::Integer(n), true) => Ok(PropertyValue::Int(-n)),
+            (Literal::Float(f), true) => Ok(PropertyValue::Float(-f)),
+            (other, true) => Err(format!("Expected number after '-', got {:?}", other)),
+            (literal, false) => Ok(literal.to_property_value()),
+        }
+    }
+
+    /// Parse CHECK's parenthesized expression, returning its source text
+    fn parse_check_source(&mut self) -> Result<String, String> {
+        self.expect(&Token::LeftParen)?;
+
+        let mut source = String::new();
+        let mut depth = 0;
+        loop {
+            let token = self.current().clone();
+            match token {
+                Token::Eof => return Err("Unterminated CHECK expression".to_string()),
+                Token::RightParen if depth == 0 => break,
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                _ => {}
+            }
+
+            let text = token_source(&token)?;
+            if !(source.is_empty() || token == Token::Dot || source.ends_with('.')) {
+                source.push(' ');
+            }
+            source.push_str(&text);
+            self.advance();
+        }
+        self.expect(&Token::RightParen)?;
+
+        // Reject expressions that wouldn't parse when the constraint is checked
+        Parser::parse_expression_source(&source)?;
+        Ok(source)
+    }
+
+    /// Parse a scalar function call: NAME(arg, ...)
+    fn parse_function_call(&mut self) -> Result<Expression, String> {
+        let name = self.parse_identifier()?;
+        let function = ScalarFunction::lookup(&name).ok_or_else(|| format!("Unknown function: {}", name))?;
+        self.expect(&Token::LeftParen)?;
+
+        let mut args = Vec::new();
+        if self.current() != &Token::RightParen {
+            loop {
+                args.push(self.parse_expression()?);
+                if self.current() == &Token::Comma {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(&Token::RightParen)?;
+
+        function.check_arity(args.len())?;
+        Ok(Expression::Function(function, args))
+    }
+
+    /// Parse the `n UNIT` of an INTERVAL into milliseconds
+    fn parse_interval(&mut self) -> Result<i64, String> {
+        let amount = self.parse_integer()?;
+        let unit = self.parse_identifier()?;
+        let millis = match unit.to_ascii_lowercase().as_str() {
+            "millisecond" | "milliseconds" => 1,
+            "second" | "seconds" => 1_000,
+            "minute" | "minutes" => 60_000,
+            "hour" | "hours" => 3_600_000,
+            "day" | "days" => 86_400_000,
+            "week" | "weeks" => 604_800_000,
+            _ => return Err(format!("Unknown interval unit: {}", unit)),
+        };
+        amount
+            .checked_mul(millis)
+            .ok_or_else(|| format!("Interval too large: {} {}", amount, unit))
+    }
+
+    /// Whether a TRAVERSE pattern continues with TOP k
+    fn at_top_modifier(&self) -> bool {
+        matches!(self.current(), Token::Identifier(name) if name.eq_ignore_ascii_case("top"))
+            && matches!(self.peek(), Some(Token::Integer(_)))
+    }
+
+    /// Whether the next token is the given contextual keyword
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(name)) if name.eq_ignore_ascii_case(word))
+    }
+
+    /// Consume an identifier used as a contextual keyword
+    fn consume_word(&mut self, word: &str) -> bool {
+        if let Token::Identifier(name) = self.current() {
+            if name.eq_ignore_ascii_case(word) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Parse ARCHIVE / UNARCHIVE: <keyword> FROM collection [WHERE condition]
+    /// [PARTITION BY column], the partition for ARCHIVE only
+    fn parse_archive(&mut self, keyword: Token) -> Result<ArchiveQuery, String> {
+        self.expect(&keyword)?;
+        self.expect(&Token::From)?;
+
+        let collection = self.parse_identifier()?;
+
+        let where_clause = if self.current() == &Token::Where {
+            Some(self.parse_where()?)
+        } else {
+            None
+        };
+
+        let partition_by = if keyword == Token::Archive && self.consume_word("PARTITION") {
+            if !self.consume_word("BY") {
+                return Err(format!("Expected BY, got {:?}", self.current()));
+            }
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+
+        Ok(ArchiveQuery {
+            collection,
+            where_clause,
+            partition_by,
+        })
+    }
+
+    /// Parse COPY: COPY collection TO|FROM 'path' [FORMAT JSONL|CSV]
+    fn parse_copy(&mut self) -> Result<CopyQuery, String> {
+        self.advance(); // consume COPY
+        let collection = self.parse_identifier()?;
+
+        let export = if self.consume_word("TO") {
+            true
+        } else if self.current() == &Token::From {
+            self.advance();
+            false
+        } else {
+            return Err(format!("Expected TO or FROM, got {:?}", self.current()));
+        };
+
+        let path = match self.current().clone() {
+            Token::String(path) | Token::QuotedIdentifier(path) => {
+                self.advance();
+                path
+            }
+            other => return Err(format!("Expected a file path, got {:?}", other)),
+        };
+
+        let format = if self.consume_word("FORMAT") {
+            let name = self.parse_identifier()?;
+            Some(DataFormat::from_name(&name).ok_or_else(|| format!("Unknown format: {}", name))?)
+        } else {
+            None
+        };
+
+        Ok(CopyQuery {
+            collection,
+            export,
+            path,
+            format,
+        })
+    }
+
+    // Helper methods
+
+    fn current(&self) -> &Token {
+        self.tokens.get(self.position).unwrap_or(&Token::Eof)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position + 1)
+    }
+
+    fn advance(&mut self) {
+        if self.position < self.tokens.len() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), String> {
+        if std::mem::discriminant(self.current()) == std::mem::discriminant(expected) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(format!("Expected {:?}, got {:?}", expected, self.current()))
+        }
+    }
+
+    /// A property name, or the `@id` pseudo-property
+    fn parse_property_name(&mut self) -> Result<String, String> {
+        if self.current() != &Token::At {
+            return self.parse_identifier();
+        }
+        self.advance();
+        match self.parse_identifier()? {
+            name if name.eq_ignore_ascii_case("id") => Ok(ID_PROPERTY.to_string()),
+            name => Err(format!("Unknown pseudo-property @{}; only @id can be read", name)),
+        }
+    }
+
+    /// A name, bare or double-quoted (`"Order Items"`) for spaces or reserved words
+    fn parse_identifier(&mut self) -> Result<String, String> {
+        if let Token::Identifier(name) | Token::QuotedIdentifier(name) = self.current() {
+            let result = name.clone();
+            self.advance();
+            Ok(result)
+        } else if let Some(name) = self.current().as_name() {
+            self.advance();
+            Ok(name.to_string())
+        } else {
+            Err(format!("Expected identifier, got {:?}", self.current()))
+        }
+    }
+
+    fn parse_integer(&mut self) -> Result<i64, String> {
+        if let Token::Integer(n) = self.current() {
+            let result = *n;
+            self.advance();
+            Ok(result)
+        } else {
+            Err(format!("Expected integer, got {:?}", self.current()))
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, String> {
+        match self.current().clone() {
+            Token::Identifier(name) if name.eq_ignore_ascii_case("timestamp") => {
+                self.advance();
+                match self.current().clone() {
+                    Token::String(text) | Token::QuotedIdentifier(text) => {
+                        self.advance();
+                        parse_timestamp(&text)
+                            .map(Literal::Timestamp)
+                            .ok_or_else(|| format!("Invalid timestamp: '{}'", text))
+                    }
+                    other => Err(format!("Expected timestamp string, found {:?}", other)),
+                }
+            }
+            Token::Integer(n) => {
+                self.advance();
+                Ok(Literal::Integer(n))
+            }
+            Token::Float(f) => {
+                self.advance();
+                Ok(Literal::Float(f))
+            }
+            Token::String(s) | Token::QuotedIdentifier(s) => {
+                self.advance();
+                Ok(Literal::String(s))
+            }
+            Token::True => {
+                self.advance();
+                Ok(Literal::Bool(true))
+            }
+            Token::False => {
+                self.advance();
+                Ok(Literal::Bool(false))
+            }
+            Token::Null => {
+                self.advance();
+                Ok(Literal::Null)
+            }
+            Token::LeftBracket => {
+                self.advance();
+                let mut items = Vec::new();
+                while self.current() != &Token::RightBracket {
+                    items.push(self.parse_element_literal()?);
+                    if self.current() == &Token::Comma {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(&Token::RightBracket)?;
+                Ok(Literal::List(items))
+            }
+            Token::LeftBrace => {
+                self.advance();
+                let mut entries = Vec::new();
+                while self.current() != &Token::RightBrace {
+                    let key = match self.current().clone() {
+                        Token::String(key) | Token::QuotedIdentifier(key) => {
+                            self.advance();
+                            key
+                        }
+                        _ => self.parse_identifier()?,
+                    };
+                    self.expect(&Token::Colon)?;
+                    entries.push((key, self.parse_element_literal()?));
+                    if self.current() == &Token::Comma {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(&Token::RightBrace)?;
+                Ok(Literal::Map(entries))
+            }
+            _ => Err(format!("Expected literal, got {:?}", self.current())),
+        }
+    }
+
+    /// Parse a list element or document value: a literal, or a negated number
+    fn parse_element_literal(&mut self) -> Result<Literal, String> {
+        if self.current() != &Token::Minus {
+            return self.parse_literal();
+        }
+
+        self.advance();
+        match self.parse_literal()? {
+            Literal::Integer(n) => Ok(Literal::Integer(-n)),
+            Literal::Float(f) => Ok(Literal::Float(-f)),
+            other => Err(format!("Expected number after '-', got {:?}", other)),
+        }
+    }
+}
+
+/// Source text of a token inside an expression
+fn token_source(token: &Token) -> Result<String, String> {
+    let text = match token {
+        Token::Identifier(name) => name.clone(),
+        Token::String(s) | Token::QuotedIdentifier(s) => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+        Token::Integer(n) => n.to_string(),
+        Token::Float(f) => format!("{:?}", f),
+        Token::True => "TRUE".to_string(),
+        Token::False => "FALSE".to_string(),
+        Token::Null => "NULL".to_string(),
+        Token::And => "AND".to_string(),
+        Token::Or => "OR".to_string(),
+        Token::Not => "NOT".to_string(),
+        Token::In => "IN".to_string(),
+        Token::Between => "BETWEEN".to_string(),
+        Token::Like => "LIKE".to_string(),
+        Token::Is => "IS".to_string(),
+        Token::Equal => "=".to_string(),
+        Token::NotEqual => "!=".to_string(),
+        Token::LessThan => "<".to_string(),
+        Token::LessThanEq => "<=".to_string(),
+        Token::GreaterThan => ">".to_string(),
+        Token::GreaterThanEq => ">=".to_string(),
+        Token::Plus => "+".to_string(),
+        Token::Minus => "-".to_string(),
+        Token::Star => "*".to_string(),
+        Token::Slash => "/".to_string(),
+        Token::Dot => ".".to_string(),
+        Token::Comma => ",".to_string(),
+        Token::LeftParen => "(".to_string(),
+        Token::RightParen => ")".to_string(),
+        other => return Err(format!("Unexpected {:?} in CHECK expression", other)),
+    };
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_select() {
+        let query = "FROM Users WHERE age = 25 SELECT name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            assert_eq!(select.from.collection, "Users");
+            assert!(select.traverse.is_none());
+            assert!(select.where_clause.is_some());
+        } else {
+            panic!("Expected SELECT query");
+        }
+    }
+
+    #[test]
+    fn test_parse_hybrid_query() {
+        let query = "FROM Users u TRAVERSE -[:PURCHASED]-> p WHERE p.price > 100 SELECT u.name, p.name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            assert_eq!(select.from.collection, "Users");
+            assert_eq!(select.from.alias, Some("u".to_string()));
+            assert!(select.traverse.is_some());
+
+            let traverse = select.traverse.unwrap();
+            assert_eq!(traverse.patterns.len(), 1);
+            assert_eq!(traverse.patterns[0].edge_types, vec!["PURCHASED".to_string()]);
+            assert_eq!(traverse.patterns[0].target_alias, Some("p".to_string()));
+        } else {
+            panic!("Expected SELECT query");
+        }
+    }
+
+    #[test]
+    fn test_parse_variable_length_traverse() {
+        let query = "FROM Users TRAVERSE -[:FOLLOWS*1..3]-> friend SELECT friend.name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            let traverse = select.traverse.unwrap();
+            assert_eq!(traverse.patterns[0].min_hops, 1);
+            assert_eq!(traverse.patterns[0].max_hops, 3);
+        } else {
+            panic!("Expected SELECT query");
+        }
+    }
+
+    #[test]
+    fn test_parse_unbounded_incoming_traverse() {
+        let query = "FROM Users TRAVERSE <-[:FOLLOWS*]- follower SELECT follower.name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            let pattern = &select.traverse.unwrap().patterns[0];
+            assert_eq!(pattern.direction, Direction::Incoming);
+            assert_eq!((pattern.min_hops, pattern.max_hops), (1, usize::MAX));
+            assert_eq!(pattern.target_alias, Some("follower".to_string()));
+        } else {
+            panic!("Expected SELECT query");
+        }
+    }
+
+    #[test]
+    fn test_parse_count_distinct() {
+        let query = "FROM Logins SELECT city, COUNT(DISTINCT device), COUNT(*) GROUP BY city";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            assert!(matches!(&select.select.fields[1].expression, Expression::Aggregate(AggregateFunction::Count, _, true)));
+            assert!(matches!(&select.select.fields[2].expression, Expression::Aggregate(AggregateFunction::Count, _, false)));
+        } else {
+            panic!("Expected SELECT query");
+        }
+
+        assert!(Parser::parse("FROM Logins SELECT COUNT(DISTINCT *)").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_predicates() {
+        let query = "FROM Users WHERE status NOT IN ('banned', 'closed') AND age BETWEEN 18 AND 30 AND name LIKE 'Al%' SELECT name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            let Expression::And(rest, like) = select.where_clause.unwrap().condition else {
+                panic!("Expected AND chain");
+            };
+            assert!(matches!(*like, Expression::Like(_, ref p) if p == "Al%"));
+
+            let Expression::And(not_in, between) = *rest else { panic!("Expected AND chain") };
+            assert!(matches!(*between, Expression::Between(..)));
+            assert!(matches!(*not_in, Expression::Not(ref e) if matches!(**e, Expression::In(_, ref v) if v.len() == 2)));
+        } else {
+            panic!("Expected SELECT query");
+        }
+
+        assert!(Parser::parse("FROM Users WHERE id IN () SELECT name").is_ok());
+        assert!(Parser::parse("FROM Users WHERE name LIKE 5 SELECT name").is_err());
+    }
+
+    #[test]
+    fn test_parse_is_null() {
+        let query = "FROM Users WHERE email IS NOT NULL AND phone IS NULL SELECT name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            let Expression::And(left, right) = select.where_clause.unwrap().condition else {
+                panic!("Expected AND");
+            };
+            assert!(matches!(*left, Expression::Not(ref e) if matches!(**e, Expression::IsNull(_))));
+            assert!(matches!(*right, Expression::IsNull(_)));
+        } else {
+            panic!("Expected SELECT query");
+        }
+
+        assert!(Parser::parse("FROM Users WHERE email IS 5 SELECT name").is_err());
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let query = "FROM Users WHERE age > $min_age AND city = ?1 SELECT name";
+        let result = Parser::parse(query).unwrap();
+
+        if let Query::Select(select) = result {
+            let Expression::And(left, right) = select.where_clause.unwrap().condition else {
+                panic!("Expected AND");
+            };
+            assert!(matches!(*left, Expression::GreaterThan(_, ref p) if **p == Expression::Parameter("min_age".to_string())));
+            assert!(matches!(*right, Expression::Equal(_, ref p) if **p == Expression::Parameter("1".to_string())));
+        } else {
+            panic!("Expected SELECT query");
+        }
+    }
+
+    #[test]
+    fn test_parse_edge_mutations() {
+        let query = "DELETE EDGE FROM Users a -[:FOLLOWS {since: 2020}]-> b WHERE b.name = 'Bob'";
+        let Query::DeleteEdge(delete) = Parser::parse(query).unwrap() else {
+            panic!("Expected DELETE EDGE query");
+        };
+        assert_eq!(delete.from.alias, Some("a".to_string()));
+        assert_eq!(delete.pattern.edge_types, vec!["FOLLOWS".to_string()]);
+        assert_eq!(delete.pattern.edge_properties, vec![("since".to_string(), Literal::Integer(2020))]);
+        assert!(delete.where_clause.is_some());
+
+        let query = "UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET weight = 5";
+        let Query::UpdateEdge(update) = Parser::parse(query).unwrap() else {
+            panic!("Expected UPDATE EDGE query");
+        };
+        assert_eq!(update.set, vec![("weight".to_string(), Expression::integer(5))]);
+
+        // EDGE isn't reserved: it can still name a collection
+        assert!(matches!(Parser::parse("DELETE FROM Edge WHERE x = 1").unwrap(), Query::Delete(_)));
+        assert!(matches!(Parser::parse("UPDATE Edge SET x = 1").unwrap(), Query::Update(_)));
+        assert!(Parser::parse("DELETE EDGE FROM Users a -[:FOLLOWS*1..2]-> b").is_err());
+    }
+
+    #[test]
+    fn test_parse_schema_commands() {
+        let query = "DEFINE SCHEMA Users (name String NOT NULL UNIQUE, age Integer DEFAULT -1 CHECK(age < 150 OR age IS NULL), nick String DEFAULT 'n/a') ALLOW EXTRA";
+        let Query::DefineSchema(schema) = Parser::parse(query).unwrap() else {
+            panic!("Expected DEFINE SCHEMA query");
+        };
+
+        assert_eq!(schema.collection, "Users");
+        assert!(schema.allow_extra_properties);
+        assert!(schema.has_constraint("name", &Constraint::NotNull));
+        assert!(schema.has_constraint("name", &Constraint::Unique));
+
+        let age = schema.get_field("age").unwrap();
+        assert_eq!(age.field_type, FieldType::Integer);
+        assert_eq!(age.get_default(), Some(&PropertyValue::Int(-1)));
+        assert_eq!(age.check_expression.as_deref(), Some("age < 150 OR age IS NULL"));
+        assert_eq!(
+            schema.get_field("nick").unwrap().get_default(),
+            Some(&PropertyValue::String("n/a".to_string()))
+        );
+
+        assert_eq!(Parser::parse("DROP SCHEMA Users").unwrap(), Query::DropSchema("Users".to_string()));
+        assert!(matches!(Parser::parse("DROP INDEX idx_age").unwrap(), Query::DropIndex(_)));
+
+        assert!(Parser::parse("DEFINE SCHEMA Users (age Number)").is_err());
+        assert!(Parser::parse("DEFINE SCHEMA Users (age Integer CHECK(age >=))").is_err());
+        assert!(Parser::parse("DEFINE SCHEMA Users (age Integer, age String)").is_err());
+    }
+
+    #[test]
+    fn test_parse_join() {
+        let query = "FROM Users u JOIN Orders o ON u.id = o.user_id JOIN Items AS i ON i.order_no = o.order_no WHERE o.total > 10 SELECT u.name, o.total";
+        let Query::Select(select) = Parser::parse(query).unwrap() else {
+            panic!("Expected SELECT query");
+        };
+
+        assert_eq!(select.from.alias.as_deref(), Some("u"));
+        assert_eq!(select.joins.len(), 2);
+        assert_eq!(select.joins[0].collection, "Orders");
+        assert_eq!(select.joins[0].alias.as_deref(), Some("o"));
+        assert_eq!(
+            select.joins[0].condition,
+            Expression::Equal(
+                Box::new(Expression::property(Some("u"), "id")),
+                Box::new(Expression::property(Some("o"), "user_id")),
+            )
+        );
+        assert_eq!(select.joins[1].alias.as_deref(), Some("i"));
+        assert!(select.where_clause.is_some());
+
+        // JOIN is reserved, so it never becomes the FROM alias
+        let Query::Select(select) = Parser::parse("FROM Users JOIN Orders ON user_id = id SELECT name").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        assert_eq!(select.from.alias, None);
+        assert_eq!(select.joins[0].alias, None);
+
+        assert!(Parser::parse("FROM Users u JOIN Orders o SELECT u.name").is_err());
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let Query::Explain(explain) = Parser::parse("EXPLAIN FROM Users WHERE age > 30 SELECT name").unwrap() else {
+            panic!("Expected EXPLAIN query");
+        };
+        assert!(!explain.analyze);
+        assert!(matches!(*explain.query, Query::Select(_)));
+
+        let Query::Explain(explain) = Parser::parse("explain analyze DELETE FROM Users WHERE age > 30").unwrap() else {
+            panic!("Expected EXPLAIN query");
+        };
+        assert!(explain.analyze);
+        assert!(matches!(*explain.query, Query::Delete(_)));
+
+        assert!(Parser::parse("EXPLAIN EXPLAIN FROM Users SELECT name").is_err());
+        assert!(Parser::parse("EXPLAIN CREATE INDEX idx ON Users(age)").is_err());
+        assert!(Parser::parse("EXPLAIN BEGIN").is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        assert_eq!(Parser::parse("ANALYZE").unwrap(), Query::Analyze(None));
+        assert_eq!(Parser::parse("analyze Users").unwrap(), Query::Analyze(Some("Users".to_string())));
+    }
+
+    #[test]
+    fn test_parse_savepoints() {
+        assert_eq!(Parser::parse("SAVEPOINT step1").unwrap(), Query::Savepoint("step1".to_string()));
+        assert_eq!(
+            Parser::parse("ROLLBACK TO SAVEPOINT step1").unwrap(),
+            Query::RollbackToSavepoint("step1".to_string())
+        );
+        assert_eq!(Parser::parse("rollback to step1").unwrap(), Query::RollbackToSavepoint("step1".to_string()));
+        assert_eq!(Parser::parse("RELEASE SAVEPOINT step1").unwrap(), Query::ReleaseSavepoint("step1".to_string()));
+        assert_eq!(Parser::parse("RELEASE step1").unwrap(), Query::ReleaseSavepoint("step1".to_string()));
+        assert_eq!(Parser::parse("ROLLBACK").unwrap(), Query::Rollback);
+
+        assert!(Parser::parse("SAVEPOINT").is_err());
+        assert!(Parser::parse("ROLLBACK TO").is_err());
+    }
+
+    #[test]
+    fn test_parse_collection_commands() {
+        assert_eq!(Parser::parse("SHOW COLLECTIONS").unwrap(), Query::ShowCollections);
+        assert_eq!(Parser::parse("truncate Users").unwrap(), Query::Truncate("Users".to_string()));
+        assert_eq!(Parser::parse("DROP COLLECTION Users").unwrap(), Query::DropCollection("Users".to_string()));
+        assert_eq!(Parser::parse("DROP INDEX idx_age").unwrap(), Query::DropIndex(DropIndexQuery { index_name: "idx_age".to_string() }));
+
+        assert!(Parser::parse("SHOW Users").is_err());
+        assert!(Parser::parse("TRUNCATE").is_err());
+    }
+
+    #[test]
+    fn test_parse_fulltext_index_and_match() {
+        let Query::CreateIndex(index) = Parser::parse("CREATE FULLTEXT INDEX idx_desc ON Products(description)").unwrap() else {
+            panic!("Expected CREATE INDEX");
+        };
+        assert!(index.fulltext && !index.unique);
+        assert_eq!((index.collection.as_str(), index.field.as_str()), ("Products", "description"));
+
+        let Query::Select(select) =
+            Parser::parse("FROM Products p WHERE MATCH(p.description, 'wireless headphones') SELECT p.name, SCORE() AS score")
+                .unwrap()
+        else {
+            panic!("Expected SELECT query");
+        };
+        let Expression::Match(field, text) = select.where_clause.unwrap().condition else {
+            panic!("Expected MATCH");
+        };
+        assert!(matches!(*field, Expression::Property(ref p) if p.property == "description"));
+        assert_eq!(text, "wireless headphones");
+        assert!(matches!(select.select.fields[1].expression, Expression::Property(ref p) if p.property == SCORE_PROPERTY));
+
+        assert!(Parser::parse("FROM Products WHERE MATCH(description, ' -- ') SELECT name").is_err());
+        assert!(Parser::parse("FROM Products WHERE MATCH('text', 'x') SELECT name").is_err());
+    }
+
+    #[test]
+    fn test_parse_traverse_edge_alias_and_types() {
+        let Query::Select(select) = Parser::parse("FROM Users u TRAVERSE -[e:FOLLOWS|FRIENDS*1..2]-> f SELECT e.weight").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        let pattern = &select.traverse.unwrap().patterns[0];
+        assert_eq!(pattern.edge_alias, Some("e".to_string()));
+        assert_eq!(pattern.edge_types, vec!["FOLLOWS".to_string(), "FRIENDS".to_string()]);
+        assert_eq!((pattern.min_hops, pattern.max_hops), (1, 2));
+
+        // A lone name is still the edge type
+        let Query::Select(select) = Parser::parse("FROM Users TRAVERSE -[FOLLOWS]-> f SELECT f.name").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        let pattern = &select.traverse.unwrap().patterns[0];
+        assert_eq!((pattern.edge_alias.clone(), pattern.edge_types.clone()), (None, vec!["FOLLOWS".to_string()]));
+
+        assert!(Parser::parse("FROM Users TRAVERSE -[:FOLLOWS|]-> f SELECT f.name").is_err());
+    }
+
+    #[test]
+    fn test_parse_degree_functions() {
+        let query = "FROM Users u WHERE OUT_DEGREE(u, 'FOLLOWS') > 1 SELECT u.name, degree(u)";
+        let Query::Select(select) = Parser::parse(query).unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        let condition = select.where_clause.unwrap().condition;
+        let Expression::GreaterThan(degree, _) = &condition else {
+            panic!("Expected a comparison");
+        };
+        let Expression::Property(out_degree) = degree.as_ref() else {
+            panic!("Expected the degree pseudo-property");
+        };
+        assert_eq!(out_degree.entity.as_deref(), Some("u"));
+        assert_eq!(
+            parse_degree_property(&out_degree.property),
+            Some((Direction::Outgoing, Some("FOLLOWS")))
+        );
+        let Expression::Property(degree) = &select.select.fields[1].expression else {
+            panic!("Expected the degree pseudo-property");
+        };
+        assert_eq!(parse_degree_property(&degree.property), Some((Direction::Both, None)));
+
+        // The edge type is a string, and the binding is required
+        assert!(Parser::parse("FROM Users u SELECT IN_DEGREE(u, FOLLOWS)").is_err());
+        assert!(Parser::parse("FROM Users u SELECT IN_DEGREE()").is_err());
+    }
+
+    #[test]
+    fn test_parse_has_function() {
+        let Query::Select(select) = Parser::parse("FROM Users u WHERE NOT has(u.address.city) SELECT u.name").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        assert_eq!(
+            select.where_clause.unwrap().condition,
+            Expression::Not(Box::new(Expression::Has(PropertyRef {
+                entity: Some("u".to_string()),
+                property: "address.city".to_string(),
+            })))
+        );
+
+        // Only a stored property can be checked for
+        assert!(Parser::parse("FROM Users u SELECT HAS(u.@id)").is_err());
+        assert!(Parser::parse("FROM Users u SELECT HAS('name')").is_err());
+    }
+
+    #[test]
+    fn test_parse_traverse_top_by_pheromone() {
+        let query = "FROM Users u TRAVERSE -[e:FOLLOWS]-> f TOP 5 BY PHEROMONE SELECT f.name, PHEROMONE(e) AS strength";
+        let Query::Select(select) = Parser::parse(query).unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        let pattern = &select.traverse.unwrap().patterns[0];
+        assert_eq!((pattern.target_alias.as_deref(), pattern.top_k), (Some("f"), Some(5)));
+        assert_eq!(
+            select.select.fields[1].expression,
+            Expression::Property(PropertyRef {
+                entity: Some("e".to_string()),
+                property: PHEROMONE_PROPERTY.to_string(),
+            })
+        );
+
+        // Without a target alias, and with an alias that happens to be named top
+        let Query::Select(select) = Parser::parse("FROM Users TRAVERSE -[:FOLLOWS]-> top 2 by pheromone SELECT name").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        let pattern = &select.traverse.unwrap().patterns[0];
+        assert_eq!((pattern.target_alias.clone(), pattern.top_k), (None, Some(2)));
+        let Query::Select(select) = Parser::parse("FROM Users TRAVERSE -[:FOLLOWS]-> top SELECT top.name").unwrap() else {
+            panic!("Expected SELECT query");
+        };
+        assert_eq!(select.traverse.unwrap().patterns[0].target_alias, Some("top".to_string()));
+
+        assert!(Parser::parse("FROM Users TRAVERSE -[:FOLLOWS]-> f TOP 5 SELECT f.name").is_err());
+    }
+
+    #[test]
+    fn test_parse_multi_row_insert() {
+        let Query::Insert(insert) = Parser::parse("INSERT INTO Users VALUES ({name: 'Ann', age: 30}), ({name: 'Bob'})").unwrap() else {
+            panic!("Expected INSERT query");
+        };
+        assert_eq!(insert.collection, "Users");
+        assert_eq!(insert.rows.len(), 2);
+        assert_eq!(insert.rows[0].len(), 2);
+        assert_eq!(insert.rows[1][0].0, "name");
+
+        assert!(Parser::parse("INSERT INTO Users VALUES ({name: 'Ann'}),").is_err());
+    }
+
+    #[test]
+    fn test_parse_unary_minus() {
+        assert_eq!(
+            Parser::parse_expression_source("-5").unwrap(),
+            Expression::Literal(Literal::Integer(-5))
+        );
+
+        let Expression::Multiply(left, _) = Parser::parse_expression_source("-stock * 2").unwrap() else {
+            panic!("Expected multiplication");
+        };
+        assert!(matches!(*left, Expression::Subtract(ref zero, _) if **zero == Expression::Literal(Literal::Integer(0))));
+    }
+
+    #[test]
+    fn test_parse_shortest_path() {
+        assert_eq!(
+            Parser::parse_expression_source("SHORTEST_PATH(a, b, :FOLLOWS, MAX 6)").unwrap(),
+            Expression::ShortestPath(PathCall {
+                source: "a".to_string(),
+                target: "b".to_string(),
+                edge_type: Some("FOLLOWS".to_string()),
+                max_depth: Some(6),
+                length_only: false,
+            })
+        );
+
+        let Expression::ShortestPath(call) = Parser::parse_expression_source("shortest_path_length(a, b)").unwrap() else {
+            panic!("Expected SHORTEST_PATH_LENGTH");
+        };
+        assert!(call.length_only);
+        assert_eq!((call.edge_type, call.max_depth), (None, None));
+
+        assert!(Parser::parse_expression_source("SHORTEST_PATH(a, b, MAX 3, :FOLLOWS)").is_err());
+        assert!(Parser::parse_expression_source("SHORTEST_PATH(a)").is_err());
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        assert_eq!(
+            Parser::parse("COPY Users TO '/tmp/users.csv' FORMAT csv").unwrap(),
+            Query::Copy(CopyQuery {
+                collection: "Users".to_string(),
+                export: true,
+                path: "/tmp/users.csv".to_string(),
+                format: Some(DataFormat::Csv),
+            })
+        );
+
+        let Query::Copy(copy) = Parser::parse("COPY Users FROM 'users.jsonl'").unwrap() else {
+            panic!("Expected COPY");
+        };
+        assert!(!copy.export);
+        assert_eq!(copy.format, None);
+
+        assert!(Parser::parse("COPY Users TO 'users.xml' FORMAT XML").is_err());
+        assert!(Parser::parse("COPY Users INTO 'users.csv'").is_err());
+    }
+
+    #[test]
+    fn test_parse_nested_values() {
+        let Query::Insert(insert) =
+            Parser::parse("INSERT INTO Users VALUES ({tags: ['a', 'b'], address: {city: 'Oslo', zip: -1}})").unwrap()
+        else {
+            panic!("Expected INSERT");
+        };
+        assert_eq!(
+            insert.rows[0],
+            vec![
+                (
+                    "tags".to_string(),
+                    Expression::Literal(Literal::List(vec![
+                        Literal::String("a".to_string()),
+                        Literal::String("b".to_string()),
+                    ])),
+                ),
+                (
+                    "address".to_string(),
+                    Expression::Literal(Literal::Map(vec![
+                        ("city".to_string(), Literal::String("Oslo".to_string())),
+                        ("zip".to_string(), Literal::Integer(-1)),
+                    ])),
+                ),
+            ]
+        );
+
+        let Query::Select(select) =
+            Parser::parse("FROM Users u WHERE 'admin' IN u.tags AND u.address.city = 'Oslo' SELECT u.name").unwrap()
+        else {
+            panic!("Expected SELECT");
+        };
+        assert_eq!(
+            select.where_clause.unwrap().condition,
+            Expression::And(
+                Box::new(Expression::Contains(
+                    Box::new(Expression::property(Some("u"), "tags")),
+                    Box::new(Expression::string("admin")),
+                )),
+                Box::new(Expression::Equal(
+                    Box::new(Expression::property(Some("u"), "address.city")),
+                    Box::new(Expression::string("Oslo")),
+                )),
+            )
+        );
+
+        let Query::DefineSchema(schema) = Parser::parse("DEFINE SCHEMA Users (tags ARRAY<ARRAY<INT>>)").unwrap() else {
+            panic!("Expected DEFINE SCHEMA");
+        };
+        assert_eq!(
+            schema.fields[0].field_type,
+            FieldType::Array(Box::new(FieldType::Array(Box::new(FieldType::Integer))))
+        );
+        assert!(Parser::parse("INSERT INTO Users VALUES ({tags: ['a',]})").is_ok());
+        assert!(Parser::parse("INSERT INTO Users VALUES ({tags: ['a' 'b']})").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamps_now_and_intervals() {
+        let Query::Select(select) = Parser::parse(
+            "FROM Posts p WHERE p.created_at > NOW() - INTERVAL 7 DAYS AND p.created_at < TIMESTAMP '2024-05-01T12:00:00+02:00' SELECT p.title",
+        )
+        .unwrap() else {
+            panic!("Expected SELECT");
+        };
+        assert_eq!(
+            select.where_clause.unwrap().condition,
+            Expression::And(
+                Box::new(Expression::GreaterThan(
+                    Box::new(Expression::property(Some("p"), "created_at")),
+                    Box::new(Expression::Subtract(
+                        Box::new(Expression::Parameter(NOW_PARAMETER.to_string())),
+                        Box::new(Expression::integer(7 * 86_400_000)),
+                    )),
+                )),
+                Box::new(Expression::LessThan(
+                    Box::new(Expression::property(Some("p"), "created_at")),
+                    Box::new(Expression::Literal(Literal::Timestamp(1_714_557_600_000))),
+                )),
+            )
+        );
+
+        // Dates alone are midnight UTC
+        let Query::Insert(insert) = Parser::parse("INSERT INTO Posts VALUES ({at: TIMESTAMP '1970-01-02'})").unwrap() else {
+            panic!("Expected INSERT");
+        };
+        assert_eq!(insert.rows[0][0].1, Expression::Literal(Literal::Timestamp(86_400_000)));
+
+        assert!(Parser::parse("FROM Posts p WHERE p.at > TIMESTAMP 'yesterday' SELECT p").is_err());
+        assert!(Parser::parse("FROM Posts p WHERE p.at > NOW() - INTERVAL 2 FORTNIGHTS SELECT p").is_err());
+
+        let Query::DefineSchema(schema) =
+            Parser::parse("DEFINE SCHEMA Posts (at TIMESTAMP) COERCE TIMESTAMPS ALLOW EXTRA").unwrap()
+        else {
+            panic!("Expected DEFINE SCHEMA");
+        };
+        assert!(schema.coerce_timestamps && schema.allow_extra_properties);
+
+        let Query::DefineSchema(schema) = Parser::parse("DEFINE SCHEMA Sessions (user STRING) TTL 30 MINUTES").unwrap() else {
+            panic!("Expected DEFINE SCHEMA");
+        };
+        assert_eq!(schema.ttl, Some(std::time::Duration::from_secs(1800)));
+        assert!(Parser::parse("DEFINE SCHEMA Sessions (user STRING) TTL 0 SECONDS").is_err());
+    }
+
+    #[test]
+    fn test_parse_function_calls() {
+        let Query::Select(select) =
+            Parser::parse("FROM Users u WHERE lower(u.name) = LOWER('ALICE') SELECT CONCAT(u.first, ' ', u.last) AS full")
+                .unwrap()
+        else {
+            panic!("Expected SELECT");
+        };
+        assert_eq!(
+            select.where_clause.unwrap().condition,
+            Expression::Equal(
+                Box::new(Expression::Function(ScalarFunction::Lower, vec![Expression::property(Some("u"), "name")])),
+                Box::new(Expression::Function(ScalarFunction::Lower, vec![Expression::string("ALICE")])),
+            )
+        );
+        assert_eq!(
+            select.select.fields[0].expression,
+            Expression::Function(
+                ScalarFunction::Concat,
+                vec![
+                    Expression::property(Some("u"), "first"),
+                    Expression::string(" "),
+                    Expression::property(Some("u"), "last"),
+                ],
+            )
+        );
+
+        // Arity is checked while parsing; a bare name is still a property
+        let err = Parser::parse("FROM Users u WHERE STARTS_WITH(u.name) SELECT u").unwrap_err();
+        assert_eq!(err.message(), "STARTS_WITH takes 2 argument(s), got 1");
+        assert!(Parser::parse("FROM Users u WHERE u.length > 3 SELECT u").is_ok());
+    }
+
+    #[test]
+    fn test_parse_select_distinct() {
+        let Query::Select(select) = Parser::parse("FROM Orders SELECT DISTINCT customer_id, region").unwrap() else {
+            panic!("Expected SELECT");
+        };
+        assert!(select.select.distinct);
+        assert_eq!(select.select.fields.len(), 2);
+
+        let Query::Select(select) = Parser::parse("FROM Orders SELECT customer_id").unwrap() else {
+            panic!("Expected SELECT");
+        };
+        assert!(!select.select.distinct);
+    }
+
+    #[test]
+    fn test_parse_select_wildcards() {
+        let Query::Select(select) = Parser::parse("FROM Users u SELECT *").unwrap() else {
+            panic!("Expected SELECT");
+        };
+        assert_eq!(select.select.fields[0].expression, Expression::Wildcard(None));
+
+        let Query::Select(select) =
+            Parser::parse("FROM Users u TRAVERSE -[WROTE]-> p SELECT u.*, p.title AS title").unwrap()
+        else {
+            panic!("Expected SELECT");
+        };
+        assert_eq!(select.select.fields[0].expression, Expression::Wildcard(Some("u".to_string())));
+        assert_eq!(select.select.fields[0].alias, None);
+        assert_eq!(select.select.fields[1].alias, Some("title".to_string()));
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let condition = |query: &str| {
+            let Query::Select(select) = Parser::parse(query).unwrap() else {
+                panic!("Expected SELECT");
+            };
+            select.where_clause.unwrap().condition
+        };
+
+        let Expression::InSubquery(left, subquery) =
+            condition("FROM Users u WHERE u.id IN (FROM Orders o SELECT o.user_id) SELECT u.name")
+        else {
+            panic!("Expected IN subquery");
+        };
+        assert!(matches!(*left, Expression::Property(_)));
+        assert_eq!(subquery.id, 0);
+        assert_eq!(subquery.query.from.collection, "Orders");
+
+        let Expression::Not(exists) =
+            condition("FROM Users WHERE NOT EXISTS (FROM Orders WHERE total > 100 SELECT total) SELECT name")
+        else {
+            panic!("Expected NOT EXISTS");
+        };
+        assert!(matches!(*exists, Expression::Exists(_)));
+
+        // Subqueries are numbered in the order they appear
+        let Expression::And(first, second) = condition(
+            "FROM Users WHERE id = (FROM Orders SELECT user_id) AND id IN (FROM Orders SELECT user_id) SELECT name",
+        ) else {
+            panic!("Expected AND");
+        };
+        assert!(matches!(*first, Expression::Equal(_, ref right) if matches!(**right, Expression::ScalarSubquery(ref s) if s.id == 0)));
+        assert!(matches!(*second, Expression::InSubquery(_, ref s) if s.id == 1));
+
+        let err = Parser::parse("FROM Users WHERE id IN (FROM Orders SELECT a, b) SELECT name").unwrap_err();
+        assert_eq!(err.message(), "A subquery used as a value must select exactly one column");
+    }
+
+    #[test]
+    fn test_parse_with_order_and_limit() {
+        let query = "FROM Products WHERE price > 50 SELECT name, price ORDER BY price DESC LIMIT 10";
+        let result = Parser::parse(query).unwrap();
 
         if let Query::Select(select) = result {
             assert!(select.order_by.is_some());
             assert_eq!(select.limit, Some(10));
 
             let order_by = select.order_by.unwrap();
-            assert_eq!(order_by.fields[0].ascending, false);
+            assert!(!order_by.fields[0].ascending);
         } else {
             panic!("Expected SELECT query");
         }
     }
+
+    #[test]
+    fn test_parse_limit_and_offset_in_either_order() {
+        for query in [
+            "FROM Products SELECT name ORDER BY name LIMIT 10 OFFSET 20",
+            "FROM Products SELECT name ORDER BY name OFFSET 20 LIMIT 10",
+        ] {
+            let Query::Select(select) = Parser::parse(query).unwrap() else {
+                panic!("Expected SELECT query");
+            };
+            assert_eq!((select.limit, select.offset), (Some(10), Some(20)), "{}", query);
+        }
+
+        assert!(Parser::parse("FROM Products SELECT name LIMIT 10 LIMIT 5").is_err());
+        assert!(Parser::parse("FROM Products SELECT name OFFSET 1 OFFSET 2").is_err());
+    }
 }
@@ -0,0 +1,140 @@
+//! Deed Errors
+//!
+//! Most layers report failures as `Result<_, String>`. [`DeedError`] is the
+//! structured form: the parser and the executor's statement path return it,
+//! each kind built where the error arises. A message from a layer that
+//! reports strings is an [`DeedError::ExecutionError`] unless the executor
+//! says otherwise where it calls that layer.
+
+use crate::dql_lexer::Position;
+use std::fmt;
+
+/// An error from any part of the database
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeedError {
+    /// A query that doesn't parse, with where parsing stopped
+    ParseError {
+        line: usize,
+        column: usize,
+        message: String,
+        /// The offending line, with a caret under the column
+        snippet: String,
+    },
+    /// A statement that parsed but failed to run
+    ExecutionError(String),
+    /// A write refused by a schema or a UNIQUE index
+    ValidationError(String),
+    /// A transaction that can't begin, continue or commit
+    TransactionError(String),
+    /// A statement the caller isn't allowed to run
+    PermissionError(String),
+}
+
+impl DeedError {
+    /// A parse error at `position` in `source`
+    pub fn parse(source: &str, position: Position, message: impl Into<String>) -> Self {
+        let text = source.lines().nth(position.line - 1).unwrap_or("");
+        let gutter = position.line.to_string();
+        let snippet = format!(
+            "{} | {}\n{} | {}^",
+            gutter,
+            text,
+            " ".repeat(gutter.len()),
+            " ".repeat(position.column - 1)
+        );
+
+        DeedError::ParseError {
+            line: position.line,
+            column: position.column,
+            message: message.into(),
+            snippet,
+        }
+    }
+
+    pub fn transaction(message: impl Into<String>) -> Self {
+        DeedError::TransactionError(message.into())
+    }
+
+    pub fn permission(message: impl Into<String>) -> Self {
+        DeedError::PermissionError(message.into())
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        DeedError::ValidationError(message.into())
+    }
+
+    /// The error's message, without position or snippet
+    pub fn message(&self) -> &str {
+        match self {
+            DeedError::ParseError { message, .. } => message,
+            DeedError::ExecutionError(message)
+            | DeedError::ValidationError(message)
+            | DeedError::TransactionError(message)
+            | DeedError::PermissionError(message) => message,
+        }
+    }
+
+    /// Name of the variant, as exceptions and logs report it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeedError::ParseError { .. } => "ParseError",
+            DeedError::ExecutionError(_) => "ExecutionError",
+            DeedError::ValidationError(_) => "ValidationError",
+            DeedError::TransactionError(_) => "TransactionError",
+            DeedError::PermissionError(_) => "PermissionError",
+        }
+    }
+}
+
+impl fmt::Display for DeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeedError::ParseError {
+                line,
+                column,
+                message,
+                snippet,
+            } => write!(f, "ParseError at line {}, column {}: {}\n{}", line, column, message, snippet),
+            other => write!(f, "{}", other.message()),
+        }
+    }
+}
+
+impl std::error::Error for DeedError {}
+
+/// A message from a layer that reports strings
+impl From<String> for DeedError {
+    fn from(message: String) -> Self {
+        DeedError::ExecutionError(message)
+    }
+}
+
+impl From<&str> for DeedError {
+    fn from(message: &str) -> Self {
+        DeedError::from(message.to_string())
+    }
+}
+
+/// Layers that still report `String` errors take a `DeedError` through `?`
+impl From<DeedError> for String {
+    fn from(error: DeedError) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_snippet_points_at_the_column() {
+        let source = "FROM Users\nWHERE age >\nSELECT name";
+        let error = DeedError::parse(source, Position { offset: 23, line: 3, column: 1 }, "Unexpected token");
+
+        assert_eq!(error.message(), "Unexpected token");
+        assert_eq!(
+            error.to_string(),
+            "ParseError at line 3, column 1: Unexpected token\n3 | SELECT name\n  | ^"
+        );
+    }
+}
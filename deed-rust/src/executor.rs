@@ -4,7 +4,6 @@
 
 use crate::graph::{Graph, Entity};
 use crate::types::*;
-use crate::transaction::IsolationLevel;
 use std::sync::Arc;
 
 /// Query executor
@@ -86,7 +85,7 @@ mod tests {
             let mut props = Properties::new();
             props.insert("age".to_string(), PropertyValue::Int(20 + i));
 
-            graph.add_entity("User".to_string(), props);
+            graph.add_entity("User".to_string(), props).unwrap();
         }
 
         let executor = Executor::new(graph);
@@ -0,0 +1,151 @@
+//! Expiry index
+//!
+//! Keeps entities and edges that expire ordered by when they do, so the TTL
+//! sweeper reads only what has expired instead of scanning collections.
+
+use crate::types::{EdgeId, EntityId, EntityType};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+/// Where an entity sits in the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ExpirySlot {
+    /// Its own expiry time (milliseconds since the Unix epoch)
+    Deadline(i64),
+    /// Its creation time, in a collection with a TTL
+    Created(EntityType, i64),
+}
+
+/// Entities and edges ordered by expiry
+///
+/// Entities of a collection with a TTL are ordered by creation time rather
+/// than expiry, so changing the TTL doesn't reorder them. Entities that set
+/// their own expiry time, and edges (which have no TTL), are ordered by it.
+#[derive(Debug, Default)]
+pub(crate) struct ExpiryIndex {
+    deadlines: BTreeSet<(i64, u64)>,
+    created: HashMap<EntityType, BTreeSet<(i64, u64)>>,
+    edge_deadlines: BTreeSet<(i64, u64)>,
+    edges: HashMap<u64, i64>,
+}
+
+impl ExpiryIndex {
+    pub fn insert(&mut self, id: EntityId, slot: ExpirySlot) {
+        match slot {
+            ExpirySlot::Deadline(at) => {
+                self.deadlines.insert((at, id.0));
+            }
+            ExpirySlot::Created(collection, at) => {
+                self.created.entry(collection).or_default().insert((at, id.0));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: EntityId, slot: &ExpirySlot) {
+        match slot {
+            ExpirySlot::Deadline(at) => {
+                self.deadlines.remove(&(*at, id.0));
+            }
+            ExpirySlot::Created(collection, at) => {
+                if let Some(created) = self.created.get_mut(collection) {
+                    created.remove(&(*at, id.0));
+                }
+            }
+        }
+    }
+
+    /// Order a collection's entities by creation time, given as (creation
+    /// time, ID) for those without an expiry time of their own
+    pub fn track_collection(&mut self, collection: &str, entities: impl IntoIterator<Item = (i64, EntityId)>) {
+        let created = entities.into_iter().map(|(at, id)| (at, id.0)).collect();
+        self.created.insert(collection.to_string(), created);
+    }
+
+    pub fn untrack_collection(&mut self, collection: &str) {
+        self.created.remove(collection);
+    }
+
+    /// Up to `limit` entities expired by `now`, given the collections' TTLs
+    pub fn expired_entities(&self, now: i64, limit: usize, ttl: impl Fn(&str) -> Option<Duration>) -> Vec<EntityId> {
+        let mut expired: Vec<EntityId> = self
+            .deadlines
+            .range(..=(now, u64::MAX))
+            .take(limit)
+            .map(|&(_, id)| EntityId(id))
+            .collect();
+
+        for (collection, created) in &self.created {
+            let Some(ttl) = ttl(collection) else { continue };
+            let cutoff = now.saturating_sub(ttl.as_millis() as i64);
+            let remaining = limit - expired.len();
+            expired.extend(created.range(..=(cutoff, u64::MAX)).take(remaining).map(|&(_, id)| EntityId(id)));
+        }
+        expired
+    }
+
+    /// Record an edge's expiry time, or forget it with `None`
+    pub fn set_edge(&mut self, id: EdgeId, deadline: Option<i64>) {
+        if let Some(old) = self.edges.remove(&id.0) {
+            self.edge_deadlines.remove(&(old, id.0));
+        }
+        if let Some(at) = deadline {
+            self.edges.insert(id.0, at);
+            self.edge_deadlines.insert((at, id.0));
+        }
+    }
+
+    /// How many edges expire
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn edge_expired(&self, id: EdgeId, now: i64) -> bool {
+        self.edges.get(&id.0).is_some_and(|&at| at <= now)
+    }
+
+    /// Up to `limit` edges expired by `now`
+    pub fn expired_edges(&self, now: i64, limit: usize) -> Vec<EdgeId> {
+        self.edge_deadlines
+            .range(..=(now, u64::MAX))
+            .take(limit)
+            .map(|&(_, id)| EdgeId(id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_entities_follow_the_current_ttl() {
+        let mut index = ExpiryIndex::default();
+        index.insert(EntityId(1), ExpirySlot::Deadline(100));
+        index.insert(EntityId(2), ExpirySlot::Deadline(300));
+        index.insert(EntityId(3), ExpirySlot::Created("Tokens".to_string(), 50));
+        index.insert(EntityId(4), ExpirySlot::Created("Tokens".to_string(), 250));
+
+        let ttl = |ms| move |_: &str| Some(Duration::from_millis(ms));
+        assert_eq!(index.expired_entities(200, 10, ttl(100)), vec![EntityId(1), EntityId(3)]);
+        assert_eq!(index.expired_entities(200, 1, ttl(100)), vec![EntityId(1)]);
+        assert_eq!(index.expired_entities(400, 10, ttl(100)).len(), 4);
+        assert_eq!(index.expired_entities(400, 10, |_| None), vec![EntityId(1), EntityId(2)]);
+
+        index.remove(EntityId(3), &ExpirySlot::Created("Tokens".to_string(), 50));
+        assert_eq!(index.expired_entities(200, 10, ttl(100)), vec![EntityId(1)]);
+    }
+
+    #[test]
+    fn test_edge_deadlines_can_move_and_go() {
+        let mut index = ExpiryIndex::default();
+        index.set_edge(EdgeId(1), Some(100));
+        index.set_edge(EdgeId(2), Some(100));
+        index.set_edge(EdgeId(2), Some(500));
+        assert_eq!(index.expired_edges(200, 10), vec![EdgeId(1)]);
+        assert!(index.edge_expired(EdgeId(1), 100) && !index.edge_expired(EdgeId(2), 100));
+
+        index.set_edge(EdgeId(1), None);
+        index.set_edge(EdgeId(2), None);
+        assert_eq!(index.edge_count(), 0);
+    }
+}
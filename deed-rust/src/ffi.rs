@@ -3,17 +3,30 @@
 //! Exposes Rust core engine to Python for integration with
 //! biological optimization algorithms.
 
+// The pyo3 0.20 `#[pymethods]` expansion defines impls inside a const item
+#![allow(non_local_definitions)]
+
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList};
+use pyo3::types::{timezone_utc, IntoPyDict, PyBool, PyBytes, PyDateTime, PyDict, PyList};
+use crate::connection_pool::{ConnectionPool, PoolConfig, PooledConnectionHandle};
+use crate::dql_executor::{DQLExecutor, QueryResult, RowStream};
+use crate::dql_ir::Value;
+use crate::dql_optimizer::{AntColonyOptimizer, StigmergyCache};
+use crate::error::DeedError;
 use crate::graph::Graph;
+use crate::transaction::TransactionManager;
 use crate::types::*;
-use std::sync::Arc;
-use parking_lot::RwLock;
+use crate::wal::WALManager;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 /// Python-exposed graph database
 #[pyclass]
 pub struct PyDeedGraph {
     graph: Arc<RwLock<Graph>>,
+    executor: DQLExecutor,
 }
 
 #[pymethods]
@@ -21,9 +34,115 @@ impl PyDeedGraph {
     /// Create a new graph database
     #[new]
     fn new() -> Self {
+        let graph = Arc::new(RwLock::new(Graph::new()));
         PyDeedGraph {
-            graph: Arc::new(RwLock::new(Graph::new())),
+            executor: DQLExecutor::new(graph.clone()),
+            graph,
+        }
+    }
+
+    /// Create a graph database whose transactions are logged to a write-ahead log
+    ///
+    /// Args:
+    ///     wal_path (str): Path of the log file
+    #[staticmethod]
+    fn with_wal(wal_path: String) -> PyResult<Self> {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::new_with_wal(graph.clone(), wal_path).map_err(PyRuntimeError::new_err)?;
+        Ok(PyDeedGraph { graph, executor })
+    }
+
+    /// Recover a graph database from an existing write-ahead log
+    ///
+    /// Args:
+    ///     wal_path (str): Path of the log file; later transactions keep logging to it
+    #[staticmethod]
+    fn recover(wal_path: String) -> PyResult<Self> {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::recover_from_wal(graph.clone(), wal_path).map_err(PyRuntimeError::new_err)?;
+        Ok(PyDeedGraph { graph, executor })
+    }
+
+    /// Execute a DQL query
+    ///
+    /// Args:
+    ///     query (str): DQL query text
+    ///
+    /// Returns:
+    ///     QueryResult: Rows, column names and types, and rows_affected
+    fn execute(&self, query: String) -> PyResult<PyQueryResult> {
+        let result = self.executor.try_execute(&query)?;
+        Ok(PyQueryResult { result })
+    }
+
+    /// Start a transaction, committed or rolled back when its `with` block exits
+    ///
+    /// Statements run through this graph inside the block are part of it too.
+    ///
+    /// Args:
+    ///     isolation_level (str or None): e.g. "SERIALIZABLE" or "READ COMMITTED"
+    ///
+    /// Returns:
+    ///     Transaction: Context manager; begins on entry
+    #[pyo3(signature = (isolation_level=None))]
+    fn transaction(&self, isolation_level: Option<String>) -> PyTransaction {
+        PyTransaction {
+            executor: self.executor.clone(),
+            isolation_level,
+            active: false,
+        }
+    }
+
+    /// Execute a DQL query with parameter values
+    ///
+    /// Args:
+    ///     query (str): DQL query text with `$name` or `?1` placeholders
+    ///     params (dict): Values keyed by parameter name ("name", or "1" for ?1)
+    ///
+    /// Returns:
+    ///     QueryResult: Same as execute()
+    fn execute_with_params(&self, query: String, params: &PyDict) -> PyResult<PyQueryResult> {
+        let mut values = HashMap::new();
+        for (key, value) in params.iter() {
+            values.insert(key.str()?.to_string(), py_to_value(value)?);
         }
+
+        let result = self.executor.try_execute_with_params(&query, &values)?;
+        Ok(PyQueryResult { result })
+    }
+
+    /// Execute a DQL query, iterating over its rows in batches
+    ///
+    /// Args:
+    ///     query (str): DQL query text
+    ///     batch_size (int): Most rows per batch
+    ///
+    /// Returns:
+    ///     RowStream: Iterator of QueryResults, one per batch
+    #[pyo3(signature = (query, batch_size=1000))]
+    fn execute_stream(&self, query: String, batch_size: usize) -> PyResult<PyRowStream> {
+        let stream = self.executor.try_execute_stream(&query, batch_size)?;
+        Ok(PyRowStream { stream })
+    }
+
+    /// Execute a SELECT one page at a time
+    ///
+    /// Args:
+    ///     query (str): DQL query text
+    ///     cursor (str or None): None for the first page, else the previous page's "cursor"
+    ///     page_size (int): Most rows per page
+    ///
+    /// Returns:
+    ///     tuple: (QueryResult, cursor for the next page or None on the last one)
+    #[pyo3(signature = (query, cursor=None, page_size=1000))]
+    fn execute_paged(
+        &self,
+        query: String,
+        cursor: Option<String>,
+        page_size: usize,
+    ) -> PyResult<(PyQueryResult, Option<String>)> {
+        let page = self.executor.try_execute_paged(&query, cursor.as_deref(), page_size)?;
+        Ok((PyQueryResult { result: page.result }, page.cursor))
     }
 
     /// Add an entity
@@ -36,8 +155,8 @@ impl PyDeedGraph {
     ///     int: Entity ID
     fn add_entity(&self, entity_type: String, properties: &PyDict) -> PyResult<u64> {
         let props = py_dict_to_properties(properties)?;
-        let graph = self.graph.read();
-        let id = graph.add_entity(entity_type, props);
+        let graph = self.graph.read().unwrap();
+        let id = graph.add_entity(entity_type, props).map_err(PyRuntimeError::new_err)?;
         Ok(id.as_u64())
     }
 
@@ -49,7 +168,7 @@ impl PyDeedGraph {
     /// Returns:
     ///     dict or None: Entity properties
     fn get_entity(&self, entity_id: u64) -> PyResult<Option<PyObject>> {
-        let graph = self.graph.read();
+        let graph = self.graph.read().unwrap();
         match graph.get_entity(EntityId::new(entity_id)) {
             Some(entity) => {
                 Python::with_gil(|py| {
@@ -89,17 +208,14 @@ impl PyDeedGraph {
         properties: &PyDict,
     ) -> PyResult<Option<u64>> {
         let props = py_dict_to_properties(properties)?;
-        let graph = self.graph.read();
-
-        match graph.add_edge(
-            EntityId::new(source_id),
-            EntityId::new(target_id),
-            edge_type,
-            props,
-        ) {
-            Some(id) => Ok(Some(id.as_u64())),
-            None => Ok(None),
+        let graph = self.graph.read().unwrap();
+        let (source, target) = (EntityId::new(source_id), EntityId::new(target_id));
+        if graph.get_entity_ref(source).is_none() || graph.get_entity_ref(target).is_none() {
+            return Ok(None);
         }
+
+        let id = graph.add_edge(source, target, edge_type, props).map_err(PyRuntimeError::new_err)?;
+        Ok(Some(id.as_u64()))
     }
 
     /// Get outgoing neighbors
@@ -115,7 +231,7 @@ impl PyDeedGraph {
         entity_id: u64,
         edge_type: Option<String>,
     ) -> PyResult<Vec<(u64, u64)>> {
-        let graph = self.graph.read();
+        let graph = self.graph.read().unwrap();
         let neighbors = graph.get_outgoing_neighbors(
             EntityId::new(entity_id),
             edge_type.as_deref(),
@@ -135,7 +251,7 @@ impl PyDeedGraph {
     /// Returns:
     ///     list: List of entity dictionaries
     fn scan_collection(&self, entity_type: String) -> PyResult<Vec<PyObject>> {
-        let graph = self.graph.read();
+        let graph = self.graph.read().unwrap();
         let entities = graph.scan_collection(&entity_type);
 
         Python::with_gil(|py| {
@@ -161,7 +277,7 @@ impl PyDeedGraph {
 
     /// Evaporate pheromones (called periodically)
     fn evaporate_pheromones(&self) -> PyResult<()> {
-        let graph = self.graph.read();
+        let graph = self.graph.read().unwrap();
         graph.evaporate_pheromones();
         Ok(())
     }
@@ -171,7 +287,7 @@ impl PyDeedGraph {
     /// Returns:
     ///     dict: Statistics dictionary
     fn stats(&self) -> PyResult<PyObject> {
-        let graph = self.graph.read();
+        let graph = self.graph.read().unwrap();
         let stats = graph.stats();
 
         Python::with_gil(|py| {
@@ -186,6 +302,302 @@ impl PyDeedGraph {
     }
 }
 
+/// Batches of a streamed query, from `PyDeedGraph.execute_stream`
+#[pyclass]
+pub struct PyRowStream {
+    stream: RowStream,
+}
+
+#[pymethods]
+impl PyRowStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyQueryResult>> {
+        match slf.stream.next() {
+            Some(batch) => Ok(Some(PyQueryResult {
+                result: batch.map_err(DeedError::from)?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Result of a DQL statement
+#[pyclass]
+pub struct PyQueryResult {
+    result: QueryResult,
+}
+
+#[pymethods]
+impl PyQueryResult {
+    /// Column names in SELECT order
+    #[getter]
+    fn columns(&self) -> Vec<String> {
+        self.result.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// Type of each column ("Integer", "String", ..., "Mixed" when rows disagree), by name
+    #[getter]
+    fn column_types(&self) -> HashMap<String, String> {
+        self.result
+            .columns
+            .iter()
+            .map(|c| (c.name.clone(), format!("{:?}", c.value_type)))
+            .collect()
+    }
+
+    /// Rows as dicts keyed by column name, missing values as None
+    #[getter]
+    fn rows(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        (0..self.result.row_count())
+            .map(|index| {
+                let dict = PyDict::new(py);
+                let values = self.result.row_values(index).unwrap_or_default();
+                for (column, value) in self.result.columns.iter().zip(&values) {
+                    dict.set_item(&column.name, value_to_py(py, value))?;
+                }
+                Ok(dict.into())
+            })
+            .collect()
+    }
+
+    #[getter]
+    fn rows_affected(&self) -> usize {
+        self.result.rows_affected
+    }
+
+    /// Non-fatal notices, e.g. archived data a read skipped
+    #[getter]
+    fn warnings(&self) -> Vec<String> {
+        self.result.warnings.clone()
+    }
+
+    fn __len__(&self) -> usize {
+        self.result.row_count()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "QueryResult(columns={:?}, rows={}, rows_affected={})",
+            self.columns(),
+            self.result.row_count(),
+            self.result.rows_affected
+        )
+    }
+}
+
+/// An explicit transaction, from `PyDeedGraph.transaction`
+///
+/// `with db.transaction():` begins on entry, then commits when the block
+/// finishes or rolls back when it raises.
+#[pyclass]
+pub struct PyTransaction {
+    executor: DQLExecutor,
+    isolation_level: Option<String>,
+    active: bool,
+}
+
+#[pymethods]
+impl PyTransaction {
+    fn __enter__(mut slf: PyRefMut<'_, Self>) -> PyResult<PyRefMut<'_, Self>> {
+        let begin = match &slf.isolation_level {
+            Some(level) => format!("BEGIN TRANSACTION ISOLATION LEVEL {}", level),
+            None => "BEGIN TRANSACTION".to_string(),
+        };
+        slf.executor.try_execute(&begin)?;
+        slf.active = true;
+        Ok(slf)
+    }
+
+    fn __exit__(
+        &mut self,
+        exc_type: Option<&PyAny>,
+        _exc_value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> PyResult<bool> {
+        // Already ended by commit() or rollback() inside the block
+        if !self.active {
+            return Ok(false);
+        }
+        match exc_type {
+            None => self.commit()?,
+            Some(_) => self.rollback()?,
+        }
+        // Never swallow the block's exception
+        Ok(false)
+    }
+
+    /// Execute a DQL statement in the transaction
+    fn execute(&self, query: String) -> PyResult<PyQueryResult> {
+        if !self.active {
+            return Err(PyRuntimeError::new_err("Transaction is not active"));
+        }
+        let result = self.executor.try_execute(&query)?;
+        Ok(PyQueryResult { result })
+    }
+
+    /// Commit now instead of when the block exits
+    ///
+    /// A failed commit rolls the transaction back before raising.
+    fn commit(&mut self) -> PyResult<()> {
+        self.active = false;
+        if let Err(e) = self.executor.try_execute("COMMIT") {
+            let _ = self.executor.try_execute("ROLLBACK");
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Roll back now instead of when the block exits
+    fn rollback(&mut self) -> PyResult<()> {
+        self.active = false;
+        self.executor.try_execute("ROLLBACK")?;
+        Ok(())
+    }
+
+    /// Whether the transaction has begun and not yet ended
+    #[getter]
+    fn active(&self) -> bool {
+        self.active
+    }
+}
+
+/// A pool of connections sharing one graph database
+#[pyclass]
+pub struct PyConnectionPool {
+    pool: ConnectionPool,
+}
+
+#[pymethods]
+impl PyConnectionPool {
+    /// Create a connection pool over a new graph database
+    ///
+    /// Args:
+    ///     min_size (int): Connections opened up front
+    ///     max_size (int): Most connections open at once
+    ///     connection_timeout (int): Seconds get_connection() waits for a free connection
+    ///     wal_path (str or None): Write-ahead log shared by the connections
+    #[new]
+    #[pyo3(signature = (min_size=2, max_size=10, connection_timeout=30, wal_path=None))]
+    fn new(min_size: usize, max_size: usize, connection_timeout: u64, wal_path: Option<String>) -> PyResult<Self> {
+        let wal_manager = match wal_path {
+            Some(path) => Some(Arc::new(
+                WALManager::new(path).map_err(|e| PyRuntimeError::new_err(format!("Failed to create WAL: {}", e)))?,
+            )),
+            None => None,
+        };
+        let config = PoolConfig {
+            min_size,
+            max_size,
+            connection_timeout,
+            ..Default::default()
+        };
+
+        let pool = ConnectionPool::new(
+            Arc::new(RwLock::new(Graph::new())),
+            Arc::new(RwLock::new(AntColonyOptimizer::new())),
+            Arc::new(RwLock::new(StigmergyCache::new(1000))),
+            Arc::new(TransactionManager::new()),
+            wal_manager,
+            config,
+        )
+        .map_err(PyRuntimeError::new_err)?;
+        Ok(PyConnectionPool { pool })
+    }
+
+    /// Take a connection, waiting up to connection_timeout for one to be free
+    ///
+    /// Returns:
+    ///     Connection: Returned to the pool by close() or at the end of a `with` block
+    fn get_connection(&self) -> PyResult<PyConnection> {
+        let handle = self.pool.get_connection().map_err(PyRuntimeError::new_err)?;
+        Ok(PyConnection { handle: Some(handle) })
+    }
+
+    /// Get pool statistics
+    ///
+    /// Returns:
+    ///     dict: Connection counts, pool bounds, queueing and maintenance counters
+    fn stats(&self) -> PyResult<PyObject> {
+        let stats = self.pool.stats();
+
+        Python::with_gil(|py| {
+            let dict = PyDict::new(py);
+            dict.set_item("total_connections", stats.total_connections)?;
+            dict.set_item("active_connections", stats.active_connections)?;
+            dict.set_item("idle_connections", stats.idle_connections)?;
+            dict.set_item("min_size", stats.min_size)?;
+            dict.set_item("max_size", stats.max_size)?;
+            dict.set_item("evictions", stats.evictions)?;
+            dict.set_item("failed_health_checks", stats.failed_health_checks)?;
+            dict.set_item("replacements", stats.replacements)?;
+            dict.set_item("queue_depth", stats.queue_depth)?;
+            dict.set_item("avg_wait_time", stats.avg_wait_time.as_secs_f64())?;
+
+            Ok(dict.into())
+        })
+    }
+}
+
+/// A connection taken from a `PyConnectionPool`
+#[pyclass]
+pub struct PyConnection {
+    /// `None` once returned to the pool
+    handle: Option<PooledConnectionHandle>,
+}
+
+impl PyConnection {
+    fn handle(&mut self) -> PyResult<&mut PooledConnectionHandle> {
+        self.handle
+            .as_mut()
+            .ok_or_else(|| PyRuntimeError::new_err("Connection is closed"))
+    }
+}
+
+#[pymethods]
+impl PyConnection {
+    /// Execute a DQL query on this connection
+    fn execute(&mut self, query: String) -> PyResult<PyQueryResult> {
+        let result = self.handle()?.try_execute(&query)?;
+        Ok(PyQueryResult { result })
+    }
+
+    /// Execute a DQL query on this connection, iterating over its rows in batches
+    #[pyo3(signature = (query, batch_size=1000))]
+    fn execute_stream(&mut self, query: String, batch_size: usize) -> PyResult<PyRowStream> {
+        let stream = self.handle()?.try_execute_stream(&query, batch_size)?;
+        Ok(PyRowStream { stream })
+    }
+
+    /// Set the timeout, in seconds, of queries on this connection (None for no limit)
+    fn set_query_timeout(&mut self, seconds: Option<f64>) -> PyResult<()> {
+        let timeout = seconds.map(Duration::from_secs_f64);
+        self.handle()?.set_query_timeout(timeout);
+        Ok(())
+    }
+
+    /// Return the connection to its pool
+    fn close(&mut self) {
+        self.handle = None;
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _exc_type: Option<&PyAny>,
+        _exc_value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
 // Helper functions for Python ↔ Rust conversion
 
 fn py_dict_to_properties(dict: &PyDict) -> PyResult<Properties> {
@@ -193,25 +605,37 @@ fn py_dict_to_properties(dict: &PyDict) -> PyResult<Properties> {
 
     for (key, value) in dict.iter() {
         let key_str: String = key.extract()?;
-
-        let prop_value = if let Ok(i) = value.extract::<i64>() {
-            PropertyValue::Int(i)
-        } else if let Ok(f) = value.extract::<f64>() {
-            PropertyValue::Float(f)
-        } else if let Ok(s) = value.extract::<String>() {
-            PropertyValue::String(s)
-        } else if let Ok(b) = value.extract::<bool>() {
-            PropertyValue::Bool(b)
-        } else {
-            PropertyValue::Null
-        };
-
-        props.insert(key_str, prop_value);
+        props.insert(key_str, py_to_property_value(value)?);
     }
 
     Ok(props)
 }
 
+/// Python value as a property; lists and dicts nest, anything unsupported is null
+fn py_to_property_value(value: &PyAny) -> PyResult<PropertyValue> {
+    let prop_value = if let Ok(b) = value.downcast::<PyBool>() {
+        PropertyValue::Bool(b.is_true())
+    } else if let Ok(i) = value.extract::<i64>() {
+        PropertyValue::Int(i)
+    } else if let Ok(f) = value.extract::<f64>() {
+        PropertyValue::Float(f)
+    } else if let Ok(s) = value.extract::<String>() {
+        PropertyValue::String(s)
+    } else if let Ok(bytes) = value.downcast::<PyBytes>() {
+        PropertyValue::Bytes(bytes.as_bytes().to_vec())
+    } else if let Ok(dt) = value.downcast::<PyDateTime>() {
+        PropertyValue::Timestamp(py_datetime_to_millis(dt)?)
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        PropertyValue::List(list.iter().map(py_to_property_value).collect::<PyResult<_>>()?)
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        PropertyValue::Map(py_dict_to_properties(dict)?)
+    } else {
+        PropertyValue::Null
+    };
+
+    Ok(prop_value)
+}
+
 fn property_value_to_py<'py>(py: Python<'py>, value: &PropertyValue) -> PyResult<PyObject> {
     let obj = match value {
         PropertyValue::Null => py.None(),
@@ -219,15 +643,322 @@ fn property_value_to_py<'py>(py: Python<'py>, value: &PropertyValue) -> PyResult
         PropertyValue::Int(i) => i.into_py(py),
         PropertyValue::Float(f) => f.into_py(py),
         PropertyValue::String(s) => s.into_py(py),
-        PropertyValue::Bytes(b) => b.clone().into_py(py),
+        PropertyValue::Bytes(b) => PyBytes::new(py, b).into_py(py),
+        PropertyValue::List(items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(property_value_to_py(py, item)?)?;
+            }
+            list.into_py(py)
+        }
+        PropertyValue::Map(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, property_value_to_py(py, item)?)?;
+            }
+            dict.into_py(py)
+        }
+        PropertyValue::Timestamp(ms) => millis_to_py_datetime(py, *ms)?,
     };
 
     Ok(obj)
 }
 
-/// Python module definition
+/// Milliseconds since the Unix epoch of a `datetime`; naive ones are local
+/// time, as Python's own `datetime.timestamp()` takes them
+fn py_datetime_to_millis(dt: &PyDateTime) -> PyResult<i64> {
+    let seconds: f64 = dt.call_method0("timestamp")?.extract()?;
+    Ok((seconds * 1000.0).round() as i64)
+}
+
+/// A timestamp as a timezone-aware UTC `datetime`
+fn millis_to_py_datetime(py: Python<'_>, ms: i64) -> PyResult<PyObject> {
+    let dt = PyDateTime::from_timestamp(py, ms as f64 / 1000.0, Some(timezone_utc(py)))?;
+    Ok(dt.to_object(py))
+}
+
+fn py_to_value(value: &PyAny) -> PyResult<Value> {
+    if value.is_none() {
+        Ok(Value::Null)
+    } else if let Ok(b) = value.downcast::<PyBool>() {
+        Ok(Value::Bool(b.is_true()))
+    } else if let Ok(i) = value.extract::<i64>() {
+        Ok(Value::Integer(i))
+    } else if let Ok(f) = value.extract::<f64>() {
+        Ok(Value::Float(f))
+    } else if let Ok(s) = value.extract::<String>() {
+        Ok(Value::String(s))
+    } else if let Ok(bytes) = value.downcast::<PyBytes>() {
+        Ok(Value::Bytes(bytes.as_bytes().to_vec()))
+    } else if let Ok(dt) = value.downcast::<PyDateTime>() {
+        Ok(Value::Timestamp(py_datetime_to_millis(dt)?))
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        Ok(Value::List(list.iter().map(py_to_value).collect::<PyResult<_>>()?))
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut map = std::collections::BTreeMap::new();
+        for (key, item) in dict.iter() {
+            map.insert(key.extract::<String>()?, py_to_value(item)?);
+        }
+        Ok(Value::Map(map))
+    } else {
+        Err(PyTypeError::new_err(format!("Unsupported parameter type: {}", value.get_type().name()?)))
+    }
+}
+
+fn value_to_py(py: Python<'_>, value: &Value) -> PyObject {
+    match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.to_object(py),
+        Value::Integer(i) => i.to_object(py),
+        Value::Float(f) => f.to_object(py),
+        Value::String(s) => s.to_object(py),
+        Value::Bytes(b) => PyBytes::new(py, b).to_object(py),
+        Value::EntityId(id) | Value::EdgeId(id) => id.to_object(py),
+        Value::Path(ids) => ids.to_object(py),
+        Value::List(items) => PyList::new(py, items.iter().map(|item| value_to_py(py, item))).to_object(py),
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, item)| (key, value_to_py(py, item)))
+            .into_py_dict(py)
+            .to_object(py),
+        // Out of datetime's range: fall back to the ISO text
+        Value::Timestamp(ms) => millis_to_py_datetime(py, *ms)
+            .unwrap_or_else(|_| crate::types::format_timestamp(*ms).to_object(py)),
+    }
+}
+
+/// Python exception types, one per [`DeedError`] variant
+///
+/// All derive from `DeedError`, itself a `RuntimeError`.
+pub mod exceptions {
+    use pyo3::exceptions::PyRuntimeError;
+
+    pyo3::create_exception!(deed_core, DeedError, PyRuntimeError, "Base class of Deed errors");
+    pyo3::create_exception!(deed_core, ParseError, DeedError, "A query that doesn't parse; has line, column and snippet");
+    pyo3::create_exception!(deed_core, ExecutionError, DeedError, "A statement that failed to run");
+    pyo3::create_exception!(deed_core, ValidationError, DeedError, "A write refused by a schema or UNIQUE index");
+    pyo3::create_exception!(deed_core, TransactionError, DeedError, "A transaction that can't begin, continue or commit");
+    pyo3::create_exception!(deed_core, DeedPermissionError, DeedError, "A statement the caller isn't allowed to run");
+}
+
+impl From<DeedError> for PyErr {
+    fn from(error: DeedError) -> PyErr {
+        let message = error.to_string();
+        match error {
+            DeedError::ParseError { line, column, snippet, .. } => Python::with_gil(|py| {
+                let err = exceptions::ParseError::new_err(message);
+                let value = err.value(py);
+                let attributes = value
+                    .setattr("line", line)
+                    .and_then(|()| value.setattr("column", column))
+                    .and_then(|()| value.setattr("snippet", snippet));
+                attributes.err().unwrap_or(err)
+            }),
+            DeedError::ExecutionError(_) => exceptions::ExecutionError::new_err(message),
+            DeedError::ValidationError(_) => exceptions::ValidationError::new_err(message),
+            DeedError::TransactionError(_) => exceptions::TransactionError::new_err(message),
+            DeedError::PermissionError(_) => exceptions::DeedPermissionError::new_err(message),
+        }
+    }
+}
+
 #[pymodule]
-fn deed_core(_py: Python, m: &PyModule) -> PyResult<()> {
+fn deed_core(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyDeedGraph>()?;
+    m.add_class::<PyRowStream>()?;
+    m.add_class::<PyQueryResult>()?;
+    m.add_class::<PyTransaction>()?;
+    m.add_class::<PyConnectionPool>()?;
+    m.add_class::<PyConnection>()?;
+    m.add("DeedError", py.get_type::<exceptions::DeedError>())?;
+    m.add("ParseError", py.get_type::<exceptions::ParseError>())?;
+    m.add("ExecutionError", py.get_type::<exceptions::ExecutionError>())?;
+    m.add("ValidationError", py.get_type::<exceptions::ValidationError>())?;
+    m.add("TransactionError", py.get_type::<exceptions::TransactionError>())?;
+    m.add("DeedPermissionError", py.get_type::<exceptions::DeedPermissionError>())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_property_value() -> Vec<PropertyValue> {
+        vec![
+            PropertyValue::Null,
+            PropertyValue::Bool(true),
+            PropertyValue::Int(-42),
+            PropertyValue::Float(2.5),
+            PropertyValue::String("héllo".to_string()),
+            PropertyValue::Bytes(vec![0, 159, 255]),
+            PropertyValue::List(vec![PropertyValue::Int(1), PropertyValue::String("two".to_string())]),
+            PropertyValue::Map(HashMap::from([
+                ("nested".to_string(), PropertyValue::Map(HashMap::from([("n".to_string(), PropertyValue::Int(1))]))),
+                ("flag".to_string(), PropertyValue::Bool(false)),
+            ])),
+            PropertyValue::Timestamp(1_709_294_400_123),
+        ]
+    }
+
+    #[test]
+    fn test_property_values_round_trip() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            for value in every_property_value() {
+                let object = property_value_to_py(py, &value).unwrap();
+                assert_eq!(py_to_property_value(object.as_ref(py)).unwrap(), value);
+            }
+        });
+    }
+
+    #[test]
+    fn test_property_values_have_python_types() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let type_names: Vec<String> = every_property_value()
+                .iter()
+                .map(|value| {
+                    let object = property_value_to_py(py, value).unwrap();
+                    object.as_ref(py).get_type().name().unwrap().to_string()
+                })
+                .collect();
+            assert_eq!(
+                type_names,
+                ["NoneType", "bool", "int", "float", "str", "bytes", "list", "dict", "datetime"]
+            );
+
+            // Integral floats stay floats
+            let object = property_value_to_py(py, &PropertyValue::Float(1.0)).unwrap();
+            assert_eq!(py_to_property_value(object.as_ref(py)).unwrap(), PropertyValue::Float(1.0));
+        });
+    }
+
+    #[test]
+    fn test_query_values_round_trip() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let values = [
+                Value::Null,
+                Value::Bool(false),
+                Value::Integer(7),
+                Value::Float(0.5),
+                Value::String("text".to_string()),
+                Value::Bytes(vec![1, 2, 3]),
+                Value::List(vec![Value::Integer(1), Value::Null]),
+                Value::Map(std::collections::BTreeMap::from([("k".to_string(), Value::Float(1.5))])),
+                Value::Timestamp(86_400_000),
+            ];
+            for value in values {
+                let object = value_to_py(py, &value);
+                assert_eq!(py_to_value(object.as_ref(py)).unwrap(), value);
+            }
+
+            // IDs and paths come back as plain ints
+            assert_eq!(value_to_py(py, &Value::EntityId(9)).extract::<u64>(py).unwrap(), 9);
+            assert_eq!(value_to_py(py, &Value::Path(vec![1, 2])).extract::<Vec<u64>>(py).unwrap(), [1, 2]);
+        });
+    }
+
+    #[test]
+    fn test_query_result_rows_are_typed_dicts() {
+        pyo3::prepare_freethreaded_python();
+        let db = PyDeedGraph::new();
+        Python::with_gil(|py| {
+            let props = PyDict::new(py);
+            props.set_item("name", "Ann").unwrap();
+            props.set_item("avatar", PyBytes::new(py, b"\x89PNG")).unwrap();
+            db.add_entity("Users".to_string(), props).unwrap();
+
+            let result = db.execute("FROM Users SELECT name, avatar".to_string()).unwrap();
+            assert_eq!(result.columns(), ["col_0", "col_1"]);
+            assert_eq!(result.column_types()["col_1"], "Bytes");
+            let rows = result.rows(py).unwrap();
+            let row = rows[0].as_ref(py).downcast::<PyDict>().unwrap();
+            assert_eq!(row.get_item("col_0").unwrap().unwrap().extract::<String>().unwrap(), "Ann");
+            let avatar = row.get_item("col_1").unwrap().unwrap();
+            assert_eq!(avatar.downcast::<PyBytes>().unwrap().as_bytes(), b"\x89PNG");
+        });
+    }
+
+    #[test]
+    fn test_transaction_commits_or_rolls_back() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let db = Py::new(py, PyDeedGraph::new()).unwrap();
+            let locals = [("db", db)].into_py_dict(py);
+            let script = r#"
+with db.transaction() as txn:
+    txn.execute("INSERT INTO Users VALUES ({name: 'Ann'})")
+assert not txn.active
+
+try:
+    with db.transaction("SERIALIZABLE"):
+        db.execute("INSERT INTO Users VALUES ({name: 'Ben'})")
+        raise ValueError("abort")
+except ValueError:
+    pass
+
+assert [row["col_0"] for row in db.execute("FROM Users SELECT name").rows] == ["Ann"]
+"#;
+            py.run(script, None, Some(locals)).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_errors_raise_distinct_exception_types() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let db = Py::new(py, PyDeedGraph::new()).unwrap();
+            let module = PyModule::new(py, "deed_core").unwrap();
+            deed_core(py, module).unwrap();
+            let locals = [("db", db.into_py(py)), ("deed", module.into_py(py))].into_py_dict(py);
+            let script = r#"
+try:
+    db.execute("FROM Users\nSELCT name")
+    assert False
+except deed.ParseError as e:
+    assert (e.line, e.column) == (2, 7)
+    assert "did you mean SELECT" in str(e)
+
+db.execute("INSERT INTO Users VALUES ({age: 0})")
+for query, kind in [
+    ("FROM Users WHERE 1 / age > 0 SELECT age", deed.ExecutionError),
+    ("INSERT INTO _audit VALUES ({age: 1})", deed.DeedPermissionError),
+    ("COMMIT", deed.TransactionError),
+]:
+    try:
+        db.execute(query)
+        assert False
+    except kind as e:
+        assert isinstance(e, deed.DeedError) and isinstance(e, RuntimeError)
+"#;
+            py.run(script, None, Some(locals)).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_pool_connections() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let pool = Py::new(py, PyConnectionPool::new(1, 2, 1, None).unwrap()).unwrap();
+            let locals = [("pool", pool)].into_py_dict(py);
+            let script = r#"
+with pool.get_connection() as conn:
+    for n in range(5):
+        conn.execute("INSERT INTO Events VALUES ({n: %d})" % n)
+    assert pool.stats()["active_connections"] == 1
+assert pool.stats()["active_connections"] == 0
+
+conn = pool.get_connection()
+assert [len(batch) for batch in conn.execute_stream("FROM Events SELECT n", 2)] == [2, 2, 1]
+conn.close()
+try:
+    conn.execute("FROM Events SELECT n")
+    assert False
+except RuntimeError as e:
+    assert str(e) == "Connection is closed"
+"#;
+            py.run(script, None, Some(locals)).unwrap();
+        });
+    }
+}
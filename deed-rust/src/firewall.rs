@@ -0,0 +1,555 @@
+//! Statement Firewall
+//!
+//! Allow/deny rules on query shapes, per user, role, or API key.
+//! - Rules match statement class, collections/edge types touched, unbounded
+//!   constructs (no LIMIT, full scans, unbounded traversals) and estimated cost
+//! - Checked after planning, before execution
+//! - Learning mode records would-be violations without blocking
+//! - Rules persist to a JSON file; rejections and learned violations are audited
+
+use crate::auth::Role;
+use crate::dql_ir::{FilterExpr, Operation, QueryPlan};
+use crate::graph::GraphStats;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of audit log entries kept in memory
+const MAX_AUDIT_ENTRIES: usize = 10_000;
+
+/// Rule name reported when allow rules exist but none matched
+const ALLOWLIST_RULE: &str = "<allowlist>";
+
+/// Who a rule applies to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FirewallSubject {
+    User(String),
+    Role(Role),
+    ApiKey(String),
+}
+
+/// Rule action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FirewallAction {
+    Allow,
+    Deny,
+}
+
+/// Statement class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatementClass {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    Create,
+    /// DROP INDEX, DROP SCHEMA, DROP COLLECTION and TRUNCATE
+    Drop,
+    /// SET, ANALYZE, REINDEX and FIREWALL
+    Admin,
+    /// BEGIN, COMMIT, ROLLBACK and savepoints
+    Transaction,
+}
+
+/// Rule condition - a rule matches when all of its conditions match
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FirewallCondition {
+    Class(StatementClass),
+    Collection(String),
+    EdgeType(String),
+    /// SELECT without LIMIT
+    NoLimit,
+    /// Scan without an indexable predicate
+    FullScan,
+    /// Variable-length traversal without a max hop count
+    UnboundedTraversal,
+    /// Any of NoLimit, FullScan, UnboundedTraversal
+    Unbounded,
+    CostAbove(f64),
+}
+
+/// Firewall rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FirewallRule {
+    pub name: String,
+    pub subject: FirewallSubject,
+    pub action: FirewallAction,
+    pub conditions: Vec<FirewallCondition>,
+}
+
+impl FirewallRule {
+    fn matches(&self, shape: &StatementShape) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            FirewallCondition::Class(class) => shape.class == *class,
+            FirewallCondition::Collection(name) => shape.collections.contains(name),
+            FirewallCondition::EdgeType(name) => shape.edge_types.contains(name),
+            FirewallCondition::NoLimit => shape.no_limit(),
+            FirewallCondition::FullScan => shape.full_scan,
+            FirewallCondition::UnboundedTraversal => shape.unbounded_traversal,
+            FirewallCondition::Unbounded => shape.is_unbounded(),
+            FirewallCondition::CostAbove(threshold) => shape.estimated_cost > *threshold,
+        })
+    }
+}
+
+/// Shape of a planned statement, as seen by the firewall
+#[derive(Debug, Clone)]
+pub struct StatementShape {
+    pub class: StatementClass,
+    pub collections: Vec<String>,
+    pub edge_types: Vec<String>,
+    pub has_limit: bool,
+    pub full_scan: bool,
+    pub unbounded_traversal: bool,
+    pub estimated_cost: f64,
+}
+
+impl StatementShape {
+    /// Derive a statement's shape from its plan
+    ///
+    /// `is_indexed(collection, field)` reports whether a predicate on the
+    /// field could be served by an index.
+    pub fn from_plan<F>(class: StatementClass, plan: &QueryPlan, stats: &GraphStats, is_indexed: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        let mut shape = StatementShape {
+            class,
+            collections: Vec::new(),
+            edge_types: Vec::new(),
+            has_limit: false,
+            full_scan: false,
+            unbounded_traversal: false,
+            estimated_cost: plan.operation_costs(stats).iter().sum::<f32>() as f64,
+        };
+
+        // A subquery's scans and traversals count toward the statement, but
+        // its LIMIT doesn't bound the statement's result
+        let subquery_operations = plan
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Subquery { plan, .. } => Some(plan.operations.iter().map(|op| (op, true))),
+                _ => None,
+            })
+            .flatten();
+
+        for (operation, in_subquery) in plan.operations.iter().map(|op| (op, false)).chain(subquery_operations) {
+            match operation {
+                Operation::Scan { collection, filter, .. } => {
+                    shape.add_collection(collection);
+                    let indexable = filter
+                        .as_ref()
+                        .is_some_and(|f| has_indexable_predicate(f, &|field| is_indexed(collection, field)));
+                    shape.full_scan |= !indexable;
+                }
+                Operation::IndexLookup { collection, .. } | Operation::InsertEntity { collection, .. } => {
+                    shape.add_collection(collection);
+                }
+                Operation::Traverse { edge_types, max_hops, .. } => {
+                    for edge_type in edge_types {
+                        shape.add_edge_type(edge_type);
+                    }
+                    shape.unbounded_traversal |= *max_hops == usize::MAX;
+                }
+                Operation::CreateEdge { edge_type, .. } => shape.add_edge_type(edge_type),
+                Operation::UpdateEdges { edges, .. } | Operation::DeleteEdges { edges } => {
+                    for edge_type in &edges.edge_types {
+                        shape.add_edge_type(edge_type);
+                    }
+                }
+                Operation::Limit { .. } if !in_subquery => shape.has_limit = true,
+                _ => {}
+            }
+        }
+
+        shape
+    }
+
+    /// Shape of a statement that runs without a plan, touching `collections`
+    ///
+    /// `full_scan` marks one that reads all of its collections (COPY ... TO),
+    /// which also leaves it without a LIMIT.
+    pub fn command(class: StatementClass, collections: Vec<String>, full_scan: bool) -> Self {
+        StatementShape {
+            class,
+            collections,
+            edge_types: Vec::new(),
+            has_limit: !full_scan,
+            full_scan,
+            unbounded_traversal: false,
+            estimated_cost: 0.0,
+        }
+    }
+
+    /// SELECT without a LIMIT
+    pub fn no_limit(&self) -> bool {
+        self.class == StatementClass::Select && !self.has_limit
+    }
+
+    /// Whether the statement contains any unbounded construct
+    pub fn is_unbounded(&self) -> bool {
+        self.no_limit() || self.full_scan || self.unbounded_traversal
+    }
+
+    fn add_collection(&mut self, collection: &str) {
+        if !self.collections.iter().any(|c| c == collection) {
+            self.collections.push(collection.to_string());
+        }
+    }
+
+    fn add_edge_type(&mut self, edge_type: &str) {
+        if !self.edge_types.iter().any(|t| t == edge_type) {
+            self.edge_types.push(edge_type.to_string());
+        }
+    }
+}
+
+/// Whether a filter constrains the scan through an indexed field
+fn has_indexable_predicate(expr: &FilterExpr, is_indexed: &dyn Fn(&str) -> bool) -> bool {
+    match expr {
+        FilterExpr::And(left, right) => {
+            has_indexable_predicate(left, is_indexed) || has_indexable_predicate(right, is_indexed)
+        }
+        FilterExpr::Or(left, right) => {
+            has_indexable_predicate(left, is_indexed) && has_indexable_predicate(right, is_indexed)
+        }
+        FilterExpr::Equal(left, right)
+        | FilterExpr::LessThan(left, right)
+        | FilterExpr::LessThanEq(left, right)
+        | FilterExpr::GreaterThan(left, right)
+        | FilterExpr::GreaterThanEq(left, right) => match (left.as_ref(), right.as_ref()) {
+            (FilterExpr::Property { property, .. }, FilterExpr::Constant(_))
+            | (FilterExpr::Constant(_), FilterExpr::Property { property, .. }) => is_indexed(property),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Identity a statement runs as
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FirewallPrincipal {
+    pub username: Option<String>,
+    pub role: Option<Role>,
+    pub api_key: Option<String>,
+}
+
+impl FirewallPrincipal {
+    /// Principal for an authenticated user
+    pub fn user(username: &str, role: Role) -> Self {
+        FirewallPrincipal {
+            username: Some(username.to_string()),
+            role: Some(role),
+            api_key: None,
+        }
+    }
+
+    /// Principal for an API key
+    pub fn api_key(key: &str) -> Self {
+        FirewallPrincipal {
+            api_key: Some(key.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Whether a rule subject refers to this principal
+    pub fn matches(&self, subject: &FirewallSubject) -> bool {
+        match subject {
+            FirewallSubject::User(name) => self.username.as_ref() == Some(name),
+            FirewallSubject::Role(role) => self.role.as_ref() == Some(role),
+            FirewallSubject::ApiKey(key) => self.api_key.as_ref() == Some(key),
+        }
+    }
+
+    /// Whether this principal may manage firewall rules
+    pub fn is_admin(&self) -> bool {
+        self.role == Some(Role::Admin)
+    }
+
+    fn describe(&self) -> String {
+        match (&self.username, &self.api_key) {
+            (Some(user), _) => format!("user '{}'", user),
+            (None, Some(key)) => format!("api key '{}'", key),
+            (None, None) => "anonymous".to_string(),
+        }
+    }
+}
+
+/// A statement refused by the firewall
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRejected {
+    pub rule: String,
+    pub principal: String,
+    pub statement: String,
+}
+
+impl FirewallRejected {
+    /// Whether an error message reports a firewall rejection
+    pub fn is_rejection(error: &str) -> bool {
+        error.starts_with("FirewallRejected")
+    }
+}
+
+impl fmt::Display for FirewallRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FirewallRejected: rule '{}' blocks this statement for {}",
+            self.rule, self.principal
+        )
+    }
+}
+
+impl std::error::Error for FirewallRejected {}
+
+/// Audit record of a rejected (or, in learning mode, would-be rejected) statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirewallAuditEntry {
+    pub timestamp: u64,
+    pub principal: String,
+    pub rule: String,
+    pub statement: String,
+    /// false when only recorded in learning mode
+    pub enforced: bool,
+}
+
+/// Persisted firewall state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FirewallState {
+    rules: Vec<FirewallRule>,
+    /// Subjects in learning mode, with the end of their learning window (secs)
+    learning: Vec<(FirewallSubject, u64)>,
+}
+
+/// Statement firewall
+pub struct Firewall {
+    state: RwLock<FirewallState>,
+    audit_log: RwLock<VecDeque<FirewallAuditEntry>>,
+    path: Option<PathBuf>,
+}
+
+impl Firewall {
+    /// Create an in-memory firewall with no rules
+    pub fn new() -> Self {
+        Firewall {
+            state: RwLock::new(FirewallState::default()),
+            audit_log: RwLock::new(VecDeque::new()),
+            path: None,
+        }
+    }
+
+    /// Open a firewall persisted at `path`, loading its rules if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+
+        let state = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read firewall rules: {}", e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse firewall rules: {}", e))?
+        } else {
+            FirewallState::default()
+        };
+
+        Ok(Firewall {
+            state: RwLock::new(state),
+            audit_log: RwLock::new(VecDeque::new()),
+            path: Some(path),
+        })
+    }
+
+    /// Add a rule
+    pub fn add_rule(&self, rule: FirewallRule) -> Result<(), String> {
+        let mut state = self.state.write().unwrap();
+
+        if state.rules.iter().any(|r| r.name == rule.name) {
+            return Err(format!("Firewall rule {} already exists", rule.name));
+        }
+        state.rules.push(rule);
+
+        self.persist(&state)
+    }
+
+    /// Drop a rule by name
+    pub fn drop_rule(&self, name: &str) -> Result<(), String> {
+        let mut state = self.state.write().unwrap();
+
+        let initial_len = state.rules.len();
+        state.rules.retain(|r| r.name != name);
+        if state.rules.len() == initial_len {
+            return Err(format!("Firewall rule {} not found", name));
+        }
+
+        self.persist(&state)
+    }
+
+    /// List rules
+    pub fn rules(&self) -> Vec<FirewallRule> {
+        self.state.read().unwrap().rules.clone()
+    }
+
+    /// Record instead of enforce violations for a subject, for `duration`
+    pub fn learn(&self, subject: FirewallSubject, duration: Duration) -> Result<(), String> {
+        let mut state = self.state.write().unwrap();
+
+        let until = current_timestamp() + duration.as_secs();
+        state.learning.retain(|(s, _)| *s != subject);
+        state.learning.push((subject, until));
+
+        self.persist(&state)
+    }
+
+    /// End learning mode for a subject
+    pub fn enforce(&self, subject: &FirewallSubject) -> Result<(), String> {
+        let mut state = self.state.write().unwrap();
+        state.learning.retain(|(s, _)| s != subject);
+        self.persist(&state)
+    }
+
+    /// Check a planned statement against the rules for a principal
+    pub fn check(
+        &self,
+        principal: &FirewallPrincipal,
+        shape: &StatementShape,
+        statement: &str,
+    ) -> Result<(), FirewallRejected> {
+        let state = self.state.read().unwrap();
+        let applicable: Vec<&FirewallRule> = state
+            .rules
+            .iter()
+            .filter(|rule| principal.matches(&rule.subject))
+            .collect();
+
+        let denied_by = applicable
+            .iter()
+            .find(|rule| rule.action == FirewallAction::Deny && rule.matches(shape))
+            .map(|rule| rule.name.clone());
+
+        let violated = denied_by.or_else(|| {
+            let mut allow_rules = applicable.iter().filter(|rule| rule.action == FirewallAction::Allow);
+            let has_allow_rules = allow_rules.clone().next().is_some();
+
+            if has_allow_rules && !allow_rules.any(|rule| rule.matches(shape)) {
+                Some(ALLOWLIST_RULE.to_string())
+            } else {
+                None
+            }
+        });
+
+        let rule = match violated {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+
+        let now = current_timestamp();
+        let learning = state
+            .learning
+            .iter()
+            .any(|(subject, until)| *until > now && principal.matches(subject));
+        drop(state);
+
+        self.audit(FirewallAuditEntry {
+            timestamp: now,
+            principal: principal.describe(),
+            rule: rule.clone(),
+            statement: statement.to_string(),
+            enforced: !learning,
+        });
+
+        if learning {
+            Ok(())
+        } else {
+            Err(FirewallRejected {
+                rule,
+                principal: principal.describe(),
+                statement: statement.to_string(),
+            })
+        }
+    }
+
+    /// Audit log of rejected and learned statements (oldest first)
+    pub fn audit_log(&self) -> Vec<FirewallAuditEntry> {
+        self.audit_log.read().unwrap().iter().cloned().collect()
+    }
+
+    fn audit(&self, entry: FirewallAuditEntry) {
+        let mut log = self.audit_log.write().unwrap();
+        if log.len() >= MAX_AUDIT_ENTRIES {
+            log.pop_front();
+        }
+        log.push_back(entry);
+    }
+
+    fn persist(&self, state: &FirewallState) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            let serialized = serde_json::to_string_pretty(state)
+                .map_err(|e| format!("Failed to serialize firewall rules: {}", e))?;
+            std::fs::write(path, serialized)
+                .map_err(|e| format!("Failed to write firewall rules: {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Firewall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select_shape(has_limit: bool, full_scan: bool) -> StatementShape {
+        StatementShape {
+            class: StatementClass::Select,
+            collections: vec!["Users".to_string()],
+            edge_types: Vec::new(),
+            has_limit,
+            full_scan,
+            unbounded_traversal: false,
+            estimated_cost: 10.0,
+        }
+    }
+
+    #[test]
+    fn test_allowlist_rejects_unmatched_shapes() {
+        let firewall = Firewall::new();
+        firewall
+            .add_rule(FirewallRule {
+                name: "users_reads".to_string(),
+                subject: FirewallSubject::ApiKey("public".to_string()),
+                action: FirewallAction::Allow,
+                conditions: vec![
+                    FirewallCondition::Class(StatementClass::Select),
+                    FirewallCondition::Collection("Users".to_string()),
+                ],
+            })
+            .unwrap();
+
+        let principal = FirewallPrincipal::api_key("public");
+        assert!(firewall.check(&principal, &select_shape(true, false), "q").is_ok());
+
+        let mut insert = select_shape(true, false);
+        insert.class = StatementClass::Insert;
+        let err = firewall.check(&principal, &insert, "q").unwrap_err();
+        assert_eq!(err.rule, ALLOWLIST_RULE);
+
+        // Other principals are unaffected
+        let other = FirewallPrincipal::api_key("internal");
+        assert!(firewall.check(&other, &insert, "q").is_ok());
+    }
+}
@@ -6,11 +6,22 @@
 //! - Pheromone tracking for biological optimization
 //! - Vectorized operations where possible
 
+use crate::change_feed::{ChangeFeed, ChangeFilter, ChangeSubscription};
+use crate::expiry::{ExpiryIndex, ExpirySlot};
+use crate::graph_export::{ExportFilter, GraphFormat, Subgraph};
+use crate::statistics::{AnalyzeReport, CollectionStats, PropertyTracker, Sample, ANALYZE_SAMPLE_SIZE};
+use crate::storage::StorageEngine;
 use crate::types::*;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Property an entity may set to the Timestamp it expires at, overriding
+/// its collection's TTL; edges expire by it too
+pub const EXPIRES_AT_PROPERTY: &str = "_expires_at";
 
 /// Entity (universal node)
 ///
@@ -41,6 +52,17 @@ impl Entity {
         }
     }
 
+    /// The entity as created at `millis` since the Unix epoch
+    pub fn with_created_at(mut self, millis: u64) -> Self {
+        self.created_at = UNIX_EPOCH + Duration::from_millis(millis);
+        self
+    }
+
+    /// When the entity was created, in milliseconds since the Unix epoch
+    pub fn created_millis(&self) -> u64 {
+        self.created_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+
     /// Mark entity as accessed (for pheromone tracking)
     pub fn mark_accessed(&mut self) {
         self.access_count += 1;
@@ -52,6 +74,19 @@ impl Entity {
         self.properties.get(key)
     }
 
+    /// Get a property, or a value nested in one by a dotted path (`address.city`)
+    ///
+    /// A property whose own name contains dots wins over the nested path.
+    pub fn get_property_path(&self, path: &str) -> Option<&PropertyValue> {
+        if let Some(value) = self.properties.get(path) {
+            return Some(value);
+        }
+
+        let mut keys = path.split('.');
+        let first = self.properties.get(keys.next()?)?;
+        keys.try_fold(first, |value, key| value.get(key))
+    }
+
     /// Set property value
     pub fn set_property(&mut self, key: String, value: PropertyValue) {
         self.properties.insert(key, value);
@@ -142,10 +177,53 @@ impl Edge {
 /// Maps entity -> {edge_type -> [(target_entity, edge_id)]}
 type AdjacencyList = DashMap<EntityId, DashMap<EdgeType, Vec<(EntityId, EdgeId)>>>;
 
+/// Number of `entity_id`'s edges in `list`, of one type or all of them
+fn count_edges(list: &AdjacencyList, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+    let Some(by_type) = list.get(&entity_id) else { return 0 };
+    match edge_type {
+        Some(edge_type) => by_type.get(edge_type).map_or(0, |neighbors| neighbors.len()),
+        None => by_type.iter().map(|neighbors| neighbors.len()).sum(),
+    }
+}
+
+/// Approximate bytes of properties, names included
+fn properties_size<'a>(properties: impl Iterator<Item = (&'a String, &'a PropertyValue)>) -> u64 {
+    properties.map(|(key, value)| (key.len() + value.approximate_size()) as u64).sum()
+}
+
+/// Edge counts read straight from a graph's adjacency lists
+///
+/// The handle shares the lists, so it stays usable after the lock on the
+/// graph is released; each call counts the edges as they are then.
+#[derive(Clone)]
+pub struct Degrees {
+    outgoing: Arc<AdjacencyList>,
+    incoming: Arc<AdjacencyList>,
+}
+
+impl Degrees {
+    /// Edges leaving the entity
+    pub fn out_degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        count_edges(&self.outgoing, entity_id, edge_type)
+    }
+
+    /// Edges arriving at the entity
+    pub fn in_degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        count_edges(&self.incoming, entity_id, edge_type)
+    }
+
+    /// Edges either way; a self-loop counts twice
+    pub fn degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        self.out_degree(entity_id, edge_type) + self.in_degree(entity_id, edge_type)
+    }
+}
+
 /// In-memory graph structure
 ///
-/// Uses concurrent data structures for lock-free access.
-/// Production version would use RocksDB for persistence.
+/// Uses concurrent data structures for lock-free access. A graph loaded with
+/// `load_from_storage` also writes every structural change through to
+/// RocksDB; pheromone trails are saved with their edge's next write rather
+/// than on every traversal.
 pub struct Graph {
     // Entity storage
     entities: DashMap<EntityId, Entity>,
@@ -153,85 +231,509 @@ pub struct Graph {
     // Edge storage
     edges: DashMap<EdgeId, Edge>,
 
-    // Adjacency lists for fast traversal
-    outgoing: AdjacencyList,
-    incoming: AdjacencyList,
+    // Adjacency lists for fast traversal, shared with `Degrees` handles
+    outgoing: Arc<AdjacencyList>,
+    incoming: Arc<AdjacencyList>,
 
     // Collections (table-like groupings)
     collections: DashMap<EntityType, Vec<EntityId>>,
 
+    // Optimizer statistics: property estimates per collection, edges per type
+    property_stats: PropertyTracker,
+    edge_type_counts: DashMap<EdgeType, usize>,
+
     // ID generators
     next_entity_id: AtomicU64,
     next_edge_id: AtomicU64,
+
+    // Entities fetched from storage (scans and point reads)
+    entity_reads: AtomicU64,
+
+    // Entities copied out of storage, and the approximate bytes of
+    // properties those copies took
+    entity_clones: AtomicU64,
+    bytes_cloned: AtomicU64,
+
+    // Write-through persistence, if any
+    storage: Option<Arc<StorageEngine>>,
+
+    // Committed changes, published by the executor to subscribers
+    changes: Arc<ChangeFeed>,
+
+    // How long entities of each collection live after they're created
+    collection_ttls: DashMap<EntityType, Duration>,
+
+    // Entities and edges that expire, by when; edges in it are counted so
+    // reads can skip the lock while none expire
+    expiries: Mutex<ExpiryIndex>,
+    expiring_edges: AtomicU64,
 }
 
+/// Borrowed entity from a streaming scan
+///
+/// Holds a read lock on the entity's shard until dropped, so don't mutate
+/// the graph while holding one.
+pub type EntityRef<'a> = dashmap::mapref::one::Ref<'a, EntityId, Entity>;
+
 impl Graph {
     pub fn new() -> Self {
         Graph {
             entities: DashMap::new(),
             edges: DashMap::new(),
-            outgoing: DashMap::new(),
-            incoming: DashMap::new(),
+            outgoing: Arc::default(),
+            incoming: Arc::default(),
             collections: DashMap::new(),
+            property_stats: PropertyTracker::default(),
+            edge_type_counts: DashMap::new(),
             next_entity_id: AtomicU64::new(1),
             next_edge_id: AtomicU64::new(1),
+            entity_reads: AtomicU64::new(0),
+            entity_clones: AtomicU64::new(0),
+            bytes_cloned: AtomicU64::new(0),
+            storage: None,
+            changes: Arc::default(),
+            collection_ttls: DashMap::new(),
+            expiries: Mutex::default(),
+            expiring_edges: AtomicU64::new(0),
+        }
+    }
+
+    /// Publish committed changes to an existing feed (keeping its subscribers)
+    pub fn with_change_feed(mut self, changes: Arc<ChangeFeed>) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    /// The feed committed changes to this graph are published to
+    pub fn change_feed(&self) -> Arc<ChangeFeed> {
+        self.changes.clone()
+    }
+
+    /// Subscribe to committed changes
+    pub fn subscribe(&self, filter: ChangeFilter) -> ChangeSubscription {
+        self.changes.subscribe(filter)
+    }
+
+    /// Rebuild a graph from RocksDB and keep writing through to it
+    ///
+    /// Adjacency lists, collections and ID generators are rebuilt from the
+    /// stored entities and edges.
+    pub fn load_from_storage(storage: Arc<StorageEngine>) -> Result<Self, String> {
+        let mut graph = Graph::new();
+
+        for entity in storage.scan_entities()? {
+            graph.insert_entity_with_id(entity)?;
+        }
+        // An edge lives with its source; its target may be on another node
+        for edge in storage.scan_edges()? {
+            if !graph.entities.contains_key(&edge.source) {
+                return Err(format!("Edge {:?} references a missing entity", edge.id));
+            }
+            graph.insert_edge_with_id(edge)?;
+        }
+
+        graph.storage = Some(storage);
+        Ok(graph)
+    }
+
+    /// Whether changes are written through to RocksDB
+    pub fn is_persistent(&self) -> bool {
+        self.storage.is_some()
+    }
+
+    /// Run a write against the storage engine, if there is one
+    ///
+    /// Mutations call this before changing the graph, so one whose write
+    /// fails leaves the graph as it was.
+    fn persist<F>(&self, write: F) -> Result<(), String>
+    where
+        F: FnOnce(&StorageEngine) -> Result<(), String>,
+    {
+        match &self.storage {
+            Some(storage) => write(storage).map_err(|e| format!("Storage write failed: {}", e)),
+            None => Ok(()),
         }
     }
 
     /// Add a new entity
-    pub fn add_entity(&self, entity_type: EntityType, properties: Properties) -> EntityId {
+    pub fn add_entity(&self, entity_type: EntityType, properties: Properties) -> Result<EntityId, String> {
         let id = EntityId::new(self.next_entity_id.fetch_add(1, Ordering::SeqCst));
         let entity = Entity::new(id, entity_type.clone(), properties);
 
+        self.persist(|storage| storage.put_entity(&entity))?;
+        self.property_stats.insert(&entity);
+        self.track_expiry(id, None, Some(&entity));
         self.entities.insert(id, entity);
 
         // Add to collection
         self.collections
             .entry(entity_type)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(id);
 
         // Initialize adjacency lists
         self.outgoing.insert(id, DashMap::new());
         self.incoming.insert(id, DashMap::new());
 
-        id
+        Ok(id)
+    }
+
+    /// Add a batch of entities of one type, returning their IDs in order
+    ///
+    /// The IDs are allocated as one contiguous block.
+    pub fn add_entities(&self, entity_type: EntityType, batch: Vec<Properties>) -> Result<Vec<EntityId>, String> {
+        let start = self.next_entity_id.fetch_add(batch.len() as u64, Ordering::SeqCst);
+        let ids: Vec<EntityId> = (start..start + batch.len() as u64).map(EntityId::new).collect();
+
+        let entities: Vec<Entity> = ids
+            .iter()
+            .zip(batch)
+            .map(|(&id, properties)| Entity::new(id, entity_type.clone(), properties))
+            .collect();
+        self.persist(|storage| storage.put_entities(&entities))?;
+
+        for entity in entities {
+            self.property_stats.insert(&entity);
+            self.track_expiry(entity.id, None, Some(&entity));
+            self.outgoing.insert(entity.id, DashMap::new());
+            self.incoming.insert(entity.id, DashMap::new());
+            self.entities.insert(entity.id, entity);
+        }
+
+        self.collections
+            .entry(entity_type)
+            .or_default()
+            .extend_from_slice(&ids);
+
+        Ok(ids)
+    }
+
+    /// Reserve IDs for entities a transaction adds when it commits
+    pub fn allocate_entity_ids(&self, count: usize) -> Vec<EntityId> {
+        let start = self.next_entity_id.fetch_add(count as u64, Ordering::SeqCst);
+        (start..start + count as u64).map(EntityId::new).collect()
+    }
+
+    /// Reserve an ID for an edge a transaction adds when it commits
+    pub fn allocate_edge_id(&self) -> EdgeId {
+        EdgeId::new(self.next_edge_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Store entities as a committing transaction left them, adding the
+    /// new ones and replacing the rest
+    pub fn put_entities(&self, entities: Vec<Entity>) -> Result<(), String> {
+        self.persist(|storage| storage.put_entities(&entities))?;
+        self.store_entities(entities);
+        Ok(())
+    }
+
+    fn store_entities(&self, entities: Vec<Entity>) {
+        let mut added: HashMap<EntityType, Vec<EntityId>> = HashMap::new();
+        for entity in entities {
+            let (id, entity_type) = (entity.id, entity.entity_type.clone());
+            self.property_stats.insert(&entity);
+            let slot = self.expiry_slot(&entity);
+            match self.entities.insert(id, entity) {
+                Some(old) => {
+                    self.property_stats.remove(&old);
+                    self.move_expiry(id, self.expiry_slot(&old), slot);
+                }
+                None => {
+                    self.move_expiry(id, None, slot);
+                    self.outgoing.entry(id).or_default();
+                    self.incoming.entry(id).or_default();
+                    added.entry(entity_type).or_default().push(id);
+                }
+            }
+        }
+
+        for (entity_type, ids) in added {
+            self.collections.entry(entity_type).or_default().extend(ids);
+        }
     }
 
     /// Get entity by ID
     pub fn get_entity(&self, id: EntityId) -> Option<Entity> {
-        self.entities.get(&id).map(|e| {
-            let mut entity = e.clone();
-            entity.mark_accessed();
-            entity
-        })
+        self.entity_reads.fetch_add(1, Ordering::Relaxed);
+        self.entities.get(&id).map(|e| self.clone_entity(&e))
+    }
+
+    /// Get an entity by ID with only the named properties
+    pub fn get_entity_projected(&self, id: EntityId, properties: &[&str]) -> Option<Entity> {
+        self.entity_reads.fetch_add(1, Ordering::Relaxed);
+        self.entities.get(&id).map(|e| self.clone_entity_projected(&e, properties))
+    }
+
+    /// [`get_entity_projected`](Self::get_entity_projected), unless the
+    /// entity has expired by `now`
+    ///
+    /// The expiry is checked on the stored entity, so the projection needn't
+    /// keep the properties it's read from.
+    pub fn get_live_entity_projected(&self, id: EntityId, properties: &[&str], now: i64) -> Option<Entity> {
+        self.entity_reads.fetch_add(1, Ordering::Relaxed);
+        let entity = self.entities.get(&id)?;
+        (!self.is_expired(&entity, now)).then(|| self.clone_entity_projected(&entity, properties))
+    }
+
+    /// Copy an entity out of storage (e.g. a scan survivor worth keeping)
+    pub fn clone_entity(&self, entity: &Entity) -> Entity {
+        self.entity_clones.fetch_add(1, Ordering::Relaxed);
+        self.bytes_cloned.fetch_add(properties_size(entity.properties.iter()), Ordering::Relaxed);
+        let mut entity = entity.clone();
+        entity.mark_accessed();
+        entity
+    }
+
+    /// Copy an entity out of storage with only the named properties
+    ///
+    /// The copy is a view for reading: writing it back would drop the rest.
+    /// A dotted name also keeps the property its path starts in
+    /// (`address.city` keeps `address`).
+    pub fn clone_entity_projected(&self, entity: &Entity, properties: &[&str]) -> Entity {
+        let kept: Properties = entity
+            .properties
+            .iter()
+            .filter(|(key, _)| {
+                properties.iter().any(|name| {
+                    name == key || name.strip_prefix(key.as_str()).is_some_and(|rest| rest.starts_with('.'))
+                })
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        self.entity_clones.fetch_add(1, Ordering::Relaxed);
+        self.bytes_cloned.fetch_add(properties_size(kept.iter()), Ordering::Relaxed);
+        let mut view = Entity {
+            id: entity.id,
+            entity_type: entity.entity_type.clone(),
+            properties: kept,
+            access_count: entity.access_count,
+            last_accessed: entity.last_accessed,
+            created_at: entity.created_at,
+        };
+        view.mark_accessed();
+        view
+    }
+
+    /// Number of entity reads served so far
+    pub fn entity_reads(&self) -> u64 {
+        self.entity_reads.load(Ordering::Relaxed)
+    }
+
+    /// Number of entities copied out of storage so far
+    pub fn entity_clones(&self) -> u64 {
+        self.entity_clones.load(Ordering::Relaxed)
+    }
+
+    /// Approximate bytes of properties copied out of storage so far
+    pub fn bytes_cloned(&self) -> u64 {
+        self.bytes_cloned.load(Ordering::Relaxed)
     }
 
     /// Update an existing entity's properties
     pub fn update_entity(&self, entity: Entity) -> Result<(), String> {
         let id = entity.id;
         if self.entities.contains_key(&id) {
-            self.entities.insert(id, entity);
+            self.persist(|storage| storage.put_entity(&entity))?;
+            self.property_stats.insert(&entity);
+            let slot = self.expiry_slot(&entity);
+            if let Some(old) = self.entities.insert(id, entity) {
+                self.property_stats.remove(&old);
+                self.move_expiry(id, self.expiry_slot(&old), slot);
+            }
             Ok(())
         } else {
             Err(format!("Entity with ID {:?} not found", id))
         }
     }
 
-    /// Delete an entity by ID
+    /// Delete an entity by ID, detaching every edge that touches it
     pub fn delete_entity(&self, id: EntityId) -> Result<(), String> {
-        if self.entities.remove(&id).is_some() {
-            // Remove from collections
-            for mut collection in self.collections.iter_mut() {
-                collection.value_mut().retain(|&entity_id| entity_id != id);
+        self.delete_entities(&[id])
+    }
+
+    /// Delete a batch of entities and their edges
+    ///
+    /// Each collection is compacted once rather than once per entity.
+    pub fn delete_entities(&self, ids: &[EntityId]) -> Result<(), String> {
+        self.check_entities(ids)?;
+        self.persist(|storage| {
+            let mut batch = storage.batch();
+            ids.iter().try_for_each(|&id| batch.delete_entity(id))?;
+            batch.commit()
+        })?;
+        self.remove_entities(ids);
+        Ok(())
+    }
+
+    /// Fail unless every entity is here
+    fn check_entities(&self, ids: &[EntityId]) -> Result<(), String> {
+        match ids.iter().find(|id| !self.entities.contains_key(id)) {
+            Some(id) => Err(format!("Entity with ID {:?} not found", id)),
+            None => Ok(()),
+        }
+    }
+
+    fn remove_entities(&self, ids: &[EntityId]) {
+        let mut removed: HashMap<EntityType, HashSet<EntityId>> = HashMap::new();
+        for &id in ids {
+            let Some((_, entity)) = self.entities.remove(&id) else { continue };
+            self.property_stats.remove(&entity);
+            self.track_expiry(id, Some(&entity), None);
+            removed.entry(entity.entity_type).or_default().insert(id);
+            self.detach_edges(id);
+        }
+
+        for (entity_type, doomed) in removed {
+            if let Some(mut collection) = self.collections.get_mut(&entity_type) {
+                collection.retain(|entity_id| !doomed.contains(entity_id));
             }
+        }
+    }
 
-            // Note: We should also clean up edges referencing this entity
-            // For now, just removing the entity
-            Ok(())
-        } else {
-            Err(format!("Entity with ID {:?} not found", id))
+    /// Delete every entity in a collection and their edges, keeping the
+    /// (now empty) collection; returns how many entities were removed
+    ///
+    /// The id list is taken in one step rather than compacted per entity.
+    pub fn truncate_collection(&self, entity_type: &str) -> Result<usize, String> {
+        let Some(ids) = self.collections.get(entity_type).map(|ids| ids.clone()) else {
+            return Ok(0);
+        };
+        self.persist(|storage| {
+            let mut batch = storage.batch();
+            ids.iter().try_for_each(|&id| batch.delete_entity(id))?;
+            batch.commit()
+        })?;
+
+        let ids = match self.collections.get_mut(entity_type) {
+            Some(mut ids) => std::mem::take(&mut *ids),
+            None => return Ok(0),
+        };
+        for &id in &ids {
+            if let Some((_, entity)) = self.entities.remove(&id) {
+                self.track_expiry(id, Some(&entity), None);
+                self.detach_edges(id);
+            }
+        }
+        self.property_stats.replace(entity_type, HashMap::new());
+
+        Ok(ids.len())
+    }
+
+    /// Delete a collection with its entities and their edges, returning how
+    /// many entities it held (or `None` if there is no such collection)
+    pub fn drop_collection(&self, entity_type: &str) -> Result<Option<usize>, String> {
+        let removed = self.truncate_collection(entity_type)?;
+        Ok(self.collections.remove(entity_type).map(|_| removed))
+    }
+
+    /// Remove entities that now live on another node, with their outgoing edges
+    ///
+    /// Unlike [`Graph::delete_entities`], edges into them from entities that
+    /// stay are kept: those belong with their sources.
+    pub fn evict_entities(&self, ids: &[EntityId]) -> Result<(), String> {
+        self.check_entities(ids)?;
+        self.persist(|storage| {
+            let mut batch = storage.batch();
+            for &id in ids {
+                batch.remove_entity(id)?;
+                for (_, edge_id) in self.get_outgoing_neighbors(id, None) {
+                    if let Some(edge) = self.edges.get(&edge_id) {
+                        batch.delete_edge(&edge)?;
+                    }
+                }
+            }
+            batch.commit()
+        })?;
+
+        let mut removed: HashMap<EntityType, HashSet<EntityId>> = HashMap::new();
+        for &id in ids {
+            let Some((_, entity)) = self.entities.remove(&id) else { continue };
+            self.property_stats.remove(&entity);
+            self.track_expiry(id, Some(&entity), None);
+            removed.entry(entity.entity_type).or_default().insert(id);
+
+            if let Some((_, outgoing)) = self.outgoing.remove(&id) {
+                for (edge_type, neighbors) in outgoing {
+                    for (target, edge_id) in neighbors {
+                        if let Some((_, edge)) = self.edges.remove(&edge_id) {
+                            self.uncount_edge(&edge.edge_type);
+                            self.track_edge_expiry(edge_id, None);
+                        }
+                        Self::unlink(&self.incoming, target, &edge_type, edge_id);
+                    }
+                }
+            }
+        }
+
+        for (entity_type, evicted) in removed {
+            if let Some(mut collection) = self.collections.get_mut(&entity_type) {
+                collection.retain(|entity_id| !evicted.contains(entity_id));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove an entity's adjacency lists and the edges in them
+    fn detach_edges(&self, id: EntityId) {
+        // Detach outgoing edges from the targets' incoming lists
+        if let Some((_, outgoing)) = self.outgoing.remove(&id) {
+            for (edge_type, neighbors) in outgoing {
+                for (target, edge_id) in neighbors {
+                    if self.edges.remove(&edge_id).is_some() {
+                        self.uncount_edge(&edge_type);
+                        self.track_edge_expiry(edge_id, None);
+                    }
+                    Self::unlink(&self.incoming, target, &edge_type, edge_id);
+                }
+            }
+        }
+
+        // Detach incoming edges from the sources' outgoing lists
+        if let Some((_, incoming)) = self.incoming.remove(&id) {
+            for (edge_type, neighbors) in incoming {
+                for (source, edge_id) in neighbors {
+                    if self.edges.remove(&edge_id).is_some() {
+                        self.uncount_edge(&edge_type);
+                        self.track_edge_expiry(edge_id, None);
+                    }
+                    Self::unlink(&self.outgoing, source, &edge_type, edge_id);
+                }
+            }
+        }
+    }
+
+    /// Remove one edge from an entity's adjacency list
+    fn unlink(adjacency: &AdjacencyList, entity_id: EntityId, edge_type: &str, edge_id: EdgeId) {
+        if let Some(lists) = adjacency.get(&entity_id) {
+            if let Some(mut neighbors) = lists.get_mut(edge_type) {
+                neighbors.retain(|&(_, id)| id != edge_id);
+            }
+        }
+    }
+
+    fn count_edge(&self, edge_type: &str) {
+        *self.edge_type_counts.entry(edge_type.to_string()).or_insert(0) += 1;
+    }
+
+    fn uncount_edge(&self, edge_type: &str) {
+        if let Some(mut count) = self.edge_type_counts.get_mut(edge_type) {
+            *count = count.saturating_sub(1);
         }
+        self.edge_type_counts.remove_if(edge_type, |_, count| *count == 0);
+    }
+
+    /// Get every edge (outgoing and incoming) that touches an entity
+    pub fn get_entity_edges(&self, id: EntityId) -> Vec<Edge> {
+        let mut seen = std::collections::HashSet::new();
+
+        self.get_outgoing_neighbors(id, None)
+            .into_iter()
+            .chain(self.get_incoming_neighbors(id, None))
+            .filter(|(_, edge_id)| seen.insert(*edge_id))
+            .filter_map(|(_, edge_id)| self.get_edge(edge_id))
+            .collect()
     }
 
     /// Add a new edge
@@ -241,32 +743,37 @@ impl Graph {
         target: EntityId,
         edge_type: EdgeType,
         properties: Properties,
-    ) -> Option<EdgeId> {
+    ) -> Result<EdgeId, String> {
         // Check that source and target exist
-        if !self.entities.contains_key(&source) || !self.entities.contains_key(&target) {
-            return None;
+        for id in [source, target] {
+            if !self.entities.contains_key(&id) {
+                return Err(format!("Entity with ID {:?} not found", id));
+            }
         }
 
         let id = EdgeId::new(self.next_edge_id.fetch_add(1, Ordering::SeqCst));
         let edge = Edge::new(id, source, target, edge_type.clone(), properties);
 
+        self.persist(|storage| storage.put_edge(&edge))?;
+        self.track_edge_expiry(id, Some(&edge));
         self.edges.insert(id, edge);
+        self.count_edge(&edge_type);
 
         // Update outgoing adjacency list
         let outgoing_entry = self.outgoing.get(&source).unwrap();
         outgoing_entry
             .entry(edge_type.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push((target, id));
 
         // Update incoming adjacency list
         let incoming_entry = self.incoming.get(&target).unwrap();
         incoming_entry
             .entry(edge_type)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push((source, id));
 
-        Some(id)
+        Ok(id)
     }
 
     /// Get edge by ID
@@ -274,6 +781,82 @@ impl Graph {
         self.edges.get(&id).map(|e| e.clone())
     }
 
+    /// Delete an edge by ID, unlinking it from both endpoints
+    ///
+    /// The edge's pheromone trail is stored on the edge, so it goes too.
+    pub fn delete_edge(&self, id: EdgeId) -> Result<(), String> {
+        let edge = self.get_edge(id)
+            .ok_or_else(|| format!("Edge with ID {:?} not found", id))?;
+        self.persist(|storage| storage.delete_edge(&edge))?;
+        self.unlink_edge(id);
+        Ok(())
+    }
+
+    fn unlink_edge(&self, id: EdgeId) {
+        if let Some((_, edge)) = self.edges.remove(&id) {
+            Self::unlink(&self.outgoing, edge.source, &edge.edge_type, id);
+            Self::unlink(&self.incoming, edge.target, &edge.edge_type, id);
+            self.uncount_edge(&edge.edge_type);
+            self.track_edge_expiry(id, None);
+        }
+    }
+
+    /// Replace an existing edge's properties
+    pub fn update_edge_properties(&self, id: EdgeId, properties: Properties) -> Result<(), String> {
+        let edge = self.get_edge(id)
+            .ok_or_else(|| format!("Edge with ID {:?} not found", id))?;
+        let edge = Edge { properties, ..edge };
+        self.persist(|storage| storage.put_edge(&edge))?;
+        self.replace_edge_properties(edge);
+        Ok(())
+    }
+
+    fn replace_edge_properties(&self, updated: Edge) {
+        if let Some(mut edge) = self.edges.get_mut(&updated.id) {
+            edge.properties = updated.properties;
+            self.track_edge_expiry(updated.id, Some(&edge));
+        }
+    }
+
+    /// Apply a committing transaction's writes: entities stored (added or
+    /// replaced), edges written or (when `None`) deleted, then entities deleted
+    ///
+    /// Storage gets them all in one batch before the graph changes, so a
+    /// failed write leaves both as they were. An edge already here has only
+    /// its properties replaced.
+    pub fn apply_writes(&self, stored: Vec<Entity>, edges: Vec<(EdgeId, Option<Edge>)>, deleted: &[EntityId]) -> Result<(), String> {
+        self.check_entities(deleted)?;
+        self.persist(|storage| {
+            let mut batch = storage.batch();
+            for entity in &stored {
+                batch.put_entity(entity)?;
+            }
+            for (id, edge) in &edges {
+                match (edge, self.edges.get(id)) {
+                    (Some(edge), _) => batch.put_edge(edge)?,
+                    (None, Some(old)) => batch.delete_edge(&old)?,
+                    (None, None) => {}
+                }
+            }
+            for &id in deleted {
+                batch.delete_entity(id)?;
+            }
+            batch.commit()
+        })?;
+
+        self.store_entities(stored);
+        for (id, edge) in edges {
+            let exists = self.edges.contains_key(&id);
+            match edge {
+                None => self.unlink_edge(id),
+                Some(edge) if exists => self.replace_edge_properties(edge),
+                Some(edge) => self.link_edge(edge),
+            }
+        }
+        self.remove_entities(deleted);
+        Ok(())
+    }
+
     /// Get outgoing neighbors of an entity
     pub fn get_outgoing_neighbors(
         &self,
@@ -322,6 +905,167 @@ impl Graph {
         result
     }
 
+    /// Number of edges leaving an entity, of one type or all of them
+    ///
+    /// Reads the adjacency list's length; no neighbor is fetched.
+    pub fn out_degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        count_edges(&self.outgoing, entity_id, edge_type)
+    }
+
+    /// Number of edges arriving at an entity, of one type or all of them
+    pub fn in_degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        count_edges(&self.incoming, entity_id, edge_type)
+    }
+
+    /// Number of edges touching an entity either way; a self-loop counts twice
+    pub fn degree(&self, entity_id: EntityId, edge_type: Option<&str>) -> usize {
+        self.out_degree(entity_id, edge_type) + self.in_degree(entity_id, edge_type)
+    }
+
+    /// A handle for counting edges without holding the graph
+    pub fn degrees(&self) -> Degrees {
+        Degrees {
+            outgoing: self.outgoing.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+
+    /// Pheromone strength of an edge
+    pub fn get_edge_pheromone(&self, id: EdgeId) -> Option<f32> {
+        self.edges.get(&id).map(|edge| edge.pheromone.strength())
+    }
+
+    /// The `k` outgoing neighbors reached over the strongest-pheromone edges
+    pub fn get_strongest_neighbors(
+        &self,
+        entity_id: EntityId,
+        edge_type: Option<&str>,
+        k: usize,
+    ) -> Vec<(EntityId, EdgeId)> {
+        self.strongest_edges(self.get_outgoing_neighbors(entity_id, edge_type), k)
+    }
+
+    /// The `k` of `candidates` whose edges carry the strongest pheromone
+    ///
+    /// Strongest first; ties go to the older edge. Only the top `k` are
+    /// sorted, so this stays cheap on high-degree nodes.
+    pub fn strongest_edges(&self, candidates: Vec<(EntityId, EdgeId)>, k: usize) -> Vec<(EntityId, EdgeId)> {
+        Self::strongest_by(candidates, k, |edge_id| self.get_edge_pheromone(edge_id))
+    }
+
+    /// [`Graph::strongest_edges`] with the strengths `pheromone` gives;
+    /// edges it has none for are left out
+    pub fn strongest_by(
+        candidates: Vec<(EntityId, EdgeId)>,
+        k: usize,
+        pheromone: impl Fn(EdgeId) -> Option<f32>,
+    ) -> Vec<(EntityId, EdgeId)> {
+        let mut ranked: Vec<(f32, EntityId, EdgeId)> = candidates
+            .into_iter()
+            .filter_map(|(neighbor, edge_id)| Some((pheromone(edge_id)?, neighbor, edge_id)))
+            .collect();
+
+        let order = |a: &(f32, EntityId, EdgeId), b: &(f32, EntityId, EdgeId)| {
+            b.0.total_cmp(&a.0).then(a.2 .0.cmp(&b.2 .0))
+        };
+        if k < ranked.len() {
+            ranked.select_nth_unstable_by(k, order);
+            ranked.truncate(k);
+        }
+        ranked.sort_by(order);
+
+        ranked.into_iter().map(|(_, neighbor, edge_id)| (neighbor, edge_id)).collect()
+    }
+
+    /// Shortest path from `source` to `target` along outgoing edges
+    ///
+    /// Bidirectional breadth-first search: each step expands whichever of
+    /// the forward and backward frontiers is smaller, so a high-degree hub
+    /// on one side doesn't dominate the search, and visited sets keep every
+    /// entity from being expanded twice. Returns the entity ids in path
+    /// order, `[source]` when source and target are the same, or `None`
+    /// when no path of at most `max_depth` hops exists.
+    pub fn shortest_path(
+        &self,
+        source: EntityId,
+        target: EntityId,
+        edge_type: Option<&str>,
+        max_depth: usize,
+    ) -> Option<Vec<EntityId>> {
+        if source == target {
+            return self.entities.contains_key(&source).then(|| vec![source]);
+        }
+
+        // Each side maps a visited entity to the one it was reached from
+        let mut forward = HashMap::from([(source, source)]);
+        let mut backward = HashMap::from([(target, target)]);
+        let mut forward_frontier = vec![source];
+        let mut backward_frontier = vec![target];
+        let mut depth = 0;
+
+        while depth < max_depth && !forward_frontier.is_empty() && !backward_frontier.is_empty() {
+            depth += 1;
+            let expand_forward = forward_frontier.len() <= backward_frontier.len();
+            let (frontier, visited, other) = if expand_forward {
+                (&mut forward_frontier, &mut forward, &backward)
+            } else {
+                (&mut backward_frontier, &mut backward, &forward)
+            };
+
+            // Both frontiers are complete levels, so the first meeting
+            // point found is on a shortest path
+            let mut next = Vec::new();
+            let mut meeting = None;
+            'expand: for id in std::mem::take(frontier) {
+                let neighbors = if expand_forward {
+                    self.get_outgoing_neighbors(id, edge_type)
+                } else {
+                    self.get_incoming_neighbors(id, edge_type)
+                };
+                for (neighbor, _) in neighbors {
+                    if visited.contains_key(&neighbor) {
+                        continue;
+                    }
+                    visited.insert(neighbor, id);
+                    if other.contains_key(&neighbor) {
+                        meeting = Some(neighbor);
+                        break 'expand;
+                    }
+                    next.push(neighbor);
+                }
+            }
+            *frontier = next;
+
+            if let Some(meeting) = meeting {
+                return Some(Self::join_path(&forward, &backward, meeting));
+            }
+        }
+
+        None
+    }
+
+    /// Stitch the two halves of a bidirectional search at `meeting`
+    fn join_path(
+        forward: &HashMap<EntityId, EntityId>,
+        backward: &HashMap<EntityId, EntityId>,
+        meeting: EntityId,
+    ) -> Vec<EntityId> {
+        let mut path = vec![meeting];
+        let mut id = meeting;
+        while forward[&id] != id {
+            id = forward[&id];
+            path.push(id);
+        }
+        path.reverse();
+
+        let mut id = meeting;
+        while backward[&id] != id {
+            id = backward[&id];
+            path.push(id);
+        }
+        path
+    }
+
     /// Scan all entities in a collection (table scan)
     pub fn scan_collection(&self, entity_type: &str) -> Vec<Entity> {
         if let Some(entity_ids) = self.collections.get(entity_type) {
@@ -334,6 +1078,149 @@ impl Graph {
         }
     }
 
+    /// Scan a collection, copying only the named properties of each entity
+    pub fn scan_collection_projected(&self, entity_type: &str, properties: &[&str]) -> Vec<Entity> {
+        self.collection_ids(entity_type)
+            .into_iter()
+            .filter_map(|id| self.get_entity_projected(id, properties))
+            .collect()
+    }
+
+    /// Stream a collection's entities without copying them
+    ///
+    /// Only the id list is snapshotted up front; entities deleted mid-scan
+    /// are skipped.
+    pub fn iter_collection<'a>(&'a self, entity_type: &str) -> impl Iterator<Item = EntityRef<'a>> + 'a {
+        self.collection_ids(entity_type)
+            .into_iter()
+            .filter_map(move |id| self.get_entity_ref(id))
+    }
+
+    /// Ids of a collection's entities, in insertion order
+    pub fn collection_ids(&self, entity_type: &str) -> Vec<EntityId> {
+        self.collections
+            .get(entity_type)
+            .map(|ids| ids.clone())
+            .unwrap_or_default()
+    }
+
+    /// Have a collection's entities expire `ttl` after they're created,
+    /// unless they set [`EXPIRES_AT_PROPERTY`]
+    pub fn set_collection_ttl(&self, entity_type: &str, ttl: Duration) {
+        let mut expiries = self.expiries.lock().unwrap();
+        if self.collection_ttls.insert(entity_type.to_string(), ttl).is_none() {
+            let created = self
+                .iter_collection(entity_type)
+                .filter(|entity| !matches!(entity.properties.get(EXPIRES_AT_PROPERTY), Some(PropertyValue::Timestamp(_))))
+                .map(|entity| (entity.created_millis() as i64, entity.id))
+                .collect::<Vec<_>>();
+            expiries.track_collection(entity_type, created);
+        }
+    }
+
+    /// Stop a collection's entities expiring, returning the TTL they had
+    pub fn clear_collection_ttl(&self, entity_type: &str) -> Option<Duration> {
+        let mut expiries = self.expiries.lock().unwrap();
+        expiries.untrack_collection(entity_type);
+        self.collection_ttls.remove(entity_type).map(|(_, ttl)| ttl)
+    }
+
+    pub fn collection_ttl(&self, entity_type: &str) -> Option<Duration> {
+        self.collection_ttls.get(entity_type).map(|ttl| *ttl)
+    }
+
+    /// When an entity expires, in milliseconds since the Unix epoch: its
+    /// [`EXPIRES_AT_PROPERTY`] timestamp, else its collection's TTL after
+    /// it was created
+    pub fn expires_at(&self, entity: &Entity) -> Option<i64> {
+        if let Some(PropertyValue::Timestamp(at)) = entity.properties.get(EXPIRES_AT_PROPERTY) {
+            return Some(*at);
+        }
+        let ttl = self.collection_ttl(&entity.entity_type)?;
+        Some((entity.created_millis() as i64).saturating_add(ttl.as_millis() as i64))
+    }
+
+    /// Whether an entity has expired by `now` (milliseconds since the Unix epoch)
+    pub fn is_expired(&self, entity: &Entity, now: i64) -> bool {
+        self.expires_at(entity).is_some_and(|at| at <= now)
+    }
+
+    /// Up to `limit` entities expired by `now`, read from the expiry index
+    pub fn expired_entities(&self, now: i64, limit: usize) -> Vec<EntityId> {
+        self.expiries
+            .lock()
+            .unwrap()
+            .expired_entities(now, limit, |collection| self.collection_ttl(collection))
+    }
+
+    /// When an edge expires: its [`EXPIRES_AT_PROPERTY`] timestamp
+    pub fn edge_expires_at(edge: &Edge) -> Option<i64> {
+        match edge.properties.get(EXPIRES_AT_PROPERTY) {
+            Some(PropertyValue::Timestamp(at)) => Some(*at),
+            _ => None,
+        }
+    }
+
+    /// Whether an edge has expired by `now`
+    pub fn is_edge_expired(&self, id: EdgeId, now: i64) -> bool {
+        self.expiring_edges.load(Ordering::Relaxed) > 0 && self.expiries.lock().unwrap().edge_expired(id, now)
+    }
+
+    /// Up to `limit` edges expired by `now`
+    pub fn expired_edges(&self, now: i64, limit: usize) -> Vec<EdgeId> {
+        if self.expiring_edges.load(Ordering::Relaxed) == 0 {
+            return Vec::new();
+        }
+        self.expiries.lock().unwrap().expired_edges(now, limit)
+    }
+
+    /// Where an entity belongs in the expiry index, if it expires
+    fn expiry_slot(&self, entity: &Entity) -> Option<ExpirySlot> {
+        if let Some(PropertyValue::Timestamp(at)) = entity.properties.get(EXPIRES_AT_PROPERTY) {
+            return Some(ExpirySlot::Deadline(*at));
+        }
+        self.collection_ttls
+            .contains_key(&entity.entity_type)
+            .then(|| ExpirySlot::Created(entity.entity_type.clone(), entity.created_millis() as i64))
+    }
+
+    /// Move an entity in the expiry index from where `old` had it to where `new` has it
+    fn track_expiry(&self, id: EntityId, old: Option<&Entity>, new: Option<&Entity>) {
+        let old = old.and_then(|entity| self.expiry_slot(entity));
+        let new = new.and_then(|entity| self.expiry_slot(entity));
+        self.move_expiry(id, old, new);
+    }
+
+    fn move_expiry(&self, id: EntityId, old: Option<ExpirySlot>, new: Option<ExpirySlot>) {
+        if old == new {
+            return;
+        }
+        let mut expiries = self.expiries.lock().unwrap();
+        if let Some(slot) = &old {
+            expiries.remove(id, slot);
+        }
+        if let Some(slot) = new {
+            expiries.insert(id, slot);
+        }
+    }
+
+    /// Record an edge's expiry time, forgetting it when the edge is gone (`None`)
+    fn track_edge_expiry(&self, id: EdgeId, edge: Option<&Edge>) {
+        let deadline = edge.and_then(Self::edge_expires_at);
+        if deadline.is_none() && self.expiring_edges.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut expiries = self.expiries.lock().unwrap();
+        expiries.set_edge(id, deadline);
+        self.expiring_edges.store(expiries.edge_count() as u64, Ordering::Relaxed);
+    }
+
+    /// Borrow an entity without copying it
+    pub fn get_entity_ref(&self, id: EntityId) -> Option<EntityRef<'_>> {
+        self.entity_reads.fetch_add(1, Ordering::Relaxed);
+        self.entities.get(&id)
+    }
+
     /// Evaporate pheromones on all edges (called periodically)
     pub fn evaporate_pheromones(&self) {
         for mut edge in self.edges.iter_mut() {
@@ -341,6 +1228,19 @@ impl Graph {
         }
     }
 
+    /// Every collection and how many entities it holds, by name
+    pub fn list_collections(&self) -> Vec<(EntityType, usize)> {
+        let mut collections: Vec<(EntityType, usize)> =
+            self.collections.iter().map(|ids| (ids.key().clone(), ids.len())).collect();
+        collections.sort();
+        collections
+    }
+
+    /// Number of entities, without computing the rest of the stats
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
+
     /// Get statistics
     pub fn stats(&self) -> GraphStats {
         GraphStats {
@@ -348,9 +1248,63 @@ impl Graph {
             edge_count: self.edges.len(),
             collection_count: self.collections.len(),
             avg_pheromone: self.average_pheromone(),
+            collections: self
+                .collections
+                .iter()
+                .map(|ids| {
+                    let stats = CollectionStats {
+                        entity_count: ids.len(),
+                        properties: self.property_stats.get(ids.key()),
+                    };
+                    (ids.key().clone(), stats)
+                })
+                .collect(),
+            edge_type_counts: self.edge_type_counts.iter().map(|count| (count.key().clone(), *count)).collect(),
         }
     }
 
+    /// Rebuild the property statistics of one collection (or all of them)
+    /// from a sample of up to [`ANALYZE_SAMPLE_SIZE`] entities
+    ///
+    /// The sample is spread evenly over the collection.
+    pub fn analyze(&self, collection: Option<&str>) -> Vec<AnalyzeReport> {
+        let mut names: Vec<EntityType> = match collection {
+            Some(name) => vec![name.to_string()],
+            None => self.collections.iter().map(|ids| ids.key().clone()).collect(),
+        };
+        names.sort();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let ids = self.collection_ids(&name);
+                let step = ids.len().div_ceil(ANALYZE_SAMPLE_SIZE).max(1);
+                let mut sample = Sample::default();
+                for id in ids.iter().step_by(step) {
+                    if let Some(entity) = self.entities.get(id) {
+                        sample.add(&entity.properties);
+                    }
+                }
+
+                let sampled = sample.len();
+                let properties = sample.finish(ids.len());
+                let report = AnalyzeReport {
+                    collection: name.clone(),
+                    entities: ids.len(),
+                    sampled,
+                    properties: properties.len(),
+                };
+                self.property_stats.replace(&name, properties);
+                report
+            })
+            .collect()
+    }
+
+    /// Ids of all entities, in no particular order
+    pub fn entity_ids(&self) -> Vec<EntityId> {
+        self.entities.iter().map(|e| *e.key()).collect()
+    }
+
     /// Get all entities (for backup)
     pub fn get_all_entities(&self) -> Vec<Entity> {
         self.entities.iter().map(|e| e.value().clone()).collect()
@@ -361,49 +1315,82 @@ impl Graph {
         self.edges.iter().map(|e| e.value().clone()).collect()
     }
 
+    /// Export the filtered graph as GraphML
+    pub fn export_graphml<W: std::io::Write>(&self, writer: W, filter: &ExportFilter) -> Result<(), String> {
+        Subgraph::select(self, filter).write(GraphFormat::GraphML, writer)
+    }
+
+    /// Export the filtered graph as Graphviz DOT
+    pub fn export_dot<W: std::io::Write>(&self, writer: W, filter: &ExportFilter) -> Result<(), String> {
+        Subgraph::select(self, filter).write(GraphFormat::Dot, writer)
+    }
+
     /// Insert entity with specific ID (for restore)
-    pub fn insert_entity_with_id(&self, entity: Entity) {
+    pub fn insert_entity_with_id(&self, entity: Entity) -> Result<(), String> {
         let id = entity.id;
         let entity_type = entity.entity_type.clone();
 
         // Insert into entities map
-        self.entities.insert(id, entity);
+        self.persist(|storage| storage.put_entity(&entity))?;
+        self.property_stats.insert(&entity);
+        let slot = self.expiry_slot(&entity);
+        let old = self.entities.insert(id, entity);
+        if let Some(old) = &old {
+            self.property_stats.remove(old);
+        }
+        self.move_expiry(id, old.as_ref().and_then(|old| self.expiry_slot(old)), slot);
 
         // Add to collections
         let mut collections = self.collections.entry(entity_type.clone())
-            .or_insert_with(Vec::new);
+            .or_default();
         if !collections.contains(&id) {
             collections.push(id);
         }
+        drop(collections);
+
+        // Make sure adjacency lists exist so new edges can attach
+        self.outgoing.entry(id).or_default();
+        self.incoming.entry(id).or_default();
 
         // Update next ID if necessary
         if id.0 >= self.next_entity_id.load(std::sync::atomic::Ordering::SeqCst) {
             self.next_entity_id.store(id.0 + 1, std::sync::atomic::Ordering::SeqCst);
         }
+        Ok(())
     }
 
     /// Insert edge with specific ID (for restore)
-    pub fn insert_edge_with_id(&self, edge: Edge) {
+    pub fn insert_edge_with_id(&self, edge: Edge) -> Result<(), String> {
+        self.persist(|storage| storage.put_edge(&edge))?;
+        self.link_edge(edge);
+        Ok(())
+    }
+
+    fn link_edge(&self, edge: Edge) {
         let id = edge.id;
         let from = edge.source;
         let to = edge.target;
         let edge_type = edge.edge_type.clone();
 
         // Insert into edges map
-        self.edges.insert(id, edge);
+        self.track_edge_expiry(id, Some(&edge));
+        if let Some(old) = self.edges.insert(id, edge) {
+            self.uncount_edge(&old.edge_type);
+        }
+        self.count_edge(&edge_type);
 
         // Add to outgoing neighbors
         self.outgoing.entry(from)
-            .or_insert_with(DashMap::new)
+            .or_default()
             .entry(edge_type.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push((to, id));
 
         // Add to incoming neighbors
         self.incoming.entry(to)
-            .or_insert_with(DashMap::new)
+            .or_default()
             .entry(edge_type)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push((from, id));
 
         // Update next ID if necessary
@@ -413,7 +1400,7 @@ impl Graph {
     }
 
     /// Create entity with properties (alias for add_entity)
-    pub fn create_entity(&self, entity_type: String, properties: Properties) -> EntityId {
+    pub fn create_entity(&self, entity_type: String, properties: Properties) -> Result<EntityId, String> {
         self.add_entity(entity_type, properties)
     }
 
@@ -433,12 +1420,44 @@ impl Default for Graph {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GraphStats {
     pub entity_count: usize,
     pub edge_count: usize,
     pub collection_count: usize,
     pub avg_pheromone: f32,
+    /// Statistics of each collection
+    #[serde(default)]
+    pub collections: HashMap<String, CollectionStats>,
+    /// Number of edges of each type
+    #[serde(default)]
+    pub edge_type_counts: HashMap<String, usize>,
+}
+
+impl GraphStats {
+    /// Statistics of a collection, if it has (or had) any entities
+    pub fn for_collection(&self, name: &str) -> Option<&CollectionStats> {
+        self.collections.get(name)
+    }
+
+    /// Entities a scan of `collection` reads
+    ///
+    /// Stats without per-collection figures fall back to the whole graph.
+    pub fn collection_rows(&self, collection: &str) -> usize {
+        match self.for_collection(collection) {
+            Some(stats) => stats.entity_count,
+            None if self.collections.is_empty() => self.entity_count,
+            None => 0,
+        }
+    }
+
+    /// Edges of any of `edge_types` (of any type when empty)
+    pub fn edges_of_types(&self, edge_types: &[String]) -> usize {
+        if edge_types.is_empty() || self.edge_type_counts.is_empty() {
+            return self.edge_count;
+        }
+        edge_types.iter().filter_map(|edge_type| self.edge_type_counts.get(edge_type)).sum()
+    }
 }
 
 #[cfg(test)]
@@ -453,7 +1472,7 @@ mod tests {
         props.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
         props.insert("age".to_string(), PropertyValue::Int(28));
 
-        let id = graph.add_entity("User".to_string(), props);
+        let id = graph.add_entity("User".to_string(), props).unwrap();
 
         let entity = graph.get_entity(id).unwrap();
         assert_eq!(entity.entity_type, "User");
@@ -465,8 +1484,8 @@ mod tests {
     fn test_edge_creation() {
         let graph = Graph::new();
 
-        let alice = graph.add_entity("User".to_string(), Properties::new());
-        let bob = graph.add_entity("User".to_string(), Properties::new());
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
 
         let edge_id = graph.add_edge(
             alice,
@@ -485,17 +1504,218 @@ mod tests {
     fn test_graph_traversal() {
         let graph = Graph::new();
 
-        let alice = graph.add_entity("User".to_string(), Properties::new());
-        let bob = graph.add_entity("User".to_string(), Properties::new());
-        let carol = graph.add_entity("User".to_string(), Properties::new());
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let carol = graph.add_entity("User".to_string(), Properties::new()).unwrap();
 
-        graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new());
-        graph.add_edge(alice, carol, "FOLLOWS".to_string(), Properties::new());
+        graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        graph.add_edge(alice, carol, "FOLLOWS".to_string(), Properties::new()).unwrap();
 
         let neighbors = graph.get_outgoing_neighbors(alice, Some("FOLLOWS"));
         assert_eq!(neighbors.len(), 2);
     }
 
+    #[test]
+    fn test_strongest_neighbors() {
+        let graph = Graph::new();
+
+        let hub = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let mut by_strength = Vec::new();
+        for (i, strength) in [2.0, 7.5, 0.5, 7.5, 4.0].into_iter().enumerate() {
+            let fan = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+            let mut edge = Edge::new(EdgeId::new(100 + i as u64), hub, fan, "FOLLOWS".to_string(), Properties::new());
+            edge.pheromone = Pheromone::new(strength);
+            graph.insert_edge_with_id(edge).unwrap();
+            by_strength.push(fan);
+        }
+        graph.add_edge(hub, by_strength[2], "BLOCKS".to_string(), Properties::new()).unwrap();
+
+        assert_eq!(graph.get_edge_pheromone(EdgeId::new(101)), Some(7.5));
+        assert_eq!(graph.get_edge_pheromone(EdgeId::new(999)), None);
+
+        // Ties go to the older edge
+        let top: Vec<EntityId> = graph
+            .get_strongest_neighbors(hub, Some("FOLLOWS"), 3)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(top, vec![by_strength[1], by_strength[3], by_strength[4]]);
+
+        assert_eq!(graph.get_strongest_neighbors(hub, Some("FOLLOWS"), 10).len(), 5);
+        assert_eq!(graph.get_strongest_neighbors(hub, None, 6).len(), 6);
+        assert!(graph.get_strongest_neighbors(hub, Some("FOLLOWS"), 0).is_empty());
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let graph = Graph::new();
+
+        let users: Vec<EntityId> = (0..5)
+            .map(|_| graph.add_entity("User".to_string(), Properties::new()).unwrap())
+            .collect();
+        let follow = |from: usize, to: usize| {
+            graph.add_edge(users[from], users[to], "FOLLOWS".to_string(), Properties::new()).unwrap();
+        };
+
+        // 0 -> 1 -> 2 -> 3, plus a shortcut 0 -> 4 -> 3
+        follow(0, 1);
+        follow(1, 2);
+        follow(2, 3);
+        follow(0, 4);
+        follow(4, 3);
+        graph.add_edge(users[1], users[3], "BLOCKS".to_string(), Properties::new()).unwrap();
+
+        assert_eq!(graph.shortest_path(users[0], users[3], Some("FOLLOWS"), 6), Some(vec![users[0], users[4], users[3]]));
+        assert_eq!(graph.shortest_path(users[0], users[3], None, 6).map(|p| p.len()), Some(3));
+        assert_eq!(graph.shortest_path(users[1], users[3], None, 6), Some(vec![users[1], users[3]]));
+        assert_eq!(graph.shortest_path(users[1], users[3], Some("FOLLOWS"), 1), None);
+        assert_eq!(graph.shortest_path(users[2], users[2], Some("FOLLOWS"), 0), Some(vec![users[2]]));
+
+        // Edges are followed in their direction only
+        assert_eq!(graph.shortest_path(users[3], users[0], None, 6), None);
+    }
+
+    #[test]
+    fn test_shortest_path_through_hub() {
+        let graph = Graph::new();
+
+        let source = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let hub = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let target = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        graph.add_edge(source, hub, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        graph.add_edge(hub, target, "FOLLOWS".to_string(), Properties::new()).unwrap();
+
+        // Thousands of fans followed by the hub, all following each other in a ring
+        let fans: Vec<EntityId> = (0..5_000)
+            .map(|_| graph.add_entity("User".to_string(), Properties::new()).unwrap())
+            .collect();
+        for (i, fan) in fans.iter().enumerate() {
+            graph.add_edge(hub, *fan, "FOLLOWS".to_string(), Properties::new()).unwrap();
+            graph.add_edge(*fan, fans[(i + 1) % fans.len()], "FOLLOWS".to_string(), Properties::new()).unwrap();
+        }
+
+        assert_eq!(graph.shortest_path(source, target, Some("FOLLOWS"), 6), Some(vec![source, hub, target]));
+        assert_eq!(graph.shortest_path(fans[0], source, Some("FOLLOWS"), 6), None);
+    }
+
+    #[test]
+    fn test_delete_entity_detaches_edges() {
+        let graph = Graph::new();
+
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let carol = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+
+        graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        graph.add_edge(carol, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        graph.add_edge(bob, bob, "NOTES".to_string(), Properties::new()).unwrap();
+
+        graph.delete_entity(bob).unwrap();
+
+        assert!(graph.get_entity(bob).is_none());
+        assert_eq!(graph.scan_collection("User").len(), 2);
+        assert_eq!(graph.stats().edge_count, 0);
+        assert!(graph.get_outgoing_neighbors(alice, None).is_empty());
+        assert!(graph.get_outgoing_neighbors(carol, None).is_empty());
+        assert!(graph.delete_entity(bob).is_err());
+
+        // A batch with a missing entity fails before deleting any of it
+        assert!(graph.delete_entities(&[alice, bob]).is_err());
+        assert!(graph.get_entity(alice).is_some());
+        assert!(graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).is_err());
+    }
+
+    #[test]
+    fn test_delete_edge_unlinks_both_endpoints() {
+        let graph = Graph::new();
+
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+
+        let first = graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        let second = graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+
+        graph.delete_edge(first).unwrap();
+
+        assert!(graph.get_edge(first).is_none());
+        assert_eq!(graph.get_outgoing_neighbors(alice, Some("FOLLOWS")), vec![(bob, second)]);
+        assert_eq!(graph.get_incoming_neighbors(bob, Some("FOLLOWS")), vec![(alice, second)]);
+        assert_eq!(graph.stats().edge_count, 1);
+        assert!(graph.delete_edge(first).is_err());
+    }
+
+    #[test]
+    fn test_degrees_follow_edge_changes() {
+        let graph = Graph::new();
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let loner = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let degrees = graph.degrees();
+
+        let follows = graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+        graph.add_edge(alice, bob, "LIKES".to_string(), Properties::new()).unwrap();
+        graph.add_edge(alice, alice, "FOLLOWS".to_string(), Properties::new()).unwrap();
+
+        assert_eq!(graph.out_degree(alice, Some("FOLLOWS")), 2);
+        assert_eq!(graph.out_degree(alice, None), 3);
+        assert_eq!(graph.in_degree(bob, None), 2);
+        assert_eq!(graph.in_degree(bob, Some("KNOWS")), 0);
+        // The self-loop counts once each way
+        assert_eq!(graph.degree(alice, Some("FOLLOWS")), 3);
+        assert_eq!(graph.degree(loner, None), 0);
+        assert_eq!(graph.degree(EntityId::new(999), None), 0);
+
+        // A handle taken earlier sees later changes
+        graph.delete_edge(follows).unwrap();
+        assert_eq!(degrees.out_degree(alice, Some("FOLLOWS")), 1);
+        assert_eq!(degrees.in_degree(bob, None), 1);
+
+        graph.delete_entity(alice).unwrap();
+        assert_eq!(degrees.in_degree(bob, None), 0);
+    }
+
+    #[test]
+    fn test_truncate_and_drop_collection() {
+        let graph = Graph::new();
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let post = graph.add_entity("Post".to_string(), Properties::new()).unwrap();
+        graph.add_edge(alice, post, "WROTE".to_string(), Properties::new()).unwrap();
+        graph.add_edge(post, bob, "MENTIONS".to_string(), Properties::new()).unwrap();
+        assert_eq!(graph.list_collections(), vec![("Post".to_string(), 1), ("User".to_string(), 2)]);
+
+        // Edges into and out of the truncated collection go with it
+        assert_eq!(graph.truncate_collection("User").unwrap(), 2);
+        assert_eq!(graph.list_collections(), vec![("Post".to_string(), 1), ("User".to_string(), 0)]);
+        assert!(graph.get_entity(bob).is_none());
+        assert_eq!(graph.degree(post, None), 0);
+        assert_eq!(graph.stats().edge_count, 0);
+        assert_eq!(graph.truncate_collection("Missing").unwrap(), 0);
+
+        assert_eq!(graph.drop_collection("User").unwrap(), Some(0));
+        assert_eq!(graph.drop_collection("Post").unwrap(), Some(1));
+        assert_eq!(graph.drop_collection("Post").unwrap(), None);
+        assert!(graph.list_collections().is_empty());
+        assert_eq!(graph.entity_count(), 0);
+    }
+
+    #[test]
+    fn test_update_edge_properties() {
+        let graph = Graph::new();
+
+        let alice = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let bob = graph.add_entity("User".to_string(), Properties::new()).unwrap();
+        let edge_id = graph.add_edge(alice, bob, "FOLLOWS".to_string(), Properties::new()).unwrap();
+
+        let mut props = Properties::new();
+        props.insert("weight".to_string(), PropertyValue::Int(5));
+        graph.update_edge_properties(edge_id, props).unwrap();
+
+        let edge = graph.get_edge(edge_id).unwrap();
+        assert_eq!(edge.properties.get("weight"), Some(&PropertyValue::Int(5)));
+        assert!(graph.update_edge_properties(EdgeId::new(99), Properties::new()).is_err());
+    }
+
     #[test]
     fn test_pheromone_reinforcement() {
         let mut edge = Edge::new(
@@ -0,0 +1,421 @@
+//! Graph export for visualization
+//!
+//! Writes a graph, or a filtered part of it, as GraphML (for yEd, Gephi or
+//! Cytoscape) or Graphviz DOT. DOT output is meant for small graphs; cut a
+//! large one down with an `ExportFilter` first.
+
+use crate::graph::{Edge, Entity, Graph};
+use crate::types::{EntityId, PropertyValue};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io::Write;
+
+/// Output format of a graph export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphML,
+    Dot,
+}
+
+/// Which part of a graph to export
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    /// Only entities of these collections (all when empty)
+    pub collections: Vec<String>,
+    /// Only edges of these types (all when empty)
+    pub edge_types: Vec<String>,
+    /// Stop after this many nodes
+    pub max_nodes: Option<usize>,
+    /// Only entities within this many edges (either way) of the seed entity
+    pub seed: Option<(EntityId, usize)>,
+}
+
+impl ExportFilter {
+    fn allows_entity(&self, entity: &Entity) -> bool {
+        self.collections.is_empty() || self.collections.contains(&entity.entity_type)
+    }
+
+    fn allows_edge(&self, edge: &Edge) -> bool {
+        self.edge_types.is_empty() || self.edge_types.contains(&edge.edge_type)
+    }
+}
+
+/// Entities and the edges between them, ready to write
+pub struct Subgraph {
+    pub entities: Vec<Entity>,
+    pub edges: Vec<Edge>,
+}
+
+impl Subgraph {
+    /// Select the part of a graph a filter asks for
+    ///
+    /// From a seed the neighbourhood is walked breadth first, so `max_nodes`
+    /// keeps the nearest entities; otherwise it keeps the lowest IDs.
+    pub fn select(graph: &Graph, filter: &ExportFilter) -> Self {
+        let limit = filter.max_nodes.unwrap_or(usize::MAX);
+
+        let entities = match filter.seed {
+            Some((seed, depth)) => {
+                let mut entities = Vec::new();
+                let mut seen = HashSet::new();
+                let mut queue = VecDeque::new();
+
+                if let Some(entity) = graph.get_entity(seed).filter(|e| filter.allows_entity(e)) {
+                    seen.insert(seed);
+                    queue.push_back((seed, 0));
+                    entities.push(entity);
+                }
+
+                while let Some((id, hops)) = queue.pop_front() {
+                    if hops == depth {
+                        continue;
+                    }
+
+                    let neighbors = graph
+                        .get_outgoing_neighbors(id, None)
+                        .into_iter()
+                        .chain(graph.get_incoming_neighbors(id, None));
+                    for (neighbor, edge_id) in neighbors {
+                        if entities.len() >= limit {
+                            break;
+                        }
+                        if seen.contains(&neighbor) || !graph.get_edge(edge_id).is_some_and(|e| filter.allows_edge(&e)) {
+                            continue;
+                        }
+                        if let Some(entity) = graph.get_entity(neighbor).filter(|e| filter.allows_entity(e)) {
+                            seen.insert(neighbor);
+                            queue.push_back((neighbor, hops + 1));
+                            entities.push(entity);
+                        }
+                    }
+                }
+
+                entities.sort_by_key(|e| e.id.0);
+                entities
+            }
+            None => {
+                // Only the ids are gathered; entities are copied until there are enough
+                let mut ids = if filter.collections.is_empty() {
+                    graph.entity_ids()
+                } else {
+                    filter.collections.iter().flat_map(|c| graph.collection_ids(c)).collect()
+                };
+                ids.sort_unstable_by_key(|id| id.0);
+                ids.dedup();
+                ids.into_iter()
+                    .filter_map(|id| graph.get_entity(id))
+                    .filter(|e| filter.allows_entity(e))
+                    .take(limit)
+                    .collect()
+            }
+        };
+
+        Self::connect(graph, entities, filter)
+    }
+
+    /// The given entities (those the filter allows) and the edges between them
+    pub fn of_entities<I>(graph: &Graph, ids: I, filter: &ExportFilter) -> Self
+    where
+        I: IntoIterator<Item = EntityId>,
+    {
+        let ids: HashSet<EntityId> = ids.into_iter().collect();
+        let mut entities: Vec<Entity> = ids
+            .into_iter()
+            .filter_map(|id| graph.get_entity(id))
+            .filter(|e| filter.allows_entity(e))
+            .collect();
+        entities.sort_by_key(|e| e.id.0);
+        entities.truncate(filter.max_nodes.unwrap_or(usize::MAX));
+
+        Self::connect(graph, entities, filter)
+    }
+
+    fn connect(graph: &Graph, entities: Vec<Entity>, filter: &ExportFilter) -> Self {
+        let ids: HashSet<EntityId> = entities.iter().map(|e| e.id).collect();
+
+        let mut edges: Vec<Edge> = entities
+            .iter()
+            .flat_map(|e| graph.get_outgoing_neighbors(e.id, None))
+            .filter(|(target, _)| ids.contains(target))
+            .filter_map(|(_, edge_id)| graph.get_edge(edge_id))
+            .filter(|e| filter.allows_edge(e))
+            .collect();
+        edges.sort_by_key(|e| e.id.0);
+
+        Subgraph { entities, edges }
+    }
+
+    /// Write in the given format
+    pub fn write<W: Write>(&self, format: GraphFormat, writer: W) -> Result<(), String> {
+        match format {
+            GraphFormat::GraphML => self.write_graphml(writer),
+            GraphFormat::Dot => self.write_dot(writer),
+        }
+        .map_err(|e| format!("Failed to write graph: {}", e))
+    }
+
+    /// Write as GraphML: nodes labelled with their collection, properties as data keys
+    pub fn write_graphml<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        let node_keys = data_keys("n", self.entities.iter().map(|e| &e.properties));
+        let edge_keys = data_keys("e", self.edges.iter().map(|e| &e.properties));
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(w, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        writeln!(w, r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#)?;
+        writeln!(w, r#"  <key id="type" for="edge" attr.name="type" attr.type="string"/>"#)?;
+        for (kind, keys) in [("node", &node_keys), ("edge", &edge_keys)] {
+            for (name, (id, attr_type)) in keys.iter() {
+                writeln!(
+                    w,
+                    r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+                    id,
+                    kind,
+                    xml_escape(name),
+                    attr_type
+                )?;
+            }
+        }
+        writeln!(w, r#"  <graph id="G" edgedefault="directed">"#)?;
+
+        for entity in &self.entities {
+            writeln!(w, r#"    <node id="n{}">"#, entity.id.0)?;
+            writeln!(w, r#"      <data key="label">{}</data>"#, xml_escape(&entity.entity_type))?;
+            write_graphml_data(&mut w, &node_keys, &entity.properties)?;
+            writeln!(w, "    </node>")?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                w,
+                r#"    <edge id="e{}" source="n{}" target="n{}">"#,
+                edge.id.0, edge.source.0, edge.target.0
+            )?;
+            writeln!(w, r#"      <data key="type">{}</data>"#, xml_escape(&edge.edge_type))?;
+            write_graphml_data(&mut w, &edge_keys, &edge.properties)?;
+            writeln!(w, "    </edge>")?;
+        }
+
+        writeln!(w, "  </graph>")?;
+        writeln!(w, "</graphml>")?;
+        w.flush()
+    }
+
+    /// Write as a Graphviz digraph, properties listed in node and edge labels
+    pub fn write_dot<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "digraph G {{")?;
+
+        for entity in &self.entities {
+            let title = format!("{} {}", entity.entity_type, entity.id.0);
+            writeln!(w, "  n{} [label=\"{}\"];", entity.id.0, dot_label(&title, &entity.properties))?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                w,
+                "  n{} -> n{} [label=\"{}\"];",
+                edge.source.0,
+                edge.target.0,
+                dot_label(&edge.edge_type, &edge.properties)
+            )?;
+        }
+
+        writeln!(w, "}}")?;
+        w.flush()
+    }
+}
+
+/// GraphML key (ID and attribute type) of every property name, IDs starting with `prefix`
+///
+/// A property whose values are all ints is a long, ints and floats a double,
+/// bools a boolean; anything else is a string.
+fn data_keys<'a, I>(prefix: &str, all_properties: I) -> BTreeMap<String, (String, &'static str)>
+where
+    I: Iterator<Item = &'a crate::types::Properties>,
+{
+    let mut types: BTreeMap<String, &'static str> = BTreeMap::new();
+    for properties in all_properties {
+        for (name, value) in properties {
+            let value_type = match value {
+                PropertyValue::Null => continue,
+                PropertyValue::Bool(_) => "boolean",
+                PropertyValue::Int(_) => "long",
+                PropertyValue::Float(_) => "double",
+                PropertyValue::String(_)
+                | PropertyValue::Bytes(_)
+                | PropertyValue::List(_)
+                | PropertyValue::Map(_)
+                | PropertyValue::Timestamp(_) => "string",
+            };
+            let merged = match (types.get(name).copied(), value_type) {
+                (None, t) => t,
+                (Some(a), b) if a == b => a,
+                (Some("long"), "double") | (Some("double"), "long") => "double",
+                _ => "string",
+            };
+            types.insert(name.clone(), merged);
+        }
+    }
+
+    types
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, attr_type))| (name, (format!("{}{}", prefix, i), attr_type)))
+        .collect()
+}
+
+fn write_graphml_data<W: Write>(
+    w: &mut W,
+    keys: &BTreeMap<String, (String, &'static str)>,
+    properties: &crate::types::Properties,
+) -> std::io::Result<()> {
+    let mut names: Vec<&String> = properties.keys().collect();
+    names.sort();
+
+    for name in names {
+        if let (Some((id, _)), Some(text)) = (keys.get(name), display_value(&properties[name])) {
+            writeln!(w, r#"      <data key="{}">{}</data>"#, id, xml_escape(&text))?;
+        }
+    }
+    Ok(())
+}
+
+/// A DOT label: the title, then one `name: value` line per property
+fn dot_label(title: &str, properties: &crate::types::Properties) -> String {
+    let mut lines: Vec<String> = properties
+        .iter()
+        .filter_map(|(name, value)| display_value(value).map(|text| format!("{}: {}", name, text)))
+        .collect();
+    lines.sort();
+
+    std::iter::once(title.to_string())
+        .chain(lines)
+        .map(|line| line.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+        .collect::<Vec<_>>()
+        .join("\\n")
+}
+
+/// Text of a property value; nulls are left out
+fn display_value(value: &PropertyValue) -> Option<String> {
+    match value {
+        PropertyValue::Null => None,
+        PropertyValue::Bool(b) => Some(b.to_string()),
+        PropertyValue::Int(n) => Some(n.to_string()),
+        PropertyValue::Float(f) => Some(f.to_string()),
+        PropertyValue::String(s) => Some(s.clone()),
+        PropertyValue::Bytes(bytes) => Some(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+        PropertyValue::List(_) | PropertyValue::Map(_) => Some(nested_text(value)),
+        PropertyValue::Timestamp(ms) => Some(crate::types::format_timestamp(*ms)),
+    }
+}
+
+/// Text of a list or document, nulls included
+fn nested_text(value: &PropertyValue) -> String {
+    match value {
+        PropertyValue::Null => "null".to_string(),
+        PropertyValue::String(s) => format!("{:?}", s),
+        PropertyValue::List(items) => {
+            let items: Vec<String> = items.iter().map(nested_text).collect();
+            format!("[{}]", items.join(", "))
+        }
+        PropertyValue::Map(map) => {
+            let mut entries: Vec<String> = map.iter().map(|(k, v)| format!("{}: {}", k, nested_text(v))).collect();
+            entries.sort();
+            format!("{{{}}}", entries.join(", "))
+        }
+        other => display_value(other).unwrap_or_default(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Properties;
+
+    fn chain(length: usize) -> (Graph, Vec<EntityId>) {
+        let graph = Graph::new();
+        let ids: Vec<EntityId> = (0..length)
+            .map(|_| graph.add_entity("Nodes".to_string(), Properties::new()).unwrap())
+            .collect();
+        for pair in ids.windows(2) {
+            graph.add_edge(pair[0], pair[1], "NEXT".to_string(), Properties::new()).unwrap();
+        }
+        (graph, ids)
+    }
+
+    #[test]
+    fn test_seed_depth_limits_subgraph() {
+        let (graph, ids) = chain(10);
+
+        let filter = ExportFilter {
+            seed: Some((ids[5], 2)),
+            ..Default::default()
+        };
+        let subgraph = Subgraph::select(&graph, &filter);
+        let selected: Vec<EntityId> = subgraph.entities.iter().map(|e| e.id).collect();
+        assert_eq!(selected, ids[3..=7].to_vec());
+        assert_eq!(subgraph.edges.len(), 4);
+
+        let filter = ExportFilter {
+            max_nodes: Some(3),
+            ..filter
+        };
+        assert_eq!(Subgraph::select(&graph, &filter).entities.len(), 3);
+    }
+
+    #[test]
+    fn test_filters_collections_and_edge_types() {
+        let (graph, ids) = chain(3);
+        let other = graph.add_entity("Others".to_string(), Properties::new()).unwrap();
+        graph.add_edge(ids[0], other, "NEXT".to_string(), Properties::new()).unwrap();
+        graph.add_edge(ids[0], ids[2], "SKIP".to_string(), Properties::new()).unwrap();
+
+        let filter = ExportFilter {
+            collections: vec!["Nodes".to_string()],
+            edge_types: vec!["NEXT".to_string()],
+            ..Default::default()
+        };
+        let subgraph = Subgraph::select(&graph, &filter);
+        assert_eq!(subgraph.entities.len(), 3);
+        assert!(subgraph.edges.iter().all(|e| e.edge_type == "NEXT" && e.target != other));
+        assert_eq!(subgraph.edges.len(), 2);
+
+        // Without a seed, max_nodes keeps the lowest ids
+        let filter = ExportFilter {
+            max_nodes: Some(2),
+            ..filter
+        };
+        let selected: Vec<EntityId> = Subgraph::select(&graph, &filter).entities.iter().map(|e| e.id).collect();
+        assert_eq!(selected, ids[..2].to_vec());
+    }
+
+    #[test]
+    fn test_graphml_key_types() {
+        let mut a = Properties::new();
+        a.insert("n".to_string(), PropertyValue::Int(1));
+        a.insert("x".to_string(), PropertyValue::Int(1));
+        let mut b = Properties::new();
+        b.insert("n".to_string(), PropertyValue::Int(2));
+        b.insert("x".to_string(), PropertyValue::Float(0.5));
+        b.insert("tag".to_string(), PropertyValue::String("<b>".to_string()));
+
+        let keys = data_keys("n", [a, b].iter());
+        assert_eq!(keys["n"].1, "long");
+        assert_eq!(keys["x"].1, "double");
+        assert_eq!(keys["tag"].1, "string");
+        assert_eq!(xml_escape("<b>"), "&lt;b&gt;");
+    }
+}
@@ -0,0 +1,480 @@
+//! Collection import and export
+//!
+//! Streams a collection's entities to or from a file, one record at a time:
+//! - JSON Lines: one JSON object per entity, its ID under the reserved `_id`
+//! - CSV: a header row, then one row per entity
+//!
+//! CSV cells carry their type by quoting: strings are always written quoted,
+//! so an unquoted cell reads back as a bool, integer or float. CSV can't tell
+//! a null from a missing property; both are written as an empty cell and read
+//! back as a missing property. Bytes are written as quoted hex, and lists
+//! and documents as quoted JSON; all read back as strings.
+//!
+//! JSON arrays and objects read as lists and documents. Bytes are written as
+//! an array of numbers, so they read back as a list of integers.
+//!
+//! Timestamps are written in both formats as ISO-8601 strings, and read back
+//! as strings unless the collection's schema coerces timestamps.
+//!
+//! On import `_id` is ignored and entities get new IDs.
+
+use crate::types::{Properties, PropertyValue};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Reserved field holding the entity ID in exported records
+pub const ID_FIELD: &str = "_id";
+
+/// File format of an import or export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataFormat {
+    JsonLines,
+    Csv,
+}
+
+impl DataFormat {
+    /// Format named in a statement (`JSONL` or `CSV`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "JSONL" | "NDJSON" => Some(DataFormat::JsonLines),
+            "CSV" => Some(DataFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Format implied by a file's extension (`.jsonl`, `.ndjson` or `.csv`)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(Self::from_name)
+    }
+}
+
+/// What an import does with a row it can't use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Leave the row out and carry on
+    Skip,
+    /// Stop the import; batches already imported stay
+    Abort,
+}
+
+/// Import settings
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Rows inserted per batch; at most this many are held in memory
+    pub batch_size: usize,
+    /// Apply the collection schema's defaults and checks to each row
+    pub validate: bool,
+    /// Handling of malformed rows and rows the schema rejects
+    pub on_mismatch: MismatchPolicy,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            batch_size: 1000,
+            validate: true,
+            on_mismatch: MismatchPolicy::Abort,
+        }
+    }
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+    pub batches: usize,
+}
+
+/// Writes entities as records of one format
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: DataFormat,
+    columns: Vec<String>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Start a JSON Lines export
+    pub fn json_lines(writer: W) -> Self {
+        RecordWriter {
+            writer,
+            format: DataFormat::JsonLines,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Start a CSV export with the given columns, writing the header row
+    pub fn csv(mut writer: W, columns: Vec<String>) -> Result<Self, String> {
+        let header: Vec<String> = columns.iter().map(|c| csv_quote(c)).collect();
+        writeln!(writer, "{}", header.join(",")).map_err(write_error)?;
+
+        Ok(RecordWriter {
+            writer,
+            format: DataFormat::Csv,
+            columns,
+        })
+    }
+
+    /// Write one entity
+    pub fn write(&mut self, id: u64, properties: &Properties) -> Result<(), String> {
+        match self.format {
+            DataFormat::JsonLines => {
+                let mut object: serde_json::Map<String, serde_json::Value> =
+                    properties.iter().map(|(k, v)| (k.clone(), to_json(v))).collect();
+                object.insert(ID_FIELD.to_string(), serde_json::Value::from(id));
+
+                serde_json::to_writer(&mut self.writer, &object)
+                    .map_err(|e| format!("Failed to write record: {}", e))?;
+                writeln!(self.writer).map_err(write_error)
+            }
+            DataFormat::Csv => {
+                let cells: Vec<String> = self
+                    .columns
+                    .iter()
+                    .map(|column| match properties.get(column) {
+                        _ if column == ID_FIELD => id.to_string(),
+                        Some(value) => to_csv_cell(value),
+                        None => String::new(),
+                    })
+                    .collect();
+                writeln!(self.writer, "{}", cells.join(",")).map_err(write_error)
+            }
+        }
+    }
+
+    /// Flush the underlying writer
+    pub fn finish(mut self) -> Result<(), String> {
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+/// Reads records of one format as entity properties
+///
+/// Yields `Err` for a read failure, which ends the import, and `Ok(Err(..))`
+/// for a malformed row, which is subject to the import's mismatch policy.
+/// `_id` is dropped from every record.
+pub struct RecordReader<R: BufRead> {
+    reader: R,
+    format: DataFormat,
+    header: Option<Vec<String>>,
+    line: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R, format: DataFormat) -> Self {
+        RecordReader {
+            reader,
+            format,
+            header: None,
+            line: 0,
+        }
+    }
+
+    /// Line number the last record started on
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    fn read_line(&mut self, buf: &mut String) -> Result<bool, String> {
+        buf.clear();
+        let read = self
+            .reader
+            .read_line(buf)
+            .map_err(|e| format!("Failed to read line {}: {}", self.line + 1, e))?;
+        Ok(read > 0)
+    }
+
+    fn next_json(&mut self) -> Result<Option<Result<Properties, String>>, String> {
+        let mut buf = String::new();
+        loop {
+            if !self.read_line(&mut buf)? {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !buf.trim().is_empty() {
+                break;
+            }
+        }
+
+        let object = match serde_json::from_str::<serde_json::Value>(&buf) {
+            Ok(serde_json::Value::Object(object)) => object,
+            Ok(_) => return Ok(Some(Err("Record is not a JSON object".to_string()))),
+            Err(e) => return Ok(Some(Err(format!("Invalid JSON: {}", e)))),
+        };
+
+        let mut properties = Properties::new();
+        for (key, value) in object {
+            if key == ID_FIELD {
+                continue;
+            }
+            properties.insert(key, from_json(&value));
+        }
+        Ok(Some(Ok(properties)))
+    }
+
+    /// Read one CSV record, which spans lines while a quoted cell is open
+    fn next_csv_cells(&mut self) -> Result<Option<Result<Vec<CsvCell>, String>>, String> {
+        let mut buf = String::new();
+        loop {
+            if !self.read_line(&mut buf)? {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !buf.trim().is_empty() {
+                break;
+            }
+        }
+
+        let start = self.line;
+        let mut record = std::mem::take(&mut buf);
+        loop {
+            match parse_csv_record(&record) {
+                Some(cells) => return Ok(Some(Ok(cells))),
+                None => {
+                    if !self.read_line(&mut buf)? {
+                        return Ok(Some(Err(format!("Unterminated quoted cell starting on line {}", start))));
+                    }
+                    self.line += 1;
+                    record.push_str(&buf);
+                }
+            }
+        }
+    }
+
+    fn next_csv(&mut self) -> Result<Option<Result<Properties, String>>, String> {
+        if self.header.is_none() {
+            let header = match self.next_csv_cells()? {
+                Some(Ok(cells)) => cells.into_iter().map(|cell| cell.text).collect(),
+                Some(Err(e)) => return Err(format!("Invalid CSV header: {}", e)),
+                None => return Ok(None),
+            };
+            self.header = Some(header);
+        }
+
+        let cells = match self.next_csv_cells()? {
+            Some(Ok(cells)) => cells,
+            Some(Err(e)) => return Ok(Some(Err(e))),
+            None => return Ok(None),
+        };
+
+        let header = self.header.as_ref().unwrap();
+        if cells.len() != header.len() {
+            return Ok(Some(Err(format!("Expected {} cells, got {}", header.len(), cells.len()))));
+        }
+
+        let properties = header
+            .iter()
+            .zip(cells)
+            .filter(|(column, _)| column.as_str() != ID_FIELD)
+            .filter_map(|(column, cell)| cell.into_value().map(|value| (column.clone(), value)))
+            .collect();
+        Ok(Some(Ok(properties)))
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = Result<Result<Properties, String>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.format {
+            DataFormat::JsonLines => self.next_json(),
+            DataFormat::Csv => self.next_csv(),
+        };
+        record.transpose()
+    }
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write record: {}", e)
+}
+
+fn to_json(value: &PropertyValue) -> serde_json::Value {
+    match value {
+        PropertyValue::Null => serde_json::Value::Null,
+        PropertyValue::Bool(b) => serde_json::Value::Bool(*b),
+        PropertyValue::Int(n) => serde_json::Value::from(*n),
+        // Non-finite floats have no JSON form
+        PropertyValue::Float(f) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        PropertyValue::String(s) => serde_json::Value::String(s.clone()),
+        PropertyValue::Bytes(bytes) => bytes.iter().map(|&b| serde_json::Value::from(b)).collect(),
+        PropertyValue::List(items) => items.iter().map(to_json).collect(),
+        PropertyValue::Map(map) => serde_json::Value::Object(map.iter().map(|(k, v)| (k.clone(), to_json(v))).collect()),
+        PropertyValue::Timestamp(ms) => serde_json::Value::String(crate::types::format_timestamp(*ms)),
+    }
+}
+
+/// A JSON value as a property; arrays are lists and objects documents
+fn from_json(value: &serde_json::Value) -> PropertyValue {
+    match value {
+        serde_json::Value::Null => PropertyValue::Null,
+        serde_json::Value::Bool(b) => PropertyValue::Bool(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => PropertyValue::Int(n),
+            None => n.as_f64().map_or(PropertyValue::Null, PropertyValue::Float),
+        },
+        serde_json::Value::String(s) => PropertyValue::String(s.clone()),
+        serde_json::Value::Array(items) => PropertyValue::List(items.iter().map(from_json).collect()),
+        serde_json::Value::Object(object) => {
+            PropertyValue::Map(object.iter().map(|(k, v)| (k.clone(), from_json(v))).collect())
+        }
+    }
+}
+
+fn to_csv_cell(value: &PropertyValue) -> String {
+    match value {
+        PropertyValue::Null => String::new(),
+        PropertyValue::Bool(b) => b.to_string(),
+        PropertyValue::Int(n) => n.to_string(),
+        // Debug formatting keeps the decimal point on integral floats
+        PropertyValue::Float(f) => format!("{:?}", f),
+        PropertyValue::String(s) => csv_quote(s),
+        PropertyValue::Bytes(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            csv_quote(&hex)
+        }
+        PropertyValue::List(_) | PropertyValue::Map(_) => csv_quote(&to_json(value).to_string()),
+        PropertyValue::Timestamp(ms) => csv_quote(&crate::types::format_timestamp(*ms)),
+    }
+}
+
+fn csv_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// A parsed CSV cell, remembering whether it was quoted
+struct CsvCell {
+    text: String,
+    quoted: bool,
+}
+
+impl CsvCell {
+    /// Quoted cells are strings; unquoted ones are typed, and empty ones missing
+    fn into_value(self) -> Option<PropertyValue> {
+        if self.quoted {
+            return Some(PropertyValue::String(self.text));
+        }
+
+        let text = self.text.trim();
+        if text.is_empty() {
+            None
+        } else if let Ok(b) = text.parse::<bool>() {
+            Some(PropertyValue::Bool(b))
+        } else if let Ok(n) = text.parse::<i64>() {
+            Some(PropertyValue::Int(n))
+        } else if let Ok(f) = text.parse::<f64>() {
+            Some(PropertyValue::Float(f))
+        } else {
+            Some(PropertyValue::String(text.to_string()))
+        }
+    }
+}
+
+/// Split a CSV record into cells, or `None` if a quoted cell is still open
+fn parse_csv_record(record: &str) -> Option<Vec<CsvCell>> {
+    let record = record.strip_suffix('\n').unwrap_or(record);
+    let record = record.strip_suffix('\r').unwrap_or(record);
+
+    let mut cells = Vec::new();
+    let mut cell = CsvCell {
+        text: String::new(),
+        quoted: false,
+    };
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.text.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if cell.text.trim().is_empty() && !cell.quoted => {
+                cell.text.clear();
+                cell.quoted = true;
+                in_quotes = true;
+            }
+            ',' if !in_quotes => {
+                cells.push(std::mem::replace(
+                    &mut cell,
+                    CsvCell {
+                        text: String::new(),
+                        quoted: false,
+                    },
+                ));
+            }
+            c => cell.text.push(c),
+        }
+    }
+
+    if in_quotes {
+        return None;
+    }
+    cells.push(cell);
+    Some(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(input: &str, format: DataFormat) -> Vec<Result<Properties, String>> {
+        RecordReader::new(Cursor::new(input), format).map(|record| record.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_csv_cells_typed_by_quoting() {
+        let records = read_all("\"_id\",a,b,c,d,e,f\n1,42,\"42\",true,1.5,,\"say \"\"hi\"\"\"\n", DataFormat::Csv);
+        let props = records[0].as_ref().unwrap();
+
+        assert_eq!(props.get("a"), Some(&PropertyValue::Int(42)));
+        assert_eq!(props.get("b"), Some(&PropertyValue::String("42".to_string())));
+        assert_eq!(props.get("c"), Some(&PropertyValue::Bool(true)));
+        assert_eq!(props.get("d"), Some(&PropertyValue::Float(1.5)));
+        assert_eq!(props.get("e"), None);
+        assert_eq!(props.get("f"), Some(&PropertyValue::String("say \"hi\"".to_string())));
+        assert!(!props.contains_key(ID_FIELD));
+    }
+
+    #[test]
+    fn test_csv_quoted_cell_spans_lines() {
+        let records = read_all("note,n\n\"two\nlines\",1\nshort,2\n", DataFormat::Csv);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].as_ref().unwrap().get("note"),
+            Some(&PropertyValue::String("two\nlines".to_string()))
+        );
+        assert_eq!(records[1].as_ref().unwrap().get("n"), Some(&PropertyValue::Int(2)));
+    }
+
+    #[test]
+    fn test_malformed_rows_reported_per_row() {
+        let records = read_all("a,b\n1\n2,3\n", DataFormat::Csv);
+        assert!(records[0].is_err());
+        assert!(records[1].is_ok());
+
+        let records = read_all("{\"a\": 1}\n[1, 2]\n\n{\"a\": {\"nested\": [true]}}\n", DataFormat::JsonLines);
+        assert_eq!(records.len(), 3);
+        assert!(records[0].is_ok());
+        assert!(records[1].is_err());
+
+        let mut nested = Properties::new();
+        nested.insert("nested".to_string(), PropertyValue::List(vec![PropertyValue::Bool(true)]));
+        assert_eq!(records[2].as_ref().unwrap().get("a"), Some(&PropertyValue::Map(nested)));
+    }
+
+    #[test]
+    fn test_float_cells_keep_their_type() {
+        for f in [1.0, -0.5, 1e20] {
+            let cell = CsvCell {
+                text: to_csv_cell(&PropertyValue::Float(f)),
+                quoted: false,
+            };
+            assert_eq!(cell.into_value(), Some(PropertyValue::Float(f)));
+        }
+    }
+}
@@ -31,80 +31,194 @@ pub mod btree;
 // Authentication module
 pub mod auth;
 
+// Audit log module
+pub mod audit;
+
 // Connection pool module
 pub mod connection_pool;
 
+// Read/write splitting module
+pub mod read_routing;
+
+// Network server module
+pub mod deed_server;
+
+// TLS transport module
+pub mod tls;
+
 // Replication module
 pub mod replication;
 
 // Backup/restore module
 pub mod backup;
 
+// Archive (cold storage) module
+pub mod archive;
+
+// Statement firewall module
+pub mod firewall;
+
+// Query metrics module
+pub mod query_metrics;
+
+// Collection import/export module
+pub mod import_export;
+
+// Graph visualization export module
+pub mod graph_export;
+
+// On-disk format migration module
+pub mod migration;
+
 // Admin dashboard module
 pub mod admin_dashboard;
 
+// Optimizer statistics module
+pub mod statistics;
+
+// Orderly shutdown module
+pub mod shutdown;
+
+// Change data capture module
+pub mod change_feed;
+
+// System catalog module
+pub mod catalog;
+
+// Structured error module
+pub mod error;
+
+// Memory budget and spill-to-disk module
+pub mod spill;
+
+// TTL expiry index module
+pub mod expiry;
+
 // Distributed database modules
 pub mod distributed_topology;
 pub mod distributed_p2p;
 pub mod distributed_shard;
 pub mod distributed_query;
+pub mod distributed_rebalance;
 pub mod distributed_consensus;
 pub mod distributed_2pc;
 pub mod distributed_partition;
 pub mod distributed_recovery;
 pub mod distributed_metrics;
+pub mod distributed_gossip;
 
 // DQL (Deed Query Language) modules
 pub mod dql_lexer;
 pub mod dql_ast;
 pub mod dql_parser;
 pub mod dql_ir;
+pub mod dql_functions;
 pub mod dql_optimizer;
 pub mod dql_executor;
 
-pub use storage::StorageEngine;
-pub use graph::{Graph, Entity, Edge};
-pub use types::{EntityId, EdgeId, PropertyValue};
+pub use storage::{StorageBatch, StorageEngine};
+pub use graph::{Degrees, Graph, GraphStats, Entity, EntityRef, Edge, EXPIRES_AT_PROPERTY};
+pub use types::{format_timestamp, parse_timestamp, EntityId, EdgeId, PropertyValue};
 pub use schema::{Schema, Field, FieldType, Constraint, SchemaValidator, ValidationError};
 
 // Transaction exports
-pub use transaction::{Transaction, TransactionId, TransactionState, IsolationLevel, TransactionManager, TransactionStats as TxnStats};
+pub use transaction::{Transaction, TransactionId, TransactionState, IsolationLevel, TransactionManager, TransactionStats as TxnStats, DeadlockError, SerializationError};
 pub use mvcc::{EntityVersion, VersionedEntity, MVCCManager};
-pub use wal::{WALEntry, WALManager, WALReader, WALWriter};
+pub use wal::{CheckpointPolicy, SyncMode, WALConfig, WALEntry, WALManager, WALReader, WALWriter};
 
 // Index exports
-pub use btree::{BTreeIndex, IndexManager, IndexKey};
+pub use btree::{BTreeIndex, IndexManager, IndexKey, IndexKind, RebuildPhase, RebuildProgress, RebuildReport};
 
 // Authentication exports
-pub use auth::{AuthManager, User, Session, Role};
+pub use auth::{Access, AuthConfig, AuthManager, User, Session, Role};
+
+// Audit log exports
+pub use audit::{AuditEntry, AuditFilter, AuditLog, AuditOutcome, AuditRetention, AUDIT_COLLECTION};
+
+// System catalog exports
+pub use catalog::{
+    is_catalog_collection, Catalog, CATALOG_COLLECTIONS, COLLECTIONS_COLLECTION, INDEXES_COLLECTION,
+    SCHEMAS_COLLECTION, TRANSACTIONS_COLLECTION,
+};
+
+// Error exports
+pub use error::DeedError;
+
+// Memory budget exports
+pub use spill::{MemoryBudget, DEFAULT_MEMORY_BUDGET};
 
 // Connection pool exports
 pub use connection_pool::{ConnectionPool, PoolConfig, PoolStats, PooledConnectionHandle};
 
+// Read/write splitting exports
+pub use read_routing::{ReadBalancing, ReadReplicas, ReplicaHealth, Route, RoutingConfig, RoutingExecutor, PRIMARY_HINT};
+
+// Network server exports
+pub use deed_server::{DeedClient, DeedServer, ServerConfig, ServerHandle};
+
+// TLS exports
+pub use tls::{certificate_file_fingerprint, certificate_fingerprint, TlsConfig, Transport};
+
 // Replication exports
-pub use replication::{ReplicationManager, ReplicationEntry, ReplicationConfig, NodeRole, ReplicationSeq, SlaveState, ReplicationStats};
+pub use replication::{ReplicationManager, ReplicationEntry, ReplicationConfig, NodeRole, ReplicationSeq, SlaveState, ReplicationStats, ReplicationSnapshot, SnapshotTransfer, SnapshotSource, Pulled};
 
 // Backup/restore exports
-pub use backup::{BackupManager, BackupConfig, BackupMetadata, BackupType};
+pub use backup::{
+    BackupConfig, BackupManager, BackupMetadata, BackupSchedule, BackupScheduler, BackupSnapshot, BackupSource, BackupStatus,
+    BackupType, IndexDefinition, RetentionPolicy, LATEST_BACKUP,
+};
+
+// Archive exports
+pub use archive::{ArchiveManager, ArchivePartition, ArchivedEntity};
+
+// Import/export exports
+pub use import_export::{DataFormat, ImportOptions, ImportReport, MismatchPolicy};
+
+// Re-export graph export types
+pub use graph_export::{ExportFilter, GraphFormat, Subgraph};
+
+// Migration exports
+pub use migration::{DataDirectory, Manifest, MigrationPlan, MigrationProgress, MigrationRegistry, MigrationStep, OpenOutcome, OpenPolicy, PlannedStep};
+
+// Firewall exports
+pub use firewall::{Firewall, FirewallRule, FirewallSubject, FirewallAction, FirewallCondition, FirewallPrincipal, FirewallRejected, FirewallAuditEntry, StatementClass, StatementShape};
+
+// Query metrics exports
+pub use query_metrics::{LatencyHistogram, QueryMetrics, QueryMetricsSnapshot, QuerySample, SlowQuery};
+
+// Statistics exports
+pub use statistics::{AnalyzeReport, CollectionStats, PropertyStats, ANALYZE_SAMPLE_SIZE};
+
+// Shutdown exports
+pub use shutdown::{BackgroundTask, ShutdownReport, ShutdownSignal};
+
+// Change data capture exports
+pub use change_feed::{ChangeEvent, ChangeFeed, ChangeFilter, ChangeKind, ChangeSubscription, OverflowPolicy};
 
 // Admin dashboard exports
-pub use admin_dashboard::{AdminDashboard, DashboardStats, DatabaseStats, AuthStats, TransactionStats};
+pub use admin_dashboard::{
+    AdminDashboard, DashboardConfig, DashboardHandle, DashboardSources, DashboardStats, DatabaseStats, AuthStats,
+    HealthReport, HealthStatus, TransactionStats,
+};
 
 // Distributed database exports
 pub use distributed_topology::{SmallWorldTopology, TopologyConfig, NodeInfo, NodeAddress, NodeId, Connection, ConnectionType, TopologyStatistics};
-pub use distributed_p2p::{P2PNetwork, P2PMessage, P2PConfig, MessageType};
-pub use distributed_shard::{ShardManager, ShardAssignment, ConsistentHash, ShardId};
-pub use distributed_query::{DistributedQueryExecutor, DistributedQueryPlan};
-pub use distributed_consensus::{RaftNode, RaftState, RaftConfig, RaftMessage, RaftStats, Term, LogIndex};
+pub use distributed_p2p::{P2PNetwork, P2PMessage, P2PConfig, MessageType, MessageKind, ShardDataOp};
+pub use distributed_shard::{ShardManager, ShardAssignment, ConsistentHash, KeyHasher, ShardId, RebalanceOperation, RebalanceType, RebalanceProgress};
+pub use distributed_query::{DistributedQueryExecutor, DistributedQueryPlan, DistributedQueryConfig, PartialResults, ShardOwnership};
+pub use distributed_rebalance::{LocalShards, P2PShardTransport, RebalanceConfig, Rebalancer, ShardBatch, ShardDigest, ShardTransport};
+pub use distributed_consensus::{RaftNode, RaftState, RaftConfig, RaftMessage, RaftStats, StateMachine, Term, LogIndex};
 pub use distributed_2pc::{TwoPhaseCommitCoordinator, TwoPhaseCommitParticipant, TwoPhaseCommitMessage, TwoPhaseCommitState, Vote, TwoPhaseCommitStats};
-pub use distributed_partition::{PartitionManager, QuorumManager, ConsistencyLevel, PartitionState, PartitionStats, QuorumStats};
+pub use distributed_partition::{is_cluster_degraded, PartitionManager, QuorumManager, ConsistencyLevel, PartitionState, PartitionStats, QuorumStats, CLUSTER_DEGRADED, LocalReplica, P2PReplicaTransport, ReplicaTransport, Version, VersionedValue};
 pub use distributed_recovery::{FailureRecoveryManager, RecoveryAction, RecoveryState, RecoveryStats};
 pub use distributed_metrics::{DeedMetrics, MetricsServer, MetricsSnapshot};
+pub use distributed_gossip::{GossipConfig, Member, MemberState, Membership, MembershipChange, MembershipListener};
 
 // DQL exports
 pub use dql_parser::Parser as DQLParser;
-pub use dql_executor::{DQLExecutor, QueryResult};
-pub use dql_optimizer::{AntColonyOptimizer, StigmergyCache};
+pub use dql_functions::ScalarFunction;
+pub use dql_executor::{Clock, DQLExecutor, QueryResult, ColumnInfo, Page, PreparedQuery, ParallelConfig, ExpirationConfig, QueryHandle, RowStream};
+pub use dql_optimizer::{AntColonyOptimizer, OptimizerConfig, StigmergyCache};
 
 // Re-export for Python
 pub use ffi::*;
@@ -0,0 +1,471 @@
+//! On-disk Format Migrations
+//!
+//! Coordinates format upgrades for a data directory on open.
+//! - Manifest: format version of each persisted component
+//! - Registry: ordered migration steps between component versions
+//! - Policies: refuse, migrate automatically, or dry-run (report only)
+//! - Safety: touched files are backed up first and restored if a step fails
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Manifest file name inside a data directory
+pub const MANIFEST_FILE: &str = "MANIFEST.json";
+
+/// Component format versions this build reads and writes
+pub const CURRENT_FORMAT_VERSIONS: &[(&str, u32)] = &[
+    ("wal", 1),
+    ("archive", 1),
+    ("firewall", 1),
+    ("audit", 1),
+    ("optimizer", 1),
+    ("replication_log", 1),
+];
+
+/// Directory-level manifest of component format versions
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub versions: BTreeMap<String, u32>,
+}
+
+impl Manifest {
+    /// Manifest for a directory written by this build
+    pub fn current() -> Self {
+        Manifest {
+            versions: CURRENT_FORMAT_VERSIONS
+                .iter()
+                .map(|(component, version)| (component.to_string(), *version))
+                .collect(),
+        }
+    }
+
+    /// Load the manifest from a data directory, if present
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read manifest: {}", e))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse manifest: {}", e))
+    }
+
+    /// Write the manifest to a data directory
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let serialized = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+        std::fs::write(dir.join(MANIFEST_FILE), serialized)
+            .map_err(|e| format!("Failed to write manifest: {}", e))
+    }
+}
+
+/// What to do when opening a directory that needs migration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPolicy {
+    RefuseIfMigrationNeeded,
+    MigrateAutomatically,
+    /// Report the plan without changing anything
+    DryRun,
+}
+
+/// One planned migration step
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedStep {
+    pub name: String,
+    pub component: String,
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Files (relative to the data directory) the step creates, rewrites or removes
+    pub touched_files: Vec<PathBuf>,
+    /// Extra disk space the step needs while running
+    pub extra_bytes: u64,
+    pub estimated_duration: Duration,
+}
+
+/// Migration plan for a data directory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MigrationPlan {
+    pub steps: Vec<PlannedStep>,
+    pub estimated_duration: Duration,
+    /// Backup of touched files plus the steps' extra space
+    pub disk_space_needed: u64,
+}
+
+impl MigrationPlan {
+    /// Plan for a single step
+    pub fn single(step: PlannedStep) -> Self {
+        MigrationPlan {
+            estimated_duration: step.estimated_duration,
+            disk_space_needed: step.extra_bytes,
+            steps: vec![step],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    fn extend(&mut self, other: MigrationPlan) {
+        self.estimated_duration += other.estimated_duration;
+        self.disk_space_needed += other.disk_space_needed;
+        self.steps.extend(other.steps);
+    }
+
+    fn touched_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.steps.iter().flat_map(|s| s.touched_files.clone()).collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+}
+
+/// Progress reported while a step runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationProgress {
+    pub step: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+/// A migration between two versions of one component's on-disk format
+pub trait MigrationStep: Send + Sync {
+    fn name(&self) -> &str;
+    fn component(&self) -> &str;
+    fn source_version(&self) -> u32;
+
+    fn target_version(&self) -> u32 {
+        self.source_version() + 1
+    }
+
+    /// Whether the step applies to a directory at these component versions
+    fn applies_to(&self, versions: &BTreeMap<String, u32>) -> bool {
+        versions.get(self.component()) == Some(&self.source_version())
+    }
+
+    /// Plan the step without changing anything
+    fn estimate(&self, dir: &Path) -> Result<MigrationPlan, String>;
+
+    /// Perform the step
+    fn apply(&self, dir: &Path, progress: &mut dyn FnMut(MigrationProgress)) -> Result<(), String>;
+
+    /// Check the directory after the step
+    fn verify(&self, dir: &Path) -> Result<(), String>;
+}
+
+/// Ordered set of known migration steps
+pub struct MigrationRegistry {
+    steps: Vec<Box<dyn MigrationStep>>,
+    supported: BTreeMap<String, u32>,
+}
+
+impl MigrationRegistry {
+    /// Registry of this build's migrations, targeting the current format versions
+    pub fn builtin() -> Self {
+        MigrationRegistry {
+            steps: Vec::new(),
+            supported: Manifest::current().versions,
+        }
+    }
+
+    /// Empty registry targeting the given component versions
+    pub fn with_versions(supported: BTreeMap<String, u32>) -> Self {
+        MigrationRegistry {
+            steps: Vec::new(),
+            supported,
+        }
+    }
+
+    /// Register a step (steps are tried in registration order)
+    pub fn register(mut self, step: Box<dyn MigrationStep>) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Plan the steps needed to bring `versions` up to the supported versions
+    pub fn plan(&self, dir: &Path, versions: &BTreeMap<String, u32>) -> Result<MigrationPlan, String> {
+        self.refuse_downgrade(versions)?;
+
+        let mut versions = versions.clone();
+        let mut plan = MigrationPlan::default();
+
+        for (component, target) in &self.supported {
+            // Components missing from the manifest are new and start at the current version
+            versions.entry(component.clone()).or_insert(*target);
+
+            while versions[component] < *target {
+                let step = self
+                    .steps
+                    .iter()
+                    .find(|s| s.component() == component && s.applies_to(&versions))
+                    .ok_or_else(|| {
+                        format!(
+                            "No migration path for {} from format v{} to v{}",
+                            component, versions[component], target
+                        )
+                    })?;
+
+                plan.extend(step.estimate(dir)?);
+                versions.insert(component.clone(), step.target_version());
+            }
+        }
+
+        // The pre-migration backup copies every existing touched file
+        plan.disk_space_needed += plan
+            .touched_files()
+            .iter()
+            .filter_map(|f| std::fs::metadata(dir.join(f)).ok())
+            .map(|m| m.len())
+            .sum::<u64>();
+
+        Ok(plan)
+    }
+
+    fn refuse_downgrade(&self, versions: &BTreeMap<String, u32>) -> Result<(), String> {
+        for (component, version) in versions {
+            match self.supported.get(component) {
+                Some(supported) if version > supported => {
+                    return Err(format!(
+                        "Data directory {} format v{} is newer than this build supports (v{}); \
+                         refusing to open. Upgrade Deed instead of downgrading.",
+                        component, version, supported
+                    ));
+                }
+                None => {
+                    return Err(format!(
+                        "Data directory has unknown component '{}' (v{}); refusing to open. \
+                         It was likely written by a newer Deed build.",
+                        component, version
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step_for(&self, planned: &PlannedStep) -> Option<&dyn MigrationStep> {
+        self.steps
+            .iter()
+            .find(|s| s.name() == planned.name && s.component() == planned.component)
+            .map(|s| s.as_ref())
+    }
+}
+
+/// Result of opening a data directory
+#[derive(Debug)]
+pub enum OpenOutcome {
+    Opened(DataDirectory),
+    Migrated {
+        directory: DataDirectory,
+        plan: MigrationPlan,
+        backup_dir: PathBuf,
+    },
+    /// DryRun policy: nothing was changed
+    DryRun(MigrationPlan),
+}
+
+/// An opened data directory at the current format versions
+#[derive(Debug, Clone)]
+pub struct DataDirectory {
+    root: PathBuf,
+    manifest: Manifest,
+}
+
+impl DataDirectory {
+    /// Open a data directory using this build's migrations
+    pub fn open<P: AsRef<Path>>(root: P, policy: OpenPolicy) -> Result<OpenOutcome, String> {
+        Self::open_with(root, policy, &MigrationRegistry::builtin(), &mut |_| {})
+    }
+
+    /// Open a data directory with a specific registry and progress callback
+    pub fn open_with<P: AsRef<Path>>(
+        root: P,
+        policy: OpenPolicy,
+        registry: &MigrationRegistry,
+        progress: &mut dyn FnMut(MigrationProgress),
+    ) -> Result<OpenOutcome, String> {
+        let root = root.as_ref().to_path_buf();
+        create_dir_all(&root)
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+
+        // Directories from before the manifest existed use the formats of that time,
+        // which are the current ones
+        let manifest = match Manifest::load(&root)? {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = Manifest {
+                    versions: registry.supported.clone(),
+                };
+                if policy != OpenPolicy::DryRun {
+                    manifest.save(&root)?;
+                }
+                manifest
+            }
+        };
+
+        let plan = registry.plan(&root, &manifest.versions)?;
+        if plan.is_empty() {
+            return match policy {
+                OpenPolicy::DryRun => Ok(OpenOutcome::DryRun(plan)),
+                _ => Ok(OpenOutcome::Opened(DataDirectory { root, manifest })),
+            };
+        }
+
+        match policy {
+            OpenPolicy::DryRun => Ok(OpenOutcome::DryRun(plan)),
+            OpenPolicy::RefuseIfMigrationNeeded => Err(format!(
+                "Data directory needs migration ({}); reopen with MigrateAutomatically or DryRun",
+                plan.steps
+                    .iter()
+                    .map(|s| format!("{} v{}->v{}", s.component, s.from_version, s.to_version))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            OpenPolicy::MigrateAutomatically => {
+                let (manifest, backup_dir) = run_migration(&root, manifest, &plan, registry, progress)?;
+                Ok(OpenOutcome::Migrated {
+                    directory: DataDirectory { root, manifest },
+                    plan,
+                    backup_dir,
+                })
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// WAL file for this directory
+    pub fn wal_path(&self) -> PathBuf {
+        self.root.join("deed.wal")
+    }
+
+    /// Archive tier directory
+    pub fn archive_dir(&self) -> PathBuf {
+        self.root.join("archive")
+    }
+
+    /// Persisted firewall rules
+    pub fn firewall_path(&self) -> PathBuf {
+        self.root.join("firewall.json")
+    }
+
+    /// Persisted audit log
+    pub fn audit_path(&self) -> PathBuf {
+        self.root.join("audit.log")
+    }
+
+    /// Saved optimizer learning state
+    pub fn optimizer_state_path(&self) -> PathBuf {
+        self.root.join("optimizer.state")
+    }
+
+    /// Replication log segments
+    pub fn replication_dir(&self) -> PathBuf {
+        self.root.join("replication")
+    }
+}
+
+/// Back up touched files, apply and verify each step, rolling back on failure
+fn run_migration(
+    root: &Path,
+    mut manifest: Manifest,
+    plan: &MigrationPlan,
+    registry: &MigrationRegistry,
+    progress: &mut dyn FnMut(MigrationProgress),
+) -> Result<(Manifest, PathBuf), String> {
+    let touched = plan.touched_files();
+    let backup_dir = root.join(format!(".migration-backup-{}", current_timestamp_ms()));
+    let backed_up = backup_files(root, &backup_dir, &touched)?;
+
+    for planned in &plan.steps {
+        let step = registry
+            .step_for(planned)
+            .ok_or_else(|| format!("Migration step {} is not registered", planned.name))?;
+
+        let result = step
+            .apply(root, progress)
+            .and_then(|_| step.verify(root));
+
+        if let Err(e) = result {
+            restore_files(root, &backup_dir, &touched, &backed_up)?;
+            return Err(format!(
+                "Migration step {} failed: {}; directory rolled back from {}",
+                planned.name,
+                e,
+                backup_dir.display()
+            ));
+        }
+
+        manifest.versions.insert(planned.component.clone(), planned.to_version);
+        manifest.save(root)?;
+    }
+
+    Ok((manifest, backup_dir))
+}
+
+/// Copy existing touched files (and the manifest) into the backup directory
+fn backup_files(root: &Path, backup_dir: &Path, touched: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut backed_up = Vec::new();
+    let manifest = PathBuf::from(MANIFEST_FILE);
+
+    for file in touched.iter().chain(std::iter::once(&manifest)) {
+        let source = root.join(file);
+        if !source.is_file() {
+            continue;
+        }
+
+        let target = backup_dir.join(file);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)
+                .map_err(|e| format!("Failed to create migration backup: {}", e))?;
+        }
+        std::fs::copy(&source, &target)
+            .map_err(|e| format!("Failed to back up {}: {}", file.display(), e))?;
+        backed_up.push(file.clone());
+    }
+
+    Ok(backed_up)
+}
+
+/// Put backed-up files back and remove files the migration created
+fn restore_files(root: &Path, backup_dir: &Path, touched: &[PathBuf], backed_up: &[PathBuf]) -> Result<(), String> {
+    for file in touched {
+        let path = root.join(file);
+        if !backed_up.contains(file) && path.exists() {
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Rollback failed to remove {}: {}", file.display(), e))?;
+        }
+    }
+
+    for file in backed_up {
+        let target = root.join(file);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)
+                .map_err(|e| format!("Rollback failed: {}", e))?;
+        }
+        std::fs::copy(backup_dir.join(file), &target)
+            .map_err(|e| format!("Rollback failed to restore {}: {}", file.display(), e))?;
+    }
+
+    Ok(())
+}
+
+fn current_timestamp_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
@@ -1,12 +1,29 @@
 //! MVCC (Multi-Version Concurrency Control)
 //!
 //! Provides snapshot isolation for concurrent transactions without locking.
+//!
+//! A transaction's writes stay in version chains until it commits, when its
+//! final versions are applied to the graph. The chains also keep the
+//! versions an entity or edge had since the oldest open snapshot, so readers
+//! can resolve the version their snapshot sees. Entities and edges without a
+//! chain are current for every reader.
 
-use crate::graph::Entity;
+use crate::graph::{Edge, Entity};
 use crate::transaction::{TransactionId, IsolationLevel};
-use crate::types::EntityId;
+use crate::types::{EdgeId, EntityId};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Transaction ID standing for data written before versioning began
+///
+/// Treated as committed before every snapshot.
+pub const BOOTSTRAP_TXN: TransactionId = 0;
+
+/// Position in the commit order; a snapshot sees commits at or before it
+pub type CommitTimestamp = u64;
 
 /// Version number for entity
 pub type VersionNumber = u64;
@@ -146,28 +163,13 @@ impl VersionedEntity {
     }
 
     /// Garbage collect old versions not visible to any active transaction
+    ///
+    /// The oldest active transaction sees the newest version created before
+    /// it; older ones are visible to no one.
     pub fn garbage_collect(&mut self, min_active_txn: TransactionId) {
-        // Keep versions that might be visible to active transactions
-        // Keep at least one version for recovery
-
-        if self.versions.len() <= 1 {
-            return; // Always keep at least one version
+        if let Some(oldest_needed) = self.versions.iter().rposition(|v| v.created_by_txn < min_active_txn) {
+            self.versions.drain(..oldest_needed);
         }
-
-        let mut keep_count = 0;
-        self.versions.retain(|v| {
-            // Keep if:
-            // 1. Created by active or recent transaction
-            // 2. Might be visible to active transactions
-            let should_keep = v.created_by_txn >= min_active_txn ||
-                              v.deleted_by_txn.map_or(true, |d| d >= min_active_txn) ||
-                              keep_count == 0; // Always keep at least one
-
-            if should_keep {
-                keep_count += 1;
-            }
-            should_keep
-        });
     }
 
     /// Get total number of versions
@@ -176,10 +178,147 @@ impl VersionedEntity {
     }
 }
 
+/// A version of an edge
+#[derive(Debug, Clone)]
+struct EdgeVersion {
+    edge: Edge,
+    created_by_txn: TransactionId,
+    deleted_by_txn: Option<TransactionId>,
+}
+
+/// Who wrote a version, which decides who sees it
+trait Version {
+    fn created_by(&self) -> TransactionId;
+    fn deleted_by(&self) -> Option<TransactionId>;
+}
+
+impl Version for EntityVersion {
+    fn created_by(&self) -> TransactionId {
+        self.created_by_txn
+    }
+
+    fn deleted_by(&self) -> Option<TransactionId> {
+        self.deleted_by_txn
+    }
+}
+
+impl Version for EdgeVersion {
+    fn created_by(&self) -> TransactionId {
+        self.created_by_txn
+    }
+
+    fn deleted_by(&self) -> Option<TransactionId> {
+        self.deleted_by_txn
+    }
+}
+
+/// Version chains of edges, with the chained edges of each endpoint
+#[derive(Debug, Default)]
+struct EdgeChains {
+    chains: HashMap<EdgeId, Vec<EdgeVersion>>,
+    by_entity: HashMap<EntityId, HashSet<EdgeId>>,
+}
+
+impl EdgeChains {
+    fn insert(&mut self, edge_id: EdgeId, versions: Vec<EdgeVersion>) {
+        if let Some(edge) = versions.first().map(|v| &v.edge) {
+            for endpoint in [edge.source, edge.target] {
+                self.by_entity.entry(endpoint).or_default().insert(edge_id);
+            }
+        }
+        self.chains.insert(edge_id, versions);
+    }
+
+    fn remove(&mut self, edge_id: EdgeId) {
+        let Some(versions) = self.chains.remove(&edge_id) else { return };
+        if let Some(edge) = versions.first().map(|v| &v.edge) {
+            for endpoint in [edge.source, edge.target] {
+                if let Some(ids) = self.by_entity.get_mut(&endpoint) {
+                    ids.remove(&edge_id);
+                    if ids.is_empty() {
+                        self.by_entity.remove(&endpoint);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An entity or edge a transaction wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Written {
+    Entity(EntityId),
+    Edge(EdgeId),
+}
+
+/// A chain as it was before a transaction's write (`None`: there was none)
+#[derive(Debug)]
+enum Undo {
+    Entity(EntityId, Option<VersionedEntity>),
+    Edge(EdgeId, Option<Vec<EdgeVersion>>),
+}
+
+/// What undoes a transaction's writes, oldest first
+#[derive(Debug, Default)]
+struct Journal {
+    undo: Vec<Undo>,
+    /// Written since the latest savepoint, whose first undo is enough
+    since_savepoint: HashSet<Written>,
+}
+
+/// A transaction's final versions, applied to the graph when it commits
+///
+/// `None` stands for a delete. Entities and edges are listed in the order
+/// they were first written.
+#[derive(Debug, Clone, Default)]
+pub struct TxnWrites {
+    pub entities: Vec<(EntityId, Option<Entity>)>,
+    pub edges: Vec<(EdgeId, Option<Edge>)>,
+}
+
+impl TxnWrites {
+    /// Whether the transaction changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty() && self.edges.is_empty()
+    }
+}
+
+/// What a reader is allowed to see
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadView {
+    /// The reader's transaction, whose own writes are always visible
+    pub txn_id: Option<TransactionId>,
+    /// Commits at or before this timestamp are visible
+    pub snapshot: CommitTimestamp,
+    /// Whether every transaction's latest writes are visible, committed or
+    /// not (READ UNCOMMITTED)
+    pub uncommitted: bool,
+}
+
+impl ReadView {
+    /// Whether the reader sees what a transaction wrote
+    fn sees(&self, txn_id: TransactionId, commits: &HashMap<TransactionId, CommitTimestamp>) -> bool {
+        txn_id == BOOTSTRAP_TXN
+            || self.uncommitted
+            || self.txn_id == Some(txn_id)
+            || commits.get(&txn_id).is_some_and(|&ts| ts <= self.snapshot)
+    }
+}
+
 /// MVCC Manager - coordinates versioning across all entities
 pub struct MVCCManager {
     /// Minimum active transaction (for garbage collection)
     min_active_txn: AtomicU64,
+    /// Timestamp of the latest commit
+    commit_clock: AtomicU64,
+    /// Commit timestamps of transactions whose versions are still tracked
+    commits: RwLock<HashMap<TransactionId, CommitTimestamp>>,
+    /// Version chains of entities written since the oldest open snapshot
+    chains: RwLock<HashMap<EntityId, VersionedEntity>>,
+    /// Version chains of edges, likewise
+    edges: RwLock<EdgeChains>,
+    /// Undo of each open transaction's writes
+    journals: Mutex<HashMap<TransactionId, Journal>>,
 }
 
 impl MVCCManager {
@@ -187,7 +326,467 @@ impl MVCCManager {
     pub fn new() -> Self {
         MVCCManager {
             min_active_txn: AtomicU64::new(u64::MAX),
+            commit_clock: AtomicU64::new(0),
+            commits: RwLock::new(HashMap::new()),
+            chains: RwLock::new(HashMap::new()),
+            edges: RwLock::new(EdgeChains::default()),
+            journals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Snapshot seeing every transaction committed so far
+    pub fn current_snapshot(&self) -> CommitTimestamp {
+        self.commit_clock.load(Ordering::SeqCst)
+    }
+
+    /// Whether any entity has tracked versions
+    ///
+    /// While none do, the graph is current for every reader.
+    pub fn has_versions(&self) -> bool {
+        !self.chains.read().unwrap().is_empty()
+    }
+
+    /// Whether any entity of a collection has tracked versions
+    pub fn has_versions_in(&self, entity_type: &str) -> bool {
+        self.chains
+            .read()
+            .unwrap()
+            .values()
+            .any(|chain| chain.versions.iter().any(|v| v.entity.entity_type == entity_type))
+    }
+
+    /// Whether any edge has tracked versions
+    pub fn has_edge_versions(&self) -> bool {
+        !self.edges.read().unwrap().chains.is_empty()
+    }
+
+    /// Record a transaction's write to an entity
+    ///
+    /// `before` is the graph's copy (`None` for an insert) and `after` the
+    /// transaction's new copy (`None` for a delete). The graph itself is
+    /// only changed when the transaction commits. Fails if another
+    /// transaction has uncommitted changes to the entity.
+    pub fn record_write(
+        &self,
+        txn_id: TransactionId,
+        entity_id: EntityId,
+        before: Option<&Entity>,
+        after: Option<Entity>,
+    ) -> Result<(), String> {
+        let mut chains = self.chains.write().unwrap();
+        let commits = self.commits.read().unwrap();
+        Self::check_writer(chains.get(&entity_id).map(|c| c.versions.as_slice()), entity_id.0, "entity", txn_id, &commits)?;
+        self.journal(txn_id, Written::Entity(entity_id), || Undo::Entity(entity_id, chains.get(&entity_id).cloned()));
+        Self::write_entity(&mut chains, txn_id, entity_id, before, after);
+        Ok(())
+    }
+
+    /// Take a transaction's hold on an entity before it computes a write
+    ///
+    /// Returns the copy the write applies to: the transaction's own
+    /// version, else the latest committed one (`current`, the graph's copy,
+    /// when the entity has no chain), or `None` if the entity is gone.
+    /// From then on other writers conflict with the transaction, so no
+    /// commit slips in between the read and the write. Fails if another
+    /// transaction has uncommitted changes to the entity.
+    pub fn lock_for_update(
+        &self,
+        txn_id: TransactionId,
+        entity_id: EntityId,
+        current: Option<&Entity>,
+    ) -> Result<Option<Entity>, String> {
+        let mut chains = self.chains.write().unwrap();
+        let commits = self.commits.read().unwrap();
+        Self::check_writer(chains.get(&entity_id).map(|c| c.versions.as_slice()), entity_id.0, "entity", txn_id, &commits)?;
+
+        let latest = match chains.get(&entity_id) {
+            Some(chain) => {
+                let latest = chain.versions.last().expect("version chains are never empty");
+                if latest.created_by_txn == txn_id || !latest.is_live() {
+                    return Ok(latest.is_live().then(|| latest.entity.clone()));
+                }
+                latest.entity.clone()
+            }
+            None => match current {
+                Some(current) => current.clone(),
+                None => return Ok(None),
+            },
+        };
+
+        self.journal(txn_id, Written::Entity(entity_id), || Undo::Entity(entity_id, chains.get(&entity_id).cloned()));
+        Self::write_entity(&mut chains, txn_id, entity_id, current, Some(latest.clone()));
+        Ok(Some(latest))
+    }
+
+    fn write_entity(
+        chains: &mut HashMap<EntityId, VersionedEntity>,
+        txn_id: TransactionId,
+        entity_id: EntityId,
+        before: Option<&Entity>,
+        after: Option<Entity>,
+    ) {
+        let Some(chain) = chains.get_mut(&entity_id) else {
+            match (before, after) {
+                // The graph's copy predates every open snapshot
+                (Some(before), after) => {
+                    chains.insert(entity_id, VersionedEntity::new(entity_id, before.clone(), BOOTSTRAP_TXN));
+                    Self::write_entity(chains, txn_id, entity_id, None, after);
+                }
+                (None, Some(entity)) => {
+                    let mut chain = VersionedEntity::new(entity_id, entity, txn_id);
+                    chain.versions[0].created_at = now_millis();
+                    chains.insert(entity_id, chain);
+                }
+                (None, None) => {}
+            }
+            return;
+        };
+
+        let latest = chain.versions.last_mut().expect("version chains are never empty");
+        match after {
+            // Later writes of the same transaction replace its version
+            Some(entity) if latest.created_by_txn == txn_id && latest.is_live() => latest.entity = entity,
+            Some(entity) => {
+                chain.add_version(entity, txn_id, now_millis());
+            }
+            None if latest.is_live() => latest.mark_deleted(txn_id),
+            None => {}
+        }
+    }
+
+    /// Record a transaction's write to an edge, as [`MVCCManager::record_write`]
+    /// does for entities
+    pub fn record_edge_write(
+        &self,
+        txn_id: TransactionId,
+        edge_id: EdgeId,
+        before: Option<&Edge>,
+        after: Option<Edge>,
+    ) -> Result<(), String> {
+        let mut edges = self.edges.write().unwrap();
+        let commits = self.commits.read().unwrap();
+        Self::check_writer(edges.chains.get(&edge_id).map(Vec::as_slice), edge_id.0, "edge", txn_id, &commits)?;
+        self.journal(txn_id, Written::Edge(edge_id), || Undo::Edge(edge_id, edges.chains.get(&edge_id).cloned()));
+
+        let version = |edge, created_by_txn| EdgeVersion { edge, created_by_txn, deleted_by_txn: None };
+        if !edges.chains.contains_key(&edge_id) {
+            let versions = match (before, after.clone()) {
+                (Some(before), _) => vec![version(before.clone(), BOOTSTRAP_TXN)],
+                (None, Some(edge)) => {
+                    edges.insert(edge_id, vec![version(edge, txn_id)]);
+                    return Ok(());
+                }
+                (None, None) => return Ok(()),
+            };
+            edges.insert(edge_id, versions);
+        }
+
+        let versions = edges.chains.get_mut(&edge_id).expect("chain exists");
+        let latest = versions.last_mut().expect("version chains are never empty");
+        match after {
+            Some(edge) if latest.created_by_txn == txn_id && latest.deleted_by_txn.is_none() => latest.edge = edge,
+            Some(edge) => versions.push(version(edge, txn_id)),
+            None if latest.deleted_by_txn.is_none() => latest.deleted_by_txn = Some(txn_id),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Fail if another transaction has uncommitted changes in a chain
+    fn check_writer<V: Version>(
+        versions: Option<&[V]>,
+        id: u64,
+        kind: &str,
+        txn_id: TransactionId,
+        commits: &HashMap<TransactionId, CommitTimestamp>,
+    ) -> Result<(), String> {
+        match versions.and_then(|versions| Self::other_writer(versions, txn_id, commits)) {
+            Some(writer) => Err(format!(
+                "Write conflict on {} {}: transaction {} has uncommitted changes",
+                kind, id, writer
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Note what undoes a write, unless the chain was already written since
+    /// the transaction's latest savepoint
+    fn journal(&self, txn_id: TransactionId, written: Written, undo: impl FnOnce() -> Undo) {
+        let mut journals = self.journals.lock().unwrap();
+        let journal = journals.entry(txn_id).or_default();
+        if journal.since_savepoint.insert(written) {
+            journal.undo.push(undo());
+        }
+    }
+
+    /// The other transaction, if any, with uncommitted changes to an entity
+    ///
+    /// A write by `txn_id` to the entity would conflict with it.
+    pub fn uncommitted_writer(&self, entity_id: EntityId, txn_id: TransactionId) -> Option<TransactionId> {
+        let chains = self.chains.read().unwrap();
+        let commits = self.commits.read().unwrap();
+        Self::other_writer(&chains.get(&entity_id)?.versions, txn_id, &commits)
+    }
+
+    fn other_writer<V: Version>(
+        versions: &[V],
+        txn_id: TransactionId,
+        commits: &HashMap<TransactionId, CommitTimestamp>,
+    ) -> Option<TransactionId> {
+        let latest = versions.last()?;
+        [Some(latest.created_by()), latest.deleted_by()]
+            .into_iter()
+            .flatten()
+            .find(|&writer| writer != txn_id && writer != BOOTSTRAP_TXN && !commits.contains_key(&writer))
+    }
+
+    /// The copy of an entity a reader sees, given the graph's current copy
+    pub fn resolve(&self, entity_id: EntityId, current: Option<Entity>, view: &ReadView) -> Option<Entity> {
+        let chains = self.chains.read().unwrap();
+        let commits = self.commits.read().unwrap();
+
+        match chains.get(&entity_id) {
+            Some(chain) => Self::visible(&chain.versions, view, &commits).map(|v| v.entity.clone()),
+            None => current,
+        }
+    }
+
+    /// The entities of a collection a reader sees, given the graph's copies
+    ///
+    /// Includes entities the graph doesn't have (yet, or any more).
+    pub fn resolve_collection(&self, entity_type: &str, current: Vec<Entity>, view: &ReadView) -> Vec<Entity> {
+        self.resolve_matching(current, view, |chain| {
+            chain.versions.iter().any(|v| v.entity.entity_type == entity_type)
+        })
+    }
+
+    /// Every entity a reader sees, given all of the graph's copies
+    pub fn resolve_all(&self, current: Vec<Entity>, view: &ReadView) -> Vec<Entity> {
+        self.resolve_matching(current, view, |_| true)
+    }
+
+    fn resolve_matching<F>(&self, current: Vec<Entity>, view: &ReadView, in_scope: F) -> Vec<Entity>
+    where
+        F: Fn(&VersionedEntity) -> bool,
+    {
+        let chains = self.chains.read().unwrap();
+        if chains.is_empty() {
+            return current;
+        }
+        let commits = self.commits.read().unwrap();
+
+        let mut seen = HashSet::new();
+        let mut entities = Vec::new();
+        for entity in current {
+            seen.insert(entity.id);
+            match chains.get(&entity.id) {
+                Some(chain) => entities.extend(Self::visible(&chain.versions, view, &commits).map(|v| v.entity.clone())),
+                None => entities.push(entity),
+            }
+        }
+
+        for (id, chain) in chains.iter() {
+            if in_scope(chain) && !seen.contains(id) {
+                entities.extend(Self::visible(&chain.versions, view, &commits).map(|v| v.entity.clone()));
+            }
+        }
+
+        entities
+    }
+
+    /// The copy of an edge a reader sees, given the graph's current copy
+    pub fn resolve_edge(&self, edge_id: EdgeId, current: Option<Edge>, view: &ReadView) -> Option<Edge> {
+        let edges = self.edges.read().unwrap();
+        let commits = self.commits.read().unwrap();
+
+        match edges.chains.get(&edge_id) {
+            Some(versions) => Self::visible(versions, view, &commits).map(|v| v.edge.clone()),
+            None => current,
+        }
+    }
+
+    /// The chained edges touching an entity: the IDs of all of them, and
+    /// the copies a reader sees
+    ///
+    /// The graph's adjacency lists are current for the other edges.
+    pub fn edges_of(&self, entity_id: EntityId, view: &ReadView) -> (HashSet<EdgeId>, Vec<Edge>) {
+        let edges = self.edges.read().unwrap();
+        let Some(ids) = edges.by_entity.get(&entity_id) else {
+            return (HashSet::new(), Vec::new());
+        };
+        let commits = self.commits.read().unwrap();
+
+        let visible = ids
+            .iter()
+            .filter_map(|id| Self::visible(&edges.chains[id], view, &commits))
+            .map(|v| v.edge.clone())
+            .collect();
+        (ids.clone(), visible)
+    }
+
+    /// Every edge a reader sees, given all of the graph's copies
+    pub fn resolve_all_edges(&self, current: Vec<Edge>, view: &ReadView) -> Vec<Edge> {
+        let edges = self.edges.read().unwrap();
+        if edges.chains.is_empty() {
+            return current;
+        }
+        let commits = self.commits.read().unwrap();
+
+        let mut resolved: Vec<Edge> = current.into_iter().filter(|e| !edges.chains.contains_key(&e.id)).collect();
+        resolved.extend(
+            edges
+                .chains
+                .values()
+                .filter_map(|versions| Self::visible(versions, view, &commits))
+                .map(|v| v.edge.clone()),
+        );
+        resolved
+    }
+
+    /// A transaction's final versions, for applying to the graph at commit
+    pub fn writes_of(&self, txn_id: TransactionId) -> TxnWrites {
+        let chains = self.chains.read().unwrap();
+        let edges = self.edges.read().unwrap();
+        let journals = self.journals.lock().unwrap();
+        let Some(journal) = journals.get(&txn_id) else {
+            return TxnWrites::default();
+        };
+
+        let mut seen = HashSet::new();
+        let mut writes = TxnWrites::default();
+        for undo in &journal.undo {
+            match *undo {
+                Undo::Entity(id, _) if seen.insert(Written::Entity(id)) => {
+                    let last = chains.get(&id).and_then(|chain| Self::final_version(&chain.versions, txn_id));
+                    if let Some(version) = last {
+                        writes.entities.push((id, version.map(|v| v.entity.clone())));
+                    }
+                }
+                Undo::Edge(id, _) if seen.insert(Written::Edge(id)) => {
+                    let last = edges.chains.get(&id).and_then(|versions| Self::final_version(versions, txn_id));
+                    if let Some(version) = last {
+                        writes.edges.push((id, version.map(|v| v.edge.clone())));
+                    }
+                }
+                _ => {}
+            }
+        }
+        writes
+    }
+
+    /// What a transaction left of a chain: `Some(None)` if it deleted it,
+    /// `None` if it left it alone
+    fn final_version<V: Version>(versions: &[V], txn_id: TransactionId) -> Option<Option<&V>> {
+        let latest = versions.last()?;
+        if latest.deleted_by() == Some(txn_id) {
+            return Some(None);
         }
+        (latest.created_by() == txn_id).then_some(Some(latest))
+    }
+
+    /// Publish a transaction's versions, returning its commit timestamp
+    ///
+    /// The caller applies [`MVCCManager::writes_of`] to the graph, so that
+    /// readers find the committed versions there once the chains are gone.
+    pub fn commit(&self, txn_id: TransactionId) -> CommitTimestamp {
+        let mut commits = self.commits.write().unwrap();
+        let timestamp = self.commit_clock.fetch_add(1, Ordering::SeqCst) + 1;
+        commits.insert(txn_id, timestamp);
+        self.journals.lock().unwrap().remove(&txn_id);
+        timestamp
+    }
+
+    /// Start a savepoint in a transaction, returning the mark to roll back to
+    pub fn savepoint(&self, txn_id: TransactionId) -> usize {
+        let mut journals = self.journals.lock().unwrap();
+        let journal = journals.entry(txn_id).or_default();
+        journal.since_savepoint.clear();
+        journal.undo.len()
+    }
+
+    /// Undo a transaction's writes since a savepoint's mark
+    pub fn rollback_to(&self, txn_id: TransactionId, mark: usize) {
+        let mut chains = self.chains.write().unwrap();
+        let mut edges = self.edges.write().unwrap();
+        let mut journals = self.journals.lock().unwrap();
+        let Some(journal) = journals.get_mut(&txn_id) else { return };
+
+        while journal.undo.len() > mark {
+            match journal.undo.pop().expect("journal is longer than the mark") {
+                Undo::Entity(id, Some(chain)) => {
+                    chains.insert(id, chain);
+                }
+                Undo::Entity(id, None) => {
+                    chains.remove(&id);
+                }
+                Undo::Edge(id, versions) => {
+                    edges.remove(id);
+                    if let Some(versions) = versions {
+                        edges.insert(id, versions);
+                    }
+                }
+            }
+        }
+        journal.since_savepoint.clear();
+    }
+
+    /// Discard a transaction's versions
+    ///
+    /// The graph never had them, so there is nothing else to undo.
+    pub fn rollback(&self, txn_id: TransactionId) {
+        self.rollback_to(txn_id, 0);
+        self.journals.lock().unwrap().remove(&txn_id);
+    }
+
+    /// Drop version chains no open snapshot needs
+    ///
+    /// A chain can go once every version in it was committed at or before
+    /// `oldest_snapshot`: the graph's copy is then what every reader sees.
+    pub fn garbage_collect(&self, oldest_snapshot: CommitTimestamp) {
+        let mut chains = self.chains.write().unwrap();
+        let mut edges = self.edges.write().unwrap();
+        let mut commits = self.commits.write().unwrap();
+
+        let settled = |txn_id: TransactionId| {
+            txn_id == BOOTSTRAP_TXN || commits.get(&txn_id).is_some_and(|&ts| ts <= oldest_snapshot)
+        };
+        chains.retain(|_, chain| !Self::all_settled(&chain.versions, &settled));
+        let done: Vec<EdgeId> = edges
+            .chains
+            .iter()
+            .filter(|(_, versions)| Self::all_settled(versions, &settled))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in done {
+            edges.remove(id);
+        }
+
+        // Commit timestamps are only needed while a chain mentions the transaction
+        let referenced: HashSet<TransactionId> = chains
+            .values()
+            .flat_map(|chain| chain.versions.iter().map(|v| (v.created_by_txn, v.deleted_by_txn)))
+            .chain(edges.chains.values().flatten().map(|v| (v.created_by_txn, v.deleted_by_txn)))
+            .flat_map(|(created, deleted)| std::iter::once(created).chain(deleted))
+            .collect();
+        commits.retain(|txn_id, _| referenced.contains(txn_id));
+    }
+
+    /// Whether every write in a chain is `settled`
+    fn all_settled<V: Version>(versions: &[V], settled: &impl Fn(TransactionId) -> bool) -> bool {
+        versions.iter().all(|v| settled(v.created_by()) && v.deleted_by().is_none_or(settled))
+    }
+
+    /// Newest version of a chain visible to a reader, unless it was deleted
+    fn visible<'a, V: Version>(
+        versions: &'a [V],
+        view: &ReadView,
+        commits: &HashMap<TransactionId, CommitTimestamp>,
+    ) -> Option<&'a V> {
+        let version = versions.iter().rev().find(|v| view.sees(v.created_by(), commits))?;
+        if version.deleted_by().is_some_and(|txn_id| view.sees(txn_id, commits)) {
+            return None;
+        }
+        Some(version)
     }
 
     /// Update minimum active transaction
@@ -201,15 +800,25 @@ impl MVCCManager {
     }
 
     /// Check if a version should be garbage collected
+    ///
+    /// It can go once it was written, and deleted if it was, before every
+    /// active transaction: none of them needs it kept apart from the
+    /// graph's copy.
     pub fn should_gc_version(&self, version: &EntityVersion) -> bool {
         let min_txn = self.get_min_active_txn();
 
-        // Can GC if version is not visible to any active transaction
         version.created_by_txn < min_txn &&
-        version.deleted_by_txn.map_or(false, |d| d < min_txn)
+        version.deleted_by_txn.is_none_or(|d| d < min_txn)
     }
 }
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 impl Default for MVCCManager {
     fn default() -> Self {
         Self::new()
@@ -225,11 +834,11 @@ mod tests {
         let mut props = Properties::new();
         props.insert("name".to_string(), crate::types::PropertyValue::String(name.to_string()));
 
-        Entity {
-            id: EntityId::new(id),
-            entity_type: "User".to_string(),
-            properties: props,
-        }
+        Entity::new(EntityId::new(id), "User".to_string(), props)
+    }
+
+    fn name(entity: Option<Entity>) -> Option<String> {
+        entity.and_then(|e| e.get_property("name").and_then(|v| v.as_str()).map(str::to_string))
     }
 
     #[test]
@@ -301,6 +910,100 @@ mod tests {
         assert!(versioned.version_count() >= 1); // At least one kept
     }
 
+    #[test]
+    fn test_snapshots_see_versions_committed_before_them() {
+        let mgr = MVCCManager::new();
+        let id = EntityId::new(1);
+        let original = create_test_entity(1, "Alice");
+        let updated = create_test_entity(1, "Alice Updated");
+
+        let before = ReadView { txn_id: Some(1), snapshot: mgr.current_snapshot(), uncommitted: false };
+        mgr.record_write(2, id, Some(&original), Some(updated.clone())).unwrap();
+
+        // Uncommitted: only the writer sees the new version
+        assert_eq!(name(mgr.resolve(id, Some(updated.clone()), &before)).as_deref(), Some("Alice"));
+        let writer = ReadView { txn_id: Some(2), snapshot: mgr.current_snapshot(), uncommitted: false };
+        assert_eq!(name(mgr.resolve(id, Some(updated.clone()), &writer)).as_deref(), Some("Alice Updated"));
+
+        // A second writer conflicts until the first one commits
+        assert!(mgr.record_write(3, id, Some(&updated), None).is_err());
+        mgr.commit(2);
+
+        let after = ReadView { txn_id: None, snapshot: mgr.current_snapshot(), uncommitted: false };
+        assert_eq!(name(mgr.resolve(id, Some(updated.clone()), &after)).as_deref(), Some("Alice Updated"));
+        assert_eq!(name(mgr.resolve(id, Some(updated.clone()), &before)).as_deref(), Some("Alice"));
+
+        // Once no snapshot predates the commit, the chain is dropped
+        mgr.garbage_collect(before.snapshot);
+        assert!(mgr.has_versions());
+        mgr.garbage_collect(mgr.current_snapshot());
+        assert!(!mgr.has_versions());
+    }
+
+    #[test]
+    fn test_rollback_discards_versions() {
+        let mgr = MVCCManager::new();
+        let alice = create_test_entity(1, "Alice");
+        let bob = create_test_entity(2, "Bob");
+
+        // Alice is inserted and Bob deleted; the graph only has Bob
+        mgr.record_write(1, alice.id, None, Some(alice.clone())).unwrap();
+        mgr.record_write(1, bob.id, Some(&bob), None).unwrap();
+
+        let other = ReadView { txn_id: Some(2), snapshot: mgr.current_snapshot(), uncommitted: false };
+        assert!(mgr.resolve(alice.id, None, &other).is_none());
+        assert_eq!(mgr.resolve_collection("User", vec![bob.clone()], &other).len(), 1);
+        let dirty = ReadView { uncommitted: true, ..other };
+        assert_eq!(name(mgr.resolve(alice.id, None, &dirty)).as_deref(), Some("Alice"));
+        assert!(mgr.resolve(bob.id, Some(bob.clone()), &dirty).is_none());
+
+        mgr.rollback(1);
+        assert!(!mgr.has_versions());
+        assert!(mgr.writes_of(1).is_empty());
+        assert!(mgr.record_write(2, bob.id, Some(&bob), None).is_ok());
+    }
+
+    #[test]
+    fn test_savepoint_rollback_keeps_earlier_writes() {
+        let mgr = MVCCManager::new();
+        let alice = create_test_entity(1, "Alice");
+        let bob = create_test_entity(2, "Bob");
+
+        mgr.record_write(1, alice.id, None, Some(alice.clone())).unwrap();
+        let mark = mgr.savepoint(1);
+        mgr.record_write(1, alice.id, None, Some(create_test_entity(1, "Alicia"))).unwrap();
+        mgr.record_write(1, bob.id, Some(&bob), None).unwrap();
+        assert_eq!(mgr.writes_of(1).entities.len(), 2);
+
+        mgr.rollback_to(1, mark);
+        let writes = mgr.writes_of(1);
+        assert_eq!(writes.entities.len(), 1);
+        assert_eq!(name(writes.entities[0].1.clone()).as_deref(), Some("Alice"));
+        assert!(mgr.uncommitted_writer(bob.id, 2).is_none());
+    }
+
+    #[test]
+    fn test_lock_for_update_reads_the_latest_commit() {
+        let mgr = MVCCManager::new();
+        let original = create_test_entity(1, "Alice");
+        let id = original.id;
+
+        let reader = ReadView { txn_id: Some(1), snapshot: mgr.current_snapshot(), uncommitted: false };
+        mgr.record_write(2, id, Some(&original), Some(create_test_entity(1, "Alice B"))).unwrap();
+        mgr.commit(2);
+
+        // The update applies to the latest commit, not the reader's snapshot,
+        // and holds off other writers
+        let locked = mgr.lock_for_update(1, id, Some(&original)).unwrap();
+        assert_eq!(name(locked).as_deref(), Some("Alice B"));
+        assert_eq!(name(mgr.resolve(id, None, &reader)).as_deref(), Some("Alice B"));
+        assert!(mgr.record_write(3, id, None, None).is_err());
+
+        mgr.record_write(1, id, None, None).unwrap();
+        assert!(mgr.lock_for_update(1, id, None).unwrap().is_none());
+        assert!(matches!(mgr.writes_of(1).entities.as_slice(), [(written, None)] if *written == id));
+    }
+
     #[test]
     fn test_mvcc_manager() {
         let mgr = MVCCManager::new();
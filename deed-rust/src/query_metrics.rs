@@ -0,0 +1,422 @@
+//! Query Metrics
+//!
+//! Per-statement measurements collected by the DQL executor.
+//! - Latency histogram with p50/p95/p99
+//! - Counters for statements, failures, plan cache hits/misses, auto-commits
+//!   rows scanned vs returned, and bytes spilled to disk
+//! - Slow-query log: the N slowest statements above a threshold, with their
+//!   normalized text and plan summary
+
+use crate::dql_ir::QueryPlan;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the latency histogram buckets; slower samples land in a final overflow bucket
+pub const LATENCY_BUCKETS: [Duration; 16] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2_500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2_500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Slow-query threshold used unless configured
+const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Slow queries kept unless configured
+const DEFAULT_SLOW_QUERY_CAPACITY: usize = 50;
+
+/// Statement latencies, bucketed by `LATENCY_BUCKETS`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// Samples per bucket; the last entry counts samples slower than every bound
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            counts: vec![0; LATENCY_BUCKETS.len() + 1],
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+
+    /// Add one sample
+    pub fn observe(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// Latency at or below which the fraction `q` of samples fall
+    ///
+    /// Reported as the upper bound of the bucket holding that sample (but
+    /// never above the slowest sample seen); zero without samples.
+    pub fn percentile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS.get(bucket).map_or(self.max, |bound| (*bound).min(self.max));
+            }
+        }
+        self.max
+    }
+
+    /// Average latency; zero without samples
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What one statement did, as reported by the executor
+#[derive(Debug, Clone)]
+pub struct QuerySample<'a> {
+    /// Statement text as submitted
+    pub text: &'a str,
+    /// Plan the statement ran (or would have run, had it not failed)
+    pub plan: &'a QueryPlan,
+    pub elapsed: Duration,
+    /// Entities the statement's scans, index lookups and traversals examined
+    pub rows_scanned: usize,
+    pub rows_returned: usize,
+    /// Bytes GROUP BY and sorts wrote to spill files
+    pub spilled_bytes: usize,
+    /// Whether the plan came from the plan cache; `None` for prepared statements
+    pub cache_hit: Option<bool>,
+    /// Whether the statement ran in (and committed) a transaction of its own
+    pub auto_committed: bool,
+    pub succeeded: bool,
+}
+
+/// A statement in the slow-query log
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    /// Statement text with literals replaced by `?`
+    pub text: String,
+    /// Operations of the plan, in execution order
+    pub plan: String,
+    pub elapsed: Duration,
+    pub rows_scanned: usize,
+    pub rows_returned: usize,
+    pub succeeded: bool,
+    /// When the statement finished (seconds since the epoch)
+    pub timestamp: u64,
+}
+
+/// Point-in-time copy of the collected metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMetricsSnapshot {
+    pub total_queries: u64,
+    pub failed_queries: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub auto_commits: u64,
+    pub rows_scanned: u64,
+    pub rows_returned: u64,
+    /// Bytes GROUP BY and sorts wrote to spill files
+    pub spilled_bytes: u64,
+    pub latency: LatencyHistogram,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub slow_query_threshold: Duration,
+    /// Slowest statements first
+    pub slow_queries: Vec<SlowQuery>,
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    total_queries: u64,
+    failed_queries: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    auto_commits: u64,
+    rows_scanned: u64,
+    rows_returned: u64,
+    spilled_bytes: u64,
+    latency: LatencyHistogram,
+    slow_query_threshold: Duration,
+    slow_query_capacity: usize,
+    /// Slowest first, at most `slow_query_capacity` long
+    slow_queries: Vec<SlowQuery>,
+}
+
+/// Query metrics collector, shared by the executors of a database
+#[derive(Debug)]
+pub struct QueryMetrics {
+    state: Mutex<MetricsState>,
+}
+
+impl QueryMetrics {
+    /// Log statements slower than `slow_query_threshold`, keeping the `slow_query_capacity` slowest
+    pub fn new(slow_query_threshold: Duration, slow_query_capacity: usize) -> Self {
+        QueryMetrics {
+            state: Mutex::new(MetricsState {
+                total_queries: 0,
+                failed_queries: 0,
+                cache_hits: 0,
+                cache_misses: 0,
+                auto_commits: 0,
+                rows_scanned: 0,
+                rows_returned: 0,
+                spilled_bytes: 0,
+                latency: LatencyHistogram::new(),
+                slow_query_threshold,
+                slow_query_capacity,
+                slow_queries: Vec::new(),
+            }),
+        }
+    }
+
+    /// Change how slow a statement must be to be logged
+    pub fn set_slow_query_threshold(&self, threshold: Duration) {
+        self.state.lock().unwrap().slow_query_threshold = threshold;
+    }
+
+    /// Record one statement
+    pub fn record(&self, sample: QuerySample<'_>) {
+        let mut state = self.state.lock().unwrap();
+
+        state.total_queries += 1;
+        if !sample.succeeded {
+            state.failed_queries += 1;
+        }
+        match sample.cache_hit {
+            Some(true) => state.cache_hits += 1,
+            Some(false) => state.cache_misses += 1,
+            None => {}
+        }
+        if sample.auto_committed {
+            state.auto_commits += 1;
+        }
+        state.rows_scanned += sample.rows_scanned as u64;
+        state.rows_returned += sample.rows_returned as u64;
+        state.spilled_bytes += sample.spilled_bytes as u64;
+        state.latency.observe(sample.elapsed);
+
+        if sample.elapsed < state.slow_query_threshold || state.slow_query_capacity == 0 {
+            return;
+        }
+
+        // Keep the slowest; a full log only takes statements slower than its fastest
+        let full = state.slow_queries.len() >= state.slow_query_capacity;
+        if full && state.slow_queries.last().is_some_and(|fastest| fastest.elapsed >= sample.elapsed) {
+            return;
+        }
+        if full {
+            state.slow_queries.pop();
+        }
+
+        let position = state.slow_queries.partition_point(|logged| logged.elapsed >= sample.elapsed);
+        let entry = SlowQuery {
+            text: normalize_query_text(sample.text),
+            plan: summarize_plan(sample.plan),
+            elapsed: sample.elapsed,
+            rows_scanned: sample.rows_scanned,
+            rows_returned: sample.rows_returned,
+            succeeded: sample.succeeded,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        state.slow_queries.insert(position, entry);
+    }
+
+    /// Copy of the metrics collected so far
+    pub fn snapshot(&self) -> QueryMetricsSnapshot {
+        let state = self.state.lock().unwrap();
+
+        QueryMetricsSnapshot {
+            total_queries: state.total_queries,
+            failed_queries: state.failed_queries,
+            cache_hits: state.cache_hits,
+            cache_misses: state.cache_misses,
+            auto_commits: state.auto_commits,
+            rows_scanned: state.rows_scanned,
+            rows_returned: state.rows_returned,
+            spilled_bytes: state.spilled_bytes,
+            latency: state.latency.clone(),
+            p50: state.latency.percentile(0.50),
+            p95: state.latency.percentile(0.95),
+            p99: state.latency.percentile(0.99),
+            slow_query_threshold: state.slow_query_threshold,
+            slow_queries: state.slow_queries.clone(),
+        }
+    }
+}
+
+impl Default for QueryMetrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_QUERY_THRESHOLD, DEFAULT_SLOW_QUERY_CAPACITY)
+    }
+}
+
+/// Statement text with string and number literals replaced by `?` and whitespace collapsed
+///
+/// Parameters (`$name`, `?1`) and digits inside identifiers are kept.
+pub fn normalize_query_text(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut previous: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                // Skip to the closing quote, honoring backslash escapes
+                while let Some(inner) = chars.next() {
+                    if inner == '\\' {
+                        chars.next();
+                    } else if inner == c {
+                        break;
+                    }
+                }
+                normalized.push('?');
+            }
+            c if c.is_ascii_digit()
+                && !previous.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '?' || p == '$') =>
+            {
+                while chars.peek().is_some_and(|next| next.is_ascii_digit() || *next == '.') {
+                    chars.next();
+                }
+                normalized.push('?');
+            }
+            c if c.is_whitespace() => {
+                while chars.peek().is_some_and(|next| next.is_whitespace()) {
+                    chars.next();
+                }
+                normalized.push(' ');
+            }
+            c => normalized.push(c),
+        }
+        previous = normalized.chars().next_back();
+    }
+
+    normalized.trim().to_string()
+}
+
+/// The plan's operations, e.g. "IndexLookup -> Project -> Limit"
+fn summarize_plan(plan: &QueryPlan) -> String {
+    plan.operations
+        .iter()
+        .map(|op| op.name())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample<'a>(text: &'a str, plan: &'a QueryPlan, elapsed_ms: u64) -> QuerySample<'a> {
+        QuerySample {
+            text,
+            plan,
+            elapsed: Duration::from_millis(elapsed_ms),
+            rows_scanned: 10,
+            rows_returned: 2,
+            spilled_bytes: 0,
+            cache_hit: Some(true),
+            auto_committed: false,
+            succeeded: true,
+        }
+    }
+
+    #[test]
+    fn test_histogram_counts_and_percentiles() {
+        let mut histogram = LatencyHistogram::new();
+        for _ in 0..90 {
+            histogram.observe(Duration::from_micros(800));
+        }
+        for _ in 0..9 {
+            histogram.observe(Duration::from_millis(40));
+        }
+        histogram.observe(Duration::from_secs(20));
+
+        assert_eq!(histogram.count, 100);
+        assert_eq!(histogram.counts[3], 90);
+        assert_eq!(histogram.counts[8], 9);
+        assert_eq!(histogram.counts[LATENCY_BUCKETS.len()], 1);
+
+        assert_eq!(histogram.percentile(0.50), Duration::from_millis(1));
+        assert_eq!(histogram.percentile(0.95), Duration::from_millis(50));
+        assert_eq!(histogram.percentile(0.99), Duration::from_millis(50));
+        assert_eq!(histogram.percentile(1.0), Duration::from_secs(20));
+        assert_eq!(LatencyHistogram::new().percentile(0.99), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_slow_query_log_keeps_slowest_above_threshold() {
+        let metrics = QueryMetrics::new(Duration::from_millis(20), 3);
+        let plan = QueryPlan::new(Vec::new());
+
+        for (i, elapsed) in [5, 30, 1, 80, 25, 60, 10, 45].iter().enumerate() {
+            let text = format!("FROM Users WHERE age = {} SELECT name", i);
+            metrics.record(sample(&text, &plan, *elapsed));
+        }
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.total_queries, 8);
+        assert_eq!(stats.cache_hits, 8);
+        assert_eq!(stats.rows_scanned, 80);
+        assert_eq!(stats.rows_returned, 16);
+        assert_eq!(stats.latency.counts.iter().sum::<u64>(), 8);
+
+        // Five statements crossed the threshold; only the three slowest are kept
+        let slow: Vec<u64> = stats.slow_queries.iter().map(|q| q.elapsed.as_millis() as u64).collect();
+        assert_eq!(slow, vec![80, 60, 45]);
+        assert_eq!(stats.slow_queries[0].text, "FROM Users WHERE age = ? SELECT name");
+
+        // Lowering the threshold logs faster statements from then on
+        metrics.set_slow_query_threshold(Duration::ZERO);
+        metrics.record(sample("FROM Users SELECT name", &plan, 50));
+        let slow: Vec<u64> = metrics.snapshot().slow_queries.iter().map(|q| q.elapsed.as_millis() as u64).collect();
+        assert_eq!(slow, vec![80, 60, 50]);
+    }
+
+    #[test]
+    fn test_normalize_query_text() {
+        assert_eq!(
+            normalize_query_text("FROM  Users\n WHERE name = 'O\\'Brien' AND age >= 42.5 SELECT name"),
+            "FROM Users WHERE name = ? AND age >= ? SELECT name"
+        );
+        assert_eq!(
+            normalize_query_text("FROM Users2 WHERE id = $id1 OR id = ?1 LIMIT 10"),
+            "FROM Users2 WHERE id = $id1 OR id = ?1 LIMIT ?"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Read/write splitting
+//!
+//! Replicas are graphs kept current by replication, registered in a
+//! [`ReadReplicas`] set that a master executor and its sessions share
+//! (clones, and a [`ConnectionPool`](crate::ConnectionPool)'s connections).
+//! Reads are routed where they are executed, after the caller's role, the
+//! firewall and the session's settings have been applied, so a replica
+//! serves exactly what the master would have let through.
+//!
+//! With read splitting on, SELECTs (and EXPLAIN ANALYZEs of them) outside a
+//! transaction, over user collections, are spread over the replicas that
+//! pass their health check and are within the lag threshold, round robin or
+//! to the least lagged; with none left they run on the master. Health
+//! checks are cached for [`RoutingConfig::health_interval`], so a read
+//! never waits on more than one check per replica in that time.
+//!
+//! A read that must see the session's own writes can start with the
+//! `/*+ primary */` hint or be run with [`RoutingExecutor::execute_on_primary`].
+//! Once the session opens a transaction, everything stays on the master
+//! until it ends.
+
+use crate::dql_executor::{DQLExecutor, QueryResult};
+use crate::dql_ir::Value;
+use crate::graph::Graph;
+use crate::replication::ReplicationManager;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Health check of a replica, e.g. a ping of the node serving it
+pub trait ReplicaHealth: Send + Sync {
+    /// Check the replica can be read from; replicas failing it aren't
+    fn ping(&self) -> Result<(), String>;
+}
+
+/// How reads are spread over the replicas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadBalancing {
+    /// Each replica in turn
+    #[default]
+    RoundRobin,
+    /// The replica with the least replication lag
+    LeastLagged,
+}
+
+/// Read routing settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingConfig {
+    pub balancing: ReadBalancing,
+    /// Lag past which a replica isn't read from (`None` for no limit)
+    pub max_lag: Option<Duration>,
+    /// How long a replica's health check result is trusted
+    pub health_interval: Duration,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        RoutingConfig {
+            balancing: ReadBalancing::default(),
+            max_lag: Some(Duration::from_secs(1)),
+            health_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where a statement is run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Primary,
+    /// The replica of this slave ID
+    Replica(String),
+}
+
+/// Hint that keeps a read on the master
+pub const PRIMARY_HINT: &str = "primary";
+
+/// A replica's graph, its health check and the last result of that
+struct Replica {
+    graph: Arc<RwLock<Graph>>,
+    health: Option<Arc<dyn ReplicaHealth>>,
+    /// When the health check last ran, and whether it passed
+    checked: Mutex<Option<(Instant, bool)>>,
+}
+
+/// The replicas a master's reads may be served by, under their slave IDs
+///
+/// Bounded-staleness reads (`SET max_staleness`) go to the freshest replica
+/// within their bound. Splitting every eligible read over the replicas is
+/// turned on with [`ReadReplicas::set_routing`].
+#[derive(Default)]
+pub struct ReadReplicas {
+    replicas: RwLock<BTreeMap<String, Replica>>,
+    routing: RwLock<Option<RoutingConfig>>,
+    /// Round-robin position
+    next_replica: AtomicUsize,
+}
+
+impl ReadReplicas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the graph of the replica of `slave_id`, and optionally its health check
+    pub fn attach(&self, slave_id: impl Into<String>, graph: Arc<RwLock<Graph>>, health: Option<Arc<dyn ReplicaHealth>>) {
+        let replica = Replica {
+            graph,
+            health,
+            checked: Mutex::new(None),
+        };
+        self.replicas.write().unwrap().insert(slave_id.into(), replica);
+    }
+
+    /// Spread every eligible read over the replicas (`None` to stop)
+    pub fn set_routing(&self, config: Option<RoutingConfig>) {
+        *self.routing.write().unwrap() = config;
+    }
+
+    pub fn routing(&self) -> Option<RoutingConfig> {
+        self.routing.read().unwrap().clone()
+    }
+
+    /// Choose a healthy replica within `max_lag`, and its lag
+    ///
+    /// `staleness` is the master's estimate of each replica's lag; replicas
+    /// it has none for aren't chosen. Without it every replica counts as
+    /// current.
+    pub(crate) fn pick(
+        &self,
+        staleness: Option<&HashMap<String, Option<Duration>>>,
+        balancing: ReadBalancing,
+        max_lag: Option<Duration>,
+        health_interval: Duration,
+    ) -> Option<(String, Arc<RwLock<Graph>>, Duration)> {
+        let replicas = self.replicas.read().unwrap();
+        let eligible: Vec<(&String, &Replica, Duration)> = replicas
+            .iter()
+            .filter_map(|(slave_id, replica)| {
+                let lag = match staleness {
+                    Some(staleness) => staleness.get(slave_id).copied().flatten()?,
+                    None => Duration::ZERO,
+                };
+                let within = max_lag.is_none_or(|max_lag| lag <= max_lag);
+                (within && replica.is_healthy(health_interval)).then_some((slave_id, replica, lag))
+            })
+            .collect();
+
+        if eligible.is_empty() {
+            return None;
+        }
+        let (slave_id, replica, lag) = match balancing {
+            ReadBalancing::RoundRobin => eligible[self.next_replica.fetch_add(1, Ordering::Relaxed) % eligible.len()],
+            ReadBalancing::LeastLagged => *eligible.iter().min_by_key(|(_, _, lag)| *lag)?,
+        };
+        Some((slave_id.clone(), replica.graph.clone(), lag))
+    }
+
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.replicas.is_poisoned() || self.routing.is_poisoned()
+    }
+}
+
+impl std::fmt::Debug for ReadReplicas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let replicas: Vec<String> = self.replicas.read().map(|r| r.keys().cloned().collect()).unwrap_or_default();
+        f.debug_struct("ReadReplicas")
+            .field("replicas", &replicas)
+            .field("routing", &self.routing())
+            .finish()
+    }
+}
+
+impl Replica {
+    /// The health check's result, run again once `interval` has passed
+    fn is_healthy(&self, interval: Duration) -> bool {
+        let Some(health) = &self.health else { return true };
+        let mut checked = self.checked.lock().unwrap();
+        match *checked {
+            Some((at, healthy)) if at.elapsed() < interval => healthy,
+            _ => {
+                let healthy = health.ping().is_ok();
+                *checked = Some((Instant::now(), healthy));
+                healthy
+            }
+        }
+    }
+}
+
+/// A master session that splits reads over its read replicas
+///
+/// Replicas are registered under their slave IDs. With the master's
+/// [`ReplicationManager`] attached, their lag is its estimate of their
+/// staleness, and replicas it has no estimate for aren't read from;
+/// without one, every replica counts as current.
+pub struct RoutingExecutor {
+    master: DQLExecutor,
+}
+
+impl RoutingExecutor {
+    pub fn new(master: DQLExecutor) -> Self {
+        Self::with_config(master, RoutingConfig::default())
+    }
+
+    pub fn with_config(master: DQLExecutor, config: RoutingConfig) -> Self {
+        master.read_replicas().set_routing(Some(config));
+        RoutingExecutor { master }
+    }
+
+    /// Read from the replica of `slave_id`
+    pub fn with_replica(self, slave_id: impl Into<String>, graph: Arc<RwLock<Graph>>) -> Self {
+        self.master.read_replicas().attach(slave_id, graph, None);
+        self
+    }
+
+    /// Read from the replica of `slave_id` while it passes `health`
+    pub fn with_checked_replica(
+        self,
+        slave_id: impl Into<String>,
+        graph: Arc<RwLock<Graph>>,
+        health: Arc<dyn ReplicaHealth>,
+    ) -> Self {
+        self.master.read_replicas().attach(slave_id, graph, Some(health));
+        self
+    }
+
+    /// Judge replicas' lag by the master's replication state
+    pub fn with_replication(self, replication: Arc<ReplicationManager>) -> Self {
+        RoutingExecutor {
+            master: self.master.with_replication(replication),
+        }
+    }
+
+    /// The master session
+    pub fn master(&self) -> &DQLExecutor {
+        &self.master
+    }
+
+    /// Execute a statement where it is routed
+    pub fn execute(&self, query: &str) -> Result<QueryResult, String> {
+        self.master.execute(query)
+    }
+
+    /// Execute a statement containing parameters where it is routed
+    pub fn execute_with_params(&self, query: &str, params: &HashMap<String, Value>) -> Result<QueryResult, String> {
+        self.master.execute_with_params(query, params)
+    }
+
+    /// Execute a statement on the master, whatever it is
+    pub fn execute_on_primary(&self, query: &str) -> Result<QueryResult, String> {
+        self.master.execute_on_primary(query)
+    }
+
+    /// Where a read would run now
+    ///
+    /// Statements that don't parse go to the master, which reports the error.
+    pub fn route(&self, query: &str) -> Route {
+        self.master.read_route(query)
+    }
+}
+
+/// Whether the statement starts with a `/*+ primary */` hint
+///
+/// Only comments before the statement are looked at; hints may share one
+/// comment, separated by spaces or commas.
+pub(crate) fn has_primary_hint(query: &str) -> bool {
+    let mut rest = query.trim_start();
+    while let Some(comment) = rest.strip_prefix("/*") {
+        let Some((body, after)) = comment.split_once("*/") else {
+            return false;
+        };
+        if let Some(hints) = body.strip_prefix('+') {
+            if hints
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .any(|hint| hint.eq_ignore_ascii_case(PRIMARY_HINT))
+            {
+                return true;
+            }
+        }
+        rest = after.trim_start();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primary_hint_must_lead_the_statement() {
+        assert!(has_primary_hint("/*+ primary */ FROM Users SELECT name"));
+        assert!(has_primary_hint("  /* audited */ /*+ index(name), PRIMARY */ FROM Users SELECT name"));
+        assert!(!has_primary_hint("/* primary */ FROM Users SELECT name"));
+        assert!(!has_primary_hint("FROM Users WHERE name = '/*+ primary */' SELECT name"));
+        assert!(!has_primary_hint("/*+ primary FROM Users SELECT name"));
+    }
+
+    struct CountedHealth(std::sync::atomic::AtomicUsize);
+
+    impl ReplicaHealth for CountedHealth {
+        fn ping(&self) -> Result<(), String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_health_checks_are_cached() {
+        let replicas = ReadReplicas::new();
+        let health = Arc::new(CountedHealth(AtomicUsize::new(0)));
+        replicas.attach("replica-1", Arc::new(RwLock::new(Graph::new())), Some(health.clone()));
+
+        let pick = |interval| replicas.pick(None, ReadBalancing::RoundRobin, None, interval).map(|(id, _, _)| id);
+        for _ in 0..10 {
+            assert_eq!(pick(Duration::from_secs(60)), Some("replica-1".to_string()));
+        }
+        assert_eq!(health.0.load(Ordering::SeqCst), 1);
+
+        pick(Duration::ZERO);
+        assert_eq!(health.0.load(Ordering::SeqCst), 2);
+    }
+}
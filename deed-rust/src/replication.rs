@@ -4,15 +4,24 @@
 //! - Master node handles all writes
 //! - Slave nodes replicate data asynchronously
 //! - Replication log tracks all mutations
+//! - Slaves pull the log over the P2P network and apply it to their graph
+//! - The log can be kept on disk, in append-only segment files
+//! - Slaves behind the trimmed log catch up from a snapshot of the graph
 //! - Automatic failover support
-
-use crate::types::{EntityId, EdgeId, Properties, PropertyValue};
-use crate::wal::WALEntry;
-use std::collections::{HashMap, VecDeque};
+//!
+//! Sequence numbers start at 1, so a slave that has applied nothing is at 0.
+
+use crate::distributed_p2p::{MessageKind, MessageType, P2PNetwork};
+use crate::distributed_topology::NodeId;
+use crate::graph::{Edge, Entity, Graph};
+use crate::shutdown::{BackgroundTask, ShutdownSignal};
+use crate::types::{EntityId, EdgeId, PropertyValue};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
 use std::sync::{Arc, RwLock, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write, BufReader, BufWriter};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use serde::{Serialize, Deserialize};
 
 /// Node role in replication topology
@@ -28,7 +37,9 @@ pub type ReplicationSeq = u64;
 /// Replication log entry
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ReplicationEntry {
-    InsertEntity {
+    /// An insert as logged before creation times were; applied as created
+    /// when applied
+    LegacyInsertEntity {
         seq: ReplicationSeq,
         entity_id: u64,
         entity_type: String,
@@ -55,35 +66,155 @@ pub enum ReplicationEntry {
         properties: HashMap<String, PropertyValue>,
         timestamp: u64,
     },
+    UpdateEdge {
+        seq: ReplicationSeq,
+        edge_id: u64,
+        properties: HashMap<String, PropertyValue>,
+        timestamp: u64,
+    },
     DeleteEdge {
         seq: ReplicationSeq,
         edge_id: u64,
         timestamp: u64,
     },
+    /// A collection removed with its entities and their edges
+    DropCollection {
+        seq: ReplicationSeq,
+        collection: String,
+        timestamp: u64,
+    },
+    /// An entity inserted, created at `created_at` (milliseconds since the Unix epoch)
+    InsertEntity {
+        seq: ReplicationSeq,
+        entity_id: u64,
+        entity_type: String,
+        properties: HashMap<String, PropertyValue>,
+        created_at: u64,
+        timestamp: u64,
+    },
+    /// Entities that moved to another node, removed with their outgoing
+    /// edges; edges into them stay
+    EvictEntities {
+        seq: ReplicationSeq,
+        entity_ids: Vec<u64>,
+        timestamp: u64,
+    },
 }
 
 impl ReplicationEntry {
     pub fn seq(&self) -> ReplicationSeq {
         match self {
+            ReplicationEntry::LegacyInsertEntity { seq, .. } => *seq,
             ReplicationEntry::InsertEntity { seq, .. } => *seq,
             ReplicationEntry::UpdateEntity { seq, .. } => *seq,
             ReplicationEntry::DeleteEntity { seq, .. } => *seq,
             ReplicationEntry::CreateEdge { seq, .. } => *seq,
+            ReplicationEntry::UpdateEdge { seq, .. } => *seq,
             ReplicationEntry::DeleteEdge { seq, .. } => *seq,
+            ReplicationEntry::DropCollection { seq, .. } => *seq,
+            ReplicationEntry::EvictEntities { seq, .. } => *seq,
         }
     }
 
     pub fn timestamp(&self) -> u64 {
         match self {
+            ReplicationEntry::LegacyInsertEntity { timestamp, .. } => *timestamp,
             ReplicationEntry::InsertEntity { timestamp, .. } => *timestamp,
             ReplicationEntry::UpdateEntity { timestamp, .. } => *timestamp,
             ReplicationEntry::DeleteEntity { timestamp, .. } => *timestamp,
             ReplicationEntry::CreateEdge { timestamp, .. } => *timestamp,
+            ReplicationEntry::UpdateEdge { timestamp, .. } => *timestamp,
             ReplicationEntry::DeleteEdge { timestamp, .. } => *timestamp,
+            ReplicationEntry::DropCollection { timestamp, .. } => *timestamp,
+            ReplicationEntry::EvictEntities { timestamp, .. } => *timestamp,
         }
     }
+
+    /// Make the change on `graph`
+    ///
+    /// Applying an entry whose change is already there leaves the graph as
+    /// it is: inserts overwrite, existing edges aren't added twice, and
+    /// updating or deleting what is gone does nothing.
+    fn apply(&self, graph: &Graph) -> Result<(), String> {
+        match self {
+            ReplicationEntry::LegacyInsertEntity { entity_id, entity_type, properties, .. } => {
+                graph.insert_entity_with_id(Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone()))?;
+            }
+            ReplicationEntry::InsertEntity { entity_id, entity_type, properties, created_at, .. } => {
+                let entity = Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone());
+                graph.insert_entity_with_id(entity.with_created_at(*created_at))?;
+            }
+            // An entity or edge missing here was deleted by a later entry a
+            // snapshot already reflects
+            ReplicationEntry::UpdateEntity { entity_id, properties, .. } => {
+                if let Some(mut entity) = graph.get_entity(EntityId::new(*entity_id)) {
+                    entity.properties = properties.clone();
+                    graph.update_entity(entity)?;
+                }
+            }
+            ReplicationEntry::DeleteEntity { entity_id, .. } => {
+                if graph.get_entity(EntityId::new(*entity_id)).is_some() {
+                    graph.delete_entity(EntityId::new(*entity_id))?;
+                }
+            }
+            ReplicationEntry::CreateEdge { edge_id, from_id, to_id, edge_type, properties, .. } => {
+                let id = EdgeId::new(*edge_id);
+                if graph.get_edge(id).is_some() {
+                    graph.update_edge_properties(id, properties.clone())?;
+                } else {
+                    let edge = Edge::new(id, EntityId::new(*from_id), EntityId::new(*to_id), edge_type.clone(), properties.clone());
+                    graph.insert_edge_with_id(edge)?;
+                }
+            }
+            ReplicationEntry::UpdateEdge { edge_id, properties, .. } => {
+                if graph.get_edge(EdgeId::new(*edge_id)).is_some() {
+                    graph.update_edge_properties(EdgeId::new(*edge_id), properties.clone())?;
+                }
+            }
+            ReplicationEntry::DeleteEdge { edge_id, .. } => {
+                if graph.get_edge(EdgeId::new(*edge_id)).is_some() {
+                    graph.delete_edge(EdgeId::new(*edge_id))?;
+                }
+            }
+            ReplicationEntry::DropCollection { collection, .. } => {
+                graph.drop_collection(collection)?;
+                graph.clear_collection_ttl(collection);
+            }
+            ReplicationEntry::EvictEntities { entity_ids, .. } => {
+                let ids: Vec<EntityId> = entity_ids
+                    .iter()
+                    .map(|id| EntityId::new(*id))
+                    .filter(|&id| graph.get_entity_ref(id).is_some())
+                    .collect();
+                graph.evict_entities(&ids)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Full copy of a master's graph, for a slave too far behind to replay the log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationSnapshot {
+    /// Every entry up to this one is reflected; later ones may be too
+    pub seq: ReplicationSeq,
+    pub entities: Vec<Entity>,
+    pub edges: Vec<Edge>,
+}
+
+/// What a slave got from one pull
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pulled {
+    /// Log entries, this many of them applied
+    Entries(usize),
+    /// A snapshot at this sequence number, installed in place of the graph
+    Snapshot(ReplicationSeq),
 }
 
+/// Builds snapshots of the master's graph for slaves to catch up from
+pub type SnapshotSource = Arc<dyn Fn() -> Result<ReplicationSnapshot, String> + Send + Sync>;
+
 /// Replication configuration
 #[derive(Debug, Clone)]
 pub struct ReplicationConfig {
@@ -97,6 +228,12 @@ pub struct ReplicationConfig {
     pub max_lag_ms: u64,
     /// Batch size for replication
     pub batch_size: usize,
+    /// Bytes a log segment file grows to before the next one is started
+    pub segment_size: u64,
+    /// Bytes of snapshot sent per message
+    pub snapshot_chunk_size: usize,
+    /// How long a snapshot is kept for a slave that stops asking for its chunks
+    pub snapshot_ttl: Duration,
 }
 
 impl Default for ReplicationConfig {
@@ -107,6 +244,9 @@ impl Default for ReplicationConfig {
             master_address: None,
             max_lag_ms: 1000, // 1 second
             batch_size: 100,
+            segment_size: 16 * 1024 * 1024, // 16 MiB
+            snapshot_chunk_size: 32 * 1024, // 32 KiB
+            snapshot_ttl: Duration::from_secs(300),
         }
     }
 }
@@ -116,14 +256,26 @@ pub struct ReplicationManager {
     config: ReplicationConfig,
     /// Replication log
     log: Arc<RwLock<VecDeque<ReplicationEntry>>>,
-    /// Next sequence number
-    next_seq: Arc<Mutex<ReplicationSeq>>,
+    /// Sequence number of the last logged entry
+    last_seq: Arc<Mutex<ReplicationSeq>>,
+    /// Last sequence number trimmed from the log; slaves behind it need a snapshot
+    trimmed_seq: Arc<Mutex<ReplicationSeq>>,
+    /// On-disk copy of the log, if it is persisted
+    segments: Option<Mutex<LogSegments>>,
     /// Slave states (for master)
     slave_states: Arc<RwLock<HashMap<String, SlaveState>>>,
+    /// Snapshot each slave is being sent (for master)
+    snapshot_transfers: Arc<Mutex<HashMap<String, PendingSnapshot>>>,
     /// Last applied sequence (for slave)
     last_applied_seq: Arc<Mutex<ReplicationSeq>>,
 }
 
+impl std::fmt::Debug for ReplicationManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicationManager").field("role", &self.config.role).finish()
+    }
+}
+
 /// Slave replication state
 #[derive(Debug, Clone)]
 pub struct SlaveState {
@@ -131,6 +283,27 @@ pub struct SlaveState {
     pub last_ack_seq: ReplicationSeq,
     pub last_contact: u64,
     pub lag_ms: u64,
+    /// Master wall-clock time (ms) of the last entry the slave applied
+    pub applied_timestamp_ms: Option<u64>,
+    /// Snapshot being sent to the slave, while it catches up from one
+    pub snapshot_transfer: Option<SnapshotTransfer>,
+}
+
+/// An encoded snapshot kept while a slave fetches it
+#[derive(Debug)]
+struct PendingSnapshot {
+    seq: ReplicationSeq,
+    data: Arc<Vec<u8>>,
+    /// When the slave last asked for a chunk
+    touched: Instant,
+}
+
+/// Progress of a snapshot transfer to a slave
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTransfer {
+    pub seq: ReplicationSeq,
+    pub sent_bytes: u64,
+    pub total_bytes: u64,
 }
 
 impl ReplicationManager {
@@ -139,20 +312,39 @@ impl ReplicationManager {
         ReplicationManager {
             config,
             log: Arc::new(RwLock::new(VecDeque::new())),
-            next_seq: Arc::new(Mutex::new(0)),
+            last_seq: Arc::new(Mutex::new(0)),
+            trimmed_seq: Arc::new(Mutex::new(0)),
+            segments: None,
             slave_states: Arc::new(RwLock::new(HashMap::new())),
+            snapshot_transfers: Arc::new(Mutex::new(HashMap::new())),
             last_applied_seq: Arc::new(Mutex::new(0)),
         }
     }
 
+    /// Create a replication manager whose log is kept in segment files under `dir`
+    ///
+    /// The log already there is loaded, so a restarted master serves slaves
+    /// from where it left off. A torn write at the end of the log is dropped.
+    pub fn open<P: AsRef<Path>>(config: ReplicationConfig, dir: P) -> Result<Self, String> {
+        let (segments, entries) = LogSegments::open(dir.as_ref(), config.segment_size)?;
+        let last_seq = entries.last().map_or(0, |entry| entry.seq());
+        let trimmed_seq = entries.first().map_or(0, |entry| entry.seq() - 1);
+
+        let mut manager = Self::new(config);
+        *manager.log.write().unwrap() = entries.into();
+        *manager.last_seq.lock().unwrap() = last_seq;
+        *manager.trimmed_seq.lock().unwrap() = trimmed_seq;
+        manager.segments = Some(Mutex::new(segments));
+        Ok(manager)
+    }
+
     /// Create a master node
     pub fn new_master(node_id: String) -> Self {
         let config = ReplicationConfig {
             node_id,
             role: NodeRole::Master,
             master_address: None,
-            max_lag_ms: 1000,
-            batch_size: 100,
+            ..ReplicationConfig::default()
         };
         Self::new(config)
     }
@@ -163,8 +355,7 @@ impl ReplicationManager {
             node_id,
             role: NodeRole::Slave,
             master_address: Some(master_address),
-            max_lag_ms: 1000,
-            batch_size: 100,
+            ..ReplicationConfig::default()
         };
         Self::new(config)
     }
@@ -174,30 +365,52 @@ impl ReplicationManager {
         self.config.role
     }
 
-    /// Log an insert operation (master only)
+    /// Append the entry built for the next sequence number and the current time
+    fn append(&self, entry: impl FnOnce(ReplicationSeq, u64) -> ReplicationEntry) -> Result<ReplicationSeq, String> {
+        let mut log = self.log.write().unwrap();
+        let mut last_seq = self.last_seq.lock().unwrap();
+        let entry = entry(*last_seq + 1, current_timestamp());
+
+        if let Some(segments) = &self.segments {
+            segments.lock().unwrap().append(&entry)?;
+        }
+
+        *last_seq = entry.seq();
+        log.push_back(entry);
+        Ok(*last_seq)
+    }
+
+    /// Log an insert of an entity created now (master only)
     pub fn log_insert(
         &self,
         entity_id: u64,
         entity_type: String,
         properties: HashMap<String, PropertyValue>,
+    ) -> Result<ReplicationSeq, String> {
+        self.log_insert_created(entity_id, entity_type, properties, current_timestamp_ms())
+    }
+
+    /// Log an insert of an entity created at `created_at`, in milliseconds
+    /// since the Unix epoch (master only)
+    pub fn log_insert_created(
+        &self,
+        entity_id: u64,
+        entity_type: String,
+        properties: HashMap<String, PropertyValue>,
+        created_at: u64,
     ) -> Result<ReplicationSeq, String> {
         if self.config.role != NodeRole::Master {
             return Err("Only master can log operations".to_string());
         }
 
-        let seq = self.get_next_seq();
-        let timestamp = current_timestamp();
-
-        let entry = ReplicationEntry::InsertEntity {
+        self.append(|seq, timestamp| ReplicationEntry::InsertEntity {
             seq,
             entity_id,
             entity_type,
             properties,
+            created_at,
             timestamp,
-        };
-
-        self.log.write().unwrap().push_back(entry);
-        Ok(seq)
+        })
     }
 
     /// Log an update operation (master only)
@@ -210,18 +423,12 @@ impl ReplicationManager {
             return Err("Only master can log operations".to_string());
         }
 
-        let seq = self.get_next_seq();
-        let timestamp = current_timestamp();
-
-        let entry = ReplicationEntry::UpdateEntity {
+        self.append(|seq, timestamp| ReplicationEntry::UpdateEntity {
             seq,
             entity_id,
             properties,
             timestamp,
-        };
-
-        self.log.write().unwrap().push_back(entry);
-        Ok(seq)
+        })
     }
 
     /// Log a delete operation (master only)
@@ -230,17 +437,37 @@ impl ReplicationManager {
             return Err("Only master can log operations".to_string());
         }
 
-        let seq = self.get_next_seq();
-        let timestamp = current_timestamp();
-
-        let entry = ReplicationEntry::DeleteEntity {
+        self.append(|seq, timestamp| ReplicationEntry::DeleteEntity {
             seq,
             entity_id,
             timestamp,
-        };
+        })
+    }
 
-        self.log.write().unwrap().push_back(entry);
-        Ok(seq)
+    /// Log the removal of a collection (master only)
+    pub fn log_drop_collection(&self, collection: String) -> Result<ReplicationSeq, String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can log operations".to_string());
+        }
+
+        self.append(|seq, timestamp| ReplicationEntry::DropCollection {
+            seq,
+            collection,
+            timestamp,
+        })
+    }
+
+    /// Log the eviction of entities that moved to another node (master only)
+    pub fn log_evict(&self, entity_ids: Vec<u64>) -> Result<ReplicationSeq, String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can log operations".to_string());
+        }
+
+        self.append(|seq, timestamp| ReplicationEntry::EvictEntities {
+            seq,
+            entity_ids,
+            timestamp,
+        })
     }
 
     /// Log a create edge operation (master only)
@@ -256,10 +483,7 @@ impl ReplicationManager {
             return Err("Only master can log operations".to_string());
         }
 
-        let seq = self.get_next_seq();
-        let timestamp = current_timestamp();
-
-        let entry = ReplicationEntry::CreateEdge {
+        self.append(|seq, timestamp| ReplicationEntry::CreateEdge {
             seq,
             edge_id,
             from_id,
@@ -267,10 +491,38 @@ impl ReplicationManager {
             edge_type,
             properties,
             timestamp,
-        };
+        })
+    }
+
+    /// Log an edge property update (master only)
+    pub fn log_update_edge(
+        &self,
+        edge_id: u64,
+        properties: HashMap<String, PropertyValue>,
+    ) -> Result<ReplicationSeq, String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can log operations".to_string());
+        }
 
-        self.log.write().unwrap().push_back(entry);
-        Ok(seq)
+        self.append(|seq, timestamp| ReplicationEntry::UpdateEdge {
+            seq,
+            edge_id,
+            properties,
+            timestamp,
+        })
+    }
+
+    /// Log a delete edge operation (master only)
+    pub fn log_delete_edge(&self, edge_id: u64) -> Result<ReplicationSeq, String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can log operations".to_string());
+        }
+
+        self.append(|seq, timestamp| ReplicationEntry::DeleteEdge {
+            seq,
+            edge_id,
+            timestamp,
+        })
     }
 
     /// Get entries since a sequence number
@@ -295,6 +547,294 @@ impl ReplicationManager {
         Ok(())
     }
 
+    /// Apply a replication entry to the slave's graph (slave only)
+    ///
+    /// Entries must arrive in sequence order. One at or below the last
+    /// applied sequence is skipped, so a batch can safely be re-delivered;
+    /// returns whether the entry was applied.
+    pub fn apply_to_graph(&self, graph: &Graph, entry: &ReplicationEntry) -> Result<bool, String> {
+        if self.config.role != NodeRole::Slave {
+            return Err("Only slave can apply entries".to_string());
+        }
+
+        let mut last_applied = self.last_applied_seq.lock().unwrap();
+        if entry.seq() <= *last_applied {
+            return Ok(false);
+        }
+        if entry.seq() != *last_applied + 1 {
+            return Err(format!(
+                "Replication gap: expected entry {}, got {}; the slave needs a full resync",
+                *last_applied + 1,
+                entry.seq()
+            ));
+        }
+
+        entry.apply(graph)?;
+        *last_applied = entry.seq();
+        Ok(true)
+    }
+
+    /// Sequence number of the last entry applied (slave only)
+    pub fn last_applied_seq(&self) -> ReplicationSeq {
+        *self.last_applied_seq.lock().unwrap()
+    }
+
+    /// Replace the slave's graph with a snapshot from the master (slave only)
+    ///
+    /// Entities and edges the snapshot doesn't have are removed; log entries
+    /// after the snapshot's sequence number are applied next.
+    pub fn install_snapshot(&self, graph: &Graph, snapshot: &ReplicationSnapshot) -> Result<(), String> {
+        if self.config.role != NodeRole::Slave {
+            return Err("Only slave can install snapshots".to_string());
+        }
+
+        let mut last_applied = self.last_applied_seq.lock().unwrap();
+
+        let edge_ids: HashSet<EdgeId> = snapshot.edges.iter().map(|e| e.id).collect();
+        for edge in graph.get_all_edges() {
+            if !edge_ids.contains(&edge.id) {
+                graph.delete_edge(edge.id)?;
+            }
+        }
+        let entity_ids: HashSet<EntityId> = snapshot.entities.iter().map(|e| e.id).collect();
+        for entity in graph.get_all_entities() {
+            if !entity_ids.contains(&entity.id) {
+                graph.delete_entity(entity.id)?;
+            }
+        }
+
+        for entity in &snapshot.entities {
+            graph.insert_entity_with_id(entity.clone())?;
+        }
+        for edge in &snapshot.edges {
+            if graph.get_edge(edge.id).is_some() {
+                graph.update_edge_properties(edge.id, edge.properties.clone())?;
+            } else {
+                graph.insert_edge_with_id(edge.clone())?;
+            }
+        }
+
+        *last_applied = snapshot.seq;
+        Ok(())
+    }
+
+    /// Answer slaves' requests for log entries on `network` (master only)
+    ///
+    /// A request also acknowledges the entries the slave has applied, and
+    /// registers a slave the master hasn't heard from before. A slave asking
+    /// for entries already trimmed from the log is sent a snapshot from
+    /// `snapshots` instead, in chunks of `snapshot_chunk_size` bytes.
+    pub fn serve_slaves(self: &Arc<Self>, network: &P2PNetwork, snapshots: SnapshotSource) -> Result<(), String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can serve replication".to_string());
+        }
+
+        let master = Arc::clone(self);
+        network.register_handler(MessageKind::ReplicationRequest, move |msg| {
+            let MessageType::ReplicationRequest { slave_id, since } = &msg.message_type else {
+                return None;
+            };
+            let reply = master
+                .answer_request(slave_id, *since, &snapshots)
+                .unwrap_or_else(|message| MessageType::Error { message });
+            Some(msg.reply(reply))
+        });
+
+        let master = Arc::clone(self);
+        network.register_handler(MessageKind::SnapshotChunkRequest, move |msg| {
+            let MessageType::SnapshotChunkRequest { slave_id, seq, offset } = &msg.message_type else {
+                return None;
+            };
+            let reply = master
+                .snapshot_chunk(slave_id, *seq, *offset)
+                .unwrap_or_else(|message| MessageType::Error { message });
+            Some(msg.reply(reply))
+        });
+
+        Ok(())
+    }
+
+    /// The entries after `since`, or the first chunk of a snapshot if some were trimmed
+    fn answer_request(&self, slave_id: &str, since: ReplicationSeq, snapshots: &SnapshotSource) -> Result<MessageType, String> {
+        if !self.slave_states.read().unwrap().contains_key(slave_id) {
+            self.register_slave(slave_id.to_string())?;
+        }
+
+        self.evict_stale_snapshots();
+        if since < *self.trimmed_seq.lock().unwrap() {
+            let snapshot = snapshots()?;
+            let data = bincode::serialize(&snapshot).map_err(|e| format!("Failed to encode snapshot: {}", e))?;
+            let pending = PendingSnapshot {
+                seq: snapshot.seq,
+                data: Arc::new(data),
+                touched: Instant::now(),
+            };
+            self.snapshot_transfers.lock().unwrap().insert(slave_id.to_string(), pending);
+            return self.snapshot_chunk(slave_id, snapshot.seq, 0);
+        }
+
+        // The slave has moved past any snapshot it was sent
+        self.snapshot_transfers.lock().unwrap().remove(slave_id);
+        if let Some(state) = self.slave_states.write().unwrap().get_mut(slave_id) {
+            state.snapshot_transfer = None;
+        }
+        if since > 0 {
+            self.update_slave_ack(slave_id, since)?;
+        }
+
+        let entries = bincode::serialize(&self.get_entries_since(since))
+            .map_err(|e| format!("Failed to encode replication entries: {}", e))?;
+        Ok(MessageType::ReplicationEntries { entries })
+    }
+
+    /// The part of a slave's snapshot starting at `offset`
+    fn snapshot_chunk(&self, slave_id: &str, seq: ReplicationSeq, offset: u64) -> Result<MessageType, String> {
+        self.evict_stale_snapshots();
+        let data = match self.snapshot_transfers.lock().unwrap().get_mut(slave_id) {
+            Some(pending) if pending.seq == seq => {
+                pending.touched = Instant::now();
+                Arc::clone(&pending.data)
+            }
+            _ => return Err(format!("No snapshot at {} is being sent to {}", seq, slave_id)),
+        };
+
+        let start = (offset as usize).min(data.len());
+        let end = (start + self.config.snapshot_chunk_size).min(data.len());
+        if let Some(state) = self.slave_states.write().unwrap().get_mut(slave_id) {
+            state.last_contact = current_timestamp();
+            state.snapshot_transfer = Some(SnapshotTransfer {
+                seq,
+                sent_bytes: end as u64,
+                total_bytes: data.len() as u64,
+            });
+        }
+
+        Ok(MessageType::SnapshotChunk {
+            seq,
+            offset: start as u64,
+            total: data.len() as u64,
+            data: data[start..end].to_vec(),
+        })
+    }
+
+    /// Drop the snapshots of slaves that stopped fetching them `snapshot_ttl` ago
+    fn evict_stale_snapshots(&self) {
+        let mut evicted = Vec::new();
+        self.snapshot_transfers.lock().unwrap().retain(|slave_id, pending| {
+            let fresh = pending.touched.elapsed() < self.config.snapshot_ttl;
+            if !fresh {
+                evicted.push(slave_id.clone());
+            }
+            fresh
+        });
+
+        let mut slaves = self.slave_states.write().unwrap();
+        for slave_id in evicted {
+            if let Some(state) = slaves.get_mut(&slave_id) {
+                state.snapshot_transfer = None;
+            }
+        }
+    }
+
+    /// Fetch what comes after the last applied entry from the master and apply it (slave only)
+    ///
+    /// That is at most one batch of entries, applied in order, or a snapshot
+    /// when the master no longer has the entries.
+    pub async fn pull_from_master(
+        &self,
+        graph: &RwLock<Graph>,
+        network: &P2PNetwork,
+        master: NodeId,
+    ) -> Result<Pulled, String> {
+        if self.config.role != NodeRole::Slave {
+            return Err("Only slave can pull from the master".to_string());
+        }
+
+        let request = MessageType::ReplicationRequest {
+            slave_id: self.config.node_id.clone(),
+            since: self.last_applied_seq(),
+        };
+        let entries: Vec<ReplicationEntry> = match network.send_message(master, request).await?.message_type {
+            MessageType::ReplicationEntries { entries } => {
+                bincode::deserialize(&entries).map_err(|e| format!("Malformed replication entries: {}", e))?
+            }
+            MessageType::SnapshotChunk { seq, total, data, .. } => {
+                let snapshot = self.fetch_snapshot(network, master, seq, total, data).await?;
+                self.install_snapshot(&graph.read().unwrap(), &snapshot)?;
+                return Ok(Pulled::Snapshot(seq));
+            }
+            MessageType::Error { message } => return Err(message),
+            _ => return Err("Master sent no replication entries".to_string()),
+        };
+
+        let graph = graph.read().unwrap();
+        let mut applied = 0;
+        for entry in &entries {
+            if self.apply_to_graph(&graph, entry)? {
+                applied += 1;
+            }
+        }
+        Ok(Pulled::Entries(applied))
+    }
+
+    /// Request the rest of a snapshot whose first chunk is `data`, then decode it
+    async fn fetch_snapshot(
+        &self,
+        network: &P2PNetwork,
+        master: NodeId,
+        seq: ReplicationSeq,
+        total: u64,
+        mut data: Vec<u8>,
+    ) -> Result<ReplicationSnapshot, String> {
+        while (data.len() as u64) < total {
+            let request = MessageType::SnapshotChunkRequest {
+                slave_id: self.config.node_id.clone(),
+                seq,
+                offset: data.len() as u64,
+            };
+            match network.send_message(master, request).await?.message_type {
+                MessageType::SnapshotChunk { offset, data: chunk, .. }
+                    if offset == data.len() as u64 && !chunk.is_empty() =>
+                {
+                    data.extend_from_slice(&chunk)
+                }
+                MessageType::Error { message } => return Err(message),
+                _ => return Err("Master sent no snapshot chunk".to_string()),
+            }
+        }
+
+        bincode::deserialize(&data).map_err(|e| format!("Malformed snapshot: {}", e))
+    }
+
+    /// Keep pulling from the master in the background (slave only)
+    ///
+    /// Pulls again at once after a full batch or a snapshot, otherwise every
+    /// `interval`. Failed pulls are reported and retried. Stops between pulls
+    /// once `shutdown` is cancelled.
+    pub fn start_pulling(
+        self: &Arc<Self>,
+        graph: Arc<RwLock<Graph>>,
+        network: Arc<P2PNetwork>,
+        master: NodeId,
+        interval: Duration,
+        shutdown: &ShutdownSignal,
+    ) -> BackgroundTask {
+        let slave = Arc::clone(self);
+        BackgroundTask::spawn("replication-pull", shutdown, |shutdown| async move {
+            while !shutdown.is_cancelled() {
+                match slave.pull_from_master(&graph, &network, master).await {
+                    Ok(Pulled::Entries(applied)) if applied < slave.config.batch_size => {}
+                    Ok(_) => continue,
+                    Err(e) => eprintln!("Replication pull from {} failed: {}", master, e),
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        })
+    }
+
     /// Get replication lag (slave only)
     pub fn get_replication_lag(&self) -> u64 {
         if self.config.role != NodeRole::Slave {
@@ -322,6 +862,8 @@ impl ReplicationManager {
             last_ack_seq: 0,
             last_contact: current_timestamp(),
             lag_ms: 0,
+            applied_timestamp_ms: None,
+            snapshot_transfer: None,
         };
 
         self.slave_states.write().unwrap().insert(slave_id, state);
@@ -334,27 +876,89 @@ impl ReplicationManager {
             return Err("Only master can update slave ack".to_string());
         }
 
+        // Applied time is the master timestamp of the acknowledged entry
+        let applied_timestamp_ms = self.log.read().unwrap()
+            .iter()
+            .find(|entry| entry.seq() == ack_seq)
+            .map(|entry| entry.timestamp() * 1000)
+            .unwrap_or_else(current_timestamp_ms);
+
+        self.update_slave_applied(slave_id, ack_seq, applied_timestamp_ms)
+    }
+
+    /// Record the sequence and master wall-clock time (ms) a slave has applied (master only)
+    pub fn update_slave_applied(
+        &self,
+        slave_id: &str,
+        ack_seq: ReplicationSeq,
+        applied_timestamp_ms: u64,
+    ) -> Result<(), String> {
+        if self.config.role != NodeRole::Master {
+            return Err("Only master can update slave ack".to_string());
+        }
+
+        let latest_seq = self.latest_seq();
         let mut slaves = self.slave_states.write().unwrap();
         if let Some(state) = slaves.get_mut(slave_id) {
             state.last_ack_seq = ack_seq;
             state.last_contact = current_timestamp();
-
-            // Calculate lag
-            let current_seq = *self.next_seq.lock().unwrap();
-            state.lag_ms = ((current_seq - ack_seq) as u64) * 10; // Approximate
+            state.applied_timestamp_ms = Some(applied_timestamp_ms);
+            state.lag_ms = Self::staleness_of(state, latest_seq)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(u64::MAX);
         }
 
         Ok(())
     }
 
+    /// Estimated staleness of a slave's data (master only)
+    ///
+    /// Zero when the slave has applied the latest logged entry, otherwise the
+    /// wall-clock age of the last entry it applied. `None` if the slave is
+    /// unknown or has not applied anything yet while the log is non-empty.
+    pub fn slave_staleness(&self, slave_id: &str) -> Option<Duration> {
+        let latest_seq = self.latest_seq();
+        let slaves = self.slave_states.read().unwrap();
+        slaves.get(slave_id).and_then(|state| Self::staleness_of(state, latest_seq))
+    }
+
+    /// Staleness of every registered slave, for read routing (master only)
+    pub fn replica_staleness(&self) -> Vec<(String, Option<Duration>)> {
+        let latest_seq = self.latest_seq();
+        let slaves = self.slave_states.read().unwrap();
+        slaves
+            .values()
+            .map(|state| (state.slave_id.clone(), Self::staleness_of(state, latest_seq)))
+            .collect()
+    }
+
+    fn staleness_of(state: &SlaveState, latest_seq: Option<ReplicationSeq>) -> Option<Duration> {
+        let latest_seq = match latest_seq {
+            Some(seq) => seq,
+            None => return Some(Duration::ZERO), // Nothing to replicate yet
+        };
+
+        let applied_at = state.applied_timestamp_ms?;
+        if state.last_ack_seq >= latest_seq {
+            Some(Duration::ZERO)
+        } else {
+            Some(Duration::from_millis(current_timestamp_ms().saturating_sub(applied_at)))
+        }
+    }
+
+    /// Sequence number of the most recently logged entry
+    fn latest_seq(&self) -> Option<ReplicationSeq> {
+        Some(self.current_seq()).filter(|seq| *seq > 0)
+    }
+
     /// Get all slave states (master only)
     pub fn get_slave_states(&self) -> Vec<SlaveState> {
         self.slave_states.read().unwrap().values().cloned().collect()
     }
 
-    /// Get current sequence number
+    /// Get current sequence number (of the last logged entry, 0 before any)
     pub fn current_seq(&self) -> ReplicationSeq {
-        *self.next_seq.lock().unwrap()
+        *self.last_seq.lock().unwrap()
     }
 
     /// Get log size
@@ -362,16 +966,26 @@ impl ReplicationManager {
         self.log.read().unwrap().len()
     }
 
-    /// Trim log up to sequence (safe after all slaves have acknowledged)
-    pub fn trim_log(&self, up_to_seq: ReplicationSeq) {
+    /// Trim log up to sequence
+    ///
+    /// Slaves that haven't applied the trimmed entries catch up from a
+    /// snapshot. Segment files holding only trimmed entries are deleted.
+    pub fn trim_log(&self, up_to_seq: ReplicationSeq) -> Result<(), String> {
         let mut log = self.log.write().unwrap();
+        let mut trimmed_seq = self.trimmed_seq.lock().unwrap();
         while let Some(entry) = log.front() {
             if entry.seq() <= up_to_seq {
+                *trimmed_seq = entry.seq();
                 log.pop_front();
             } else {
                 break;
             }
         }
+
+        match &self.segments {
+            Some(segments) => segments.lock().unwrap().trim(*trimmed_seq),
+            None => Ok(()),
+        }
     }
 
     /// Get minimum acknowledged sequence across all slaves
@@ -382,17 +996,10 @@ impl ReplicationManager {
             .min()
             .unwrap_or(0)
     }
-
-    fn get_next_seq(&self) -> ReplicationSeq {
-        let mut seq = self.next_seq.lock().unwrap();
-        let current = *seq;
-        *seq += 1;
-        current
-    }
 }
 
 /// Replication statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ReplicationStats {
     pub node_id: String,
     pub role: NodeRole,
@@ -420,6 +1027,144 @@ impl ReplicationManager {
     }
 }
 
+/// Replication log kept on disk as append-only segment files
+///
+/// Each segment is named after the sequence number of its first entry and
+/// holds frames of a little-endian u32 length, the CRC-32 of the entry as a
+/// little-endian u32, then the bincode-encoded entry. Every append is synced
+/// to disk before it returns.
+struct LogSegments {
+    dir: PathBuf,
+    segment_size: u64,
+    /// First sequence number of each segment, oldest first
+    firsts: Vec<ReplicationSeq>,
+    /// Segment being appended to, and its length
+    active: Option<(File, u64)>,
+}
+
+impl LogSegments {
+    /// Open the segments in `dir`, returning the entries they hold
+    fn open(dir: &Path, segment_size: u64) -> Result<(Self, Vec<ReplicationEntry>), String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create replication log directory: {}", e))?;
+
+        let mut firsts: Vec<ReplicationSeq> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read replication log directory: {}", e))?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "rlog" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        firsts.sort_unstable();
+
+        let mut entries = Vec::new();
+        let mut active = None;
+        for (i, first) in firsts.iter().enumerate() {
+            let path = segment_path(dir, *first);
+            let bytes = std::fs::read(&path).map_err(|e| format!("Failed to read replication log: {}", e))?;
+
+            let mut valid = 0;
+            while let Some(header) = bytes.get(valid..valid + FRAME_HEADER) {
+                let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+                let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+                let end = valid + FRAME_HEADER + len;
+                let Some(frame) = bytes.get(valid + FRAME_HEADER..end) else {
+                    break;
+                };
+                let entry = match bincode::deserialize(frame) {
+                    Ok(entry) if crc32fast::hash(frame) == crc => entry,
+                    // Only the last frame can have been torn
+                    _ if end < bytes.len() => {
+                        return Err(format!("Corrupt replication log segment {}", path.display()));
+                    }
+                    _ => break,
+                };
+                entries.push(entry);
+                valid = end;
+            }
+
+            if i + 1 < firsts.len() {
+                if valid < bytes.len() {
+                    return Err(format!("Corrupt replication log segment {}", path.display()));
+                }
+            } else {
+                // A torn write at the end of the newest segment is cut off
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .and_then(|file| file.set_len(valid as u64).and_then(|_| file.sync_all()).map(|_| file))
+                    .map_err(|e| format!("Failed to open replication log: {}", e))?;
+                active = Some((file, valid as u64));
+            }
+        }
+
+        let segments = LogSegments {
+            dir: dir.to_path_buf(),
+            segment_size,
+            firsts,
+            active,
+        };
+        Ok((segments, entries))
+    }
+
+    /// Append an entry, starting a new segment once the current one is full
+    fn append(&mut self, entry: &ReplicationEntry) -> Result<(), String> {
+        let encoded = bincode::serialize(entry).map_err(|e| format!("Failed to encode replication entry: {}", e))?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER + encoded.len());
+        frame.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&encoded).to_le_bytes());
+        frame.extend_from_slice(&encoded);
+
+        if !matches!(&self.active, Some((_, len)) if *len < self.segment_size) {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.dir, entry.seq()))
+                .and_then(|file| sync_dir(&self.dir).map(|_| file))
+                .map_err(|e| format!("Failed to create replication log segment: {}", e))?;
+            self.firsts.push(entry.seq());
+            self.active = Some((file, 0));
+        }
+
+        let (file, len) = self.active.as_mut().unwrap();
+        if let Err(e) = file.write_all(&frame).and_then(|_| file.sync_data()) {
+            // Don't leave a partial frame for the next entry to follow
+            let _ = file.set_len(*len);
+            return Err(format!("Failed to write replication log: {}", e));
+        }
+        *len += frame.len() as u64;
+        Ok(())
+    }
+
+    /// Delete the segments holding nothing after `up_to`, keeping the newest
+    fn trim(&mut self, up_to: ReplicationSeq) -> Result<(), String> {
+        while self.firsts.len() > 1 && self.firsts[1] <= up_to + 1 {
+            std::fs::remove_file(segment_path(&self.dir, self.firsts[0]))
+                .map_err(|e| format!("Failed to delete replication log segment: {}", e))?;
+            self.firsts.remove(0);
+        }
+        Ok(())
+    }
+}
+
+/// Bytes before each entry in a log segment: its length and CRC-32
+const FRAME_HEADER: usize = 8;
+
+fn segment_path(dir: &Path, first: ReplicationSeq) -> PathBuf {
+    dir.join(format!("{:020}.rlog", first))
+}
+
+/// Make a file created in `dir` survive a crash
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
 fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -427,6 +1172,13 @@ fn current_timestamp() -> u64 {
         .as_secs()
 }
 
+fn current_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -455,7 +1207,7 @@ mod tests {
         props.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
 
         let seq = master.log_insert(1, "User".to_string(), props).unwrap();
-        assert_eq!(seq, 0);
+        assert_eq!(seq, 1);
         assert_eq!(master.log_size(), 1);
     }
 
@@ -470,9 +1222,9 @@ mod tests {
         let seq2 = master.log_update(1, props.clone()).unwrap();
         let seq3 = master.log_delete(1).unwrap();
 
-        assert_eq!(seq1, 0);
-        assert_eq!(seq2, 1);
-        assert_eq!(seq3, 2);
+        assert_eq!(seq1, 1);
+        assert_eq!(seq2, 2);
+        assert_eq!(seq3, 3);
         assert_eq!(master.log_size(), 3);
     }
 
@@ -543,8 +1295,201 @@ mod tests {
 
         assert_eq!(master.log_size(), 10);
 
-        master.trim_log(5);
-        assert_eq!(master.log_size(), 4); // Sequences 6, 7, 8, 9 remain
+        master.trim_log(5).unwrap();
+        assert_eq!(master.log_size(), 5); // Sequences 6 through 10 remain
+    }
+
+    /// A master's log of two users, an edge between them, and changes to both
+    fn mixed_log() -> ReplicationManager {
+        let master = ReplicationManager::new_master("master-1".to_string());
+        let mut props = HashMap::new();
+        props.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
+
+        master.log_insert(1, "User".to_string(), props.clone()).unwrap();
+        master.log_insert(2, "User".to_string(), props.clone()).unwrap();
+        master.log_create_edge(1, 1, 2, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        master.log_update_edge(1, props.clone()).unwrap();
+        master.log_update(2, HashMap::new()).unwrap();
+        master.log_create_edge(2, 2, 1, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        master.log_delete_edge(1).unwrap();
+        master.log_delete(1).unwrap();
+        master
+    }
+
+    #[test]
+    fn test_apply_to_graph() {
+        let master = mixed_log();
+        let slave = ReplicationManager::new_slave("slave-1".to_string(), "localhost:9000".to_string());
+        let graph = Graph::new();
+
+        // Apply part of the log, then all of it again
+        let entries = master.get_entries_since(0);
+        for entry in &entries[..4] {
+            assert!(slave.apply_to_graph(&graph, entry).unwrap());
+        }
+        let applied = entries.iter().filter(|e| slave.apply_to_graph(&graph, e).unwrap()).count();
+        assert_eq!(applied, 4);
+        assert_eq!(slave.last_applied_seq(), 8);
+
+        // Replaying the whole log over it changes nothing
+        let fresh = ReplicationManager::new_slave("slave-2".to_string(), "localhost:9000".to_string());
+        for entry in &entries {
+            fresh.apply_to_graph(&graph, entry).unwrap();
+        }
+
+        assert_eq!(graph.entity_count(), 1);
+        let user = graph.get_entity(EntityId::new(2)).unwrap();
+        assert!(user.properties.is_empty());
+        assert_eq!(graph.get_all_edges().len(), 0);
+        assert!(graph.get_outgoing_neighbors(EntityId::new(2), None).is_empty());
+    }
+
+    #[test]
+    fn test_applied_inserts_keep_their_creation_time() {
+        let master = ReplicationManager::new_master("master-1".to_string());
+        let slave = ReplicationManager::new_slave("slave-1".to_string(), "localhost:9000".to_string());
+        master.log_insert_created(1, "Sessions".to_string(), HashMap::new(), 1_000).unwrap();
+
+        let graph = Graph::new();
+        slave.apply_to_graph(&graph, &master.get_entries_since(0)[0]).unwrap();
+        assert_eq!(graph.get_entity(EntityId::new(1)).unwrap().created_millis(), 1_000);
+    }
+
+    #[test]
+    fn test_apply_rejects_gaps() {
+        let master = mixed_log();
+        let slave = ReplicationManager::new_slave("slave-1".to_string(), "localhost:9000".to_string());
+        let graph = Graph::new();
+
+        let entries = master.get_entries_since(0);
+        let err = slave.apply_to_graph(&graph, &entries[1]).unwrap_err();
+        assert!(err.contains("expected entry 1, got 2"), "{}", err);
+        assert_eq!(graph.entity_count(), 0);
+
+        assert!(master.apply_to_graph(&graph, &entries[0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slave_pulls_over_p2p() {
+        use crate::distributed_p2p::P2PConfig;
+        use crate::distributed_topology::NodeAddress;
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = P2PConfig {
+            listen_port: port,
+            insecure: true,
+            ..Default::default()
+        };
+        let master_network = P2PNetwork::new(1, NodeAddress::new("127.0.0.1".to_string(), port), config.clone());
+        let master = Arc::new(ReplicationManager::new(ReplicationConfig {
+            batch_size: 3,
+            ..ReplicationConfig::default()
+        }));
+        master.log_insert(1, "User".to_string(), HashMap::new()).unwrap();
+        master.log_insert(2, "User".to_string(), HashMap::new()).unwrap();
+        master.log_create_edge(1, 1, 2, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        master.log_delete(1).unwrap();
+        master
+            .serve_slaves(&master_network, Arc::new(|| Err("Nothing was trimmed".to_string())))
+            .unwrap();
+        master_network.start_listener().await.unwrap();
+
+        let slave_network = P2PNetwork::new(2, NodeAddress::new("127.0.0.1".to_string(), 0), config);
+        slave_network.add_peer(1, NodeAddress::new("127.0.0.1".to_string(), port));
+        let slave = ReplicationManager::new_slave("slave-1".to_string(), format!("127.0.0.1:{}", port));
+        let graph = RwLock::new(Graph::new());
+
+        // One batch per pull, until the slave has caught up
+        assert_eq!(slave.pull_from_master(&graph, &slave_network, 1).await.unwrap(), Pulled::Entries(3));
+        assert_eq!(graph.read().unwrap().get_all_edges().len(), 1);
+        assert_eq!(slave.pull_from_master(&graph, &slave_network, 1).await.unwrap(), Pulled::Entries(1));
+        assert_eq!(slave.pull_from_master(&graph, &slave_network, 1).await.unwrap(), Pulled::Entries(0));
+        assert_eq!(graph.read().unwrap().entity_count(), 1);
+        assert!(graph.read().unwrap().get_all_edges().is_empty());
+
+        // Pulling acknowledged what the slave applied
+        let slaves = master.get_slave_states();
+        assert_eq!(slaves[0].slave_id, "slave-1");
+        assert_eq!(slaves[0].last_ack_seq, 4);
+    }
+
+    #[test]
+    fn test_log_survives_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = ReplicationConfig {
+            segment_size: 64,
+            ..ReplicationConfig::default()
+        };
+
+        let master = ReplicationManager::open(config.clone(), dir.path()).unwrap();
+        for i in 1..=6 {
+            master.log_insert(i, "User".to_string(), HashMap::new()).unwrap();
+        }
+        master.log_delete(2).unwrap();
+        drop(master);
+
+        // A torn write at the end of the log is dropped
+        let newest = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).max().unwrap();
+        OpenOptions::new().append(true).open(&newest).unwrap().write_all(&[9, 0, 0, 0, 1]).unwrap();
+
+        let master = ReplicationManager::open(config.clone(), dir.path()).unwrap();
+        assert_eq!(master.current_seq(), 7);
+        assert_eq!(master.get_entries_since(0).len(), 7);
+        assert_eq!(master.log_insert(7, "User".to_string(), HashMap::new()).unwrap(), 8);
+
+        // Trimming deletes segments holding only trimmed entries
+        let segments = std::fs::read_dir(dir.path()).unwrap().count();
+        assert!(segments > 2);
+        master.trim_log(6).unwrap();
+        assert!(std::fs::read_dir(dir.path()).unwrap().count() < segments);
+        drop(master);
+
+        let master = ReplicationManager::open(config.clone(), dir.path()).unwrap();
+        assert_eq!(master.current_seq(), 8);
+        let entries = master.get_entries_since(6);
+        assert_eq!(entries.iter().map(|e| e.seq()).collect::<Vec<_>>(), vec![7, 8]);
+        drop(master);
+
+        // A damaged entry the log goes on past fails its checksum
+        let oldest = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).min().unwrap();
+        let mut bytes = std::fs::read(&oldest).unwrap();
+        bytes[FRAME_HEADER + 1] ^= 0xff;
+        std::fs::write(&oldest, bytes).unwrap();
+        let err = ReplicationManager::open(config, dir.path()).unwrap_err();
+        assert!(err.starts_with("Corrupt replication log segment"), "{}", err);
+    }
+
+    #[test]
+    fn test_abandoned_snapshot_transfer_expires() {
+        let master = ReplicationManager::new(ReplicationConfig {
+            snapshot_ttl: Duration::from_millis(50),
+            ..ReplicationConfig::default()
+        });
+        master.log_insert(1, "User".to_string(), HashMap::new()).unwrap();
+        master.trim_log(1).unwrap();
+        let snapshots: SnapshotSource = Arc::new(|| {
+            Ok(ReplicationSnapshot {
+                seq: 1,
+                entities: vec![Entity::new(EntityId::new(1), "User".to_string(), HashMap::new())],
+                edges: Vec::new(),
+            })
+        });
+
+        assert!(matches!(
+            master.answer_request("slave-1", 0, &snapshots).unwrap(),
+            MessageType::SnapshotChunk { seq: 1, .. }
+        ));
+        assert!(master.snapshot_chunk("slave-1", 1, 0).is_ok());
+
+        // Another slave's request drops the snapshot the first stopped fetching
+        std::thread::sleep(Duration::from_millis(100));
+        master.answer_request("slave-2", 1, &snapshots).unwrap();
+        assert_eq!(
+            master.snapshot_chunk("slave-1", 1, 0).unwrap_err(),
+            "No snapshot at 1 is being sent to slave-1"
+        );
+        let state = master.get_slave_states().into_iter().find(|s| s.slave_id == "slave-1").unwrap();
+        assert_eq!(state.snapshot_transfer, None);
     }
 
     #[test]
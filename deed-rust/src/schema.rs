@@ -3,17 +3,24 @@
 //! Provides optional schema enforcement for collections.
 //! Collections can be schema-less (default) or schema-enforced.
 
-use crate::types::{PropertyValue, Properties};
+use crate::types::{parse_timestamp, PropertyValue, Properties};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::time::Duration;
 
 /// Schema definition for a collection
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Schema {
     pub collection: String,
     pub fields: Vec<Field>,
     pub allow_extra_properties: bool,
+    /// Store ISO-8601 strings written to Timestamp fields as timestamps
+    #[serde(default)]
+    pub coerce_timestamps: bool,
     pub indexes: Vec<String>, // Indexed field names
+    /// How long the collection's entities live after they're created
+    #[serde(default)]
+    pub ttl: Option<Duration>,
 }
 
 impl Schema {
@@ -22,7 +29,9 @@ impl Schema {
             collection,
             fields: Vec::new(),
             allow_extra_properties: false,
+            coerce_timestamps: false,
             indexes: Vec::new(),
+            ttl: None,
         }
     }
 
@@ -58,7 +67,7 @@ impl Schema {
 }
 
 /// Field definition within a schema
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Field {
     pub name: String,
     pub field_type: FieldType,
@@ -123,6 +132,12 @@ impl FieldType {
             (FieldType::Float, PropertyValue::Float(_)) => true,
             (FieldType::Boolean, PropertyValue::Bool(_)) => true,
             (FieldType::Bytes, PropertyValue::Bytes(_)) => true,
+            // ISO strings only once coerced (see `Schema::coerce_timestamps`)
+            (FieldType::Timestamp, PropertyValue::Timestamp(_)) => true,
+            // Every element must match
+            (FieldType::Array(element), PropertyValue::List(items)) => items.iter().all(|item| element.matches(item)),
+            // Any document JSON can represent, so no bytes at any depth
+            (FieldType::Json, value) => is_json(value),
             // Allow int for float (coercion)
             (FieldType::Float, PropertyValue::Int(_)) => true,
             // Null matches any type (unless NOT NULL constraint)
@@ -146,6 +161,16 @@ impl FieldType {
     }
 }
 
+/// Whether a value has a JSON representation
+fn is_json(value: &PropertyValue) -> bool {
+    match value {
+        PropertyValue::Bytes(_) => false,
+        PropertyValue::List(items) => items.iter().all(is_json),
+        PropertyValue::Map(map) => map.values().all(is_json),
+        _ => true,
+    }
+}
+
 /// Field constraints
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Constraint {
@@ -166,7 +191,10 @@ pub enum ValidationError {
         expected: String,
         got: String,
     },
-    UniqueViolation(String),
+    UniqueViolation {
+        field: String,
+        value: PropertyValue,
+    },
     CheckFailed {
         field: String,
         expression: String,
@@ -187,8 +215,15 @@ impl std::fmt::Display for ValidationError {
                 "Type mismatch for '{}': expected {}, got {}",
                 field, expected, got
             ),
-            ValidationError::UniqueViolation(field) => {
-                write!(f, "UNIQUE constraint violation on '{}'", field)
+            ValidationError::UniqueViolation { field, value } => {
+                write!(f, "UNIQUE constraint violation on '{}': ", field)?;
+                match value {
+                    PropertyValue::String(s) => write!(f, "'{}' already exists", s),
+                    PropertyValue::Int(i) => write!(f, "{} already exists", i),
+                    PropertyValue::Float(x) => write!(f, "{} already exists", x),
+                    PropertyValue::Bool(b) => write!(f, "{} already exists", b),
+                    other => write!(f, "{:?} already exists", other),
+                }
             }
             ValidationError::CheckFailed { field, expression } => {
                 write!(f, "CHECK constraint failed for '{}': {}", field, expression)
@@ -200,6 +235,9 @@ impl std::fmt::Display for ValidationError {
     }
 }
 
+/// Evaluates a CHECK expression given the field's value and every property being written
+pub type CheckFn<'a> = &'a dyn Fn(&str, &PropertyValue, &Properties) -> Result<bool, String>;
+
 /// Schema validator
 pub struct SchemaValidator {
     schemas: HashMap<String, Schema>,
@@ -227,6 +265,11 @@ impl SchemaValidator {
         self.schemas.remove(collection)
     }
 
+    /// Every registered schema
+    pub fn schemas(&self) -> impl Iterator<Item = &Schema> {
+        self.schemas.values()
+    }
+
     /// Check if collection has a schema
     pub fn has_schema(&self, collection: &str) -> bool {
         self.schemas.contains_key(collection)
@@ -237,9 +280,22 @@ impl SchemaValidator {
         &self,
         collection: &str,
         properties: &Properties,
+    ) -> Result<(), ValidationError> {
+        self.validate_insert_with(collection, properties, &|expr, value, _| Ok(self.evaluate_check(value, expr)))
+    }
+
+    /// Validate properties against schema, evaluating CHECK constraints with `check`
+    ///
+    /// `check` gets the CHECK expression, the field's value and all the
+    /// properties being written. NULL values pass CHECK constraints.
+    pub fn validate_insert_with(
+        &self,
+        collection: &str,
+        properties: &Properties,
+        check: CheckFn,
     ) -> Result<(), ValidationError> {
         if let Some(schema) = self.get_schema(collection) {
-            self.validate_against_schema(schema, properties)
+            self.validate_against_schema(schema, properties, check)
         } else {
             // No schema - allow anything
             Ok(())
@@ -251,6 +307,16 @@ impl SchemaValidator {
         &self,
         collection: &str,
         updates: &HashMap<String, PropertyValue>,
+    ) -> Result<(), ValidationError> {
+        self.validate_update_with(collection, updates, &|expr, value, _| Ok(self.evaluate_check(value, expr)))
+    }
+
+    /// Validate updates against schema, evaluating CHECK constraints with `check`
+    pub fn validate_update_with(
+        &self,
+        collection: &str,
+        updates: &HashMap<String, PropertyValue>,
+        check: CheckFn,
     ) -> Result<(), ValidationError> {
         if let Some(schema) = self.get_schema(collection) {
             // Check each update field
@@ -272,17 +338,8 @@ impl SchemaValidator {
                         return Err(ValidationError::FieldRequired(field_name.clone()));
                     }
 
-                    // Check CHECK constraint (simplified - would need expression evaluator)
-                    if field.has_constraint(&Constraint::Check) {
-                        if let Some(expr) = &field.check_expression {
-                            if !self.evaluate_check(value, expr) {
-                                return Err(ValidationError::CheckFailed {
-                                    field: field_name.clone(),
-                                    expression: expr.clone(),
-                                });
-                            }
-                        }
-                    }
+                    // Check CHECK constraint
+                    self.check_field(field, value, updates, check)?;
                 } else if !schema.allow_extra_properties {
                     return Err(ValidationError::UnknownField(field_name.clone()));
                 }
@@ -308,24 +365,41 @@ impl SchemaValidator {
         }
     }
 
+    /// Convert ISO-8601 strings in Timestamp fields to timestamps, if the
+    /// collection's schema coerces them
+    ///
+    /// Strings that don't parse are left for validation to reject.
+    pub fn coerce_timestamps(&self, collection: &str, properties: &mut Properties) {
+        let Some(schema) = self.get_schema(collection).filter(|schema| schema.coerce_timestamps) else {
+            return;
+        };
+
+        for field in schema.fields.iter().filter(|field| field.field_type == FieldType::Timestamp) {
+            if let Some(value) = properties.get_mut(&field.name) {
+                if let Some(ms) = value.as_str().and_then(parse_timestamp) {
+                    *value = PropertyValue::Timestamp(ms);
+                }
+            }
+        }
+    }
+
     /// Validate properties against a specific schema
     fn validate_against_schema(
         &self,
         schema: &Schema,
         properties: &Properties,
+        check: CheckFn,
     ) -> Result<(), ValidationError> {
         // Check all required fields are present
         for field in &schema.fields {
-            if field.has_constraint(&Constraint::NotNull)
-                || field.has_constraint(&Constraint::PrimaryKey)
-            {
-                if !properties.contains_key(&field.name) {
+            if (field.has_constraint(&Constraint::NotNull)
+                || field.has_constraint(&Constraint::PrimaryKey))
+                && !properties.contains_key(&field.name) {
                     // Check if has default
                     if field.get_default().is_none() {
                         return Err(ValidationError::FieldRequired(field.name.clone()));
                     }
                 }
-            }
         }
 
         // Check all provided properties
@@ -347,17 +421,8 @@ impl SchemaValidator {
                     return Err(ValidationError::FieldRequired(prop_name.clone()));
                 }
 
-                // CHECK constraint (simplified)
-                if field.has_constraint(&Constraint::Check) {
-                    if let Some(expr) = &field.check_expression {
-                        if !self.evaluate_check(prop_value, expr) {
-                            return Err(ValidationError::CheckFailed {
-                                field: prop_name.clone(),
-                                expression: expr.clone(),
-                            });
-                        }
-                    }
-                }
+                // CHECK constraint
+                self.check_field(field, prop_value, properties, check)?;
             } else if !schema.allow_extra_properties {
                 return Err(ValidationError::UnknownField(prop_name.clone()));
             }
@@ -366,6 +431,29 @@ impl SchemaValidator {
         Ok(())
     }
 
+    /// Run a field's CHECK constraint, if it has one, on a non-NULL value
+    fn check_field(
+        &self,
+        field: &Field,
+        value: &PropertyValue,
+        properties: &Properties,
+        check: CheckFn,
+    ) -> Result<(), ValidationError> {
+        let expression = match &field.check_expression {
+            Some(expr) if field.has_constraint(&Constraint::Check) => expr,
+            _ => return Ok(()),
+        };
+
+        if matches!(value, PropertyValue::Null) || check(expression, value, properties) == Ok(true) {
+            Ok(())
+        } else {
+            Err(ValidationError::CheckFailed {
+                field: field.name.clone(),
+                expression: expression.clone(),
+            })
+        }
+    }
+
     /// Get type name from PropertyValue
     fn value_type_name(&self, value: &PropertyValue) -> String {
         match value {
@@ -375,6 +463,18 @@ impl SchemaValidator {
             PropertyValue::Float(_) => "Float".to_string(),
             PropertyValue::String(_) => "String".to_string(),
             PropertyValue::Bytes(_) => "Bytes".to_string(),
+            // Elements of one type name it; a mix is reported as such
+            PropertyValue::List(items) => {
+                let mut names: Vec<String> = items.iter().map(|item| self.value_type_name(item)).collect();
+                names.dedup();
+                match names.as_slice() {
+                    [] => "Array".to_string(),
+                    [name] => format!("Array<{}>", name),
+                    _ => "Array<Mixed>".to_string(),
+                }
+            }
+            PropertyValue::Map(_) => "Map".to_string(),
+            PropertyValue::Timestamp(_) => "Timestamp".to_string(),
         }
     }
 
@@ -437,7 +537,7 @@ mod tests {
     fn test_field_type_matching() {
         assert!(FieldType::String.matches(&PropertyValue::String("test".to_string())));
         assert!(FieldType::Integer.matches(&PropertyValue::Int(42)));
-        assert!(FieldType::Float.matches(&PropertyValue::Float(3.14)));
+        assert!(FieldType::Float.matches(&PropertyValue::Float(2.5)));
         assert!(FieldType::Boolean.matches(&PropertyValue::Bool(true)));
 
         // Float accepts int (coercion)
@@ -499,4 +599,66 @@ mod tests {
         // Stock should now have default value
         assert_eq!(props.get("stock"), Some(&PropertyValue::Int(0)));
     }
+
+    #[test]
+    fn test_nested_element_types() {
+        let mut validator = SchemaValidator::new();
+
+        let mut schema = Schema::new("Users".to_string());
+        schema.add_field(Field::new("tags".to_string(), FieldType::Array(Box::new(FieldType::String))));
+        schema.add_field(Field::new("address".to_string(), FieldType::Json));
+        validator.register_schema(schema);
+
+        let tags = |items: Vec<PropertyValue>| {
+            let mut props = Properties::new();
+            props.insert("tags".to_string(), PropertyValue::List(items));
+            props
+        };
+        let strings = vec![PropertyValue::String("a".to_string()), PropertyValue::String("b".to_string())];
+        assert!(validator.validate_insert("Users", &tags(strings)).is_ok());
+        assert!(validator.validate_insert("Users", &tags(Vec::new())).is_ok());
+
+        let mixed = vec![PropertyValue::String("a".to_string()), PropertyValue::Int(1)];
+        let err = validator.validate_insert("Users", &tags(mixed)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Type mismatch for 'tags': expected Array<String>, got Array<Mixed>"
+        );
+
+        let mut address = HashMap::new();
+        address.insert("city".to_string(), PropertyValue::String("Oslo".to_string()));
+        address.insert("zip".to_string(), PropertyValue::List(vec![PropertyValue::Int(150)]));
+        let mut props = Properties::new();
+        props.insert("address".to_string(), PropertyValue::Map(address.clone()));
+        assert!(validator.validate_insert("Users", &props).is_ok());
+
+        address.insert("raw".to_string(), PropertyValue::Bytes(vec![1]));
+        props.insert("address".to_string(), PropertyValue::Map(address));
+        assert!(validator.validate_insert("Users", &props).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_fields_coerce_only_when_enabled() {
+        let mut validator = SchemaValidator::new();
+        let mut schema = Schema::new("Events".to_string());
+        schema.add_field(Field::new("at".to_string(), FieldType::Timestamp));
+        validator.register_schema(schema.clone());
+
+        let at = |value: PropertyValue| Properties::from([("at".to_string(), value)]);
+        assert!(validator.validate_insert("Events", &at(PropertyValue::Timestamp(0))).is_ok());
+
+        let mut props = at(PropertyValue::String("1970-01-01T00:00:01Z".to_string()));
+        validator.coerce_timestamps("Events", &mut props);
+        assert!(validator.validate_insert("Events", &props).is_err());
+
+        schema.coerce_timestamps = true;
+        validator.register_schema(schema);
+        validator.coerce_timestamps("Events", &mut props);
+        assert_eq!(props, at(PropertyValue::Timestamp(1_000)));
+
+        let mut props = at(PropertyValue::String("not a time".to_string()));
+        validator.coerce_timestamps("Events", &mut props);
+        let err = validator.validate_insert("Events", &props).unwrap_err();
+        assert_eq!(err.to_string(), "Type mismatch for 'at': expected Timestamp, got String");
+    }
 }
@@ -0,0 +1,242 @@
+//! Orderly shutdown
+//!
+//! A [`ShutdownSignal`] tells background work to stop: threads sleep on it
+//! between rounds, async tasks await it. Work started as a
+//! [`BackgroundTask`] marks itself finished when it returns (or panics), so
+//! whoever stopped it can wait for it to wind down.
+
+use std::future::Future;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// A flag that is set once and can be waited on from threads and async tasks
+#[derive(Default)]
+struct Latch {
+    set: Mutex<bool>,
+    changed: Condvar,
+    /// Wakes async waiters
+    notify: Notify,
+}
+
+impl Latch {
+    fn set(&self) {
+        *self.set.lock().unwrap() = true;
+        self.changed.notify_all();
+        self.notify.notify_waiters();
+    }
+
+    fn is_set(&self) -> bool {
+        *self.set.lock().unwrap()
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let set = self.set.lock().unwrap();
+        *self.changed.wait_timeout_while(set, timeout, |set| !*set).unwrap().0
+    }
+
+    async fn wait(&self) {
+        loop {
+            // Registered before the check, so a `set` in between still wakes it
+            let notified = self.notify.notified();
+            if self.is_set() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Tells background work to stop
+///
+/// Clones share the signal; once cancelled it stays cancelled.
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    latch: Arc<Latch>,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tell everything holding the signal to stop
+    pub fn cancel(&self) {
+        self.latch.set();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.latch.is_set()
+    }
+
+    /// Sleep for up to `timeout`, waking early once cancelled
+    ///
+    /// Returns whether the signal was cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.latch.wait_timeout(timeout)
+    }
+
+    /// Wait until the signal is cancelled
+    pub async fn cancelled(&self) {
+        self.latch.wait().await
+    }
+}
+
+/// A background thread or task that stops when its signal is cancelled
+///
+/// Dropping the guard detaches the work; it still stops on the signal.
+pub struct BackgroundTask {
+    name: String,
+    finished: Arc<Latch>,
+}
+
+/// Marks a task finished when its work returns or unwinds
+struct FinishOnDrop(Arc<Latch>);
+
+impl Drop for FinishOnDrop {
+    fn drop(&mut self) {
+        self.0.set();
+    }
+}
+
+impl BackgroundTask {
+    /// Run `work` on a new thread, handing it the signal to watch
+    pub fn spawn_thread<F>(name: &str, shutdown: &ShutdownSignal, work: F) -> Self
+    where
+        F: FnOnce(ShutdownSignal) + Send + 'static,
+    {
+        let finished = Arc::new(Latch::default());
+        let guard = FinishOnDrop(finished.clone());
+        let shutdown = shutdown.clone();
+
+        thread::spawn(move || {
+            let _guard = guard;
+            work(shutdown)
+        });
+
+        BackgroundTask {
+            name: name.to_string(),
+            finished,
+        }
+    }
+
+    /// Run the future `work` returns on the tokio runtime, handing it the signal to watch
+    ///
+    /// The task also counts as finished if the runtime drops it.
+    pub fn spawn<F, Fut>(name: &str, shutdown: &ShutdownSignal, work: F) -> Self
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let finished = Arc::new(Latch::default());
+        let guard = FinishOnDrop(finished.clone());
+        let work = work(shutdown.clone());
+
+        tokio::spawn(async move {
+            let _guard = guard;
+            work.await
+        });
+
+        BackgroundTask {
+            name: name.to_string(),
+            finished,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_set()
+    }
+
+    /// Block for up to `timeout` until the work has finished, returning whether it has
+    ///
+    /// Don't call this on a runtime thread for a task on that runtime; await
+    /// [`BackgroundTask::finished`] instead.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.finished.wait_timeout(timeout)
+    }
+
+    /// Wait until the work has finished
+    pub async fn finished(&self) {
+        self.finished.wait().await
+    }
+}
+
+/// What a shutdown had to do
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Statements still running at the timeout, which were cancelled
+    pub cancelled_queries: usize,
+    /// Cancelled statements that hadn't stopped when shutdown went on
+    pub unstopped_queries: usize,
+    /// Open transactions that were rolled back
+    pub rolled_back_transactions: usize,
+    /// Background tasks that hadn't finished by the timeout, by name
+    pub unfinished_tasks: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// Add up the reports of shutting down several parts
+    pub(crate) fn merge(&mut self, other: ShutdownReport) {
+        self.cancelled_queries += other.cancelled_queries;
+        self.unstopped_queries += other.unstopped_queries;
+        self.rolled_back_transactions += other.rolled_back_transactions;
+        self.unfinished_tasks.extend(other.unfinished_tasks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn test_thread_stops_when_cancelled() {
+        let shutdown = ShutdownSignal::new();
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let task = BackgroundTask::spawn_thread("ticker", &shutdown, {
+            let rounds = rounds.clone();
+            move |shutdown| {
+                while !shutdown.wait_timeout(Duration::from_millis(5)) {
+                    rounds.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        assert!(!task.wait_timeout(Duration::from_millis(30)));
+        let started = Instant::now();
+        shutdown.cancel();
+        assert!(task.wait_timeout(Duration::from_secs(5)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(rounds.load(Ordering::Relaxed) > 0);
+        assert_eq!(task.name(), "ticker");
+
+        // Work started after the signal stops at once
+        let late = BackgroundTask::spawn_thread("late", &shutdown, |shutdown| while !shutdown.wait_timeout(Duration::from_secs(60)) {});
+        assert!(late.wait_timeout(Duration::from_secs(5)));
+    }
+
+    #[tokio::test]
+    async fn test_task_stops_when_cancelled() {
+        let shutdown = ShutdownSignal::new();
+        let task = BackgroundTask::spawn("sleeper", &shutdown, |shutdown| async move {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(60)) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        });
+        assert!(!task.is_finished());
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), task.finished()).await.unwrap();
+
+        // A task that panics still counts as finished
+        let panicking = BackgroundTask::spawn("panicking", &shutdown, |_| async { panic!("task failed") });
+        tokio::time::timeout(Duration::from_secs(5), panicking.finished()).await.unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+//! Memory budget and spilling
+//!
+//! GROUP BY and ORDER BY hold their working set in memory up to a
+//! [`MemoryBudget`]; past it they write it out to [`SpillFile`]s in the
+//! budget's directory and read it back to finish. Sizes are estimates of
+//! the bytes values occupy, not measured allocations.
+
+use crate::dql_ir::Value;
+use crate::types::{DistinctKey, PropertyValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Budget used unless configured: 256 MiB
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Memory a statement's GROUP BY and sorts may hold before spilling to disk
+///
+/// Spilled groups are merged back one partition at a time; a statement
+/// with a partition that still doesn't fit fails rather than going over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryBudget {
+    /// Estimated bytes held in memory
+    pub limit: usize,
+    /// Where spill files are written
+    pub spill_dir: PathBuf,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes, spilling to the system's temporary directory
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Spill to `dir` instead
+    pub fn with_spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = dir.into();
+        self
+    }
+
+    /// The error of a statement whose working set can't fit even by spilling
+    pub fn exceeded(&self) -> String {
+        format!("Query exceeded memory budget of {} bytes, even after spilling to disk", self.limit)
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget {
+            limit: DEFAULT_MEMORY_BUDGET,
+            spill_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// Memory a statement's operators held against its budget, and what they spilled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Most estimated bytes held at once
+    pub peak: usize,
+    /// Bytes written to spill files
+    pub spilled_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Note that `held` bytes are held now
+    pub fn hold(&mut self, held: usize) {
+        self.peak = self.peak.max(held);
+    }
+
+    /// Combine with the usage of another operator of the same statement
+    pub fn merge(&mut self, other: MemoryUsage) {
+        self.peak = self.peak.max(other.peak);
+        self.spilled_bytes += other.spilled_bytes;
+    }
+}
+
+/// Spill files created by this process, for unique names
+static SPILL_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary file of records, written once and then read back in order
+///
+/// The file is removed when the spill file (or its reader) is dropped.
+pub struct SpillFile<T> {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    records: usize,
+    bytes: usize,
+    _records: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SpillFile<T> {
+    /// Create an empty spill file in `dir`
+    pub fn create(dir: &Path) -> Result<Self, String> {
+        let number = SPILL_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("deed_spill_{}_{}", std::process::id(), number));
+        let file = File::create(&path).map_err(|e| format!("Failed to create spill file {}: {}", path.display(), e))?;
+
+        Ok(SpillFile {
+            path,
+            writer: BufWriter::new(file),
+            records: 0,
+            bytes: 0,
+            _records: PhantomData,
+        })
+    }
+
+    /// Append a record, returning the bytes it took
+    pub fn write(&mut self, record: &T) -> Result<usize, String> {
+        let bytes = bincode::serialize(record).map_err(|e| format!("Serialization error: {}", e))?;
+        self.writer
+            .write_all(&bytes)
+            .map_err(|e| format!("Failed to write spill file: {}", e))?;
+        self.records += 1;
+        self.bytes += bytes.len();
+        Ok(bytes.len())
+    }
+
+    /// Records written so far
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Bytes written so far
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Read the records back, in the order they were written
+    pub fn read(mut self) -> Result<SpillReader<T>, String> {
+        self.writer.flush().map_err(|e| format!("Failed to write spill file: {}", e))?;
+        let file = File::open(&self.path).map_err(|e| format!("Failed to read spill file: {}", e))?;
+
+        Ok(SpillReader {
+            path: std::mem::take(&mut self.path),
+            reader: BufReader::new(file),
+            remaining: self.records,
+            _records: PhantomData,
+        })
+    }
+}
+
+impl<T> Drop for SpillFile<T> {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Records of a [`SpillFile`], read back in order
+pub struct SpillReader<T> {
+    path: PathBuf,
+    reader: BufReader<File>,
+    remaining: usize,
+    _records: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for SpillReader<T> {
+    type Item = Result<T, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(bincode::deserialize_from(&mut self.reader).map_err(|e| format!("Failed to read spill file: {}", e)))
+    }
+}
+
+impl<T> Drop for SpillReader<T> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Records sorted within a memory budget
+///
+/// Records are gathered in memory until they outgrow the budget, then
+/// sorted and written out as a run. [`ExternalSort::finish`] merges the
+/// runs back lazily, holding one record of each at a time. Ties keep the
+/// order the records were added in, as in a stable sort.
+pub struct ExternalSort<T, F> {
+    compare: F,
+    budget: MemoryBudget,
+    /// Records not yet written to a run, and their estimated bytes
+    records: Vec<T>,
+    held: usize,
+    runs: Vec<SpillFile<T>>,
+    usage: MemoryUsage,
+}
+
+impl<T, F> ExternalSort<T, F>
+where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&T, &T) -> CmpOrdering,
+{
+    pub fn new(budget: &MemoryBudget, compare: F) -> Self {
+        ExternalSort {
+            compare,
+            budget: budget.clone(),
+            records: Vec::new(),
+            held: 0,
+            runs: Vec::new(),
+            usage: MemoryUsage::default(),
+        }
+    }
+
+    /// Add a record of an estimated `size` bytes
+    pub fn push(&mut self, record: T, size: usize) -> Result<(), String> {
+        self.records.push(record);
+        self.held += size;
+        self.usage.hold(self.held);
+        if self.held > self.budget.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Add records that were held elsewhere, writing them out as a run of
+    /// their own
+    pub fn push_run(&mut self, records: Vec<T>) -> Result<(), String> {
+        self.spill()?;
+        self.records = records;
+        self.spill()
+    }
+
+    /// Sort the records gathered so far and write them out as a run
+    fn spill(&mut self) -> Result<(), String> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        self.records.sort_by(&self.compare);
+        let mut run = SpillFile::create(&self.budget.spill_dir)?;
+        for record in self.records.drain(..) {
+            self.usage.spilled_bytes += run.write(&record)?;
+        }
+        self.runs.push(run);
+        self.held = 0;
+        Ok(())
+    }
+
+    /// The records in order, and the memory gathering them took
+    pub fn finish(mut self) -> Result<(Sorted<T, F>, MemoryUsage), String> {
+        if self.runs.is_empty() {
+            self.records.sort_by(&self.compare);
+            return Ok((Sorted::Memory(self.records.into_iter()), self.usage));
+        }
+
+        self.spill()?;
+        let runs = self.runs.into_iter().map(SpillFile::read).collect::<Result<Vec<_>, _>>()?;
+        let sorted = Sorted::Merge {
+            heads: Vec::new(),
+            runs,
+            compare: self.compare,
+        };
+        Ok((sorted, self.usage))
+    }
+}
+
+/// Records of an [`ExternalSort`], in order
+pub enum Sorted<T, F> {
+    /// Everything fit the budget
+    Memory(std::vec::IntoIter<T>),
+    /// Merged from spilled runs; `heads` holds each run's next record once started
+    Merge {
+        heads: Vec<Option<T>>,
+        runs: Vec<SpillReader<T>>,
+        compare: F,
+    },
+}
+
+impl<T, F> Iterator for Sorted<T, F>
+where
+    T: DeserializeOwned,
+    F: Fn(&T, &T) -> CmpOrdering,
+{
+    type Item = Result<T, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (heads, runs, compare) = match self {
+            Sorted::Memory(records) => return records.next().map(Ok),
+            Sorted::Merge { heads, runs, compare } => (heads, runs, compare),
+        };
+
+        if heads.is_empty() {
+            for run in runs.iter_mut() {
+                match run.next().transpose() {
+                    Ok(head) => heads.push(head),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+
+        // The least head wins; on a tie, the earliest run
+        let mut least: Option<usize> = None;
+        for (run, head) in heads.iter().enumerate() {
+            let Some(head) = head else { continue };
+            if least.is_none_or(|best| compare(head, heads[best].as_ref().expect("least head exists")).is_lt()) {
+                least = Some(run);
+            }
+        }
+
+        let run = least?;
+        let next = match runs[run].next().transpose() {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        std::mem::replace(&mut heads[run], next).map(Ok)
+    }
+}
+
+/// Estimated bytes a value occupies, its own and those it owns on the heap
+pub fn value_size(value: &Value) -> usize {
+    let owned = match value {
+        Value::String(s) => s.len(),
+        Value::Bytes(b) => b.len(),
+        Value::List(items) => items.iter().map(value_size).sum(),
+        Value::Map(map) => map.iter().map(|(k, v)| std::mem::size_of::<String>() + k.len() + value_size(v)).sum(),
+        _ => 0,
+    };
+    std::mem::size_of::<Value>() + owned
+}
+
+/// Estimated bytes a property value occupies, its own and those it owns on the heap
+pub fn property_value_size(value: &PropertyValue) -> usize {
+    let owned = match value {
+        PropertyValue::String(s) => s.len(),
+        PropertyValue::Bytes(b) => b.len(),
+        PropertyValue::List(items) => items.iter().map(property_value_size).sum(),
+        PropertyValue::Map(map) => map
+            .iter()
+            .map(|(k, v)| std::mem::size_of::<String>() + k.len() + property_value_size(v))
+            .sum(),
+        _ => 0,
+    };
+    std::mem::size_of::<PropertyValue>() + owned
+}
+
+/// Estimated bytes a DISTINCT key occupies, its own and those it owns on the heap
+pub fn distinct_key_size(key: &DistinctKey) -> usize {
+    let owned = match key {
+        DistinctKey::String(s) => s.len(),
+        DistinctKey::Bytes(b) => b.len(),
+        DistinctKey::List(items) => items.iter().map(distinct_key_size).sum(),
+        DistinctKey::Map(entries) => entries
+            .iter()
+            .map(|(k, v)| std::mem::size_of::<String>() + k.len() + distinct_key_size(v))
+            .sum(),
+        DistinctKey::Path(ids) => ids.len() * std::mem::size_of::<u64>(),
+        _ => 0,
+    };
+    std::mem::size_of::<DistinctKey>() + owned
+}
+
+/// Estimated bytes a result row occupies
+pub fn row_size(row: &HashMap<String, Value>) -> usize {
+    std::mem::size_of::<HashMap<String, Value>>()
+        + row
+            .iter()
+            .map(|(column, value)| std::mem::size_of::<String>() + column.len() + value_size(value))
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_file_reads_back_records_and_removes_itself() {
+        let mut file = SpillFile::create(&std::env::temp_dir()).unwrap();
+        for n in 0..1000i64 {
+            file.write(&(n, format!("row {}", n))).unwrap();
+        }
+        assert_eq!(file.len(), 1000);
+        let path = file.path.clone();
+
+        let reader = file.read().unwrap();
+        let records: Vec<(i64, String)> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(records.len(), 1000);
+        assert_eq!(records[999], (999, "row 999".to_string()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_external_sort_merges_spilled_runs_stably() {
+        let budget = MemoryBudget::new(100);
+        let mut sorter = ExternalSort::new(&budget, |a: &(i64, usize), b: &(i64, usize)| a.0.cmp(&b.0));
+        for n in 0..1000usize {
+            sorter.push(((n as i64 * 7919) % 10, n), 16).unwrap();
+        }
+        let (sorted, usage) = sorter.finish().unwrap();
+        assert!(usage.spilled_bytes > 0);
+        assert!(usage.peak <= 116);
+
+        let sorted: Vec<(i64, usize)> = sorted.collect::<Result<_, _>>().unwrap();
+        assert_eq!(sorted.len(), 1000);
+        assert!(sorted.windows(2).all(|w| w[0].0 < w[1].0 || (w[0].0 == w[1].0 && w[0].1 < w[1].1)));
+    }
+
+    #[test]
+    fn test_sizes_count_owned_bytes() {
+        let short = value_size(&Value::String("a".to_string()));
+        let long = value_size(&Value::String("a".repeat(101)));
+        assert_eq!(long - short, 100);
+
+        let row = HashMap::from([("name".to_string(), Value::Integer(1))]);
+        assert!(row_size(&row) > value_size(&Value::Integer(1)));
+    }
+}
@@ -0,0 +1,353 @@
+//! Collection statistics for the query optimizer
+//!
+//! Entity and edge counts are exact: the graph updates them on every write.
+//! Per-property statistics are estimates. Distinct values are counted with a
+//! HyperLogLog sketch and numeric properties track their range; neither can
+//! forget a value, so after deletes and updates they drift high (and ranges
+//! wide) until `ANALYZE` rebuilds them from a sample.
+
+use crate::dql_ir::{FilterExpr, IndexProbe, Value};
+use crate::graph::Entity;
+use crate::types::{DistinctKey, EntityType, Properties, PropertyValue};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Entities ANALYZE reads from each collection
+pub const ANALYZE_SAMPLE_SIZE: usize = 10_000;
+
+/// A distinct-value sketch has 2^SKETCH_BITS registers (about 6.5% error)
+const SKETCH_BITS: u32 = 8;
+
+/// Fraction of rows assumed to pass a predicate nothing is known about
+const DEFAULT_SELECTIVITY: f32 = 0.5;
+
+/// Fraction of rows assumed to fall in a range over non-numeric values
+const DEFAULT_RANGE_SELECTIVITY: f32 = 1.0 / 3.0;
+
+/// Fraction of rows assumed to hold each term of a MATCH
+const DEFAULT_TERM_SELECTIVITY: f32 = 0.1;
+
+/// HyperLogLog sketch of the distinct values of a property
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    fn new() -> Self {
+        Sketch {
+            registers: vec![0; 1 << SKETCH_BITS],
+        }
+    }
+
+    fn add(&mut self, key: &DistinctKey) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // The top bits pick a register; it keeps the longest run of leading
+        // zeros seen in the rest (a sentinel bit caps the run)
+        let register = (hash >> (64 - SKETCH_BITS)) as usize;
+        let rank = ((hash << SKETCH_BITS) | (1 << (SKETCH_BITS - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let raw = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+
+        // Linear counting is more accurate while some registers are unused
+        let unused = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if raw <= 2.5 * m && unused > 0 {
+            m * (m / unused as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// Statistics of one property of a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PropertyStats {
+    /// Entities with a non-null value
+    pub non_null: usize,
+    /// Smallest numeric value (integers, floats and timestamps)
+    pub min: Option<f64>,
+    /// Largest numeric value
+    pub max: Option<f64>,
+    sketch: Sketch,
+    /// Scales the sketch's estimate up to the collection when built from a sample
+    scale: f64,
+}
+
+impl PropertyStats {
+    fn new() -> Self {
+        PropertyStats {
+            non_null: 0,
+            min: None,
+            max: None,
+            sketch: Sketch::new(),
+            scale: 1.0,
+        }
+    }
+
+    fn add(&mut self, value: &PropertyValue) {
+        if matches!(value, PropertyValue::Null) {
+            return;
+        }
+        self.non_null += 1;
+        self.sketch.add(&value.distinct_key());
+
+        let number = match value {
+            PropertyValue::Int(n) | PropertyValue::Timestamp(n) => Some(*n as f64),
+            PropertyValue::Float(f) if !f.is_nan() => Some(*f),
+            _ => None,
+        };
+        if let Some(number) = number {
+            self.min = Some(self.min.map_or(number, |min| min.min(number)));
+            self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        }
+    }
+
+    fn remove(&mut self, value: &PropertyValue) {
+        if !matches!(value, PropertyValue::Null) {
+            self.non_null = self.non_null.saturating_sub(1);
+        }
+    }
+
+    /// Estimated number of distinct non-null values
+    pub fn distinct(&self) -> f64 {
+        let estimate = (self.sketch.estimate() * self.scale).min(self.non_null as f64);
+        if self.non_null > 0 {
+            estimate.max(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Statistics of one collection, as seen by the optimizer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollectionStats {
+    /// Entities in the collection (exact)
+    pub entity_count: usize,
+    /// Statistics of each property any entity has had (shared with the
+    /// graph until its next write to the collection)
+    pub properties: Arc<HashMap<String, PropertyStats>>,
+}
+
+impl CollectionStats {
+    /// Fraction of non-null values of `property` among the collection
+    fn presence(&self, stats: &PropertyStats) -> f64 {
+        if self.entity_count == 0 {
+            return 0.0;
+        }
+        (stats.non_null as f64 / self.entity_count as f64).min(1.0)
+    }
+
+    /// Fraction of the collection expected to match `property = value`
+    ///
+    /// Values are assumed evenly spread over the distinct values. A property
+    /// no entity has matches nothing.
+    pub fn equality_selectivity(&self, property: &str) -> f32 {
+        match self.properties.get(property) {
+            Some(stats) if stats.non_null > 0 => (self.presence(stats) / stats.distinct()) as f32,
+            _ => 0.0,
+        }
+    }
+
+    /// Fraction of the collection expected to lie between the (inclusive) bounds
+    ///
+    /// Numeric bounds are interpolated over the property's range; other
+    /// bounds get a fixed guess.
+    pub fn range_selectivity(&self, property: &str, lower: Option<&Value>, upper: Option<&Value>) -> f32 {
+        let Some(stats) = self.properties.get(property) else {
+            return 0.0;
+        };
+        let presence = self.presence(stats);
+
+        let numeric = |bound: Option<&Value>| bound.map(|value| value.as_f64().ok_or(())).transpose();
+        let (Ok(lower), Ok(upper), Some(min), Some(max)) = (numeric(lower), numeric(upper), stats.min, stats.max) else {
+            return (presence * DEFAULT_RANGE_SELECTIVITY as f64) as f32;
+        };
+
+        let low = lower.unwrap_or(min).max(min);
+        let high = upper.unwrap_or(max).min(max);
+        let covered = if high < low {
+            0.0
+        } else if max > min {
+            (high - low) / (max - min)
+        } else {
+            1.0
+        };
+        (presence * covered) as f32
+    }
+
+    /// Fraction of the collection expected to satisfy `filter`, which reads
+    /// the collection's entities as `binding`
+    ///
+    /// AND-ed comparisons of a property with a constant are taken as
+    /// independent; anything else is assumed to keep half the rows.
+    pub fn selectivity(&self, binding: &str, filter: &FilterExpr) -> f32 {
+        let mut predicates: Vec<(String, IndexProbe)> = Vec::new();
+        for (field, probe) in filter.index_probes(binding) {
+            match predicates.iter_mut().find(|(f, _)| *f == field) {
+                Some((_, existing)) => existing.narrow(probe),
+                None => predicates.push((field, probe)),
+            }
+        }
+        if predicates.is_empty() {
+            return DEFAULT_SELECTIVITY;
+        }
+
+        predicates
+            .iter()
+            .map(|(field, probe)| match probe {
+                IndexProbe::Equal(_) => self.equality_selectivity(field),
+                IndexProbe::In(values) => (self.equality_selectivity(field) * values.len() as f32).min(1.0),
+                IndexProbe::Range { lower, upper } => self.range_selectivity(field, lower.as_ref(), upper.as_ref()),
+                IndexProbe::Match(terms) => DEFAULT_TERM_SELECTIVITY.powi(terms.len() as i32),
+            })
+            .product()
+    }
+
+    /// Entities expected to satisfy `filter` (all of them without one)
+    pub fn estimated_rows(&self, binding: &str, filter: Option<&FilterExpr>) -> f32 {
+        let rows = self.entity_count as f32;
+        match filter {
+            Some(filter) => (rows * self.selectivity(binding, filter)).clamp(rows.min(1.0), rows),
+            None => rows,
+        }
+    }
+}
+
+/// Per-property statistics the graph keeps up to date as entities are written
+///
+/// Each collection's statistics are copied on write: a snapshot handed out by
+/// [`PropertyTracker::get`] is only cloned if a write comes while it is held.
+#[derive(Default)]
+pub struct PropertyTracker {
+    collections: DashMap<EntityType, Arc<HashMap<String, PropertyStats>>>,
+}
+
+impl PropertyTracker {
+    /// Count a written entity's values
+    pub fn insert(&self, entity: &Entity) {
+        let mut properties = self.collections.entry(entity.entity_type.clone()).or_default();
+        add_properties(Arc::make_mut(&mut properties), &entity.properties);
+    }
+
+    /// Forget a removed (or overwritten) entity's values
+    ///
+    /// Only the non-null counts go down; the sketches and ranges keep them.
+    pub fn remove(&self, entity: &Entity) {
+        if let Some(mut properties) = self.collections.get_mut(&entity.entity_type) {
+            let properties = Arc::make_mut(&mut properties);
+            for (name, value) in &entity.properties {
+                if let Some(stats) = properties.get_mut(name) {
+                    stats.remove(value);
+                }
+            }
+        }
+    }
+
+    /// Current statistics of a collection's properties
+    pub fn get(&self, collection: &str) -> Arc<HashMap<String, PropertyStats>> {
+        self.collections.get(collection).map(|p| Arc::clone(&p)).unwrap_or_default()
+    }
+
+    /// Replace a collection's statistics with ones rebuilt by ANALYZE
+    pub fn replace(&self, collection: &str, properties: HashMap<String, PropertyStats>) {
+        self.collections.insert(collection.to_string(), Arc::new(properties));
+    }
+}
+
+fn add_properties(stats: &mut HashMap<String, PropertyStats>, properties: &Properties) {
+    for (name, value) in properties {
+        stats.entry(name.clone()).or_insert_with(PropertyStats::new).add(value);
+    }
+}
+
+/// Property statistics built from a sample of a collection
+#[derive(Default)]
+pub struct Sample {
+    properties: HashMap<String, PropertyStats>,
+    entities: usize,
+}
+
+impl Sample {
+    pub fn add(&mut self, properties: &Properties) {
+        add_properties(&mut self.properties, properties);
+        self.entities += 1;
+    }
+
+    /// Entities sampled so far
+    pub fn len(&self) -> usize {
+        self.entities
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities == 0
+    }
+
+    /// Scale the sample up to a collection of `rows` entities
+    ///
+    /// Counts scale linearly. Distinct values only scale for properties
+    /// that look unique in the sample (allowing for the sketch's error): a
+    /// property with few values has likely shown all of them already.
+    pub fn finish(self, rows: usize) -> HashMap<String, PropertyStats> {
+        let mut properties = self.properties;
+        if self.entities == 0 || rows <= self.entities {
+            return properties;
+        }
+
+        let factor = rows as f64 / self.entities as f64;
+        for stats in properties.values_mut() {
+            if stats.sketch.estimate() > 0.8 * stats.non_null as f64 {
+                stats.scale = factor;
+            }
+            stats.non_null = ((stats.non_null as f64 * factor).round() as usize).min(rows);
+        }
+        properties
+    }
+}
+
+/// What ANALYZE rebuilt for a collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalyzeReport {
+    pub collection: String,
+    pub entities: usize,
+    pub sampled: usize,
+    pub properties: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_estimates_distinct_values() {
+        let mut sketch = Sketch::new();
+        assert_eq!(sketch.estimate(), 0.0);
+
+        for round in 0..3 {
+            for n in 0..10_000 {
+                sketch.add(&PropertyValue::Int(n).distinct_key());
+            }
+            // Repeats don't count
+            let estimate = sketch.estimate();
+            assert!((estimate - 10_000.0).abs() < 1_500.0, "round {}: {}", round, estimate);
+        }
+
+        let mut small = Sketch::new();
+        for value in [true, false, true, true] {
+            small.add(&PropertyValue::Bool(value).distinct_key());
+        }
+        assert!((small.estimate() - 2.0).abs() < 0.1);
+    }
+}
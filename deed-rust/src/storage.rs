@@ -6,13 +6,13 @@
 use crate::types::*;
 use crate::graph::{Entity, Edge};
 use rocksdb::{DB, Options, WriteBatch, IteratorMode};
-use serde::{Serialize, Deserialize};
 use std::path::Path;
 use std::sync::Arc;
 
 /// Column families for different data types
 const CF_ENTITIES: &str = "entities";
 const CF_EDGES: &str = "edges";
+const CF_ADJACENCY: &str = "adjacency";
 const CF_INDEXES: &str = "indexes";
 const CF_METADATA: &str = "metadata";
 
@@ -44,7 +44,7 @@ impl StorageEngine {
         opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
 
         // Column families for different data types
-        let cfs = vec![CF_ENTITIES, CF_EDGES, CF_INDEXES, CF_METADATA];
+        let cfs = vec![CF_ENTITIES, CF_EDGES, CF_ADJACENCY, CF_INDEXES, CF_METADATA];
 
         let db = DB::open_cf(&opts, path, cfs)
             .map_err(|e| format!("Failed to open database: {}", e))?;
@@ -61,7 +61,7 @@ impl StorageEngine {
         let value = bincode::serialize(entity)
             .map_err(|e| format!("Serialization error: {}", e))?;
 
-        self.db.put_cf(&cf, key, value)
+        self.db.put_cf(cf, key, value)
             .map_err(|e| format!("Put error: {}", e))
     }
 
@@ -72,7 +72,7 @@ impl StorageEngine {
 
         let key = entity_key(id);
 
-        match self.db.get_cf(&cf, key) {
+        match self.db.get_cf(cf, key) {
             Ok(Some(value)) => {
                 let entity: Entity = bincode::deserialize(&value)
                     .map_err(|e| format!("Deserialization error: {}", e))?;
@@ -83,28 +83,70 @@ impl StorageEngine {
         }
     }
 
-    /// Delete an entity
-    pub fn delete_entity(&self, id: EntityId) -> Result<(), String> {
-        let cf = self.db.cf_handle(CF_ENTITIES)
-            .ok_or("Entity column family not found")?;
+    /// Store a batch of entities in one write
+    pub fn put_entities(&self, entities: &[Entity]) -> Result<(), String> {
+        let mut batch = self.batch();
+        for entity in entities {
+            batch.put_entity(entity)?;
+        }
+        batch.commit()
+    }
 
-        let key = entity_key(id);
+    /// Delete an entity along with every edge that touches it
+    pub fn delete_entity(&self, id: EntityId) -> Result<(), String> {
+        let mut batch = self.batch();
+        batch.delete_entity(id)?;
+        batch.commit()
+    }
 
-        self.db.delete_cf(&cf, key)
-            .map_err(|e| format!("Delete error: {}", e))
+    /// Delete an entity's own record, leaving the edges that touch it
+    pub fn remove_entity(&self, id: EntityId) -> Result<(), String> {
+        let mut batch = self.batch();
+        batch.remove_entity(id)?;
+        batch.commit()
     }
 
-    /// Store an edge
+    /// Store an edge and link it to both endpoints
     pub fn put_edge(&self, edge: &Edge) -> Result<(), String> {
-        let cf = self.db.cf_handle(CF_EDGES)
-            .ok_or("Edge column family not found")?;
+        let mut batch = self.batch();
+        batch.put_edge(edge)?;
+        batch.commit()
+    }
 
-        let key = edge_key(edge.id);
-        let value = bincode::serialize(edge)
-            .map_err(|e| format!("Serialization error: {}", e))?;
+    /// Delete an edge and unlink it from both endpoints
+    pub fn delete_edge(&self, edge: &Edge) -> Result<(), String> {
+        let mut batch = self.batch();
+        batch.delete_edge(edge)?;
+        batch.commit()
+    }
 
-        self.db.put_cf(&cf, key, value)
-            .map_err(|e| format!("Put error: {}", e))
+    /// Start a set of changes written together in one atomic batch
+    pub fn batch(&self) -> StorageBatch<'_> {
+        StorageBatch { engine: self, batch: WriteBatch::default() }
+    }
+
+    /// IDs of the edges (in either direction) that touch an entity
+    pub fn adjacent_edges(&self, id: EntityId) -> Result<Vec<EdgeId>, String> {
+        let cf = self.db.cf_handle(CF_ADJACENCY)
+            .ok_or("Adjacency column family not found")?;
+
+        let prefix = adjacency_prefix(id);
+        let mut edge_ids = Vec::new();
+
+        let iter = self.db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward));
+        for item in iter {
+            let (key, _) = item.map_err(|e| format!("Iterator error: {}", e))?;
+            let Some(edge_id) = key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            let edge_id = std::str::from_utf8(edge_id)
+                .ok()
+                .and_then(|id| id.parse().ok())
+                .ok_or("Corrupt adjacency key")?;
+            edge_ids.push(EdgeId::new(edge_id));
+        }
+
+        Ok(edge_ids)
     }
 
     /// Get an edge by ID
@@ -114,7 +156,7 @@ impl StorageEngine {
 
         let key = edge_key(id);
 
-        match self.db.get_cf(&cf, key) {
+        match self.db.get_cf(cf, key) {
             Ok(Some(value)) => {
                 let edge: Edge = bincode::deserialize(&value)
                     .map_err(|e| format!("Deserialization error: {}", e))?;
@@ -138,7 +180,7 @@ impl StorageEngine {
 
         let mut entities = Vec::new();
 
-        let iter = self.db.iterator_cf(&cf, IteratorMode::Start);
+        let iter = self.db.iterator_cf(cf, IteratorMode::Start);
         for item in iter {
             match item {
                 Ok((_key, value)) => {
@@ -153,6 +195,28 @@ impl StorageEngine {
         Ok(entities)
     }
 
+    /// Scan all edges (range scan)
+    pub fn scan_edges(&self) -> Result<Vec<Edge>, String> {
+        let cf = self.db.cf_handle(CF_EDGES)
+            .ok_or("Edge column family not found")?;
+
+        let mut edges = Vec::new();
+
+        let iter = self.db.iterator_cf(cf, IteratorMode::Start);
+        for item in iter {
+            match item {
+                Ok((_key, value)) => {
+                    let edge: Edge = bincode::deserialize(&value)
+                        .map_err(|e| format!("Deserialization error: {}", e))?;
+                    edges.push(edge);
+                }
+                Err(e) => return Err(format!("Iterator error: {}", e)),
+            }
+        }
+
+        Ok(edges)
+    }
+
     /// Create a secondary index on a property
     ///
     /// Stores mapping: property_value -> [entity_ids]
@@ -163,7 +227,7 @@ impl StorageEngine {
             .ok_or("Index column family not found")?;
 
         // Scan all entities in collection
-        let iter = self.db.iterator_cf(&cf_entities, IteratorMode::Start);
+        let iter = self.db.iterator_cf(cf_entities, IteratorMode::Start);
 
         let mut index_entries: std::collections::HashMap<Vec<u8>, Vec<EntityId>> =
             std::collections::HashMap::new();
@@ -185,7 +249,7 @@ impl StorageEngine {
 
                         index_entries
                             .entry(index_key)
-                            .or_insert_with(Vec::new)
+                            .or_default()
                             .push(entity.id);
                     }
                 }
@@ -198,7 +262,7 @@ impl StorageEngine {
         for (key, entity_ids) in index_entries {
             let value = bincode::serialize(&entity_ids)
                 .map_err(|e| format!("Serialization error: {}", e))?;
-            batch.put_cf(&cf_indexes, key, value);
+            batch.put_cf(cf_indexes, key, value);
         }
 
         self.write_batch(batch)
@@ -216,7 +280,7 @@ impl StorageEngine {
 
         let key = index_key(collection, property, value);
 
-        match self.db.get_cf(&cf, key) {
+        match self.db.get_cf(cf, key) {
             Ok(Some(bytes)) => {
                 let entity_ids: Vec<EntityId> = bincode::deserialize(&bytes)
                     .map_err(|e| format!("Deserialization error: {}", e))?;
@@ -234,6 +298,75 @@ impl StorageEngine {
     }
 }
 
+/// Changes to entities and edges that reach storage together or not at all
+pub struct StorageBatch<'a> {
+    engine: &'a StorageEngine,
+    batch: WriteBatch,
+}
+
+impl StorageBatch<'_> {
+    /// Store an entity
+    pub fn put_entity(&mut self, entity: &Entity) -> Result<(), String> {
+        let cf = self.engine.db.cf_handle(CF_ENTITIES)
+            .ok_or("Entity column family not found")?;
+        let value = bincode::serialize(entity)
+            .map_err(|e| format!("Serialization error: {}", e))?;
+        self.batch.put_cf(cf, entity_key(entity.id), value);
+        Ok(())
+    }
+
+    /// Delete an entity along with every stored edge that touches it
+    pub fn delete_entity(&mut self, id: EntityId) -> Result<(), String> {
+        for edge_id in self.engine.adjacent_edges(id)? {
+            if let Some(edge) = self.engine.get_edge(edge_id)? {
+                self.delete_edge(&edge)?;
+            }
+        }
+        self.remove_entity(id)
+    }
+
+    /// Delete an entity's own record, leaving the edges that touch it
+    pub fn remove_entity(&mut self, id: EntityId) -> Result<(), String> {
+        let cf = self.engine.db.cf_handle(CF_ENTITIES)
+            .ok_or("Entity column family not found")?;
+        self.batch.delete_cf(cf, entity_key(id));
+        Ok(())
+    }
+
+    /// Store an edge and link it to both endpoints
+    pub fn put_edge(&mut self, edge: &Edge) -> Result<(), String> {
+        let cf = self.engine.db.cf_handle(CF_EDGES)
+            .ok_or("Edge column family not found")?;
+        let cf_adjacency = self.engine.db.cf_handle(CF_ADJACENCY)
+            .ok_or("Adjacency column family not found")?;
+        let value = bincode::serialize(edge)
+            .map_err(|e| format!("Serialization error: {}", e))?;
+
+        self.batch.put_cf(cf, edge_key(edge.id), value);
+        self.batch.put_cf(cf_adjacency, adjacency_key(edge.source, edge.id), b"");
+        self.batch.put_cf(cf_adjacency, adjacency_key(edge.target, edge.id), b"");
+        Ok(())
+    }
+
+    /// Delete an edge and unlink it from both endpoints
+    pub fn delete_edge(&mut self, edge: &Edge) -> Result<(), String> {
+        let cf = self.engine.db.cf_handle(CF_EDGES)
+            .ok_or("Edge column family not found")?;
+        let cf_adjacency = self.engine.db.cf_handle(CF_ADJACENCY)
+            .ok_or("Adjacency column family not found")?;
+
+        self.batch.delete_cf(cf, edge_key(edge.id));
+        self.batch.delete_cf(cf_adjacency, adjacency_key(edge.source, edge.id));
+        self.batch.delete_cf(cf_adjacency, adjacency_key(edge.target, edge.id));
+        Ok(())
+    }
+
+    /// Write every change at once
+    pub fn commit(self) -> Result<(), String> {
+        self.engine.write_batch(self.batch)
+    }
+}
+
 // Key encoding functions
 
 fn entity_key(id: EntityId) -> Vec<u8> {
@@ -244,6 +377,16 @@ fn edge_key(id: EdgeId) -> Vec<u8> {
     format!("g:{}", id.as_u64()).into_bytes()
 }
 
+fn adjacency_key(entity: EntityId, edge: EdgeId) -> Vec<u8> {
+    let mut key = adjacency_prefix(entity);
+    key.extend_from_slice(edge.as_u64().to_string().as_bytes());
+    key
+}
+
+fn adjacency_prefix(entity: EntityId) -> Vec<u8> {
+    format!("a:{}:", entity.as_u64()).into_bytes()
+}
+
 fn index_key(collection: &str, property: &str, value: &PropertyValue) -> Vec<u8> {
     // Encode as: i:{collection}:{property}:{value}
     let value_str = match value {
@@ -251,6 +394,7 @@ fn index_key(collection: &str, property: &str, value: &PropertyValue) -> Vec<u8>
         PropertyValue::Float(v) => format!("float:{}", v),
         PropertyValue::String(s) => format!("str:{}", s),
         PropertyValue::Bool(b) => format!("bool:{}", b),
+        PropertyValue::Timestamp(ms) => format!("ts:{}", ms),
         _ => String::from("null"),
     };
 
@@ -283,6 +427,29 @@ mod tests {
         assert_eq!(retrieved.entity_type, entity.entity_type);
     }
 
+    #[test]
+    fn test_nested_values_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = StorageEngine::open(temp_dir.path()).unwrap();
+
+        let mut address = Properties::new();
+        address.insert("city".to_string(), PropertyValue::String("Oslo".to_string()));
+        address.insert("lines".to_string(), PropertyValue::List(vec![PropertyValue::String("Gate 1".to_string())]));
+
+        let mut props = Properties::new();
+        props.insert(
+            "tags".to_string(),
+            PropertyValue::List(vec![PropertyValue::String("admin".to_string()), PropertyValue::Int(7)]),
+        );
+        props.insert("address".to_string(), PropertyValue::Map(address));
+
+        let entity = Entity::new(EntityId::new(1), "User".to_string(), props);
+        storage.put_entity(&entity).unwrap();
+
+        let retrieved = storage.get_entity(EntityId::new(1)).unwrap().unwrap();
+        assert_eq!(retrieved.properties, entity.properties);
+    }
+
     #[test]
     fn test_index_creation() {
         let temp_dir = TempDir::new().unwrap();
@@ -315,4 +482,29 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0], EntityId::new(3));
     }
+
+    #[test]
+    fn test_delete_entity_removes_its_edges() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = StorageEngine::open(temp_dir.path()).unwrap();
+
+        for i in 1..=3 {
+            storage.put_entity(&Entity::new(EntityId::new(i), "User".to_string(), Properties::new())).unwrap();
+        }
+        let edge = |id, source, target| {
+            Edge::new(EdgeId::new(id), EntityId::new(source), EntityId::new(target), "KNOWS".to_string(), Properties::new())
+        };
+        storage.put_edge(&edge(1, 1, 2)).unwrap();
+        storage.put_edge(&edge(2, 3, 1)).unwrap();
+        storage.put_edge(&edge(3, 2, 3)).unwrap();
+
+        assert_eq!(storage.adjacent_edges(EntityId::new(1)).unwrap(), vec![EdgeId::new(1), EdgeId::new(2)]);
+
+        storage.delete_entity(EntityId::new(1)).unwrap();
+
+        let remaining: Vec<EdgeId> = storage.scan_edges().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(remaining, vec![EdgeId::new(3)]);
+        assert!(storage.adjacent_edges(EntityId::new(1)).unwrap().is_empty());
+        assert_eq!(storage.adjacent_edges(EntityId::new(2)).unwrap(), vec![EdgeId::new(3)]);
+    }
 }
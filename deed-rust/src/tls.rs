@@ -0,0 +1,274 @@
+//! TLS for node-to-node and client connections
+//!
+//! Connections are upgraded with rustls before their first frame. A side
+//! accepting connections presents the certificate and key of its
+//! [`TlsConfig`]; the connecting side verifies it against the config's CA
+//! bundle, or the webpki roots without one. With a CA bundle the accepting
+//! side also requires a certificate signed by it (mutual TLS), and the
+//! connecting side presents its own.
+//!
+//! Plaintext is only used when a config sets its `insecure` flag.
+
+use crate::distributed_topology::NodeId;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Longest an accepted connection may take to finish its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificates a node, server or client presents and trusts
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM certificate chain presented to peers; required to accept connections
+    pub cert_path: Option<PathBuf>,
+    /// PEM private key of the certificate
+    pub key_path: Option<PathBuf>,
+    /// PEM bundle of the CAs peers' certificates must be signed by; with one,
+    /// peers connecting must present a certificate too
+    pub ca_path: Option<PathBuf>,
+    /// Fingerprint of each node's certificate (see [`certificate_fingerprint`]);
+    /// when not empty, a peer is only taken for a node if it presented that
+    /// node's certificate
+    pub node_fingerprints: HashMap<NodeId, String>,
+}
+
+impl TlsConfig {
+    /// Present the certificate chain at `cert_path`, with the key at `key_path`
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert_path: Some(cert_path.into()),
+            key_path: Some(key_path.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Trust only the CAs in the bundle at `path`, and require peers' certificates
+    pub fn with_ca(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_path = Some(path.into());
+        self
+    }
+
+    /// Take a peer for `node` only if it presents the certificate with `fingerprint`
+    pub fn with_node_fingerprint(mut self, node: NodeId, fingerprint: impl Into<String>) -> Self {
+        self.node_fingerprints.insert(node, fingerprint.into());
+        self
+    }
+}
+
+/// Hex SHA-256 of a DER-encoded certificate
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Fingerprint of the first certificate in a PEM file
+pub fn certificate_file_fingerprint(path: &Path) -> Result<String, String> {
+    Ok(certificate_fingerprint(&load_certs(path)?[0]))
+}
+
+/// A byte stream a connection's frames are carried over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub type BoxedStream = Box<dyn Stream>;
+
+/// How a connection's bytes are carried: in the clear, or over TLS
+#[derive(Clone)]
+pub enum Transport {
+    Plaintext,
+    Tls {
+        /// `None` without a certificate to present
+        acceptor: Option<TlsAcceptor>,
+        connector: TlsConnector,
+        node_fingerprints: Arc<HashMap<NodeId, String>>,
+    },
+}
+
+impl Transport {
+    /// The transport of a config's TLS settings and `insecure` flag
+    pub fn new(tls: Option<&TlsConfig>, insecure: bool) -> Result<Self, String> {
+        match (tls, insecure) {
+            (None, true) => Ok(Transport::Plaintext),
+            (Some(_), true) => Err("Both TLS and insecure plaintext are configured".to_string()),
+            (None, false) => Err("TLS is not configured; set insecure to use plaintext".to_string()),
+            (Some(tls), false) => Self::tls(tls),
+        }
+    }
+
+    fn tls(config: &TlsConfig) -> Result<Self, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let identity = match (&config.cert_path, &config.key_path) {
+            (Some(cert), Some(key)) => Some((load_certs(cert)?, load_key(key)?)),
+            (None, None) => None,
+            _ => return Err("TLS needs both a certificate and its key".to_string()),
+        };
+        let roots = match &config.ca_path {
+            Some(path) => load_roots(path)?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+
+        let acceptor = match &identity {
+            Some((certs, key)) => {
+                let verifier = match config.ca_path {
+                    Some(_) => WebPkiClientVerifier::builder_with_provider(Arc::new(roots.clone()), provider.clone())
+                        .build()
+                        .map_err(|e| format!("Invalid CA bundle: {}", e))?,
+                    None => WebPkiClientVerifier::no_client_auth(),
+                };
+                let server = ServerConfig::builder_with_provider(provider.clone())
+                    .with_safe_default_protocol_versions()
+                    .map_err(|e| format!("TLS setup failed: {}", e))?
+                    .with_client_cert_verifier(verifier)
+                    .with_single_cert(certs.clone(), key.clone_key())
+                    .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+                Some(TlsAcceptor::from(Arc::new(server)))
+            }
+            None => None,
+        };
+
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("TLS setup failed: {}", e))?
+            .with_root_certificates(roots);
+        let client = match identity {
+            Some((certs, key)) => client
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| format!("Invalid certificate or key: {}", e))?,
+            None => client.with_no_client_auth(),
+        };
+
+        Ok(Transport::Tls {
+            acceptor,
+            connector: TlsConnector::from(Arc::new(client)),
+            node_fingerprints: Arc::new(config.node_fingerprints.clone()),
+        })
+    }
+
+    /// Upgrade an accepted connection, returning it with the fingerprint of
+    /// the certificate its peer presented, if any
+    pub async fn accept(&self, stream: TcpStream) -> Result<(BoxedStream, Option<String>), String> {
+        let Transport::Tls { acceptor, .. } = self else {
+            return Ok((Box::new(stream), None));
+        };
+        let acceptor = acceptor
+            .as_ref()
+            .ok_or_else(|| "TLS needs a certificate to accept connections".to_string())?;
+
+        let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .map_err(|_| "TLS handshake timed out".to_string())?
+            .map_err(|e| format!("TLS handshake failed: {}", describe_io_error(&e)))?;
+        let fingerprint = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| certificate_fingerprint(cert));
+        Ok((Box::new(stream), fingerprint))
+    }
+
+    /// Upgrade a connection to `host`, checking it's `node` if given
+    pub async fn connect(&self, stream: TcpStream, host: &str, node: Option<NodeId>) -> Result<BoxedStream, String> {
+        let Transport::Tls { connector, .. } = self else {
+            return Ok(Box::new(stream));
+        };
+        let name = ServerName::try_from(host.to_string()).map_err(|e| format!("Invalid server name {}: {}", host, e))?;
+
+        let stream = connector
+            .connect(name, stream)
+            .await
+            .map_err(|e| format!("TLS handshake with {} failed: {}", host, describe_io_error(&e)))?;
+        if let Some(node) = node {
+            let fingerprint = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| certificate_fingerprint(cert));
+            self.check_node(node, fingerprint.as_deref())?;
+        }
+        Ok(Box::new(stream))
+    }
+
+    /// Whether peers are checked against their nodes' known certificates
+    pub fn verifies_nodes(&self) -> bool {
+        matches!(self, Transport::Tls { node_fingerprints, .. } if !node_fingerprints.is_empty())
+    }
+
+    /// Check that a peer claiming to be `node` presented its certificate
+    pub fn check_node(&self, node: NodeId, fingerprint: Option<&str>) -> Result<(), String> {
+        let Transport::Tls { node_fingerprints, .. } = self else {
+            return Ok(());
+        };
+        if node_fingerprints.is_empty() {
+            return Ok(());
+        }
+
+        match node_fingerprints.get(&node) {
+            Some(expected) if Some(expected.as_str()) == fingerprint => Ok(()),
+            Some(_) => Err(format!("Peer claiming to be node {} presented another certificate", node)),
+            None => Err(format!("Node {} has no known certificate", node)),
+        }
+    }
+}
+
+/// Describe an I/O error, naming the usual reasons a TLS handshake fails
+pub fn describe_io_error(e: &std::io::Error) -> String {
+    use rustls::{AlertDescription, CertificateError, Error};
+
+    let Some(error) = e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) else {
+        return e.to_string();
+    };
+    match error {
+        Error::InvalidCertificate(CertificateError::Expired | CertificateError::ExpiredContext { .. })
+        | Error::AlertReceived(AlertDescription::CertificateExpired) => format!("certificate expired ({})", error),
+        Error::InvalidCertificate(CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }) => {
+            format!("certificate name mismatch ({})", error)
+        }
+        Error::InvalidCertificate(CertificateError::UnknownIssuer) | Error::AlertReceived(AlertDescription::UnknownCA) => {
+            format!("certificate signed by an untrusted CA ({})", error)
+        }
+        _ => error.to_string(),
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open certificate {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read certificate {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open key {}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to read key {}: {}", path.display(), e))?
+        .ok_or_else(|| format!("No private key in {}", path.display()))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    Ok(roots)
+}
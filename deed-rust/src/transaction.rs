@@ -1,12 +1,20 @@
 //! Transaction Management
 //!
 //! Provides ACID-compliant transactions with MVCC (Multi-Version Concurrency Control).
+//!
+//! A transaction writing an entity another one has uncommitted changes to
+//! may wait for it to finish, up to a lock wait timeout. Waits are tracked
+//! in a wait-for graph; a wait that closes a cycle is a deadlock, broken by
+//! aborting the youngest transaction in it with a [`DeadlockError`].
 
+use crate::mvcc::{CommitTimestamp, MVCCManager};
+use crate::types::EntityId;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Unique transaction identifier
 pub type TransactionId = u64;
@@ -24,25 +32,46 @@ pub enum TransactionState {
     Aborted,
 }
 
+impl TransactionState {
+    /// Lowercase name of the state
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionState::Active => "active",
+            TransactionState::Preparing => "preparing",
+            TransactionState::Committed => "committed",
+            TransactionState::Aborted => "aborted",
+        }
+    }
+}
+
 /// Isolation level for transaction
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Default)]
 pub enum IsolationLevel {
     /// Dirty reads allowed (lowest isolation)
     ReadUncommitted,
     /// No dirty reads (default for most databases)
     ReadCommitted,
     /// No dirty reads, no non-repeatable reads
+    #[default]
     RepeatableRead,
     /// Full serializability (highest isolation)
     Serializable,
 }
 
-impl Default for IsolationLevel {
-    fn default() -> Self {
-        IsolationLevel::RepeatableRead
+impl IsolationLevel {
+    /// The level as DQL writes it
+    pub fn name(&self) -> &'static str {
+        match self {
+            IsolationLevel::ReadUncommitted => "READ UNCOMMITTED",
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        }
     }
 }
 
+
 /// Transaction metadata and state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -54,10 +83,24 @@ pub struct Transaction {
     pub start_time: u64,
     /// Isolation level
     pub isolation_level: IsolationLevel,
+    /// Commits visible to the transaction's reads (REPEATABLE READ and up)
+    pub snapshot: CommitTimestamp,
     /// Read set - entities read by this transaction
     pub read_set: Vec<(u64, u64)>, // (entity_id, version)
     /// Write set - entities modified by this transaction
     pub write_set: Vec<u64>, // entity_ids
+    /// Collections scanned by this transaction (SERIALIZABLE only)
+    #[serde(default)]
+    pub read_collections: Vec<String>,
+    /// Collections this transaction wrote to
+    #[serde(default)]
+    pub write_collections: Vec<String>,
+    /// When the transaction committed
+    #[serde(default)]
+    pub commit_timestamp: Option<CommitTimestamp>,
+    /// Whether it runs a single auto-committed statement
+    #[serde(default)]
+    pub autocommit: bool,
 }
 
 impl Transaction {
@@ -73,8 +116,13 @@ impl Transaction {
             state: TransactionState::Active,
             start_time,
             isolation_level,
+            snapshot: 0,
             read_set: Vec::new(),
             write_set: Vec::new(),
+            read_collections: Vec::new(),
+            write_collections: Vec::new(),
+            commit_timestamp: None,
+            autocommit: false,
         }
     }
 
@@ -90,6 +138,13 @@ impl Transaction {
         }
     }
 
+    /// Mark a collection as scanned by this transaction
+    pub fn track_scan(&mut self, collection: &str) {
+        if !self.read_collections.iter().any(|c| c == collection) {
+            self.read_collections.push(collection.to_string());
+        }
+    }
+
     /// Check if transaction is active
     pub fn is_active(&self) -> bool {
         self.state == TransactionState::Active
@@ -106,16 +161,125 @@ impl Transaction {
     }
 }
 
+/// A named point in a transaction, with the MVCC mark to roll back to
+#[derive(Debug, Clone)]
+struct Savepoint {
+    name: String,
+    mark: usize,
+}
+
+/// A transaction aborted to break a deadlock; the client may retry it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadlockError {
+    pub victim: TransactionId,
+    /// The transactions that waited on each other, starting with the victim
+    pub cycle: Vec<TransactionId>,
+}
+
+impl DeadlockError {
+    /// Whether an error message reports a deadlock
+    pub fn is_deadlock(error: &str) -> bool {
+        error.starts_with("DeadlockError")
+    }
+}
+
+impl fmt::Display for DeadlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cycle: Vec<String> = self.cycle.iter().map(|txn_id| txn_id.to_string()).collect();
+        write!(
+            f,
+            "DeadlockError: transaction {} aborted to break the wait cycle {}",
+            self.victim,
+            cycle.join(" -> ")
+        )
+    }
+}
+
+impl std::error::Error for DeadlockError {}
+
+/// A transaction that can't commit without breaking its isolation level;
+/// it has been rolled back and the client may retry it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerializationError {
+    pub txn_id: TransactionId,
+    /// The transaction that committed first with conflicting changes
+    pub conflicting: TransactionId,
+}
+
+impl SerializationError {
+    /// Whether an error message reports a serialization failure
+    pub fn is_serialization_failure(error: &str) -> bool {
+        error.starts_with("SerializationError")
+    }
+}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SerializationError: transaction {} conflicts with transaction {}, which committed first; retry it",
+            self.txn_id, self.conflicting
+        )
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
+/// Who waits on whom for a write
+#[derive(Debug, Default)]
+struct WaitGraph {
+    /// Waiting transaction -> the transaction whose write it waits on
+    waits_for: HashMap<TransactionId, TransactionId>,
+    /// Transactions chosen to break a deadlock, with the cycle
+    victims: HashMap<TransactionId, Vec<TransactionId>>,
+}
+
+impl WaitGraph {
+    /// The cycle a transaction's wait closes, starting with it
+    fn cycle_from(&self, txn_id: TransactionId) -> Option<Vec<TransactionId>> {
+        let mut cycle = vec![txn_id];
+        let mut next = *self.waits_for.get(&txn_id)?;
+        while next != txn_id {
+            // A cycle elsewhere in the chain isn't this wait's to break
+            if cycle.contains(&next) {
+                return None;
+            }
+            cycle.push(next);
+            next = *self.waits_for.get(&next)?;
+        }
+        Some(cycle)
+    }
+
+    fn forget(&mut self, txn_id: TransactionId) {
+        self.waits_for.remove(&txn_id);
+        self.victims.remove(&txn_id);
+    }
+}
+
 /// Transaction manager - coordinates all transactions
 pub struct TransactionManager {
     /// Next transaction ID to allocate
     next_txn_id: AtomicU64,
     /// Active transactions
     active_transactions: Arc<RwLock<HashMap<TransactionId, Transaction>>>,
-    /// Committed transactions (keep recent history for MVCC)
+    /// Transactions committed since the oldest active snapshot, which
+    /// commits are validated against
     committed_transactions: Arc<RwLock<HashMap<TransactionId, Transaction>>>,
-    /// Entity snapshots for rollback (txn_id -> entity_id -> entity_json)
-    entity_snapshots: Arc<RwLock<HashMap<TransactionId, HashMap<u64, String>>>>,
+    /// Open savepoints of each transaction, oldest first
+    savepoints: Arc<RwLock<HashMap<TransactionId, Vec<Savepoint>>>>,
+    /// Entity versions written by transactions, for snapshot reads
+    mvcc: Arc<MVCCManager>,
+    /// Transactions waiting on others' writes
+    waits: Mutex<WaitGraph>,
+    /// Signalled when a transaction ends or is chosen as a deadlock victim
+    waits_changed: Condvar,
+    /// How long a write waits on another transaction's (zero: not at all)
+    lock_wait_timeout: RwLock<Duration>,
+    deadlocks_detected: AtomicU64,
+    deadlock_aborts: AtomicU64,
+    lock_wait_timeouts: AtomicU64,
+    serialization_aborts: AtomicU64,
+    commits: AtomicU64,
 }
 
 impl TransactionManager {
@@ -125,35 +289,69 @@ impl TransactionManager {
             next_txn_id: AtomicU64::new(1),
             active_transactions: Arc::new(RwLock::new(HashMap::new())),
             committed_transactions: Arc::new(RwLock::new(HashMap::new())),
-            entity_snapshots: Arc::new(RwLock::new(HashMap::new())),
+            savepoints: Arc::new(RwLock::new(HashMap::new())),
+            mvcc: Arc::new(MVCCManager::new()),
+            waits: Mutex::new(WaitGraph::default()),
+            waits_changed: Condvar::new(),
+            lock_wait_timeout: RwLock::new(Duration::ZERO),
+            deadlocks_detected: AtomicU64::new(0),
+            deadlock_aborts: AtomicU64::new(0),
+            lock_wait_timeouts: AtomicU64::new(0),
+            serialization_aborts: AtomicU64::new(0),
+            commits: AtomicU64::new(0),
         }
     }
 
+    /// Set how long a write waits for another transaction's uncommitted
+    /// write to the same entity before failing
+    ///
+    /// With the default of zero it fails at once with a write conflict.
+    pub fn set_lock_wait_timeout(&self, timeout: Duration) {
+        *self.lock_wait_timeout.write().unwrap() = timeout;
+    }
+
+    /// Entity versions shared by every transaction of this manager
+    pub fn mvcc(&self) -> &MVCCManager {
+        &self.mvcc
+    }
+
     /// Begin a new transaction
     pub fn begin(&self, isolation_level: IsolationLevel) -> Result<TransactionId, String> {
         let txn_id = self.next_txn_id.fetch_add(1, Ordering::SeqCst);
-        let transaction = Transaction::new(txn_id, isolation_level);
+        let mut transaction = Transaction::new(txn_id, isolation_level);
 
         let mut active = self.active_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
+        // Taken under the lock so no commit can collect versions the snapshot needs
+        transaction.snapshot = self.mvcc.current_snapshot();
         active.insert(txn_id, transaction);
 
-        // Initialize empty snapshot map for this transaction
-        let mut snapshots = self.entity_snapshots.write()
-            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
-        snapshots.insert(txn_id, HashMap::new());
+        Ok(txn_id)
+    }
 
+    /// Begin a transaction for a single auto-committed statement
+    ///
+    /// A lone statement is trivially serializable, so it isn't validated
+    /// at commit.
+    pub fn begin_autocommit(&self, isolation_level: IsolationLevel) -> Result<TransactionId, String> {
+        let txn_id = self.begin(isolation_level)?;
+        if let Some(transaction) = self.active_transactions.write().unwrap().get_mut(&txn_id) {
+            transaction.autocommit = true;
+        }
         Ok(txn_id)
     }
 
     /// Commit a transaction
+    ///
+    /// Fails with a [`SerializationError`] if it conflicts with one that
+    /// committed since its snapshot; it stays active, to be rolled back.
     pub fn commit(&self, txn_id: TransactionId) -> Result<(), String> {
         // Move from active to committed
         let mut active = self.active_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
-        let mut transaction = active.remove(&txn_id)
+        let transaction = active.get(&txn_id)
             .ok_or_else(|| format!("Transaction {} not found", txn_id))?;
 
         if !transaction.is_active() {
@@ -161,28 +359,33 @@ impl TransactionManager {
         }
 
         // Validate transaction (check for conflicts)
-        self.validate_transaction(&transaction)?;
+        if let Err(e) = self.validate_transaction(transaction) {
+            self.serialization_aborts.fetch_add(1, Ordering::SeqCst);
+            return Err(e);
+        }
+        let mut transaction = active.remove(&txn_id).expect("transaction was just validated");
 
-        // Mark as committed
+        // Mark as committed, publishing its versions
         transaction.state = TransactionState::Committed;
+        transaction.commit_timestamp = Some(self.mvcc.commit(txn_id));
+        let oldest_snapshot = self.collect_versions(&active);
 
-        // Move to committed transactions
+        // Move to committed transactions, dropping those no active
+        // transaction can conflict with any more
         let mut committed = self.committed_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
         committed.insert(txn_id, transaction);
-
-        // Clean up snapshots since transaction is committed
-        let mut snapshots = self.entity_snapshots.write()
-            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
-        snapshots.remove(&txn_id);
+        committed.retain(|_, other| other.commit_timestamp.is_some_and(|ts| ts > oldest_snapshot));
+        self.commits.fetch_add(1, Ordering::SeqCst);
+        self.savepoints.write().unwrap().remove(&txn_id);
+        self.wake_waiters(txn_id);
 
         Ok(())
     }
 
-    /// Rollback (abort) a transaction
-    /// Returns the entity snapshots that need to be restored
-    pub fn rollback(&self, txn_id: TransactionId) -> Result<HashMap<u64, String>, String> {
+    /// Rollback (abort) a transaction, discarding its versions
+    pub fn rollback(&self, txn_id: TransactionId) -> Result<(), String> {
         let mut active = self.active_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
@@ -191,15 +394,147 @@ impl TransactionManager {
 
         // Mark as aborted
         transaction.state = TransactionState::Aborted;
+        self.mvcc.rollback(txn_id);
+        self.collect_versions(&active);
+        self.savepoints.write().unwrap().remove(&txn_id);
+        self.wake_waiters(txn_id);
 
-        // Get and remove snapshots for this transaction
-        let mut snapshots = self.entity_snapshots.write()
-            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
+        Ok(())
+    }
+
+    /// Wait until no other transaction has uncommitted changes to an entity
+    ///
+    /// Gives up after the lock wait timeout; with none set, returns at once
+    /// and leaves the conflict for the write to report. Fails with a
+    /// [`DeadlockError`] if the transaction is aborted to break a wait
+    /// cycle, in which case it must be rolled back.
+    pub fn wait_for_writer(&self, txn_id: TransactionId, entity_id: EntityId) -> Result<(), String> {
+        let timeout = *self.lock_wait_timeout.read().unwrap();
+        let deadline = Instant::now() + timeout;
+        let mut waits = self.waits.lock().unwrap();
+
+        loop {
+            if let Some(cycle) = waits.victims.remove(&txn_id) {
+                waits.waits_for.remove(&txn_id);
+                return Err(self.abort_for_deadlock(txn_id, cycle));
+            }
+
+            let holder = match self.mvcc.uncommitted_writer(entity_id, txn_id) {
+                Some(holder) if !timeout.is_zero() => holder,
+                _ => {
+                    waits.waits_for.remove(&txn_id);
+                    return Ok(());
+                }
+            };
+
+            // A new wait may close a cycle; the youngest transaction in it gives way
+            if waits.waits_for.insert(txn_id, holder) != Some(holder) {
+                if let Some(mut cycle) = waits.cycle_from(txn_id) {
+                    self.deadlocks_detected.fetch_add(1, Ordering::SeqCst);
+                    let victim = *cycle.iter().max().expect("a cycle has transactions");
+                    let start = cycle.iter().position(|&member| member == victim).unwrap_or(0);
+                    cycle.rotate_left(start);
+
+                    if victim == txn_id {
+                        waits.waits_for.remove(&txn_id);
+                        return Err(self.abort_for_deadlock(txn_id, cycle));
+                    }
+                    waits.victims.insert(victim, cycle);
+                    self.waits_changed.notify_all();
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                waits.waits_for.remove(&txn_id);
+                self.lock_wait_timeouts.fetch_add(1, Ordering::SeqCst);
+                return Err(format!(
+                    "Lock wait timeout: transaction {} waited {:?} on transaction {}'s changes to entity {}",
+                    txn_id, timeout, holder, entity_id.0
+                ));
+            }
+            waits = self.waits_changed.wait_timeout(waits, deadline - now).unwrap().0;
+        }
+    }
 
-        let entity_snapshots = snapshots.remove(&txn_id)
-            .unwrap_or_default();
+    fn abort_for_deadlock(&self, victim: TransactionId, cycle: Vec<TransactionId>) -> String {
+        self.deadlock_aborts.fetch_add(1, Ordering::SeqCst);
+        DeadlockError { victim, cycle }.to_string()
+    }
 
-        Ok(entity_snapshots)
+    /// Let transactions waiting on an ended one carry on
+    fn wake_waiters(&self, txn_id: TransactionId) {
+        self.waits.lock().unwrap().forget(txn_id);
+        self.waits_changed.notify_all();
+    }
+
+    /// Set a savepoint in an active transaction
+    ///
+    /// A savepoint with the name of an open one hides it until released.
+    pub fn savepoint(&self, txn_id: TransactionId, name: &str) -> Result<(), String> {
+        if !self.active_transactions.read().unwrap().contains_key(&txn_id) {
+            return Err(format!("Transaction {} not found", txn_id));
+        }
+
+        self.savepoints.write().unwrap().entry(txn_id).or_default().push(Savepoint {
+            name: name.to_string(),
+            mark: self.mvcc.savepoint(txn_id),
+        });
+        Ok(())
+    }
+
+    /// Depth of a transaction's latest open savepoint with a name
+    pub fn savepoint_depth(&self, txn_id: TransactionId, name: &str) -> Result<usize, String> {
+        let savepoints = self.savepoints.read().unwrap();
+        Self::find_savepoint(savepoints.get(&txn_id).map_or(&[], Vec::as_slice), name)
+    }
+
+    /// Undo a transaction's writes since a savepoint
+    ///
+    /// The savepoint stays open and the ones set after it are released.
+    /// Returns the savepoint's depth (0 for the oldest open one).
+    pub fn rollback_to_savepoint(&self, txn_id: TransactionId, name: &str) -> Result<usize, String> {
+        let mut savepoints = self.savepoints.write().unwrap();
+        let stack = savepoints.entry(txn_id).or_default();
+        let depth = Self::find_savepoint(stack, name)?;
+
+        stack.truncate(depth + 1);
+        self.mvcc.rollback_to(txn_id, stack[depth].mark);
+
+        Ok(depth)
+    }
+
+    /// Release a savepoint and the ones set after it, keeping their changes
+    ///
+    /// The changes become part of the enclosing savepoint (or of the
+    /// transaction). Returns the released savepoint's depth.
+    pub fn release_savepoint(&self, txn_id: TransactionId, name: &str) -> Result<usize, String> {
+        let mut savepoints = self.savepoints.write().unwrap();
+        let stack = savepoints.entry(txn_id).or_default();
+        let depth = Self::find_savepoint(stack, name)?;
+        stack.truncate(depth);
+
+        Ok(depth)
+    }
+
+    /// Depth of the latest open savepoint with a name
+    fn find_savepoint(stack: &[Savepoint], name: &str) -> Result<usize, String> {
+        stack
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| format!("Savepoint '{}' does not exist", name))
+    }
+
+    /// Drop entity versions older than every active transaction's snapshot,
+    /// returning the oldest snapshot
+    fn collect_versions(&self, active: &HashMap<TransactionId, Transaction>) -> CommitTimestamp {
+        let oldest_snapshot = active
+            .values()
+            .map(|txn| txn.snapshot)
+            .min()
+            .unwrap_or_else(|| self.mvcc.current_snapshot());
+        self.mvcc.garbage_collect(oldest_snapshot);
+        oldest_snapshot
     }
 
     /// Get a transaction
@@ -239,31 +574,56 @@ impl TransactionManager {
         Ok(())
     }
 
-    /// Track a write operation
-    pub fn track_write(&self, txn_id: TransactionId, entity_id: u64) -> Result<(), String> {
+    /// A transaction's isolation level and snapshot, without copying its
+    /// read and write sets
+    pub fn snapshot_of(&self, txn_id: TransactionId) -> Result<(IsolationLevel, CommitTimestamp), String> {
+        let active = self.active_transactions.read()
+            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
+
+        active.get(&txn_id)
+            .map(|txn| (txn.isolation_level, txn.snapshot))
+            .ok_or_else(|| format!("Transaction {} not found", txn_id))
+    }
+
+    /// Track a scan of a collection, which a SERIALIZABLE transaction
+    /// must not see changed by one committing before it
+    pub fn track_scan(&self, txn_id: TransactionId, collection: &str) -> Result<(), String> {
         let mut active = self.active_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
         let transaction = active.get_mut(&txn_id)
             .ok_or_else(|| format!("Transaction {} not found", txn_id))?;
 
-        transaction.track_write(entity_id);
+        if transaction.isolation_level == IsolationLevel::Serializable {
+            transaction.track_scan(collection);
+        }
         Ok(())
     }
 
-    /// Save entity snapshot before modification (for rollback)
-    pub fn save_entity_snapshot(&self, txn_id: TransactionId, entity_id: u64, entity_json: String) -> Result<(), String> {
-        let mut snapshots = self.entity_snapshots.write()
+    /// Track writes to entities of a collection
+    pub fn track_writes(&self, txn_id: TransactionId, collection: &str, entity_ids: &[EntityId]) -> Result<(), String> {
+        let mut active = self.active_transactions.write()
             .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
-        let txn_snapshots = snapshots.get_mut(&txn_id)
-            .ok_or_else(|| format!("Transaction {} not found in snapshots", txn_id))?;
+        let transaction = active.get_mut(&txn_id)
+            .ok_or_else(|| format!("Transaction {} not found", txn_id))?;
 
-        // Only save if not already saved (first modification wins)
-        if !txn_snapshots.contains_key(&entity_id) {
-            txn_snapshots.insert(entity_id, entity_json);
+        if !transaction.write_collections.iter().any(|c| c == collection) {
+            transaction.write_collections.push(collection.to_string());
         }
+        transaction.write_set.extend(entity_ids.iter().map(|id| id.as_u64()));
+        Ok(())
+    }
+
+    /// Track a write operation
+    pub fn track_write(&self, txn_id: TransactionId, entity_id: u64) -> Result<(), String> {
+        let mut active = self.active_transactions.write()
+            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
 
+        let transaction = active.get_mut(&txn_id)
+            .ok_or_else(|| format!("Transaction {} not found", txn_id))?;
+
+        transaction.track_write(entity_id);
         Ok(())
     }
 
@@ -274,6 +634,25 @@ impl TransactionManager {
         active.keys().min().copied().unwrap_or(u64::MAX)
     }
 
+    /// Active transactions, oldest first
+    pub fn active_transactions(&self) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = self.active_transactions.read().unwrap().values().cloned().collect();
+        transactions.sort_by_key(|transaction| transaction.id);
+        transactions
+    }
+
+    /// Active transactions other than `except` that wrote to a collection, oldest first
+    pub fn writers_of(&self, collection: &str, except: TransactionId) -> Vec<TransactionId> {
+        let active = self.active_transactions.read().unwrap();
+        let mut writers: Vec<TransactionId> = active
+            .values()
+            .filter(|transaction| transaction.id != except && transaction.write_collections.iter().any(|c| c == collection))
+            .map(|transaction| transaction.id)
+            .collect();
+        writers.sort_unstable();
+        writers
+    }
+
     /// Get all active transaction IDs
     pub fn get_active_txn_ids(&self) -> Vec<TransactionId> {
         let active = self.active_transactions.read().unwrap();
@@ -305,33 +684,50 @@ impl TransactionManager {
     }
 
     /// Check for conflicts in repeatable read isolation
-    fn check_repeatable_read_conflicts(&self, _transaction: &Transaction) -> Result<(), String> {
-        // Simplified implementation
-        // Full version would:
-        // 1. Check if any read entities were modified by concurrent transactions
-        // 2. Check for write-write conflicts
-
-        // For now, just succeed (optimistic)
-        Ok(())
+    ///
+    /// First committer wins: no transaction that committed since the
+    /// snapshot may have written an entity this one wrote.
+    fn check_repeatable_read_conflicts(&self, transaction: &Transaction) -> Result<(), String> {
+        self.check_conflicts(transaction, false)
     }
 
     /// Check for conflicts in serializable isolation
-    fn check_serializable_conflicts(&self, _transaction: &Transaction) -> Result<(), String> {
-        // Simplified implementation
-        // Full version would:
-        // 1. Build dependency graph of transactions
-        // 2. Detect cycles (indicating non-serializable execution)
-        // 3. Abort if cycles found
-
-        // For now, just succeed (optimistic)
-        Ok(())
+    ///
+    /// Besides write-write conflicts, no transaction that committed since
+    /// the snapshot may have written what this one read: an entity in its
+    /// read set or any entity of a collection it scanned. Otherwise it
+    /// can't be ordered after them, having missed their writes.
+    fn check_serializable_conflicts(&self, transaction: &Transaction) -> Result<(), String> {
+        self.check_conflicts(transaction, true)
     }
 
-    /// Cleanup old committed transactions
-    pub fn cleanup_old_transactions(&self, min_txn_to_keep: TransactionId) {
-        let mut committed = self.committed_transactions.write().unwrap();
+    fn check_conflicts(&self, transaction: &Transaction, check_reads: bool) -> Result<(), String> {
+        // Lone statements and pure readers serialize at their snapshot
+        if transaction.autocommit || (transaction.write_set.is_empty() && transaction.write_collections.is_empty()) {
+            return Ok(());
+        }
+
+        let written: HashSet<u64> = transaction.write_set.iter().copied().collect();
+        let read: HashSet<u64> = transaction.read_set.iter().map(|(entity_id, _)| *entity_id).collect();
+        let conflicts = |other: &Transaction| {
+            other.write_set.iter().any(|id| written.contains(id))
+                || (check_reads
+                    && (other.write_set.iter().any(|id| read.contains(id))
+                        || other.write_collections.iter().any(|c| transaction.read_collections.contains(c))))
+        };
 
-        committed.retain(|&id, _| id >= min_txn_to_keep);
+        let committed = self.committed_transactions.read()
+            .map_err(|e| format!("Failed to acquire lock: {}", e))?;
+        let first = committed
+            .values()
+            .filter(|other| other.commit_timestamp.is_some_and(|ts| ts > transaction.snapshot))
+            .filter(|other| conflicts(other))
+            .min_by_key(|other| other.commit_timestamp);
+
+        match first {
+            Some(other) => Err(SerializationError { txn_id: transaction.id, conflicting: other.id }.to_string()),
+            None => Ok(()),
+        }
     }
 
     /// Get transaction statistics
@@ -341,8 +737,13 @@ impl TransactionManager {
 
         TransactionStats {
             active_count: active.len(),
-            committed_count: committed.len(),
+            committed_count: self.commits.load(Ordering::SeqCst) as usize,
+            retained_count: committed.len(),
             rollbacked_count: 0, // We don't track rollbacks separately
+            deadlocks_detected: self.deadlocks_detected.load(Ordering::SeqCst),
+            deadlock_aborts: self.deadlock_aborts.load(Ordering::SeqCst),
+            lock_wait_timeouts: self.lock_wait_timeouts.load(Ordering::SeqCst),
+            serialization_aborts: self.serialization_aborts.load(Ordering::SeqCst),
         }
     }
 }
@@ -351,8 +752,19 @@ impl TransactionManager {
 #[derive(Debug, Clone)]
 pub struct TransactionStats {
     pub active_count: usize,
+    /// Transactions committed since the manager started
     pub committed_count: usize,
+    /// Committed transactions kept to validate active ones against
+    pub retained_count: usize,
     pub rollbacked_count: usize,
+    /// Wait cycles found between transactions
+    pub deadlocks_detected: u64,
+    /// Transactions aborted to break a wait cycle
+    pub deadlock_aborts: u64,
+    /// Writes that gave up waiting on another transaction
+    pub lock_wait_timeouts: u64,
+    /// Commits refused for conflicting with an earlier committer
+    pub serialization_aborts: u64,
 }
 
 impl Default for TransactionManager {
@@ -380,11 +792,20 @@ mod tests {
     fn test_commit_transaction() {
         let mgr = TransactionManager::new();
 
+        let reader = mgr.begin(IsolationLevel::default()).unwrap();
         let txn_id = mgr.begin(IsolationLevel::default()).unwrap();
         mgr.commit(txn_id).unwrap();
 
+        // Kept while an older snapshot may conflict with it
         let txn = mgr.get_transaction(txn_id).unwrap();
         assert!(txn.is_committed());
+
+        mgr.rollback(reader).unwrap();
+        let later = mgr.begin(IsolationLevel::default()).unwrap();
+        mgr.commit(later).unwrap();
+        assert!(mgr.get_transaction(txn_id).is_err());
+        assert_eq!(mgr.stats().retained_count, 0);
+        assert_eq!(mgr.stats().committed_count, 2);
     }
 
     #[test]
@@ -411,6 +832,171 @@ mod tests {
         assert_eq!(txn.write_set.len(), 1);
     }
 
+    #[test]
+    fn test_savepoint_undo_covers_changes_since_it() {
+        let mgr = TransactionManager::new();
+        let txn_id = mgr.begin(IsolationLevel::default()).unwrap();
+        let written = |mgr: &TransactionManager| -> Vec<u64> {
+            mgr.mvcc().writes_of(txn_id).entities.iter().map(|(id, _)| id.0).collect()
+        };
+
+        write(&mgr, txn_id, 1);
+        mgr.savepoint(txn_id, "a").unwrap();
+        write(&mgr, txn_id, 2);
+        mgr.savepoint(txn_id, "b").unwrap();
+        write(&mgr, txn_id, 3);
+
+        // Rolling back to `a` undoes what followed it and releases `b`
+        assert_eq!(mgr.rollback_to_savepoint(txn_id, "a").unwrap(), 0);
+        assert_eq!(written(&mgr), vec![1]);
+        assert!(mgr.release_savepoint(txn_id, "b").is_err());
+
+        // Released changes move to the enclosing savepoint
+        mgr.savepoint(txn_id, "c").unwrap();
+        write(&mgr, txn_id, 4);
+        assert_eq!(mgr.release_savepoint(txn_id, "c").unwrap(), 1);
+        assert_eq!(written(&mgr), vec![1, 4]);
+        mgr.rollback_to_savepoint(txn_id, "a").unwrap();
+        assert_eq!(written(&mgr), vec![1]);
+
+        // The whole transaction still rolls back to before it began
+        mgr.rollback(txn_id).unwrap();
+        assert!(!mgr.mvcc().has_versions());
+        assert!(mgr.savepoint(txn_id, "a").is_err());
+    }
+
+    fn write(mgr: &TransactionManager, txn_id: TransactionId, id: u64) {
+        let entity = crate::graph::Entity::new(EntityId::new(id), "Users".to_string(), Default::default());
+        mgr.mvcc().record_write(txn_id, entity.id, Some(&entity), Some(entity.clone())).unwrap();
+    }
+
+    fn abort(mgr: &TransactionManager, txn_id: TransactionId) {
+        mgr.rollback(txn_id).unwrap();
+    }
+
+    #[test]
+    fn test_two_way_deadlock_aborts_the_younger_transaction() {
+        let mgr = Arc::new(TransactionManager::new());
+        mgr.set_lock_wait_timeout(Duration::from_secs(10));
+        let t1 = mgr.begin(IsolationLevel::default()).unwrap();
+        let t2 = mgr.begin(IsolationLevel::default()).unwrap();
+        write(&mgr, t1, 1);
+        write(&mgr, t2, 2);
+
+        let started = Instant::now();
+        let waiter = std::thread::spawn({
+            let mgr = mgr.clone();
+            move || mgr.wait_for_writer(t1, EntityId::new(2))
+        });
+        let err = mgr.wait_for_writer(t2, EntityId::new(1)).unwrap_err();
+        assert_eq!(err, DeadlockError { victim: t2, cycle: vec![t2, t1] }.to_string());
+
+        // The survivor goes on once the victim rolls back
+        abort(&mgr, t2);
+        waiter.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let stats = mgr.stats();
+        assert_eq!((stats.deadlocks_detected, stats.deadlock_aborts, stats.lock_wait_timeouts), (1, 1, 0));
+    }
+
+    #[test]
+    fn test_three_way_deadlock_resolves() {
+        let mgr = Arc::new(TransactionManager::new());
+        mgr.set_lock_wait_timeout(Duration::from_secs(10));
+        let txns: Vec<TransactionId> = (0..3).map(|_| mgr.begin(IsolationLevel::default()).unwrap()).collect();
+        for (i, &txn_id) in txns.iter().enumerate() {
+            write(&mgr, txn_id, i as u64 + 1);
+        }
+
+        // Each waits on the next one's entity; the last one on the first's
+        let waiters: Vec<_> = txns[..2]
+            .iter()
+            .enumerate()
+            .map(|(i, &txn_id)| {
+                let mgr = mgr.clone();
+                std::thread::spawn(move || mgr.wait_for_writer(txn_id, EntityId::new(i as u64 + 2)))
+            })
+            .collect();
+        let err = mgr.wait_for_writer(txns[2], EntityId::new(1)).unwrap_err();
+        assert!(DeadlockError::is_deadlock(&err), "unexpected error: {}", err);
+
+        abort(&mgr, txns[2]);
+        let mut waiters = waiters.into_iter();
+        let first = waiters.next().unwrap();
+        waiters.next().unwrap().join().unwrap().unwrap();
+        mgr.commit(txns[1]).unwrap();
+        first.join().unwrap().unwrap();
+
+        let stats = mgr.stats();
+        assert_eq!((stats.deadlocks_detected, stats.deadlock_aborts), (1, 1));
+    }
+
+    #[test]
+    fn test_non_conflicting_writes_do_not_wait() {
+        let mgr = TransactionManager::new();
+        let t1 = mgr.begin(IsolationLevel::default()).unwrap();
+        let t2 = mgr.begin(IsolationLevel::default()).unwrap();
+        write(&mgr, t1, 1);
+        write(&mgr, t2, 2);
+
+        // Without a timeout a conflict is left for the write to report
+        mgr.wait_for_writer(t2, EntityId::new(1)).unwrap();
+
+        mgr.set_lock_wait_timeout(Duration::from_millis(20));
+        mgr.wait_for_writer(t1, EntityId::new(1)).unwrap();
+        mgr.wait_for_writer(t1, EntityId::new(3)).unwrap();
+        mgr.wait_for_writer(t2, EntityId::new(2)).unwrap();
+        let err = mgr.wait_for_writer(t2, EntityId::new(1)).unwrap_err();
+        assert!(err.starts_with("Lock wait timeout"), "unexpected error: {}", err);
+
+        // Once the writer commits the entity is free
+        mgr.commit(t1).unwrap();
+        mgr.wait_for_writer(t2, EntityId::new(1)).unwrap();
+
+        let stats = mgr.stats();
+        assert_eq!((stats.deadlocks_detected, stats.deadlock_aborts, stats.lock_wait_timeouts), (0, 0, 1));
+    }
+
+    #[test]
+    fn test_commit_validation_by_isolation_level() {
+        let mgr = TransactionManager::new();
+        let users = |ids: &[u64]| ids.iter().map(|&id| EntityId::new(id)).collect::<Vec<_>>();
+
+        // SERIALIZABLE: a scanned collection written by an earlier committer
+        let t1 = mgr.begin(IsolationLevel::Serializable).unwrap();
+        let t2 = mgr.begin(IsolationLevel::Serializable).unwrap();
+        mgr.track_scan(t1, "Users").unwrap();
+        mgr.track_scan(t2, "Users").unwrap();
+        mgr.track_writes(t1, "Users", &users(&[1])).unwrap();
+        mgr.track_writes(t2, "Users", &users(&[2])).unwrap();
+        mgr.commit(t1).unwrap();
+        let err = mgr.commit(t2).unwrap_err();
+        assert_eq!(err, SerializationError { txn_id: t2, conflicting: t1 }.to_string());
+        mgr.rollback(t2).unwrap();
+
+        // REPEATABLE READ: only the same entity, and not for lone statements
+        let t3 = mgr.begin(IsolationLevel::RepeatableRead).unwrap();
+        let t4 = mgr.begin(IsolationLevel::RepeatableRead).unwrap();
+        let t5 = mgr.begin_autocommit(IsolationLevel::RepeatableRead).unwrap();
+        mgr.track_scan(t4, "Users").unwrap();
+        mgr.track_writes(t3, "Users", &users(&[3])).unwrap();
+        mgr.track_writes(t4, "Users", &users(&[4])).unwrap();
+        mgr.track_writes(t5, "Users", &users(&[3])).unwrap();
+        mgr.commit(t3).unwrap();
+        mgr.commit(t4).unwrap();
+        mgr.commit(t5).unwrap();
+
+        let t6 = mgr.begin(IsolationLevel::RepeatableRead).unwrap();
+        let t7 = mgr.begin(IsolationLevel::RepeatableRead).unwrap();
+        mgr.track_writes(t6, "Users", &users(&[5])).unwrap();
+        mgr.track_writes(t7, "Users", &users(&[5])).unwrap();
+        mgr.commit(t6).unwrap();
+        assert!(SerializationError::is_serialization_failure(&mgr.commit(t7).unwrap_err()));
+
+        assert_eq!(mgr.stats().serialization_aborts, 2);
+    }
+
     #[test]
     fn test_get_min_active_txn() {
         let mgr = TransactionManager::new();
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Unique identifier for entities (nodes)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct EntityId(pub u64);
 
 impl EntityId {
@@ -40,6 +40,11 @@ pub enum PropertyValue {
     Float(f64),
     String(String),
     Bytes(Vec<u8>),
+    List(Vec<PropertyValue>),
+    /// Nested document
+    Map(HashMap<String, PropertyValue>),
+    /// Milliseconds since the Unix epoch, UTC
+    Timestamp(i64),
 }
 
 impl PropertyValue {
@@ -64,6 +69,107 @@ impl PropertyValue {
             _ => None,
         }
     }
+
+    /// Approximate bytes the value occupies, nested contents included
+    pub fn approximate_size(&self) -> usize {
+        let contents = match self {
+            PropertyValue::String(s) => s.len(),
+            PropertyValue::Bytes(b) => b.len(),
+            PropertyValue::List(items) => items.iter().map(PropertyValue::approximate_size).sum(),
+            PropertyValue::Map(map) => map.iter().map(|(k, v)| k.len() + v.approximate_size()).sum(),
+            _ => 0,
+        };
+        std::mem::size_of::<PropertyValue>() + contents
+    }
+
+    /// Value under `key` of a nested document
+    pub fn get(&self, key: &str) -> Option<&PropertyValue> {
+        match self {
+            PropertyValue::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Hashable identity used to deduplicate values (e.g. for DISTINCT)
+    ///
+    /// Integral floats collapse onto the integer key, so `1` and `1.0` are
+    /// one value; all NaNs are one value.
+    pub fn distinct_key(&self) -> DistinctKey {
+        match self {
+            PropertyValue::Null => DistinctKey::Null,
+            PropertyValue::Bool(b) => DistinctKey::Bool(*b),
+            PropertyValue::Int(n) => DistinctKey::Int(*n),
+            PropertyValue::Float(f) if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 => {
+                DistinctKey::Int(*f as i64)
+            }
+            PropertyValue::Float(f) if f.is_nan() => DistinctKey::Float(f64::NAN.to_bits()),
+            PropertyValue::Float(f) => DistinctKey::Float(f.to_bits()),
+            PropertyValue::String(s) => DistinctKey::String(s.clone()),
+            PropertyValue::Bytes(b) => DistinctKey::Bytes(b.clone()),
+            PropertyValue::List(items) => DistinctKey::List(items.iter().map(PropertyValue::distinct_key).collect()),
+            PropertyValue::Map(map) => {
+                let mut entries: Vec<(String, DistinctKey)> =
+                    map.iter().map(|(k, v)| (k.clone(), v.distinct_key())).collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                DistinctKey::Map(entries)
+            }
+            PropertyValue::Timestamp(ms) => DistinctKey::Timestamp(*ms),
+        }
+    }
+}
+
+/// Canonical, hashable form of a [`PropertyValue`] (see [`PropertyValue::distinct_key`])
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DistinctKey {
+    Null,
+    Bool(bool),
+    Int(i64),
+    /// Bit pattern of a non-integral float
+    Float(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<DistinctKey>),
+    /// Entries sorted by key
+    Map(Vec<(String, DistinctKey)>),
+    Timestamp(i64),
+    /// Query results only: entity ID, edge ID, and path of entity IDs
+    EntityId(u64),
+    EdgeId(u64),
+    Path(Vec<u64>),
+}
+
+/// Parse an ISO-8601 timestamp into milliseconds since the Unix epoch
+///
+/// Accepts RFC 3339 (`2024-03-01T12:00:00Z`, with any offset), a date and
+/// time without an offset (taken as UTC, `T` or space separated), or a bare
+/// date (midnight UTC).
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+    let text = text.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.timestamp_millis());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(dt.and_utc().timestamp_millis());
+        }
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc().timestamp_millis())
+}
+
+/// Format milliseconds since the Unix epoch as RFC 3339 UTC, e.g.
+/// `2024-03-01T12:00:00.000Z`
+pub fn format_timestamp(ms: i64) -> String {
+    use chrono::{SecondsFormat, TimeZone, Utc};
+
+    match Utc.timestamp_millis_opt(ms).single() {
+        Some(dt) => dt.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => ms.to_string(),
+    }
 }
 
 /// Properties map (like row columns or node attributes)
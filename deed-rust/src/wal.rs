@@ -2,14 +2,16 @@
 //!
 //! Ensures durability - committed transactions survive crashes.
 
-use crate::graph::Entity;
+use crate::graph::{Edge, Entity, Graph};
 use crate::transaction::{TransactionId, IsolationLevel};
 use crate::types::{EntityId, EdgeId, Properties};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 /// WAL file magic number
 const WAL_MAGIC: u32 = 0xDEED_0001;
@@ -17,6 +19,9 @@ const WAL_MAGIC: u32 = 0xDEED_0001;
 /// WAL format version
 const WAL_VERSION: u32 = 1;
 
+/// Transaction ID the checkpoint's snapshot is logged under
+const CHECKPOINT_TXN: TransactionId = 0;
+
 /// Write-Ahead Log entry types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WALEntry {
@@ -27,8 +32,9 @@ pub enum WALEntry {
         timestamp: u64,
     },
 
-    /// Insert entity
-    InsertEntity {
+    /// Insert entity, as logged before creation times were (replayed as
+    /// created when replayed)
+    LegacyInsertEntity {
         txn_id: TransactionId,
         entity_id: u64,
         entity_type: String,
@@ -84,6 +90,74 @@ pub enum WALEntry {
         txn_id: TransactionId,
         timestamp: u64,
     },
+
+    /// Replace an edge's properties
+    UpdateEdge {
+        txn_id: TransactionId,
+        edge_id: u64,
+        properties: Properties,
+    },
+
+    /// Insert a batch of entities of one type, as logged before creation
+    /// times were
+    LegacyInsertEntities {
+        txn_id: TransactionId,
+        entity_type: String,
+        entities: Vec<(u64, Properties)>,
+    },
+
+    /// Savepoint within a transaction
+    Savepoint {
+        txn_id: TransactionId,
+        name: String,
+    },
+
+    /// Discard the transaction's changes since its latest savepoint of this name
+    RollbackToSavepoint {
+        txn_id: TransactionId,
+        name: String,
+    },
+
+    /// Forget the transaction's latest savepoint of this name, and later ones
+    ReleaseSavepoint {
+        txn_id: TransactionId,
+        name: String,
+    },
+
+    /// Remove a collection with its entities and their edges
+    DropCollection {
+        txn_id: TransactionId,
+        collection: String,
+    },
+
+    /// Insert entity, created at `created_at` (milliseconds since the Unix epoch)
+    InsertEntity {
+        txn_id: TransactionId,
+        entity_id: u64,
+        entity_type: String,
+        properties: Properties,
+        created_at: u64,
+    },
+
+    /// Insert a batch of entities of one type, as (ID, properties, creation time)
+    InsertEntities {
+        txn_id: TransactionId,
+        entity_type: String,
+        entities: Vec<(u64, Properties, u64)>,
+    },
+
+    /// Remove entities and their outgoing edges, keeping the edges into them
+    EvictEntities {
+        txn_id: TransactionId,
+        entity_ids: Vec<u64>,
+    },
+
+    /// Opens a checkpointed log: LSNs continue from `lsn`, the last one the
+    /// log it replaced had reached
+    LogStart {
+        txn_id: TransactionId,
+        lsn: u64,
+    },
 }
 
 impl WALEntry {
@@ -99,6 +173,16 @@ impl WALEntry {
             WALEntry::Commit { txn_id, .. } => *txn_id,
             WALEntry::Rollback { txn_id, .. } => *txn_id,
             WALEntry::Checkpoint { txn_id, .. } => *txn_id,
+            WALEntry::UpdateEdge { txn_id, .. } => *txn_id,
+            WALEntry::InsertEntities { txn_id, .. } => *txn_id,
+            WALEntry::Savepoint { txn_id, .. } => *txn_id,
+            WALEntry::RollbackToSavepoint { txn_id, .. } => *txn_id,
+            WALEntry::ReleaseSavepoint { txn_id, .. } => *txn_id,
+            WALEntry::DropCollection { txn_id, .. } => *txn_id,
+            WALEntry::LegacyInsertEntity { txn_id, .. } => *txn_id,
+            WALEntry::LegacyInsertEntities { txn_id, .. } => *txn_id,
+            WALEntry::EvictEntities { txn_id, .. } => *txn_id,
+            WALEntry::LogStart { txn_id, .. } => *txn_id,
         }
     }
 
@@ -141,7 +225,6 @@ impl WALHeader {
 /// Write-Ahead Log writer
 pub struct WALWriter {
     file: Arc<Mutex<BufWriter<File>>>,
-    path: PathBuf,
     entry_count: usize,
 }
 
@@ -162,7 +245,7 @@ impl WALWriter {
         if writer.get_ref().metadata()?.len() == 0 {
             let header = WALHeader::new();
             let header_bytes = bincode::serialize(&header)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                .map_err(io::Error::other)?;
 
             writer.write_all(&header_bytes)?;
             writer.flush()?;
@@ -170,18 +253,23 @@ impl WALWriter {
 
         Ok(WALWriter {
             file: Arc::new(Mutex::new(writer)),
-            path,
             entry_count: 0,
         })
     }
 
-    /// Write a WAL entry
+    /// Write a WAL entry and fsync it
     pub fn write_entry(&mut self, entry: &WALEntry) -> io::Result<()> {
+        self.append_entry(entry)?;
+        self.flush()
+    }
+
+    /// Buffer a WAL entry without syncing it; it is durable after `flush`
+    pub fn append_entry(&mut self, entry: &WALEntry) -> io::Result<()> {
         let mut file = self.file.lock().unwrap();
 
         // Serialize entry
         let entry_bytes = bincode::serialize(entry)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
 
         // Write length prefix
         let len = entry_bytes.len() as u32;
@@ -190,15 +278,16 @@ impl WALWriter {
         // Write entry
         file.write_all(&entry_bytes)?;
 
-        // Flush to ensure durability (fsync)
-        file.flush()?;
-        file.get_mut().sync_all()?;
-
         self.entry_count += 1;
 
         Ok(())
     }
 
+    /// Hand buffered entries to the OS without an fsync
+    pub fn flush_buffer(&self) -> io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+
     /// Get entry count
     pub fn entry_count(&self) -> usize {
         self.entry_count
@@ -253,9 +342,13 @@ impl WALReader {
 
         let len = u32::from_le_bytes(len_bytes) as usize;
 
-        // Read entry
+        // Read entry; a torn write at the end of the log ends it
         let mut entry_bytes = vec![0u8; len];
-        self.file.read_exact(&mut entry_bytes)?;
+        match self.file.read_exact(&mut entry_bytes) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
 
         // Deserialize entry
         let entry: WALEntry = bincode::deserialize(&entry_bytes)
@@ -280,20 +373,138 @@ impl WALReader {
 pub struct WALManager {
     writer: Arc<Mutex<WALWriter>>,
     path: PathBuf,
+    config: WALConfig,
+    checkpoint_policy: Mutex<CheckpointPolicy>,
+    last_checkpoint: Mutex<Instant>,
+    /// LSN of the last appended entry
+    appended_lsn: AtomicU64,
+    sync_state: Mutex<SyncState>,
+    synced: Condvar,
+    sync_count: AtomicU64,
+}
+
+/// How commits reach disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// fsync after every entry
+    Always,
+    /// Commits share fsyncs, each covering every commit queued behind it
+    Group,
+    /// Hand commits to the OS without an fsync; a crash may lose them
+    Off,
+}
+
+/// WAL durability settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WALConfig {
+    pub sync_mode: SyncMode,
+    /// How long a group commit waits for others to join its fsync
+    pub max_batch_delay: Duration,
+    /// Sync a group as soon as this many commits are waiting
+    pub max_batch_size: usize,
+}
+
+impl Default for WALConfig {
+    fn default() -> Self {
+        WALConfig {
+            sync_mode: SyncMode::Group,
+            max_batch_delay: Duration::from_millis(1),
+            max_batch_size: 64,
+        }
+    }
+}
+
+/// Progress of group commit syncs
+#[derive(Debug, Default)]
+struct SyncState {
+    /// Every entry up to this LSN is on disk
+    durable_lsn: u64,
+    /// A leader is gathering or syncing a group
+    syncing: bool,
+    /// Commits waiting for their entries to be durable
+    waiters: usize,
+}
+
+/// When the log is due for a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// Checkpoint once the log reaches this many bytes
+    pub max_log_bytes: Option<u64>,
+    /// Checkpoint once this long has passed since the last one
+    pub max_interval: Option<Duration>,
+}
+
+impl Default for CheckpointPolicy {
+    fn default() -> Self {
+        CheckpointPolicy {
+            max_log_bytes: Some(64 * 1024 * 1024),
+            max_interval: None,
+        }
+    }
+}
+
+impl CheckpointPolicy {
+    /// Never checkpoint automatically
+    pub fn manual() -> Self {
+        CheckpointPolicy {
+            max_log_bytes: None,
+            max_interval: None,
+        }
+    }
 }
 
 impl WALManager {
     /// Create a new WAL manager
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_config(path, WALConfig::default())
+    }
+
+    /// Create a new WAL manager with the given durability settings
+    pub fn with_config<P: AsRef<Path>>(path: P, config: WALConfig) -> io::Result<Self> {
         let path = path.as_ref().to_path_buf();
         let writer = WALWriter::new(&path)?;
+        let lsn = Self::logged_lsn(&path)?;
 
         Ok(WALManager {
             writer: Arc::new(Mutex::new(writer)),
             path,
+            config,
+            checkpoint_policy: Mutex::new(CheckpointPolicy::default()),
+            last_checkpoint: Mutex::new(Instant::now()),
+            appended_lsn: AtomicU64::new(lsn),
+            sync_state: Mutex::new(SyncState { durable_lsn: lsn, ..Default::default() }),
+            synced: Condvar::new(),
+            sync_count: AtomicU64::new(0),
         })
     }
 
+    /// Set when automatic checkpoints are due
+    pub fn set_checkpoint_policy(&self, policy: CheckpointPolicy) {
+        *self.checkpoint_policy.lock().unwrap() = policy;
+    }
+
+    /// Current size of the log file in bytes
+    pub fn log_size(&self) -> io::Result<u64> {
+        self.writer.lock().unwrap().flush_buffer()?;
+        Ok(std::fs::metadata(&self.path)?.len())
+    }
+
+    /// Whether the checkpoint policy calls for a checkpoint now
+    pub fn checkpoint_due(&self) -> io::Result<bool> {
+        let policy = *self.checkpoint_policy.lock().unwrap();
+
+        if let Some(max_interval) = policy.max_interval {
+            if self.last_checkpoint.lock().unwrap().elapsed() >= max_interval {
+                return Ok(true);
+            }
+        }
+
+        match policy.max_log_bytes {
+            Some(max_bytes) => Ok(self.log_size()? >= max_bytes),
+            None => Ok(false),
+        }
+    }
+
     /// Log a begin transaction
     pub fn log_begin(
         &self,
@@ -306,7 +517,7 @@ impl WALManager {
             timestamp: Self::current_timestamp(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
     }
 
     /// Log an insert operation
@@ -320,9 +531,29 @@ impl WALManager {
             entity_id: entity.id.as_u64(),
             entity_type: entity.entity_type.clone(),
             properties: entity.properties.clone(),
+            created_at: entity.created_millis(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log a batch insert as a single record
+    pub fn log_insert_batch(
+        &self,
+        txn_id: TransactionId,
+        entity_type: &str,
+        entities: &[Entity],
+    ) -> io::Result<()> {
+        let entry = WALEntry::InsertEntities {
+            txn_id,
+            entity_type: entity_type.to_string(),
+            entities: entities
+                .iter()
+                .map(|entity| (entity.id.as_u64(), entity.properties.clone(), entity.created_millis()))
+                .collect(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
     }
 
     /// Log an update operation
@@ -340,7 +571,7 @@ impl WALManager {
             new_properties: new_props,
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
     }
 
     /// Log a delete operation
@@ -356,17 +587,76 @@ impl WALManager {
             properties: entity.properties.clone(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log the eviction of entities that moved to another node
+    pub fn log_evict(&self, txn_id: TransactionId, entity_ids: &[EntityId]) -> io::Result<()> {
+        let entry = WALEntry::EvictEntities {
+            txn_id,
+            entity_ids: entity_ids.iter().map(|id| id.as_u64()).collect(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log an edge creation
+    pub fn log_create_edge(
+        &self,
+        txn_id: TransactionId,
+        edge: &Edge,
+    ) -> io::Result<()> {
+        let entry = WALEntry::CreateEdge {
+            txn_id,
+            edge_id: edge.id.as_u64(),
+            source_id: edge.source.as_u64(),
+            target_id: edge.target.as_u64(),
+            edge_type: edge.edge_type.clone(),
+            properties: edge.properties.clone(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log an edge property update
+    pub fn log_update_edge(
+        &self,
+        txn_id: TransactionId,
+        edge_id: EdgeId,
+        properties: Properties,
+    ) -> io::Result<()> {
+        let entry = WALEntry::UpdateEdge {
+            txn_id,
+            edge_id: edge_id.as_u64(),
+            properties,
+        };
+
+        self.append(&entry).map(|_| ())
     }
 
-    /// Log a commit
+    /// Log an edge deletion
+    pub fn log_delete_edge(&self, txn_id: TransactionId, edge_id: EdgeId) -> io::Result<()> {
+        let entry = WALEntry::DeleteEdge {
+            txn_id,
+            edge_id: edge_id.as_u64(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log a commit, returning once it is durable under the sync mode
     pub fn log_commit(&self, txn_id: TransactionId) -> io::Result<()> {
         let entry = WALEntry::Commit {
             txn_id,
             timestamp: Self::current_timestamp(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        let lsn = self.append(&entry)?;
+        match self.config.sync_mode {
+            SyncMode::Always => Ok(()),
+            SyncMode::Group => self.sync_to(lsn),
+            SyncMode::Off => self.writer.lock().unwrap().flush_buffer(),
+        }
     }
 
     /// Log a rollback
@@ -376,7 +666,47 @@ impl WALManager {
             timestamp: Self::current_timestamp(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log a savepoint
+    pub fn log_savepoint(&self, txn_id: TransactionId, name: &str) -> io::Result<()> {
+        let entry = WALEntry::Savepoint {
+            txn_id,
+            name: name.to_string(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log a rollback to a savepoint
+    pub fn log_rollback_to_savepoint(&self, txn_id: TransactionId, name: &str) -> io::Result<()> {
+        let entry = WALEntry::RollbackToSavepoint {
+            txn_id,
+            name: name.to_string(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log the release of a savepoint
+    pub fn log_release_savepoint(&self, txn_id: TransactionId, name: &str) -> io::Result<()> {
+        let entry = WALEntry::ReleaseSavepoint {
+            txn_id,
+            name: name.to_string(),
+        };
+
+        self.append(&entry).map(|_| ())
+    }
+
+    /// Log the removal of a collection
+    pub fn log_drop_collection(&self, txn_id: TransactionId, collection: &str) -> io::Result<()> {
+        let entry = WALEntry::DropCollection {
+            txn_id,
+            collection: collection.to_string(),
+        };
+
+        self.append(&entry).map(|_| ())
     }
 
     /// Log a checkpoint
@@ -386,16 +716,115 @@ impl WALManager {
             timestamp: Self::current_timestamp(),
         };
 
-        self.writer.lock().unwrap().write_entry(&entry)
+        self.append(&entry).map(|_| ())
     }
 
     /// Flush WAL to disk
     pub fn flush(&self) -> io::Result<()> {
-        self.writer.lock().unwrap().flush()
+        let writer = self.writer.lock().unwrap();
+        let target = self.appended_lsn.load(Ordering::SeqCst);
+        writer.flush()?;
+        self.sync_count.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = self.sync_state.lock().unwrap();
+        state.durable_lsn = state.durable_lsn.max(target);
+        self.synced.notify_all();
+        Ok(())
+    }
+
+    /// LSN of the last entry appended
+    ///
+    /// LSNs only grow, across restarts and checkpoints of the same log.
+    pub fn current_lsn(&self) -> u64 {
+        self.appended_lsn.load(Ordering::SeqCst)
+    }
+
+    /// LSN an existing log has reached: where its checkpoint started it,
+    /// plus an LSN for each entry since
+    fn logged_lsn(path: &Path) -> io::Result<u64> {
+        let entries = WALReader::new(path)?.read_all()?;
+        Ok(match entries.first() {
+            Some(WALEntry::LogStart { lsn, .. }) => lsn + entries.len() as u64 - 1,
+            _ => entries.len() as u64,
+        })
+    }
+
+    /// Number of fsyncs issued for commits and flushes
+    pub fn sync_count(&self) -> u64 {
+        self.sync_count.load(Ordering::Relaxed)
+    }
+
+    /// Append an entry, returning its log sequence number
+    fn append(&self, entry: &WALEntry) -> io::Result<u64> {
+        let mut writer = self.writer.lock().unwrap();
+        if self.config.sync_mode == SyncMode::Always {
+            writer.write_entry(entry)?;
+            self.sync_count.fetch_add(1, Ordering::Relaxed);
+        } else {
+            writer.append_entry(entry)?;
+        }
+
+        // Assigned under the writer lock, so LSNs follow file order
+        Ok(self.appended_lsn.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    /// Wait until every entry up to `lsn` is on disk
+    ///
+    /// The first caller to find no sync in progress leads the next one: it
+    /// waits up to `max_batch_delay` for more commits to queue up (or until
+    /// `max_batch_size` are waiting), then fsyncs everything appended so far
+    /// and releases every caller that sync covered.
+    fn sync_to(&self, lsn: u64) -> io::Result<()> {
+        let mut state = self.sync_state.lock().unwrap();
+        state.waiters += 1;
+        self.synced.notify_all();
+
+        let result = loop {
+            if state.durable_lsn >= lsn {
+                break Ok(());
+            }
+
+            if state.syncing {
+                state = self.synced.wait(state).unwrap();
+                continue;
+            }
+
+            state.syncing = true;
+            if !self.config.max_batch_delay.is_zero() {
+                let max_batch_size = self.config.max_batch_size;
+                state = self
+                    .synced
+                    .wait_timeout_while(state, self.config.max_batch_delay, |s| s.waiters < max_batch_size)
+                    .unwrap()
+                    .0;
+            }
+            drop(state);
+
+            let synced = {
+                let writer = self.writer.lock().unwrap();
+                let target = self.appended_lsn.load(Ordering::SeqCst);
+                writer.flush().map(|_| target)
+            };
+
+            state = self.sync_state.lock().unwrap();
+            state.syncing = false;
+            self.synced.notify_all();
+            match synced {
+                Ok(target) => {
+                    self.sync_count.fetch_add(1, Ordering::Relaxed);
+                    state.durable_lsn = state.durable_lsn.max(target);
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        state.waiters -= 1;
+        result
     }
 
     /// Recover from WAL
     pub fn recover(&self) -> io::Result<RecoveryResult> {
+        self.writer.lock().unwrap().flush_buffer()?;
         let mut reader = WALReader::new(&self.path)?;
         let entries = reader.read_all()?;
 
@@ -403,6 +832,10 @@ impl WALManager {
 
         for entry in entries {
             match &entry {
+                WALEntry::Checkpoint { txn_id: CHECKPOINT_TXN, .. } => {
+                    // A snapshot checkpoint supersedes everything before it
+                    result = RecoveryResult::new();
+                }
                 WALEntry::BeginTransaction { txn_id, .. } => {
                     result.active_txns.insert(*txn_id);
                 }
@@ -425,6 +858,80 @@ impl WALManager {
         Ok(result)
     }
 
+    /// Replace the log with a checkpoint of the graph's current state
+    ///
+    /// The new log holds one committed transaction that recreates every
+    /// entity and edge, so replaying it rebuilds the graph as it is now.
+    /// It is written next to the old log and renamed over it, so a crash
+    /// part way through leaves the old log in place. Uncommitted changes in
+    /// the graph would be made durable, so callers checkpoint only while no
+    /// transaction is open.
+    pub fn checkpoint(&self, graph: &Graph) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.flush()?;
+
+        let tmp_path = self.path.with_extension("checkpoint");
+        if tmp_path.exists() {
+            std::fs::remove_file(&tmp_path)?;
+        }
+
+        {
+            let mut tmp = WALWriter::new(&tmp_path)?;
+            let timestamp = Self::current_timestamp();
+
+            let lsn = self.appended_lsn.load(Ordering::SeqCst);
+            tmp.append_entry(&WALEntry::LogStart { txn_id: CHECKPOINT_TXN, lsn })?;
+            tmp.append_entry(&WALEntry::Checkpoint { txn_id: CHECKPOINT_TXN, timestamp })?;
+            tmp.append_entry(&WALEntry::BeginTransaction {
+                txn_id: CHECKPOINT_TXN,
+                isolation_level: IsolationLevel::Serializable,
+                timestamp,
+            })?;
+
+            let mut entities = graph.get_all_entities();
+            entities.sort_by_key(|entity| entity.id.0);
+            for entity in entities {
+                tmp.append_entry(&WALEntry::InsertEntity {
+                    txn_id: CHECKPOINT_TXN,
+                    entity_id: entity.id.as_u64(),
+                    created_at: entity.created_millis(),
+                    entity_type: entity.entity_type,
+                    properties: entity.properties,
+                })?;
+            }
+
+            let mut edges = graph.get_all_edges();
+            edges.sort_by_key(|edge| edge.id.0);
+            for edge in edges {
+                tmp.append_entry(&WALEntry::CreateEdge {
+                    txn_id: CHECKPOINT_TXN,
+                    edge_id: edge.id.as_u64(),
+                    source_id: edge.source.as_u64(),
+                    target_id: edge.target.as_u64(),
+                    edge_type: edge.edge_type,
+                    properties: edge.properties,
+                })?;
+            }
+
+            tmp.append_entry(&WALEntry::Commit { txn_id: CHECKPOINT_TXN, timestamp })?;
+            tmp.flush()?;
+
+            // The snapshot's entries take LSNs after the start, as a reopened log counts them
+            self.appended_lsn.fetch_add(tmp.entry_count() as u64 - 1, Ordering::SeqCst);
+        }
+
+        std::fs::rename(&tmp_path, &self.path)?;
+        *writer = WALWriter::new(&self.path)?;
+        *self.last_checkpoint.lock().unwrap() = Instant::now();
+
+        // Whatever the old log held is covered by the checkpoint
+        let mut state = self.sync_state.lock().unwrap();
+        state.durable_lsn = state.durable_lsn.max(self.appended_lsn.load(Ordering::SeqCst));
+        self.synced.notify_all();
+
+        Ok(())
+    }
+
     fn current_timestamp() -> u64 {
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -454,12 +961,131 @@ impl RecoveryResult {
             active_txns: std::collections::HashSet::new(),
         }
     }
+
+    /// Apply the committed transactions' changes to a graph
+    ///
+    /// Changes are applied in commit order; rolled back and unfinished
+    /// transactions are skipped, as are changes undone by rolling back to a
+    /// savepoint. A savepoint used without being set fails the replay.
+    /// Returns the number of transactions applied.
+    pub fn replay(&self, graph: &Graph) -> Result<usize, String> {
+        let mut pending: std::collections::HashMap<TransactionId, Vec<&WALEntry>> =
+            std::collections::HashMap::new();
+        let mut applied = 0;
+
+        for entry in &self.entries {
+            match entry {
+                WALEntry::BeginTransaction { txn_id, .. } => {
+                    pending.insert(*txn_id, Vec::new());
+                }
+                WALEntry::Commit { txn_id, .. } => {
+                    for change in pending.remove(txn_id).unwrap_or_default() {
+                        Self::apply(change, graph)?;
+                    }
+                    applied += 1;
+                }
+                WALEntry::Rollback { txn_id, .. } => {
+                    pending.remove(txn_id);
+                }
+                WALEntry::Checkpoint { .. } | WALEntry::LogStart { .. } => {}
+                WALEntry::RollbackToSavepoint { txn_id, name } => {
+                    // Keep the savepoint itself, which can be rolled back to again
+                    let changes = pending.entry(*txn_id).or_default();
+                    let position = Self::find_savepoint(changes, name, *txn_id)?;
+                    changes.truncate(position + 1);
+                }
+                WALEntry::ReleaseSavepoint { txn_id, name } => {
+                    // The changes stay; only the savepoints go
+                    let changes = pending.entry(*txn_id).or_default();
+                    let position = Self::find_savepoint(changes, name, *txn_id)?;
+                    let mut index = 0;
+                    changes.retain(|change| {
+                        index += 1;
+                        index <= position || !matches!(change, WALEntry::Savepoint { .. })
+                    });
+                }
+                change => pending.entry(change.txn_id()).or_default().push(change),
+            }
+        }
+
+        Ok(applied)
+    }
+
+    /// Position of a transaction's latest savepoint with a name among its changes
+    fn find_savepoint(changes: &[&WALEntry], name: &str, txn_id: TransactionId) -> Result<usize, String> {
+        changes
+            .iter()
+            .rposition(|change| matches!(change, WALEntry::Savepoint { name: saved, .. } if saved == name))
+            .ok_or_else(|| format!("WAL uses savepoint '{}' of transaction {} before setting it", name, txn_id))
+    }
+
+    fn apply(entry: &WALEntry, graph: &Graph) -> Result<(), String> {
+        match entry {
+            WALEntry::InsertEntity { entity_id, entity_type, properties, created_at, .. } => {
+                let entity = Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone());
+                graph.insert_entity_with_id(entity.with_created_at(*created_at))?;
+            }
+            WALEntry::InsertEntities { entity_type, entities, .. } => {
+                for (entity_id, properties, created_at) in entities {
+                    let entity = Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone());
+                    graph.insert_entity_with_id(entity.with_created_at(*created_at))?;
+                }
+            }
+            WALEntry::LegacyInsertEntity { entity_id, entity_type, properties, .. } => {
+                let entity = Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone());
+                graph.insert_entity_with_id(entity)?;
+            }
+            WALEntry::LegacyInsertEntities { entity_type, entities, .. } => {
+                for (entity_id, properties) in entities {
+                    let entity = Entity::new(EntityId::new(*entity_id), entity_type.clone(), properties.clone());
+                    graph.insert_entity_with_id(entity)?;
+                }
+            }
+            WALEntry::UpdateEntity { entity_id, new_properties, .. } => {
+                let mut entity = graph
+                    .get_entity(EntityId::new(*entity_id))
+                    .ok_or_else(|| format!("WAL replay: entity {} not found", entity_id))?;
+                entity.properties = new_properties.clone();
+                graph.update_entity(entity)?;
+            }
+            WALEntry::DeleteEntity { entity_id, .. } => {
+                graph.delete_entity(EntityId::new(*entity_id))?;
+            }
+            WALEntry::CreateEdge { edge_id, source_id, target_id, edge_type, properties, .. } => {
+                let edge = Edge::new(
+                    EdgeId::new(*edge_id),
+                    EntityId::new(*source_id),
+                    EntityId::new(*target_id),
+                    edge_type.clone(),
+                    properties.clone(),
+                );
+                graph.insert_edge_with_id(edge)?;
+            }
+            WALEntry::UpdateEdge { edge_id, properties, .. } => {
+                graph.update_edge_properties(EdgeId::new(*edge_id), properties.clone())?;
+            }
+            WALEntry::DeleteEdge { edge_id, .. } => {
+                graph.delete_edge(EdgeId::new(*edge_id))?;
+            }
+            WALEntry::DropCollection { collection, .. } => {
+                graph.drop_collection(collection)?;
+                graph.clear_collection_ttl(collection);
+            }
+            WALEntry::EvictEntities { entity_ids, .. } => {
+                let ids: Vec<EntityId> = entity_ids.iter().map(|id| EntityId::new(*id)).collect();
+                graph.evict_entities(&ids)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    
     use tempfile::TempDir;
 
     #[test]
@@ -516,4 +1142,212 @@ mod tests {
         assert_eq!(result.committed_txns.len(), 1);
         assert_eq!(result.active_txns.len(), 1); // Transaction 2 is still active
     }
+
+    #[test]
+    fn test_replay_applies_committed_changes_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        let alice = Entity::new(EntityId::new(1), "Users".to_string(), Properties::new());
+        let bob = Entity::new(EntityId::new(2), "Users".to_string(), Properties::new());
+
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_insert(1, &alice).unwrap();
+        manager.log_commit(1).unwrap();
+
+        manager.log_begin(2, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_insert(2, &bob).unwrap();
+        manager.log_rollback(2).unwrap();
+
+        manager.log_begin(3, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_delete(3, &alice).unwrap();
+        // Transaction 3 never commits
+
+        let graph = Graph::new();
+        let applied = manager.recover().unwrap().replay(&graph).unwrap();
+
+        assert_eq!(applied, 1);
+        assert!(graph.get_entity(EntityId::new(1)).is_some());
+        assert!(graph.get_entity(EntityId::new(2)).is_none());
+    }
+
+    #[test]
+    fn test_replay_skips_changes_rolled_back_to_a_savepoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        let entity = |id| Entity::new(EntityId::new(id), "Users".to_string(), Properties::new());
+
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_insert(1, &entity(1)).unwrap();
+        manager.log_savepoint(1, "outer").unwrap();
+        manager.log_insert(1, &entity(2)).unwrap();
+        manager.log_savepoint(1, "inner").unwrap();
+        manager.log_insert(1, &entity(3)).unwrap();
+        manager.log_rollback_to_savepoint(1, "outer").unwrap();
+        manager.log_insert(1, &entity(4)).unwrap();
+
+        // A released savepoint no longer hides the older one of its name
+        manager.log_savepoint(1, "outer").unwrap();
+        manager.log_insert(1, &entity(5)).unwrap();
+        manager.log_release_savepoint(1, "outer").unwrap();
+        manager.log_insert(1, &entity(6)).unwrap();
+        manager.log_rollback_to_savepoint(1, "outer").unwrap();
+        manager.log_commit(1).unwrap();
+
+        let graph = Graph::new();
+        assert_eq!(manager.recover().unwrap().replay(&graph).unwrap(), 1);
+
+        let ids: Vec<u64> = (1..=6).filter(|&id| graph.get_entity(EntityId::new(id)).is_some()).collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[test]
+    fn test_replay_fails_on_unknown_savepoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = WALManager::new(temp_dir.path().join("test.wal")).unwrap();
+
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_savepoint(1, "outer").unwrap();
+        manager.log_rollback_to_savepoint(1, "inner").unwrap();
+        manager.log_commit(1).unwrap();
+
+        let error = manager.recover().unwrap().replay(&Graph::new()).unwrap_err();
+        assert!(error.contains("savepoint 'inner'"), "{}", error);
+    }
+
+    #[test]
+    fn test_checkpoint_replaces_log_with_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let graph = Graph::new();
+        let a = graph.add_entity("Users".to_string(), Properties::new()).unwrap();
+        let b = graph.add_entity("Users".to_string(), Properties::new()).unwrap();
+        graph.add_edge(a, b, "FOLLOWS".to_string(), Properties::new()).unwrap();
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        manager.log_begin(7, IsolationLevel::ReadCommitted).unwrap();
+        manager.checkpoint(&graph).unwrap();
+
+        // Appends after the checkpoint go to the new log
+        manager.log_begin(8, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_commit(8).unwrap();
+
+        let result = manager.recover().unwrap();
+        assert!(matches!(result.entries[0], WALEntry::Checkpoint { .. }));
+        assert!(!result.active_txns.contains(&7));
+
+        let restored = Graph::new();
+        assert_eq!(result.replay(&restored).unwrap(), 2);
+        assert_eq!(restored.entity_count(), 2);
+        assert_eq!(restored.get_outgoing_neighbors(a, Some("FOLLOWS")).len(), 1);
+    }
+
+    #[test]
+    fn test_lsn_keeps_growing_across_reopens_and_checkpoints() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_commit(1).unwrap();
+        let logged = manager.current_lsn();
+        assert_eq!(logged, 2);
+        drop(manager);
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        assert_eq!(manager.current_lsn(), logged);
+        manager.checkpoint(&Graph::new()).unwrap();
+        let checkpointed = manager.current_lsn();
+        assert!(checkpointed > logged);
+        manager.log_begin(2, IsolationLevel::ReadCommitted).unwrap();
+        drop(manager);
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        assert_eq!(manager.current_lsn(), checkpointed + 1);
+    }
+
+    #[test]
+    fn test_replay_and_checkpoint_keep_creation_times() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        let created = |id: u64| Entity::new(EntityId::new(id), "Users".to_string(), Properties::new()).with_created_at(id * 1000);
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        manager.log_insert(1, &created(1)).unwrap();
+        manager.log_insert_batch(1, "Users", &[created(2), created(3)]).unwrap();
+        manager.log_commit(1).unwrap();
+
+        let graph = Graph::new();
+        manager.recover().unwrap().replay(&graph).unwrap();
+        manager.checkpoint(&graph).unwrap();
+
+        let restored = Graph::new();
+        manager.recover().unwrap().replay(&restored).unwrap();
+        for id in 1..=3 {
+            assert_eq!(restored.get_entity(EntityId::new(id)).unwrap().created_millis(), id * 1000);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let manager = WALManager::new(&wal_path).unwrap();
+        manager.set_checkpoint_policy(CheckpointPolicy::manual());
+        manager.log_begin(1, IsolationLevel::ReadCommitted).unwrap();
+        assert!(!manager.checkpoint_due().unwrap());
+
+        manager.set_checkpoint_policy(CheckpointPolicy {
+            max_log_bytes: Some(manager.log_size().unwrap()),
+            max_interval: None,
+        });
+        assert!(manager.checkpoint_due().unwrap());
+
+        manager.set_checkpoint_policy(CheckpointPolicy {
+            max_log_bytes: None,
+            max_interval: Some(std::time::Duration::ZERO),
+        });
+        assert!(manager.checkpoint_due().unwrap());
+    }
+
+    #[test]
+    fn test_group_commit_shares_fsyncs() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let config = WALConfig {
+            sync_mode: SyncMode::Group,
+            max_batch_delay: std::time::Duration::from_millis(5),
+            max_batch_size: 8,
+        };
+        let manager = Arc::new(WALManager::with_config(&wal_path, config).unwrap());
+
+        let threads: Vec<_> = (0..8u64)
+            .map(|t| {
+                let manager = manager.clone();
+                std::thread::spawn(move || {
+                    for i in 0..10 {
+                        let txn_id = t * 100 + i;
+                        manager.log_begin(txn_id, IsolationLevel::ReadCommitted).unwrap();
+                        manager.log_commit(txn_id).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(manager.sync_count() < 80, "{} fsyncs for 80 commits", manager.sync_count());
+
+        // Read what's on disk, not the writer's buffer
+        let entries = WALReader::new(&wal_path).unwrap().read_all().unwrap();
+        assert_eq!(entries.iter().filter(|e| e.is_commit()).count(), 80);
+    }
 }
@@ -0,0 +1,229 @@
+//! Integration tests for change data capture
+//!
+//! Subscribers see committed entity and edge changes in commit order, never
+//! rolled back ones, and can't hold up writers by falling behind.
+
+use deed_core::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn props(pairs: &[(&str, PropertyValue)]) -> HashMap<String, PropertyValue> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+}
+
+fn kinds(events: &[ChangeEvent]) -> Vec<ChangeKind> {
+    events.iter().map(|event| event.kind).collect()
+}
+
+#[test]
+fn test_committed_changes_arrive_in_commit_order() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    let other_session = DQLExecutor::new(graph.clone());
+    let subscription = executor.subscribe(ChangeFilter::all());
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("UPDATE Users SET age = 31 WHERE name = 'Alice'").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob', age: 25})").unwrap();
+
+    // Nothing is delivered before the transaction commits
+    assert_eq!(subscription.try_recv().unwrap().map(|e| e.kind), Some(ChangeKind::Insert));
+    assert!(subscription.try_recv().unwrap().is_none());
+    executor.execute("COMMIT").unwrap();
+
+    // Changes from another session on the same graph are delivered too
+    other_session.execute("DELETE FROM Users WHERE name = 'Alice'").unwrap();
+
+    let events = subscription.drain();
+    assert_eq!(kinds(&events), [ChangeKind::Update, ChangeKind::Insert, ChangeKind::Delete]);
+
+    let alice = &events[0];
+    assert_eq!(alice.collection.as_deref(), Some("Users"));
+    assert_eq!(alice.before.as_ref().unwrap()["age"], PropertyValue::Int(30));
+    assert_eq!(alice.after.as_ref().unwrap()["age"], PropertyValue::Int(31));
+    assert_eq!(events[1].txn_id, alice.txn_id);
+    assert!(events[1].before.is_none());
+    assert_eq!(events[2].entity_id, alice.entity_id);
+    assert_ne!(events[2].txn_id, alice.txn_id);
+    assert_eq!(events[2].before.as_ref().unwrap()["age"], PropertyValue::Int(31));
+    assert!(events[2].after.is_none());
+    assert!(events.iter().all(|event| event.timestamp > 0));
+}
+
+#[test]
+fn test_rolled_back_changes_are_never_delivered() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("DEFINE SCHEMA Orders (total Integer UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    let subscription = executor.subscribe(ChangeFilter::all());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob', age: 25})").unwrap();
+    executor.execute("UPDATE Users SET age = 99 WHERE name = 'Alice'").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Alice'").unwrap();
+    executor.execute("ROLLBACK").unwrap();
+
+    // A failed autocommit statement rolls back too
+    executor.execute("INSERT INTO Orders VALUES ({total: 20}), ({total: 10})").unwrap_err();
+
+    assert_eq!(subscription.recv_timeout(Duration::from_millis(20)).unwrap(), None);
+
+    // The next commit's changes are delivered alone
+    executor.execute("INSERT INTO Orders VALUES ({total: 30})").unwrap();
+    let events = subscription.drain();
+    assert_eq!(kinds(&events), [ChangeKind::Insert]);
+    assert_eq!(events[0].collection.as_deref(), Some("Orders"));
+}
+
+#[test]
+fn test_slow_subscriber_does_not_stall_writers() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    let lagging = executor.subscribe_with(ChangeFilter::all(), 8, OverflowPolicy::DropOldest);
+    let strict = executor.subscribe_with(ChangeFilter::all(), 8, OverflowPolicy::Disconnect);
+
+    // Neither subscriber reads while the writer runs
+    let writer = thread::spawn({
+        let executor = executor.clone();
+        move || {
+            let started = Instant::now();
+            for i in 0..500 {
+                executor
+                    .execute(&format!("INSERT INTO Users VALUES ({{name: 'User{}', age: {}}})", i, i))
+                    .unwrap();
+            }
+            started.elapsed()
+        }
+    });
+    let elapsed = writer.join().unwrap();
+    assert!(elapsed < Duration::from_secs(10), "writer took {:?}", elapsed);
+
+    // The lagging subscriber kept the newest events
+    let events = lagging.drain();
+    assert_eq!(events.len(), 8);
+    assert_eq!(lagging.dropped(), 492);
+    assert_eq!(events[7].after.as_ref().unwrap()["name"], PropertyValue::String("User499".to_string()));
+
+    // The strict one was cut off after what it could hold
+    assert!(strict.is_disconnected());
+    assert_eq!(strict.drain().len(), 8);
+    assert!(strict.try_recv().is_err());
+}
+
+#[test]
+fn test_filters_exclude_unrelated_changes() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    let alice = executor.bulk_insert("Users", vec![props(&[("name", PropertyValue::String("Alice".into()))])]).unwrap()[0];
+    let bob = executor.bulk_insert("Users", vec![props(&[("name", PropertyValue::String("Bob".into()))])]).unwrap()[0];
+    let follows = graph
+        .read()
+        .unwrap()
+        .add_edge(alice, bob, "FOLLOWS".to_string(), props(&[("since", PropertyValue::Int(2020))]))
+        .unwrap();
+
+    let users = executor.subscribe(ChangeFilter::all().with_collection("Users"));
+    let edges = executor.subscribe(
+        ChangeFilter::all()
+            .with_edge_type("FOLLOWS")
+            .with_kind(ChangeKind::EdgeUpdate)
+            .with_kind(ChangeKind::EdgeDelete),
+    );
+
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    executor.execute("UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET since = 2021").unwrap();
+    executor.execute("UPDATE Users SET age = 30 WHERE name = 'Bob'").unwrap();
+    executor.execute("DELETE EDGE FROM Users a -[:FOLLOWS]-> b").unwrap();
+
+    let user_events = users.drain();
+    assert_eq!(kinds(&user_events), [ChangeKind::Update]);
+    assert_eq!(user_events[0].entity_id, bob);
+
+    let edge_events = edges.drain();
+    assert_eq!(kinds(&edge_events), [ChangeKind::EdgeUpdate, ChangeKind::EdgeDelete]);
+    assert!(edge_events.iter().all(|event| event.edge_id == Some(follows) && event.entity_id == alice));
+    assert!(edge_events.iter().all(|event| event.edge_type.as_deref() == Some("FOLLOWS") && event.collection.is_none()));
+    assert_eq!(edge_events[0].before.as_ref().unwrap()["since"], PropertyValue::Int(2020));
+    assert_eq!(edge_events[0].after.as_ref().unwrap()["since"], PropertyValue::Int(2021));
+}
+
+#[test]
+fn test_concurrent_commits_arrive_in_commit_order() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Counters VALUES ({n: 0})").unwrap();
+    let subscription = executor.subscribe(ChangeFilter::all());
+
+    // Each update waits for the one before it, so values rise in commit order
+    let writers: Vec<_> = (0..4)
+        .map(|_| {
+            let session = executor.new_session();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    let _ = session.execute("UPDATE Counters SET n = n + 1");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let values: Vec<PropertyValue> = subscription.drain().iter().map(|e| e.after.as_ref().unwrap()["n"].clone()).collect();
+    let expected: Vec<PropertyValue> = (1..=values.len() as i64).map(PropertyValue::Int).collect();
+    assert!(!values.is_empty());
+    assert_eq!(values, expected);
+}
+
+#[test]
+fn test_subscriber_joining_mid_transaction_gets_all_or_nothing() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    let subscription = executor.subscribe(ChangeFilter::all());
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap();
+    executor.execute("COMMIT").unwrap();
+    assert!(subscription.drain().is_empty());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Carol'})").unwrap();
+    let late = executor.subscribe(ChangeFilter::all());
+    executor.execute("INSERT INTO Users VALUES ({name: 'Dave'})").unwrap();
+    executor.execute("COMMIT").unwrap();
+    assert_eq!(kinds(&late.drain()), [ChangeKind::Insert, ChangeKind::Insert]);
+}
+
+#[test]
+fn test_cascaded_edge_deletes_are_published() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    let ids = executor
+        .bulk_insert(
+            "Users",
+            vec![
+                props(&[("name", PropertyValue::String("Alice".into()))]),
+                props(&[("name", PropertyValue::String("Bob".into()))]),
+                props(&[("name", PropertyValue::String("Carol".into()))]),
+            ],
+        )
+        .unwrap();
+    let (alice, bob, carol) = (ids[0], ids[1], ids[2]);
+    let follows = graph.read().unwrap().add_edge(alice, bob, "FOLLOWS".to_string(), HashMap::new()).unwrap();
+    let likes = graph.read().unwrap().add_edge(bob, carol, "LIKES".to_string(), HashMap::new()).unwrap();
+    let subscription = executor.subscribe(ChangeFilter::all());
+
+    executor.execute("DELETE FROM Users WHERE name = 'Alice'").unwrap();
+    let events = subscription.drain();
+    assert_eq!(kinds(&events), [ChangeKind::EdgeDelete, ChangeKind::Delete]);
+    assert_eq!(events[0].edge_id, Some(follows));
+
+    // Each edge between truncated entities is deleted once
+    executor.execute("TRUNCATE Users").unwrap();
+    let events = subscription.drain();
+    let edges: Vec<_> = events.iter().filter_map(|event| event.edge_id).collect();
+    assert_eq!(edges, vec![likes]);
+    assert_eq!(events.iter().filter(|event| event.kind == ChangeKind::Delete).count(), 2);
+}
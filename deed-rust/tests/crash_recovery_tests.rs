@@ -2,7 +2,7 @@
 //!
 //! Tests that committed transactions survive crashes and WAL recovery works correctly.
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use std::fs;
 use tempfile::TempDir;
@@ -93,8 +93,9 @@ fn test_wal_mixed_recovery() {
         executor.execute("COMMIT").unwrap();
 
         // Transaction 2: Uncommitted (crash)
-        executor.execute("BEGIN TRANSACTION").unwrap();
-        executor.execute("INSERT INTO Users VALUES ({id: 2, name: \"Bob\"})").unwrap();
+        let crashed = executor.new_session();
+        crashed.execute("BEGIN TRANSACTION").unwrap();
+        crashed.execute("INSERT INTO Users VALUES ({id: 2, name: \"Bob\"})").unwrap();
         // NO COMMIT
 
         // Transaction 3: Committed
@@ -197,7 +198,8 @@ fn test_wal_corruption_detection() {
 
     // Corrupt the WAL file
     let mut content = fs::read(&wal_path).unwrap();
-    content[content.len() - 10] ^= 0xFF; // Flip some bits
+    let at = content.len() - 10;
+    content[at] ^= 0xFF; // Flip some bits
     fs::write(&wal_path, content).unwrap();
 
     // Recovery should detect corruption
@@ -253,6 +255,8 @@ fn test_durability_guarantee() {
         }
 
         assert!(has_begin, "WAL should contain BEGIN");
+        assert!(has_insert, "WAL should contain the insert");
+        assert!(has_update, "WAL should contain the update");
         assert!(has_commit, "WAL should contain COMMIT");
     }
 }
@@ -286,7 +290,7 @@ fn test_wal_checkpoint() {
     // After checkpoint, recovery should still work
     let wal_manager = WALManager::new(&wal_path).unwrap();
     let recovery_result = wal_manager.recover().unwrap();
-    assert!(recovery_result.committed_txns.len() > 0);
+    assert!(!recovery_result.committed_txns.is_empty());
 }
 
 /// Test recovery order matters
@@ -327,3 +331,253 @@ fn test_recovery_order() {
         assert_eq!(operation_order, vec!["BEGIN", "INSERT", "UPDATE", "UPDATE", "COMMIT"]);
     }
 }
+
+/// Test that a new executor over the WAL gets committed data back
+#[test]
+fn test_recover_from_wal_restores_committed_data() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("test.wal");
+
+    {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::new_with_wal(graph, &wal_path).unwrap();
+
+        executor.execute("INSERT INTO Users VALUES ({name: \"Alice\", balance: 100})").unwrap();
+
+        executor.execute("BEGIN TRANSACTION").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: \"Bob\", balance: 200})").unwrap();
+        executor.execute("UPDATE Users SET balance = 150 WHERE name = 'Alice'").unwrap();
+        executor.execute("COMMIT").unwrap();
+
+        executor.execute("BEGIN TRANSACTION").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: \"Carol\", balance: 300})").unwrap();
+        executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+        // NO COMMIT - simulate crash
+    }
+
+    // Recover, then write more on top of the recovered state
+    {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::recover_from_wal(graph, &wal_path).unwrap();
+
+        assert_eq!(balances(&executor), vec![("Alice".to_string(), 150), ("Bob".to_string(), 200)]);
+
+        executor.execute("INSERT INTO Users VALUES ({name: \"Dave\", balance: 400})").unwrap();
+    }
+
+    // The checkpointed log plus the new transaction recover again
+    {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::recover_from_wal(graph.clone(), &wal_path).unwrap();
+
+        assert_eq!(
+            balances(&executor),
+            vec![("Alice".to_string(), 150), ("Bob".to_string(), 200), ("Dave".to_string(), 400)]
+        );
+        assert_eq!(graph.read().unwrap().entity_count(), 3);
+    }
+}
+
+fn balances(executor: &DQLExecutor) -> Vec<(String, i64)> {
+    let result = executor.execute("FROM Users SELECT name, balance").unwrap();
+    let mut balances: Vec<(String, i64)> = result
+        .rows
+        .iter()
+        .filter_map(|row| match (row.get("col_0"), row.get("col_1")) {
+            (Some(dql_ir::Value::String(name)), Some(dql_ir::Value::Integer(balance))) => {
+                Some((name.clone(), *balance))
+            }
+            _ => None,
+        })
+        .collect();
+    balances.sort();
+    balances
+}
+
+/// Test that recovering a checkpointed log matches recovering the full log
+#[test]
+fn test_checkpoint_matches_full_log_recovery() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("test.wal");
+    let full_log_path = temp_dir.path().join("full.wal");
+
+    {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::new_with_wal(graph, &wal_path)
+            .unwrap()
+            .with_checkpoint_policy(CheckpointPolicy::manual());
+
+        executor.execute("INSERT INTO Users VALUES ({name: \"Alice\", balance: 0})").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: \"Bob\", balance: 0})").unwrap();
+        for i in 1..=50 {
+            executor.execute(&format!("UPDATE Users SET balance = {} WHERE name = 'Alice'", i)).unwrap();
+        }
+        executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+
+        fs::copy(&wal_path, &full_log_path).unwrap();
+        let size_before = fs::metadata(&wal_path).unwrap().len();
+
+        executor.checkpoint().unwrap();
+        assert!(fs::metadata(&wal_path).unwrap().len() < size_before);
+
+        // Later commits land in the tail after the checkpoint
+        executor.execute("INSERT INTO Users VALUES ({name: \"Carol\", balance: 7})").unwrap();
+    }
+
+    let from_checkpoint = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    let from_full_log = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &full_log_path).unwrap();
+
+    assert_eq!(balances(&from_full_log), vec![("Alice".to_string(), 50)]);
+    assert_eq!(balances(&from_checkpoint), vec![("Alice".to_string(), 50), ("Carol".to_string(), 7)]);
+}
+
+/// Test that writes racing automatic checkpoints all survive recovery
+#[test]
+fn test_concurrent_writes_during_checkpoint() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("test.wal");
+
+    let wal = Arc::new(WALManager::new(&wal_path).unwrap());
+
+    // Every commit is due for a checkpoint
+    wal.set_checkpoint_policy(CheckpointPolicy { max_log_bytes: Some(1), max_interval: None });
+    insert_concurrently(&wal, 4, 25);
+    drop(wal);
+
+    let recovered = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::recover_from_wal(recovered.clone(), &wal_path).unwrap();
+    assert_eq!(balances(&executor).len(), 100);
+    assert_eq!(recovered.read().unwrap().entity_count(), 100);
+}
+
+/// Test that group commit needs far fewer fsyncs than syncing every entry
+#[test]
+fn test_group_commit_throughput() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let run = |sync_mode: SyncMode| {
+        let wal_path = temp_dir.path().join(format!("{:?}.wal", sync_mode));
+        let config = WALConfig { sync_mode, ..WALConfig::default() };
+        let wal = Arc::new(WALManager::with_config(&wal_path, config).unwrap());
+        insert_concurrently(&wal, 16, 20);
+        wal.sync_count()
+    };
+
+    let always_syncs = run(SyncMode::Always);
+    let group_syncs = run(SyncMode::Group);
+
+    // Every entry (begin, insert, commit) is synced on its own without batching
+    assert_eq!(always_syncs, 16 * 20 * 3);
+    assert!(group_syncs < 16 * 20 / 2);
+}
+
+/// Test that a crash mid-write loses no commit that was acknowledged
+#[test]
+fn test_crash_during_group_commit_keeps_acknowledged_commits() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("test.wal");
+    let crash_path = temp_dir.path().join("crash.wal");
+
+    let wal = Arc::new(WALManager::new(&wal_path).unwrap());
+    let acknowledged = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let writer = {
+        let (wal, acknowledged) = (wal.clone(), acknowledged.clone());
+        std::thread::spawn(move || insert_concurrently_acknowledged(&wal, 8, 50, &acknowledged))
+    };
+
+    // "Crash": once some commits are acknowledged, take the log as it is on
+    // disk while writers are mid-flight
+    while acknowledged.lock().unwrap().len() < 50 && !writer.is_finished() {
+        std::thread::yield_now();
+    }
+    let acknowledged_before_crash: Vec<String> = acknowledged.lock().unwrap().clone();
+    fs::copy(&wal_path, &crash_path).unwrap();
+    writer.join().unwrap();
+    assert!(acknowledged_before_crash.len() >= 50);
+
+    let recovered = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &crash_path).unwrap();
+    let names: Vec<String> = balances(&recovered).into_iter().map(|(name, _)| name).collect();
+    for name in acknowledged_before_crash {
+        assert!(names.contains(&name), "acknowledged commit {} was lost", name);
+    }
+}
+
+/// Test that a multi-row insert is logged as one record and replays in full
+#[test]
+fn test_batch_insert_logs_one_record_and_recovers() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("test.wal");
+
+    {
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let executor = DQLExecutor::new_with_wal(graph, &wal_path).unwrap();
+
+        executor
+            .execute("INSERT INTO Users VALUES ({name: \"Alice\", balance: 100}), ({name: \"Bob\", balance: 200})")
+            .unwrap();
+
+        executor.execute("BEGIN TRANSACTION").unwrap();
+        executor
+            .execute("INSERT INTO Users VALUES ({name: \"Carol\", balance: 300}), ({name: \"Dave\", balance: 400})")
+            .unwrap();
+        // NO COMMIT - simulate crash
+    }
+
+    let recovery = WALManager::new(&wal_path).unwrap().recover().unwrap();
+    let batches: Vec<usize> = recovery
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            WALEntry::InsertEntities { entities, .. } => Some(entities.len()),
+            WALEntry::InsertEntity { .. } => panic!("batch was logged row by row"),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(batches, vec![2, 2]);
+
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::recover_from_wal(graph, &wal_path).unwrap();
+    assert_eq!(balances(&executor), vec![("Alice".to_string(), 100), ("Bob".to_string(), 200)]);
+}
+
+fn insert_concurrently(wal: &Arc<WALManager>, threads: usize, per_thread: usize) {
+    insert_concurrently_acknowledged(wal, threads, per_thread, &Arc::new(std::sync::Mutex::new(Vec::new())));
+}
+
+fn insert_concurrently_acknowledged(
+    wal: &Arc<WALManager>,
+    threads: usize,
+    per_thread: usize,
+    acknowledged: &Arc<std::sync::Mutex<Vec<String>>>,
+) {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let transactions = Arc::new(TransactionManager::new());
+    let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
+    let indexes = Arc::new(IndexManager::new());
+    let schemas = Arc::new(RwLock::new(SchemaValidator::new()));
+
+    let writers: Vec<_> = (0..threads)
+        .map(|writer| {
+            let (graph, wal, transactions) = (graph.clone(), wal.clone(), transactions.clone());
+            let (optimizer, cache, acknowledged) = (optimizer.clone(), cache.clone(), acknowledged.clone());
+            let (indexes, schemas) = (indexes.clone(), schemas.clone());
+            std::thread::spawn(move || {
+                let executor =
+                    DQLExecutor::with_shared_components(graph, optimizer, cache, transactions, Some(wal), indexes, schemas);
+                for i in 0..per_thread {
+                    let name = format!("W{}-{}", writer, i);
+                    executor
+                        .execute(&format!("INSERT INTO Users VALUES ({{name: \"{}\", balance: {}}})", name, i))
+                        .unwrap();
+                    acknowledged.lock().unwrap().push(name);
+                }
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+}
@@ -78,9 +78,8 @@ fn test_update_entities() {
     assert!(res.rows_affected > 0, "Should affect at least 1 row");
 
     // Verify update
-    let verify = executor.execute("FROM Users WHERE name = 'Alice' SELECT age");
-    // Note: Verification depends on UPDATE actually modifying the graph
-    // Currently it modifies in-memory but doesn't persist
+    let verify = executor.execute("FROM Users WHERE name = 'Alice' SELECT age").unwrap();
+    assert_eq!(verify.rows[0].get("col_0"), Some(&dql_ir::Value::Integer(30)));
 }
 
 #[test]
@@ -93,7 +92,54 @@ fn test_update_multiple_entities() {
 
     assert!(result.is_ok());
     let res = result.unwrap();
-    assert!(res.rows_affected > 0, "Should update multiple users");
+    assert_eq!(res.rows_affected, 3, "Should update Alice, Bob and Dave");
+
+    let verify = executor.execute("FROM Users WHERE age = 99 SELECT name").unwrap();
+    assert_eq!(verify.row_count(), 3);
+    let verify = executor.execute("FROM Users WHERE age < 99 SELECT name").unwrap();
+    assert_eq!(verify.row_count(), 2, "Carol and Eve are unchanged");
+}
+
+#[test]
+fn test_update_property_used_in_predicate() {
+    let graph = setup_test_users();
+    let executor = DQLExecutor::new(graph);
+
+    // Bob (32) and Dave (35) both move below the predicate's threshold
+    let res = executor.execute("UPDATE Users SET age = 18 WHERE age > 30").unwrap();
+    assert_eq!(res.rows_affected, 2);
+
+    let res = executor.execute("UPDATE Users SET age = 18 WHERE age > 30").unwrap();
+    assert_eq!(res.rows_affected, 0, "Updated rows no longer match the predicate");
+
+    let verify = executor.execute("FROM Users WHERE age = 18 SELECT name").unwrap();
+    assert_eq!(verify.row_count(), 2);
+}
+
+#[test]
+fn test_update_maintains_indexes() {
+    let graph = setup_test_users();
+    let executor = DQLExecutor::new(graph);
+
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+    executor.execute("CREATE UNIQUE INDEX idx_name ON Users(name)").unwrap();
+    executor.execute("REINDEX COLLECTION Users").unwrap();
+
+    executor.execute("UPDATE Users SET age = 31 WHERE name = 'Alice'").unwrap();
+
+    let index = executor.index_manager().get_index("idx_age").unwrap();
+    assert!(index.lookup(&PropertyValue::Int(28)).is_empty(), "Old key should be invalidated");
+    assert_eq!(index.lookup(&PropertyValue::Int(31)).len(), 1);
+
+    // A unique conflict rejects the update and keeps the index intact
+    let err = executor.execute("UPDATE Users SET name = 'Bob' WHERE name = 'Carol'").unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation on 'name'"), "unexpected error: {}", err);
+    let index = executor.index_manager().get_index("idx_name").unwrap();
+    assert_eq!(index.lookup(&PropertyValue::String("Carol".to_string())).len(), 1);
+    assert_eq!(index.lookup(&PropertyValue::String("Bob".to_string())).len(), 1);
+
+    let verify = executor.execute("FROM Users WHERE name = 'Carol' SELECT age").unwrap();
+    assert_eq!(verify.row_count(), 1);
 }
 
 #[test]
@@ -106,10 +152,106 @@ fn test_delete_entities() {
 
     assert!(result.is_ok(), "DELETE should succeed");
     let res = result.unwrap();
-    assert!(res.rows_affected > 0, "Should delete at least 1 user");
+    assert_eq!(res.rows_affected, 2, "Should delete Bob and Dave");
+
+    // Deleted users are gone from a follow-up query
+    let res = executor.execute("FROM Users WHERE age > 30 SELECT name").unwrap();
+    assert_eq!(res.row_count(), 0);
+    assert_eq!(graph.read().unwrap().scan_collection("Users").len(), 3);
+}
+
+#[test]
+fn test_delete_entity_with_edges() {
+    let graph = setup_test_users();
+    let (alice, bob, dave) = {
+        let g = graph.read().unwrap();
+        let ids: HashMap<String, EntityId> = g
+            .scan_collection("Users")
+            .into_iter()
+            .map(|e| (e.get_property("name").unwrap().as_str().unwrap().to_string(), e.id))
+            .collect();
+
+        // Alice -> Bob -> Dave, and Dave -> Alice
+        g.add_edge(ids["Alice"], ids["Bob"], "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        g.add_edge(ids["Bob"], ids["Dave"], "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        g.add_edge(ids["Dave"], ids["Alice"], "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        (ids["Alice"], ids["Bob"], ids["Dave"])
+    };
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+
+    {
+        let g = graph.read().unwrap();
+        assert_eq!(g.stats().edge_count, 1, "Only Dave -> Alice should survive");
+        assert!(g.get_outgoing_neighbors(alice, None).is_empty());
+        assert!(g.get_incoming_neighbors(dave, None).is_empty());
+        assert!(g.get_entity_edges(bob).is_empty());
+    }
+
+    // Traversals never reach the deleted entity
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:FOLLOWS]-> friend SELECT u.name, friend.name")
+        .unwrap();
+    for row in &res.rows {
+        for value in row.values() {
+            assert_ne!(value, &dql_ir::Value::String("Bob".to_string()), "Traversal returned a phantom row: {:?}", row);
+        }
+    }
+}
+
+#[test]
+fn test_delete_rolled_back_in_transaction() {
+    let graph = setup_test_users();
+    let (alice, bob) = {
+        let g = graph.read().unwrap();
+        let users = g.scan_collection("Users");
+        let id_of = |name: &str| {
+            users.iter().find(|e| e.get_property("name").unwrap().as_str() == Some(name)).unwrap().id
+        };
+        g.add_edge(id_of("Alice"), id_of("Bob"), "FOLLOWS".to_string(), HashMap::new()).unwrap();
+        (id_of("Alice"), id_of("Bob"))
+    };
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    let res = executor.execute("DELETE FROM Users WHERE age > 30").unwrap();
+    assert_eq!(res.rows_affected, 2);
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 3);
+    // The graph only changes when the transaction commits
+    assert_eq!(graph.read().unwrap().stats().edge_count, 1);
+    executor.execute("ROLLBACK").unwrap();
 
-    // Verify deletion (depends on actual deletion implementation)
-    // Currently tracks count but doesn't actually delete
+    let res = executor.execute("FROM Users WHERE age > 30 SELECT name").unwrap();
+    assert_eq!(res.row_count(), 2, "Rollback should restore deleted users");
+
+    let g = graph.read().unwrap();
+    assert_eq!(g.get_entity(bob).unwrap().get_property("age"), Some(&PropertyValue::Int(32)));
+    assert_eq!(g.get_outgoing_neighbors(alice, Some("FOLLOWS")), vec![(bob, g.get_entity_edges(bob)[0].id)]);
+}
+
+#[test]
+fn test_transaction_writes_reach_graph_and_indexes_at_commit() {
+    let graph = setup_test_users();
+    let executor = DQLExecutor::new(graph.clone());
+    executor.execute("CREATE UNIQUE INDEX idx_name ON Users(name)").unwrap();
+    let zed = PropertyValue::String("Zed".to_string());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Zed', age: 40})").unwrap();
+    executor.execute("UPDATE Users SET age = 29 WHERE name = 'Alice'").unwrap();
+    assert_eq!(executor.execute("FROM Users WHERE name = 'Zed' SELECT age").unwrap().row_count(), 1);
+
+    // Until the commit, only the transaction sees its writes
+    assert_eq!(graph.read().unwrap().stats().entity_count, 5);
+    assert!(executor.index_manager().get_index("idx_name").unwrap().lookup(&zed).is_empty());
+    let alice = executor.index_manager().get_index("idx_name").unwrap().lookup(&PropertyValue::String("Alice".to_string()))[0];
+    assert_eq!(graph.read().unwrap().get_entity(alice).unwrap().get_property("age"), Some(&PropertyValue::Int(28)));
+
+    executor.execute("COMMIT").unwrap();
+    assert_eq!(graph.read().unwrap().stats().entity_count, 6);
+    assert_eq!(executor.index_manager().get_index("idx_name").unwrap().lookup(&zed).len(), 1);
+    assert_eq!(graph.read().unwrap().get_entity(alice).unwrap().get_property("age"), Some(&PropertyValue::Int(29)));
 }
 
 #[test]
@@ -124,8 +266,8 @@ fn test_create_edge() {
     // Get their IDs (simplified - in production you'd query for them)
     let g = graph.read().unwrap();
     let users = g.scan_collection("Users");
-    let alice_id = users[0].id;
-    let bob_id = users[1].id;
+    let _alice_id = users[0].id;
+    let _bob_id = users[1].id;
     drop(g);
 
     // Create FOLLOWS edge (this is simplified syntax)
@@ -222,6 +364,96 @@ fn test_insert_with_different_types() {
     assert_eq!(all.row_count(), 3);
 }
 
+#[test]
+fn test_update_with_aliased_set_target() {
+    let graph = setup_test_users();
+    let executor = DQLExecutor::new(graph);
+
+    let result = executor
+        .execute("UPDATE Users u SET u.age = 40 WHERE u.name = 'Alice'")
+        .unwrap();
+    assert_eq!(result.rows_affected, 1);
+
+    let verify = executor.execute("FROM Users WHERE name = 'Alice' SELECT age").unwrap();
+    assert_eq!(verify.rows[0].get("col_0"), Some(&dql_ir::Value::Integer(40)));
+
+    // Qualifier must name the UPDATE target
+    let err = executor.execute("UPDATE Users u SET x.age = 40").unwrap_err();
+    assert!(err.contains("does not match"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_insert_with_expression_values() {
+    use dql_ast::Query;
+    use dql_ir::{FilterExpr, Operation, QueryPlanBuilder, Value};
+
+    // Arithmetic is folded into a constant at plan time
+    let query = match DQLParser::parse("INSERT INTO Metrics VALUES ({total: 1 + 2 * 3, ratio: 10 / 4.0})").unwrap() {
+        Query::Insert(q) => q,
+        _ => panic!("Expected INSERT query"),
+    };
+    let plan = QueryPlanBuilder::new().build_insert(&query).unwrap();
+    match &plan.operations[0] {
+        Operation::InsertEntity { rows, .. } => {
+            let properties = &rows[0];
+            assert!(matches!(properties.get("total"), Some(FilterExpr::Constant(Value::Integer(7)))));
+            assert!(matches!(properties.get("ratio"), Some(FilterExpr::Constant(Value::Float(r))) if *r == 2.5));
+        }
+        other => panic!("Expected InsertEntity, got {:?}", other),
+    }
+
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    executor.execute("INSERT INTO Metrics VALUES ({total: 1 + 2 * 3})").unwrap();
+
+    let entity = graph.read().unwrap().scan_collection("Metrics").remove(0);
+    assert_eq!(entity.get_property("total"), Some(&PropertyValue::Int(7)));
+}
+
+#[test]
+fn test_insert_rejects_property_references() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+
+    let err = executor
+        .execute("INSERT INTO Metrics VALUES ({total: other + 1})")
+        .unwrap_err();
+    assert!(err.contains("cannot reference property 'other'"), "unexpected error: {}", err);
+    assert_eq!(graph.read().unwrap().scan_collection("Metrics").len(), 0);
+}
+
+#[test]
+fn test_update_with_arithmetic_on_current_values() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph);
+
+    executor.execute("INSERT INTO Products VALUES ({sku: 5, stock: 10, price: 20, a: 1, b: 10})").unwrap();
+    let product = |field: &str| {
+        let res = executor.execute(&format!("FROM Products WHERE sku = 5 SELECT {}", field)).unwrap();
+        res.rows[0]["col_0"].clone()
+    };
+
+    executor.execute("UPDATE Products SET stock = stock - 1 WHERE sku = 5").unwrap();
+    assert_eq!(product("stock"), dql_ir::Value::Integer(9));
+
+    // Int * Float promotes to Float
+    executor.execute("UPDATE Products SET price = price * 1.1 WHERE sku = 5").unwrap();
+    assert!(matches!(product("price"), dql_ir::Value::Float(p) if (p - 22.0).abs() < 1e-9));
+
+    executor.execute("UPDATE Products SET stock = -stock / 3 WHERE sku = 5").unwrap();
+    assert_eq!(product("stock"), dql_ir::Value::Integer(-3));
+
+    // Division by zero fails the statement instead of writing NULL
+    let err = executor.execute("UPDATE Products SET stock = stock / (a - 1) WHERE sku = 5").unwrap_err();
+    assert!(err.contains("Division by zero"), "unexpected error: {}", err);
+    assert_eq!(product("stock"), dql_ir::Value::Integer(-3));
+
+    // Every SET expression sees the values from before the statement
+    executor.execute("UPDATE Products SET a = b + 1, b = a + 1 WHERE sku = 5").unwrap();
+    assert_eq!(product("a"), dql_ir::Value::Integer(11));
+    assert_eq!(product("b"), dql_ir::Value::Integer(2));
+}
+
 // Helper function to setup test data
 fn setup_test_users() -> Arc<RwLock<Graph>> {
     let graph = Arc::new(RwLock::new(Graph::new()));
@@ -243,7 +475,7 @@ fn setup_test_users() -> Arc<RwLock<Graph>> {
             props.insert("name".to_string(), PropertyValue::String(name.to_string()));
             props.insert("age".to_string(), PropertyValue::Int(age));
 
-            g.add_entity("Users".to_string(), props);
+            g.add_entity("Users".to_string(), props).unwrap();
         }
     }
 
@@ -0,0 +1,219 @@
+//! Integration tests for the admin dashboard's HTTP endpoint
+//!
+//! Each test serves the dashboard on an ephemeral port and sends raw HTTP
+//! requests, checking the JSON on /stats and /health and that /metrics is
+//! well-formed Prometheus text.
+
+use deed_core::*;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn sources() -> DashboardSources {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let graph = graph.write().unwrap();
+        let mut alice = HashMap::new();
+        alice.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
+        let alice = graph.add_entity("Users".to_string(), alice).unwrap();
+        let order = graph.add_entity("Orders".to_string(), HashMap::new()).unwrap();
+        graph.add_edge(alice, order, "PLACED".to_string(), HashMap::new()).unwrap();
+    }
+    let auth = Arc::new(AuthManager::new());
+    auth.create_user("reader".to_string(), "secret", Role::ReadOnly).unwrap();
+
+    DashboardSources::new(graph, auth, Arc::new(TransactionManager::new()))
+}
+
+async fn serve(sources: DashboardSources, config: DashboardConfig) -> DashboardHandle {
+    AdminDashboard::new().serve("127.0.0.1:0", sources, config).await.unwrap()
+}
+
+/// Send a GET and return the status code, headers and body
+async fn get(addr: SocketAddr, path: &str, token: Option<&str>) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path);
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, head.to_string(), body.to_string())
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_stats_endpoint_serves_json() {
+    let replication = Arc::new(ReplicationManager::new_master("node-1".to_string()));
+    replication.log_delete(1).unwrap();
+    let server = serve(sources().with_replication(replication), DashboardConfig::default()).await;
+
+    let (status, head, body) = get(server.local_addr(), "/stats", None).await;
+    assert_eq!(status, 200);
+    assert!(head.contains("Content-Type: application/json"), "{}", head);
+
+    let stats = json(&body);
+    assert_eq!(stats["database"]["entity_count"], 2);
+    assert_eq!(stats["database"]["edge_count"], 1);
+    assert_eq!(stats["database"]["total_properties"], 1);
+    assert_eq!(stats["database"]["collections"], serde_json::json!(["Orders", "Users"]));
+    assert_eq!(stats["auth"]["total_users"], 2);
+    assert_eq!(stats["auth"]["users_by_role"]["readonly_count"], 1);
+    assert_eq!(stats["transactions"]["active_transactions"], 0);
+    assert!(stats["uptime_seconds"].is_u64());
+    // Parts the dashboard wasn't given are null
+    assert!(stats["pool"].is_null());
+    assert_eq!(stats["replication"]["role"], "Master");
+    assert_eq!(stats["replication"]["current_seq"], 1);
+
+    // The query string is ignored, unknown paths are not found
+    assert_eq!(get(server.local_addr(), "/stats?pretty=1", None).await.0, 200);
+    assert_eq!(get(server.local_addr(), "/nope", None).await.0, 404);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_health_reports_wal_and_replication_lag() {
+    let wal_path = std::env::temp_dir().join("deed_test_dashboard_wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let wal = Arc::new(WALManager::new(&wal_path).unwrap());
+
+    let replication = Arc::new(ReplicationManager::new_master("node-1".to_string()));
+    let config = DashboardConfig {
+        max_replication_lag: Some(Duration::from_secs(5)),
+        ..Default::default()
+    };
+    let server = serve(sources().with_wal(wal).with_replication(replication.clone()), config).await;
+
+    let (status, _, body) = get(server.local_addr(), "/health", None).await;
+    assert_eq!(status, 200);
+    let health = json(&body);
+    assert_eq!(health["status"], "ok");
+    assert!(health["wal_log_bytes"].is_u64());
+    assert_eq!(health["wal_checkpoint_due"], false);
+    assert_eq!(health["replication_role"], "Master");
+    assert_eq!(health["replication_lag_ms"], 0);
+
+    // A replica that last applied an entry a minute old takes the node out of rotation
+    replication.log_delete(1).unwrap();
+    replication.log_delete(2).unwrap();
+    replication.register_slave("replica-1".to_string()).unwrap();
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    replication.update_slave_applied("replica-1", 1, now_ms - 60_000).unwrap();
+
+    let (status, _, body) = get(server.local_addr(), "/health", None).await;
+    assert_eq!(status, 503);
+    let health = json(&body);
+    assert_eq!(health["status"], "lagging");
+    assert!(health["replication_lag_ms"].as_u64().unwrap() >= 60_000);
+
+    server.shutdown().await;
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[tokio::test]
+async fn test_bearer_token_is_required() {
+    let config = DashboardConfig {
+        bearer_token: Some("s3cret".to_string()),
+        ..Default::default()
+    };
+    let server = serve(sources(), config).await;
+    let addr = server.local_addr();
+
+    for path in ["/stats", "/health", "/metrics"] {
+        let (status, head, _) = get(addr, path, None).await;
+        assert_eq!(status, 401, "{}", path);
+        assert!(head.contains("WWW-Authenticate: Bearer"), "{}", head);
+        assert_eq!(get(addr, path, Some("wrong")).await.0, 401, "{}", path);
+        assert_eq!(get(addr, path, Some("s3cret")).await.0, 200, "{}", path);
+    }
+    // Unknown paths aren't revealed to unauthenticated clients
+    assert_eq!(get(addr, "/nope", None).await.0, 401);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_metrics_are_valid_prometheus_text() {
+    let pool = ConnectionPool::new(
+        Arc::new(RwLock::new(Graph::new())),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        PoolConfig {
+            min_size: 1,
+            max_size: 2,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let wal_path = std::env::temp_dir().join("deed_test_dashboard_metrics_wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let sources = sources()
+        .with_pool(Arc::new(pool))
+        .with_replication(Arc::new(ReplicationManager::new_master("node-1".to_string())))
+        .with_wal(Arc::new(WALManager::new(&wal_path).unwrap()));
+    let server = serve(sources, DashboardConfig::default()).await;
+
+    let (status, head, body) = get(server.local_addr(), "/metrics", None).await;
+    assert_eq!(status, 200);
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4"), "{}", head);
+
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    };
+    let mut types: HashMap<String, String> = HashMap::new();
+    let mut samples: HashSet<String> = HashSet::new();
+    let mut values: HashMap<String, f64> = HashMap::new();
+    for line in body.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            let (name, kind) = declaration.split_once(' ').unwrap();
+            assert!(valid_name(name), "{}", line);
+            assert!(matches!(kind, "counter" | "gauge"), "{}", line);
+            if kind == "counter" {
+                assert!(name.ends_with("_total"), "{}", line);
+            }
+            assert!(types.insert(name.to_string(), kind.to_string()).is_none(), "duplicate family {}", name);
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        let (series, value) = line.rsplit_once(' ').unwrap();
+        let value: f64 = value.parse().unwrap_or_else(|_| panic!("non-numeric sample: {}", line));
+        let name = series.split('{').next().unwrap();
+        assert!(types.contains_key(name), "sample before its TYPE: {}", line);
+        assert!(samples.insert(series.to_string()), "duplicate sample: {}", line);
+        values.insert(series.to_string(), value);
+    }
+
+    assert_eq!(values["deed_up"], 1.0);
+    assert_eq!(values["deed_entities"], 2.0);
+    assert_eq!(values["deed_edges"], 1.0);
+    assert_eq!(values["deed_users{role=\"readonly\"}"], 1.0);
+    assert_eq!(values["deed_pool_max_connections"], 2.0);
+    assert_eq!(types["deed_transactions_committed_total"], "counter");
+    assert_eq!(types["deed_pool_connections"], "gauge");
+    assert!(values.contains_key("deed_pool_connections{state=\"idle\"}"));
+    assert_eq!(values["deed_replication_replicas"], 0.0);
+    assert_eq!(values["deed_wal_checkpoint_due"], 0.0);
+
+    server.shutdown().await;
+    let _ = std::fs::remove_file(&wal_path);
+}
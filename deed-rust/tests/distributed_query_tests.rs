@@ -0,0 +1,314 @@
+//! Integration tests for distributed query execution
+//!
+//! Nodes are P2P networks on ephemeral ports; a dead node is a peer address
+//! nothing listens on.
+
+use deed_core::distributed_shard::ShardConfig;
+use deed_core::*;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+fn local_network(id: NodeId) -> Arc<P2PNetwork> {
+    let config = P2PConfig {
+        listen_port: 0,
+        insecure: true,
+        ..P2PConfig::default()
+    };
+    Arc::new(P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config))
+}
+
+fn executor_with(users: &[&str]) -> Arc<DQLExecutor> {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    for user in users {
+        executor.execute(&format!("INSERT INTO Users VALUES ({})", user)).unwrap();
+    }
+    Arc::new(executor)
+}
+
+/// Serving nodes run the coordinator's sub-queries, which carry no role, as admin
+fn trusting() -> DistributedQueryConfig {
+    DistributedQueryConfig {
+        anonymous_role: Some(Role::Admin),
+        ..DistributedQueryConfig::default()
+    }
+}
+
+/// A node answering sub-queries over `users`; returns its network and port
+async fn serving_node(id: NodeId, users: &[&str]) -> (Arc<P2PNetwork>, u16) {
+    let network = local_network(id);
+    let port = network.start_listener().await.unwrap().port();
+    let shards = Arc::new(ShardManager::new(ShardConfig::default()));
+    DistributedQueryExecutor::new(id, shards, network.clone(), executor_with(users))
+        .with_config(trusting())
+        .serve_queries();
+    (network, port)
+}
+
+/// A port nothing listens on
+fn dead_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// The coordinator, node 1, which holds no shards itself
+fn coordinator(shards: ShardManager, peers: &[(NodeId, u16)], config: DistributedQueryConfig) -> DistributedQueryExecutor {
+    let network = local_network(1);
+    for &(id, port) in peers {
+        network.add_peer(id, NodeAddress::new("127.0.0.1".to_string(), port));
+    }
+    DistributedQueryExecutor::new(1, Arc::new(shards), network, executor_with(&[])).with_config(config)
+}
+
+fn names(result: &QueryResult) -> Vec<String> {
+    let mut names: Vec<String> = result
+        .rows
+        .iter()
+        .map(|row| match &row["name"] {
+            dql_ir::Value::String(name) => name.clone(),
+            other => panic!("Unexpected name: {:?}", other),
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_dead_node_falls_back_to_replica() {
+    let shards = ShardManager::new(ShardConfig {
+        replication_factor: 2,
+        ..ShardConfig::default()
+    });
+    shards.add_node(2);
+    shards.add_node(3);
+    let shard = shards.get_shard_for_key("7").unwrap();
+    let primary = shards.get_node_for_shard(shard).unwrap();
+    let replica = if primary == 2 { 3 } else { 2 };
+    assert_eq!(shards.get_replicas_for_shard(shard), vec![replica]);
+
+    // Only the replica is up
+    let (_node, port) = serving_node(replica, &["{id: 7, name: 'Alice'}", "{id: 8, name: 'Bob'}"]).await;
+    let config = DistributedQueryConfig {
+        max_attempts: 2,
+        retry_backoff_ms: 10,
+        ..DistributedQueryConfig::default()
+    };
+    let executor = coordinator(shards, &[(replica, port), (primary, dead_port())], config);
+
+    let result = executor.execute("FROM Users WHERE id = 7 SELECT name AS name").await.unwrap();
+    assert_eq!(names(&result), ["Alice"]);
+    assert!(result.warnings.is_empty());
+
+    // Both attempts on the primary, then one on the replica
+    let stats = executor.get_statistics();
+    assert_eq!(stats.sub_query_attempts, 3);
+    assert_eq!(stats.replica_fallbacks, 1);
+}
+
+#[tokio::test]
+async fn test_best_effort_returns_partial_results_with_warnings() {
+    let shards = ShardManager::new(ShardConfig {
+        replication_factor: 1,
+        ..ShardConfig::default()
+    });
+    shards.add_node(2);
+    shards.add_node(3);
+
+    let (_node, port) = serving_node(2, &["{name: 'Alice'}", "{name: 'Bob'}"]).await;
+    let config = DistributedQueryConfig {
+        max_attempts: 1,
+        ..DistributedQueryConfig::default()
+    };
+    let executor = coordinator(shards, &[(2, port), (3, dead_port())], config);
+    let query = "FROM Users SELECT name AS name";
+
+    // Strict is the default
+    let err = executor.execute(query).await.unwrap_err();
+    assert!(err.starts_with("Query failed on 1 nodes"), "{}", err);
+
+    let result = executor.execute_with(query, PartialResults::BestEffort).await.unwrap();
+    assert_eq!(names(&result), ["Alice", "Bob"]);
+    assert_eq!(result.warnings.len(), 1);
+    assert!(result.warnings[0].contains("node 3 is unreachable"), "{:?}", result.warnings);
+    assert_eq!(executor.get_statistics().replica_fallbacks, 0);
+}
+
+#[tokio::test]
+async fn test_replica_fallback_answers_each_shard_once() {
+    let shards = ShardManager::new(ShardConfig {
+        replication_factor: 2,
+        ..ShardConfig::default()
+    });
+    shards.add_node(2);
+    shards.add_node(3);
+
+    // Node 2 holds every shard, as primary or replica; node 3 is down
+    let users: Vec<String> = (1..=6).map(|id| format!("{{id: {}, name: 'User {}'}}", id, id)).collect();
+    let users: Vec<&str> = users.iter().map(String::as_str).collect();
+    let (_node, port) = serving_node(2, &users).await;
+    let config = DistributedQueryConfig {
+        max_attempts: 1,
+        ..DistributedQueryConfig::default()
+    };
+    let executor = coordinator(shards, &[(2, port), (3, dead_port())], config);
+
+    let result = executor.execute("FROM Users SELECT name AS name").await.unwrap();
+    assert_eq!(names(&result).len(), 6);
+    let result = executor.execute("FROM Users SELECT COUNT(*) AS n").await.unwrap();
+    assert_eq!(result.rows[0]["n"], dql_ir::Value::Integer(6));
+    assert_eq!(executor.get_statistics().replica_fallbacks, 2);
+
+    // Writes don't fall back, and aren't partial even under BestEffort
+    let err = executor
+        .execute_with("DELETE FROM Users", PartialResults::BestEffort)
+        .await
+        .unwrap_err();
+    assert!(err.starts_with("Query failed on 1 nodes"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_sub_queries_run_in_parallel() {
+    const NODES: usize = 3;
+
+    // Each node answers like serve_queries, but only once every node has
+    // the sub-query, which sub-queries sent one after another never do
+    let arrived = Arc::new((Mutex::new(0), Condvar::new()));
+    let shards = ShardManager::new(ShardConfig::default());
+    let mut peers = Vec::new();
+    let mut nodes = Vec::new();
+    for (id, name) in [(2, "Alice"), (3, "Bob"), (4, "Carol")] {
+        shards.add_node(id);
+
+        let network = local_network(id);
+        let port = network.start_listener().await.unwrap().port();
+        let executor = executor_with(&[&format!("{{name: '{}'}}", name)]);
+        let arrived = Arc::clone(&arrived);
+        network.register_handler(MessageKind::QueryRequest, move |msg| {
+            let MessageType::QueryRequest { query, statement: Some(statement), .. } = &msg.message_type else {
+                return None;
+            };
+            let (count, all_arrived) = &*arrived;
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            all_arrived.notify_all();
+            let (count, timeout) = all_arrived
+                .wait_timeout_while(count, Duration::from_secs(10), |count| *count < NODES)
+                .unwrap();
+            if timeout.timed_out() {
+                let message = format!("Only {} of {} sub-queries arrived together", *count, NODES);
+                return Some(msg.reply(MessageType::Error { message }));
+            }
+            drop(count);
+
+            let statement = bincode::deserialize(statement).unwrap();
+            let result = executor.execute_parsed(&statement, query).unwrap().to_bytes().unwrap();
+            Some(msg.reply(MessageType::QueryResponse { result }))
+        });
+        peers.push((id, port));
+        nodes.push(network);
+    }
+    let executor = coordinator(shards, &peers, DistributedQueryConfig::default());
+
+    let result = executor.execute("FROM Users SELECT name AS name").await.unwrap();
+    assert_eq!(names(&result), ["Alice", "Bob", "Carol"]);
+}
+
+/// A node of `shards` serving sub-queries, taking writes only for its own
+/// shards; returns its network, port and executor
+async fn owning_node(id: NodeId, shards: &Arc<ShardManager>) -> (Arc<P2PNetwork>, u16, Arc<DQLExecutor>) {
+    let network = local_network(id);
+    let port = network.start_listener().await.unwrap().port();
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor =
+        Arc::new(DQLExecutor::new(graph).with_shard_ownership(ShardOwnership::new(id, Arc::clone(shards))));
+    DistributedQueryExecutor::new(id, Arc::clone(shards), network.clone(), Arc::clone(&executor))
+        .with_config(trusting())
+        .serve_queries();
+    (network, port, executor)
+}
+
+/// A key whose shard `owner` owns
+fn key_owned_by(shards: &ShardManager, owner: NodeId) -> String {
+    (0..1000)
+        .map(|n| n.to_string())
+        .find(|key| shards.get_node_for_shard(shards.get_shard_for_key(key).unwrap()) == Some(owner))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_writes_are_routed_to_shard_owners() {
+    let shards = Arc::new(ShardManager::new(ShardConfig {
+        replication_factor: 1,
+        ..ShardConfig::default()
+    }));
+    shards.add_node(2);
+    shards.add_node(3);
+    let (_node2, port2, executor2) = owning_node(2, &shards).await;
+    let (_node3, port3, executor3) = owning_node(3, &shards).await;
+
+    // Node 2 coordinates, and takes writes only for its own shards too
+    let network = local_network(2);
+    network.add_peer(3, NodeAddress::new("127.0.0.1".to_string(), port3));
+    let coordinator = DistributedQueryExecutor::new(2, Arc::clone(&shards), network, Arc::clone(&executor2));
+    let key2 = key_owned_by(&shards, 2);
+    let key3 = key_owned_by(&shards, 3);
+
+    // Rows land on their owners only, an id-less one once given an id
+    let insert = format!(
+        "INSERT INTO Users VALUES ({{id: {}, name: 'Alice'}}), ({{id: {}, name: 'Bob'}}), ({{name: 'Carol'}})",
+        key2, key3
+    );
+    assert_eq!(coordinator.execute(&insert).await.unwrap().rows_affected, 3);
+    let local_names = |executor: &DQLExecutor| names(&executor.execute("FROM Users SELECT name AS name").unwrap());
+    let (on2, on3) = (local_names(&executor2), local_names(&executor3));
+    assert!(on2.contains(&"Alice".to_string()) && on3.contains(&"Bob".to_string()));
+    assert_eq!(on2.len() + on3.len(), 3);
+
+    let carol: Vec<_> = [&executor2, &executor3]
+        .iter()
+        .flat_map(|executor| executor.execute("FROM Users WHERE name = 'Carol' SELECT id AS id").unwrap().rows)
+        .collect();
+    assert_eq!(carol.len(), 1);
+    let dql_ir::Value::Integer(id) = carol[0]["id"] else { panic!("Carol has no id: {:?}", carol) };
+    assert_eq!(id >> 47, 2, "{} wasn't assigned by node 2", id);
+
+    // Queryable cluster-wide, from any node
+    let everyone = coordinator.execute("FROM Users SELECT name AS name").await.unwrap();
+    assert_eq!(names(&everyone), ["Alice", "Bob", "Carol"]);
+    let network = local_network(4);
+    network.add_peer(2, NodeAddress::new("127.0.0.1".to_string(), port2));
+    network.add_peer(3, NodeAddress::new("127.0.0.1".to_string(), port3));
+    let elsewhere = DistributedQueryExecutor::new(4, Arc::clone(&shards), network, executor_with(&[]));
+    let bob = elsewhere.execute(&format!("FROM Users WHERE id = {} SELECT name AS name", key3)).await.unwrap();
+    assert_eq!(names(&bob), ["Bob"]);
+
+    // Updates follow the same path
+    let update = format!("UPDATE Users SET name = 'Robert' WHERE id = {}", key3);
+    assert_eq!(coordinator.execute(&update).await.unwrap().rows_affected, 1);
+    let robert = executor3.execute(&format!("FROM Users WHERE id = {} SELECT name AS name", key3)).unwrap();
+    assert_eq!(names(&robert), ["Robert"]);
+    assert!(coordinator.execute(&format!("UPDATE Users SET id = 1 WHERE id = {}", key3)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_strict_node_rejects_foreign_writes() {
+    let shards = Arc::new(ShardManager::new(ShardConfig::default()));
+    shards.add_node(2);
+    shards.add_node(3);
+    let (_node, _port, executor) = owning_node(2, &shards).await;
+    let key3 = key_owned_by(&shards, 3);
+    let shard = shards.get_shard_for_key(&key3).unwrap();
+
+    let err = executor
+        .execute(&format!("INSERT INTO Users VALUES ({{id: {}, name: 'Bob'}})", key3))
+        .unwrap_err();
+    assert_eq!(
+        err,
+        format!("Not owner of shard {} (key '{}'): owner is node 3; redirect the write to node 3", shard, key3)
+    );
+    assert!(executor.execute(&format!("DELETE FROM Users WHERE id = {}", key3)).is_err());
+    assert!(executor.execute("INSERT INTO Users VALUES ({name: 'Carol'})").is_err());
+
+    // Its own shards' writes, and reads of any shard, are served
+    let key2 = key_owned_by(&shards, 2);
+    executor.execute(&format!("INSERT INTO Users VALUES ({{id: {}, name: 'Alice'}})", key2)).unwrap();
+    executor.execute(&format!("FROM Users WHERE id = {} SELECT name", key3)).unwrap();
+}
@@ -2,6 +2,7 @@
 
 use deed_core::*;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[test]
 fn test_dql_simple_select() {
@@ -41,6 +42,46 @@ fn test_dql_parser() {
     }
 }
 
+#[test]
+fn test_parse_errors_report_line_and_column() {
+    let err = DQLParser::parse("FROM Users\nWHERE age > 21\nSELCT name").unwrap_err();
+    match &err {
+        DeedError::ParseError { line, column, message, snippet } => {
+            assert_eq!((*line, *column), (3, 1));
+            assert!(message.contains("did you mean SELECT instead of SELCT?"), "{}", message);
+            assert_eq!(snippet, "3 | SELCT name\n  | ^");
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+
+    // Indentation and earlier lines don't throw the column off
+    let err = DQLParser::parse("FROM Users\n  WHERE (age > 3\n    SELECT name").unwrap_err();
+    assert!(matches!(err, DeedError::ParseError { line: 3, column: 5, .. }), "{}", err);
+    assert!(err.to_string().starts_with("ParseError at line 3, column 5: Expected RightParen"), "{}", err);
+}
+
+#[test]
+fn test_try_execute_classifies_errors() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', age: 0})").unwrap();
+    executor.execute("DEFINE SCHEMA Accounts (email String NOT NULL)").unwrap();
+
+    let err = executor.try_execute("FROM Users\nWHERE age >\nSELECT name").unwrap_err();
+    assert!(matches!(err, DeedError::ParseError { line: 3, column: 1, .. }), "{}", err);
+
+    let err = executor.try_execute("FROM Users WHERE 1 / age > 0 SELECT name").unwrap_err();
+    assert_eq!(err, DeedError::ExecutionError("Division by zero".to_string()));
+
+    let err = executor.try_execute("INSERT INTO Accounts VALUES ({name: 'Ann'})").unwrap_err();
+    assert!(matches!(err, DeedError::ValidationError(_)), "{}", err);
+
+    let err = executor.try_execute("INSERT INTO _audit VALUES ({name: 'Ann'})").unwrap_err();
+    assert!(matches!(err, DeedError::PermissionError(_)), "{}", err);
+
+    let err = executor.try_execute("COMMIT").unwrap_err();
+    assert!(matches!(err, DeedError::TransactionError(_)), "{}", err);
+}
+
 #[test]
 fn test_dql_lexer() {
     use dql_lexer::{Lexer, Token};
@@ -56,6 +97,61 @@ fn test_dql_lexer() {
     assert_eq!(tokens[5], Token::Integer(25));
 }
 
+#[test]
+fn test_comments_parse_like_the_stripped_query() {
+    let commented = "-- adult users\nFROM Users /* everyone, \n for now */ WHERE age > 21 -- adults only\nSELECT name /* just the name */ --";
+    let stripped = "FROM Users WHERE age > 21 SELECT name";
+    assert_eq!(DQLParser::parse(commented).unwrap(), DQLParser::parse(stripped).unwrap());
+
+    let err = DQLParser::parse("FROM Users /* unfinished\nSELECT name").unwrap_err();
+    assert!(matches!(err, DeedError::ParseError { line: 1, column: 12, .. }), "{}", err);
+    assert_eq!(err.message(), "Unterminated block comment");
+}
+
+#[test]
+fn test_quotes_inside_string_literals() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({name: 'O''Brien'}), ({name: 'D\\'Arcy'}), ({name: \"say \"\"hi\"\"\"})").unwrap();
+
+    for (query, expected) in [
+        ("FROM Users WHERE name = 'O''Brien' SELECT name AS name", "O'Brien"),
+        ("FROM Users WHERE name = 'D''Arcy' SELECT name AS name", "D'Arcy"),
+        ("FROM Users WHERE name = 'say \"hi\"' SELECT name AS name", "say \"hi\""),
+    ] {
+        let res = executor.execute(query).unwrap();
+        assert_eq!(res.row_count(), 1, "{}", query);
+        assert_eq!(res.rows[0]["name"], dql_ir::Value::String(expected.to_string()));
+    }
+}
+
+#[test]
+fn test_quoted_identifiers_name_collections_and_properties() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    executor
+        .execute("INSERT INTO \"Order Items\" VALUES ({sku: 'A-1', \"unit price\": 5}), ({sku: 'B-2', \"unit price\": 12})")
+        .unwrap();
+    executor.execute("INSERT INTO \"Index\" VALUES ({page: 1})").unwrap();
+
+    let res = executor
+        .execute("FROM \"Order Items\" oi WHERE oi.\"unit price\" > 10 SELECT oi.sku AS sku")
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["sku"], dql_ir::Value::String("B-2".to_string()));
+
+    // Reserved words work as names once quoted
+    assert_eq!(executor.execute("FROM \"Index\" SELECT page AS page").unwrap().row_count(), 1);
+
+    // In an expression a quoted name is a property, never a string
+    let res = executor
+        .execute("FROM \"Order Items\" WHERE \"sku\" = 'A-1' AND \"unit price\" < 10 SELECT \"sku\" AS sku")
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["sku"], dql_ir::Value::String("A-1".to_string()));
+    assert_eq!(executor.execute("FROM \"Order Items\" WHERE sku = \"A-1\" SELECT sku").unwrap().row_count(), 0);
+    assert_eq!(graph.read().unwrap().scan_collection("Order Items").len(), 2);
+}
+
 #[test]
 fn test_dql_traverse_pattern() {
     let query = "FROM Users TRAVERSE -[:PURCHASED*1..3]-> Product SELECT Product.name";
@@ -92,6 +188,7 @@ fn test_ant_colony_optimizer() {
         edge_count: 50000,
         collection_count: 10,
         avg_pheromone: 1.0,
+        ..Default::default()
     };
 
     let operations = vec![
@@ -105,15 +202,17 @@ fn test_ant_colony_optimizer() {
                 }),
                 Box::new(FilterExpr::Constant(dql_ir::Value::String("NYC".to_string()))),
             )),
+            properties: None,
         },
         Operation::Project {
-            fields: vec![ProjectField {
+            fields: vec![Projection::Field(ProjectField {
                 expression: FilterExpr::Property {
                     binding: "u".to_string(),
                     property: "name".to_string(),
                 },
                 alias: "name".to_string(),
-            }],
+            })],
+            distinct: false,
         },
     ];
 
@@ -151,68 +250,5033 @@ fn test_stigmergy_cache() {
     assert_eq!(stats.total_hits, 1);
 }
 
-// Helper functions
+#[test]
+fn test_bounded_staleness_routes_to_replica() {
+    let (executor, _) = setup_replicated_executor();
 
-fn setup_test_graph() -> Arc<RwLock<Graph>> {
-    let graph = Arc::new(RwLock::new(Graph::new()));
+    // Replica is ~10s behind: within a 30s bound, outside a 5s bound
+    let res = executor
+        .execute_with_staleness("FROM Users SELECT name", Duration::from_secs(30))
+        .unwrap();
+    assert_eq!(res.row_count(), 1, "Should read the replica's single user");
+    let staleness = res.staleness_ms.expect("Replica read should report staleness");
+    assert!((9_000..=11_000).contains(&staleness), "Unexpected staleness {}", staleness);
+
+    let res = executor
+        .execute_with_staleness("FROM Users SELECT name", Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(res.row_count(), 10, "Should fall back to the master");
+    assert_eq!(res.staleness_ms, Some(0));
+
+    let res = executor.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 10);
+    assert_eq!(res.staleness_ms, None, "Plain reads are not bounded-staleness reads");
+}
+
+#[test]
+fn test_session_max_staleness_setting() {
+    let (executor, _) = setup_replicated_executor();
+
+    executor.execute("SET max_staleness = '30s'").unwrap();
+    let res = executor.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 1, "Session bound should route to the replica");
+
+    // Explicit transactions always read the master
+    executor.execute("BEGIN").unwrap();
+    let res = executor.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 10);
+    assert_eq!(res.staleness_ms, None);
+    executor.execute("COMMIT").unwrap();
+
+    executor.execute("SET max_staleness = 'off'").unwrap();
+    let res = executor.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 10);
+
+    assert!(executor.execute("SET max_staleness = 'soon'").is_err());
+    assert!(executor.execute("SET unknown_option = 1").is_err());
+}
+
+#[test]
+fn test_archive_with_transparent_reads() {
+    let graph = setup_orders_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    let res = executor
+        .execute("ARCHIVE FROM Orders WHERE created_at < TIMESTAMP '2022-01-01'")
+        .unwrap();
+    assert_eq!(res.rows_affected, 5);
+    assert_eq!(graph.read().unwrap().scan_collection("Orders").len(), 5, "Archived rows leave the hot tier");
+
+    // Full query merges hot and archived rows
+    let res = executor.execute("FROM Orders SELECT order_no").unwrap();
+    assert_eq!(res.row_count(), 10);
+    assert!(res.warnings.is_empty());
 
+    // Point lookup into the archive
+    let res = executor.execute("FROM Orders WHERE order_no = 2 SELECT total").unwrap();
+    assert_eq!(res.row_count(), 1);
+
+    // Hot-only reads skip the archive and say so
+    executor.execute("SET archive_reads = off").unwrap();
+    let res = executor.execute("FROM Orders SELECT order_no").unwrap();
+    assert_eq!(res.row_count(), 5);
+    assert_eq!(res.warnings.len(), 1);
+    assert!(res.warnings[0].contains("5 archived"));
+}
+
+#[test]
+fn test_archive_reads_stream_only_the_collection_archive() {
+    let graph = setup_orders_graph();
     {
         let g = graph.read().unwrap();
+        for i in 0..1_000 {
+            let props = std::collections::HashMap::from([("n".to_string(), PropertyValue::Int(i))]);
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+        let props = std::collections::HashMap::from([("name".to_string(), PropertyValue::String("Ann".to_string()))]);
+        let customer = g.add_entity("Customers".to_string(), props).unwrap();
+        for order in g.scan_collection("Orders") {
+            g.add_edge(order.id, customer, "PLACED_BY".to_string(), Default::default()).unwrap();
+        }
+    }
+    let executor = DQLExecutor::new(graph.clone());
+    executor
+        .execute("ARCHIVE FROM Orders WHERE created_at < TIMESTAMP '2022-01-01'")
+        .unwrap();
 
-        // Add test users
-        for i in 1..=10 {
-            let mut props = std::collections::HashMap::new();
-            props.insert(
-                "name".to_string(),
-                PropertyValue::String(format!("User{}", i)),
-            );
-            props.insert("age".to_string(), PropertyValue::Int(20 + i));
-            props.insert(
-                "city".to_string(),
-                PropertyValue::String(if i % 2 == 0 {
-                    "NYC".to_string()
-                } else {
-                    "SF".to_string()
-                }),
-            );
+    // The rest of the graph isn't copied to read the archive
+    let clones = || graph.read().unwrap().entity_clones();
+    let before = clones();
+    assert_eq!(executor.execute("FROM Orders SELECT order_no").unwrap().row_count(), 10);
+    assert!(clones() - before <= 10, "{} entities copied", clones() - before);
+
+    // Archived orders still lead to their customer and fill their groups
+    let res = executor
+        .execute("FROM Orders TRAVERSE -[:PLACED_BY]-> c WHERE order_no = 2 SELECT c.name")
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Ann".to_string()));
+    let res = executor.execute("FROM Orders SELECT created_at, COUNT(*) GROUP BY created_at").unwrap();
+    assert_eq!(res.row_count(), 10);
+}
+
+#[test]
+fn test_unarchive_round_trips_properties() {
+    let dir = std::env::temp_dir().join("deed_test_unarchive");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let graph = setup_orders_graph();
+    let original = graph.read().unwrap().scan_collection("Orders");
+    let archive = Arc::new(ArchiveManager::open(&dir).unwrap());
+    let executor = DQLExecutor::new(graph.clone()).with_archive(archive.clone());
+
+    executor.execute("ARCHIVE FROM Orders").unwrap();
+    assert_eq!(archive.archived_count("Orders"), 10);
+    assert!(graph.read().unwrap().scan_collection("Orders").is_empty());
+
+    let res = executor.execute("UNARCHIVE FROM Orders").unwrap();
+    assert_eq!(res.rows_affected, 10);
+    assert_eq!(archive.archived_count("Orders"), 0);
+    assert!(archive.segment_files().is_empty());
+
+    let g = graph.read().unwrap();
+    for entity in original {
+        let restored = g.get_entity(entity.id).expect("Entity should be restored");
+        assert_eq!(restored.properties, entity.properties);
+    }
+}
+
+#[test]
+fn test_archive_and_unarchive_are_logged_and_replicated() {
+    let base = std::env::temp_dir().join(format!("deed_test_archive_wal_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(&base).unwrap();
+    let wal_path = base.join("wal");
+    let master = Arc::new(ReplicationManager::new_master("master".to_string()));
+    let archive = Arc::new(ArchiveManager::open(base.join("archive")).unwrap());
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new_with_wal(graph.clone(), &wal_path)
+        .unwrap()
+        .with_replication(master.clone())
+        .with_archive(archive.clone());
 
-            g.add_entity("Users".to_string(), props);
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    let order = graph.read().unwrap().scan_collection("Orders")[0].id;
+    executor.execute("INSERT INTO Orders VALUES ({total: 20})").unwrap();
+    let alice = Entity::new(EntityId::new(1_000), "Users".to_string(), Default::default());
+    let placed = Edge::new(EdgeId::new(1_000), alice.id, order, "PLACED".to_string(), Default::default());
+    executor.store_with_ids(vec![alice], vec![placed]).unwrap();
+
+    let replay = |graph: &Graph| {
+        let slave = ReplicationManager::new_slave("replica-1".to_string(), "master".to_string());
+        for entry in master.get_entries_since(0) {
+            slave.apply_to_graph(graph, &entry).unwrap();
         }
+    };
+    // Recovery checkpoints the log it reads, so it reads a copy
+    let recover = || {
+        let copy = base.join("wal_copy");
+        std::fs::copy(&wal_path, &copy).unwrap();
+        let recovered = Arc::new(RwLock::new(Graph::new()));
+        DQLExecutor::recover_from_wal(recovered.clone(), &copy).unwrap();
+        recovered
+    };
+
+    // The archived order and the edge into it leave recovered and replicated graphs too
+    executor.execute("ARCHIVE FROM Orders WHERE total = 10").unwrap();
+    let replica = Graph::new();
+    replay(&replica);
+    let recovered = recover();
+    for graph in [&replica, &*recovered.read().unwrap()] {
+        assert_eq!(graph.scan_collection("Orders").len(), 1);
+        assert!(graph.get_all_edges().is_empty());
     }
 
-    graph
+    assert_eq!(executor.execute("UNARCHIVE FROM Orders").unwrap().rows_affected, 1);
+    assert_eq!(archive.archived_count("Orders"), 0);
+    let replica = Graph::new();
+    replay(&replica);
+    let recovered = recover();
+    for graph in [&replica, &*recovered.read().unwrap()] {
+        assert_eq!(graph.scan_collection("Orders").len(), 2);
+        assert_eq!(graph.get_all_edges().len(), 1);
+    }
+    let _ = std::fs::remove_dir_all(&base);
 }
 
-fn setup_test_graph_with_edges() -> Arc<RwLock<Graph>> {
-    let graph = Arc::new(RwLock::new(Graph::new()));
+#[test]
+fn test_archive_included_in_backup() {
+    let base = std::env::temp_dir().join("deed_test_archive_backup");
+    let _ = std::fs::remove_dir_all(&base);
+
+    let graph = setup_orders_graph();
+    let archive = Arc::new(ArchiveManager::open(base.join("archive")).unwrap());
+    let executor = DQLExecutor::new(graph.clone()).with_archive(archive.clone());
+    executor
+        .execute("ARCHIVE FROM Orders WHERE created_at < TIMESTAMP '2022-01-01' PARTITION BY created_at")
+        .unwrap();
+    assert_eq!(archive.partitions("Orders").len(), 5, "One chunk per created_at value");
+
+    let mut backups = BackupManager::new(BackupConfig {
+        backup_dir: base.join("backups"),
+        compress: true,
+        verify: true,
+    })
+    .unwrap();
+    let metadata = backups
+        .create_full_backup_with_archive(&graph.read().unwrap(), &archive)
+        .unwrap();
+    assert_eq!(metadata.entity_count, 5);
+    assert_eq!(metadata.archive_segments, 5);
+
+    let restored = backups.restore_archive(&metadata.backup_id, base.join("restored")).unwrap();
+    assert_eq!(restored.archived_count("Orders"), 5);
+    assert_eq!(restored.partitions("Orders"), archive.partitions("Orders"));
+    assert!(base.join("restored/Orders/created_at=2021-01-01").is_dir());
+}
 
+#[test]
+fn test_reindex_while_writer_mutates() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let mut ids = Vec::new();
     {
         let g = graph.read().unwrap();
-
-        // Add users
-        let mut user_ids = Vec::new();
-        for i in 1..=5 {
+        for i in 0..5000 {
             let mut props = std::collections::HashMap::new();
-            props.insert(
-                "name".to_string(),
-                PropertyValue::String(format!("User{}", i)),
-            );
-            props.insert("age".to_string(), PropertyValue::Int(20 + i));
-
-            let id = g.add_entity("Users".to_string(), props);
-            user_ids.push(id);
+            props.insert("score".to_string(), PropertyValue::Int(i));
+            ids.push(g.add_entity("Users".to_string(), props).unwrap());
         }
+    }
 
-        // Add FOLLOWS edges
-        for i in 0..user_ids.len() - 1 {
-            g.add_edge(
-                user_ids[i],
-                user_ids[i + 1],
-                "FOLLOWS".to_string(),
-                std::collections::HashMap::new(),
-            );
+    let executor = DQLExecutor::new(graph.clone());
+    executor.execute("CREATE INDEX idx_score ON Users(score)").unwrap();
+    let indexes = executor.index_manager();
+
+    // Writer keeps changing the indexed column (and maintaining indexes) during the rebuild
+    let writer = {
+        let graph = graph.clone();
+        let indexes = indexes.clone();
+        let ids = ids.clone();
+        std::thread::spawn(move || {
+            for id in ids.iter().step_by(3) {
+                let g = graph.read().unwrap();
+                let mut entity = g.get_entity(*id).unwrap();
+                let old_props = entity.properties.clone();
+                let score = entity.get_property("score").and_then(|v| v.as_i64()).unwrap();
+                entity.set_property("score".to_string(), PropertyValue::Int(score + 100_000));
+                let new_props = entity.properties.clone();
+                g.update_entity(entity).unwrap();
+
+                indexes.remove_from_indexes("Users", *id, &old_props);
+                indexes.insert_into_indexes("Users", *id, &new_props).unwrap();
+            }
+        })
+    };
+
+    let res = executor.execute("REINDEX idx_score").unwrap();
+    writer.join().unwrap();
+    assert_eq!(res.row_count(), 1);
+
+    let index = indexes.get_index("idx_score").unwrap();
+    assert_eq!(index.total_entities(), 5000);
+
+    let g = graph.read().unwrap();
+    for id in &ids {
+        let score = g.get_entity(*id).unwrap().get_property("score").cloned().unwrap();
+        assert_eq!(index.lookup(&score), vec![*id], "Index entry for {:?} is stale", id);
+    }
+
+    let progress = indexes.rebuild_progress();
+    assert_eq!(progress[0].phase, RebuildPhase::Swapped);
+}
+
+#[test]
+fn test_reindex_unique_violation_keeps_old_index() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    executor.execute("CREATE UNIQUE INDEX idx_email ON Users(email)").unwrap();
+    let indexes = executor.index_manager();
+
+    let mut ids = Vec::new();
+    {
+        let g = graph.read().unwrap();
+        for email in ["a@example.com", "b@example.com", "a@example.com"] {
+            let mut props = std::collections::HashMap::new();
+            props.insert("email".to_string(), PropertyValue::String(email.to_string()));
+            let id = g.add_entity("Users".to_string(), props.clone()).unwrap();
+            // The duplicate slipped in without index maintenance
+            if ids.len() < 2 {
+                indexes.insert_into_indexes("Users", id, &props).unwrap();
+            }
+            ids.push(id);
         }
     }
 
-    graph
+    let err = executor.execute("REINDEX COLLECTION Users").unwrap_err();
+    assert!(err.contains(&format!("[{}, {}]", ids[0].0, ids[2].0)), "Unexpected report: {}", err);
+
+    let index = indexes.get_index("idx_email").unwrap();
+    assert_eq!(index.total_entities(), 2, "Old index should stay in service");
+    assert_eq!(index.lookup(&PropertyValue::String("a@example.com".to_string())), vec![ids[0]]);
+    assert_eq!(indexes.rebuild_progress()[0].phase, RebuildPhase::Aborted);
+}
+
+#[test]
+fn test_firewall_denies_unbounded_scans() {
+    let graph = setup_test_graph();
+    let firewall = Arc::new(Firewall::new());
+    let admin = DQLExecutor::new(graph.clone())
+        .with_firewall(firewall.clone(), FirewallPrincipal::user("admin", Role::Admin));
+    let public = DQLExecutor::new(graph).with_firewall(firewall.clone(), FirewallPrincipal::api_key("public"));
+    public.execute("CREATE INDEX idx_city ON Users(city)").unwrap();
+
+    assert!(public.execute("FROM Users SELECT name").is_ok());
+
+    // Rule changes apply to already-open sessions
+    admin
+        .execute("FIREWALL DENY no_unbounded FOR KEY 'public' WHEN UNBOUNDED")
+        .unwrap();
+
+    let err = public.execute("FROM Users SELECT name").unwrap_err();
+    assert!(err.starts_with("FirewallRejected"), "Unexpected error: {}", err);
+    assert!(err.contains("no_unbounded"));
+
+    let res = public
+        .execute("FROM Users WHERE city = 'NYC' SELECT name LIMIT 3")
+        .unwrap();
+    assert_eq!(res.row_count(), 3);
+
+    // Unindexed predicate is still a full scan
+    assert!(public.execute("FROM Users WHERE age > 25 SELECT name LIMIT 3").is_err());
+
+    let audit = firewall.audit_log();
+    assert_eq!(audit.len(), 2);
+    assert!(audit.iter().all(|entry| entry.enforced && entry.rule == "no_unbounded"));
+
+    // Only admins manage rules
+    assert!(public.execute("FIREWALL DROP no_unbounded").is_err());
+    admin.execute("FIREWALL DROP no_unbounded").unwrap();
+    assert!(public.execute("FROM Users SELECT name").is_ok());
+}
+
+#[test]
+fn test_firewall_checks_statements_that_run_without_a_plan() {
+    let graph = setup_test_graph();
+    let firewall = Arc::new(Firewall::new());
+    let admin = DQLExecutor::new(graph.clone())
+        .with_firewall(firewall.clone(), FirewallPrincipal::user("admin", Role::Admin));
+    let public = DQLExecutor::new(graph).with_firewall(firewall.clone(), FirewallPrincipal::api_key("public"));
+
+    admin.execute("FIREWALL DENY no_drops FOR KEY 'public' WHEN DROP").unwrap();
+    admin.execute("FIREWALL DENY no_admin FOR KEY 'public' WHEN ADMIN").unwrap();
+    admin.execute("FIREWALL DENY no_users_ddl FOR KEY 'public' WHEN CREATE, COLLECTION Users").unwrap();
+    admin.execute("FIREWALL DENY no_txns FOR KEY 'public' WHEN TRANSACTION").unwrap();
+
+    for statement in [
+        "TRUNCATE Users",
+        "DROP COLLECTION Users",
+        "SET memory_budget = '1MB'",
+        "REINDEX COLLECTION Users",
+        "CREATE INDEX idx_age ON Users(age)",
+        "BEGIN TRANSACTION",
+        "SAVEPOINT before",
+    ] {
+        let err = public.execute(statement).unwrap_err();
+        assert!(err.starts_with("FirewallRejected"), "{} wasn't rejected: {}", statement, err);
+    }
+    assert_eq!(public.execute("FROM Users SELECT name").unwrap().row_count(), 10);
+
+    // Exports read the whole collection
+    admin.execute("FIREWALL DENY no_full_scans FOR KEY 'public' WHEN FULL_SCAN").unwrap();
+    let path = std::env::temp_dir().join(format!("deed_firewall_export_{}.jsonl", std::process::id()));
+    let err = public
+        .execute(&format!("COPY Users TO '{}'", path.display()))
+        .unwrap_err();
+    assert!(err.starts_with("FirewallRejected"), "Unexpected error: {}", err);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_firewall_learning_mode_records_without_blocking() {
+    let firewall = Arc::new(Firewall::new());
+    let admin = DQLExecutor::new(setup_test_graph())
+        .with_firewall(firewall.clone(), FirewallPrincipal::user("admin", Role::Admin));
+    let public = DQLExecutor::new(setup_test_graph())
+        .with_firewall(firewall.clone(), FirewallPrincipal::api_key("public"));
+
+    admin
+        .execute("FIREWALL ALLOW vetted_reads FOR KEY 'public' WHEN SELECT, COLLECTION Users, COST > 0")
+        .unwrap();
+    admin.execute("FIREWALL LEARN FOR KEY 'public' '1h'").unwrap();
+
+    // Not on the allowlist, but only recorded while learning
+    public
+        .execute("INSERT INTO Users VALUES ({name: 'Mallory'})")
+        .unwrap();
+    let audit = firewall.audit_log();
+    assert_eq!(audit.len(), 1);
+    assert!(!audit[0].enforced);
+    assert_eq!(audit[0].statement, "INSERT INTO Users VALUES ({name: 'Mallory'})");
+
+    admin.execute("FIREWALL ENFORCE FOR KEY 'public'").unwrap();
+    let err = public
+        .execute("INSERT INTO Users VALUES ({name: 'Mallory'})")
+        .unwrap_err();
+    assert!(err.starts_with("FirewallRejected"));
+    assert!(public.execute("FROM Users SELECT name").is_ok());
+}
+
+#[test]
+fn test_firewall_rules_persist() {
+    let path = std::env::temp_dir().join("deed_test_firewall.json");
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let firewall = Arc::new(Firewall::open(&path).unwrap());
+        let admin = DQLExecutor::new(setup_test_graph())
+            .with_firewall(firewall, FirewallPrincipal::user("admin", Role::Admin));
+        admin
+            .execute("FIREWALL DENY no_traversals FOR ROLE readonly WHEN UNBOUNDED_TRAVERSAL")
+            .unwrap();
+    }
+
+    let reopened = Firewall::open(&path).unwrap();
+    let rules = reopened.rules();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].subject, FirewallSubject::Role(Role::ReadOnly));
+    assert_eq!(rules[0].conditions, vec![FirewallCondition::UnboundedTraversal]);
+}
+
+#[test]
+fn test_multi_hop_traverse_chain() {
+    // N0 -> N1 -> N2 -> N3 -> N4, plus N0 -[:OTHER]-> N4
+    let graph = setup_hop_graph(5, &[(0, 1), (1, 2), (2, 3), (3, 4)]);
+    {
+        let g = graph.read().unwrap();
+        let ids: Vec<EntityId> = g.scan_collection("Nodes").iter().map(|e| e.id).collect();
+        g.add_edge(ids[0], ids[4], "OTHER".to_string(), std::collections::HashMap::new()).unwrap();
+    }
+    let executor = DQLExecutor::new(graph);
+
+    let reached = |query: &str| traversed_names(&executor, query);
+
+    assert_eq!(reached("FROM Nodes TRAVERSE -[:NEXT*1..3]-> n WHERE name = 'N0' SELECT n.name"), ["N1", "N2", "N3"]);
+    assert_eq!(reached("FROM Nodes TRAVERSE -[:NEXT*2..3]-> n WHERE name = 'N0' SELECT n.name"), ["N2", "N3"]);
+    assert_eq!(reached("FROM Nodes TRAVERSE -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name"), ["N1", "N2", "N3", "N4"]);
+    assert_eq!(reached("FROM Nodes TRAVERSE <-[:NEXT*1..2]- n WHERE name = 'N4' SELECT n.name"), ["N2", "N3"]);
+    assert_eq!(reached("FROM Nodes TRAVERSE -[:NEXT]-> n WHERE name = 'N0' SELECT n.name"), ["N1"]);
+}
+
+#[test]
+fn test_unbounded_traverse_terminates_on_cycle() {
+    // N0 -> N1 -> N2 -> N0
+    let graph = setup_hop_graph(3, &[(0, 1), (1, 2), (2, 0)]);
+    let executor = DQLExecutor::new(graph);
+
+    let res = executor
+        .execute("FROM Nodes TRAVERSE -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name")
+        .unwrap();
+    assert_eq!(res.row_count(), 2, "N1 and N2, each once");
+    assert_eq!(
+        traversed_names(&executor, "FROM Nodes TRAVERSE -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name"),
+        ["N1", "N2"]
+    );
+}
+
+#[test]
+fn test_multi_hop_traverse_diamond_deduplicates() {
+    // N0 -> N1 -> N3 and N0 -> N2 -> N3
+    let graph = setup_hop_graph(4, &[(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let executor = DQLExecutor::new(graph);
+
+    let res = executor
+        .execute("FROM Nodes TRAVERSE -[:NEXT*1..2]-> n WHERE name = 'N0' SELECT n.name")
+        .unwrap();
+    assert_eq!(res.row_count(), 3, "N3 is reachable twice but returned once");
+
+    assert_eq!(
+        traversed_names(&executor, "FROM Nodes TRAVERSE -[:NEXT*2..2]-> n WHERE name = 'N0' SELECT n.name"),
+        ["N3"]
+    );
+}
+
+#[test]
+fn test_traverse_all_keeps_one_row_per_path() {
+    // N0 -> N1 -> N3 and N0 -> N2 -> N3, with a self-loop on N3 and N3 -> N0
+    let graph = setup_hop_graph(4, &[(0, 1), (0, 2), (1, 3), (2, 3), (3, 3), (3, 0)]);
+    let executor = DQLExecutor::new(graph);
+    let reached = |query: &str| traversed_names(&executor, query);
+
+    // The far corner of the diamond once by default, once per path with ALL
+    assert_eq!(reached("FROM Nodes TRAVERSE -[:NEXT*2]-> n WHERE name = 'N0' SELECT n.name"), ["N3"]);
+    assert_eq!(reached("FROM Nodes TRAVERSE ALL -[:NEXT*2]-> n WHERE name = 'N0' SELECT n.name"), ["N3", "N3"]);
+
+    // Paths never revisit an entity, so the self-loop and the cycle back to N0 end
+    let all = executor
+        .execute("FROM Nodes TRAVERSE ALL -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name")
+        .unwrap();
+    assert_eq!(all.row_count(), 4, "N1, N2 and N3 by each of its two paths");
+    let distinct = executor
+        .execute("FROM Nodes TRAVERSE -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name")
+        .unwrap();
+    assert_eq!(distinct.row_count(), 3);
+
+    // Rows from different sources stay distinct even when they reach the same entity
+    assert_eq!(
+        reached("FROM Nodes TRAVERSE -[:NEXT]-> n WHERE name = 'N1' OR name = 'N2' SELECT n.name"),
+        ["N3", "N3"]
+    );
+    assert!(executor
+        .execute("EXPLAIN FROM Nodes TRAVERSE ALL -[:NEXT*2]-> n SELECT n.name")
+        .unwrap()
+        .rows
+        .iter()
+        .any(|row| matches!(row.get("details"), Some(dql_ir::Value::String(details)) if details.contains("ALL PATHS"))));
+}
+
+#[test]
+fn test_unbounded_traverse_stops_at_limit() {
+    let edges: Vec<(usize, usize)> = (0..999).map(|i| (i, i + 1)).collect();
+    let graph = setup_hop_graph(1000, &edges);
+    let executor = DQLExecutor::new(graph);
+
+    let res = executor
+        .execute("FROM Nodes TRAVERSE -[:NEXT*]-> n WHERE name = 'N0' SELECT n.name LIMIT 5")
+        .unwrap();
+    assert_eq!(res.row_count(), 5);
+}
+
+#[test]
+fn test_order_by_two_keys_mixed_directions() {
+    let executor = DQLExecutor::new(setup_products_graph());
+
+    let res = executor
+        .execute("FROM Products SELECT name AS name, category, price ORDER BY category ASC, price DESC")
+        .unwrap();
+    let names: Vec<dql_ir::Value> = res.rows.iter().map(|row| row["name"].clone()).collect();
+    assert_eq!(names, string_values(&["Apple", "Pear", "Plum", "Desk", "Lamp", "Chair"]));
+
+    // ORDER BY a column that isn't selected, by alias-free expression
+    let res = executor.execute("FROM Products SELECT name ORDER BY price DESC").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Desk".to_string()));
+    assert_eq!(res.rows[0].len(), 1, "Hidden sort column should be dropped");
+}
+
+#[test]
+fn test_order_by_column_with_nulls() {
+    let executor = DQLExecutor::new(setup_products_graph());
+
+    // Only Apple, Chair and Desk have a rating; NULLs sort last ascending
+    let res = executor.execute("FROM Products SELECT name, rating ORDER BY rating").unwrap();
+    let ratings: Vec<&dql_ir::Value> = res.rows.iter().map(|row| &row["col_1"]).collect();
+    assert_eq!(ratings[..3], [&dql_ir::Value::Integer(2), &dql_ir::Value::Integer(4), &dql_ir::Value::Integer(5)]);
+    assert!(ratings[3..].iter().all(|v| **v == dql_ir::Value::Null));
+
+    // ...and first descending
+    let res = executor.execute("FROM Products SELECT name, rating ORDER BY rating DESC").unwrap();
+    assert_eq!(res.rows[2]["col_1"], dql_ir::Value::Null);
+    assert_eq!(res.rows[3]["col_1"], dql_ir::Value::Integer(5));
+}
+
+#[test]
+fn test_order_by_strings_and_numbers() {
+    let executor = DQLExecutor::new(setup_products_graph());
+
+    // sku mixes integers, floats and strings: numbers first (numerically), then strings
+    let res = executor.execute("FROM Products SELECT sku ORDER BY sku").unwrap();
+    let skus: Vec<&dql_ir::Value> = res.rows.iter().map(|row| &row["col_0"]).collect();
+    assert_eq!(
+        skus,
+        [
+            &dql_ir::Value::Integer(7),
+            &dql_ir::Value::Float(9.5),
+            &dql_ir::Value::Integer(10),
+            &dql_ir::Value::Integer(100),
+            &dql_ir::Value::String("A-1".to_string()),
+            &dql_ir::Value::String("B-2".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_traverse_projection_pairs_bindings() {
+    let executor = DQLExecutor::new(setup_purchase_graph());
+
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:PURCHASED]-> p SELECT u.name, p.name, p.price")
+        .unwrap();
+
+    let mut pairs: Vec<(String, String)> = res
+        .rows
+        .iter()
+        .map(|row| match (&row["col_0"], &row["col_1"]) {
+            (dql_ir::Value::String(user), dql_ir::Value::String(product)) => (user.clone(), product.clone()),
+            other => panic!("Expected user/product names, got {:?}", other),
+        })
+        .collect();
+    pairs.sort();
+
+    // Alice's two purchases give two rows; Carol bought nothing and has none
+    assert_eq!(
+        pairs,
+        [
+            ("Alice".to_string(), "Laptop".to_string()),
+            ("Alice".to_string(), "Mouse".to_string()),
+            ("Bob".to_string(), "Desk".to_string()),
+        ]
+    );
+
+    // Fields from different bindings combine within one row
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:PURCHASED]-> p WHERE u.name = 'Alice' SELECT p.name, p.price + u.credit ORDER BY p.price DESC")
+        .unwrap();
+    assert_eq!(res.row_count(), 2);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Laptop".to_string()));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(1010));
+    assert_eq!(res.rows[1]["col_1"], dql_ir::Value::Integer(35));
+}
+
+#[test]
+fn test_index_lookup_reads_fewer_entities_than_scan() {
+    let graph = setup_aged_users_graph(10_000);
+    let executor = DQLExecutor::new(graph.clone());
+    let reads = || graph.read().unwrap().entity_reads();
+
+    let before = reads();
+    let scanned = executor.execute("FROM Users WHERE age = 42 SELECT name").unwrap();
+    let scan_reads = reads() - before;
+    assert_eq!(scanned.row_count(), 100);
+
+    // Created after the data exists, so it must be backfilled
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+
+    let before = reads();
+    let indexed = executor.execute("FROM Users WHERE age = 42 SELECT name").unwrap();
+    let index_reads = reads() - before;
+    assert_eq!(indexed.row_count(), 100);
+
+    assert!(scan_reads >= 10_000, "Scan read {} entities", scan_reads);
+    assert!(index_reads <= 100, "Index lookup read {} entities", index_reads);
+
+    // Range predicates combine into one probe; exclusive bounds are re-checked
+    let before = reads();
+    let ranged = executor.execute("FROM Users WHERE age >= 10 AND age < 12 SELECT name").unwrap();
+    assert_eq!(ranged.row_count(), 200);
+    assert!(reads() - before <= 300, "Range scan read {} entities", reads() - before);
+
+    let flipped = executor.execute("FROM Users WHERE 98 < age SELECT name").unwrap();
+    assert_eq!(flipped.row_count(), 100);
+}
+
+#[test]
+fn test_index_lookup_sees_writes() {
+    let executor = DQLExecutor::new(setup_aged_users_graph(1_000));
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Newcomer', age: 7})").unwrap();
+    assert_eq!(count("FROM Users WHERE age = 7 SELECT name"), 11);
+
+    executor.execute("UPDATE Users SET age = 500 WHERE name = 'Newcomer'").unwrap();
+    assert_eq!(count("FROM Users WHERE age = 7 SELECT name"), 10);
+    assert_eq!(count("FROM Users WHERE age = 500 SELECT name"), 1);
+
+    executor.execute("DELETE FROM Users WHERE age = 500").unwrap();
+    assert_eq!(count("FROM Users WHERE age = 500 SELECT name"), 0);
+    assert_eq!(count("FROM Users WHERE age > 98 SELECT name"), 10);
+}
+
+#[test]
+fn test_having_resolves_each_aggregate() {
+    let executor = DQLExecutor::new(setup_city_users_graph());
+
+    let res = executor
+        .execute("FROM Users SELECT city, COUNT(*), AVG(age) GROUP BY city HAVING COUNT(*) > 5 AND AVG(age) < 40")
+        .unwrap();
+
+    // SF has enough users but is too old on average; LA is too small
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("NYC".to_string()));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(6));
+    assert_eq!(res.rows[0]["col_2"], dql_ir::Value::Float(37.5));
+}
+
+#[test]
+fn test_having_on_unselected_aggregate() {
+    let executor = DQLExecutor::new(setup_city_users_graph());
+
+    let res = executor
+        .execute("FROM Users SELECT city GROUP BY city HAVING MIN(age) >= 40")
+        .unwrap();
+
+    assert_eq!(group_names(&res), ["SF"]);
+    assert_eq!(res.rows[0].len(), 1, "Hidden HAVING column leaked: {:?}", res.rows[0]);
+}
+
+#[test]
+fn test_having_mixes_group_key_and_aggregate() {
+    let executor = DQLExecutor::new(setup_city_users_graph());
+
+    let res = executor
+        .execute("FROM Users SELECT city, SUM(age) GROUP BY city HAVING city = 'LA' OR SUM(age) > 300")
+        .unwrap();
+    assert_eq!(group_names(&res), ["LA", "SF"]);
+
+    let res = executor
+        .execute("FROM Users SELECT city GROUP BY city HAVING city != 'LA' AND COUNT(*) <= 6")
+        .unwrap();
+    assert_eq!(group_names(&res), ["NYC"]);
+}
+
+#[test]
+fn test_count_distinct_ignores_duplicates_and_nulls() {
+    let executor = DQLExecutor::new(setup_logins_graph());
+
+    let res = executor
+        .execute("FROM Logins SELECT COUNT(*), COUNT(device), COUNT(DISTINCT device), COUNT(DISTINCT city)")
+        .unwrap();
+
+    // One row for the whole collection; the login without a device only counts for COUNT(*)
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Integer(7));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(6));
+    assert_eq!(res.rows[0]["col_2"], dql_ir::Value::Integer(3));
+    assert_eq!(res.rows[0]["col_3"], dql_ir::Value::Integer(2));
+
+    // 1 and 1.0 are the same value
+    let res = executor
+        .execute("FROM Logins SELECT SUM(attempts), SUM(DISTINCT attempts), AVG(DISTINCT attempts)")
+        .unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Float(13.0));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(6));
+    assert_eq!(res.rows[0]["col_2"], dql_ir::Value::Float(2.0));
+
+    let res = executor.execute("FROM Nobody SELECT COUNT(DISTINCT device)").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Integer(0));
+}
+
+#[test]
+fn test_count_distinct_per_group() {
+    let executor = DQLExecutor::new(setup_logins_graph());
+
+    let res = executor
+        .execute("FROM Logins SELECT city, COUNT(*), COUNT(DISTINCT device) GROUP BY city HAVING COUNT(DISTINCT device) > 1")
+        .unwrap();
+
+    assert_eq!(group_names(&res), ["Paris"]);
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(4));
+    assert_eq!(res.rows[0]["col_2"], dql_ir::Value::Integer(2));
+}
+
+/// A "Readings" collection holding each set of properties as an entity
+fn readings(rows: Vec<Vec<(&str, PropertyValue)>>) -> DQLExecutor {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        for row in rows {
+            let props = row.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+            g.add_entity("Readings".to_string(), props).unwrap();
+        }
+    }
+    DQLExecutor::new(graph)
+}
+
+/// Each row's group columns followed by its COUNT(*)
+fn group_counts(res: &QueryResult, keys: usize) -> Vec<(Vec<dql_ir::Value>, dql_ir::Value)> {
+    res.rows
+        .iter()
+        .map(|row| {
+            let key = (0..keys).map(|i| row[&format!("col_{}", i)].clone()).collect();
+            (key, row[&format!("col_{}", keys)].clone())
+        })
+        .collect()
+}
+
+#[test]
+fn test_group_by_float_column() {
+    use dql_ir::Value;
+    let executor = readings(vec![
+        vec![("level", PropertyValue::Float(f64::NAN))],
+        vec![("level", PropertyValue::Float(1.5))],
+        vec![("level", PropertyValue::Float(2.0))],
+        vec![("level", PropertyValue::Int(2))],
+        vec![("level", PropertyValue::Float(0.1 + 0.2))],
+        vec![("level", PropertyValue::Float(0.3))],
+        vec![("level", PropertyValue::Float(-f64::NAN))],
+        vec![("level", PropertyValue::Float(1.5))],
+    ]);
+
+    let query = "FROM Readings SELECT level, COUNT(*) GROUP BY level";
+    let res = executor.execute(query).unwrap();
+    let groups = group_counts(&res, 1);
+
+    // 2 and 2.0 are one group, shown as the float it first was; 0.1 + 0.2 isn't 0.3
+    assert_eq!(groups.len(), 5);
+    assert_eq!(groups[0], (vec![Value::Float(0.3)], Value::Integer(1)));
+    assert_eq!(groups[1], (vec![Value::Float(0.1 + 0.2)], Value::Integer(1)));
+    assert_eq!(groups[2], (vec![Value::Float(1.5)], Value::Integer(2)));
+    assert_eq!(groups[3], (vec![Value::Float(2.0)], Value::Integer(2)));
+    // Every NaN lands in one group, after the numbers
+    assert!(matches!(groups[4].0[..], [Value::Float(f)] if f.is_nan()));
+    assert_eq!(groups[4].1, Value::Integer(2));
+
+    // The order doesn't depend on hashing
+    for _ in 0..5 {
+        assert_eq!(group_counts(&executor.execute(query).unwrap(), 1)[..4], groups[..4]);
+    }
+}
+
+#[test]
+fn test_group_by_nullable_column() {
+    use dql_ir::Value;
+    let executor = readings(vec![
+        vec![("note", PropertyValue::Null)],
+        vec![("note", PropertyValue::String("NULL".to_string()))],
+        vec![("note", PropertyValue::String("ok".to_string()))],
+        vec![],
+        vec![("note", PropertyValue::String("NULL".to_string()))],
+    ]);
+
+    let res = executor.execute("FROM Readings SELECT note, COUNT(*) GROUP BY note").unwrap();
+
+    // An explicit null and a missing property group together, apart from the string 'NULL', and sort last
+    assert_eq!(
+        group_counts(&res, 1),
+        [
+            (vec![Value::String("NULL".to_string())], Value::Integer(2)),
+            (vec![Value::String("ok".to_string())], Value::Integer(1)),
+            (vec![Value::Null], Value::Integer(2)),
+        ]
+    );
+}
+
+#[test]
+fn test_group_by_multiple_columns_with_booleans() {
+    use dql_ir::Value;
+    let reading = |active: bool, level: PropertyValue| vec![("active", PropertyValue::Bool(active)), ("level", level)];
+    let executor = readings(vec![
+        reading(true, PropertyValue::Int(1)),
+        reading(false, PropertyValue::Int(2)),
+        reading(true, PropertyValue::Float(1.0)),
+        reading(false, PropertyValue::Int(1)),
+        reading(true, PropertyValue::String("true".to_string())),
+        reading(true, PropertyValue::Int(1)),
+    ]);
+
+    let res = executor
+        .execute("FROM Readings SELECT active, level, COUNT(*) GROUP BY active, level")
+        .unwrap();
+
+    // Booleans stay booleans and group apart from the string 'true'
+    assert_eq!(
+        group_counts(&res, 2),
+        [
+            (vec![Value::Bool(false), Value::Integer(1)], Value::Integer(1)),
+            (vec![Value::Bool(false), Value::Integer(2)], Value::Integer(1)),
+            (vec![Value::Bool(true), Value::Integer(1)], Value::Integer(3)),
+            (vec![Value::Bool(true), Value::String("true".to_string())], Value::Integer(1)),
+        ]
+    );
+}
+
+#[test]
+fn test_sum_of_integers_near_i64_max_is_exact() {
+    use dql_ir::Value;
+    let executor = readings(vec![
+        vec![("n", PropertyValue::Int(i64::MAX)), ("batch", PropertyValue::Int(1))],
+        vec![("n", PropertyValue::Int(i64::MAX)), ("batch", PropertyValue::Int(1))],
+        vec![("n", PropertyValue::Int(-i64::MAX)), ("batch", PropertyValue::Int(1))],
+        vec![("n", PropertyValue::Int(i64::MAX - 1)), ("batch", PropertyValue::Int(2))],
+        vec![("n", PropertyValue::Int(1)), ("batch", PropertyValue::Int(2))],
+    ]);
+
+    // The running total passes i64::MAX on the way, but the sum fits and is exact
+    let res = executor
+        .execute("FROM Readings WHERE batch = 1 SELECT SUM(n), AVG(n)")
+        .unwrap();
+    assert_eq!(res.rows[0]["col_0"], Value::Integer(i64::MAX));
+    assert_eq!(res.rows[0]["col_1"], Value::Float(i64::MAX as f64 / 3.0));
+
+    let res = executor.execute("FROM Readings WHERE batch = 2 SELECT SUM(n)").unwrap();
+    assert_eq!(res.rows[0]["col_0"], Value::Integer(i64::MAX));
+
+    // A total that doesn't fit is an error, not a rounded float
+    let err = executor.execute("FROM Readings SELECT SUM(n)").unwrap_err();
+    assert!(err.contains("Integer overflow in SUM"), "{}", err);
+}
+
+#[test]
+fn test_sum_and_avg_over_mixed_int_and_float() {
+    use dql_ir::Value;
+    let executor = readings(vec![
+        vec![("n", PropertyValue::Int(1))],
+        vec![("n", PropertyValue::Float(2.5))],
+        vec![("n", PropertyValue::Int(3))],
+        vec![("n", PropertyValue::String("ten".to_string()))],
+    ]);
+
+    // A float makes the sum a float; the string isn't a number and is left out
+    let res = executor.execute("FROM Readings SELECT SUM(n), AVG(n)").unwrap();
+    assert_eq!(res.rows[0]["col_0"], Value::Float(6.5));
+    assert_eq!(res.rows[0]["col_1"], Value::Float(6.5 / 3.0));
+}
+
+#[test]
+fn test_aggregates_over_all_null_group() {
+    use dql_ir::Value;
+    let executor = readings(vec![
+        vec![("kind", PropertyValue::String("empty".to_string())), ("n", PropertyValue::Null)],
+        vec![("kind", PropertyValue::String("empty".to_string()))],
+        vec![("kind", PropertyValue::String("full".to_string())), ("n", PropertyValue::Int(4))],
+    ]);
+
+    let res = executor
+        .execute("FROM Readings SELECT kind, COUNT(*), COUNT(n), SUM(n), AVG(n), MIN(n), MAX(n) GROUP BY kind")
+        .unwrap();
+    assert_eq!(
+        res.row_values(0).unwrap(),
+        [
+            Value::String("empty".to_string()),
+            Value::Integer(2),
+            Value::Integer(0),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+        ]
+    );
+    assert_eq!(res.rows[1]["col_3"], Value::Integer(4));
+}
+
+#[test]
+fn test_aggregate_result_types() {
+    use dql_ir::Value;
+    let day = 86_400_000;
+    let executor = readings(vec![
+        vec![
+            ("n", PropertyValue::Int(2)),
+            ("x", PropertyValue::Float(0.5)),
+            ("name", PropertyValue::String("pear".to_string())),
+            ("at", PropertyValue::Timestamp(3 * day)),
+        ],
+        vec![
+            ("n", PropertyValue::Int(4)),
+            ("x", PropertyValue::Float(1.5)),
+            ("name", PropertyValue::String("apple".to_string())),
+            ("at", PropertyValue::Timestamp(day)),
+        ],
+    ]);
+
+    let res = executor
+        .execute("FROM Readings SELECT COUNT(n), SUM(n), AVG(n), MIN(n), MAX(n), SUM(x), MIN(x), MIN(name), MAX(name), MIN(at), MAX(at)")
+        .unwrap();
+    assert_eq!(
+        res.row_values(0).unwrap(),
+        [
+            Value::Integer(2),
+            Value::Integer(6),
+            Value::Float(3.0),
+            Value::Integer(2),
+            Value::Integer(4),
+            Value::Float(2.0),
+            Value::Float(0.5),
+            Value::String("apple".to_string()),
+            Value::String("pear".to_string()),
+            Value::Timestamp(day),
+            Value::Timestamp(3 * day),
+        ]
+    );
+}
+
+#[test]
+fn test_in_predicate() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    assert_eq!(count("FROM Users WHERE age IN (21, 23, 99) SELECT name"), 2);
+    assert_eq!(count("FROM Users WHERE city IN ('NYC', 'LA') SELECT name"), 5);
+    assert_eq!(count("FROM Users WHERE age NOT IN (21, 23) SELECT name"), 8);
+
+    // An empty list matches nothing, so its negation matches everything
+    assert_eq!(count("FROM Users WHERE age IN () SELECT name"), 0);
+    assert_eq!(count("FROM Users WHERE age NOT IN () SELECT name"), 10);
+
+    // A missing value is in no list, and not out of one either
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ageless'})").unwrap();
+    assert_eq!(count("FROM Users WHERE age IN (21, 23) SELECT name"), 2);
+    assert_eq!(count("FROM Users WHERE age NOT IN (21, 23) SELECT name"), 8);
+    assert_eq!(count("FROM Users WHERE NOT (age IN (21, 23) AND name = 'Ageless') SELECT name"), 10);
+    assert_eq!(count("FROM Users WHERE age NOT IN (21, NULL) SELECT name"), 0);
+}
+
+#[test]
+fn test_between_predicate() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    // Bounds are inclusive: ages 23, 24, 25
+    assert_eq!(count("FROM Users WHERE age BETWEEN 23 AND 25 SELECT name"), 3);
+    assert_eq!(count("FROM Users WHERE age NOT BETWEEN 23 AND 25 SELECT name"), 7);
+    assert_eq!(count("FROM Users WHERE age BETWEEN 23 AND 25 AND city = 'NYC' SELECT name"), 1);
+
+    // Reversed bounds match nothing
+    assert_eq!(count("FROM Users WHERE age BETWEEN 25 AND 23 SELECT name"), 0);
+
+    // A missing value is neither between the bounds nor outside them
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ageless'})").unwrap();
+    assert_eq!(count("FROM Users WHERE age BETWEEN 23 AND 25 SELECT name"), 3);
+    assert_eq!(count("FROM Users WHERE age NOT BETWEEN 23 AND 25 SELECT name"), 7);
+}
+
+#[test]
+fn test_like_predicate() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let names = |query: &str| {
+        let mut names: Vec<String> = executor
+            .execute(query)
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| match &row["col_0"] {
+                dql_ir::Value::String(name) => name.clone(),
+                other => panic!("Expected name, got {:?}", other),
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(names("FROM Users WHERE name LIKE 'User1%' SELECT name"), ["User1", "User10"]);
+    assert_eq!(names("FROM Users WHERE name LIKE 'User_' SELECT name").len(), 9);
+    assert_eq!(names("FROM Users WHERE name NOT LIKE '%1%' SELECT name").len(), 8);
+
+    // Case-sensitive
+    assert!(names("FROM Users WHERE name LIKE 'user%' SELECT name").is_empty());
+
+    // Non-string properties never match
+    assert!(names("FROM Users WHERE age LIKE '2%' SELECT name").is_empty());
+    assert!(names("FROM Users WHERE missing LIKE '%' SELECT name").is_empty());
+
+    // A missing value doesn't match the negation either
+    assert!(names("FROM Users WHERE missing NOT LIKE '%' SELECT name").is_empty());
+    assert_eq!(names("FROM Users WHERE age NOT LIKE '2%' SELECT name").len(), 10);
+}
+
+#[test]
+fn test_is_null_matches_missing_and_explicit_null() {
+    let executor = DQLExecutor::new(setup_contacts_graph());
+
+    assert_eq!(contact_names(&executor, "FROM Contacts WHERE email IS NULL SELECT name"), ["Bob", "Carol"]);
+    assert_eq!(contact_names(&executor, "FROM Contacts WHERE email IS NOT NULL SELECT name"), ["Alice", "Dave"]);
+    assert_eq!(
+        contact_names(&executor, "FROM Contacts WHERE email IS NOT NULL AND age > 30 SELECT name"),
+        ["Dave"]
+    );
+}
+
+#[test]
+fn test_comparisons_with_null_are_false() {
+    let executor = DQLExecutor::new(setup_contacts_graph());
+
+    // `= NULL` never matches; IS NULL is the way to find absent values
+    assert!(contact_names(&executor, "FROM Contacts WHERE email = NULL SELECT name").is_empty());
+    assert!(contact_names(&executor, "FROM Contacts WHERE age > NULL SELECT name").is_empty());
+
+    // Contacts without an age satisfy neither side of a comparison
+    assert_eq!(contact_names(&executor, "FROM Contacts WHERE age < 30 SELECT name"), ["Alice", "Bob"]);
+    assert_eq!(contact_names(&executor, "FROM Contacts WHERE age != 25 SELECT name"), ["Dave"]);
+}
+
+#[test]
+fn test_aggregate_in_where_is_an_error() {
+    let executor = DQLExecutor::new(setup_test_graph());
+
+    let err = executor.execute("FROM Users WHERE COUNT(*) > 3 SELECT name").unwrap_err();
+    assert!(err.contains("Aggregate"), "Unexpected error: {}", err);
+
+    // Arithmetic isn't a condition on its own
+    let err = executor.execute("FROM Users WHERE age + 1 SELECT name").unwrap_err();
+    assert!(err.starts_with("Unsupported predicate in WHERE"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_arithmetic_in_conditions() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    // Ages are 21..=30
+    assert_eq!(count("FROM Users WHERE age - 20 > 8 SELECT name"), 2);
+    assert_eq!(count("FROM Users WHERE age * 2 = 50 SELECT name"), 1);
+    assert_eq!(count("FROM Users WHERE age / 2.0 <= 11 SELECT name"), 2);
+
+    assert_eq!(executor.execute("FROM Users WHERE age / 0 > 1 SELECT name").unwrap_err(), "Division by zero");
+}
+
+#[test]
+fn test_result_columns_follow_select_order() {
+    let executor = DQLExecutor::new(setup_products_graph());
+
+    let res = executor
+        .execute("FROM Products SELECT sku, name AS product, price, rating ORDER BY category, name")
+        .unwrap();
+
+    // The hidden ORDER BY column isn't reported
+    let names: Vec<&str> = res.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["col_0", "product", "col_2", "col_3"]);
+
+    let types: Vec<dql_ir::ValueType> = res.columns.iter().map(|c| c.value_type).collect();
+    assert_eq!(
+        types,
+        [
+            dql_ir::ValueType::Mixed,
+            dql_ir::ValueType::String,
+            dql_ir::ValueType::Float,
+            dql_ir::ValueType::Integer,
+        ]
+    );
+
+    // Apple is the first food product by name
+    assert_eq!(res.column_index("product"), Some(1));
+    assert_eq!(res.get(0, "product"), Some(&dql_ir::Value::String("Apple".to_string())));
+    assert_eq!(
+        res.row_values(0).unwrap(),
+        [
+            dql_ir::Value::Integer(100),
+            dql_ir::Value::String("Apple".to_string()),
+            dql_ir::Value::Float(1.5),
+            dql_ir::Value::Integer(4),
+        ]
+    );
+
+    let inserted = executor.execute("INSERT INTO Products VALUES ({name: \"Stool\"})").unwrap();
+    assert_eq!(inserted.columns[0].name, "id");
+    assert_eq!(inserted.columns[0].value_type, dql_ir::ValueType::EntityId);
+}
+
+#[test]
+fn test_duplicate_column_names_are_rejected() {
+    let executor = DQLExecutor::new(setup_products_graph());
+
+    let err = executor.execute("FROM Products SELECT name AS n, price AS n").unwrap_err();
+    assert!(err.contains("Duplicate column name"), "Unexpected error: {}", err);
+
+    // An explicit alias may not shadow a generated one either
+    let err = executor.execute("FROM Products SELECT name, price AS col_0").unwrap_err();
+    assert!(err.contains("'col_0'"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_aggregate_aliases_in_result_columns() {
+    let executor = DQLExecutor::new(setup_city_users_graph());
+
+    let res = executor
+        .execute("FROM Users SELECT city AS name, COUNT(*) AS users, AVG(age) AS avg_age GROUP BY city HAVING MAX(age) < 30")
+        .unwrap();
+
+    let columns: Vec<(&str, dql_ir::ValueType)> = res.columns.iter().map(|c| (c.name.as_str(), c.value_type)).collect();
+    assert_eq!(
+        columns,
+        [
+            ("name", dql_ir::ValueType::String),
+            ("users", dql_ir::ValueType::Integer),
+            ("avg_age", dql_ir::ValueType::Float),
+        ]
+    );
+
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.get(0, "name"), Some(&dql_ir::Value::String("LA".to_string())));
+    assert_eq!(res.get(0, "users"), Some(&dql_ir::Value::Integer(3)));
+}
+
+#[test]
+fn test_params_share_one_cached_plan() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let query = "FROM Users WHERE age > $min_age SELECT name";
+    let params = |age: i64| std::collections::HashMap::from([("min_age".to_string(), dql_ir::Value::Integer(age))]);
+
+    // Ages are 21..=30
+    assert_eq!(executor.execute_with_params(query, &params(28)).unwrap().row_count(), 2);
+    assert_eq!(executor.execute_with_params(query, &params(25)).unwrap().row_count(), 5);
+
+    let stats = executor.cache_stats();
+    assert_eq!(stats.size, 1);
+    assert_eq!(stats.total_hits, 1);
+
+    // A prepared query keeps its plan across parameter sets
+    let prepared = executor.prepare("FROM Users WHERE age = ?1 SELECT name").unwrap();
+    for age in [21, 30] {
+        let params = std::collections::HashMap::from([("1".to_string(), dql_ir::Value::Integer(age))]);
+        let res = executor.execute_prepared(&prepared, &params).unwrap();
+        assert_eq!(res.row_count(), 1);
+        assert_eq!(res.get(0, "col_0"), Some(&dql_ir::Value::String(format!("User{}", age - 20))));
+    }
+    assert_eq!(executor.cache_stats().size, 2);
+}
+
+#[test]
+fn test_missing_parameter_is_an_error() {
+    let executor = DQLExecutor::new(setup_test_graph());
+
+    let err = executor
+        .execute_with_params("FROM Users WHERE age > $min_age SELECT name", &std::collections::HashMap::new())
+        .unwrap_err();
+    assert_eq!(err, "Missing value for parameter 'min_age'");
+
+    // Nothing is inserted when a value is missing
+    let err = executor.execute("INSERT INTO Users VALUES ({name: $name})").unwrap_err();
+    assert_eq!(err, "Missing value for parameter 'name'");
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 10);
+}
+
+#[test]
+fn test_string_parameters_with_quotes() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let name = |value: &str| std::collections::HashMap::from([("name".to_string(), dql_ir::Value::String(value.to_string()))]);
+
+    let tricky = r#"O'Brien "Bob" \"#;
+    executor
+        .execute_with_params("INSERT INTO Users VALUES ({name: $name, age: 99})", &name(tricky))
+        .unwrap();
+
+    let res = executor
+        .execute_with_params("FROM Users WHERE name = $name SELECT name, age", &name(tricky))
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.get(0, "col_0"), Some(&dql_ir::Value::String(tricky.to_string())));
+
+    // A value is never parsed as DQL
+    let res = executor
+        .execute_with_params("FROM Users WHERE name = $name SELECT name", &name("x' OR age > 0 OR name = 'x"))
+        .unwrap();
+    assert_eq!(res.row_count(), 0);
+}
+
+#[test]
+fn test_plan_cache_ignores_literals_and_keyword_case() {
+    let executor = DQLExecutor::new(setup_test_graph());
+
+    let res = executor.execute("FROM Users WHERE age = 25 SELECT name").unwrap();
+    assert_eq!(res.get(0, "col_0"), Some(&dql_ir::Value::String("User5".to_string())));
+
+    let res = executor.execute("from Users  where age = 26 select name").unwrap();
+    assert_eq!(res.get(0, "col_0"), Some(&dql_ir::Value::String("User6".to_string())));
+
+    let stats = executor.cache_stats();
+    assert_eq!(stats.size, 1);
+    assert_eq!(stats.total_hits, 1);
+
+    // Collection names are identifiers, so their case still matters
+    executor.execute("FROM users WHERE age = 25 SELECT name").unwrap();
+    assert_eq!(executor.cache_stats().size, 2);
+}
+
+#[test]
+fn test_create_index_invalidates_cached_plans() {
+    let executor = DQLExecutor::new(setup_test_graph());
+
+    executor.execute("FROM Users WHERE age > 28 SELECT name").unwrap();
+    executor.execute("FROM Products SELECT name").unwrap();
+    assert_eq!(executor.cache_stats().size, 2);
+
+    // Only plans over the indexed collection are dropped
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+    assert_eq!(executor.cache_stats().size, 1);
+
+    // The query is re-planned rather than served from the cache
+    assert_eq!(executor.execute("FROM Users WHERE age > 27 SELECT name").unwrap().row_count(), 3);
+    let stats = executor.cache_stats();
+    assert_eq!(stats.size, 2);
+    assert_eq!(stats.total_hits, 0);
+
+    executor.execute("DROP INDEX idx_age").unwrap();
+    assert_eq!(executor.cache_stats().size, 1);
+}
+
+#[test]
+fn test_delete_one_of_parallel_edges() {
+    let graph = setup_follows_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    let res = executor
+        .execute("DELETE EDGE FROM Users a -[:FOLLOWS {since: 2019}]-> b WHERE a.name = 'Alice' AND b.name = 'Bob'")
+        .unwrap();
+    assert_eq!(res.rows_affected, 1);
+
+    // The other Alice -> Bob edge survives, on both endpoints
+    let g = graph.read().unwrap();
+    let edges = g.get_all_edges();
+    assert_eq!(edges.len(), 2);
+    assert!(edges.iter().all(|e| e.properties.get("since") != Some(&PropertyValue::Int(2019))));
+    let bob = edges.iter().find(|e| e.properties.get("since") == Some(&PropertyValue::Int(2020))).unwrap();
+    assert_eq!(g.get_incoming_neighbors(bob.target, Some("FOLLOWS")), vec![(bob.source, bob.id)]);
+    assert_eq!(g.get_outgoing_neighbors(bob.source, Some("FOLLOWS")).len(), 2);
+    drop(g);
+
+    let traverse = "FROM Users u TRAVERSE -[:FOLLOWS {since: 2019}]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert!(traversed_names(&executor, traverse).is_empty());
+    assert_eq!(
+        traversed_names(&executor, "FROM Users u TRAVERSE <-[:FOLLOWS]- f WHERE u.name = 'Bob' SELECT f.name"),
+        ["Alice"]
+    );
+}
+
+#[test]
+fn test_update_edge_then_filter_traversal() {
+    let executor = DQLExecutor::new(setup_follows_graph());
+
+    let res = executor
+        .execute("UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET close = true, since = since + 1 WHERE b.name = 'Carol'")
+        .unwrap();
+    assert_eq!(res.rows_affected, 1);
+
+    let close = "FROM Users u TRAVERSE -[:FOLLOWS {close: true}]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, close), ["Carol"]);
+
+    let since = "FROM Users u TRAVERSE -[:FOLLOWS {since: 2022}]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, since), ["Carol"]);
+}
+
+#[test]
+fn test_filter_and_order_by_degree() {
+    use dql_ir::Value;
+    let graph = setup_degree_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    assert_eq!(
+        traversed_names(&executor, "FROM Users u WHERE OUT_DEGREE(u, 'FOLLOWS') > 1 SELECT u.name"),
+        ["Alice"]
+    );
+    assert_eq!(
+        traversed_names(&executor, "FROM Users u WHERE in_degree(u, 'FOLLOWS') = 1 SELECT u.name"),
+        ["Bob", "Dave"]
+    );
+
+    // Ordered by a degree that isn't selected, then by name
+    let res = executor
+        .execute("FROM Users u SELECT u.name ORDER BY IN_DEGREE(u, 'FOLLOWS') DESC, u.name LIMIT 3")
+        .unwrap();
+    let names: Vec<Value> = (0..res.row_count()).map(|i| res.row_values(i).unwrap()[0].clone()).collect();
+    assert_eq!(names, ["Alice", "Carol", "Bob"].map(|name| Value::String(name.to_string())));
+
+    // Without a type every edge counts; DEGREE adds both directions
+    let res = executor
+        .execute("FROM Users u WHERE u.name = 'Alice' SELECT OUT_DEGREE(u), IN_DEGREE(u), DEGREE(u), DEGREE(u, 'LIKES')")
+        .unwrap();
+    assert_eq!(
+        res.row_values(0).unwrap(),
+        [Value::Integer(4), Value::Integer(2), Value::Integer(6), Value::Integer(1)]
+    );
+
+    // Counting reads the adjacency lists: no neighbor is copied, only the matching users
+    let clones = || graph.read().unwrap().entity_clones();
+    let before = clones();
+    let res = executor
+        .execute("FROM Users u WHERE u.name = 'Bob' AND OUT_DEGREE(u, 'FOLLOWS') = 1 SELECT u.name")
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert!(clones() - before <= 1, "cloned {} entities", clones() - before);
+
+    // EXPLAIN shows the call rather than the pseudo-property behind it
+    let plan = executor.execute("EXPLAIN FROM Users u WHERE OUT_DEGREE(u, 'FOLLOWS') > 1 SELECT u.name").unwrap();
+    let text = format!("{:?}", plan.rows);
+    assert!(text.contains("OUT_DEGREE(u, 'FOLLOWS')"), "{}", text);
+}
+
+#[test]
+fn test_degree_tracks_edge_changes_and_zero_edges() {
+    use dql_ir::Value;
+    let graph = setup_degree_graph();
+    let executor = DQLExecutor::new(graph.clone());
+    let degrees = |name: &str| {
+        let query = format!(
+            "FROM Users u WHERE u.name = '{}' SELECT OUT_DEGREE(u, 'FOLLOWS'), IN_DEGREE(u, 'FOLLOWS')",
+            name
+        );
+        executor.execute(&query).unwrap().row_values(0).unwrap()
+    };
+
+    // An entity without edges has degree 0, not NULL
+    let frank = {
+        let mut props = std::collections::HashMap::new();
+        props.insert("name".to_string(), PropertyValue::String("Frank".to_string()));
+        graph.read().unwrap().add_entity("Users".to_string(), props).unwrap()
+    };
+    assert_eq!(degrees("Frank"), [Value::Integer(0), Value::Integer(0)]);
+    assert_eq!(degrees("Erin"), [Value::Integer(0), Value::Integer(0)]);
+
+    {
+        let g = graph.read().unwrap();
+        let carol = g
+            .scan_collection("Users")
+            .into_iter()
+            .find(|user| user.get_property("name") == Some(&PropertyValue::String("Carol".to_string())))
+            .unwrap();
+        g.add_edge(frank, carol.id, "FOLLOWS".to_string(), std::collections::HashMap::new()).unwrap();
+    }
+    assert_eq!(degrees("Frank"), [Value::Integer(1), Value::Integer(0)]);
+    assert_eq!(degrees("Carol"), [Value::Integer(1), Value::Integer(3)]);
+
+    executor.execute("DELETE EDGE FROM Users a -[:FOLLOWS]-> b WHERE b.name = 'Carol'").unwrap();
+    assert_eq!(degrees("Carol"), [Value::Integer(1), Value::Integer(0)]);
+    assert_eq!(degrees("Alice"), [Value::Integer(2), Value::Integer(2)]);
+    assert_eq!(degrees("Bob"), [Value::Integer(0), Value::Integer(1)]);
+
+    // Deleting an entity takes its edges out of its neighbors' counts
+    executor.execute("DELETE FROM Users WHERE name = 'Dave'").unwrap();
+    assert_eq!(degrees("Alice"), [Value::Integer(1), Value::Integer(1)]);
+}
+
+#[test]
+fn test_edge_mutations_roll_back() {
+    let executor = DQLExecutor::new(setup_follows_graph());
+    let traverse = "FROM Users u TRAVERSE -[:FOLLOWS]-> f WHERE u.name = 'Alice' SELECT f.name";
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET since = 2000").unwrap();
+    executor.execute("DELETE EDGE FROM Users a -[:FOLLOWS]-> b WHERE b.name = 'Carol'").unwrap();
+    assert_eq!(traversed_names(&executor, traverse), ["Bob"]);
+    executor.execute("ROLLBACK").unwrap();
+
+    // The deleted edge is back and the updated ones have their old properties
+    assert_eq!(traversed_names(&executor, traverse), ["Bob", "Carol"]);
+    let carol = "FROM Users u TRAVERSE -[:FOLLOWS {since: 2021}]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, carol), ["Carol"]);
+    let stale = "FROM Users u TRAVERSE -[:FOLLOWS {since: 2000}]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert!(traversed_names(&executor, stale).is_empty());
+}
+
+#[test]
+fn test_uncommitted_writes_are_invisible_to_other_sessions() {
+    let (writer, reader) = setup_shared_executors(setup_test_graph());
+
+    writer.execute("BEGIN TRANSACTION").unwrap();
+    writer.execute("UPDATE Users SET age = 99 WHERE name = 'User1'").unwrap();
+    writer.execute("INSERT INTO Users VALUES ({name: 'Draft', age: 1})").unwrap();
+
+    // The writer sees its own changes, the reader does not
+    assert_eq!(user_age(&writer, "User1"), Some(99));
+    assert_eq!(user_age(&reader, "User1"), Some(21));
+    assert_eq!(user_age(&writer, "Draft"), Some(1));
+    assert_eq!(user_age(&reader, "Draft"), None);
+
+    writer.execute("COMMIT").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(99));
+    assert_eq!(user_age(&reader, "Draft"), Some(1));
+}
+
+#[test]
+fn test_rollback_discards_inserts_and_updates() {
+    let graph = setup_test_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Draft', age: 1})").unwrap();
+    executor.execute("UPDATE Users SET age = 99 WHERE name = 'User1'").unwrap();
+    executor.execute("ROLLBACK").unwrap();
+
+    assert_eq!(user_age(&executor, "Draft"), None);
+    assert_eq!(user_age(&executor, "User1"), Some(21));
+    assert_eq!(graph.read().unwrap().scan_collection("Users").len(), 10);
+}
+
+#[test]
+fn test_rollback_to_savepoint_keeps_earlier_changes() {
+    let graph = setup_test_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("UPDATE Users SET age = 50 WHERE name = 'User1'").unwrap();
+    executor.execute("SAVEPOINT before_cleanup").unwrap();
+    executor.execute("UPDATE Users SET age = 99 WHERE name = 'User1'").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'User2'").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Draft', age: 1})").unwrap();
+    executor.execute("ROLLBACK TO SAVEPOINT before_cleanup").unwrap();
+
+    // Only the changes since the savepoint are gone, and the transaction carries on
+    assert_eq!(user_age(&executor, "User1"), Some(50));
+    assert_eq!(user_age(&executor, "User2"), Some(22));
+    assert_eq!(user_age(&executor, "Draft"), None);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Kept', age: 2})").unwrap();
+    executor.execute("COMMIT").unwrap();
+
+    assert_eq!(user_age(&executor, "User1"), Some(50));
+    assert_eq!(user_age(&executor, "Kept"), Some(2));
+    assert_eq!(graph.read().unwrap().scan_collection("Users").len(), 11);
+}
+
+#[test]
+fn test_nested_savepoints_unwind_in_order() {
+    let executor = DQLExecutor::new(setup_test_graph());
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("SAVEPOINT outer").unwrap();
+    executor.execute("UPDATE Users SET age = 40 WHERE name = 'User1'").unwrap();
+    executor.execute("SAVEPOINT inner").unwrap();
+    executor.execute("UPDATE Users SET age = 41 WHERE name = 'User1'").unwrap();
+
+    executor.execute("ROLLBACK TO inner").unwrap();
+    assert_eq!(user_age(&executor, "User1"), Some(40));
+
+    // A savepoint survives being rolled back to; later ones don't
+    executor.execute("UPDATE Users SET age = 42 WHERE name = 'User1'").unwrap();
+    executor.execute("ROLLBACK TO SAVEPOINT inner").unwrap();
+    assert_eq!(user_age(&executor, "User1"), Some(40));
+    executor.execute("ROLLBACK TO SAVEPOINT outer").unwrap();
+    assert_eq!(user_age(&executor, "User1"), Some(21));
+    let err = executor.execute("ROLLBACK TO SAVEPOINT inner").unwrap_err();
+    assert_eq!(err, "Savepoint 'inner' does not exist");
+
+    // Releasing keeps the changes but forgets the savepoint
+    executor.execute("UPDATE Users SET age = 43 WHERE name = 'User1'").unwrap();
+    executor.execute("RELEASE SAVEPOINT outer").unwrap();
+    let err = executor.execute("ROLLBACK TO SAVEPOINT outer").unwrap_err();
+    assert_eq!(err, "Savepoint 'outer' does not exist");
+    executor.execute("COMMIT").unwrap();
+    assert_eq!(user_age(&executor, "User1"), Some(43));
+
+    // Savepoints end with their transaction and need one to begin with
+    for statement in ["SAVEPOINT s", "ROLLBACK TO SAVEPOINT s", "RELEASE SAVEPOINT s"] {
+        let err = executor.execute(statement).unwrap_err();
+        assert!(err.contains("can only be used inside a transaction"), "unexpected error: {}", err);
+    }
+}
+
+#[test]
+fn test_rollback_to_savepoint_restores_unique_index() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("DEFINE SCHEMA Accounts (email String UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Accounts VALUES ({email: 'ann@example.com'})").unwrap();
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("SAVEPOINT rename").unwrap();
+    executor.execute("UPDATE Accounts SET email = 'anne@example.com' WHERE email = 'ann@example.com'").unwrap();
+    executor.execute("INSERT INTO Accounts VALUES ({email: 'bob@example.com'})").unwrap();
+    executor.execute("ROLLBACK TO SAVEPOINT rename").unwrap();
+
+    // The undone values are free again and the restored one is taken
+    executor.execute("INSERT INTO Accounts VALUES ({email: 'anne@example.com'})").unwrap();
+    executor.execute("INSERT INTO Accounts VALUES ({email: 'bob@example.com'})").unwrap();
+    let err = executor.execute("INSERT INTO Accounts VALUES ({email: 'ann@example.com'})").unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation on 'email'"), "unexpected error: {}", err);
+    executor.execute("COMMIT").unwrap();
+
+    let res = executor.execute("FROM Accounts WHERE email = 'ann@example.com' SELECT email").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(executor.execute("FROM Accounts SELECT email").unwrap().row_count(), 3);
+}
+
+#[test]
+fn test_wal_recovery_skips_changes_rolled_back_to_a_savepoint() {
+    let wal_path = std::env::temp_dir().join("deed_test_savepoint_wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let executor = DQLExecutor::new_with_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    executor.execute("SAVEPOINT s").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob', age: 25})").unwrap();
+    executor.execute("UPDATE Users SET age = 31 WHERE name = 'Alice'").unwrap();
+    executor.execute("ROLLBACK TO SAVEPOINT s").unwrap();
+    executor.execute("COMMIT").unwrap();
+
+    let recovered = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    let res = recovered.execute("FROM Users SELECT name, age").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Alice".to_string()));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Integer(30));
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn test_read_committed_and_repeatable_read_snapshots() {
+    let (writer, reader) = setup_shared_executors(setup_test_graph());
+
+    reader.execute("BEGIN TRANSACTION ISOLATION LEVEL READ COMMITTED").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(21));
+    writer.execute("UPDATE Users SET age = 50 WHERE name = 'User1'").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(50));
+    reader.execute("COMMIT").unwrap();
+
+    reader.execute("BEGIN TRANSACTION ISOLATION LEVEL REPEATABLE READ").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(50));
+    writer.execute("UPDATE Users SET age = 60 WHERE name = 'User1'").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(50));
+    reader.execute("COMMIT").unwrap();
+
+    assert_eq!(user_age(&reader, "User1"), Some(60));
+}
+
+#[test]
+fn test_concurrent_writes_to_same_entity_conflict() {
+    let (first, second) = setup_shared_executors(setup_test_graph());
+
+    first.execute("BEGIN TRANSACTION").unwrap();
+    second.execute("BEGIN TRANSACTION").unwrap();
+    first.execute("UPDATE Users SET age = 70 WHERE name = 'User1'").unwrap();
+
+    let err = second.execute("UPDATE Users SET age = 80 WHERE name = 'User1'").unwrap_err();
+    assert!(err.contains("Write conflict"), "unexpected error: {}", err);
+
+    second.execute("ROLLBACK").unwrap();
+    first.execute("COMMIT").unwrap();
+    assert_eq!(user_age(&second, "User1"), Some(70));
+}
+
+#[test]
+fn test_opposite_order_updates_resolve_deadlock_with_one_abort() {
+    let graph = setup_test_graph();
+    let transactions = Arc::new(TransactionManager::new());
+    let session = || session_on(&graph, &transactions).with_lock_wait_timeout(Duration::from_secs(10));
+    let (first, second) = (session(), session());
+
+    first.execute("BEGIN TRANSACTION").unwrap();
+    second.execute("BEGIN TRANSACTION").unwrap();
+    first.execute("UPDATE Users SET age = 71 WHERE name = 'User1'").unwrap();
+    second.execute("UPDATE Users SET age = 82 WHERE name = 'User2'").unwrap();
+
+    let started = std::time::Instant::now();
+    let waiter = std::thread::spawn(move || {
+        let result = first.execute("UPDATE Users SET age = 72 WHERE name = 'User2'");
+        (first, result)
+    });
+
+    // The younger transaction gives way and is rolled back
+    let err = second.execute("UPDATE Users SET age = 81 WHERE name = 'User1'").unwrap_err();
+    assert!(DeadlockError::is_deadlock(&err), "unexpected error: {}", err);
+    assert!(second.execute("COMMIT").is_err());
+
+    let (first, result) = waiter.join().unwrap();
+    result.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    first.execute("COMMIT").unwrap();
+
+    assert_eq!(user_age(&second, "User1"), Some(71));
+    assert_eq!(user_age(&second, "User2"), Some(72));
+    let stats = transactions.stats();
+    assert_eq!((stats.deadlocks_detected, stats.deadlock_aborts), (1, 1));
+}
+
+#[test]
+fn test_lock_wait_timeout_fails_the_waiting_write() {
+    let (first, second) = setup_shared_executors(setup_test_graph());
+    let second = second.with_lock_wait_timeout(Duration::from_millis(50));
+
+    first.execute("BEGIN TRANSACTION").unwrap();
+    second.execute("BEGIN TRANSACTION").unwrap();
+    first.execute("UPDATE Users SET age = 70 WHERE name = 'User1'").unwrap();
+
+    let err = second.execute("UPDATE Users SET age = 80 WHERE name = 'User1'").unwrap_err();
+    assert!(err.starts_with("Lock wait timeout"), "unexpected error: {}", err);
+
+    // Unrelated writes don't wait
+    second.execute("UPDATE Users SET age = 80 WHERE name = 'User2'").unwrap();
+    second.execute("COMMIT").unwrap();
+    first.execute("COMMIT").unwrap();
+    assert_eq!(user_age(&first, "User1"), Some(70));
+    assert_eq!(user_age(&first, "User2"), Some(80));
+}
+
+#[test]
+fn test_serializable_write_skew_has_one_survivor() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let transactions = Arc::new(TransactionManager::new());
+    let (first, second) = (session_on(&graph, &transactions), session_on(&graph, &transactions));
+    first.execute("INSERT INTO Doctors VALUES ({name: 'Alice', on_call: true}), ({name: 'Bob', on_call: true})").unwrap();
+
+    // Each sees two doctors on call, so takes one of them off
+    let on_call = "FROM Doctors WHERE on_call = true SELECT name";
+    first.execute("BEGIN TRANSACTION ISOLATION LEVEL SERIALIZABLE").unwrap();
+    second.execute("BEGIN TRANSACTION ISOLATION LEVEL SERIALIZABLE").unwrap();
+    assert_eq!(first.execute(on_call).unwrap().row_count(), 2);
+    assert_eq!(second.execute(on_call).unwrap().row_count(), 2);
+    first.execute("UPDATE Doctors SET on_call = false WHERE name = 'Alice'").unwrap();
+    second.execute("UPDATE Doctors SET on_call = false WHERE name = 'Bob'").unwrap();
+
+    first.execute("COMMIT").unwrap();
+    let err = second.execute("COMMIT").unwrap_err();
+    assert!(SerializationError::is_serialization_failure(&err), "unexpected error: {}", err);
+
+    // The loser was rolled back, leaving someone on call
+    let res = first.execute(on_call).unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Bob".to_string()));
+    assert!(second.execute("ROLLBACK").is_err());
+    assert_eq!(transactions.stats().serialization_aborts, 1);
+}
+
+#[test]
+fn test_serializable_readers_and_repeatable_read_writers() {
+    let graph = setup_test_graph();
+    let transactions = Arc::new(TransactionManager::new());
+    let (reader, writer) = (session_on(&graph, &transactions), session_on(&graph, &transactions));
+
+    // A pure reader commits whatever was written meanwhile
+    reader.execute("BEGIN TRANSACTION ISOLATION LEVEL SERIALIZABLE").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(21));
+    writer.execute("UPDATE Users SET age = 50 WHERE name = 'User1'").unwrap();
+    assert_eq!(user_age(&reader, "User1"), Some(21));
+    reader.execute("COMMIT").unwrap();
+
+    // Under REPEATABLE READ the first committer of an entity wins
+    reader.execute("BEGIN TRANSACTION ISOLATION LEVEL REPEATABLE READ").unwrap();
+    writer.execute("UPDATE Users SET age = 60 WHERE name = 'User1'").unwrap();
+    reader.execute("UPDATE Users SET age = 70 WHERE name = 'User1'").unwrap();
+    let err = reader.execute("COMMIT").unwrap_err();
+    assert!(SerializationError::is_serialization_failure(&err), "unexpected error: {}", err);
+    assert_eq!(user_age(&reader, "User1"), Some(60));
+
+    // ...but writes to other entities commit
+    reader.execute("BEGIN TRANSACTION ISOLATION LEVEL REPEATABLE READ").unwrap();
+    writer.execute("UPDATE Users SET age = 61 WHERE name = 'User1'").unwrap();
+    reader.execute("UPDATE Users SET age = 32 WHERE name = 'User2'").unwrap();
+    reader.execute("COMMIT").unwrap();
+    assert_eq!(user_age(&reader, "User2"), Some(32));
+    assert_eq!(transactions.stats().serialization_aborts, 1);
+}
+
+#[test]
+fn test_serializable_retry_loop_converges() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let transactions = Arc::new(TransactionManager::new());
+    session_on(&graph, &transactions).execute("INSERT INTO Counters VALUES ({name: 'hits', value: 0})").unwrap();
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let session = session_on(&graph, &transactions).with_lock_wait_timeout(Duration::from_secs(10));
+            std::thread::spawn(move || {
+                for _ in 0..5 {
+                    // Read-modify-write, retried until it commits
+                    loop {
+                        session.execute("BEGIN TRANSACTION ISOLATION LEVEL SERIALIZABLE").unwrap();
+                        let res = session.execute("FROM Counters WHERE name = 'hits' SELECT value").unwrap();
+                        let dql_ir::Value::Integer(value) = res.rows[0]["col_0"] else { panic!("no counter") };
+                        let update = format!("UPDATE Counters SET value = {} WHERE name = 'hits'", value + 1);
+                        let committed = session.execute(&update).and_then(|_| session.execute("COMMIT"));
+                        match committed {
+                            Ok(_) => break,
+                            Err(e) if SerializationError::is_serialization_failure(&e) => continue,
+                            Err(e) => panic!("unexpected error: {}", e),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    let res = session_on(&graph, &transactions).execute("FROM Counters SELECT value").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Integer(20));
+}
+
+#[test]
+fn test_schema_rejects_invalid_inserts_and_updates() {
+    let graph = setup_test_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor
+        .execute("DEFINE SCHEMA Users (name String NOT NULL, age Integer CHECK(age < 150), city String)")
+        .unwrap();
+
+    let err = executor.execute("INSERT INTO Users VALUES ({age: 5})").unwrap_err();
+    assert_eq!(err, "Schema violation: Field 'name' is required");
+
+    let err = executor.execute("INSERT INTO Users VALUES ({name: 42})").unwrap_err();
+    assert!(err.contains("Type mismatch for 'name'"), "unexpected error: {}", err);
+
+    let err = executor.execute("INSERT INTO Users VALUES ({name: 'Old', age: 200})").unwrap_err();
+    assert!(err.contains("CHECK constraint failed for 'age'"), "unexpected error: {}", err);
+
+    // The failed UPDATE is rolled back with its auto-commit transaction
+    let err = executor.execute("UPDATE Users SET age = age * 10 WHERE name = 'User9'").unwrap_err();
+    assert!(err.contains("CHECK constraint failed"), "unexpected error: {}", err);
+    assert_eq!(user_age(&executor, "User9"), Some(29));
+
+    assert_eq!(graph.read().unwrap().scan_collection("Users").len(), 10);
+}
+
+#[test]
+fn test_schema_defaults_are_stored() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor
+        .execute("DEFINE SCHEMA Products (name String NOT NULL, stock Integer DEFAULT 0, tag String DEFAULT 'new')")
+        .unwrap();
+    executor.execute("INSERT INTO Products VALUES ({name: 'Lamp'})").unwrap();
+    executor.execute("INSERT INTO Products VALUES ({name: 'Desk', stock: 3})").unwrap();
+
+    let g = graph.read().unwrap();
+    let lamp = g
+        .scan_collection("Products")
+        .into_iter()
+        .find(|p| p.get_property("name") == Some(&PropertyValue::String("Lamp".to_string())))
+        .unwrap();
+    assert_eq!(lamp.get_property("stock"), Some(&PropertyValue::Int(0)));
+    assert_eq!(lamp.get_property("tag"), Some(&PropertyValue::String("new".to_string())));
+    drop(g);
+
+    let res = executor.execute("FROM Products WHERE stock = 0 SELECT name").unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Lamp".to_string()));
+}
+
+#[test]
+fn test_schema_extra_properties() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+
+    executor.execute("DEFINE SCHEMA Users (name String)").unwrap();
+    let err = executor.execute("INSERT INTO Users VALUES ({name: 'Ann', nickname: 'A'})").unwrap_err();
+    assert!(err.contains("Unknown field 'nickname'"), "unexpected error: {}", err);
+
+    executor.execute("DEFINE SCHEMA Users (name String) ALLOW EXTRA").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', nickname: 'A'})").unwrap();
+
+    // Without a schema anything goes again
+    executor.execute("DROP SCHEMA Users").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 7})").unwrap();
+    assert_eq!(executor.execute("DROP SCHEMA Users").unwrap_err(), "No schema defined for 'Users'");
+}
+
+#[test]
+fn test_schema_unique_rejects_duplicate_inserts() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+
+    executor.execute("DEFINE SCHEMA Users (name String, email String UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'})").unwrap();
+
+    let err = executor
+        .execute("INSERT INTO Users VALUES ({name: 'Annie', email: 'ann@example.com'})")
+        .unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation on 'email'"), "unexpected error: {}", err);
+    assert!(err.contains("'ann@example.com'"), "unexpected error: {}", err);
+
+    // The rejected row left nothing behind
+    let result = executor.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(result.rows.len(), 1);
+
+    // Rows without the field don't collide
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Cat'})").unwrap();
+}
+
+#[test]
+fn test_schema_unique_rejects_duplicate_updates() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+
+    executor.execute("DEFINE SCHEMA Users (name String, email String UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob', email: 'bob@example.com'})").unwrap();
+
+    let err = executor
+        .execute("UPDATE Users SET email = 'ann@example.com' WHERE name = 'Bob'")
+        .unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation on 'email'"), "unexpected error: {}", err);
+
+    // Bob keeps his address and can still claim a fresh one
+    let result = executor.execute("FROM Users WHERE email = 'bob@example.com' SELECT name").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    executor.execute("UPDATE Users SET email = 'robert@example.com' WHERE name = 'Bob'").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bobby', email: 'bob@example.com'})").unwrap();
+}
+
+#[test]
+fn test_schema_unique_value_reusable_after_delete_and_rollback() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+
+    executor.execute("DEFINE SCHEMA Users (name String, email String UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'})").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Ann'").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Annie', email: 'ann@example.com'})").unwrap();
+
+    // A rolled-back insert gives its value back
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Cat', email: 'cat@example.com'})").unwrap();
+    executor.execute("ROLLBACK").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Cathy', email: 'cat@example.com'})").unwrap();
+
+    // Existing duplicates keep a UNIQUE schema from being defined
+    executor.execute("DEFINE SCHEMA Pets (name String)").unwrap();
+    executor.execute("INSERT INTO Pets VALUES ({name: 'Rex'})").unwrap();
+    executor.execute("INSERT INTO Pets VALUES ({name: 'Rex'})").unwrap();
+    let err = executor.execute("DEFINE SCHEMA Pets (name String UNIQUE)").unwrap_err();
+    assert!(err.contains("Can't enforce UNIQUE on 'name'"), "unexpected error: {}", err);
+    executor.execute("INSERT INTO Pets VALUES ({name: 'Rex'})").unwrap();
+}
+
+#[test]
+fn test_failed_schema_redefinition_keeps_previous_indexes() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("DEFINE SCHEMA Users (name String, email String UNIQUE)").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann2@example.com'})").unwrap();
+
+    let err = executor.execute("DEFINE SCHEMA Users (name String UNIQUE, email String UNIQUE)").unwrap_err();
+    assert!(err.contains("Can't enforce UNIQUE on 'name'"), "unexpected error: {}", err);
+    assert_eq!(executor.index_manager().list_indexes(), vec!["schema:Users.email".to_string()]);
+    let err = executor
+        .execute("INSERT INTO Users VALUES ({name: 'Bob', email: 'ann@example.com'})")
+        .unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation"), "unexpected error: {}", err);
+
+    // A redefinition that succeeds swaps the indexes over
+    executor.execute("DEFINE SCHEMA Users (name String INDEX, email String)").unwrap();
+    assert_eq!(executor.index_manager().list_indexes(), vec!["schema:Users.name".to_string()]);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob', email: 'ann@example.com'})").unwrap();
+}
+
+#[test]
+fn test_schema_unique_concurrent_inserts() {
+    let (first, second) = setup_shared_executors(Arc::new(RwLock::new(Graph::new())));
+
+    first.execute("DEFINE SCHEMA Users (name String, email String UNIQUE)").unwrap();
+
+    for round in 0..20 {
+        let barrier = std::sync::Barrier::new(2);
+        let query = format!("INSERT INTO Users VALUES ({{name: 'u', email: 'user{}@example.com'}})", round);
+        let (a, b) = std::thread::scope(|s| {
+            let a = s.spawn(|| {
+                barrier.wait();
+                first.execute(&query)
+            });
+            let b = s.spawn(|| {
+                barrier.wait();
+                second.execute(&query)
+            });
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        assert!(a.is_ok() != b.is_ok(), "round {}: expected exactly one insert to win", round);
+    }
+
+    let result = first.execute("FROM Users SELECT email").unwrap();
+    assert_eq!(result.rows.len(), 20);
+}
+
+#[test]
+fn test_pooled_connections_share_schemas() {
+    let pool = ConnectionPool::new(
+        Arc::new(RwLock::new(Graph::new())),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        PoolConfig {
+            min_size: 2,
+            maintenance_interval: None,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut first = pool.get_connection().unwrap();
+    let mut second = pool.get_connection().unwrap();
+    first.execute("DEFINE SCHEMA Users (name String NOT NULL, email String UNIQUE)").unwrap();
+
+    let err = second.execute("INSERT INTO Users VALUES ({email: 'ann@example.com'})").unwrap_err();
+    assert!(err.contains("name"), "unexpected error: {}", err);
+    second.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'})").unwrap();
+    let err = first.execute("INSERT INTO Users VALUES ({name: 'Bo', email: 'ann@example.com'})").unwrap_err();
+    assert!(err.contains("UNIQUE"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_show_collections_counts_entities() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    assert_eq!(executor.execute("SHOW COLLECTIONS").unwrap().row_count(), 0);
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'}), ({name: 'Bob'}), ({name: 'Carol'})").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10}), ({total: 20})").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+
+    let res = executor.execute("SHOW COLLECTIONS").unwrap();
+    let names: Vec<&str> = res.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["collection", "entities"]);
+    let listed: Vec<(dql_ir::Value, dql_ir::Value)> =
+        res.rows.iter().map(|row| (row["collection"].clone(), row["entities"].clone())).collect();
+    assert_eq!(
+        listed,
+        vec![
+            (dql_ir::Value::String("Orders".to_string()), dql_ir::Value::Integer(2)),
+            (dql_ir::Value::String("Users".to_string()), dql_ir::Value::Integer(2)),
+        ]
+    );
+}
+
+#[test]
+fn test_catalog_collections_describe_indexes_schemas_and_transactions() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({email: 'a@x.io', age: 30}), ({email: 'b@x.io', age: 40})").unwrap();
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+    executor.execute("DEFINE SCHEMA Products (name String NOT NULL, stock Integer DEFAULT 0)").unwrap();
+
+    let res = executor
+        .execute("FROM _indexes WHERE name = 'idx_age' SELECT collection AS collection, field AS field, unique AS unique, entries AS entries")
+        .unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["collection"], dql_ir::Value::String("Users".to_string()));
+    assert_eq!(res.rows[0]["field"], dql_ir::Value::String("age".to_string()));
+    assert_eq!(res.rows[0]["unique"], dql_ir::Value::Bool(false));
+    assert_eq!(res.rows[0]["entries"], dql_ir::Value::Integer(2));
+
+    let res = executor
+        .execute("FROM _schemas WHERE collection = 'Products' SELECT field AS field, type AS type ORDER BY field")
+        .unwrap();
+    let fields: Vec<dql_ir::Value> = res.rows.iter().map(|row| row["field"].clone()).collect();
+    assert_eq!(
+        fields,
+        vec![dql_ir::Value::String("name".to_string()), dql_ir::Value::String("stock".to_string())]
+    );
+
+    let res = executor.execute("FROM _collections WHERE name = 'Users' SELECT entity_count AS entity_count").unwrap();
+    assert_eq!(res.rows[0]["entity_count"], dql_ir::Value::Integer(2));
+
+    assert_eq!(executor.execute("FROM _transactions SELECT id AS id").unwrap().row_count(), 0);
+    executor.execute("BEGIN").unwrap();
+    let res = executor.execute("FROM _transactions SELECT state AS state").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["state"], dql_ir::Value::String("active".to_string()));
+    executor.execute("COMMIT").unwrap();
+    assert_eq!(executor.execute("FROM _transactions SELECT id AS id").unwrap().row_count(), 0);
+
+    executor.execute("DROP INDEX idx_age").unwrap();
+    assert_eq!(executor.execute("FROM _indexes SELECT name AS name").unwrap().row_count(), 0);
+}
+
+#[test]
+fn test_catalog_collections_are_read_only() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+
+    let err = executor.execute("INSERT INTO _indexes VALUES ({name: 'fake'})").unwrap_err();
+    assert!(err.contains("read-only"), "{}", err);
+    let err = executor.execute("DELETE FROM _collections WHERE name = 'Users'").unwrap_err();
+    assert!(err.contains("read-only"), "{}", err);
+    assert_eq!(executor.execute("FROM Users SELECT name AS name").unwrap().row_count(), 1);
+}
+
+#[test]
+fn test_truncate_leaves_other_collections_and_detaches_edges() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30}), ({name: 'Bob', age: 30})").unwrap();
+    executor.execute("INSERT INTO Posts VALUES ({title: 'Hello'})").unwrap();
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+
+    let g = graph.read().unwrap();
+    let users = g.collection_ids("Users");
+    let post = g.collection_ids("Posts")[0];
+    g.add_edge(users[0], post, "WROTE".to_string(), std::collections::HashMap::new()).unwrap();
+    g.add_edge(post, users[1], "MENTIONS".to_string(), std::collections::HashMap::new()).unwrap();
+    drop(g);
+
+    // Not inside an explicit transaction
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    assert_eq!(executor.execute("TRUNCATE Users").unwrap_err(), "TRUNCATE can't run inside a transaction");
+    executor.execute("ROLLBACK").unwrap();
+
+    assert_eq!(executor.execute("TRUNCATE Users").unwrap().rows_affected, 2);
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 0);
+    assert_eq!(executor.execute("FROM Posts SELECT title").unwrap().row_count(), 1);
+
+    // The post's edges to and from the users are gone too
+    let g = graph.read().unwrap();
+    assert_eq!(g.degree(post, None), 0);
+    assert_eq!(g.stats().edge_count, 0);
+    drop(g);
+
+    // The collection stays, empty, and its index is emptied rather than dropped
+    let res = executor.execute("SHOW COLLECTIONS").unwrap();
+    assert_eq!(res.rows[1]["entities"], dql_ir::Value::Integer(0));
+    assert_eq!(executor.index_manager().list_indexes(), vec!["idx_age".to_string()]);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Dave', age: 30})").unwrap();
+    assert_eq!(executor.execute("FROM Users WHERE age = 30 SELECT name").unwrap().row_count(), 1);
+}
+
+#[test]
+fn test_truncate_is_not_undone_by_wal_recovery() {
+    let wal_path = std::env::temp_dir().join("deed_test_truncate_wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let executor = DQLExecutor::new_with_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'}), ({name: 'Bob'})").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    executor.execute("TRUNCATE Users").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Carol'})").unwrap();
+
+    let recovered = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    let res = recovered.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Carol".to_string()));
+    assert_eq!(recovered.execute("FROM Orders SELECT total").unwrap().row_count(), 1);
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn test_drop_collection_removes_schema_and_indexes() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("DEFINE SCHEMA Users (name String, email String UNIQUE, age Integer)").unwrap();
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+    executor.execute("CREATE INDEX idx_total ON Orders(total)").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com', age: 30})").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+
+    assert_eq!(executor.execute("DROP COLLECTION Users").unwrap().rows_affected, 1);
+    assert_eq!(executor.index_manager().list_indexes(), vec!["idx_total".to_string()]);
+    assert_eq!(executor.execute("DROP SCHEMA Users").unwrap_err(), "No schema defined for 'Users'");
+    let res = executor.execute("SHOW COLLECTIONS").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["collection"], dql_ir::Value::String("Orders".to_string()));
+
+    // The name can be reused without the old schema or unique values
+    executor.execute("INSERT INTO Users VALUES ({name: 7, email: 'ann@example.com'})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 8, email: 'ann@example.com'})").unwrap();
+
+    assert_eq!(executor.execute("DROP COLLECTION Missing").unwrap_err(), "Collection 'Missing' not found");
+}
+
+#[test]
+fn test_truncate_and_drop_conflict_with_open_writers() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    let other = executor.new_session();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+
+    other.execute("BEGIN TRANSACTION").unwrap();
+    other.execute("UPDATE Users SET age = 31 WHERE name = 'Alice'").unwrap();
+    for statement in ["TRUNCATE Users", "DROP COLLECTION Users"] {
+        let err = executor.execute(statement).unwrap_err();
+        assert!(err.contains("conflicts with open transactions that wrote to it"), "{}", err);
+    }
+
+    // Collections the transaction didn't write to aren't held up
+    assert_eq!(executor.execute("TRUNCATE Orders").unwrap().rows_affected, 1);
+
+    other.execute("ROLLBACK").unwrap();
+    assert_eq!(executor.execute("DROP COLLECTION Users").unwrap().rows_affected, 1);
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 0);
+}
+
+#[test]
+fn test_drop_collection_is_logged_and_replicated_as_one_entry() {
+    let wal_path = std::env::temp_dir().join(format!("deed_test_drop_collection_wal_{}", std::process::id()));
+    let _ = std::fs::remove_file(&wal_path);
+    let master = Arc::new(ReplicationManager::new_master("master".to_string()));
+    let executor = DQLExecutor::new_with_wal(Arc::new(RwLock::new(Graph::new())), &wal_path)
+        .unwrap()
+        .with_replication(master.clone());
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'}), ({name: 'Bob'})").unwrap();
+    executor.execute("INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    let seq = master.current_seq();
+    executor.execute("DROP COLLECTION Users").unwrap();
+
+    let entries = master.get_entries_since(seq);
+    assert_eq!(entries.len(), 1);
+    assert!(matches!(&entries[0], ReplicationEntry::DropCollection { collection, .. } if collection == "Users"));
+
+    // A replica and a recovered node both lose the collection
+    let slave = ReplicationManager::new_slave("replica-1".to_string(), "master".to_string());
+    let replica = Graph::new();
+    for entry in master.get_entries_since(0) {
+        slave.apply_to_graph(&replica, &entry).unwrap();
+    }
+    let recovered = Arc::new(RwLock::new(Graph::new()));
+    DQLExecutor::recover_from_wal(recovered.clone(), &wal_path).unwrap();
+    for graph in [&replica, &*recovered.read().unwrap()] {
+        let collections: Vec<String> = graph.list_collections().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(collections, ["Orders"]);
+    }
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn test_filtered_scan_clones_only_matching_entities() {
+    let graph = setup_aged_users_graph(50_000);
+    let executor = DQLExecutor::new(graph.clone());
+    let clones = || graph.read().unwrap().entity_clones();
+    let reads = || graph.read().unwrap().entity_reads();
+
+    // Every entity is visited, but only the 1% that match are copied
+    let (clones_before, reads_before) = (clones(), reads());
+    let result = executor.execute("FROM Users WHERE age = 42 SELECT name").unwrap();
+    assert_eq!(result.row_count(), 500);
+    assert!(reads() - reads_before >= 50_000, "Scan read {} entities", reads() - reads_before);
+    assert!(clones() - clones_before <= 500, "Scan cloned {} entities", clones() - clones_before);
+
+    // Projection with LIMIT stops scanning once enough rows are found
+    let reads_before = reads();
+    let result = executor.execute("FROM Users WHERE age = 7 SELECT name LIMIT 3 OFFSET 2").unwrap();
+    assert_eq!(result.row_count(), 3);
+    assert!(reads() - reads_before <= 600, "Limited scan read {} entities", reads() - reads_before);
+
+    // Sorting needs every match, so it still sees the whole collection
+    let result = executor.execute("FROM Users WHERE age = 7 SELECT name AS name ORDER BY name LIMIT 1").unwrap();
+    assert_eq!(result.rows[0]["name"], dql_ir::Value::String("User10007".to_string()));
+}
+
+#[test]
+fn test_narrow_select_copies_only_referenced_properties() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    let blob = "x".repeat(200_000);
+    let rows = (0..50)
+        .map(|i| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(format!("Doc{}", i)));
+            props.insert("age".to_string(), PropertyValue::Int(i));
+            props.insert("description".to_string(), PropertyValue::String(blob.clone()));
+            props
+        })
+        .collect();
+    executor.bulk_insert("Users", rows).unwrap();
+    let bytes_cloned = || graph.read().unwrap().bytes_cloned();
+
+    // A narrow SELECT leaves the blobs in storage
+    let before = bytes_cloned();
+    let result = executor.execute("FROM Users WHERE age >= 10 SELECT name AS name ORDER BY age").unwrap();
+    assert_eq!(result.row_count(), 40);
+    assert_eq!(result.rows[0]["name"], dql_ir::Value::String("Doc10".to_string()));
+    let narrow = bytes_cloned() - before;
+    assert!(narrow < 40 * 1_000, "Narrow SELECT cloned {} bytes", narrow);
+
+    // SELECT * still copies (and returns) everything
+    let before = bytes_cloned();
+    let result = executor.execute("FROM Users WHERE age >= 10 SELECT *").unwrap();
+    assert_eq!(result.rows[0]["description"], dql_ir::Value::String(blob.clone()));
+    assert!(bytes_cloned() - before > 40 * 200_000);
+
+    // Reading the blob in a filter widens the projection to include it
+    let result = executor
+        .execute("FROM Users WHERE LENGTH(description) = 200000 AND age < 5 SELECT name")
+        .unwrap();
+    assert_eq!(result.row_count(), 5);
+
+    // Updates through a narrow scan keep the properties they don't read
+    executor.execute("UPDATE Users SET age = age + 100 WHERE name = 'Doc1'").unwrap();
+    let result = executor.execute("FROM Users WHERE name = 'Doc1' SELECT age AS age, description AS description").unwrap();
+    assert_eq!(result.rows[0]["age"], dql_ir::Value::Integer(101));
+    assert_eq!(result.rows[0]["description"], dql_ir::Value::String(blob));
+}
+
+#[test]
+fn test_parallel_filter_matches_sequential() {
+    let graph = setup_aged_users_graph(30_000);
+    let sequential = DQLExecutor::new(graph.clone());
+    let parallel = DQLExecutor::new(graph)
+        .with_parallelism(ParallelConfig { threshold: 1_000, max_threads: Some(4) })
+        .unwrap();
+
+    // Sorting on a column full of ties must order them the same either way
+    for query in [
+        "FROM Users WHERE age >= 90 SELECT name AS name, age AS age ORDER BY age",
+        "FROM Users u WHERE u.age * 3 = 111 OR u.name = 'User5' SELECT u.name",
+    ] {
+        let expected = sequential.execute(query).unwrap();
+        let actual = parallel.execute(query).unwrap();
+        assert!(expected.row_count() > 0);
+        assert_eq!(actual.rows, expected.rows, "{}", query);
+    }
+
+    // Errors raised on a worker thread still fail the query
+    assert!(parallel.execute("FROM Users WHERE age / 0 > 1 SELECT name").is_err());
+    assert!(sequential.execute("FROM Users WHERE age / 0 > 1 SELECT name").is_err());
+}
+
+#[test]
+fn test_hash_join_matches_nested_loop() {
+    let graph = setup_users_orders_graph();
+    let executor = DQLExecutor::new(graph.clone());
+
+    // Nested-loop oracle over the raw entities
+    let (users, orders) = {
+        let g = graph.read().unwrap();
+        (g.scan_collection("Users"), g.scan_collection("Orders"))
+    };
+    let oracle = |keep: &dyn Fn(i64) -> bool| {
+        let mut pairs = Vec::new();
+        for user in &users {
+            for order in &orders {
+                if user.get_property("user_id") == order.get_property("user_id") {
+                    let Some(PropertyValue::String(name)) = user.get_property("name") else { continue };
+                    let Some(PropertyValue::Int(order_no)) = order.get_property("order_no") else { continue };
+                    let Some(PropertyValue::Int(total)) = order.get_property("total") else { continue };
+                    if keep(*total) {
+                        pairs.push((name.clone(), *order_no));
+                    }
+                }
+            }
+        }
+        pairs.sort();
+        pairs
+    };
+    let pairs = |result: QueryResult| {
+        let mut pairs: Vec<(String, i64)> = result
+            .rows
+            .iter()
+            .map(|row| match (&row["name"], &row["order_no"]) {
+                (dql_ir::Value::String(name), dql_ir::Value::Integer(order_no)) => (name.clone(), *order_no),
+                other => panic!("unexpected row {:?}", other),
+            })
+            .collect();
+        pairs.sort();
+        pairs
+    };
+
+    let expected = oracle(&|_| true);
+    assert_eq!(expected.len(), 160);
+
+    // Users is the smaller side here; Orders is when the join is written the other way round
+    let result = executor
+        .execute("FROM Users u JOIN Orders o ON u.user_id = o.user_id SELECT u.name AS name, o.order_no AS order_no")
+        .unwrap();
+    assert_eq!(pairs(result), expected);
+    let result = executor
+        .execute("FROM Orders o JOIN Users u ON u.user_id = o.user_id SELECT u.name AS name, o.order_no AS order_no")
+        .unwrap();
+    assert_eq!(pairs(result), expected);
+
+    // WHERE filters joined rows and can test either side
+    let result = executor
+        .execute("FROM Users u JOIN Orders o ON o.user_id = u.user_id WHERE o.total > 50 AND u.name != 'User3' SELECT u.name AS name, o.order_no AS order_no")
+        .unwrap();
+    let filtered: Vec<_> = oracle(&|total| total > 50).into_iter().filter(|(name, _)| name != "User3").collect();
+    assert_eq!(pairs(result), filtered);
+
+    // Non-equi conditions fall back to comparing every pair
+    let result = executor
+        .execute("FROM Users u JOIN Orders o ON u.user_id = o.user_id + 0 AND o.total > 50 SELECT u.name AS name, o.order_no AS order_no")
+        .unwrap();
+    assert_eq!(pairs(result), oracle(&|total| total > 50));
+
+    // Sorting and limiting joined rows
+    let result = executor
+        .execute("FROM Users u JOIN Orders o ON u.user_id = o.user_id SELECT u.name AS name, o.order_no AS order_no ORDER BY order_no DESC LIMIT 3")
+        .unwrap();
+    let mut newest = expected.clone();
+    newest.sort_by_key(|(_, order_no)| std::cmp::Reverse(*order_no));
+    newest.truncate(3);
+    newest.sort();
+    assert_eq!(pairs(result), newest);
+}
+
+#[test]
+fn test_join_without_matches() {
+    let graph = setup_users_orders_graph();
+    let executor = DQLExecutor::new(graph);
+
+    let result = executor
+        .execute("FROM Users u JOIN Orders o ON u.user_id = o.order_no + 1000 SELECT u.name")
+        .unwrap();
+    assert_eq!(result.row_count(), 0);
+
+    // Order 0 belongs to nobody: its user_id is missing, and NULL never equals anything
+    let result = executor
+        .execute("FROM Orders o JOIN Users u ON o.user_id = u.user_id WHERE o.order_no = 0 SELECT o.order_no")
+        .unwrap();
+    assert_eq!(result.row_count(), 0);
+
+    // Aliases must be distinct
+    assert!(executor.execute("FROM Users u JOIN Orders u ON u.user_id = u.user_id SELECT u.name").is_err());
+}
+
+#[test]
+fn test_join_with_empty_side() {
+    let graph = setup_users_orders_graph();
+    let executor = DQLExecutor::new(graph);
+
+    let result = executor
+        .execute("FROM Users u JOIN Refunds r ON u.user_id = r.user_id SELECT u.name, r.amount")
+        .unwrap();
+    assert_eq!(result.row_count(), 0);
+    let names: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["col_0", "col_1"]);
+
+    let result = executor
+        .execute("FROM Refunds r JOIN Users u ON u.user_id = r.user_id SELECT u.name")
+        .unwrap();
+    assert_eq!(result.row_count(), 0);
+}
+
+#[test]
+fn test_order_by_limit_matches_full_sort() {
+    let graph = setup_aged_users_graph(1_000);
+    let executor = DQLExecutor::new(graph);
+    let query = "FROM Users SELECT name AS name, age AS age ORDER BY age DESC";
+    let full = executor.execute(query).unwrap().rows;
+    assert_eq!(full.len(), 1_000);
+
+    // Ten users share each age, so the cut at 15 falls inside a tie: the
+    // same five of the 98-year-olds come back as from the full sort
+    let top = executor.execute(&format!("{} LIMIT 15", query)).unwrap().rows;
+    assert_eq!(top, full[..15].to_vec());
+    assert_eq!(top[14]["age"], dql_ir::Value::Integer(98));
+
+    let page = executor.execute(&format!("{} LIMIT 5 OFFSET 12", query)).unwrap().rows;
+    assert_eq!(page, full[12..17].to_vec());
+
+    // Offsets past the end, LIMIT 0 and limits larger than the result
+    assert_eq!(executor.execute(&format!("{} LIMIT 5 OFFSET 2000", query)).unwrap().row_count(), 0);
+    assert_eq!(executor.execute(&format!("{} LIMIT 0", query)).unwrap().row_count(), 0);
+    assert_eq!(executor.execute(&format!("{} LIMIT 5000", query)).unwrap().rows, full);
+
+    // Hidden sort columns are still dropped
+    let top = executor.execute("FROM Users SELECT name AS name ORDER BY age, name DESC LIMIT 2").unwrap();
+    let names: Vec<&str> = top.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["name"]);
+    assert_eq!(top.rows[0]["name"], dql_ir::Value::String("User900".to_string()));
+    assert_eq!(top.rows[1]["name"], dql_ir::Value::String("User800".to_string()));
+}
+
+#[test]
+fn test_offset_and_limit_paginate() {
+    let executor = DQLExecutor::new(setup_aged_users_graph(100));
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    // Offsets at or past the end leave nothing; OFFSET 0 skips nothing
+    assert_eq!(count("FROM Users SELECT name OFFSET 150"), 0);
+    assert_eq!(count("FROM Users SELECT name OFFSET 100"), 0);
+    assert_eq!(count("FROM Users SELECT name OFFSET 99"), 1);
+    assert_eq!(count("FROM Users SELECT name OFFSET 0"), 100);
+    assert_eq!(count("FROM Users SELECT name LIMIT 10 OFFSET 95"), 5);
+
+    // Huge pages neither overflow nor panic
+    let max = i64::MAX;
+    assert_eq!(count(&format!("FROM Users SELECT name LIMIT {} OFFSET {}", max, max)), 0);
+    assert_eq!(count(&format!("FROM Users SELECT name ORDER BY age LIMIT {} OFFSET {}", max, max)), 0);
+    assert_eq!(count(&format!("FROM Users SELECT name ORDER BY age LIMIT {} OFFSET 3", max)), 97);
+
+    // Walking the pages visits every row once, in order
+    let full = executor.execute("FROM Users SELECT name AS name").unwrap().rows;
+    let mut walked = Vec::new();
+    for offset in (0..110).step_by(7) {
+        walked.extend(executor.execute(&format!("FROM Users SELECT name AS name LIMIT 7 OFFSET {}", offset)).unwrap().rows);
+    }
+    assert_eq!(walked, full);
+}
+
+#[test]
+fn test_offset_with_order_by_gives_stable_pages() {
+    let executor = DQLExecutor::new(setup_aged_users_graph(1_000));
+    let query = "FROM Users SELECT name AS name, age AS age ORDER BY age DESC";
+    let full = executor.execute(query).unwrap().rows;
+
+    // Ten users share each age, so most pages cut through ties; OFFSET
+    // applies after the sort whichever order it's written in
+    let mut walked = Vec::new();
+    for offset in (0..1_000).step_by(15) {
+        let page = executor.execute(&format!("{} OFFSET {} LIMIT 15", query, offset)).unwrap().rows;
+        assert_eq!(page, executor.execute(&format!("{} LIMIT 15 OFFSET {}", query, offset)).unwrap().rows);
+        walked.extend(page);
+    }
+    assert_eq!(walked, full);
+
+    let names: std::collections::HashSet<String> = walked.iter().map(|row| format!("{:?}", row["name"])).collect();
+    assert_eq!(names.len(), 1_000);
+}
+
+#[test]
+fn test_explain_shows_index_lookup_or_scan() {
+    let executor = DQLExecutor::new(setup_aged_users_graph(1_000));
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+
+    let plan = executor.execute("EXPLAIN FROM Users WHERE age = 42 SELECT name").unwrap();
+    let names: Vec<&str> = plan.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["step", "operation", "details", "estimated_cost", "cached", "pheromone_strength"]
+    );
+    assert_eq!(plan.rows[0]["operation"], dql_ir::Value::String("Plan".to_string()));
+    assert_eq!(plan.rows[0]["cached"], dql_ir::Value::Bool(false));
+    assert!(matches!(plan.rows[0]["pheromone_strength"], dql_ir::Value::Float(_)));
+    assert_eq!(explained_operations(&plan), vec!["IndexLookup", "Project"]);
+    let dql_ir::Value::String(details) = &plan.rows[1]["details"] else {
+        panic!("Expected operation details");
+    };
+    assert!(details.contains("idx_age") && details.contains("= 42"), "{}", details);
+
+    // Same shape with another literal reuses the cached plan
+    let plan = executor.execute("EXPLAIN FROM Users WHERE age = 7 SELECT name").unwrap();
+    assert_eq!(plan.rows[0]["cached"], dql_ir::Value::Bool(true));
+
+    let plan = executor.execute("EXPLAIN FROM Users WHERE name = 'User42' SELECT age").unwrap();
+    assert_eq!(explained_operations(&plan), vec!["Scan", "Project"]);
+
+    // Plain EXPLAIN doesn't execute the statement
+    let plan = executor.execute("EXPLAIN DELETE FROM Users WHERE age = 42").unwrap();
+    assert_eq!(explained_operations(&plan), vec!["IndexLookup", "DeleteEntities"]);
+    assert_eq!(plan.rows_affected, 0);
+    assert_eq!(executor.execute("FROM Users WHERE age = 42 SELECT name").unwrap().row_count(), 10);
+}
+
+#[test]
+fn test_explain_analyze_reports_rows_and_time() {
+    let executor = DQLExecutor::new(setup_aged_users_graph(1_000));
+    executor.execute("CREATE INDEX idx_age ON Users(age)").unwrap();
+
+    let plan = executor
+        .execute("EXPLAIN ANALYZE FROM Users WHERE age = 42 SELECT name ORDER BY name LIMIT 3")
+        .unwrap();
+    assert!(plan.column_index("rows").is_some() && plan.column_index("elapsed_ms").is_some());
+    assert_eq!(plan.rows[1]["operation"], dql_ir::Value::String("IndexLookup".to_string()));
+    assert_eq!(plan.rows[1]["rows"], dql_ir::Value::Integer(10));
+    assert_eq!(plan.rows.last().unwrap()["rows"], dql_ir::Value::Integer(3));
+    assert_eq!(plan.rows[0]["rows"], dql_ir::Value::Integer(3));
+    for row in &plan.rows {
+        assert!(matches!(row["elapsed_ms"], dql_ir::Value::Float(ms) if ms >= 0.0));
+    }
+
+    // ANALYZE runs mutations for real
+    let plan = executor.execute("EXPLAIN ANALYZE DELETE FROM Users WHERE age = 42").unwrap();
+    assert_eq!(plan.rows_affected, 10);
+    assert_eq!(plan.rows.last().unwrap()["rows"], dql_ir::Value::Integer(10));
+    assert_eq!(executor.execute("FROM Users WHERE age = 42 SELECT name").unwrap().row_count(), 0);
+}
+
+#[test]
+fn test_at_id_fetches_entities_by_id() {
+    use dql_ir::Value;
+    let metrics = Arc::new(QueryMetrics::new(Duration::from_secs(60), 0));
+    let executor = DQLExecutor::new(setup_aged_users_graph(500)).with_query_metrics(metrics.clone());
+    let id_of = |name: &str| {
+        let query = format!("FROM Users u WHERE u.name = '{}' SELECT u.@id AS id", name);
+        match executor.execute(&query).unwrap().get(0, "id") {
+            Some(Value::EntityId(id)) => *id,
+            other => panic!("Expected an entity id, got {:?}", other),
+        }
+    };
+    let (seven, forty_two) = (id_of("User7"), id_of("User42"));
+    let scanned = || metrics.snapshot().rows_scanned;
+
+    // Equality fetches the one entity instead of scanning the collection
+    let before = scanned();
+    let res = executor.execute(&format!("FROM Users WHERE @id = {} SELECT name AS name", forty_two)).unwrap();
+    assert_eq!(res.row_values(0).unwrap(), [Value::String("User42".to_string())]);
+    assert_eq!(scanned() - before, 1);
+
+    let plan = executor.execute(&format!("EXPLAIN FROM Users WHERE @id = {} SELECT name", forty_two)).unwrap();
+    assert_eq!(explained_operations(&plan), vec!["IndexLookup", "Project"]);
+
+    // IN fetches each listed id; ids no entity has match nothing
+    let before = scanned();
+    let res = executor
+        .execute(&format!(
+            "FROM Users u WHERE u.@id IN ({}, {}, 999999) SELECT u.name AS name ORDER BY name",
+            forty_two, seven
+        ))
+        .unwrap();
+    let names: Vec<Value> = (0..res.row_count()).map(|i| res.get(i, "name").unwrap().clone()).collect();
+    assert_eq!(names, [Value::String("User42".to_string()), Value::String("User7".to_string())]);
+    assert_eq!(scanned() - before, 2);
+
+    // A returned id is accepted back as a parameter
+    let params = std::collections::HashMap::from([("id".to_string(), Value::EntityId(seven))]);
+    let res = executor.execute_with_params("FROM Users WHERE @id = $id SELECT name AS name", &params).unwrap();
+    assert_eq!(res.get(0, "name"), Some(&Value::String("User7".to_string())));
+
+    // Other filters still apply to the fetched entity
+    let res = executor
+        .execute(&format!("FROM Users WHERE @id = {} AND age > 50 SELECT name", seven))
+        .unwrap();
+    assert_eq!(res.row_count(), 0);
+
+    let err = executor.execute("FROM Users u WHERE u.@name = 'User7' SELECT u.name").unwrap_err();
+    assert!(err.contains("only @id can be read"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_group_by_and_sort_spill_within_memory_budget() {
+    let metrics = Arc::new(QueryMetrics::new(Duration::from_secs(60), 0));
+    let executor = DQLExecutor::new(setup_aged_users_graph(2000)).with_query_metrics(metrics.clone());
+    let spilled = || metrics.snapshot().spilled_bytes;
+    let all_rows = |res: &QueryResult| (0..res.row_count()).map(|i| res.row_values(i).unwrap()).collect::<Vec<_>>();
+
+    // One group per user; the in-memory results are the reference
+    let grouped =
+        "FROM Users SELECT name, COUNT(*) AS n, SUM(age) AS total, MAX(age) AS oldest, COUNT(DISTINCT age) AS ages GROUP BY name";
+    let sorted = "FROM Users SELECT name AS name, age AS age ORDER BY age DESC, name";
+    let in_memory = (executor.execute(grouped).unwrap(), executor.execute(sorted).unwrap());
+    assert_eq!(in_memory.0.row_count(), 2000);
+    assert_eq!(spilled(), 0);
+
+    let res = executor.execute_with_memory_budget(grouped, 256 * 1024).unwrap();
+    assert_eq!(all_rows(&res), all_rows(&in_memory.0));
+    assert!(spilled() > 0);
+
+    let before = spilled();
+    let res = executor.execute_with_memory_budget(sorted, 16 * 1024).unwrap();
+    assert_eq!(all_rows(&res), all_rows(&in_memory.1));
+    assert!(spilled() > before);
+
+    // A streamed ORDER BY is sorted externally too, and merged back a batch at a time
+    let before = spilled();
+    executor.execute("SET memory_budget = '16KB'").unwrap();
+    let mut streamed = Vec::new();
+    for batch in executor.execute_stream(sorted, 100).unwrap() {
+        let batch = batch.unwrap();
+        assert!(batch.row_count() <= 100);
+        streamed.extend(all_rows(&batch));
+    }
+    assert_eq!(streamed, all_rows(&in_memory.1));
+    assert!(spilled() > before);
+    let top: Vec<_> = executor
+        .execute_stream(&format!("{} LIMIT 5 OFFSET 10", sorted), 100)
+        .unwrap()
+        .flat_map(|batch| all_rows(&batch.unwrap()))
+        .collect();
+    assert_eq!(top, all_rows(&in_memory.1)[10..15]);
+    executor.execute("SET memory_budget = 'off'").unwrap();
+
+    // Too small for even one spill partition's groups
+    let err = executor.execute_with_memory_budget(grouped, 8 * 1024).unwrap_err();
+    assert!(err.contains("exceeded memory budget"), "Unexpected error: {}", err);
+
+    // The session's budget applies until it's reset
+    executor.execute("SET memory_budget = '8KB'").unwrap();
+    assert!(executor.execute(grouped).is_err());
+    executor.execute("SET memory_budget = 'off'").unwrap();
+    assert_eq!(all_rows(&executor.execute(grouped).unwrap()), all_rows(&in_memory.0));
+    assert!(executor.execute("SET memory_budget = '8 parsecs'").is_err());
+}
+
+#[test]
+fn test_slow_query_times_out_or_is_cancelled() {
+    let graph = setup_aged_users_graph(4_000);
+    let executor = DQLExecutor::new(graph.clone());
+
+    // A non-equi self join compares all 16M pairs
+    let slow = "FROM Users a JOIN Users b ON a.age < b.age SELECT a.name, b.name";
+
+    let started = std::time::Instant::now();
+    let err = executor.execute_with_timeout(slow, Duration::from_millis(50)).unwrap_err();
+    assert!(err.starts_with("Query timed out"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+    let handle = executor.execute_async(slow);
+    std::thread::sleep(Duration::from_millis(50));
+    let started = std::time::Instant::now();
+    handle.cancel();
+    let err = handle.wait().unwrap_err();
+    assert_eq!(err, "Query cancelled");
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+    // Locks are released and no transaction is left open
+    assert!(graph.try_write().is_ok());
+    assert_eq!(executor.execute("FROM Users WHERE age = 7 SELECT name").unwrap().row_count(), 40);
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Late', age: 7})").unwrap();
+    executor.execute("COMMIT").unwrap();
+
+    // A fast query finishes well within its timeout
+    let res = executor
+        .execute_with_timeout("FROM Users WHERE age = 7 SELECT name", Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(res.row_count(), 41);
+}
+
+#[test]
+fn test_query_metrics_and_slow_query_log() {
+    // Log every statement, keeping the three slowest
+    let metrics = Arc::new(QueryMetrics::new(Duration::ZERO, 3));
+    let executor = DQLExecutor::new(setup_aged_users_graph(500)).with_query_metrics(metrics.clone());
+
+    for age in 0..5 {
+        executor.execute(&format!("FROM Users WHERE age = {} SELECT name", age)).unwrap();
+    }
+    let stats = metrics.snapshot();
+    assert_eq!(stats.total_queries, 5);
+    assert_eq!((stats.cache_hits, stats.cache_misses), (4, 1));
+    assert_eq!(stats.rows_scanned, 2_500);
+    assert_eq!(stats.rows_returned, 25);
+    assert_eq!(stats.auto_commits, 0);
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'New', age: 1})").unwrap();
+    executor.execute("FROM Users a JOIN Users b ON a.age < b.age SELECT a.name").unwrap();
+    assert!(executor.execute("FROM Users WHERE age + 1 SELECT name").is_err());
+
+    let stats = AdminDashboard::new().get_query_stats(&metrics);
+    assert_eq!(stats.total_queries, 8);
+    assert_eq!(stats.failed_queries, 1);
+    assert_eq!(stats.auto_commits, 1);
+    assert_eq!(stats.latency.count, 8);
+    assert_eq!(stats.latency.counts.iter().sum::<u64>(), 8);
+    assert!(stats.p50 <= stats.p95 && stats.p95 <= stats.p99);
+
+    // The log is bounded and slowest first; the cross join tops it
+    assert_eq!(stats.slow_queries.len(), 3);
+    assert!(stats.slow_queries.windows(2).all(|w| w[0].elapsed >= w[1].elapsed));
+    assert_eq!(stats.slow_queries[0].text, "FROM Users a JOIN Users b ON a.age < b.age SELECT a.name");
+    assert!(stats.slow_queries[0].plan.contains("Join"), "{}", stats.slow_queries[0].plan);
+
+    let report = AdminDashboard::new().format_query_stats(&stats);
+    assert!(report.contains("SLOW QUERIES") && report.contains("JOIN"), "{}", report);
+}
+
+#[test]
+fn test_multi_row_insert_is_all_or_nothing() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("DEFINE SCHEMA Users (name String NOT NULL, email String UNIQUE)").unwrap();
+
+    let res = executor
+        .execute("INSERT INTO Users VALUES ({name: 'Ann', email: 'ann@example.com'}), ({name: 'Bob'}), ({name: 'Cat'})")
+        .unwrap();
+    assert_eq!(res.rows_affected, 3);
+    assert_eq!(res.row_count(), 3);
+
+    // A bad row rejects the whole statement, naming the row
+    let err = executor
+        .execute("INSERT INTO Users VALUES ({name: 'Dan'}), ({email: 'eve@example.com'})")
+        .unwrap_err();
+    assert!(err.contains("Schema violation") && err.contains("(row 2)"), "unexpected error: {}", err);
+
+    let err = executor
+        .execute("INSERT INTO Users VALUES ({name: 'Dan', email: 'dan@example.com'}), ({name: 'Dee', email: 'dan@example.com'})")
+        .unwrap_err();
+    assert!(err.contains("UNIQUE constraint violation on 'email'"), "unexpected error: {}", err);
+
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 3);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Dan', email: 'dan@example.com'})").unwrap();
+}
+
+#[test]
+fn test_bulk_insert_is_faster_than_single_inserts() {
+    let rows = |start: i64, count: i64| -> Vec<std::collections::HashMap<String, PropertyValue>> {
+        (start..start + count)
+            .map(|i| {
+                let mut props = std::collections::HashMap::new();
+                props.insert("name".to_string(), PropertyValue::String(format!("user{}", i)));
+                props.insert("seq".to_string(), PropertyValue::Int(i));
+                props
+            })
+            .collect()
+    };
+
+    // Per-row cost of one statement per row
+    let single = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    single.execute("CREATE UNIQUE INDEX idx_seq ON Users(seq)").unwrap();
+    let sample = 2_000;
+    let started = std::time::Instant::now();
+    for i in 0..sample {
+        single.execute(&format!("INSERT INTO Users VALUES ({{name: 'user{}', seq: {}}})", i, i)).unwrap();
+    }
+    let per_row_single = started.elapsed() / sample as u32;
+
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("CREATE UNIQUE INDEX idx_seq ON Users(seq)").unwrap();
+    let started = std::time::Instant::now();
+    let ids = executor.bulk_insert("Users", rows(0, 50_000)).unwrap();
+    let per_row_bulk = started.elapsed() / 50_000;
+
+    assert_eq!(ids.len(), 50_000);
+    assert!(
+        per_row_bulk * 3 < per_row_single,
+        "bulk {:?}/row vs single {:?}/row",
+        per_row_bulk,
+        per_row_single
+    );
+
+    // Every row is committed, indexed and queryable
+    let res = executor.execute("FROM Users SELECT COUNT(*)").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Integer(50_000));
+    let res = executor.execute("FROM Users WHERE seq = 49999 SELECT name").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("user49999".to_string()));
+
+    // A duplicate anywhere in the batch leaves nothing behind
+    let mut batch = rows(50_000, 1_000);
+    batch[999].insert("seq".to_string(), PropertyValue::Int(7));
+    assert!(executor.bulk_insert("Users", batch).is_err());
+    let res = executor.execute("FROM Users SELECT COUNT(*)").unwrap();
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::Integer(50_000));
+    assert_eq!(executor.execute("FROM Users WHERE seq = 50000 SELECT name").unwrap().row_count(), 0);
+}
+
+#[test]
+fn test_traverse_multiple_edge_types_and_edge_alias() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let user = |name: &str| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            g.add_entity("Users".to_string(), props).unwrap()
+        };
+        let (alice, bob, carol, dave) = (user("Alice"), user("Bob"), user("Carol"), user("Dave"));
+
+        let weighted = |weight: f64| std::collections::HashMap::from([("weight".to_string(), PropertyValue::Float(weight))]);
+        g.add_edge(alice, bob, "FOLLOWS".to_string(), weighted(0.9)).unwrap();
+        g.add_edge(alice, carol, "FRIENDS".to_string(), weighted(0.3)).unwrap();
+        g.add_edge(alice, dave, "BLOCKS".to_string(), weighted(1.0)).unwrap();
+    }
+    let executor = DQLExecutor::new(graph);
+
+    // Several edge types union their targets
+    let both = "FROM Users u TRAVERSE -[:FOLLOWS|FRIENDS]-> f WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, both), ["Bob", "Carol"]);
+    assert_eq!(
+        traversed_names(&executor, "FROM Users u TRAVERSE -[:FRIENDS]-> f WHERE u.name = 'Alice' SELECT f.name"),
+        ["Carol"]
+    );
+
+    // WHERE on the edge alias excludes edges, alongside conditions on the source
+    let heavy = "FROM Users u TRAVERSE -[e:FOLLOWS|FRIENDS|BLOCKS]-> f WHERE u.name = 'Alice' AND e.weight > 0.5 AND f.name != 'Dave' SELECT f.name";
+    assert_eq!(traversed_names(&executor, heavy), ["Bob"]);
+
+    // The edge's properties can be projected and sorted on
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[e:FOLLOWS|FRIENDS]-> f WHERE u.name = 'Alice' SELECT f.name, e.weight ORDER BY e.weight DESC")
+        .unwrap();
+    assert_eq!(res.row_count(), 2);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Bob".to_string()));
+    assert_eq!(res.rows[0]["col_1"], dql_ir::Value::Float(0.9));
+    assert_eq!(res.rows[1]["col_1"], dql_ir::Value::Float(0.3));
+
+    let err = executor.execute("DELETE EDGE FROM Users u -[e:FOLLOWS]-> f").unwrap_err();
+    assert!(err.contains("only supported in TRAVERSE"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_shortest_path_between_joined_entities() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let (alice, hub, bob) = {
+        let g = graph.read().unwrap();
+        let user = |name: &str| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            g.add_entity("Users".to_string(), props).unwrap()
+        };
+        let (alice, hub, bob, carol, dave) = (user("Alice"), user("Hub"), user("Bob"), user("Carol"), user("Dave"));
+
+        // Alice -> Hub -> Bob, with the hub following many more users;
+        // Alice also reaches Carol by a longer chain, and Dave by BLOCKS only
+        g.add_edge(alice, hub, "FOLLOWS".to_string(), Default::default()).unwrap();
+        g.add_edge(hub, bob, "FOLLOWS".to_string(), Default::default()).unwrap();
+        for i in 0..300 {
+            let fan = user(&format!("Fan{}", i));
+            g.add_edge(hub, fan, "FOLLOWS".to_string(), Default::default()).unwrap();
+        }
+        g.add_edge(bob, carol, "FOLLOWS".to_string(), Default::default()).unwrap();
+        g.add_edge(alice, dave, "BLOCKS".to_string(), Default::default()).unwrap();
+        (alice, hub, bob)
+    };
+    let executor = DQLExecutor::new(graph);
+
+    let path = |to: &str, args: &str| {
+        let query = format!(
+            "FROM Users a JOIN Users b ON a.name = 'Alice' AND b.name = '{}' SELECT SHORTEST_PATH(a, b{}) AS path, SHORTEST_PATH_LENGTH(a, b{}) AS hops",
+            to, args, args
+        );
+        let res = executor.execute(&query).unwrap();
+        assert_eq!(res.row_count(), 1);
+        (res.rows[0]["path"].clone(), res.rows[0]["hops"].clone())
+    };
+
+    let (found, hops) = path("Bob", ", :FOLLOWS, MAX 6");
+    assert_eq!(found, dql_ir::Value::Path(vec![alice.as_u64(), hub.as_u64(), bob.as_u64()]));
+    assert_eq!(hops, dql_ir::Value::Integer(2));
+
+    // Beyond the depth limit, or along the wrong edge type, there is no path
+    assert_eq!(path("Carol", ", :FOLLOWS, MAX 3").1, dql_ir::Value::Integer(3));
+    assert_eq!(path("Carol", ", :FOLLOWS, MAX 2"), (dql_ir::Value::Null, dql_ir::Value::Null));
+    assert_eq!(path("Dave", ", :FOLLOWS").0, dql_ir::Value::Null);
+    assert_eq!(path("Dave", "").1, dql_ir::Value::Integer(1));
+
+    // An entity's path to itself has no hops
+    assert_eq!(path("Alice", ""), (dql_ir::Value::Path(vec![alice.as_u64()]), dql_ir::Value::Integer(0)));
+
+    // Paths come from TRAVERSE rows too
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:FOLLOWS]-> f WHERE u.name = 'Alice' SELECT f.name, SHORTEST_PATH_LENGTH(u, f) AS hops")
+        .unwrap();
+    assert_eq!(res.rows[0]["hops"], dql_ir::Value::Integer(1));
+
+    for (query, expected) in [
+        ("FROM Users SELECT SHORTEST_PATH(Users, Users)", "needs two bindings"),
+        ("FROM Users a JOIN Users b ON a.name = b.name SELECT SHORTEST_PATH(a, c)", "Unknown binding 'c'"),
+        ("FROM Users a JOIN Users b ON a.name = b.name SELECT SHORTEST_PATH_LENGTH(a, b) + 1", "only supported as a SELECT field"),
+    ] {
+        let err = executor.execute(query).unwrap_err();
+        assert!(err.contains(expected), "unexpected error for {}: {}", query, err);
+    }
+}
+
+#[test]
+fn test_traverse_top_k_by_pheromone() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let user = |name: &str| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            g.add_entity("Users".to_string(), props).unwrap()
+        };
+        let alice = user("Alice");
+
+        // Alice follows five users with seeded pheromone strengths; one
+        // strong edge is muted
+        let seeded = [("Bob", 2.0, false), ("Carol", 8.0, false), ("Dave", 0.5, false), ("Erin", 4.0, false), ("Frank", 9.0, true)];
+        for (i, (name, strength, muted)) in seeded.into_iter().enumerate() {
+            let target = user(name);
+            let props = std::collections::HashMap::from([("muted".to_string(), PropertyValue::Bool(muted))]);
+            let mut edge = Edge::new(EdgeId::new(1_000 + i as u64), alice, target, "FOLLOWS".to_string(), props);
+            edge.pheromone = deed_core::types::Pheromone::new(strength);
+            g.insert_edge_with_id(edge).unwrap();
+        }
+        assert_eq!(g.get_edge_pheromone(EdgeId::new(1_001)), Some(8.0));
+    }
+    let executor = DQLExecutor::new(graph);
+
+    // PHEROMONE(e) can be selected and ordered on
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[e:FOLLOWS]-> f WHERE u.name = 'Alice' SELECT f.name, PHEROMONE(e) AS strength ORDER BY PHEROMONE(e) DESC")
+        .unwrap();
+    let order: Vec<_> = res.rows.iter().map(|row| (row["col_0"].clone(), row["strength"].clone())).collect();
+    assert_eq!(order.len(), 5);
+    assert_eq!(order[0], (dql_ir::Value::String("Frank".to_string()), dql_ir::Value::Float(9.0)));
+    assert_eq!(order[4], (dql_ir::Value::String("Dave".to_string()), dql_ir::Value::Float(0.5)));
+
+    // TOP k follows only the strongest edges; matched edge properties are
+    // applied before the cutoff
+    let top = "FROM Users u TRAVERSE -[:FOLLOWS]-> f TOP 2 BY PHEROMONE WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, top), ["Carol", "Frank"]);
+    let unmuted = "FROM Users u TRAVERSE -[:FOLLOWS {muted: false}]-> f TOP 2 BY PHEROMONE WHERE u.name = 'Alice' SELECT f.name";
+    assert_eq!(traversed_names(&executor, unmuted), ["Carol", "Erin"]);
+
+    // ... and in WHERE
+    let strong = "FROM Users u TRAVERSE -[e:FOLLOWS]-> f WHERE u.name = 'Alice' AND PHEROMONE(e) >= 4.0 SELECT f.name";
+    assert_eq!(traversed_names(&executor, strong), ["Carol", "Erin", "Frank"]);
+
+    let plan = executor.execute(&format!("EXPLAIN {}", top)).unwrap();
+    assert!(plan.rows.iter().any(|row| matches!(row.get("details"), Some(dql_ir::Value::String(d)) if d.contains("TOP 2 BY PHEROMONE"))));
+}
+
+#[test]
+fn test_optimizer_pushes_filters_and_learns_traversal_order() {
+    let executor = DQLExecutor::new(setup_likes_and_owns_graph());
+    executor.execute("CREATE INDEX idx_user_name ON Users(name)").unwrap();
+
+    // A condition on the traversed posts runs inside the traversal, and the
+    // one on users is served by the index
+    let filtered = "FROM Users u TRAVERSE -[:LIKES]-> p WHERE u.name = 'User3' AND p.n < 5 SELECT p.name";
+    assert_eq!(executor.execute(filtered).unwrap().row_count(), 5);
+    let plan = executor.execute(&format!("EXPLAIN {}", filtered)).unwrap();
+    assert_eq!(explained_operations(&plan), vec!["IndexLookup", "Traverse", "Project"]);
+
+    // The first run learns that LIKES fans out 40x and OWNS doesn't, and
+    // the cached plan is reordered to expand OWNS first
+    let skewed = "FROM Users u TRAVERSE -[:LIKES]-> p, -[:OWNS]-> o SELECT p.name, o.name";
+    let first = executor.execute(skewed).unwrap();
+    assert_eq!(first.row_count(), 400);
+
+    let report = executor.last_optimization().unwrap();
+    assert_eq!(report.rewrites, vec!["Reordered traversals by fan-out: p, o -> o, p".to_string()]);
+    assert!(report.pheromone_strength > 0.0);
+
+    let plan = executor.execute(&format!("EXPLAIN {}", skewed)).unwrap();
+    let traversals: Vec<String> = plan
+        .rows
+        .iter()
+        .filter(|row| row.get("operation") == Some(&dql_ir::Value::String("Traverse".to_string())))
+        .filter_map(|row| match row.get("details") {
+            Some(dql_ir::Value::String(details)) => Some(details.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(traversals.len(), 2);
+    assert!(traversals[0].ends_with("AS o") && traversals[1].ends_with("AS p"), "{:?}", traversals);
+
+    // The reordered plan returns the same rows
+    let pairs = |res: &QueryResult| {
+        let mut pairs: Vec<String> = res.rows.iter().map(|row| format!("{}/{}", row["col_0"], row["col_1"])).collect();
+        pairs.sort();
+        pairs
+    };
+    assert_eq!(pairs(&executor.execute(skewed).unwrap()), pairs(&first));
+}
+
+/// Target aliases of a statement's traversals, in the order EXPLAIN shows them
+fn explained_traversals(executor: &DQLExecutor, query: &str) -> Vec<String> {
+    let plan = executor.execute(&format!("EXPLAIN {}", query)).unwrap();
+    plan.rows
+        .iter()
+        .filter(|row| row.get("operation") == Some(&dql_ir::Value::String("Traverse".to_string())))
+        .filter_map(|row| match row.get("details") {
+            Some(dql_ir::Value::String(details)) => details.rsplit("AS ").next().map(String::from),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_optimizer_learning_survives_restart_until_reset() {
+    let path = std::env::temp_dir().join(format!("deed_test_optimizer_learning_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = OptimizerConfig {
+        decay_interval: None,
+        ..OptimizerConfig::default()
+    }
+    .with_state_path(&path);
+    let graph = setup_likes_and_owns_graph();
+    let skewed = "FROM Users u TRAVERSE -[:LIKES]-> p, -[:OWNS]-> o SELECT p.name, o.name";
+
+    let executor = DQLExecutor::new(graph.clone()).with_optimizer_config(config.clone()).unwrap();
+    assert_eq!(explained_traversals(&executor, skewed), vec!["p", "o"]);
+    executor.execute(skewed).unwrap();
+    executor.shutdown(Duration::from_secs(5)).unwrap();
+
+    // Restarted, the first plan already expands OWNS first
+    let restarted = DQLExecutor::new(graph.clone()).with_optimizer_config(config.clone()).unwrap();
+    assert_eq!(explained_traversals(&restarted, skewed), vec!["o", "p"]);
+
+    // A reset forgets it, in memory and on disk
+    restarted.reset_learning().unwrap();
+    assert_eq!(restarted.cache_stats().size, 0);
+    assert_eq!(explained_traversals(&restarted, skewed), vec!["p", "o"]);
+    let clean = DQLExecutor::new(graph).with_optimizer_config(config.clone()).unwrap();
+    assert_eq!(clean.cache_stats().size, 0);
+    assert_eq!(explained_traversals(&clean, skewed), vec!["p", "o"]);
+
+    // A state file that can't be read starts the optimizer afresh
+    let _ = std::fs::remove_file(&path);
+    std::fs::create_dir(&path).unwrap();
+    let afresh = DQLExecutor::new(setup_likes_and_owns_graph()).with_optimizer_config(config).unwrap();
+    assert_eq!(afresh.cache_stats().size, 0);
+    assert_eq!(explained_traversals(&afresh, skewed), vec!["p", "o"]);
+
+    let _ = std::fs::remove_dir(&path);
+}
+
+#[test]
+fn test_optimizer_learning_decays_until_forgotten() {
+    let config = OptimizerConfig {
+        decay_factor: 0.5,
+        decay_interval: Some(Duration::from_millis(10)),
+        eviction_floor: 0.3,
+        state_path: None,
+    };
+    let executor = DQLExecutor::new(setup_likes_and_owns_graph()).with_optimizer_config(config).unwrap();
+    let skewed = "FROM Users u TRAVERSE -[:LIKES]-> p, -[:OWNS]-> o SELECT p.name, o.name";
+    executor.execute(skewed).unwrap();
+
+    // Left unused, the learned order and the cached plan decay away
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while explained_traversals(&executor, skewed) != vec!["p", "o"] {
+        assert!(std::time::Instant::now() < deadline, "Learning never decayed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let report = executor.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(report.unfinished_tasks.is_empty());
+    assert_eq!(
+        DQLExecutor::new(setup_likes_and_owns_graph())
+            .with_optimizer_config(OptimizerConfig {
+                decay_factor: 0.0,
+                ..OptimizerConfig::default()
+            })
+            .err()
+            .unwrap(),
+        "Decay factor must be in (0, 1], got 0"
+    );
+}
+
+#[test]
+fn test_backup_under_concurrent_transactions_is_consistent() {
+    let base = std::env::temp_dir().join("deed_test_concurrent_backup");
+    let _ = std::fs::remove_dir_all(&base);
+    let config = BackupConfig {
+        backup_dir: base.clone(),
+        compress: false,
+        verify: true,
+    };
+
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let (writer, backer) = setup_shared_executors(graph);
+
+    // Users are only ever committed in pairs
+    let writes = std::thread::spawn(move || {
+        for i in 0..100 {
+            writer.execute("BEGIN TRANSACTION").unwrap();
+            writer.execute(&format!("INSERT INTO Users VALUES ({{name: 'A{}'}})", i)).unwrap();
+            writer.execute(&format!("INSERT INTO Users VALUES ({{name: 'B{}'}})", i)).unwrap();
+            writer.execute("COMMIT").unwrap();
+        }
+    });
+
+    let mut backups = Vec::new();
+    while !writes.is_finished() {
+        backups.push(backer.create_backup(&config, BackupType::Full).unwrap());
+    }
+    writes.join().unwrap();
+    backups.push(backer.create_backup(&config, BackupType::Full).unwrap());
+
+    for metadata in &backups {
+        assert_eq!(metadata.entity_count % 2, 0, "Backup caught half a transaction");
+
+        let restored = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+        restored.restore_backup(&config, &metadata.backup_id, false).unwrap();
+        let res = restored.execute("FROM Users SELECT name").unwrap();
+        assert_eq!(res.row_count(), metadata.entity_count);
+    }
+    assert_eq!(backups.last().unwrap().entity_count, 200);
+}
+
+#[test]
+fn test_incremental_backup_chain_restores_like_full_backup() {
+    let base = std::env::temp_dir().join("deed_test_incremental_backup");
+    let _ = std::fs::remove_dir_all(&base);
+    let config = BackupConfig {
+        backup_dir: base.clone(),
+        compress: true,
+        verify: true,
+    };
+
+    let executor = DQLExecutor::new(setup_follows_graph());
+    executor.execute("CREATE INDEX idx_name ON Users(name)").unwrap();
+    let full = executor.create_backup(&config, BackupType::Full).unwrap();
+
+    executor.execute("INSERT INTO Users VALUES ({name: 'Dave'})").unwrap();
+    executor.execute("UPDATE Users SET age = 40 WHERE name = 'Alice'").unwrap();
+    let first = executor.create_backup(&config, BackupType::Incremental).unwrap();
+    assert_eq!(first.parent_backup_id, Some(full.backup_id.clone()));
+
+    executor.execute("DELETE FROM Users WHERE name = 'Carol'").unwrap();
+    executor
+        .execute("DELETE EDGE FROM Users a -[:FOLLOWS {since: 2019}]-> b WHERE a.name = 'Alice'")
+        .unwrap();
+    let second = executor.create_backup(&config, BackupType::Incremental).unwrap();
+    assert_eq!(second.parent_backup_id, Some(first.backup_id.clone()));
+    assert_eq!((second.entity_count, second.edge_count), (3, 1));
+
+    let latest = executor.create_backup(&config, BackupType::Full).unwrap();
+
+    let dump = |executor: &DQLExecutor| {
+        let users = executor.execute("FROM Users SELECT name, age ORDER BY name").unwrap();
+        let follows = traversed_names(executor, "FROM Users TRAVERSE -[:FOLLOWS]-> f SELECT f.name");
+        (users.rows, follows)
+    };
+
+    let from_chain = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    from_chain.restore_backup(&config, &second.backup_id, false).unwrap();
+    let from_full = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    from_full.restore_backup(&config, &latest.backup_id, false).unwrap();
+
+    assert_eq!(dump(&from_chain), dump(&from_full));
+    assert_eq!(dump(&from_chain), dump(&executor));
+
+    // Index definitions come back with the data
+    let plan = from_chain.execute("EXPLAIN FROM Users WHERE name = 'Dave' SELECT name").unwrap();
+    assert!(explained_operations(&plan).contains(&"IndexLookup".to_string()));
+
+    // Restoring over data needs force
+    let err = from_chain.restore_backup(&config, &full.backup_id, false).unwrap_err();
+    assert!(err.contains("not empty"), "Unexpected error: {}", err);
+    from_chain.restore_backup(&config, &full.backup_id, true).unwrap();
+    assert_eq!(from_chain.execute("FROM Users SELECT name").unwrap().row_count(), 3);
+    assert_eq!(
+        traversed_names(&from_chain, "FROM Users TRAVERSE ALL -[:FOLLOWS]-> f SELECT f.name"),
+        vec!["Bob", "Bob", "Carol"]
+    );
+}
+
+#[test]
+fn test_restore_is_logged_and_backup_lsns_keep_growing() {
+    let base = std::env::temp_dir().join(format!("deed_test_restore_wal_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(&base).unwrap();
+    let config = BackupConfig {
+        backup_dir: base.join("backups"),
+        compress: true,
+        verify: true,
+    };
+    let wal_path = base.join("wal");
+
+    let source = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    source.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    let backup = source.create_backup(&config, BackupType::Full).unwrap();
+
+    let executor = DQLExecutor::new_with_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap();
+    let before = executor.create_backup(&config, BackupType::Full).unwrap().wal_lsn;
+    executor.restore_backup(&config, &backup.backup_id, true).unwrap();
+    let restored = executor.create_backup(&config, BackupType::Full).unwrap().wal_lsn;
+    assert!(restored > before);
+    drop(executor);
+
+    // After a restart the log holds the restored data and LSNs go on from there
+    let recovered = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    let res = recovered.execute("FROM Users SELECT name").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.rows[0]["col_0"], dql_ir::Value::String("Alice".to_string()));
+    assert!(recovered.create_backup(&config, BackupType::Full).unwrap().wal_lsn >= restored);
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scheduled_backups_prune_old_chains_and_restore_latest() {
+    let base = std::env::temp_dir().join("deed_test_scheduled_backup");
+    let _ = std::fs::remove_dir_all(&base);
+    let config = BackupConfig {
+        backup_dir: base.clone(),
+        compress: true,
+        verify: true,
+    };
+
+    let executor = DQLExecutor::new(setup_follows_graph());
+    let scheduler = Arc::new(
+        BackupScheduler::new(
+            config.clone(),
+            BackupSchedule {
+                full_interval: Duration::from_millis(200),
+                incremental_interval: Some(Duration::from_millis(50)),
+                retention: RetentionPolicy { keep_full: 1 },
+            },
+        )
+        .unwrap(),
+    );
+    executor.schedule_backups(&scheduler).unwrap();
+
+    for i in 0..10 {
+        executor.execute(&format!("INSERT INTO Users VALUES ({{name: 'User{}'}})", i)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let users = executor.execute("FROM Users SELECT name").unwrap().row_count();
+
+    // Let a backup that may have started before the last insert finish, then one more
+    for _ in 0..2 {
+        let before = scheduler.status().last_backup_id;
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while scheduler.status().last_backup_id == before {
+            assert!(std::time::Instant::now() < deadline, "Scheduler stopped taking backups");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    let stopping = executor.clone();
+    let report = tokio::task::spawn_blocking(move || stopping.shutdown(Duration::from_secs(5)))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(report.unfinished_tasks.is_empty());
+
+    let status = scheduler.status();
+    assert_eq!(status.last_error, None);
+    assert!(status.last_backup_time.is_some() && status.next_run.is_some());
+
+    // Only the newest full backup's chain is kept
+    let backups = BackupManager::new(config.clone()).unwrap().list_backups().unwrap();
+    let fulls: Vec<&BackupMetadata> = backups.iter().filter(|b| b.backup_type == BackupType::Full).collect();
+    assert_eq!(fulls.len(), 1);
+    assert!(backups
+        .iter()
+        .filter(|b| b.backup_type == BackupType::Incremental)
+        .all(|b| b.base_backup_id.as_ref() == Some(&fulls[0].backup_id)));
+
+    let restored = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    restored.restore_backup(&config, LATEST_BACKUP, false).unwrap();
+    assert_eq!(restored.execute("FROM Users SELECT name").unwrap().row_count(), users);
+}
+
+#[test]
+fn test_collection_round_trips_through_jsonl_and_csv() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        for i in 0..5i64 {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            props.insert("age".to_string(), PropertyValue::Int(20 + i));
+            props.insert("score".to_string(), PropertyValue::Float(i as f64));
+            props.insert("active".to_string(), PropertyValue::Bool(i % 2 == 0));
+            props.insert("code".to_string(), PropertyValue::String(format!("{}", 100 + i)));
+            props.insert("note".to_string(), PropertyValue::Null);
+            if i == 0 {
+                props.insert("bio".to_string(), PropertyValue::String("likes \"quotes\",\ncommas".to_string()));
+            }
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+    }
+    let executor = DQLExecutor::new(graph.clone());
+
+    let by_name = |collection: &str| {
+        let g = graph.read().unwrap();
+        let mut entities: Vec<_> = g.scan_collection(collection).into_iter().map(|e| e.properties).collect();
+        entities.sort_by_key(|props| props["name"].as_str().unwrap().to_string());
+        entities
+    };
+    let original = by_name("Users");
+
+    let mut jsonl = Vec::new();
+    assert_eq!(executor.export_collection("Users", DataFormat::JsonLines, &mut jsonl).unwrap(), 5);
+    let report = executor
+        .import_collection("UsersJson", DataFormat::JsonLines, jsonl.as_slice(), &ImportOptions::default())
+        .unwrap();
+    assert_eq!((report.imported, report.skipped), (5, 0));
+    assert_eq!(by_name("UsersJson"), original);
+
+    // CSV has no null distinct from a missing property
+    let mut csv = Vec::new();
+    executor.export_collection("Users", DataFormat::Csv, &mut csv).unwrap();
+    assert!(String::from_utf8_lossy(&csv).starts_with("\"_id\",\"active\",\"age\",\"bio\""));
+    executor
+        .import_collection("UsersCsv", DataFormat::Csv, csv.as_slice(), &ImportOptions::default())
+        .unwrap();
+    let without_nulls: Vec<_> = original
+        .iter()
+        .map(|props| {
+            let mut props = props.clone();
+            props.retain(|_, v| *v != PropertyValue::Null);
+            props
+        })
+        .collect();
+    assert_eq!(by_name("UsersCsv"), without_nulls);
+
+    // An explicit column list, and the DQL surface through files
+    let mut names = Vec::new();
+    executor
+        .export_collection_csv("Users", &["name".to_string()], &mut names)
+        .unwrap();
+    assert_eq!(String::from_utf8(names).unwrap().lines().count(), 6);
+
+    let path = std::env::temp_dir().join("deed_test_copy_users.jsonl");
+    let res = executor.execute(&format!("COPY Users TO '{}'", path.display())).unwrap();
+    assert_eq!(res.rows_affected, 5);
+    let res = executor
+        .execute(&format!("COPY UsersFile FROM '{}' FORMAT JSONL", path.display()))
+        .unwrap();
+    assert_eq!(res.rows_affected, 5);
+    assert_eq!(by_name("UsersFile"), original);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_import_streams_large_input_in_batches() {
+    /// Generates CSV rows on demand, so the input never exists in memory
+    struct GeneratedRows {
+        next: usize,
+        total: usize,
+        pending: Vec<u8>,
+    }
+
+    impl std::io::Read for GeneratedRows {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() {
+                if self.next > self.total {
+                    return Ok(0);
+                }
+                self.pending = match self.next {
+                    0 => b"id,name\n".to_vec(),
+                    // Every 10,000th row is malformed
+                    n if n % 10_000 == 0 => format!("{}\n", n).into_bytes(),
+                    n => format!("{},\"user{}\"\n", n, n).into_bytes(),
+                };
+                self.next += 1;
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+    let rows = |total| GeneratedRows {
+        next: 0,
+        total,
+        pending: Vec::new(),
+    };
+
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    let options = ImportOptions {
+        batch_size: 1000,
+        validate: true,
+        on_mismatch: MismatchPolicy::Skip,
+    };
+    let report = executor.import_collection("Users", DataFormat::Csv, rows(100_000), &options).unwrap();
+    assert_eq!(report.imported, 99_990);
+    assert_eq!(report.skipped, 10);
+    assert_eq!(report.batches, 100);
+
+    let res = executor.execute("FROM Users WHERE id = 54321 SELECT name AS name").unwrap();
+    assert_eq!(res.rows[0]["name"], dql_ir::Value::String("user54321".to_string()));
+
+    // Aborting keeps the batches already imported
+    let options = ImportOptions {
+        on_mismatch: MismatchPolicy::Abort,
+        ..options
+    };
+    let err = executor.import_collection("Others", DataFormat::Csv, rows(20_000), &options).unwrap_err();
+    assert!(err.contains("Line 10001"), "Unexpected error: {}", err);
+    let res = executor.execute("FROM Others SELECT id").unwrap();
+    assert_eq!(res.row_count(), 9_000);
+}
+
+#[test]
+fn test_graph_exports_as_graphml_and_dot() {
+    let graph = setup_three_user_graph();
+    let g = graph.read().unwrap();
+
+    let mut graphml = Vec::new();
+    g.export_graphml(&mut graphml, &ExportFilter::default()).unwrap();
+    let graphml = String::from_utf8(graphml).unwrap();
+
+    let mut reader = quick_xml::Reader::from_str(&graphml);
+    let (mut nodes, mut edges) = (0, 0);
+    loop {
+        match reader.read_event() {
+            Ok(quick_xml::events::Event::Start(e)) if e.name().as_ref() == b"node" => nodes += 1,
+            Ok(quick_xml::events::Event::Start(e)) if e.name().as_ref() == b"edge" => edges += 1,
+            Ok(quick_xml::events::Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => panic!("Exported GraphML should parse: {}", e),
+        }
+    }
+    assert_eq!((nodes, edges), (3, 3));
+    assert!(graphml.contains(r#"attr.name="since" attr.type="long""#));
+
+    let mut dot = Vec::new();
+    g.export_dot(&mut dot, &ExportFilter::default()).unwrap();
+    let sorted_lines = |text: &str| {
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(
+        sorted_lines(&String::from_utf8(dot).unwrap()),
+        sorted_lines(include_str!("fixtures/three_node_graph.dot"))
+    );
+}
+
+#[test]
+fn test_export_query_graph_includes_interconnecting_edges() {
+    let graph = setup_three_user_graph();
+    {
+        let g = graph.read().unwrap();
+        let mut props = std::collections::HashMap::new();
+        props.insert("name".to_string(), PropertyValue::String("Dave".to_string()));
+        let dave = g.add_entity("Users".to_string(), props).unwrap();
+        g.add_edge(EntityId(3), dave, "FOLLOWS".to_string(), std::collections::HashMap::new()).unwrap();
+    }
+    let executor = DQLExecutor::new(graph);
+
+    // Alice and the users she follows, with the edges among them but not Dave
+    let mut dot = Vec::new();
+    let nodes = executor
+        .export_query_graph(
+            "FROM Users u TRAVERSE -[:FOLLOWS]-> friend WHERE u.name = 'Alice' SELECT friend.name",
+            GraphFormat::Dot,
+            &mut dot,
+        )
+        .unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+
+    assert_eq!(nodes, 3);
+    assert_eq!(dot.matches(" -> ").count(), 3);
+    assert!(dot.contains("n2 -> n3"));
+    assert!(!dot.contains("Dave"));
+
+    let err = executor
+        .export_query_graph("DELETE FROM Users WHERE name = 'Bob'", GraphFormat::GraphML, Vec::new())
+        .unwrap_err();
+    assert!(err.contains("Only SELECT"));
+}
+
+#[test]
+fn test_nested_documents_insert_filter_and_validate() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+
+    executor
+        .execute("DEFINE SCHEMA Users (name String NOT NULL, tags ARRAY<STRING>, address JSON)")
+        .unwrap();
+    executor
+        .execute("INSERT INTO Users VALUES ({name: 'Ann', tags: ['admin', 'ops'], address: {city: 'Oslo', zip: '0150'}})")
+        .unwrap();
+    executor
+        .execute("INSERT INTO Users VALUES ({name: 'Bob', tags: ['ops'], address: {city: 'Bergen'}})")
+        .unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Cy', tags: []})").unwrap();
+
+    // Element types are checked
+    let err = executor.execute("INSERT INTO Users VALUES ({name: 'Dee', tags: ['ok', 3]})").unwrap_err();
+    assert!(err.contains("expected Array<String>, got Array<Mixed>"), "unexpected error: {}", err);
+
+    let names = |query: &str| -> Vec<dql_ir::Value> {
+        let res = executor.execute(query).unwrap();
+        (0..res.rows.len()).map(|i| res.get(i, "name").cloned().unwrap()).collect()
+    };
+    let ann = vec![dql_ir::Value::String("Ann".to_string())];
+
+    assert_eq!(names("FROM Users u WHERE 'admin' IN u.tags SELECT u.name AS name"), ann);
+    assert_eq!(names("FROM Users u WHERE u.address.city = 'Oslo' SELECT u.name AS name"), ann);
+    assert_eq!(names("FROM Users u WHERE 'zip' IN u.address SELECT u.name AS name"), ann);
+    assert_eq!(
+        names("FROM Users u WHERE 'ops' NOT IN u.tags SELECT u.name AS name"),
+        vec![dql_ir::Value::String("Cy".to_string())]
+    );
+
+    // Nested values come back whole
+    let res = executor.execute("FROM Users u WHERE u.name = 'Bob' SELECT u.tags AS tags, u.address.city AS city").unwrap();
+    assert_eq!(res.get(0, "tags"), Some(&dql_ir::Value::List(vec![dql_ir::Value::String("ops".to_string())])));
+    assert_eq!(res.get(0, "city"), Some(&dql_ir::Value::String("Bergen".to_string())));
+
+    let g = graph.read().unwrap();
+    let bob = g
+        .scan_collection("Users")
+        .into_iter()
+        .find(|u| u.get_property("name") == Some(&PropertyValue::String("Bob".to_string())))
+        .unwrap();
+    assert_eq!(bob.get_property_path("address.city"), Some(&PropertyValue::String("Bergen".to_string())));
+}
+
+#[test]
+fn test_timestamp_range_order_and_coercion() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+
+    // Without coercion an ISO string is not a timestamp
+    executor.execute("DEFINE SCHEMA Strict (at TIMESTAMP)").unwrap();
+    let err = executor.execute("INSERT INTO Strict VALUES ({at: '2024-05-01T12:00:00Z'})").unwrap_err();
+    assert!(err.contains("expected Timestamp, got String"), "unexpected error: {}", err);
+
+    executor.execute("DEFINE SCHEMA Events (name String, at TIMESTAMP) COERCE TIMESTAMPS").unwrap();
+    executor
+        .execute("INSERT INTO Events VALUES ({name: 'launch', at: '2024-05-01T12:00:00Z'})")
+        .unwrap();
+    executor
+        .execute("INSERT INTO Events VALUES ({name: 'review', at: TIMESTAMP '2024-04-15 09:30:00'})")
+        .unwrap();
+    executor
+        .execute("INSERT INTO Events VALUES ({name: 'kickoff', at: '2024-01-02'})")
+        .unwrap();
+    let err = executor.execute("INSERT INTO Events VALUES ({name: 'bad', at: 'next tuesday'})").unwrap_err();
+    assert!(err.contains("expected Timestamp, got String"), "unexpected error: {}", err);
+
+    let g = graph.read().unwrap();
+    let launch = g
+        .scan_collection("Events")
+        .into_iter()
+        .find(|e| e.get_property("name") == Some(&PropertyValue::String("launch".to_string())))
+        .unwrap();
+    assert_eq!(launch.get_property("at"), Some(&PropertyValue::Timestamp(1_714_564_800_000)));
+    drop(g);
+
+    let names = |query: &str| -> Vec<dql_ir::Value> {
+        let res = executor.execute(query).unwrap();
+        (0..res.rows.len()).map(|i| res.get(i, "name").cloned().unwrap()).collect()
+    };
+    let name = |s: &str| dql_ir::Value::String(s.to_string());
+
+    assert_eq!(
+        names("FROM Events e WHERE e.at >= TIMESTAMP '2024-04-01' AND e.at < TIMESTAMP '2024-05-01T12:00:00Z' SELECT e.name AS name"),
+        vec![name("review")]
+    );
+    assert_eq!(
+        names("FROM Events e SELECT e.name AS name ORDER BY e.at DESC"),
+        vec![name("launch"), name("review"), name("kickoff")]
+    );
+
+    // Timestamps come back as timestamps; differences are milliseconds
+    let res = executor
+        .execute("FROM Events e WHERE e.name = 'launch' SELECT e.at AS at, e.at - TIMESTAMP '2024-05-01' AS since")
+        .unwrap();
+    assert_eq!(res.get(0, "at"), Some(&dql_ir::Value::Timestamp(1_714_564_800_000)));
+    assert_eq!(res.get(0, "since"), Some(&dql_ir::Value::Integer(12 * 3_600_000)));
+
+    assert!(executor.execute("FROM Events e WHERE e.at > TIMESTAMP 'soon' SELECT e.name").is_err());
+}
+
+#[test]
+fn test_now_relative_filter_with_injected_clock() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let now = Arc::new(std::sync::atomic::AtomicI64::new(parse_timestamp("2024-05-10T00:00:00Z").unwrap()));
+    let clock = now.clone();
+    let executor = DQLExecutor::new(graph).with_clock(Arc::new(move || clock.load(std::sync::atomic::Ordering::SeqCst)));
+
+    executor.execute("INSERT INTO Posts VALUES ({title: 'old', created_at: TIMESTAMP '2024-04-01'})").unwrap();
+    executor.execute("INSERT INTO Posts VALUES ({title: 'recent', created_at: TIMESTAMP '2024-05-05'})").unwrap();
+    executor.execute("INSERT INTO Posts VALUES ({title: 'fresh', created_at: NOW()})").unwrap();
+
+    let titles = |query: &str| -> Vec<dql_ir::Value> {
+        let res = executor.execute(query).unwrap();
+        (0..res.rows.len()).map(|i| res.get(i, "title").cloned().unwrap()).collect()
+    };
+    let title = |s: &str| dql_ir::Value::String(s.to_string());
+    let last_week = "FROM Posts p WHERE p.created_at > NOW() - INTERVAL 7 DAYS SELECT p.title AS title ORDER BY p.created_at";
+
+    assert_eq!(titles(last_week), vec![title("recent"), title("fresh")]);
+
+    // NOW() is read per execution, so the same (cached) query sees time move on
+    now.fetch_add(6 * 86_400_000, std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(titles(last_week), vec![title("fresh")]);
+    assert_eq!(
+        titles("FROM Posts p WHERE p.created_at + INTERVAL 1 HOUR < NOW() - INTERVAL 30 DAYS SELECT p.title AS title"),
+        vec![title("old")]
+    );
+}
+
+#[test]
+fn test_string_functions_in_where_select_and_order_by() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph);
+
+    executor.execute("INSERT INTO Users VALUES ({first: 'Alice', last: 'Liddell', age: 30})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({first: 'BOB', last: 'Stone', age: 25})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({first: 'Christopher', last: 'Robin', age: 8})").unwrap();
+
+    let column = |query: &str, name: &str| -> Vec<dql_ir::Value> {
+        let res = executor.execute(query).unwrap();
+        (0..res.rows.len()).map(|i| res.get(i, name).cloned().unwrap()).collect()
+    };
+    let s = |text: &str| dql_ir::Value::String(text.to_string());
+
+    // Case-insensitive match by lowering both sides
+    assert_eq!(
+        column("FROM Users u WHERE LOWER(u.first) = LOWER('bOb') SELECT u.first AS first", "first"),
+        vec![s("BOB")]
+    );
+    assert_eq!(
+        column("FROM Users u WHERE STARTS_WITH(UPPER(u.first), 'CH') SELECT u.last AS last", "last"),
+        vec![s("Robin")]
+    );
+
+    assert_eq!(
+        column("FROM Users u WHERE u.age > 20 SELECT CONCAT(u.first, ' ', u.last) AS full ORDER BY u.age", "full"),
+        vec![s("BOB Stone"), s("Alice Liddell")]
+    );
+
+    assert_eq!(
+        column("FROM Users u SELECT u.first AS first ORDER BY LENGTH(u.first) DESC", "first"),
+        vec![s("Christopher"), s("Alice"), s("BOB")]
+    );
+
+    // Wrong argument types give NULL, or an error in strict mode
+    assert_eq!(
+        column("FROM Users u WHERE u.first = 'BOB' SELECT LENGTH(u.age) AS n", "n"),
+        vec![dql_ir::Value::Null]
+    );
+    executor.execute("SET strict_functions = on").unwrap();
+    let err = executor.execute("FROM Users u WHERE u.first = 'BOB' SELECT LENGTH(u.age) AS n").unwrap_err();
+    assert!(err.contains("LENGTH expects a string"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_has_and_typeof_report_on_property_shapes() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let payloads = [
+            ("a", Some(PropertyValue::Int(1))),
+            ("b", Some(PropertyValue::Int(2))),
+            ("c", Some(PropertyValue::String("3".to_string()))),
+            ("d", Some(PropertyValue::Float(4.5))),
+            ("e", Some(PropertyValue::Null)),
+            ("f", None),
+        ];
+        for (name, payload) in payloads {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            if let Some(payload) = payload {
+                props.insert("payload".to_string(), payload);
+            }
+            g.add_entity("Events".to_string(), props).unwrap();
+        }
+    }
+    let executor = DQLExecutor::new(graph);
+
+    let names = |query: &str| -> Vec<String> {
+        let res = executor.execute(query).unwrap();
+        (0..res.rows.len()).map(|i| res.get(i, "name").unwrap().to_string()).collect()
+    };
+    let s = |text: &str| dql_ir::Value::String(text.to_string());
+
+    // HAS tells a property holding NULL from a missing one
+    assert_eq!(names("FROM Events e WHERE NOT HAS(e.payload) SELECT e.name AS name"), vec!["'f'"]);
+    assert_eq!(names("FROM Events e WHERE e.payload IS NULL SELECT e.name AS name ORDER BY e.name"), vec!["'e'", "'f'"]);
+    let res = executor.execute("FROM Events e WHERE e.name = 'e' SELECT HAS(e.payload) AS has, TYPEOF(e.payload) AS type").unwrap();
+    assert_eq!(res.get(0, "has"), Some(&dql_ir::Value::Bool(true)));
+    assert_eq!(res.get(0, "type"), Some(&s("Null")));
+
+    // Both work inside AND/OR trees
+    assert_eq!(
+        names("FROM Events e WHERE HAS(e.payload) AND (TYPEOF(e.payload) = 'String' OR TYPEOF(e.payload) = 'Float') SELECT e.name AS name ORDER BY e.name"),
+        vec!["'c'", "'d'"]
+    );
+    assert_eq!(
+        names("FROM Events e WHERE TYPEOF(e.payload) = 'Int' OR NOT HAS(e.payload) SELECT e.name AS name ORDER BY e.name"),
+        vec!["'a'", "'b'", "'f'"]
+    );
+
+    // A data quality report: how many of each type, missing counted as NULL
+    let res = executor
+        .execute("FROM Events e SELECT TYPEOF(e.payload) AS type, COUNT(*) AS n GROUP BY TYPEOF(e.payload) ORDER BY type")
+        .unwrap();
+    let report: Vec<(dql_ir::Value, dql_ir::Value)> = (0..res.rows.len())
+        .map(|i| (res.get(i, "type").unwrap().clone(), res.get(i, "n").unwrap().clone()))
+        .collect();
+    assert_eq!(
+        report,
+        vec![
+            (s("Float"), dql_ir::Value::Integer(1)),
+            (s("Int"), dql_ir::Value::Integer(2)),
+            (s("Null"), dql_ir::Value::Integer(2)),
+            (s("String"), dql_ir::Value::Integer(1)),
+        ]
+    );
+
+    let res = executor
+        .execute("FROM Events e SELECT HAS(e.payload) AS has, COUNT(*) AS n GROUP BY HAS(e.payload) ORDER BY has")
+        .unwrap();
+    assert_eq!(res.get(0, "has"), Some(&dql_ir::Value::Bool(false)));
+    assert_eq!(res.get(0, "n"), Some(&dql_ir::Value::Integer(1)));
+    assert_eq!(res.get(1, "n"), Some(&dql_ir::Value::Integer(5)));
+
+    assert!(executor.execute("FROM Events e WHERE HAS(e.@id) SELECT e").is_err());
+    assert!(executor.execute("FROM Events e WHERE HAS(LOWER(e.name)) SELECT e").is_err());
+}
+
+#[test]
+fn test_select_star_unions_properties_with_null_fill() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let mut alice = std::collections::HashMap::new();
+        alice.insert("name".to_string(), PropertyValue::String("Alice".to_string()));
+        alice.insert("age".to_string(), PropertyValue::Int(30));
+        alice.insert("id".to_string(), PropertyValue::Int(7));
+        g.add_entity("Users".to_string(), alice).unwrap();
+
+        let mut bob = std::collections::HashMap::new();
+        bob.insert("name".to_string(), PropertyValue::String("Bob".to_string()));
+        bob.insert("city".to_string(), PropertyValue::String("Oslo".to_string()));
+        g.add_entity("Users".to_string(), bob).unwrap();
+    }
+    let executor = DQLExecutor::new(graph);
+
+    let res = executor.execute("FROM Users SELECT * ORDER BY name").unwrap();
+
+    // The entity id comes first, then the union of the properties by name;
+    // an `id` property is a column of its own
+    let names: Vec<&str> = res.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["@id", "age", "city", "id", "name"]);
+    assert_eq!(res.row_count(), 2);
+    assert!(matches!(res.get(0, "@id"), Some(dql_ir::Value::EntityId(_))));
+    assert_eq!(res.get(0, "id"), Some(&dql_ir::Value::Integer(7)));
+    assert_eq!(res.get(1, "id"), Some(&dql_ir::Value::Null));
+    assert_eq!(res.get(0, "age"), Some(&dql_ir::Value::Integer(30)));
+    assert_eq!(res.get(0, "city"), Some(&dql_ir::Value::Null));
+    assert_eq!(res.get(1, "age"), Some(&dql_ir::Value::Null));
+    assert_eq!(res.get(1, "city"), Some(&dql_ir::Value::String("Oslo".to_string())));
+}
+
+#[test]
+fn test_select_star_prefixes_columns_across_bindings() {
+    let executor = DQLExecutor::new(setup_test_graph_with_edges());
+
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:FOLLOWS]-> f WHERE u.name = 'User1' SELECT *")
+        .unwrap();
+    let names: Vec<&str> = res.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["u.@id", "u.age", "u.name", "f.@id", "f.age", "f.name"]);
+    assert_eq!(res.get(0, "u.name"), Some(&dql_ir::Value::String("User1".to_string())));
+    assert_eq!(res.get(0, "f.name"), Some(&dql_ir::Value::String("User2".to_string())));
+
+    // One binding's properties next to a field of another
+    let res = executor
+        .execute("FROM Users u TRAVERSE -[:FOLLOWS]-> f WHERE u.name = 'User1' SELECT u.*, f.name AS followed")
+        .unwrap();
+    let names: Vec<&str> = res.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["u.@id", "u.age", "u.name", "followed"]);
+    assert_eq!(res.get(0, "followed"), Some(&dql_ir::Value::String("User2".to_string())));
+
+    let err = executor.execute("FROM Users u SELECT x.*").unwrap_err();
+    assert_eq!(err, "Unknown binding 'x' in SELECT x.*");
+    let err = executor.execute("FROM Users SELECT *, COUNT(*) AS n").unwrap_err();
+    assert_eq!(err, "SELECT * can't be combined with GROUP BY or aggregates");
+}
+
+#[test]
+fn test_select_distinct_removes_duplicate_rows() {
+    let executor = DQLExecutor::new(setup_customer_orders_graph());
+
+    // 1 and 1.0 are one customer
+    let res = executor.execute("FROM Orders SELECT DISTINCT customer_id").unwrap();
+    assert_eq!(res.row_count(), 3);
+
+    let res = executor.execute("FROM Orders SELECT customer_id").unwrap();
+    assert_eq!(res.row_count(), 6);
+
+    // Every column counts, and NaN amounts are one value
+    let res = executor.execute("FROM Orders SELECT DISTINCT customer_id, region").unwrap();
+    assert_eq!(res.row_count(), 4);
+    let res = executor.execute("FROM Orders SELECT DISTINCT region, amount").unwrap();
+    assert_eq!(res.row_count(), 4);
+
+    // A limit without ORDER BY still counts distinct rows
+    let res = executor.execute("FROM Orders SELECT DISTINCT region LIMIT 2").unwrap();
+    assert_eq!(res.row_count(), 2);
+    assert_ne!(res.get(0, "col_0"), res.get(1, "col_0"));
+}
+
+#[test]
+fn test_select_distinct_dedupes_before_order_and_limit() {
+    let executor = DQLExecutor::new(setup_customer_orders_graph());
+
+    let customers = |query: &str| -> Vec<dql_ir::Value> {
+        let res = executor.execute(query).unwrap();
+        (0..res.row_count()).map(|i| res.get(i, "c").unwrap().clone()).collect()
+    };
+
+    assert_eq!(
+        customers("FROM Orders SELECT DISTINCT customer_id AS c ORDER BY c DESC LIMIT 2"),
+        [dql_ir::Value::Integer(3), dql_ir::Value::Integer(2)]
+    );
+    assert_eq!(
+        customers("FROM Orders SELECT DISTINCT customer_id AS c ORDER BY c LIMIT 2 OFFSET 1"),
+        [dql_ir::Value::Integer(2), dql_ir::Value::Integer(3)]
+    );
+
+    // Sorting by an unselected expression would bring duplicates back
+    let err = executor.execute("FROM Orders SELECT DISTINCT customer_id ORDER BY amount").unwrap_err();
+    assert!(err.contains("SELECT DISTINCT"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_in_subquery_semi_join() {
+    let executor = DQLExecutor::new(setup_users_orders_graph());
+    let names = |query: &str| {
+        let mut names: Vec<String> = executor
+            .execute(query)
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| match &row["col_0"] {
+                dql_ir::Value::String(name) => name.clone(),
+                other => panic!("Expected name, got {:?}", other),
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    // Four orders total over 98; two of them have no user
+    assert_eq!(
+        names("FROM Users u WHERE u.user_id IN (FROM Orders o WHERE o.total > 98 SELECT o.user_id) SELECT u.name"),
+        ["User1", "User3"]
+    );
+    // Integer and float IDs match, and the subquery composes with other conditions
+    executor.execute("INSERT INTO Orders VALUES ({order_no: 200, user_id: 35.0, total: 5})").unwrap();
+    assert_eq!(
+        names("FROM Users u WHERE u.user_id IN (FROM Orders o SELECT o.user_id) AND u.user_id >= 32 SELECT u.name"),
+        ["User35"]
+    );
+
+    // A scalar subquery is its single row's value
+    assert_eq!(
+        names("FROM Users u WHERE u.user_id = (FROM Orders o WHERE o.order_no = 131 SELECT o.user_id) SELECT u.name"),
+        ["User3"]
+    );
+    let err = executor
+        .execute("FROM Users u WHERE u.user_id = (FROM Orders o WHERE o.total > 98 SELECT o.user_id) SELECT u.name")
+        .unwrap_err();
+    assert_eq!(err, "Scalar subquery returned 4 rows");
+
+    // The subquery's plan runs first, under EXPLAIN's Subquery step (after
+    // the summary row)
+    let plan = executor
+        .execute("EXPLAIN FROM Users u WHERE u.user_id IN (FROM Orders o SELECT o.user_id) SELECT u.name")
+        .unwrap();
+    assert_eq!(plan.rows[0]["operation"], dql_ir::Value::String("Plan".to_string()));
+    assert_eq!(plan.rows[1]["operation"], dql_ir::Value::String("Subquery".to_string()));
+}
+
+#[test]
+fn test_not_in_and_not_exists_anti_joins() {
+    let executor = DQLExecutor::new(setup_users_orders_graph());
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    // Users without orders
+    let res = executor
+        .execute("FROM Users u WHERE u.user_id NOT IN (FROM Orders o SELECT o.user_id) SELECT u.name")
+        .unwrap();
+    let mut names: Vec<String> = res
+        .rows
+        .iter()
+        .map(|row| match &row["col_0"] {
+            dql_ir::Value::String(name) => name.clone(),
+            other => panic!("Expected name, got {:?}", other),
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, (32..40).map(|i| format!("User{}", i)).collect::<Vec<_>>());
+
+    assert_eq!(count("FROM Users WHERE EXISTS (FROM Orders o WHERE o.total > 98 SELECT o.total) SELECT name"), 40);
+    assert_eq!(count("FROM Users WHERE NOT EXISTS (FROM Orders o WHERE o.total > 98 SELECT o.total) SELECT name"), 0);
+
+    // Subqueries also narrow UPDATE and DELETE
+    executor
+        .execute("DELETE FROM Users WHERE user_id NOT IN (FROM Orders o SELECT o.user_id)")
+        .unwrap();
+    assert_eq!(count("FROM Users SELECT name"), 32);
+}
+
+#[test]
+fn test_empty_and_correlated_subqueries() {
+    let executor = DQLExecutor::new(setup_users_orders_graph());
+    let count = |query: &str| executor.execute(query).unwrap().row_count();
+
+    // Nothing is in an empty result, and an empty scalar subquery is NULL
+    assert_eq!(count("FROM Users u WHERE u.user_id IN (FROM Orders o WHERE o.total > 1000 SELECT o.user_id) SELECT u.name"), 0);
+    assert_eq!(count("FROM Users u WHERE u.user_id NOT IN (FROM Orders o WHERE o.total > 1000 SELECT o.user_id) SELECT u.name"), 40);
+    assert_eq!(count("FROM Users WHERE NOT EXISTS (FROM Orders o WHERE o.total > 1000 SELECT o.total) SELECT name"), 40);
+    assert_eq!(count("FROM Users u WHERE u.user_id = (FROM Orders o WHERE o.total > 1000 SELECT o.user_id) SELECT u.name"), 0);
+
+    // Reading the enclosing query's bindings isn't supported
+    let err = executor
+        .execute("FROM Users u WHERE EXISTS (FROM Orders o WHERE o.user_id = u.user_id SELECT o.total) SELECT u.name")
+        .unwrap_err();
+    assert!(err.contains("Correlated subqueries aren't supported"), "Unexpected error: {}", err);
+
+    let err = executor
+        .execute("FROM Users u WHERE u.user_id IN (FROM Orders o SELECT o.user_id, o.total) SELECT u.name")
+        .unwrap_err();
+    assert!(err.contains("A subquery used as a value must select exactly one column"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_stream_scan_in_batches() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let events: Vec<_> = (0..1_000_000)
+            .map(|n| std::collections::HashMap::from([("n".to_string(), PropertyValue::Int(n))]))
+            .collect();
+        g.add_entities("Events".to_string(), events).unwrap();
+    }
+    let executor = DQLExecutor::new(graph);
+
+    let mut batches = 0;
+    let mut total = 0;
+    for batch in executor.execute_stream("FROM Events SELECT *", 10_000).unwrap() {
+        let batch = batch.unwrap();
+        assert_eq!(batch.row_count(), 10_000);
+        if batches == 0 {
+            let columns: Vec<&str> = batch.columns.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(columns, ["@id", "n"]);
+        }
+        batches += 1;
+        total += batch.row_count();
+    }
+    assert_eq!(batches, 100);
+    assert_eq!(total, 1_000_000);
+}
+
+#[test]
+fn test_stream_reads_collection_as_batches_are_taken() {
+    let executor = DQLExecutor::new(setup_events_graph(100));
+    let numbers = |batch: &QueryResult| -> Vec<i64> {
+        batch
+            .rows
+            .iter()
+            .map(|row| match row["col_0"] {
+                dql_ir::Value::Integer(n) => n,
+                ref other => panic!("Expected number, got {:?}", other),
+            })
+            .collect()
+    };
+
+    // Entities deleted after the first batch are never read
+    let mut stream = executor.execute_stream("FROM Events SELECT n", 10).unwrap();
+    assert_eq!(numbers(&stream.next().unwrap().unwrap()), (0..10).collect::<Vec<_>>());
+    executor.execute("DELETE FROM Events WHERE n >= 10").unwrap();
+    assert!(stream.next().is_none());
+
+    // Filters, OFFSET and LIMIT apply across batches; batches without rows are skipped
+    let executor = DQLExecutor::new(setup_events_graph(100));
+    let batches: Vec<Vec<i64>> = executor
+        .execute_stream("FROM Events WHERE n >= 50 SELECT n LIMIT 15 OFFSET 8", 10)
+        .unwrap()
+        .map(|batch| numbers(&batch.unwrap()))
+        .collect();
+    assert_eq!(batches, [vec![58, 59], (60..70).collect(), vec![70, 71, 72]]);
+}
+
+#[test]
+fn test_stream_sorts_ordered_queries() {
+    let executor = DQLExecutor::new(setup_events_graph(100));
+
+    let batches: Vec<QueryResult> = executor
+        .execute_stream("FROM Events SELECT n ORDER BY n DESC", 30)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let sizes: Vec<usize> = batches.iter().map(QueryResult::row_count).collect();
+    assert_eq!(sizes, [30, 30, 30, 10]);
+    assert_eq!(batches[1].get(0, "col_0"), Some(&dql_ir::Value::Integer(69)));
+    assert_eq!(batches[3].get(9, "col_0"), Some(&dql_ir::Value::Integer(0)));
+
+    // A mutation's one batch reports what it changed
+    let batches: Vec<QueryResult> = executor
+        .execute_stream("DELETE FROM Events WHERE n < 5", 30)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].rows_affected, 5);
+
+    assert_eq!(
+        executor.execute_stream("FROM Events SELECT n", 0).err(),
+        Some("Batch size must be at least 1".to_string())
+    );
+}
+
+#[test]
+fn test_paged_query_returns_disjoint_complete_pages() {
+    let executor = DQLExecutor::new(setup_events_graph(100));
+    let query = "FROM Events WHERE n >= 10 SELECT n";
+
+    let mut seen = Vec::new();
+    let mut sizes = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = executor.execute_paged(query, cursor.as_deref(), 25).unwrap();
+        sizes.push(page.result.row_count());
+        for row in &page.result.rows {
+            match row["col_0"] {
+                dql_ir::Value::Integer(n) => seen.push(n),
+                ref other => panic!("Expected number, got {:?}", other),
+            }
+        }
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    assert_eq!(sizes, [25, 25, 25, 15]);
+    assert_eq!(seen, (10..100).collect::<Vec<_>>());
+
+    // Ordered queries page too
+    let page = executor.execute_paged("FROM Events SELECT n ORDER BY n DESC", None, 10).unwrap();
+    let page = executor
+        .execute_paged("FROM Events SELECT n ORDER BY n DESC", page.cursor.as_deref(), 10)
+        .unwrap();
+    assert_eq!(page.result.get(0, "col_0"), Some(&dql_ir::Value::Integer(89)));
+
+    // A cursor only resumes the query it came from
+    let first = executor.execute_paged(query, None, 25).unwrap();
+    let err = executor
+        .execute_paged("FROM Events SELECT n", first.cursor.as_deref(), 25)
+        .unwrap_err();
+    assert_eq!(err, "Cursor was issued for a different query");
+    let err = executor.execute_paged("DELETE FROM Events", None, 25).unwrap_err();
+    assert_eq!(err, "Only SELECT statements can be paged");
+}
+
+#[test]
+fn test_paged_scan_resumes_after_the_last_row() {
+    let metrics = Arc::new(QueryMetrics::new(Duration::from_secs(60), 0));
+    let graph = setup_events_graph(1_000);
+    let executor = DQLExecutor::new(graph.clone()).with_query_metrics(metrics.clone());
+    let numbers = |result: &QueryResult, column: &str| -> Vec<i64> {
+        (0..result.row_count())
+            .map(|i| match result.get(i, column) {
+                Some(dql_ir::Value::Integer(n)) => *n,
+                other => panic!("Expected number, got {:?}", other),
+            })
+            .collect()
+    };
+
+    // Each page reads the entities after the one before, not those again
+    let query = "FROM Events SELECT n";
+    let mut cursor: Option<String> = None;
+    let mut seen = Vec::new();
+    loop {
+        let before = metrics.snapshot().rows_scanned;
+        let page = executor.execute_paged(query, cursor.as_deref(), 100).unwrap();
+        assert!(metrics.snapshot().rows_scanned - before <= 200);
+        seen.extend(numbers(&page.result, "col_0"));
+        cursor = page.cursor;
+        if cursor.is_none() {
+            break;
+        }
+        // Deleting a returned row doesn't shift the later pages
+        if seen.len() == 100 {
+            executor.execute("DELETE FROM Events WHERE n = 0").unwrap();
+        }
+    }
+    assert_eq!(seen, (0..1_000).collect::<Vec<_>>());
+
+    // Equal ORDER BY keys split across pages are neither repeated nor lost
+    let query = "FROM Events e WHERE e.n < 100 SELECT e.n AS n ORDER BY e.n / 30 DESC";
+    let mut cursor: Option<String> = None;
+    let mut seen = Vec::new();
+    loop {
+        let page = executor.execute_paged(query, cursor.as_deref(), 7).unwrap();
+        assert!(page.result.rows.iter().all(|row| row.len() == 1));
+        seen.extend(numbers(&page.result, "n"));
+        cursor = page.cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    let mut expected: Vec<i64> = (1..100).collect();
+    expected.sort_by_key(|n| -(n / 30));
+    assert_eq!(seen, expected);
+
+    // A LIMIT holds across pages
+    let query = "FROM Events SELECT n LIMIT 150";
+    let first = executor.execute_paged(query, None, 100).unwrap();
+    let second = executor.execute_paged(query, first.cursor.as_deref(), 100).unwrap();
+    assert_eq!(numbers(&second.result, "col_0"), (101..151).collect::<Vec<_>>());
+    assert_eq!(second.cursor, None);
+}
+
+#[test]
+fn test_execute_as_enforces_roles() {
+    let executor = DQLExecutor::new(setup_test_graph());
+    let reader = Session::new("reader".to_string(), Role::ReadOnly, 3600);
+    let writer = Session::new("writer".to_string(), Role::ReadWrite, 3600);
+    let admin = Session::new("admin".to_string(), Role::Admin, 3600);
+    let count = |executor: &DQLExecutor| executor.execute("FROM Users SELECT name").unwrap().row_count();
+    let before = count(&executor);
+
+    // Read-only sessions read but don't write
+    executor.execute_as(&reader, "FROM Users WHERE age > 20 SELECT name").unwrap();
+    let err = executor
+        .execute_as(&reader, "INSERT INTO Users VALUES ({name: 'Eve', age: 40})")
+        .unwrap_err();
+    assert_eq!(err, "Permission denied: write access required");
+    let err = executor.execute_as(&reader, "DELETE FROM Users").unwrap_err();
+    assert_eq!(err, "Permission denied: write access required");
+    assert_eq!(count(&executor), before);
+
+    // Read-write sessions change data but not indexes or schemas
+    executor
+        .execute_as(&writer, "INSERT INTO Users VALUES ({name: 'Eve', age: 40})")
+        .unwrap();
+    let err = executor
+        .execute_as(&writer, "CREATE INDEX idx_age ON Users(age)")
+        .unwrap_err();
+    assert_eq!(err, "Permission denied: admin access required");
+    assert!(executor.index_manager().find_index("Users", "age").is_none());
+
+    // Admins can do everything
+    executor.execute_as(&admin, "CREATE INDEX idx_age ON Users(age)").unwrap();
+    executor.execute_as(&admin, "DROP INDEX idx_age").unwrap();
+    executor.execute_as(&admin, "DELETE FROM Users WHERE name = 'Eve'").unwrap();
+    assert_eq!(count(&executor), before);
+
+    // An executor bound to a session checks every entry point
+    let as_reader = executor.clone().with_caller(reader);
+    let err = as_reader.bulk_insert("Users", vec![types::Properties::new()]).unwrap_err();
+    assert_eq!(err, "Permission denied: write access required");
+    let err = as_reader.execute_stream("DELETE FROM Users", 10).err().unwrap();
+    assert_eq!(err, "Permission denied: write access required");
+}
+
+#[test]
+fn test_audit_log_records_callers_writes() {
+    let audit = Arc::new(AuditLog::in_memory(AuditRetention::default()));
+    let executor = DQLExecutor::new(setup_test_graph()).with_audit_log(audit.clone());
+    let alice = Session::new("alice".to_string(), Role::ReadWrite, 3600);
+    let reader = Session::new("reader".to_string(), Role::ReadOnly, 3600);
+
+    executor
+        .execute_as(&alice, "UPDATE Users   SET age = 99\n WHERE city = 'NYC'")
+        .unwrap();
+    executor.execute_as(&alice, "FROM Users WHERE age > 20 SELECT name").unwrap();
+    executor.execute("DELETE FROM Users WHERE age = 99").unwrap();
+    let err = executor.execute_as(&reader, "DELETE FROM Users").unwrap_err();
+    assert_eq!(err, "Permission denied: write access required");
+
+    // Reads and statements run without a caller aren't recorded
+    let entries = audit.entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].username, "alice");
+    assert_eq!(entries[0].role, Role::ReadWrite);
+    assert_eq!(entries[0].statement, "UPDATE Users SET age = 99 WHERE city = 'NYC'");
+    assert_eq!(entries[0].collections, ["Users"]);
+    assert_eq!(entries[0].rows_affected, 5);
+    assert_eq!(entries[0].outcome, AuditOutcome::Succeeded);
+    assert_eq!(entries[1].username, "reader");
+    assert_eq!(entries[1].outcome, AuditOutcome::Denied(err));
+
+    let denied = audit.query(&AuditFilter {
+        username: Some("reader".to_string()),
+        collection: Some("Users".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(denied.len(), 1);
+}
+
+#[test]
+fn test_pooled_connections_record_writes_in_the_pools_audit_log() {
+    let audit = Arc::new(AuditLog::in_memory(AuditRetention::default()));
+    let pool = ConnectionPool::new(
+        setup_test_graph(),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        PoolConfig {
+            maintenance_interval: None,
+            audit: Some(audit.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut conn = pool.get_connection().unwrap();
+    conn.set_session(Some(Session::new("alice".to_string(), Role::ReadWrite, 3600)));
+    conn.execute("DELETE FROM Users WHERE city = 'NYC'").unwrap();
+
+    let entries = audit.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].username, "alice");
+    assert_eq!(entries[0].outcome, AuditOutcome::Succeeded);
+}
+
+#[test]
+fn test_audit_collection_is_queryable_and_read_only() {
+    let now = Arc::new(std::sync::atomic::AtomicI64::new(1_700_000_000_000));
+    let clock = now.clone();
+    let audit = Arc::new(AuditLog::in_memory(AuditRetention {
+        max_entries: Some(3),
+        max_age: Some(Duration::from_secs(3600)),
+    }));
+    let executor = DQLExecutor::new(setup_test_graph())
+        .with_audit_log(audit.clone())
+        .with_clock(Arc::new(move || clock.load(std::sync::atomic::Ordering::SeqCst)));
+    let alice = Session::new("alice".to_string(), Role::Admin, 3600);
+    let bob = Session::new("bob".to_string(), Role::ReadWrite, 3600);
+
+    executor.execute_as(&alice, "INSERT INTO Orders VALUES ({total: 10})").unwrap();
+    executor.execute_as(&bob, "INSERT INTO Orders VALUES ({total: 20}), ({total: 30})").unwrap();
+    let err = executor.execute_as(&bob, "UPDATE Orders SET total = total / 0").unwrap_err();
+    assert_eq!(audit.entries()[2].outcome, AuditOutcome::Failed(err));
+
+    let res = executor
+        .execute_as(
+            &alice,
+            "FROM _audit WHERE user = 'bob' SELECT rows_affected AS rows, outcome AS outcome",
+        )
+        .unwrap();
+    assert_eq!(res.row_count(), 2);
+    assert_eq!(res.get(0, "rows"), Some(&dql_ir::Value::Integer(2)));
+    assert_eq!(res.get(0, "outcome"), Some(&dql_ir::Value::String("succeeded".to_string())));
+    assert_eq!(res.get(1, "outcome"), Some(&dql_ir::Value::String("failed".to_string())));
+
+    // No statement can write to it, whatever the caller's role
+    for statement in [
+        "INSERT INTO _audit VALUES ({user: 'mallory'})",
+        "UPDATE _audit SET user = 'mallory'",
+        "DELETE FROM _audit",
+    ] {
+        let err = executor.execute_as(&alice, statement).unwrap_err();
+        assert_eq!(err, "The _audit collection is read-only");
+        assert_eq!(executor.execute(statement).unwrap_err(), err);
+    }
+    let err = executor.bulk_insert("_audit", vec![types::Properties::new()]).unwrap_err();
+    assert_eq!(err, "The _audit collection is read-only");
+
+    // The refused writes were recorded; only the newest three entries are kept
+    let outcomes: Vec<AuditOutcome> = audit.entries().into_iter().map(|e| e.outcome).collect();
+    assert_eq!(outcomes, vec![AuditOutcome::Denied(err.clone()); 3]);
+
+    // Entries past the maximum age are dropped as new ones arrive
+    now.fetch_add(2 * 3600 * 1000, std::sync::atomic::Ordering::SeqCst);
+    executor.execute_as(&bob, "DELETE FROM Orders WHERE total = 10").unwrap();
+    let entries = audit.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].rows_affected, 1);
+    assert_eq!(entries[0].timestamp, now.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn test_committed_mutations_replicate_to_slave() {
+    let graph = setup_follows_graph();
+    let replica = copy_graph(&graph.read().unwrap());
+    let master = Arc::new(ReplicationManager::new_master("master".to_string()));
+    let slave = ReplicationManager::new_slave("replica-1".to_string(), "master:9000".to_string());
+    let executor = DQLExecutor::new(graph.clone()).with_replication(master.clone());
+
+    executor
+        .execute("INSERT INTO Users VALUES ({name: 'Dave', age: 40}), ({name: 'Erin', age: 35})")
+        .unwrap();
+    executor.execute("UPDATE Users SET age = 41 WHERE name = 'Dave'").unwrap();
+    executor
+        .execute("UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET since = 2000 WHERE b.name = 'Carol'")
+        .unwrap();
+    executor.execute("DELETE EDGE FROM Users a -[:FOLLOWS {since: 2019}]-> b").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+    let mut frank = types::Properties::new();
+    frank.insert("name".to_string(), PropertyValue::String("Frank".to_string()));
+    executor.bulk_insert("Users", vec![frank]).unwrap();
+
+    // Rolled back changes never reach the log
+    let logged = master.log_size();
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Erin'").unwrap();
+    executor.execute("ROLLBACK").unwrap();
+    assert_eq!(master.log_size(), logged);
+
+    let apply_all = |slave: &ReplicationManager| {
+        let replica = replica.read().unwrap();
+        loop {
+            let entries = master.get_entries_since(slave.last_applied_seq());
+            if entries.is_empty() {
+                break;
+            }
+            for entry in &entries {
+                slave.apply_to_graph(&replica, entry).unwrap();
+            }
+        }
+    };
+    apply_all(&slave);
+    assert_eq!(slave.last_applied_seq(), master.current_seq());
+    assert_eq!(graph_contents(&replica.read().unwrap()), graph_contents(&graph.read().unwrap()));
+
+    // Re-delivered entries are skipped, and a slave replaying the whole log
+    // over changes it already has doesn't duplicate them
+    for entry in master.get_entries_since(0) {
+        assert!(!slave.apply_to_graph(&replica.read().unwrap(), &entry).unwrap());
+    }
+    apply_all(&ReplicationManager::new_slave("replica-2".to_string(), "master:9000".to_string()));
+    assert_eq!(graph_contents(&replica.read().unwrap()), graph_contents(&graph.read().unwrap()));
+}
+
+#[tokio::test]
+async fn test_slave_behind_trimmed_log_catches_up_from_snapshot() {
+    let graph = setup_follows_graph();
+    let replica = copy_graph(&graph.read().unwrap());
+    let master = Arc::new(ReplicationManager::new(ReplicationConfig {
+        snapshot_chunk_size: 64,
+        ..ReplicationConfig::default()
+    }));
+    let direct = ReplicationManager::new_slave("replica-1".to_string(), "master:9000".to_string());
+    let executor = DQLExecutor::new(graph.clone()).with_replication(master.clone());
+    let replicate_directly = || {
+        for entry in master.get_entries_since(direct.last_applied_seq()) {
+            direct.apply_to_graph(&replica.read().unwrap(), &entry).unwrap();
+        }
+    };
+
+    executor
+        .execute("INSERT INTO Users VALUES ({name: 'Dave', age: 40}), ({name: 'Erin', age: 35})")
+        .unwrap();
+    executor.execute("UPDATE Users SET age = 41 WHERE name = 'Dave'").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Bob'").unwrap();
+    replicate_directly();
+    let trimmed = master.current_seq();
+    master.trim_log(trimmed).unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = P2PConfig {
+        listen_port: port,
+        insecure: true,
+        ..Default::default()
+    };
+    let master_network = P2PNetwork::new(1, NodeAddress::new("127.0.0.1".to_string(), port), config.clone());
+    let snapshots = executor.clone();
+    master
+        .serve_slaves(&master_network, Arc::new(move || snapshots.replication_snapshot()))
+        .unwrap();
+    master_network.start_listener().await.unwrap();
+
+    let slave_network = P2PNetwork::new(2, NodeAddress::new("127.0.0.1".to_string(), 0), config);
+    slave_network.add_peer(1, NodeAddress::new("127.0.0.1".to_string(), port));
+    let slave = ReplicationManager::new_slave("replica-2".to_string(), format!("127.0.0.1:{}", port));
+    let lagging = Arc::new(RwLock::new(Graph::new()));
+
+    // The entries the slave needs are gone, so it gets a snapshot, in chunks
+    let pulled = slave.pull_from_master(&lagging, &slave_network, 1).await.unwrap();
+    assert_eq!(pulled, Pulled::Snapshot(trimmed));
+    assert_eq!(slave.last_applied_seq(), trimmed);
+    assert_eq!(graph_contents(&lagging.read().unwrap()), graph_contents(&graph.read().unwrap()));
+    let state = master.get_slave_states().into_iter().find(|s| s.slave_id == "replica-2").unwrap();
+    let transfer = state.snapshot_transfer.unwrap();
+    assert_eq!(transfer.seq, trimmed);
+    assert!(transfer.total_bytes > 64);
+    assert_eq!(transfer.sent_bytes, transfer.total_bytes);
+
+    // Then replicates incrementally from the snapshot on
+    executor
+        .execute("UPDATE EDGE FROM Users a -[:FOLLOWS]-> b SET since = 2000 WHERE b.name = 'Carol'")
+        .unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Frank', age: 28})").unwrap();
+    executor.execute("DELETE FROM Users WHERE name = 'Erin'").unwrap();
+    replicate_directly();
+    assert!(matches!(
+        slave.pull_from_master(&lagging, &slave_network, 1).await.unwrap(),
+        Pulled::Entries(applied) if applied > 0
+    ));
+    assert_eq!(slave.last_applied_seq(), master.current_seq());
+    let state = master.get_slave_states().into_iter().find(|s| s.slave_id == "replica-2").unwrap();
+    assert_eq!(state.snapshot_transfer, None);
+
+    let expected = graph_contents(&graph.read().unwrap());
+    assert_eq!(graph_contents(&lagging.read().unwrap()), expected);
+    assert_eq!(graph_contents(&replica.read().unwrap()), expected);
+}
+
+// Helper functions
+
+/// Users Alice (1), Bob (2) and Carol (3); Alice follows both, Bob follows Carol
+fn setup_three_user_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        let ids: Vec<EntityId> = ["Alice", "Bob", "Carol \"C\""]
+            .iter()
+            .map(|name| {
+                let mut props = std::collections::HashMap::new();
+                props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+                g.add_entity("Users".to_string(), props).unwrap()
+            })
+            .collect();
+
+        let mut since = std::collections::HashMap::new();
+        since.insert("since".to_string(), PropertyValue::Int(2020));
+        g.add_edge(ids[0], ids[1], "FOLLOWS".to_string(), since).unwrap();
+        g.add_edge(ids[0], ids[2], "FOLLOWS".to_string(), std::collections::HashMap::new()).unwrap();
+        g.add_edge(ids[1], ids[2], "FOLLOWS".to_string(), std::collections::HashMap::new()).unwrap();
+    }
+
+    graph
+}
+
+
+
+/// 40 users; 200 orders spread over users 0-31 (160 of them) or user ids
+/// no one has, plus order 0 without a user_id
+fn setup_users_orders_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        for i in 0..40 {
+            let mut props = std::collections::HashMap::new();
+            props.insert("user_id".to_string(), PropertyValue::Int(i));
+            props.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+
+        for i in 0..200 {
+            let mut props = std::collections::HashMap::new();
+            props.insert("order_no".to_string(), PropertyValue::Int(i));
+            props.insert("total".to_string(), PropertyValue::Int((i * 37) % 101));
+            if i > 0 {
+                let user_id = if i % 5 == 0 { 1000 + i } else { i % 32 };
+                props.insert("user_id".to_string(), PropertyValue::Int(user_id));
+            }
+            g.add_entity("Orders".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn setup_orders_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        // Orders 1-5 are from 2021, 6-10 from 2023
+        for i in 1..=10 {
+            let mut props = std::collections::HashMap::new();
+            props.insert("order_no".to_string(), PropertyValue::Int(i));
+            props.insert("total".to_string(), PropertyValue::Float(i as f64 * 12.5));
+            props.insert(
+                "created_at".to_string(),
+                PropertyValue::String(format!("{}-{:02}-01", if i <= 5 { 2021 } else { 2023 }, i)),
+            );
+
+            g.add_entity("Orders".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+
+fn setup_replicated_executor() -> (DQLExecutor, Arc<ReplicationManager>) {
+    let master = Arc::new(ReplicationManager::new_master("master".to_string()));
+    master.register_slave("replica-1".to_string()).unwrap();
+    master.log_insert(1, "Users".to_string(), std::collections::HashMap::new()).unwrap();
+    master.log_insert(2, "Users".to_string(), std::collections::HashMap::new()).unwrap();
+
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    master.update_slave_applied("replica-1", 0, now_ms - 10_000).unwrap();
+
+    // Replica holds different data so tests can tell which graph served a read
+    let replica = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = replica.read().unwrap();
+        let mut props = std::collections::HashMap::new();
+        props.insert("name".to_string(), PropertyValue::String("Stale".to_string()));
+        g.add_entity("Users".to_string(), props).unwrap();
+    }
+
+    let executor = DQLExecutor::new(setup_test_graph()).with_replication(master.clone());
+    executor.attach_replica("replica-1".to_string(), replica);
+
+    (executor, master)
+}
+
+
+fn setup_test_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        // Add test users
+        for i in 1..=10 {
+            let mut props = std::collections::HashMap::new();
+            props.insert(
+                "name".to_string(),
+                PropertyValue::String(format!("User{}", i)),
+            );
+            props.insert("age".to_string(), PropertyValue::Int(20 + i));
+            props.insert(
+                "city".to_string(),
+                PropertyValue::String(if i % 2 == 0 {
+                    "NYC".to_string()
+                } else {
+                    "SF".to_string()
+                }),
+            );
+
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn setup_test_graph_with_edges() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        // Add users
+        let mut user_ids = Vec::new();
+        for i in 1..=5 {
+            let mut props = std::collections::HashMap::new();
+            props.insert(
+                "name".to_string(),
+                PropertyValue::String(format!("User{}", i)),
+            );
+            props.insert("age".to_string(), PropertyValue::Int(20 + i));
+
+            let id = g.add_entity("Users".to_string(), props).unwrap();
+            user_ids.push(id);
+        }
+
+        // Add FOLLOWS edges
+        for i in 0..user_ids.len() - 1 {
+            g.add_edge(
+                user_ids[i],
+                user_ids[i + 1],
+                "FOLLOWS".to_string(),
+                std::collections::HashMap::new(),
+            ).unwrap();
+        }
+    }
+
+    graph
+}
+
+
+/// Alice follows Bob twice (since 2019 and 2020) and Carol once (since 2021)
+fn setup_follows_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let ids: Vec<EntityId> = ["Alice", "Bob", "Carol"]
+            .iter()
+            .map(|name| {
+                let mut props = std::collections::HashMap::new();
+                props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+                g.add_entity("Users".to_string(), props).unwrap()
+            })
+            .collect();
+
+        for (target, since) in [(1, 2019), (1, 2020), (2, 2021)] {
+            let mut props = std::collections::HashMap::new();
+            props.insert("since".to_string(), PropertyValue::Int(since));
+            g.add_edge(ids[0], ids[target], "FOLLOWS".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+/// Alice follows Bob, Carol and Dave and likes Erin; Bob follows Carol;
+/// Carol and Dave follow Alice
+fn setup_degree_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let ids: Vec<EntityId> = ["Alice", "Bob", "Carol", "Dave", "Erin"]
+            .iter()
+            .map(|name| {
+                let mut props = std::collections::HashMap::new();
+                props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+                g.add_entity("Users".to_string(), props).unwrap()
+            })
+            .collect();
+
+        let edges = [(0, 1, "FOLLOWS"), (0, 2, "FOLLOWS"), (0, 3, "FOLLOWS"), (0, 4, "LIKES"), (1, 2, "FOLLOWS"), (2, 0, "FOLLOWS"), (3, 0, "FOLLOWS")];
+        for (source, target, edge_type) in edges {
+            g.add_edge(ids[source], ids[target], edge_type.to_string(), std::collections::HashMap::new()).unwrap();
+        }
+    }
+
+    graph
+}
+
+/// Graph of `Nodes` named N0..N{count-1} joined by NEXT edges
+fn setup_hop_graph(count: usize, edges: &[(usize, usize)]) -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let ids: Vec<EntityId> = (0..count)
+            .map(|i| {
+                let mut props = std::collections::HashMap::new();
+                props.insert("name".to_string(), PropertyValue::String(format!("N{}", i)));
+                g.add_entity("Nodes".to_string(), props).unwrap()
+            })
+            .collect();
+
+        for &(from, to) in edges {
+            g.add_edge(ids[from], ids[to], "NEXT".to_string(), std::collections::HashMap::new()).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn session_on(graph: &Arc<RwLock<Graph>>, transactions: &Arc<TransactionManager>) -> DQLExecutor {
+    DQLExecutor::with_shared_components(
+        graph.clone(),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        transactions.clone(),
+        None,
+        Arc::new(IndexManager::new()),
+        Arc::new(RwLock::new(SchemaValidator::new())),
+    )
+}
+
+fn setup_shared_executors(graph: Arc<RwLock<Graph>>) -> (DQLExecutor, DQLExecutor) {
+    let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
+    let transactions = Arc::new(TransactionManager::new());
+    let indexes = Arc::new(IndexManager::new());
+    let schemas = Arc::new(RwLock::new(SchemaValidator::new()));
+
+    let first = DQLExecutor::with_shared_components(
+        graph.clone(),
+        optimizer.clone(),
+        cache.clone(),
+        transactions.clone(),
+        None,
+        indexes.clone(),
+        schemas.clone(),
+    );
+    let second = DQLExecutor::with_shared_components(graph, optimizer, cache, transactions, None, indexes, schemas);
+    (first, second)
+}
+
+fn user_age(executor: &DQLExecutor, name: &str) -> Option<i64> {
+    let query = format!("FROM Users WHERE name = '{}' SELECT age", name);
+    let res = executor.execute(&query).unwrap();
+    match res.rows.first().and_then(|row| row.get("col_0")) {
+        Some(dql_ir::Value::Integer(age)) => Some(*age),
+        _ => None,
+    }
+}
+
+/// Sorted names a traversal reached (the query's single projected column)
+fn traversed_names(executor: &DQLExecutor, query: &str) -> Vec<String> {
+    let mut names: Vec<String> = executor
+        .execute(query)
+        .unwrap()
+        .rows
+        .iter()
+        .filter_map(|row| match row.get("col_0") {
+            Some(dql_ir::Value::String(name)) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Products across two categories; only some have a rating, and sku mixes types
+fn setup_products_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let products = [
+            ("Apple", "food", 1.5, Some(4), PropertyValue::Int(100)),
+            ("Pear", "food", 1.2, None, PropertyValue::String("B-2".to_string())),
+            ("Plum", "food", 0.8, None, PropertyValue::Int(7)),
+            ("Desk", "furniture", 250.0, Some(5), PropertyValue::String("A-1".to_string())),
+            ("Lamp", "furniture", 40.0, None, PropertyValue::Float(9.5)),
+            ("Chair", "furniture", 35.0, Some(2), PropertyValue::Int(10)),
+        ];
+
+        for (name, category, price, rating, sku) in products {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            props.insert("category".to_string(), PropertyValue::String(category.to_string()));
+            props.insert("price".to_string(), PropertyValue::Float(price));
+            if let Some(rating) = rating {
+                props.insert("rating".to_string(), PropertyValue::Int(rating));
+            }
+            props.insert("sku".to_string(), sku);
+
+            g.add_entity("Products".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn string_values(values: &[&str]) -> Vec<dql_ir::Value> {
+    values.iter().map(|v| dql_ir::Value::String(v.to_string())).collect()
+}
+
+/// Users with PURCHASED edges: Alice bought a Laptop and a Mouse, Bob a Desk, Carol nothing
+fn setup_purchase_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let add = |collection: &str, name: &str, key: &str, amount: i64| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            props.insert(key.to_string(), PropertyValue::Int(amount));
+            g.add_entity(collection.to_string(), props).unwrap()
+        };
+
+        let alice = add("Users", "Alice", "credit", 10);
+        let bob = add("Users", "Bob", "credit", 20);
+        add("Users", "Carol", "credit", 30);
+        let laptop = add("Products", "Laptop", "price", 1000);
+        let mouse = add("Products", "Mouse", "price", 25);
+        let desk = add("Products", "Desk", "price", 300);
+
+        for (user, product) in [(alice, laptop), (alice, mouse), (bob, desk)] {
+            g.add_edge(user, product, "PURCHASED".to_string(), std::collections::HashMap::new()).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn setup_aged_users_graph(count: i64) -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        for i in 0..count {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            props.insert("age".to_string(), PropertyValue::Int(i % 100));
+            g.add_entity("Users".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+/// NYC: 6 users averaging 37.5; SF: 7 users aged 40-46; LA: 3 users in their twenties
+fn setup_city_users_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        let cities = [
+            ("NYC", vec![25, 30, 35, 40, 45, 50]),
+            ("SF", vec![40, 41, 42, 43, 44, 45, 46]),
+            ("LA", vec![20, 22, 24]),
+        ];
+        for (city, ages) in cities {
+            for age in ages {
+                let mut props = std::collections::HashMap::new();
+                props.insert("city".to_string(), PropertyValue::String(city.to_string()));
+                props.insert("age".to_string(), PropertyValue::Int(age));
+                g.add_entity("Users".to_string(), props).unwrap();
+            }
+        }
+    }
+
+    graph
+}
+
+fn group_names(res: &QueryResult) -> Vec<String> {
+    let mut names: Vec<String> = res
+        .rows
+        .iter()
+        .map(|row| match &row["col_0"] {
+            dql_ir::Value::String(name) => name.clone(),
+            other => panic!("Expected group name, got {:?}", other),
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Paris: phone x2, laptop, and a login with no device; Oslo: tablet x3
+fn setup_logins_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        let logins = [
+            ("Paris", Some("phone"), PropertyValue::Int(1)),
+            ("Paris", Some("phone"), PropertyValue::Float(1.0)),
+            ("Paris", Some("laptop"), PropertyValue::Int(2)),
+            ("Paris", None, PropertyValue::Int(3)),
+            ("Oslo", Some("tablet"), PropertyValue::Int(2)),
+            ("Oslo", Some("tablet"), PropertyValue::Int(2)),
+            ("Oslo", Some("tablet"), PropertyValue::Int(2)),
+        ];
+        for (city, device, attempts) in logins {
+            let mut props = std::collections::HashMap::new();
+            props.insert("city".to_string(), PropertyValue::String(city.to_string()));
+            if let Some(device) = device {
+                props.insert("device".to_string(), PropertyValue::String(device.to_string()));
+            }
+            props.insert("attempts".to_string(), attempts);
+            g.add_entity("Logins".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+/// Alice and Dave have an email; Bob's is an explicit null and Carol has none.
+/// Carol also has no age.
+fn setup_contacts_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+        let contacts = [
+            ("Alice", Some(PropertyValue::String("alice@example.com".to_string())), Some(25)),
+            ("Bob", Some(PropertyValue::Null), Some(25)),
+            ("Carol", None, None),
+            ("Dave", Some(PropertyValue::String("dave@example.com".to_string())), Some(40)),
+        ];
+        for (name, email, age) in contacts {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+            if let Some(email) = email {
+                props.insert("email".to_string(), email);
+            }
+            if let Some(age) = age {
+                props.insert("age".to_string(), PropertyValue::Int(age));
+            }
+            g.add_entity("Contacts".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn contact_names(executor: &DQLExecutor, query: &str) -> Vec<String> {
+    let mut names: Vec<String> = executor
+        .execute(query)
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| match &row["col_0"] {
+            dql_ir::Value::String(name) => name.clone(),
+            other => panic!("Expected name, got {:?}", other),
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Operation names of an EXPLAIN result, after its summary row
+fn explained_operations(plan: &QueryResult) -> Vec<String> {
+    plan.rows[1..]
+        .iter()
+        .map(|row| match &row["operation"] {
+            dql_ir::Value::String(name) => name.clone(),
+            other => panic!("Expected operation name, got {:?}", other),
+        })
+        .collect()
+}
+
+/// Users who each like 40 posts but own a single profile
+fn setup_likes_and_owns_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    {
+        let g = graph.read().unwrap();
+        let named = |collection: &str, name: String, n: i64| {
+            let mut props = std::collections::HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(name));
+            props.insert("n".to_string(), PropertyValue::Int(n));
+            g.add_entity(collection.to_string(), props).unwrap()
+        };
+
+        // Each user likes 40 posts but owns a single profile
+        for u in 0..10 {
+            let user = named("Users", format!("User{}", u), u);
+            for p in 0..40 {
+                let post = named("Posts", format!("Post{}-{}", u, p), p);
+                g.add_edge(user, post, "LIKES".to_string(), Default::default()).unwrap();
+            }
+            let profile = named("Profiles", format!("Profile{}", u), u);
+            g.add_edge(user, profile, "OWNS".to_string(), Default::default()).unwrap();
+        }
+
+        // Shops own many items, so by edge counts alone OWNS looks like
+        // the larger fan-out; only running a query shows it isn't for users
+        for s in 0..10 {
+            let shop = named("Shops", format!("Shop{}", s), s);
+            for i in 0..50 {
+                let item = named("Items", format!("Item{}-{}", s, i), i);
+                g.add_edge(shop, item, "OWNS".to_string(), Default::default()).unwrap();
+            }
+        }
+    }
+    graph
+}
+
+fn setup_customer_orders_graph() -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        let orders = [
+            (PropertyValue::Int(1), "north", 10.0),
+            (PropertyValue::Float(1.0), "north", 10.0),
+            (PropertyValue::Int(2), "south", f64::NAN),
+            (PropertyValue::Int(2), "south", f64::NAN),
+            (PropertyValue::Int(2), "north", 5.0),
+            (PropertyValue::Int(3), "east", 7.5),
+        ];
+
+        for (customer_id, region, amount) in orders {
+            let mut props = std::collections::HashMap::new();
+            props.insert("customer_id".to_string(), customer_id);
+            props.insert("region".to_string(), PropertyValue::String(region.to_string()));
+            props.insert("amount".to_string(), PropertyValue::Float(amount));
+
+            g.add_entity("Orders".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+fn setup_events_graph(count: i64) -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+
+    {
+        let g = graph.read().unwrap();
+
+        for n in 0..count {
+            let mut props = std::collections::HashMap::new();
+            props.insert("n".to_string(), PropertyValue::Int(n));
+            g.add_entity("Events".to_string(), props).unwrap();
+        }
+    }
+
+    graph
+}
+
+/// A copy of a graph's entities and edges, with their ids
+fn copy_graph(graph: &Graph) -> Arc<RwLock<Graph>> {
+    let copy = Graph::new();
+    for entity in graph.get_all_entities() {
+        copy.insert_entity_with_id(entity).unwrap();
+    }
+    for edge in graph.get_all_edges() {
+        copy.insert_edge_with_id(edge).unwrap();
+    }
+    Arc::new(RwLock::new(copy))
+}
+
+/// Every entity and edge of a graph, by id, without access metadata
+#[allow(clippy::type_complexity)]
+fn graph_contents(graph: &Graph) -> (Vec<(u64, String, types::Properties)>, Vec<(u64, u64, u64, String, types::Properties)>) {
+    let mut entities: Vec<_> = graph
+        .get_all_entities()
+        .into_iter()
+        .map(|e| (e.id.as_u64(), e.entity_type, e.properties))
+        .collect();
+    entities.sort_by_key(|e| e.0);
+
+    let mut edges: Vec<_> = graph
+        .get_all_edges()
+        .into_iter()
+        .map(|e| (e.id.as_u64(), e.source.as_u64(), e.target.as_u64(), e.edge_type, e.properties))
+        .collect();
+    edges.sort_by_key(|e| e.0);
+
+    // Each edge is linked once from each endpoint
+    for entity in &entities {
+        let id = EntityId::new(entity.0);
+        let linked = graph.get_outgoing_neighbors(id, None).len() + graph.get_incoming_neighbors(id, None).len();
+        assert_eq!(linked, edges.iter().filter(|e| e.1 == entity.0).count() + edges.iter().filter(|e| e.2 == entity.0).count());
+    }
+
+    (entities, edges)
 }
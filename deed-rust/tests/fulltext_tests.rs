@@ -0,0 +1,144 @@
+//! Integration tests for full-text indexes and MATCH
+//!
+//! A FULLTEXT index maps each lowercased term of a text field to the
+//! entities holding it; MATCH intersects the terms' entities and SCORE()
+//! ranks them by how often the terms occur.
+
+use deed_core::dql_ir::Value;
+use deed_core::*;
+use std::sync::{Arc, RwLock};
+
+fn products() -> DQLExecutor {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    for (name, description) in [
+        ("Aria", "Wireless headphones with active noise cancelling"),
+        ("Bolt", "Wired headphones, studio grade"),
+        ("Comet", "Wireless earbuds. Noise-cancelling, wireless charging case, wireless pairing"),
+        ("Dune", "Bluetooth speaker with wireless range of 30m"),
+    ] {
+        executor
+            .execute(&format!("INSERT INTO Products VALUES ({{name: '{}', description: '{}'}})", name, description))
+            .unwrap();
+    }
+    executor.execute("CREATE FULLTEXT INDEX idx_desc ON Products(description)").unwrap();
+    executor
+}
+
+fn names(executor: &DQLExecutor, query: &str) -> Vec<String> {
+    let mut names: Vec<String> = executor
+        .execute(query)
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| match &row["name"] {
+            Value::String(name) => name.clone(),
+            other => panic!("Expected a name, got {:?}", other),
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_match_requires_every_term() {
+    let executor = products();
+
+    assert_eq!(
+        names(&executor, "FROM Products WHERE MATCH(description, 'wireless') SELECT name AS name"),
+        ["Aria", "Comet", "Dune"]
+    );
+    // Terms are AND-ed, case-insensitive and split on punctuation
+    assert_eq!(
+        names(&executor, "FROM Products WHERE MATCH(description, 'Wireless NOISE cancelling') SELECT name AS name"),
+        ["Aria", "Comet"]
+    );
+    assert_eq!(
+        names(&executor, "FROM Products p WHERE MATCH(p.description, 'headphones') AND p.name != 'Bolt' SELECT name AS name"),
+        ["Aria"]
+    );
+    assert!(names(&executor, "FROM Products WHERE MATCH(description, 'wireless studio') SELECT name AS name").is_empty());
+    assert!(names(&executor, "FROM Products WHERE MATCH(description, 'turntable') SELECT name AS name").is_empty());
+
+    // The predicate is served by the full-text index
+    let plan = executor
+        .execute("EXPLAIN FROM Products WHERE MATCH(description, 'noise wireless') SELECT name AS name")
+        .unwrap();
+    let Value::String(details) = &plan.rows[1]["details"] else {
+        panic!("Expected operation details");
+    };
+    assert!(details.contains("USING idx_desc MATCH 'noise wireless'"), "{}", details);
+}
+
+#[test]
+fn test_score_ranks_by_term_frequency() {
+    let executor = products();
+
+    let result = executor
+        .execute("FROM Products WHERE MATCH(description, 'wireless') SELECT name AS name, SCORE() AS score ORDER BY score DESC, name")
+        .unwrap();
+    let ranked: Vec<(Value, Value)> =
+        result.rows.iter().map(|row| (row["name"].clone(), row["score"].clone())).collect();
+    assert_eq!(
+        ranked,
+        [
+            (Value::String("Comet".to_string()), Value::Float(3.0)),
+            (Value::String("Aria".to_string()), Value::Float(1.0)),
+            (Value::String("Dune".to_string()), Value::Float(1.0)),
+        ]
+    );
+
+    // SCORE() can order without being selected
+    let result = executor
+        .execute("FROM Products WHERE MATCH(description, 'wireless noise') SELECT name AS name ORDER BY SCORE() DESC")
+        .unwrap();
+    assert_eq!(result.rows[0]["name"], Value::String("Comet".to_string()));
+    assert_eq!(result.columns.len(), 1);
+
+    let err = executor.execute("FROM Products SELECT name AS name, SCORE()").unwrap_err();
+    assert!(err.contains("SCORE() needs a MATCH"), "{}", err);
+}
+
+#[test]
+fn test_index_follows_updates_and_deletes() {
+    let executor = products();
+    let matching = |terms: &str| names(&executor, &format!("FROM Products WHERE MATCH(description, '{}') SELECT name AS name", terms));
+
+    executor
+        .execute("UPDATE Products SET description = 'Wired studio monitor' WHERE name = 'Aria'")
+        .unwrap();
+    assert_eq!(matching("wireless"), ["Comet", "Dune"]);
+    assert_eq!(matching("studio"), ["Aria", "Bolt"]);
+
+    executor.execute("DELETE FROM Products WHERE name = 'Comet'").unwrap();
+    executor
+        .execute("INSERT INTO Products VALUES ({name: 'Echo', description: 'Wireless studio headphones'})")
+        .unwrap();
+    assert_eq!(matching("wireless"), ["Dune", "Echo"]);
+    assert_eq!(matching("studio headphones"), ["Bolt", "Echo"]);
+
+    // The index holds exactly the terms the collection's text has
+    let index = executor.index_manager().get_index("idx_desc").unwrap();
+    assert_eq!(index.kind, IndexKind::FullText);
+    assert!(index.lookup(&PropertyValue::String("cancelling".to_string())).is_empty());
+    assert_eq!(index.lookup(&PropertyValue::String("wireless".to_string())).len(), 2);
+}
+
+#[test]
+fn test_match_needs_a_fulltext_index() {
+    let executor = products();
+
+    let err = executor
+        .execute("FROM Products WHERE MATCH(name, 'aria') SELECT name AS name")
+        .unwrap_err();
+    assert!(err.contains("MATCH on Products.name needs a FULLTEXT index"), "{}", err);
+
+    // A value index on the field doesn't serve MATCH
+    executor.execute("CREATE INDEX idx_name ON Products(name)").unwrap();
+    assert!(executor.execute("FROM Products WHERE MATCH(name, 'aria') SELECT name AS name").is_err());
+
+    // Once indexed, the same query runs; dropping the index refuses it again
+    executor.execute("CREATE FULLTEXT INDEX idx_name_text ON Products(name)").unwrap();
+    assert_eq!(names(&executor, "FROM Products WHERE MATCH(name, 'ARIA') SELECT name AS name"), ["Aria"]);
+    executor.execute("DROP INDEX idx_name_text").unwrap();
+    assert!(executor.execute("FROM Products WHERE MATCH(name, 'aria') SELECT name AS name").is_err());
+}
@@ -0,0 +1,179 @@
+//! Integration tests for gossip membership
+//!
+//! Nodes are P2P networks on ephemeral ports in one process; a node is
+//! killed by cancelling its shutdown signal, after which it refuses gossip.
+
+use deed_core::distributed_shard::ShardConfig;
+use deed_core::*;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct Node {
+    address: NodeAddress,
+    membership: Arc<Membership>,
+    shutdown: ShutdownSignal,
+    _task: BackgroundTask,
+}
+
+fn config() -> GossipConfig {
+    GossipConfig {
+        interval: Duration::from_millis(50),
+        fanout: 2,
+        suspect_after: Duration::from_millis(400),
+        dead_after: Duration::from_millis(800),
+        forget_after: Duration::from_secs(30),
+    }
+}
+
+/// A plaintext network for node `id` on an ephemeral port, and its address
+async fn network(id: NodeId) -> (Arc<P2PNetwork>, NodeAddress) {
+    let p2p = P2PConfig {
+        listen_port: 0,
+        insecure: true,
+        connection_timeout_ms: 200,
+        message_timeout_ms: 500,
+        max_retries: 0,
+        ..P2PConfig::default()
+    };
+    let network = Arc::new(P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), p2p));
+    let port = network.start_listener().await.unwrap().port();
+    (network, NodeAddress::new("127.0.0.1".to_string(), port))
+}
+
+/// Start gossiping as node `id`, joining through `seed` if given
+async fn start_node(
+    id: NodeId,
+    seed: Option<&NodeAddress>,
+    incarnation: u64,
+    listeners: Vec<Arc<dyn MembershipListener>>,
+) -> Node {
+    let (network, address) = network(id).await;
+    let mut membership = Membership::new(id, address.clone(), network, config())
+        .with_incarnation(incarnation)
+        .with_unverified_peers();
+    if let Some(seed) = seed {
+        membership = membership.with_seed(seed.clone());
+    }
+    for listener in listeners {
+        membership = membership.with_listener(listener);
+    }
+    let membership = Arc::new(membership);
+    let shutdown = ShutdownSignal::new();
+    let task = membership.start(&shutdown);
+    Node {
+        address,
+        membership,
+        shutdown,
+        _task: task,
+    }
+}
+
+/// Nodes 1 to `size`, all but the first told only of the first
+async fn cluster(size: NodeId, listeners: Vec<Arc<dyn MembershipListener>>) -> Vec<Node> {
+    let mut nodes = vec![start_node(1, None, 0, listeners).await];
+    for id in 2..=size {
+        let seed = nodes[0].address.clone();
+        nodes.push(start_node(id, Some(&seed), 0, Vec::new()).await);
+    }
+    nodes
+}
+
+async fn eventually(within: Duration, check: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + within;
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    check()
+}
+
+/// A node's view without heartbeats, which keep moving
+fn view(node: &Node) -> Vec<(NodeId, NodeAddress, u64, MemberState)> {
+    node.membership
+        .members()
+        .into_iter()
+        .map(|member| (member.id, member.address, member.incarnation, member.state))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_cluster_converges_from_one_seed() {
+    let topology = Arc::new(SmallWorldTopology::new(1, TopologyConfig::default()));
+    let shards = Arc::new(ShardManager::new(ShardConfig::default()));
+    let partition = Arc::new(PartitionManager::new(1, 5, 3, 10));
+    let listeners: Vec<Arc<dyn MembershipListener>> = vec![topology.clone(), shards.clone(), partition.clone()];
+    let nodes = cluster(5, listeners).await;
+
+    let converged = eventually(Duration::from_secs(5), || {
+        nodes.iter().all(|node| node.membership.live_members() == [1, 2, 3, 4, 5])
+            && nodes.iter().all(|node| view(node) == view(&nodes[0]))
+    })
+    .await;
+    assert!(converged, "{:?}", nodes.iter().map(view).collect::<Vec<_>>());
+    let addresses: Vec<NodeAddress> = view(&nodes[0]).into_iter().map(|(_, address, _, _)| address).collect();
+    assert_eq!(addresses, nodes.iter().map(|node| node.address.clone()).collect::<Vec<_>>());
+
+    // Node 1's topology, shard ring and partition detector followed along
+    assert_eq!(topology.get_all_nodes().len(), 5);
+    let owners: HashSet<NodeId> = shards.get_all_shards().iter().map(|shard| shard.primary_node).collect();
+    assert!(owners.len() > 1 && owners.iter().all(|owner| (1..=5).contains(owner)), "{:?}", owners);
+    assert!(partition.has_quorum());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_killed_node_is_declared_dead_and_rejoins_when_restarted() {
+    let topology = Arc::new(SmallWorldTopology::new(1, TopologyConfig::default()));
+    let partition = Arc::new(PartitionManager::new(1, 5, 3, 10));
+    let listeners: Vec<Arc<dyn MembershipListener>> = vec![topology.clone(), partition.clone()];
+    let nodes = cluster(5, listeners).await;
+    let converged = eventually(Duration::from_secs(5), || {
+        nodes.iter().all(|node| node.membership.live_members() == [1, 2, 3, 4, 5])
+    })
+    .await;
+    assert!(converged);
+
+    // Dead everywhere within the dead timeout and a few rounds of gossip
+    nodes[4].shutdown.cancel();
+    let bound = config().dead_after + config().interval * 10;
+    let dead = eventually(bound, || {
+        nodes[..4]
+            .iter()
+            .all(|node| node.membership.member(5).is_some_and(|member| member.state == MemberState::Dead))
+    })
+    .await;
+    assert!(dead, "{:?}", nodes.iter().map(view).collect::<Vec<_>>());
+    assert_eq!(partition.get_unreachable_nodes(), vec![5]);
+    assert!(topology.get_node(5).is_none());
+
+    // Back under a new incarnation and address, through any one node
+    let restarted = start_node(5, Some(&nodes[2].address), 1, Vec::new()).await;
+    let rejoined = eventually(Duration::from_secs(5), || {
+        nodes[..4].iter().chain([&restarted]).all(|node| {
+            node.membership.member(5).is_some_and(|member| {
+                member.state == MemberState::Alive && member.incarnation == 1 && member.address == restarted.address
+            })
+        })
+    })
+    .await;
+    assert!(rejoined, "{:?}", nodes.iter().map(view).collect::<Vec<_>>());
+    assert_eq!(restarted.membership.live_members(), [1, 2, 3, 4, 5]);
+    assert!(partition.get_unreachable_nodes().is_empty());
+    assert_eq!(topology.get_node(5).unwrap().address, restarted.address);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gossip_from_unverified_peers_is_refused() {
+    let (network, address) = network(1).await;
+    let strict = Arc::new(Membership::new(1, address.clone(), network, config()));
+    let shutdown = ShutdownSignal::new();
+    let _task = strict.start(&shutdown);
+
+    // Over plaintext no peer is verified, so the joining node is turned away
+    let joining = start_node(2, Some(&address), 0, Vec::new()).await;
+    tokio::time::sleep(config().interval * 10).await;
+    assert_eq!(strict.live_members(), [1]);
+    assert_eq!(joining.membership.live_members(), [2]);
+}
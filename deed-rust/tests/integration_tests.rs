@@ -2,7 +2,7 @@
 //!
 //! Tests complete workflows that users would actually perform.
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock};
 use tempfile::TempDir;
 
@@ -77,6 +77,7 @@ fn test_bank_transfer_with_validation() {
     let result = executor.execute("UPDATE Accounts SET balance = balance - 2000 WHERE id = 1 AND balance >= 2000");
 
     // This should fail or update 0 rows
+    assert!(result.map_or(true, |r| r.rows_affected == 0));
     executor.execute("ROLLBACK").unwrap();
 
     println!("✓ Bank transfer workflow completed");
@@ -374,3 +375,78 @@ fn test_application_lifecycle() {
 
     println!("✓ Application lifecycle completed");
 }
+
+/// Test: Graph persisted to RocksDB survives a restart
+#[test]
+fn test_persistent_graph_survives_restart() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("graph.db");
+
+    // Phase 1: Build a small social graph
+    {
+        let storage = Arc::new(StorageEngine::open(&db_path).unwrap());
+        let graph = Arc::new(RwLock::new(Graph::load_from_storage(storage).unwrap()));
+        let executor = DQLExecutor::new(graph.clone());
+
+        executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: 'Bob', age: 25})").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: 'Carol', age: 35})").unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: 'Dave', age: 40})").unwrap();
+
+        {
+            let g = graph.read().unwrap();
+            let id_of = |name: &str| {
+                g.scan_collection("Users")
+                    .into_iter()
+                    .find(|e| e.get_property("name").and_then(|v| v.as_str()) == Some(name))
+                    .unwrap()
+                    .id
+            };
+            let (alice, bob, carol, dave) = (id_of("Alice"), id_of("Bob"), id_of("Carol"), id_of("Dave"));
+            g.add_edge(alice, bob, "FOLLOWS".to_string(), Default::default()).unwrap();
+            g.add_edge(bob, carol, "FOLLOWS".to_string(), Default::default()).unwrap();
+            g.add_edge(alice, dave, "FOLLOWS".to_string(), Default::default()).unwrap();
+        }
+
+        executor.execute("UPDATE Users SET age = 31 WHERE name = 'Alice'").unwrap();
+        executor.execute("DELETE FROM Users WHERE name = 'Dave'").unwrap();
+    }
+
+    // Phase 2: Reopen from the same path
+    {
+        let executor = DQLExecutor::new_persistent(&db_path).unwrap();
+
+        let res = executor.execute("FROM Users SELECT name").unwrap();
+        assert_eq!(res.row_count(), 3, "Dave's delete must not resurrect on reload");
+
+        let res = executor.execute("FROM Users WHERE name = 'Alice' SELECT age AS age").unwrap();
+        assert_eq!(res.rows[0]["age"], dql_ir::Value::Integer(31));
+
+        let res = executor
+            .execute("FROM Users TRAVERSE -[:FOLLOWS*1..2]-> f WHERE name = 'Alice' SELECT f.name")
+            .unwrap();
+        let mut names: Vec<String> = res
+            .rows
+            .iter()
+            .filter_map(|row| match row.get("col_0") {
+                Some(dql_ir::Value::String(name)) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["Bob", "Carol"]);
+
+        // New IDs continue after the stored ones
+        executor.execute("INSERT INTO Users VALUES ({name: 'Erin', age: 28})").unwrap();
+        let res = executor.execute("FROM Users SELECT name").unwrap();
+        assert_eq!(res.row_count(), 4);
+    }
+
+    // Phase 3: The edge into the deleted entity is gone from storage too
+    {
+        let storage = StorageEngine::open(&db_path).unwrap();
+        assert_eq!(storage.scan_edges().unwrap().len(), 2);
+    }
+
+    println!("✓ Persistent graph survived restart");
+}
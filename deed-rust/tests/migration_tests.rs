@@ -0,0 +1,232 @@
+//! On-disk Format Migration Tests
+//!
+//! Uses a fabricated "orders" component whose v1 format is `id,total` lines
+//! and whose v2 format is one JSON object per line.
+
+use deed_core::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const V1_FILE: &str = "orders.v1";
+const V2_FILE: &str = "orders.v2";
+
+struct OrdersV1ToV2 {
+    fail_verify: bool,
+}
+
+impl MigrationStep for OrdersV1ToV2 {
+    fn name(&self) -> &str {
+        "orders_csv_to_json"
+    }
+
+    fn component(&self) -> &str {
+        "orders"
+    }
+
+    fn source_version(&self) -> u32 {
+        1
+    }
+
+    fn estimate(&self, dir: &Path) -> Result<MigrationPlan, String> {
+        let size = std::fs::metadata(dir.join(V1_FILE)).map_err(|e| e.to_string())?.len();
+        Ok(MigrationPlan::single(PlannedStep {
+            name: self.name().to_string(),
+            component: self.component().to_string(),
+            from_version: 1,
+            to_version: 2,
+            touched_files: vec![PathBuf::from(V1_FILE), PathBuf::from(V2_FILE)],
+            extra_bytes: size * 2,
+            estimated_duration: Duration::from_millis(size / 1000 + 1),
+        }))
+    }
+
+    fn apply(&self, dir: &Path, progress: &mut dyn FnMut(MigrationProgress)) -> Result<(), String> {
+        let v1 = std::fs::read_to_string(dir.join(V1_FILE)).map_err(|e| e.to_string())?;
+        let lines: Vec<&str> = v1.lines().collect();
+
+        let mut v2 = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            let (id, total) = line.split_once(',').ok_or("malformed v1 line")?;
+            v2.push_str(&format!("{{\"id\":{},\"total\":{}}}\n", id, total));
+            progress(MigrationProgress {
+                step: self.name().to_string(),
+                done: i as u64 + 1,
+                total: lines.len() as u64,
+            });
+        }
+
+        std::fs::write(dir.join(V2_FILE), v2).map_err(|e| e.to_string())?;
+        std::fs::remove_file(dir.join(V1_FILE)).map_err(|e| e.to_string())
+    }
+
+    fn verify(&self, dir: &Path) -> Result<(), String> {
+        if self.fail_verify {
+            return Err("simulated verification failure".to_string());
+        }
+        if dir.join(V1_FILE).exists() || !dir.join(V2_FILE).exists() {
+            return Err("orders not converted".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn orders_registry(fail_verify: bool) -> MigrationRegistry {
+    let mut supported = BTreeMap::new();
+    supported.insert("orders".to_string(), 2);
+    MigrationRegistry::with_versions(supported).register(Box::new(OrdersV1ToV2 { fail_verify }))
+}
+
+/// Fabricate a data directory written by an older build (orders v1)
+fn setup_v1_fixture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let orders: String = (1..=100).map(|i| format!("{},{}\n", i, i * 25)).collect();
+    std::fs::write(dir.join(V1_FILE), orders).unwrap();
+
+    let mut manifest = Manifest::default();
+    manifest.versions.insert("orders".to_string(), 1);
+    manifest.save(&dir).unwrap();
+
+    dir
+}
+
+fn manifest_version(dir: &Path) -> u32 {
+    Manifest::load(dir).unwrap().unwrap().versions["orders"]
+}
+
+#[test]
+fn test_dry_run_reports_plan_without_changes() {
+    let dir = setup_v1_fixture("deed_test_migration_dry_run");
+    let v1_size = std::fs::metadata(dir.join(V1_FILE)).unwrap().len();
+
+    let outcome = DataDirectory::open_with(&dir, OpenPolicy::DryRun, &orders_registry(false), &mut |_| {}).unwrap();
+    let plan = match outcome {
+        OpenOutcome::DryRun(plan) => plan,
+        other => panic!("Expected dry run, got {:?}", other),
+    };
+
+    assert_eq!(plan.steps.len(), 1);
+    assert_eq!(plan.steps[0].name, "orders_csv_to_json");
+    assert_eq!((plan.steps[0].from_version, plan.steps[0].to_version), (1, 2));
+    assert_eq!(plan.disk_space_needed, v1_size * 3, "Backup of orders.v1 plus the step's extra space");
+    assert!(plan.estimated_duration > Duration::ZERO);
+
+    // Nothing changed
+    assert!(dir.join(V1_FILE).exists());
+    assert!(!dir.join(V2_FILE).exists());
+    assert_eq!(manifest_version(&dir), 1);
+}
+
+#[test]
+fn test_migrate_automatically_converts_data() {
+    let dir = setup_v1_fixture("deed_test_migration_apply");
+
+    let refused = DataDirectory::open_with(&dir, OpenPolicy::RefuseIfMigrationNeeded, &orders_registry(false), &mut |_| {});
+    assert!(refused.unwrap_err().contains("needs migration"));
+
+    let mut events = Vec::new();
+    let outcome = DataDirectory::open_with(
+        &dir,
+        OpenPolicy::MigrateAutomatically,
+        &orders_registry(false),
+        &mut |p| events.push(p),
+    )
+    .unwrap();
+
+    let backup_dir = match outcome {
+        OpenOutcome::Migrated { directory, backup_dir, .. } => {
+            assert_eq!(directory.manifest().versions["orders"], 2);
+            backup_dir
+        }
+        other => panic!("Expected migration, got {:?}", other),
+    };
+
+    assert_eq!(events.len(), 100);
+    assert_eq!(events.last().unwrap().done, 100);
+    assert_eq!(manifest_version(&dir), 2);
+    assert!(backup_dir.join(V1_FILE).exists(), "Pre-migration backup should be kept");
+
+    let v2 = std::fs::read_to_string(dir.join(V2_FILE)).unwrap();
+    for (i, line) in v2.lines().enumerate() {
+        let order: serde_json::Value = serde_json::from_str(line).unwrap();
+        let id = i as u64 + 1;
+        assert_eq!(order["id"], id);
+        assert_eq!(order["total"], id * 25);
+    }
+    assert_eq!(v2.lines().count(), 100);
+
+    // Reopening needs no further migration
+    let reopened = DataDirectory::open_with(&dir, OpenPolicy::RefuseIfMigrationNeeded, &orders_registry(false), &mut |_| {});
+    assert!(matches!(reopened, Ok(OpenOutcome::Opened(_))));
+}
+
+#[test]
+fn test_failed_step_rolls_back() {
+    let dir = setup_v1_fixture("deed_test_migration_rollback");
+    let original = std::fs::read(dir.join(V1_FILE)).unwrap();
+
+    let err = DataDirectory::open_with(&dir, OpenPolicy::MigrateAutomatically, &orders_registry(true), &mut |_| {})
+        .unwrap_err();
+    assert!(err.contains("simulated verification failure"));
+    assert!(err.contains("rolled back"));
+
+    assert_eq!(std::fs::read(dir.join(V1_FILE)).unwrap(), original);
+    assert!(!dir.join(V2_FILE).exists());
+    assert_eq!(manifest_version(&dir), 1);
+}
+
+#[test]
+fn test_refuses_downgrade() {
+    let dir = setup_v1_fixture("deed_test_migration_downgrade");
+    let mut manifest = Manifest::default();
+    manifest.versions.insert("orders".to_string(), 3);
+    manifest.save(&dir).unwrap();
+
+    let err = DataDirectory::open_with(&dir, OpenPolicy::MigrateAutomatically, &orders_registry(false), &mut |_| {})
+        .unwrap_err();
+    assert!(err.contains("newer than this build supports"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_new_directory_gets_current_manifest() {
+    let dir = std::env::temp_dir().join("deed_test_migration_new");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let outcome = DataDirectory::open(&dir, OpenPolicy::RefuseIfMigrationNeeded).unwrap();
+    match outcome {
+        OpenOutcome::Opened(directory) => {
+            assert_eq!(directory.manifest(), &Manifest::current());
+            assert_eq!(directory.archive_dir(), dir.join("archive"));
+        }
+        other => panic!("Expected open, got {:?}", other),
+    }
+    assert_eq!(Manifest::load(&dir).unwrap(), Some(Manifest::current()));
+}
+
+#[test]
+fn test_executor_opens_through_data_directory() {
+    let dir = std::env::temp_dir().join("deed_test_migration_executor");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    {
+        let executor = DQLExecutor::open_data_dir(&dir, OpenPolicy::RefuseIfMigrationNeeded).unwrap();
+        executor.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    }
+    assert_eq!(Manifest::load(&dir).unwrap(), Some(Manifest::current()));
+
+    let executor = DQLExecutor::open_data_dir(&dir, OpenPolicy::RefuseIfMigrationNeeded).unwrap();
+    let result = executor.execute("FROM Users AS u SELECT u.name").unwrap();
+    assert_eq!(result.rows.len(), 1);
+
+    let err = DQLExecutor::open_data_dir(&dir, OpenPolicy::DryRun).err().unwrap();
+    assert!(err.contains("Dry run"), "Unexpected error: {}", err);
+
+    let mut newer = Manifest::current();
+    newer.versions.insert("wal".to_string(), 99);
+    newer.save(&dir).unwrap();
+    let err = DQLExecutor::open_data_dir(&dir, OpenPolicy::MigrateAutomatically).err().unwrap();
+    assert!(err.contains("newer than this build supports"), "Unexpected error: {}", err);
+}
@@ -0,0 +1,349 @@
+//! Integration tests for P2P messaging
+//!
+//! Each test starts networks on ephemeral ports and exchanges messages over real sockets.
+
+use deed_core::distributed_shard::ShardConfig;
+use deed_core::*;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A network on an ephemeral port, talking plaintext
+fn local_network(id: NodeId, config: P2PConfig) -> P2PNetwork {
+    let config = P2PConfig {
+        listen_port: 0,
+        insecure: true,
+        ..config
+    };
+    P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config)
+}
+
+/// Node 1 and node 2, listening, with node 1 knowing node 2 as a peer
+async fn connected_pair(config: P2PConfig) -> (P2PNetwork, P2PNetwork) {
+    let client = local_network(1, config.clone());
+    let server = local_network(2, config);
+    let addr = server.start_listener().await.unwrap();
+    client.add_peer(2, NodeAddress::new("127.0.0.1".to_string(), addr.port()));
+    (client, server)
+}
+
+/// Answers `ShardDataRequest`s after sleeping for `shard_id` milliseconds
+fn register_slow_handler(network: &P2PNetwork) {
+    network.register_handler(MessageKind::ShardDataRequest, |msg| {
+        let MessageType::ShardDataRequest { shard_id, .. } = msg.message_type else {
+            return None;
+        };
+        std::thread::sleep(Duration::from_millis(shard_id));
+        Some(msg.reply(MessageType::ShardDataResponse { shard_id, data: vec![] }))
+    });
+}
+
+fn shard_of(reply: &P2PMessage) -> u64 {
+    match reply.message_type {
+        MessageType::ShardDataResponse { shard_id, .. } => shard_id,
+        ref other => panic!("Unexpected reply: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_ping_pong() {
+    let (client, _server) = connected_pair(P2PConfig::default()).await;
+
+    let reply = client.send_message(2, MessageType::Ping).await.unwrap();
+    assert_eq!(reply.message_type, MessageType::Pong);
+    assert_eq!(reply.sender_id, 2);
+    assert_eq!(reply.receiver_id, 1);
+    client.ping(2).await.unwrap();
+
+    // Both went over the same connection
+    assert_eq!(client.get_stats().active_connections, 1);
+}
+
+#[tokio::test]
+async fn test_query_request_and_response() {
+    let (client, server) = connected_pair(P2PConfig::default()).await;
+    let executor = Arc::new(DQLExecutor::new(Arc::new(RwLock::new(Graph::new()))));
+    executor
+        .execute("INSERT INTO Users VALUES ({name: 'Alice'}), ({name: 'Bob'})")
+        .unwrap();
+    let shards = Arc::new(ShardManager::new(ShardConfig::default()));
+    DistributedQueryExecutor::new(2, shards, Arc::new(server), executor.clone()).serve_queries();
+
+    let request = |role| MessageType::QueryRequest {
+        query: "FROM Users SELECT name".to_string(),
+        statement: None,
+        role,
+        shards: None,
+    };
+
+    // A sub-query without a role is refused
+    let reply = client.send_message(2, request(None)).await.unwrap();
+    assert_eq!(
+        reply.message_type,
+        MessageType::Error {
+            message: "Permission denied: sub-query carries no role".to_string()
+        }
+    );
+
+    let reply = client.send_message(2, request(Some(Role::ReadOnly))).await.unwrap();
+    let MessageType::QueryResponse { result } = reply.message_type else {
+        panic!("Unexpected reply: {:?}", reply);
+    };
+    let result = QueryResult::from_bytes(&result).unwrap();
+    assert_eq!(result.row_count(), 2);
+
+    // The caller's role is enforced on the serving node
+    let request = MessageType::QueryRequest {
+        query: "DELETE FROM Users".to_string(),
+        statement: None,
+        role: Some(Role::ReadOnly),
+        shards: None,
+    };
+    let reply = client.send_message(2, request).await.unwrap();
+    assert_eq!(
+        reply.message_type,
+        MessageType::Error {
+            message: "Permission denied: write access required".to_string()
+        }
+    );
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap().row_count(), 2);
+
+    // A message nobody handles is answered with an error rather than silence
+    let reply = client
+        .send_message(2, MessageType::ShardReassignment { shard_id: 1, new_owner: 3 })
+        .await
+        .unwrap();
+    assert!(matches!(reply.message_type, MessageType::Error { .. }), "{:?}", reply);
+}
+
+#[tokio::test]
+async fn test_concurrent_responses_reach_their_requests() {
+    let (client, server) = connected_pair(P2PConfig::default()).await;
+    register_slow_handler(&server);
+
+    // The slow request is answered last, over the shared connection
+    let (slow, fast) = tokio::join!(
+        client.send_message(2, MessageType::ShardDataRequest { shard_id: 300, op: ShardDataOp::Digest }),
+        client.send_message(2, MessageType::ShardDataRequest { shard_id: 10, op: ShardDataOp::Digest }),
+    );
+    assert_eq!(shard_of(&slow.unwrap()), 300);
+    assert_eq!(shard_of(&fast.unwrap()), 10);
+    assert_eq!(client.get_stats().active_connections, 1);
+}
+
+#[tokio::test]
+async fn test_response_timeout() {
+    let config = P2PConfig {
+        message_timeout_ms: 200,
+        ..P2PConfig::default()
+    };
+    let (client, server) = connected_pair(config).await;
+    register_slow_handler(&server);
+
+    let started = Instant::now();
+    let err = client
+        .send_message(2, MessageType::ShardDataRequest { shard_id: 600, op: ShardDataOp::Digest })
+        .await
+        .unwrap_err();
+    assert_eq!(err, "Response timeout");
+    assert!(started.elapsed() < Duration::from_millis(600));
+    assert_eq!(client.get_stats().pending_requests, 0);
+
+    // The late response is dropped, not mistaken for the next one's
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let reply = client
+        .send_message(2, MessageType::ShardDataRequest { shard_id: 0, op: ShardDataOp::Digest })
+        .await
+        .unwrap();
+    assert_eq!(shard_of(&reply), 0);
+}
+
+#[tokio::test]
+async fn test_send_retries_over_a_new_connection() {
+    // A peer that drops its first connection before replying
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let peer = tokio::spawn(async move {
+        for attempt in 0..2 {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut length = [0u8; 4];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut payload = vec![0u8; u32::from_be_bytes(length) as usize];
+            stream.read_exact(&mut payload).await.unwrap();
+            if attempt == 0 {
+                continue;
+            }
+
+            let reply = P2PMessage::from_bytes(&payload).unwrap().reply(MessageType::Pong).to_bytes().unwrap();
+            stream.write_all(&(reply.len() as u32).to_be_bytes()).await.unwrap();
+            stream.write_all(&reply).await.unwrap();
+            return stream;
+        }
+        unreachable!()
+    });
+
+    let client = local_network(1, P2PConfig::default());
+    client.add_peer(2, NodeAddress::new("127.0.0.1".to_string(), port));
+    let reply = client.send_message(2, MessageType::Ping).await.unwrap();
+    assert_eq!(reply.message_type, MessageType::Pong);
+    drop(peer.await.unwrap());
+
+    // Retries run out against a peer that isn't listening
+    let client = local_network(
+        1,
+        P2PConfig {
+            max_retries: 2,
+            ..P2PConfig::default()
+        },
+    );
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    client.add_peer(3, NodeAddress::new("127.0.0.1".to_string(), closed));
+    let err = client.send_message(3, MessageType::Ping).await.unwrap_err();
+    assert!(err.starts_with("Failed to connect"), "{}", err);
+    assert_eq!(client.send_message(4, MessageType::Ping).await.unwrap_err(), "Unknown peer: 4");
+}
+
+#[tokio::test]
+async fn test_route_lookup_asks_for_unknown_neighbors() {
+    // A ring of four in which each node connects to the two next to it
+    let config = TopologyConfig {
+        local_connections: 2,
+        longrange_connections: 0,
+        ..TopologyConfig::default()
+    };
+    let mut networks = Vec::new();
+    let mut topologies = Vec::new();
+    for id in 1..=4 {
+        let network = Arc::new(local_network(id, P2PConfig::default()));
+        let port = network.start_listener().await.unwrap().port();
+        networks.push((network, port));
+        topologies.push(SmallWorldTopology::new(id, config.clone()));
+    }
+    for topology in &topologies {
+        for (id, (_, port)) in (1..=4).zip(&networks) {
+            topology.add_node(NodeInfo::new(id, NodeAddress::new("127.0.0.1".to_string(), *port), 100));
+        }
+        topology.build_topology();
+    }
+    for (topology, (network, _)) in topologies.iter().zip(&networks) {
+        topology.serve_neighbors(network);
+    }
+    let (network, _) = &networks[0];
+    for (id, (_, port)) in (2..=4).zip(&networks[1..]) {
+        network.add_peer(id, NodeAddress::new("127.0.0.1".to_string(), *port));
+    }
+
+    // Node 3 is two hops away, through node 2 or node 4
+    let topology = &topologies[0];
+    assert_eq!(topology.find_route(3), None);
+    let route = topology.route_to(network, 3).await.unwrap();
+    assert_eq!(route.len(), 3);
+    assert_eq!((route[0], route[2]), (1, 3));
+    assert!(topology.stale_nodes().is_empty());
+    assert_eq!(topology.known_neighbors(3).map(|neighbors| neighbors.len()), Some(2));
+}
+
+/// Answers `ShardDataRequest`s with `size` zero bytes of shard data
+fn register_bulk_handler(network: &P2PNetwork, size: usize) {
+    network.register_handler(MessageKind::ShardDataRequest, move |msg| {
+        let MessageType::ShardDataRequest { shard_id, .. } = msg.message_type else {
+            return None;
+        };
+        Some(msg.reply(MessageType::ShardDataResponse { shard_id, data: vec![0; size] }))
+    });
+}
+
+#[tokio::test]
+async fn test_large_messages_are_compressed_and_split_into_frames() {
+    // Encoding 50 MiB takes seconds in a debug build
+    let config = P2PConfig {
+        max_frame_size: 16 * 1024,
+        message_timeout_ms: 60_000,
+        ..P2PConfig::default()
+    };
+    let (client, server) = connected_pair(config).await;
+    register_bulk_handler(&server, 50 * 1024 * 1024);
+
+    let reply = client
+        .send_message(2, MessageType::ShardDataRequest { shard_id: 7, op: ShardDataOp::Digest })
+        .await
+        .unwrap();
+    let MessageType::ShardDataResponse { shard_id: 7, data } = reply.message_type else {
+        panic!("Unexpected reply: {:?}", reply.message_type);
+    };
+    assert_eq!(data.len(), 50 * 1024 * 1024);
+    assert!(data.iter().all(|&b| b == 0));
+
+    let stats = client.get_stats();
+    assert!(stats.raw_bytes_received > 50 * 1024 * 1024);
+    assert!(stats.wire_bytes_received < stats.raw_bytes_received / 10, "{:?}", stats);
+
+    // Uncompressed, the same message still arrives in parts
+    let config = P2PConfig {
+        max_frame_size: 1024 * 1024,
+        compression: false,
+        ..P2PConfig::default()
+    };
+    let (client, server) = connected_pair(config).await;
+    register_bulk_handler(&server, 4 * 1024 * 1024);
+    let reply = client
+        .send_message(2, MessageType::ShardDataRequest { shard_id: 7, op: ShardDataOp::Digest })
+        .await
+        .unwrap();
+    assert!(matches!(reply.message_type, MessageType::ShardDataResponse { ref data, .. } if data.len() == 4 * 1024 * 1024));
+    let stats = client.get_stats();
+    assert!(stats.wire_bytes_received >= stats.raw_bytes_received);
+}
+
+#[tokio::test]
+async fn test_oversized_frame_is_refused_before_allocating() {
+    let server = local_network(2, P2PConfig::default());
+    let port = server.start_listener().await.unwrap().port();
+
+    // Announce a frame of nearly 1 GiB and send none of it
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.write_all(&0x3FFF_FFFFu32.to_be_bytes()).await.unwrap();
+
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).await.unwrap();
+    let mut payload = vec![0u8; u32::from_be_bytes(length) as usize];
+    stream.read_exact(&mut payload).await.unwrap();
+    let reply = P2PMessage::from_bytes(&payload).unwrap();
+    assert_eq!(reply.sender_id, 2);
+    match reply.message_type {
+        MessageType::Error { message } => assert!(message.contains("exceeds"), "{}", message),
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+
+    // Then the connection is closed
+    assert_eq!(stream.read(&mut length).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_peer_with_smaller_frame_limit_answers_with_an_error() {
+    let client = local_network(
+        1,
+        P2PConfig {
+            compression: false,
+            ..P2PConfig::default()
+        },
+    );
+    let server = local_network(
+        2,
+        P2PConfig {
+            max_frame_size: 1024,
+            ..P2PConfig::default()
+        },
+    );
+    let port = server.start_listener().await.unwrap().port();
+    client.add_peer(2, NodeAddress::new("127.0.0.1".to_string(), port));
+
+    let reply = client
+        .send_message(2, MessageType::ShardDataResponse { shard_id: 1, data: vec![0; 64 * 1024] })
+        .await
+        .unwrap();
+    match reply.message_type {
+        MessageType::Error { message } => assert!(message.contains("exceeds the 1024 byte limit"), "{}", message),
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+}
@@ -0,0 +1,75 @@
+//! Integration tests for read-only mode in a network partition
+//!
+//! A five-node cluster as node 1 sees it; partitions are simulated by
+//! recording failed and successful contact with the other nodes.
+
+use deed_core::*;
+use std::sync::{Arc, RwLock};
+
+fn cluster_view() -> Arc<PartitionManager> {
+    let manager = Arc::new(PartitionManager::new(1, 5, 3, 10));
+    for node in 2..=5 {
+        manager.add_node(node);
+    }
+    manager
+}
+
+/// Lose contact with nodes 3, 4 and 5, leaving node 1 in the minority
+fn split(manager: &PartitionManager) {
+    for node in 3..=5 {
+        for _ in 0..3 {
+            manager.record_failure(node);
+        }
+    }
+    assert_eq!(manager.check_partition(), PartitionState::Partitioned);
+}
+
+fn executor(manager: &Arc<PartitionManager>, read_consistency: ConsistencyLevel) -> DQLExecutor {
+    DQLExecutor::new(Arc::new(RwLock::new(Graph::new()))).with_partition_manager(manager.clone(), read_consistency)
+}
+
+#[test]
+fn test_minority_partition_refuses_writes_until_healed() {
+    let manager = cluster_view();
+    let executor = executor(&manager, ConsistencyLevel::One);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+
+    split(&manager);
+
+    let err = executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap_err();
+    assert!(is_cluster_degraded(&err), "{}", err);
+    assert!(err.contains("2 of 5 nodes reachable"), "{}", err);
+    let err = executor.execute("UPDATE Users SET name = 'Alicia' WHERE name = 'Alice'").unwrap_err();
+    assert!(is_cluster_degraded(&err), "{}", err);
+
+    // Reads at One are still served, and nothing was written
+    let result = executor.execute("FROM Users SELECT name AS name").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0]["name"], dql_ir::Value::String("Alice".to_string()));
+
+    // A query error isn't mistaken for a degraded cluster
+    let err = executor.execute("FROM Users SELEKT name").unwrap_err();
+    assert!(!is_cluster_degraded(&err), "{}", err);
+
+    // Writes resume once the partition heals, without a new executor
+    for node in 3..=5 {
+        manager.record_heartbeat(node);
+    }
+    assert_eq!(manager.check_partition(), PartitionState::Healthy);
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap();
+    assert_eq!(executor.execute("FROM Users SELECT name AS name").unwrap().rows.len(), 2);
+}
+
+#[test]
+fn test_minority_partition_refuses_reads_it_cant_satisfy() {
+    let manager = cluster_view();
+    let executor = executor(&manager, ConsistencyLevel::All);
+    executor.execute("FROM Users SELECT name AS name").unwrap();
+
+    split(&manager);
+
+    // All needs every one of the three replicas; two nodes are reachable
+    let err = executor.execute("FROM Users SELECT name AS name").unwrap_err();
+    assert!(is_cluster_degraded(&err), "{}", err);
+    assert!(err.contains("All consistency"), "{}", err);
+}
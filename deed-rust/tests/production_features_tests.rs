@@ -2,7 +2,8 @@
 //!
 //! Tests B-tree indexes, authentication, and connection pooling
 
-use deed_rust::*;
+use deed_core::*;
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
@@ -42,6 +43,13 @@ fn test_drop_index() {
     assert!(result.is_ok());
 }
 
+/// Index one value the way inserting an entity would
+fn index_value(index_manager: &IndexManager, index: &str, value: PropertyValue, id: EntityId) -> Result<(), String> {
+    let index = index_manager.get_index(index).ok_or("No such index")?;
+    let properties = HashMap::from([(index.field.clone(), value)]);
+    index_manager.insert_into_indexes(&index.collection, id, &properties)
+}
+
 #[test]
 fn test_unique_index_constraint() {
     let index_manager = IndexManager::new();
@@ -55,19 +63,11 @@ fn test_unique_index_constraint() {
     ).unwrap();
 
     // Insert first value
-    let result1 = index_manager.insert_into_index(
-        "idx_email",
-        &PropertyValue::String("alice@example.com".to_string()),
-        EntityId::new(1),
-    );
+    let result1 = index_value(&index_manager, "idx_email", PropertyValue::String("alice@example.com".to_string()), EntityId::new(1));
     assert!(result1.is_ok());
 
     // Insert duplicate value - should fail
-    let result2 = index_manager.insert_into_index(
-        "idx_email",
-        &PropertyValue::String("alice@example.com".to_string()),
-        EntityId::new(2),
-    );
+    let result2 = index_value(&index_manager, "idx_email", PropertyValue::String("alice@example.com".to_string()), EntityId::new(2));
     assert!(result2.is_err());
     assert!(result2.unwrap_err().contains("already exists"));
 }
@@ -84,22 +84,14 @@ fn test_index_lookup() {
         false,
     ).unwrap();
 
-    index_manager.insert_into_index(
-        "idx_age",
-        &PropertyValue::Integer(25),
-        EntityId::new(1),
-    ).unwrap();
+    index_value(&index_manager, "idx_age", PropertyValue::Int(25), EntityId::new(1)).unwrap();
 
-    index_manager.insert_into_index(
-        "idx_age",
-        &PropertyValue::Integer(25),
-        EntityId::new(2),
-    ).unwrap();
+    index_value(&index_manager, "idx_age", PropertyValue::Int(25), EntityId::new(2)).unwrap();
 
     // Lookup
     let results = index_manager.lookup_in_index(
         "idx_age",
-        &PropertyValue::Integer(25),
+        &PropertyValue::Int(25),
     ).unwrap();
 
     assert_eq!(results.len(), 2);
@@ -119,18 +111,14 @@ fn test_index_range_scan() {
 
     // Insert values
     for (id, price) in [(1, 50), (2, 100), (3, 150), (4, 200)] {
-        index_manager.insert_into_index(
-            "idx_price",
-            &PropertyValue::Integer(price),
-            EntityId::new(id),
-        ).unwrap();
+        index_value(&index_manager, "idx_price", PropertyValue::Int(price), EntityId::new(id)).unwrap();
     }
 
     // Range scan: price >= 100 and price <= 150
     let results = index_manager.range_scan_in_index(
         "idx_price",
-        &PropertyValue::Integer(100),
-        &PropertyValue::Integer(150),
+        Some(&PropertyValue::Int(100)),
+        Some(&PropertyValue::Int(150)),
     ).unwrap();
 
     assert_eq!(results.len(), 2); // Should find IDs 2 and 3
@@ -175,12 +163,12 @@ fn test_auth_login_failure() {
     // Wrong password
     let result = auth.login("charlie", "wrong_password");
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Invalid password"));
+    assert!(result.unwrap_err().contains("Invalid username or password"));
 
-    // Nonexistent user
+    // Nonexistent user, reported the same way
     let result2 = auth.login("nonexistent", "password");
     assert!(result2.is_err());
-    assert!(result2.unwrap_err().contains("not found"));
+    assert!(result2.unwrap_err().contains("Invalid username or password"));
 }
 
 #[test]
@@ -291,7 +279,7 @@ fn test_auth_delete_user() {
 fn create_test_pool() -> ConnectionPool {
     let graph = Arc::new(RwLock::new(Graph::new()));
     let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
-    let cache = Arc::new(RwLock::new(StigmergyCache::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
     let transaction_manager = Arc::new(TransactionManager::new());
 
     ConnectionPool::with_defaults(
@@ -358,7 +346,7 @@ fn test_pool_concurrent_access() {
         let handle = thread::spawn(move || {
             let mut conn = pool_clone.get_connection().unwrap();
             thread::sleep(Duration::from_millis(10));
-            conn.executor().unwrap();
+            let _executor = conn.executor().unwrap();
         });
 
         handles.push(handle);
@@ -377,7 +365,7 @@ fn test_pool_concurrent_access() {
 fn test_pool_max_size_enforcement() {
     let graph = Arc::new(RwLock::new(Graph::new()));
     let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
-    let cache = Arc::new(RwLock::new(StigmergyCache::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
     let transaction_manager = Arc::new(TransactionManager::new());
 
     let config = PoolConfig {
@@ -386,6 +374,11 @@ fn test_pool_max_size_enforcement() {
         connection_timeout: 1, // 1 second timeout
         max_idle_time: 300,
         health_check_enabled: false,
+        query_timeout: None,
+        maintenance_interval: None,
+        audit: None,
+        read_replicas: None,
+        replication: None,
     };
 
     let pool = ConnectionPool::new(
@@ -403,7 +396,7 @@ fn test_pool_max_size_enforcement() {
     // Should timeout trying to get third connection
     let result = pool.get_connection();
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("timeout"));
+    assert!(result.err().unwrap().contains("timeout"));
 }
 
 #[test]
@@ -431,7 +424,7 @@ fn test_integrated_workflow() {
     // Setup all components
     let graph = Arc::new(RwLock::new(Graph::new()));
     let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
-    let cache = Arc::new(RwLock::new(StigmergyCache::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
     let transaction_manager = Arc::new(TransactionManager::new());
 
     let pool = Arc::new(ConnectionPool::with_defaults(
@@ -476,7 +469,7 @@ fn test_concurrent_authenticated_queries() {
     // Setup
     let graph = Arc::new(RwLock::new(Graph::new()));
     let optimizer = Arc::new(RwLock::new(AntColonyOptimizer::new()));
-    let cache = Arc::new(RwLock::new(StigmergyCache::new()));
+    let cache = Arc::new(RwLock::new(StigmergyCache::new(1000)));
     let transaction_manager = Arc::new(TransactionManager::new());
 
     let pool = Arc::new(ConnectionPool::with_defaults(
@@ -543,7 +536,7 @@ fn test_concurrent_authenticated_queries() {
 fn test_permission_denied_scenario() {
     // Setup
     let graph = Arc::new(RwLock::new(Graph::new()));
-    let executor = DQLExecutor::new(graph);
+    let _executor = DQLExecutor::new(graph);
     let auth = AuthManager::new();
 
     // Create read-only user
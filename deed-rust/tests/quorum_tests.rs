@@ -0,0 +1,249 @@
+//! Integration tests for quorum reads and writes
+//!
+//! Most run over a fake transport that reaches each replica's store
+//! directly and can make a replica dead or slow; one runs over the P2P
+//! network.
+
+use deed_core::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const REPLICAS: [NodeId; 3] = [2, 3, 4];
+
+#[derive(Default)]
+struct FakeTransport {
+    replicas: HashMap<NodeId, Arc<LocalReplica>>,
+    dead: RwLock<HashSet<NodeId>>,
+    /// How long each slow replica takes to answer
+    slow: RwLock<HashMap<NodeId, Duration>>,
+}
+
+impl FakeTransport {
+    fn new() -> Arc<Self> {
+        let replicas = REPLICAS.iter().map(|&node| (node, Arc::new(LocalReplica::new()))).collect();
+        Arc::new(Self { replicas, ..Self::default() })
+    }
+
+    fn replica(&self, node: NodeId) -> Result<&LocalReplica, String> {
+        let delay = self.slow.read().unwrap().get(&node).copied();
+        if let Some(delay) = delay {
+            std::thread::sleep(delay);
+        }
+        if self.dead.read().unwrap().contains(&node) {
+            return Err("Connection refused".to_string());
+        }
+        Ok(self.replicas[&node].as_ref())
+    }
+
+    fn kill(&self, node: NodeId) {
+        self.dead.write().unwrap().insert(node);
+    }
+
+    fn revive(&self, node: NodeId) {
+        self.dead.write().unwrap().remove(&node);
+    }
+
+    fn held(&self, node: NodeId, key: &str) -> Option<Vec<u8>> {
+        self.replicas[&node].read(key).map(|held| held.value)
+    }
+}
+
+impl ReplicaTransport for FakeTransport {
+    fn read(&self, node: NodeId, key: &str) -> Result<Option<VersionedValue>, String> {
+        Ok(self.replica(node)?.read(key))
+    }
+
+    fn write(&self, node: NodeId, key: &str, value: &VersionedValue) -> Result<(), String> {
+        self.replica(node)?.write(key, value);
+        Ok(())
+    }
+}
+
+/// Node 1's quorum manager, reading and writing at `consistency`
+fn quorum_manager(transport: Arc<dyn ReplicaTransport>, consistency: ConsistencyLevel) -> QuorumManager {
+    let partitions = Arc::new(PartitionManager::new(1, 4, 3, 10));
+    for node in REPLICAS {
+        partitions.add_node(node);
+    }
+
+    let mut manager = QuorumManager::new(1, 3, partitions)
+        .with_transport(transport)
+        .with_timeout(Duration::from_millis(200));
+    manager.set_read_consistency(consistency);
+    manager.set_write_consistency(consistency);
+    manager
+}
+
+/// Wait up to a second for `condition` to hold
+async fn eventually(condition: impl Fn() -> bool) -> bool {
+    let started = Instant::now();
+    while !condition() {
+        if started.elapsed() > Duration::from_secs(1) {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    true
+}
+
+#[tokio::test]
+async fn test_quorum_write_tolerates_one_dead_replica() {
+    let transport = FakeTransport::new();
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::Quorum);
+    transport.kill(2);
+
+    manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    assert_eq!(transport.held(3, "user:1"), Some(b"Alice".to_vec()));
+    assert_eq!(transport.held(4, "user:1"), Some(b"Alice".to_vec()));
+
+    let value = manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap();
+    assert_eq!(value, Some(b"Alice".to_vec()));
+    assert_eq!(manager.quorum_read("user:2", REPLICAS.to_vec()).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_quorum_write_fails_with_two_dead_replicas() {
+    let transport = FakeTransport::new();
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::Quorum);
+    transport.kill(2);
+    transport.kill(3);
+
+    // The write gives up once two replicas have failed, whether or not the
+    // live one has answered yet
+    let err = manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap_err();
+    assert!(err.starts_with("Write failed - only "), "{}", err);
+    assert!(err.contains("Connection refused"), "{}", err);
+
+    let err = manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap_err();
+    assert!(err.starts_with("Read failed"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_slow_replica_does_not_hold_up_a_quorum() {
+    let transport = FakeTransport::new();
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::Quorum).with_timeout(Duration::from_secs(5));
+    transport.slow.write().unwrap().insert(2, Duration::from_millis(500));
+
+    let started = Instant::now();
+    manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+
+    // The slow replica still gets the write
+    assert!(eventually(|| transport.held(2, "user:1").is_some()).await);
+}
+
+#[tokio::test]
+async fn test_timeouts_count_as_failures() {
+    let transport = FakeTransport::new();
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::Quorum);
+    for node in [2, 3] {
+        transport.slow.write().unwrap().insert(node, Duration::from_millis(500));
+    }
+
+    let started = Instant::now();
+    let err = manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap_err();
+    assert!(err.contains("2 replicas timed out"), "{}", err);
+    assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn test_read_repair_converges_a_stale_replica() {
+    let transport = FakeTransport::new();
+    let quorum = quorum_manager(transport.clone(), ConsistencyLevel::Quorum);
+    quorum.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap();
+
+    // Node 4 misses the update
+    transport.kill(4);
+    quorum.quorum_write("user:1", b"Alicia".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    transport.revive(4);
+    assert_eq!(transport.held(4, "user:1"), Some(b"Alice".to_vec()));
+
+    // A read that hears from it returns the newest value and repairs it
+    let all = quorum_manager(transport.clone(), ConsistencyLevel::All);
+    let value = all.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap();
+    assert_eq!(value, Some(b"Alicia".to_vec()));
+    assert!(eventually(|| transport.held(4, "user:1") == Some(b"Alicia".to_vec())).await);
+}
+
+#[tokio::test]
+async fn test_versions_order_concurrent_and_skewed_writes() {
+    // Same timestamp from two coordinators: the higher node wins on every replica,
+    // whichever write arrives first
+    let tie = |node: NodeId, value: &[u8]| VersionedValue {
+        value: value.to_vec(),
+        version: Version { timestamp: 1_000, node },
+    };
+    let (early, late) = (LocalReplica::new(), LocalReplica::new());
+    early.write("user:1", &tie(1, b"Alice"));
+    early.write("user:1", &tie(5, b"Alicia"));
+    late.write("user:1", &tie(5, b"Alicia"));
+    late.write("user:1", &tie(1, b"Alice"));
+    assert_eq!(early.read("user:1"), late.read("user:1"));
+    assert_eq!(early.read("user:1").unwrap().value, b"Alicia".to_vec());
+
+    // A write from a node whose clock runs far ahead...
+    let transport = FakeTransport::new();
+    let ahead = VersionedValue {
+        value: b"Alice".to_vec(),
+        version: Version { timestamp: u64::MAX / 2, node: 9 },
+    };
+    for node in REPLICAS {
+        transport.write(node, "user:1", &ahead).unwrap();
+    }
+
+    // ...is still superseded by a write coordinated after reading it
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::Quorum);
+    manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap();
+    manager.quorum_write("user:1", b"Alicia".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    assert_eq!(manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap(), Some(b"Alicia".to_vec()));
+}
+
+#[tokio::test]
+async fn test_all_requires_every_replica() {
+    let transport = FakeTransport::new();
+    let manager = quorum_manager(transport.clone(), ConsistencyLevel::All);
+    manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap();
+
+    transport.kill(4);
+    let err = manager.quorum_write("user:1", b"Alicia".to_vec(), REPLICAS.to_vec()).await.unwrap_err();
+    assert!(err.contains("only 2 of 3 required nodes succeeded"), "{}", err);
+    let err = manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap_err();
+    assert!(err.contains("only 2 of 3 required nodes succeeded"), "{}", err);
+
+    transport.revive(4);
+    manager.quorum_write("user:1", b"Alicia".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    assert!(REPLICAS.iter().all(|&node| transport.held(node, "user:1") == Some(b"Alicia".to_vec())));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_quorum_over_p2p() {
+    let network = |id: NodeId| {
+        let config = P2PConfig {
+            listen_port: 0,
+            insecure: true,
+            ..P2PConfig::default()
+        };
+        Arc::new(P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config))
+    };
+
+    let coordinator = network(1);
+    let mut replicas = Vec::new();
+    for node in REPLICAS {
+        let replica_network = network(node);
+        let port = replica_network.start_listener().await.unwrap().port();
+        let replica = Arc::new(LocalReplica::new());
+        replica.serve(&replica_network);
+        coordinator.add_peer(node, NodeAddress::new("127.0.0.1".to_string(), port));
+        replicas.push((replica_network, replica));
+    }
+
+    let transport = P2PReplicaTransport::new(coordinator, tokio::runtime::Handle::current());
+    let manager = quorum_manager(Arc::new(transport), ConsistencyLevel::Quorum);
+    manager.quorum_write("user:1", b"Alice".to_vec(), REPLICAS.to_vec()).await.unwrap();
+    let value = manager.quorum_read("user:1", REPLICAS.to_vec()).await.unwrap();
+    assert_eq!(value, Some(b"Alice".to_vec()));
+
+    // Every replica got the write, though only two were waited for
+    assert!(eventually(|| replicas.iter().all(|(_, replica)| replica.read("user:1").is_some())).await);
+}
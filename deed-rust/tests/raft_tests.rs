@@ -0,0 +1,164 @@
+//! Integration tests for Raft consensus
+//!
+//! Each node runs on its own Tokio runtime over loopback, so shutting its
+//! runtime down kills it. Committed commands are DQL, applied to each
+//! node's own graph.
+
+use deed_core::*;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+struct Node {
+    raft: RaftNode,
+    executor: Arc<DQLExecutor>,
+    runtime: Option<Runtime>,
+}
+
+impl Node {
+    fn users(&self) -> usize {
+        self.executor.execute("FROM Users SELECT name AS name").unwrap().rows.len()
+    }
+
+    fn is_alive(&self) -> bool {
+        self.runtime.is_some()
+    }
+
+    fn kill(&mut self) {
+        self.runtime.take().unwrap().shutdown_background();
+    }
+}
+
+/// A started cluster of `size` nodes, numbered from 1
+fn cluster(size: NodeId) -> Vec<Node> {
+    let ids: Vec<NodeId> = (1..=size).collect();
+    let mut networks = Vec::new();
+    for &id in &ids {
+        let runtime = Runtime::new().unwrap();
+        let config = P2PConfig {
+            listen_port: 0,
+            insecure: true,
+            ..P2PConfig::default()
+        };
+        let network = Arc::new(P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config));
+        let port = runtime.block_on(network.start_listener()).unwrap().port();
+        networks.push((id, network, port, runtime));
+    }
+
+    let mut nodes = Vec::new();
+    for (id, network, _, _) in &networks {
+        for (peer, _, port, _) in &networks {
+            if peer != id {
+                network.add_peer(*peer, NodeAddress::new("127.0.0.1".to_string(), *port));
+            }
+        }
+        let executor = Arc::new(DQLExecutor::new(Arc::new(RwLock::new(Graph::new()))));
+        let raft = RaftNode::new(*id, RaftConfig::default(), network.clone()).with_state_machine(executor.clone());
+        for &node_id in &ids {
+            raft.add_cluster_node(node_id);
+        }
+        nodes.push((raft, executor));
+    }
+
+    nodes
+        .into_iter()
+        .zip(networks)
+        .map(|((raft, executor), (_, _, _, runtime))| {
+            let _guard = runtime.enter();
+            raft.start();
+            Node {
+                raft,
+                executor,
+                runtime: Some(runtime),
+            }
+        })
+        .collect()
+}
+
+/// Wait up to `limit` for `condition` to hold
+fn wait_for(limit: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < limit {
+        if condition() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    condition()
+}
+
+/// The leader every live node agrees on, if there is exactly one
+fn agreed_leader(nodes: &[Node]) -> Option<NodeId> {
+    let alive: Vec<&Node> = nodes.iter().filter(|n| n.is_alive()).collect();
+    let leaders: Vec<&Node> = alive.iter().copied().filter(|n| n.raft.is_leader()).collect();
+    let [leader] = leaders[..] else {
+        return None;
+    };
+    let leader_id = leader.raft.get_statistics().node_id;
+    alive
+        .iter()
+        .all(|n| n.raft.get_current_leader() == Some(leader_id) && n.raft.get_current_term() == leader.raft.get_current_term())
+        .then_some(leader_id)
+}
+
+/// Wait for the leader's entry at `index` to be committed
+fn wait_for_commit(nodes: &[Node], leader: usize, index: LogIndex) -> bool {
+    wait_for(Duration::from_secs(2), || {
+        let replicas = nodes.iter().filter(|n| n.is_alive() && n.raft.get_statistics().log_length as LogIndex >= index).count();
+        nodes[leader].raft.get_statistics().commit_index >= index && replicas >= 2
+    })
+}
+
+#[test]
+fn test_cluster_elects_one_leader_and_replicates() {
+    let nodes = cluster(3);
+
+    assert!(wait_for(Duration::from_secs(3), || agreed_leader(&nodes).is_some()), "no leader elected");
+    let leader = agreed_leader(&nodes).unwrap();
+    let leader = nodes.iter().position(|n| n.raft.get_statistics().node_id == leader).unwrap();
+
+    // Followers refuse writes
+    let follower = (leader + 1) % nodes.len();
+    assert!(nodes[follower].raft.append_entry(b"refused".to_vec()).is_err());
+
+    let index = nodes[leader].raft.append_entry(b"INSERT INTO Users VALUES ({name: 'Alice'})".to_vec()).unwrap();
+    assert_eq!(index, 1);
+    assert!(wait_for_commit(&nodes, leader, index), "entry not committed");
+
+    // The followers learn it's committed from the next heartbeat, and
+    // every node applies it
+    assert!(wait_for(Duration::from_secs(2), || {
+        nodes.iter().all(|n| n.raft.get_statistics().last_applied == index)
+    }));
+    assert!(nodes.iter().all(|n| n.users() == 1));
+
+    // Later heartbeats don't apply it again
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(nodes.iter().all(|n| n.users() == 1 && n.raft.get_statistics().last_applied == index));
+}
+
+#[test]
+fn test_killing_the_leader_triggers_reelection() {
+    let mut nodes = cluster(3);
+
+    assert!(wait_for(Duration::from_secs(3), || agreed_leader(&nodes).is_some()), "no leader elected");
+    let old_leader = agreed_leader(&nodes).unwrap();
+    let old = nodes.iter().position(|n| n.raft.get_statistics().node_id == old_leader).unwrap();
+    let old_term = nodes[old].raft.get_current_term();
+
+    nodes[old].kill();
+
+    // A new leader within a few election timeouts
+    let started = Instant::now();
+    assert!(wait_for(Duration::from_secs(2), || agreed_leader(&nodes).is_some()), "no leader re-elected");
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let new_leader = agreed_leader(&nodes).unwrap();
+    assert_ne!(new_leader, old_leader);
+    let leader = nodes.iter().position(|n| n.raft.get_statistics().node_id == new_leader).unwrap();
+    assert!(nodes[leader].raft.get_current_term() > old_term);
+
+    // The two survivors are still a majority
+    let index = nodes[leader].raft.append_entry(b"INSERT INTO Users VALUES ({name: 'Bob'})".to_vec()).unwrap();
+    assert!(wait_for_commit(&nodes, leader, index), "entry not committed");
+}
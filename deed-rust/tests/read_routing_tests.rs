@@ -0,0 +1,226 @@
+//! Integration tests for read/write splitting
+//!
+//! Each replica's graph holds a single user named after the replica, so a
+//! read's answer shows where it ran.
+
+use deed_core::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A health check that can be made to fail, counting how often it ran
+struct FakeHealth {
+    healthy: AtomicBool,
+    pings: AtomicUsize,
+}
+
+impl FakeHealth {
+    fn new() -> Arc<Self> {
+        Arc::new(FakeHealth {
+            healthy: AtomicBool::new(true),
+            pings: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl ReplicaHealth for FakeHealth {
+    fn ping(&self) -> Result<(), String> {
+        self.pings.fetch_add(1, Ordering::SeqCst);
+        if self.healthy.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err("Replica unreachable".to_string())
+        }
+    }
+}
+
+fn replica_graph(name: &str) -> Arc<RwLock<Graph>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let mut props = types::Properties::new();
+    props.insert("name".to_string(), PropertyValue::String(name.to_string()));
+    props.insert("age".to_string(), PropertyValue::Int(1));
+    graph.read().unwrap().add_entity("Users".to_string(), props).unwrap();
+    graph
+}
+
+fn routing(config: RoutingConfig) -> (RoutingExecutor, Arc<FakeHealth>, Arc<FakeHealth>) {
+    let (first, second) = (FakeHealth::new(), FakeHealth::new());
+    let master = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    let router = RoutingExecutor::with_config(master, config)
+        .with_checked_replica("replica-1", replica_graph("Replica-1"), first.clone())
+        .with_checked_replica("replica-2", replica_graph("Replica-2"), second.clone());
+    (router, first, second)
+}
+
+fn fresh_checks() -> RoutingConfig {
+    RoutingConfig {
+        health_interval: Duration::ZERO,
+        ..RoutingConfig::default()
+    }
+}
+
+fn served_by(res: &QueryResult) -> String {
+    match &res.rows[0]["name"] {
+        dql_ir::Value::String(name) => name.clone(),
+        other => panic!("Unexpected name: {:?}", other),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[test]
+fn test_reads_spread_over_replicas_and_writes_go_to_master() {
+    let (router, _, _) = routing(fresh_checks());
+
+    router.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    router.execute("UPDATE Users SET age = 30 WHERE name = 'Alice'").unwrap();
+    let mut served = Vec::new();
+    for _ in 0..4 {
+        served.push(served_by(&router.execute("FROM Users SELECT name AS name").unwrap()));
+    }
+    assert_eq!(served, ["Replica-1", "Replica-2", "Replica-1", "Replica-2"]);
+    let explained = router.execute("EXPLAIN ANALYZE FROM Users SELECT name").unwrap();
+    assert!(explained.row_count() > 0);
+
+    // The writes landed on the master only
+    let res = router.execute_on_primary("FROM Users SELECT name AS name, age AS age").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(served_by(&res), "Alice");
+    assert_eq!(res.rows[0]["age"], dql_ir::Value::Integer(30));
+
+    // The hint keeps a read on the master too
+    let hinted = router.execute("/*+ primary */ FROM Users SELECT name AS name").unwrap();
+    assert_eq!(served_by(&hinted), "Alice");
+    assert_eq!(router.route("/*+ primary */ FROM Users SELECT name"), Route::Primary);
+
+    // System collections, writes and unparseable statements go to the master
+    assert_eq!(router.route(&format!("FROM {} SELECT collection", COLLECTIONS_COLLECTION)), Route::Primary);
+    assert_eq!(router.route("DELETE FROM Users"), Route::Primary);
+    assert_eq!(router.route("FROM Users SELEKT name"), Route::Primary);
+}
+
+#[test]
+fn test_unhealthy_and_lagging_replicas_fall_back_to_master() {
+    let replication = Arc::new(ReplicationManager::new_master("master".to_string()));
+    replication.register_slave("replica-1".to_string()).unwrap();
+    replication.register_slave("replica-2".to_string()).unwrap();
+    replication.log_delete(1).unwrap();
+    let seq = replication.current_seq();
+
+    let (router, first, _) = routing(RoutingConfig {
+        balancing: ReadBalancing::LeastLagged,
+        max_lag: Some(Duration::from_secs(5)),
+        health_interval: Duration::ZERO,
+    });
+    let router = router.with_replication(replication.clone());
+
+    // Replicas the master has heard nothing from aren't read
+    assert_eq!(router.route("FROM Users SELECT name"), Route::Primary);
+
+    // replica-1 is current; replica-2 applied an entry from a minute ago
+    replication.update_slave_applied("replica-1", seq, now_ms()).unwrap();
+    replication.update_slave_applied("replica-2", seq - 1, now_ms() - 60_000).unwrap();
+    for _ in 0..4 {
+        let res = router.execute("FROM Users SELECT name AS name").unwrap();
+        assert_eq!(served_by(&res), "Replica-1");
+    }
+
+    // With replica-1 down, replica-2 is past the lag threshold
+    first.healthy.store(false, Ordering::SeqCst);
+    assert_eq!(router.route("FROM Users SELECT name"), Route::Primary);
+    assert_eq!(router.execute("FROM Users SELECT name").unwrap().row_count(), 0);
+
+    replication.update_slave_applied("replica-2", seq, now_ms()).unwrap();
+    assert_eq!(router.route("FROM Users SELECT name"), Route::Replica("replica-2".to_string()));
+}
+
+#[test]
+fn test_health_checks_are_cached_between_reads() {
+    let (router, first, second) = routing(RoutingConfig {
+        health_interval: Duration::from_secs(3600),
+        ..RoutingConfig::default()
+    });
+
+    for _ in 0..10 {
+        router.execute("FROM Users SELECT name").unwrap();
+    }
+    assert_eq!(first.pings.load(Ordering::SeqCst), 1);
+    assert_eq!(second.pings.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_reads_in_a_transaction_stay_on_master() {
+    let (router, _, _) = routing(fresh_checks());
+
+    router.execute("BEGIN TRANSACTION").unwrap();
+    router.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    assert_eq!(router.route("FROM Users SELECT name"), Route::Primary);
+    let res = router.execute("FROM Users SELECT name AS name").unwrap();
+    assert_eq!(served_by(&res), "Alice");
+    router.execute("COMMIT").unwrap();
+
+    // Once the transaction ends, reads go back to the replicas
+    let res = router.execute("FROM Users SELECT name AS name").unwrap();
+    assert!(served_by(&res).starts_with("Replica-"));
+}
+
+#[test]
+fn test_routed_reads_are_authorized_and_use_session_settings() {
+    let firewall = Arc::new(Firewall::new());
+    let master = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())))
+        .with_firewall(firewall.clone(), FirewallPrincipal::api_key("public"));
+    let admin = master
+        .clone()
+        .with_firewall(firewall.clone(), FirewallPrincipal::user("admin", Role::Admin));
+    let router = RoutingExecutor::with_config(master, fresh_checks()).with_replica("replica-1", replica_graph("Replica-1"));
+
+    // The firewall sees reads a replica would serve
+    admin
+        .execute("FIREWALL DENY no_unbounded FOR KEY 'public' WHEN UNBOUNDED")
+        .unwrap();
+    let err = router.execute("FROM Users SELECT name").unwrap_err();
+    assert!(err.starts_with("FirewallRejected"), "Unexpected error: {}", err);
+    admin.execute("FIREWALL DROP no_unbounded").unwrap();
+
+    // Session settings apply to them
+    let query = "FROM Users u SELECT LENGTH(u.age) AS n";
+    assert_eq!(router.execute(query).unwrap().rows[0]["n"], dql_ir::Value::Null);
+    router.execute("SET strict_functions = on").unwrap();
+    let err = router.execute(query).unwrap_err();
+    assert!(err.contains("LENGTH expects a string"), "Unexpected error: {}", err);
+
+    // And a shut-down session serves none
+    router.master().shutdown(Duration::from_secs(1)).unwrap();
+    assert!(router.execute("FROM Users SELECT name").is_err());
+}
+
+#[test]
+fn test_pooled_connections_share_the_read_replicas() {
+    let replicas = Arc::new(ReadReplicas::new());
+    replicas.attach("replica-1", replica_graph("Replica-1"), None);
+    replicas.set_routing(Some(fresh_checks()));
+
+    let pool = ConnectionPool::new(
+        Arc::new(RwLock::new(Graph::new())),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        PoolConfig {
+            maintenance_interval: None,
+            read_replicas: Some(replicas.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut conn = pool.get_connection().unwrap();
+    conn.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    assert_eq!(served_by(&conn.execute("FROM Users SELECT name AS name").unwrap()), "Replica-1");
+
+    // Turning routing off applies to every connection
+    replicas.set_routing(None);
+    assert_eq!(served_by(&conn.execute("FROM Users SELECT name AS name").unwrap()), "Alice");
+}
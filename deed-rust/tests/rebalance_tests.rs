@@ -0,0 +1,369 @@
+//! Integration tests for shard rebalancing
+//!
+//! Most run over an in-process transport that calls each node's shards
+//! directly; one runs over the P2P network.
+
+use deed_core::distributed_rebalance::shard_key;
+use deed_core::distributed_shard::ShardConfig;
+use deed_core::types::Properties;
+use deed_core::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const USERS: u64 = 120;
+
+/// Calls each node's shards directly, recording what it's asked
+#[derive(Default)]
+struct InProcessTransport {
+    nodes: RwLock<HashMap<NodeId, Arc<LocalShards>>>,
+    /// Fetches, as (shard, after)
+    fetches: Mutex<Vec<(ShardId, Option<EntityId>)>>,
+    /// Fail the next store that isn't a transfer's first batch
+    interrupt: AtomicBool,
+    interrupted: Mutex<Option<ShardId>>,
+    active_stores: AtomicUsize,
+    max_active_stores: AtomicUsize,
+}
+
+impl InProcessTransport {
+    fn node(&self, node: NodeId) -> Result<Arc<LocalShards>, String> {
+        self.nodes.read().unwrap().get(&node).cloned().ok_or_else(|| format!("Unknown node {}", node))
+    }
+}
+
+impl ShardTransport for InProcessTransport {
+    fn fetch(&self, node: NodeId, shard_id: ShardId, after: Option<EntityId>, limit: usize) -> Result<ShardBatch, String> {
+        self.fetches.lock().unwrap().push((shard_id, after));
+        self.node(node)?.fetch(shard_id, after, limit)
+    }
+
+    fn store(&self, node: NodeId, batch: &ShardBatch) -> Result<(), String> {
+        let first_batch = self.fetches.lock().unwrap().iter().filter(|(shard, _)| *shard == batch.shard_id).count() == 1;
+        if !first_batch && self.interrupt.swap(false, Ordering::SeqCst) {
+            *self.interrupted.lock().unwrap() = Some(batch.shard_id);
+            return Err("Connection reset".to_string());
+        }
+
+        let active = self.active_stores.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_active_stores.fetch_max(active, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(2));
+        let stored = self.node(node)?.store(batch);
+        self.active_stores.fetch_sub(1, Ordering::SeqCst);
+        stored
+    }
+
+    fn digest(&self, node: NodeId, shard_id: ShardId) -> Result<ShardDigest, String> {
+        self.node(node)?.digest(shard_id)
+    }
+
+    fn delete(&self, node: NodeId, shard_id: ShardId) -> Result<(), String> {
+        self.node(node)?.delete(shard_id)
+    }
+
+    fn fence(&self, node: NodeId, shard_id: ShardId, fenced: bool) -> Result<(), String> {
+        self.node(node)?.fence(shard_id, fenced)
+    }
+}
+
+/// A cluster of `nodes` holding `USERS` users, each following the next,
+/// placed where their shards are assigned
+struct Cluster {
+    shards: Arc<ShardManager>,
+    graphs: HashMap<NodeId, Arc<RwLock<Graph>>>,
+    executors: HashMap<NodeId, DQLExecutor>,
+}
+
+impl Cluster {
+    fn new(replication_factor: usize, nodes: &[NodeId]) -> Self {
+        let shards = Arc::new(ShardManager::new(ShardConfig {
+            total_shards: 16,
+            replication_factor,
+            ..ShardConfig::default()
+        }));
+        for &node in nodes {
+            shards.add_node(node);
+        }
+        let mut cluster = Cluster {
+            shards,
+            graphs: HashMap::new(),
+            executors: HashMap::new(),
+        };
+        for &node in nodes {
+            cluster.local_shards(node);
+        }
+
+        for i in 1..=USERS {
+            let mut properties = Properties::new();
+            properties.insert("id".to_string(), PropertyValue::Int(i as i64));
+            properties.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            let user = Entity::new(EntityId::new(i), "Users".to_string(), properties);
+            let follows = Edge::new(
+                EdgeId::new(i),
+                user.id,
+                EntityId::new(i % USERS + 1),
+                "FOLLOWS".to_string(),
+                Properties::new(),
+            );
+            for node in cluster.holders(&user) {
+                let graph = cluster.graphs[&node].read().unwrap();
+                graph.insert_entity_with_id(user.clone()).unwrap();
+                graph.insert_edge_with_id(follows.clone()).unwrap();
+            }
+        }
+        cluster
+    }
+
+    /// A node's shards, starting it with an empty graph if it's new
+    fn node_shards(&mut self, node: NodeId) -> LocalShards {
+        let graph = self.graphs.entry(node).or_insert_with(|| Arc::new(RwLock::new(Graph::new())));
+        let executor = self.executors.entry(node).or_insert_with(|| DQLExecutor::new(graph.clone()));
+        LocalShards::new(executor.clone(), self.shards.clone())
+    }
+
+    fn local_shards(&mut self, node: NodeId) -> Arc<LocalShards> {
+        Arc::new(self.node_shards(node))
+    }
+
+    fn holders(&self, entity: &Entity) -> HashSet<NodeId> {
+        let shard_id = self.shards.get_shard_for_key(&shard_key(entity)).unwrap();
+        let mut holders: HashSet<NodeId> = self.shards.get_replicas_for_shard(shard_id).into_iter().collect();
+        holders.insert(self.shards.get_node_for_shard(shard_id).unwrap());
+        holders
+    }
+
+    fn transport(&mut self) -> Arc<InProcessTransport> {
+        let transport = Arc::new(InProcessTransport::default());
+        let nodes: Vec<NodeId> = self.graphs.keys().copied().collect();
+        for node in nodes {
+            let shards = self.local_shards(node);
+            transport.nodes.write().unwrap().insert(node, shards);
+        }
+        transport
+    }
+
+    /// Every user and follow is on exactly the nodes its shard is assigned
+    fn assert_placement(&self) {
+        let mut entity_nodes: HashMap<u64, HashSet<NodeId>> = HashMap::new();
+        let mut edge_nodes: HashMap<u64, HashSet<NodeId>> = HashMap::new();
+        for (&node, graph) in &self.graphs {
+            let graph = graph.read().unwrap();
+            for entity in graph.get_all_entities() {
+                assert_eq!(entity.get_property("name"), Some(&PropertyValue::String(format!("User{}", entity.id.as_u64()))));
+                assert!(entity_nodes.entry(entity.id.as_u64()).or_default().insert(node));
+            }
+            for edge in graph.get_all_edges() {
+                assert!(edge_nodes.entry(edge.id.as_u64()).or_default().insert(node));
+            }
+        }
+
+        assert_eq!(entity_nodes.len() as u64, USERS);
+        assert_eq!(edge_nodes.len() as u64, USERS);
+        for i in 1..=USERS {
+            let graph = self.graphs.values().find(|g| g.read().unwrap().get_entity(EntityId::new(i)).is_some()).unwrap();
+            let user = graph.read().unwrap().get_entity(EntityId::new(i)).unwrap();
+            let holders = self.holders(&user);
+            assert_eq!(entity_nodes[&i], holders, "user {}", i);
+            assert_eq!(edge_nodes[&i], holders, "follow from user {}", i);
+        }
+    }
+}
+
+#[test]
+fn test_rebalance_moves_data_to_a_new_node() {
+    let mut cluster = Cluster::new(2, &[1, 2, 3]);
+    cluster.assert_placement();
+
+    let operations = cluster.shards.join_node(4);
+    cluster.local_shards(4);
+    assert!(!operations.is_empty());
+    let moved: HashSet<ShardId> = operations.iter().map(|op| op.shard_id).collect();
+    let transport = cluster.transport();
+
+    // Nothing has moved yet, and routing still agrees with the data
+    assert!(cluster.shards.get_shards_for_node(4).is_empty());
+    cluster.assert_placement();
+
+    let config = RebalanceConfig {
+        max_concurrent_transfers: 2,
+        batch_size: 3,
+        ..RebalanceConfig::default()
+    };
+    Rebalancer::new(cluster.shards.clone(), transport.clone()).with_config(config).run(operations).unwrap();
+
+    assert!(!cluster.shards.get_shards_for_node(4).is_empty());
+    assert!(cluster.shards.plan_rebalance().is_empty());
+    cluster.assert_placement();
+
+    let progress = cluster.shards.get_statistics().rebalance;
+    assert_eq!(
+        progress,
+        RebalanceProgress {
+            pending: 0,
+            in_flight: 0,
+            done: moved.len(),
+            failed: 0
+        }
+    );
+    assert!(transport.max_active_stores.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn test_interrupted_transfer_resumes() {
+    let mut cluster = Cluster::new(1, &[1, 2]);
+    let operations = cluster.shards.join_node(3);
+    cluster.local_shards(3);
+    let transport = cluster.transport();
+    transport.interrupt.store(true, Ordering::SeqCst);
+
+    let config = RebalanceConfig {
+        max_concurrent_transfers: 1,
+        batch_size: 2,
+        ..RebalanceConfig::default()
+    };
+    let rebalancer = Rebalancer::new(cluster.shards.clone(), transport.clone()).with_config(config);
+    let err = rebalancer.run(operations).unwrap_err();
+    assert!(err.contains("Connection reset"), "{}", err);
+    assert_eq!(cluster.shards.rebalance_progress().failed, 1);
+
+    // The interrupted shard is still routed to its source
+    let interrupted = transport.interrupted.lock().unwrap().unwrap();
+    let remaining = cluster.shards.plan_rebalance();
+    assert_eq!(remaining.iter().map(|op| op.shard_id).collect::<Vec<_>>(), vec![interrupted]);
+    assert_eq!(cluster.shards.get_node_for_shard(interrupted), Some(remaining[0].from_node));
+
+    // Running what's left picks up after the batch that arrived
+    transport.fetches.lock().unwrap().clear();
+    rebalancer.run(remaining).unwrap();
+    let (shard_id, after) = transport.fetches.lock().unwrap()[0];
+    assert_eq!(shard_id, interrupted);
+    assert!(after.is_some());
+
+    assert!(cluster.shards.plan_rebalance().is_empty());
+    cluster.assert_placement();
+}
+
+#[test]
+fn test_rebalance_is_throttled() {
+    let mut cluster = Cluster::new(1, &[1]);
+    let operations = cluster.shards.join_node(2);
+    cluster.local_shards(2);
+    let moved: HashSet<ShardId> = operations.iter().map(|op| op.shard_id).collect();
+    let entities = cluster.graphs[&1]
+        .read()
+        .unwrap()
+        .get_all_entities()
+        .iter()
+        .filter(|e| moved.contains(&cluster.shards.get_shard_for_key(&shard_key(e)).unwrap()))
+        .count();
+    assert!(entities > 20);
+    let transport = cluster.transport();
+
+    // The budget is shared, so concurrency doesn't get around it
+    let config = RebalanceConfig {
+        max_concurrent_transfers: 4,
+        batch_size: 5,
+        max_entities_per_sec: Some(200),
+        ..RebalanceConfig::default()
+    };
+    let started = Instant::now();
+    Rebalancer::new(cluster.shards.clone(), transport).with_config(config).run(operations).unwrap();
+
+    // The first batch goes straight away; the rest wait their turn
+    let floor = Duration::from_secs_f64((entities - 5) as f64 / 200.0);
+    assert!(started.elapsed() >= floor, "{:?} < {:?}", started.elapsed(), floor);
+    cluster.assert_placement();
+}
+
+#[test]
+fn test_rebalance_over_p2p() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut cluster = Cluster::new(1, &[1]);
+    let operations = cluster.shards.join_node(2);
+    assert!(operations.iter().all(|op| op.operation_type == RebalanceType::Move));
+
+    // Node 2 serves its shards over the network; node 1 coordinates and
+    // reaches its own directly
+    let network = |id: NodeId| {
+        let config = P2PConfig {
+            listen_port: 0,
+            insecure: true,
+            ..P2PConfig::default()
+        };
+        Arc::new(P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config))
+    };
+    let (coordinator, node2) = (network(1), network(2));
+    let port = runtime.block_on(node2.start_listener()).unwrap().port();
+    cluster.local_shards(2).serve(&node2);
+    coordinator.add_peer(2, NodeAddress::new("127.0.0.1".to_string(), port));
+
+    // Plaintext doesn't say who's asking, so node 2 must be told to answer anyway
+    let digest = MessageType::ShardDataRequest { shard_id: 0, op: ShardDataOp::Digest };
+    let refused = runtime.block_on(coordinator.send_message(2, digest)).unwrap();
+    assert!(
+        matches!(&refused.message_type, MessageType::Error { message } if message.starts_with("Permission denied")),
+        "{:?}",
+        refused.message_type
+    );
+    Arc::new(cluster.node_shards(2).with_unverified_peers()).serve(&node2);
+
+    let transport = P2PShardTransport::new(coordinator, runtime.handle().clone())
+        .with_local_shards(1, cluster.local_shards(1));
+    Rebalancer::new(cluster.shards.clone(), Arc::new(transport)).run(operations).unwrap();
+
+    assert!(!cluster.graphs[&2].read().unwrap().get_all_entities().is_empty());
+    cluster.assert_placement();
+}
+
+#[test]
+fn test_moving_shard_refuses_writes() {
+    let mut cluster = Cluster::new(1, &[1]);
+    let shards = cluster.local_shards(1);
+    let executor = cluster.executors[&1]
+        .clone()
+        .with_shard_ownership(ShardOwnership::new(1, cluster.shards.clone()));
+    let shard_id = cluster.shards.get_shard_for_key("1").unwrap();
+
+    shards.fence(shard_id, true).unwrap();
+    let err = executor.execute("INSERT INTO Users VALUES ({id: 1, name: 'Again'})").unwrap_err();
+    assert!(err.contains("is being moved"), "{}", err);
+    let err = executor.execute("UPDATE Users SET name = 'Renamed'").unwrap_err();
+    assert!(err.contains("is being moved"), "{}", err);
+
+    // The fenced shard is read from its ID list, not the whole graph
+    let batch = shards.fetch(shard_id, None, USERS as usize).unwrap();
+    assert!(batch.last && batch.entities.iter().all(|e| cluster.shards.get_shard_for_key(&shard_key(e)) == Some(shard_id)));
+    assert_eq!(shards.digest(shard_id).unwrap().entities, batch.entities.len());
+
+    shards.fence(shard_id, false).unwrap();
+    assert!(shards.fetch(shard_id, None, 1).unwrap_err().contains("isn't fenced"));
+    executor.execute("UPDATE Users SET name = 'User1' WHERE id = 1").unwrap();
+}
+
+#[test]
+fn test_moved_data_is_logged() {
+    let dir = std::env::temp_dir().join(format!("deed_rebalance_wal_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut cluster = Cluster::new(1, &[1]);
+    let operations = cluster.shards.join_node(2);
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let wal = dir.join("node2.wal");
+    cluster.graphs.insert(2, graph.clone());
+    cluster.executors.insert(2, DQLExecutor::new_with_wal(graph.clone(), &wal).unwrap());
+    let transport = cluster.transport();
+    Rebalancer::new(cluster.shards.clone(), transport).run(operations).unwrap();
+    cluster.assert_placement();
+
+    // Node 2 rebuilds what it was sent from its WAL alone, edges to node 1's users included
+    let recovered = Arc::new(RwLock::new(Graph::new()));
+    DQLExecutor::recover_from_wal(recovered.clone(), &wal).unwrap();
+    let (recovered, graph) = (recovered.read().unwrap(), graph.read().unwrap());
+    assert!(graph.entity_count() > 0);
+    assert_eq!(recovered.entity_count(), graph.entity_count());
+    assert_eq!(recovered.get_all_edges().len(), graph.get_all_edges().len());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
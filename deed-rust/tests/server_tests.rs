@@ -0,0 +1,163 @@
+//! Integration tests for the network server
+//!
+//! Each test starts a server on an ephemeral port and talks to it over real sockets.
+
+use deed_core::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// A server on an ephemeral port, talking plaintext
+async fn start_server(config: ServerConfig) -> (ServerHandle, Arc<AuthManager>) {
+    let config = ServerConfig { insecure: true, ..config };
+    let pool = ConnectionPool::new(
+        Arc::new(RwLock::new(Graph::new())),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        // One executor, so a client's executor is the next one's
+        PoolConfig {
+            min_size: 1,
+            max_size: 1,
+            connection_timeout: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let auth = Arc::new(AuthManager::new());
+    auth.create_user("reader".to_string(), "secret", Role::ReadOnly).unwrap();
+
+    let server = DeedServer::new(Arc::new(pool), auth.clone(), config);
+    (server.bind("127.0.0.1:0").await.unwrap(), auth)
+}
+
+async fn admin_client(server: &ServerHandle) -> DeedClient {
+    let mut client = DeedClient::connect(server.local_addr()).await.unwrap();
+    client.login("admin", "admin").await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn test_queries_over_socket() {
+    let (server, _) = start_server(ServerConfig::default()).await;
+    let mut client = admin_client(&server).await;
+
+    client.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").await.unwrap();
+    client.execute("INSERT INTO Users VALUES ({name: 'Bob', age: 25})").await.unwrap();
+
+    let params = HashMap::from([("min".to_string(), dql_ir::Value::Integer(28))]);
+    let result = client
+        .execute_with_params("FROM Users WHERE age > $min SELECT name", params)
+        .await
+        .unwrap();
+    assert_eq!(result.row_count(), 1);
+    assert_eq!(result.get(0, "col_0"), Some(&dql_ir::Value::String("Alice".to_string())));
+
+    // Errors come back as errors, and the connection stays usable
+    let err = client.execute("FROM Users SELEKT name").await.unwrap_err();
+    assert!(!err.is_empty());
+    assert_eq!(client.execute("FROM Users SELECT name").await.unwrap().row_count(), 2);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_sessions_and_permissions() {
+    let (server, _) = start_server(ServerConfig::default()).await;
+
+    let mut client = DeedClient::connect(server.local_addr()).await.unwrap();
+    assert_eq!(client.execute("FROM Users SELECT name").await.unwrap_err(), "Not logged in");
+    assert_eq!(client.login("admin", "wrong").await.unwrap_err(), "Invalid username or password");
+
+    // Read-only users can query but not write
+    client.login("reader", "secret").await.unwrap();
+    client.execute("FROM Users SELECT name").await.unwrap();
+    let err = client.execute("INSERT INTO Users VALUES ({name: 'Eve'})").await.unwrap_err();
+    assert_eq!(err, "Permission denied: write access required");
+    let err = client.execute("CREATE INDEX idx_name ON Users(name)").await.unwrap_err();
+    assert_eq!(err, "Permission denied: admin access required");
+
+    // A logged out session is rejected
+    client.logout().await.unwrap();
+    assert_eq!(client.execute("FROM Users SELECT name").await.unwrap_err(), "Not logged in");
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_transactions_span_queries_and_roll_back_on_disconnect() {
+    let (server, _) = start_server(ServerConfig::default()).await;
+
+    let mut client = admin_client(&server).await;
+    client.execute("BEGIN").await.unwrap();
+    client.execute("INSERT INTO Users VALUES ({name: 'Alice'})").await.unwrap();
+    client.execute("COMMIT").await.unwrap();
+
+    client.execute("BEGIN").await.unwrap();
+    client.execute("INSERT INTO Users VALUES ({name: 'Bob'})").await.unwrap();
+    drop(client);
+
+    // The executor went back to the pool with the open transaction rolled back
+    let mut client = admin_client(&server).await;
+    client.execute("BEGIN").await.unwrap();
+    assert_eq!(client.execute("FROM Users SELECT name").await.unwrap().row_count(), 1);
+    client.execute("COMMIT").await.unwrap();
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_connection_limit() {
+    let config = ServerConfig {
+        max_connections: 1,
+        ..Default::default()
+    };
+    let (server, _) = start_server(config).await;
+
+    let mut first = admin_client(&server).await;
+    let mut second = DeedClient::connect(server.local_addr()).await.unwrap();
+    assert_eq!(second.login("admin", "admin").await.unwrap_err(), "Too many connections (max 1)");
+    first.execute("FROM Users SELECT name").await.unwrap();
+
+    // The slot frees up once the first client leaves
+    drop(first);
+    let mut third = None;
+    for _ in 0..50 {
+        let mut client = DeedClient::connect(server.local_addr()).await.unwrap();
+        if client.login("admin", "admin").await.is_ok() {
+            third = Some(client);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(third.is_some());
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_finishes_running_queries() {
+    let (server, _) = start_server(ServerConfig::default()).await;
+    let addr = server.local_addr();
+
+    let mut client = admin_client(&server).await;
+    let rows: Vec<String> = (0..2000).map(|n| format!("({{n: {}}})", n)).collect();
+    client
+        .execute(&format!("INSERT INTO Events VALUES {}", rows.join(", ")))
+        .await
+        .unwrap();
+
+    let running = tokio::spawn(async move {
+        let result = client.execute("FROM Events WHERE n >= 0 SELECT n ORDER BY n DESC").await;
+        (client, result)
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    server.shutdown().await;
+
+    // The query completed; the connection was closed after it
+    let (mut client, result) = running.await.unwrap();
+    assert_eq!(result.unwrap().row_count(), 2000);
+    assert!(client.execute("FROM Events SELECT n").await.is_err());
+    assert!(DeedClient::connect(addr).await.is_err());
+}
@@ -0,0 +1,159 @@
+//! Integration tests for shutting down an executor and a connection pool
+//!
+//! Shutdown refuses new work, waits for (then cancels) running statements,
+//! rolls back open transactions and flushes the WAL.
+
+use deed_core::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn users(count: i64) -> Vec<HashMap<String, PropertyValue>> {
+    (0..count)
+        .map(|i| {
+            let mut props = HashMap::new();
+            props.insert("name".to_string(), PropertyValue::String(format!("User{}", i)));
+            props.insert("age".to_string(), PropertyValue::Int(i % 100));
+            props
+        })
+        .collect()
+}
+
+#[test]
+fn test_shutdown_cancels_long_query_and_flushes_committed_work() {
+    let wal_path = std::env::temp_dir().join("deed_test_shutdown_wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let executor = DQLExecutor::new_with_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+
+    executor.bulk_insert("Users", users(4_000)).unwrap();
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Pending', age: 1})").unwrap();
+
+    // A non-equi self join compares all 16M pairs
+    let slow = executor.execute_async("FROM Users a JOIN Users b ON a.age < b.age SELECT a.name, b.name");
+    thread::sleep(Duration::from_millis(50));
+
+    let started = Instant::now();
+    let report = executor.shutdown(Duration::from_millis(50)).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+    assert_eq!(report.cancelled_queries, 1);
+    assert_eq!(report.unstopped_queries, 0);
+    assert_eq!(report.rolled_back_transactions, 1);
+    assert!(report.unfinished_tasks.is_empty());
+    assert_eq!(slow.wait().unwrap_err(), "Query cancelled by shutdown");
+
+    // The bulk insert is committed on disk and the open transaction's rollback is too
+    let entries = WALReader::new(&wal_path).unwrap().read_all().unwrap();
+    assert_eq!(entries.iter().filter(|e| e.is_commit()).count(), 1);
+    assert!(entries.last().unwrap().is_rollback());
+
+    let recovered = DQLExecutor::recover_from_wal(Arc::new(RwLock::new(Graph::new())), &wal_path).unwrap();
+    assert_eq!(recovered.execute("FROM Users SELECT name").unwrap().row_count(), 4_000);
+    assert_eq!(recovered.execute("FROM Users WHERE name = 'Pending' SELECT name").unwrap().row_count(), 0);
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn test_double_shutdown_is_idempotent() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    executor.execute("BEGIN TRANSACTION").unwrap();
+
+    // A background task started with the executor's signal is stopped and waited for
+    let stopped = Arc::new(AtomicBool::new(false));
+    let task = BackgroundTask::spawn_thread("ticker", &executor.shutdown_signal(), {
+        let stopped = stopped.clone();
+        move |shutdown| {
+            while !shutdown.wait_timeout(Duration::from_millis(5)) {}
+            stopped.store(true, Ordering::SeqCst);
+        }
+    });
+    executor.register_task(task);
+
+    let first = executor.shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(first.rolled_back_transactions, 1);
+    assert!(first.unfinished_tasks.is_empty());
+    assert!(stopped.load(Ordering::SeqCst));
+
+    // Again, on the executor or a clone sharing it, nothing more happens
+    assert_eq!(executor.shutdown(Duration::from_secs(5)).unwrap(), first);
+    assert_eq!(executor.clone().shutdown(Duration::ZERO).unwrap(), first);
+    assert!(executor.shutdown_signal().is_cancelled());
+}
+
+#[test]
+fn test_queries_after_shutdown_fail_fast() {
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice', age: 30})").unwrap();
+    executor.shutdown(Duration::from_secs(1)).unwrap();
+
+    let started = Instant::now();
+    assert_eq!(executor.execute("FROM Users SELECT name").unwrap_err(), "Executor is shut down");
+    assert_eq!(
+        executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap_err(),
+        "Executor is shut down"
+    );
+    assert_eq!(executor.execute_async("FROM Users SELECT name").wait().unwrap_err(), "Executor is shut down");
+    assert_eq!(executor.bulk_insert("Users", users(3)).unwrap_err(), "Executor is shut down");
+    assert!(matches!(executor.prepare("FROM Users SELECT name"), Err(e) if e == "Executor is shut down"));
+    assert!(matches!(executor.execute_stream("FROM Users SELECT name", 10), Err(e) if e == "Executor is shut down"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_pool_shutdown_drains_handles() {
+    let pool = Arc::new(
+        ConnectionPool::new(
+            Arc::new(RwLock::new(Graph::new())),
+            Arc::new(RwLock::new(AntColonyOptimizer::new())),
+            Arc::new(RwLock::new(StigmergyCache::new(1000))),
+            Arc::new(TransactionManager::new()),
+            None,
+            PoolConfig {
+                min_size: 1,
+                max_size: 1,
+                connection_timeout: 30,
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    );
+
+    // The only connection is out, with a transaction open on it
+    let mut handle = pool.get_connection().unwrap();
+    handle.execute("BEGIN TRANSACTION").unwrap();
+    handle.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+
+    let waiter = thread::spawn({
+        let pool = pool.clone();
+        move || {
+            let started = Instant::now();
+            (pool.get_connection().err(), started.elapsed())
+        }
+    });
+    while pool.queue_depth() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let returner = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        drop(handle);
+    });
+
+    // Shutdown waits for the handle, then rolls back what it left open
+    let report = pool.shutdown(Duration::from_secs(5)).unwrap();
+    returner.join().unwrap();
+    assert_eq!(report.rolled_back_transactions, 1);
+    assert_eq!(report.cancelled_queries, 0);
+    assert!(report.unfinished_tasks.is_empty());
+
+    // The queued caller was turned away rather than left to time out
+    let (err, waited) = waiter.join().unwrap();
+    assert_eq!(err.as_deref(), Some("Connection pool is shut down"));
+    assert!(waited < Duration::from_secs(5), "waited {:?}", waited);
+
+    assert_eq!(pool.get_connection().err().as_deref(), Some("Connection pool is shut down"));
+    assert_eq!(pool.shutdown(Duration::ZERO).unwrap(), report);
+}
@@ -0,0 +1,207 @@
+//! Integration tests for optimizer statistics
+//!
+//! Collection counts and edge counts are kept up to date on every write;
+//! property estimates feed predicate selectivity and ANALYZE rebuilds them.
+
+use deed_core::dql_ir::{FilterExpr, Operation, QueryPlan, Value};
+use deed_core::types::Properties;
+use deed_core::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+fn user(n: i64) -> Properties {
+    let mut props = Properties::new();
+    props.insert("email".to_string(), PropertyValue::String(format!("user{}@example.com", n)));
+    props.insert("active".to_string(), PropertyValue::Bool(n % 2 == 0));
+    props.insert("age".to_string(), PropertyValue::Int(20 + n % 50));
+    props
+}
+
+fn equals(binding: &str, property: &str, value: Value) -> FilterExpr {
+    FilterExpr::Equal(
+        Box::new(FilterExpr::Property {
+            binding: binding.to_string(),
+            property: property.to_string(),
+        }),
+        Box::new(FilterExpr::Constant(value)),
+    )
+}
+
+fn scan(collection: &str, alias: &str) -> Operation {
+    Operation::Scan {
+        collection: collection.to_string(),
+        alias: alias.to_string(),
+        filter: None,
+        properties: None,
+    }
+}
+
+#[test]
+fn test_costs_follow_collection_size() {
+    let graph = Graph::new();
+    graph.add_entities("Settings".to_string(), (0..10).map(user).collect()).unwrap();
+    graph.add_entities("Users".to_string(), (0..2000).map(user).collect()).unwrap();
+    let stats = graph.stats();
+
+    let small = scan("Settings", "s").estimate_cost(&stats);
+    let large = scan("Users", "u").estimate_cost(&stats);
+    assert_eq!(small, 10.0);
+    assert_eq!(large, 2000.0);
+    assert_eq!(scan("Missing", "m").estimate_cost(&stats), 0.0);
+
+    // A filter is costed against the collection its binding was scanned from,
+    // and a selective one passes fewer rows on
+    let filtered = |collection: &str, condition: FilterExpr| {
+        let plan = QueryPlan::new(vec![
+            scan(collection, "x"),
+            Operation::Filter {
+                binding: "x".to_string(),
+                condition,
+            },
+        ]);
+        plan.operation_costs(&stats)[1]
+    };
+    let email = || equals("x", "email", Value::String("user7@example.com".to_string()));
+    let active = || equals("x", "active", Value::Bool(true));
+    assert!(filtered("Settings", email()) < filtered("Users", email()));
+    assert!(filtered("Users", email()) < filtered("Users", active()));
+}
+
+#[test]
+fn test_equality_selectivity_follows_cardinality() {
+    let graph = Graph::new();
+    graph.add_entities("Users".to_string(), (0..5000).map(user).collect()).unwrap();
+
+    let stats = graph.stats();
+    let users = stats.for_collection("Users").unwrap();
+    assert_eq!(users.entity_count, 5000);
+
+    let unique = users.equality_selectivity("email");
+    let boolean = users.equality_selectivity("active");
+    assert!(unique < 0.001, "email selectivity {}", unique);
+    assert!((boolean - 0.5).abs() < 0.05, "active selectivity {}", boolean);
+    assert_eq!(users.equality_selectivity("nickname"), 0.0);
+
+    // The same estimates come from a filter over the collection
+    let by_email = equals("u", "email", Value::String("user7@example.com".to_string()));
+    assert_eq!(users.selectivity("u", &by_email), unique);
+    assert!(users.estimated_rows("u", Some(&by_email)) <= 5.0);
+
+    // Ranges interpolate over the numeric min/max (ages run 20..=69)
+    let age = users.properties.get("age").unwrap();
+    assert_eq!((age.min, age.max), (Some(20.0), Some(69.0)));
+    let young = users.range_selectivity("age", None, Some(&Value::Integer(30)));
+    assert!(young > 0.15 && young < 0.25, "age <= 30 selectivity {}", young);
+}
+
+#[test]
+fn test_counts_stay_exact_through_mixed_writes() {
+    let graph = Graph::new();
+    let mut alive = Vec::new();
+    let mut follows = HashMap::new();
+
+    for round in 0..20 {
+        alive.extend(graph.add_entities("Users".to_string(), (0..50).map(|n| user(round * 50 + n)).collect()).unwrap());
+        graph.add_entity("Posts".to_string(), user(round)).unwrap();
+
+        // Delete every third user, one at a time and in batches
+        let doomed: Vec<EntityId> = alive.iter().copied().step_by(3).collect();
+        let (one_by_one, batch) = doomed.split_at(doomed.len() / 2);
+        for id in one_by_one {
+            graph.delete_entity(*id).unwrap();
+        }
+        graph.delete_entities(batch).unwrap();
+        alive.retain(|id| !doomed.contains(id));
+        follows.retain(|_, (source, target)| !doomed.contains(source) && !doomed.contains(target));
+
+        for pair in alive.windows(2).skip(round as usize).take(5) {
+            let edge = graph.add_edge(pair[0], pair[1], "FOLLOWS".to_string(), Properties::new()).unwrap();
+            follows.insert(edge, (pair[0], pair[1]));
+        }
+        if let Some(&edge) = follows.keys().next() {
+            graph.delete_edge(edge).unwrap();
+            follows.remove(&edge);
+        }
+    }
+
+    let stats = graph.stats();
+    assert_eq!(stats.for_collection("Users").unwrap().entity_count, alive.len());
+    assert_eq!(stats.for_collection("Users").unwrap().entity_count, graph.scan_collection("Users").len());
+    assert_eq!(stats.for_collection("Posts").unwrap().entity_count, 20);
+    assert_eq!(stats.edge_type_counts.get("FOLLOWS").copied().unwrap_or(0), follows.len());
+    assert_eq!(stats.edge_count, follows.len());
+
+    let users = stats.for_collection("Users").unwrap();
+    assert_eq!(users.properties["email"].non_null, alive.len());
+}
+
+#[test]
+fn test_analyze_rebuilds_stale_estimates() {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = DQLExecutor::new(graph.clone());
+    let ids = graph.read().unwrap().add_entities("Users".to_string(), (0..1000).map(user).collect()).unwrap();
+    graph.read().unwrap().add_entities("Posts".to_string(), (0..10).map(user).collect()).unwrap();
+
+    // Overwrite every age with one value: the sketch still remembers the old ones
+    for id in &ids {
+        let mut entity = graph.read().unwrap().get_entity(*id).unwrap();
+        entity.properties.insert("age".to_string(), PropertyValue::Int(40));
+        graph.read().unwrap().update_entity(entity).unwrap();
+    }
+    let before = graph.read().unwrap().stats();
+    assert!(before.for_collection("Users").unwrap().properties["age"].distinct() > 40.0);
+
+    let result = executor.execute("ANALYZE Users").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0]["collection"], Value::String("Users".to_string()));
+    assert_eq!(result.rows[0]["entities"], Value::Integer(1000));
+    assert_eq!(result.rows[0]["sampled"], Value::Integer(1000));
+    assert_eq!(result.rows[0]["properties"], Value::Integer(3));
+
+    let after = graph.read().unwrap().stats();
+    let age = &after.for_collection("Users").unwrap().properties["age"];
+    assert!((age.distinct() - 1.0).abs() < 0.01, "age distinct {}", age.distinct());
+    assert_eq!((age.min, age.max), (Some(40.0), Some(40.0)));
+
+    // Without a collection, every collection is analyzed
+    let result = executor.execute("analyze").unwrap();
+    let analyzed: Vec<&Value> = result.rows.iter().map(|row| &row["collection"]).collect();
+    assert_eq!(analyzed, [&Value::String("Posts".to_string()), &Value::String("Users".to_string())]);
+}
+
+#[test]
+fn test_analyze_scales_a_sample_up() {
+    let graph = Graph::new();
+    let count = ANALYZE_SAMPLE_SIZE * 3;
+    graph.add_entities("Users".to_string(), (0..count as i64).map(user).collect()).unwrap();
+
+    let reports = graph.analyze(Some("Users"));
+    assert_eq!(reports[0].entities, count);
+    assert_eq!(reports[0].sampled, ANALYZE_SAMPLE_SIZE);
+
+    let stats = graph.stats();
+    let users = stats.for_collection("Users").unwrap();
+    // Unique values scale with the collection; a boolean has shown both values
+    let email = users.properties["email"].distinct();
+    assert!(email > count as f64 * 0.8, "email distinct {}", email);
+    assert!((users.properties["active"].distinct() - 2.0).abs() < 0.5);
+    assert_eq!(users.properties["email"].non_null, count);
+}
+
+#[test]
+fn test_stats_share_property_estimates_until_the_next_write() {
+    let graph = Graph::new();
+    graph.add_entities("Users".to_string(), (0..100).map(user).collect()).unwrap();
+
+    let first = graph.stats();
+    let second = graph.stats();
+    let properties = |stats: &GraphStats| stats.for_collection("Users").unwrap().properties.clone();
+    assert!(Arc::ptr_eq(&properties(&first), &properties(&second)));
+
+    // A write copies the estimates instead of changing a snapshot in use
+    graph.add_entity("Users".to_string(), user(100)).unwrap();
+    let third = graph.stats();
+    assert!(!Arc::ptr_eq(&properties(&first), &properties(&third)));
+    assert_eq!(properties(&first)["email"].non_null, 100);
+    assert_eq!(properties(&third)["email"].non_null, 101);
+}
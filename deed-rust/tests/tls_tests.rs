@@ -0,0 +1,299 @@
+//! Integration tests for TLS on node and client connections
+//!
+//! Each test issues its certificates with rcgen into a directory of its own,
+//! then talks over real sockets on ephemeral ports.
+
+use deed_core::*;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A directory for one test's certificates
+fn cert_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("deed_tls_{}_{}", std::process::id(), test));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A CA, saved as `<name>.pem`
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+    path: PathBuf,
+}
+
+impl Ca {
+    fn new(dir: &Path, name: &str) -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        let path = dir.join(format!("{}.pem", name));
+        std::fs::write(&path, cert.pem()).unwrap();
+        Ca { cert, key, path }
+    }
+
+    /// A certificate signed by this CA, saved as `<name>.pem` and `<name>.key`,
+    /// as a config trusting `trusted`
+    fn issue(&self, dir: &Path, name: &str, params: CertificateParams, trusted: &Ca) -> TlsConfig {
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+
+        let cert_path = dir.join(format!("{}.pem", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        TlsConfig::new(cert_path, key_path).with_ca(&trusted.path)
+    }
+}
+
+/// Params of a certificate for this machine
+fn local_params() -> CertificateParams {
+    CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()]).unwrap()
+}
+
+fn tls_network(id: NodeId, tls: TlsConfig) -> P2PNetwork {
+    let config = P2PConfig {
+        listen_port: 0,
+        connection_timeout_ms: 2000,
+        max_retries: 0,
+        tls: Some(tls),
+        ..P2PConfig::default()
+    };
+    P2PNetwork::new(id, NodeAddress::new("127.0.0.1".to_string(), 0), config)
+}
+
+/// Start `server` listening, returning its address
+async fn listen(server: &P2PNetwork) -> NodeAddress {
+    let port = server.start_listener().await.unwrap().port();
+    NodeAddress::new("127.0.0.1".to_string(), port)
+}
+
+#[tokio::test]
+async fn test_nodes_talk_over_mutual_tls() {
+    let dir = cert_dir("mutual");
+    let ca = Ca::new(&dir, "ca");
+    let one = ca.issue(&dir, "node1", local_params(), &ca);
+    let two = ca.issue(&dir, "node2", local_params(), &ca);
+
+    let client = tls_network(1, one);
+    let server = tls_network(2, two);
+    let address = listen(&server).await;
+    client.add_peer(2, address.clone());
+
+    let reply = client.send_message(2, MessageType::Ping).await.unwrap();
+    assert_eq!(reply.message_type, MessageType::Pong);
+
+    // Large messages are compressed and split inside the encrypted stream
+    server.register_handler(MessageKind::ShardDataRequest, |msg| {
+        Some(msg.reply(MessageType::ShardDataResponse { shard_id: 1, data: vec![7; 4 * 1024 * 1024] }))
+    });
+    let reply = client
+        .send_message(2, MessageType::ShardDataRequest { shard_id: 1, op: ShardDataOp::Digest })
+        .await
+        .unwrap();
+    assert!(matches!(reply.message_type, MessageType::ShardDataResponse { ref data, .. } if data.len() == 4 * 1024 * 1024));
+
+    // A plaintext frame never reaches the handlers
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", address.port)).await.unwrap();
+    let ping = P2PMessage::new(1, 2, MessageType::Ping).to_bytes().unwrap();
+    stream.write_all(&(ping.len() as u32).to_be_bytes()).await.unwrap();
+    stream.write_all(&ping).await.unwrap();
+    let mut reply = Vec::new();
+    let _ = stream.read_to_end(&mut reply).await;
+    assert!(reply.len() < 4 || P2PMessage::from_bytes(&reply[4..]).is_err());
+}
+
+#[tokio::test]
+async fn test_untrusted_client_certificate_is_rejected() {
+    let dir = cert_dir("untrusted_client");
+    let ca = Ca::new(&dir, "ca");
+    let rogue = Ca::new(&dir, "rogue");
+
+    // The client trusts the server, but its own certificate is the rogue CA's
+    let client = tls_network(1, rogue.issue(&dir, "node1", local_params(), &ca));
+    let server = tls_network(2, ca.issue(&dir, "node2", local_params(), &ca));
+    let address = listen(&server).await;
+    client.add_peer(2, address.clone());
+
+    assert!(client.send_message(2, MessageType::Ping).await.is_err());
+
+    // Reissued by the cluster's CA, it's let in
+    let client = tls_network(1, ca.issue(&dir, "node1", local_params(), &ca));
+    client.add_peer(2, address);
+    client.ping(2).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handshake_errors_name_the_problem() {
+    let dir = cert_dir("handshake_errors");
+    let ca = Ca::new(&dir, "ca");
+    let rogue = Ca::new(&dir, "rogue");
+    let client = tls_network(1, ca.issue(&dir, "node1", local_params(), &ca));
+
+    let mut expired = local_params();
+    expired.not_before = rcgen::date_time_ymd(2000, 1, 1);
+    expired.not_after = rcgen::date_time_ymd(2001, 1, 1);
+    let elsewhere = CertificateParams::new(vec!["db.example.com".to_string()]).unwrap();
+
+    let cases = [
+        (ca.issue(&dir, "expired", expired, &ca), "certificate expired"),
+        (ca.issue(&dir, "elsewhere", elsewhere, &ca), "certificate name mismatch"),
+        (rogue.issue(&dir, "rogue_node", local_params(), &ca), "certificate signed by an untrusted CA"),
+    ];
+    for (tls, problem) in cases {
+        let server = tls_network(2, tls);
+        client.add_peer(2, listen(&server).await);
+
+        let err = client.send_message(2, MessageType::Ping).await.unwrap_err();
+        assert!(err.starts_with("TLS handshake with 127.0.0.1 failed"), "{}", err);
+        assert!(err.contains(problem), "{}", err);
+    }
+}
+
+#[tokio::test]
+async fn test_spoofed_node_is_rejected() {
+    let dir = cert_dir("spoofed");
+    let ca = Ca::new(&dir, "ca");
+    let one = ca.issue(&dir, "node1", local_params(), &ca);
+    let two = ca.issue(&dir, "node2", local_params(), &ca);
+    let three = ca.issue(&dir, "node3", local_params(), &ca);
+    let fingerprint = |tls: &TlsConfig| certificate_file_fingerprint(tls.cert_path.as_ref().unwrap()).unwrap();
+    let (one_print, two_print) = (fingerprint(&one), fingerprint(&two));
+
+    let known = |tls: TlsConfig| {
+        tls.with_node_fingerprint(1, one_print.clone())
+            .with_node_fingerprint(2, two_print.clone())
+    };
+    let server = tls_network(2, known(two));
+    let client = tls_network(1, known(one));
+    let address = listen(&server).await;
+    client.add_peer(2, address.clone());
+    client.ping(2).await.unwrap();
+
+    // Node 3 has a certificate from the cluster's CA, but not node 1's
+    let spoofer = tls_network(1, three);
+    spoofer.add_peer(2, address);
+    let reply = spoofer.send_message(2, MessageType::Ping).await.unwrap();
+    assert_eq!(
+        reply.message_type,
+        MessageType::Error {
+            message: "Peer claiming to be node 1 presented another certificate".to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_shard_data_goes_only_to_verified_nodes() {
+    let dir = cert_dir("shard_data");
+    let ca = Ca::new(&dir, "ca");
+    let one = ca.issue(&dir, "node1", local_params(), &ca);
+    let two = ca.issue(&dir, "node2", local_params(), &ca);
+    let fingerprint = |tls: &TlsConfig| certificate_file_fingerprint(tls.cert_path.as_ref().unwrap()).unwrap();
+    let (one_print, two_print) = (fingerprint(&one), fingerprint(&two));
+
+    let shards = Arc::new(ShardManager::new(deed_core::distributed_shard::ShardConfig::default()));
+    shards.add_node(2);
+    let executor = DQLExecutor::new(Arc::new(RwLock::new(Graph::new())));
+    let local = Arc::new(LocalShards::new(executor, shards));
+    let request = |op| MessageType::ShardDataRequest { shard_id: 1, op };
+
+    // Encrypted, but nothing ties the client to node 1
+    let server = tls_network(2, two.clone());
+    local.serve(&server);
+    let client = tls_network(1, one.clone());
+    client.add_peer(2, listen(&server).await);
+    let reply = client.send_message(2, request(ShardDataOp::Digest)).await.unwrap();
+    assert!(
+        matches!(&reply.message_type, MessageType::Error { message } if message.starts_with("Permission denied")),
+        "{:?}",
+        reply.message_type
+    );
+
+    let known = |tls: TlsConfig| {
+        tls.with_node_fingerprint(1, one_print.clone())
+            .with_node_fingerprint(2, two_print.clone())
+    };
+    let server = tls_network(2, known(two));
+    local.serve(&server);
+    let client = tls_network(1, known(one));
+    client.add_peer(2, listen(&server).await);
+    let reply = client.send_message(2, request(ShardDataOp::Fence { fenced: true })).await.unwrap();
+    assert_eq!(reply.message_type, MessageType::ShardDataResponse { shard_id: 1, data: Vec::new() });
+    let reply = client.send_message(2, request(ShardDataOp::Digest)).await.unwrap();
+    assert!(matches!(reply.message_type, MessageType::ShardDataResponse { .. }), "{:?}", reply.message_type);
+}
+
+#[tokio::test]
+async fn test_plaintext_needs_insecure_flag() {
+    let config = P2PConfig {
+        listen_port: 0,
+        ..P2PConfig::default()
+    };
+    let network = P2PNetwork::new(1, NodeAddress::new("127.0.0.1".to_string(), 0), config.clone());
+    assert_eq!(
+        network.start_listener().await.unwrap_err(),
+        "TLS is not configured; set insecure to use plaintext"
+    );
+
+    // With it, plain frames flow as before
+    let network = P2PNetwork::new(2, NodeAddress::new("127.0.0.1".to_string(), 0), P2PConfig { insecure: true, ..config });
+    let port = network.start_listener().await.unwrap().port();
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let ping = P2PMessage::new(1, 2, MessageType::Ping).to_bytes().unwrap();
+    stream.write_all(&(ping.len() as u32).to_be_bytes()).await.unwrap();
+    stream.write_all(&ping).await.unwrap();
+
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).await.unwrap();
+    let mut payload = vec![0u8; u32::from_be_bytes(length) as usize];
+    stream.read_exact(&mut payload).await.unwrap();
+    assert_eq!(P2PMessage::from_bytes(&payload).unwrap().message_type, MessageType::Pong);
+}
+
+#[tokio::test]
+async fn test_server_accepts_tls_clients() {
+    let dir = cert_dir("server");
+    let ca = Ca::new(&dir, "ca");
+    let mut tls = ca.issue(&dir, "server", local_params(), &ca);
+    // Clients aren't asked for certificates without a CA bundle
+    tls.ca_path = None;
+
+    let pool = ConnectionPool::new(
+        Arc::new(RwLock::new(Graph::new())),
+        Arc::new(RwLock::new(AntColonyOptimizer::new())),
+        Arc::new(RwLock::new(StigmergyCache::new(1000))),
+        Arc::new(TransactionManager::new()),
+        None,
+        PoolConfig::default(),
+    )
+    .unwrap();
+    let config = ServerConfig {
+        tls: Some(tls),
+        ..ServerConfig::default()
+    };
+    let server = DeedServer::new(Arc::new(pool), Arc::new(AuthManager::new()), config);
+    let server = server.bind("127.0.0.1:0").await.unwrap();
+
+    let trusting = TlsConfig::default().with_ca(&ca.path);
+    let mut client = DeedClient::connect_tls(server.local_addr(), "localhost", &trusting).await.unwrap();
+    client.login("admin", "admin").await.unwrap();
+    client.execute("INSERT INTO Users VALUES ({name: 'Alice'})").await.unwrap();
+    assert_eq!(client.execute("FROM Users SELECT name").await.unwrap().row_count(), 1);
+
+    let err = DeedClient::connect_tls(server.local_addr(), "db.example.com", &trusting)
+        .await
+        .err()
+        .unwrap();
+    assert!(err.contains("certificate name mismatch"), "{}", err);
+
+    // A plaintext client gets nowhere
+    let mut plain = DeedClient::connect(server.local_addr()).await.unwrap();
+    assert!(plain.login("admin", "admin").await.is_err());
+
+    drop(client);
+    server.shutdown().await;
+}
@@ -2,11 +2,30 @@
 //!
 //! Tests concurrent transactions, race conditions, and system limits.
 
-use deed_rust::*;
+use deed_core::*;
 use std::sync::{Arc, RwLock, Barrier};
 use std::thread;
 use std::time::Duration;
 
+/// Run `statements` in one transaction, retrying it while it conflicts with others
+fn commit_with_retry(executor: &DQLExecutor, statements: &[&str]) {
+    loop {
+        executor.execute("BEGIN TRANSACTION").unwrap();
+        let result = statements
+            .iter()
+            .try_for_each(|statement| executor.execute(statement).map(|_| ()))
+            .and_then(|()| executor.execute("COMMIT").map(|_| ()));
+        match result {
+            Ok(()) => return,
+            Err(e) if e.contains("conflict") => {
+                // A failed COMMIT has already ended the transaction
+                let _ = executor.execute("ROLLBACK");
+            }
+            Err(e) => panic!("transaction failed: {}", e),
+        }
+    }
+}
+
 /// Test concurrent inserts from multiple threads
 #[test]
 fn test_concurrent_inserts() {
@@ -20,7 +39,7 @@ fn test_concurrent_inserts() {
     let mut handles = vec![];
 
     for thread_id in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -80,7 +99,7 @@ fn test_concurrent_reads_writes() {
 
     // Spawn reader threads
     for _ in 0..num_readers {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -97,21 +116,19 @@ fn test_concurrent_reads_writes() {
 
     // Spawn writer threads
     for thread_id in 0..num_writers {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
             barrier.wait();
 
             for i in 0..operations_per_thread {
-                executor.execute("BEGIN TRANSACTION").unwrap();
                 let account_id = (thread_id * operations_per_thread + i) % 100;
                 let query = format!(
                     "UPDATE Accounts SET balance = balance + 10 WHERE id = {}",
                     account_id
                 );
-                executor.execute(&query).unwrap();
-                executor.execute("COMMIT").unwrap();
+                commit_with_retry(&executor, &[&query]);
             }
         });
 
@@ -144,7 +161,7 @@ fn test_concurrent_updates_same_entity() {
     let mut handles = vec![];
 
     for _ in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -152,9 +169,7 @@ fn test_concurrent_updates_same_entity() {
 
             for _ in 0..updates_per_thread {
                 // Each thread tries to update the same account
-                executor.execute("BEGIN TRANSACTION").unwrap();
-                executor.execute("UPDATE Accounts SET balance = balance + 10 WHERE id = 1").unwrap();
-                executor.execute("COMMIT").unwrap();
+                commit_with_retry(&executor, &["UPDATE Accounts SET balance = balance + 10 WHERE id = 1"]);
             }
         });
 
@@ -168,6 +183,7 @@ fn test_concurrent_updates_same_entity() {
     // Verify final balance is correct
     let result = executor.execute("FROM Accounts WHERE id = 1 SELECT balance").unwrap();
     println!("Final balance after concurrent updates: {:?}", result);
+    assert_eq!(result.rows[0]["col_0"], dql_ir::Value::Integer(1000 + 10 * (num_threads * updates_per_thread) as i64));
 }
 
 /// Test rollback under concurrent load
@@ -181,7 +197,7 @@ fn test_concurrent_rollbacks() {
     let mut handles = vec![];
 
     for thread_id in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -230,7 +246,7 @@ fn test_high_throughput_inserts() {
     let mut handles = vec![];
 
     for thread_id in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -283,7 +299,7 @@ fn test_transaction_retry_pattern() {
     let mut handles = vec![];
 
     for _ in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -338,7 +354,7 @@ fn test_mixed_transaction_sizes() {
     let mut handles = vec![];
 
     for thread_id in 0..num_threads {
-        let executor = Arc::clone(&executor);
+        let executor = executor.new_session();
         let barrier = Arc::clone(&barrier);
 
         let handle = thread::spawn(move || {
@@ -384,7 +400,7 @@ fn test_long_running_transaction() {
     let executor = Arc::new(DQLExecutor::new(graph));
 
     // Start long-running transaction
-    let executor1 = Arc::clone(&executor);
+    let executor1 = executor.new_session();
     let handle1 = thread::spawn(move || {
         executor1.execute("BEGIN TRANSACTION").unwrap();
 
@@ -411,7 +427,7 @@ fn test_long_running_transaction() {
     });
 
     // Meanwhile, other short transactions
-    let executor2 = Arc::clone(&executor);
+    let executor2 = executor.new_session();
     let handle2 = thread::spawn(move || {
         thread::sleep(Duration::from_millis(10));
 
@@ -0,0 +1,252 @@
+//! Integration tests for entity expiration
+//!
+//! Entities expire by their collection's TTL or their own `_expires_at`.
+//! Expired entities vanish from reads at once and are deleted in batches.
+
+use deed_core::*;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MINUTE: i64 = 60_000;
+
+/// An executor whose clock starts at the real time and only moves when told
+fn setup() -> (DQLExecutor, Arc<RwLock<Graph>>, Arc<AtomicI64>) {
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+    let now = Arc::new(AtomicI64::new(start));
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let executor = executor_on(graph.clone(), &now);
+    (executor, graph, now)
+}
+
+fn executor_on(graph: Arc<RwLock<Graph>>, now: &Arc<AtomicI64>) -> DQLExecutor {
+    let clock = now.clone();
+    DQLExecutor::new(graph).with_clock(Arc::new(move || clock.load(Ordering::SeqCst)))
+}
+
+fn stored(graph: &RwLock<Graph>) -> usize {
+    graph.read().unwrap().entity_count()
+}
+
+fn insert_expiring(executor: &DQLExecutor, now: &AtomicI64, name: &str, after: i64) {
+    let at = format_timestamp(now.load(Ordering::SeqCst) + after);
+    executor
+        .execute(&format!("INSERT INTO Sessions VALUES ({{name: '{}', _expires_at: TIMESTAMP '{}'}})", name, at))
+        .unwrap();
+}
+
+fn count(executor: &DQLExecutor, collection: &str) -> usize {
+    executor.execute(&format!("FROM {} SELECT *", collection)).unwrap().row_count()
+}
+
+#[test]
+fn test_expired_entity_is_hidden_then_deleted() {
+    let (executor, graph, now) = setup();
+    insert_expiring(&executor, &now, "short", MINUTE);
+    executor.execute("INSERT INTO Sessions VALUES ({name: 'forever'})").unwrap();
+    assert_eq!(count(&executor, "Sessions"), 2);
+
+    now.fetch_add(MINUTE, Ordering::SeqCst);
+
+    // Gone from reads before anything deletes it
+    let res = executor.execute("FROM Sessions s SELECT s.name AS name").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.get(0, "name"), Some(&dql_ir::Value::String("forever".to_string())));
+    assert_eq!(executor.execute("FROM Sessions s WHERE s.name = 'short' SELECT s.name").unwrap().row_count(), 0);
+    assert_eq!(stored(&graph), 2);
+
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 1);
+    assert_eq!(stored(&graph), 1);
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 0);
+}
+
+#[test]
+fn test_collection_ttl_from_schema() {
+    let (executor, _graph, now) = setup();
+    executor.execute("DEFINE SCHEMA Tokens (token String) TTL 10 MINUTES").unwrap();
+    executor.execute("INSERT INTO Tokens VALUES ({token: 'abc'})").unwrap();
+
+    now.fetch_add(9 * MINUTE, Ordering::SeqCst);
+    assert_eq!(count(&executor, "Tokens"), 1);
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 0);
+
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+    assert_eq!(count(&executor, "Tokens"), 0);
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 1);
+
+    // Dropping the schema drops the TTL
+    executor.execute("DROP SCHEMA Tokens").unwrap();
+    executor.execute("INSERT INTO Tokens VALUES ({token: 'def'})").unwrap();
+    now.fetch_add(60 * MINUTE, Ordering::SeqCst);
+    assert_eq!(count(&executor, "Tokens"), 1);
+}
+
+#[test]
+fn test_expiration_deletes_in_batches_and_logs_each_delete() {
+    let (executor, graph, now) = setup();
+    for i in 0..6 {
+        insert_expiring(&executor, &now, &format!("s{}", i), MINUTE);
+    }
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+
+    let changes = executor.subscribe(ChangeFilter::all());
+    assert_eq!(executor.expire_entities(4, 2).unwrap(), 4);
+
+    let events = changes.drain();
+    assert_eq!(events.len(), 4);
+    assert!(events.iter().all(|event| event.kind == ChangeKind::Delete));
+    let mut txns: Vec<_> = events.iter().map(|event| event.txn_id).collect();
+    txns.dedup();
+    assert_eq!(txns.len(), 2, "Each batch of 2 commits on its own");
+
+    assert_eq!(executor.expire_entities(4, 2).unwrap(), 2);
+    assert_eq!(stored(&graph), 0);
+}
+
+#[test]
+fn test_expiration_is_refused_inside_a_transaction() {
+    let (executor, _graph, _now) = setup();
+    executor.execute("BEGIN TRANSACTION").unwrap();
+    assert!(executor.expire_entities(100, 10).is_err());
+    executor.execute("ROLLBACK").unwrap();
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 0);
+}
+
+#[test]
+fn test_deleted_or_extended_entities_are_left_alone() {
+    let (executor, _graph, now) = setup();
+    insert_expiring(&executor, &now, "deleted", MINUTE);
+    insert_expiring(&executor, &now, "extended", MINUTE);
+    insert_expiring(&executor, &now, "expiring", MINUTE);
+
+    executor.execute("DELETE FROM Sessions WHERE name = 'deleted'").unwrap();
+    let later = format_timestamp(now.load(Ordering::SeqCst) + 60 * MINUTE);
+    executor
+        .execute(&format!("UPDATE Sessions SET _expires_at = TIMESTAMP '{}' WHERE name = 'extended'", later))
+        .unwrap();
+
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+    let res = executor.execute("FROM Sessions s SELECT s.name AS name").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.get(0, "name"), Some(&dql_ir::Value::String("extended".to_string())));
+
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 1);
+    assert_eq!(count(&executor, "Sessions"), 1);
+}
+
+#[test]
+fn test_restored_entities_keep_their_creation_time() {
+    let base = std::env::temp_dir().join(format!("deed_test_ttl_restore_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    let config = BackupConfig {
+        backup_dir: base.clone(),
+        compress: false,
+        verify: true,
+    };
+
+    let (executor, graph, now) = setup();
+    executor.execute("DEFINE SCHEMA Tokens (token String) TTL 10 MINUTES").unwrap();
+    executor.execute("INSERT INTO Tokens VALUES ({token: 'new'})").unwrap();
+    // Created 9 minutes ago, so it expires a minute from now
+    let mut props = types::Properties::new();
+    props.insert("token".to_string(), PropertyValue::String("old".to_string()));
+    let created = (now.load(Ordering::SeqCst) - 9 * MINUTE) as u64;
+    graph
+        .read()
+        .unwrap()
+        .insert_entity_with_id(Entity::new(EntityId(100), "Tokens".to_string(), props).with_created_at(created)).unwrap();
+    insert_expiring(&executor, &now, "short", MINUTE);
+    let metadata = executor.create_backup(&config, BackupType::Full).unwrap();
+
+    // Restored once the old token and the session have expired, but not the new token
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+    let restored_graph = Arc::new(RwLock::new(Graph::new()));
+    let restored = executor_on(restored_graph.clone(), &now);
+    restored.restore_backup(&config, &metadata.backup_id, false).unwrap();
+    restored.execute("DEFINE SCHEMA Tokens (token String) TTL 10 MINUTES").unwrap();
+
+    let res = restored.execute("FROM Tokens t SELECT t.token AS token").unwrap();
+    assert_eq!(res.row_count(), 1);
+    assert_eq!(res.get(0, "token"), Some(&dql_ir::Value::String("new".to_string())));
+    assert_eq!(count(&restored, "Sessions"), 0);
+    assert_eq!(restored.expire_entities(100, 10).unwrap(), 2);
+    assert_eq!(stored(&restored_graph), 1);
+
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[test]
+fn test_expired_entities_release_their_unique_values() {
+    let (executor, _graph, now) = setup();
+    executor.execute("CREATE UNIQUE INDEX idx_session_name ON Sessions(name)").unwrap();
+    insert_expiring(&executor, &now, "alice", MINUTE);
+    let err = executor.execute("INSERT INTO Sessions VALUES ({name: 'alice'})").unwrap_err();
+    assert!(err.contains("alice"), "Unexpected error: {}", err);
+
+    // Expired but not yet deleted, the old session no longer holds the name
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+    executor.execute("INSERT INTO Sessions VALUES ({name: 'alice'})").unwrap();
+    assert_eq!(count(&executor, "Sessions"), 1);
+
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 1);
+    let res = executor.execute("FROM Sessions s WHERE s.name = 'alice' SELECT s.name").unwrap();
+    assert_eq!(res.row_count(), 1);
+}
+
+#[test]
+fn test_expired_edges_are_hidden_then_deleted() {
+    let (executor, graph, now) = setup();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Alice'})").unwrap();
+    executor.execute("INSERT INTO Users VALUES ({name: 'Bob'})").unwrap();
+    let ids = graph.read().unwrap().collection_ids("Users");
+    let mut props = types::Properties::new();
+    let at = now.load(Ordering::SeqCst) + MINUTE;
+    props.insert(EXPIRES_AT_PROPERTY.to_string(), PropertyValue::Timestamp(at));
+    graph.read().unwrap().add_edge(ids[0], ids[1], "FOLLOWS".to_string(), props).unwrap();
+    graph.read().unwrap().add_edge(ids[1], ids[0], "FOLLOWS".to_string(), types::Properties::new()).unwrap();
+
+    let follows = |executor: &DQLExecutor| {
+        executor
+            .execute("FROM Users u TRAVERSE -[:FOLLOWS]-> f SELECT u.name, f.name")
+            .unwrap()
+            .row_count()
+    };
+    assert_eq!(follows(&executor), 2);
+
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+    assert_eq!(follows(&executor), 1);
+    assert_eq!(graph.read().unwrap().get_all_edges().len(), 2);
+
+    let changes = executor.subscribe(ChangeFilter::all());
+    assert_eq!(executor.expire_entities(100, 10).unwrap(), 1);
+    assert_eq!(changes.drain()[0].kind, ChangeKind::EdgeDelete);
+    assert_eq!(graph.read().unwrap().get_all_edges().len(), 1);
+    assert_eq!(stored(&graph), 2);
+}
+
+#[test]
+fn test_background_expiration_runs_until_shutdown() {
+    let (executor, graph, now) = setup();
+    let executor = executor
+        .with_expiration(ExpirationConfig {
+            interval: Duration::from_millis(10),
+            batch_size: 2,
+            ..ExpirationConfig::default()
+        })
+        .unwrap();
+    for i in 0..5 {
+        insert_expiring(&executor, &now, &format!("s{}", i), MINUTE);
+    }
+    now.fetch_add(2 * MINUTE, Ordering::SeqCst);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while stored(&graph) > 0 {
+        assert!(Instant::now() < deadline, "Expired entities were never deleted");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    executor.shutdown(Duration::from_secs(1)).unwrap();
+    assert!(DQLExecutor::new(Arc::new(RwLock::new(Graph::new())))
+        .with_expiration(ExpirationConfig { batch_size: 0, ..ExpirationConfig::default() })
+        .is_err());
+}